@@ -0,0 +1,91 @@
+//! Bakes build info into the binary, see `consts.rs`. Also sets `cfg(wayland)` on targets where winit can use Wayland.
+
+use std::{
+    env,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout)
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+/// Days since the unix epoch to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+fn main() {
+    // No dirty flag: cargo has no way to rerun this when a tracked file is edited, so it would go stale.
+    let hash = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".into());
+
+    // Reproducible builds pin the date.
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+
+    println!("cargo:rustc-env=CROWBAR_GIT_HASH={hash}");
+    println!("cargo:rustc-env=CROWBAR_BUILD_DATE={year:04}-{month:02}-{day:02}");
+    println!(
+        "cargo:rustc-env=CROWBAR_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+
+    // Matches the Wayland dependencies' target in Cargo.toml.
+    println!("cargo::rustc-check-cfg=cfg(wayland)");
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if env::var_os("CARGO_CFG_UNIX").is_some()
+        && !["android", "ios", "macos"].contains(&os.as_str())
+    {
+        println!("cargo:rustc-cfg=wayland");
+    }
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // HEAD only changes on checkout, committing moves the branch it points at. That's a loose file, or a line in
+    // packed-refs once it's been packed. A file that doesn't exist would rerun this every build, so its directory
+    // is watched until it does.
+    let watch = |path: &Path| match path.exists() {
+        true => println!("cargo:rerun-if-changed={}", path.display()),
+        false => {
+            if let Some(parent) = path.parent() {
+                println!("cargo:rerun-if-changed={}", parent.display());
+            }
+        }
+    };
+    let git_dir = git(&["rev-parse", "--absolute-git-dir"]);
+    // Worktrees keep their own HEAD, but refs are shared.
+    let common_dir = git(&["rev-parse", "--path-format=absolute", "--git-common-dir"]);
+    if let (Some(git_dir), Some(common_dir)) = (git_dir, common_dir) {
+        let common_dir = Path::new(&common_dir);
+        watch(&Path::new(&git_dir).join("HEAD"));
+        watch(&common_dir.join("packed-refs"));
+        if let Some(branch) =
+            git(&["rev-parse", "--symbolic-full-name", "HEAD"]).filter(|r| r.starts_with("refs/"))
+        {
+            watch(&common_dir.join(branch));
+        }
+    }
+}
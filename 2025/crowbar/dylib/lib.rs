@@ -0,0 +1,3 @@
+//! Crowbar, linked as a dylib. See `crowbar::hot_reload`.
+
+pub use crowbar::*;
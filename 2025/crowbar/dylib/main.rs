@@ -0,0 +1,3 @@
+fn main() {
+    crowbar_dylib::main();
+}
@@ -0,0 +1,15 @@
+//! `cargo +nightly fuzz run vk_alloc`, from the crate root.
+//! The allocator only needs std and ash, so it's pulled in by path rather than needing a lib target.
+
+#![no_main]
+#![feature(allocator_api)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/render"]
+mod render {
+    #[allow(dead_code)]
+    pub mod alloc;
+}
+
+fuzz_target!(|data: &[u8]| render::alloc::fuzz::run(data));
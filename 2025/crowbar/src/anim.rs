@@ -0,0 +1,515 @@
+//! Property animation: easing functions, keyframed curves and timelines to play them on.
+//! Enough for UI transitions and moving objects around, not a replacement for skeletal clips.
+//!
+//! [`Tween`] covers the one-off "go from here to there" case. Anything with more than two keys is a
+//! [`Curve`], and [`TransformAnimation`] plays curves on an entity's [`Transform`].
+
+use std::f32::consts::{PI, TAU};
+
+use glam::{Quat, Vec2, Vec3, Vec4};
+use hecs::World;
+use serde::{Deserialize, Serialize};
+
+use crate::{color::LinearColor, ecs::components::Transform};
+
+pub mod sprite;
+
+/// Easing functions, mapping 0..1 progress to 0..1 (with overshoot for `Back` and `Elastic`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ease {
+    #[default]
+    Linear,
+    /// Jumps to the end value right away.
+    Step,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExpoIn,
+    ExpoOut,
+    BackIn,
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+impl Ease {
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        // Overshoot for the back eases, the usual 10%.
+        const C1: f32 = 1.70158;
+        const C3: f32 = C1 + 1.0;
+
+        match self {
+            Ease::Linear => t,
+            Ease::Step => {
+                if t > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Ease::QuadIn => t * t,
+            Ease::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Ease::CubicIn => t * t * t,
+            Ease::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Ease::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Ease::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Ease::SineOut => (t * PI / 2.0).sin(),
+            Ease::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Ease::ExpoIn => {
+                if t == 0.0 {
+                    0.0
+                } else {
+                    2f32.powf(10.0 * t - 10.0)
+                }
+            }
+            Ease::ExpoOut => {
+                if t == 1.0 {
+                    1.0
+                } else {
+                    1.0 - 2f32.powf(-10.0 * t)
+                }
+            }
+            Ease::BackIn => C3 * t * t * t - C1 * t * t,
+            Ease::BackOut => 1.0 + C3 * (t - 1.0).powi(3) + C1 * (t - 1.0).powi(2),
+            Ease::ElasticOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * TAU / 3.0).sin() + 1.0
+                }
+            }
+            Ease::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+/// Values that can be blended between.
+pub trait Animatable: Copy {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Animatable for f32 {
+    fn interpolate(a: f32, b: f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+}
+
+impl Animatable for Vec2 {
+    fn interpolate(a: Vec2, b: Vec2, t: f32) -> Vec2 {
+        a.lerp(b, t)
+    }
+}
+
+impl Animatable for Vec3 {
+    fn interpolate(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+        a.lerp(b, t)
+    }
+}
+
+impl Animatable for Vec4 {
+    fn interpolate(a: Vec4, b: Vec4, t: f32) -> Vec4 {
+        a.lerp(b, t)
+    }
+}
+
+impl Animatable for Quat {
+    fn interpolate(a: Quat, b: Quat, t: f32) -> Quat {
+        a.slerp(b, t)
+    }
+}
+
+impl Animatable for LinearColor {
+    fn interpolate(a: LinearColor, b: LinearColor, t: f32) -> LinearColor {
+        a.lerp(b, t)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe<T> {
+    /// Seconds from the start of the curve.
+    pub time: f32,
+    pub value: T,
+    /// How to get from this key to the next one.
+    pub ease: Ease,
+}
+
+/// Keyframes, sampled by blending between the pair around a time. Holds the end values outside them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Animatable> Curve<T> {
+    /// Keys get sorted by time. Panics if there aren't any.
+    pub fn new(mut keys: Vec<Keyframe<T>>) -> Curve<T> {
+        assert!(!keys.is_empty(), "Curves need at least one key!");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Curve { keys }
+    }
+
+    /// Two keys, `from` at 0 and `to` at `duration`.
+    pub fn tween(from: T, to: T, duration: f32, ease: Ease) -> Curve<T> {
+        Curve::new(vec![
+            Keyframe {
+                time: 0.0,
+                value: from,
+                ease,
+            },
+            Keyframe {
+                time: duration,
+                value: to,
+                ease: Ease::Linear,
+            },
+        ])
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    /// The time of the last key.
+    pub fn duration(&self) -> f32 {
+        self.keys.last().unwrap().time
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        // The first key after `time`.
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keys[0].value;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].value;
+        }
+
+        let (a, b) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (time - a.time) / (b.time - a.time);
+        return T::interpolate(a.value, b.value, a.ease.apply(t));
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopMode {
+    /// Stop at the end.
+    #[default]
+    Once,
+    /// Wrap back to the start.
+    Loop,
+    /// Run back and forth.
+    PingPong,
+}
+
+/// A playhead over some duration, with named events along it.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    pub duration: f32,
+    pub speed: f32,
+    pub mode: LoopMode,
+    pub playing: bool,
+    /// Seconds into the timeline, always in `0..=duration`.
+    time: f32,
+    /// Going backwards, in the second half of a ping-pong.
+    reversed: bool,
+    events: Vec<(f32, String)>,
+}
+
+impl Timeline {
+    pub fn new(duration: f32, mode: LoopMode) -> Timeline {
+        Timeline {
+            duration,
+            speed: 1.0,
+            mode,
+            playing: true,
+            time: 0.0,
+            reversed: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Fire `name` whenever the playhead crosses `time`.
+    pub fn with_event(mut self, time: f32, name: impl Into<String>) -> Timeline {
+        self.events.push((time, name.into()));
+        return self;
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration);
+    }
+
+    /// Whether a `Once` timeline has reached the end.
+    pub fn finished(&self) -> bool {
+        self.mode == LoopMode::Once && self.time >= self.duration
+    }
+
+    /// Move the playhead on, calling `on_event` for every event passed, in order.
+    pub fn advance(&mut self, delta: f32, mut on_event: impl FnMut(&str)) {
+        if !self.playing || self.duration <= 0.0 {
+            return;
+        }
+
+        let mut left = delta * self.speed;
+        // Each pass runs to the end of the timeline or the time left, whichever's first.
+        // Loops are bounded so a huge delta can't spin forever.
+        for _ in 0..64 {
+            if left <= 0.0 {
+                break;
+            }
+
+            let (from, to) = if self.reversed {
+                let to = (self.time - left).max(0.0);
+                left -= self.time - to;
+                (to, self.time)
+            } else {
+                let to = (self.time + left).min(self.duration);
+                left -= to - self.time;
+                (self.time, to)
+            };
+
+            let mut passed: Vec<&(f32, String)> = self
+                .events
+                .iter()
+                .filter(|(t, _)| {
+                    if self.reversed {
+                        *t >= from && *t < to
+                    } else {
+                        *t > from && *t <= to
+                    }
+                })
+                .collect();
+            passed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            if self.reversed {
+                passed.reverse();
+            }
+            for (_, name) in passed {
+                on_event(name);
+            }
+
+            self.time = if self.reversed { from } else { to };
+            let at_end = if self.reversed {
+                self.time <= 0.0
+            } else {
+                self.time >= self.duration
+            };
+            if !at_end {
+                break;
+            }
+
+            match self.mode {
+                LoopMode::Once => {
+                    self.playing = false;
+                    break;
+                }
+                LoopMode::Loop => {
+                    self.time = 0.0;
+                    // Events at exactly 0 fire again on the next lap.
+                    for (t, name) in &self.events {
+                        if *t == 0.0 {
+                            on_event(name);
+                        }
+                    }
+                }
+                LoopMode::PingPong => self.reversed = !self.reversed,
+            }
+        }
+    }
+}
+
+/// A value animating from one thing to another, for when a [`Curve`] and a [`Timeline`] are overkill.
+#[derive(Clone, Debug)]
+pub struct Tween<T> {
+    curve: Curve<T>,
+    elapsed: f32,
+}
+
+impl<T: Animatable> Tween<T> {
+    pub fn new(from: T, to: T, duration: f32, ease: Ease) -> Tween<T> {
+        Tween {
+            curve: Curve::tween(from, to, duration, ease),
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advance and return the new value.
+    pub fn update(&mut self, delta: f32) -> T {
+        self.elapsed = (self.elapsed + delta).min(self.curve.duration());
+        return self.value();
+    }
+
+    pub fn value(&self) -> T {
+        self.curve.sample(self.elapsed)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.curve.duration()
+    }
+}
+
+/// Plays curves on an entity's [`Transform`]. Channels without a curve are left alone.
+pub struct TransformAnimation {
+    pub timeline: Timeline,
+    pub translation: Option<Curve<Vec3>>,
+    pub rotation: Option<Curve<Quat>>,
+    pub scale: Option<Curve<Vec3>>,
+    /// Events the timeline passed on the last update, for gameplay to react to.
+    pub fired: Vec<String>,
+}
+
+impl TransformAnimation {
+    /// A timeline as long as the longest curve.
+    pub fn new(
+        mode: LoopMode,
+        translation: Option<Curve<Vec3>>,
+        rotation: Option<Curve<Quat>>,
+        scale: Option<Curve<Vec3>>,
+    ) -> TransformAnimation {
+        let duration = [
+            translation.as_ref().map(Curve::duration),
+            rotation.as_ref().map(Curve::duration),
+            scale.as_ref().map(Curve::duration),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(0.0, f32::max);
+
+        TransformAnimation {
+            timeline: Timeline::new(duration, mode),
+            translation,
+            rotation,
+            scale,
+            fired: Vec::new(),
+        }
+    }
+}
+
+/// The system that plays [`TransformAnimation`]s. Part of the default schedule.
+pub fn animate_transforms(world: &mut World, delta: f32) {
+    for (_, (anim, transform)) in world
+        .query_mut::<(&mut TransformAnimation, &mut Transform)>()
+        .into_iter()
+    {
+        anim.fired.clear();
+        let fired = &mut anim.fired;
+        anim.timeline.advance(delta, |e| fired.push(e.to_owned()));
+
+        let t = anim.timeline.time();
+        if let Some(c) = &anim.translation {
+            transform.translation = c.sample(t);
+        }
+        if let Some(c) = &anim.rotation {
+            transform.rotation = c.sample(t);
+        }
+        if let Some(c) = &anim.scale {
+            transform.scale = c.sample(t);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::{Curve, Ease, Keyframe, LoopMode, Timeline, Tween};
+
+    #[test]
+    pub fn eases_hit_their_ends() {
+        use Ease::*;
+        for ease in [
+            Linear, Step, QuadIn, QuadOut, QuadInOut, CubicIn, CubicOut, CubicInOut, SineIn,
+            SineOut, SineInOut, ExpoIn, ExpoOut, BackIn, BackOut, ElasticOut, BounceOut,
+        ] {
+            assert!(ease.apply(0.0).abs() < 1e-3, "{ease:?}");
+            assert!((ease.apply(1.0) - 1.0).abs() < 1e-3, "{ease:?}");
+        }
+        assert_eq!(QuadIn.apply(0.5), 0.25);
+    }
+
+    #[test]
+    pub fn curves_and_tweens() {
+        let key = |time, value| Keyframe {
+            time,
+            value,
+            ease: Ease::Linear,
+        };
+        let curve = Curve::new(vec![
+            key(2.0, Vec3::Y),
+            key(0.0, Vec3::ZERO),
+            key(1.0, Vec3::X),
+        ]);
+        assert_eq!(curve.duration(), 2.0);
+        assert_eq!(curve.sample(-1.0), Vec3::ZERO);
+        assert_eq!(curve.sample(0.5), Vec3::X * 0.5);
+        assert_eq!(curve.sample(1.5), Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(curve.sample(5.0), Vec3::Y);
+
+        let mut tween = Tween::new(0.0, 10.0, 2.0, Ease::Linear);
+        assert_eq!(tween.update(0.5), 2.5);
+        assert_eq!(tween.update(5.0), 10.0);
+        assert!(tween.finished());
+    }
+
+    #[test]
+    pub fn timeline_events() {
+        let mut timeline = Timeline::new(1.0, LoopMode::Loop)
+            .with_event(0.25, "a")
+            .with_event(0.75, "b");
+
+        let mut fired = Vec::new();
+        timeline.advance(0.5, |e| fired.push(e.to_owned()));
+        assert_eq!(fired, ["a"]);
+
+        // Across the loop point.
+        timeline.advance(1.0, |e| fired.push(e.to_owned()));
+        assert_eq!(fired, ["a", "b", "a"]);
+        assert!((timeline.time() - 0.5).abs() < 1e-6);
+
+        let mut ping = Timeline::new(1.0, LoopMode::PingPong).with_event(0.75, "b");
+        fired.clear();
+        ping.advance(1.5, |e| fired.push(e.to_owned()));
+        assert_eq!(fired, ["b", "b"]);
+        assert!((ping.time() - 0.5).abs() < 1e-6);
+
+        let mut once = Timeline::new(1.0, LoopMode::Once);
+        once.advance(3.0, |_| {});
+        assert!(once.finished() && !once.playing);
+    }
+}
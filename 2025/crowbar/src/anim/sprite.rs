@@ -0,0 +1,181 @@
+//! Flipbook animation: sprites that step through a sequence of images.
+//!
+//! A [`SpriteClip`] is the sequence, usually from an atlas's `clips` (see [`crate::render::atlas`]), shared
+//! between everything playing it. A [`SpriteAnimation`] component plays one on an entity on a [`Timeline`], so it
+//! gets the same speed, looping and events as the rest of [`crate::anim`]. Events are named by frame and fire as
+//! the frame starts, bar the first frame's on the first lap.
+
+use std::sync::Arc;
+
+use hecs::World;
+
+use super::{LoopMode, Timeline};
+use crate::render::sprite::SpriteImage;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipFrame {
+    pub image: SpriteImage,
+    /// In seconds.
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpriteClip {
+    pub frames: Vec<ClipFrame>,
+    pub mode: LoopMode,
+    /// Named events, by the frame they fire on.
+    pub events: Vec<(usize, String)>,
+}
+
+impl SpriteClip {
+    /// Every frame for the same time, `fps` a second.
+    pub fn uniform(
+        images: impl IntoIterator<Item = SpriteImage>,
+        fps: f32,
+        mode: LoopMode,
+    ) -> SpriteClip {
+        let duration = 1.0 / fps.max(f32::EPSILON);
+        SpriteClip {
+            frames: images
+                .into_iter()
+                .map(|image| ClipFrame { image, duration })
+                .collect(),
+            mode,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|f| f.duration).sum()
+    }
+
+    /// When `frame` starts.
+    pub fn frame_start(&self, frame: usize) -> f32 {
+        self.frames[..frame.min(self.frames.len())]
+            .iter()
+            .map(|f| f.duration)
+            .sum()
+    }
+
+    /// The frame showing `time` seconds in, the last one from its end on.
+    pub fn frame_at(&self, time: f32) -> usize {
+        let mut start = 0.0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            start += frame.duration;
+            if time < start {
+                return i;
+            }
+        }
+        return self.frames.len().saturating_sub(1);
+    }
+
+    /// A timeline to play it on, with its events.
+    pub fn timeline(&self) -> Timeline {
+        let mut timeline = Timeline::new(self.duration(), self.mode);
+        for (frame, name) in &self.events {
+            timeline = timeline.with_event(self.frame_start(*frame), name.clone());
+        }
+        return timeline;
+    }
+}
+
+/// Plays a [`SpriteClip`] on an entity. Draw [`SpriteAnimation::image`] for it.
+pub struct SpriteAnimation {
+    pub clip: Arc<SpriteClip>,
+    pub timeline: Timeline,
+    /// Events the timeline passed on the last update, for gameplay to react to.
+    pub fired: Vec<String>,
+}
+
+impl SpriteAnimation {
+    pub fn new(clip: Arc<SpriteClip>) -> SpriteAnimation {
+        SpriteAnimation {
+            timeline: clip.timeline(),
+            clip,
+            fired: Vec::new(),
+        }
+    }
+
+    /// Switch to `clip` from its start, keeping the speed. Does nothing if it's already playing it, so it can be
+    /// called every frame with whatever clip the state wants.
+    pub fn play(&mut self, clip: &Arc<SpriteClip>) {
+        if Arc::ptr_eq(&self.clip, clip) && self.timeline.playing {
+            return;
+        }
+        let speed = self.timeline.speed;
+        self.clip = clip.clone();
+        self.timeline = clip.timeline();
+        self.timeline.speed = speed;
+    }
+
+    pub fn frame(&self) -> usize {
+        self.clip.frame_at(self.timeline.time())
+    }
+
+    /// What to draw now. Panics if the clip has no frames.
+    pub fn image(&self) -> &SpriteImage {
+        &self.clip.frames[self.frame()].image
+    }
+}
+
+/// The system that plays [`SpriteAnimation`]s. Part of the default schedule.
+pub fn animate_sprites(world: &mut World, delta: f32) {
+    for (_, anim) in world.query_mut::<&mut SpriteAnimation>() {
+        anim.fired.clear();
+        let fired = &mut anim.fired;
+        anim.timeline.advance(delta, |e| fired.push(e.to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use glam::Vec2;
+    use hecs::World;
+
+    use super::{SpriteAnimation, SpriteClip, animate_sprites};
+    use crate::{
+        anim::LoopMode,
+        render::sprite::{SpriteImage, TextureId},
+    };
+
+    #[test]
+    pub fn plays_clips() {
+        let images = (0..4).map(|i| SpriteImage::whole(TextureId(i), Vec2::splat(16.0)));
+        let mut run = SpriteClip::uniform(images, 10.0, LoopMode::Loop);
+        run.frames[1].duration = 0.2;
+        run.events.push((2, "step".to_owned()));
+        let run = Arc::new(run);
+        assert_eq!(run.frame_at(0.25), 1);
+        assert_eq!(run.frame_at(0.35), 2);
+
+        let mut world = World::new();
+        let e = world.spawn((SpriteAnimation::new(run.clone()),));
+        animate_sprites(&mut world, 0.32);
+        {
+            let anim = world.get::<&SpriteAnimation>(e).unwrap();
+            assert_eq!(anim.frame(), 2);
+            assert_eq!(anim.image().texture, TextureId(2));
+            assert_eq!(anim.fired, ["step"]);
+        }
+        // Round the loop and back to the second frame.
+        animate_sprites(&mut world, 0.3);
+        let mut anim = world.get::<&mut SpriteAnimation>(e).unwrap();
+        assert_eq!(anim.frame(), 1);
+        assert!(anim.fired.is_empty());
+
+        let hit = Arc::new(SpriteClip::uniform(
+            [SpriteImage::whole(TextureId(9), Vec2::ONE)],
+            10.0,
+            LoopMode::Once,
+        ));
+        anim.timeline.speed = 2.0;
+        anim.play(&hit);
+        assert_eq!(anim.frame(), 0);
+        anim.timeline.advance(1.0, |_| {});
+        assert!(anim.timeline.finished());
+        assert_eq!(anim.image().texture, TextureId(9));
+        assert_eq!(anim.timeline.speed, 2.0);
+    }
+}
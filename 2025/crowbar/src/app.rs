@@ -1,33 +1,474 @@
-use std::{collections::HashMap, process, sync::Arc};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
 
+use glam::Vec2;
+use hecs::World;
 use winit::{
+    dpi::PhysicalSize,
     error::OsError,
     event::WindowEvent,
     event_loop::EventLoop,
-    window::{Window, WindowAttributes, WindowId},
+    raw_window_handle::HasDisplayHandle,
+    window::{Theme, UserAttentionType, Window, WindowAttributes, WindowId},
+};
+
+use self::info::AppInfo;
+use crate::{
+    budget::{BudgetCategory, Budgets},
+    capture::Capture,
+    console::{self, Console},
+    cvar::{CVars, EngineCVars},
+    debug_draw::DebugDraw,
+    ecs::{FrameContext, Schedule, spatial::SpatialIndex},
+    input::{Input, InputEvent},
+    jobs::{
+        JobSystem,
+        graph::{FrameGraph, FrameStage, TaskTiming},
+    },
+    overlay::{
+        DebugOverlay, about::AboutPanel, analysis::AnalysisPanel, budget::BudgetPanel,
+        gpu_memory::GpuMemoryPanel, present::PresentPanel, profiler::ProfilerPanel,
+        render_graph::RenderGraphPanel, shader_errors::ShaderErrorsPanel,
+    },
+    pack::AssetCache,
+    platform::{self, Decorations, PresentationFeedback, TaskbarProgress},
+    plugin::{Plugin, Plugins},
+    profile, profile_scope,
+    render::{
+        device_lost::{DeviceLost, DeviceLostAction},
+        draw::{DrawList, PipelineId},
+        extract::ExtractedScene,
+        gpu_select::GpuOverride,
+        hal::Device as _,
+        lines::LineBatch,
+        motion_blur::MotionBlurSettings,
+        pacing::{FramePacer, refresh_from_millihertz},
+        quality::QualityTracker,
+        renderer::{FrameContent, Renderer},
+        shader::ShaderErrors,
+        sprite::SpriteBatch,
+        surface::{PresentStats, WindowSurface},
+        swapchain::{PresentModePreference, Swapchain},
+        texture::TextureCache,
+        validation::{self, Severity},
+    },
+    replay::{Recorder, Replay},
+    rng::RngService,
+    snapshot::{Snapshot, SnapshotRegistry},
+    ui::{UI_LAYER, Ui, UiEvent},
 };
 
+pub mod info;
+
 pub struct WindowState {
+    // Borrows the window's surface, so has to go first.
+    presentation: Option<PresentationFeedback>,
+    // Presents to `surface`, which the window has to outlive. Neither is there without a renderer that presents,
+    // or while suspended.
+    swapchain: Option<Swapchain>,
+    surface: Option<WindowSurface>,
     winit_window: Arc<Window>,
+    progress: TaskbarProgress,
+    /// Can be fractional, like 1.25 or 1.5, on Wayland and Windows.
+    scale_factor: f64,
+    size: PhysicalSize<u32>,
+    /// Overrides `r_vsync` for this window.
+    present_mode: Option<PresentModePreference>,
+    pacer: FramePacer,
+    present_stats: PresentStats,
 }
 
 impl WindowState {
     pub fn new(window: Window) -> WindowState {
-        WindowState {
+        let mut state = WindowState {
+            presentation: PresentationFeedback::new(&window),
+            swapchain: None,
+            surface: None,
+            scale_factor: window.scale_factor(),
+            size: window.inner_size(),
             winit_window: Arc::new(window),
+            progress: TaskbarProgress::None,
+            present_mode: None,
+            pacer: FramePacer::default(),
+            present_stats: PresentStats::default(),
+        };
+        state.update_monitor();
+        return state;
+    }
+
+    /// Pick up the refresh rate of the monitor the window is on now.
+    fn update_monitor(&mut self) {
+        let refresh = self
+            .winit_window
+            .current_monitor()
+            .and_then(|m| m.refresh_rate_millihertz())
+            .and_then(refresh_from_millihertz);
+        if refresh != self.pacer.monitor_refresh() {
+            if let Some(refresh) = refresh {
+                log::info!(
+                    "Window is on a {:.1} Hz monitor",
+                    1.0 / refresh.as_secs_f32()
+                );
+            }
+            self.pacer.set_monitor_refresh(refresh);
+        }
+    }
+
+    /// How to present, going by `r_vsync` unless the window overrides it.
+    pub fn present_mode(&self, default_vsync: bool) -> PresentModePreference {
+        self.present_mode
+            .unwrap_or(PresentModePreference::from_vsync(default_vsync))
+    }
+
+    /// Whether presenting waits for vsync, going by `r_vsync` unless the window overrides it.
+    pub fn vsync(&self, default: bool) -> bool {
+        self.present_mode(default).vsync()
+    }
+
+    pub fn present_mode_override(&self) -> Option<PresentModePreference> {
+        self.present_mode
+    }
+
+    /// Override `r_vsync` for just this window, or follow it again with `None`. Takes effect on the next frame,
+    /// which remakes the swapchain for it.
+    pub fn set_present_mode(&mut self, present_mode: Option<PresentModePreference>) {
+        self.present_mode = present_mode;
+    }
+
+    /// Physical pixels per logical pixel.
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Inner size in physical pixels, which is what the swapchain should match.
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    /// How frames are reaching the screen. Without presentation feedback it only knows the monitor's refresh rate.
+    pub fn pacer(&self) -> &FramePacer {
+        &self.pacer
+    }
+
+    /// How acquiring and presenting swapchain images has been going.
+    pub fn present_stats(&self) -> &PresentStats {
+        &self.present_stats
+    }
+
+    /// For whatever presents to the window to record into.
+    pub fn present_stats_mut(&mut self) -> &mut PresentStats {
+        &mut self.present_stats
+    }
+
+    pub fn swapchain(&self) -> Option<&Swapchain> {
+        self.swapchain.as_ref()
+    }
+
+    /// The swapchain and the stats to report into while acquiring and presenting with it.
+    pub fn swapchain_mut(&mut self) -> Option<(&mut Swapchain, &mut PresentStats)> {
+        let swapchain = self.swapchain.as_mut()?;
+        return Some((swapchain, &mut self.present_stats));
+    }
+
+    /// Make a surface and swapchain for the window with `renderer`.
+    fn attach(&mut self, renderer: &Renderer, default_vsync: bool, hdr: bool) {
+        // SAFETY: The renderer made its instance for presenting, and outlives the window states. The surface goes
+        // before the window, see the field order.
+        let surface = unsafe {
+            WindowSurface::new(renderer.entry(), renderer.instance(), &self.winit_window)
+        };
+        let surface = match surface {
+            Ok(surface) => surface,
+            Err(e) => {
+                log::error!("Couldn't make a surface for a window: {e}");
+                return;
+            }
+        };
+        match Swapchain::new(
+            renderer,
+            surface.raw(),
+            self.size,
+            self.present_mode(default_vsync),
+            hdr,
+        ) {
+            Ok(swapchain) => self.swapchain = Some(swapchain),
+            Err(e) => log::error!("Couldn't make a swapchain for a window: {e}"),
+        }
+        self.surface = Some(surface);
+    }
+
+    /// Let go of the swapchain and surface, as Android takes the window's away while suspended.
+    fn detach(&mut self) {
+        self.swapchain = None;
+        self.surface = None;
+    }
+
+    /// Whether there's a surface to present to.
+    pub fn has_surface(&self) -> bool {
+        self.surface.is_some()
+    }
+
+    /// Pick up feedback on earlier frames, and ask for it on the one about to be presented.
+    fn update_presentation(&mut self) {
+        let Some(feedback) = &mut self.presentation else {
+            return;
+        };
+
+        let (presented, discarded) = feedback.poll();
+        for frame in presented {
+            self.pacer.on_presented(frame);
+        }
+        for _ in 0..discarded {
+            self.pacer.on_discarded();
+        }
+        feedback.request();
+    }
+
+    pub fn progress(&self) -> TaskbarProgress {
+        self.progress
+    }
+
+    /// Set the progress shown on the taskbar/dock icon, for long running work like bakes.
+    /// Platforms without support just remember the state.
+    pub fn set_progress(&mut self, progress: TaskbarProgress) {
+        if self.progress == progress {
+            return;
         }
+
+        self.progress = progress;
+        platform::set_taskbar_progress(&self.winit_window, progress);
+    }
+
+    /// Flash/bounce the window to get the user's attention, or stop doing so with `None`.
+    /// The platform clears this itself once the window is focused.
+    pub fn request_attention(&self, kind: Option<UserAttentionType>) {
+        self.winit_window.request_user_attention(kind);
     }
 }
 
-pub(crate) struct WinitApp {
+/// Where input comes from this run.
+pub enum InputMode {
+    Live,
+    /// Live input, also written out for replaying later.
+    Recording(Recorder),
+    /// Input and frame deltas come from a recording, live input is ignored.
+    Replaying(Replay),
+}
+
+pub struct WinitApp {
     windows: HashMap<WindowId, WindowState>,
+    /// After the windows, so anything presenting to them is gone first. Made when the app first resumes.
+    renderer: Option<Renderer>,
+    jobs: JobSystem,
+    /// Task timings from the last frame graph run, for the profiler.
+    frame_timings: Vec<TaskTiming>,
+    world: World,
+    schedule: Schedule,
+    extracted: ExtractedScene,
+    /// What each extracted camera sees, culled and sorted each frame. Kept for their allocations.
+    views: Vec<DrawList>,
+    frame_ctx: FrameContext,
+    last_frame: Instant,
+    input: Input,
+    debug_draw: DebugDraw,
+    ui: Ui,
+    /// What the UI pushed in the last frame it ran.
+    ui_events: Vec<UiEvent>,
+    /// 2D drawing for the main window, built each frame.
+    sprites: SpriteBatch,
+    /// Where the debug lines are built before they go in with the sprites. Kept for its allocations.
+    lines: LineBatch,
+    capture: Capture,
+    overlay: DebugOverlay,
+    plugins: Plugins,
+    plugins_initialized: bool,
+    /// The first window created, which frame driven things (benchmarks, the overlay) follow.
+    main_window: Option<WindowId>,
+    /// Overrides the wall clock frame delta, for deterministic runs.
+    fixed_timestep: Option<f32>,
+    exit_requested: bool,
+    /// In the background, with no surfaces to draw to. Mobile apps spend a lot of time here.
+    suspended: bool,
+    decorations: Decorations,
+    /// Light or dark window chrome, `None` to follow the system.
+    theme: Option<Theme>,
+    input_mode: InputMode,
+    seed: u64,
+    rng: RngService,
+    cvars: CVars,
+    engine_cvars: EngineCVars,
+    quality: QualityTracker,
+    /// Where archived cvars get saved on exit.
+    config_path: Option<PathBuf>,
+    snapshots: SnapshotRegistry,
+    budgets: Budgets,
+    textures: TextureCache,
+    assets: AssetCache,
+    shader_errors: ShaderErrors,
+    info: AppInfo,
 }
 
 impl WinitApp {
     pub fn new(event_loop: &mut EventLoop<()>) -> WinitApp {
+        return WinitApp::bare();
+    }
+
+    /// Everything but the windows and renderer, which come once the event loop is running.
+    fn bare() -> WinitApp {
+        let capture = Capture::default();
+        let mut overlay = DebugOverlay::default();
+        overlay.add_panel(ProfilerPanel::default());
+        overlay.add_panel(BudgetPanel);
+        overlay.add_panel(PresentPanel);
+        overlay.add_panel(AnalysisPanel);
+        overlay.add_panel(GpuMemoryPanel::new(|app| {
+            app.renderer().map(|r| r.device().memory_report())
+        }));
+        overlay.add_panel(
+            RenderGraphPanel::new(|app| app.renderer().and_then(|r| r.frame_graph()).cloned())
+                .with_recorded_barriers(|app| {
+                    app.renderer().and_then(|r| r.frame_barriers()).cloned()
+                })
+                .with_readback(capture.resource_requests().clone()),
+        );
+        overlay.add_panel(ShaderErrorsPanel);
+        overlay.add_panel(AboutPanel);
+        overlay.console_mut().register_builtins();
+        let (mut cvars, engine_cvars) = CVars::new();
+        cvars.on_change(
+            engine_cvars.r_validation_severity,
+            |severity| match Severity::parse(&severity) {
+                Some(severity) => validation::set_min_severity(severity),
+                None => log::warn!(
+                    "No validation severity {severity:?}, there's verbose, info, warning and error"
+                ),
+            },
+        );
+        let quality = QualityTracker::new(&cvars, engine_cvars);
+        let budgets = Budgets::new();
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+
         WinitApp {
             windows: Default::default(),
+            renderer: None,
+            jobs: JobSystem::new(None),
+            frame_timings: Vec::new(),
+            world: World::new(),
+            schedule: Schedule::new(),
+            extracted: ExtractedScene::default(),
+            views: Vec::new(),
+            frame_ctx: FrameContext {
+                delta: 0.0,
+                frame: 0,
+                time: 0.0,
+                seed: 0,
+            },
+            last_frame: Instant::now(),
+            input: Input::default(),
+            debug_draw: DebugDraw::default(),
+            ui: Ui::default(),
+            ui_events: Vec::new(),
+            sprites: SpriteBatch::default(),
+            lines: LineBatch::default(),
+            capture,
+            overlay,
+            plugins: Plugins::default(),
+            plugins_initialized: false,
+            main_window: None,
+            fixed_timestep: None,
+            exit_requested: false,
+            suspended: false,
+            decorations: Decorations::default(),
+            theme: None,
+            input_mode: InputMode::Live,
+            seed,
+            rng: RngService::new(seed),
+            cvars,
+            engine_cvars,
+            quality,
+            config_path: None,
+            snapshots: SnapshotRegistry::default(),
+            textures: TextureCache::new(&budgets, BudgetCategory::Textures, "textures"),
+            assets: AssetCache::new(&budgets, BudgetCategory::Assets, "assets"),
+            budgets,
+            shader_errors: ShaderErrors::new(),
+            info: AppInfo::default(),
+        }
+    }
+
+    /// Name the application. This should happen before the event loop starts, and before [`WinitApp::use_config`]
+    /// if the config should go in the app's config directory.
+    pub fn with_app_info(mut self, info: AppInfo) -> WinitApp {
+        self.info = info;
+        return self;
+    }
+
+    pub fn app_info(&self) -> &AppInfo {
+        &self.info
+    }
+
+    /// Who draws the titlebar. Only applies to windows created after this.
+    pub fn with_decorations(mut self, decorations: Decorations) -> WinitApp {
+        self.decorations = decorations;
+        return self;
+    }
+
+    /// Light or dark titlebars regardless of the system theme, for tools with a fixed look.
+    /// Applies to windows created after this that don't ask for a theme of their own.
+    pub fn with_theme(mut self, theme: Option<Theme>) -> WinitApp {
+        self.theme = theme;
+        return self;
+    }
+
+    pub fn with_plugin(mut self, plugin: impl Plugin) -> WinitApp {
+        self.add_plugin(plugin);
+        return self;
+    }
+
+    /// Register a plugin. This should happen before the event loop starts, later additions never get `init` called.
+    pub fn add_plugin(&mut self, plugin: impl Plugin) {
+        self.plugins.add(plugin);
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    /// Run `f` over every plugin, in registration order (or reverse), stopping early if it returns true.
+    fn dispatch_plugins(
+        &mut self,
+        reverse: bool,
+        mut f: impl FnMut(&mut dyn Plugin, &mut WinitApp) -> bool,
+    ) {
+        let mut plugins = std::mem::take(&mut self.plugins);
+
+        if reverse {
+            for p in plugins.iter_mut().rev() {
+                if f(p.as_mut(), self) {
+                    break;
+                }
+            }
+        } else {
+            for p in plugins.iter_mut() {
+                if f(p.as_mut(), self) {
+                    break;
+                }
+            }
         }
+
+        self.plugins.restore(plugins);
+    }
+
+    pub fn jobs(&self) -> &JobSystem {
+        &self.jobs
     }
 
     pub fn create_window(
@@ -35,9 +476,22 @@ impl WinitApp {
         event_loop: &winit::event_loop::ActiveEventLoop,
         attribs: WindowAttributes,
     ) -> Result<WindowId, OsError> {
-        let window = event_loop.create_window(attribs)?;
+        let theme = attribs.preferred_theme.or(self.theme);
+        let window = event_loop.create_window(attribs.with_theme(theme))?;
+        platform::window_created(&window, theme);
         let id = window.id();
-        self.windows.insert(id, WindowState::new(window));
+        let mut state = WindowState::new(window);
+        if let Some(renderer) = &self.renderer
+            && renderer.presents()
+        {
+            state.attach(
+                renderer,
+                self.cvars.get(self.engine_cvars.r_vsync),
+                self.cvars.get(self.engine_cvars.r_hdr),
+            );
+        }
+        self.windows.insert(id, state);
+        self.main_window.get_or_insert(id);
         return Ok(id);
     }
 
@@ -48,17 +502,645 @@ impl WinitApp {
             .winit_window
             .clone()
     }
+
+    pub fn frame_timings(&self) -> &[TaskTiming] {
+        &self.frame_timings
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn schedule_mut(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// Where everything was as of the last frame, for finding things in space.
+    pub fn spatial(&self) -> &SpatialIndex {
+        self.extracted.spatial()
+    }
+
+    /// Memory budgets. Clone it to track usage from subsystems and worker threads.
+    pub fn budgets(&self) -> &Budgets {
+        &self.budgets
+    }
+
+    /// Textures loaded through [`TextureLoader::load_cached`](crate::render::texture::TextureLoader::load_cached),
+    /// evicted when they go over the texture budget.
+    pub fn texture_cache(&self) -> &TextureCache {
+        &self.textures
+    }
+
+    /// Assets read through [`Pack::read_cached`](crate::pack::Pack::read_cached), evicted when they go over the
+    /// asset budget. For the one pack, see [`AssetCache`].
+    pub fn asset_cache(&self) -> &AssetCache {
+        &self.assets
+    }
+
+    /// Where pipelines report failed builds for the overlay. Clone it for each [`PipelineSlot`].
+    ///
+    /// [`PipelineSlot`]: crate::render::shader::PipelineSlot
+    pub fn shader_errors(&self) -> &ShaderErrors {
+        &self.shader_errors
+    }
+
+    /// `None` until the app first resumes, or if there's no device to render with.
+    pub fn renderer(&self) -> Option<&Renderer> {
+        self.renderer.as_ref()
+    }
+
+    /// For adding pipelines and the like. `None` like [`WinitApp::renderer`].
+    pub fn renderer_mut(&mut self) -> Option<&mut Renderer> {
+        self.renderer.as_mut()
+    }
+
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    /// The in-game UI, on the main window.
+    pub fn ui(&self) -> &Ui {
+        &self.ui
+    }
+
+    pub fn ui_mut(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    /// What happened in the UI this frame. Empty on frames of windows other than the main one.
+    pub fn ui_events(&self) -> &[UiEvent] {
+        &self.ui_events
+    }
+
+    /// The main window's 2D drawing for this frame, drawn over the scene with the UI on top. Push to it before the
+    /// frame's presented, from [`Plugin::pre_frame`], as it's cleared after.
+    pub fn sprites(&self) -> &SpriteBatch {
+        &self.sprites
+    }
+
+    pub fn sprites_mut(&mut self) -> &mut SpriteBatch {
+        &mut self.sprites
+    }
+
+    /// Split borrow, for things that draw the world.
+    pub fn world_and_debug_draw(&mut self) -> (&World, &mut DebugDraw) {
+        (&self.world, &mut self.debug_draw)
+    }
+
+    pub fn capture_mut(&mut self) -> &mut Capture {
+        &mut self.capture
+    }
+
+    pub fn overlay_mut(&mut self) -> &mut DebugOverlay {
+        &mut self.overlay
+    }
+
+    pub fn console_mut(&mut self) -> &mut Console {
+        self.overlay.console_mut()
+    }
+
+    pub fn cvars(&self) -> &CVars {
+        &self.cvars
+    }
+
+    pub fn cvars_mut(&mut self) -> &mut CVars {
+        &mut self.cvars
+    }
+
+    pub fn engine_cvars(&self) -> EngineCVars {
+        self.engine_cvars
+    }
+
+    /// Load cvars from a config file, and save archived ones back to it on exit.
+    pub fn use_config(&mut self, path: PathBuf) {
+        if let Err(e) = self.cvars.load(&path) {
+            log::warn!("Couldn't read config {}: {e}", path.display());
+        }
+        self.config_path = Some(path);
+    }
+
+    /// Register game components here to have them saved in snapshots.
+    pub fn snapshots_mut(&mut self) -> &mut SnapshotRegistry {
+        &mut self.snapshots
+    }
+
+    /// Save the world, seed, random streams and clock to a file.
+    pub fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        let ctx = &self.frame_ctx;
+        let mut snapshot = self
+            .snapshots
+            .capture(&self.world, self.seed, ctx.frame, ctx.time)?;
+        snapshot.rng = Some(self.rng.clone());
+        return snapshot.save(path);
+    }
+
+    /// Put the world, seed, random streams and clock back the way a snapshot has them.
+    pub fn load_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        let snapshot = Snapshot::load(path)?;
+        self.snapshots.restore(&snapshot, &mut self.world)?;
+
+        self.seed = snapshot.seed;
+        self.rng = snapshot
+            .rng
+            .unwrap_or_else(|| RngService::new(snapshot.seed));
+        self.frame_ctx.frame = snapshot.frame;
+        self.frame_ctx.time = snapshot.time;
+        self.frame_ctx.seed = snapshot.seed;
+        return Ok(());
+    }
+
+    pub fn main_window(&self) -> Option<WindowId> {
+        self.main_window
+    }
+
+    /// Ask for another frame on the main window.
+    pub fn request_redraw(&self) {
+        if let Some(id) = self.main_window {
+            self.get_window(id).request_redraw();
+        }
+    }
+
+    /// Use a fixed frame delta instead of the wall clock, or go back to the wall clock with `None`.
+    pub fn set_fixed_timestep(&mut self, timestep: Option<f32>) {
+        self.fixed_timestep = timestep;
+    }
+
+    /// Shut down cleanly once the current event has been handled.
+    pub fn request_exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    /// The seed handed to systems through [`FrameContext`]. Random per run unless set.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Change the seed, restarting every random stream from it.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = RngService::new(seed);
+    }
+
+    /// The simulation's random streams. Take one per system with [`RngService::stream`].
+    pub fn rng_mut(&mut self) -> &mut RngService {
+        &mut self.rng
+    }
+
+    pub fn input_mode(&self) -> &InputMode {
+        &self.input_mode
+    }
+
+    /// Record input from here on, under the current seed.
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        // The recording only has the seed, so streams have to start fresh from it to replay the same.
+        self.rng = RngService::new(self.seed);
+        self.input_mode = InputMode::Recording(recorder);
+    }
+
+    /// Drive the app from a recording, taking its seed. The app exits once the recording runs out.
+    pub fn start_replay(&mut self, replay: Replay) {
+        self.set_seed(replay.header.seed);
+        self.input_mode = InputMode::Replaying(replay);
+    }
+
+    fn handle_input(&mut self, event: InputEvent) {
+        match &mut self.input_mode {
+            // Live input would desync the replay.
+            InputMode::Replaying(_) => return,
+            InputMode::Recording(rec) => rec.record(event),
+            InputMode::Live => {}
+        }
+
+        self.input.apply(event);
+    }
+
+    /// Timing info for the frame currently being run.
+    pub fn frame_context(&self) -> FrameContext {
+        self.frame_ctx
+    }
+
+    /// Run the CPU side of a frame for the given window.
+    fn run_frame(&mut self, window_id: WindowId) {
+        let now = Instant::now();
+        let default_vsync = self.cvars.get(self.engine_cvars.r_vsync);
+        let hdr = self.cvars.get(self.engine_cvars.r_hdr);
+        let mut measured = (now - self.last_frame).as_secs_f32();
+        if let Some(state) = self.windows.get_mut(&window_id) {
+            let present_mode = state.present_mode(default_vsync);
+            if let Some(swapchain) = &mut state.swapchain {
+                swapchain.set_present_mode(present_mode);
+                swapchain.set_hdr(hdr);
+            }
+            // Frames go out on refreshes, so time moves in refreshes. Otherwise a 144 Hz window steps unevenly.
+            if present_mode.vsync() {
+                measured = state.pacer.snap_delta(measured);
+            }
+        }
+        let mut delta = self.fixed_timestep.unwrap_or(measured);
+        self.last_frame = now;
+
+        match &mut self.input_mode {
+            InputMode::Replaying(replay) => match replay.next_frame() {
+                Some(frame) => {
+                    delta = frame.delta;
+                    for e in frame.events {
+                        self.input.apply(e);
+                    }
+                }
+                None => {
+                    log::info!("Replay finished.");
+                    self.input_mode = InputMode::Live;
+                    self.exit_requested = true;
+                }
+            },
+            InputMode::Recording(rec) => {
+                if let Err(e) = rec.frame(delta) {
+                    log::error!("Input recording failed, stopping it: {e}");
+                    self.input_mode = InputMode::Live;
+                }
+            }
+            InputMode::Live => {}
+        }
+
+        self.frame_ctx = FrameContext {
+            delta,
+            frame: self.frame_ctx.frame + 1,
+            time: self.frame_ctx.time + delta as f64,
+            seed: self.seed,
+        };
+        profile_scope!("frame");
+        self.capture.update(&self.input, self.frame_ctx.delta);
+        let main_size = self
+            .main_window
+            .filter(|&id| id == window_id)
+            .and_then(|id| self.windows.get(&id))
+            .map(|s| (s.size, s.scale_factor));
+        self.ui_events.clear();
+        if let Some((size, scale_factor)) = main_size {
+            profile_scope!("ui");
+            self.ui.set_scale(scale_factor as f32);
+            let viewport = Vec2::new(size.width as f32, size.height as f32);
+            self.ui_events = self.ui.update(&self.input, viewport);
+        }
+
+        self.debug_draw.enabled = self.cvars.get(self.engine_cvars.debug_draw);
+        {
+            profile_scope!("plugins_pre_frame");
+            self.dispatch_plugins(false, |p, app| {
+                p.pre_frame(app);
+                false
+            });
+        }
+        {
+            profile_scope!("quality");
+            if let Some(change) = self.quality.poll(&mut self.cvars, self.engine_cvars) {
+                log::debug!("Quality settings changed: {change}");
+            }
+            let size = self
+                .main_window
+                .and_then(|id| self.windows.get(&id))
+                .map(|s| s.size);
+            if let (Some(renderer), Some(size)) = (&mut self.renderer, size) {
+                let frame = self.frame_ctx.frame;
+                let frames_in_flight = self.cvars.get(self.engine_cvars.r_frames_in_flight);
+                renderer.set_frames_in_flight(frames_in_flight as u32);
+                renderer.begin_frame(frame);
+                if self.cvars.get(self.engine_cvars.r_shader_reload) {
+                    renderer.reload_pipelines(false);
+                }
+                renderer.update_targets(&self.quality.settings(), [size.width, size.height], frame);
+                renderer.set_motion_blur(MotionBlurSettings::from_cvars(
+                    &self.cvars,
+                    self.engine_cvars,
+                ));
+            }
+        }
+        self.record_frame(window_id);
+        if let Some((size, _)) = main_size {
+            profile_scope!("sprites");
+            let viewport = Vec2::new(size.width as f32, size.height as f32);
+            // Through the camera drawn first, the one the game's seen through.
+            if let Some(camera) = self.extracted.cameras.first() {
+                let view_proj = camera.view_proj(viewport.x / viewport.y.max(1.0));
+                let (lines, sprites) = (&mut self.lines, &mut self.sprites);
+                self.debug_draw.draw(&view_proj, viewport, lines, sprites);
+            }
+            self.ui.draw(&mut self.sprites, UI_LAYER);
+            self.sprites.build();
+        }
+        self.present_frame(window_id);
+        if main_size.is_some() {
+            self.sprites.clear();
+            self.debug_draw.clear();
+        }
+        let lost = self
+            .renderer
+            .as_ref()
+            .and_then(|r| r.device_lost())
+            .cloned();
+        if let Some(lost) = lost {
+            self.handle_device_lost(&lost);
+        }
+        {
+            profile_scope!("plugins_post_frame");
+            self.dispatch_plugins(false, |p, app| {
+                p.post_frame(app);
+                false
+            });
+        }
+        {
+            profile_scope!("enforce_budgets");
+            for category in self.budgets.enforce() {
+                log::warn!("{} still over budget after eviction", category.name());
+            }
+        }
+        {
+            profile_scope!("overlay");
+            if self.overlay.console().variable_count() != self.cvars.len() {
+                let names = self.cvars.names().collect();
+                self.overlay.console_mut().set_variable_names(names);
+            }
+
+            let window = self.get_window(window_id);
+            let mut overlay = std::mem::take(&mut self.overlay);
+            overlay.run(self, &window);
+            self.overlay = overlay;
+        }
+        for line in self.overlay.console_mut().take_submitted() {
+            console::execute(self, &line);
+        }
+        self.input.end_frame();
+    }
+
+    fn record_frame(&mut self, window_id: WindowId) {
+        let ctx = self.frame_ctx;
+        let aspect = self
+            .windows
+            .get(&window_id)
+            .map_or(1.0, |s| s.size.width as f32 / s.size.height.max(1) as f32);
+        let world = RwLock::new(&mut self.world);
+        let schedule = &mut self.schedule;
+        let extracted = Mutex::new(&mut self.extracted);
+        let views = &mut self.views;
+        let jobs = &self.jobs;
+
+        let mut frame = {
+            profile_scope!("build_frame_graph");
+            FrameGraph::new()
+        };
+        frame.add_to_stage(FrameStage::Simulation, "systems", || {
+            schedule.run(&mut world.write().unwrap(), &ctx);
+        });
+        let extract = frame.add_to_stage(FrameStage::Culling, "extract", || {
+            extracted.lock().unwrap().extract(&world.read().unwrap());
+        });
+        let cull = frame.graph.add_task("cull", &[extract], || {
+            let scene = extracted.lock().unwrap();
+            let scene: &ExtractedScene = &scene;
+            views.resize_with(scene.cameras.len(), DrawList::default);
+            jobs.scope(|s| {
+                for (camera, view) in scene.cameras.iter().zip(views.iter_mut()) {
+                    s.spawn("cull_view", move |_| {
+                        view.cull(scene, camera, aspect, PipelineId(0))
+                    });
+                }
+            });
+        });
+        frame
+            .graph
+            .add_dependency(cull, frame.stage(FrameStage::Culling));
+        {
+            profile_scope!("run_frame_graph");
+            frame.run(&self.jobs);
+        }
+        self.frame_timings = frame.graph.timings().to_vec();
+    }
+
+    fn present_frame(&mut self, window_id: WindowId) {
+        profile_scope!("present");
+        // Taken whether or not there's anything to paint it on, so its texture changes don't pile up.
+        let overlay = self.overlay.take_output();
+        let (Some(renderer), Some(state)) = (&mut self.renderer, self.windows.get_mut(&window_id))
+        else {
+            return;
+        };
+        // Asked for ahead of presenting, so it covers this frame.
+        state.update_presentation();
+        let Some((swapchain, stats)) = state.swapchain_mut() else {
+            return;
+        };
+        let main = self.main_window == Some(window_id);
+        let resources = match main {
+            true => self.capture.resource_requests().take(),
+            false => Vec::new(),
+        };
+        let content = FrameContent {
+            scene: main.then_some(&self.extracted),
+            views: if main { &self.views } else { &[] },
+            sprites: main.then_some(&self.sprites),
+            overlay: overlay.as_ref(),
+            readback: main && self.capture.wants_readback(),
+            analyse: main && self.cvars.get(self.engine_cvars.r_analysis),
+            resources: &resources,
+            time: self.frame_ctx.time as f32,
+        };
+        if let Err(e) = renderer.present(swapchain, stats, content) {
+            log::error!("Couldn't present: {e}");
+        }
+        for captured in renderer.take_captures() {
+            self.capture.submit_frame(captured, &self.jobs);
+        }
+        for (name, captured) in renderer.take_resource_captures() {
+            self.capture.submit_resource(&name, captured, &self.jobs);
+        }
+    }
+
+    /// Ask the plugins whether to recover from `lost`, and do what they say. Recovering makes the renderer again,
+    /// and the windows' swapchains with it.
+    fn handle_device_lost(&mut self, lost: &DeviceLost) {
+        let mut action = None;
+        self.dispatch_plugins(false, |p, app| {
+            action = p.device_lost(app, lost);
+            action.is_some()
+        });
+        // Swapchains and surfaces go before the device and instance they were made from.
+        for window in self.windows.values_mut() {
+            window.detach();
+        }
+        let Some(renderer) = self.renderer.take() else {
+            return;
+        };
+        if action.unwrap_or(lost.default_action()) == DeviceLostAction::Exit {
+            // Dropped without saving its pipeline cache, which could have anything in it now.
+            log::error!("Exiting after losing the device");
+            drop(renderer);
+            self.exit_requested = true;
+            return;
+        }
+        match renderer.recreate() {
+            Ok(renderer) => {
+                log::info!("Recovered from losing the device");
+                let vsync = self.cvars.get(self.engine_cvars.r_vsync);
+                let hdr = self.cvars.get(self.engine_cvars.r_hdr);
+                if renderer.presents() && !self.suspended {
+                    for window in self.windows.values_mut() {
+                        window.attach(&renderer, vsync, hdr);
+                    }
+                }
+                self.renderer = Some(renderer);
+            }
+            Err(e) => {
+                log::error!("Couldn't recover from losing the device, exiting: {e}");
+                self.exit_requested = true;
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.dispatch_plugins(true, |p, app| {
+            p.shutdown(app);
+            false
+        });
+        self.capture.stop_video();
+        self.save_config();
+        if let Some(renderer) = &self.renderer {
+            renderer.save_pipeline_cache();
+        }
+    }
+
+    fn save_config(&self) {
+        if let Some(path) = &self.config_path
+            && let Err(e) = self.cvars.save(path)
+        {
+            log::warn!("Couldn't save config {}: {e}", path.display());
+        }
+    }
+
+    /// In the background, not drawing. See [`Plugin::suspended`].
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn get_window_state(&self, id: WindowId) -> Option<&WindowState> {
+        self.windows.get(&id)
+    }
+
+    pub fn get_window_state_mut(&mut self, id: WindowId) -> Option<&mut WindowState> {
+        self.windows.get_mut(&id)
+    }
+}
+
+#[cfg(test)]
+impl WinitApp {
+    /// An app with no event loop behind it, stepping `delta` seconds a frame, for driving plugins in tests.
+    pub(crate) fn for_tests(delta: f32) -> WinitApp {
+        let mut app = WinitApp::bare();
+        app.frame_ctx.delta = delta;
+        return app;
+    }
+
+    pub(crate) fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
 }
 
 impl winit::application::ApplicationHandler for WinitApp {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.create_window(
-            event_loop,
-            WindowAttributes::default()
-                .with_title("Crowbar Application")
-                .with_active(true),
-        )
-        .expect("Initial window creation MUST succeed!");
+        // Android resumes every time the app comes back to the foreground, the windows are still there.
+        if self.main_window.is_none() {
+            platform::register_app(&self.info);
+            let display = event_loop.display_handle().ok().map(|d| d.as_raw());
+            let gpu = GpuOverride::from_env_or(&self.cvars.get(self.engine_cvars.r_gpu));
+            let validation = validation::requested(self.cvars.get(self.engine_cvars.r_validation));
+            match Renderer::new(&self.info, display, gpu.as_ref(), validation, &self.jobs) {
+                Ok(renderer) => self.renderer = Some(renderer),
+                Err(e) => log::error!("Couldn't set up the renderer: {e}"),
+            }
+            self.create_window(
+                event_loop,
+                WindowAttributes::default()
+                    .with_title(self.info.name())
+                    .with_decorations(self.decorations != Decorations::None)
+                    .with_active(true),
+            )
+            .expect("Initial window creation MUST succeed!");
+        }
+
+        if !self.plugins_initialized {
+            self.plugins_initialized = true;
+            self.dispatch_plugins(false, |p, app| {
+                p.init(app, event_loop);
+                false
+            });
+        } else if self.suspended {
+            self.suspended = false;
+            // The clock kept going while we were away, don't make that one long frame.
+            self.last_frame = Instant::now();
+            if let Some(renderer) = &self.renderer
+                && renderer.presents()
+            {
+                let vsync = self.cvars.get(self.engine_cvars.r_vsync);
+                let hdr = self.cvars.get(self.engine_cvars.r_hdr);
+                for window in self.windows.values_mut() {
+                    window.attach(renderer, vsync, hdr);
+                }
+            }
+            self.dispatch_plugins(false, |p, app| {
+                p.resumed(app, event_loop);
+                false
+            });
+            for window in self.windows.values() {
+                window.winit_window.request_redraw();
+            }
+        }
+    }
+
+    fn suspended(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        if self.suspended {
+            return;
+        }
+        self.suspended = true;
+        // Nothing comes in while we're away, so don't leave anything held down.
+        self.handle_input(InputEvent::FocusLost);
+        self.dispatch_plugins(true, |p, app| {
+            p.suspended(app);
+            false
+        });
+        for window in self.windows.values_mut() {
+            window.detach();
+        }
+
+        // Android can kill a backgrounded app without warning, so this might be the last chance to save.
+        self.save_config();
+    }
+
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.dispatch_plugins(false, |p, app| {
+            p.about_to_wait(app, event_loop);
+            false
+        });
+
+        if self.exit_requested || platform::take_quit_request() {
+            self.exit_requested = false;
+            self.shutdown();
+            event_loop.exit();
+            return;
+        }
+
+        // The event loop waits, so the next frame has to be asked for. Presenting paces them.
+        if !self.suspended {
+            for window in self.windows.values() {
+                window.winit_window.request_redraw();
+            }
+        }
     }
 
     fn window_event(
@@ -67,17 +1149,68 @@ impl winit::application::ApplicationHandler for WinitApp {
         window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        let window = self.get_window(window_id);
+        // A redraw is a new frame, started before any scope opens so none of them straddles two frames.
+        if matches!(event, WindowEvent::RedrawRequested) && !self.suspended {
+            profile::new_frame(self.frame_ctx.frame + 1);
+        }
+        profile_scope!("window_event");
+        // Closed windows still hear about being destroyed.
+        let Some(window) = self.windows.get(&window_id).map(|s| s.winit_window.clone()) else {
+            return;
+        };
+
+        if !self.overlay.handle_event(&window, &event) {
+            if let Some(input) = InputEvent::from_window_event(&event) {
+                self.handle_input(input);
+            }
+            for input in InputEvent::text_from_window_event(&event) {
+                self.handle_input(input);
+            }
+        }
+        self.dispatch_plugins(false, |p, app| p.window_event(app, window_id, &event));
 
         match event {
             WindowEvent::RedrawRequested => {
-                // ...
+                // Android drops the surface while suspended, there's nothing to draw to.
+                if !self.suspended {
+                    self.run_frame(window_id);
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(state) = self.windows.get_mut(&window_id) {
+                    state.scale_factor = scale_factor;
+                    state.update_monitor();
+                }
+            }
+            // Possibly onto another monitor, with another refresh rate.
+            WindowEvent::Moved(_) => {
+                if let Some(state) = self.windows.get_mut(&window_id) {
+                    state.update_monitor();
+                }
+            }
+            // A scale change is followed by a resize with the new physical size.
+            WindowEvent::Resized(size) => {
+                if let Some(state) = self.windows.get_mut(&window_id) {
+                    state.size = size;
+                    platform::window_resized(&state.winit_window);
+                    // Recreated on the next acquire, not for every event of a drag.
+                    if let Some(swapchain) = &mut state.swapchain {
+                        swapchain.resize(size);
+                    }
+                }
             }
+            // The renderer drops once `run_app` returns, after the deletion queues drain in its `Drop`. Other
+            // windows just go, swapchain and all, while the main one's still open.
             WindowEvent::CloseRequested => {
-                process::exit(0); // todo: sane exit handling :)
+                if self.main_window == Some(window_id) || self.windows.len() == 1 {
+                    self.shutdown();
+                    event_loop.exit();
+                } else {
+                    self.windows.remove(&window_id);
+                }
             }
             e => {
-                println!("Unhandled log event {e:?}")
+                log::debug!("Unhandled window event {e:?}");
             }
         }
     }
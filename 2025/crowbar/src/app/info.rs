@@ -0,0 +1,143 @@
+//! Who the application is, as opposed to the engine it runs on.
+//! This names the window, goes to the driver, and decides where config and logs live.
+
+use std::{env, path::PathBuf};
+
+use crate::consts::BUILD;
+
+#[derive(Clone, Debug)]
+pub struct AppInfo {
+    name: String,
+    version: (u32, u32, u32),
+    organization: Option<String>,
+}
+
+impl Default for AppInfo {
+    /// Crowbar itself, for running the engine without a game.
+    fn default() -> Self {
+        AppInfo {
+            name: "Crowbar".into(),
+            version: (BUILD.major, BUILD.minor, BUILD.patch),
+            organization: None,
+        }
+    }
+}
+
+impl AppInfo {
+    pub fn new(name: impl Into<String>) -> AppInfo {
+        AppInfo {
+            name: name.into(),
+            version: (0, 1, 0),
+            organization: None,
+        }
+    }
+
+    pub fn with_version(mut self, major: u32, minor: u32, patch: u32) -> AppInfo {
+        self.version = (major, minor, patch);
+        return self;
+    }
+
+    /// Groups the app's directories under the organization's, where the platform does that.
+    pub fn with_organization(mut self, organization: impl Into<String>) -> AppInfo {
+        self.organization = Some(organization.into());
+        return self;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> (u32, u32, u32) {
+        self.version
+    }
+
+    pub fn organization(&self) -> Option<&str> {
+        self.organization.as_deref()
+    }
+
+    /// The version packed for Vulkan.
+    pub fn vk_version(&self) -> u32 {
+        let (major, minor, patch) = self.version;
+        return ash::vk::make_api_version(0, major, minor, patch);
+    }
+
+    /// `base/org/name`, or `base/name` without an organization.
+    fn under(&self, base: PathBuf) -> PathBuf {
+        let mut path = base;
+        if let Some(org) = &self.organization {
+            path.push(org);
+        }
+        path.push(&self.name);
+        return path;
+    }
+
+    /// Where config files go. Falls back to the working directory if the platform's can't be found.
+    pub fn config_dir(&self) -> PathBuf {
+        // The app's private storage is already just ours.
+        #[cfg(target_os = "android")]
+        {
+            if let Some(dir) = crate::platform::android::data_dir() {
+                return dir;
+            }
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|h| PathBuf::from(h).join("Library/Application Support"))
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        };
+
+        return base.map_or_else(|| PathBuf::from("."), |b| self.under(b));
+    }
+
+    /// Where log files go. Falls back to `logs` in the working directory.
+    pub fn log_dir(&self) -> PathBuf {
+        #[cfg(target_os = "android")]
+        {
+            if let Some(dir) = crate::platform::android::data_dir() {
+                return dir.join("logs");
+            }
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("LOCALAPPDATA").map(|d| self.under(PathBuf::from(d)).join("logs"))
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|h| PathBuf::from(h).join("Library/Logs").join(&self.name))
+        } else {
+            env::var_os("XDG_STATE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))
+                .map(|b| self.under(b).join("logs"))
+        };
+
+        return base.unwrap_or_else(|| PathBuf::from("logs"));
+    }
+
+    /// Where things worth keeping between runs but safe to lose go, like the pipeline cache. Falls back to `cache`
+    /// in the working directory.
+    pub fn cache_dir(&self) -> PathBuf {
+        #[cfg(target_os = "android")]
+        {
+            if let Some(dir) = crate::platform::android::data_dir() {
+                return dir.join("cache");
+            }
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("LOCALAPPDATA").map(|d| self.under(PathBuf::from(d)).join("cache"))
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|h| PathBuf::from(h).join("Library/Caches").join(&self.name))
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+                .map(|b| self.under(b))
+        };
+
+        return base.unwrap_or_else(|| PathBuf::from("cache"));
+    }
+}
@@ -0,0 +1,337 @@
+//! Benchmark mode. Plays a deterministic camera path through a scene at a fixed timestep,
+//! records per-frame timings, and writes them out as CSV and JSON on exit.
+
+use std::{f32::consts::TAU, fmt::Write as _, fs, path::PathBuf, time::Instant};
+
+use glam::{Quat, Vec3};
+use hecs::{Entity, World};
+use serde::Serialize;
+use winit::event_loop::ActiveEventLoop;
+
+use crate::{
+    app::WinitApp,
+    color::LinearColor,
+    ecs::components::{
+        Camera, Light, LightKind, MaterialId, MeshId, MeshRenderer, Projection, Transform,
+    },
+    plugin::Plugin,
+    profile, render,
+};
+
+/// Frames run before recording starts, so startup hitches don't skew results.
+const WARMUP_FRAMES: u32 = 30;
+
+/// Camera position and look-at target for a point along the path, `t` in `0.0..1.0`.
+pub type CameraPath = fn(f32) -> (Vec3, Vec3);
+
+pub struct BenchmarkScene {
+    pub name: &'static str,
+    pub setup: fn(&mut World),
+    pub path: CameraPath,
+}
+
+fn orbit(t: f32) -> (Vec3, Vec3) {
+    let a = t * TAU;
+    (Vec3::new(a.cos() * 40.0, 12.0, a.sin() * 40.0), Vec3::ZERO)
+}
+
+fn grid_scene(world: &mut World) {
+    for x in -16..16 {
+        for z in -16..16 {
+            world.spawn((
+                Transform::from_translation(Vec3::new(x as f32 * 2.0, 0.0, z as f32 * 2.0)),
+                MeshRenderer {
+                    mesh: MeshId(0),
+                    material: MaterialId(0),
+                    visible: true,
+                    cast_shadows: true,
+                },
+            ));
+        }
+    }
+
+    world.spawn((
+        Transform {
+            rotation: Quat::from_rotation_x(-1.0),
+            ..Transform::IDENTITY
+        },
+        Light {
+            kind: LightKind::Directional,
+            color: LinearColor::WHITE,
+            intensity: 3.0,
+        },
+    ));
+}
+
+pub const BUILTIN_SCENES: &[BenchmarkScene] = &[
+    BenchmarkScene {
+        name: "empty",
+        setup: |_| {},
+        path: orbit,
+    },
+    BenchmarkScene {
+        name: "grid",
+        setup: grid_scene,
+        path: orbit,
+    },
+];
+
+#[derive(Clone, Debug, Serialize)]
+struct Sample {
+    frame: u32,
+    /// Wall time between the end of the previous frame and the end of this one.
+    cpu_ms: f64,
+    /// Time the frame graph took.
+    graph_ms: f64,
+    gpu_ms: Option<f64>,
+    /// Host memory the Vulkan driver has allocated through us.
+    vk_host_bytes: usize,
+    /// The profiler frame index, for matching up GPU timings that arrive later.
+    #[serde(skip)]
+    profile_index: u64,
+}
+
+/// The JSON results file.
+#[derive(Serialize)]
+struct Results<'a> {
+    scene: &'a str,
+    frames: usize,
+    cpu_ms: Percentiles,
+    samples: &'a [Sample],
+}
+
+#[derive(Serialize)]
+struct Percentiles {
+    mean: f64,
+    p50: f64,
+    p95: f64,
+    p99: f64,
+}
+
+pub struct BenchmarkPlugin {
+    scene_name: String,
+    scenes: Vec<BenchmarkScene>,
+    path: CameraPath,
+    frames: u32,
+    output: PathBuf,
+    camera: Option<Entity>,
+    frame: u32,
+    last_frame_end: Option<Instant>,
+    samples: Vec<Sample>,
+    done: bool,
+}
+
+impl BenchmarkPlugin {
+    pub fn new(
+        scene: impl Into<String>,
+        frames: u32,
+        output: impl Into<PathBuf>,
+    ) -> BenchmarkPlugin {
+        BenchmarkPlugin {
+            scene_name: scene.into(),
+            scenes: Vec::new(),
+            path: orbit,
+            frames,
+            output: output.into(),
+            camera: None,
+            frame: 0,
+            last_frame_end: None,
+            samples: Vec::with_capacity(frames as usize),
+            done: false,
+        }
+    }
+
+    /// Make an app specific scene available, alongside the built-in ones.
+    pub fn with_scene(mut self, scene: BenchmarkScene) -> BenchmarkPlugin {
+        self.scenes.push(scene);
+        return self;
+    }
+
+    fn percentile(sorted: &[f64], p: f64) -> f64 {
+        if sorted.is_empty() {
+            return 0.0;
+        }
+        let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+        return sorted[i];
+    }
+
+    fn write_results(&self) -> std::io::Result<()> {
+        fs::create_dir_all(&self.output)?;
+
+        let mut csv = String::from("frame,cpu_ms,graph_ms,gpu_ms,vk_host_bytes\n");
+        for s in &self.samples {
+            let gpu = s.gpu_ms.map(|g| format!("{g:.4}")).unwrap_or_default();
+            writeln!(
+                csv,
+                "{},{:.4},{:.4},{},{}",
+                s.frame, s.cpu_ms, s.graph_ms, gpu, s.vk_host_bytes
+            )
+            .unwrap();
+        }
+
+        let mut cpu: Vec<f64> = self.samples.iter().map(|s| s.cpu_ms).collect();
+        cpu.sort_by(f64::total_cmp);
+        let mean = cpu.iter().sum::<f64>() / cpu.len().max(1) as f64;
+        let (p50, p95, p99) = (
+            Self::percentile(&cpu, 0.5),
+            Self::percentile(&cpu, 0.95),
+            Self::percentile(&cpu, 0.99),
+        );
+
+        let json = serde_json::to_string_pretty(&Results {
+            scene: &self.scene_name,
+            frames: self.samples.len(),
+            cpu_ms: Percentiles {
+                mean,
+                p50,
+                p95,
+                p99,
+            },
+            samples: &self.samples,
+        })?;
+
+        let stem = format!("benchmark-{}", self.scene_name);
+        fs::write(self.output.join(format!("{stem}.csv")), csv)?;
+        fs::write(self.output.join(format!("{stem}.json")), json)?;
+
+        println!(
+            "Benchmark {}: {} frames, cpu mean {mean:.2} ms, p50 {p50:.2} ms, p95 {p95:.2} ms, p99 {p99:.2} ms",
+            self.scene_name,
+            self.samples.len()
+        );
+
+        return Ok(());
+    }
+
+    /// Fill in GPU timings for recent frames, which resolve a few frames after the fact.
+    fn collect_gpu_times(&mut self) {
+        for frame in profile::history().iter().rev().take(8) {
+            if frame.gpu_spans.is_empty() {
+                continue;
+            }
+
+            let Some(sample) = self
+                .samples
+                .iter_mut()
+                .rev()
+                .find(|s| s.profile_index == frame.index)
+            else {
+                continue;
+            };
+
+            if sample.gpu_ms.is_none() {
+                let start = frame.gpu_spans.iter().map(|s| s.start).min().unwrap();
+                let end = frame.gpu_spans.iter().map(|s| s.end).max().unwrap();
+                sample.gpu_ms = Some((end - start).as_secs_f64() * 1000.0);
+            }
+        }
+    }
+}
+
+impl Plugin for BenchmarkPlugin {
+    fn name(&self) -> &'static str {
+        "benchmark"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, _event_loop: &ActiveEventLoop) {
+        let Some(scene) = self
+            .scenes
+            .iter()
+            .chain(BUILTIN_SCENES)
+            .find(|s| s.name == self.scene_name)
+        else {
+            let names: Vec<_> = self
+                .scenes
+                .iter()
+                .chain(BUILTIN_SCENES)
+                .map(|s| s.name)
+                .collect();
+            eprintln!(
+                "Unknown benchmark scene {}, available: {}",
+                self.scene_name,
+                names.join(", ")
+            );
+            self.done = true;
+            app.request_exit();
+            return;
+        };
+
+        (scene.setup)(app.world_mut());
+        self.path = scene.path;
+        self.camera = Some(app.world_mut().spawn((
+            Transform::IDENTITY,
+            Camera {
+                projection: Projection::Perspective {
+                    fov_y: 60f32.to_radians(),
+                    near: 0.1,
+                    far: 1000.0,
+                },
+                order: 0,
+                active: true,
+                lens: None,
+            },
+        )));
+
+        app.set_fixed_timestep(Some(1.0 / 60.0));
+        profile::set_enabled(true);
+        app.request_redraw();
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        let Some(camera) = self.camera else {
+            return;
+        };
+
+        let t = self.frame.saturating_sub(WARMUP_FRAMES) as f32 / self.frames.max(1) as f32;
+        let (eye, target) = (self.path)(t);
+        if let Ok(mut transform) = app.world_mut().get::<&mut Transform>(camera) {
+            transform.translation = eye;
+            transform.look_at(target, Vec3::Y);
+        }
+    }
+
+    fn post_frame(&mut self, app: &mut WinitApp) {
+        if self.done {
+            return;
+        }
+
+        let now = Instant::now();
+        let cpu_ms = self
+            .last_frame_end
+            .map(|t| (now - t).as_secs_f64() * 1000.0)
+            .unwrap_or_default();
+        self.last_frame_end = Some(now);
+
+        if self.frame >= WARMUP_FRAMES {
+            let graph_ms = app
+                .frame_timings()
+                .iter()
+                .map(|t| t.end)
+                .max()
+                .unwrap_or_default()
+                .as_secs_f64()
+                * 1000.0;
+
+            self.samples.push(Sample {
+                frame: self.frame - WARMUP_FRAMES,
+                cpu_ms,
+                graph_ms,
+                gpu_ms: None,
+                vk_host_bytes: render::vk_host_allocated(),
+                profile_index: app.frame_context().frame,
+            });
+        }
+        self.collect_gpu_times();
+
+        self.frame += 1;
+        if self.samples.len() as u32 >= self.frames {
+            self.done = true;
+            if let Err(e) = self.write_results() {
+                eprintln!("Failed to write benchmark results: {e}");
+            }
+            app.request_exit();
+        } else {
+            app.request_redraw();
+        }
+    }
+}
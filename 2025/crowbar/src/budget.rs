@@ -0,0 +1,467 @@
+//! Memory budgets for the big resource categories.
+//! Subsystems track what they hold with [`BudgetHandle`]s, and register evictors that get asked to free
+//! memory whenever a category goes over its limit. A [`BudgetedCache`] does both for things kept around to reuse,
+//! like loaded textures.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BudgetCategory {
+    Textures,
+    Meshes,
+    RenderTargets,
+    Audio,
+    /// Assets read out of packs and kept decompressed.
+    Assets,
+}
+
+impl BudgetCategory {
+    pub const ALL: [BudgetCategory; 5] = [
+        BudgetCategory::Textures,
+        BudgetCategory::Meshes,
+        BudgetCategory::RenderTargets,
+        BudgetCategory::Audio,
+        BudgetCategory::Assets,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BudgetCategory::Textures => "Textures",
+            BudgetCategory::Meshes => "Meshes",
+            BudgetCategory::RenderTargets => "Render targets",
+            BudgetCategory::Audio => "Audio",
+            BudgetCategory::Assets => "Assets",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Where a category stands, for display.
+#[derive(Clone, Debug, Default)]
+pub struct Usage {
+    pub used: u64,
+    pub limit: Option<u64>,
+    /// Highest `used` has been since startup.
+    pub peak: u64,
+    /// Bytes held per owner, named by the subsystem tracking them.
+    pub by_owner: BTreeMap<&'static str, u64>,
+}
+
+impl Usage {
+    /// How far past the limit this is, 0 if under it or unlimited.
+    pub fn over(&self) -> u64 {
+        self.limit.map_or(0, |l| self.used.saturating_sub(l))
+    }
+
+    /// Fraction of the limit in use, `None` if unlimited.
+    pub fn fraction(&self) -> Option<f32> {
+        self.limit.map(|l| self.used as f32 / l.max(1) as f32)
+    }
+}
+
+/// Asked to free at least this many bytes, by dropping or shrinking its handles.
+pub type Evictor = Box<dyn FnMut(u64) + Send>;
+
+struct EvictorEntry {
+    category: BudgetCategory,
+    owner: &'static str,
+    /// Shared, so [`Budgets::enforce`] can run it without holding the list.
+    evict: Arc<Mutex<Evictor>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    usage: [Usage; BudgetCategory::ALL.len()],
+}
+
+/// Shared between subsystems, cloning gives another handle to the same budgets.
+#[derive(Clone, Default)]
+pub struct Budgets {
+    inner: Arc<Mutex<Inner>>,
+    /// Separate from the usage, so evictors can release handles while they run.
+    evictors: Arc<Mutex<Vec<EvictorEntry>>>,
+}
+
+impl Budgets {
+    pub fn new() -> Budgets {
+        Budgets::default()
+    }
+
+    /// Limit a category to `bytes`, or lift the limit with `None`.
+    pub fn set_limit(&self, category: BudgetCategory, bytes: Option<u64>) {
+        self.inner.lock().unwrap().usage[category.index()].limit = bytes;
+    }
+
+    pub fn usage(&self, category: BudgetCategory) -> Usage {
+        self.inner.lock().unwrap().usage[category.index()].clone()
+    }
+
+    pub fn total_used(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        return inner.usage.iter().map(|u| u.used).sum();
+    }
+
+    /// Start tracking `bytes` held by `owner`. They count until the handle is dropped.
+    pub fn track(&self, category: BudgetCategory, owner: &'static str, bytes: u64) -> BudgetHandle {
+        self.adjust(category, owner, bytes as i64);
+        return BudgetHandle {
+            budgets: self.clone(),
+            category,
+            owner,
+            bytes,
+        };
+    }
+
+    /// Register a callback for when `category` is over its limit.
+    /// Evictors run in registration order until the category is back under.
+    pub fn on_evict(
+        &self,
+        category: BudgetCategory,
+        owner: &'static str,
+        evictor: impl FnMut(u64) + Send + 'static,
+    ) {
+        self.evictors.lock().unwrap().push(EvictorEntry {
+            category,
+            owner,
+            evict: Arc::new(Mutex::new(Box::new(evictor))),
+        });
+    }
+
+    /// Drop every evictor `owner` registered, for subsystems shutting down.
+    pub fn remove_evictors(&self, owner: &'static str) {
+        self.evictors.lock().unwrap().retain(|e| e.owner != owner);
+    }
+
+    /// Run evictors for any category over its limit. Called once per frame by the app.
+    /// Returns the categories still over afterwards.
+    pub fn enforce(&self) -> Vec<BudgetCategory> {
+        // Taken out of the list first, so evictors can register and remove evictors themselves.
+        let evictors: Vec<_> = self
+            .evictors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e.category, e.evict.clone()))
+            .collect();
+        let mut still_over = Vec::new();
+
+        for category in BudgetCategory::ALL {
+            for (_, evict) in evictors.iter().filter(|(c, _)| *c == category) {
+                let over = self.usage_over(category);
+                if over == 0 {
+                    break;
+                }
+                (evict.lock().unwrap())(over);
+            }
+
+            if self.usage_over(category) > 0 {
+                still_over.push(category);
+            }
+        }
+
+        return still_over;
+    }
+
+    fn usage_over(&self, category: BudgetCategory) -> u64 {
+        self.inner.lock().unwrap().usage[category.index()].over()
+    }
+
+    fn adjust(&self, category: BudgetCategory, owner: &'static str, delta: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        let usage = &mut inner.usage[category.index()];
+
+        usage.used = usage.used.saturating_add_signed(delta);
+        usage.peak = usage.peak.max(usage.used);
+
+        let held = usage.by_owner.entry(owner).or_default();
+        *held = held.saturating_add_signed(delta);
+        if *held == 0 {
+            usage.by_owner.remove(owner);
+        }
+    }
+}
+
+/// Memory counted against a budget. Releases it on drop.
+pub struct BudgetHandle {
+    budgets: Budgets,
+    category: BudgetCategory,
+    owner: &'static str,
+    bytes: u64,
+}
+
+impl BudgetHandle {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn category(&self) -> BudgetCategory {
+        self.category
+    }
+
+    /// Change how much this handle counts for, e.g. after streaming in more mips.
+    pub fn resize(&mut self, bytes: u64) {
+        let delta = bytes as i64 - self.bytes as i64;
+        self.budgets.adjust(self.category, self.owner, delta);
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for BudgetHandle {
+    fn drop(&mut self) {
+        self.budgets
+            .adjust(self.category, self.owner, -(self.bytes as i64));
+    }
+}
+
+struct CacheEntry<V> {
+    value: V,
+    handle: BudgetHandle,
+    last_used: u64,
+}
+
+struct CacheInner<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    /// Counts up with every use, for finding the least recently used.
+    clock: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> CacheInner<K, V> {
+    /// Drop the least recently used entries until `bytes` are freed, or there's nothing left.
+    fn evict(&mut self, bytes: u64) {
+        let mut by_age: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(k, e)| (e.last_used, k.clone()))
+            .collect();
+        by_age.sort_by_key(|(used, _)| *used);
+
+        let mut freed = 0;
+        for (_, key) in by_age {
+            if freed >= bytes {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&key) {
+                freed += entry.handle.bytes();
+            }
+        }
+    }
+}
+
+/// Values kept around to be used again, counted against a category's budget. When it goes over, the least
+/// recently used go first. Cheap to clone, clones share the cache.
+pub struct BudgetedCache<K, V> {
+    inner: Arc<Mutex<CacheInner<K, V>>>,
+    budgets: Budgets,
+    category: BudgetCategory,
+    owner: &'static str,
+}
+
+impl<K, V> Clone for BudgetedCache<K, V> {
+    fn clone(&self) -> Self {
+        BudgetedCache {
+            inner: self.inner.clone(),
+            budgets: self.budgets.clone(),
+            category: self.category,
+            owner: self.owner,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static, V: Clone + Send + 'static> BudgetedCache<K, V> {
+    /// A cache counting against `category` as `owner`, registering its evictor with `budgets`.
+    pub fn new(budgets: &Budgets, category: BudgetCategory, owner: &'static str) -> Self {
+        let inner = Arc::new(Mutex::new(CacheInner {
+            entries: HashMap::new(),
+            clock: 0,
+        }));
+        // Weak, so the budgets don't keep the cache alive.
+        let weak: Weak<Mutex<CacheInner<K, V>>> = Arc::downgrade(&inner);
+        budgets.on_evict(category, owner, move |bytes| {
+            if let Some(inner) = weak.upgrade() {
+                inner.lock().unwrap().evict(bytes);
+            }
+        });
+        return BudgetedCache {
+            inner,
+            budgets: budgets.clone(),
+            category,
+            owner,
+        };
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let now = inner.clock;
+        let entry = inner.entries.get_mut(key)?;
+        entry.last_used = now;
+        return Some(entry.value.clone());
+    }
+
+    /// Keep `value`, counting `bytes` for it, in place of anything already under `key`.
+    pub fn insert(&self, key: K, value: V, bytes: u64) {
+        let handle = self.budgets.track(self.category, self.owner, bytes);
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                handle,
+                last_used,
+            },
+        );
+    }
+
+    /// `key`'s value, made with `make` and kept if it isn't there. `make` gives the value and its size.
+    pub fn get_or_insert_with<E>(
+        &self,
+        key: &K,
+        make: impl FnOnce() -> Result<(V, u64), E>,
+    ) -> Result<V, E> {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        // Made without the lock held, so two threads might both make it. The second one wins, which is fine.
+        let (value, bytes) = make()?;
+        self.insert(key.clone(), value.clone(), bytes);
+        return Ok(value);
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let entry = self.inner.lock().unwrap().entries.remove(key)?;
+        return Some(entry.value);
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::{BudgetCategory, BudgetedCache, Budgets};
+
+    #[test]
+    pub fn evicts_down_to_limit() {
+        let budgets = Budgets::new();
+        budgets.set_limit(BudgetCategory::Textures, Some(1000));
+
+        let cache = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..4 {
+            let handle = budgets.track(BudgetCategory::Textures, "textures", 400);
+            cache.lock().unwrap().push(handle);
+        }
+        let _mesh = budgets.track(BudgetCategory::Meshes, "meshes", 5000);
+
+        let usage = budgets.usage(BudgetCategory::Textures);
+        assert_eq!(usage.used, 1600);
+        assert_eq!(usage.over(), 600);
+        assert_eq!(usage.by_owner["textures"], 1600);
+
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        {
+            let cache = cache.clone();
+            let asked = asked.clone();
+            budgets.on_evict(BudgetCategory::Textures, "textures", move |bytes| {
+                asked.lock().unwrap().push(bytes);
+                // Oldest first, until enough is freed.
+                let mut cache = cache.lock().unwrap();
+                let mut freed = 0;
+                while freed < bytes && !cache.is_empty() {
+                    freed += cache.remove(0).bytes();
+                }
+            });
+        }
+
+        assert!(budgets.enforce().is_empty());
+        assert_eq!(*asked.lock().unwrap(), vec![600]);
+
+        let usage = budgets.usage(BudgetCategory::Textures);
+        assert_eq!(usage.used, 800);
+        assert_eq!(usage.peak, 1600);
+        assert_eq!(budgets.total_used(), 5800);
+
+        // Under the limit, nothing gets asked.
+        cache.lock().unwrap()[0].resize(100);
+        budgets.enforce();
+        assert_eq!(asked.lock().unwrap().len(), 1);
+        assert_eq!(budgets.usage(BudgetCategory::Textures).used, 500);
+
+        cache.lock().unwrap().clear();
+        assert!(budgets.usage(BudgetCategory::Textures).by_owner.is_empty());
+    }
+
+    #[test]
+    pub fn caches_evict_least_recently_used() {
+        let budgets = Budgets::new();
+        budgets.set_limit(BudgetCategory::Textures, Some(1000));
+        let cache = BudgetedCache::new(&budgets, BudgetCategory::Textures, "textures");
+        for name in ["a", "b", "c"] {
+            cache.insert(name, name.len(), 400);
+        }
+        // Using a makes b the oldest.
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(
+            budgets.usage(BudgetCategory::Textures).by_owner["textures"],
+            1200
+        );
+
+        assert!(budgets.enforce().is_empty());
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(budgets.usage(BudgetCategory::Textures).used, 800);
+
+        let made = cache.get_or_insert_with(&"d", || Ok::<_, ()>((4, 300)));
+        assert_eq!(made, Ok(4));
+        assert_eq!(cache.get_or_insert_with(&"d", || Err(())), Ok(4));
+        budgets.enforce();
+        assert_eq!(cache.len(), 2);
+
+        drop(cache);
+        assert_eq!(budgets.usage(BudgetCategory::Textures).used, 0);
+    }
+
+    #[test]
+    pub fn evictors_can_register_evictors() {
+        let budgets = Budgets::new();
+        budgets.set_limit(BudgetCategory::Audio, Some(0));
+        let handle = Arc::new(Mutex::new(Some(budgets.track(
+            BudgetCategory::Audio,
+            "audio",
+            10,
+        ))));
+        {
+            let (budgets, handle) = (budgets.clone(), handle.clone());
+            budgets
+                .clone()
+                .on_evict(BudgetCategory::Audio, "audio", move |_| {
+                    // This would deadlock if enforce held the evictors while calling them.
+                    budgets.remove_evictors("audio");
+                    budgets.on_evict(BudgetCategory::Audio, "late", |_| {});
+                    handle.lock().unwrap().take();
+                });
+        }
+        assert!(budgets.enforce().is_empty());
+        assert_eq!(budgets.usage(BudgetCategory::Audio).used, 0);
+        assert_eq!(budgets.evictors.lock().unwrap().len(), 1);
+    }
+}
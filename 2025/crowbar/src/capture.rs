@@ -0,0 +1,454 @@
+//! Frame capture. The renderer reads back presented frames while [`Capture::wants_readback`] says so,
+//! and hands them over here to be encoded off the main thread.
+//! Screenshots go out as PNG (and EXR), video goes through [`video`].
+
+use std::{
+    fs,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use exr::prelude::f16;
+use winit::keyboard::KeyCode;
+
+use self::video::{VideoRecording, VideoSettings};
+use crate::{
+    color::{linear_to_srgb, srgb_to_linear, tonemap},
+    input::Input,
+    jobs::JobSystem,
+    render::graph::readback::ReadbackRequests,
+};
+
+pub mod video;
+
+pub const SCREENSHOT_KEY: KeyCode = KeyCode::F12;
+/// Starts and stops video capture with the default settings.
+pub const VIDEO_KEY: KeyCode = KeyCode::F9;
+
+/// Pixel layouts the readback path can hand us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Rgba8Srgb,
+    Bgra8Srgb,
+    Rgba8Unorm,
+    Bgra8Unorm,
+    /// Linear HDR, as the scene target is before tonemapping.
+    Rgba16Float,
+    /// Two linear channels, as motion vectors are.
+    Rg16Float,
+    /// A depth buffer, read back to look at.
+    Depth32Float,
+    /// The depth of a depth stencil buffer, in the low 24 bits of each 32.
+    Depth24Unorm,
+}
+
+impl CaptureFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            CaptureFormat::Rgba16Float => 8,
+            _ => 4,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, CaptureFormat::Rgba16Float | CaptureFormat::Rg16Float)
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(
+            self,
+            CaptureFormat::Depth32Float | CaptureFormat::Depth24Unorm
+        )
+    }
+}
+
+/// A frame read back from the GPU. Rows are tightly packed, top row first.
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub format: CaptureFormat,
+    pub data: Vec<u8>,
+    /// The app frame this was rendered on.
+    pub frame: u64,
+}
+
+impl CapturedFrame {
+    fn pixels(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.format.bytes_per_pixel())
+    }
+
+    /// The frame as 8 bit sRGB encoded RGBA.
+    pub fn to_srgb8(&self) -> Vec<u8> {
+        let encode = |c: f32| (linear_to_srgb(c) * 255.0 + 0.5) as u8;
+
+        return match self.format {
+            CaptureFormat::Rgba8Srgb => self.data.clone(),
+            CaptureFormat::Bgra8Srgb => self
+                .pixels()
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect(),
+            CaptureFormat::Rgba8Unorm => self
+                .pixels()
+                .flat_map(|p| {
+                    [
+                        encode(p[0] as f32 / 255.0),
+                        encode(p[1] as f32 / 255.0),
+                        encode(p[2] as f32 / 255.0),
+                        p[3],
+                    ]
+                })
+                .collect(),
+            CaptureFormat::Bgra8Unorm => self
+                .pixels()
+                .flat_map(|p| {
+                    [
+                        encode(p[2] as f32 / 255.0),
+                        encode(p[1] as f32 / 255.0),
+                        encode(p[0] as f32 / 255.0),
+                        p[3],
+                    ]
+                })
+                .collect(),
+            // Stretched over whatever range the frame covers, or it'd all come out near white.
+            CaptureFormat::Depth32Float | CaptureFormat::Depth24Unorm => {
+                let depths: Vec<f32> = self.linear_rgba().into_iter().map(|p| p[0]).collect();
+                let finite = depths.iter().copied().filter(|d| d.is_finite());
+                let min = finite.clone().fold(f32::INFINITY, f32::min);
+                let max = finite.fold(f32::NEG_INFINITY, f32::max);
+                let range = (max - min).max(f32::EPSILON);
+                depths
+                    .into_iter()
+                    .flat_map(|d| {
+                        let v = (((d - min) / range).clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+                        [v, v, v, 255]
+                    })
+                    .collect()
+            }
+            // Tonemapped, so what's brighter than white rolls off rather than clipping.
+            CaptureFormat::Rgba16Float | CaptureFormat::Rg16Float => self
+                .linear_rgba()
+                .into_iter()
+                .flat_map(|[r, g, b, a]| {
+                    [
+                        encode(tonemap(r)),
+                        encode(tonemap(g)),
+                        encode(tonemap(b)),
+                        (a.clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+                    ]
+                })
+                .collect(),
+        };
+    }
+
+    /// The frame as linear RGBA floats.
+    pub fn linear_rgba(&self) -> Vec<[f32; 4]> {
+        let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+        let half = |b: &[u8]| f16::from_le_bytes([b[0], b[1]]).to_f32();
+
+        return match self.format {
+            CaptureFormat::Rgba8Srgb => self
+                .pixels()
+                .map(|p| {
+                    [
+                        decode(p[0]),
+                        decode(p[1]),
+                        decode(p[2]),
+                        p[3] as f32 / 255.0,
+                    ]
+                })
+                .collect(),
+            CaptureFormat::Bgra8Srgb => self
+                .pixels()
+                .map(|p| {
+                    [
+                        decode(p[2]),
+                        decode(p[1]),
+                        decode(p[0]),
+                        p[3] as f32 / 255.0,
+                    ]
+                })
+                .collect(),
+            CaptureFormat::Rgba8Unorm => self
+                .pixels()
+                .map(|p| [p[0], p[1], p[2], p[3]].map(|c| c as f32 / 255.0))
+                .collect(),
+            CaptureFormat::Bgra8Unorm => self
+                .pixels()
+                .map(|p| [p[2], p[1], p[0], p[3]].map(|c| c as f32 / 255.0))
+                .collect(),
+            CaptureFormat::Rgba16Float => self
+                .pixels()
+                .map(|p| {
+                    [
+                        half(&p[0..2]),
+                        half(&p[2..4]),
+                        half(&p[4..6]),
+                        half(&p[6..8]),
+                    ]
+                })
+                .collect(),
+            CaptureFormat::Rg16Float => self
+                .pixels()
+                .map(|p| [half(&p[0..2]), half(&p[2..4]), 0.0, 1.0])
+                .collect(),
+            CaptureFormat::Depth32Float => self
+                .pixels()
+                .map(|p| {
+                    let d = f32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                    [d, d, d, 1.0]
+                })
+                .collect(),
+            CaptureFormat::Depth24Unorm => self
+                .pixels()
+                .map(|p| {
+                    let d = (u32::from_le_bytes([p[0], p[1], p[2], p[3]]) & 0xff_ffff) as f32
+                        / 0xff_ffff as f32;
+                    [d, d, d, 1.0]
+                })
+                .collect(),
+        };
+    }
+}
+
+pub fn write_png(path: &Path, frame: &CapturedFrame) -> io::Result<()> {
+    let file = BufWriter::new(fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer
+        .write_image_data(&frame.to_srgb8())
+        .map_err(io::Error::other)?;
+    return writer.finish().map_err(io::Error::other);
+}
+
+fn write_exr(path: &Path, frame: &CapturedFrame) -> io::Result<()> {
+    let pixels = frame.linear_rgba();
+    let width = frame.width as usize;
+
+    exr::prelude::write_rgba_file(path, width, frame.height as usize, |x, y| {
+        let [r, g, b, a] = pixels[y * width + x];
+        (
+            f16::from_f32(r),
+            f16::from_f32(g),
+            f16::from_f32(b),
+            f16::from_f32(a),
+        )
+    })
+    .map_err(io::Error::other)
+}
+
+/// Write a screenshot out as `{prefix}-{time}-{frame}.png`, returning its path.
+fn write_screenshot(
+    dir: &Path,
+    prefix: &str,
+    frame: &CapturedFrame,
+    hdr_exr: bool,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let stem = format!("{prefix}-{stamp}-{}", frame.frame);
+
+    let png_path = dir.join(format!("{stem}.png"));
+    write_png(&png_path, frame)?;
+
+    // Depth keeps its real values in the EXR, the PNG is only stretched to be seen.
+    if hdr_exr && (frame.format.is_hdr() || frame.format.is_depth()) {
+        write_exr(&dir.join(format!("{stem}.exr")), frame)?;
+    }
+
+    return Ok(png_path);
+}
+
+pub struct Capture {
+    pending_screenshots: u32,
+    video: Option<VideoRecording>,
+    /// Render graph textures asked for, from the debug UI, see [`Capture::submit_resource`].
+    resources: ReadbackRequests,
+    pub screenshot_dir: PathBuf,
+    /// Also write an EXR alongside the PNG when the captured frame is HDR.
+    pub hdr_exr: bool,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            pending_screenshots: 0,
+            video: None,
+            resources: ReadbackRequests::new(),
+            screenshot_dir: PathBuf::from("screenshots"),
+            hdr_exr: true,
+        }
+    }
+}
+
+impl Capture {
+    /// Grab the next presented frame.
+    pub fn request_screenshot(&mut self) {
+        self.pending_screenshots += 1;
+    }
+
+    /// Start recording video, ending any recording already going.
+    pub fn start_video(&mut self, settings: VideoSettings) {
+        self.stop_video();
+        log::info!("Recording video to {}", settings.output.display());
+        self.video = Some(VideoRecording::new(settings));
+    }
+
+    /// Stop recording video, blocking until the file is finished.
+    pub fn stop_video(&mut self) {
+        let Some(video) = self.video.take() else {
+            return;
+        };
+
+        let (written, dropped) = (video.frames_written, video.frames_dropped);
+        let output = video.settings().output.clone();
+        match video.finish() {
+            Ok(()) => log::info!(
+                "Saved video to {} ({written} frames, {dropped} dropped)",
+                output.display()
+            ),
+            Err(e) => log::error!("Video capture failed: {e}"),
+        }
+    }
+
+    pub fn is_recording_video(&self) -> bool {
+        self.video.is_some()
+    }
+
+    /// Check hotkeys and advance video time. Called once per frame by the app.
+    pub fn update(&mut self, input: &Input, delta: f32) {
+        if input.key_pressed(SCREENSHOT_KEY) {
+            self.request_screenshot();
+        }
+
+        if input.key_pressed(VIDEO_KEY) {
+            if self.is_recording_video() {
+                self.stop_video();
+            } else {
+                let stamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.start_video(VideoSettings {
+                    output: PathBuf::from(format!("capture-{stamp}.mp4")),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if let Some(video) = &mut self.video {
+            video.advance(delta);
+        }
+    }
+
+    /// Where render graph textures to read back are asked for. The renderer takes them each frame, and hands them
+    /// back to [`Capture::submit_resource`].
+    pub fn resource_requests(&self) -> &ReadbackRequests {
+        &self.resources
+    }
+
+    /// Whether the renderer should read back the frame it's about to present.
+    pub fn wants_readback(&self) -> bool {
+        self.pending_screenshots > 0 || self.video.is_some()
+    }
+
+    /// Hand over a frame read back from the GPU. Encoding happens off the main thread.
+    pub fn submit_frame(&mut self, frame: CapturedFrame, jobs: &JobSystem) {
+        let frame = Arc::new(frame);
+
+        if let Some(video) = &mut self.video {
+            video.submit(frame.clone());
+        }
+
+        if self.pending_screenshots == 0 {
+            return;
+        }
+        self.pending_screenshots -= 1;
+
+        let dir = self.screenshot_dir.clone();
+        let hdr_exr = self.hdr_exr;
+        jobs.spawn("write_screenshot", move || {
+            match write_screenshot(&dir, "screenshot", &frame, hdr_exr) {
+                Ok(path) => log::info!("Saved screenshot to {}", path.display()),
+                Err(e) => log::error!("Failed to save screenshot: {e}"),
+            }
+        });
+    }
+
+    /// Save a render graph texture read back for debugging, named after it.
+    pub fn submit_resource(&mut self, name: &str, frame: CapturedFrame, jobs: &JobSystem) {
+        let dir = self.screenshot_dir.clone();
+        let hdr_exr = self.hdr_exr;
+        let prefix: String = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        jobs.spawn("write_resource_capture", move || {
+            match write_screenshot(&dir, &prefix, &frame, hdr_exr) {
+                Ok(path) => log::info!("Saved {prefix} to {}", path.display()),
+                Err(e) => log::error!("Failed to save {prefix}: {e}"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CaptureFormat, CapturedFrame};
+
+    #[test]
+    pub fn format_conversion() {
+        let frame = |format, data: Vec<u8>| CapturedFrame {
+            width: 1,
+            height: 1,
+            format,
+            data,
+            frame: 0,
+        };
+
+        let bgra = frame(CaptureFormat::Bgra8Srgb, vec![10, 20, 30, 255]);
+        assert_eq!(bgra.to_srgb8(), vec![30, 20, 10, 255]);
+
+        // Linear 0.5 is about 188 in sRGB.
+        let unorm = frame(CaptureFormat::Rgba8Unorm, vec![128, 0, 255, 255]);
+        assert_eq!(unorm.to_srgb8(), vec![188, 0, 255, 255]);
+
+        // HDR values are tonemapped, so 4.0 is nearly but not quite white and 1.0 is well short of it.
+        let half = |v: f32| exr::prelude::f16::from_f32(v).to_le_bytes();
+        let hdr = frame(
+            CaptureFormat::Rgba16Float,
+            [half(4.0), half(0.0), half(1.0), half(1.0)].concat(),
+        );
+        assert_eq!(hdr.to_srgb8(), vec![252, 0, 232, 255]);
+
+        // Depth is stretched over the range there is.
+        let depth = CapturedFrame {
+            width: 3,
+            height: 1,
+            format: CaptureFormat::Depth32Float,
+            data: [0.5f32, 0.75, 1.0]
+                .iter()
+                .flat_map(|d| d.to_le_bytes())
+                .collect(),
+            frame: 0,
+        };
+        let gray: Vec<u8> = depth.to_srgb8().chunks(4).map(|p| p[0]).collect();
+        assert_eq!(gray, vec![0, 128, 255]);
+
+        // The stencil in the top byte doesn't come into it.
+        let packed = frame(
+            CaptureFormat::Depth24Unorm,
+            0xff_ffffffu32.to_le_bytes().to_vec(),
+        );
+        assert_eq!(packed.linear_rgba(), vec![[1.0, 1.0, 1.0, 1.0]]);
+    }
+}
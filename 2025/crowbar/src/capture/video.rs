@@ -0,0 +1,196 @@
+//! Video capture, streaming read back frames into an ffmpeg child process.
+//!
+//! Frames go through a bounded queue to a writer thread. If ffmpeg can't keep up the queue fills
+//! and frames get dropped, rather than the frame loop stalling on a pipe write.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        mpsc::{self, SyncSender, TrySendError},
+    },
+    thread::{self, JoinHandle},
+};
+
+use super::CapturedFrame;
+
+#[derive(Clone, Debug)]
+pub struct VideoSettings {
+    /// The framerate of the video. Frames are duplicated or skipped to hit it regardless of how fast we render.
+    pub fps: u32,
+    pub output: PathBuf,
+    pub ffmpeg: PathBuf,
+    /// Frames that can be waiting on the encoder before we start dropping them.
+    pub queue_depth: usize,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            fps: 60,
+            output: PathBuf::from("capture.mp4"),
+            ffmpeg: PathBuf::from("ffmpeg"),
+            queue_depth: 8,
+        }
+    }
+}
+
+/// A frame and how many times in a row it goes into the video.
+type QueuedFrame = (Arc<CapturedFrame>, u32);
+
+struct Encoder {
+    width: u32,
+    height: u32,
+    tx: SyncSender<QueuedFrame>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl Encoder {
+    fn spawn(settings: &VideoSettings, width: u32, height: u32) -> io::Result<Encoder> {
+        let mut child = Command::new(&settings.ffmpeg)
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{width}x{height}")])
+            .args(["-r", &settings.fps.to_string()])
+            .args([
+                "-i", "-", "-c:v", "libx264", "-pix_fmt", "yuv420p", "-crf", "18",
+            ])
+            .arg(&settings.output)
+            .stdin(Stdio::piped())
+            .spawn()?;
+
+        let (tx, rx) = mpsc::sync_channel::<QueuedFrame>(settings.queue_depth);
+
+        // A plain thread rather than a job, it spends its life blocked on the pipe.
+        let writer = thread::Builder::new()
+            .name("crowbar-video-writer".into())
+            .spawn(move || {
+                let res = (|| {
+                    let mut stdin = child.stdin.take().unwrap();
+                    for (frame, repeats) in rx {
+                        let pixels = frame.to_srgb8();
+                        for _ in 0..repeats {
+                            stdin.write_all(&pixels)?;
+                        }
+                    }
+                    return Ok(());
+                })();
+
+                // stdin is dropped by now, which is ffmpeg's cue to finish the file.
+                finish(child)?;
+                return res;
+            })?;
+
+        return Ok(Encoder {
+            width,
+            height,
+            tx,
+            writer,
+        });
+    }
+}
+
+fn finish(mut child: Child) -> io::Result<()> {
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+    }
+    return Ok(());
+}
+
+/// An in progress recording.
+pub struct VideoRecording {
+    settings: VideoSettings,
+    /// Started on the first frame, since that's when we know the size.
+    encoder: Option<Encoder>,
+    /// Seconds of video owed since the last frame went out.
+    time_debt: f32,
+    pub frames_written: u64,
+    pub frames_dropped: u64,
+    failed: bool,
+}
+
+impl VideoRecording {
+    pub fn new(settings: VideoSettings) -> VideoRecording {
+        VideoRecording {
+            settings,
+            encoder: None,
+            time_debt: 0.0,
+            frames_written: 0,
+            frames_dropped: 0,
+            failed: false,
+        }
+    }
+
+    pub fn settings(&self) -> &VideoSettings {
+        &self.settings
+    }
+
+    /// Account for `delta` seconds of app time passing.
+    pub fn advance(&mut self, delta: f32) {
+        self.time_debt += delta;
+    }
+
+    /// Queue a rendered frame, as many times as the elapsed time calls for. Never blocks.
+    pub fn submit(&mut self, frame: Arc<CapturedFrame>) {
+        if self.failed {
+            return;
+        }
+
+        let interval = 1.0 / self.settings.fps as f32;
+        let repeats = (self.time_debt / interval) as u32;
+        if repeats == 0 {
+            return;
+        }
+        self.time_debt -= repeats as f32 * interval;
+
+        if self.encoder.is_none() {
+            match Encoder::spawn(&self.settings, frame.width, frame.height) {
+                Ok(e) => self.encoder = Some(e),
+                Err(e) => {
+                    log::error!("Couldn't start ffmpeg for video capture: {e}");
+                    self.failed = true;
+                    return;
+                }
+            }
+        }
+        let encoder = self.encoder.as_ref().unwrap();
+
+        // todo: restart into a new file on resize, rather than dropping everything after it.
+        if frame.width != encoder.width || frame.height != encoder.height {
+            self.frames_dropped += repeats as u64;
+            return;
+        }
+
+        match encoder.tx.try_send((frame, repeats)) {
+            Ok(()) => self.frames_written += repeats as u64,
+            Err(TrySendError::Full(_)) => self.frames_dropped += repeats as u64,
+            Err(TrySendError::Disconnected(_)) => {
+                log::warn!("Video writer stopped early, ending capture.");
+                self.failed = true;
+            }
+        }
+    }
+
+    /// Flush everything queued and wait for ffmpeg to finish the file.
+    pub fn finish(self) -> io::Result<()> {
+        let Some(encoder) = self.encoder else {
+            return Ok(());
+        };
+
+        drop(encoder.tx);
+        return encoder
+            .writer
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Video writer panicked")));
+    }
+}
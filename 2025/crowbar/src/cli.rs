@@ -0,0 +1,149 @@
+//! Command line arguments.
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: crowbar [options]
+
+Options:
+  --benchmark <scene>         Run a benchmark scene and write timings on exit
+  --benchmark-frames <n>      Frames to record in benchmark mode (default 1000)
+  --benchmark-output <dir>    Where benchmark results go (default .)
+  --record <file>             Record input to a file for replaying later
+  --replay <file>             Replay recorded input, then exit
+  --seed <n>                  Fix the simulation seed
+  --config <file>             Config file to load cvars from and save them to
+                              (default config.cfg in the app's config directory)
+  --set <name> <value>        Set a cvar, after the config file is loaded
+  --editor                    Start with the editor panels and gizmo
+  --game <lib>                Run game code from a dynamic library, reloading it when rebuilt
+  --diag                      Print a report on the Vulkan setup, for bug reports, then exit
+  --diag-output <file>        Write the --diag report to a file as well
+  --help                      Show this";
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub benchmark: Option<String>,
+    pub benchmark_frames: u32,
+    pub benchmark_output: PathBuf,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub seed: Option<u64>,
+    /// `None` for the default, in the app's config directory.
+    pub config: Option<PathBuf>,
+    /// Cvars to set, in order.
+    pub set: Vec<(String, String)>,
+    pub editor: bool,
+    /// Game library to load with hot reloading.
+    pub game: Option<PathBuf>,
+    pub diag: bool,
+    pub diag_output: Option<PathBuf>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            benchmark: None,
+            benchmark_frames: 1000,
+            benchmark_output: PathBuf::from("."),
+            record: None,
+            replay: None,
+            seed: None,
+            config: None,
+            set: Vec::new(),
+            editor: false,
+            game: None,
+            diag: false,
+            diag_output: None,
+        }
+    }
+}
+
+impl Args {
+    /// Parse the process arguments. On error, the message is meant for the user alongside [`USAGE`].
+    pub fn parse() -> Result<Args, String> {
+        Args::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+
+            match arg.as_str() {
+                "--benchmark" => parsed.benchmark = Some(value("--benchmark")?),
+                "--benchmark-frames" => {
+                    let v = value("--benchmark-frames")?;
+                    parsed.benchmark_frames = v
+                        .parse()
+                        .map_err(|_| format!("--benchmark-frames: {v} isn't a frame count"))?;
+                }
+                "--benchmark-output" => {
+                    parsed.benchmark_output = value("--benchmark-output")?.into()
+                }
+                "--record" => parsed.record = Some(value("--record")?.into()),
+                "--replay" => parsed.replay = Some(value("--replay")?.into()),
+                "--seed" => {
+                    let v = value("--seed")?;
+                    parsed.seed = Some(
+                        v.parse()
+                            .map_err(|_| format!("--seed: {v} isn't a number"))?,
+                    );
+                }
+                "--config" => parsed.config = Some(value("--config")?.into()),
+                "--set" => {
+                    let name = value("--set")?;
+                    let v = value("--set")?;
+                    parsed.set.push((name, v));
+                }
+                "--editor" => parsed.editor = true,
+                "--game" => parsed.game = Some(value("--game")?.into()),
+                "--diag" => parsed.diag = true,
+                "--diag-output" => {
+                    parsed.diag = true;
+                    parsed.diag_output = Some(value("--diag-output")?.into());
+                }
+                "--help" | "-h" => return Err(String::new()),
+                other => return Err(format!("Unknown argument {other}")),
+            }
+        }
+
+        if parsed.record.is_some() && parsed.replay.is_some() {
+            return Err("--record and --replay can't be used together".into());
+        }
+
+        return Ok(parsed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Args;
+
+    fn parse(s: &str) -> Result<Args, String> {
+        Args::parse_from(s.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    pub fn benchmark_args() {
+        let args = parse("--benchmark grid --benchmark-frames 20").unwrap();
+        assert_eq!(args.benchmark.as_deref(), Some("grid"));
+        assert_eq!(args.benchmark_frames, 20);
+
+        assert!(parse("--benchmark").is_err());
+        assert!(parse("--benchmark-frames lots").is_err());
+        assert!(parse("--nonsense").is_err());
+        assert!(parse("--record a --replay b").is_err());
+
+        let args = parse("--set r_vsync 0 --set r_render_scale 0.5").unwrap();
+        assert_eq!(args.set.len(), 2);
+        assert_eq!(args.set[1], ("r_render_scale".into(), "0.5".into()));
+        assert!(parse("--set r_vsync").is_err());
+
+        let args = parse("--diag-output report.txt").unwrap();
+        assert!(args.diag);
+        assert_eq!(args.diag_output, Some("report.txt".into()));
+    }
+}
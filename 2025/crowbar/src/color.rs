@@ -0,0 +1,265 @@
+//! Colors, in two flavors so the type says which space the numbers are in.
+//!
+//! [`Color`] is sRGB encoded, the way color pickers, hex codes and image files have it.
+//! [`LinearColor`] is linear, the way lighting, blending and shaders need it.
+//! Convert at the edges, and do math only on linear colors.
+
+use serde::{Deserialize, Serialize};
+
+/// sRGB transfer function, for one channel in 0..1.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        return c * 12.92;
+    }
+    return 1.055 * c.powf(1.0 / 2.4) - 0.055;
+}
+
+/// Inverse sRGB transfer function, for one channel in 0..1.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        return c / 12.92;
+    }
+    return ((c + 0.055) / 1.055).powf(2.4);
+}
+
+/// Filmic tonemapping for one channel, from linear HDR to linear 0..1: Narkowicz's fit of the ACES curve. Rolls off
+/// towards white rather than clipping, for showing HDR frames on SDR displays and in SDR images.
+pub fn tonemap(c: f32) -> f32 {
+    let c = c.max(0.0);
+    return ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0);
+}
+
+/// An sRGB encoded color. Alpha is never encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+/// A linear RGB color. Channels can go past 1 for HDR. Alpha is straight, not premultiplied.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinearColor {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+macro_rules! color_constants {
+    ($t:ident) => {
+        impl $t {
+            pub const WHITE: $t = $t::rgb(1.0, 1.0, 1.0);
+            pub const BLACK: $t = $t::rgb(0.0, 0.0, 0.0);
+            pub const TRANSPARENT: $t = $t::rgba(0.0, 0.0, 0.0, 0.0);
+            pub const RED: $t = $t::rgb(1.0, 0.0, 0.0);
+            pub const GREEN: $t = $t::rgb(0.0, 1.0, 0.0);
+            pub const BLUE: $t = $t::rgb(0.0, 0.0, 1.0);
+            pub const YELLOW: $t = $t::rgb(1.0, 1.0, 0.0);
+            pub const CYAN: $t = $t::rgb(0.0, 1.0, 1.0);
+            pub const MAGENTA: $t = $t::rgb(1.0, 0.0, 1.0);
+
+            pub const fn rgb(r: f32, g: f32, b: f32) -> $t {
+                $t { r, g, b, a: 1.0 }
+            }
+
+            pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> $t {
+                $t { r, g, b, a }
+            }
+
+            pub const fn with_alpha(self, a: f32) -> $t {
+                $t { a, ..self }
+            }
+
+            pub const fn to_array(self) -> [f32; 4] {
+                [self.r, self.g, self.b, self.a]
+            }
+
+            pub const fn from_array([r, g, b, a]: [f32; 4]) -> $t {
+                $t { r, g, b, a }
+            }
+        }
+
+        impl Default for $t {
+            fn default() -> Self {
+                $t::WHITE
+            }
+        }
+
+        impl From<$t> for [f32; 4] {
+            fn from(c: $t) -> [f32; 4] {
+                c.to_array()
+            }
+        }
+    };
+}
+
+color_constants!(Color);
+color_constants!(LinearColor);
+
+impl Color {
+    /// A gray of sRGB brightness `v`.
+    pub const fn gray(v: f32) -> Color {
+        Color::rgb(v, v, v)
+    }
+
+    pub fn from_rgba8([r, g, b, a]: [u8; 4]) -> Color {
+        Color::rgba(r as f32, g as f32, b as f32, a as f32) * (1.0 / 255.0)
+    }
+
+    pub fn to_rgba8(self) -> [u8; 4] {
+        self.to_array()
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+    }
+
+    /// From `0xRRGGBB`, like a CSS color.
+    pub fn hex(rgb: u32) -> Color {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        Color::from_rgba8([r, g, b, 255])
+    }
+
+    /// From hue in degrees, and saturation and value in 0..1.
+    pub fn hsv(h: f32, s: f32, v: f32) -> Color {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let c = v * s;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        Color::rgb(r + m, g + m, b + m)
+    }
+
+    /// Hue in degrees, saturation and value in 0..1. Grays have a hue of 0.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == self.r {
+            60.0 * ((self.g - self.b) / delta).rem_euclid(6.0)
+        } else if max == self.g {
+            60.0 * ((self.b - self.r) / delta + 2.0)
+        } else {
+            60.0 * ((self.r - self.g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        return (h, s, max);
+    }
+
+    pub fn to_linear(self) -> LinearColor {
+        LinearColor::rgba(
+            srgb_to_linear(self.r),
+            srgb_to_linear(self.g),
+            srgb_to_linear(self.b),
+            self.a,
+        )
+    }
+}
+
+impl LinearColor {
+    /// Encode for display. Anything past 1 clips, tonemap HDR colors first.
+    pub fn to_srgb(self) -> Color {
+        Color::rgba(
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+            self.a,
+        )
+    }
+
+    /// Perceived brightness, with Rec. 709 weights.
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn lerp(self, other: LinearColor, t: f32) -> LinearColor {
+        self + (other - self) * t
+    }
+
+    pub fn to_vec3(self) -> glam::Vec3 {
+        glam::Vec3::new(self.r, self.g, self.b)
+    }
+
+    pub fn to_vec4(self) -> glam::Vec4 {
+        glam::Vec4::from_array(self.to_array())
+    }
+}
+
+impl From<Color> for LinearColor {
+    fn from(c: Color) -> LinearColor {
+        c.to_linear()
+    }
+}
+
+impl From<LinearColor> for Color {
+    fn from(c: LinearColor) -> Color {
+        c.to_srgb()
+    }
+}
+
+// Scaling an sRGB color is only right for alpha, but it's handy for `from_rgba8`.
+impl std::ops::Mul<f32> for Color {
+    type Output = Color;
+
+    fn mul(self, k: f32) -> Color {
+        Color::rgba(self.r * k, self.g * k, self.b * k, self.a * k)
+    }
+}
+
+impl std::ops::Mul<f32> for LinearColor {
+    type Output = LinearColor;
+
+    /// Scales the color, alpha included.
+    fn mul(self, k: f32) -> LinearColor {
+        LinearColor::rgba(self.r * k, self.g * k, self.b * k, self.a * k)
+    }
+}
+
+impl std::ops::Add for LinearColor {
+    type Output = LinearColor;
+
+    fn add(self, o: LinearColor) -> LinearColor {
+        LinearColor::rgba(self.r + o.r, self.g + o.g, self.b + o.b, self.a + o.a)
+    }
+}
+
+impl std::ops::Sub for LinearColor {
+    type Output = LinearColor;
+
+    fn sub(self, o: LinearColor) -> LinearColor {
+        LinearColor::rgba(self.r - o.r, self.g - o.g, self.b - o.b, self.a - o.a)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Color, LinearColor};
+
+    #[test]
+    pub fn conversions() {
+        let c = Color::from_rgba8([188, 0, 255, 128]);
+        let linear = c.to_linear();
+        assert!((linear.r - 0.5).abs() < 0.01);
+        assert_eq!(linear.a, c.a);
+        assert_eq!(linear.to_srgb().to_rgba8(), [188, 0, 255, 128]);
+
+        assert_eq!(Color::hex(0xff8000).to_rgba8(), [255, 128, 0, 255]);
+        assert_eq!(LinearColor::WHITE.to_srgb().to_rgba8(), [255; 4]);
+
+        for (h, s, v) in [(0.0, 1.0, 1.0), (120.0, 0.5, 0.5), (300.0, 0.25, 1.0)] {
+            let (h2, s2, v2) = Color::hsv(h, s, v).to_hsv();
+            assert!((h - h2).abs() < 1e-3 && (s - s2).abs() < 1e-4 && (v - v2).abs() < 1e-4);
+        }
+        assert_eq!(Color::hsv(240.0, 1.0, 1.0), Color::BLUE);
+    }
+}
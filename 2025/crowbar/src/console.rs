@@ -0,0 +1,506 @@
+//! The drop-down console, toggled with `~`. Commands get registered by name and poke at the app,
+//! and everything logged through the `log` crate shows up in its output.
+//!
+//! The console draws through the debug overlay's egui context, but works with the overlay hidden.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use winit::keyboard::KeyCode;
+
+use crate::app::WinitApp;
+
+pub const TOGGLE_KEY: KeyCode = KeyCode::Backquote;
+
+/// Lines of output kept around for the console to show.
+const MAX_LINES: usize = 1024;
+
+pub struct LogLine {
+    pub level: Level,
+    pub text: String,
+}
+
+static LINES: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+/// Add a line to the console output, without it going through the logger.
+pub fn print_line(level: Level, text: impl Into<String>) {
+    let mut lines = LINES.lock().unwrap();
+    if lines.len() == MAX_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(LogLine {
+        level,
+        text: text.into(),
+    });
+}
+
+pub fn clear_output() {
+    LINES.lock().unwrap().clear();
+}
+
+/// Where log lines get written as well, once there's somewhere.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Sends log records to stderr, the console, and the log file if there is one.
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let text = format!("[{}] {}", record.target(), record.args());
+        eprintln!("{} {text}", record.level());
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = writeln!(file, "{} {text}", record.level());
+        }
+        print_line(record.level(), text);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Write the log to `latest.log` in `dir` from now on, starting with what's been logged already. Replaces the
+/// last run's.
+pub fn log_to_file(dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("latest.log");
+    let mut file = File::create(&path)?;
+    for line in LINES.lock().unwrap().iter() {
+        writeln!(file, "{} {}", line.level, line.text)?;
+    }
+    *LOG_FILE.lock().unwrap() = Some(file);
+    return Ok(path);
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+/// Install the console as the `log` backend. Does nothing if there's a logger already.
+pub fn install_logger(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// A console command. Gets the arguments after the command name, and returns text to print.
+pub type CommandFn = Box<dyn FnMut(&mut WinitApp, &[&str]) -> Result<String, String>>;
+
+struct Command {
+    help: &'static str,
+    run: CommandFn,
+}
+
+/// Split a command line into words. Double quotes group words, `\"` is a literal quote.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => word.extend(chars.next()),
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+    return words;
+}
+
+fn common_prefix<'a>(mut names: impl Iterator<Item = &'a str>) -> &'a str {
+    let Some(first) = names.next() else {
+        return "";
+    };
+
+    let mut len = first.len();
+    for name in names {
+        len = first
+            .char_indices()
+            .zip(name.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    return &first[..len];
+}
+
+#[derive(Default)]
+pub struct Console {
+    commands: BTreeMap<&'static str, Command>,
+    history: Vec<String>,
+    /// Where Up/Down has got to in the history, `None` when editing a fresh line.
+    history_pos: Option<usize>,
+    input: String,
+    /// Lines entered this frame, waiting for the app to run them.
+    submitted: Vec<String>,
+    /// Set when we change the input, so the cursor gets moved to the end of it.
+    input_changed: bool,
+    /// Cvar names, for completion. Kept in sync by the app.
+    variables: Vec<&'static str>,
+    pub open: bool,
+}
+
+impl Console {
+    /// Register a command. Replaces any existing command with the same name.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        run: impl FnMut(&mut WinitApp, &[&str]) -> Result<String, String> + 'static,
+    ) {
+        self.commands.insert(
+            name,
+            Command {
+                help,
+                run: Box::new(run),
+            },
+        );
+    }
+
+    pub fn command_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.commands.keys().copied()
+    }
+
+    pub fn variable_count(&self) -> usize {
+        self.variables.len()
+    }
+
+    pub fn set_variable_names(&mut self, mut names: Vec<&'static str>) {
+        names.sort_unstable();
+        self.variables = names;
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// The engine's own commands.
+    pub fn register_builtins(&mut self) {
+        self.register(
+            "help",
+            "List commands, or show help for one",
+            |app, args| {
+                let console = app.console_mut();
+                if let Some(name) = args.first() {
+                    return match console.commands.get(name) {
+                        Some(c) => Ok(format!("{name}: {}", c.help)),
+                        None => Err(format!("No command named {name}")),
+                    };
+                }
+
+                let mut out = String::new();
+                for (name, c) in &console.commands {
+                    out.push_str(&format!("{name}: {}\n", c.help));
+                }
+                return Ok(out.trim_end().to_owned());
+            },
+        );
+        self.register("clear", "Clear the console output", |_, _| {
+            clear_output();
+            Ok(String::new())
+        });
+        self.register("echo", "Print the arguments", |_, args| Ok(args.join(" ")));
+        self.register("quit", "Exit the app", |app, _| {
+            app.request_exit();
+            Ok(String::new())
+        });
+        self.register("set", "Set a cvar: set <name> <value>", |app, args| {
+            let [name, value] = args else {
+                return Err("Usage: set <name> <value>".into());
+            };
+            app.cvars_mut().set_from_str(name, value)?;
+            app.cvars().describe(name)
+        });
+        self.register("reset", "Reset a cvar to its default", |app, args| {
+            let [name] = args else {
+                return Err("Usage: reset <name>".into());
+            };
+            app.cvars_mut().reset(name)?;
+            app.cvars().describe(name)
+        });
+        self.register(
+            "cvarlist",
+            "List cvars, optionally only those starting with a prefix",
+            |app, args| {
+                let prefix = args.first().copied().unwrap_or("");
+                let mut names: Vec<_> = app
+                    .cvars()
+                    .names()
+                    .filter(|n| n.starts_with(prefix))
+                    .collect();
+                names.sort_unstable();
+
+                let mut out = String::new();
+                for name in names {
+                    out.push_str(&app.cvars().describe(name)?);
+                    out.push('\n');
+                }
+                return Ok(out.trim_end().to_owned());
+            },
+        );
+        self.register(
+            "screenshot",
+            "Save a screenshot of the next frame",
+            |app, _| {
+                app.capture_mut().request_screenshot();
+                Ok(String::new())
+            },
+        );
+        self.register(
+            "save_state",
+            "Save a snapshot of the world: save_state <file>",
+            |app, args| {
+                let [path] = args else {
+                    return Err("Usage: save_state <file>".into());
+                };
+                app.save_snapshot(Path::new(path))
+                    .map_err(|e| format!("Couldn't save {path}: {e}"))?;
+                Ok(format!("Saved {path}"))
+            },
+        );
+        self.register(
+            "load_state",
+            "Restore a snapshot saved with save_state: load_state <file>",
+            |app, args| {
+                let [path] = args else {
+                    return Err("Usage: load_state <file>".into());
+                };
+                app.load_snapshot(Path::new(path))
+                    .map_err(|e| format!("Couldn't load {path}: {e}"))?;
+                Ok(format!("Loaded {path}"))
+            },
+        );
+    }
+
+    /// Complete the command or cvar name being typed, as far as it's unambiguous.
+    /// Returns the candidates when there's more than one.
+    pub fn complete(&mut self) -> Vec<&'static str> {
+        if self.input.contains(char::is_whitespace) {
+            return Vec::new();
+        }
+
+        let mut matches: Vec<&'static str> = self
+            .command_names()
+            .chain(self.variables.iter().copied())
+            .filter(|n| n.starts_with(self.input.as_str()))
+            .collect();
+        matches.sort_unstable();
+
+        match matches.as_slice() {
+            [] => {}
+            [only] => self.input = format!("{only} "),
+            many => self.input = common_prefix(many.iter().copied()).to_owned(),
+        }
+        self.input_changed = true;
+
+        if matches.len() > 1 {
+            return matches;
+        }
+        return Vec::new();
+    }
+
+    fn submit(&mut self) {
+        let line = std::mem::take(&mut self.input);
+        self.history_pos = None;
+        if line.trim().is_empty() {
+            return;
+        }
+
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        self.submitted.push(line);
+    }
+
+    fn step_history(&mut self, back: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        self.history_pos = match (self.history_pos, back) {
+            (None, true) => Some(self.history.len() - 1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (_, false) => None,
+        };
+        self.input = self
+            .history_pos
+            .map(|i| self.history[i].clone())
+            .unwrap_or_default();
+        self.input_changed = true;
+    }
+
+    /// Lines entered since the last call, for [`execute`].
+    pub fn take_submitted(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.submitted)
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        if !self.open {
+            return;
+        }
+
+        let input_id = egui::Id::new("crowbar_console_input");
+
+        egui::TopBottomPanel::top("crowbar_console")
+            .resizable(true)
+            .default_height(300.0)
+            .show(ctx, |ui| {
+                let (tab, up, down) = ui.input_mut(|i| {
+                    (
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                    )
+                });
+                if tab {
+                    let candidates = self.complete();
+                    if !candidates.is_empty() {
+                        print_line(Level::Info, candidates.join("  "));
+                    }
+                }
+                if up || down {
+                    self.step_history(up);
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(ui.available_height() - 28.0)
+                    .stick_to_bottom(true)
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for line in LINES.lock().unwrap().iter() {
+                            let color = match line.level {
+                                Level::Error => egui::Color32::from_rgb(240, 90, 90),
+                                Level::Warn => egui::Color32::from_rgb(230, 190, 80),
+                                Level::Info => ui.visuals().text_color(),
+                                Level::Debug | Level::Trace => egui::Color32::GRAY,
+                            };
+                            ui.label(egui::RichText::new(&line.text).monospace().color(color));
+                        }
+                    });
+
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.input)
+                        .id(input_id)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(f32::INFINITY)
+                        .lock_focus(true),
+                );
+
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    self.submit();
+                }
+                response.request_focus();
+
+                if std::mem::take(&mut self.input_changed)
+                    && let Some(mut state) = egui::TextEdit::load_state(ctx, input_id)
+                {
+                    let end = egui::text::CCursor::new(self.input.chars().count());
+                    state
+                        .cursor
+                        .set_char_range(Some(egui::text::CCursorRange::one(end)));
+                    state.store(ctx, input_id);
+                }
+            });
+    }
+}
+
+/// Run a command line against the app, printing the result to the console.
+/// A cvar name on its own describes it, and followed by a value sets it.
+pub fn execute(app: &mut WinitApp, line: &str) {
+    print_line(Level::Info, format!("> {line}"));
+
+    let words = tokenize(line);
+    let Some((name, args)) = words.split_first() else {
+        return;
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    // Take the command out while it runs, so it can have the app (console included) to itself.
+    let res = match app.console_mut().commands.remove_entry(name.as_str()) {
+        Some((name, mut command)) => {
+            let res = (command.run)(app, &args);
+            app.console_mut().commands.entry(name).or_insert(command);
+            res
+        }
+        None if app.cvars().contains(name) => match args.as_slice() {
+            [] => app.cvars().describe(name),
+            [value] => app
+                .cvars_mut()
+                .set_from_str(name, value)
+                .map(|()| String::new()),
+            _ => Err(format!("Usage: {name} <value>")),
+        },
+        None => Err(format!("Unknown command {name}")),
+    };
+
+    match res {
+        Ok(out) if out.is_empty() => {}
+        Ok(out) => print_line(Level::Info, out),
+        Err(e) => print_line(Level::Error, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Console, tokenize};
+
+    #[test]
+    pub fn tokenizing() {
+        assert_eq!(tokenize("  echo a   b "), vec!["echo", "a", "b"]);
+        assert_eq!(
+            tokenize(r#"say "hello there" "a \"quote\"" """#),
+            vec!["say", "hello there", r#"a "quote""#, ""]
+        );
+    }
+
+    #[test]
+    pub fn completion() {
+        let mut console = Console::default();
+        console.register("r_vsync", "", |_, _| Ok(String::new()));
+        console.register("r_render_scale", "", |_, _| Ok(String::new()));
+        console.register("quit", "", |_, _| Ok(String::new()));
+
+        console.input = "r".into();
+        assert_eq!(console.complete(), vec!["r_render_scale", "r_vsync"]);
+        assert_eq!(console.input, "r_");
+
+        console.input = "r_v".into();
+        assert!(console.complete().is_empty());
+        assert_eq!(console.input, "r_vsync ");
+    }
+}
@@ -1,2 +1,62 @@
-pub const APPLICATION_VERSION: u32 = ash::vk::make_api_version(1, 0, 0, 1);
-pub const ENGINE_VERSION: u32 = ash::vk::make_api_version(1, 0, 0, 1);
+//! Version and build info, filled in at build time by `build.rs`.
+
+use std::fmt;
+
+/// Parse a version component at compile time.
+const fn parse_u32(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "Version component isn't a number"
+        );
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    return value;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// Short commit hash, `unknown` outside a git checkout.
+    pub git_hash: &'static str,
+    /// UTC, as `YYYY-MM-DD`.
+    pub build_date: &'static str,
+    /// Cargo profile, `debug` or `release`.
+    pub profile: &'static str,
+}
+
+impl BuildInfo {
+    /// The version packed for Vulkan.
+    pub const fn vk_version(&self) -> u32 {
+        ash::vk::make_api_version(0, self.major, self.minor, self.patch)
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Crowbar {} ({}, {}, {})",
+            self.version, self.git_hash, self.build_date, self.profile
+        )
+    }
+}
+
+pub const BUILD: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    major: parse_u32(env!("CARGO_PKG_VERSION_MAJOR")),
+    minor: parse_u32(env!("CARGO_PKG_VERSION_MINOR")),
+    patch: parse_u32(env!("CARGO_PKG_VERSION_PATCH")),
+    git_hash: env!("CROWBAR_GIT_HASH"),
+    build_date: env!("CROWBAR_BUILD_DATE"),
+    profile: env!("CROWBAR_PROFILE"),
+};
+
+pub const ENGINE_VERSION: u32 = BUILD.vk_version();
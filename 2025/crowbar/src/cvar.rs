@@ -0,0 +1,581 @@
+//! Console variables. Named, typed settings that systems read instead of hardcoding,
+//! settable from the console, the command line and the config file.
+
+use std::{collections::HashMap, fmt, fs, io, marker::PhantomData, ops::BitOr, path::Path};
+
+use crate::console::tokenize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CVarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl fmt::Display for CVarValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CVarValue::Bool(v) => write!(f, "{}", *v as u8),
+            CVarValue::Int(v) => write!(f, "{v}"),
+            CVarValue::Float(v) => write!(f, "{v}"),
+            CVarValue::String(v) => write!(f, "{v:?}"),
+        }
+    }
+}
+
+impl CVarValue {
+    /// Parse `text` as the same type as `self`.
+    fn parse_like(&self, text: &str) -> Result<CVarValue, String> {
+        let bad = || format!("{text} isn't a valid {}", self.type_name());
+        return match self {
+            CVarValue::Bool(_) => match text {
+                "1" | "true" | "on" => Ok(CVarValue::Bool(true)),
+                "0" | "false" | "off" => Ok(CVarValue::Bool(false)),
+                _ => Err(bad()),
+            },
+            CVarValue::Int(_) => text.parse().map(CVarValue::Int).map_err(|_| bad()),
+            CVarValue::Float(_) => text.parse().map(CVarValue::Float).map_err(|_| bad()),
+            CVarValue::String(_) => Ok(CVarValue::String(text.to_owned())),
+        };
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            CVarValue::Bool(_) => "bool",
+            CVarValue::Int(_) => "integer",
+            CVarValue::Float(_) => "number",
+            CVarValue::String(_) => "string",
+        }
+    }
+}
+
+/// Rust types that can back a cvar.
+pub trait CVarType: Sized + 'static {
+    fn into_value(self) -> CVarValue;
+    fn from_value(value: &CVarValue) -> Self;
+}
+
+impl CVarType for bool {
+    fn into_value(self) -> CVarValue {
+        CVarValue::Bool(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Self {
+        matches!(value, CVarValue::Bool(true))
+    }
+}
+
+impl CVarType for i64 {
+    fn into_value(self) -> CVarValue {
+        CVarValue::Int(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Self {
+        match value {
+            CVarValue::Int(v) => *v,
+            _ => 0,
+        }
+    }
+}
+
+impl CVarType for f32 {
+    fn into_value(self) -> CVarValue {
+        CVarValue::Float(self as f64)
+    }
+
+    fn from_value(value: &CVarValue) -> Self {
+        match value {
+            CVarValue::Float(v) => *v as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+impl CVarType for String {
+    fn into_value(self) -> CVarValue {
+        CVarValue::String(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Self {
+        match value {
+            CVarValue::String(v) => v.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CVarFlags(u32);
+
+impl CVarFlags {
+    pub const NONE: CVarFlags = CVarFlags(0);
+    /// Only changeable with `sv_cheats` on.
+    pub const CHEAT: CVarFlags = CVarFlags(1);
+    /// Saved to the config file on exit.
+    pub const ARCHIVE: CVarFlags = CVarFlags(2);
+
+    pub fn contains(&self, other: CVarFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for CVarFlags {
+    type Output = CVarFlags;
+
+    fn bitor(self, rhs: CVarFlags) -> CVarFlags {
+        CVarFlags(self.0 | rhs.0)
+    }
+}
+
+/// A typed handle to a registered cvar, for reading it without a name lookup.
+pub struct CVar<T> {
+    index: usize,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for CVar<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CVar<T> {}
+
+type ChangeFn = Box<dyn FnMut(&CVarValue) + Send>;
+
+struct Entry {
+    name: &'static str,
+    help: &'static str,
+    value: CVarValue,
+    default: CVarValue,
+    range: Option<(f64, f64)>,
+    flags: CVarFlags,
+    on_change: Vec<ChangeFn>,
+}
+
+#[derive(Default)]
+pub struct CVars {
+    entries: Vec<Entry>,
+    by_name: HashMap<&'static str, usize>,
+    cheats: Option<CVar<bool>>,
+}
+
+impl CVars {
+    /// A registry with the engine's own cvars in it.
+    pub fn new() -> (CVars, EngineCVars) {
+        let mut cvars = CVars::default();
+        let engine = EngineCVars::register(&mut cvars);
+        cvars.cheats = Some(engine.sv_cheats);
+        return (cvars, engine);
+    }
+
+    /// Register a cvar. Panics if the name is taken, two systems fighting over a name is a bug.
+    pub fn register<T: CVarType>(
+        &mut self,
+        name: &'static str,
+        default: T,
+        flags: CVarFlags,
+        help: &'static str,
+    ) -> CVar<T> {
+        assert!(
+            !self.by_name.contains_key(name),
+            "CVar {name} registered twice!"
+        );
+
+        let value = default.into_value();
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            name,
+            help,
+            default: value.clone(),
+            value,
+            range: None,
+            flags,
+            on_change: Vec::new(),
+        });
+        self.by_name.insert(name, index);
+
+        return CVar {
+            index,
+            _type: PhantomData,
+        };
+    }
+
+    /// Register a numeric cvar that gets clamped to `min..=max`.
+    pub fn register_ranged<T: CVarType>(
+        &mut self,
+        name: &'static str,
+        default: T,
+        min: f64,
+        max: f64,
+        flags: CVarFlags,
+        help: &'static str,
+    ) -> CVar<T> {
+        let cvar = self.register(name, default, flags, help);
+        self.entries[cvar.index].range = Some((min, max));
+        return cvar;
+    }
+
+    pub fn get<T: CVarType>(&self, cvar: CVar<T>) -> T {
+        T::from_value(&self.entries[cvar.index].value)
+    }
+
+    /// Set from code. Skips the cheat check, but still clamps and fires callbacks.
+    pub fn set<T: CVarType>(&mut self, cvar: CVar<T>, value: T) {
+        self.store(cvar.index, value.into_value());
+    }
+
+    /// Call `f` with the new value whenever the cvar changes.
+    pub fn on_change<T: CVarType>(&mut self, cvar: CVar<T>, mut f: impl FnMut(T) + Send + 'static) {
+        self.entries[cvar.index]
+            .on_change
+            .push(Box::new(move |v| f(T::from_value(v))));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|e| e.name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn find(&self, name: &str) -> Result<usize, String> {
+        self.by_name
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("No cvar named {name}"))
+    }
+
+    /// A description of the cvar for the console: value, default and help.
+    pub fn describe(&self, name: &str) -> Result<String, String> {
+        let e = &self.entries[self.find(name)?];
+        let mut out = format!("{} = {} (default {})", e.name, e.value, e.default);
+        if let Some((min, max)) = e.range {
+            out.push_str(&format!(" [{min}..{max}]"));
+        }
+        if e.flags.contains(CVarFlags::CHEAT) {
+            out.push_str(" cheat");
+        }
+        out.push_str(&format!("\n  {}", e.help));
+        return Ok(out);
+    }
+
+    /// Set from text, as the console, command line and config file do.
+    pub fn set_from_str(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let index = self.find(name)?;
+        let entry = &self.entries[index];
+
+        if entry.flags.contains(CVarFlags::CHEAT) && !self.cheats.is_some_and(|c| self.get(c)) {
+            return Err(format!("{name} is cheat protected, set sv_cheats 1 first"));
+        }
+
+        let value = entry.value.parse_like(text)?;
+        self.store(index, value);
+        return Ok(());
+    }
+
+    pub fn reset(&mut self, name: &str) -> Result<(), String> {
+        let index = self.find(name)?;
+        let default = self.entries[index].default.clone();
+        self.store(index, default);
+        return Ok(());
+    }
+
+    fn store(&mut self, index: usize, mut value: CVarValue) {
+        let entry = &mut self.entries[index];
+
+        if let Some((min, max)) = entry.range {
+            value = match value {
+                CVarValue::Int(v) => CVarValue::Int(v.clamp(min as i64, max as i64)),
+                CVarValue::Float(v) => CVarValue::Float(v.clamp(min, max)),
+                v => v,
+            };
+        }
+
+        if entry.value == value {
+            return;
+        }
+
+        entry.value = value;
+        for f in &mut entry.on_change {
+            f(&entry.value);
+        }
+    }
+
+    /// Run a config file's worth of `name value` lines. Blank lines and `//` comments are skipped.
+    /// Bad lines don't stop the rest from applying, their errors come back at the end.
+    pub fn exec(&mut self, config: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            let words = tokenize(line);
+            let res = match words.as_slice() {
+                [name, value] => self.set_from_str(name, value),
+                _ => Err("expected a name and a value".into()),
+            };
+            if let Err(e) = res {
+                errors.push(format!("line {}: {e}", i + 1));
+            }
+        }
+
+        return errors;
+    }
+
+    /// Load a config file, if it exists. Errors in individual lines get logged.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let config = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        for e in self.exec(&config) {
+            log::warn!("{}: {e}", path.display());
+        }
+        return Ok(());
+    }
+
+    /// The archived cvars, in config file form.
+    pub fn archive(&self) -> String {
+        let mut out = String::from("// Written on exit, changes to archived cvars end up here.\n");
+        for e in self
+            .entries
+            .iter()
+            .filter(|e| e.flags.contains(CVarFlags::ARCHIVE))
+        {
+            out.push_str(&format!("{} {}\n", e.name, e.value));
+        }
+        return out;
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.archive())
+    }
+}
+
+/// Handles to the cvars the engine itself registers.
+#[derive(Clone, Copy)]
+pub struct EngineCVars {
+    pub sv_cheats: CVar<bool>,
+    pub r_vsync: CVar<bool>,
+    pub r_hdr: CVar<bool>,
+    pub r_frames_in_flight: CVar<i64>,
+    pub r_render_scale: CVar<f32>,
+    pub r_shadow_quality: CVar<i64>,
+    pub r_msaa: CVar<i64>,
+    pub r_ssao: CVar<bool>,
+    pub r_post_quality: CVar<i64>,
+    pub r_dof: CVar<bool>,
+    pub r_motion_blur: CVar<bool>,
+    pub r_shutter_angle: CVar<f32>,
+    pub r_quality: CVar<String>,
+    pub r_gpu: CVar<String>,
+    pub r_validation: CVar<bool>,
+    pub r_validation_severity: CVar<String>,
+    pub r_shader_reload: CVar<bool>,
+    pub r_analysis: CVar<bool>,
+    pub debug_draw: CVar<bool>,
+}
+
+impl EngineCVars {
+    fn register(cvars: &mut CVars) -> EngineCVars {
+        EngineCVars {
+            sv_cheats: cvars.register(
+                "sv_cheats",
+                false,
+                CVarFlags::NONE,
+                "Allow changing cheat protected cvars",
+            ),
+            r_vsync: cvars.register(
+                "r_vsync",
+                true,
+                CVarFlags::ARCHIVE,
+                "Wait for vertical sync when presenting",
+            ),
+            r_hdr: cvars.register(
+                "r_hdr",
+                true,
+                CVarFlags::ARCHIVE,
+                "Present in HDR when the display supports it",
+            ),
+            r_frames_in_flight: cvars.register_ranged(
+                "r_frames_in_flight",
+                2i64,
+                1.0,
+                4.0,
+                CVarFlags::ARCHIVE,
+                "Frames the CPU can get ahead of the GPU. More is smoother, less has lower latency",
+            ),
+            r_render_scale: cvars.register_ranged(
+                "r_render_scale",
+                1.0f32,
+                0.25,
+                2.0,
+                CVarFlags::ARCHIVE,
+                "Scene resolution relative to the window",
+            ),
+            r_shadow_quality: cvars.register_ranged(
+                "r_shadow_quality",
+                2i64,
+                0.0,
+                3.0,
+                CVarFlags::ARCHIVE,
+                "Shadow map quality, 0 turns shadows off",
+            ),
+            r_msaa: cvars.register_ranged(
+                "r_msaa",
+                4i64,
+                1.0,
+                8.0,
+                CVarFlags::ARCHIVE,
+                "Samples per pixel for the scene, rounded down to a power of two",
+            ),
+            r_ssao: cvars.register(
+                "r_ssao",
+                true,
+                CVarFlags::ARCHIVE,
+                "Screen space ambient occlusion",
+            ),
+            r_post_quality: cvars.register_ranged(
+                "r_post_quality",
+                2i64,
+                0.0,
+                2.0,
+                CVarFlags::ARCHIVE,
+                "Post effects, 0 tonemaps only, 1 adds colour grading, 2 adds bloom",
+            ),
+            r_dof: cvars.register(
+                "r_dof",
+                true,
+                CVarFlags::ARCHIVE,
+                "Depth of field, for cameras with a lens",
+            ),
+            r_motion_blur: cvars.register(
+                "r_motion_blur",
+                true,
+                CVarFlags::ARCHIVE,
+                "Motion blur, which also has the scene write motion vectors",
+            ),
+            r_shutter_angle: cvars.register_ranged(
+                "r_shutter_angle",
+                180.0f32,
+                0.0,
+                360.0,
+                CVarFlags::ARCHIVE,
+                "How much of each frame's motion blurs, in degrees, 360 for all of it",
+            ),
+            r_quality: cvars.register(
+                "r_quality",
+                "high".to_string(),
+                CVarFlags::ARCHIVE,
+                "Quality preset, low, medium, high or ultra. Sets the other quality cvars, custom once they're changed",
+            ),
+            r_gpu: cvars.register(
+                "r_gpu",
+                String::new(),
+                CVarFlags::ARCHIVE,
+                "GPU to render with, by index or part of its name. Empty picks the best, takes a restart",
+            ),
+            r_validation: cvars.register(
+                "r_validation",
+                false,
+                CVarFlags::ARCHIVE,
+                "Validate Vulkan usage, needs the Vulkan SDK and slows rendering a lot. Takes a restart",
+            ),
+            r_validation_severity: cvars.register(
+                "r_validation_severity",
+                "warning".to_string(),
+                CVarFlags::ARCHIVE,
+                "Least severe validation messages to log: verbose, info, warning or error",
+            ),
+            r_shader_reload: cvars.register(
+                "r_shader_reload",
+                cfg!(debug_assertions),
+                CVarFlags::ARCHIVE,
+                "Rebuild pipelines when their shader files change",
+            ),
+            r_analysis: cvars.register(
+                "r_analysis",
+                false,
+                CVarFlags::NONE,
+                "Analyse presented frames on the GPU, for the overlay's luminance histogram and average colour",
+            ),
+            debug_draw: cvars.register(
+                "debug_draw",
+                false,
+                CVarFlags::NONE,
+                "Draw debug lines, such as collider wireframes and editor gizmos",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use super::{CVarFlags, CVars};
+
+    #[test]
+    pub fn setting_and_bounds() {
+        let (mut cvars, engine) = CVars::new();
+
+        let changes = Arc::new(AtomicU32::new(0));
+        let c = changes.clone();
+        cvars.on_change(engine.r_vsync, move |_| {
+            c.fetch_add(1, Ordering::Relaxed);
+        });
+
+        cvars.set_from_str("r_vsync", "0").unwrap();
+        cvars.set_from_str("r_vsync", "off").unwrap();
+        assert!(!cvars.get(engine.r_vsync));
+        assert_eq!(changes.load(Ordering::Relaxed), 1);
+
+        cvars.set_from_str("r_render_scale", "10").unwrap();
+        assert_eq!(cvars.get(engine.r_render_scale), 2.0);
+        assert!(cvars.set_from_str("r_shadow_quality", "high").is_err());
+        assert!(cvars.set_from_str("r_nonsense", "1").is_err());
+
+        let noclip = cvars.register("noclip", false, CVarFlags::CHEAT, "");
+        assert!(cvars.set_from_str("noclip", "1").is_err());
+        cvars.set_from_str("sv_cheats", "1").unwrap();
+        cvars.set_from_str("noclip", "1").unwrap();
+        assert!(cvars.get(noclip));
+    }
+
+    #[test]
+    pub fn config_round_trip() {
+        let (mut cvars, engine) = CVars::new();
+        cvars.set(engine.r_render_scale, 0.5);
+        cvars.set(engine.r_shadow_quality, 0);
+        let config = cvars.archive();
+
+        let (mut fresh, engine) = CVars::new();
+        assert!(fresh.exec(&config).is_empty());
+        assert_eq!(fresh.get(engine.r_render_scale), 0.5);
+        assert_eq!(fresh.get(engine.r_shadow_quality), 0);
+
+        assert_eq!(fresh.exec("// comment\n\nr_vsync\nsv_cheats 1").len(), 1);
+    }
+}
@@ -0,0 +1,182 @@
+//! Immediate mode debug lines. Anything can push shapes in during a frame, and they're cleared once drawn.
+//!
+//! The app draws them through the main camera each frame, as 2D lines of a constant width over the game's sprites
+//! (see [`crate::render::lines`]), so they show through whatever's in front of them.
+
+use std::f32::consts::TAU;
+
+use glam::{Affine3A, Mat4, Vec2, Vec3};
+
+use crate::{
+    color::LinearColor,
+    math::bounds::{Aabb, Frustum, Obb},
+    render::{
+        lines::LineBatch,
+        sprite::{SpriteBatch, TextureId},
+    },
+    ui::UI_LAYER,
+};
+
+/// The sprite layer debug lines are drawn on, over the game's sprites and under the UI.
+pub const DEBUG_LAYER: i32 = UI_LAYER - 1;
+/// In pixels.
+pub const DEBUG_LINE_WIDTH: f32 = 1.5;
+
+pub type DebugColor = LinearColor;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebugLine {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub color: DebugColor,
+}
+
+/// Segments used for circles. Debug shapes don't need to be pretty.
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[derive(Default, Debug)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    /// Follows the `debug_draw` cvar, set at the top of every frame.
+    pub enabled: bool,
+}
+
+impl DebugDraw {
+    pub fn lines(&self) -> &[DebugLine] {
+        &self.lines
+    }
+
+    /// Drop everything queued. The app calls this once the frame they were drawn in is presented.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Draw everything queued into `sprites` on [`DEBUG_LAYER`], as seen through `view_proj` on a `viewport`
+    /// pixels big, building the lines in `lines`.
+    pub fn draw(
+        &self,
+        view_proj: &Mat4,
+        viewport: Vec2,
+        lines: &mut LineBatch,
+        sprites: &mut SpriteBatch,
+    ) {
+        lines.clear();
+        lines.debug_lines(&self.lines, view_proj, viewport, DEBUG_LINE_WIDTH);
+        sprites.triangles(
+            lines.vertices(),
+            lines.indices(),
+            TextureId::WHITE,
+            DEBUG_LAYER,
+        );
+    }
+
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: DebugColor) {
+        if self.enabled {
+            self.lines.push(DebugLine { from, to, color });
+        }
+    }
+
+    /// An oriented box, given its half extents and transform.
+    pub fn cuboid(&mut self, transform: Affine3A, half_extents: Vec3, color: DebugColor) {
+        let corners = Aabb::from_center_half_extents(Vec3::ZERO, half_extents)
+            .corners()
+            .map(|c| transform.transform_point3(c));
+        self.box_edges(&corners, color);
+    }
+
+    /// The edges between eight corners in [`Aabb::corners`] order.
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: DebugColor) {
+        // Every pair of corners that differ in exactly one axis is an edge.
+        for a in 0..8 {
+            for bit in [1, 2, 4] {
+                if a & bit == 0 {
+                    self.line(corners[a], corners[a | bit], color);
+                }
+            }
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: DebugColor) {
+        self.cuboid(
+            Affine3A::from_translation(aabb.center()),
+            aabb.half_extents(),
+            color,
+        );
+    }
+
+    pub fn obb(&mut self, obb: &Obb, color: DebugColor) {
+        self.cuboid(obb.to_affine(), Vec3::ONE, color);
+    }
+
+    /// The frustum of a camera's view projection matrix. Needs a finite far plane.
+    pub fn frustum(&mut self, view_proj: &glam::Mat4, color: DebugColor) {
+        self.box_edges(&Frustum::corners(view_proj), color);
+    }
+
+    pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: DebugColor) {
+        let (u, v) = normal.normalize_or(Vec3::Y).any_orthonormal_pair();
+        let point = |i: usize| {
+            let a = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + (u * a.cos() + v * a.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// Three axis circles, the classic wire sphere.
+    pub fn sphere(&mut self, transform: Affine3A, radius: f32, color: DebugColor) {
+        let center = transform.translation.into();
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.circle(center, transform.transform_vector3(axis), radius, color);
+        }
+    }
+
+    /// A capsule along the transform's Y axis.
+    pub fn capsule(
+        &mut self,
+        transform: Affine3A,
+        half_height: f32,
+        radius: f32,
+        color: DebugColor,
+    ) {
+        let up = transform.transform_vector3(Vec3::Y).normalize_or(Vec3::Y);
+        let center: Vec3 = transform.translation.into();
+        let (top, bottom) = (center + up * half_height, center - up * half_height);
+
+        self.circle(top, up, radius, color);
+        self.circle(bottom, up, radius, color);
+
+        let (u, v) = up.any_orthonormal_pair();
+        for side in [u, -u, v, -v] {
+            self.line(top + side * radius, bottom + side * radius, color);
+        }
+
+        let mut cap = transform;
+        cap.translation = top.into();
+        self.sphere(cap, radius, color);
+        cap.translation = bottom.into();
+        self.sphere(cap, radius, color);
+    }
+
+    /// Little RGB axes, for marking transforms.
+    pub fn axes(&mut self, transform: Affine3A, size: f32) {
+        let o = transform.transform_point3(Vec3::ZERO);
+        self.line(
+            o,
+            transform.transform_point3(Vec3::X * size),
+            LinearColor::RED,
+        );
+        self.line(
+            o,
+            transform.transform_point3(Vec3::Y * size),
+            LinearColor::GREEN,
+        );
+        self.line(
+            o,
+            transform.transform_point3(Vec3::Z * size),
+            LinearColor::BLUE,
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The engine object model, a thin layer over hecs.
+//! Entities carry the components in [`components`], and [`Schedule`] runs systems over the world each frame.
+
+use hecs::{Entity, World};
+
+use self::components::{GlobalTransform, Parent, Transform};
+use crate::anim;
+
+pub mod components;
+pub mod spatial;
+
+/// Per-frame data handed to every system.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameContext {
+    /// Seconds since the last frame.
+    pub delta: f32,
+    pub frame: u64,
+    /// Simulated seconds since the start of the run, the sum of every delta so far.
+    pub time: f64,
+    /// The run's seed, fixed so replays reproduce it. Systems that hold their own state can seed an
+    /// [`Rng`](crate::rng::Rng) stream from it, otherwise use the app's [`RngService`](crate::rng::RngService).
+    pub seed: u64,
+}
+
+type SystemFn = Box<dyn FnMut(&mut World, &FrameContext) + Send>;
+
+/// An ordered list of systems, run one after another over the world.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<(&'static str, SystemFn)>,
+}
+
+impl Schedule {
+    /// A schedule with the engine's own systems already in it.
+    pub fn new() -> Schedule {
+        let mut s = Schedule::default();
+        s.add_system("animate_transforms", |world, ctx| {
+            anim::animate_transforms(world, ctx.delta)
+        });
+        s.add_system("animate_sprites", |world, ctx| {
+            anim::sprite::animate_sprites(world, ctx.delta)
+        });
+        s.add_system("propagate_transforms", |world, _| {
+            propagate_transforms(world)
+        });
+        return s;
+    }
+
+    /// Add a system to run before the engine's transform propagation.
+    pub fn add_system(
+        &mut self,
+        name: &'static str,
+        system: impl FnMut(&mut World, &FrameContext) + Send + 'static,
+    ) {
+        // Keep propagation last, so everyone's writes this frame land in GlobalTransform.
+        let at = self
+            .systems
+            .iter()
+            .position(|(n, _)| *n == "propagate_transforms")
+            .unwrap_or(self.systems.len());
+        self.systems.insert(at, (name, Box::new(system)));
+    }
+
+    pub fn system_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.systems.iter().map(|(n, _)| *n)
+    }
+
+    pub fn run(&mut self, world: &mut World, ctx: &FrameContext) {
+        for (_, system) in &mut self.systems {
+            system(world, ctx);
+        }
+    }
+}
+
+/// Compute [`GlobalTransform`] for every entity with a [`Transform`], walking up [`Parent`] links.
+/// Entities with a transform but no global transform get one added.
+pub fn propagate_transforms(world: &mut World) {
+    let missing: Vec<Entity> = world
+        .query::<&Transform>()
+        .without::<&GlobalTransform>()
+        .iter()
+        .map(|(e, _)| e)
+        .collect();
+
+    for e in missing {
+        world.insert_one(e, GlobalTransform::default()).unwrap();
+    }
+
+    let mut results = Vec::new();
+    for (e, t) in world.query::<&Transform>().iter() {
+        let mut global = t.to_affine();
+        let mut current = e;
+        // Bounded, so an accidental parent cycle doesn't hang the frame.
+        for _ in 0..256 {
+            let Ok(parent) = world.get::<&Parent>(current) else {
+                break;
+            };
+            current = parent.0;
+            let Ok(pt) = world.get::<&Transform>(current) else {
+                break;
+            };
+            global = pt.to_affine() * global;
+        }
+        results.push((e, global));
+    }
+
+    for (e, global) in results {
+        if let Ok(mut g) = world.get::<&mut GlobalTransform>(e) {
+            g.0 = global;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+    use hecs::World;
+
+    use super::{
+        components::{GlobalTransform, Parent, Transform},
+        propagate_transforms,
+    };
+
+    #[test]
+    pub fn child_follows_parent() {
+        let mut world = World::new();
+        let parent = world.spawn((Transform::from_translation(Vec3::new(1.0, 0.0, 0.0)),));
+        let child = world.spawn((
+            Transform::from_translation(Vec3::new(0.0, 2.0, 0.0)),
+            Parent(parent),
+        ));
+
+        propagate_transforms(&mut world);
+
+        let g = world.get::<&GlobalTransform>(child).unwrap();
+        assert_eq!(Vec3::from(g.0.translation), Vec3::new(1.0, 2.0, 0.0));
+    }
+}
@@ -0,0 +1,362 @@
+//! The engine's built-in components.
+
+use std::sync::Arc;
+
+use glam::{Affine3A, Mat4, Vec2, Vec3};
+use hecs::Entity;
+use serde::{Deserialize, Serialize};
+
+/// Local translation/rotation/scale, relative to the [`Parent`] if there is one.
+pub use crate::math::Transform;
+use crate::{
+    color::LinearColor,
+    math::{self, bounds::Aabb},
+    render::{
+        foliage::FoliagePatch, lightmap::Lightmap, probes::ProbeGrid, reflections::ReflectionProbes,
+    },
+};
+
+/// A human readable name, for editors and debugging. Doesn't need to be unique.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Name(pub String);
+
+/// The world space transform, computed from [`Transform`] and the parent chain every frame.
+/// Don't write this yourself, it'll get stomped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GlobalTransform(pub Affine3A);
+
+/// Attaches an entity to another, making its [`Transform`] relative to the parent's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// Index of a mesh owned by the renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MeshId(pub u32);
+
+/// Index of a material owned by the renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MaterialId(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshRenderer {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub visible: bool,
+    pub cast_shadows: bool,
+}
+
+impl MeshRenderer {
+    /// Local space bounds of the mesh.
+    // todo: real mesh bounds, once meshes are a thing. Until then everything's a unit cube.
+    pub fn local_bounds(&self) -> Aabb {
+        Aabb::new(Vec3::splat(-0.5), Vec3::splat(0.5))
+    }
+}
+
+/// Draws an outline around the entity's mesh, see [`crate::render::outline`]. The editor puts these on what's
+/// selected and hovered, so it isn't saved with the scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outline {
+    /// Drawn opaque, the alpha's ignored.
+    pub color: LinearColor,
+    /// In pixels, up to [`crate::render::outline::MAX_WIDTH`].
+    pub width: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Shines down the entity's -Z axis, from infinitely far away.
+    Directional,
+    Point {
+        range: f32,
+    },
+    /// Cone down the entity's -Z axis. Angles are half-angles in radians.
+    Spot {
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// Lights meshes from a baked irradiance probe grid rather than a flat ambient, see [`crate::render::probes`]. It's
+/// loaded from the level's bake with [`ProbeGrid::load`], so it isn't saved with the scene. Only one is used.
+#[derive(Clone, Debug)]
+pub struct IrradianceProbes(pub Arc<ProbeGrid>);
+
+/// The level's baked lightmap, for meshes with [`Lightmapped`] to be lit from, see [`crate::render::lightmap`]. It's
+/// loaded from the level's bake with [`Lightmap::load`], so it isn't saved with the scene. Only one is used.
+#[derive(Clone, Debug)]
+pub struct BakedLightmap(pub Arc<Lightmap>);
+
+/// Lights the entity's mesh from the [`BakedLightmap`] rather than the irradiance probes. Its lightmap UVs are
+/// scaled by `scale` then moved by `offset`, to where the bake put the mesh. Only for what doesn't move, as the
+/// lighting doesn't move with it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lightmapped {
+    pub scale: Vec2,
+    pub offset: Vec2,
+}
+
+/// The level's reflection probes, for every mesh to reflect, see [`crate::render::reflections`]. They're captured
+/// when the level loads, so they aren't saved with the scene. Only one is used, and a new [`Arc`] has them uploaded
+/// again, after capturing some anew.
+#[derive(Clone, Debug)]
+pub struct Reflections(pub Arc<ReflectionProbes>);
+
+/// Grass, flowers or bushes, scattered with [`crate::render::foliage::scatter`], culled on the GPU and swayed by the
+/// renderer's wind, see [`crate::render::foliage`]. They're scattered when the level loads, so they aren't saved with
+/// the scene, and a new [`Arc`] has them uploaded again.
+#[derive(Clone, Debug)]
+pub struct Foliage(pub Arc<FoliagePatch>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Alpha is ignored.
+    pub color: LinearColor,
+    pub intensity: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Vertical field of view in radians.
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// Height of the view volume in world units, width follows the aspect ratio.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    /// The clip space projection, following the conventions in [`math`].
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                math::perspective(fov_y, aspect, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                math::orthographic(height, aspect, near, far)
+            }
+        }
+    }
+}
+
+/// Looks down the entity's -Z axis.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Camera {
+    pub projection: Projection,
+    /// Cameras render in ascending order.
+    pub order: i32,
+    pub active: bool,
+    /// Depth of field as a real camera would have it, none for everything in focus.
+    #[serde(default)]
+    pub lens: Option<Lens>,
+}
+
+/// The physical camera settings depth of field goes by, see [`crate::render::depth_of_field`]. Only the blur
+/// follows from these, the field of view is still the [`Projection`]'s, which [`Lens::fov_y`] can match.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lens {
+    /// In millimetres.
+    pub focal_length: f32,
+    /// The f-number, focal length over aperture diameter. Lower blurs more.
+    pub f_stop: f32,
+    /// How far away what's in focus is, in metres.
+    pub focus_distance: f32,
+    /// Sensor height in millimetres, 24 for full frame.
+    pub sensor_height: f32,
+    /// Aperture blades, the number of sides the bokeh has. Under 3 for round bokeh.
+    pub blades: u32,
+}
+
+impl Lens {
+    /// A 50mm full frame lens wide open at f/2, focused 5m away.
+    pub fn standard() -> Lens {
+        Lens {
+            focal_length: 50.0,
+            f_stop: 2.0,
+            focus_distance: 5.0,
+            sensor_height: 24.0,
+            blades: 6,
+        }
+    }
+
+    /// The vertical field of view this lens has on its sensor, in radians.
+    pub fn fov_y(&self) -> f32 {
+        return 2.0 * (self.sensor_height / (2.0 * self.focal_length)).atan();
+    }
+}
+
+/// Makes the entity a mirror or a water surface, reflecting about its XZ plane with its +Y side showing. The 3D
+/// pass draws the unit square on that plane with the scene mirrored in it; see [`crate::render::planar`] for the
+/// view it reflects.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanarReflector {
+    /// The reflection's resolution relative to the view's.
+    pub resolution_scale: f32,
+    /// How far behind the surface things get cut off, so what touches it doesn't leave a gap along the seam.
+    pub clip_offset: f32,
+    pub active: bool,
+}
+
+impl PlanarReflector {
+    /// Sharp, at full resolution.
+    pub fn mirror() -> PlanarReflector {
+        PlanarReflector {
+            resolution_scale: 1.0,
+            clip_offset: 0.01,
+            active: true,
+        }
+    }
+
+    /// At half resolution, as ripples blur it anyway, and cut off further down for the waves to dip into.
+    pub fn water() -> PlanarReflector {
+        PlanarReflector {
+            resolution_scale: 0.5,
+            clip_offset: 0.1,
+            active: true,
+        }
+    }
+}
+
+/// What a [`Portal`] shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortalKind {
+    /// The view out of [`Portal::exit`], as if the camera had stepped through the surface and out of the exit's.
+    Window,
+    /// The scene mirrored about the surface. Unlike a [`PlanarReflector`] it's drawn in place, and mirrors seen in
+    /// it show their own reflections, down to [`Portal::max_depth`].
+    Mirror,
+    /// The same view, with everything between the camera and the surface cut away, for seeing through walls.
+    XRay,
+}
+
+/// A region of the view showing another view. The surface is a rectangle on the entity's XZ plane, showing its view
+/// to the +Y side, drawn through the last camera. See [`crate::render::portal`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Portal {
+    pub kind: PortalKind,
+    /// The other end of a [`PortalKind::Window`], an entity with a [`Portal`] of its own for its surface. Shows
+    /// nothing without one.
+    #[serde(skip)]
+    pub exit: Option<Entity>,
+    /// Along X and Z.
+    pub size: Vec2,
+    /// How many portals deep views through this one can be, counting it. Two mirrors facing each other stop here.
+    pub max_depth: u32,
+    pub active: bool,
+}
+
+impl Portal {
+    pub fn window(exit: Entity, size: Vec2) -> Portal {
+        Portal {
+            kind: PortalKind::Window,
+            exit: Some(exit),
+            size,
+            max_depth: 2,
+            active: true,
+        }
+    }
+
+    pub fn mirror(size: Vec2) -> Portal {
+        Portal {
+            kind: PortalKind::Mirror,
+            exit: None,
+            size,
+            max_depth: 3,
+            active: true,
+        }
+    }
+
+    pub fn x_ray(size: Vec2) -> Portal {
+        Portal {
+            kind: PortalKind::XRay,
+            exit: None,
+            size,
+            max_depth: 1,
+            active: true,
+        }
+    }
+}
+
+/// One Gerstner wave on a [`Water`] surface. It moves as fast as its wavelength makes it in deep water.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WaterWave {
+    /// Which way it travels in the entity's XZ plane. Doesn't need to be normalized.
+    pub direction: Vec2,
+    pub wavelength: f32,
+    pub amplitude: f32,
+    /// 0 for a sine wave, 1 for crests that come to a point. Summed over the waves it should stay under 1, or the
+    /// crests loop over.
+    pub steepness: f32,
+}
+
+impl WaterWave {
+    /// A wave that doesn't move anything, for the slots a surface doesn't use.
+    pub const FLAT: WaterWave = WaterWave {
+        direction: Vec2::X,
+        wavelength: 1.0,
+        amplitude: 0.0,
+        steepness: 0.0,
+    };
+}
+
+/// Makes the unit square on the entity's XZ plane a water surface, moved by its waves, with ripples scrolled over the
+/// top. The scene shows through it bent by the ripples and fading into the depths and the shore. Give it a
+/// [`PlanarReflector::water`] too for reflections, otherwise it reflects only the sky. See [`crate::render::water`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Water {
+    pub waves: [WaterWave; 4],
+    /// How fast the two layers of the ripple normal map scroll, in repeats a second.
+    pub ripple_scroll: [Vec2; 2],
+    /// How many world units one repeat of the ripple normal map covers.
+    pub ripple_scale: f32,
+    /// Tints what's seen through the water where it's shallow.
+    pub shallow_color: LinearColor,
+    pub deep_color: LinearColor,
+    /// How deep the water is where nothing below it shows through any more.
+    pub depth_fade: f32,
+    /// How deep the water is where it stops fading out into the shore.
+    pub shore_fade: f32,
+    /// How far the ripples bend what's seen through it and reflected in it, in screen UV.
+    pub distortion: f32,
+}
+
+impl Water {
+    /// Still and clear, with only ripples.
+    pub fn pond() -> Water {
+        Water {
+            waves: [WaterWave::FLAT; 4],
+            ripple_scroll: [Vec2::new(0.02, 0.01), Vec2::new(-0.015, 0.02)],
+            ripple_scale: 2.0,
+            shallow_color: LinearColor::rgb(0.6, 0.8, 0.7),
+            deep_color: LinearColor::rgb(0.02, 0.08, 0.06),
+            depth_fade: 3.0,
+            shore_fade: 0.2,
+            distortion: 0.01,
+        }
+    }
+
+    /// Rolling swell from one direction, with choppier waves across it.
+    pub fn ocean() -> Water {
+        let wave = |x, z, wavelength, amplitude, steepness| WaterWave {
+            direction: Vec2::new(x, z),
+            wavelength,
+            amplitude,
+            steepness,
+        };
+        Water {
+            waves: [
+                wave(1.0, 0.2, 24.0, 0.6, 0.25),
+                wave(0.8, -0.5, 11.0, 0.25, 0.15),
+                wave(0.3, 1.0, 5.0, 0.1, 0.12),
+                wave(-0.4, 0.9, 2.3, 0.04, 0.08),
+            ],
+            ripple_scroll: [Vec2::new(0.05, 0.02), Vec2::new(-0.03, 0.04)],
+            ripple_scale: 4.0,
+            shallow_color: LinearColor::rgb(0.3, 0.7, 0.7),
+            deep_color: LinearColor::rgb(0.005, 0.03, 0.06),
+            depth_fade: 12.0,
+            shore_fade: 0.5,
+            distortion: 0.02,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! A spatial index over the world's meshes, so picking and culling don't have to test every entity.
+
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+
+use super::components::{GlobalTransform, MeshRenderer};
+use crate::math::{
+    Ray,
+    bounds::{Aabb, Frustum, Sphere},
+    bvh::{Bvh, ProxyId},
+};
+
+/// World space bounds of every entity with a [`MeshRenderer`], kept in a [`Bvh`].
+/// Call [`SpatialIndex::sync`] once the frame's transforms are settled.
+#[derive(Default)]
+pub struct SpatialIndex {
+    bvh: Bvh<Entity>,
+    /// Each entity's proxy, and the sync it was last seen in.
+    proxies: HashMap<Entity, (ProxyId, u64)>,
+    syncs: u64,
+}
+
+impl SpatialIndex {
+    /// Bring the index up to date with the world. Entities that haven't moved far cost a lookup.
+    pub fn sync(&mut self, world: &World) {
+        self.syncs += 1;
+
+        for (entity, (mr, g)) in world.query::<(&MeshRenderer, &GlobalTransform)>().iter() {
+            let bounds = mr.local_bounds().transformed(&g.0);
+            match self.proxies.get_mut(&entity) {
+                Some((proxy, seen)) => {
+                    self.bvh.update(*proxy, &bounds);
+                    *seen = self.syncs;
+                }
+                None => {
+                    let proxy = self.bvh.insert(&bounds, entity);
+                    self.proxies.insert(entity, (proxy, self.syncs));
+                }
+            }
+        }
+
+        // Anything not seen this time was despawned or lost its mesh.
+        let bvh = &mut self.bvh;
+        self.proxies.retain(|_, (proxy, seen)| {
+            if *seen != self.syncs {
+                bvh.remove(*proxy);
+                return false;
+            }
+            true
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.bvh.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bvh.is_empty()
+    }
+
+    pub fn bvh(&self) -> &Bvh<Entity> {
+        &self.bvh
+    }
+
+    /// Entities that might be in view. Bounds are a little loose, so expect some that aren't.
+    pub fn query_frustum(&self, frustum: &Frustum, mut f: impl FnMut(Entity)) {
+        self.bvh.query_frustum(frustum, |_, e| f(e));
+    }
+
+    /// Entities near a sphere.
+    pub fn query_sphere(&self, sphere: &Sphere, mut f: impl FnMut(Entity)) {
+        self.bvh.query_sphere(sphere, |_, e| f(e));
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb, mut f: impl FnMut(Entity)) {
+        self.bvh.query_aabb(aabb, |_, e| f(e));
+    }
+
+    /// The nearest entity along the ray. `hit` gives the exact distance to an entity, if it's hit.
+    pub fn ray_cast(
+        &self,
+        ray: &Ray,
+        max_t: f32,
+        hit: impl FnMut(Entity) -> Option<f32>,
+    ) -> Option<(Entity, f32)> {
+        self.bvh.ray_cast(ray, max_t, hit)
+    }
+}
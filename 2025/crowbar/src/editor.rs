@@ -0,0 +1,202 @@
+//! Editor mode. Scene hierarchy and inspector panels in the debug overlay,
+//! click to select things in the viewport, and a gizmo to move them around.
+
+use glam::Vec2;
+use hecs::{Entity, World};
+use winit::{event::MouseButton, event_loop::ActiveEventLoop, keyboard::KeyCode};
+
+use self::{
+    gizmo::{Gizmo, GizmoMode},
+    panels::{HierarchyPanel, InspectorPanel},
+    undo::{Command, SharedUndo},
+};
+use crate::{
+    app::WinitApp,
+    color::LinearColor,
+    ecs::components::Outline,
+    picking::{ViewCamera, pick},
+    plugin::Plugin,
+};
+
+pub mod gizmo;
+pub mod panels;
+pub mod undo;
+
+/// How the editor outlines what's selected.
+pub const SELECTED_OUTLINE: Outline = Outline {
+    color: LinearColor::rgb(1.0, 0.6, 0.1),
+    width: 3.0,
+};
+/// How the editor outlines what's under the cursor, when it isn't selected.
+pub const HOVERED_OUTLINE: Outline = Outline {
+    color: LinearColor::rgb(0.3, 0.6, 1.0),
+    width: 2.0,
+};
+
+/// Marks the entity the editor has selected. There's at most one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Selected;
+
+pub fn selected(world: &World) -> Option<Entity> {
+    world.query::<&Selected>().iter().next().map(|(e, _)| e)
+}
+
+/// Select `entity`, or clear the selection with `None`.
+pub fn select(world: &mut World, entity: Option<Entity>) {
+    let old: Vec<Entity> = world.query::<&Selected>().iter().map(|(e, _)| e).collect();
+    for e in old {
+        let _ = world.remove_one::<Selected>(e);
+    }
+
+    if let Some(e) = entity {
+        let _ = world.insert_one(e, Selected);
+    }
+}
+
+#[derive(Default)]
+pub struct EditorPlugin {
+    gizmo: Gizmo,
+    undo: SharedUndo,
+    /// Entities the editor's outlined, with the outline each had before, to put back.
+    outlined: Vec<(Entity, Option<Outline>)>,
+}
+
+impl EditorPlugin {
+    pub fn new() -> EditorPlugin {
+        EditorPlugin::default()
+    }
+
+    /// Outline `selected` and `hovered`, putting back whatever outlines the last ones had.
+    fn outline(&mut self, world: &mut World, selected: Option<Entity>, hovered: Option<Entity>) {
+        let hovered = hovered.filter(|&e| Some(e) != selected);
+        let wanted: Vec<_> = [(selected, SELECTED_OUTLINE), (hovered, HOVERED_OUTLINE)]
+            .into_iter()
+            .filter_map(|(e, outline)| Some((e?, outline)))
+            .collect();
+        if self
+            .outlined
+            .iter()
+            .map(|(e, _)| e)
+            .eq(wanted.iter().map(|(e, _)| e))
+        {
+            return;
+        }
+
+        for (e, old) in self.outlined.drain(..).rev() {
+            if let Some(old) = old {
+                let _ = world.insert_one(e, old);
+            } else {
+                let _ = world.remove_one::<Outline>(e);
+            }
+        }
+        for (e, outline) in wanted {
+            let old = world.get::<&Outline>(e).ok().map(|o| *o);
+            if world.insert_one(e, outline).is_ok() {
+                self.outlined.push((e, old));
+            }
+        }
+    }
+}
+
+impl Plugin for EditorPlugin {
+    fn name(&self) -> &'static str {
+        "editor"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, _event_loop: &ActiveEventLoop) {
+        let overlay = app.overlay_mut();
+        overlay.add_panel(HierarchyPanel::new(self.undo.clone()));
+        overlay.add_panel(InspectorPanel::new(self.undo.clone()));
+        overlay.visible = true;
+        let debug_draw = app.engine_cvars().debug_draw;
+        app.cvars_mut().set(debug_draw, true);
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        let Some(window) = app.main_window() else {
+            return;
+        };
+        let size = app.get_window(window).inner_size();
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        let viewport = Vec2::new(size.width as f32, size.height as f32);
+
+        let Some(camera) = ViewCamera::find(app.world()) else {
+            return;
+        };
+
+        // Cloned so the world can be borrowed alongside it. It's a few small sets.
+        let input = app.input().clone();
+
+        let (egui_pointer_down, egui_typing) = {
+            let ctx = app.overlay_mut().context();
+            (
+                ctx.input(|i| i.pointer.any_down()),
+                ctx.wants_keyboard_input(),
+            )
+        };
+        self.undo
+            .borrow_mut()
+            .seal_if_idle(egui_pointer_down || input.button_down(MouseButton::Left));
+
+        let ctrl = input.key_down(KeyCode::ControlLeft) || input.key_down(KeyCode::ControlRight);
+        let shift = input.key_down(KeyCode::ShiftLeft) || input.key_down(KeyCode::ShiftRight);
+        // Text fields have their own undo.
+        if ctrl && !egui_typing && !self.gizmo.dragging() {
+            let mut undo = self.undo.borrow_mut();
+            let redo =
+                input.key_pressed(KeyCode::KeyY) || (shift && input.key_pressed(KeyCode::KeyZ));
+            let done = if redo {
+                undo.redo(app.world_mut()).map(|n| format!("Redo {n}"))
+            } else if input.key_pressed(KeyCode::KeyZ) {
+                undo.undo(app.world_mut()).map(|n| format!("Undo {n}"))
+            } else {
+                None
+            };
+            if let Some(done) = done {
+                log::info!("{done}");
+            }
+        }
+
+        // Ctrl+W and friends aren't gizmo mode switches.
+        if !ctrl && !self.gizmo.dragging() {
+            for (key, mode) in [
+                (KeyCode::KeyW, GizmoMode::Translate),
+                (KeyCode::KeyE, GizmoMode::Rotate),
+                (KeyCode::KeyR, GizmoMode::Scale),
+            ] {
+                if input.key_pressed(key) {
+                    self.gizmo.mode = mode;
+                }
+            }
+        }
+
+        let (world, draw) = app.world_and_debug_draw();
+        let used = match selected(world) {
+            Some(e) => {
+                let used = self.gizmo.update(world, e, &camera, viewport, &input, draw);
+                if let Some((before, after)) = self.gizmo.take_finished_drag() {
+                    let name = format!("{:?}", self.gizmo.mode);
+                    let mut undo = self.undo.borrow_mut();
+                    undo.record(Command::set_component(name, e, before, after));
+                    // Each drag is its own step.
+                    undo.seal();
+                }
+                used
+            }
+            None => false,
+        };
+
+        let mut hovered = None;
+        if !used && !self.gizmo.dragging() {
+            let ray = camera.ray(input.cursor_position(), viewport);
+            hovered = pick(app.world(), app.spatial(), ray);
+            if input.button_pressed(MouseButton::Left) {
+                select(app.world_mut(), hovered);
+            }
+        }
+        let selection = selected(app.world());
+        self.outline(app.world_mut(), selection, hovered);
+    }
+}
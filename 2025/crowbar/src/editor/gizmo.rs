@@ -0,0 +1,221 @@
+//! The translate/rotate/scale gizmo, drawn with debug lines and dragged in screen space.
+
+use glam::{Quat, Vec2, Vec3};
+use hecs::{Entity, World};
+use winit::event::MouseButton;
+
+use crate::{
+    debug_draw::{DebugColor, DebugDraw},
+    ecs::components::{GlobalTransform, Parent, Transform},
+    input::Input,
+    picking::ViewCamera,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+const AXIS_COLORS: [DebugColor; 3] = [
+    DebugColor::rgb(0.9, 0.2, 0.2),
+    DebugColor::rgb(0.2, 0.9, 0.2),
+    DebugColor::rgb(0.2, 0.4, 1.0),
+];
+const HIGHLIGHT: DebugColor = DebugColor::rgb(1.0, 0.9, 0.1);
+
+/// How close the cursor has to be to a handle to grab it.
+const GRAB_PIXELS: f32 = 8.0;
+/// Handle length as a fraction of the distance to the camera, so the gizmo stays the same size on screen.
+const SCREEN_SCALE: f32 = 0.15;
+const CIRCLE_POINTS: usize = 32;
+
+fn segment_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared().max(1e-6)).clamp(0.0, 1.0);
+    return p.distance(a + ab * t);
+}
+
+struct Drag {
+    axis: usize,
+    start_cursor: Vec2,
+    start: Transform,
+    /// Screen direction that counts as "forwards" for the drag.
+    screen_dir: Vec2,
+    /// Pixels of drag per unit of change: one handle length, or one radian.
+    pixels: f32,
+    /// The axis, in the space of the entity's parent.
+    local_axis: Vec3,
+}
+
+#[derive(Default)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    drag: Option<Drag>,
+    /// The transform before and after the last drag, until someone takes it for undo.
+    finished: Option<(Transform, Transform)>,
+}
+
+impl Gizmo {
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// The transform before and after a drag that just ended.
+    pub fn take_finished_drag(&mut self) -> Option<(Transform, Transform)> {
+        self.finished.take()
+    }
+
+    /// Draw the gizmo on `entity` and handle dragging it.
+    /// Returns true if the gizmo used this frame's click, so it shouldn't select anything.
+    pub fn update(
+        &mut self,
+        world: &World,
+        entity: Entity,
+        camera: &ViewCamera,
+        viewport: Vec2,
+        input: &Input,
+        draw: &mut DebugDraw,
+    ) -> bool {
+        let Ok(global) = world.get::<&GlobalTransform>(entity).map(|g| g.0) else {
+            return false;
+        };
+        let (_, global_rot, center) = global.to_scale_rotation_translation();
+        let parent_inverse = world
+            .get::<&Parent>(entity)
+            .ok()
+            .and_then(|p| {
+                world
+                    .get::<&GlobalTransform>(p.0)
+                    .ok()
+                    .map(|g| g.0.inverse())
+            })
+            .unwrap_or_default();
+
+        let size = camera.world.translation.distance(center.into()) * SCREEN_SCALE;
+        let axes = match self.mode {
+            GizmoMode::Scale => [
+                global_rot * Vec3::X,
+                global_rot * Vec3::Y,
+                global_rot * Vec3::Z,
+            ],
+            _ => [Vec3::X, Vec3::Y, Vec3::Z],
+        };
+
+        let Some(origin_px) = camera.project(center, viewport) else {
+            return false;
+        };
+        let cursor = input.cursor_position();
+
+        // Which handle is under the cursor, and the screen direction/scale to drag it with.
+        let mut hovered: Option<(usize, Vec2, f32)> = None;
+        let mut best = GRAB_PIXELS;
+        for (i, axis) in axes.iter().enumerate() {
+            match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let Some(end) = camera.project(center + *axis * size, viewport) else {
+                        continue;
+                    };
+                    let d = segment_distance(cursor, origin_px, end);
+                    if d < best && end.distance(origin_px) > 1.0 {
+                        best = d;
+                        hovered = Some((i, (end - origin_px).normalize(), end.distance(origin_px)));
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let (u, v) = axis.any_orthonormal_pair();
+                    for k in 0..CIRCLE_POINTS {
+                        let a = k as f32 / CIRCLE_POINTS as f32 * std::f32::consts::TAU;
+                        let p = center + (u * a.cos() + v * a.sin()) * size;
+                        let tangent = axis.cross(p - center).normalize() * size * 0.1;
+                        let (Some(p_px), Some(t_px)) = (
+                            camera.project(p, viewport),
+                            camera.project(p + tangent, viewport),
+                        ) else {
+                            continue;
+                        };
+
+                        let d = cursor.distance(p_px);
+                        if d < best && t_px.distance(p_px) > 0.01 {
+                            best = d;
+                            hovered =
+                                Some((i, (t_px - p_px).normalize(), p_px.distance(origin_px)));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut used = false;
+        if self.drag.is_none()
+            && input.button_pressed(MouseButton::Left)
+            && let Some((axis, screen_dir, pixels)) = hovered
+            && let Ok(t) = world.get::<&Transform>(entity)
+        {
+            self.drag = Some(Drag {
+                axis,
+                start_cursor: cursor,
+                start: *t,
+                screen_dir,
+                pixels: pixels.max(1.0),
+                local_axis: parent_inverse.transform_vector3(axes[axis]),
+            });
+            used = true;
+        }
+
+        if let Some(drag) = &self.drag {
+            if !input.button_down(MouseButton::Left) {
+                if let Ok(t) = world.get::<&Transform>(entity)
+                    && *t != drag.start
+                {
+                    self.finished = Some((drag.start, *t));
+                }
+                self.drag = None;
+            } else if let Ok(mut t) = world.get::<&mut Transform>(entity) {
+                let amount = (cursor - drag.start_cursor).dot(drag.screen_dir) / drag.pixels;
+                let start = drag.start;
+
+                *t = match self.mode {
+                    GizmoMode::Translate => Transform {
+                        translation: start.translation + drag.local_axis * amount * size,
+                        ..start
+                    },
+                    GizmoMode::Rotate => Transform {
+                        rotation: Quat::from_axis_angle(drag.local_axis.normalize(), amount)
+                            * start.rotation,
+                        ..start
+                    },
+                    GizmoMode::Scale => {
+                        let mut scale = start.scale;
+                        scale[drag.axis] = (scale[drag.axis] * (1.0 + amount)).max(0.01);
+                        Transform { scale, ..start }
+                    }
+                };
+            }
+            used = true;
+        }
+
+        let active = self.drag.as_ref().map(|d| d.axis).or(hovered.map(|h| h.0));
+        for (i, axis) in axes.iter().enumerate() {
+            let color = if active == Some(i) {
+                HIGHLIGHT
+            } else {
+                AXIS_COLORS[i]
+            };
+
+            match self.mode {
+                GizmoMode::Translate => draw.line(center, center + *axis * size, color),
+                GizmoMode::Rotate => draw.circle(center, *axis, size, color),
+                GizmoMode::Scale => {
+                    let end = center + *axis * size;
+                    draw.line(center, end, color);
+                    draw.circle(end, *axis, size * 0.05, color);
+                }
+            }
+        }
+
+        return used;
+    }
+}
@@ -0,0 +1,437 @@
+//! The editor's overlay panels: the scene hierarchy and the selected entity's inspector.
+
+use std::collections::HashMap;
+
+use egui::{DragValue, Ui};
+use glam::{EulerRot, Quat, Vec3};
+use hecs::{Component, Entity, World};
+
+use super::{
+    select, selected,
+    undo::{Command, SharedUndo, UndoStack, snapshot_entity},
+};
+use crate::{
+    app::WinitApp,
+    color::LinearColor,
+    ecs::components::{
+        Camera, Lens, Light, LightKind, MeshRenderer, Name, Parent, PlanarReflector, Projection,
+        Transform, Water,
+    },
+    overlay::OverlayPanel,
+};
+
+/// Guard against parent cycles turning the tree into an infinite one.
+const MAX_DEPTH: usize = 64;
+
+fn entity_label(world: &World, entity: Entity) -> String {
+    match world.get::<&Name>(entity) {
+        Ok(name) => name.0.clone(),
+        Err(_) => format!("Entity {}", entity.id()),
+    }
+}
+
+pub struct HierarchyPanel {
+    undo: SharedUndo,
+}
+
+impl HierarchyPanel {
+    pub fn new(undo: SharedUndo) -> HierarchyPanel {
+        HierarchyPanel { undo }
+    }
+
+    fn row(
+        ui: &mut Ui,
+        world: &World,
+        children: &HashMap<Entity, Vec<Entity>>,
+        entity: Entity,
+        selection: Option<Entity>,
+        clicked: &mut Option<Entity>,
+        depth: usize,
+    ) {
+        let label = entity_label(world, entity);
+        let is_selected = selection == Some(entity);
+
+        let Some(kids) = children.get(&entity).filter(|_| depth < MAX_DEPTH) else {
+            if ui.selectable_label(is_selected, label).clicked() {
+                *clicked = Some(entity);
+            }
+            return;
+        };
+
+        let id = ui.make_persistent_id(("hierarchy", entity));
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
+            .show_header(ui, |ui| {
+                if ui.selectable_label(is_selected, label).clicked() {
+                    *clicked = Some(entity);
+                }
+            })
+            .body(|ui| {
+                for kid in kids {
+                    Self::row(ui, world, children, *kid, selection, clicked, depth + 1);
+                }
+            });
+    }
+}
+
+impl OverlayPanel for HierarchyPanel {
+    fn name(&self) -> &'static str {
+        "Hierarchy"
+    }
+
+    fn ui(&mut self, ui: &mut Ui, app: &mut WinitApp) {
+        let world = app.world_mut();
+
+        if ui.button("Spawn entity").clicked() {
+            let e = world.spawn((Name("Entity".into()), Transform::IDENTITY));
+            let snapshot = snapshot_entity(world, e);
+            self.undo.borrow_mut().record(Command::spawn(e, snapshot));
+            select(world, Some(e));
+        }
+        ui.separator();
+
+        let mut roots = Vec::new();
+        let mut children: HashMap<Entity, Vec<Entity>> = HashMap::new();
+        for (e, parent) in world.query::<Option<&Parent>>().iter() {
+            match parent {
+                Some(p) if world.contains(p.0) => children.entry(p.0).or_default().push(e),
+                _ => roots.push(e),
+            }
+        }
+        roots.sort_by_key(|e| e.id());
+        for kids in children.values_mut() {
+            kids.sort_by_key(|e| e.id());
+        }
+
+        let selection = selected(world);
+        let mut clicked = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for root in roots {
+                Self::row(ui, world, &children, root, selection, &mut clicked, 0);
+            }
+        });
+
+        if clicked.is_some() {
+            select(world, clicked);
+        }
+    }
+}
+
+fn vec3_ui(ui: &mut Ui, label: &str, v: &mut Vec3, speed: f32) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let mut changed = false;
+        for c in [&mut v.x, &mut v.y, &mut v.z] {
+            changed |= ui.add(DragValue::new(c).speed(speed)).changed();
+        }
+        changed
+    })
+    .inner
+}
+
+fn transform_ui(ui: &mut Ui, t: &mut Transform) {
+    vec3_ui(ui, "Translation", &mut t.translation, 0.05);
+
+    // Edited as Euler angles in degrees, but only written back when touched, so the quaternion doesn't drift.
+    let (y, x, z) = t.rotation.to_euler(EulerRot::YXZ);
+    let mut euler = Vec3::new(x, y, z) * (180.0 / std::f32::consts::PI);
+    if vec3_ui(ui, "Rotation", &mut euler, 0.5) {
+        let r = euler * (std::f32::consts::PI / 180.0);
+        t.rotation = Quat::from_euler(EulerRot::YXZ, r.y, r.x, r.z);
+    }
+
+    vec3_ui(ui, "Scale", &mut t.scale, 0.01);
+}
+
+fn light_ui(ui: &mut Ui, light: &mut Light) {
+    let mut color = light.color.to_vec3().to_array();
+    ui.horizontal(|ui| {
+        ui.label("Color");
+        ui.color_edit_button_rgb(&mut color);
+    });
+    light.color = LinearColor::rgb(color[0], color[1], color[2]);
+    ui.add(
+        DragValue::new(&mut light.intensity)
+            .speed(0.05)
+            .prefix("Intensity "),
+    );
+
+    match &mut light.kind {
+        LightKind::Directional => {}
+        LightKind::Point { range } => {
+            ui.add(DragValue::new(range).speed(0.1).prefix("Range "));
+        }
+        LightKind::Spot {
+            range,
+            inner_angle,
+            outer_angle,
+        } => {
+            ui.add(DragValue::new(range).speed(0.1).prefix("Range "));
+            ui.drag_angle(inner_angle);
+            ui.drag_angle(outer_angle);
+        }
+    }
+}
+
+fn water_ui(ui: &mut Ui, water: &mut Water) {
+    for (label, color) in [
+        ("Shallow", &mut water.shallow_color),
+        ("Deep", &mut water.deep_color),
+    ] {
+        let mut rgb = color.to_vec3().to_array();
+        ui.horizontal(|ui| {
+            ui.label(label);
+            ui.color_edit_button_rgb(&mut rgb);
+        });
+        *color = LinearColor::rgb(rgb[0], rgb[1], rgb[2]);
+    }
+    ui.add(
+        DragValue::new(&mut water.depth_fade)
+            .speed(0.05)
+            .range(0.01..=f32::MAX)
+            .prefix("Depth fade "),
+    );
+    ui.add(
+        DragValue::new(&mut water.shore_fade)
+            .speed(0.01)
+            .range(0.01..=f32::MAX)
+            .prefix("Shore fade "),
+    );
+    ui.add(
+        DragValue::new(&mut water.distortion)
+            .speed(0.001)
+            .prefix("Distortion "),
+    );
+    ui.add(
+        DragValue::new(&mut water.ripple_scale)
+            .speed(0.05)
+            .range(0.01..=f32::MAX)
+            .prefix("Ripple scale "),
+    );
+
+    for (i, wave) in water.waves.iter_mut().enumerate() {
+        ui.label(format!("Wave {}", i + 1));
+        ui.horizontal(|ui| {
+            ui.add(
+                DragValue::new(&mut wave.direction.x)
+                    .speed(0.01)
+                    .prefix("x "),
+            );
+            ui.add(
+                DragValue::new(&mut wave.direction.y)
+                    .speed(0.01)
+                    .prefix("z "),
+            );
+        });
+        ui.add(
+            DragValue::new(&mut wave.wavelength)
+                .speed(0.05)
+                .range(0.01..=f32::MAX)
+                .prefix("Wavelength "),
+        );
+        ui.add(
+            DragValue::new(&mut wave.amplitude)
+                .speed(0.01)
+                .prefix("Amplitude "),
+        );
+        ui.add(
+            DragValue::new(&mut wave.steepness)
+                .speed(0.01)
+                .range(0.0..=1.0)
+                .prefix("Steepness "),
+        );
+    }
+}
+
+fn camera_ui(ui: &mut Ui, camera: &mut Camera) {
+    ui.checkbox(&mut camera.active, "Active");
+    ui.add(DragValue::new(&mut camera.order).prefix("Order "));
+
+    match &mut camera.projection {
+        Projection::Perspective { fov_y, near, far } => {
+            ui.horizontal(|ui| {
+                ui.label("Vertical FOV");
+                ui.drag_angle(fov_y);
+            });
+            ui.add(DragValue::new(near).speed(0.01).prefix("Near "));
+            ui.add(DragValue::new(far).speed(1.0).prefix("Far "));
+        }
+        Projection::Orthographic { height, near, far } => {
+            ui.add(DragValue::new(height).speed(0.1).prefix("Height "));
+            ui.add(DragValue::new(near).speed(0.01).prefix("Near "));
+            ui.add(DragValue::new(far).speed(1.0).prefix("Far "));
+        }
+    }
+
+    let mut dof = camera.lens.is_some();
+    if ui.checkbox(&mut dof, "Depth of field").changed() {
+        camera.lens = dof.then(Lens::standard);
+    }
+    if let Some(lens) = &mut camera.lens {
+        ui.add(
+            DragValue::new(&mut lens.focal_length)
+                .speed(0.5)
+                .range(1.0..=f32::MAX)
+                .prefix("Focal length ")
+                .suffix("mm"),
+        );
+        ui.add(
+            DragValue::new(&mut lens.f_stop)
+                .speed(0.05)
+                .range(0.5..=64.0)
+                .prefix("f/"),
+        );
+        ui.add(
+            DragValue::new(&mut lens.focus_distance)
+                .speed(0.05)
+                .range(0.01..=f32::MAX)
+                .prefix("Focus ")
+                .suffix("m"),
+        );
+        ui.add(
+            DragValue::new(&mut lens.sensor_height)
+                .speed(0.1)
+                .range(1.0..=f32::MAX)
+                .prefix("Sensor height ")
+                .suffix("mm"),
+        );
+        ui.add(
+            DragValue::new(&mut lens.blades)
+                .range(0..=16)
+                .prefix("Blades "),
+        );
+        if let Projection::Perspective { fov_y, .. } = &mut camera.projection
+            && ui.button("Match FOV to lens").clicked()
+        {
+            *fov_y = lens.fov_y();
+        }
+    }
+}
+
+/// Edit a component through `f`, recording any change with the undo stack.
+/// Returns false if the entity doesn't have one.
+fn edit<T: Component + Clone + PartialEq>(
+    world: &World,
+    undo: &mut UndoStack,
+    entity: Entity,
+    name: &str,
+    f: impl FnOnce(&mut T),
+) -> bool {
+    let Ok(mut c) = world.get::<&mut T>(entity) else {
+        return false;
+    };
+
+    let before = (*c).clone();
+    f(&mut c);
+    if *c != before {
+        undo.record(Command::set_component(name, entity, before, (*c).clone()));
+    }
+    return true;
+}
+
+fn has<T: Component>(world: &World, entity: Entity) -> bool {
+    world.entity(entity).is_ok_and(|e| e.has::<T>())
+}
+
+pub struct InspectorPanel {
+    undo: SharedUndo,
+}
+
+impl InspectorPanel {
+    pub fn new(undo: SharedUndo) -> InspectorPanel {
+        InspectorPanel { undo }
+    }
+}
+
+impl OverlayPanel for InspectorPanel {
+    fn name(&self) -> &'static str {
+        "Inspector"
+    }
+
+    fn ui(&mut self, ui: &mut Ui, app: &mut WinitApp) {
+        let world = app.world_mut();
+        let undo = &mut *self.undo.borrow_mut();
+        let Some(entity) = selected(world) else {
+            ui.label("Nothing selected.");
+            return;
+        };
+
+        ui.label(format!("Entity {}", entity.id()));
+        let has_name = edit::<Name>(world, undo, entity, "Rename", |name| {
+            ui.text_edit_singleline(&mut name.0);
+        });
+        if !has_name && ui.button("Add name").clicked() {
+            let name = Name(format!("Entity {}", entity.id()));
+            let _ = world.insert_one(entity, name.clone());
+            undo.record(Command::insert_component("Add name", entity, name));
+        }
+
+        if let Ok(parent) = world.get::<&Parent>(entity) {
+            ui.label(format!("Parent: {}", entity_label(world, parent.0)));
+        }
+
+        if has::<Transform>(world, entity) {
+            ui.collapsing("Transform", |ui| {
+                edit::<Transform>(world, undo, entity, "Edit transform", |t| {
+                    transform_ui(ui, t)
+                })
+            });
+        }
+
+        if has::<MeshRenderer>(world, entity) {
+            ui.collapsing("Mesh renderer", |ui| {
+                edit::<MeshRenderer>(world, undo, entity, "Edit mesh renderer", |mr| {
+                    ui.add(DragValue::new(&mut mr.mesh.0).prefix("Mesh "));
+                    ui.add(DragValue::new(&mut mr.material.0).prefix("Material "));
+                    ui.checkbox(&mut mr.visible, "Visible");
+                    ui.checkbox(&mut mr.cast_shadows, "Cast shadows");
+                })
+            });
+        }
+
+        if has::<Light>(world, entity) {
+            ui.collapsing("Light", |ui| {
+                edit::<Light>(world, undo, entity, "Edit light", |l| light_ui(ui, l))
+            });
+        }
+
+        if has::<Camera>(world, entity) {
+            ui.collapsing("Camera", |ui| {
+                edit::<Camera>(world, undo, entity, "Edit camera", |c| camera_ui(ui, c))
+            });
+        }
+
+        if has::<PlanarReflector>(world, entity) {
+            ui.collapsing("Planar reflector", |ui| {
+                edit::<PlanarReflector>(world, undo, entity, "Edit planar reflector", |r| {
+                    ui.checkbox(&mut r.active, "Active");
+                    ui.add(
+                        DragValue::new(&mut r.resolution_scale)
+                            .speed(0.01)
+                            .range(0.1..=1.0)
+                            .prefix("Resolution scale "),
+                    );
+                    ui.add(
+                        DragValue::new(&mut r.clip_offset)
+                            .speed(0.005)
+                            .prefix("Clip offset "),
+                    );
+                })
+            });
+        }
+
+        if has::<Water>(world, entity) {
+            ui.collapsing("Water", |ui| {
+                edit::<Water>(world, undo, entity, "Edit water", |w| water_ui(ui, w))
+            });
+        }
+
+        ui.separator();
+        if ui.button("Despawn").clicked() {
+            // Children get left behind as roots, which is more forgiving than taking them too.
+            let snapshot = snapshot_entity(world, entity);
+            if world.despawn(entity).is_ok() {
+                undo.record(Command::despawn(entity, snapshot));
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Undo/redo for editor operations. Edits get made as usual and then recorded here,
+//! as a pair of closures that redo and revert them.
+
+use std::{
+    any::type_name,
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use hecs::{BuiltEntityClone, Component, Entity, EntityBuilderClone, World};
+
+use crate::ecs::components::{
+    Camera, GlobalTransform, Light, MeshRenderer, Name, Parent, PlanarReflector, Portal, Transform,
+    Water,
+};
+
+/// Commands kept before the oldest start falling off.
+const LIMIT: usize = 256;
+/// Edits to the same thing this close together merge, so typing a name is one undo, not one per letter.
+const MERGE_WINDOW: Duration = Duration::from_millis(500);
+
+type EditFn = Box<dyn FnMut(&mut World)>;
+
+/// What a command touched, for merging continuous edits (drags, typing) into one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergeKey {
+    pub entity: Entity,
+    pub what: &'static str,
+}
+
+pub struct Command {
+    pub name: String,
+    apply: EditFn,
+    revert: EditFn,
+    merge_key: Option<MergeKey>,
+}
+
+impl Command {
+    pub fn new(
+        name: impl Into<String>,
+        apply: impl FnMut(&mut World) + 'static,
+        revert: impl FnMut(&mut World) + 'static,
+    ) -> Command {
+        Command {
+            name: name.into(),
+            apply: Box::new(apply),
+            revert: Box::new(revert),
+            merge_key: None,
+        }
+    }
+
+    pub fn merging(mut self, key: MergeKey) -> Command {
+        self.merge_key = Some(key);
+        return self;
+    }
+
+    /// A component changed from `before` to `after`. Merges with other changes to the same component.
+    pub fn set_component<T: Component + Clone>(
+        name: impl Into<String>,
+        entity: Entity,
+        before: T,
+        after: T,
+    ) -> Command {
+        Command::new(
+            name,
+            move |world| {
+                let _ = world.insert_one(entity, after.clone());
+            },
+            move |world| {
+                let _ = world.insert_one(entity, before.clone());
+            },
+        )
+        .merging(MergeKey {
+            entity,
+            what: type_name::<T>(),
+        })
+    }
+
+    /// A component was added.
+    pub fn insert_component<T: Component + Clone>(
+        name: impl Into<String>,
+        entity: Entity,
+        value: T,
+    ) -> Command {
+        Command::new(
+            name,
+            move |world| {
+                let _ = world.insert_one(entity, value.clone());
+            },
+            move |world| {
+                let _ = world.remove_one::<T>(entity);
+            },
+        )
+    }
+
+    /// An entity was spawned, and looked like `snapshot` right after.
+    pub fn spawn(entity: Entity, snapshot: BuiltEntityClone) -> Command {
+        Command::new(
+            "Spawn entity",
+            move |world| world.spawn_at(entity, &snapshot),
+            move |world| {
+                let _ = world.despawn(entity);
+            },
+        )
+    }
+
+    /// An entity was despawned, and looked like `snapshot` right before.
+    pub fn despawn(entity: Entity, snapshot: BuiltEntityClone) -> Command {
+        Command::new(
+            "Despawn entity",
+            move |world| {
+                let _ = world.despawn(entity);
+            },
+            move |world| world.spawn_at(entity, &snapshot),
+        )
+    }
+}
+
+/// A copy of an entity's components, for bringing it back later.
+/// Only knows about the engine's components, anything else is lost.
+pub fn snapshot_entity(world: &World, entity: Entity) -> BuiltEntityClone {
+    let mut builder = EntityBuilderClone::new();
+
+    fn copy<T: Component + Clone>(world: &World, entity: Entity, builder: &mut EntityBuilderClone) {
+        if let Ok(c) = world.get::<&T>(entity) {
+            builder.add((*c).clone());
+        }
+    }
+
+    copy::<Name>(world, entity, &mut builder);
+    copy::<Transform>(world, entity, &mut builder);
+    copy::<GlobalTransform>(world, entity, &mut builder);
+    copy::<Parent>(world, entity, &mut builder);
+    copy::<MeshRenderer>(world, entity, &mut builder);
+    copy::<Light>(world, entity, &mut builder);
+    copy::<Camera>(world, entity, &mut builder);
+    copy::<PlanarReflector>(world, entity, &mut builder);
+    copy::<Water>(world, entity, &mut builder);
+    copy::<Portal>(world, entity, &mut builder);
+
+    return builder.build();
+}
+
+pub struct UndoStack {
+    done: Vec<Command>,
+    undone: Vec<Command>,
+    /// Whether the top command is closed to merging.
+    sealed: bool,
+    last_record: Instant,
+}
+
+/// The undo stack, shared between the editor and its panels.
+pub type SharedUndo = Rc<RefCell<UndoStack>>;
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        UndoStack {
+            done: Vec::new(),
+            undone: Vec::new(),
+            sealed: true,
+            last_record: Instant::now(),
+        }
+    }
+}
+
+impl UndoStack {
+    /// Record an edit that's already been made.
+    pub fn record(&mut self, command: Command) {
+        self.undone.clear();
+        self.last_record = Instant::now();
+
+        if let Some(top) = self.done.last_mut()
+            && !self.sealed
+            && command.merge_key.is_some()
+            && top.merge_key == command.merge_key
+        {
+            // Keep the oldest revert and the newest apply, and the pair covers the whole edit.
+            top.apply = command.apply;
+            return;
+        }
+
+        if self.done.len() == LIMIT {
+            self.done.remove(0);
+        }
+        self.done.push(command);
+        self.sealed = false;
+    }
+
+    /// Stop the last command merging with anything recorded after this.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Seal once an edit looks finished: nothing's held down, and it's been a moment since the last change.
+    pub fn seal_if_idle(&mut self, pointer_down: bool) {
+        if !pointer_down && self.last_record.elapsed() > MERGE_WINDOW {
+            self.seal();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Revert the last command, returning its name.
+    pub fn undo(&mut self, world: &mut World) -> Option<&str> {
+        let mut command = self.done.pop()?;
+        (command.revert)(world);
+        self.sealed = true;
+        self.undone.push(command);
+        return self.undone.last().map(|c| c.name.as_str());
+    }
+
+    /// Redo the last undone command, returning its name.
+    pub fn redo(&mut self, world: &mut World) -> Option<&str> {
+        let mut command = self.undone.pop()?;
+        (command.apply)(world);
+        self.sealed = true;
+        self.done.push(command);
+        return self.done.last().map(|c| c.name.as_str());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+    use hecs::World;
+
+    use super::{Command, UndoStack, snapshot_entity};
+    use crate::ecs::components::{Name, Transform};
+
+    #[test]
+    pub fn merged_drags_undo_together() {
+        let mut world = World::new();
+        let e = world.spawn((Transform::IDENTITY,));
+        let mut undo = UndoStack::default();
+
+        // A drag, in three steps.
+        let mut prev = Transform::IDENTITY;
+        for x in 1..=3 {
+            let next = Transform::from_translation(Vec3::X * x as f32);
+            world.insert_one(e, next).unwrap();
+            undo.record(Command::set_component("Move", e, prev, next));
+            prev = next;
+        }
+        undo.seal();
+
+        undo.undo(&mut world);
+        assert_eq!(*world.get::<&Transform>(e).unwrap(), Transform::IDENTITY);
+        assert!(!undo.can_undo());
+
+        undo.redo(&mut world);
+        assert_eq!(
+            world.get::<&Transform>(e).unwrap().translation,
+            Vec3::X * 3.0
+        );
+    }
+
+    #[test]
+    pub fn despawn_comes_back() {
+        let mut world = World::new();
+        let e = world.spawn((Name("thing".into()), Transform::IDENTITY));
+        let mut undo = UndoStack::default();
+
+        let snapshot = snapshot_entity(&world, e);
+        world.despawn(e).unwrap();
+        undo.record(Command::despawn(e, snapshot));
+
+        assert_eq!(undo.undo(&mut world), Some("Despawn entity"));
+        assert_eq!(world.get::<&Name>(e).unwrap().0, "thing");
+        undo.redo(&mut world);
+        assert!(!world.contains(e));
+    }
+}
@@ -0,0 +1,261 @@
+//! Hot reloading of game code. The game lives in a library exporting [`GAME_ENTRY`], which hands back
+//! the game as a [`Plugin`]. When the library is rebuilt it gets swapped out under the running app, and
+//! since windows, the device and the world all belong to the app, they carry straight over.
+//!
+//! The engine has to be shared between the two, so its statics like the console buffer are the same on
+//! both sides. The `crowbar-dylib` package in `dylib/` builds it as a Rust dylib, with a `crowbar-host`
+//! binary linked against it, run as `cargo run -- --game <lib>` from there. The game is a `dylib`
+//! depending on `crowbar-dylib`, built with `-C prefer-dynamic` like it, and exports its entry point
+//! with [`hot_reload_game`](crate::hot_reload_game):
+//!
+//! ```ignore
+//! crowbar_dylib::hot_reload_game!(MyGame::default());
+//! ```
+//!
+//! The plugin still crosses as a Rust trait object, so the game has to be built by the same compiler
+//! with the same engine source and flags, and nothing crosses the boundary that wasn't made on the same
+//! side of it.
+//! Anything the game leaves behind in the app that points into its code (overlay panels, console
+//! commands, cvar callbacks, components with drop glue from the library) has to be cleaned up in
+//! `shutdown`, or it dangles once the old library is unloaded.
+
+use std::{
+    fs, io,
+    mem::ManuallyDrop,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use libloading::{Library, Symbol};
+use winit::{
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, ControlFlow},
+    window::WindowId,
+};
+
+use crate::{app::WinitApp, plugin::Plugin};
+
+/// The symbol the game library exports, an `extern "C" fn() -> *mut Box<dyn Plugin>` handing over the game.
+pub const GAME_ENTRY: &[u8] = b"crowbar_game_create";
+
+type CreateFn = unsafe extern "C" fn() -> *mut Box<dyn Plugin>;
+
+/// Export [`GAME_ENTRY`] from a game library, creating the game with `$game`.
+#[macro_export]
+macro_rules! hot_reload_game {
+    ($game:expr) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn crowbar_game_create()
+        -> *mut ::std::boxed::Box<dyn $crate::plugin::Plugin> {
+            let game: ::std::boxed::Box<dyn $crate::plugin::Plugin> =
+                ::std::boxed::Box::new($game);
+            return ::std::boxed::Box::into_raw(::std::boxed::Box::new(game));
+        }
+    };
+}
+
+/// How often the library is checked for changes.
+const RELOAD_POLL: Duration = Duration::from_millis(250);
+/// How long the library has to go untouched before we load it, so we don't grab a half-linked file.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+struct LoadedGame {
+    game: ManuallyDrop<Box<dyn Plugin>>,
+    library: ManuallyDrop<Library>,
+    /// The copy we actually loaded. The original stays free for the linker to overwrite.
+    shadow: PathBuf,
+}
+
+impl LoadedGame {
+    fn load(source: &Path, shadow: PathBuf) -> io::Result<LoadedGame> {
+        match fs::copy(source, &shadow).and_then(|_| LoadedGame::open(&shadow)) {
+            Ok((game, library)) => Ok(LoadedGame {
+                game: ManuallyDrop::new(game),
+                library: ManuallyDrop::new(library),
+                shadow,
+            }),
+            Err(e) => {
+                // Whatever got loaded was dropped on the way out of `open`, so the copy is free to go.
+                let _ = fs::remove_file(&shadow);
+                Err(e)
+            }
+        }
+    }
+
+    fn open(shadow: &Path) -> io::Result<(Box<dyn Plugin>, Library)> {
+        // SAFETY: Loading runs the library's initializers, and calling the entry point trusts it has the
+        // signature we expect and hands over a box of its own, as `hot_reload_game` makes. Both are on
+        // whoever built the library, see the module docs.
+        unsafe {
+            let library = Library::new(shadow).map_err(io::Error::other)?;
+            let create: Symbol<CreateFn> = library.get(GAME_ENTRY).map_err(io::Error::other)?;
+            let game = create();
+            if game.is_null() {
+                return Err(io::Error::other("the game's entry point returned nothing"));
+            }
+            let game = *Box::from_raw(game);
+            return Ok((game, library));
+        }
+    }
+}
+
+impl Drop for LoadedGame {
+    fn drop(&mut self) {
+        // SAFETY: Neither is touched again. The game goes first, its drop glue lives in the library.
+        unsafe {
+            ManuallyDrop::drop(&mut self.game);
+            ManuallyDrop::drop(&mut self.library);
+        }
+        // Windows won't let us remove it while it's loaded, so this has to come last.
+        let _ = fs::remove_file(&self.shadow);
+    }
+}
+
+/// Runs game code from a dynamic library, reloading it whenever the file changes.
+pub struct HotReloadPlugin {
+    source: PathBuf,
+    loaded: Option<LoadedGame>,
+    /// When the library we last tried to load was modified, whether or not it loaded. A broken build
+    /// isn't retried until it changes again.
+    attempted: Option<SystemTime>,
+    /// Bumped every load, so each copy gets its own file name. Some platforms cache libraries by path.
+    generation: u32,
+    last_poll: Instant,
+}
+
+impl HotReloadPlugin {
+    pub fn new(source: impl Into<PathBuf>) -> HotReloadPlugin {
+        HotReloadPlugin {
+            source: source.into(),
+            loaded: None,
+            attempted: None,
+            generation: 0,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn load_next(&mut self) -> io::Result<LoadedGame> {
+        self.attempted = fs::metadata(&self.source).and_then(|m| m.modified()).ok();
+        self.generation += 1;
+        let name = self
+            .source
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let shadow = std::env::temp_dir().join(format!(
+            "crowbar-{}-{}-{name}",
+            std::process::id(),
+            self.generation
+        ));
+
+        return LoadedGame::load(&self.source, shadow);
+    }
+
+    /// Whether the library on disk is newer than the loaded one, and done being written.
+    fn changed(&self) -> bool {
+        let Ok(modified) = fs::metadata(&self.source).and_then(|m| m.modified()) else {
+            return false;
+        };
+
+        let settled = modified.elapsed().is_ok_and(|age| age >= SETTLE_TIME);
+        return settled && self.attempted != Some(modified);
+    }
+
+    fn reload(&mut self, app: &mut WinitApp, event_loop: &ActiveEventLoop) {
+        // Load the new one first, so a broken build leaves the old game running.
+        let mut next = match self.load_next() {
+            Ok(next) => next,
+            Err(e) => {
+                log::error!("Couldn't load game {}: {e}", self.source.display());
+                return;
+            }
+        };
+
+        if let Some(mut old) = self.loaded.take() {
+            old.game.shutdown(app);
+        }
+
+        next.game.init(app, event_loop);
+        log::info!(
+            "Loaded game {} ({})",
+            self.source.display(),
+            next.game.name()
+        );
+        self.loaded = Some(next);
+    }
+}
+
+impl Plugin for HotReloadPlugin {
+    fn name(&self) -> &'static str {
+        "hot_reload"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, event_loop: &ActiveEventLoop) {
+        self.reload(app, event_loop);
+    }
+
+    fn about_to_wait(&mut self, app: &mut WinitApp, event_loop: &ActiveEventLoop) {
+        if self.last_poll.elapsed() >= RELOAD_POLL {
+            self.last_poll = Instant::now();
+            if self.changed() {
+                self.reload(app, event_loop);
+            }
+        }
+        // Wake up for the next poll, even with nothing else going on.
+        event_loop.set_control_flow(ControlFlow::WaitUntil(self.last_poll + RELOAD_POLL));
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        if let Some(loaded) = &mut self.loaded {
+            loaded.game.pre_frame(app);
+        }
+    }
+
+    fn post_frame(&mut self, app: &mut WinitApp) {
+        if let Some(loaded) = &mut self.loaded {
+            loaded.game.post_frame(app);
+        }
+    }
+
+    fn window_event(&mut self, app: &mut WinitApp, window: WindowId, event: &WindowEvent) -> bool {
+        match &mut self.loaded {
+            Some(loaded) => loaded.game.window_event(app, window, event),
+            None => false,
+        }
+    }
+
+    fn shutdown(&mut self, app: &mut WinitApp) {
+        if let Some(mut loaded) = self.loaded.take() {
+            loaded.game.shutdown(app);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn broken_library_is_cleaned_up_and_not_retried() {
+        let source =
+            std::env::temp_dir().join(format!("crowbar-test-{}-broken.so", std::process::id()));
+        let file = fs::File::create(&source).unwrap();
+        // Old enough to count as settled.
+        file.set_modified(SystemTime::now() - Duration::from_secs(10))
+            .unwrap();
+        drop(file);
+
+        let mut plugin = HotReloadPlugin::new(&source);
+        assert!(plugin.changed());
+        assert!(plugin.load_next().is_err());
+        let shadow = std::env::temp_dir().join(format!(
+            "crowbar-{}-1-{}",
+            std::process::id(),
+            source.file_name().unwrap().to_string_lossy()
+        ));
+        assert!(!shadow.exists());
+        assert!(!plugin.changed());
+
+        let _ = fs::remove_file(&source);
+    }
+}
@@ -0,0 +1,355 @@
+//! Keyboard, mouse and touch state, accumulated from window events.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::{
+    event::{ElementState, Ime, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+/// The subset of window events that input state is built from.
+/// Kept separate from winit's events so they can be recorded and replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key {
+        key: KeyCode,
+        pressed: bool,
+    },
+    Button {
+        button: MouseButton,
+        pressed: bool,
+    },
+    /// Physical pixels.
+    CursorMoved {
+        x: f32,
+        y: f32,
+    },
+    /// Lines.
+    Scroll {
+        x: f32,
+        y: f32,
+    },
+    /// Physical pixels. `id` identifies the finger until it lifts, then may be reused.
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        x: f32,
+        y: f32,
+    },
+    FocusLost,
+    /// A character typed, after the keyboard layout and any IME. Control characters are left to [`InputEvent::Key`].
+    Text {
+        ch: char,
+    },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<InputEvent> {
+        let event = match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(key) = event.physical_key else {
+                    return None;
+                };
+
+                InputEvent::Key {
+                    key,
+                    pressed: event.state == ElementState::Pressed,
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => InputEvent::Button {
+                button: *button,
+                pressed: *state == ElementState::Pressed,
+            },
+            WindowEvent::CursorMoved { position, .. } => InputEvent::CursorMoved {
+                x: position.x as f32,
+                y: position.y as f32,
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+                    // Roughly a line's worth of pixels, it's all guesswork anyway.
+                    MouseScrollDelta::PixelDelta(p) => (p.x as f32 / 20.0, p.y as f32 / 20.0),
+                };
+                InputEvent::Scroll { x, y }
+            }
+            WindowEvent::Touch(touch) => InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                x: touch.location.x as f32,
+                y: touch.location.y as f32,
+            },
+            WindowEvent::Focused(false) => InputEvent::FocusLost,
+            _ => return None,
+        };
+
+        return Some(event);
+    }
+
+    /// The characters a window event typed, which come alongside its [`InputEvent::Key`].
+    pub fn text_from_window_event(event: &WindowEvent) -> impl Iterator<Item = InputEvent> + '_ {
+        let text = match event {
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                event.text.as_deref()
+            }
+            WindowEvent::Ime(Ime::Commit(text)) => Some(text.as_str()),
+            _ => None,
+        };
+        return text
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|ch| InputEvent::Text { ch });
+    }
+}
+
+/// Input state for the current frame.
+/// "Pressed"/"released" are edges and only true for the frame they happened in.
+///
+/// The first finger down also drives the cursor and the left mouse button, so mouse driven code
+/// (picking, the overlay) works on touch screens without knowing about them.
+#[derive(Default, Debug, Clone)]
+pub struct Input {
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    /// Physical pixels, relative to the top left of the focused window.
+    cursor_position: Vec2,
+    /// Accumulated scroll this frame, in lines.
+    scroll: Vec2,
+    /// Fingers down, and where, in physical pixels.
+    touches: HashMap<u64, Vec2>,
+    touches_started: HashSet<u64>,
+    touches_ended: HashSet<u64>,
+    /// The finger standing in for the mouse.
+    primary_touch: Option<u64>,
+    /// Typed this frame.
+    text: String,
+}
+
+impl Input {
+    /// Feed a window event in. Returns true if it was an input event we care about.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        let Some(event) = InputEvent::from_window_event(event) else {
+            return false;
+        };
+
+        self.apply(event);
+        return true;
+    }
+
+    pub fn apply(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Key { key, pressed: true } => {
+                // Key repeat shouldn't look like a fresh press.
+                if self.keys_down.insert(key) {
+                    self.keys_pressed.insert(key);
+                }
+            }
+            InputEvent::Key {
+                key,
+                pressed: false,
+            } => {
+                self.keys_down.remove(&key);
+                self.keys_released.insert(key);
+            }
+            InputEvent::Button {
+                button,
+                pressed: true,
+            } => {
+                self.buttons_down.insert(button);
+                self.buttons_pressed.insert(button);
+            }
+            InputEvent::Button {
+                button,
+                pressed: false,
+            } => {
+                self.buttons_down.remove(&button);
+                self.buttons_released.insert(button);
+            }
+            InputEvent::CursorMoved { x, y } => {
+                self.cursor_position = Vec2::new(x, y);
+            }
+            InputEvent::Scroll { x, y } => {
+                self.scroll += Vec2::new(x, y);
+            }
+            InputEvent::Touch { id, phase, x, y } => self.apply_touch(id, phase, Vec2::new(x, y)),
+            InputEvent::FocusLost => {
+                // We won't see the releases, so don't leave keys stuck down.
+                self.keys_released.extend(self.keys_down.drain());
+                self.buttons_released.extend(self.buttons_down.drain());
+                self.touches_ended
+                    .extend(self.touches.drain().map(|(id, _)| id));
+                self.primary_touch = None;
+            }
+            InputEvent::Text { ch } => self.text.push(ch),
+        }
+    }
+
+    fn apply_touch(&mut self, id: u64, phase: TouchPhase, position: Vec2) {
+        let primary = match phase {
+            TouchPhase::Started => {
+                self.touches.insert(id, position);
+                self.touches_started.insert(id);
+                *self.primary_touch.get_or_insert(id) == id
+            }
+            TouchPhase::Moved => {
+                if let Some(p) = self.touches.get_mut(&id) {
+                    *p = position;
+                }
+                self.primary_touch == Some(id)
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&id);
+                self.touches_ended.insert(id);
+                self.primary_touch == Some(id)
+            }
+        };
+        if !primary {
+            return;
+        }
+
+        self.apply(InputEvent::CursorMoved {
+            x: position.x,
+            y: position.y,
+        });
+        match phase {
+            TouchPhase::Started => self.apply(InputEvent::Button {
+                button: MouseButton::Left,
+                pressed: true,
+            }),
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.primary_touch = None;
+                self.apply(InputEvent::Button {
+                    button: MouseButton::Left,
+                    pressed: false,
+                });
+            }
+            TouchPhase::Moved => {}
+        }
+    }
+
+    /// Clear the per-frame edges. Call once the frame has consumed them.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.touches_started.clear();
+        self.touches_ended.clear();
+        self.scroll = Vec2::ZERO;
+        self.text.clear();
+    }
+
+    pub fn key_down(&self, key: KeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_released(&self, key: KeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keys_down.iter().copied()
+    }
+
+    pub fn keys_pressed(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keys_pressed.iter().copied()
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    pub fn cursor_position(&self) -> Vec2 {
+        self.cursor_position
+    }
+
+    pub fn scroll(&self) -> Vec2 {
+        self.scroll
+    }
+
+    /// Characters typed this frame, in order, for text fields and the like.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Fingers currently down, and where.
+    pub fn touches(&self) -> impl Iterator<Item = (u64, Vec2)> + '_ {
+        self.touches.iter().map(|(&id, &p)| (id, p))
+    }
+
+    pub fn touch(&self, id: u64) -> Option<Vec2> {
+        self.touches.get(&id).copied()
+    }
+
+    pub fn touch_started(&self, id: u64) -> bool {
+        self.touches_started.contains(&id)
+    }
+
+    pub fn touch_ended(&self, id: u64) -> bool {
+        self.touches_ended.contains(&id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec2;
+    use winit::event::{MouseButton, TouchPhase};
+
+    use super::{Input, InputEvent};
+
+    fn touch(id: u64, phase: TouchPhase, x: f32) -> InputEvent {
+        InputEvent::Touch {
+            id,
+            phase,
+            x,
+            y: 0.0,
+        }
+    }
+
+    #[test]
+    pub fn first_finger_is_the_mouse() {
+        let mut input = Input::default();
+        input.apply(touch(1, TouchPhase::Started, 10.0));
+        input.apply(touch(2, TouchPhase::Started, 50.0));
+        assert!(input.button_pressed(MouseButton::Left));
+        assert!(input.touch_started(2));
+        assert_eq!(input.cursor_position(), Vec2::new(10.0, 0.0));
+        input.end_frame();
+
+        // Only the first finger moves the cursor.
+        input.apply(touch(2, TouchPhase::Moved, 60.0));
+        input.apply(touch(1, TouchPhase::Moved, 20.0));
+        assert_eq!(input.cursor_position(), Vec2::new(20.0, 0.0));
+        assert_eq!(input.touch(2), Some(Vec2::new(60.0, 0.0)));
+
+        input.apply(touch(2, TouchPhase::Ended, 60.0));
+        assert!(input.button_down(MouseButton::Left));
+        input.apply(touch(1, TouchPhase::Cancelled, 20.0));
+        assert!(input.button_released(MouseButton::Left));
+        assert_eq!(input.touches().count(), 0);
+        input.end_frame();
+
+        // With the first finger gone, the next one down takes over.
+        input.apply(touch(3, TouchPhase::Started, 5.0));
+        assert!(input.button_pressed(MouseButton::Left));
+        assert_eq!(input.cursor_position(), Vec2::new(5.0, 0.0));
+    }
+}
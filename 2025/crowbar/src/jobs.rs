@@ -0,0 +1,264 @@
+//! The engine job system. A thin layer over a rayon pool that adds names and dependencies.
+//!
+//! Jobs are fire-and-forget closures, optionally waiting on other jobs before they start.
+//! Per-frame parallel work that borrows from the stack should use [`JobSystem::scope`] instead.
+
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::profile_scope;
+
+pub mod graph;
+
+thread_local! {
+    static CURRENT_JOB: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// The name of the job currently running on this thread, if any.
+pub fn current_job() -> Option<&'static str> {
+    CURRENT_JOB.get()
+}
+
+fn run_named<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+    profile_scope!(name);
+    let prev = CURRENT_JOB.replace(Some(name));
+    let res = f();
+    CURRENT_JOB.set(prev);
+    return res;
+}
+
+type Continuation = Box<dyn FnOnce() + Send>;
+
+struct JobState {
+    name: &'static str,
+    panicked: AtomicBool,
+    /// Jobs to poke when we finish. `None` once the job has completed.
+    continuations: Mutex<Option<Vec<Continuation>>>,
+    finished: Condvar,
+}
+
+impl JobState {
+    fn new(name: &'static str) -> JobState {
+        JobState {
+            name,
+            panicked: AtomicBool::new(false),
+            continuations: Mutex::new(Some(Vec::new())),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Run `c` once this job is done, immediately if it already is.
+    fn on_finish(&self, c: Continuation) {
+        let mut guard = self.continuations.lock().unwrap();
+        match guard.as_mut() {
+            Some(list) => list.push(c),
+            None => {
+                drop(guard);
+                c();
+            }
+        }
+    }
+
+    fn finish(&self) {
+        let continuations = self
+            .continuations
+            .lock()
+            .unwrap()
+            .take()
+            .expect("Job finished twice!");
+        self.finished.notify_all();
+
+        for c in continuations {
+            c();
+        }
+    }
+}
+
+/// A handle to a spawned job, usable as a dependency of other jobs or waited on directly.
+#[derive(Clone)]
+pub struct JobHandle(Arc<JobState>);
+
+impl JobHandle {
+    pub fn name(&self) -> &'static str {
+        self.0.name
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.0.continuations.lock().unwrap().is_none()
+    }
+
+    /// Whether the job's body panicked. Dependents still run, it's on them to check.
+    pub fn panicked(&self) -> bool {
+        self.0.panicked.load(Ordering::Acquire)
+    }
+
+    /// Block until the job is done.
+    /// Don't call this from inside a job, you'll eat a worker thread (or deadlock a small pool).
+    pub fn wait(&self) {
+        let mut guard = self.0.continuations.lock().unwrap();
+        while guard.is_some() {
+            guard = self.0.finished.wait(guard).unwrap();
+        }
+    }
+}
+
+/// Cheap to clone, clones share the workers.
+#[derive(Clone)]
+pub struct JobSystem {
+    pool: Arc<ThreadPool>,
+}
+
+impl JobSystem {
+    /// Create a job system with `threads` workers, or one per core if `None`.
+    pub fn new(threads: Option<usize>) -> JobSystem {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .thread_name(|i| format!("crowbar-worker-{i}"))
+            .build()
+            .expect("Job system thread pool creation failed.");
+
+        JobSystem {
+            pool: Arc::new(pool),
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Spawn a job that starts as soon as a worker is free.
+    pub fn spawn(&self, name: &'static str, f: impl FnOnce() + Send + 'static) -> JobHandle {
+        self.spawn_after(name, &[], f)
+    }
+
+    /// Spawn a job that only starts once every job in `deps` has finished.
+    pub fn spawn_after(
+        &self,
+        name: &'static str,
+        deps: &[JobHandle],
+        f: impl FnOnce() + Send + 'static,
+    ) -> JobHandle {
+        let state = Arc::new(JobState::new(name));
+        // One extra count held by us, so the job can't start while we're still registering it.
+        let pending = Arc::new(AtomicUsize::new(deps.len() + 1));
+
+        let start = {
+            let pool = self.pool.clone();
+            let state = state.clone();
+            let f = Mutex::new(Some(f));
+
+            Arc::new(move || {
+                let f = f.lock().unwrap().take().expect("Job started twice!");
+                let state = state.clone();
+                pool.spawn(move || {
+                    let res = run_named(state.name, || panic::catch_unwind(AssertUnwindSafe(f)));
+                    if res.is_err() {
+                        log::error!("Job {} panicked.", state.name);
+                        state.panicked.store(true, Ordering::Release);
+                    }
+                    state.finish();
+                });
+            })
+        };
+
+        let release = move || {
+            if pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                start();
+            }
+        };
+
+        for dep in deps {
+            let release = release.clone();
+            dep.0.on_finish(Box::new(release));
+        }
+
+        release();
+
+        return JobHandle(state);
+    }
+
+    /// Run `f` with a scope that jobs borrowing from the caller can be spawned into.
+    /// Returns once every job spawned in the scope has finished, making it the tool for per-frame fan-out.
+    pub fn scope<'scope, R: Send>(&self, f: impl FnOnce(&JobScope<'_, 'scope>) -> R + Send) -> R {
+        self.pool.scope(|s| f(&JobScope { inner: s }))
+    }
+
+    /// Run `f` inside the pool, so rayon parallel iterators in it use our workers.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(f)
+    }
+}
+
+pub struct JobScope<'a, 'scope> {
+    inner: &'a rayon::Scope<'scope>,
+}
+
+impl<'a, 'scope> JobScope<'a, 'scope> {
+    pub fn spawn<F>(&self, name: &'static str, f: F)
+    where
+        F: FnOnce(&JobScope<'_, 'scope>) + Send + 'scope,
+    {
+        self.inner
+            .spawn(move |s| run_named(name, || f(&JobScope { inner: s })));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::{JobSystem, current_job};
+
+    #[test]
+    pub fn dependencies_run_in_order() {
+        let jobs = JobSystem::new(Some(4));
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let push = |v: u32| {
+            let log = log.clone();
+            move || log.lock().unwrap().push(v)
+        };
+
+        let a = jobs.spawn("a", push(0));
+        let b = jobs.spawn("b", push(0));
+        let c = jobs.spawn_after("c", &[a, b], push(1));
+        let d = jobs.spawn_after("d", std::slice::from_ref(&c), push(2));
+
+        d.wait();
+        assert!(c.is_done());
+        assert_eq!(*log.lock().unwrap(), vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    pub fn dependency_already_finished() {
+        let jobs = JobSystem::new(Some(1));
+        let a = jobs.spawn("a", || {});
+        a.wait();
+
+        let b = jobs.spawn_after("b", &[a], || assert_eq!(current_job(), Some("b")));
+        b.wait();
+        assert!(!b.panicked());
+    }
+
+    #[test]
+    pub fn scoped_jobs_borrow() {
+        let jobs = JobSystem::new(Some(2));
+        let mut results = [0u32; 8];
+
+        jobs.scope(|s| {
+            for (i, r) in results.iter_mut().enumerate() {
+                s.spawn("fill", move |_| *r = i as u32 * 2);
+            }
+        });
+
+        assert_eq!(results, [0, 2, 4, 6, 8, 10, 12, 14]);
+    }
+}
@@ -0,0 +1,332 @@
+//! Task graphs, for CPU work with explicit dependencies that should overlap where it can.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use super::{JobScope, JobSystem};
+use crate::profile_scope;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+struct Task<'a> {
+    name: &'static str,
+    /// Tasks that wait on us.
+    dependents: Vec<TaskId>,
+    dependency_count: usize,
+    body: Mutex<Box<dyn FnMut() + Send + 'a>>,
+}
+
+/// When and where a task ran during the last [`TaskGraph::run`], relative to the start of the run.
+#[derive(Clone, Debug)]
+pub struct TaskTiming {
+    pub name: &'static str,
+    pub start: Duration,
+    pub end: Duration,
+    /// The worker thread the task ran on.
+    pub thread: Option<usize>,
+}
+
+/// A set of named tasks with dependencies between them, executed on the job system.
+/// Tasks run as soon as everything they depend on has finished, so unrelated chains overlap.
+#[derive(Default)]
+pub struct TaskGraph<'a> {
+    tasks: Vec<Task<'a>>,
+    timings: Vec<TaskTiming>,
+}
+
+impl<'a> TaskGraph<'a> {
+    pub fn new() -> TaskGraph<'a> {
+        TaskGraph {
+            tasks: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn add_task(
+        &mut self,
+        name: &'static str,
+        deps: &[TaskId],
+        body: impl FnMut() + Send + 'a,
+    ) -> TaskId {
+        let id = TaskId(self.tasks.len());
+        self.tasks.push(Task {
+            name,
+            dependents: Vec::new(),
+            dependency_count: 0,
+            body: Mutex::new(Box::new(body)),
+        });
+
+        for dep in deps {
+            self.add_dependency(*dep, id);
+        }
+
+        return id;
+    }
+
+    /// Make `after` wait on `before`.
+    pub fn add_dependency(&mut self, before: TaskId, after: TaskId) {
+        if self.tasks[before.0].dependents.contains(&after) {
+            return;
+        }
+
+        self.tasks[before.0].dependents.push(after);
+        self.tasks[after.0].dependency_count += 1;
+    }
+
+    pub fn name(&self, id: TaskId) -> &'static str {
+        self.tasks[id.0].name
+    }
+
+    /// Every task in the graph, in insertion order.
+    pub fn tasks(&self) -> impl Iterator<Item = TaskId> {
+        (0..self.tasks.len()).map(TaskId)
+    }
+
+    /// Every `(before, after)` edge in the graph.
+    pub fn edges(&self) -> impl Iterator<Item = (TaskId, TaskId)> + '_ {
+        self.tasks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, t)| t.dependents.iter().map(move |d| (TaskId(i), *d)))
+    }
+
+    /// Timings from the last run, in completion order.
+    pub fn timings(&self) -> &[TaskTiming] {
+        &self.timings
+    }
+
+    /// Kahn's algorithm, to catch cycles before they turn into a hang.
+    fn find_cycle(&self) -> Option<TaskId> {
+        let mut counts: Vec<usize> = self.tasks.iter().map(|t| t.dependency_count).collect();
+        let mut ready: Vec<usize> = (0..counts.len()).filter(|&i| counts[i] == 0).collect();
+        let mut visited = 0;
+
+        while let Some(i) = ready.pop() {
+            visited += 1;
+            for d in &self.tasks[i].dependents {
+                counts[d.0] -= 1;
+                if counts[d.0] == 0 {
+                    ready.push(d.0);
+                }
+            }
+        }
+
+        if visited == self.tasks.len() {
+            return None;
+        }
+
+        return counts.iter().position(|&c| c != 0).map(TaskId);
+    }
+
+    /// Run every task once, blocking until the whole graph is done.
+    /// Panics if the graph has a cycle in it.
+    pub fn run(&mut self, jobs: &JobSystem) {
+        {
+            profile_scope!("compile_task_graph");
+            if let Some(task) = self.find_cycle() {
+                panic!("Task graph has a cycle involving {}!", self.name(task));
+            }
+        }
+
+        let remaining: Vec<AtomicUsize> = self
+            .tasks
+            .iter()
+            .map(|t| AtomicUsize::new(t.dependency_count))
+            .collect();
+        let timings = Mutex::new(Vec::with_capacity(self.tasks.len()));
+        let epoch = Instant::now();
+
+        let run = RunState {
+            tasks: &self.tasks,
+            remaining: &remaining,
+            timings: &timings,
+            epoch,
+        };
+
+        jobs.scope(|s| {
+            for (i, t) in run.tasks.iter().enumerate() {
+                if t.dependency_count == 0 {
+                    run.spawn(s, i);
+                }
+            }
+        });
+
+        self.timings = timings.into_inner().unwrap();
+    }
+}
+
+/// Borrowed state shared by every task during a run.
+#[derive(Clone, Copy)]
+struct RunState<'r, 'a> {
+    tasks: &'r [Task<'a>],
+    remaining: &'r [AtomicUsize],
+    timings: &'r Mutex<Vec<TaskTiming>>,
+    epoch: Instant,
+}
+
+impl<'r, 'a> RunState<'r, 'a> {
+    fn spawn(self, scope: &JobScope<'_, 'r>, i: usize) {
+        let task = &self.tasks[i];
+
+        scope.spawn(task.name, move |s| {
+            let start = self.epoch.elapsed();
+            (task.body.lock().unwrap())();
+            let end = self.epoch.elapsed();
+
+            self.timings.lock().unwrap().push(TaskTiming {
+                name: task.name,
+                start,
+                end,
+                thread: rayon::current_thread_index(),
+            });
+
+            for d in &task.dependents {
+                if self.remaining[d.0].fetch_sub(1, Ordering::AcqRel) == 1 {
+                    self.spawn(s, d.0);
+                }
+            }
+        });
+    }
+}
+
+/// The coarse stages of a CPU frame, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FrameStage {
+    Input,
+    Simulation,
+    Culling,
+    Record,
+    Submit,
+}
+
+impl FrameStage {
+    pub const ALL: [FrameStage; 5] = [
+        FrameStage::Input,
+        FrameStage::Simulation,
+        FrameStage::Culling,
+        FrameStage::Record,
+        FrameStage::Submit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameStage::Input => "input",
+            FrameStage::Simulation => "simulation",
+            FrameStage::Culling => "culling",
+            FrameStage::Record => "record",
+            FrameStage::Submit => "submit",
+        }
+    }
+}
+
+/// A task graph preloaded with the frame stages.
+/// Each stage is a no-op marker task that completes once everything added to that stage has,
+/// and tasks in a stage only start once the previous stage is complete.
+/// Work that doesn't need the whole previous stage can go straight into `graph` with its own dependencies.
+pub struct FrameGraph<'a> {
+    pub graph: TaskGraph<'a>,
+    stages: [TaskId; 5],
+}
+
+impl<'a> FrameGraph<'a> {
+    pub fn new() -> FrameGraph<'a> {
+        let mut graph = TaskGraph::new();
+        let mut prev: Option<TaskId> = None;
+
+        let stages = FrameStage::ALL.map(|stage| {
+            let deps: &[TaskId] = match &prev {
+                Some(p) => std::slice::from_ref(p),
+                None => &[],
+            };
+            let id = graph.add_task(stage.name(), deps, || {});
+            prev = Some(id);
+            id
+        });
+
+        FrameGraph { graph, stages }
+    }
+
+    /// The marker task that completes when `stage` does.
+    pub fn stage(&self, stage: FrameStage) -> TaskId {
+        self.stages[stage as usize]
+    }
+
+    pub fn add_to_stage(
+        &mut self,
+        stage: FrameStage,
+        name: &'static str,
+        body: impl FnMut() + Send + 'a,
+    ) -> TaskId {
+        let deps: &[TaskId] = match stage as usize {
+            0 => &[],
+            i => std::slice::from_ref(&self.stages[i - 1]),
+        };
+
+        let id = self.graph.add_task(name, deps, body);
+        self.graph.add_dependency(id, self.stage(stage));
+        return id;
+    }
+
+    pub fn run(&mut self, jobs: &JobSystem) {
+        self.graph.run(jobs);
+    }
+}
+
+impl Default for FrameGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::{FrameGraph, FrameStage, TaskGraph};
+    use crate::jobs::JobSystem;
+
+    #[test]
+    pub fn stages_run_in_order() {
+        let jobs = JobSystem::new(Some(4));
+        let order = Mutex::new(Vec::new());
+
+        let mut frame = FrameGraph::new();
+        frame.add_to_stage(FrameStage::Record, "record", || {
+            order.lock().unwrap().push("record")
+        });
+        frame.add_to_stage(FrameStage::Culling, "cull_a", || {
+            order.lock().unwrap().push("cull")
+        });
+        frame.add_to_stage(FrameStage::Culling, "cull_b", || {
+            order.lock().unwrap().push("cull")
+        });
+        frame.add_to_stage(FrameStage::Input, "input", || {
+            order.lock().unwrap().push("input")
+        });
+        frame.run(&jobs);
+
+        assert_eq!(frame.graph.timings().len(), 9);
+        drop(frame);
+        assert_eq!(
+            order.into_inner().unwrap(),
+            vec!["input", "cull", "cull", "record"]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn cycles_are_caught() {
+        let jobs = JobSystem::new(Some(1));
+        let mut graph = TaskGraph::new();
+        let a = graph.add_task("a", &[], || {});
+        let b = graph.add_task("b", &[a], || {});
+        graph.add_dependency(b, a);
+        graph.run(&jobs);
+    }
+}
@@ -0,0 +1,152 @@
+//! Crowbar, as a library. The desktop binary in `main.rs` starts it through [`main`], and the Android entry point in
+//! `platform::android` through [`init`] and [`run`].
+
+#![cfg_attr(not(feature = "stable"), feature(allocator_api))]
+use winit::event_loop::EventLoop;
+
+use crate::{app::info::AppInfo, render::diag};
+
+pub mod anim;
+pub mod app;
+pub mod benchmark;
+pub mod budget;
+pub mod capture;
+pub mod cli;
+pub mod color;
+pub mod console;
+pub mod consts;
+pub mod cvar;
+pub mod debug_draw;
+pub mod ecs;
+pub mod editor;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod input;
+pub mod jobs;
+pub mod math;
+pub mod overlay;
+pub mod pack;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod picking;
+pub mod platform;
+pub mod plugin;
+pub mod profile;
+pub mod render;
+pub mod replay;
+pub mod rng;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod snapshot;
+pub mod state;
+#[cfg(test)]
+pub mod test_support;
+pub mod ui;
+
+/// The desktop binaries' `main`: take the command line, and run the app as it says.
+pub fn main() {
+    init();
+
+    let args = match cli::Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{e}");
+            }
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Before the event loop, as there might not be a display to connect to.
+    if args.diag {
+        let ok = diag::run(&AppInfo::default(), args.diag_output.clone());
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    run(EventLoop::new().unwrap(), &args, AppInfo::default());
+}
+
+/// Logging and the crash hook, before anything else happens.
+pub fn init() {
+    console::install_logger(log::LevelFilter::Info);
+    log::info!("{}", consts::BUILD);
+
+    // Say what build crashed, ahead of the usual panic message.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        eprintln!("{} crashed.", consts::BUILD);
+        default_hook(info);
+    }));
+}
+
+/// Set the app up as `args` say, as the application `info` names, and run it until it exits.
+pub fn run(mut event_loop: EventLoop<()>, args: &cli::Args, info: AppInfo) {
+    event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+    let mut app = app::WinitApp::new(&mut event_loop).with_app_info(info);
+    let log_dir = app.app_info().log_dir();
+    if let Err(e) = console::log_to_file(&log_dir) {
+        log::warn!("Couldn't write a log file in {}: {e}", log_dir.display());
+    }
+    let config = args
+        .config
+        .clone()
+        .unwrap_or_else(|| app.app_info().config_dir().join("config.cfg"));
+    app.use_config(config);
+    for (name, value) in &args.set {
+        if let Err(e) = app.cvars_mut().set_from_str(name, value) {
+            eprintln!("--set {name}: {e}");
+            std::process::exit(2);
+        }
+    }
+    if let Some(seed) = args.seed {
+        app.set_seed(seed);
+    }
+    if let Some(path) = &args.record {
+        match replay::Recorder::create(path, app.seed()) {
+            Ok(rec) => app.start_recording(rec),
+            Err(e) => {
+                eprintln!("Couldn't record to {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.replay {
+        match replay::Replay::open(path) {
+            Ok(replay) => app.start_replay(replay),
+            Err(e) => {
+                eprintln!("Couldn't load replay {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    if args.editor {
+        app.add_plugin(editor::EditorPlugin::new());
+    }
+    if let Some(scene) = &args.benchmark {
+        app.add_plugin(benchmark::BenchmarkPlugin::new(
+            scene.clone(),
+            args.benchmark_frames,
+            args.benchmark_output.clone(),
+        ));
+    }
+    if let Some(path) = &args.game {
+        #[cfg(feature = "hot-reload")]
+        app.add_plugin(hot_reload::HotReloadPlugin::new(path));
+        #[cfg(not(feature = "hot-reload"))]
+        {
+            eprintln!(
+                "Can't load {}, crowbar was built without the hot-reload feature.",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+    #[cfg(feature = "physics")]
+    app.add_plugin(physics::PhysicsPlugin::new());
+    #[cfg(feature = "scripting")]
+    app.add_plugin(script::ScriptPlugin::new("scripts"));
+    event_loop
+        .run_app(&mut app)
+        .expect("Event loop should return successfully or not return.");
+}
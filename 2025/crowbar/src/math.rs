@@ -0,0 +1,402 @@
+//! Math types and the engine's coordinate conventions. Vectors and matrices are glam's, re-exported
+//! here so everything agrees on the version.
+//!
+//! The conventions, which cameras, culling and the scene graph all go by:
+//! - World and view space are right handed, +Y up, and things look down their -Z axis.
+//! - Clip space has Vulkan's 0..1 depth range, reversed: the near plane is at 1 and the far plane at 0.
+//!   Depth tests want `GREATER`, and depth buffers clear to 0.
+//! - Clip space Y is up. The renderer flips the viewport (negative height) so that holds on Vulkan.
+//! - Viewport coordinates are pixels from the top left.
+
+pub use glam::{Affine3A, Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+pub mod bounds;
+pub mod bvh;
+
+pub const RIGHT: Vec3 = Vec3::X;
+pub const UP: Vec3 = Vec3::Y;
+pub const FORWARD: Vec3 = Vec3::NEG_Z;
+
+/// Clip space depth at the near plane.
+pub const NDC_NEAR: f32 = 1.0;
+/// Clip space depth at the far plane.
+pub const NDC_FAR: f32 = 0.0;
+
+/// Reversed-Z perspective projection. `fov_y` is the vertical field of view in radians.
+pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Mat4 {
+    // Swapping the planes is all reversing takes.
+    Mat4::perspective_rh(fov_y, aspect, far, near)
+}
+
+/// Reversed-Z perspective projection with the far plane at infinity.
+pub fn perspective_infinite(fov_y: f32, aspect: f32, near: f32) -> Mat4 {
+    Mat4::perspective_infinite_reverse_rh(fov_y, aspect, near)
+}
+
+/// Reversed-Z orthographic projection, centered on the view axis. `height` is in world units.
+pub fn orthographic(height: f32, aspect: f32, near: f32, far: f32) -> Mat4 {
+    let (hw, hh) = (height * aspect / 2.0, height / 2.0);
+    Mat4::orthographic_rh(-hw, hw, -hh, hh, far, near)
+}
+
+/// `projection` with its near plane swapped for `clip`, in view space, so nothing behind it is drawn. The far plane
+/// tilts to stay behind the rest of the view, which costs depth precision the further `clip` is from the real near
+/// plane. For planar reflections, where the mirror's plane is the near plane (see Lengyel, "Oblique View Frustum
+/// Depth Projection and Clipping").
+pub fn oblique_near_plane(projection: Mat4, clip: &Plane) -> Mat4 {
+    let clip = clip.normal.extend(clip.d);
+    // The corner of the far plane furthest behind the clip plane, which the new far plane goes through.
+    let clip_space = projection.inverse().transpose() * clip;
+    let corner = Vec4::new(clip_space.x.signum(), clip_space.y.signum(), NDC_FAR, 1.0);
+    let corner = projection.inverse() * corner;
+    // Reversed-Z: the near plane is w - z, so z becomes w minus the clip plane, scaled to put the corner at 0.
+    let mut rows = projection.transpose();
+    rows.z_axis = rows.w_axis - clip * (rows.w_axis.dot(corner) / clip.dot(corner));
+    return rows.transpose();
+}
+
+/// The view matrix for something placed at `world`, like a camera.
+pub fn view_matrix(world: &Affine3A) -> Mat4 {
+    Mat4::from(world.inverse())
+}
+
+/// Viewport pixels to normalized device coordinates.
+pub fn viewport_to_ndc(point: Vec2, viewport: Vec2) -> Vec2 {
+    let uv = point / viewport;
+    return Vec2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+/// Normalized device coordinates to viewport pixels.
+pub fn ndc_to_viewport(ndc: Vec2, viewport: Vec2) -> Vec2 {
+    Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * viewport
+}
+
+/// Local translation/rotation/scale. Applied scale first, then rotation, then translation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Transform {
+        Transform {
+            rotation,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vec3) -> Transform {
+        Transform {
+            scale,
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// Placed at `eye`, looking at `target`.
+    pub fn looking_at(eye: Vec3, target: Vec3, up: Vec3) -> Transform {
+        let mut t = Transform::from_translation(eye);
+        t.look_at(target, up);
+        return t;
+    }
+
+    /// Turn to face `target`, keeping translation and scale.
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        let view = Mat4::look_at_rh(self.translation, target, up);
+        self.rotation = Quat::from_mat4(&view.inverse());
+    }
+
+    /// Decompose an affine transform. Shear doesn't survive.
+    pub fn from_affine(affine: &Affine3A) -> Transform {
+        let (scale, rotation, translation) = affine.to_scale_rotation_translation();
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn to_affine(&self) -> Affine3A {
+        Affine3A::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * FORWARD
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * RIGHT
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * UP
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.translation + self.rotation * (self.scale * point)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+/// The points where `normal.dot(p) + d == 0`. Points on the side the normal faces are in front.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    /// Normalized.
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    pub fn from_point_normal(point: Vec3, normal: Vec3) -> Plane {
+        let normal = normal.normalize();
+        Plane {
+            normal,
+            d: -normal.dot(point),
+        }
+    }
+
+    /// From unnormalized `(a, b, c, d)` coefficients, like the rows of a projection matrix give.
+    pub fn from_coefficients(v: Vec4) -> Plane {
+        let len = v.truncate().length();
+        Plane {
+            normal: v.truncate() / len,
+            d: v.w / len,
+        }
+    }
+
+    /// Signed distance, positive in front.
+    pub fn distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.d
+    }
+
+    /// The plane moved by `transform`.
+    pub fn transformed(&self, transform: &Affine3A) -> Plane {
+        let point = transform.transform_point3(self.normal * -self.d);
+        let normal = transform.matrix3.inverse().transpose() * self.normal;
+        return Plane::from_point_normal(point, normal);
+    }
+
+    /// Mirroring about the plane. It turns right handed into left handed, so triangles drawn through it wind the
+    /// other way round.
+    pub fn reflection(&self) -> Affine3A {
+        let n = self.normal;
+        let linear = Mat3::IDENTITY - 2.0 * Mat3::from_cols(n * n.x, n * n.y, n * n.z);
+        return Affine3A::from_mat3_translation(linear, -2.0 * self.d * n);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Normalized.
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// Distance along the ray to where it enters a sphere, if it hits.
+    pub fn sphere(&self, center: Vec3, radius: f32) -> Option<f32> {
+        let oc = self.origin - center;
+        let b = oc.dot(self.dir);
+        let c = oc.length_squared() - radius * radius;
+        let disc = b * b - c;
+        if disc < 0.0 {
+            return None;
+        }
+
+        let (near, far) = (-b - disc.sqrt(), -b + disc.sqrt());
+        if far < 0.0 {
+            return None;
+        }
+        // Starting inside the sphere counts as hitting it right away.
+        return Some(near.max(0.0));
+    }
+
+    /// Distance along the ray to a plane, if it crosses it going forward.
+    pub fn plane(&self, plane: &Plane) -> Option<f32> {
+        let denom = plane.normal.dot(self.dir);
+        if denom.abs() < 1e-6 {
+            return None;
+        }
+
+        let t = -plane.distance(self.origin) / denom;
+        return (t >= 0.0).then_some(t);
+    }
+
+    /// Distance along the ray to a triangle, from either side, and the barycentric weights of `b` and `c` where it
+    /// hits.
+    pub fn triangle(&self, [a, b, c]: [Vec3; 3]) -> Option<(f32, Vec2)> {
+        // Moller-Trumbore.
+        let (ab, ac) = (b - a, c - a);
+        let p = self.dir.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < 1e-8 {
+            return None;
+        }
+
+        let inv = det.recip();
+        let ao = self.origin - a;
+        let u = ao.dot(p) * inv;
+        let q = ao.cross(ab);
+        let v = self.dir.dot(q) * inv;
+        if u < 0.0 || v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = ac.dot(q) * inv;
+        return (t >= 0.0).then_some((t, Vec2::new(u, v)));
+    }
+}
+
+/// An axis aligned rectangle, for 2D. In viewport pixels it goes from the top left `min` to the bottom right `max`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Rect {
+    /// 0..1 on both axes, the whole of a texture.
+    pub const UNIT: Rect = Rect {
+        min: Vec2::ZERO,
+        max: Vec2::ONE,
+    };
+
+    pub fn new(min: Vec2, max: Vec2) -> Rect {
+        Rect { min, max }
+    }
+
+    pub fn from_pos_size(pos: Vec2, size: Vec2) -> Rect {
+        Rect {
+            min: pos,
+            max: pos + size,
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vec2 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Inclusive of the top left edges, exclusive of the bottom right, so neighbours don't both contain a point.
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmplt(self.max).all()
+    }
+
+    /// Whether the two share any area.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+    }
+
+    /// Grown by `amount` on every side, or shrunk for negative amounts.
+    pub fn expand(&self, amount: f32) -> Rect {
+        Rect {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec2, Vec3};
+
+    use super::{
+        NDC_FAR, NDC_NEAR, Plane, Ray, Rect, Transform, ndc_to_viewport, oblique_near_plane,
+        perspective, perspective_infinite, viewport_to_ndc,
+    };
+
+    #[test]
+    pub fn reversed_z() {
+        let proj = perspective(1.0, 1.5, 0.1, 100.0);
+        let depth = |z: f32| proj.project_point3(Vec3::new(0.0, 0.0, z)).z;
+        assert!((depth(-0.1) - NDC_NEAR).abs() < 1e-5);
+        assert!((depth(-100.0) - NDC_FAR).abs() < 1e-5);
+
+        // Clip space Y is up.
+        assert!(proj.project_point3(Vec3::new(0.0, 1.0, -5.0)).y > 0.0);
+
+        let viewport = Vec2::new(800.0, 600.0);
+        assert_eq!(viewport_to_ndc(Vec2::ZERO, viewport), Vec2::new(-1.0, 1.0));
+        assert_eq!(ndc_to_viewport(Vec2::new(-1.0, 1.0), viewport), Vec2::ZERO);
+
+        // Clipped by a plane tilted across the view, crossing it 4 units out.
+        for proj in [proj, perspective_infinite(1.0, 1.5, 0.1)] {
+            let clip =
+                Plane::from_point_normal(Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 1.0, -1.0));
+            let oblique = oblique_near_plane(proj, &clip);
+            let on = Vec3::new(0.5, -1.0, -5.0);
+            assert!(clip.distance(on).abs() < 1e-5);
+            let point = |p: Vec3| oblique.project_point3(p);
+            assert!((point(on).z - NDC_NEAR).abs() < 1e-4);
+            assert!(
+                point(on)
+                    .truncate()
+                    .abs_diff_eq(proj.project_point3(on).truncate(), 1e-5)
+            );
+            // In front of the plane is in, behind it is past the near plane.
+            assert!((NDC_FAR..NDC_NEAR).contains(&point(Vec3::new(0.0, 0.0, -50.0)).z));
+            assert!(point(Vec3::new(0.0, 0.0, -3.0)).z > NDC_NEAR);
+        }
+    }
+
+    #[test]
+    pub fn transforms_and_planes() {
+        let t = Transform::looking_at(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        assert!(t.forward().abs_diff_eq(Vec3::NEG_Z, 1e-5));
+        let back = Transform::from_affine(&t.to_affine());
+        assert!(back.translation.abs_diff_eq(t.translation, 1e-5));
+
+        let ground = Plane::from_point_normal(Vec3::ZERO, Vec3::Y);
+        let ray = Ray {
+            origin: Vec3::new(1.0, 2.0, 0.0),
+            dir: Vec3::NEG_Y,
+        };
+        assert_eq!(ray.plane(&ground), Some(2.0));
+        let floor = [Vec3::ZERO, Vec3::X * 2.0, Vec3::Z * 2.0];
+        assert_eq!(ray.triangle(floor), Some((2.0, Vec2::new(0.5, 0.0))));
+        let past = Ray {
+            origin: Vec3::new(2.0, 2.0, 2.0),
+            ..ray
+        };
+        assert_eq!(past.triangle(floor), None);
+        assert_eq!(ground.distance(Vec3::Y * 3.0), 3.0);
+        let raised = ground.transformed(&Affine3A::from_translation(Vec3::Y));
+        assert!((raised.distance(Vec3::Y * 3.0) - 2.0).abs() < 1e-5);
+        let mirrored = raised
+            .reflection()
+            .transform_point3(Vec3::new(1.0, 3.0, 2.0));
+        assert!(mirrored.abs_diff_eq(Vec3::new(1.0, -1.0, 2.0), 1e-5));
+
+        let rect = Rect::from_pos_size(Vec2::new(10.0, 20.0), Vec2::new(30.0, 40.0));
+        assert!(rect.contains(Vec2::new(10.0, 20.0)));
+        assert!(!rect.contains(Vec2::new(40.0, 30.0)));
+        assert_eq!(rect.center(), Vec2::new(25.0, 40.0));
+        assert_eq!(rect.expand(-5.0).size(), Vec2::new(20.0, 30.0));
+    }
+}
@@ -0,0 +1,385 @@
+//! Bounding volumes and the intersection tests between them, for culling, picking, broadphase checks
+//! and debug drawing.
+//!
+//! There are no mesh assets yet, so "from a mesh" means from its vertex positions,
+//! see [`Aabb::from_points`] and [`Sphere::from_points`].
+
+use glam::{Affine3A, Mat3A, Mat4, Vec3, Vec3A, Vec4};
+
+use super::{NDC_FAR, NDC_NEAR, Plane, Ray};
+
+/// An axis aligned box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn from_center_half_extents(center: Vec3, half_extents: Vec3) -> Aabb {
+        Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    /// The smallest box around the points. `None` if there aren't any.
+    pub fn from_points(points: &[Vec3]) -> Option<Aabb> {
+        let first = *points.first()?;
+        let (min, max) = points
+            .iter()
+            .fold((first, first), |(min, max), p| (min.min(*p), max.max(*p)));
+        return Some(Aabb { min, max });
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let closest = sphere.center.clamp(self.min, self.max);
+        closest.distance_squared(sphere.center) <= sphere.radius * sphere.radius
+    }
+
+    /// The box around this one after a transform. Grows under rotation, as boxes do.
+    pub fn transformed(&self, transform: &Affine3A) -> Aabb {
+        // Arvo's trick: the world half extents are the local ones through the absolute matrix.
+        let m = transform.matrix3;
+        let abs = Mat3A::from_cols(m.x_axis.abs(), m.y_axis.abs(), m.z_axis.abs());
+        let center = transform.transform_point3(self.center());
+        let half = abs * Vec3A::from(self.half_extents());
+        return Aabb::from_center_half_extents(center, half.into());
+    }
+
+    /// The eight corners, in the same order as [`DebugDraw::cuboid`](crate::debug_draw::DebugDraw::cuboid)'s.
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            Vec3::new(
+                if i & 1 != 0 { self.max.x } else { self.min.x },
+                if i & 2 != 0 { self.max.y } else { self.min.y },
+                if i & 4 != 0 { self.max.z } else { self.min.z },
+            )
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    /// A sphere around the points, centered on their bounding box. Not the tightest, but close enough
+    /// for culling. `None` if there aren't any.
+    pub fn from_points(points: &[Vec3]) -> Option<Sphere> {
+        let center = Aabb::from_points(points)?.center();
+        let radius_sq = points
+            .iter()
+            .map(|p| p.distance_squared(center))
+            .fold(0.0, f32::max);
+        return Some(Sphere::new(center, radius_sq.sqrt()));
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        point.distance_squared(self.center) <= self.radius * self.radius
+    }
+
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let r = self.radius + other.radius;
+        self.center.distance_squared(other.center) <= r * r
+    }
+
+    /// The sphere around this one after a transform. Non-uniform scale takes the largest axis.
+    pub fn transformed(&self, transform: &Affine3A) -> Sphere {
+        let m = transform.matrix3;
+        let scale = m
+            .x_axis
+            .length_squared()
+            .max(m.y_axis.length_squared())
+            .max(m.z_axis.length_squared())
+            .sqrt();
+        Sphere::new(transform.transform_point3(self.center), self.radius * scale)
+    }
+}
+
+/// An oriented box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb {
+    pub center: Vec3,
+    /// Normalized local axes.
+    pub axes: [Vec3; 3],
+    pub half_extents: Vec3,
+}
+
+impl Obb {
+    /// A local box put through a transform. Shear doesn't survive.
+    pub fn from_aabb(aabb: &Aabb, transform: &Affine3A) -> Obb {
+        let (scale, rotation, _) = transform.to_scale_rotation_translation();
+        Obb {
+            center: transform.transform_point3(aabb.center()),
+            axes: [rotation * Vec3::X, rotation * Vec3::Y, rotation * Vec3::Z],
+            half_extents: aabb.half_extents() * scale.abs(),
+        }
+    }
+
+    /// The transform that takes a unit cube (-1..1) to this box, for drawing it.
+    pub fn to_affine(&self) -> Affine3A {
+        Affine3A::from_cols(
+            (self.axes[0] * self.half_extents.x).into(),
+            (self.axes[1] * self.half_extents.y).into(),
+            (self.axes[2] * self.half_extents.z).into(),
+            self.center.into(),
+        )
+    }
+
+    fn local_point(&self, point: Vec3) -> Vec3 {
+        let d = point - self.center;
+        Vec3::new(
+            d.dot(self.axes[0]),
+            d.dot(self.axes[1]),
+            d.dot(self.axes[2]),
+        )
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.local_point(point).abs().cmple(self.half_extents).all()
+    }
+
+    pub fn to_aabb(&self) -> Aabb {
+        let half = self.axes[0].abs() * self.half_extents.x
+            + self.axes[1].abs() * self.half_extents.y
+            + self.axes[2].abs() * self.half_extents.z;
+        return Aabb::from_center_half_extents(self.center, half);
+    }
+
+    /// Half the box's extent along `axis`.
+    fn project(&self, axis: Vec3) -> f32 {
+        (0..3)
+            .map(|i| self.half_extents[i] * self.axes[i].dot(axis).abs())
+            .sum()
+    }
+
+    /// Separating axis test, over the 15 candidate axes.
+    pub fn intersects(&self, other: &Obb) -> bool {
+        let d = other.center - self.center;
+        let separated = |axis: Vec3| {
+            // Parallel edges give a zero cross product, which separates nothing.
+            if axis.length_squared() < 1e-10 {
+                return false;
+            }
+            d.dot(axis).abs() > self.project(axis) + other.project(axis)
+        };
+
+        for i in 0..3 {
+            if separated(self.axes[i]) || separated(other.axes[i]) {
+                return false;
+            }
+        }
+        for a in self.axes {
+            for b in other.axes {
+                if separated(a.cross(b)) {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+}
+
+/// The volume a camera sees, as six inward facing planes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extract the planes from a view projection matrix, following the clip space conventions in
+    /// [`math`](super). An infinite far plane comes out as one everything is in front of.
+    pub fn from_view_proj(view_proj: &Mat4) -> Frustum {
+        let m = view_proj.transpose();
+        let (x, y, z, w) = (m.x_axis, m.y_axis, m.z_axis, m.w_axis);
+
+        // Reversed-Z: near is where z == w, far is where z == 0.
+        const { assert!(NDC_NEAR == 1.0 && NDC_FAR == 0.0) };
+        let plane = |v: Vec4| {
+            if v.truncate().length_squared() < 1e-12 {
+                return Plane {
+                    normal: Vec3::Z,
+                    d: f32::INFINITY,
+                };
+            }
+            Plane::from_coefficients(v)
+        };
+
+        Frustum {
+            planes: [
+                plane(w + x),
+                plane(w - x),
+                plane(w + y),
+                plane(w - y),
+                plane(w - z),
+                plane(z),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes.iter().all(|p| p.distance(point) >= 0.0)
+    }
+
+    /// Conservative, boxes near the corners can pass while being outside.
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Conservative in the same way as [`Frustum::intersects_sphere`].
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let (center, half) = (aabb.center(), aabb.half_extents());
+        self.planes.iter().all(|p| {
+            let reach = half.dot(p.normal.abs());
+            p.distance(center) >= -reach
+        })
+    }
+
+    /// The eight corners of the frustum of `view_proj`, in [`Aabb::corners`] order.
+    /// Meaningless for an infinite far plane.
+    pub fn corners(view_proj: &Mat4) -> [Vec3; 8] {
+        let inv = view_proj.inverse();
+        std::array::from_fn(|i| {
+            let ndc = Vec3::new(
+                if i & 1 != 0 { 1.0 } else { -1.0 },
+                if i & 2 != 0 { 1.0 } else { -1.0 },
+                if i & 4 != 0 { NDC_FAR } else { NDC_NEAR },
+            );
+            inv.project_point3(ndc)
+        })
+    }
+}
+
+impl Ray {
+    /// Distance along the ray to where it enters a box, if it hits. Starting inside counts as 0.
+    pub fn aabb(&self, aabb: &Aabb) -> Option<f32> {
+        // Slabs. Division by zero gives infinities, which sort out the parallel cases.
+        let inv = self.dir.recip();
+        let t0 = (aabb.min - self.origin) * inv;
+        let t1 = (aabb.max - self.origin) * inv;
+
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element();
+        return (near <= far).then_some(near);
+    }
+
+    /// Distance along the ray to where it enters an oriented box, if it hits.
+    pub fn obb(&self, obb: &Obb) -> Option<f32> {
+        let local = Ray {
+            origin: obb.local_point(self.origin),
+            dir: Vec3::new(
+                self.dir.dot(obb.axes[0]),
+                self.dir.dot(obb.axes[1]),
+                self.dir.dot(obb.axes[2]),
+            ),
+        };
+        return local.aabb(&Aabb::from_center_half_extents(
+            Vec3::ZERO,
+            obb.half_extents,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Quat, Vec3};
+
+    use super::{Aabb, Frustum, Obb, Sphere};
+    use crate::math::{Ray, perspective};
+
+    #[test]
+    pub fn boxes_and_spheres() {
+        let unit = Aabb::from_center_half_extents(Vec3::ZERO, Vec3::ONE);
+        assert_eq!(
+            Aabb::from_points(&[Vec3::NEG_ONE, Vec3::ONE, Vec3::ZERO]),
+            Some(unit)
+        );
+
+        let turned = Affine3A::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        let grown = unit.transformed(&turned);
+        assert!((grown.max.x - 2f32.sqrt()).abs() < 1e-5);
+
+        let sphere = Sphere::from_points(&unit.corners()).unwrap();
+        assert!((sphere.radius - 3f32.sqrt()).abs() < 1e-5);
+        assert!(unit.intersects_sphere(&Sphere::new(Vec3::new(1.5, 0.0, 0.0), 0.6)));
+        assert!(!unit.intersects_sphere(&Sphere::new(Vec3::new(1.5, 1.5, 0.0), 0.6)));
+
+        // Two boxes whose AABBs overlap but who don't touch.
+        let a = Obb::from_aabb(&unit, &turned);
+        let b = Obb::from_aabb(&unit, &Affine3A::from_translation(Vec3::new(2.2, 0.0, 2.2)));
+        assert!(a.to_aabb().intersects(&b.to_aabb()));
+        assert!(!a.intersects(&b));
+        assert!(a.intersects(&Obb::from_aabb(&unit, &Affine3A::from_translation(Vec3::X))));
+    }
+
+    #[test]
+    pub fn rays_and_frustums() {
+        let unit = Aabb::from_center_half_extents(Vec3::ZERO, Vec3::ONE);
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 5.0),
+            dir: Vec3::NEG_Z,
+        };
+        assert_eq!(ray.aabb(&unit), Some(4.0));
+        assert_eq!(
+            Ray {
+                dir: Vec3::Z,
+                ..ray
+            }
+            .aabb(&unit),
+            None
+        );
+
+        let obb = Obb::from_aabb(&unit, &Affine3A::from_quat(Quat::from_rotation_z(1.0)));
+        assert!((ray.obb(&obb).unwrap() - 4.0).abs() < 1e-5);
+
+        let frustum = Frustum::from_view_proj(&perspective(1.0, 1.0, 0.1, 100.0));
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -10.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -200.0)));
+        assert!(frustum.intersects_aabb(&Aabb::from_center_half_extents(
+            Vec3::new(0.0, 0.0, -101.0),
+            Vec3::splat(2.0)
+        )));
+        assert!(!frustum.intersects_sphere(&Sphere::new(Vec3::new(50.0, 0.0, -10.0), 1.0)));
+
+        let infinite = Frustum::from_view_proj(&crate::math::perspective_infinite(1.0, 1.0, 0.1));
+        assert!(infinite.contains_point(Vec3::new(0.0, 0.0, -1e6)));
+    }
+}
@@ -0,0 +1,489 @@
+//! A dynamic bounding volume hierarchy, for finding things in space without looking at everything.
+//!
+//! Leaves hold a fattened copy of their bounds, so things that move a little don't touch the tree at
+//! all, and things that move further get pulled out and reinserted, refitting the nodes above them.
+//! Insertion picks siblings by surface area and rotations keep the tree balanced, the same way
+//! Box2D's dynamic tree does it.
+
+use super::{
+    Ray,
+    bounds::{Aabb, Frustum, Sphere},
+};
+
+const NULL: u32 = u32::MAX;
+
+/// A handle to something in a [`Bvh`], valid until it's removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProxyId(u32);
+
+#[derive(Clone, Debug)]
+struct Node<T> {
+    aabb: Aabb,
+    parent: u32,
+    /// Both `NULL` for leaves.
+    children: [u32; 2],
+    /// Leaves are 0, free nodes -1.
+    height: i32,
+    item: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL
+    }
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    let d = aabb.max - aabb.min;
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+#[derive(Clone, Debug)]
+pub struct Bvh<T: Copy> {
+    nodes: Vec<Node<T>>,
+    root: u32,
+    free: Vec<u32>,
+    /// How far leaf bounds are grown past the real bounds.
+    margin: f32,
+    len: usize,
+}
+
+impl<T: Copy> Default for Bvh<T> {
+    fn default() -> Self {
+        Bvh::new(0.1)
+    }
+}
+
+impl<T: Copy> Bvh<T> {
+    /// `margin` is how far things can move before the tree has to be updated, in world units.
+    pub fn new(margin: f32) -> Bvh<T> {
+        Bvh {
+            nodes: Vec::new(),
+            root: NULL,
+            free: Vec::new(),
+            margin,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Levels from the root down to the deepest leaf.
+    pub fn height(&self) -> i32 {
+        match self.root {
+            NULL => 0,
+            root => self.nodes[root as usize].height,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = NULL;
+        self.len = 0;
+    }
+
+    pub fn get(&self, id: ProxyId) -> T {
+        self.nodes[id.0 as usize]
+            .item
+            .expect("Bvh proxy used after removal!")
+    }
+
+    /// The fattened bounds stored for `id`.
+    pub fn fat_aabb(&self, id: ProxyId) -> Aabb {
+        self.nodes[id.0 as usize].aabb
+    }
+
+    fn fatten(&self, aabb: &Aabb) -> Aabb {
+        Aabb::new(aabb.min - self.margin, aabb.max + self.margin)
+    }
+
+    fn alloc(&mut self, aabb: Aabb, item: Option<T>) -> u32 {
+        let node = Node {
+            aabb,
+            parent: NULL,
+            children: [NULL; 2],
+            height: 0,
+            item,
+        };
+
+        if let Some(i) = self.free.pop() {
+            self.nodes[i as usize] = node;
+            return i;
+        }
+        self.nodes.push(node);
+        return self.nodes.len() as u32 - 1;
+    }
+
+    fn release(&mut self, i: u32) {
+        let node = &mut self.nodes[i as usize];
+        node.height = -1;
+        node.item = None;
+        self.free.push(i);
+    }
+
+    pub fn insert(&mut self, aabb: &Aabb, item: T) -> ProxyId {
+        let leaf = self.alloc(self.fatten(aabb), Some(item));
+        self.insert_leaf(leaf);
+        self.len += 1;
+        return ProxyId(leaf);
+    }
+
+    pub fn remove(&mut self, id: ProxyId) -> T {
+        let item = self.get(id);
+        self.remove_leaf(id.0);
+        self.release(id.0);
+        self.len -= 1;
+        return item;
+    }
+
+    /// Tell the tree `id` has moved. Returns whether the tree had to change.
+    pub fn update(&mut self, id: ProxyId, aabb: &Aabb) -> bool {
+        let fat = &self.nodes[id.0 as usize].aabb;
+        if fat.contains_point(aabb.min) && fat.contains_point(aabb.max) {
+            return false;
+        }
+
+        self.remove_leaf(id.0);
+        self.nodes[id.0 as usize].aabb = self.fatten(aabb);
+        self.insert_leaf(id.0);
+        return true;
+    }
+
+    fn insert_leaf(&mut self, leaf: u32) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf as usize].parent = NULL;
+            return;
+        }
+
+        // Walk down to the sibling that costs the least surface area to pair up with.
+        let leaf_aabb = self.nodes[leaf as usize].aabb;
+        let mut index = self.root;
+        while !self.nodes[index as usize].is_leaf() {
+            let node = &self.nodes[index as usize];
+            let area = surface_area(&node.aabb);
+            let combined = surface_area(&node.aabb.union(&leaf_aabb));
+
+            // Making a new parent here, versus pushing the leaf further down.
+            let cost = 2.0 * combined;
+            let inheritance = 2.0 * (combined - area);
+
+            let child_cost = |c: u32| {
+                let child = &self.nodes[c as usize];
+                let grown = surface_area(&child.aabb.union(&leaf_aabb));
+                if child.is_leaf() {
+                    return grown + inheritance;
+                }
+                return grown - surface_area(&child.aabb) + inheritance;
+            };
+            let [a, b] = node.children;
+            let (cost_a, cost_b) = (child_cost(a), child_cost(b));
+
+            if cost < cost_a && cost < cost_b {
+                break;
+            }
+            index = if cost_a < cost_b { a } else { b };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling as usize].parent;
+        let new_parent = self.alloc(self.nodes[sibling as usize].aabb.union(&leaf_aabb), None);
+        {
+            let p = &mut self.nodes[new_parent as usize];
+            p.parent = old_parent;
+            p.children = [sibling, leaf];
+        }
+        self.nodes[new_parent as usize].height = self.nodes[sibling as usize].height + 1;
+        self.nodes[sibling as usize].parent = new_parent;
+        self.nodes[leaf as usize].parent = new_parent;
+
+        if old_parent == NULL {
+            self.root = new_parent;
+        } else {
+            let p = &mut self.nodes[old_parent as usize];
+            let slot = if p.children[0] == sibling { 0 } else { 1 };
+            p.children[slot] = new_parent;
+        }
+
+        self.refit_from(self.nodes[leaf as usize].parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: u32) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        }
+
+        let parent = self.nodes[leaf as usize].parent;
+        let grandparent = self.nodes[parent as usize].parent;
+        let [a, b] = self.nodes[parent as usize].children;
+        let sibling = if a == leaf { b } else { a };
+
+        if grandparent == NULL {
+            self.root = sibling;
+            self.nodes[sibling as usize].parent = NULL;
+        } else {
+            let g = &mut self.nodes[grandparent as usize];
+            let slot = if g.children[0] == parent { 0 } else { 1 };
+            g.children[slot] = sibling;
+            self.nodes[sibling as usize].parent = grandparent;
+            self.refit_from(grandparent);
+        }
+        self.release(parent);
+    }
+
+    /// Fix up bounds and heights from `index` to the root, rebalancing on the way.
+    fn refit_from(&mut self, mut index: u32) {
+        while index != NULL {
+            index = self.balance(index);
+
+            let [a, b] = self.nodes[index as usize].children;
+            let (na, nb) = (&self.nodes[a as usize], &self.nodes[b as usize]);
+            let aabb = na.aabb.union(&nb.aabb);
+            let height = 1 + na.height.max(nb.height);
+
+            let node = &mut self.nodes[index as usize];
+            node.aabb = aabb;
+            node.height = height;
+            index = node.parent;
+        }
+    }
+
+    /// If one child of `a` is more than a level taller than the other, rotate the taller one up.
+    /// Returns the node now where `a` was.
+    fn balance(&mut self, a: u32) -> u32 {
+        let node = &self.nodes[a as usize];
+        if node.is_leaf() || node.height < 2 {
+            return a;
+        }
+
+        let [b, c] = node.children;
+        let balance = self.nodes[c as usize].height - self.nodes[b as usize].height;
+        if balance > 1 {
+            return self.rotate_up(a, c, b);
+        }
+        if balance < -1 {
+            return self.rotate_up(a, b, c);
+        }
+        return a;
+    }
+
+    /// Swap `a` with its tall child `up`. `up` takes `a`'s place, `a` takes one of `up`'s children
+    /// alongside `other`, and `up` keeps the taller of its children.
+    fn rotate_up(&mut self, a: u32, up: u32, other: u32) -> u32 {
+        let [f, g] = self.nodes[up as usize].children;
+
+        // `up` replaces `a` under `a`'s parent.
+        let a_parent = self.nodes[a as usize].parent;
+        self.nodes[up as usize].parent = a_parent;
+        self.nodes[a as usize].parent = up;
+        if a_parent == NULL {
+            self.root = up;
+        } else {
+            let p = &mut self.nodes[a_parent as usize];
+            let slot = if p.children[0] == a { 0 } else { 1 };
+            p.children[slot] = up;
+        }
+
+        // The taller grandchild stays with `up`, the shorter one moves under `a`.
+        let (keep, give) = if self.nodes[f as usize].height > self.nodes[g as usize].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+        self.nodes[up as usize].children = [a, keep];
+        self.nodes[a as usize].children = [other, give];
+        self.nodes[give as usize].parent = a;
+
+        let refit = |nodes: &mut Vec<Node<T>>, i: u32| {
+            let [x, y] = nodes[i as usize].children;
+            let aabb = nodes[x as usize].aabb.union(&nodes[y as usize].aabb);
+            let height = 1 + nodes[x as usize].height.max(nodes[y as usize].height);
+            nodes[i as usize].aabb = aabb;
+            nodes[i as usize].height = height;
+        };
+        refit(&mut self.nodes, a);
+        refit(&mut self.nodes, up);
+
+        return up;
+    }
+
+    /// Visit every item whose fat bounds pass `test`, pruning subtrees that fail it.
+    pub fn query(&self, mut test: impl FnMut(&Aabb) -> bool, mut f: impl FnMut(ProxyId, T)) {
+        if self.root == NULL {
+            return;
+        }
+
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i as usize];
+            if !test(&node.aabb) {
+                continue;
+            }
+
+            match node.item {
+                Some(item) => f(ProxyId(i), item),
+                None => stack.extend(node.children),
+            }
+        }
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb, f: impl FnMut(ProxyId, T)) {
+        self.query(|b| b.intersects(aabb), f);
+    }
+
+    /// Everything within (or at least near) a sphere, for neighborhood queries.
+    pub fn query_sphere(&self, sphere: &Sphere, f: impl FnMut(ProxyId, T)) {
+        self.query(|b| b.intersects_sphere(sphere), f);
+    }
+
+    pub fn query_frustum(&self, frustum: &Frustum, f: impl FnMut(ProxyId, T)) {
+        self.query(|b| frustum.intersects_aabb(b), f);
+    }
+
+    /// The nearest item along the ray within `max_t`. `hit` gives the exact distance to an item, or
+    /// `None` if the ray misses it, since the tree only knows rough bounds.
+    pub fn ray_cast(
+        &self,
+        ray: &Ray,
+        max_t: f32,
+        mut hit: impl FnMut(T) -> Option<f32>,
+    ) -> Option<(T, f32)> {
+        if self.root == NULL {
+            return None;
+        }
+
+        let mut best: Option<(T, f32)> = None;
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i as usize];
+            let limit = best.map_or(max_t, |(_, t)| t);
+            if ray.aabb(&node.aabb).is_none_or(|t| t > limit) {
+                continue;
+            }
+
+            match node.item {
+                Some(item) => {
+                    if let Some(t) = hit(item)
+                        && t <= limit
+                    {
+                        best = Some((item, t));
+                    }
+                }
+                None => stack.extend(node.children),
+            }
+        }
+        return best;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::Bvh;
+    use crate::{
+        math::{
+            Ray,
+            bounds::{Aabb, Sphere},
+        },
+        rng::Rng,
+    };
+
+    /// Check parent links, bounds and heights all hold together.
+    fn validate(bvh: &Bvh<usize>, i: u32) -> i32 {
+        let node = &bvh.nodes[i as usize];
+        if node.is_leaf() {
+            assert_eq!(node.height, 0);
+            return 0;
+        }
+
+        let mut height = 0;
+        for c in node.children {
+            let child = &bvh.nodes[c as usize];
+            assert_eq!(child.parent, i);
+            assert_eq!(node.aabb.union(&child.aabb), node.aabb);
+            height = height.max(validate(bvh, c) + 1);
+        }
+        assert_eq!(node.height, height);
+        return height;
+    }
+
+    #[test]
+    pub fn matches_brute_force() {
+        let mut rng = Rng::new(3);
+        let mut bvh = Bvh::new(0.2);
+        let mut boxes = Vec::new();
+        let mut proxies = Vec::new();
+
+        let random_box = |rng: &mut Rng| {
+            let center = Vec3::new(
+                rng.range(-50.0, 50.0),
+                rng.range(-50.0, 50.0),
+                rng.range(-50.0, 50.0),
+            );
+            Aabb::from_center_half_extents(center, Vec3::splat(rng.range(0.1, 2.0)))
+        };
+
+        for i in 0..500 {
+            let aabb = random_box(&mut rng);
+            boxes.push(Some(aabb));
+            proxies.push(Some(bvh.insert(&aabb, i)));
+        }
+        for _ in 0..1000 {
+            let i = rng.below(500) as usize;
+            match (proxies[i], rng.chance(0.1)) {
+                (Some(p), true) => {
+                    assert_eq!(bvh.remove(p), i);
+                    boxes[i] = None;
+                    proxies[i] = None;
+                }
+                (Some(p), false) => {
+                    let aabb = random_box(&mut rng);
+                    bvh.update(p, &aabb);
+                    boxes[i] = Some(aabb);
+                }
+                (None, _) => {
+                    let aabb = random_box(&mut rng);
+                    boxes[i] = Some(aabb);
+                    proxies[i] = Some(bvh.insert(&aabb, i));
+                }
+            }
+        }
+
+        validate(&bvh, bvh.root);
+        assert_eq!(bvh.len(), boxes.iter().flatten().count());
+        // Balanced, roughly. A list would be hundreds deep.
+        assert!(bvh.height() < 20, "height {}", bvh.height());
+
+        let sphere = Sphere::new(Vec3::ZERO, 20.0);
+        let mut found = Vec::new();
+        bvh.query_sphere(&sphere, |_, i| found.push(i));
+        for (i, b) in boxes.iter().enumerate() {
+            if let Some(b) = b
+                && b.intersects_sphere(&sphere)
+            {
+                assert!(found.contains(&i));
+            }
+        }
+
+        let ray = Ray {
+            origin: Vec3::new(-100.0, 0.0, 0.0),
+            dir: Vec3::X,
+        };
+        let exact = |i: usize| ray.aabb(&boxes[i].unwrap());
+        let nearest = boxes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| Some((i, ray.aabb(b.as_ref()?)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(bvh.ray_cast(&ray, f32::INFINITY, exact), nearest);
+    }
+}
@@ -0,0 +1,281 @@
+//! The egui debug overlay. Subsystems register panels, and the overlay toggles with F3.
+//! It also hosts the [`Console`], which has its own toggle.
+//!
+//! The overlay only produces tessellated egui output, it's on the renderer to paint it, with
+//! [`OverlayPass`](crate::render::overlay::OverlayPass).
+
+use std::sync::Arc;
+
+use winit::{
+    event::{ElementState, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Window, WindowId},
+};
+
+use crate::{app::WinitApp, console::Console};
+
+pub mod about;
+pub mod analysis;
+pub mod budget;
+pub mod gpu_memory;
+pub mod present;
+pub mod profiler;
+pub mod render_graph;
+pub mod shader_errors;
+
+pub const TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+/// A window in the debug overlay.
+pub trait OverlayPanel: 'static {
+    fn name(&self) -> &'static str;
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp);
+}
+
+/// Everything the renderer needs to paint a frame of the overlay.
+pub struct OverlayOutput {
+    pub primitives: Vec<egui::ClippedPrimitive>,
+    pub textures_delta: egui::TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+/// A full screen cover under the overlay's panels, for fades and loading screens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Curtain {
+    /// 0 is see through, 1 is black.
+    pub alpha: f32,
+    /// Shows a progress bar, for loading screens.
+    pub progress: Option<f32>,
+}
+
+impl Curtain {
+    pub fn fade(alpha: f32) -> Curtain {
+        Curtain {
+            alpha,
+            progress: None,
+        }
+    }
+
+    pub fn loading(progress: f32) -> Curtain {
+        Curtain {
+            alpha: 1.0,
+            progress: Some(progress),
+        }
+    }
+
+    fn ui(&self, ctx: &egui::Context) {
+        let alpha = (self.alpha.clamp(0.0, 1.0) * 255.0) as u8;
+        ctx.layer_painter(egui::LayerId::background()).rect_filled(
+            ctx.viewport_rect(),
+            0.0,
+            egui::Color32::from_black_alpha(alpha),
+        );
+
+        if let Some(progress) = self.progress {
+            egui::Area::new(egui::Id::new("crowbar_curtain_progress"))
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .interactable(false)
+                .show(ctx, |ui| {
+                    ui.label("Loading...");
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .desired_width(300.0)
+                            .show_percentage(),
+                    );
+                });
+        }
+    }
+}
+
+struct PanelEntry {
+    panel: Box<dyn OverlayPanel>,
+    open: bool,
+}
+
+#[derive(Default)]
+pub struct DebugOverlay {
+    ctx: egui::Context,
+    /// Input state, tied to whichever window the overlay is shown on.
+    state: Option<(WindowId, egui_winit::State)>,
+    panels: Vec<PanelEntry>,
+    output: Option<OverlayOutput>,
+    console: Console,
+    pub visible: bool,
+    /// Drawn whenever it's set, even with the overlay hidden.
+    pub curtain: Option<Curtain>,
+}
+
+impl DebugOverlay {
+    pub fn add_panel(&mut self, panel: impl OverlayPanel) {
+        self.panels.push(PanelEntry {
+            panel: Box::new(panel),
+            open: false,
+        });
+    }
+
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    pub fn console_mut(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    pub fn context(&self) -> &egui::Context {
+        &self.ctx
+    }
+
+    fn state_for(&mut self, window: &Window) -> &mut egui_winit::State {
+        if self.state.as_ref().is_none_or(|(id, _)| *id != window.id()) {
+            let state = egui_winit::State::new(
+                self.ctx.clone(),
+                egui::ViewportId::ROOT,
+                window,
+                Some(window.scale_factor() as f32),
+                window.theme(),
+                None,
+            );
+            self.state = Some((window.id(), state));
+        }
+
+        return &mut self.state.as_mut().unwrap().1;
+    }
+
+    /// Feed a window event in. Returns true if egui wants it for itself.
+    pub fn handle_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput { event: key, .. } = event
+            && key.state == ElementState::Pressed
+            && !key.repeat
+        {
+            if key.physical_key == PhysicalKey::Code(TOGGLE_KEY) {
+                self.visible = !self.visible;
+                return true;
+            }
+            if key.physical_key == PhysicalKey::Code(crate::console::TOGGLE_KEY) {
+                self.console.toggle();
+                return true;
+            }
+        }
+
+        if !self.visible && !self.console.open {
+            return false;
+        }
+
+        return self
+            .state_for(window)
+            .on_window_event(window, event)
+            .consumed;
+    }
+
+    /// Build this frame's overlay. Does nothing while hidden, unless there's a curtain up.
+    pub fn run(&mut self, app: &mut WinitApp, window: &Arc<Window>) {
+        if !self.visible && !self.console.open && self.curtain.is_none() {
+            return;
+        }
+
+        let raw = self.state_for(window).take_egui_input(window);
+        let ctx = self.ctx.clone();
+        let visible = self.visible;
+        let panels = &mut self.panels;
+        let console = &mut self.console;
+        let curtain = self.curtain;
+
+        let full = ctx.run(raw, |ctx| {
+            if let Some(curtain) = curtain {
+                curtain.ui(ctx);
+            }
+            console.ui(ctx);
+            if !visible {
+                return;
+            }
+
+            egui::TopBottomPanel::top("crowbar_overlay_bar").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for entry in panels.iter_mut() {
+                        ui.toggle_value(&mut entry.open, entry.panel.name());
+                    }
+                });
+            });
+
+            for entry in panels.iter_mut() {
+                let panel = &mut entry.panel;
+                egui::Window::new(panel.name())
+                    .open(&mut entry.open)
+                    .show(ctx, |ui| panel.ui(ui, app));
+            }
+        });
+
+        self.state_for(window)
+            .handle_platform_output(window, full.platform_output);
+        self.keep(full.shapes, full.textures_delta, full.pixels_per_point);
+    }
+
+    /// Tessellate a frame's `shapes` for [`DebugOverlay::take_output`], with its `textures_delta` after whatever
+    /// the renderer hasn't taken yet.
+    fn keep(
+        &mut self,
+        shapes: Vec<egui::epaint::ClippedShape>,
+        textures_delta: egui::TexturesDelta,
+        pixels_per_point: f32,
+    ) {
+        let primitives = self.ctx.tessellate(shapes, pixels_per_point);
+        // Texture updates are incremental, so keep any the renderer hasn't picked up yet.
+        let mut pending = self
+            .output
+            .take()
+            .map(|o| o.textures_delta)
+            .unwrap_or_default();
+        pending.append(textures_delta);
+
+        self.output = Some(OverlayOutput {
+            primitives,
+            textures_delta: pending,
+            pixels_per_point,
+        });
+    }
+
+    /// Take the latest overlay output for painting. Its texture changes pile up until it's taken.
+    pub fn take_output(&mut self) -> Option<OverlayOutput> {
+        self.output.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Curtain, DebugOverlay};
+
+    /// A frame of `overlay` with a loading screen up, as [`DebugOverlay::run`] builds it.
+    fn frame(overlay: &mut DebugOverlay) {
+        let ctx = overlay.ctx.clone();
+        let full = ctx.run(egui::RawInput::default(), |ctx| {
+            Curtain::loading(0.5).ui(ctx)
+        });
+        overlay.keep(full.shapes, full.textures_delta, full.pixels_per_point);
+    }
+
+    #[test]
+    pub fn taking_output_clears_texture_changes() {
+        let mut overlay = DebugOverlay::default();
+        frame(&mut overlay);
+        // The font's made the first frame.
+        let first = overlay.take_output().unwrap();
+        assert!(!first.primitives.is_empty());
+        assert!(!first.textures_delta.set.is_empty());
+        assert!(overlay.take_output().is_none());
+
+        // Taken, so only what's changed since is sent, patching the font with what's new on screen.
+        frame(&mut overlay);
+        let second = overlay.take_output().unwrap().textures_delta;
+        assert!(second.set.iter().all(|(_, delta)| delta.pos.is_some()));
+        frame(&mut overlay);
+        assert!(overlay.take_output().unwrap().textures_delta.is_empty());
+
+        // Not taken, so it's kept for the next.
+        let mut fresh = DebugOverlay::default();
+        frame(&mut fresh);
+        frame(&mut fresh);
+        let kept = fresh.take_output().unwrap().textures_delta;
+        let sets = first.textures_delta.set.len() + second.set.len();
+        assert_eq!(kept.set.len(), sets);
+    }
+}
@@ -0,0 +1,33 @@
+//! What build this is, for bug reports.
+
+use crate::{app::WinitApp, consts::BUILD};
+
+use super::OverlayPanel;
+
+#[derive(Default)]
+pub struct AboutPanel;
+
+impl OverlayPanel for AboutPanel {
+    fn name(&self) -> &'static str {
+        "About"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _app: &mut WinitApp) {
+        egui::Grid::new("crowbar_about").show(ui, |ui| {
+            for (label, value) in [
+                ("Version", BUILD.version),
+                ("Commit", BUILD.git_hash),
+                ("Built", BUILD.build_date),
+                ("Profile", BUILD.profile),
+            ] {
+                ui.label(label);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+
+        if ui.button("Copy").clicked() {
+            ui.ctx().copy_text(BUILD.to_string());
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! What the GPU made of the frames presented to the main window: their luminance histogram and average colour,
+//! while `r_analysis` is on.
+
+use crate::{app::WinitApp, render::analysis::FrameAnalysis};
+
+use super::OverlayPanel;
+
+/// Bins as bars, scaled to the fullest one.
+fn histogram(ui: &mut egui::Ui, analysis: &FrameAnalysis) {
+    let top = analysis.histogram.iter().copied().max().unwrap_or(0).max(1);
+    let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(ui.available_width(), 80.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(96));
+
+    let step = rect.width() / analysis.histogram.len() as f32;
+    for (i, &count) in analysis.histogram.iter().enumerate() {
+        let height = count as f32 / top as f32 * rect.height();
+        let x = rect.left() + i as f32 * step;
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(x, rect.bottom() - height),
+                egui::pos2(x + step.max(1.0), rect.bottom()),
+            ),
+            0.0,
+            egui::Color32::LIGHT_GRAY,
+        );
+    }
+    ui.weak(format!(
+        "Luminance from 2^{} to 2^{}, bin 0 is darker",
+        analysis.range.min_log2, analysis.range.max_log2
+    ));
+}
+
+#[derive(Default)]
+pub struct AnalysisPanel;
+
+impl OverlayPanel for AnalysisPanel {
+    fn name(&self) -> &'static str {
+        "Frame analysis"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let cvar = app.engine_cvars().r_analysis;
+        let mut enabled = app.cvars().get(cvar);
+        if ui
+            .checkbox(&mut enabled, "Analyse frames (r_analysis)")
+            .changed()
+        {
+            app.cvars_mut().set(cvar, enabled);
+        }
+        if !enabled {
+            return;
+        }
+        let Some(analysis) = app.renderer().and_then(|r| r.frame_analysis()) else {
+            ui.weak("Nothing analysed yet.");
+            return;
+        };
+
+        ui.label(format!(
+            "Frame {}, {} pixels",
+            analysis.frame, analysis.pixels
+        ));
+        let average = analysis.average;
+        ui.horizontal(|ui| {
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+            let color = egui::Rgba::from_rgb(average.r, average.g, average.b);
+            ui.painter().rect_filled(rect, 2.0, color);
+            ui.monospace(format!(
+                "Average {:.3} {:.3} {:.3}",
+                average.r, average.g, average.b
+            ));
+        });
+        ui.monospace(format!(
+            "Median luminance {:.3}, metered {:.3}",
+            analysis.percentile(0.5),
+            analysis.average_luminance(0.5, 0.95)
+        ));
+        histogram(ui, analysis);
+    }
+}
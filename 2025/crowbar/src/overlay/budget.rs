@@ -0,0 +1,65 @@
+//! Memory use per budget category, and who's using it.
+
+use crate::{
+    app::WinitApp,
+    budget::{BudgetCategory, Usage},
+    render,
+};
+
+use super::OverlayPanel;
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn usage_bar(ui: &mut egui::Ui, usage: &Usage) {
+    let text = match usage.limit {
+        Some(limit) => format!("{:.1} / {:.1} MiB", mib(usage.used), mib(limit)),
+        None => format!("{:.1} MiB, no limit", mib(usage.used)),
+    };
+
+    let mut bar = egui::ProgressBar::new(usage.fraction().unwrap_or(0.0).min(1.0)).text(text);
+    if usage.over() > 0 {
+        bar = bar.fill(egui::Color32::DARK_RED);
+    }
+    ui.add(bar);
+}
+
+#[derive(Default)]
+pub struct BudgetPanel;
+
+impl OverlayPanel for BudgetPanel {
+    fn name(&self) -> &'static str {
+        "Memory"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let budgets = app.budgets();
+        ui.label(format!(
+            "Tracked: {:.1} MiB, Vulkan host: {:.1} MiB",
+            mib(budgets.total_used()),
+            mib(render::vk_host_allocated() as u64)
+        ));
+        ui.separator();
+
+        for category in BudgetCategory::ALL {
+            let usage = budgets.usage(category);
+            ui.horizontal(|ui| {
+                ui.label(category.name());
+                usage_bar(ui, &usage);
+            });
+
+            egui::CollapsingHeader::new(format!("{} by owner", category.name()))
+                .id_salt(category)
+                .show(ui, |ui| {
+                    if usage.by_owner.is_empty() {
+                        ui.weak("Nothing tracked.");
+                    }
+                    for (owner, bytes) in &usage.by_owner {
+                        ui.monospace(format!("{owner:<24} {:>9.2} MiB", mib(*bytes)));
+                    }
+                    ui.weak(format!("Peak {:.1} MiB", mib(usage.peak)));
+                });
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! The device allocator's blocks, drawn to scale, with what each is holding, and what each render graph pass is
+//! holding on to.
+
+use crate::{
+    app::WinitApp,
+    render::hal::memory::{BlockReport, MemoryCategory, MemoryReport},
+};
+
+use super::OverlayPanel;
+
+const BAR_HEIGHT: f32 = 14.0;
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn color(category: MemoryCategory) -> egui::Color32 {
+    match category {
+        MemoryCategory::Buffer => egui::Color32::from_rgb(90, 160, 230),
+        MemoryCategory::Upload => egui::Color32::from_rgb(120, 200, 120),
+        MemoryCategory::Readback => egui::Color32::from_rgb(220, 200, 90),
+        MemoryCategory::Texture => egui::Color32::from_rgb(200, 120, 220),
+        MemoryCategory::RenderTarget => egui::Color32::from_rgb(230, 120, 90),
+    }
+}
+
+/// What's put down to each owner, biggest first, and what nobody claimed.
+fn by_owner(ui: &mut egui::Ui, report: &MemoryReport) {
+    let owners = report.by_owner();
+    if owners.is_empty() {
+        ui.weak("Nothing's been put down to a pass.");
+        return;
+    }
+    let claimed: u64 = owners.iter().map(|(_, size)| size).sum();
+    egui::Grid::new("crowbar_gpu_memory_owners").show(ui, |ui| {
+        for (name, size) in owners {
+            ui.label(name);
+            ui.monospace(format!("{:.2} MiB", mib(size)));
+            ui.end_row();
+        }
+        ui.weak("Other");
+        ui.monospace(format!(
+            "{:.2} MiB",
+            mib(report.used().saturating_sub(claimed))
+        ));
+        ui.end_row();
+    });
+}
+
+/// One block as a bar, allocations coloured by category over the free space.
+fn block_bar(ui: &mut egui::Ui, block: &BlockReport, report: &MemoryReport) {
+    let (rect, response) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width(), BAR_HEIGHT),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(40));
+
+    let x = |offset: u64| rect.left() + rect.width() * (offset as f64 / block.size as f64) as f32;
+    let mut hovered = None;
+    for a in &block.allocations {
+        // At least a pixel, or small allocations in big blocks vanish.
+        let span = egui::Rect::from_min_max(
+            egui::pos2(x(a.offset), rect.top()),
+            egui::pos2(x(a.offset + a.size).max(x(a.offset) + 1.0), rect.bottom()),
+        );
+        painter.rect_filled(span, 0.0, color(a.category));
+        if response
+            .hover_pos()
+            .is_some_and(|p| span.x_range().contains(p.x))
+        {
+            hovered = Some(a);
+        }
+    }
+    if block.dedicated {
+        painter.rect_stroke(
+            rect,
+            0.0,
+            egui::Stroke::new(1.0f32, egui::Color32::WHITE),
+            egui::StrokeKind::Inside,
+        );
+    }
+
+    if let Some(a) = hovered {
+        let owner = match a.owner {
+            Some(owner) => format!(", for {}", report.owner_name(owner)),
+            None => String::new(),
+        };
+        response.on_hover_text(format!(
+            "{}: {:.2} MiB at {:#x}{owner}",
+            a.category.name(),
+            mib(a.size),
+            a.offset
+        ));
+    }
+}
+
+type MemoryReporter = Box<dyn FnMut(&WinitApp) -> Option<MemoryReport>>;
+
+/// Draws a device's [`MemoryReport`]. Whoever owns the device registers this, with a way to get the report from the
+/// app, `None` while there's no device.
+pub struct GpuMemoryPanel {
+    report: MemoryReporter,
+    hide_empty_heaps: bool,
+}
+
+impl GpuMemoryPanel {
+    pub fn new(report: impl FnMut(&WinitApp) -> Option<MemoryReport> + 'static) -> GpuMemoryPanel {
+        GpuMemoryPanel {
+            report: Box::new(report),
+            hide_empty_heaps: true,
+        }
+    }
+}
+
+impl OverlayPanel for GpuMemoryPanel {
+    fn name(&self) -> &'static str {
+        "GPU memory"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let Some(report) = (self.report)(app) else {
+            ui.weak("No GPU.");
+            return;
+        };
+        ui.label(format!(
+            "{:.1} MiB used of {:.1} MiB reserved",
+            mib(report.used()),
+            mib(report.reserved())
+        ));
+        ui.horizontal_wrapped(|ui| {
+            for category in MemoryCategory::ALL {
+                ui.colored_label(color(category), "■");
+                ui.label(category.name());
+            }
+            ui.label("Outlined: dedicated");
+        });
+        ui.collapsing("By pass", |ui| by_owner(ui, &report));
+        ui.checkbox(&mut self.hide_empty_heaps, "Hide empty heaps");
+        ui.separator();
+
+        for (i, heap) in report.heaps.iter().enumerate() {
+            if self.hide_empty_heaps && heap.blocks.is_empty() {
+                continue;
+            }
+            let kind = if heap.device_local { "device" } else { "host" };
+            egui::CollapsingHeader::new(format!(
+                "Heap {i} ({kind}): {:.1} / {:.1} MiB of {:.0} MiB",
+                mib(heap.used()),
+                mib(heap.reserved()),
+                mib(heap.size)
+            ))
+            .id_salt(("crowbar_gpu_heap", i))
+            .default_open(true)
+            .show(ui, |ui| {
+                for block in &heap.blocks {
+                    let fragmentation = block.fragmentation();
+                    let text = format!(
+                        "Type {}, {:.1} / {:.1} MiB, {} allocations, {:.0}% fragmented",
+                        block.memory_type,
+                        mib(block.used()),
+                        mib(block.size),
+                        block.allocations.len(),
+                        fragmentation * 100.0
+                    );
+                    // Past half, the free space is in pieces too small to be much use.
+                    if fragmentation > 0.5 {
+                        ui.colored_label(egui::Color32::ORANGE, text);
+                    } else {
+                        ui.weak(text);
+                    }
+                    block_bar(ui, block, &report);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! How frames are getting to the screen, for the main window: acquire waits, swapchain churn, and what the display
+//! made of them where the platform says.
+
+use crate::{
+    app::WinitApp,
+    render::{surface::PresentStats, swapchain::PresentModePreference},
+};
+
+use super::OverlayPanel;
+
+fn ms(d: std::time::Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn acquire_graph(ui: &mut egui::Ui, stats: &PresentStats) {
+    let waits: Vec<f64> = stats.recent_acquire_waits().map(ms).collect();
+    let top = waits.iter().copied().fold(1.0, f64::max);
+    let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(96));
+
+    let step = rect.width() / waits.len().max(1) as f32;
+    for (i, wait) in waits.iter().enumerate() {
+        let height = (wait / top) as f32 * rect.height();
+        let x = rect.left() + i as f32 * step;
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(x, rect.bottom() - height),
+                egui::pos2(x + step.max(1.0), rect.bottom()),
+            ),
+            0.0,
+            egui::Color32::LIGHT_BLUE,
+        );
+    }
+    ui.weak(format!("Acquire waits, top {top:.2} ms"));
+}
+
+#[derive(Default)]
+pub struct PresentPanel;
+
+impl OverlayPanel for PresentPanel {
+    fn name(&self) -> &'static str {
+        "Presentation"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let default_vsync = app.cvars().get(app.engine_cvars().r_vsync);
+        let Some(state) = app
+            .main_window()
+            .and_then(|id| app.get_window_state_mut(id))
+        else {
+            ui.weak("No window.");
+            return;
+        };
+
+        let size = state.size();
+        ui.label(format!(
+            "{}x{} at {}x",
+            size.width,
+            size.height,
+            state.scale_factor()
+        ));
+
+        let mut present_mode = state.present_mode_override();
+        egui::ComboBox::from_label("Present mode")
+            .selected_text(match present_mode {
+                None => format!("r_vsync ({})", if default_vsync { "on" } else { "off" }),
+                Some(mode) => format!("{mode:?}"),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut present_mode, None, "r_vsync");
+                for mode in [
+                    PresentModePreference::Fifo,
+                    PresentModePreference::Mailbox,
+                    PresentModePreference::Immediate,
+                ] {
+                    ui.selectable_value(&mut present_mode, Some(mode), format!("{mode:?}"));
+                }
+            });
+        if present_mode != state.present_mode_override() {
+            state.set_present_mode(present_mode);
+        }
+        if let Some(swapchain) = state.swapchain() {
+            ui.label(format!("Presenting with {:?}", swapchain.present_mode()));
+            ui.label(format!(
+                "Output: {:?} in {:?}",
+                swapchain.output(),
+                swapchain.format().format
+            ));
+        }
+
+        let pacer = state.pacer();
+        match pacer.refresh_rate() {
+            Some(hz) => ui.label(format!("Display: {hz:.1} Hz")),
+            None => ui.weak("Refresh rate unknown."),
+        };
+        if pacer.presented() > 0 {
+            ui.label(format!(
+                "Presented {}, missed refreshes {}, discarded {}",
+                pacer.presented(),
+                pacer.missed(),
+                pacer.discarded()
+            ));
+        }
+        ui.separator();
+
+        let stats = state.present_stats();
+        egui::Grid::new("crowbar_present_stats").show(ui, |ui| {
+            for (label, value) in [
+                ("Acquires", stats.acquires().to_string()),
+                ("Suboptimal", stats.suboptimal().to_string()),
+                ("Out of date", stats.out_of_date().to_string()),
+                ("Recreated", stats.recreated().to_string()),
+                ("Skipped", stats.skipped().to_string()),
+                (
+                    "Acquire wait",
+                    format!(
+                        "{:.2} ms mean, {:.2} ms max",
+                        ms(stats.mean_acquire_wait()),
+                        ms(stats.max_acquire_wait())
+                    ),
+                ),
+            ] {
+                ui.label(label);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+
+        // Which images come back, uneven counts mean the presentation engine is holding some.
+        let acquires = stats.acquires().max(1);
+        for (i, count) in stats.acquired().iter().enumerate() {
+            ui.add(
+                egui::ProgressBar::new(*count as f32 / acquires as f32)
+                    .text(format!("Image {i}: {count}")),
+            );
+        }
+        acquire_graph(ui, stats);
+
+        if ui.button("Reset").clicked() {
+            state.present_stats_mut().reset();
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Flame view of the built-in profiler, with GPU work on the same timeline.
+
+use egui::{Color32, Painter, Pos2, Rect, Response, Sense, Stroke, vec2};
+
+use crate::{
+    app::WinitApp,
+    profile::{self, Span},
+};
+
+use super::OverlayPanel;
+
+const ROW_HEIGHT: f32 = 18.0;
+
+#[derive(Default)]
+pub struct ProfilerPanel {
+    /// Keep showing this frame rather than following the latest.
+    paused: Option<u64>,
+}
+
+/// Stable colours per scope name, so things are easy to follow frame to frame.
+fn scope_color(name: &str) -> Color32 {
+    let hash = name.bytes().fold(0x811c9dc5u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x01000193)
+    });
+    Color32::from_rgb(
+        96 + (hash & 0x7f) as u8,
+        96 + ((hash >> 8) & 0x7f) as u8,
+        96 + ((hash >> 16) & 0x7f) as u8,
+    )
+}
+
+fn ms(d: std::time::Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Everything needed to draw one band of spans.
+struct Timeline<'a> {
+    painter: &'a Painter,
+    response: &'a Response,
+    origin: Pos2,
+    width: f32,
+    /// Seconds covered by the full width.
+    total: f32,
+    hover: Option<Pos2>,
+}
+
+impl Timeline<'_> {
+    /// Draw a labelled band of spans starting at `row`, returning the row after it.
+    fn band<'s>(
+        &self,
+        row: u32,
+        label: &str,
+        label_color: Color32,
+        spans: impl Iterator<Item = &'s Span>,
+    ) -> u32 {
+        self.painter.text(
+            self.origin + vec2(0.0, row as f32 * ROW_HEIGHT),
+            egui::Align2::LEFT_TOP,
+            label,
+            egui::FontId::monospace(11.0),
+            label_color,
+        );
+
+        let mut depth = 0;
+        for span in spans {
+            depth = depth.max(span.depth + 1);
+
+            let x0 = span.start.as_secs_f32() / self.total * self.width;
+            let x1 = span.end.as_secs_f32() / self.total * self.width;
+            let y = (row + 1 + span.depth) as f32 * ROW_HEIGHT;
+            let rect = Rect::from_min_size(
+                self.origin + vec2(x0, y),
+                vec2((x1 - x0).max(1.0), ROW_HEIGHT - 1.0),
+            );
+
+            self.painter.rect(
+                rect,
+                2.0,
+                scope_color(span.name),
+                Stroke::NONE,
+                egui::StrokeKind::Inside,
+            );
+            if rect.width() > 40.0 {
+                self.painter.with_clip_rect(rect).text(
+                    rect.left_center() + vec2(2.0, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    span.name,
+                    egui::FontId::monospace(11.0),
+                    Color32::BLACK,
+                );
+            }
+
+            if self.hover.is_some_and(|p| rect.contains(p)) {
+                self.response.clone().on_hover_text(format!(
+                    "{}: {:.3} ms",
+                    span.name,
+                    ms(span.end - span.start)
+                ));
+            }
+        }
+
+        return row + 1 + depth;
+    }
+}
+
+impl OverlayPanel for ProfilerPanel {
+    fn name(&self) -> &'static str {
+        "Profiler"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _app: &mut WinitApp) {
+        let mut enabled = profile::is_enabled();
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut enabled, "Record").changed() {
+                profile::set_enabled(enabled);
+            }
+
+            let mut paused = self.paused.is_some();
+            if ui.checkbox(&mut paused, "Pause").changed() {
+                self.paused = match paused {
+                    true => profile::latest_frame().map(|f| f.index),
+                    false => None,
+                };
+            }
+        });
+
+        let frame = match self.paused {
+            Some(index) => profile::history().into_iter().find(|f| f.index == index),
+            // The newest frame's GPU work won't have resolved yet, so show one with GPU data if we can.
+            None => profile::history()
+                .into_iter()
+                .rev()
+                .take(8)
+                .find(|f| !f.gpu_spans.is_empty())
+                .or_else(profile::latest_frame),
+        };
+
+        let Some(frame) = frame else {
+            ui.label("No frames recorded.");
+            return;
+        };
+
+        ui.label(format!(
+            "Frame {}: {:.2} ms CPU",
+            frame.index,
+            ms(frame.duration)
+        ));
+        if let Some(latency) = frame.gpu_latency() {
+            ui.label(format!(
+                "GPU finished {:.2} ms after CPU frame end, idle {:.2} ms in between work{}",
+                ms(latency),
+                ms(frame.gpu_idle()),
+                if frame.gpu_calibrated {
+                    ""
+                } else {
+                    " (uncalibrated)"
+                }
+            ));
+        }
+
+        let mut threads: Vec<(usize, u32)> = Vec::new();
+        for span in &frame.spans {
+            match threads.iter_mut().find(|(t, _)| *t == span.thread) {
+                Some((_, depth)) => *depth = (*depth).max(span.depth + 1),
+                None => threads.push((span.thread, span.depth + 1)),
+            }
+        }
+        threads.sort();
+
+        let gpu_rows = frame
+            .gpu_spans
+            .iter()
+            .map(|s| s.depth + 2)
+            .max()
+            .unwrap_or(0);
+        let rows: u32 = threads.iter().map(|(_, d)| d + 1).sum::<u32>() + gpu_rows;
+        let width = ui.available_width().max(200.0);
+        let (response, painter) =
+            ui.allocate_painter(vec2(width, rows as f32 * ROW_HEIGHT), Sense::hover());
+
+        let timeline = Timeline {
+            painter: &painter,
+            response: &response,
+            origin: response.rect.min,
+            width,
+            total: frame.extent().as_secs_f32().max(f32::EPSILON),
+            hover: response.hover_pos(),
+        };
+
+        // Mark the end of the CPU frame, anything GPU side past it is latency.
+        let frame_end = response.rect.min.x + frame.duration.as_secs_f32() / timeline.total * width;
+        painter.vline(
+            frame_end,
+            response.rect.y_range(),
+            Stroke::new(1.0f32, Color32::from_gray(160)),
+        );
+
+        let text_color = ui.visuals().text_color();
+        let mut row = 0;
+        for (thread, _) in threads {
+            row = timeline.band(
+                row,
+                &frame.threads[thread],
+                text_color,
+                frame.spans.iter().filter(|s| s.thread == thread),
+            );
+        }
+
+        if !frame.gpu_spans.is_empty() {
+            timeline.band(row, "GPU", Color32::LIGHT_RED, frame.gpu_spans.iter());
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! A frame's render graph as nodes: passes in columns by how deep they are, lines for what waits on what, and
+//! anything [`CompiledGraph::validate`] has to say about it. Also what the frame spends on barriers, and which
+//! passes spend the most, and how much memory aliasing saves. With [`ReadbackRequests`] to send them to, textures
+//! can be captured to disk from here.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    app::WinitApp,
+    render::graph::{
+        CompiledGraph, GraphIssue, PassId, barriers::BarrierStats, readback::ReadbackRequests,
+    },
+};
+
+use super::OverlayPanel;
+
+/// How many passes get listed under the barrier totals.
+const TOP_OFFENDERS: usize = 5;
+
+const NODE_SIZE: egui::Vec2 = egui::vec2(140.0, 36.0);
+const SPACING: egui::Vec2 = egui::vec2(60.0, 16.0);
+
+/// What `recorded` says went in, or what the graph works out without it.
+fn barriers(ui: &mut egui::Ui, compiled: &CompiledGraph, recorded: Option<BarrierStats>) {
+    let stats = match recorded {
+        Some(recorded) => recorded,
+        None => {
+            ui.weak("Worked out from the graph, nothing's said what was recorded.");
+            compiled.barriers()
+        }
+    };
+    let total = stats.total();
+    egui::Grid::new("crowbar_render_graph_barriers").show(ui, |ui| {
+        for (label, value) in [
+            ("Image barriers", total.image_barriers),
+            ("Buffer barriers", total.buffer_barriers),
+            ("Layout transitions", total.layout_transitions),
+            ("Queue transfers", total.queue_transfers),
+        ] {
+            ui.label(label);
+            ui.monospace(value.to_string());
+            ui.end_row();
+        }
+    });
+
+    for offender in stats.top_offenders(TOP_OFFENDERS) {
+        let graph = compiled.graph();
+        let names: Vec<&str> = offender
+            .barriers
+            .iter()
+            .map(|b| graph.resource(b.resource).name.as_str())
+            .collect();
+        ui.horizontal_wrapped(|ui| {
+            ui.strong(graph.pass(offender.pass).name());
+            ui.label(format!(
+                "{} barriers, {} transitions, {} transfers:",
+                offender.counts.barriers(),
+                offender.counts.layout_transitions,
+                offender.counts.queue_transfers
+            ));
+            ui.weak(names.join(", "));
+        });
+    }
+}
+
+fn captures(ui: &mut egui::Ui, compiled: &CompiledGraph, requests: &ReadbackRequests) {
+    let graph = compiled.graph();
+    egui::Grid::new("crowbar_render_graph_captures").show(ui, |ui| {
+        for resource in graph.resources() {
+            let resource = graph.resource(resource);
+            let Some(desc) = resource.kind.texture_desc() else {
+                continue;
+            };
+            ui.label(&resource.name);
+            ui.weak(format!("{}x{} {:?}", desc.width, desc.height, desc.format));
+            match compiled.readback_point(&resource.name) {
+                Ok(_) => {
+                    if ui.button("Capture").clicked() {
+                        requests.request(&resource.name);
+                    }
+                }
+                Err(e) => {
+                    ui.add_enabled(false, egui::Button::new("Capture"))
+                        .on_disabled_hover_text(e.to_string());
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+fn submissions(ui: &mut egui::Ui, compiled: &CompiledGraph) {
+    let graph = compiled.graph();
+    for (i, submission) in compiled.schedule().iter().enumerate() {
+        let passes: Vec<&str> = submission
+            .passes
+            .iter()
+            .map(|p| graph.pass(*p).name())
+            .collect();
+        ui.horizontal_wrapped(|ui| {
+            ui.monospace(format!("{i}: {}", submission.queue.name()));
+            ui.label(passes.join(", "));
+            if !submission.waits.is_empty() {
+                ui.weak(format!("waits on {:?}", submission.waits));
+            }
+            if submission.signals {
+                ui.weak("signals");
+            }
+        });
+    }
+}
+
+fn node_view(ui: &mut egui::Ui, compiled: &CompiledGraph, issues: &[GraphIssue]) {
+    let graph = compiled.graph();
+    let depths = compiled.depths();
+    let mut broken = BTreeSet::new();
+    let mut unused = BTreeSet::new();
+    for issue in issues {
+        match issue {
+            GraphIssue::Unused(p) => {
+                unused.insert(*p);
+            }
+            issue => broken.extend(issue.passes()),
+        }
+    }
+
+    // Stack each column's passes in the order they run, with cycles tacked on the end.
+    let mut rows = vec![0; depths.iter().max().map_or(0, |d| d + 1)];
+    let mut positions = vec![egui::Vec2::ZERO; depths.len()];
+    let stuck = graph.passes().filter(|p| !compiled.order().contains(p));
+    for pass in compiled.order().iter().copied().chain(stuck) {
+        let depth = depths[pass.index()];
+        positions[pass.index()] = egui::vec2(
+            depth as f32 * (NODE_SIZE.x + SPACING.x),
+            rows[depth] as f32 * (NODE_SIZE.y + SPACING.y),
+        );
+        rows[depth] += 1;
+    }
+
+    let size = positions
+        .iter()
+        .fold(egui::Vec2::ZERO, |size, p| size.max(*p + NODE_SIZE));
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let node =
+        |pass: PassId| egui::Rect::from_min_size(rect.min + positions[pass.index()], NODE_SIZE);
+
+    for &(before, after) in compiled.edges() {
+        painter.line_segment(
+            [node(before).right_center(), node(after).left_center()],
+            egui::Stroke::new(1.0f32, egui::Color32::GRAY),
+        );
+    }
+
+    for pass in graph.passes() {
+        let rect = node(pass);
+        let fill = if broken.contains(&pass) {
+            egui::Color32::from_rgb(120, 40, 40)
+        } else if unused.contains(&pass) {
+            egui::Color32::from_gray(50)
+        } else {
+            egui::Color32::from_rgb(40, 60, 90)
+        };
+        painter.rect_filled(rect, 4.0, fill);
+        let p = graph.pass(pass);
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!("{}\n{}", p.name(), p.queue().name()),
+            egui::FontId::proportional(12.0),
+            egui::Color32::WHITE,
+        );
+
+        let response = ui.interact(
+            rect,
+            ui.id().with(("render_graph_pass", pass.index())),
+            egui::Sense::hover(),
+        );
+        response.on_hover_ui(|ui| {
+            for &(resource, access) in p.uses() {
+                ui.label(format!(
+                    "{}: {}",
+                    graph.resource(resource).name,
+                    access.name()
+                ));
+            }
+        });
+    }
+}
+
+type GraphFn = Box<dyn FnMut(&WinitApp) -> Option<CompiledGraph>>;
+type RecordedFn = Box<dyn FnMut(&WinitApp) -> Option<BarrierStats>>;
+
+/// Shows the render graph it's given each frame. Whoever builds the graph registers this, with a way to get it from
+/// the app.
+pub struct RenderGraphPanel {
+    graph: GraphFn,
+    recorded: Option<RecordedFn>,
+    readback: Option<ReadbackRequests>,
+}
+
+impl RenderGraphPanel {
+    pub fn new(
+        graph: impl FnMut(&WinitApp) -> Option<CompiledGraph> + 'static,
+    ) -> RenderGraphPanel {
+        RenderGraphPanel {
+            graph: Box::new(graph),
+            recorded: None,
+            readback: None,
+        }
+    }
+
+    /// Show the barriers recording the graph put in, from `recorded`, rather than what the graph works out.
+    pub fn with_recorded_barriers(
+        mut self,
+        recorded: impl FnMut(&WinitApp) -> Option<BarrierStats> + 'static,
+    ) -> Self {
+        self.recorded = Some(Box::new(recorded));
+        return self;
+    }
+
+    /// Add capture buttons for the graph's textures, sending what's clicked to `requests`.
+    pub fn with_readback(mut self, requests: ReadbackRequests) -> Self {
+        self.readback = Some(requests);
+        return self;
+    }
+}
+
+impl OverlayPanel for RenderGraphPanel {
+    fn name(&self) -> &'static str {
+        "Render graph"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let Some(compiled) = (self.graph)(app) else {
+            ui.weak("No render graph.");
+            return;
+        };
+        let recorded = self.recorded.as_mut().and_then(|recorded| recorded(app));
+
+        let issues = compiled.validate();
+        ui.horizontal(|ui| {
+            let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            ui.label(format!(
+                "{} passes, {} resources, {:.1} MiB of transients in {:.1} MiB aliased",
+                compiled.graph().passes().count(),
+                compiled.graph().resources().count(),
+                mib(compiled.transient_bytes().iter().sum()),
+                mib(compiled.aliased_bytes())
+            ));
+            if ui.button("Copy Graphviz").clicked() {
+                ui.ctx().copy_text(compiled.to_dot());
+            }
+        });
+        for issue in &issues {
+            let color = match issue {
+                GraphIssue::Unused(_) => egui::Color32::YELLOW,
+                _ => egui::Color32::LIGHT_RED,
+            };
+            ui.colored_label(color, compiled.describe(issue));
+        }
+        ui.separator();
+        ui.collapsing("Barriers", |ui| barriers(ui, &compiled, recorded));
+        ui.collapsing("Submissions", |ui| submissions(ui, &compiled));
+        if let Some(requests) = &self.readback {
+            ui.collapsing("Capture", |ui| captures(ui, &compiled, requests));
+        }
+
+        egui::ScrollArea::both().show(ui, |ui| node_view(ui, &compiled, &issues));
+    }
+}
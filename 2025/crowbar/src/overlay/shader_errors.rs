@@ -0,0 +1,59 @@
+//! Why shaders didn't build, in the files the mistakes are in. The pipelines keep running on their last good build
+//! meanwhile, so errors stay listed until the pipeline builds or they get dismissed.
+
+use crate::{
+    app::WinitApp,
+    render::shader::{Severity, ShaderDiagnostic},
+};
+
+use super::OverlayPanel;
+
+fn diagnostic(ui: &mut egui::Ui, d: &ShaderDiagnostic) {
+    let color = match d.severity {
+        Severity::Error => egui::Color32::LIGHT_RED,
+        Severity::Warning => egui::Color32::YELLOW,
+    };
+    ui.horizontal_wrapped(|ui| {
+        let location = match (d.line, d.column) {
+            (0, _) => d.file.display().to_string(),
+            (line, None) => format!("{}:{line}", d.file.display()),
+            (line, Some(column)) => format!("{}:{line}:{column}", d.file.display()),
+        };
+        ui.colored_label(color, egui::RichText::new(location).monospace());
+        ui.label(&d.message);
+    });
+}
+
+#[derive(Default)]
+pub struct ShaderErrorsPanel;
+
+impl OverlayPanel for ShaderErrorsPanel {
+    fn name(&self) -> &'static str {
+        "Shader errors"
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, app: &mut WinitApp) {
+        let errors = app.shader_errors();
+        let failed = errors.snapshot();
+        if failed.is_empty() {
+            ui.weak("All shaders built.");
+            return;
+        }
+
+        for (pipeline, diagnostics) in &failed {
+            ui.horizontal(|ui| {
+                ui.strong(pipeline);
+                if ui.small_button("Dismiss").clicked() {
+                    errors.dismiss(pipeline);
+                }
+            });
+            for d in diagnostics {
+                diagnostic(ui, d);
+            }
+            ui.separator();
+        }
+        if ui.button("Dismiss all").clicked() {
+            errors.dismiss_all();
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! Pack files: many assets in one file, memory mapped so reads come straight out of the page cache.
+//!
+//! Layout, all little endian:
+//! - Header: magic, version, entry count, table offset.
+//! - Asset data, each either stored as is or as a run of LZ4 compressed [`CHUNK_SIZE`] chunks.
+//! - The table: per entry the name, data offset, uncompressed size and compressed chunk lengths.
+//!
+//! Stored assets are handed out as slices of the mapping without copying. Compressed ones are
+//! decompressed chunk by chunk across the job system.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    ops::{Deref, Range},
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use memmap2::Mmap;
+
+use crate::{budget::BudgetedCache, jobs::JobSystem, profile_scope};
+
+pub const MAGIC: [u8; 4] = *b"CBPK";
+pub const VERSION: u32 = 1;
+/// Uncompressed size of each compressed chunk, bar the last.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const HEADER_SIZE: usize = 4 + 4 + 4 + 8;
+/// The least a table entry takes: an empty name, offset, size and no chunks.
+const MIN_ENTRY_SIZE: usize = 2 + 8 + 8 + 4;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    offset: u64,
+    size: u64,
+    /// Compressed length of each chunk, empty if stored as is.
+    chunks: Vec<u32>,
+}
+
+impl Entry {
+    fn stored_size(&self) -> u64 {
+        if self.chunks.is_empty() {
+            return self.size;
+        }
+        return self.chunks.iter().map(|&c| c as u64).sum();
+    }
+}
+
+/// Reads little endian values off the front of a slice.
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.0.len() < n {
+            return Err(invalid("Pack table is truncated"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        return Ok(head);
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Part of a mapped pack. Keeps the mapping alive, so it can outlive the [`Pack`] and cross threads.
+#[derive(Clone)]
+pub struct MappedBytes {
+    map: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl Deref for MappedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+}
+
+/// An asset read out of a pack.
+pub enum AssetData {
+    /// Straight from the mapping, no copy made.
+    Mapped(MappedBytes),
+    /// Decompressed into memory.
+    Owned(Vec<u8>),
+}
+
+impl Deref for AssetData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            AssetData::Mapped(bytes) => bytes,
+            AssetData::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Decompressed assets by name, counted against [`BudgetCategory::Assets`](crate::budget::BudgetCategory). Names
+/// are only unique within a pack, so one of these per pack.
+pub type AssetCache = BudgetedCache<String, Arc<AssetData>>;
+
+/// A mounted pack file. Cheap to clone.
+// todo: mount these through the asset loader once there is one.
+#[derive(Clone)]
+pub struct Pack {
+    map: Arc<Mmap>,
+    entries: Arc<HashMap<String, Entry>>,
+}
+
+impl Pack {
+    pub fn open(path: &Path) -> io::Result<Pack> {
+        let file = fs::File::open(path)?;
+        // SAFETY: Packs are build output and never written to while the engine has them open.
+        // A pack changing under us would make reads return garbage, but the bounds are checked on open.
+        let map = unsafe { Mmap::map(&file)? };
+        return Pack::from_map(map);
+    }
+
+    /// Open a pack shipped with the game, mapped straight out of the APK on Android.
+    /// It has to be stored uncompressed there, which cargo-apk and xbuild do for unknown extensions.
+    pub fn open_asset(path: &str) -> io::Result<Pack> {
+        #[cfg(target_os = "android")]
+        {
+            let (apk, offset, len) = crate::platform::android::asset_file(path)?;
+            // SAFETY: As in `open`, and nothing writes to an installed APK.
+            let map = unsafe {
+                memmap2::MmapOptions::new()
+                    .offset(offset)
+                    .len(len)
+                    .map(&apk)?
+            };
+            return Pack::from_map(map);
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            return Pack::open(Path::new(path));
+        }
+    }
+
+    fn from_map(map: Mmap) -> io::Result<Pack> {
+        let mut header = Cursor(&map[..]);
+        if header.take(4)? != MAGIC {
+            return Err(invalid("Not a pack file"));
+        }
+        let version = header.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "Pack version {version} isn't supported, expected {VERSION}"
+            )));
+        }
+        let count = header.u32()?;
+        let table = header.u64()? as usize;
+
+        let mut cursor = Cursor(
+            map.get(table..)
+                .ok_or_else(|| invalid("Bad table offset"))?,
+        );
+        // The count is only as trustworthy as the file, so don't reserve more than the table could hold.
+        let mut entries =
+            HashMap::with_capacity((count as usize).min(cursor.0.len() / MIN_ENTRY_SIZE));
+        for _ in 0..count {
+            let len = cursor.u16()? as usize;
+            let name = std::str::from_utf8(cursor.take(len)?)
+                .map_err(|_| invalid("Asset name isn't UTF-8"))?
+                .to_owned();
+            let offset = cursor.u64()?;
+            let size = cursor.u64()?;
+            let chunk_count = cursor.u32()?;
+            let mut chunks = Vec::with_capacity((chunk_count as usize).min(cursor.0.len() / 4));
+            for _ in 0..chunk_count {
+                chunks.push(cursor.u32()?);
+            }
+
+            let entry = Entry {
+                offset,
+                size,
+                chunks,
+            };
+            let end = offset.checked_add(entry.stored_size());
+            if end.is_none_or(|end| end > table as u64) {
+                return Err(invalid(&format!("{name} runs past the asset data")));
+            }
+            if !entry.chunks.is_empty()
+                && entry.chunks.len() != size.div_ceil(CHUNK_SIZE as u64) as usize
+            {
+                return Err(invalid(&format!("{name} has the wrong number of chunks")));
+            }
+            entries.insert(name, entry);
+        }
+
+        return Ok(Pack {
+            map: Arc::new(map),
+            entries: Arc::new(entries),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    /// Uncompressed size of an asset.
+    pub fn size(&self, name: &str) -> Option<u64> {
+        self.entries.get(name).map(|e| e.size)
+    }
+
+    pub fn is_compressed(&self, name: &str) -> bool {
+        self.entries.get(name).is_some_and(|e| !e.chunks.is_empty())
+    }
+
+    fn entry(&self, name: &str) -> io::Result<&Entry> {
+        self.entries
+            .get(name)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{name} isn't in the pack")))
+    }
+
+    /// Hint the OS to start paging an asset in, ahead of reading it.
+    pub fn prefetch(&self, name: &str) {
+        let Ok(entry) = self.entry(name) else {
+            return;
+        };
+
+        #[cfg(unix)]
+        {
+            let _ = self.map.advise_range(
+                memmap2::Advice::WillNeed,
+                entry.offset as usize,
+                entry.stored_size() as usize,
+            );
+        }
+        #[cfg(not(unix))]
+        let _ = entry;
+    }
+
+    /// Read an asset. Stored ones come straight from the mapping, compressed ones get their chunks
+    /// decompressed on `jobs`.
+    pub fn read(&self, name: &str, jobs: &JobSystem) -> io::Result<AssetData> {
+        profile_scope!("pack_read");
+        let entry = self.entry(name)?;
+        let start = entry.offset as usize;
+
+        if entry.chunks.is_empty() {
+            return Ok(AssetData::Mapped(MappedBytes {
+                map: self.map.clone(),
+                range: start..start + entry.size as usize,
+            }));
+        }
+
+        // Each chunk's compressed data, laid out back to back.
+        let mut sources = Vec::with_capacity(entry.chunks.len());
+        let mut at = start;
+        for &len in &entry.chunks {
+            sources.push(&self.map[at..at + len as usize]);
+            at += len as usize;
+        }
+
+        let mut out = vec![0u8; entry.size as usize];
+        let failed = AtomicBool::new(false);
+        jobs.scope(|s| {
+            for (src, dst) in sources.iter().zip(out.chunks_mut(CHUNK_SIZE)) {
+                let failed = &failed;
+                s.spawn("pack_decompress", move |_| {
+                    let res = lz4_flex::block::decompress_into(src, dst);
+                    if res.is_ok_and(|n| n == dst.len()) {
+                        return;
+                    }
+                    failed.store(true, Ordering::Relaxed);
+                });
+            }
+        });
+
+        if failed.into_inner() {
+            return Err(invalid(&format!("{name} failed to decompress")));
+        }
+        return Ok(AssetData::Owned(out));
+    }
+
+    /// [`Pack::read`], keeping decompressed assets in `cache` to hand out again. Stored ones aren't kept, they're
+    /// in the mapping already.
+    pub fn read_cached(
+        &self,
+        name: &str,
+        jobs: &JobSystem,
+        cache: &AssetCache,
+    ) -> io::Result<Arc<AssetData>> {
+        if !self.is_compressed(name) {
+            return self.read(name, jobs).map(Arc::new);
+        }
+        return cache.get_or_insert_with(&name.to_owned(), || {
+            let data = self.read(name, jobs)?;
+            let size = data.len() as u64;
+            Ok((Arc::new(data), size))
+        });
+    }
+
+    /// Start reading `names`, each as a job of its own on `jobs`, to take from the [`Preload`] as they finish.
+    pub fn preload<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        jobs: &JobSystem,
+    ) -> Preload {
+        let mut preload = Preload {
+            total: 0,
+            read: Arc::default(),
+            left: Arc::default(),
+            assets: Arc::default(),
+        };
+        for name in names {
+            self.prefetch(name);
+            let size = self.size(name).unwrap_or(0);
+            preload.total += size;
+            preload.left.fetch_add(1, Ordering::AcqRel);
+
+            let (pack, workers, name) = (self.clone(), jobs.clone(), name.to_owned());
+            let (read, left, assets) = (
+                preload.read.clone(),
+                preload.left.clone(),
+                preload.assets.clone(),
+            );
+            jobs.spawn("pack_preload", move || {
+                let data = pack.read(&name, &workers);
+                assets.lock().unwrap().insert(name, data);
+                read.fetch_add(size, Ordering::AcqRel);
+                left.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        return preload;
+    }
+}
+
+/// Assets being read in the background, for a loading screen to show the progress of. See [`Pack::preload`].
+pub struct Preload {
+    /// Uncompressed bytes to read.
+    total: u64,
+    read: Arc<AtomicU64>,
+    /// Assets not read yet.
+    left: Arc<AtomicUsize>,
+    assets: Arc<Mutex<HashMap<String, io::Result<AssetData>>>>,
+}
+
+impl Preload {
+    /// How much has been read, by size, in `0.0..=1.0`.
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        return self.read.load(Ordering::Acquire) as f32 / self.total as f32;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.left.load(Ordering::Acquire) == 0
+    }
+
+    /// Take an asset once it's been read, or failed to be.
+    pub fn take(&self, name: &str) -> Option<io::Result<AssetData>> {
+        self.assets.lock().unwrap().remove(name)
+    }
+}
+
+/// Builds pack files, for tools and tests. Everything is held in memory until written.
+#[derive(Default)]
+pub struct PackWriter {
+    data: Vec<u8>,
+    table: Vec<(String, Entry)>,
+}
+
+impl PackWriter {
+    pub fn new() -> PackWriter {
+        PackWriter::default()
+    }
+
+    /// Add an asset, compressing it if asked to and it actually gets smaller.
+    pub fn add(&mut self, name: &str, bytes: &[u8], compress: bool) {
+        let offset = (HEADER_SIZE + self.data.len()) as u64;
+        let mut chunks = Vec::new();
+
+        if compress {
+            let compressed: Vec<_> = bytes
+                .chunks(CHUNK_SIZE)
+                .map(lz4_flex::block::compress)
+                .collect();
+            let total: usize = compressed.iter().map(|c| c.len()).sum();
+
+            if total < bytes.len() {
+                for c in compressed {
+                    chunks.push(c.len() as u32);
+                    self.data.extend_from_slice(&c);
+                }
+            }
+        }
+        if chunks.is_empty() {
+            self.data.extend_from_slice(bytes);
+        }
+
+        self.table.push((
+            name.to_owned(),
+            Entry {
+                offset,
+                size: bytes.len() as u64,
+                chunks,
+            },
+        ));
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let table_offset = (HEADER_SIZE + self.data.len()) as u64;
+
+        let mut out = Vec::with_capacity(table_offset as usize);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.table.len() as u32).to_le_bytes());
+        out.extend_from_slice(&table_offset.to_le_bytes());
+        out.extend_from_slice(&self.data);
+
+        for (name, entry) in &self.table {
+            let len: u16 = name
+                .len()
+                .try_into()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Asset name too long"))?;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&entry.offset.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&(entry.chunks.len() as u32).to_le_bytes());
+            for c in &entry.chunks {
+                out.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+
+        return fs::write(path, out);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{AssetCache, AssetData, CHUNK_SIZE, HEADER_SIZE, MAGIC, Pack, PackWriter, VERSION};
+    use crate::{
+        budget::{BudgetCategory, Budgets},
+        jobs::JobSystem,
+        rng::Rng,
+    };
+
+    #[test]
+    pub fn round_trip() {
+        let big: Vec<u8> = (0..CHUNK_SIZE * 3 + 100).map(|i| (i / 7) as u8).collect();
+        let mut rng = Rng::new(1);
+        let noise: Vec<u8> = (0..1000).map(|_| rng.next_u32() as u8).collect();
+
+        let mut writer = PackWriter::new();
+        writer.add("big.bin", &big, true);
+        writer.add("raw.bin", b"hello", false);
+        writer.add("noise.bin", &noise, true);
+
+        let path =
+            std::env::temp_dir().join(format!("crowbar-pack-test-{}.pack", std::process::id()));
+        writer.write(&path).unwrap();
+        let pack = Pack::open(&path).unwrap();
+        let jobs = JobSystem::new(Some(2));
+
+        assert_eq!(pack.len(), 3);
+        assert!(pack.is_compressed("big.bin"));
+        // Noise doesn't compress, so it gets stored.
+        assert!(!pack.is_compressed("noise.bin"));
+
+        let data = pack.read("big.bin", &jobs).unwrap();
+        assert!(matches!(data, AssetData::Owned(_)));
+        assert_eq!(&*data, &big[..]);
+
+        let data = pack.read("raw.bin", &jobs).unwrap();
+        assert!(matches!(data, AssetData::Mapped(_)));
+        assert_eq!(&*data, b"hello");
+        assert_eq!(&*pack.read("noise.bin", &jobs).unwrap(), &noise[..]);
+        assert!(pack.read("missing", &jobs).is_err());
+
+        let budgets = Budgets::new();
+        let cache = AssetCache::new(&budgets, BudgetCategory::Assets, "pack");
+        let cached = pack.read_cached("big.bin", &jobs, &cache).unwrap();
+        assert_eq!(&**cached, &big[..]);
+        assert!(Arc::ptr_eq(
+            &cached,
+            &pack.read_cached("big.bin", &jobs, &cache).unwrap()
+        ));
+        pack.read_cached("raw.bin", &jobs, &cache).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(budgets.usage(BudgetCategory::Assets).used, big.len() as u64);
+
+        let preload = pack.preload(["big.bin", "raw.bin", "missing"], &jobs);
+        while !preload.is_done() {
+            std::thread::yield_now();
+        }
+        assert_eq!(preload.progress(), 1.0);
+        assert_eq!(&*preload.take("big.bin").unwrap().unwrap(), &big[..]);
+        assert!(preload.take("big.bin").is_none());
+        assert!(preload.take("missing").unwrap().is_err());
+
+        drop(pack);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn huge_counts_fail_cleanly() {
+        // Billions of entries claimed, in a table with room for none.
+        let mut header = MAGIC.to_vec();
+        header.extend(VERSION.to_le_bytes());
+        header.extend(u32::MAX.to_le_bytes());
+        header.extend((HEADER_SIZE as u64 + 4).to_le_bytes());
+        header.extend([0; 4]);
+
+        let path =
+            std::env::temp_dir().join(format!("crowbar-pack-huge-{}.pack", std::process::id()));
+        std::fs::write(&path, &header).unwrap();
+        assert!(Pack::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Rigid body physics through rapier, as a plugin.
+//!
+//! Entities with a [`RigidBody`], [`Collider`] and [`Transform`] get simulated. Physics steps at a fixed rate
+//! regardless of frame rate, and dynamic bodies write their pose back into [`Transform`] after stepping.
+//! Bodies ignore [`Parent`](crate::ecs::components::Parent), so keep them at the top level.
+
+use std::collections::HashMap;
+
+use glam::{Quat, Vec3};
+use hecs::{Entity, World};
+use rapier3d::{
+    na::{Quaternion, Translation3, UnitQuaternion},
+    prelude::*,
+};
+use winit::event_loop::ActiveEventLoop;
+
+use crate::{
+    app::WinitApp,
+    debug_draw::{DebugColor, DebugDraw},
+    ecs::components::{GlobalTransform, Transform},
+    plugin::Plugin,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyKind {
+    Dynamic,
+    Fixed,
+    /// Moved by writing its [`Transform`], pushes dynamic bodies out of the way.
+    Kinematic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RigidBody {
+    pub kind: BodyKind,
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    pub gravity_scale: f32,
+}
+
+impl RigidBody {
+    pub fn new(kind: BodyKind) -> RigidBody {
+        RigidBody {
+            kind,
+            linear_damping: 0.0,
+            angular_damping: 0.0,
+            gravity_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColliderShape {
+    Ball {
+        radius: f32,
+    },
+    Cuboid {
+        half_extents: Vec3,
+    },
+    /// Along the local Y axis.
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    pub friction: f32,
+    pub restitution: f32,
+    pub density: f32,
+    /// Sensors detect overlaps but don't collide.
+    pub sensor: bool,
+}
+
+impl Collider {
+    pub fn new(shape: ColliderShape) -> Collider {
+        Collider {
+            shape,
+            friction: 0.5,
+            restitution: 0.0,
+            density: 1.0,
+            sensor: false,
+        }
+    }
+}
+
+/// Added by the plugin once an entity has a body in the simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicsHandle(pub RigidBodyHandle);
+
+const COLLIDER_COLOR: DebugColor = DebugColor::rgb(0.2, 1.0, 0.4);
+const SENSOR_COLOR: DebugColor = DebugColor::rgb(1.0, 0.8, 0.1);
+
+pub struct PhysicsPlugin {
+    pub gravity: Vec3,
+    /// Seconds per physics step.
+    pub timestep: f32,
+    /// Steps per frame before we give up catching up, so a hitch doesn't snowball.
+    pub max_substeps: u32,
+    /// Draw collider wireframes through debug draw, turning the `debug_draw` cvar on at init.
+    pub draw_colliders: bool,
+    accumulator: f32,
+
+    pipeline: PhysicsPipeline,
+    params: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    entities: HashMap<RigidBodyHandle, Entity>,
+}
+
+impl PhysicsPlugin {
+    pub fn new() -> PhysicsPlugin {
+        PhysicsPlugin {
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            timestep: 1.0 / 60.0,
+            max_substeps: 4,
+            draw_colliders: false,
+            accumulator: 0.0,
+            pipeline: PhysicsPipeline::new(),
+            params: IntegrationParameters::default(),
+            islands: IslandManager::new(),
+            broad_phase: DefaultBroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
+            entities: HashMap::new(),
+        }
+    }
+
+    pub fn bodies(&self) -> &RigidBodySet {
+        &self.bodies
+    }
+
+    pub fn bodies_mut(&mut self) -> &mut RigidBodySet {
+        &mut self.bodies
+    }
+
+    /// Add bodies for new entities, and drop bodies whose entity is gone.
+    fn sync_bodies(&mut self, world: &mut World) {
+        let added: Vec<(Entity, RigidBody, Collider, Transform)> = world
+            .query::<(&RigidBody, &Collider, &Transform)>()
+            .without::<&PhysicsHandle>()
+            .iter()
+            .map(|(e, (b, c, t))| (e, *b, *c, *t))
+            .collect();
+
+        for (entity, body, collider, transform) in added {
+            let builder = match body.kind {
+                BodyKind::Dynamic => RigidBodyBuilder::dynamic(),
+                BodyKind::Fixed => RigidBodyBuilder::fixed(),
+                BodyKind::Kinematic => RigidBodyBuilder::kinematic_position_based(),
+            };
+
+            let handle = self.bodies.insert(
+                builder
+                    .position(to_isometry(&transform))
+                    .linear_damping(body.linear_damping)
+                    .angular_damping(body.angular_damping)
+                    .gravity_scale(body.gravity_scale)
+                    .user_data(entity.to_bits().get() as u128)
+                    .build(),
+            );
+
+            let shape = match collider.shape {
+                ColliderShape::Ball { radius } => ColliderBuilder::ball(radius),
+                ColliderShape::Cuboid { half_extents: h } => ColliderBuilder::cuboid(h.x, h.y, h.z),
+                ColliderShape::Capsule {
+                    half_height,
+                    radius,
+                } => ColliderBuilder::capsule_y(half_height, radius),
+            };
+
+            self.colliders.insert_with_parent(
+                shape
+                    .friction(collider.friction)
+                    .restitution(collider.restitution)
+                    .density(collider.density)
+                    .sensor(collider.sensor)
+                    .build(),
+                handle,
+                &mut self.bodies,
+            );
+
+            self.entities.insert(handle, entity);
+            world.insert_one(entity, PhysicsHandle(handle)).unwrap();
+        }
+
+        let dead: Vec<RigidBodyHandle> = self
+            .entities
+            .iter()
+            .filter(|(_, e)| !world.contains(**e) || world.get::<&PhysicsHandle>(**e).is_err())
+            .map(|(h, _)| *h)
+            .collect();
+
+        for handle in dead {
+            self.entities.remove(&handle);
+            self.bodies.remove(
+                handle,
+                &mut self.islands,
+                &mut self.colliders,
+                &mut self.impulse_joints,
+                &mut self.multibody_joints,
+                true,
+            );
+        }
+    }
+
+    fn step(&mut self) {
+        self.params.dt = self.timestep;
+        let gravity = vector![self.gravity.x, self.gravity.y, self.gravity.z];
+
+        self.pipeline.step(
+            &gravity,
+            &self.params,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            Some(&mut self.query_pipeline),
+            &(),
+            &(),
+        );
+    }
+
+    fn draw(world: &World, debug: &mut DebugDraw) {
+        for (_, (collider, g)) in world.query::<(&Collider, &GlobalTransform)>().iter() {
+            let color = match collider.sensor {
+                true => SENSOR_COLOR,
+                false => COLLIDER_COLOR,
+            };
+
+            match collider.shape {
+                ColliderShape::Ball { radius } => debug.sphere(g.0, radius, color),
+                ColliderShape::Cuboid { half_extents } => debug.cuboid(g.0, half_extents, color),
+                ColliderShape::Capsule {
+                    half_height,
+                    radius,
+                } => debug.capsule(g.0, half_height, radius, color),
+            }
+        }
+    }
+}
+
+impl Default for PhysicsPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_isometry(t: &Transform) -> Isometry<Real> {
+    let q = t.rotation;
+    Isometry::from_parts(
+        Translation3::new(t.translation.x, t.translation.y, t.translation.z),
+        UnitQuaternion::from_quaternion(Quaternion::new(q.w, q.x, q.y, q.z)),
+    )
+}
+
+fn write_back(iso: &Isometry<Real>, t: &mut Transform) {
+    let v = iso.translation.vector;
+    let q = iso.rotation.coords;
+    t.translation = Vec3::new(v.x, v.y, v.z);
+    t.rotation = Quat::from_xyzw(q.x, q.y, q.z, q.w);
+}
+
+impl Plugin for PhysicsPlugin {
+    fn name(&self) -> &'static str {
+        "physics"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, _event_loop: &ActiveEventLoop) {
+        self.sync_bodies(app.world_mut());
+        if self.draw_colliders {
+            let debug_draw = app.engine_cvars().debug_draw;
+            app.cvars_mut().set(debug_draw, true);
+        }
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        let world = app.world_mut();
+        self.sync_bodies(world);
+
+        for (_, (handle, t)) in world.query_mut::<(&PhysicsHandle, &Transform)>() {
+            if let Some(body) = self.bodies.get_mut(handle.0)
+                && body.is_kinematic()
+            {
+                body.set_next_kinematic_position(to_isometry(t));
+            }
+        }
+
+        self.accumulator += app.frame_context().delta;
+        let mut steps = 0;
+        while self.accumulator >= self.timestep && steps < self.max_substeps {
+            self.step();
+            self.accumulator -= self.timestep;
+            steps += 1;
+        }
+
+        if steps == self.max_substeps {
+            // Too far behind to catch up, drop the backlog rather than spiral.
+            self.accumulator = self.accumulator.min(self.timestep);
+        }
+
+        for (_, (handle, t)) in app
+            .world_mut()
+            .query_mut::<(&PhysicsHandle, &mut Transform)>()
+        {
+            if let Some(body) = self.bodies.get(handle.0)
+                && body.is_dynamic()
+            {
+                write_back(body.position(), t);
+            }
+        }
+    }
+
+    fn post_frame(&mut self, app: &mut WinitApp) {
+        if self.draw_colliders {
+            let (world, debug) = app.world_and_debug_draw();
+            Self::draw(world, debug);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Picking entities under the cursor, by casting rays from the active camera.
+
+use glam::{Affine3A, Mat4, Vec2, Vec3};
+use hecs::{Entity, World};
+
+use crate::{
+    ecs::{
+        components::{Camera, GlobalTransform, MeshRenderer, Projection},
+        spatial::SpatialIndex,
+    },
+    math::{self, NDC_FAR, NDC_NEAR, Ray, bounds::Obb},
+};
+
+/// The camera the world is viewed through: the active one that renders last.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewCamera {
+    pub entity: Entity,
+    pub world: Affine3A,
+    pub projection: Projection,
+}
+
+impl ViewCamera {
+    pub fn find(world: &World) -> Option<ViewCamera> {
+        world
+            .query::<(&Camera, &GlobalTransform)>()
+            .iter()
+            .filter(|(_, (c, _))| c.active)
+            .max_by_key(|(_, (c, _))| c.order)
+            .map(|(entity, (c, g))| ViewCamera {
+                entity,
+                world: g.0,
+                projection: c.projection,
+            })
+    }
+
+    pub fn view_proj(&self, viewport: Vec2) -> Mat4 {
+        return self.projection.matrix(viewport.x / viewport.y) * math::view_matrix(&self.world);
+    }
+
+    /// World space to viewport pixels. `None` if the point is behind the camera.
+    pub fn project(&self, point: Vec3, viewport: Vec2) -> Option<Vec2> {
+        let clip = self.view_proj(viewport) * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let ndc = clip.truncate() / clip.w;
+        return Some(math::ndc_to_viewport(ndc.truncate(), viewport));
+    }
+
+    /// The ray through a point in the viewport, in pixels from the top left.
+    pub fn ray(&self, cursor: Vec2, viewport: Vec2) -> Ray {
+        let inv = self.view_proj(viewport).inverse();
+        let ndc = math::viewport_to_ndc(cursor, viewport);
+
+        let near = inv.project_point3(ndc.extend(NDC_NEAR));
+        let far = inv.project_point3(ndc.extend(NDC_FAR));
+        return Ray {
+            origin: near,
+            dir: (far - near).normalize(),
+        };
+    }
+}
+
+/// The nearest rendered entity along the ray.
+/// Candidates come from `spatial`, and get checked against the world as it is now.
+pub fn pick(world: &World, spatial: &SpatialIndex, ray: Ray) -> Option<Entity> {
+    let hit = spatial.ray_cast(&ray, f32::INFINITY, |e| {
+        let mr = world.get::<&MeshRenderer>(e).ok()?;
+        let g = world.get::<&GlobalTransform>(e).ok()?;
+        ray.obb(&Obb::from_aabb(&mr.local_bounds(), &g.0))
+    });
+    return hit.map(|(e, _)| e);
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec2, Vec3};
+    use hecs::World;
+
+    use super::{ViewCamera, pick};
+    use crate::ecs::{
+        components::{GlobalTransform, MaterialId, MeshId, MeshRenderer, Projection},
+        spatial::SpatialIndex,
+    };
+
+    #[test]
+    pub fn picks_nearest() {
+        let mut world = World::new();
+        let mesh = MeshRenderer {
+            mesh: MeshId(0),
+            material: MaterialId(0),
+            visible: true,
+            cast_shadows: false,
+        };
+        let at = |z: f32| GlobalTransform(Affine3A::from_translation(Vec3::new(0.0, 0.0, z)));
+
+        let far = world.spawn((mesh, at(-20.0)));
+        let near = world.spawn((mesh, at(-10.0)));
+        world.spawn((
+            mesh,
+            GlobalTransform(Affine3A::from_translation(Vec3::X * 5.0)),
+        ));
+
+        let camera = ViewCamera {
+            entity: far,
+            world: Affine3A::IDENTITY,
+            projection: Projection::Perspective {
+                fov_y: 1.0,
+                near: 0.1,
+                far: 100.0,
+            },
+        };
+        let viewport = Vec2::new(800.0, 600.0);
+        let mut spatial = SpatialIndex::default();
+        spatial.sync(&world);
+
+        let center = camera.ray(viewport / 2.0, viewport);
+        assert!(center.dir.abs_diff_eq(Vec3::NEG_Z, 1e-4));
+        assert_eq!(pick(&world, &spatial, center), Some(near));
+
+        let projected = camera
+            .project(Vec3::new(0.0, 0.0, -10.0), viewport)
+            .unwrap();
+        assert!(projected.abs_diff_eq(viewport / 2.0, 1e-3));
+
+        assert_eq!(
+            pick(&world, &spatial, camera.ray(Vec2::ZERO, viewport)),
+            None
+        );
+
+        world.despawn(near).unwrap();
+        spatial.sync(&world);
+        assert_eq!(spatial.len(), 2);
+        assert_eq!(pick(&world, &spatial, center), Some(far));
+    }
+}
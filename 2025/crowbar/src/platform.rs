@@ -0,0 +1,179 @@
+//! Platform specific odds and ends that winit doesn't cover for us.
+
+use std::io;
+
+use winit::window::{Theme, Window};
+
+use crate::app::info::AppInfo;
+
+#[cfg(not(wayland))]
+use crate::render::pacing::PresentedFrame;
+
+#[cfg(target_os = "android")]
+pub mod android;
+#[cfg(target_os = "macos")]
+pub mod macos;
+#[cfg(wayland)]
+pub mod wayland;
+#[cfg(target_os = "windows")]
+pub mod windows;
+
+#[cfg(wayland)]
+pub use wayland::PresentationFeedback;
+
+/// When frames actually reach the screen, on platforms that say. Only Wayland does so far.
+#[cfg(not(wayland))]
+pub struct PresentationFeedback(());
+
+#[cfg(not(wayland))]
+impl PresentationFeedback {
+    pub fn new(_window: &Window) -> Option<PresentationFeedback> {
+        None
+    }
+
+    pub fn request(&mut self) {}
+
+    pub fn poll(&mut self) -> (Vec<PresentedFrame>, u32) {
+        (Vec::new(), 0)
+    }
+}
+
+/// Who draws the window's titlebar and borders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Decorations {
+    /// The compositor/window manager's, falling back to our own on Wayland compositors that won't (GNOME).
+    #[default]
+    PreferServer,
+    /// Our own, to match the app's look.
+    /// todo: winit always asks Wayland compositors for server side decorations if they'll do them,
+    /// with no way to ask for client side ones, so for now this behaves like `PreferServer`.
+    PreferClient,
+    /// None at all, for borderless windows.
+    None,
+}
+
+/// Progress state shown on a window's taskbar/dock icon.
+/// Progress values are fractions in `0.0..=1.0`, and are clamped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TaskbarProgress {
+    /// No progress indicator at all.
+    #[default]
+    None,
+    /// Busy, but with no idea how long for.
+    Indeterminate,
+    Normal(f32),
+    Paused(f32),
+    Error(f32),
+}
+
+impl TaskbarProgress {
+    /// The progress fraction, if this state has one.
+    pub fn fraction(&self) -> Option<f32> {
+        match *self {
+            TaskbarProgress::Normal(f) | TaskbarProgress::Paused(f) | TaskbarProgress::Error(f) => {
+                Some(f.clamp(0.0, 1.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Push the given progress state to the platform's taskbar/dock for this window.
+/// Windows shows it on the window's taskbar button, macOS on the app's dock tile whichever window it's for.
+/// Everywhere else this does nothing and returns false.
+/// Returns false if the platform doesn't support it (or it failed), which callers are free to ignore.
+pub fn set_taskbar_progress(window: &Window, progress: TaskbarProgress) -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        return windows::set_taskbar_progress(window, progress);
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let _ = window;
+        return macos::set_dock_progress(progress);
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        let _ = (window, progress);
+        return false;
+    }
+}
+
+/// Tell the platform who the app is, ahead of its first window.
+/// On Windows this is what groups its windows on the taskbar and owns its jump list,
+/// on macOS it names the menu bar.
+pub fn register_app(info: &AppInfo) {
+    #[cfg(target_os = "windows")]
+    {
+        windows::register_app(info);
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        macos::install_menu(info);
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        let _ = info;
+    }
+}
+
+/// Whether the platform asked the app to quit since this was last called, from outside any window, like the
+/// macOS menu bar's Quit.
+pub fn take_quit_request() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        return macos::take_quit_request();
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        return false;
+    }
+}
+
+/// Platform touches on a freshly created window. `theme` is the one the app asked for, if any.
+pub fn window_created(window: &Window, theme: Option<Theme>) {
+    #[cfg(target_os = "windows")]
+    {
+        windows::check_dpi(window);
+        if let Some(theme) = theme {
+            windows::set_dark_titlebar(window, theme == Theme::Dark);
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = (window, theme);
+    }
+}
+
+/// Keep platform state that follows the window's size and scale in step, after a resize or scale change.
+pub fn window_resized(window: &Window) {
+    #[cfg(target_os = "macos")]
+    {
+        macos::sync_metal_layer(window);
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = window;
+    }
+}
+
+/// Read a file shipped with the game: out of the APK on Android, relative to the working directory elsewhere.
+// todo: this is all the VFS there is. Packs and loose files should mount over it once there's an asset loader.
+pub fn read_asset(path: &str) -> io::Result<Vec<u8>> {
+    #[cfg(target_os = "android")]
+    {
+        return android::read_asset(path);
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        return std::fs::read(path);
+    }
+}
@@ -0,0 +1,69 @@
+//! Android: the entry point the activity starts us through, and getting at what's packaged in the APK.
+//!
+//! Build with cargo-apk or xbuild, which package the cdylib and the `assets/` directory.
+
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, ErrorKind, Read},
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use winit::{
+    event_loop::EventLoop,
+    platform::android::{EventLoopBuilderExtAndroid, activity::AndroidApp},
+};
+
+use crate::{app::info::AppInfo, cli::Args};
+
+static APP: OnceLock<AndroidApp> = OnceLock::new();
+
+/// Called by android-activity, on its own thread, once the activity is created.
+#[unsafe(no_mangle)]
+fn android_main(app: AndroidApp) {
+    let _ = APP.set(app.clone());
+    crate::init();
+
+    let event_loop = EventLoop::builder()
+        .with_android_app(app)
+        .build()
+        .expect("Event loop creation MUST succeed!");
+    // No command line here, so everything starts at its defaults. Cvars still come from the config file.
+    crate::run(event_loop, &Args::default(), AppInfo::default());
+}
+
+fn open(path: &str) -> io::Result<winit::platform::android::activity::ndk::asset::Asset> {
+    let app = APP
+        .get()
+        .ok_or_else(|| io::Error::other("The activity hasn't started"))?;
+    let name = CString::new(path).map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+
+    return app
+        .asset_manager()
+        .open(&name)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{path} isn't in the APK")));
+}
+
+/// Read an asset out of the APK.
+pub fn read_asset(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open(path)?.read_to_end(&mut data)?;
+    return Ok(data);
+}
+
+/// The APK itself, and where in it an asset is, for mapping it.
+/// Only works for assets stored uncompressed, which is how packs should go in.
+pub fn asset_file(path: &str) -> io::Result<(File, u64, usize)> {
+    let asset = open(path)?;
+    let opened = asset
+        .open_file_descriptor()
+        .map_err(|_| io::Error::other(format!("{path} is compressed in the APK")))?;
+
+    return Ok((File::from(opened.fd), opened.offset as u64, opened.size));
+}
+
+/// The app's private storage, where config and logs go.
+pub fn data_dir() -> Option<PathBuf> {
+    APP.get()?.internal_data_path()
+}
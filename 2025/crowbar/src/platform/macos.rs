@@ -0,0 +1,295 @@
+//! macOS: the CAMetalLayer MoltenVK presents through, and a menu bar that names the app.
+
+use std::{
+    cell::OnceCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use objc2::{
+    DefinedClass, MainThreadMarker, MainThreadOnly, define_class, msg_send,
+    rc::Retained,
+    runtime::{AnyObject, NSObject, Sel},
+    sel,
+};
+use objc2_app_kit::{
+    NSAboutPanelOptionApplicationName, NSAboutPanelOptionApplicationVersion,
+    NSAboutPanelOptionVersion, NSApplication, NSEventModifierFlags, NSImageView, NSMenu,
+    NSMenuItem, NSProgressIndicator, NSProgressIndicatorStyle, NSView,
+};
+use objc2_core_foundation::CGSize;
+use objc2_foundation::{NSDictionary, NSPoint, NSRect, NSSize, NSString};
+use objc2_quartz_core::CAMetalLayer;
+use winit::{
+    raw_window_handle::{HasWindowHandle, RawWindowHandle},
+    window::Window,
+};
+
+use super::TaskbarProgress;
+use crate::{app::info::AppInfo, consts::BUILD};
+
+/// How the CAMetalLayer should present.
+#[derive(Clone, Copy, Debug)]
+pub struct MetalLayerConfig {
+    /// Wait for vblank to present. Off tears, but doesn't wait.
+    pub display_sync: bool,
+    /// 2 or 3. 3 keeps the GPU busier, at the cost of a frame of latency.
+    pub drawable_count: u32,
+}
+
+impl Default for MetalLayerConfig {
+    fn default() -> Self {
+        MetalLayerConfig {
+            display_sync: true,
+            drawable_count: 3,
+        }
+    }
+}
+
+fn view(window: &Window) -> Option<&NSView> {
+    let RawWindowHandle::AppKit(handle) = window.window_handle().ok()?.as_raw() else {
+        return None;
+    };
+    // SAFETY: The handle's view is an NSView, alive as long as the window we're borrowing.
+    return Some(unsafe { handle.ns_view.cast().as_ref() });
+}
+
+fn existing_metal_layer(window: &Window) -> Option<Retained<CAMetalLayer>> {
+    return view(window)?.layer()?.downcast::<CAMetalLayer>().ok();
+}
+
+/// The window's CAMetalLayer, giving its view one if it doesn't have one yet.
+/// The Vulkan surface should be made from this with `VK_EXT_metal_surface`.
+pub fn metal_layer(window: &Window) -> Option<Retained<CAMetalLayer>> {
+    if let Some(layer) = existing_metal_layer(window) {
+        return Some(layer);
+    }
+
+    let view = view(window)?;
+    let layer = CAMetalLayer::new();
+    view.setWantsLayer(true);
+    view.setLayer(Some(&**layer));
+    sync_metal_layer(window);
+    return Some(layer);
+}
+
+/// Apply `config` to the window's layer. MoltenVK sets both from the present mode and image count when it creates a
+/// swapchain, so this has to come after that to stick.
+pub fn configure_metal_layer(window: &Window, config: MetalLayerConfig) -> bool {
+    let Some(layer) = metal_layer(window) else {
+        return false;
+    };
+    layer.setDisplaySyncEnabled(config.display_sync);
+    layer.setMaximumDrawableCount(config.drawable_count.clamp(2, 3) as usize);
+    return true;
+}
+
+/// Match the layer to the window's backing scale and size. A layer we added starts at 1x, which gets upscaled
+/// blurrily on Retina displays, and MoltenVK takes the surface's extent from the layer's drawable size.
+pub(super) fn sync_metal_layer(window: &Window) {
+    let Some(layer) = existing_metal_layer(window) else {
+        return;
+    };
+    let size = window.inner_size();
+    layer.setContentsScale(window.scale_factor());
+    layer.setDrawableSize(CGSize::new(size.width as f64, size.height as f64));
+}
+
+/// The standard about panel, filled in from `info` rather than an Info.plist, which a bare binary doesn't have.
+pub fn show_about_panel(info: &AppInfo, mtm: MainThreadMarker) {
+    let (major, minor, patch) = info.version();
+    let name = NSString::from_str(info.name());
+    let version = NSString::from_str(&format!("{major}.{minor}.{patch}"));
+    let engine = NSString::from_str(&format!("crowbar {}", BUILD.version));
+    let values: [&AnyObject; 3] = [&name, &version, &engine];
+
+    // SAFETY: The keys are AppKit's own, and they all take strings.
+    unsafe {
+        let options = NSDictionary::from_slices(
+            &[
+                NSAboutPanelOptionApplicationName,
+                NSAboutPanelOptionApplicationVersion,
+                NSAboutPanelOptionVersion,
+            ],
+            &values,
+        );
+        NSApplication::sharedApplication(mtm).orderFrontStandardAboutPanelWithOptions(&options);
+    }
+}
+
+define_class!(
+    /// Handles the menu items AppKit doesn't handle itself.
+    #[unsafe(super(NSObject))]
+    #[thread_kind = MainThreadOnly]
+    #[name = "CrowbarMenuTarget"]
+    #[ivars = AppInfo]
+    struct MenuTarget;
+
+    impl MenuTarget {
+        #[unsafe(method(showAbout:))]
+        fn show_about(&self, _sender: Option<&AnyObject>) {
+            show_about_panel(self.ivars(), self.mtm());
+        }
+
+        #[unsafe(method(quit:))]
+        fn quit(&self, _sender: Option<&AnyObject>) {
+            QUIT_REQUESTED.store(true, Ordering::Relaxed);
+        }
+    }
+);
+
+impl MenuTarget {
+    fn new(info: AppInfo, mtm: MainThreadMarker) -> Retained<MenuTarget> {
+        let this = MenuTarget::alloc(mtm).set_ivars(info);
+        // SAFETY: NSObject's plain init.
+        return unsafe { msg_send![super(this), init] };
+    }
+}
+
+/// Set by the Quit menu item, for the app to pick up in its event loop. `terminate:` would end the process from
+/// inside AppKit, without the app shutting down or saving its config.
+static QUIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Quit was picked from the menu since this was last asked.
+pub(super) fn take_quit_request() -> bool {
+    return QUIT_REQUESTED.swap(false, Ordering::Relaxed);
+}
+
+thread_local! {
+    // Menu items only hold their target weakly.
+    static MENU_TARGET: OnceCell<Retained<MenuTarget>> = const { OnceCell::new() };
+}
+
+fn item(mtm: MainThreadMarker, title: &str, action: Sel, key: &str) -> Retained<NSMenuItem> {
+    // SAFETY: The actions are standard responder methods, or MenuTarget's.
+    unsafe {
+        return NSMenuItem::initWithTitle_action_keyEquivalent(
+            NSMenuItem::alloc(mtm),
+            &NSString::from_str(title),
+            Some(action),
+            &NSString::from_str(key),
+        );
+    }
+}
+
+/// Replace winit's default menu bar with one that uses the app's name, and an About that says which version it is.
+pub(super) fn install_menu(info: &AppInfo) {
+    let Some(mtm) = MainThreadMarker::new() else {
+        log::warn!("The menu bar can only be set up from the main thread.");
+        return;
+    };
+    let name = info.name();
+    let target = MenuTarget::new(info.clone(), mtm);
+
+    let app_menu = NSMenu::new(mtm);
+    let about = item(mtm, &format!("About {name}"), sel!(showAbout:), "");
+    // SAFETY: The target is kept alive in MENU_TARGET.
+    unsafe { about.setTarget(Some(&target)) };
+    app_menu.addItem(&about);
+    app_menu.addItem(&NSMenuItem::separatorItem(mtm));
+    app_menu.addItem(&item(mtm, &format!("Hide {name}"), sel!(hide:), "h"));
+    let hide_others = item(mtm, "Hide Others", sel!(hideOtherApplications:), "h");
+    hide_others
+        .setKeyEquivalentModifierMask(NSEventModifierFlags::Option | NSEventModifierFlags::Command);
+    app_menu.addItem(&hide_others);
+    app_menu.addItem(&item(mtm, "Show All", sel!(unhideAllApplications:), ""));
+    app_menu.addItem(&NSMenuItem::separatorItem(mtm));
+    let quit = item(mtm, &format!("Quit {name}"), sel!(quit:), "q");
+    // SAFETY: As for about.
+    unsafe { quit.setTarget(Some(&target)) };
+    app_menu.addItem(&quit);
+
+    let window_menu = NSMenu::new(mtm);
+    window_menu.setTitle(&NSString::from_str("Window"));
+    window_menu.addItem(&item(mtm, "Minimize", sel!(performMiniaturize:), "m"));
+    window_menu.addItem(&item(mtm, "Zoom", sel!(performZoom:), ""));
+    let full_screen = item(mtm, "Enter Full Screen", sel!(toggleFullScreen:), "f");
+    full_screen.setKeyEquivalentModifierMask(
+        NSEventModifierFlags::Control | NSEventModifierFlags::Command,
+    );
+    window_menu.addItem(&full_screen);
+
+    let menu_bar = NSMenu::new(mtm);
+    for submenu in [&app_menu, &window_menu] {
+        let holder = NSMenuItem::new(mtm);
+        holder.setSubmenu(Some(submenu));
+        menu_bar.addItem(&holder);
+    }
+
+    let app = NSApplication::sharedApplication(mtm);
+    app.setMainMenu(Some(&menu_bar));
+    // AppKit lists the open windows in this one.
+    app.setWindowsMenu(Some(&window_menu));
+    MENU_TARGET.with(|cell| {
+        let _ = cell.set(target);
+    });
+}
+
+/// The bar's height, in the dock tile's points.
+const DOCK_BAR_HEIGHT: f64 = 12.0;
+
+thread_local! {
+    // The dock tile's content view while it's showing progress, the app icon with a bar over it.
+    static DOCK_PROGRESS: OnceCell<(Retained<NSImageView>, Retained<NSProgressIndicator>)> =
+        const { OnceCell::new() };
+}
+
+fn dock_progress_view(
+    app: &NSApplication,
+    size: NSSize,
+    mtm: MainThreadMarker,
+) -> Option<(Retained<NSImageView>, Retained<NSProgressIndicator>)> {
+    let icon = NSImageView::imageViewWithImage(&*app.applicationIconImage()?, mtm);
+    icon.setFrame(NSRect::new(NSPoint::new(0.0, 0.0), size));
+    let bar = NSProgressIndicator::initWithFrame(
+        NSProgressIndicator::alloc(mtm),
+        NSRect::new(
+            NSPoint::new(0.0, 0.0),
+            NSSize::new(size.width, DOCK_BAR_HEIGHT),
+        ),
+    );
+    bar.setStyle(NSProgressIndicatorStyle::Bar);
+    bar.setMinValue(0.0);
+    bar.setMaxValue(1.0);
+    icon.addSubview(&bar);
+    return Some((icon, bar));
+}
+
+/// Draw `progress` over the app's dock tile, which is the app's rather than any one window's. The dock has no
+/// paused or error states, so paused shows as normal and errors put a badge on the tile as well.
+pub(super) fn set_dock_progress(progress: TaskbarProgress) -> bool {
+    let Some(mtm) = MainThreadMarker::new() else {
+        return false;
+    };
+    let app = NSApplication::sharedApplication(mtm);
+    let tile = app.dockTile();
+
+    if let TaskbarProgress::None = progress {
+        // Back to the plain icon.
+        tile.setContentView(None);
+        tile.setBadgeLabel(None);
+        tile.display();
+        return true;
+    }
+
+    return DOCK_PROGRESS.with(|cell| {
+        let (icon, bar) = match cell.get() {
+            Some(view) => view,
+            None => {
+                let Some(view) = dock_progress_view(&app, tile.size(), mtm) else {
+                    return false;
+                };
+                cell.get_or_init(|| view)
+            }
+        };
+        bar.setIndeterminate(progress.fraction().is_none());
+        if let Some(f) = progress.fraction() {
+            bar.setDoubleValue(f as f64);
+        }
+        let badge = matches!(progress, TaskbarProgress::Error(_)).then(|| NSString::from_str("!"));
+        tile.setBadgeLabel(badge.as_deref());
+        tile.setContentView(Some(icon));
+        // The tile only redraws its content view when asked.
+        tile.display();
+        return true;
+    });
+}
@@ -0,0 +1,173 @@
+//! Wayland specifics winit doesn't cover: presentation feedback through `wp_presentation`.
+//!
+//! This talks to winit's display connection on a queue of its own, so it never gets in the way of
+//! winit's events. winit reads the socket, and we pick up what landed on our queue each frame.
+
+use std::time::Duration;
+
+use wayland_backend::client::{Backend, ObjectId};
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum,
+    globals::{GlobalListContents, registry_queue_init},
+    protocol::{wl_registry::WlRegistry, wl_surface::WlSurface},
+};
+use wayland_protocols::wp::presentation_time::client::{
+    wp_presentation::{self, WpPresentation},
+    wp_presentation_feedback::{self, Kind, WpPresentationFeedback},
+};
+use winit::{
+    raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle},
+    window::Window,
+};
+
+use crate::render::pacing::PresentedFrame;
+
+/// What came back since the last poll.
+#[derive(Default)]
+struct Received {
+    presented: Vec<PresentedFrame>,
+    discarded: u32,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for Received {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpPresentation, ()> for Received {
+    fn event(
+        _: &mut Self,
+        _: &WpPresentation,
+        event: wp_presentation::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Every compositor around uses CLOCK_MONOTONIC, which is what Instant uses too.
+        if let wp_presentation::Event::ClockId { clk_id } = event
+            && clk_id != 1
+        {
+            log::warn!("Wayland presentation clock is {clk_id}, not CLOCK_MONOTONIC");
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, ()> for Received {
+    fn event(
+        received: &mut Self,
+        _: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => {
+                let secs = (tv_sec_hi as u64) << 32 | tv_sec_lo as u64;
+                let flags = match flags {
+                    WEnum::Value(flags) => flags,
+                    WEnum::Unknown(_) => Kind::empty(),
+                };
+                received.presented.push(PresentedFrame {
+                    time: Duration::new(secs, tv_nsec),
+                    refresh: (refresh != 0).then(|| Duration::from_nanos(refresh as u64)),
+                    seq: (seq_hi as u64) << 32 | seq_lo as u64,
+                    vsync: flags.contains(Kind::Vsync),
+                    zero_copy: flags.contains(Kind::ZeroCopy),
+                });
+            }
+            wp_presentation_feedback::Event::Discarded => received.discarded += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Presentation feedback for one window.
+pub struct PresentationFeedback {
+    connection: Connection,
+    queue: EventQueue<Received>,
+    presentation: WpPresentation,
+    surface: WlSurface,
+    received: Received,
+}
+
+impl PresentationFeedback {
+    /// `None` if the window isn't on Wayland, or the compositor doesn't do presentation-time.
+    pub fn new(window: &Window) -> Option<PresentationFeedback> {
+        let RawDisplayHandle::Wayland(display) = window.display_handle().ok()?.as_raw() else {
+            return None;
+        };
+        let RawWindowHandle::Wayland(surface) = window.window_handle().ok()?.as_raw() else {
+            return None;
+        };
+
+        // SAFETY: The display and surface belong to the window, which outlives us, as the app
+        // keeps the two together in its window state, and we're declared first so dropped first.
+        let (connection, surface) = unsafe {
+            let connection = Connection::from_backend(Backend::from_foreign_display(
+                display.display.as_ptr().cast(),
+            ));
+            let id =
+                ObjectId::from_ptr(WlSurface::interface(), surface.surface.as_ptr().cast()).ok()?;
+            (
+                connection.clone(),
+                WlSurface::from_id(&connection, id).ok()?,
+            )
+        };
+
+        let (globals, queue) = registry_queue_init::<Received>(&connection).ok()?;
+        let presentation = match globals.bind::<WpPresentation, _, _>(&queue.handle(), 1..=1, ()) {
+            Ok(p) => p,
+            Err(e) => {
+                log::info!("No Wayland presentation feedback: {e}");
+                return None;
+            }
+        };
+
+        return Some(PresentationFeedback {
+            connection,
+            queue,
+            presentation,
+            surface,
+            received: Received::default(),
+        });
+    }
+
+    /// Ask for feedback on the next frame. Call each frame before presenting it.
+    pub fn request(&mut self) {
+        self.presentation
+            .feedback(&self.surface, &self.queue.handle(), ());
+        let _ = self.connection.flush();
+    }
+
+    /// Frames presented since the last poll, and how many were discarded.
+    pub fn poll(&mut self) -> (Vec<PresentedFrame>, u32) {
+        if let Err(e) = self.queue.dispatch_pending(&mut self.received) {
+            log::warn!("Wayland presentation feedback failed: {e}");
+        }
+        let received = std::mem::take(&mut self.received);
+        return (received.presented, received.discarded);
+    }
+}
+
+impl Drop for PresentationFeedback {
+    fn drop(&mut self) {
+        self.presentation.destroy();
+        let _ = self.connection.flush();
+    }
+}
@@ -0,0 +1,244 @@
+//! Windows desktop integration: the taskbar, titlebar theming, DPI awareness, and the jump list.
+
+use std::{cell::OnceCell, iter, os::windows::ffi::OsStrExt, path::Path};
+
+use windows::{
+    Win32::{
+        Foundation::HWND,
+        Graphics::Dwm::{DWMWA_USE_IMMERSIVE_DARK_MODE, DwmSetWindowAttribute},
+        System::Com::{
+            CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED, CoCreateInstance, CoInitializeEx,
+        },
+        UI::{
+            HiDpi::{
+                AreDpiAwarenessContextsEqual, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+                DPI_AWARENESS_PER_MONITOR_AWARE, DPI_AWARENESS_SYSTEM_AWARE,
+                GetAwarenessFromDpiAwarenessContext, GetDpiForWindow, GetThreadDpiAwarenessContext,
+            },
+            Shell::{
+                Common::IObjectArray, DestinationList, ICustomDestinationList, ITaskbarList3,
+                KDC_RECENT, SHARD_PATHW, SHAddToRecentDocs,
+                SetCurrentProcessExplicitAppUserModelID, TBPF_ERROR, TBPF_INDETERMINATE,
+                TBPF_NOPROGRESS, TBPF_NORMAL, TBPF_PAUSED, TaskbarList,
+            },
+        },
+    },
+    core::PCWSTR,
+};
+use winit::{
+    raw_window_handle::{HasWindowHandle, RawWindowHandle},
+    window::Window,
+};
+
+use super::TaskbarProgress;
+use crate::app::info::AppInfo;
+
+/// Resolution we hand the taskbar, it only takes integers.
+const PROGRESS_STEPS: u64 = 10_000;
+
+thread_local! {
+    // The taskbar interface is apartment threaded, so keep one per thread (in practice, the event loop thread).
+    static TASKBAR: OnceCell<Option<ITaskbarList3>> = const { OnceCell::new() };
+}
+
+fn hwnd(window: &Window) -> Option<HWND> {
+    match window.window_handle().ok()?.as_raw() {
+        RawWindowHandle::Win32(h) => Some(HWND(h.hwnd.get() as *mut _)),
+        _ => None,
+    }
+}
+
+fn wide(s: impl AsRef<std::ffi::OsStr>) -> Vec<u16> {
+    s.as_ref().encode_wide().chain(iter::once(0)).collect()
+}
+
+fn init_com() {
+    // SAFETY: Plain COM setup. Re-initializing on a thread winit already initialized just returns S_FALSE.
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+    }
+}
+
+fn create_taskbar() -> Option<ITaskbarList3> {
+    init_com();
+    // SAFETY: Plain COM object creation.
+    unsafe {
+        let taskbar: ITaskbarList3 =
+            CoCreateInstance(&TaskbarList, None, CLSCTX_INPROC_SERVER).ok()?;
+        taskbar.HrInit().ok()?;
+        return Some(taskbar);
+    }
+}
+
+pub(super) fn set_taskbar_progress(window: &Window, progress: TaskbarProgress) -> bool {
+    let Some(hwnd) = hwnd(window) else {
+        return false;
+    };
+
+    TASKBAR.with(|cell| {
+        let Some(taskbar) = cell.get_or_init(create_taskbar) else {
+            return false;
+        };
+
+        let state = match progress {
+            TaskbarProgress::None => TBPF_NOPROGRESS,
+            TaskbarProgress::Indeterminate => TBPF_INDETERMINATE,
+            TaskbarProgress::Normal(_) => TBPF_NORMAL,
+            TaskbarProgress::Paused(_) => TBPF_PAUSED,
+            TaskbarProgress::Error(_) => TBPF_ERROR,
+        };
+
+        // SAFETY: hwnd is live for as long as the winit window is, which we're borrowing.
+        unsafe {
+            if taskbar.SetProgressState(hwnd, state).is_err() {
+                return false;
+            }
+
+            if let Some(f) = progress.fraction() {
+                let completed = (f as f64 * PROGRESS_STEPS as f64) as u64;
+                return taskbar
+                    .SetProgressValue(hwnd, completed, PROGRESS_STEPS)
+                    .is_ok();
+            }
+        }
+
+        return true;
+    })
+}
+
+/// Force the titlebar dark or light. winit follows the system theme, or the window's preferred one, through
+/// undocumented APIs; this sets the documented DWM attribute, which Windows 10 20H1 and later go by.
+pub fn set_dark_titlebar(window: &Window, dark: bool) -> bool {
+    let Some(hwnd) = hwnd(window) else {
+        return false;
+    };
+    let value = dark as i32;
+
+    // SAFETY: The attribute takes a BOOL, which is an i32, and hwnd lives as long as the window we're borrowing.
+    unsafe {
+        return DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_USE_IMMERSIVE_DARK_MODE,
+            (&value as *const i32).cast(),
+            size_of::<i32>() as u32,
+        )
+        .is_ok();
+    }
+}
+
+/// How the process handles monitors with different DPIs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DpiAwareness {
+    /// Windows draws us at 96 DPI and stretches the result, blurrily.
+    Unaware,
+    /// Windows stretches us on any monitor not at the primary monitor's DPI.
+    System,
+    /// Scales itself, but the titlebar and dialogs don't follow DPI changes.
+    PerMonitor,
+    /// Scales itself, properly. What winit asks for, unless a manifest got there first.
+    PerMonitorV2,
+}
+
+/// The event loop thread's DPI awareness, which is the process's unless something changed it per thread.
+pub fn dpi_awareness() -> DpiAwareness {
+    // SAFETY: Just queries.
+    unsafe {
+        let context = GetThreadDpiAwarenessContext();
+        if AreDpiAwarenessContextsEqual(context, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2)
+            .as_bool()
+        {
+            return DpiAwareness::PerMonitorV2;
+        }
+        return match GetAwarenessFromDpiAwarenessContext(context) {
+            DPI_AWARENESS_PER_MONITOR_AWARE => DpiAwareness::PerMonitor,
+            DPI_AWARENESS_SYSTEM_AWARE => DpiAwareness::System,
+            _ => DpiAwareness::Unaware,
+        };
+    }
+}
+
+/// Warn if a window won't be scaled right: the process isn't per-monitor v2 aware, say because a manifest or the
+/// host embedding us set it first, or winit's idea of the window's scale doesn't match Windows'.
+pub(super) fn check_dpi(window: &Window) {
+    let awareness = dpi_awareness();
+    if awareness != DpiAwareness::PerMonitorV2 {
+        log::warn!(
+            "DPI awareness is {awareness:?}, not PerMonitorV2. Expect blurry scaling on high DPI monitors."
+        );
+    }
+
+    let Some(hwnd) = hwnd(window) else {
+        return;
+    };
+    // SAFETY: hwnd lives as long as the window we're borrowing.
+    let dpi = unsafe { GetDpiForWindow(hwnd) };
+    let scale = dpi as f64 / 96.0;
+    if dpi != 0 && (scale - window.scale_factor()).abs() > 0.01 {
+        log::warn!(
+            "Window is at {dpi} DPI ({scale}x), but winit thinks it's at {}x.",
+            window.scale_factor()
+        );
+    }
+}
+
+/// The AppUserModelID Windows groups the app's windows and jump list under: `Organization.Name`, no spaces.
+pub fn app_id(info: &AppInfo) -> String {
+    let part = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect()
+    };
+    let mut id = match info.organization() {
+        Some(organization) => format!("{}.{}", part(organization), part(info.name())),
+        None => part(info.name()),
+    };
+    id.truncate(128);
+    return id;
+}
+
+/// Set the process's AppUserModelID. Has to happen before the first window is shown to take.
+pub(super) fn register_app(info: &AppInfo) {
+    let id = wide(app_id(info));
+    // SAFETY: id is nul terminated, and outlives the call.
+    if let Err(e) = unsafe { SetCurrentProcessExplicitAppUserModelID(PCWSTR(id.as_ptr())) } {
+        log::warn!("Couldn't set the AppUserModelID: {e}");
+    }
+}
+
+/// Add a file to the recent items in Explorer and the app's jump list, for tools with documents to open.
+/// The jump list only shows files of types registered to open with the app, see [`show_recent_in_jump_list`].
+pub fn add_recent_file(path: &Path) {
+    let Ok(path) = std::path::absolute(path) else {
+        return;
+    };
+    let path = wide(path);
+    // SAFETY: SHARD_PATHW takes a nul terminated wide string, which outlives the call.
+    unsafe {
+        SHAddToRecentDocs(SHARD_PATHW.0 as u32, Some(path.as_ptr().cast()));
+    }
+}
+
+fn commit_jump_list() -> windows::core::Result<()> {
+    init_com();
+    // SAFETY: Plain COM calls. The list goes under the process's AppUserModelID, see `register_app`.
+    unsafe {
+        let list: ICustomDestinationList =
+            CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)?;
+        let mut slots = 0;
+        // What the user removed from the list since last time. Recent files keep track of that themselves.
+        let _removed: IObjectArray = list.BeginList(&mut slots)?;
+        list.AppendKnownCategory(KDC_RECENT)?;
+        return list.CommitList();
+    }
+}
+
+/// Give the app's jump list a recent files category. Only needs doing once, the list persists.
+// todo: custom tasks (New, Open...), once there's a use for them. Those need the shell link's
+// title set through its property store.
+pub fn show_recent_in_jump_list() -> bool {
+    if let Err(e) = commit_jump_list() {
+        log::warn!("Couldn't set up the jump list: {e}");
+        return false;
+    }
+    return true;
+}
@@ -0,0 +1,91 @@
+//! Plugins, so subsystems can hook into the app without `WinitApp` knowing about every one of them.
+
+use winit::{event::WindowEvent, event_loop::ActiveEventLoop, window::WindowId};
+
+use crate::{
+    app::WinitApp,
+    render::device_lost::{DeviceLost, DeviceLostAction},
+};
+
+/// A subsystem that hooks into the app lifecycle. Every hook is optional.
+///
+/// Hooks get the whole app, minus the plugin list itself, which is taken out while plugins run.
+pub trait Plugin: 'static {
+    fn name(&self) -> &'static str;
+
+    /// Called once, after the event loop has started and the initial window exists.
+    fn init(&mut self, _app: &mut WinitApp, _event_loop: &ActiveEventLoop) {}
+
+    /// Called at the start of every frame, before the frame graph runs.
+    fn pre_frame(&mut self, _app: &mut WinitApp) {}
+
+    /// Called at the end of every frame, after the frame graph is done.
+    fn post_frame(&mut self, _app: &mut WinitApp) {}
+
+    /// Called whenever the event loop is about to wait for more events, including when no frames are being drawn.
+    fn about_to_wait(&mut self, _app: &mut WinitApp, _event_loop: &ActiveEventLoop) {}
+
+    /// Return true to consume the event, hiding it from plugins registered after this one.
+    fn window_event(
+        &mut self,
+        _app: &mut WinitApp,
+        _window: WindowId,
+        _event: &WindowEvent,
+    ) -> bool {
+        false
+    }
+
+    /// The app went into the background. On Android its windows' surfaces are about to be destroyed,
+    /// so anything presenting to them has to let go here. Called in reverse registration order.
+    fn suspended(&mut self, _app: &mut WinitApp) {}
+
+    /// The app came back from [`Plugin::suspended`], and surfaces can be made again.
+    fn resumed(&mut self, _app: &mut WinitApp, _event_loop: &ActiveEventLoop) {}
+
+    /// The renderer's device was lost, and everything made on it with it, which has to be let go of here. Return
+    /// an action to decide whether the app recovers or exits, the first plugin to decide wins. Otherwise it goes by
+    /// [`DeviceLost::default_action`]. After recovering, the renderer is a new one to make things on again.
+    fn device_lost(&mut self, _app: &mut WinitApp, _lost: &DeviceLost) -> Option<DeviceLostAction> {
+        None
+    }
+
+    /// Called once on exit, in reverse registration order.
+    fn shutdown(&mut self, _app: &mut WinitApp) {}
+}
+
+#[derive(Default)]
+pub struct Plugins {
+    list: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub fn add(&mut self, plugin: impl Plugin) {
+        if self.contains(plugin.name()) {
+            panic!("Plugin {} registered twice!", plugin.name());
+        }
+
+        self.list.push(Box::new(plugin));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.list.iter().any(|p| p.name() == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.list.iter().map(|p| p.name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Put plugins back after a dispatch, keeping any that were registered during it.
+    pub(crate) fn restore(&mut self, mut taken: Plugins) {
+        taken.list.append(&mut self.list);
+        *self = taken;
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Box<dyn Plugin>> {
+        self.list.iter_mut()
+    }
+}
@@ -0,0 +1,317 @@
+//! A small built-in CPU profiler, fed by [`profile_scope!`](crate::profile_scope) and shown in the overlay.
+//!
+//! Scopes are only recorded while the profiler is enabled, so leaving them in hot code is cheap otherwise.
+//! GPU timings land in the same frames once the renderer resolves them, mapped onto the CPU clock.
+//! With the `puffin` feature, scopes are forwarded to puffin as well for use with external viewers.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::render::gpu_clock::GpuClock;
+
+/// Frames of history kept around for the overlay.
+const HISTORY: usize = 240;
+
+/// Profile the rest of the enclosing block under the given name.
+/// Puffin interns scope names per call site, so non-literal names get forwarded as scope data instead.
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:literal) => {
+        let _profile_scope = $crate::profile::ScopeGuard::new($name);
+        #[cfg(feature = "puffin")]
+        puffin::profile_scope!($name);
+    };
+    ($name:expr) => {
+        let _profile_scope = $crate::profile::ScopeGuard::new($name);
+        #[cfg(feature = "puffin")]
+        puffin::profile_scope!("dynamic_scope", $name);
+    };
+}
+
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub name: &'static str,
+    /// Index into [`ProfiledFrame::threads`].
+    pub thread: usize,
+    /// Nesting depth within its thread, 0 for outermost scopes.
+    pub depth: u32,
+    /// Relative to the start of the frame.
+    pub start: Duration,
+    pub end: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfiledFrame {
+    pub index: u64,
+    pub start: Instant,
+    pub duration: Duration,
+    pub spans: Vec<Span>,
+    /// Names of every thread that has ever recorded a span.
+    pub threads: Vec<String>,
+    /// GPU work submitted during this frame, on the CPU timeline. Usually arrives a few frames late.
+    /// `thread` is always 0 and depth is nesting as with CPU spans.
+    pub gpu_spans: Vec<Span>,
+    /// Whether the GPU spans were placed with a calibrated clock, or just guessed from submission time.
+    pub gpu_calibrated: bool,
+}
+
+impl ProfiledFrame {
+    /// The end of the last span, CPU or GPU. GPU work often runs past the end of the CPU frame.
+    pub fn extent(&self) -> Duration {
+        self.spans
+            .iter()
+            .chain(&self.gpu_spans)
+            .map(|s| s.end)
+            .fold(self.duration, Duration::max)
+    }
+
+    /// Time between the end of the CPU frame and the end of GPU work, if there was any.
+    pub fn gpu_latency(&self) -> Option<Duration> {
+        let end = self.gpu_spans.iter().map(|s| s.end).max()?;
+        Some(end.saturating_sub(self.duration))
+    }
+
+    /// Total time within the GPU's busy range where no GPU span was running.
+    /// These are the bubbles where the GPU sat waiting on the CPU.
+    pub fn gpu_idle(&self) -> Duration {
+        let mut top: Vec<&Span> = self.gpu_spans.iter().filter(|s| s.depth == 0).collect();
+        top.sort_by_key(|s| s.start);
+
+        let mut idle = Duration::ZERO;
+        let mut cursor: Option<Duration> = None;
+        for s in top {
+            if let Some(c) = cursor {
+                idle += s.start.saturating_sub(c);
+            }
+            cursor = Some(cursor.map_or(s.end, |c| c.max(s.end)));
+        }
+
+        return idle;
+    }
+}
+
+/// A resolved GPU scope, in raw timestamp ticks.
+#[derive(Clone, Debug)]
+pub struct GpuScope {
+    pub name: &'static str,
+    pub depth: u32,
+    pub start_ticks: u64,
+    pub end_ticks: u64,
+}
+
+struct RawSpan {
+    name: &'static str,
+    thread: usize,
+    depth: u32,
+    start: Instant,
+    end: Instant,
+}
+
+struct Current {
+    index: u64,
+    start: Instant,
+    spans: Vec<RawSpan>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+static HISTORY_FRAMES: Mutex<VecDeque<Arc<ProfiledFrame>>> = Mutex::new(VecDeque::new());
+static THREADS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static THREAD_SLOT: Cell<Option<usize>> = const { Cell::new(None) };
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+fn thread_slot() -> usize {
+    if let Some(slot) = THREAD_SLOT.get() {
+        return slot;
+    }
+
+    let mut threads = THREADS.lock().unwrap();
+    let current = std::thread::current();
+    threads.push(
+        current
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("{:?}", current.id())),
+    );
+    let slot = threads.len() - 1;
+    THREAD_SLOT.set(Some(slot));
+    return slot;
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    #[cfg(feature = "puffin")]
+    puffin::set_scopes_on(enabled);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Close off the current frame and start a new one. Called by the app at the top of every frame.
+pub fn new_frame(index: u64) {
+    #[cfg(feature = "puffin")]
+    puffin::GlobalProfiler::lock().new_frame();
+
+    let now = Instant::now();
+    let Some(finished) = CURRENT.lock().unwrap().replace(Current {
+        index,
+        start: now,
+        spans: Vec::new(),
+    }) else {
+        return;
+    };
+
+    if !is_enabled() {
+        return;
+    }
+
+    let rel = |t: Instant| t.saturating_duration_since(finished.start);
+    let frame = ProfiledFrame {
+        index: finished.index,
+        start: finished.start,
+        duration: now - finished.start,
+        spans: finished
+            .spans
+            .into_iter()
+            .map(|s| Span {
+                name: s.name,
+                thread: s.thread,
+                depth: s.depth,
+                start: rel(s.start),
+                end: rel(s.end),
+            })
+            .collect(),
+        threads: THREADS.lock().unwrap().clone(),
+        gpu_spans: Vec::new(),
+        gpu_calibrated: false,
+    };
+
+    let mut history = HISTORY_FRAMES.lock().unwrap();
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(Arc::new(frame));
+}
+
+/// Attach resolved GPU timings to the frame they were submitted in.
+/// Dropped if that frame has already fallen out of the history (or wasn't recorded).
+pub fn record_gpu_frame(index: u64, clock: &GpuClock, scopes: &[GpuScope]) {
+    let mut history = HISTORY_FRAMES.lock().unwrap();
+    let Some(frame) = history.iter_mut().find(|f| f.index == index) else {
+        return;
+    };
+
+    let frame = Arc::make_mut(frame);
+    let rel = |ticks: u64| {
+        clock.to_instant(ticks).map_or(Duration::ZERO, |at| {
+            at.saturating_duration_since(frame.start)
+        })
+    };
+    frame.gpu_calibrated = clock.calibrated;
+    frame.gpu_spans = scopes
+        .iter()
+        .map(|s| Span {
+            name: s.name,
+            thread: 0,
+            depth: s.depth,
+            start: rel(s.start_ticks),
+            end: rel(s.end_ticks),
+        })
+        .collect();
+}
+
+pub fn latest_frame() -> Option<Arc<ProfiledFrame>> {
+    HISTORY_FRAMES.lock().unwrap().back().cloned()
+}
+
+/// Every frame in the history, oldest first.
+pub fn history() -> Vec<Arc<ProfiledFrame>> {
+    HISTORY_FRAMES.lock().unwrap().iter().cloned().collect()
+}
+
+/// Records a span from creation to drop. Use [`profile_scope!`](crate::profile_scope) rather than this directly.
+pub struct ScopeGuard {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+impl ScopeGuard {
+    pub fn new(name: &'static str) -> ScopeGuard {
+        if !is_enabled() {
+            return ScopeGuard { name, start: None };
+        }
+
+        DEPTH.set(DEPTH.get() + 1);
+        ScopeGuard {
+            name,
+            start: Some(Instant::now()),
+        }
+    }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+
+        let end = Instant::now();
+        let depth = DEPTH.get() - 1;
+        DEPTH.set(depth);
+
+        if let Some(current) = CURRENT.lock().unwrap().as_mut() {
+            current.spans.push(RawSpan {
+                name: self.name,
+                thread: thread_slot(),
+                depth,
+                start,
+                end,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{ProfiledFrame, Span};
+
+    fn span(start: u64, end: u64, depth: u32) -> Span {
+        Span {
+            name: "pass",
+            thread: 0,
+            depth,
+            start: Duration::from_millis(start),
+            end: Duration::from_millis(end),
+        }
+    }
+
+    #[test]
+    pub fn gpu_bubbles() {
+        let frame = ProfiledFrame {
+            index: 0,
+            start: Instant::now(),
+            duration: Duration::from_millis(10),
+            spans: Vec::new(),
+            threads: Vec::new(),
+            gpu_spans: vec![span(4, 8, 0), span(5, 6, 1), span(11, 16, 0)],
+            gpu_calibrated: true,
+        };
+
+        assert_eq!(frame.gpu_idle(), Duration::from_millis(3));
+        assert_eq!(frame.gpu_latency(), Some(Duration::from_millis(6)));
+        assert_eq!(frame.extent(), Duration::from_millis(16));
+    }
+}
@@ -1,29 +1,69 @@
 use std::sync::LazyLock;
-use std::{ffi::CString, ptr};
 
-use ash::{Entry, vk};
+use ash::Entry;
 
-use crate::consts::{APPLICATION_VERSION, ENGINE_VERSION};
 mod alloc;
+pub mod analysis;
+pub mod atlas;
+pub mod background;
+pub mod bindless;
+pub mod breadcrumbs;
+pub mod camera2d;
+pub mod commands;
+pub mod compute;
+pub mod deletion;
+pub mod depth_of_field;
+pub mod descriptors;
+pub mod device_lost;
+pub mod diag;
+pub mod draw;
+pub mod extract;
+pub mod foliage;
+pub mod frame_sync;
+pub mod fullscreen;
+pub mod gpu_clock;
+pub mod gpu_profiler;
+pub mod gpu_select;
+pub mod graph;
+pub mod hal;
+pub mod headless;
+pub mod indirect;
+pub mod lightmap;
+pub mod lines;
+pub mod mesh;
+pub mod motion_blur;
+pub mod outline;
+pub mod overlay;
+pub mod pacing;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod planar;
+pub mod portal;
+pub mod probes;
+pub mod quality;
+pub mod reflections;
+pub mod rendering;
+pub mod renderer;
+pub mod shader;
+pub mod shapes;
+pub mod sprite;
+pub mod surface;
+pub mod swapchain;
+pub mod text;
+pub mod texture;
+pub mod tilemap;
+pub mod uniforms;
+pub mod validation;
+pub mod water;
 
 pub static VK_ENTRY: LazyLock<Option<Entry>> = LazyLock::new(|| unsafe { Entry::load().ok() });
 
-pub fn render_setup() {
-    if let Some(vk) = VK_ENTRY.as_ref() {
-        let app_name = CString::new("Crowbar").unwrap();
-        let engine_name = CString::new("Crowbar").unwrap();
+/// How many frames the CPU can be recording ahead of the GPU.
+pub const FRAMES_IN_FLIGHT: u32 = 2;
 
-        let app_info = vk::ApplicationInfo {
-            s_type: vk::StructureType::APPLICATION_INFO,
-            p_next: ptr::null(),
-            p_application_name: app_name.as_ptr(),
-            application_version: APPLICATION_VERSION,
-            p_engine_name: engine_name.as_ptr(),
-            engine_version: ENGINE_VERSION,
-            api_version: ash::vk::API_VERSION_1_3,
-            ..Default::default()
-        };
-
-        vk.create_instance(&info, Some(alloc::VK_ALLOCATOR_CALLBACKS.to_owned()))
-    }
+/// Host memory the driver currently has allocated through our allocation callbacks.
+pub fn vk_host_allocated() -> usize {
+    alloc::VK_ALLOCATOR
+        .allocated
+        .load(std::sync::atomic::Ordering::Relaxed)
 }
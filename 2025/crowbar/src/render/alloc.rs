@@ -1,5 +1,5 @@
 use std::{
-    alloc::{Allocator, Global, Layout},
+    alloc::Layout,
     ffi::c_void,
     marker::PhantomData,
     ptr::{self, NonNull},
@@ -11,6 +11,34 @@ use std::{
 
 use ash::vk::{AllocationCallbacks, SystemAllocationScope};
 
+// The `stable` feature swaps in allocator-api2's copy of the unstable allocator API.
+#[cfg(feature = "stable")]
+use allocator_api2::alloc::{Allocator, Global};
+#[cfg(not(feature = "stable"))]
+use std::alloc::{Allocator, Global};
+
+#[cfg(any(test, fuzzing))]
+pub mod fuzz;
+
+/// An allocation scope, as far as the allocation logic cares.
+/// Kept apart from ash's enum so the logic (and its tests) can run under Miri without any FFI types:
+/// `cargo +nightly miri test render::alloc`.
+pub(crate) trait AllocScope: Copy {
+    fn as_raw(self) -> i32;
+}
+
+impl AllocScope for SystemAllocationScope {
+    fn as_raw(self) -> i32 {
+        SystemAllocationScope::as_raw(self)
+    }
+}
+
+impl AllocScope for i32 {
+    fn as_raw(self) -> i32 {
+        self
+    }
+}
+
 /// A vulkan allocator wrapping the global allocation context.
 pub static VK_ALLOCATOR: LazyLock<&'static CrowbarVkAllocator<Global>> =
     LazyLock::new(|| Box::leak(Box::new(CrowbarVkAllocator::<Global>::new(Global))));
@@ -65,7 +93,8 @@ struct MemoryTag {
     magic: usize,
     size: usize,
     align: usize,
-    scope: SystemAllocationScope,
+    /// Raw [`SystemAllocationScope`].
+    scope: i32,
     base: *mut c_void,
 }
 
@@ -103,6 +132,11 @@ unsafe fn validate_alloc(alloc: *mut c_void) -> bool {
 }
 
 fn make_layout(size: usize, align: usize) -> Option<(Layout, usize)> {
+    // Bumping it up to the tag's alignment below would hide a bad alignment otherwise.
+    if !align.is_power_of_two() {
+        return None;
+    }
+
     Some(
         MT_LAYOUT
             .extend(Layout::from_size_align(size, align.max(align_of::<MemoryTag>())).ok()?)
@@ -110,28 +144,15 @@ fn make_layout(size: usize, align: usize) -> Option<(Layout, usize)> {
     )
 }
 
-unsafe extern "system" fn vk_alloc<TAlloc: Allocator + Send + Sync + 'static>(
-    userdata: *mut c_void,
-    size: usize,
-    align: usize,
-    scope: SystemAllocationScope,
+/// Fill in the tag ahead of a block, returning the block.
+///
+/// # Safety
+/// `allocated` must be a live allocation of `layout.0`, with the block at offset `layout.1`.
+unsafe fn write_tag(
+    allocated: *mut c_void,
+    layout: (Layout, usize),
+    scope: impl AllocScope,
 ) -> *mut c_void {
-    let Some(layout) = make_layout(size, align) else {
-        return ptr::null::<u8>() as *mut c_void;
-    };
-
-    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
-
-    data.allocated
-        .fetch_add(layout.0.size(), atomic::Ordering::Relaxed);
-
-    // SAFETY: Simple allocation using the provided layout, we're just a shim.
-    let Ok(allocated) = data.allocator.allocate(layout.0) else {
-        return ptr::null::<u8>() as *mut c_void;
-    };
-
-    let allocated = allocated.as_mut_ptr() as *mut c_void;
-
     // SAFETY: Offset to account for the tag, we accounted for this when allocating.
     let block = unsafe { allocated.byte_offset(layout.1 as isize) };
 
@@ -142,7 +163,7 @@ unsafe extern "system" fn vk_alloc<TAlloc: Allocator + Send + Sync + 'static>(
     tag.base = allocated;
     tag.align = layout.0.align();
     tag.size = layout.0.size();
-    tag.scope = scope;
+    tag.scope = scope.as_raw();
     #[cfg(debug_assertions)]
     {
         tag.magic = MT_MAGIC;
@@ -151,29 +172,55 @@ unsafe extern "system" fn vk_alloc<TAlloc: Allocator + Send + Sync + 'static>(
     return block; // and return the untagged allocation.
 }
 
-unsafe extern "system" fn vk_realloc<TAlloc: Allocator + Send + Sync + 'static>(
-    userdata: *mut c_void,
+/// Allocate a tagged block. The logic behind [`vk_alloc`], minus the FFI.
+unsafe fn alloc_block<TAlloc: Allocator + Send + Sync>(
+    data: &CrowbarVkAllocator<TAlloc>,
+    size: usize,
+    align: usize,
+    scope: impl AllocScope,
+) -> *mut c_void {
+    let Some(layout) = make_layout(size, align) else {
+        return ptr::null::<u8>() as *mut c_void;
+    };
+
+    // SAFETY: Simple allocation using the provided layout, we're just a shim.
+    let Ok(allocated) = data.allocator.allocate(layout.0) else {
+        return ptr::null::<u8>() as *mut c_void;
+    };
+
+    data.allocated
+        .fetch_add(layout.0.size(), atomic::Ordering::Relaxed);
+
+    return unsafe { write_tag(allocated.as_ptr() as *mut c_void, layout, scope) };
+}
+
+/// Resize a tagged block, per the rules for `PFN_vkReallocationFunction`.
+unsafe fn realloc_block<TAlloc: Allocator + Send + Sync>(
+    data: &CrowbarVkAllocator<TAlloc>,
     original: *mut c_void,
     size: usize,
     align: usize,
-    scope: SystemAllocationScope,
+    scope: impl AllocScope,
 ) -> *mut c_void {
-    #[derive(PartialEq, Eq)]
-    enum GrowOrShrink {
-        Grow,
-        Shrink,
+    // Per spec, reallocating null is allocating, and reallocating to nothing is freeing.
+    if original.is_null() {
+        return unsafe { alloc_block(data, size, align, scope) };
+    }
+    if size == 0 {
+        unsafe { free_block(data, original) };
+        return ptr::null::<u8>() as *mut c_void;
     }
 
+    // On failure the original has to stay as it was, so bail before touching it.
     let Some(layout) = make_layout(size, align) else {
         return ptr::null::<u8>() as *mut c_void;
     };
 
-    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
     let allocator = &data.allocator;
 
     assert!(unsafe { validate_alloc(original) });
 
-    let (base_ptr, grow_or_shrink, old_layout) = 
+    let (base_ptr, old_layout, old_offset) = 
     // Safety scope, as we're going to do a reallocation and the old tag would be UB to hang on to.
     {
         let (tag, _) = unsafe { as_tag_and_block(original) };
@@ -181,70 +228,55 @@ unsafe extern "system" fn vk_realloc<TAlloc: Allocator + Send + Sync + 'static>(
         // SAFETY: We got this from a layout before, we know it's valid.
         let old_layout = tag.layout();
 
-        (tag.base, 
-            // If new layout larger, grow, else shrink.
-            (old_layout.size() < layout.0.size())
-            .then_some(GrowOrShrink::Grow).unwrap_or(GrowOrShrink::Shrink),
-            old_layout
-        )
+        // SAFETY: Both are in the same allocation, the block after the base.
+        (tag.base, old_layout, unsafe { original.byte_offset_from(tag.base) } as usize)
     };
 
     let new_alloc;
     unsafe {
-        if grow_or_shrink == GrowOrShrink::Grow {
-            new_alloc = allocator.grow(
-                NonNull::new_unchecked(base_ptr).cast(),
-                old_layout,
-                layout.0,
-            );
-            data.allocated.fetch_add(
-                layout.0.size() - old_layout.size(),
-                atomic::Ordering::Relaxed,
-            );
+        let base = NonNull::new_unchecked(base_ptr).cast();
+
+        if old_offset != layout.1 {
+            // A different alignment moves the block relative to the base, which grow/shrink
+            // wouldn't account for, so move it by hand.
+            new_alloc = allocator.allocate(layout.0);
+            if let Ok(new) = &new_alloc {
+                ptr::copy_nonoverlapping(
+                    original as *const u8,
+                    new.cast::<u8>().as_ptr().add(layout.1),
+                    (old_layout.size() - old_offset).min(size),
+                );
+                allocator.deallocate(base, old_layout);
+            }
+        } else if old_layout.size() < layout.0.size() {
+            new_alloc = allocator.grow(base, old_layout, layout.0);
         } else {
-            new_alloc = allocator.shrink(
-                NonNull::new_unchecked(base_ptr).cast(),
-                old_layout,
-                layout.0,
-            );
-            data.allocated.fetch_sub(
-                old_layout.size() - layout.0.size(),
-                atomic::Ordering::Relaxed,
-            );
+            new_alloc = allocator.shrink(base, old_layout, layout.0);
         }
     };
 
-    if let Err(_) = new_alloc {
+    let Ok(new_alloc) = new_alloc else {
         // Return null as per spec, due to allocation failure.
         return ptr::null::<u8>() as *mut c_void;
-    }
-
-    let allocated = new_alloc.unwrap().as_ptr() as *mut c_void;
-
-    // SAFETY: Offset to account for the tag, we accounted for this when allocating.
-    let block = unsafe { allocated.byte_offset(layout.1 as isize) };
-
-    // SAFETY: This is a correctly aligned tag/block pair.
-    let (tag, block) = unsafe { as_tag_and_block(block) };
+    };
 
-    // Set up the tag for validity. Necessary for this to be safe.
-    tag.base = allocated;
-    tag.align = layout.0.align();
-    tag.size = layout.0.size();
-    tag.scope = scope;
-    #[cfg(debug_assertions)]
-    {
-        tag.magic = MT_MAGIC;
-    }
+    data.allocated
+        .fetch_add(layout.0.size(), atomic::Ordering::Relaxed);
+    data.allocated
+        .fetch_sub(old_layout.size(), atomic::Ordering::Relaxed);
 
-    return block; // and return the untagged allocation.
+    return unsafe { write_tag(new_alloc.as_ptr() as *mut c_void, layout, scope) };
 }
 
-unsafe extern "system" fn vk_free<TAlloc: Allocator + Send + Sync + 'static>(
-    userdata: *mut c_void,
+/// Free a tagged block. Null is allowed, and does nothing.
+unsafe fn free_block<TAlloc: Allocator + Send + Sync>(
+    data: &CrowbarVkAllocator<TAlloc>,
     original: *mut c_void,
 ) {
-    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
+    if original.is_null() {
+        return;
+    }
+
     let allocator = &data.allocator;
 
     assert!(unsafe { validate_alloc(original) });
@@ -263,44 +295,83 @@ unsafe extern "system" fn vk_free<TAlloc: Allocator + Send + Sync + 'static>(
     data.allocated.fetch_sub(size, atomic::Ordering::Relaxed);
 }
 
+unsafe extern "system" fn vk_alloc<TAlloc: Allocator + Send + Sync + 'static>(
+    userdata: *mut c_void,
+    size: usize,
+    align: usize,
+    scope: SystemAllocationScope,
+) -> *mut c_void {
+    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
+    return unsafe { alloc_block(data, size, align, scope) };
+}
+
+unsafe extern "system" fn vk_realloc<TAlloc: Allocator + Send + Sync + 'static>(
+    userdata: *mut c_void,
+    original: *mut c_void,
+    size: usize,
+    align: usize,
+    scope: SystemAllocationScope,
+) -> *mut c_void {
+    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
+    return unsafe { realloc_block(data, original, size, align, scope) };
+}
+
+unsafe extern "system" fn vk_free<TAlloc: Allocator + Send + Sync + 'static>(
+    userdata: *mut c_void,
+    original: *mut c_void,
+) {
+    let data = unsafe { userdata_as_allocator::<TAlloc>(userdata) };
+    unsafe { free_block(data, original) };
+}
+
 #[cfg(test)]
 mod test {
-    use std::{ffi::c_void, ptr::slice_from_raw_parts_mut, usize};
+    use std::{ffi::c_void, ptr::{self, slice_from_raw_parts_mut}, usize};
 
     use ash::vk::SystemAllocationScope;
 
-    use crate::render::alloc::validate_alloc;
+    use crate::{render::alloc::validate_alloc, rng::Rng};
+
+    use super::{
+        VK_ALLOCATOR, VK_ALLOCATOR_CALLBACKS, alloc_block, free_block, fuzz, realloc_block,
+    };
 
-    use super::VK_ALLOCATOR_CALLBACKS;
+    // These go straight to the allocation logic with raw scopes rather than through the callbacks,
+    // so they run under Miri: `cargo +nightly miri test render::alloc`.
+    const OBJECT: i32 = 1;
+    const INSTANCE: i32 = 4;
 
-    unsafe fn vk_global_alloc(
-        size: usize,
-        align: usize,
-        scope: SystemAllocationScope,
-    ) -> *mut c_void {
-        let cb = VK_ALLOCATOR_CALLBACKS.to_owned();
-        return unsafe { cb.pfn_allocation.unwrap()(cb.p_user_data, size, align, scope) };
+    unsafe fn vk_global_alloc(size: usize, align: usize, scope: i32) -> *mut c_void {
+        return unsafe { alloc_block(*VK_ALLOCATOR, size, align, scope) };
     }
 
     unsafe fn vk_global_realloc(
         original: *mut c_void,
         size: usize,
         align: usize,
-        scope: SystemAllocationScope,
+        scope: i32,
     ) -> *mut c_void {
-        let cb = VK_ALLOCATOR_CALLBACKS.to_owned();
-        return unsafe {
-            cb.pfn_reallocation.unwrap()(cb.p_user_data, original, size, align, scope)
-        };
+        return unsafe { realloc_block(*VK_ALLOCATOR, original, size, align, scope) };
     }
 
-    unsafe fn vk_global_free(
-        original: *mut c_void,
-    ) {
+    unsafe fn vk_global_free(original: *mut c_void) {
+        unsafe { free_block(*VK_ALLOCATOR, original) };
+    }
+
+    /// The callbacks handed to the driver are wired up to the logic tested below.
+    #[test]
+    pub fn callbacks() {
         let cb = VK_ALLOCATOR_CALLBACKS.to_owned();
+        let scope = SystemAllocationScope::DEVICE;
         unsafe {
-            cb.pfn_free.unwrap()(cb.p_user_data, original)
-        };
+            let alloc = cb.pfn_allocation.unwrap()(cb.p_user_data, 24, 32, scope);
+            assert!(!alloc.is_null() && validate_alloc(alloc) && alloc.addr().is_multiple_of(32));
+
+            let alloc = cb.pfn_reallocation.unwrap()(cb.p_user_data, alloc, 48, 32, scope);
+            assert!(!alloc.is_null() && validate_alloc(alloc));
+
+            cb.pfn_free.unwrap()(cb.p_user_data, alloc);
+        }
     }
 
 
@@ -310,12 +381,12 @@ mod test {
             const SIZE: usize = 320;
             const ALIGN: usize = 128;
 
-            let alloc = vk_global_alloc(SIZE, ALIGN, SystemAllocationScope::INSTANCE);
+            let alloc = vk_global_alloc(SIZE, ALIGN, INSTANCE);
 
             assert!(!alloc.is_null(), "Allocation in test must succeed.");
             assert!(validate_alloc(alloc), "Allocation validation failed.");
             assert!(
-                alloc.is_aligned_to(ALIGN),
+                alloc.addr().is_multiple_of(ALIGN),
                 "Allocation alignment is incorrect."
             );
 
@@ -329,12 +400,12 @@ mod test {
                 }
             }
 
-            let alloc = vk_global_realloc(alloc, SIZE * 2, ALIGN, SystemAllocationScope::INSTANCE);
+            let alloc = vk_global_realloc(alloc, SIZE * 2, ALIGN, INSTANCE);
 
             assert!(!alloc.is_null(), "Allocation in test must succeed.");
             assert!(validate_alloc(alloc), "Allocation validation failed.");
             assert!(
-                alloc.is_aligned_to(ALIGN),
+                alloc.addr().is_multiple_of(ALIGN),
                 "Allocation alignment is incorrect."
             );
 
@@ -343,8 +414,8 @@ mod test {
                     .as_mut()
                     .unwrap();
 
-                for i in 0..SIZE {
-                    assert_eq!(slice[i], 37, "Reallocation grow garbled memory.");
+                for i in slice {
+                    assert_eq!(*i, 37, "Reallocation grow garbled memory.");
                 }
             }
 
@@ -352,13 +423,50 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn spec_edge_cases() {
+        unsafe {
+            // Reallocating null allocates, reallocating to zero frees, and freeing null does nothing.
+            let alloc = vk_global_realloc(ptr::null_mut(), 16, 16, OBJECT);
+            assert!(!alloc.is_null() && validate_alloc(alloc));
+            let alloc = vk_global_realloc(alloc, 0, 16, OBJECT);
+            assert!(alloc.is_null());
+            vk_global_free(ptr::null_mut());
+
+            // Changing alignment on realloc keeps the contents.
+            let alloc = vk_global_alloc(64, 8, OBJECT) as *mut u8;
+            alloc.write_bytes(7, 64);
+            let alloc = vk_global_realloc(alloc as _, 32, 256, OBJECT);
+            assert!(alloc.addr().is_multiple_of(256));
+            assert!((0..32).all(|i| *(alloc as *const u8).add(i) == 7));
+
+            // A failed realloc leaves the original alone.
+            let failed = vk_global_realloc(alloc, usize::MAX, 8, OBJECT);
+            assert!(failed.is_null() && validate_alloc(alloc));
+            vk_global_free(alloc);
+        }
+    }
+
+    /// Random call sequences through the fuzzing driver, so the edge cases get some coverage without cargo-fuzz.
+    #[test]
+    pub fn random_sequences() {
+        // Miri is a lot slower, and a few runs are enough for it to find undefined behaviour.
+        let runs = if cfg!(miri) { 20 } else { 500 };
+        let mut rng = Rng::new(0xa110c);
+        for _ in 0..runs {
+            let len = rng.below(256) as usize;
+            let data: Vec<u8> = (0..len).map(|_| rng.next_u32() as u8).collect();
+            fuzz::run(&data);
+        }
+    }
+
     #[test]
     pub fn reasonable_failure() {
         unsafe {
-            let alloc = vk_global_alloc(usize::MAX, 1, SystemAllocationScope::INSTANCE);
+            let alloc = vk_global_alloc(usize::MAX, 1, INSTANCE);
             assert!(alloc.is_null(), "Allocation should fail gracefully.");
 
-            let alloc = vk_global_alloc(4, usize::MAX, SystemAllocationScope::INSTANCE);
+            let alloc = vk_global_alloc(4, usize::MAX, INSTANCE);
             assert!(alloc.is_null(), "Allocation should fail gracefully.");
         }
     }
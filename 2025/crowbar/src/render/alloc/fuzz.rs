@@ -0,0 +1,191 @@
+//! Drives the allocation logic behind the callbacks through arbitrary sequences of calls, checking every block stays
+//! aligned, tagged and intact, and that the usage count adds back up to nothing.
+//! Shared by the `vk_alloc` fuzz target and the property tests.
+
+use std::{ffi::c_void, ptr, sync::atomic::Ordering};
+
+use super::{CrowbarVkAllocator, Global, alloc_block, free_block, realloc_block, validate_alloc};
+
+const SLOTS: usize = 8;
+
+/// Something a driver is allowed to ask for.
+#[derive(Clone, Copy, Debug)]
+enum Size {
+    Normal(usize),
+    Zero,
+    /// Can't possibly be satisfied.
+    Huge(usize),
+}
+
+impl Size {
+    fn get(self) -> usize {
+        match self {
+            Size::Normal(n) => n,
+            Size::Zero => 0,
+            Size::Huge(n) => usize::MAX - n,
+        }
+    }
+}
+
+struct Block {
+    ptr: *mut c_void,
+    size: usize,
+    fill: u8,
+}
+
+/// Pulls values off the fuzzer's bytes, reading zeros once they run out.
+struct Bytes<'a>(&'a [u8]);
+
+impl Bytes<'_> {
+    fn u8(&mut self) -> u8 {
+        let Some((&b, rest)) = self.0.split_first() else {
+            return 0;
+        };
+        self.0 = rest;
+        return b;
+    }
+
+    fn size(&mut self) -> Size {
+        let n = u16::from_le_bytes([self.u8(), self.u8()]) as usize;
+        match self.u8() {
+            0 => Size::Zero,
+            1 => Size::Huge(n),
+            _ => Size::Normal(n),
+        }
+    }
+
+    /// Mostly small powers of two, now and then something silly.
+    fn align(&mut self) -> usize {
+        match self.u8() {
+            0xff => 3,
+            0xfe => 0,
+            0xfd => 1 << 40,
+            b => 1 << (b % 13),
+        }
+    }
+
+    /// A raw `VkSystemAllocationScope`.
+    fn scope(&mut self) -> i32 {
+        (self.u8() % 5) as i32
+    }
+}
+
+/// Whether a request has to succeed. Others are allowed to fail, as long as they fail cleanly.
+fn must_succeed(size: Size, align: usize) -> bool {
+    !matches!(size, Size::Huge(_)) && align.is_power_of_two() && align <= 4096
+}
+
+/// # Safety
+/// `block` must be live, with `block.size` bytes.
+unsafe fn check(block: &Block, expect_fill: Option<u8>, align: usize) {
+    assert_eq!(
+        block.ptr as usize & (align - 1),
+        0,
+        "Block isn't aligned to {align}"
+    );
+    assert!(
+        unsafe { validate_alloc(block.ptr) },
+        "Block's tag is broken"
+    );
+
+    // SAFETY: The caller vouches for the size.
+    let bytes = unsafe { std::slice::from_raw_parts_mut(block.ptr as *mut u8, block.size) };
+    if let Some(fill) = expect_fill {
+        assert!(
+            bytes.iter().all(|&b| b == fill),
+            "Block contents got garbled"
+        );
+    }
+    bytes.fill(block.fill);
+}
+
+pub fn run(data: &[u8]) {
+    let allocator = CrowbarVkAllocator::new(Global);
+    let mut slots: [Option<Block>; SLOTS] = Default::default();
+    let mut bytes = Bytes(data);
+    let mut fill = 0u8;
+
+    // SAFETY: Every pointer handed back to the allocator came from it, and is still live.
+    unsafe {
+        while !bytes.0.is_empty() {
+            let op = bytes.u8();
+            let slot = &mut slots[(op >> 2) as usize % SLOTS];
+            fill = fill.wrapping_add(1);
+
+            match op % 3 {
+                0 => {
+                    let (size, align, scope) = (bytes.size(), bytes.align(), bytes.scope());
+                    if let Some(old) = slot.take() {
+                        free_block(&allocator, old.ptr);
+                    }
+
+                    let p = alloc_block(&allocator, size.get(), align, scope);
+                    if p.is_null() {
+                        assert!(!must_succeed(size, align), "{size:?} at {align} failed");
+                        continue;
+                    }
+                    let block = Block {
+                        ptr: p,
+                        size: size.get(),
+                        fill,
+                    };
+                    check(&block, None, align);
+                    *slot = Some(block);
+                }
+                1 => {
+                    let (size, align, scope) = (bytes.size(), bytes.align(), bytes.scope());
+                    let original = slot.as_ref().map_or(ptr::null_mut(), |b| b.ptr);
+
+                    let p = realloc_block(&allocator, original, size.get(), align, scope);
+                    if let Size::Zero = size
+                        && !original.is_null()
+                    {
+                        // Reallocating to nothing frees.
+                        assert!(p.is_null());
+                        *slot = None;
+                        continue;
+                    }
+                    if p.is_null() {
+                        assert!(!must_succeed(size, align), "{size:?} at {align} failed");
+                        // The original has to be left alone.
+                        if let Some(block) = slot {
+                            check(block, Some(block.fill), 1);
+                        }
+                        continue;
+                    }
+
+                    let kept = slot.as_ref().map(|b| (b.size.min(size.get()), b.fill));
+                    let block = Block {
+                        ptr: p,
+                        size: size.get(),
+                        fill,
+                    };
+                    if let Some((kept, old_fill)) = kept {
+                        let head = std::slice::from_raw_parts(p as *const u8, kept);
+                        assert!(
+                            head.iter().all(|&b| b == old_fill),
+                            "Realloc lost the contents"
+                        );
+                    }
+                    check(&block, None, align);
+                    *slot = Some(block);
+                }
+                _ => {
+                    let p = slot.take().map_or(ptr::null_mut(), |b| b.ptr);
+                    free_block(&allocator, p);
+                }
+            }
+        }
+
+        for block in slots.into_iter().flatten() {
+            check(&block, Some(block.fill), 1);
+            free_block(&allocator, block.ptr);
+        }
+    }
+
+    assert_eq!(
+        allocator.allocated.load(Ordering::Relaxed),
+        0,
+        "Usage count doesn't add back up"
+    );
+}
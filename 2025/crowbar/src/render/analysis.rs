@@ -0,0 +1,588 @@
+//! Frame analysis: a compute pass that boils a frame down to a luminance histogram, its average colour and the
+//! range of depths in it, for auto exposure, debug tools and tests to use.
+//!
+//! The shader (`analysis/analysis.comp`) reduces each 16x16 tile in shared memory, adding its histogram into a
+//! global one and writing the tile's sums out, and the CPU finishes the job adding the tiles up. Results go into a
+//! readback buffer per frame in flight, so nothing waits: a frame's results come back from
+//! [`AnalysisPass::collect`] once the GPU's done with it, when its slot comes round again.
+//!
+//! [`FrameAnalysis::of_capture`] does the same sums on the CPU, for captures and tests without a GPU.
+//!
+//! Ship the shader compiled, as [`SHADER_ASSET`] for [`Spirv::load`], or compile [`SHADER`] at runtime with
+//! [`ShaderCompiler::compile_text`](super::shader::compile::ShaderCompiler::compile_text) during development.
+//!
+//! The renderer runs it over presented frames with `r_analysis` on, for the overlay, see
+//! [`Renderer::frame_analysis`](super::renderer::Renderer::frame_analysis).
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    hal::{
+        BufferDesc, BufferUsage, Device, MemoryLocation,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture},
+    },
+    shader::reflect::Spirv,
+};
+use crate::{capture::CapturedFrame, color::LinearColor};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+pub const HISTOGRAM_BINS: usize = 256;
+/// The shader's source, to compile at runtime.
+pub const SHADER: &str = include_str!("analysis/analysis.comp");
+/// Where the compiled shader goes among the assets, without the `.spv`.
+pub const SHADER_ASSET: &str = "shaders/analysis.comp";
+
+/// Pixels along each side of a workgroup's tile.
+const TILE: u32 = 16;
+const HISTOGRAM_BYTES: u64 = HISTOGRAM_BINS as u64 * 4;
+/// A tile's sums: colour and count, depth min and max, and how many pixels had depth.
+const PARTIAL_BYTES: u64 = 32;
+
+/// Rec. 709 luminance.
+fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+/// The luminances the histogram covers, in stops.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuminanceRange {
+    pub min_log2: f32,
+    pub max_log2: f32,
+}
+
+impl Default for LuminanceRange {
+    fn default() -> Self {
+        LuminanceRange {
+            min_log2: -8.0,
+            max_log2: 8.0,
+        }
+    }
+}
+
+impl LuminanceRange {
+    fn inv_range(&self) -> f32 {
+        1.0 / (self.max_log2 - self.min_log2).max(f32::EPSILON)
+    }
+
+    /// Which bin `luminance` falls in. Bin 0 is everything darker than the range, the rest split it evenly.
+    pub fn bin(&self, luminance: f32) -> usize {
+        // NaN included.
+        if luminance.is_nan() || luminance < self.min_log2.exp2() {
+            return 0;
+        }
+        let t = ((luminance.log2() - self.min_log2) * self.inv_range()).clamp(0.0, 1.0);
+        return (t * 254.0 + 1.0) as usize;
+    }
+
+    /// The luminance in the middle of `bin`, the bottom of the range for bin 0.
+    pub fn bin_luminance(&self, bin: usize) -> f32 {
+        if bin == 0 {
+            return self.min_log2.exp2();
+        }
+        let t = (bin as f32 - 0.5) / 254.0;
+        return (self.min_log2 + t * (self.max_log2 - self.min_log2)).exp2();
+    }
+}
+
+/// What a frame came to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameAnalysis {
+    pub frame: u64,
+    pub range: LuminanceRange,
+    /// Pixels by [`LuminanceRange::bin`].
+    pub histogram: Vec<u32>,
+    pub pixels: u32,
+    /// Of every pixel, linear.
+    pub average: LinearColor,
+    /// Smallest and largest depth, in the depth buffer's reversed Z, of pixels off the far plane. `None` without a
+    /// depth buffer or with nothing drawn.
+    pub depth: Option<(f32, f32)>,
+}
+
+impl FrameAnalysis {
+    /// The same sums on the CPU, for a captured frame and optionally its depth buffer the same size.
+    pub fn of_capture(
+        color: &CapturedFrame,
+        depth: Option<&CapturedFrame>,
+        range: LuminanceRange,
+    ) -> FrameAnalysis {
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        let mut sum = [0.0f64; 3];
+        let pixels = color.linear_rgba();
+        for [r, g, b, _] in &pixels {
+            let rgb = [r.max(0.0), g.max(0.0), b.max(0.0)];
+            histogram[range.bin(luminance(rgb))] += 1;
+            for c in 0..3 {
+                sum[c] += rgb[c] as f64;
+            }
+        }
+        let count = pixels.len().max(1) as f64;
+        let depth = depth.and_then(|d| {
+            let drawn = d
+                .linear_rgba()
+                .into_iter()
+                .map(|p| p[0])
+                .filter(|&d| d > 0.0);
+            drawn.fold(None, |range: Option<(f32, f32)>, d| {
+                Some(range.map_or((d, d), |(min, max)| (min.min(d), max.max(d))))
+            })
+        });
+        return FrameAnalysis {
+            frame: color.frame,
+            range,
+            histogram,
+            pixels: pixels.len() as u32,
+            average: LinearColor::rgb(
+                (sum[0] / count) as f32,
+                (sum[1] / count) as f32,
+                (sum[2] / count) as f32,
+            ),
+            depth,
+        };
+    }
+
+    /// Add up what the shader wrote for `tiles` tiles.
+    fn decode(bytes: &[u8], tiles: u32, frame: u64, range: LuminanceRange) -> FrameAnalysis {
+        let word = |at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+        let float = |at: usize| f32::from_bits(word(at));
+        let histogram = (0..HISTOGRAM_BINS).map(|i| word(i * 4)).collect();
+        let mut sum = [0.0f64; 4];
+        let mut depth: Option<(f32, f32)> = None;
+        for tile in 0..tiles as usize {
+            let at = HISTOGRAM_BYTES as usize + tile * PARTIAL_BYTES as usize;
+            for (c, s) in sum.iter_mut().enumerate() {
+                *s += float(at + c * 4) as f64;
+            }
+            if word(at + 24) > 0 {
+                let (min, max) = (float(at + 16), float(at + 20));
+                depth = Some(depth.map_or((min, max), |(a, b)| (a.min(min), b.max(max))));
+            }
+        }
+        let count = sum[3].max(1.0);
+        return FrameAnalysis {
+            frame,
+            range,
+            histogram,
+            pixels: sum[3] as u32,
+            average: LinearColor::rgb(
+                (sum[0] / count) as f32,
+                (sum[1] / count) as f32,
+                (sum[2] / count) as f32,
+            ),
+            depth,
+        };
+    }
+
+    /// The luminance `fraction` of the way through the pixels, darkest first, to the histogram's precision.
+    pub fn percentile(&self, fraction: f32) -> f32 {
+        let target = (fraction.clamp(0.0, 1.0) * self.pixels as f32).max(1.0);
+        let mut seen = 0.0;
+        for (bin, &count) in self.histogram.iter().enumerate() {
+            seen += count as f32;
+            if seen >= target {
+                return self.range.bin_luminance(bin);
+            }
+        }
+        return self.range.max_log2.exp2();
+    }
+
+    /// The average luminance of the pixels between the `low` and `high` fractions, darkest first, averaged in stops.
+    /// What auto exposure meters, with the ends cut off so a few bright lights or a black border don't swing it.
+    pub fn average_luminance(&self, low: f32, high: f32) -> f32 {
+        let (low, high) = (low * self.pixels as f32, high * self.pixels as f32);
+        let (mut seen, mut total, mut weight) = (0.0, 0.0, 0.0);
+        for (bin, &count) in self.histogram.iter().enumerate() {
+            // The part of this bin's pixels between the cut offs.
+            let (start, end) = (seen, seen + count as f32);
+            seen = end;
+            let inside = end.min(high) - start.max(low);
+            if inside > 0.0 {
+                total += self.range.bin_luminance(bin).log2() * inside;
+                weight += inside;
+            }
+        }
+        if weight <= 0.0 {
+            return self.range.min_log2.exp2();
+        }
+        return (total / weight).exp2();
+    }
+}
+
+/// A frame in flight's results.
+struct Slot {
+    buffer: Option<VulkanBuffer>,
+    set: vk::DescriptorSet,
+    /// The frame that was recorded into it, and how many tiles it had.
+    recorded: Option<(u64, u32)>,
+}
+
+/// The compute pass. Hands back each frame's results a few frames later, see [`AnalysisPass::collect`].
+pub struct AnalysisPass {
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+    slots: Vec<Slot>,
+    pub range: LuminanceRange,
+}
+
+impl AnalysisPass {
+    /// Set up for `frames_in_flight` frames, with `spirv` compiled from [`SHADER`].
+    ///
+    /// # Safety
+    /// `cache` must be `device`'s, or null.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<AnalysisPass> {
+        let mut pass = AnalysisPass {
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            pool: vk::DescriptorPool::null(),
+            sampler: vk::Sampler::null(),
+            slots: Vec::new(),
+            range: LuminanceRange::default(),
+        };
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device.raw(), cache, spirv, frames_in_flight.max(1)) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        return Ok(pass);
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &ash::Device,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        frames: u32,
+    ) -> VkResult<()> {
+        let binding = |binding, ty| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        };
+        let bindings = [
+            binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            binding(2, vk::DescriptorType::STORAGE_BUFFER),
+        ];
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(20);
+        let sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: frames * 2,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: frames,
+            },
+        ];
+
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                allocs(),
+            )?;
+            let set_layouts = [self.set_layout];
+            self.layout = device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&set_layouts)
+                    .push_constant_ranges(std::slice::from_ref(&push)),
+                allocs(),
+            )?;
+            self.sampler = device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::NEAREST)
+                    .min_filter(vk::Filter::NEAREST),
+                allocs(),
+            )?;
+            self.pool = device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(frames)
+                    .pool_sizes(&sizes),
+                allocs(),
+            )?;
+            let layouts = vec![self.set_layout; frames as usize];
+            let sets = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.pool)
+                    .set_layouts(&layouts),
+            )?;
+            self.slots = sets
+                .into_iter()
+                .map(|set| Slot {
+                    buffer: None,
+                    set,
+                    recorded: None,
+                })
+                .collect();
+
+            let module = spirv.create_module(device)?;
+            let stage = vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(module)
+                .name(c"main");
+            let info = vk::ComputePipelineCreateInfo::default()
+                .stage(stage)
+                .layout(self.layout);
+            let made = device.create_compute_pipelines(cache, &[info], allocs());
+            device.destroy_shader_module(module, allocs());
+            self.pipeline = made.map_err(|(_, e)| e)?[0];
+        }
+        return Ok(());
+    }
+
+    /// Record analysing `color`, and `depth` if there is one, into `cmd` as part of `frame`. Both have to be in
+    /// [`TextureState::ShaderRead`](super::hal::TextureState::ShaderRead), and the same size.
+    ///
+    /// # Safety
+    /// `cmd` must be recording, and the GPU done with the frame that last used this frame's slot, like
+    /// [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for. [`AnalysisPass::collect`]
+    /// it first, or its results are lost.
+    pub unsafe fn record(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        color: &VulkanTexture,
+        depth: Option<&VulkanTexture>,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let index = (frame % self.slots.len() as u64) as usize;
+        let vk::Extent2D { width, height } = color.extent;
+        let (across, down) = (width.div_ceil(TILE), height.div_ceil(TILE));
+        let size = HISTOGRAM_BYTES + (across * down) as u64 * PARTIAL_BYTES;
+
+        let slot = &mut self.slots[index];
+        slot.recorded = None;
+        if slot.buffer.as_ref().is_none_or(|b| b.size < size) {
+            if let Some(old) = slot.buffer.take() {
+                // SAFETY: The caller vouches the GPU's done with this slot.
+                unsafe { device.destroy_buffer(old) };
+            }
+            slot.buffer = Some(device.create_buffer(&BufferDesc {
+                size,
+                usage: BufferUsage::STORAGE | BufferUsage::COPY_DST,
+                location: MemoryLocation::Readback,
+            })?);
+        }
+        let buffer = slot.buffer.as_ref().unwrap().buffer;
+
+        let image = |texture: &VulkanTexture| {
+            [vk::DescriptorImageInfo::default()
+                .sampler(self.sampler)
+                .image_view(texture.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        };
+        // Something has to be bound without depth, the shader doesn't look at it.
+        let (color_info, depth_info) = (image(color), image(depth.unwrap_or(color)));
+        let buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .range(vk::WHOLE_SIZE)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(slot.set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&color_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(slot.set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&depth_info),
+            vk::WriteDescriptorSet::default()
+                .dst_set(slot.set)
+                .dst_binding(2)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info),
+        ];
+        let push = [
+            width.to_ne_bytes(),
+            height.to_ne_bytes(),
+            self.range.min_log2.to_ne_bytes(),
+            self.range.inv_range().to_ne_bytes(),
+            (depth.is_some() as u32).to_ne_bytes(),
+        ]
+        .concat();
+        let barrier = |src_stage, src_access, dst_stage, dst_access| {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            // SAFETY: Recording into the caller's command buffer.
+            unsafe {
+                raw.cmd_pipeline_barrier(
+                    cmd,
+                    src_stage,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[],
+                    &[],
+                )
+            };
+        };
+
+        // SAFETY: The set isn't in use, as the caller vouches for the slot, and the rest is recording.
+        unsafe {
+            raw.update_descriptor_sets(&writes, &[]);
+            raw.cmd_fill_buffer(cmd, buffer, 0, HISTOGRAM_BYTES, 0);
+            barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[slot.set],
+                &[],
+            );
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::COMPUTE, 0, &push);
+            raw.cmd_dispatch(cmd, across, down, 1);
+            barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::HOST,
+                vk::AccessFlags::HOST_READ,
+            );
+        }
+        slot.recorded = Some((frame, across * down));
+        return Ok(());
+    }
+
+    /// What the frame that last used `frame`'s slot found, `frame - frames in flight` if it was analysed. Call it
+    /// at the start of `frame`, before [`AnalysisPass::record`] reuses the slot.
+    ///
+    /// # Safety
+    /// The GPU must be done with the frame that last used the slot.
+    pub unsafe fn collect(&mut self, device: &VulkanDevice, frame: u64) -> Option<FrameAnalysis> {
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+        let (recorded, tiles) = slot.recorded.take()?;
+        let buffer = slot.buffer.as_ref()?;
+        let mut bytes = vec![0; (HISTOGRAM_BYTES + tiles as u64 * PARTIAL_BYTES) as usize];
+        // SAFETY: The caller vouches the GPU's done writing it.
+        if let Err(e) = unsafe { device.read_buffer(buffer, 0, &mut bytes) } {
+            log::error!("Couldn't read back frame {recorded}'s analysis: {e}");
+            return None;
+        }
+        return Some(FrameAnalysis::decode(&bytes, tiles, recorded, self.range));
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan.
+        unsafe {
+            for slot in self.slots.drain(..) {
+                if let Some(buffer) = slot.buffer {
+                    device.destroy_buffer(buffer);
+                }
+            }
+            raw.destroy_pipeline(self.pipeline, allocs());
+            raw.destroy_descriptor_pool(self.pool, allocs());
+            raw.destroy_sampler(self.sampler, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+            raw.destroy_descriptor_set_layout(self.set_layout, allocs());
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.pool = vk::DescriptorPool::null();
+        self.sampler = vk::Sampler::null();
+        self.layout = vk::PipelineLayout::null();
+        self.set_layout = vk::DescriptorSetLayout::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FrameAnalysis, HISTOGRAM_BINS, LuminanceRange, PARTIAL_BYTES};
+    use crate::capture::{CaptureFormat, CapturedFrame};
+
+    #[test]
+    pub fn reduces_frames() {
+        let range = LuminanceRange::default();
+        assert_eq!(range.bin(0.0), 0);
+        assert_eq!(range.bin(f32::NAN), 0);
+        assert_eq!(range.bin(1.0), 128);
+        assert_eq!(range.bin(1e9), 255);
+        assert!((range.bin_luminance(128) / 1.0 - 1.0).abs() < 0.05);
+
+        // Half black, a quarter white and a quarter grey; depth drawn in one pixel only.
+        let color = CapturedFrame {
+            width: 4,
+            height: 2,
+            format: CaptureFormat::Rgba8Unorm,
+            data: [[0, 0, 0, 255]; 4]
+                .into_iter()
+                .chain([[255; 4]; 2])
+                .chain([[51, 51, 51, 255]; 2])
+                .flatten()
+                .collect(),
+            frame: 7,
+        };
+        let depth = CapturedFrame {
+            format: CaptureFormat::Depth32Float,
+            data: [0.0f32, 0.0, 0.25, 0.0, 0.0, 0.0, 0.5, 0.0]
+                .iter()
+                .flat_map(|d| d.to_le_bytes())
+                .collect(),
+            ..color
+        };
+        let cpu = FrameAnalysis::of_capture(&color, Some(&depth), range);
+        assert_eq!(cpu.pixels, 8);
+        assert_eq!(cpu.histogram[0], 4);
+        assert_eq!(cpu.histogram[range.bin(1.0)], 2);
+        assert!((cpu.average.r - 0.3).abs() < 1e-4);
+        assert_eq!(cpu.depth, Some((0.25, 0.5)));
+        assert_eq!(cpu.percentile(0.5), range.bin_luminance(0));
+        assert!((cpu.percentile(1.0) - 1.0).abs() < 0.05);
+        // The brightest quarter left out, it's the grey.
+        let metered = cpu.average_luminance(0.5, 0.75);
+        assert!((metered - 0.2).abs() < 0.01, "{metered}");
+
+        // The same frame as two tiles from the shader, the second without depth.
+        let mut bytes = vec![0u8; HISTOGRAM_BINS * 4 + 2 * PARTIAL_BYTES as usize];
+        let mut put = |at: usize, word: u32| bytes[at..at + 4].copy_from_slice(&word.to_ne_bytes());
+        for (bin, &count) in cpu.histogram.iter().enumerate() {
+            put(bin * 4, count);
+        }
+        let tiles = [
+            ([1.0f32, 1.0, 1.0, 5.0], 0.25, 0.5, 2),
+            ([1.4, 1.4, 1.4, 3.0], 0.0, 0.0, 0),
+        ];
+        for (tile, (sum, min, max, drawn)) in tiles.into_iter().enumerate() {
+            let at = HISTOGRAM_BINS * 4 + tile * PARTIAL_BYTES as usize;
+            for (i, c) in sum.into_iter().chain([min, max]).enumerate() {
+                put(at + i * 4, c.to_bits());
+            }
+            put(at + 24, drawn);
+        }
+        let gpu = FrameAnalysis::decode(&bytes, 2, 7, range);
+        assert_eq!(gpu.histogram, cpu.histogram);
+        assert_eq!(gpu.pixels, 8);
+        assert!((gpu.average.g - cpu.average.g).abs() < 1e-4);
+        assert_eq!(gpu.depth, cpu.depth);
+    }
+}
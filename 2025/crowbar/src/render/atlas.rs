@@ -0,0 +1,239 @@
+//! Sprite atlases: many images packed into one texture, so they draw together. The atlas file is JSON next to the
+//! texture, naming each region and saying how it scales:
+//!
+//! ```json
+//! {
+//!     "size": [256, 256],
+//!     "regions": {
+//!         "panel": { "rect": [0, 0, 48, 48], "border": [8, 8, 8, 8] },
+//!         "bricks": { "rect": [48, 0, 32, 32], "fill": "tile" }
+//!     }
+//! }
+//! ```
+//!
+//! `rect` is `x y width height` in texels from the top left. `border` makes the region nine-slice, in texels like
+//! the texture's [`super::texture::SLICE_KEY`], and `fill` is `stretch` or `tile`.
+//!
+//! Flipbook clips (see [`crate::anim::sprite`]) go alongside, as sequences of regions:
+//!
+//! ```json
+//! "clips": {
+//!     "run": { "frames": ["run0", "run1", "run2"], "fps": 12, "mode": "loop", "events": { "step": [0, 2] } },
+//!     "hit": { "frames": [{ "region": "hit0", "duration": 0.05 }, "hit1"], "mode": "once" }
+//! }
+//! ```
+//!
+//! Frames last `1 / fps` seconds (10 fps if unset) unless they say otherwise. `mode` is `once` (the default),
+//! `loop` or `pingpong`, and `events` names frames to fire events on.
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use glam::Vec2;
+use serde::Deserialize;
+
+use super::sprite::{Border, SliceFill, SpriteImage, TextureId};
+use crate::{
+    anim::{
+        LoopMode,
+        sprite::{ClipFrame, SpriteClip},
+    },
+    math::Rect,
+    platform,
+};
+
+#[derive(Deserialize)]
+struct AtlasFile {
+    size: [u32; 2],
+    regions: HashMap<String, RegionFile>,
+    #[serde(default)]
+    clips: HashMap<String, ClipFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FrameFile {
+    Region(String),
+    Timed { region: String, duration: f32 },
+}
+
+fn default_fps() -> f32 {
+    10.0
+}
+
+#[derive(Deserialize)]
+struct ClipFile {
+    frames: Vec<FrameFile>,
+    #[serde(default = "default_fps")]
+    fps: f32,
+    #[serde(default)]
+    mode: LoopMode,
+    #[serde(default)]
+    events: HashMap<String, Vec<usize>>,
+}
+
+#[derive(Deserialize)]
+struct RegionFile {
+    rect: [u32; 4],
+    #[serde(default)]
+    border: Option<[f32; 4]>,
+    #[serde(default)]
+    fill: SliceFill,
+}
+
+pub struct SpriteAtlas {
+    texture: TextureId,
+    regions: HashMap<String, SpriteImage>,
+    clips: HashMap<String, Arc<SpriteClip>>,
+}
+
+impl SpriteAtlas {
+    /// Parse an atlas file for `texture`.
+    pub fn parse(json: &[u8], texture: TextureId) -> io::Result<SpriteAtlas> {
+        let file: AtlasFile = serde_json::from_slice(json)?;
+        let size = Vec2::new(file.size[0] as f32, file.size[1] as f32);
+        let mut regions = HashMap::new();
+        for (name, region) in file.regions {
+            let [x, y, w, h] = region.rect;
+            if x + w > file.size[0] || y + h > file.size[1] || w == 0 || h == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Atlas region {name} isn't inside the atlas"),
+                ));
+            }
+            let texels = Vec2::new(w as f32, h as f32);
+            let image = SpriteImage {
+                texture,
+                uv: Rect::from_pos_size(Vec2::new(x as f32, y as f32) / size, texels / size),
+                size: texels,
+                border: region.border.map(|[left, top, right, bottom]| Border {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                }),
+                fill: region.fill,
+            };
+            regions.insert(name, image);
+        }
+
+        let mut clips = HashMap::new();
+        for (name, clip) in file.clips {
+            let mut frames = Vec::new();
+            for frame in clip.frames {
+                let (region, duration) = match frame {
+                    FrameFile::Region(region) => (region, 1.0 / clip.fps.max(f32::EPSILON)),
+                    FrameFile::Timed { region, duration } => (region, duration),
+                };
+                let Some(image) = regions.get(&region) else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Clip {name} uses missing region {region}"),
+                    ));
+                };
+                frames.push(ClipFrame {
+                    image: *image,
+                    duration,
+                });
+            }
+            if frames.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Clip {name} has no frames"),
+                ));
+            }
+            let mut events: Vec<_> = clip
+                .events
+                .into_iter()
+                .flat_map(|(event, at)| at.into_iter().map(move |f| (f, event.clone())))
+                .filter(|(f, _)| *f < frames.len())
+                .collect();
+            events.sort();
+            let clip = SpriteClip {
+                frames,
+                mode: clip.mode,
+                events,
+            };
+            clips.insert(name, Arc::new(clip));
+        }
+        return Ok(SpriteAtlas {
+            texture,
+            regions,
+            clips,
+        });
+    }
+
+    /// Load `name`, a path without the `.atlas.json`, through [`platform::read_asset`].
+    pub fn load(name: &str, texture: TextureId) -> io::Result<SpriteAtlas> {
+        return SpriteAtlas::parse(
+            &platform::read_asset(&format!("{name}.atlas.json"))?,
+            texture,
+        );
+    }
+
+    pub fn texture(&self) -> TextureId {
+        self.texture
+    }
+
+    pub fn get(&self, region: &str) -> Option<&SpriteImage> {
+        self.regions.get(region)
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = (&str, &SpriteImage)> {
+        self.regions
+            .iter()
+            .map(|(name, image)| (name.as_str(), image))
+    }
+
+    /// A flipbook clip, to hand to a [`crate::anim::sprite::SpriteAnimation`].
+    pub fn clip(&self, name: &str) -> Option<&Arc<SpriteClip>> {
+        self.clips.get(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec2;
+
+    use super::SpriteAtlas;
+    use crate::{
+        anim::LoopMode,
+        render::sprite::{SliceFill, TextureId},
+    };
+
+    #[test]
+    pub fn parses_regions() {
+        let json = br#"{
+            "size": [256, 128],
+            "regions": {
+                "panel": { "rect": [0, 0, 48, 48], "border": [8, 8, 8, 8] },
+                "bricks": { "rect": [64, 32, 32, 32], "fill": "tile" }
+            },
+            "clips": {
+                "crumble": {
+                    "frames": ["bricks", { "region": "panel", "duration": 0.5 }],
+                    "fps": 4,
+                    "mode": "pingpong",
+                    "events": { "dust": [1, 7] }
+                }
+            }
+        }"#;
+        let atlas = SpriteAtlas::parse(json, TextureId(7)).unwrap();
+        let panel = atlas.get("panel").unwrap();
+        assert_eq!(panel.border.unwrap().left, 8.0);
+        assert_eq!(panel.fill, SliceFill::Stretch);
+        let bricks = atlas.get("bricks").unwrap();
+        assert_eq!(bricks.uv.min, Vec2::new(0.25, 0.25));
+        assert_eq!(bricks.uv.max, Vec2::new(0.375, 0.5));
+        assert_eq!(bricks.fill, SliceFill::Tile);
+        assert_eq!(bricks.texture, TextureId(7));
+        let crumble = atlas.clip("crumble").unwrap();
+        assert_eq!(crumble.mode, LoopMode::PingPong);
+        assert_eq!(crumble.frames[0].image, *bricks);
+        assert_eq!(crumble.duration(), 0.75);
+        // The event past the last frame is dropped.
+        assert_eq!(crumble.events, [(1, "dust".to_owned())]);
+
+        let outside = br#"{ "size": [16, 16], "regions": { "big": { "rect": [8, 8, 16, 16] } } }"#;
+        assert!(SpriteAtlas::parse(outside, TextureId(7)).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Background GPU work too heavy for one frame, like GI bakes or terrain generation, cut into slices so each frame
+//! only takes on what fits in its budget.
+//!
+//! A job keeps its own state between slices and records one at a time. What a slice costs is learnt from GPU
+//! timings fed back through [`BackgroundWork::on_timed`], and each frame only gets as many slices as its budget
+//! covers. Unused budget carries over, up to the cost of the biggest slice, so a slice that's too big for any one
+//! frame still gets a turn every few frames rather than never.
+
+use std::time::Duration;
+
+/// How much of a new measurement goes into a job's slice cost.
+const COST_SMOOTHING: f64 = 0.25;
+/// The least a slice is taken to cost, so one timed at nothing doesn't fit in the budget endlessly.
+const MIN_SLICE_COST: Duration = Duration::from_micros(1);
+
+/// Work spread over frames. `E` is whatever slices get recorded into, usually a hal command encoder.
+pub trait GpuJob<E> {
+    fn name(&self) -> &str;
+
+    /// Record the next slice. True once that was the last of it.
+    fn record_slice(&mut self, encoder: &mut E) -> bool;
+
+    /// How far along it is, `0.0..=1.0`, if it knows.
+    fn progress(&self) -> Option<f32> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct JobId(u64);
+
+struct JobEntry<E> {
+    id: JobId,
+    job: Box<dyn GpuJob<E>>,
+    /// GPU time per slice, once something's been timed.
+    cost: Option<Duration>,
+    slices: u64,
+}
+
+/// What went into a frame, to match up with its GPU timing once that comes back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameSlices {
+    /// Slices per job, with the cost each was expected to have.
+    pub jobs: Vec<(JobId, u32, Duration)>,
+    /// Jobs that finished this frame.
+    pub finished: Vec<JobId>,
+}
+
+impl FrameSlices {
+    pub fn estimated(&self) -> Duration {
+        self.jobs.iter().map(|(_, n, cost)| *cost * *n).sum()
+    }
+}
+
+pub struct BackgroundWork<E> {
+    jobs: Vec<JobEntry<E>>,
+    next_id: u64,
+    budget: Duration,
+    /// Budget saved up from frames that didn't use theirs.
+    credit: Duration,
+}
+
+impl<E> BackgroundWork<E> {
+    /// `budget` is the GPU time per frame background work can have.
+    pub fn new(budget: Duration) -> BackgroundWork<E> {
+        BackgroundWork {
+            jobs: Vec::new(),
+            next_id: 0,
+            budget,
+            credit: Duration::ZERO,
+        }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: Duration) {
+        self.budget = budget;
+    }
+
+    /// Jobs run in the order they were added, one after another.
+    pub fn add(&mut self, job: impl GpuJob<E> + 'static) -> JobId {
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        self.jobs.push(JobEntry {
+            id,
+            job: Box::new(job),
+            cost: None,
+            slices: 0,
+        });
+        return id;
+    }
+
+    pub fn cancel(&mut self, id: JobId) {
+        self.jobs.retain(|j| j.id != id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Name, progress and slices recorded so far of every job still going.
+    pub fn jobs(&self) -> impl Iterator<Item = (JobId, &str, Option<f32>, u64)> {
+        self.jobs
+            .iter()
+            .map(|j| (j.id, j.job.name(), j.job.progress(), j.slices))
+    }
+
+    /// Record this frame's slices into `encoder`.
+    pub fn record(&mut self, encoder: &mut E) -> FrameSlices {
+        if self.budget.is_zero() {
+            return FrameSlices::default();
+        }
+        // Untimed jobs are guessed at a whole frame's budget, until their first slice comes back.
+        let biggest = self
+            .jobs
+            .iter()
+            .map(|j| j.cost.unwrap_or(self.budget))
+            .max()
+            .unwrap_or_default();
+        self.credit = (self.credit + self.budget).min(biggest.max(self.budget));
+
+        let mut frame = FrameSlices::default();
+        for entry in &mut self.jobs {
+            let cost = entry.cost.unwrap_or(self.budget);
+            let mut count = 0;
+            let mut done = false;
+            while !done && cost <= self.credit {
+                self.credit -= cost;
+                done = entry.job.record_slice(encoder);
+                entry.slices += 1;
+                count += 1;
+            }
+            if count > 0 {
+                frame.jobs.push((entry.id, count, cost));
+            }
+            if done {
+                log::info!(
+                    "Background job {} done in {} slices",
+                    entry.job.name(),
+                    entry.slices
+                );
+                frame.finished.push(entry.id);
+            } else {
+                // Later jobs wait their turn, or they'd take slices this one couldn't fit.
+                break;
+            }
+        }
+        self.jobs.retain(|j| !frame.finished.contains(&j.id));
+        return frame;
+    }
+
+    /// Feed back how long a frame's slices took on the GPU, to size later frames by.
+    /// Spread over the jobs in proportion to what they were expected to cost.
+    pub fn on_timed(&mut self, frame: &FrameSlices, measured: Duration) {
+        let estimated = frame.estimated();
+        if estimated.is_zero() {
+            return;
+        }
+        let scale = measured.as_secs_f64() / estimated.as_secs_f64();
+
+        for &(id, _, expected) in &frame.jobs {
+            let Some(entry) = self.jobs.iter_mut().find(|j| j.id == id) else {
+                continue;
+            };
+            let actual = expected.mul_f64(scale);
+            let cost = match entry.cost {
+                Some(cost) => cost.mul_f64(1.0 - COST_SMOOTHING) + actual.mul_f64(COST_SMOOTHING),
+                None => actual,
+            };
+            entry.cost = Some(cost.max(MIN_SLICE_COST));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{BackgroundWork, GpuJob};
+
+    struct Bake {
+        left: u32,
+    }
+
+    impl GpuJob<Vec<&'static str>> for Bake {
+        fn name(&self) -> &str {
+            "bake"
+        }
+
+        fn record_slice(&mut self, encoder: &mut Vec<&'static str>) -> bool {
+            encoder.push("bake");
+            self.left -= 1;
+            return self.left == 0;
+        }
+    }
+
+    #[test]
+    pub fn stays_in_budget() {
+        let ms = Duration::from_millis;
+        let mut work = BackgroundWork::new(ms(2));
+        let bake = work.add(Bake { left: 10 });
+        let mut encoder = Vec::new();
+
+        // Untimed, so one slice to find out.
+        let frame = work.record(&mut encoder);
+        assert_eq!(frame.jobs, vec![(bake, 1, ms(2))]);
+        work.on_timed(&frame, Duration::from_micros(500));
+
+        // Four fit now.
+        let frame = work.record(&mut encoder);
+        assert_eq!(frame.jobs[0].1, 4);
+        assert!(frame.estimated() <= ms(2));
+        // Turns out they were slower, so fewer next time.
+        work.on_timed(&frame, ms(8));
+        let frame = work.record(&mut encoder);
+        assert!(frame.jobs[0].1 < 4);
+
+        while !work.is_empty() {
+            work.record(&mut encoder);
+        }
+        assert_eq!(encoder.len(), 10);
+    }
+
+    #[test]
+    pub fn big_slices_still_run() {
+        let ms = Duration::from_millis;
+        let mut work = BackgroundWork::new(ms(2));
+        work.add(Bake { left: 3 });
+        let mut encoder = Vec::new();
+
+        let frame = work.record(&mut encoder);
+        work.on_timed(&frame, ms(5));
+        // A 5ms slice runs every third frame of 2ms budgets.
+        let counts: Vec<usize> = (0..6)
+            .map(|_| work.record(&mut encoder).jobs.len())
+            .collect();
+        assert_eq!(counts, vec![0, 0, 1, 0, 0, 1]);
+        assert!(work.is_empty());
+    }
+
+    #[test]
+    pub fn free_slices_and_no_budget() {
+        let mut work = BackgroundWork::new(Duration::from_millis(2));
+        work.add(Bake { left: 100_000 });
+        let mut encoder = Vec::new();
+
+        // Timed at nothing, so it's taken as the least a slice can cost.
+        let frame = work.record(&mut encoder);
+        work.on_timed(&frame, Duration::ZERO);
+        let frame = work.record(&mut encoder);
+        assert_eq!(frame.jobs[0].1, 2000);
+
+        work.set_budget(Duration::ZERO);
+        assert_eq!(work.record(&mut encoder).jobs, vec![]);
+        assert_eq!(encoder.len(), 2001);
+    }
+}
@@ -0,0 +1,364 @@
+//! Bindless descriptors: one big table of every texture and storage buffer, bound once, that shaders index into with
+//! handles instead of having sets bound per draw.
+//!
+//! It's opt in, through [`Renderer::enable_bindless`](super::renderer::Renderer::enable_bindless), and needs the
+//! descriptor indexing features (`VK_EXT_descriptor_indexing`, core since Vulkan 1.2), which
+//! [`DeviceCaps::bindless`] says whether the device has. The table's descriptors are partially bound and updated
+//! after bind, so registering something while a frame that uses the table is in flight is fine: only slots nothing
+//! in flight can be reading get written. A handle stays the same for as long as it's registered, and
+//! [`BindlessTable::replace_texture`] points it at something else, like a texture that's been reloaded. Unregistered
+//! handles are only reused once the frames in flight are done with them.
+//!
+//! Shaders include [`GLSL`] and look textures up with `bindless_texture(handle)`.
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    deletion::DeletionQueue,
+    descriptors::{LayoutBinding, LayoutCache, LayoutDesc},
+    hal::{
+        caps::DeviceCaps,
+        vulkan::{VulkanBuffer, VulkanTexture},
+    },
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// Declarations for shaders using the table.
+pub const GLSL: &str = include_str!("bindless/bindless.glsl");
+
+pub const TEXTURE_BINDING: u32 = 0;
+pub const BUFFER_BINDING: u32 = 1;
+/// The most of each there'll be, whatever the device allows, so the table doesn't take more memory than it needs.
+pub const MAX_TEXTURES: u32 = 16384;
+pub const MAX_BUFFERS: u32 = 4096;
+
+/// Handles for one kind of descriptor.
+struct Slots {
+    capacity: u32,
+    /// Never handed out, from here up.
+    next: u32,
+    free: Vec<u32>,
+    retired: DeletionQueue<u32>,
+}
+
+impl Slots {
+    fn new(capacity: u32, frames_in_flight: u32) -> Slots {
+        Slots {
+            capacity,
+            next: 0,
+            free: Vec::new(),
+            retired: DeletionQueue::new(frames_in_flight),
+        }
+    }
+
+    fn take(&mut self) -> Option<u32> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        if self.next == self.capacity {
+            return None;
+        }
+        self.next += 1;
+        return Some(self.next - 1);
+    }
+
+    /// Handles registered.
+    fn used(&self) -> u32 {
+        self.next - self.free.len() as u32 - self.retired.len() as u32
+    }
+
+    fn recycle(&mut self, frame: u64) {
+        self.free.extend(self.retired.ready(frame));
+    }
+
+    fn set_frames_in_flight(&mut self, frames: u32) {
+        self.free.extend(self.retired.drain());
+        self.retired = DeletionQueue::new(frames);
+    }
+}
+
+/// The table, and the handles handed out of it.
+pub struct BindlessTable {
+    device: ash::Device,
+    /// Belongs to the layout cache it came from.
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    textures: Slots,
+    buffers: Slots,
+}
+
+impl BindlessTable {
+    /// A table as big as the device allows, up to [`MAX_TEXTURES`] and [`MAX_BUFFERS`], visible to every stage.
+    /// Fails with `ERROR_FEATURE_NOT_PRESENT` on devices without bindless.
+    ///
+    /// # Safety
+    /// `caps` must be `device`'s, with the bindless features enabled, and `layouts` must be caching for `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        caps: &DeviceCaps,
+        layouts: &mut LayoutCache,
+        frames_in_flight: u32,
+    ) -> VkResult<BindlessTable> {
+        let limits = caps.bindless.ok_or(vk::Result::ERROR_FEATURE_NOT_PRESENT)?;
+        let textures = limits.max_textures.min(MAX_TEXTURES);
+        let buffers = limits.max_storage_buffers.min(MAX_BUFFERS);
+        let flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
+        let desc = LayoutDesc::new([
+            LayoutBinding::new(
+                TEXTURE_BINDING,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                textures,
+                vk::ShaderStageFlags::ALL,
+            )
+            .flags(flags),
+            LayoutBinding::new(
+                BUFFER_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                buffers,
+                vk::ShaderStageFlags::ALL,
+            )
+            .flags(flags),
+        ])
+        .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
+        // SAFETY: Passed on to the caller.
+        let layout = unsafe { layouts.get(device, &desc)? };
+
+        let sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: textures,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: buffers,
+            },
+        ];
+        // SAFETY: The layout fits the pool, and the pool's destroyed if the set can't be made.
+        unsafe {
+            let pool = device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                    .max_sets(1)
+                    .pool_sizes(&sizes),
+                allocs(),
+            )?;
+            let set = match device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&[layout]),
+            ) {
+                Ok(sets) => sets[0],
+                Err(e) => {
+                    device.destroy_descriptor_pool(pool, allocs());
+                    return Err(e);
+                }
+            };
+            return Ok(BindlessTable {
+                device: device.clone(),
+                layout,
+                pool,
+                set,
+                textures: Slots::new(textures, frames_in_flight),
+                buffers: Slots::new(buffers, frames_in_flight),
+            });
+        }
+    }
+
+    /// For pipeline layouts, at whichever set the shaders have the table.
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    /// How many textures can be registered at once.
+    pub fn texture_capacity(&self) -> u32 {
+        self.textures.capacity
+    }
+
+    pub fn buffer_capacity(&self) -> u32 {
+        self.buffers.capacity
+    }
+
+    /// Textures registered.
+    pub fn textures(&self) -> u32 {
+        self.textures.used()
+    }
+
+    pub fn buffers(&self) -> u32 {
+        self.buffers.used()
+    }
+
+    fn write_texture(&self, handle: u32, texture: &VulkanTexture, sampler: vk::Sampler) {
+        let image = [vk::DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(texture.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(TEXTURE_BINDING)
+            .dst_array_element(handle)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image);
+        // SAFETY: Update after bind, and nothing in flight reads this slot, or it'd still be retired.
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+    }
+
+    /// Put `texture` in the table, sampled with `sampler`, for shaders to find with the handle. `None` once the
+    /// table's full. It has to be in [`TextureState::ShaderRead`](super::hal::TextureState::ShaderRead) whenever
+    /// something reads it.
+    pub fn register_texture(
+        &mut self,
+        texture: &VulkanTexture,
+        sampler: vk::Sampler,
+    ) -> Option<u32> {
+        let handle = self.textures.take()?;
+        self.write_texture(handle, texture, sampler);
+        return Some(handle);
+    }
+
+    /// Point `handle` at another texture.
+    ///
+    /// # Safety
+    /// No frame in flight can be reading `handle`, as the descriptor changes under it.
+    pub unsafe fn replace_texture(
+        &mut self,
+        handle: u32,
+        texture: &VulkanTexture,
+        sampler: vk::Sampler,
+    ) {
+        self.write_texture(handle, texture, sampler);
+    }
+
+    /// Put the whole of `buffer` in the table as a storage buffer. `None` once the table's full.
+    pub fn register_buffer(&mut self, buffer: &VulkanBuffer) -> Option<u32> {
+        let handle = self.buffers.take()?;
+        let info = [vk::DescriptorBufferInfo::default()
+            .buffer(buffer.buffer)
+            .range(vk::WHOLE_SIZE)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(BUFFER_BINDING)
+            .dst_array_element(handle)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&info);
+        // SAFETY: As for textures.
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+        return Some(handle);
+    }
+
+    /// `handle` was last used in `frame`, and can go to something else once that's done with.
+    pub fn unregister_texture(&mut self, frame: u64, handle: u32) {
+        self.textures.retired.retire(frame, handle);
+    }
+
+    pub fn unregister_buffer(&mut self, frame: u64, handle: u32) {
+        self.buffers.retired.retire(frame, handle);
+    }
+
+    /// Start `frame`, taking back handles the frames in flight are done with.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.textures.recycle(frame);
+        self.buffers.recycle(frame);
+    }
+
+    /// After the frames in flight changed, once the GPU's gone idle.
+    pub fn set_frames_in_flight(&mut self, frames: u32) {
+        self.textures.set_frames_in_flight(frames);
+        self.buffers.set_frames_in_flight(frames);
+    }
+
+    /// Bind the table as set `set` of `layout`.
+    ///
+    /// # Safety
+    /// `cmd` must be recording, and `layout` have the table's layout at `set`.
+    pub unsafe fn bind(
+        &self,
+        cmd: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        set: u32,
+    ) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.device
+                .cmd_bind_descriptor_sets(cmd, bind_point, layout, set, &[self.set], &[])
+        };
+    }
+}
+
+impl Drop for BindlessTable {
+    fn drop(&mut self) {
+        // SAFETY: Whoever owns us waits for the GPU before dropping. The set goes with its pool, and the layout
+        // with its cache.
+        unsafe { self.device.destroy_descriptor_pool(self.pool, allocs()) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{BindlessTable, Slots};
+    use crate::{
+        render::{
+            descriptors::LayoutCache,
+            hal::{BufferDesc, BufferUsage, Device, MemoryLocation},
+        },
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn hands_out_stable_handles() {
+        let mut slots = Slots::new(3, 2);
+        assert_eq!([slots.take(), slots.take()], [Some(0), Some(1)]);
+        slots.retired.retire(10, 0);
+        assert_eq!(slots.used(), 1);
+        // Not back until frame 10's done, two frames on.
+        slots.recycle(11);
+        assert_eq!([slots.take(), slots.take()], [Some(2), None]);
+        slots.recycle(12);
+        assert_eq!(slots.take(), Some(0));
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let raw = device.raw();
+        let mut layouts = LayoutCache::new();
+        // SAFETY: The caps are the device's, which enables bindless whenever it has it.
+        let table = unsafe { BindlessTable::new(raw, device.caps(), &mut layouts, 2) };
+        let mut table = match table {
+            Ok(table) => table,
+            Err(vk::Result::ERROR_FEATURE_NOT_PRESENT) => {
+                assert!(device.caps().bindless.is_none());
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        let buffer = device
+            .create_buffer(&BufferDesc {
+                size: 256,
+                usage: BufferUsage::STORAGE,
+                location: MemoryLocation::Device,
+            })
+            .unwrap();
+        assert_eq!(table.register_buffer(&buffer), Some(0));
+        assert_eq!(table.register_buffer(&buffer), Some(1));
+        assert_eq!(table.buffers(), 2);
+        drop(table);
+        // SAFETY: Nothing was submitted.
+        unsafe {
+            device.destroy_buffer(buffer);
+            layouts.destroy(raw);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+// The bindless table's declarations, see bindless.rs. Define BINDLESS_SET before including it to bind the table
+// somewhere other than set 0.
+#extension GL_EXT_nonuniform_qualifier : require
+
+#ifndef BINDLESS_SET
+#define BINDLESS_SET 0
+#endif
+
+layout(set = BINDLESS_SET, binding = 0) uniform sampler2D bindless_textures[];
+
+// Storage buffers hold whatever each shader wants, so they're declared where they're used, like:
+//   layout(std430, set = BINDLESS_SET, binding = 1) readonly buffer Lights { Light lights[]; } bindless_lights[];
+// and read with bindless_lights[nonuniformEXT(handle)].lights[i].
+
+// The texture with `handle`, from register_texture. Handles can differ between invocations.
+#define bindless_texture(handle) bindless_textures[nonuniformEXT(handle)]
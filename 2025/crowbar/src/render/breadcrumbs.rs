@@ -0,0 +1,371 @@
+//! GPU crash breadcrumbs. Markers get written around each pass as the GPU reaches them,
+//! so after a device loss we can say which pass it died in instead of just "it hung".
+//!
+//! Markers are recorded outside render passes, which is where pass boundaries are anyway.
+
+use std::{
+    ffi::{CStr, c_void},
+    fmt::Write as _,
+    ptr,
+};
+
+use ash::{amd, nv, prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+use crate::consts::BUILD;
+
+/// Passes per frame we can track. Any past this go unmarked.
+pub const MAX_PASSES: usize = 256;
+
+enum Backend {
+    /// `VK_NV_device_diagnostic_checkpoints`. The driver keeps track, we just ask it after a loss.
+    NvCheckpoints(nv::device_diagnostic_checkpoints::Device),
+    /// `VK_AMD_buffer_marker`. Pipelined writes into a buffer that outlives the device.
+    AmdMarkers(amd::buffer_marker::Device),
+    /// `vkCmdFillBuffer`, for everyone else. It's a transfer, so it can't say much about
+    /// where in the pipeline things stopped, only which passes the GPU got past.
+    FillBuffer,
+}
+
+/// The device extensions breadcrumbs would like, out of the ones `physical_device` has available. Enable these at
+/// device creation. `VK_AMD_device_coherent_memory` is only wanted if its feature's there, and needs that enabling
+/// too.
+///
+/// # Safety
+/// `physical_device` has to be from `instance`.
+pub unsafe fn wanted_extensions(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    available: &[vk::ExtensionProperties],
+) -> Vec<&'static CStr> {
+    let has = |name: &CStr| {
+        available
+            .iter()
+            .any(|e| e.extension_name_as_c_str() == Ok(name))
+    };
+
+    let mut wanted: Vec<&'static CStr> = [
+        nv::device_diagnostic_checkpoints::NAME,
+        amd::buffer_marker::NAME,
+    ]
+    .into_iter()
+    .find(|name| has(name))
+    .into_iter()
+    .collect();
+
+    if has(amd::device_coherent_memory::NAME) {
+        let mut coherent = vk::PhysicalDeviceCoherentMemoryFeaturesAMD::default();
+        // SAFETY: A query, on a device the caller vouches for.
+        unsafe {
+            instance.get_physical_device_features2(
+                physical_device,
+                &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut coherent),
+            );
+        }
+        if coherent.device_coherent_memory == vk::TRUE {
+            wanted.push(amd::device_coherent_memory::NAME);
+        }
+    }
+    return wanted;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PassMarker {
+    slot: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassState {
+    NotReached,
+    /// Started but didn't finish. If there's one of these, it's the prime suspect.
+    Started,
+    Finished,
+}
+
+fn pass_state(begin: u32, end: u32, expected: u32) -> PassState {
+    if end == expected {
+        PassState::Finished
+    } else if begin == expected {
+        PassState::Started
+    } else {
+        PassState::NotReached
+    }
+}
+
+/// Describe how far the GPU got through a frame, given the begin/end pairs from the marker buffer.
+fn describe_frame(out: &mut String, frame: u64, names: &[&'static str], markers: &[u32]) {
+    let expected = frame as u32;
+    writeln!(out, "Frame {frame}:").unwrap();
+
+    for (i, name) in names.iter().enumerate() {
+        let state = pass_state(markers[i * 2], markers[i * 2 + 1], expected);
+        let note = match state {
+            PassState::Started => "  <-- started, never finished",
+            _ => "",
+        };
+        writeln!(out, "  {name}: {state:?}{note}").unwrap();
+    }
+}
+
+struct FrameSlot {
+    frame: u64,
+    names: Vec<&'static str>,
+}
+
+pub struct Breadcrumbs {
+    backend: Backend,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    /// Persistently mapped, `MAX_PASSES * 2` u32s per frame in flight.
+    mapped: *mut u32,
+    frames: Vec<FrameSlot>,
+    current: usize,
+}
+
+impl Breadcrumbs {
+    /// `enabled` is the device extensions the device was created with, see [`wanted_extensions`].
+    ///
+    /// # Safety
+    /// The device must outlive this, and [`Breadcrumbs::destroy`] must be called before it's destroyed.
+    pub unsafe fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        enabled: &[&CStr],
+        frames_in_flight: usize,
+    ) -> VkResult<Breadcrumbs> {
+        let backend = if enabled.contains(&nv::device_diagnostic_checkpoints::NAME) {
+            Backend::NvCheckpoints(nv::device_diagnostic_checkpoints::Device::new(
+                instance, device,
+            ))
+        } else if enabled.contains(&amd::buffer_marker::NAME) {
+            Backend::AmdMarkers(amd::buffer_marker::Device::new(instance, device))
+        } else {
+            Backend::FillBuffer
+        };
+
+        let size = (frames_in_flight * MAX_PASSES * 2 * size_of::<u32>()) as vk::DeviceSize;
+        let allocs = Some(&*VK_ALLOCATOR_CALLBACKS);
+
+        // SAFETY: Plain resource creation on a device the caller vouches for.
+        unsafe {
+            let buffer = device.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(size)
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                allocs,
+            )?;
+            let reqs = device.get_buffer_memory_requirements(buffer);
+            let props = instance.get_physical_device_memory_properties(physical_device);
+
+            // Host visible so we can read it after a loss. Device coherent (AMD) if the device was made with it,
+            // otherwise writes may still be sitting in a GPU cache when the device goes down. Those types can't
+            // be allocated from at all without it.
+            let host =
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+            let coherent = vk::MemoryPropertyFlags::DEVICE_COHERENT_AMD;
+            let find = |flags: vk::MemoryPropertyFlags| {
+                (0..props.memory_type_count).find(|&i| {
+                    let theirs = props.memory_types[i as usize].property_flags;
+                    reqs.memory_type_bits & (1 << i) != 0
+                        && theirs.contains(flags)
+                        && (flags.contains(coherent) || !theirs.contains(coherent))
+                })
+            };
+            let found = match enabled.contains(&amd::device_coherent_memory::NAME) {
+                true => find(host | coherent).or_else(|| find(host)),
+                false => find(host),
+            };
+            let Some(memory_type) = found else {
+                device.destroy_buffer(buffer, allocs);
+                return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+            };
+
+            let memory = device.allocate_memory(
+                &vk::MemoryAllocateInfo::default()
+                    .allocation_size(reqs.size)
+                    .memory_type_index(memory_type),
+                allocs,
+            )?;
+            device.bind_buffer_memory(buffer, memory, 0)?;
+            let mapped =
+                device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())? as *mut u32;
+            ptr::write_bytes(mapped, 0, size as usize / size_of::<u32>());
+
+            return Ok(Breadcrumbs {
+                backend,
+                buffer,
+                memory,
+                mapped,
+                frames: (0..frames_in_flight)
+                    .map(|_| FrameSlot {
+                        frame: 0,
+                        names: Vec::new(),
+                    })
+                    .collect(),
+                current: 0,
+            });
+        }
+    }
+
+    fn markers(&self, slot: usize) -> &[u32] {
+        // SAFETY: The mapping covers every frame slot, and lives as long as we do.
+        unsafe {
+            std::slice::from_raw_parts(self.mapped.add(slot * MAX_PASSES * 2), MAX_PASSES * 2)
+        }
+    }
+
+    /// Start tracking a new frame. The previous use of its slot must be done on the GPU,
+    /// which it is once its frame in flight fence has been waited on.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.current = frame as usize % self.frames.len();
+        let slot = &mut self.frames[self.current];
+        slot.frame = frame;
+        slot.names.clear();
+    }
+
+    fn write(&self, device: &ash::Device, cmd: vk::CommandBuffer, index: usize, end: bool) {
+        let value = self.frames[self.current].frame as u32;
+        let offset = ((self.current * MAX_PASSES * 2 + index) * size_of::<u32>()) as vk::DeviceSize;
+        let stage = match end {
+            false => vk::PipelineStageFlags::TOP_OF_PIPE,
+            true => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        };
+
+        // SAFETY: The caller is recording `cmd`, outside a render pass.
+        unsafe {
+            match &self.backend {
+                Backend::NvCheckpoints(nv) => {
+                    // The marker is an opaque pointer sized value, we stuff the slot index and frame in it.
+                    let marker = ((value as usize) << 16 | index) as *const c_void;
+                    nv.cmd_set_checkpoint(cmd, marker);
+                }
+                Backend::AmdMarkers(amd) => {
+                    amd.cmd_write_buffer_marker(cmd, stage, self.buffer, offset, value)
+                }
+                Backend::FillBuffer => {
+                    // Transfers don't wait for anything else by themselves, so without this the end marker
+                    // could land before the pass's work is done.
+                    if end {
+                        device.cmd_pipeline_barrier(
+                            cmd,
+                            vk::PipelineStageFlags::ALL_COMMANDS,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[],
+                        );
+                    }
+                    device.cmd_fill_buffer(cmd, self.buffer, offset, 4, value)
+                }
+            }
+        }
+    }
+
+    /// Mark a pass as started. Returns `None` past [`MAX_PASSES`] passes in a frame.
+    pub fn begin_pass(
+        &mut self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        name: &'static str,
+    ) -> Option<PassMarker> {
+        let names = &mut self.frames[self.current].names;
+        if names.len() == MAX_PASSES {
+            return None;
+        }
+        let slot = names.len();
+        names.push(name);
+
+        self.write(device, cmd, slot * 2, false);
+        return Some(PassMarker { slot });
+    }
+
+    pub fn end_pass(
+        &mut self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        marker: Option<PassMarker>,
+    ) {
+        if let Some(marker) = marker {
+            self.write(device, cmd, marker.slot * 2 + 1, true);
+        }
+    }
+
+    /// After `VK_ERROR_DEVICE_LOST`, describe how far the GPU got through each frame in flight.
+    pub fn report(&self, queue: vk::Queue) -> String {
+        let mut out = format!("GPU breadcrumbs after device loss, {BUILD}:\n");
+
+        if let Backend::NvCheckpoints(nv) = &self.backend {
+            // SAFETY: Querying checkpoints is explicitly allowed after a device loss.
+            let data = unsafe {
+                let mut data =
+                    vec![vk::CheckpointDataNV::default(); nv.get_queue_checkpoint_data_len(queue)];
+                nv.get_queue_checkpoint_data(queue, &mut data);
+                data
+            };
+
+            for d in data {
+                let marker = d.p_checkpoint_marker as usize;
+                let (frame, index) = (marker >> 16, marker & 0xffff);
+                let name = self
+                    .frames
+                    .iter()
+                    .find(|f| f.frame as u32 as usize == frame)
+                    .and_then(|f| f.names.get(index / 2))
+                    .copied()
+                    .unwrap_or("?");
+                let edge = if index % 2 == 0 { "start" } else { "end" };
+                writeln!(
+                    out,
+                    "  {:?} last reached the {edge} of {name} (frame {frame})",
+                    d.stage
+                )
+                .unwrap();
+            }
+            return out;
+        }
+
+        let mut frames: Vec<usize> = (0..self.frames.len()).collect();
+        frames.sort_by_key(|&i| self.frames[i].frame);
+        for i in frames {
+            let slot = &self.frames[i];
+            if !slot.names.is_empty() {
+                describe_frame(&mut out, slot.frame, &slot.names, self.markers(i));
+            }
+        }
+        return out;
+    }
+
+    /// # Safety
+    /// The GPU must be done with the buffer (or the device lost).
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        let allocs = Some(&*VK_ALLOCATOR_CALLBACKS);
+        // SAFETY: Up to the caller.
+        unsafe {
+            device.destroy_buffer(self.buffer, allocs);
+            device.free_memory(self.memory, allocs);
+        }
+        self.mapped = ptr::null_mut();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::describe_frame;
+
+    #[test]
+    pub fn finds_the_hung_pass() {
+        let names = ["shadows", "gbuffer", "lighting", "post"];
+        // shadows finished, gbuffer started, the rest are left over from an older frame.
+        let markers = [7, 7, 7, 0, 3, 3, 3, 3];
+
+        let mut out = String::new();
+        describe_frame(&mut out, 7, &names, &markers);
+
+        assert!(out.contains("shadows: Finished\n"));
+        assert!(out.contains("gbuffer: Started  <-- started, never finished"));
+        assert!(out.contains("lighting: NotReached"));
+    }
+}
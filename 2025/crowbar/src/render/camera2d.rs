@@ -0,0 +1,229 @@
+//! A camera for 2D worlds: panning, zooming and rotating over a plane, instead of a [`Camera`] entity's 3D
+//! projection.
+//!
+//! World space is +Y up like everywhere else (see [`crate::math`]), in whatever units the game likes, with the
+//! camera's position at the middle of the view. Zoom is viewport pixels per world unit.
+//!
+//! In pixel-perfect mode the world is drawn at a virtual resolution and scaled up by the biggest whole number that
+//! fits the window, letterboxed, so every virtual pixel is the same number of real ones. Zoom rounds to whole
+//! numbers too, and the position snaps to virtual pixels, so art on whole world units stays crisp. Rotating gives
+//! that up, but still works.
+//!
+//! [`Camera`]: crate::ecs::components::Camera
+
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::math::{NDC_FAR, NDC_NEAR, Rect};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelPerfect {
+    /// The virtual resolution, in virtual pixels.
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Camera2d {
+    /// World position at the middle of the view.
+    pub position: Vec2,
+    /// Viewport pixels per world unit, or virtual pixels per world unit when pixel-perfect.
+    pub zoom: f32,
+    /// Counter-clockwise, in radians. The world turns the other way on screen.
+    pub rotation: f32,
+    pub pixel_perfect: Option<PixelPerfect>,
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        Camera2d {
+            position: Vec2::ZERO,
+            zoom: 1.0f32,
+            rotation: 0.0,
+            pixel_perfect: None,
+        }
+    }
+}
+
+impl Camera2d {
+    /// Whole number scale from virtual to viewport pixels, 1 if it's not pixel-perfect.
+    pub fn pixel_scale(&self, viewport: Vec2) -> u32 {
+        let Some(pp) = self.pixel_perfect else {
+            return 1;
+        };
+        let fit = (viewport / Vec2::new(pp.width as f32, pp.height as f32)).min_element();
+        return (fit.floor() as u32).max(1);
+    }
+
+    /// The part of the viewport drawn to. All of it, unless pixel-perfect letterboxes it.
+    pub fn viewport_rect(&self, viewport: Vec2) -> Rect {
+        let Some(pp) = self.pixel_perfect else {
+            return Rect::new(Vec2::ZERO, viewport);
+        };
+        let size = Vec2::new(pp.width as f32, pp.height as f32) * self.pixel_scale(viewport) as f32;
+        // Whole pixels, so the virtual ones line up with real ones.
+        let min = ((viewport - size) * 0.5).floor();
+        return Rect::from_pos_size(min, size);
+    }
+
+    /// Zoom as it's used: rounded to a whole number when pixel-perfect.
+    fn effective_zoom(&self) -> f32 {
+        match self.pixel_perfect {
+            Some(_) => self.zoom.round().max(1.0),
+            None => self.zoom,
+        }
+    }
+
+    /// Viewport pixels per world unit.
+    pub fn pixels_per_unit(&self, viewport: Vec2) -> f32 {
+        self.effective_zoom() * self.pixel_scale(viewport) as f32
+    }
+
+    /// The position as it's used: snapped to virtual pixels when pixel-perfect.
+    pub fn snapped_position(&self) -> Vec2 {
+        match self.pixel_perfect {
+            Some(_) => {
+                let zoom = self.effective_zoom();
+                (self.position * zoom).round() / zoom
+            }
+            None => self.position,
+        }
+    }
+
+    /// World space to clip space, for drawing into [`Camera2d::viewport_rect`]. Everything lands at `depth`, 0..1
+    /// from the far plane to the near one.
+    pub fn view_proj(&self, viewport: Vec2, depth: f32) -> Mat4 {
+        let half = self.viewport_rect(viewport).size() * 0.5;
+        let scale = self.pixels_per_unit(viewport) / half;
+        let view = Mat4::from_rotation_z(-self.rotation)
+            * Mat4::from_translation(-self.snapped_position().extend(0.0));
+        let depth = NDC_FAR + (NDC_NEAR - NDC_FAR) * depth;
+        return Mat4::from_translation(Vec3::new(0.0, 0.0, depth))
+            * Mat4::from_scale(Vec3::new(scale.x, scale.y, 0.0))
+            * view;
+    }
+
+    /// World space to viewport pixels from the top left.
+    pub fn world_to_viewport(&self, world: Vec2, viewport: Vec2) -> Vec2 {
+        let local = Vec2::from_angle(-self.rotation).rotate(world - self.snapped_position());
+        let pixels = local * self.pixels_per_unit(viewport);
+        return self.viewport_rect(viewport).center() + Vec2::new(pixels.x, -pixels.y);
+    }
+
+    /// Viewport pixels from the top left to world space, like the cursor for picking.
+    pub fn viewport_to_world(&self, point: Vec2, viewport: Vec2) -> Vec2 {
+        let pixels = point - self.viewport_rect(viewport).center();
+        let local = Vec2::new(pixels.x, -pixels.y) / self.pixels_per_unit(viewport);
+        return self.snapped_position() + Vec2::from_angle(self.rotation).rotate(local);
+    }
+
+    /// The world space bounds of what's in view, rotation and all, for culling.
+    pub fn world_bounds(&self, viewport: Vec2) -> Rect {
+        let rect = self.viewport_rect(viewport);
+        let corners = [
+            rect.min,
+            Vec2::new(rect.max.x, rect.min.y),
+            rect.max,
+            Vec2::new(rect.min.x, rect.max.y),
+        ]
+        .map(|c| self.viewport_to_world(c, viewport));
+        let min = corners.into_iter().reduce(Vec2::min).unwrap();
+        let max = corners.into_iter().reduce(Vec2::max).unwrap();
+        return Rect::new(min, max);
+    }
+
+    /// Move by a drag of `delta` viewport pixels, so whatever was under the cursor stays under it.
+    pub fn pan(&mut self, delta: Vec2, viewport: Vec2) {
+        let local = Vec2::new(-delta.x, delta.y) / self.pixels_per_unit(viewport);
+        self.position += Vec2::from_angle(self.rotation).rotate(local);
+    }
+
+    /// Zoom by `factor`, keeping the world point under `point` in viewport pixels where it is.
+    pub fn zoom_at(&mut self, factor: f32, point: Vec2, viewport: Vec2) {
+        let before = self.viewport_to_world(point, viewport);
+        self.zoom *= factor;
+        let after = self.viewport_to_world(point, viewport);
+        self.position += before - after;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::Vec2;
+
+    use super::{Camera2d, PixelPerfect};
+    use crate::math::{self, Rect};
+
+    #[test]
+    pub fn converts_coordinates() {
+        let viewport = Vec2::new(800.0, 600.0);
+        let mut camera = Camera2d {
+            position: Vec2::new(10.0, 20.0),
+            zoom: 4.0,
+            ..Default::default()
+        };
+        // +Y up in the world is up on screen.
+        assert_eq!(
+            camera.world_to_viewport(Vec2::new(11.0, 21.0), viewport),
+            Vec2::new(404.0, 296.0)
+        );
+        let clip = camera
+            .view_proj(viewport, 0.5)
+            .project_point3(Vec2::new(11.0, 21.0).extend(0.0));
+        assert!(
+            math::ndc_to_viewport(clip.truncate(), viewport)
+                .abs_diff_eq(Vec2::new(404.0, 296.0), 1e-3)
+        );
+
+        // Turning the camera a quarter counter-clockwise turns the world clockwise, world +X pointing down.
+        camera.rotation = FRAC_PI_2;
+        let down = camera.world_to_viewport(Vec2::new(11.0, 20.0), viewport);
+        assert!(down.abs_diff_eq(Vec2::new(400.0, 304.0), 1e-3));
+        let point = Vec2::new(123.0, 456.0);
+        let back = camera.viewport_to_world(camera.world_to_viewport(point, viewport), viewport);
+        assert!(back.abs_diff_eq(point, 1e-3));
+
+        let cursor = Vec2::new(600.0, 100.0);
+        let under = camera.viewport_to_world(cursor, viewport);
+        camera.zoom_at(2.5, cursor, viewport);
+        assert!(
+            camera
+                .viewport_to_world(cursor, viewport)
+                .abs_diff_eq(under, 1e-3)
+        );
+        camera.pan(Vec2::new(30.0, -7.0), viewport);
+        let moved = camera.world_to_viewport(under, viewport);
+        assert!(moved.abs_diff_eq(cursor + Vec2::new(30.0, -7.0), 1e-3));
+    }
+
+    #[test]
+    pub fn pixel_perfect() {
+        let camera = Camera2d {
+            position: Vec2::new(0.3, 0.0),
+            zoom: 1.6,
+            pixel_perfect: Some(PixelPerfect {
+                width: 320,
+                height: 180,
+            }),
+            ..Default::default()
+        };
+        // 1000x700 fits 320x180 three times across, and letterboxes the rest.
+        let viewport = Vec2::new(1000.0, 700.0);
+        assert_eq!(camera.pixel_scale(viewport), 3);
+        assert_eq!(
+            camera.viewport_rect(viewport),
+            Rect::from_pos_size(Vec2::new(20.0, 80.0), Vec2::new(960.0, 540.0))
+        );
+        // Zoom rounds to 2, and the position to the nearest half unit.
+        assert_eq!(camera.pixels_per_unit(viewport), 6.0);
+        assert_eq!(camera.snapped_position(), Vec2::new(0.5, 0.0));
+        assert_eq!(
+            camera.world_to_viewport(Vec2::new(1.5, 0.0), viewport),
+            Vec2::new(506.0, 350.0)
+        );
+        let bounds = camera.world_bounds(viewport);
+        assert_eq!(bounds.size(), Vec2::new(160.0, 90.0));
+    }
+}
@@ -0,0 +1,221 @@
+//! Command pools and the command buffers recorded from them, reset a frame at a time.
+//!
+//! Every frame in flight gets its own pools, so resetting the one about to be recorded doesn't touch what the GPU
+//! is still running. Pools can't be used from two threads at once, so each frame has one per recording thread too,
+//! handed out as [`CommandRecorder`]s that can go to jobs. Buffers are kept across frames and handed out again after
+//! the reset, so a steady frame allocates nothing.
+
+use ash::{prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+struct ThreadPool {
+    pool: vk::CommandPool,
+    /// Every buffer allocated from the pool, the first `used` handed out since the last reset.
+    buffers: Vec<vk::CommandBuffer>,
+    used: usize,
+}
+
+/// Hands out command buffers from one thread's pool, for the current frame.
+pub struct CommandRecorder<'a> {
+    device: &'a ash::Device,
+    pool: &'a mut ThreadPool,
+}
+
+impl CommandRecorder<'_> {
+    /// A primary command buffer, begun for one submission. End it before submitting.
+    pub fn begin(&mut self) -> VkResult<vk::CommandBuffer> {
+        let pool = &mut *self.pool;
+        if pool.used == pool.buffers.len() {
+            // SAFETY: Allocated from our pool, and freed with it.
+            let cmd = unsafe {
+                self.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::default()
+                        .command_pool(pool.pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )?[0]
+            };
+            pool.buffers.push(cmd);
+        }
+        let cmd = pool.buffers[pool.used];
+        // SAFETY: Not handed out since the pool was reset, so it's not recording or pending.
+        unsafe {
+            self.device.begin_command_buffer(
+                cmd,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+        }
+        pool.used += 1;
+        return Ok(cmd);
+    }
+
+    /// Buffers handed out this frame.
+    pub fn used(&self) -> usize {
+        self.pool.used
+    }
+}
+
+pub struct CommandManager {
+    device: ash::Device,
+    /// By frame in flight, then by thread.
+    frames: Vec<Vec<ThreadPool>>,
+    current: usize,
+}
+
+impl CommandManager {
+    /// Pools for `threads` recording threads in each of `frames_in_flight` frames, for queues from `queue_family`.
+    pub fn new(
+        device: &ash::Device,
+        queue_family: u32,
+        frames_in_flight: u32,
+        threads: usize,
+    ) -> VkResult<CommandManager> {
+        let mut manager = CommandManager {
+            device: device.clone(),
+            frames: Vec::new(),
+            current: 0,
+        };
+        for _ in 0..frames_in_flight.max(1) {
+            let mut pools = Vec::new();
+            for _ in 0..threads.max(1) {
+                // SAFETY: Made from our device. On failure `manager` drops, destroying what's made so far.
+                let pool = unsafe {
+                    device.create_command_pool(
+                        &vk::CommandPoolCreateInfo::default()
+                            .queue_family_index(queue_family)
+                            .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                        allocs(),
+                    )
+                };
+                let pool = match pool {
+                    Ok(pool) => pool,
+                    Err(e) => {
+                        manager.frames.push(pools);
+                        return Err(e);
+                    }
+                };
+                pools.push(ThreadPool {
+                    pool,
+                    buffers: Vec::new(),
+                    used: 0,
+                });
+            }
+            manager.frames.push(pools);
+        }
+        return Ok(manager);
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    pub fn threads(&self) -> usize {
+        self.frames[0].len()
+    }
+
+    /// Start recording `frame`, resetting its pools and taking back everything handed out from them.
+    ///
+    /// # Safety
+    /// The GPU must be done with what was last recorded for the frame's slot, `frame % frames_in_flight`, like once
+    /// [`super::frame_sync::FrameSync::begin_frame`] has waited on its fence.
+    pub unsafe fn begin_frame(&mut self, frame: u64) -> VkResult<()> {
+        self.current = (frame % self.frames.len() as u64) as usize;
+        for pool in &mut self.frames[self.current] {
+            // SAFETY: Passed on to the caller.
+            unsafe {
+                self.device
+                    .reset_command_pool(pool.pool, vk::CommandPoolResetFlags::empty())?;
+            }
+            pool.used = 0;
+        }
+        return Ok(());
+    }
+
+    /// A primary command buffer for the current frame, from the first thread's pool. See [`CommandRecorder::begin`].
+    pub fn begin(&mut self) -> VkResult<vk::CommandBuffer> {
+        self.recorder(0).begin()
+    }
+
+    /// Thread `thread`'s recorder for the current frame.
+    pub fn recorder(&mut self, thread: usize) -> CommandRecorder<'_> {
+        CommandRecorder {
+            device: &self.device,
+            pool: &mut self.frames[self.current][thread],
+        }
+    }
+
+    /// A recorder for each thread, for the current frame, to hand out to jobs.
+    pub fn recorders(&mut self) -> Vec<CommandRecorder<'_>> {
+        let device = &self.device;
+        self.frames[self.current]
+            .iter_mut()
+            .map(|pool| CommandRecorder { device, pool })
+            .collect()
+    }
+}
+
+impl Drop for CommandManager {
+    fn drop(&mut self) {
+        // SAFETY: Waited on, so nothing's running. Buffers go with their pools.
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            for pool in self.frames.drain(..).flatten() {
+                self.device.destroy_command_pool(pool.pool, allocs());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CommandManager;
+    use crate::test_support::headless;
+
+    #[test]
+    pub fn reuses_buffers() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let raw = device.raw();
+        let mut commands = CommandManager::new(raw, device.queue_family(), 2, 3).unwrap();
+        assert_eq!(commands.threads(), 3);
+
+        // SAFETY: Nothing's been submitted.
+        unsafe { commands.begin_frame(0).unwrap() };
+        let first = commands.begin().unwrap();
+        let second = commands.begin().unwrap();
+        assert_ne!(first, second);
+        let buffers: Vec<_> = std::thread::scope(|s| {
+            let jobs: Vec<_> = commands
+                .recorders()
+                .into_iter()
+                .map(|mut r| s.spawn(move || (r.begin().unwrap(), r.used())))
+                .collect();
+            jobs.into_iter().map(|j| j.join().unwrap()).collect()
+        });
+        assert_eq!(buffers[0].1, 3);
+        assert_eq!(buffers[1].1, 1);
+        for (cmd, _) in &buffers[1..] {
+            assert_ne!(*cmd, first);
+        }
+        for cmd in [first, second]
+            .into_iter()
+            .chain(buffers.iter().map(|b| b.0))
+        {
+            // SAFETY: Begun above, and never submitted.
+            unsafe { raw.end_command_buffer(cmd).unwrap() };
+        }
+
+        // Frame 2 goes back to the first slot, with its buffers handed out again.
+        // SAFETY: Still nothing submitted.
+        unsafe { commands.begin_frame(2).unwrap() };
+        assert_eq!(commands.begin().unwrap(), first);
+    }
+}
@@ -0,0 +1,255 @@
+//! Compute pipelines, made straight from a compute shader's SPIR-V.
+//!
+//! The layout comes from reflecting the shader, with its set layouts shared through a [`LayoutCache`], and so does
+//! the workgroup size, so dispatches can be sized in invocations. Recording goes through
+//! [`CommandEncoder::dispatch`](super::hal::CommandEncoder::dispatch), on the main queue or, where the device has
+//! one, the async compute queue from
+//! [`Device::begin_compute_commands`](super::hal::Device::begin_compute_commands).
+
+use std::{ffi::CStr, io};
+
+use ash::vk;
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::LayoutCache,
+    shader::reflect::{Reflection, Spirv},
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+pub struct ComputePipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    /// By set number. They belong to the layout cache.
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_size: u32,
+    workgroup_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    /// Make a pipeline running `spirv`'s `entry`. Runtime sized arrays in it get `max_unbounded` descriptors.
+    /// Fails on shaders that aren't compute, or that can't be reflected.
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, `cache` must be `device`'s or null, and `spirv` valid for it.
+    pub unsafe fn new(
+        device: &ash::Device,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        entry: &CStr,
+        max_unbounded: u32,
+    ) -> io::Result<ComputePipeline> {
+        let reflection = spirv.reflect()?;
+        if !reflection.stages().contains(vk::ShaderStageFlags::COMPUTE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not a compute shader",
+            ));
+        }
+        // SAFETY: Passed on to the caller.
+        return unsafe {
+            Self::with_reflection(
+                device,
+                layouts,
+                cache,
+                spirv,
+                &reflection,
+                entry,
+                max_unbounded,
+            )
+        }
+        .map_err(io::Error::other);
+    }
+
+    unsafe fn with_reflection(
+        device: &ash::Device,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        reflection: &Reflection,
+        entry: &CStr,
+        max_unbounded: u32,
+    ) -> ash::prelude::VkResult<ComputePipeline> {
+        // SAFETY: Passed on to the caller. Whatever's made is destroyed if something after it fails.
+        unsafe {
+            let set_layouts = layouts.get_reflected(device, reflection, max_unbounded)?;
+            let ranges: Vec<_> = reflection.push_constant_range().into_iter().collect();
+            let layout = device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&set_layouts)
+                    .push_constant_ranges(&ranges),
+                allocs(),
+            )?;
+            let made = spirv.create_module(device).and_then(|module| {
+                let stage = vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(entry);
+                let info = vk::ComputePipelineCreateInfo::default()
+                    .stage(stage)
+                    .layout(layout);
+                let made = device.create_compute_pipelines(cache, &[info], allocs());
+                device.destroy_shader_module(module, allocs());
+                made.map(|p| p[0]).map_err(|(_, e)| e)
+            });
+            let pipeline = match made {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    device.destroy_pipeline_layout(layout, allocs());
+                    return Err(e);
+                }
+            };
+            return Ok(ComputePipeline {
+                pipeline,
+                layout,
+                set_layouts,
+                push_constant_size: reflection.push_constant_size,
+                // Sized by specialization constants it's unknown, and dispatching by groups is up to the caller.
+                workgroup_size: reflection.workgroup_size.unwrap_or([1, 1, 1]),
+            });
+        }
+    }
+
+    pub fn raw(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    /// The layout of set `set`, to allocate sets for it with.
+    pub fn set_layout(&self, set: u32) -> Option<vk::DescriptorSetLayout> {
+        self.set_layouts.get(set as usize).copied()
+    }
+
+    pub fn push_constant_size(&self) -> u32 {
+        self.push_constant_size
+    }
+
+    pub fn workgroup_size(&self) -> [u32; 3] {
+        self.workgroup_size
+    }
+
+    /// Workgroups to dispatch to cover `invocations`, rounding up.
+    pub fn groups(&self, invocations: [u32; 3]) -> [u32; 3] {
+        std::array::from_fn(|i| invocations[i].div_ceil(self.workgroup_size[i]))
+    }
+
+    /// # Safety
+    /// `device` must be the one it was made with, and the GPU done with it.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        // SAFETY: Passed on to the caller. The set layouts go with their cache.
+        unsafe {
+            device.destroy_pipeline(self.pipeline, allocs());
+            device.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use ash::vk;
+
+    use super::ComputePipeline;
+    use crate::{
+        render::{
+            descriptors::{DEFAULT_RATIOS, DescriptorAllocator, LayoutCache},
+            hal::{BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation},
+            shader::compile::{ShaderCompiler, ShaderLanguage, ShaderStage},
+        },
+        test_support::headless,
+    };
+
+    const SHADER: &str = "#version 450\n\
+        layout(local_size_x = 64) in;\n\
+        layout(std430, binding = 0) buffer Out { uint values[]; };\n\
+        layout(push_constant) uniform Push { uint count; uint scale; };\n\
+        void main() {\n\
+            uint i = gl_GlobalInvocationID.x;\n\
+            if (i < count) values[i] = i * scale;\n\
+        }\n";
+
+    #[test]
+    pub fn dispatches() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let compiler = ShaderCompiler::new(std::env::temp_dir());
+        if !compiler.available(ShaderLanguage::Glsl) {
+            return;
+        }
+        let spirv = compiler
+            .compile_text(Path::new("fill.comp"), SHADER, ShaderStage::Compute, "main")
+            .unwrap();
+        let device = headless.device();
+        let raw = device.raw();
+        let mut layouts = LayoutCache::new();
+        // SAFETY: Everything's this device's, and waited on before it's destroyed.
+        unsafe {
+            let mut pipeline = ComputePipeline::new(
+                raw,
+                &mut layouts,
+                vk::PipelineCache::null(),
+                &spirv,
+                c"main",
+                0,
+            )
+            .unwrap();
+            assert_eq!(pipeline.workgroup_size(), [64, 1, 1]);
+            assert_eq!(pipeline.groups([100, 1, 1]), [2, 1, 1]);
+
+            let count = 100u32;
+            let buffer = device
+                .create_buffer(&BufferDesc {
+                    size: count as u64 * 4,
+                    usage: BufferUsage::STORAGE,
+                    location: MemoryLocation::Readback,
+                })
+                .unwrap();
+            let mut descriptors = DescriptorAllocator::new(raw, DEFAULT_RATIOS);
+            let set = descriptors
+                .allocate(pipeline.set_layout(0).unwrap())
+                .unwrap();
+            let info = [vk::DescriptorBufferInfo::default()
+                .buffer(buffer.buffer)
+                .range(vk::WHOLE_SIZE)];
+            raw.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&info)],
+                &[],
+            );
+
+            // On the compute queue where there is one, to try it.
+            let mut cmds = device.begin_compute_commands().unwrap();
+            cmds.bind_compute_sets(&pipeline, 0, &[set]);
+            let push: Vec<u8> = [count, 3].iter().flat_map(|w| w.to_ne_bytes()).collect();
+            cmds.dispatch(&pipeline, &push, pipeline.groups([count, 1, 1]));
+            cmds.compute_barrier();
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+
+            let mut out = vec![0; count as usize * 4];
+            device.read_buffer(&buffer, 0, &mut out).unwrap();
+            let values: Vec<u32> = out
+                .chunks(4)
+                .map(|w| u32::from_ne_bytes(w.try_into().unwrap()))
+                .collect();
+            assert_eq!(values[99], 297);
+
+            drop(descriptors);
+            device.destroy_buffer(buffer);
+            pipeline.destroy(raw);
+            layouts.destroy(raw);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Destroying GPU resources once the GPU is done with them, instead of waiting for it to go idle.
+//!
+//! Something replaced mid-run, like a render target after a quality change, can still be in use by the frames in
+//! flight. It gets retired with the frame it was last used in, and destroyed once that many frames have gone by.
+
+use std::collections::VecDeque;
+
+use super::hal::Device;
+
+/// Things waiting for the frames that might use them to finish.
+pub struct DeletionQueue<T> {
+    /// Oldest first, as frames only go up.
+    pending: VecDeque<(u64, T)>,
+    frames_in_flight: u64,
+}
+
+impl<T> DeletionQueue<T> {
+    pub fn new(frames_in_flight: u32) -> DeletionQueue<T> {
+        DeletionQueue {
+            pending: VecDeque::new(),
+            frames_in_flight: frames_in_flight as u64,
+        }
+    }
+
+    /// `item` was last used in `frame`.
+    pub fn retire(&mut self, frame: u64, item: T) {
+        self.pending.push_back((frame, item));
+    }
+
+    /// Everything retired long enough before `frame` starts that no frame in flight can be using it.
+    pub fn ready(&mut self, frame: u64) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(&(retired, _)) = self.pending.front()
+            && retired.saturating_add(self.frames_in_flight) <= frame
+        {
+            ready.push(self.pending.pop_front().unwrap().1);
+        }
+        return ready;
+    }
+
+    /// Everything, for when the GPU has gone idle.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.pending.drain(..).map(|(_, item)| item).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// A resource on its way out.
+pub enum Retired<D: Device> {
+    Texture(D::Texture),
+    Buffer(D::Buffer),
+}
+
+impl<D: Device> Retired<D> {
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(self, device: &D) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            match self {
+                Retired::Texture(texture) => device.destroy_texture(texture),
+                Retired::Buffer(buffer) => device.destroy_buffer(buffer),
+            }
+        }
+    }
+}
+
+impl<D: Device> DeletionQueue<Retired<D>> {
+    /// Destroy whatever's done with, at the start of `frame`.
+    ///
+    /// # Safety
+    /// Frames up to `frame - frames_in_flight` must have finished on the GPU, as waiting on their fences makes sure
+    /// of.
+    pub unsafe fn collect(&mut self, device: &D, frame: u64) {
+        for item in self.ready(frame) {
+            // SAFETY: Passed on to the caller.
+            unsafe { item.destroy(device) };
+        }
+    }
+
+    /// Wait for the GPU and destroy everything, for shutting down.
+    pub fn flush(&mut self, device: &D) {
+        device.wait_idle();
+        for item in self.drain() {
+            // SAFETY: Waited on above.
+            unsafe { item.destroy(device) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeletionQueue;
+
+    #[test]
+    pub fn waits_out_frames_in_flight() {
+        let mut queue = DeletionQueue::new(2);
+        queue.retire(10, "shadow map");
+        queue.retire(11, "scene color");
+        queue.retire(11, "bloom");
+
+        // Frames 10 and 11 could both still be on the GPU while 11 and 12 are recorded.
+        assert!(queue.ready(11).is_empty());
+        assert_eq!(queue.ready(12), vec!["shadow map"]);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.ready(20), vec!["scene color", "bloom"]);
+        assert!(queue.is_empty());
+
+        queue.retire(30, "ssao");
+        assert_eq!(queue.drain(), vec!["ssao"]);
+    }
+}
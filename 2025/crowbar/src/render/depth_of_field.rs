@@ -0,0 +1,248 @@
+//! Depth of field, for cameras with a [`Lens`].
+//!
+//! A thin lens blurs each point into a disc, its circle of confusion, as wide as the aperture times how far out of
+//! focus the point is. [`CocTerms`] has its radius in pixels, negative for what's in front of the focus distance.
+//!
+//! With [`QualitySettings::depth_of_field`] on and the last camera drawn having a lens, the renderer runs it over the
+//! scene's colour and depth before motion blur, in passes all at half resolution but the last:
+//! 1. [`SPLIT_SHADER`] halves the scene, with each pixel's circle of confusion from the depth buffer.
+//! 2. [`BLUR_SHADER`], drawn once for the near layer and once for the far one, gathers them from it. Rather than
+//!    each pixel scattering itself over its circle, which GPUs are bad at, each pixel looks at [`taps`] across the
+//!    widest circle there can be and takes those whose own circle reaches it. The taps follow the aperture's shape,
+//!    so bright spots come out as bokeh with as many sides as the lens has blades. Near and far are gathered apart,
+//!    so a blurred foreground spreads out over what's behind it without what's behind spreading onto it.
+//! 3. [`COMPOSITE_SHADER`] puts the far layer in where the full resolution scene is behind the focus, then the near
+//!    layer over it by how much of each pixel it covers.
+//!
+//! [`blur_layers`] and [`composite`] do the last two on the CPU.
+//!
+//! [`QualitySettings::depth_of_field`]: super::quality::QualitySettings::depth_of_field
+
+use std::f32::consts::TAU;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use super::extract::ExtractedScene;
+use crate::ecs::components::Lens;
+
+pub const GLSL: &str = include_str!("depth_of_field/dof.glsl");
+pub const SPLIT_SHADER: &str = include_str!("depth_of_field/split.frag");
+pub const BLUR_SHADER: &str = include_str!("depth_of_field/blur.frag");
+pub const COMPOSITE_SHADER: &str = include_str!("depth_of_field/composite.frag");
+
+/// Rings of taps around the center of the aperture. Keep in step with `DOF_RINGS` in [`GLSL`].
+pub const RINGS: u32 = 4;
+
+/// The widest circle of confusion's radius, in half resolution pixels.
+pub const MAX_RADIUS: f32 = 8.0;
+
+/// A lens's circle of confusion as `scale * (1 - focus / distance)`, a signed radius in half resolution pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CocTerms {
+    /// The radius for what's infinitely far away.
+    pub scale: f32,
+    pub focus: f32,
+}
+
+impl CocTerms {
+    /// For `lens` on a full resolution target `height` pixels tall.
+    pub fn new(lens: &Lens, height: u32) -> CocTerms {
+        let focal_length = lens.focal_length / 1000.0;
+        let aperture = focal_length / lens.f_stop.max(0.1);
+        // Nothing focuses closer than the focal length.
+        let focus = lens.focus_distance.max(focal_length * 1.01);
+        let on_sensor = aperture * focal_length / (focus - focal_length);
+        let diameter = on_sensor / (lens.sensor_height / 1000.0) * height as f32;
+        // Half of that for the radius, half again for half resolution.
+        return CocTerms {
+            scale: diameter / 4.0,
+            focus,
+        };
+    }
+
+    /// What's `distance` away blurs this much, up to [`MAX_RADIUS`].
+    pub fn radius(&self, distance: f32) -> f32 {
+        let radius = self.scale * (1.0 - self.focus / distance.max(1e-4));
+        return radius.clamp(-MAX_RADIUS, MAX_RADIUS);
+    }
+}
+
+/// How far along the view axis what's at `depth` in the depth buffer is, for a projection with `inverse_projection`.
+pub fn view_distance(inverse_projection: Mat4, depth: f32) -> f32 {
+    let view = inverse_projection * Vec4::new(0.0, 0.0, depth, 1.0);
+    return -view.z / view.w;
+}
+
+/// How far out the edge of an aperture with `blades` is at `angle`, 1 at its corners.
+fn aperture_edge(angle: f32, blades: u32) -> f32 {
+    if blades < 3 {
+        return 1.0;
+    }
+    let side = TAU / blades as f32;
+    return (side / 2.0).cos() / (angle.rem_euclid(side) - side / 2.0).cos();
+}
+
+/// Where the blur's taps go across a unit aperture with `blades`: the center, then [`RINGS`] rings with 8 times
+/// their number each, every other one turned half a tap. Keep in step with `dof_tap` in [`GLSL`].
+pub fn taps(blades: u32) -> Vec<Vec2> {
+    let mut out = vec![Vec2::ZERO];
+    for ring in 1..=RINGS {
+        let count = ring * 8;
+        let radius = ring as f32 / RINGS as f32;
+        for i in 0..count {
+            let angle = (i as f32 + 0.5 * (ring % 2) as f32) / count as f32 * TAU;
+            out.push(Vec2::from_angle(angle) * radius * aperture_edge(angle, blades));
+        }
+    }
+    return out;
+}
+
+/// What [`BLUR_SHADER`] gathers from `split`, a `size` image of colours with their radius in alpha, rows top to
+/// bottom. The near layer, with how much of each pixel it covers in alpha, and the far one.
+pub fn blur_layers(split: &[Vec4], size: [u32; 2], blades: u32) -> (Vec<Vec4>, Vec<Vec4>) {
+    let [width, height] = size.map(|s| s as i32);
+    let taps = taps(blades);
+    let mut near_layer = Vec::with_capacity(split.len());
+    let mut far_layer = Vec::with_capacity(split.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (mut near, mut far) = (Vec4::ZERO, Vec4::ZERO);
+            for tap in &taps {
+                let offset = *tap * MAX_RADIUS;
+                let at = (Vec2::new(x as f32, y as f32) + 0.5 + offset)
+                    .floor()
+                    .as_ivec2();
+                let (tx, ty) = (at.x.clamp(0, width - 1), at.y.clamp(0, height - 1));
+                let sample = split[(ty * width + tx) as usize];
+                let radius = sample.w.abs();
+                if radius < offset.length() - 0.5 {
+                    continue;
+                }
+                let weighted = sample.xyz().extend(1.0) / (radius * radius).max(1.0);
+                match sample.w < 0.0 {
+                    true => near += weighted,
+                    false => far += weighted,
+                }
+            }
+
+            let center = split[(y * width + x) as usize].xyz();
+            let average = |layer: Vec4| match layer.w > 0.0 {
+                true => layer.xyz() / layer.w,
+                false => center,
+            };
+            let coverage = near.w / (near.w + far.w).max(1e-6);
+            near_layer.push(average(near).extend(coverage));
+            far_layer.push(average(far).extend(1.0));
+        }
+    }
+    return (near_layer, far_layer);
+}
+
+/// What [`COMPOSITE_SHADER`] makes of a full resolution pixel of colour `sharp` with circle of confusion `radius`,
+/// and the `near` and `far` layers over it.
+pub fn composite(sharp: Vec3, radius: f32, near: Vec4, far: Vec4) -> Vec3 {
+    let color = sharp.lerp(far.xyz(), radius.clamp(0.0, 1.0));
+    return color.lerp(near.xyz(), near.w);
+}
+
+/// The lens the scene's seen through and its projection at `aspect`: the last camera's, as the depth buffer is left
+/// with what it drew. `None` if it has no lens.
+pub fn scene_lens(scene: &ExtractedScene, aspect: f32) -> Option<(Lens, Mat4)> {
+    let camera = scene.cameras.last()?;
+    return Some((camera.lens?, camera.projection.matrix(aspect)));
+}
+
+/// The push constants the passes take, for `lens` on a `size` target drawn with `projection`: the inverse
+/// projection, the [`CocTerms`], [`MAX_RADIUS`] and the number of blades, then whether [`BLUR_SHADER`] gathers the
+/// `far` layer rather than the near one.
+pub fn gpu_data(lens: &Lens, projection: Mat4, size: [u32; 2], far: bool) -> Vec<u8> {
+    let terms = CocTerms::new(lens, size[1]);
+    let mut out = Vec::with_capacity(84);
+    let mut vec4 = |v: [f32; 4]| v.iter().for_each(|f| out.extend(f.to_ne_bytes()));
+    for column in projection.inverse().to_cols_array().chunks_exact(4) {
+        vec4(column.try_into().unwrap());
+    }
+    vec4([terms.scale, terms.focus, MAX_RADIUS, lens.blades as f32]);
+    out.extend((far as u32).to_ne_bytes());
+    return out;
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec2, Vec3, Vec4};
+
+    use super::{CocTerms, MAX_RADIUS, blur_layers, composite, gpu_data, taps, view_distance};
+    use crate::{ecs::components::Lens, math};
+
+    #[test]
+    pub fn blurs_out_of_focus() {
+        let lens = Lens::standard();
+        assert!((lens.fov_y().to_degrees() - 27.0).abs() < 0.1);
+
+        // 1080p, sharp at the focus, blurrier further off either side, up to the cap.
+        let terms = CocTerms::new(&lens, 1080);
+        assert_eq!(terms.radius(lens.focus_distance), 0.0);
+        assert!(terms.radius(2.0) < 0.0 && terms.radius(20.0) > 0.0);
+        assert!(terms.radius(1000.0) > terms.radius(20.0));
+        assert_eq!(terms.radius(0.1), -MAX_RADIUS);
+
+        let projection = math::perspective(1.0, 1.5, 0.1, 100.0);
+        let clip = projection * Vec4::new(0.3, 0.2, -7.0, 1.0);
+        let distance = view_distance(projection.inverse(), clip.z / clip.w);
+        assert!((distance - 7.0).abs() < 1e-3);
+
+        // Six blades make a hexagon, out to its corners and the middle of its sides.
+        let hexagon = taps(6);
+        assert_eq!(hexagon.len(), 81);
+        let lengths = hexagon.iter().map(|t| t.length());
+        let longest = lengths.clone().fold(0.0, f32::max);
+        assert!((longest - 1.0).abs() < 1e-5);
+        let shortest_edge = hexagon[hexagon.len() - 32..]
+            .iter()
+            .map(|t| t.length())
+            .fold(1.0, f32::min);
+        assert!(shortest_edge >= 30f32.to_radians().cos() - 1e-5);
+        assert!(taps(0).iter().all(|t| t.length() <= 1.0 + 1e-6));
+
+        // Everything in focus comes through untouched.
+        let size = [16, 16];
+        let mut split: Vec<Vec4> = (0..256)
+            .map(|i| Vec4::new(i as f32, 0.0, 0.0, 0.0))
+            .collect();
+        let (near, far) = blur_layers(&split, size, 6);
+        assert!(near.iter().all(|n| n.w == 0.0));
+        assert!(far.iter().zip(&split).all(|(f, s)| f.x == s.x));
+
+        // A far light spreads through the far layer as far as its circle, but sharp pixels around it stay sharp.
+        let light = 8 * 16 + 8;
+        let from_light =
+            |i: usize| Vec2::new((i % 16) as f32 - 8.0, (i / 16) as f32 - 8.0).length();
+        split.fill(Vec4::ZERO);
+        split[light] = Vec4::new(1.0, 1.0, 1.0, 3.0);
+        let (near, far) = blur_layers(&split, size, 6);
+        let lit: Vec<usize> = (0..256).filter(|&i| i != light && far[i].x > 0.0).collect();
+        assert!(!lit.is_empty());
+        assert!(lit.iter().all(|&i| from_light(i) <= 3.5));
+        assert!(
+            lit.iter()
+                .all(|&i| composite(Vec3::ZERO, 0.0, near[i], far[i]) == Vec3::ZERO)
+        );
+
+        // A near one spreads over what's in focus behind it, partly covering it.
+        split[light].w = -3.0;
+        let (near, far) = blur_layers(&split, size, 6);
+        assert_eq!(near[light].w, 1.0);
+        let covered: Vec<usize> = (0..256)
+            .filter(|&i| i != light && near[i].w > 0.0)
+            .collect();
+        assert_eq!(covered, lit);
+        for i in covered {
+            assert!(near[i].w < 1.0 && near[i].x == 1.0);
+            assert!(composite(Vec3::ZERO, 0.0, near[i], far[i]).x > 0.0);
+        }
+
+        let push = gpu_data(&lens, projection, [1920, 1080], true);
+        assert_eq!(push.len(), 84);
+        assert_eq!(push[80..], 1u32.to_ne_bytes());
+    }
+}
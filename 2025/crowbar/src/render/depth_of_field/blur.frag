@@ -0,0 +1,44 @@
+#version 450
+// Gathers the near or far layer from split.frag's output, drawn once for each. Keep in step with blur_layers in
+// depth_of_field.rs.
+#include "dof.glsl"
+
+layout(set = 0, binding = 0) uniform texture2D split;
+layout(set = 0, binding = 1) uniform sampler linear_sampler;
+
+layout(location = 0) in vec2 uv;
+
+// Colour, and for the near layer how much of the pixel it covers.
+layout(location = 0) out vec4 out_layer;
+
+void main() {
+    ivec2 size = textureSize(sampler2D(split, linear_sampler), 0);
+    vec4 center = texelFetch(sampler2D(split, linear_sampler), ivec2(gl_FragCoord.xy), 0);
+    vec4 near = vec4(0.0);
+    vec4 far = vec4(0.0);
+    for (uint ring = 0u; ring <= DOF_RINGS; ring++) {
+        uint count = max(ring * 8u, 1u);
+        for (uint i = 0u; i < count; i++) {
+            vec2 offset = ring == 0u ? vec2(0.0) : dof_tap(ring, i) * dof.lens.z;
+            ivec2 at = clamp(ivec2(floor(gl_FragCoord.xy + offset)), ivec2(0), size - 1);
+            vec4 tap = texelFetch(sampler2D(split, linear_sampler), at, 0);
+            float radius = abs(tap.a);
+            // Scatter as gather: the tap only lands here if its own circle reaches this far.
+            if (radius < length(offset) - 0.5) {
+                continue;
+            }
+            // Its light is spread over its circle, so wide ones count for less.
+            float weight = 1.0 / max(radius * radius, 1.0);
+            if (tap.a < 0.0) {
+                near += vec4(tap.rgb, 1.0) * weight;
+            } else {
+                far += vec4(tap.rgb, 1.0) * weight;
+            }
+        }
+    }
+    if (dof.far != 0u) {
+        out_layer = vec4(far.w > 0.0 ? far.rgb / far.w : center.rgb, 1.0);
+    } else {
+        out_layer = vec4(near.w > 0.0 ? near.rgb / near.w : center.rgb, near.w / max(near.w + far.w, 1e-6));
+    }
+}
@@ -0,0 +1,23 @@
+#version 450
+// Puts the blurred layers back over the full resolution scene. Keep in step with composite in depth_of_field.rs.
+#include "dof.glsl"
+
+layout(set = 0, binding = 0) uniform texture2D scene_color;
+layout(set = 0, binding = 1) uniform texture2D scene_depth;
+layout(set = 0, binding = 2) uniform texture2D near_layer;
+layout(set = 0, binding = 3) uniform texture2D far_layer;
+layout(set = 0, binding = 4) uniform sampler linear_sampler;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec3 color = texture(sampler2D(scene_color, linear_sampler), uv).rgb;
+    float depth = texelFetch(sampler2D(scene_depth, linear_sampler), ivec2(gl_FragCoord.xy), 0).r;
+    float radius = dof_radius(depth);
+    // Only what's behind the focus takes the far layer, so it can't bleed onto sharp things in front of it.
+    color = mix(color, texture(sampler2D(far_layer, linear_sampler), uv).rgb, clamp(radius, 0.0, 1.0));
+    vec4 near = texture(sampler2D(near_layer, linear_sampler), uv);
+    out_color = vec4(mix(color, near.rgb, near.a), 1.0);
+}
@@ -0,0 +1,41 @@
+// Depth of field, see depth_of_field.rs. Its passes all include this and take the same push constants, from
+// depth_of_field::gpu_data.
+
+// Keep in step with RINGS in depth_of_field.rs.
+#define DOF_RINGS 4u
+
+layout(push_constant) uniform DofPush {
+    mat4 inverse_projection;
+    // The circle of confusion's scale and focus distance, the widest radius and the aperture's blades.
+    vec4 lens;
+    // 1 for blur.frag to gather the far layer, 0 for the near one.
+    uint far;
+} dof;
+
+const float DOF_TAU = 6.28318530718;
+
+// How far along the view axis what's at `depth` is.
+float dof_distance(float depth) {
+    vec4 view = dof.inverse_projection * vec4(0.0, 0.0, depth, 1.0);
+    return -view.z / view.w;
+}
+
+// The signed radius of the circle of confusion at `depth` in half resolution pixels, negative in front of the focus.
+// Keep in step with CocTerms::radius.
+float dof_radius(float depth) {
+    float radius = dof.lens.x * (1.0 - dof.lens.y / max(dof_distance(depth), 1e-4));
+    return clamp(radius, -dof.lens.z, dof.lens.z);
+}
+
+// Tap `index` on ring `ring` of a unit aperture, which has 8 times the ring's number. Keep in step with taps.
+vec2 dof_tap(uint ring, uint index) {
+    uint count = ring * 8u;
+    float angle = (float(index) + 0.5 * float(ring % 2u)) / float(count) * DOF_TAU;
+    float radius = float(ring) / float(DOF_RINGS);
+    uint blades = uint(dof.lens.w);
+    if (blades >= 3u) {
+        float side = DOF_TAU / float(blades);
+        radius *= cos(side / 2.0) / cos(mod(angle, side) - side / 2.0);
+    }
+    return vec2(cos(angle), sin(angle)) * radius;
+}
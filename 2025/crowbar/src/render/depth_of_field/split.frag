@@ -0,0 +1,24 @@
+#version 450
+// Halves the scene, with each pixel's circle of confusion in alpha for blur.frag.
+#include "dof.glsl"
+
+layout(set = 0, binding = 0) uniform texture2D scene_color;
+layout(set = 0, binding = 1) uniform texture2D scene_depth;
+// Linear, so each pixel averages the four colours under it.
+layout(set = 0, binding = 2) uniform sampler linear_sampler;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_split;
+
+void main() {
+    ivec2 size = textureSize(sampler2D(scene_depth, linear_sampler), 0);
+    ivec2 corner = ivec2(gl_FragCoord.xy) * 2;
+    // The nearest of the four under it, reversed-Z has it the biggest, so the foreground's blur reaches its edges.
+    float depth = 0.0;
+    for (int i = 0; i < 4; i++) {
+        ivec2 at = min(corner + ivec2(i & 1, i >> 1), size - 1);
+        depth = max(depth, texelFetch(sampler2D(scene_depth, linear_sampler), at, 0).r);
+    }
+    out_split = vec4(texture(sampler2D(scene_color, linear_sampler), uv).rgb, dof_radius(depth));
+}
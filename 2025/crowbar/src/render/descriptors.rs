@@ -0,0 +1,429 @@
+//! Descriptor sets allocated a frame at a time, and descriptor set layouts shared between pipelines.
+//!
+//! [`DescriptorAllocator`] hands sets out of a list of pools, making a bigger one whenever the last fills up, and
+//! gets them all back at once by resetting the pools rather than freeing sets one by one. [`FrameDescriptors`] keeps
+//! one per frame in flight, reset at the start of its frame like [`super::commands::CommandManager`]'s pools, so
+//! sets written for a frame can't be overwritten while the GPU is still reading them. A steady frame allocates no
+//! new pools.
+//!
+//! [`LayoutCache`] makes each distinct layout once, keyed by its bindings, so pipelines whose sets look the same get
+//! the same layout and their sets can be bound to either.
+
+use std::collections::HashMap;
+
+use ash::{prelude::VkResult, vk};
+
+use super::{alloc::VK_ALLOCATOR_CALLBACKS, shader::reflect::Reflection};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// Descriptors of each type per set in a pool, for sets that are mostly a few uniforms and textures.
+pub const DEFAULT_RATIOS: &[(vk::DescriptorType, f32)] = &[
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 1.0),
+    (vk::DescriptorType::SAMPLER, 1.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+];
+
+/// Sets in the first pool. Each one after holds half as many again, up to [`MAX_SETS_PER_POOL`].
+const FIRST_SETS_PER_POOL: u32 = 64;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// A pool's sizes for `sets` sets, at least one of each type.
+fn pool_sizes(ratios: &[(vk::DescriptorType, f32)], sets: u32) -> Vec<vk::DescriptorPoolSize> {
+    ratios
+        .iter()
+        .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: ((ratio * sets as f32).ceil() as u32).max(1),
+        })
+        .collect()
+}
+
+/// Allocates descriptor sets from pools it makes as it needs them, all freed together by
+/// [`DescriptorAllocator::reset`].
+pub struct DescriptorAllocator {
+    device: ash::Device,
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    /// Pools that may have room, the last one being allocated from.
+    ready: Vec<vk::DescriptorPool>,
+    /// Pools that ran out since the last reset.
+    full: Vec<vk::DescriptorPool>,
+    /// Sets in the next pool made.
+    sets_per_pool: u32,
+}
+
+impl DescriptorAllocator {
+    /// Sized by `ratios`, descriptors of each type per set. Makes no pools until something's allocated.
+    pub fn new(device: &ash::Device, ratios: &[(vk::DescriptorType, f32)]) -> DescriptorAllocator {
+        DescriptorAllocator {
+            device: device.clone(),
+            ratios: ratios.to_vec(),
+            ready: Vec::new(),
+            full: Vec::new(),
+            sets_per_pool: FIRST_SETS_PER_POOL,
+        }
+    }
+
+    /// Every pool made so far.
+    pub fn pools(&self) -> usize {
+        self.ready.len() + self.full.len()
+    }
+
+    fn create_pool(&mut self) -> VkResult<vk::DescriptorPool> {
+        let sizes = pool_sizes(&self.ratios, self.sets_per_pool);
+        // SAFETY: Made from our device, and destroyed with us.
+        let pool = unsafe {
+            self.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(self.sets_per_pool)
+                    .pool_sizes(&sizes),
+                allocs(),
+            )?
+        };
+        self.sets_per_pool = (self.sets_per_pool + self.sets_per_pool / 2).min(MAX_SETS_PER_POOL);
+        return Ok(pool);
+    }
+
+    /// A set with `layout`, good until the next reset.
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout) -> VkResult<vk::DescriptorSet> {
+        let layouts = [layout];
+        // One try in the pool being used, then one in a fresh one. Failing there too, the set's too big for any.
+        for _ in 0..2 {
+            let pool = match self.ready.last() {
+                Some(&pool) => pool,
+                None => {
+                    let pool = self.create_pool()?;
+                    self.ready.push(pool);
+                    pool
+                }
+            };
+            // SAFETY: The pool's ours, and only used from here.
+            let result = unsafe {
+                self.device.allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(pool)
+                        .set_layouts(&layouts),
+                )
+            };
+            match result {
+                Ok(sets) => return Ok(sets[0]),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    self.full.extend(self.ready.pop());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+    }
+
+    /// Free every set allocated, keeping the pools to allocate from again.
+    ///
+    /// # Safety
+    /// The GPU must be done with every set allocated since the last reset.
+    pub unsafe fn reset(&mut self) -> VkResult<()> {
+        self.ready.append(&mut self.full);
+        for &pool in &self.ready {
+            // SAFETY: Passed on to the caller.
+            unsafe {
+                self.device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?
+            };
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        // SAFETY: Whoever owns us waits for the GPU before dropping, like with command pools. Sets go with their
+        // pools.
+        unsafe {
+            for pool in self.ready.drain(..).chain(self.full.drain(..)) {
+                self.device.destroy_descriptor_pool(pool, allocs());
+            }
+        }
+    }
+}
+
+/// A [`DescriptorAllocator`] per frame in flight, for sets written fresh every frame.
+pub struct FrameDescriptors {
+    frames: Vec<DescriptorAllocator>,
+    current: usize,
+}
+
+impl FrameDescriptors {
+    pub fn new(
+        device: &ash::Device,
+        frames_in_flight: u32,
+        ratios: &[(vk::DescriptorType, f32)],
+    ) -> FrameDescriptors {
+        FrameDescriptors {
+            frames: (0..frames_in_flight.max(1))
+                .map(|_| DescriptorAllocator::new(device, ratios))
+                .collect(),
+            current: 0,
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    /// Start `frame`, freeing every set its slot handed out last time round.
+    ///
+    /// # Safety
+    /// The GPU must be done with what was last recorded for the frame's slot, `frame % frames_in_flight`.
+    pub unsafe fn begin_frame(&mut self, frame: u64) -> VkResult<()> {
+        self.current = (frame % self.frames.len() as u64) as usize;
+        // SAFETY: Passed on to the caller.
+        return unsafe { self.frames[self.current].reset() };
+    }
+
+    /// A set with `layout` for the current frame.
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout) -> VkResult<vk::DescriptorSet> {
+        self.frames[self.current].allocate(layout)
+    }
+
+    /// The current frame's allocator.
+    pub fn current(&mut self) -> &mut DescriptorAllocator {
+        &mut self.frames[self.current]
+    }
+}
+
+/// One binding in a [`LayoutDesc`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayoutBinding {
+    pub binding: u32,
+    pub ty: vk::DescriptorType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    /// Like `PARTIALLY_BOUND` or `UPDATE_AFTER_BIND`, empty for a plain binding.
+    pub flags: vk::DescriptorBindingFlags,
+}
+
+impl LayoutBinding {
+    pub fn new(
+        binding: u32,
+        ty: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> LayoutBinding {
+        LayoutBinding {
+            binding,
+            ty,
+            count,
+            stages,
+            flags: vk::DescriptorBindingFlags::empty(),
+        }
+    }
+
+    pub fn flags(mut self, flags: vk::DescriptorBindingFlags) -> Self {
+        self.flags = flags;
+        return self;
+    }
+}
+
+/// Everything that makes a descriptor set layout what it is, and what [`LayoutCache`] looks them up by.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LayoutDesc {
+    pub flags: vk::DescriptorSetLayoutCreateFlags,
+    /// Sorted by binding, so the same bindings in another order are the same layout.
+    bindings: Vec<LayoutBinding>,
+}
+
+impl LayoutDesc {
+    pub fn new(bindings: impl IntoIterator<Item = LayoutBinding>) -> LayoutDesc {
+        let mut bindings: Vec<_> = bindings.into_iter().collect();
+        bindings.sort_by_key(|b| b.binding);
+        return LayoutDesc {
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            bindings,
+        };
+    }
+
+    /// Set `set` of a shader's reflection. Runtime sized arrays get `max_unbounded` descriptors.
+    pub fn from_reflection(reflection: &Reflection, set: u32, max_unbounded: u32) -> LayoutDesc {
+        LayoutDesc::new(
+            reflection
+                .bindings
+                .iter()
+                .filter(|b| b.set == set)
+                .map(|b| {
+                    let count = if b.count == 0 { max_unbounded } else { b.count };
+                    LayoutBinding::new(b.binding, b.ty, count, b.stages)
+                }),
+        )
+    }
+
+    pub fn flags(mut self, flags: vk::DescriptorSetLayoutCreateFlags) -> Self {
+        self.flags = flags;
+        return self;
+    }
+
+    pub fn bindings(&self) -> &[LayoutBinding] {
+        &self.bindings
+    }
+}
+
+/// Descriptor set layouts, each made once per distinct [`LayoutDesc`] and kept until the cache is destroyed.
+#[derive(Debug, Default)]
+pub struct LayoutCache {
+    layouts: HashMap<LayoutDesc, vk::DescriptorSetLayout>,
+}
+
+impl LayoutCache {
+    pub fn new() -> LayoutCache {
+        LayoutCache::default()
+    }
+
+    /// Distinct layouts made.
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    /// The layout for `desc`, made if this is the first time it's been asked for. It belongs to the cache, don't
+    /// destroy it.
+    ///
+    /// # Safety
+    /// `device` must be the one every layout was made with, and support `desc`.
+    pub unsafe fn get(
+        &mut self,
+        device: &ash::Device,
+        desc: &LayoutDesc,
+    ) -> VkResult<vk::DescriptorSetLayout> {
+        if let Some(&layout) = self.layouts.get(desc) {
+            return Ok(layout);
+        }
+        let bindings: Vec<_> = desc
+            .bindings
+            .iter()
+            .map(|b| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(b.binding)
+                    .descriptor_type(b.ty)
+                    .descriptor_count(b.count)
+                    .stage_flags(b.stages)
+            })
+            .collect();
+        let binding_flags: Vec<_> = desc.bindings.iter().map(|b| b.flags).collect();
+        let mut flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+        let mut info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(desc.flags)
+            .bindings(&bindings);
+        if binding_flags.iter().any(|f| !f.is_empty()) {
+            info = info.push_next(&mut flags_info);
+        }
+        // SAFETY: Passed on to the caller.
+        let layout = unsafe { device.create_descriptor_set_layout(&info, allocs())? };
+        self.layouts.insert(desc.clone(), layout);
+        return Ok(layout);
+    }
+
+    /// Layouts for every set a shader's reflection uses, by set number.
+    ///
+    /// # Safety
+    /// As for [`LayoutCache::get`].
+    pub unsafe fn get_reflected(
+        &mut self,
+        device: &ash::Device,
+        reflection: &Reflection,
+        max_unbounded: u32,
+    ) -> VkResult<Vec<vk::DescriptorSetLayout>> {
+        (0..reflection.set_count())
+            .map(|set| {
+                let desc = LayoutDesc::from_reflection(reflection, set, max_unbounded);
+                // SAFETY: Passed on to the caller.
+                unsafe { self.get(device, &desc) }
+            })
+            .collect()
+    }
+
+    /// # Safety
+    /// `device` must be the one they were made with, and nothing can still be using them.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (_, layout) in self.layouts.drain() {
+            // SAFETY: Passed on to the caller.
+            unsafe { device.destroy_descriptor_set_layout(layout, allocs()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{
+        DEFAULT_RATIOS, FIRST_SETS_PER_POOL, FrameDescriptors, LayoutBinding, LayoutCache,
+        LayoutDesc, pool_sizes,
+    };
+    use crate::test_support::headless;
+
+    #[test]
+    pub fn allocates_and_shares_layouts() {
+        let sizes = pool_sizes(&[(vk::DescriptorType::SAMPLER, 0.1)], 64);
+        assert_eq!(sizes[0].descriptor_count, 7);
+        assert_eq!(
+            pool_sizes(&[(vk::DescriptorType::SAMPLER, 0.0)], 64)[0].descriptor_count,
+            1
+        );
+
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let texture =
+            LayoutBinding::new(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, fragment);
+        let uniforms = LayoutBinding::new(0, vk::DescriptorType::UNIFORM_BUFFER, 1, fragment);
+        let desc = LayoutDesc::new([texture, uniforms]);
+        assert_eq!(desc, LayoutDesc::new([uniforms, texture]));
+        assert_eq!(desc.bindings()[0], uniforms);
+        assert_ne!(desc, LayoutDesc::new([uniforms]));
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let raw = headless.device().raw();
+        let mut layouts = LayoutCache::new();
+        let mut descriptors = FrameDescriptors::new(raw, 2, DEFAULT_RATIOS);
+        // SAFETY: Nothing's submitted, so nothing's in use.
+        unsafe {
+            let layout = layouts.get(raw, &desc).unwrap();
+            assert_eq!(
+                layouts
+                    .get(raw, &LayoutDesc::new([uniforms, texture]))
+                    .unwrap(),
+                layout
+            );
+            assert_ne!(
+                layouts.get(raw, &LayoutDesc::new([uniforms])).unwrap(),
+                layout
+            );
+            assert_eq!(layouts.len(), 2);
+
+            // More than one pool holds, so it grows.
+            descriptors.begin_frame(0).unwrap();
+            for _ in 0..FIRST_SETS_PER_POOL * 2 {
+                descriptors.allocate(layout).unwrap();
+            }
+            let pools = descriptors.current().pools();
+            assert!(pools > 1);
+
+            // Coming back round, the same pools are used again.
+            descriptors.begin_frame(2).unwrap();
+            for _ in 0..FIRST_SETS_PER_POOL * 2 {
+                descriptors.allocate(layout).unwrap();
+            }
+            assert_eq!(descriptors.current().pools(), pools);
+            drop(descriptors);
+            layouts.destroy(raw);
+        }
+    }
+}
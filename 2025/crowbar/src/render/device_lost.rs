@@ -0,0 +1,91 @@
+//! Getting back from `VK_ERROR_DEVICE_LOST`.
+//!
+//! A device is lost when the GPU hangs or faults, or its driver is reset or updated under us. Nothing made on it
+//! works after that, only destroying things does. The renderer writes down what it can about where the GPU got to
+//! in a [`DeviceLost`], including what its [`super::breadcrumbs`] say, and stops drawing.
+//!
+//! The app then asks its plugins what to do through [`Plugin::device_lost`]. Recovering tears the renderer down
+//! and makes it again with the settings it had, on a new device. Its pipelines are built again from what they were
+//! added with, and windows get new surfaces and swapchains. Anything else made on the old device is gone, so
+//! plugins let go of theirs when asked, and make them again on the new renderer. Exiting shuts down cleanly, as if
+//! asked to.
+//!
+//! [`Plugin::device_lost`]: crate::plugin::Plugin::device_lost
+
+use std::fmt;
+
+/// After this many losses in a run, a device that keeps getting lost probably always will, so the app exits
+/// rather than recovering, unless a plugin says otherwise.
+pub const MAX_RECOVERIES: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceLostAction {
+    /// Make the renderer again, on a new device.
+    Recover,
+    /// Shut down cleanly.
+    Exit,
+}
+
+/// What there is to know about a device loss.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceLost {
+    /// The device's name.
+    pub device: String,
+    /// The last frame begun before the loss was seen.
+    pub frame: u64,
+    /// How far the GPU got through the frames in flight, if there were breadcrumbs to ask.
+    pub breadcrumbs: Option<String>,
+    /// How many times the device was lost before this, this run.
+    pub previous: u32,
+}
+
+impl DeviceLost {
+    /// What to do if no plugin has a say: recover, unless it's happened [`MAX_RECOVERIES`] times already.
+    pub fn default_action(&self) -> DeviceLostAction {
+        match self.previous < MAX_RECOVERIES {
+            true => DeviceLostAction::Recover,
+            false => DeviceLostAction::Exit,
+        }
+    }
+}
+
+impl fmt::Display for DeviceLost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lost {} on frame {}", self.device, self.frame)?;
+        if self.previous > 0 {
+            write!(f, ", {} times before this run", self.previous)?;
+        }
+        return match &self.breadcrumbs {
+            Some(breadcrumbs) => write!(f, "\n{}", breadcrumbs.trim_end()),
+            None => write!(f, ", no breadcrumbs to say where"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeviceLost, DeviceLostAction, MAX_RECOVERIES};
+
+    #[test]
+    pub fn recovers_until_it_keeps_happening() {
+        let mut lost = DeviceLost {
+            device: "Test GPU".into(),
+            frame: 42,
+            breadcrumbs: None,
+            previous: 0,
+        };
+        assert_eq!(lost.default_action(), DeviceLostAction::Recover);
+        assert_eq!(
+            lost.to_string(),
+            "Lost Test GPU on frame 42, no breadcrumbs to say where"
+        );
+
+        lost.previous = MAX_RECOVERIES;
+        lost.breadcrumbs = Some("Frame 42:\n  clear: Started\n".into());
+        assert_eq!(lost.default_action(), DeviceLostAction::Exit);
+        assert_eq!(
+            lost.to_string(),
+            "Lost Test GPU on frame 42, 3 times before this run\nFrame 42:\n  clear: Started"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! `--diag`: a report of what Vulkan has to offer here, for attaching to bug reports.
+//!
+//! Covers the loader, instance extensions and layers, then for each device its extensions, queue families, memory,
+//! what it can present to a window with, and its limits.
+
+use std::{
+    ffi::CString,
+    fmt::{self, Write},
+    fs,
+    path::PathBuf,
+};
+
+use ash::{Entry, Instance, khr, vk};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    raw_window_handle::HasDisplayHandle,
+    window::{Window, WindowAttributes, WindowId},
+};
+
+use super::{VK_ENTRY, alloc::VK_ALLOCATOR_CALLBACKS, surface};
+use crate::{app::info::AppInfo, consts::BUILD};
+
+fn version(v: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(v),
+        vk::api_version_minor(v),
+        vk::api_version_patch(v)
+    )
+}
+
+fn name(s: Result<&std::ffi::CStr, std::ffi::FromBytesUntilNulError>) -> String {
+    s.map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Write out the report. A window adds the surface formats and present modes, which need something to present to.
+pub fn report(info: &AppInfo, window: Option<&Window>) -> String {
+    let mut out = String::new();
+    // Writing to a String can't fail.
+    let _ = write_report(&mut out, info, window);
+    return out;
+}
+
+fn write_report(out: &mut String, info: &AppInfo, window: Option<&Window>) -> fmt::Result {
+    writeln!(out, "{BUILD}")?;
+    writeln!(
+        out,
+        "OS: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )?;
+
+    let Some(entry) = VK_ENTRY.as_ref() else {
+        writeln!(out, "No Vulkan loader found.")?;
+        return Ok(());
+    };
+
+    // SAFETY: Just queries on the entry.
+    let (loader, extensions, layers) = unsafe {
+        (
+            entry.try_enumerate_instance_version().ok().flatten(),
+            entry
+                .enumerate_instance_extension_properties(None)
+                .unwrap_or_default(),
+            entry
+                .enumerate_instance_layer_properties()
+                .unwrap_or_default(),
+        )
+    };
+    writeln!(
+        out,
+        "Loader: {}",
+        loader.map_or("1.0 (no vkEnumerateInstanceVersion)".into(), version)
+    )?;
+    writeln!(out, "\nInstance extensions:")?;
+    for ext in &extensions {
+        writeln!(
+            out,
+            "  {} v{}",
+            name(ext.extension_name_as_c_str()),
+            ext.spec_version
+        )?;
+    }
+    writeln!(out, "\nLayers:")?;
+    for layer in &layers {
+        writeln!(
+            out,
+            "  {} ({}, v{}): {}",
+            name(layer.layer_name_as_c_str()),
+            version(layer.spec_version),
+            layer.implementation_version,
+            name(layer.description_as_c_str())
+        )?;
+    }
+
+    // Only ask for surface extensions the loader has, so a missing one costs the surface part and not the report.
+    let surface_extensions = window
+        .and_then(|w| w.display_handle().ok())
+        .and_then(|d| surface::required_extensions(d.as_raw()))
+        .filter(|required| {
+            required.iter().all(|r| {
+                extensions
+                    .iter()
+                    .any(|e| e.extension_name_as_c_str() == Ok(*r))
+            })
+        });
+    let enabled: Vec<_> = surface_extensions
+        .iter()
+        .flatten()
+        .map(|e| e.as_ptr())
+        .collect();
+
+    let app_name = CString::new(info.name()).unwrap_or_default();
+    let app_info = vk::ApplicationInfo::default()
+        .application_name(&app_name)
+        .api_version(vk::API_VERSION_1_3);
+    // SAFETY: The extensions were checked for above.
+    let instance = match unsafe {
+        entry.create_instance(
+            &vk::InstanceCreateInfo::default()
+                .application_info(&app_info)
+                .enabled_extension_names(&enabled),
+            Some(&*VK_ALLOCATOR_CALLBACKS),
+        )
+    } {
+        Ok(instance) => instance,
+        Err(e) => {
+            writeln!(out, "\nCouldn't create an instance: {e}")?;
+            return Ok(());
+        }
+    };
+
+    // SAFETY: The surface is made from the window, which outlives it, and everything is destroyed before returning.
+    unsafe {
+        let surface = match (window, surface_extensions) {
+            (Some(window), Some(_)) => surface::create_surface(entry, &instance, window).ok(),
+            _ => None,
+        };
+        if surface.is_none() {
+            writeln!(
+                out,
+                "\nNo window surface, so no surface formats or present modes."
+            )?;
+        }
+
+        let result = write_devices(out, entry, &instance, surface);
+
+        if let Some(surface) = surface {
+            khr::surface::Instance::new(entry, &instance)
+                .destroy_surface(surface, Some(&*VK_ALLOCATOR_CALLBACKS));
+        }
+        instance.destroy_instance(Some(&*VK_ALLOCATOR_CALLBACKS));
+        return result;
+    }
+}
+
+/// # Safety
+/// `surface` must belong to `instance`.
+unsafe fn write_devices(
+    out: &mut String,
+    entry: &Entry,
+    instance: &Instance,
+    surface: Option<vk::SurfaceKHR>,
+) -> fmt::Result {
+    let surfaces = khr::surface::Instance::new(entry, instance);
+
+    // SAFETY: Queries on handles from this instance.
+    unsafe {
+        for pd in instance.enumerate_physical_devices().unwrap_or_default() {
+            let props = instance.get_physical_device_properties(pd);
+            writeln!(out, "\n== {} ==", name(props.device_name_as_c_str()))?;
+            writeln!(out, "Type: {:?}", props.device_type)?;
+            writeln!(out, "API version: {}", version(props.api_version))?;
+            writeln!(
+                out,
+                "Driver version: {:#x}, vendor {:#06x}, device {:#06x}",
+                props.driver_version, props.vendor_id, props.device_id
+            )?;
+            if props.api_version >= vk::API_VERSION_1_2 {
+                let mut driver = vk::PhysicalDeviceDriverProperties::default();
+                let mut props2 = vk::PhysicalDeviceProperties2::default().push_next(&mut driver);
+                instance.get_physical_device_properties2(pd, &mut props2);
+                writeln!(
+                    out,
+                    "Driver: {:?}, {} {}",
+                    driver.driver_id,
+                    name(driver.driver_name_as_c_str()),
+                    name(driver.driver_info_as_c_str())
+                )?;
+            }
+
+            writeln!(out, "\nQueue families:")?;
+            let families = instance.get_physical_device_queue_family_properties(pd);
+            for (i, family) in families.iter().enumerate() {
+                let present = match surface {
+                    Some(surface) => surfaces
+                        .get_physical_device_surface_support(pd, i as u32, surface)
+                        .map_or(", present?", |p| if p { ", present" } else { "" }),
+                    None => "",
+                };
+                writeln!(
+                    out,
+                    "  {i}: {:?} x{}, {} timestamp bits{present}",
+                    family.queue_flags, family.queue_count, family.timestamp_valid_bits
+                )?;
+            }
+
+            let memory = instance.get_physical_device_memory_properties(pd);
+            writeln!(out, "\nMemory heaps:")?;
+            for (i, heap) in memory.memory_heaps_as_slice().iter().enumerate() {
+                writeln!(
+                    out,
+                    "  {i}: {} MiB {:?}",
+                    heap.size / (1024 * 1024),
+                    heap.flags
+                )?;
+            }
+            writeln!(out, "Memory types:")?;
+            for (i, ty) in memory.memory_types_as_slice().iter().enumerate() {
+                writeln!(out, "  {i}: heap {} {:?}", ty.heap_index, ty.property_flags)?;
+            }
+
+            if let Some(surface) = surface {
+                writeln!(out, "\nSurface formats:")?;
+                for format in surfaces
+                    .get_physical_device_surface_formats(pd, surface)
+                    .unwrap_or_default()
+                {
+                    writeln!(out, "  {:?} {:?}", format.format, format.color_space)?;
+                }
+                let modes = surfaces
+                    .get_physical_device_surface_present_modes(pd, surface)
+                    .unwrap_or_default();
+                writeln!(out, "Present modes: {modes:?}")?;
+                if let Ok(caps) = surfaces.get_physical_device_surface_capabilities(pd, surface) {
+                    writeln!(
+                        out,
+                        "Images: {}..={}, usage {:?}",
+                        caps.min_image_count, caps.max_image_count, caps.supported_usage_flags
+                    )?;
+                }
+            }
+
+            writeln!(out, "\nDevice extensions:")?;
+            for ext in instance
+                .enumerate_device_extension_properties(pd)
+                .unwrap_or_default()
+            {
+                writeln!(
+                    out,
+                    "  {} v{}",
+                    name(ext.extension_name_as_c_str()),
+                    ext.spec_version
+                )?;
+            }
+
+            writeln!(out, "\nLimits: {:#?}", props.limits)?;
+        }
+    }
+
+    return Ok(());
+}
+
+struct DiagApp {
+    info: AppInfo,
+    output: Option<PathBuf>,
+    done: bool,
+    failed: bool,
+}
+
+impl DiagApp {
+    fn finish(&mut self, report: &str) {
+        print!("{report}");
+        if let Some(path) = &self.output {
+            if let Err(e) = fs::write(path, report) {
+                eprintln!("Couldn't write {}: {e}", path.display());
+                self.failed = true;
+            } else {
+                eprintln!("Wrote {}", path.display());
+            }
+        }
+    }
+}
+
+impl ApplicationHandler for DiagApp {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+
+        // Never shown, it's only there to ask about surfaces.
+        let window = event_loop.create_window(
+            WindowAttributes::default()
+                .with_title(self.info.name())
+                .with_visible(false),
+        );
+        if let Err(e) = &window {
+            log::warn!("No window for the diagnostics report: {e}");
+        }
+
+        self.finish(&report(&self.info, window.as_ref().ok()));
+        event_loop.exit();
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, _: WindowEvent) {}
+}
+
+/// Print the report, and write it to `output` as well if given. False if that failed.
+/// Runs an event loop of its own for a window, or does without one if there's no display.
+pub fn run(info: &AppInfo, output: Option<PathBuf>) -> bool {
+    let mut app = DiagApp {
+        info: info.clone(),
+        output,
+        done: false,
+        failed: false,
+    };
+    match EventLoop::new() {
+        Ok(event_loop) => {
+            if let Err(e) = event_loop.run_app(&mut app) {
+                eprintln!("Event loop failed: {e}");
+                return false;
+            }
+        }
+        Err(e) => {
+            log::warn!("No display for the diagnostics report: {e}");
+            app.finish(&report(info, None));
+        }
+    }
+    return !app.failed;
+}
+
+#[cfg(test)]
+mod test {
+    use super::report;
+    use crate::{app::info::AppInfo, render::VK_ENTRY};
+
+    #[test]
+    pub fn report_without_window() {
+        let report = report(&AppInfo::default(), None);
+        if VK_ENTRY.is_none() {
+            assert!(report.contains("No Vulkan loader"));
+            return;
+        }
+        assert!(report.contains("Loader: "));
+        assert!(report.contains("No window surface"));
+        // Every device gets its memory listed.
+        assert_eq!(
+            report.matches("\n== ").count(),
+            report.matches("Memory heaps:").count()
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Draw submission: what a view wants drawn, sorted to keep state changes down and merged into instanced draws
+//! wherever nothing but the transform tells draws apart.
+//!
+//! Opaque draws sort by [`StateKey`], pipeline first as it's the most expensive thing to switch, then front to back
+//! so early depth testing gets to throw away more. Transparent draws have to go back to front to blend right, so
+//! they only merge with neighbours that happen to match.
+
+use glam::{Affine3A, Mat4, Vec3};
+
+use super::extract::{ExtractedCamera, ExtractedMesh, ExtractedScene};
+use crate::{
+    ecs::components::{Lightmapped, MaterialId, MeshId},
+    math::bounds::Frustum,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PipelineId(pub u32);
+
+/// What has to match for draws to share a call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StateKey {
+    pub pipeline: PipelineId,
+    pub material: MaterialId,
+    pub mesh: MeshId,
+}
+
+impl StateKey {
+    fn sort_key(&self) -> (u32, u32, u32) {
+        (self.pipeline.0, self.material.0, self.mesh.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Draw {
+    pub key: StateKey,
+    pub transform: Affine3A,
+    /// `transform` as of the last frame, for motion vectors.
+    pub previous_transform: Affine3A,
+    /// Where its lightmap UVs go, if it's lit from the lightmap.
+    pub lightmap: Option<Lightmapped>,
+    /// Distance in front of the camera.
+    pub depth: f32,
+}
+
+/// One instanced draw call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawBatch {
+    pub key: StateKey,
+    /// Where its transforms start in [`DrawList::instances`].
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+/// Before and after batching, for tuning.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    /// Draws pushed.
+    pub submitted: u32,
+    /// Calls they turned into.
+    pub draw_calls: u32,
+    pub opaque_calls: u32,
+    pub transparent_calls: u32,
+}
+
+/// A view's draws for a frame. Keep it around and [`DrawList::clear`] it each frame, to reuse its allocations.
+#[derive(Default)]
+pub struct DrawList {
+    opaque: Vec<Draw>,
+    transparent: Vec<Draw>,
+    instances: Vec<Affine3A>,
+    /// Alongside `instances`, as of the last frame.
+    previous_instances: Vec<Affine3A>,
+    /// Alongside `instances`, where each one's lightmap UVs go.
+    lightmaps: Vec<Option<Lightmapped>>,
+    batches: Vec<DrawBatch>,
+    /// Where the transparent batches start in `batches`.
+    transparent_start: usize,
+}
+
+impl DrawList {
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+        self.instances.clear();
+        self.previous_instances.clear();
+        self.lightmaps.clear();
+        self.batches.clear();
+        self.transparent_start = 0;
+    }
+
+    pub fn push(&mut self, draw: Draw, transparent: bool) {
+        if transparent {
+            self.transparent.push(draw);
+        } else {
+            self.opaque.push(draw);
+        }
+    }
+
+    /// Push an extracted mesh, as seen from `camera`.
+    pub fn push_mesh(
+        &mut self,
+        mesh: &ExtractedMesh,
+        camera: &ExtractedCamera,
+        pipeline: PipelineId,
+        transparent: bool,
+    ) {
+        self.push_seen_from(mesh, &camera.world, pipeline, transparent);
+    }
+
+    /// Push an extracted mesh, as seen from a view at `world`.
+    fn push_seen_from(
+        &mut self,
+        mesh: &ExtractedMesh,
+        world: &Affine3A,
+        pipeline: PipelineId,
+        transparent: bool,
+    ) {
+        let eye = Vec3::from(world.translation);
+        let forward = -Vec3::from(world.matrix3.z_axis).normalize_or_zero();
+        let draw = Draw {
+            key: StateKey {
+                pipeline,
+                material: mesh.material,
+                mesh: mesh.mesh,
+            },
+            transform: mesh.world,
+            previous_transform: mesh.previous_world,
+            lightmap: mesh.lightmap,
+            depth: (mesh.bounds.center() - eye).dot(forward),
+        };
+        self.push(draw, transparent);
+    }
+
+    /// Refill the list with the meshes in `scene` that `camera` might see, at `aspect`, and build it. Everything is
+    /// opaque and drawn with `pipeline` until materials say otherwise.
+    pub fn cull(
+        &mut self,
+        scene: &ExtractedScene,
+        camera: &ExtractedCamera,
+        aspect: f32,
+        pipeline: PipelineId,
+    ) {
+        self.cull_from(scene, &camera.world, &camera.view_proj(aspect), pipeline);
+    }
+
+    /// [`DrawList::cull`], from a view at `world` that isn't a camera's, like a reflection's.
+    pub fn cull_from(
+        &mut self,
+        scene: &ExtractedScene,
+        world: &Affine3A,
+        view_proj: &Mat4,
+        pipeline: PipelineId,
+    ) {
+        self.clear();
+        let frustum = Frustum::from_view_proj(view_proj);
+        scene.cull(&frustum, |mesh| {
+            self.push_seen_from(mesh, world, pipeline, false)
+        });
+        self.build(true);
+    }
+
+    /// Sort everything pushed and merge it into batches. With `merge` off every draw gets a call of its own, to
+    /// compare against.
+    pub fn build(&mut self, merge: bool) {
+        self.opaque.sort_by(|a, b| {
+            a.key
+                .sort_key()
+                .cmp(&b.key.sort_key())
+                .then(a.depth.total_cmp(&b.depth))
+        });
+        self.transparent.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+
+        self.instances.clear();
+        self.previous_instances.clear();
+        self.lightmaps.clear();
+        self.batches.clear();
+        for draw in &self.opaque {
+            Self::add(&mut self.batches, 0, &mut self.instances, draw, merge);
+        }
+        self.transparent_start = self.batches.len();
+        for draw in &self.transparent {
+            let start = self.transparent_start;
+            Self::add(&mut self.batches, start, &mut self.instances, draw, merge);
+        }
+        let drawn = || self.opaque.iter().chain(&self.transparent);
+        self.previous_instances
+            .extend(drawn().map(|d| d.previous_transform));
+        self.lightmaps.extend(drawn().map(|d| d.lightmap));
+    }
+
+    /// Add `draw`, merging it into the last batch if it matches and isn't before `start`.
+    fn add(
+        batches: &mut Vec<DrawBatch>,
+        start: usize,
+        instances: &mut Vec<Affine3A>,
+        draw: &Draw,
+        merge: bool,
+    ) {
+        instances.push(draw.transform);
+        if merge
+            && batches.len() > start
+            && let Some(last) = batches.last_mut()
+            && last.key == draw.key
+        {
+            last.instance_count += 1;
+            return;
+        }
+        batches.push(DrawBatch {
+            key: draw.key,
+            first_instance: instances.len() as u32 - 1,
+            instance_count: 1,
+        });
+    }
+
+    /// Every transform, in the order the batches use them.
+    pub fn instances(&self) -> &[Affine3A] {
+        &self.instances
+    }
+
+    /// [`DrawList::instances`] as of the last frame, in the same order.
+    pub fn previous_instances(&self) -> &[Affine3A] {
+        &self.previous_instances
+    }
+
+    /// Where each of [`DrawList::instances`]' lightmap UVs go, in the same order.
+    pub fn lightmaps(&self) -> &[Option<Lightmapped>] {
+        &self.lightmaps
+    }
+
+    /// As of the last [`DrawList::build`].
+    pub fn opaque_batches(&self) -> &[DrawBatch] {
+        &self.batches[..self.transparent_start]
+    }
+
+    /// As of the last [`DrawList::build`], after the opaque ones.
+    pub fn transparent_batches(&self) -> &[DrawBatch] {
+        &self.batches[self.transparent_start..]
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        let opaque = self.transparent_start as u32;
+        let transparent = (self.batches.len() - self.transparent_start) as u32;
+        DrawStats {
+            submitted: (self.opaque.len() + self.transparent.len()) as u32,
+            draw_calls: opaque + transparent,
+            opaque_calls: opaque,
+            transparent_calls: transparent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec3};
+    use hecs::World;
+
+    use super::{Draw, DrawList, DrawStats, PipelineId, StateKey};
+    use crate::{
+        ecs::components::{Camera, GlobalTransform, MaterialId, MeshId, MeshRenderer, Projection},
+        render::extract::ExtractedScene,
+    };
+
+    fn draw(pipeline: u32, material: u32, depth: f32) -> Draw {
+        Draw {
+            key: StateKey {
+                pipeline: PipelineId(pipeline),
+                material: MaterialId(material),
+                mesh: MeshId(0),
+            },
+            transform: Affine3A::from_translation(Vec3::Z * depth),
+            previous_transform: Affine3A::from_translation(Vec3::Z * (depth + 1.0)),
+            lightmap: None,
+            depth,
+        }
+    }
+
+    #[test]
+    pub fn sorts_and_merges() {
+        let mut list = DrawList::default();
+        for (pipeline, material, depth) in [
+            (1, 0, 5.0),
+            (0, 1, 3.0),
+            (1, 0, 1.0),
+            (0, 1, 2.0),
+            (0, 0, 9.0),
+        ] {
+            list.push(draw(pipeline, material, depth), false);
+        }
+        // Back to front, with a different material between the last one and the rest.
+        for (material, depth) in [(2, 4.0), (3, 5.0), (2, 6.0), (2, 8.0)] {
+            list.push(draw(2, material, depth), true);
+        }
+        list.build(true);
+
+        let opaque: Vec<_> = list
+            .opaque_batches()
+            .iter()
+            .map(|b| (b.key.pipeline.0, b.key.material.0, b.instance_count))
+            .collect();
+        assert_eq!(opaque, vec![(0, 0, 1), (0, 1, 2), (1, 0, 2)]);
+        let transparent: Vec<_> = list
+            .transparent_batches()
+            .iter()
+            .map(|b| (b.key.material.0, b.instance_count))
+            .collect();
+        assert_eq!(transparent, vec![(2, 2), (3, 1), (2, 1)]);
+
+        // Front to back within a batch.
+        let batch = list.opaque_batches()[1];
+        let first = &list.instances()[batch.first_instance as usize];
+        assert_eq!(first.translation.z, 2.0);
+        // Last frame's transforms line up with them.
+        let previous = list.previous_instances();
+        assert_eq!(previous.len(), list.instances().len());
+        assert_eq!(previous[batch.first_instance as usize].translation.z, 3.0);
+        assert_eq!(list.lightmaps().len(), list.instances().len());
+
+        assert_eq!(
+            list.stats(),
+            DrawStats {
+                submitted: 9,
+                draw_calls: 6,
+                opaque_calls: 3,
+                transparent_calls: 3,
+            }
+        );
+        list.build(false);
+        assert_eq!(list.stats().draw_calls, 9);
+    }
+
+    #[test]
+    pub fn keeps_opaque_and_transparent_apart() {
+        let mut list = DrawList::default();
+        list.push(draw(0, 0, 1.0), false);
+        list.push(draw(0, 0, 2.0), true);
+        list.build(true);
+
+        assert_eq!(list.opaque_batches().len(), 1);
+        assert_eq!(list.opaque_batches()[0].instance_count, 1);
+        assert_eq!(list.transparent_batches().len(), 1);
+        assert_eq!(list.transparent_batches()[0].first_instance, 1);
+    }
+
+    #[test]
+    pub fn culls_what_the_camera_cant_see() {
+        let mut world = World::new();
+        world.spawn((
+            Camera {
+                projection: Projection::Perspective {
+                    fov_y: 1.0,
+                    near: 0.1,
+                    far: 100.0,
+                },
+                order: 0,
+                active: true,
+                lens: None,
+            },
+            GlobalTransform(Affine3A::IDENTITY),
+        ));
+        // In front twice, behind, and in front but hidden.
+        for (z, visible) in [(-5.0, true), (-10.0, true), (5.0, true), (-5.0, false)] {
+            let mesh = MeshRenderer {
+                mesh: MeshId(0),
+                material: MaterialId(0),
+                visible,
+                cast_shadows: false,
+            };
+            world.spawn((
+                mesh,
+                GlobalTransform(Affine3A::from_translation(Vec3::Z * z)),
+            ));
+        }
+        let mut scene = ExtractedScene::default();
+        scene.extract(&world);
+
+        let mut list = DrawList::default();
+        list.cull(&scene, &scene.cameras[0], 1.0, PipelineId(0));
+        assert_eq!(list.stats().submitted, 2);
+        assert_eq!(list.opaque_batches().len(), 1);
+        // Nearest first.
+        assert_eq!(list.instances()[0].translation.z, -5.0);
+    }
+}
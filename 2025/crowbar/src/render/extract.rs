@@ -0,0 +1,308 @@
+//! Extraction of the render relevant parts of the world into a plain snapshot.
+//! The renderer only ever reads this, so simulation can carry on with the world while a frame records.
+
+use std::{collections::HashMap, sync::Arc};
+
+use glam::{Affine3A, Mat4, Vec2, Vec3};
+use hecs::{Entity, World};
+
+use crate::{
+    color::LinearColor,
+    ecs::{
+        components::{
+            BakedLightmap, Camera, Foliage, GlobalTransform, IrradianceProbes, Lens, Light,
+            LightKind, Lightmapped, MaterialId, MeshId, MeshRenderer, Outline, PlanarReflector,
+            Portal, PortalKind, Projection, Reflections, Water,
+        },
+        spatial::SpatialIndex,
+    },
+    math::{
+        self, Plane,
+        bounds::{Aabb, Frustum},
+    },
+    render::{
+        foliage::FoliagePatch, lightmap::Lightmap, probes::ProbeGrid, reflections::ReflectionProbes,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct ExtractedCamera {
+    pub entity: Entity,
+    pub world: Affine3A,
+    /// `world` as of the last extraction, the same as it for a camera that wasn't there, for motion vectors.
+    pub previous_world: Affine3A,
+    pub projection: Projection,
+    pub order: i32,
+    pub lens: Option<Lens>,
+}
+
+impl ExtractedCamera {
+    pub fn view_proj(&self, aspect: f32) -> Mat4 {
+        return self.projection.matrix(aspect) * math::view_matrix(&self.world);
+    }
+
+    /// [`ExtractedCamera::view_proj`] as of the last extraction, with the projection as it is now.
+    pub fn previous_view_proj(&self, aspect: f32) -> Mat4 {
+        return self.projection.matrix(aspect) * math::view_matrix(&self.previous_world);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedMesh {
+    pub entity: Entity,
+    pub world: Affine3A,
+    /// `world` as of the last extraction, the same as it for a mesh that wasn't there, for motion vectors.
+    pub previous_world: Affine3A,
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub cast_shadows: bool,
+    /// World space.
+    pub bounds: Aabb,
+    pub outline: Option<Outline>,
+    /// Where its lightmap UVs go in [`ExtractedScene::lightmap`], if it's lit from there.
+    pub lightmap: Option<Lightmapped>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedLight {
+    pub entity: Entity,
+    pub kind: LightKind,
+    pub position: Vec3,
+    /// Normalized, down the entity's -Z axis.
+    pub direction: Vec3,
+    pub color: LinearColor,
+    pub intensity: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedReflector {
+    pub entity: Entity,
+    /// The surface is on this transform's XZ plane.
+    pub world: Affine3A,
+    /// World space, facing the side that's reflected.
+    pub plane: Plane,
+    pub resolution_scale: f32,
+    pub clip_offset: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedWater {
+    pub entity: Entity,
+    /// The surface is the unit square on this transform's XZ plane.
+    pub world: Affine3A,
+    pub water: Water,
+    /// Whether it has an active planar reflector, see [`ExtractedScene::reflectors`].
+    pub reflects: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedFoliage {
+    pub entity: Entity,
+    pub patch: Arc<FoliagePatch>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractedPortal {
+    pub entity: Entity,
+    /// The surface is on this transform's XZ plane.
+    pub world: Affine3A,
+    pub size: Vec2,
+    pub max_depth: u32,
+    /// Moves a camera looking into it to where it looks out from, see [`super::portal::through`].
+    pub transform: Affine3A,
+    /// World space, what the view out of it is clipped to.
+    pub clip: Plane,
+    /// Whether `transform` mirrors, turning triangles round.
+    pub mirrors: bool,
+}
+
+#[derive(Default)]
+pub struct ExtractedScene {
+    /// Active cameras, sorted by render order.
+    pub cameras: Vec<ExtractedCamera>,
+    pub meshes: Vec<ExtractedMesh>,
+    pub lights: Vec<ExtractedLight>,
+    /// What lights the meshes besides `lights`, if the level's had them baked.
+    pub probes: Option<Arc<ProbeGrid>>,
+    /// What lights meshes with [`Lightmapped`] instead of the probes, if the level's had it baked.
+    pub lightmap: Option<Arc<Lightmap>>,
+    /// What the meshes reflect, if the level has any probes.
+    pub reflections: Option<Arc<ReflectionProbes>>,
+    /// Active planar reflectors.
+    pub reflectors: Vec<ExtractedReflector>,
+    pub waters: Vec<ExtractedWater>,
+    pub foliage: Vec<ExtractedFoliage>,
+    /// Active portals, with somewhere to look out of.
+    pub portals: Vec<ExtractedPortal>,
+    /// Kept across frames, it only needs touching for what moved.
+    spatial: SpatialIndex,
+    /// Where each entity's mesh is in `meshes`.
+    mesh_index: HashMap<Entity, usize>,
+    /// Camera and mesh transforms as of the last extraction.
+    previous: HashMap<Entity, Affine3A>,
+}
+
+impl ExtractedScene {
+    /// The spatial index as of the last extraction.
+    pub fn spatial(&self) -> &SpatialIndex {
+        &self.spatial
+    }
+
+    /// Meshes that might be visible in `frustum`.
+    pub fn cull(&self, frustum: &Frustum, mut f: impl FnMut(&ExtractedMesh)) {
+        self.spatial.query_frustum(frustum, |e| {
+            // Hidden meshes are in the index but weren't extracted.
+            if let Some(&i) = self.mesh_index.get(&e) {
+                f(&self.meshes[i]);
+            }
+        });
+    }
+
+    /// Meshes with an outline, for [`super::outline`]'s mask pass.
+    pub fn outlined(&self) -> impl Iterator<Item = (&ExtractedMesh, Outline)> {
+        self.meshes.iter().filter_map(|m| Some((m, m.outline?)))
+    }
+
+    /// Refill this snapshot from the world, reusing the allocations.
+    pub fn extract(&mut self, world: &World) {
+        self.previous.clear();
+        let cameras = self.cameras.drain(..).map(|c| (c.entity, c.world));
+        self.previous.extend(cameras);
+        let meshes = self.meshes.drain(..).map(|m| (m.entity, m.world));
+        self.previous.extend(meshes);
+        self.lights.clear();
+        self.reflectors.clear();
+        self.waters.clear();
+        self.foliage.clear();
+        self.portals.clear();
+        self.mesh_index.clear();
+        self.spatial.sync(world);
+
+        for (entity, (cam, g)) in world.query::<(&Camera, &GlobalTransform)>().iter() {
+            if !cam.active {
+                continue;
+            }
+
+            self.cameras.push(ExtractedCamera {
+                entity,
+                world: g.0,
+                previous_world: self.previous.get(&entity).copied().unwrap_or(g.0),
+                projection: cam.projection,
+                order: cam.order,
+                lens: cam.lens,
+            });
+        }
+        self.cameras.sort_by_key(|c| c.order);
+
+        for (entity, (mr, g, outline, lightmapped)) in world
+            .query::<(
+                &MeshRenderer,
+                &GlobalTransform,
+                Option<&Outline>,
+                Option<&Lightmapped>,
+            )>()
+            .iter()
+        {
+            if !mr.visible {
+                continue;
+            }
+
+            self.mesh_index.insert(entity, self.meshes.len());
+            self.meshes.push(ExtractedMesh {
+                entity,
+                world: g.0,
+                previous_world: self.previous.get(&entity).copied().unwrap_or(g.0),
+                mesh: mr.mesh,
+                material: mr.material,
+                cast_shadows: mr.cast_shadows,
+                bounds: mr.local_bounds().transformed(&g.0),
+                outline: outline.copied(),
+                lightmap: lightmapped.copied(),
+            });
+        }
+
+        for (entity, (light, g)) in world.query::<(&Light, &GlobalTransform)>().iter() {
+            self.lights.push(ExtractedLight {
+                entity,
+                kind: light.kind,
+                position: g.0.translation.into(),
+                direction: g.0.transform_vector3(math::FORWARD).normalize_or_zero(),
+                color: light.color,
+                intensity: light.intensity,
+            });
+        }
+
+        self.probes = world
+            .query::<&IrradianceProbes>()
+            .iter()
+            .next()
+            .map(|(_, p)| p.0.clone());
+        self.lightmap = world
+            .query::<&BakedLightmap>()
+            .iter()
+            .next()
+            .map(|(_, l)| l.0.clone());
+        self.reflections = world
+            .query::<&Reflections>()
+            .iter()
+            .next()
+            .map(|(_, r)| r.0.clone());
+
+        for (entity, (reflector, g)) in world.query::<(&PlanarReflector, &GlobalTransform)>().iter()
+        {
+            if !reflector.active {
+                continue;
+            }
+
+            self.reflectors.push(ExtractedReflector {
+                entity,
+                world: g.0,
+                plane: Plane::from_point_normal(Vec3::ZERO, math::UP).transformed(&g.0),
+                resolution_scale: reflector.resolution_scale,
+                clip_offset: reflector.clip_offset,
+            });
+        }
+
+        for (entity, (water, g, reflector)) in world
+            .query::<(&Water, &GlobalTransform, Option<&PlanarReflector>)>()
+            .iter()
+        {
+            self.waters.push(ExtractedWater {
+                entity,
+                world: g.0,
+                water: *water,
+                reflects: reflector.is_some_and(|r| r.active),
+            });
+        }
+
+        for (entity, foliage) in world.query::<&Foliage>().iter() {
+            self.foliage.push(ExtractedFoliage {
+                entity,
+                patch: foliage.0.clone(),
+            });
+        }
+
+        for (entity, (portal, g)) in world.query::<(&Portal, &GlobalTransform)>().iter() {
+            if !portal.active {
+                continue;
+            }
+
+            let exit = portal
+                .exit
+                .and_then(|e| world.get::<&GlobalTransform>(e).ok().map(|g| g.0));
+            let Some((transform, clip)) = super::portal::through(portal.kind, &g.0, exit.as_ref())
+            else {
+                continue;
+            };
+            self.portals.push(ExtractedPortal {
+                entity,
+                world: g.0,
+                size: portal.size,
+                max_depth: portal.max_depth,
+                transform,
+                clip,
+                mirrors: portal.kind == PortalKind::Mirror,
+            });
+        }
+    }
+}
@@ -0,0 +1,1183 @@
+//! Foliage: grass, flowers and bushes scattered over a surface by the thousand, drawn instanced.
+//!
+//! [`scatter`] places a [`FoliageLayer`]'s instances over an area on a jittered grid, thinned by a [`DensityMap`]
+//! and by how steep the surface is. Each grid cell seeds its own numbers, so the same area and seed always grows
+//! the same plants, whichever way it's split into chunks.
+//!
+//! Scattered, a layer goes in the scene as a [`FoliagePatch`] on a [`Foliage`] entity. Each frame [`FoliagePass`]
+//! has [`FoliageCuller`] run a compute pass over each patch's instances (`foliage/cull.comp`) that throws away those
+//! outside the last camera's view or past [`FoliageLayer::fade_end`], and appends the rest, with how far faded out
+//! they are, for an indirect draw. [`cull`] does the same on the CPU. Then the 3D pass draws them after the meshes:
+//! the vertex shader places and sways them in the [`Wind`] with `foliage_vertex` from [`GLSL`], bending each more
+//! the further up the plant a vertex is, and the fragment shader dithers the faded ones out with
+//! `foliage_fade_discard`. Plants are unit cubes stood on end, like every mesh until there are mesh assets.
+//!
+//! Ship the shaders compiled, as [`SHADER_ASSET`], [`VERTEX_ASSET`] and [`FRAGMENT_ASSET`], or they're compiled from
+//! [`SHADER`], [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`], with [`INCLUDES`], at startup.
+//!
+//! [`Foliage`]: crate::ecs::components::Foliage
+
+use std::{f32::consts::TAU, sync::Arc};
+
+use ash::{prelude::VkResult, vk};
+use glam::{Mat4, Quat, Vec2, Vec3};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::{FrameDescriptors, LayoutBinding, LayoutCache, LayoutDesc},
+    extract::{ExtractedLight, ExtractedScene},
+    hal::{
+        BufferDesc, BufferUsage, Device, MemoryLocation,
+        vulkan::{VulkanBuffer, VulkanDevice},
+    },
+    lightmap::LightmapVertex,
+    mesh::{self, AMBIENT},
+    motion_blur,
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, TargetFormats},
+    rendering::PassContext,
+    shader::{
+        compile::{ShaderStage, builtin_shader, builtin_shader_including},
+        reflect::Spirv,
+    },
+};
+use crate::{
+    ecs::components::{LightKind, MaterialId, MeshId},
+    math::bounds::{Frustum, Sphere},
+    rng::Rng,
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+pub const GLSL: &str = include_str!("foliage/foliage.glsl");
+/// The cull pass's source, to compile at runtime.
+pub const SHADER: &str = include_str!("foliage/cull.comp");
+/// Where the compiled cull pass goes among the assets, without the `.spv`.
+pub const SHADER_ASSET: &str = "shaders/foliage_cull.comp";
+/// The drawing shaders' sources, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("foliage/foliage.vert");
+pub const FRAGMENT_SHADER: &str = include_str!("foliage/foliage.frag");
+/// What the drawing shaders include, to compile them at runtime.
+pub const INCLUDES: &[(&str, &str)] = &[
+    ("../foliage/foliage.glsl", GLSL),
+    ("../motion_blur/velocity.glsl", motion_blur::VELOCITY_GLSL),
+];
+/// Where the compiled drawing shaders go among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/foliage.vert";
+pub const FRAGMENT_ASSET: &str = "shaders/foliage.frag";
+/// Bytes per instance, both scattered and culled.
+pub const INSTANCE_BYTES: u64 = 48;
+
+const WORKGROUP: u32 = 64;
+const PUSH_BYTES: u32 = 128;
+/// The camera's view projection, now and as of the last frame.
+const DRAW_PUSH_BYTES: u32 = 128;
+/// Bytes of a patch's [`patch_data`], and how far apart they are in the buffer, which is as far as a uniform
+/// buffer's offset ever has to be aligned to.
+const BLOCK_BYTES: u64 = 256;
+/// A `VkDrawIndirectCommand`.
+const DRAW_BYTES: u64 = 16;
+
+/// One kind of plant and how it's scattered, faded and swayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoliageLayer {
+    /// Its root at the origin, growing up +Y.
+    pub mesh: MeshId,
+    /// Instances per square world unit where the density map is 1.
+    pub density: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Steepest slope, in radians, it grows on.
+    pub max_slope: f32,
+    /// How far it leans with the surface, from 0 for straight up to 1 for square to it.
+    pub align_to_normal: f32,
+    /// A sphere this big around the root holds the mesh at scale 1, for culling.
+    pub radius: f32,
+    /// Where it starts fading out with distance from the camera, and where it's gone.
+    pub fade_start: f32,
+    pub fade_end: f32,
+    /// How much it resists the wind. Grass is around 1, bushes more.
+    pub stiffness: f32,
+}
+
+/// How much of a layer grows where, over the area it's scattered across, from 0 to 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityMap {
+    width: u32,
+    height: u32,
+    /// Rows from the area's min Z to its max.
+    values: Vec<f32>,
+}
+
+impl DensityMap {
+    /// `width` by `height` texels, row by row. `None` if that's not how many `values` there are.
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> Option<DensityMap> {
+        if width == 0 || height == 0 || values.len() != width as usize * height as usize {
+            return None;
+        }
+        return Some(DensityMap {
+            width,
+            height,
+            values,
+        });
+    }
+
+    /// The same everywhere.
+    pub fn uniform(density: f32) -> DensityMap {
+        DensityMap {
+            width: 1,
+            height: 1,
+            values: vec![density],
+        }
+    }
+
+    /// Filtered between texel centers at `uv`, clamped to the edges.
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let at = (uv * size - 0.5).clamp(Vec2::ZERO, size - 1.0);
+        let (x0, y0) = (at.x as u32, at.y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let texel = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let t = at.fract();
+        let top = texel(x0, y0) + (texel(x1, y0) - texel(x0, y0)) * t.x;
+        let bottom = texel(x0, y1) + (texel(x1, y1) - texel(x0, y1)) * t.x;
+        return top + (bottom - top) * t.y;
+    }
+}
+
+/// Where the surface being scattered over is, under a point on the XZ plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfacePoint {
+    pub position: Vec3,
+    /// Normalized.
+    pub normal: Vec3,
+}
+
+/// One plant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoliageInstance {
+    pub position: Vec3,
+    pub scale: f32,
+    pub rotation: Quat,
+    /// Offsets its sway, so neighbours don't move in lockstep.
+    pub phase: f32,
+    pub stiffness: f32,
+}
+
+impl FoliageInstance {
+    /// How visible it is from `camera`, 1 up to the layer's fade start and 0 from its fade end. Keep in step with the
+    /// shader.
+    pub fn fade(&self, camera: Vec3, layer: &FoliageLayer) -> f32 {
+        let range = (layer.fade_end - layer.fade_start).max(1e-4);
+        return ((layer.fade_end - self.position.distance(camera)) / range).clamp(0.0, 1.0);
+    }
+}
+
+/// `layer` scattered over the XZ area from `min` to `max`, thinned by `density`, with `surface` saying where the
+/// surface is under each point, or `None` where there isn't one.
+pub fn scatter(
+    layer: &FoliageLayer,
+    min: Vec2,
+    max: Vec2,
+    density: &DensityMap,
+    seed: u64,
+    surface: impl Fn(Vec2) -> Option<SurfacePoint>,
+) -> Vec<FoliageInstance> {
+    let mut instances = Vec::new();
+    if layer.density <= 0.0 {
+        return instances;
+    }
+    let cell = layer.density.recip().sqrt();
+    let first = (min / cell).floor().as_ivec2();
+    let last = (max / cell).ceil().as_ivec2();
+    let min_up = layer.max_slope.cos();
+    for z in first.y..last.y {
+        for x in first.x..last.x {
+            let mut rng = Rng::new(seed ^ cell_hash(x, z));
+            let point = (Vec2::new(x as f32, z as f32) + Vec2::new(rng.f32(), rng.f32())) * cell;
+            let (keep, yaw, scale, phase) = (rng.f32(), rng.f32(), rng.f32(), rng.f32());
+            if point.cmplt(min).any() || point.cmpge(max).any() {
+                continue;
+            }
+            if keep >= density.sample((point - min) / (max - min)) {
+                continue;
+            }
+            let Some(hit) = surface(point) else {
+                continue;
+            };
+            if hit.normal.y < min_up {
+                continue;
+            }
+            let up = Vec3::Y.lerp(hit.normal, layer.align_to_normal).normalize();
+            instances.push(FoliageInstance {
+                position: hit.position,
+                scale: layer.min_scale + (layer.max_scale - layer.min_scale) * scale,
+                rotation: Quat::from_rotation_arc(Vec3::Y, up) * Quat::from_rotation_y(yaw * TAU),
+                phase: phase * TAU,
+                stiffness: layer.stiffness,
+            });
+        }
+    }
+    return instances;
+}
+
+fn cell_hash(x: i32, z: i32) -> u64 {
+    let cell = ((x as u32 as u64) << 32) | z as u32 as u64;
+    return cell.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(29);
+}
+
+/// The instances visible from a camera at `camera` seeing `frustum`, by index, with how faded they are. What the
+/// cull pass appends, in order rather than however the GPU gets to them.
+pub fn cull(
+    instances: &[FoliageInstance],
+    frustum: &Frustum,
+    camera: Vec3,
+    layer: &FoliageLayer,
+) -> Vec<(u32, f32)> {
+    return instances
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instance)| {
+            let fade = instance.fade(camera, layer);
+            let bounds = Sphere::new(instance.position, layer.radius * instance.scale);
+            (fade > 0.0 && frustum.intersects_sphere(&bounds)).then_some((i as u32, fade))
+        })
+        .collect();
+}
+
+/// `instances` as the cull pass reads them: per instance its position and scale, its rotation, then its phase,
+/// stiffness, a 0 and a fade of 1, which the cull pass replaces.
+pub fn gpu_data(instances: &[FoliageInstance]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(instances.len() * INSTANCE_BYTES as usize);
+    let mut vec4 = |v: [f32; 4]| v.iter().for_each(|f| out.extend(f.to_ne_bytes()));
+    for instance in instances {
+        vec4(instance.position.extend(instance.scale).to_array());
+        vec4(instance.rotation.to_array());
+        vec4([instance.phase, instance.stiffness, 0.0, 1.0]);
+    }
+    return out;
+}
+
+/// What moves the foliage, the same for every layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wind {
+    /// Which way it blows on the XZ plane. Doesn't need to be normalized.
+    pub direction: Vec2,
+    /// How far it pushes the top of a 1 unit tall plant of stiffness 1.
+    pub strength: f32,
+    /// How often it gusts, per second.
+    pub gust_frequency: f32,
+}
+
+impl Wind {
+    /// As `foliage_vertex` takes it, at `time` seconds: the direction times the strength, the gust frequency and
+    /// the time.
+    pub fn gpu_data(&self, time: f32) -> [f32; 4] {
+        let push = self.direction.normalize_or_zero() * self.strength;
+        return [push.x, push.y, self.gust_frequency, time];
+    }
+}
+
+impl Default for Wind {
+    /// A light breeze.
+    fn default() -> Self {
+        Wind {
+            direction: Vec2::new(1.0, 0.3),
+            strength: 0.15,
+            gust_frequency: 0.25,
+        }
+    }
+}
+
+/// A layer scattered over whatever it grows on, for a [`Foliage`](crate::ecs::components::Foliage) to put in the
+/// scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoliagePatch {
+    pub layer: FoliageLayer,
+    /// What colour it's drawn, like a mesh's.
+    pub material: MaterialId,
+    /// In world space, wherever the entity is.
+    pub instances: Vec<FoliageInstance>,
+}
+
+/// What the drawing shaders read for `patch` in `wind`, as [`Wind::gpu_data`] gives it, lit by `sun`: the wind, the
+/// direction towards the sun and its colour times its intensity, all 0 without one, [`AMBIENT`], and the material's
+/// colour, padded to [`BLOCK_BYTES`].
+fn patch_data(patch: &FoliagePatch, wind: [f32; 4], sun: Option<&ExtractedLight>) -> Vec<u8> {
+    let (towards, light) = sun.map_or((Vec3::ZERO, Vec3::ZERO), |sun| {
+        (-sun.direction, sun.color.to_vec3() * sun.intensity)
+    });
+    let color = mesh::material_color(patch.material).to_vec3();
+    let mut out: Vec<u8> = [
+        wind,
+        towards.extend(0.0).to_array(),
+        light.extend(0.0).to_array(),
+        AMBIENT.to_vec3().extend(0.0).to_array(),
+        color.extend(1.0).to_array(),
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(f32::to_ne_bytes)
+    .collect();
+    out.resize(BLOCK_BYTES as usize, 0);
+    return out;
+}
+
+/// A layer's instances, and where the visible ones and the draw go.
+#[derive(Clone, Copy)]
+pub struct FoliageBatch<'a> {
+    /// [`gpu_data`], needing [`BufferUsage::STORAGE`](super::hal::BufferUsage::STORAGE).
+    pub instances: &'a VulkanBuffer,
+    pub count: u32,
+    /// Room for `count` instances, needing storage and vertex usage, for the draw to take its instances from.
+    pub visible: &'a VulkanBuffer,
+    /// Where the `VkDrawIndirectCommand` goes, needing storage, indirect and copy destination usage.
+    pub args: &'a VulkanBuffer,
+    /// Bytes to the draw in `args`, a multiple of 4.
+    pub args_offset: u64,
+    /// The layer's mesh's.
+    pub vertex_count: u32,
+    pub frustum: &'a Frustum,
+    pub camera: Vec3,
+    pub layer: &'a FoliageLayer,
+}
+
+/// The cull pass. See the module docs.
+pub struct FoliageCuller {
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptors: FrameDescriptors,
+}
+
+impl FoliageCuller {
+    /// Set up for `frames_in_flight` frames, with `spirv` compiled from [`SHADER`].
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s or null.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<FoliageCuller> {
+        let raw = device.raw();
+        let storage = |binding| {
+            LayoutBinding::new(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                1,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+        };
+        let desc = LayoutDesc::new([storage(0), storage(1), storage(2)]);
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(PUSH_BYTES);
+        // SAFETY: Passed on to the caller, and whatever's made is destroyed if something after it fails.
+        unsafe {
+            let set_layout = layouts.get(raw, &desc)?;
+            let layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[set_layout])
+                    .push_constant_ranges(&[push]),
+                allocs(),
+            )?;
+            let made = spirv.create_module(raw).and_then(|module| {
+                let stage = vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(c"main");
+                let info = vk::ComputePipelineCreateInfo::default()
+                    .stage(stage)
+                    .layout(layout);
+                let made = raw.create_compute_pipelines(cache, &[info], allocs());
+                raw.destroy_shader_module(module, allocs());
+                made.map(|p| p[0]).map_err(|(_, e)| e)
+            });
+            let pipeline = match made {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    raw.destroy_pipeline_layout(layout, allocs());
+                    return Err(e);
+                }
+            };
+            let ratios = [(vk::DescriptorType::STORAGE_BUFFER, 3.0)];
+            return Ok(FoliageCuller {
+                set_layout,
+                layout,
+                pipeline,
+                descriptors: FrameDescriptors::new(raw, frames_in_flight, &ratios),
+            });
+        }
+    }
+
+    /// Start `frame`, reusing the descriptors of the frame that last had its slot.
+    ///
+    /// # Safety
+    /// The GPU must be done with that frame, like
+    /// [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    pub unsafe fn begin_frame(&mut self, frame: u64) {
+        // SAFETY: Passed on to the caller.
+        if let Err(e) = unsafe { self.descriptors.begin_frame(frame) } {
+            log::error!("Couldn't reset the foliage cull descriptors: {e}");
+        }
+    }
+
+    /// Cull `batch` into its visible buffer and draw, recorded into `cmd` before anything draws from them.
+    ///
+    /// # Safety
+    /// `cmd` must be recording for the frame begun with [`FoliageCuller::begin_frame`], outside a render pass.
+    pub unsafe fn record(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        batch: &FoliageBatch,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let set = self.descriptors.allocate(self.set_layout)?;
+        let info = |buffer: &VulkanBuffer| {
+            [vk::DescriptorBufferInfo::default()
+                .buffer(buffer.buffer)
+                .range(vk::WHOLE_SIZE)]
+        };
+        let (instances, visible, args) =
+            (info(batch.instances), info(batch.visible), info(batch.args));
+        let write = |binding, info| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(info)
+        };
+
+        let mut push = Vec::with_capacity(PUSH_BYTES as usize);
+        for plane in &batch.frustum.planes {
+            // An infinite far plane's distance is infinite, which the shader compares fine.
+            push.extend(
+                plane
+                    .normal
+                    .extend(plane.d)
+                    .to_array()
+                    .map(f32::to_ne_bytes)
+                    .concat(),
+            );
+        }
+        let layer = batch.layer;
+        push.extend(
+            batch
+                .camera
+                .extend(layer.radius)
+                .to_array()
+                .map(f32::to_ne_bytes)
+                .concat(),
+        );
+        push.extend(layer.fade_start.to_ne_bytes());
+        push.extend(layer.fade_end.to_ne_bytes());
+        push.extend(batch.count.to_ne_bytes());
+        push.extend(((batch.args_offset / 4) as u32).to_ne_bytes());
+
+        // Nothing visible until the pass counts it in.
+        let draw: Vec<u8> = [batch.vertex_count, 0, 0, 0]
+            .iter()
+            .flat_map(|w| w.to_ne_bytes())
+            .collect();
+        let barrier = |src_stage, src_access, dst_stage, dst_access| {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            // SAFETY: Recording into the caller's command buffer.
+            unsafe {
+                raw.cmd_pipeline_barrier(
+                    cmd,
+                    src_stage,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[],
+                    &[],
+                )
+            };
+        };
+
+        // SAFETY: The set was just allocated, and the rest is recording.
+        unsafe {
+            raw.update_descriptor_sets(
+                &[write(0, &instances), write(1, &visible), write(2, &args)],
+                &[],
+            );
+            // After last frame's draw from the same buffers.
+            barrier(
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::empty(),
+            );
+            raw.cmd_update_buffer(cmd, batch.args.buffer, batch.args_offset, &draw);
+            // After the reset, and whatever wrote the instances.
+            barrier(
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::COMPUTE, 0, &push);
+            raw.cmd_dispatch(cmd, batch.count.div_ceil(WORKGROUP), 1, 1);
+            barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            );
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. The set layout goes with its cache.
+        unsafe {
+            raw.destroy_pipeline(self.pipeline, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+/// A patch's instances on the GPU, with where its visible ones and its draw go.
+struct UploadedPatch {
+    patch: Arc<FoliagePatch>,
+    /// [`gpu_data`].
+    instances: VulkanBuffer,
+    visible: VulkanBuffer,
+    args: VulkanBuffer,
+}
+
+impl UploadedPatch {
+    /// # Safety
+    /// `patch` mustn't be empty.
+    unsafe fn new(device: &VulkanDevice, patch: &Arc<FoliagePatch>) -> VkResult<UploadedPatch> {
+        let data = gpu_data(&patch.instances);
+        let size = data.len() as u64;
+        let instances = device.create_buffer(&BufferDesc {
+            size,
+            usage: BufferUsage::STORAGE,
+            location: MemoryLocation::Upload,
+        })?;
+        let made = device
+            .create_buffer(&BufferDesc {
+                size,
+                usage: BufferUsage::STORAGE | BufferUsage::VERTEX,
+                location: MemoryLocation::Device,
+            })
+            .and_then(|visible| {
+                let args = device.create_buffer(&BufferDesc {
+                    size: DRAW_BYTES,
+                    usage: BufferUsage::STORAGE | BufferUsage::INDIRECT | BufferUsage::COPY_DST,
+                    location: MemoryLocation::Device,
+                });
+                match args {
+                    Ok(args) => Ok((visible, args)),
+                    Err(e) => {
+                        // SAFETY: Just made, so the GPU hasn't seen it.
+                        unsafe { device.destroy_buffer(visible) };
+                        Err(e)
+                    }
+                }
+            });
+        // SAFETY: Just made, so the GPU hasn't seen it.
+        let made = made
+            .and_then(|made| unsafe { device.write_buffer(&instances, 0, &data).map(|_| made) });
+        return match made {
+            Ok((visible, args)) => Ok(UploadedPatch {
+                patch: patch.clone(),
+                instances,
+                visible,
+                args,
+            }),
+            Err(e) => {
+                // SAFETY: As above.
+                unsafe { device.destroy_buffer(instances) };
+                Err(e)
+            }
+        };
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    unsafe fn destroy(self, device: &VulkanDevice) {
+        for buffer in [self.instances, self.visible, self.args] {
+            // SAFETY: Passed on to the caller.
+            unsafe { device.destroy_buffer(buffer) };
+        }
+    }
+}
+
+/// Culls the scene's foliage and draws it, see the module docs.
+pub struct FoliagePass {
+    culler: FoliageCuller,
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, kept until the pass goes as there are only ever a few.
+    pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    descriptors: FrameDescriptors,
+    /// [`mesh::cube_bytes`].
+    cube: Option<VulkanBuffer>,
+    /// Each frame in flight's patches, as [`patch_data`] [`BLOCK_BYTES`] apart.
+    blocks: Vec<Option<VulkanBuffer>>,
+    /// The scene's patches as of the last prepare, in its order.
+    patches: Vec<UploadedPatch>,
+    /// Patches gone from the scene, with the frame they went in, until the GPU's done with them.
+    retired: Vec<(u64, UploadedPatch)>,
+    /// The frame last prepared, if it had anything to draw.
+    prepared: Option<u64>,
+}
+
+impl FoliagePass {
+    /// Set up for `frames_in_flight` frames, with `cull` compiled from [`SHADER`], `vertex` from [`VERTEX_SHADER`]
+    /// and `fragment` from [`FRAGMENT_SHADER`].
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s, or null. Both must outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        cull: &Spirv,
+        vertex: &Spirv,
+        fragment: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<FoliagePass> {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let culler = unsafe { FoliageCuller::new(device, layouts, cache, cull, frames_in_flight)? };
+        let ratios = [(vk::DescriptorType::UNIFORM_BUFFER, 1.0)];
+        let mut pass = FoliagePass {
+            culler,
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            descriptors: FrameDescriptors::new(raw, frames_in_flight, &ratios),
+            cube: None,
+            blocks: Vec::new(),
+            patches: Vec::new(),
+            retired: Vec::new(),
+            prepared: None,
+        };
+        pass.blocks
+            .resize_with(frames_in_flight.max(1) as usize, || None);
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, layouts, vertex, fragment) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        return Ok(pass);
+    }
+
+    /// The pass with the shaders shipped compiled or compiled now. `None`, having said why, if they couldn't be had
+    /// or it couldn't be made.
+    ///
+    /// # Safety
+    /// As [`FoliagePass::new`].
+    pub unsafe fn builtin(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        frames_in_flight: u32,
+    ) -> Option<FoliagePass> {
+        let cull = builtin_shader(SHADER_ASSET, "cull.comp", SHADER, ShaderStage::Compute);
+        let vertex = builtin_shader_including(
+            VERTEX_ASSET,
+            "foliage.vert",
+            VERTEX_SHADER,
+            INCLUDES,
+            ShaderStage::Vertex,
+        );
+        let fragment = builtin_shader_including(
+            FRAGMENT_ASSET,
+            "foliage.frag",
+            FRAGMENT_SHADER,
+            INCLUDES,
+            ShaderStage::Fragment,
+        );
+        let (Some(cull), Some(vertex), Some(fragment)) = (cull, vertex, fragment) else {
+            log::warn!("No foliage shaders, foliage's off");
+            return None;
+        };
+        // SAFETY: Passed on to the caller.
+        let made = unsafe {
+            FoliagePass::new(
+                device,
+                layouts,
+                cache,
+                &cull,
+                &vertex,
+                &fragment,
+                frames_in_flight,
+            )
+        };
+        return made
+            .inspect_err(|e| log::warn!("Couldn't make the foliage pass, foliage's off: {e}"))
+            .ok();
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let both = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+        let bindings = [LayoutBinding::new(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            1,
+            both,
+        )];
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(DRAW_PUSH_BYTES);
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = layouts.get(raw, &LayoutDesc::new(bindings))?;
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[self.set_layout])
+                    .push_constant_ranges(&[push]),
+                allocs(),
+            )?;
+            self.vertex = vertex.create_module(raw)?;
+            self.fragment = fragment.create_module(raw)?;
+        }
+        let vertices = mesh::cube_bytes();
+        let cube = device.create_buffer(&BufferDesc {
+            size: vertices.len() as u64,
+            usage: BufferUsage::VERTEX,
+            location: MemoryLocation::Upload,
+        })?;
+        // SAFETY: Just made, so the GPU hasn't seen it.
+        let written = unsafe { device.write_buffer(&cube, 0, &vertices) };
+        self.cube = Some(cube);
+        return written;
+    }
+
+    /// Keep buffers and descriptors for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        let retired = self.retired.drain(..).map(|(_, patch)| patch);
+        for patch in retired {
+            // SAFETY: Passed on to the caller.
+            unsafe { patch.destroy(device) };
+        }
+        for buffer in self.blocks.drain(..).flatten() {
+            // SAFETY: Passed on to the caller.
+            unsafe { device.destroy_buffer(buffer) };
+        }
+        self.blocks.resize_with(frames.max(1) as usize, || None);
+        let ratios = [(vk::DescriptorType::UNIFORM_BUFFER, 1.0)];
+        self.descriptors = FrameDescriptors::new(device.raw(), frames, &ratios);
+        let ratios = [(vk::DescriptorType::STORAGE_BUFFER, 3.0)];
+        self.culler.descriptors = FrameDescriptors::new(device.raw(), frames, &ratios);
+        self.prepared = None;
+    }
+
+    /// The pipeline for drawing to `target`, built the first time it's drawn to.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        let depth = match target.depth {
+            vk::Format::UNDEFINED => DepthMode::Off,
+            _ => DepthMode::TestWrite,
+        };
+        // The cube, then the instances as the cull pass wrote them.
+        let mut builder =
+            LightmapVertex::vertex_input(GraphicsPipelineBuilder::new(self.layout), 0)
+                .vertex_buffer(1, INSTANCE_BYTES as u32, true)
+                .attribute(4, 1, vk::Format::R32G32B32A32_SFLOAT, 0)
+                .attribute(5, 1, vk::Format::R32G32B32A32_SFLOAT, 16)
+                .attribute(6, 1, vk::Format::R32G32B32A32_SFLOAT, 32)
+                .vertex_fragment(self.vertex, self.fragment)
+                .samples(vk::SampleCountFlags::from_raw(target.samples))
+                .color(target.color, BlendMode::Opaque)
+                .depth(target.depth, depth)
+                .cache(self.cache);
+        if target.velocity != vk::Format::UNDEFINED {
+            builder = builder.color(target.velocity, BlendMode::Opaque);
+        }
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// Upload `scene`'s patches that are new, let go of those that are gone, and record culling them to a camera
+    /// seeing through `view_proj` from `camera`, swayed by `wind` as [`Wind::gpu_data`] gives it, into `cmd`.
+    /// Nothing's culled or drawn without a camera. Goes before the pass that draws them.
+    ///
+    /// # Safety
+    /// `cmd` must be recording outside a pass, and the GPU done with the frame that last used this frame's buffers,
+    /// like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    pub unsafe fn prepare(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        scene: &ExtractedScene,
+        view: Option<(Mat4, Vec3)>,
+        wind: [f32; 4],
+    ) -> VkResult<()> {
+        self.prepared = None;
+        let frames = self.blocks.len() as u64;
+        let (done, retired) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|(gone, _)| frame >= *gone + frames);
+        self.retired = retired;
+        for (_, patch) in done {
+            // SAFETY: The frame it went in is done, and so every frame that drew it.
+            unsafe { patch.destroy(device) };
+        }
+        let mut patches = Vec::with_capacity(scene.foliage.len());
+        for foliage in scene
+            .foliage
+            .iter()
+            .filter(|f| !f.patch.instances.is_empty())
+        {
+            let kept = self
+                .patches
+                .iter()
+                .position(|p| Arc::ptr_eq(&p.patch, &foliage.patch));
+            let uploaded = match kept {
+                Some(i) => Ok(self.patches.swap_remove(i)),
+                // SAFETY: Not empty, as filtered.
+                None => unsafe { UploadedPatch::new(device, &foliage.patch) },
+            };
+            match uploaded {
+                Ok(patch) => patches.push(patch),
+                // Tried again next frame.
+                Err(e) => log::error!("Couldn't upload foliage: {e}"),
+            }
+        }
+        let gone = std::mem::replace(&mut self.patches, patches);
+        self.retired
+            .extend(gone.into_iter().map(|patch| (frame, patch)));
+        let Some((view_proj, camera)) = view.filter(|_| !self.patches.is_empty()) else {
+            return Ok(());
+        };
+
+        let sun = scene
+            .lights
+            .iter()
+            .find(|l| matches!(l.kind, LightKind::Directional));
+        let data: Vec<u8> = self
+            .patches
+            .iter()
+            .flat_map(|p| patch_data(&p.patch, wind, sun))
+            .collect();
+        let buffer = &mut self.blocks[(frame % frames) as usize];
+        let frustum = Frustum::from_view_proj(&view_proj);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            mesh::grow(device, buffer, data.len() as u64, BufferUsage::UNIFORM)?;
+            device.write_buffer(buffer.as_ref().unwrap(), 0, &data)?;
+            self.culler.begin_frame(frame);
+            for patch in &self.patches {
+                let batch = FoliageBatch {
+                    instances: &patch.instances,
+                    count: patch.patch.instances.len() as u32,
+                    visible: &patch.visible,
+                    args: &patch.args,
+                    args_offset: 0,
+                    vertex_count: mesh::CUBE_VERTICES,
+                    frustum: &frustum,
+                    camera,
+                    layer: &patch.patch.layer,
+                };
+                self.culler.record(device, cmd, &batch)?;
+            }
+        }
+        self.prepared = Some(frame);
+        return Ok(());
+    }
+
+    /// Record drawing the foliage culled for the context's frame, through `view_proj`, and `previous_view_proj` as
+    /// of the last frame for motion vectors. Nothing's drawn if it wasn't prepared.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record(
+        &mut self,
+        ctx: PassContext,
+        view_proj: Mat4,
+        previous_view_proj: Mat4,
+    ) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            ..
+        } = ctx;
+        if self
+            .prepared
+            .take_if(|prepared| *prepared == frame)
+            .is_none()
+        {
+            return Ok(());
+        }
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target)? };
+        // SAFETY: The caller vouched for the frame to prepare it.
+        unsafe { self.descriptors.begin_frame(frame)? };
+        let blocks = self.blocks[(frame % self.blocks.len() as u64) as usize]
+            .as_ref()
+            .unwrap()
+            .buffer;
+        let push: Vec<u8> = [view_proj, previous_view_proj]
+            .iter()
+            .flat_map(Mat4::to_cols_array)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        let point = vk::PipelineBindPoint::GRAPHICS;
+        let cube = self.cube.as_ref().unwrap().buffer;
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, point, pipeline);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &[cube], &[0]);
+        }
+        for (i, patch) in self.patches.iter().enumerate() {
+            let set = self.descriptors.allocate(self.set_layout)?;
+            let block = [vk::DescriptorBufferInfo::default()
+                .buffer(blocks)
+                .offset(i as u64 * BLOCK_BYTES)
+                .range(BLOCK_BYTES)];
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&block);
+            // SAFETY: The set was just allocated. Recording into the caller's command buffer, inside its pass, with
+            // the draw counted in by the cull the prepare recorded.
+            unsafe {
+                raw.update_descriptor_sets(&[write], &[]);
+                raw.cmd_bind_descriptor_sets(cmd, point, self.layout, 0, &[set], &[]);
+                raw.cmd_bind_vertex_buffers(cmd, 1, &[patch.visible.buffer], &[0]);
+                raw.cmd_draw_indirect(cmd, patch.args.buffer, 0, 1, DRAW_BYTES as u32);
+            }
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        let patches = self.retired.drain(..).map(|(_, patch)| patch);
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and the set layout goes with its
+        // cache.
+        unsafe {
+            self.culler.destroy(device);
+            for patch in patches.chain(self.patches.drain(..)) {
+                patch.destroy(device);
+            }
+            for buffer in self.blocks.drain(..).flatten().chain(self.cube.take()) {
+                device.destroy_buffer(buffer);
+            }
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec2, Vec3, Vec4};
+    use hecs::Entity;
+
+    use super::{
+        BLOCK_BYTES, DensityMap, FoliageLayer, FoliagePatch, INSTANCE_BYTES, SurfacePoint, Wind,
+        cull, gpu_data, patch_data, scatter,
+    };
+    use crate::{
+        color::LinearColor,
+        ecs::components::{LightKind, MaterialId, MeshId},
+        math::{Transform, bounds::Frustum, perspective, view_matrix},
+        render::{
+            extract::ExtractedLight,
+            mesh::{AMBIENT, material_color},
+        },
+    };
+
+    #[test]
+    pub fn scatters_and_culls() {
+        let layer = FoliageLayer {
+            mesh: MeshId(0),
+            density: 4.0,
+            min_scale: 0.8,
+            max_scale: 1.2,
+            max_slope: 0.5,
+            align_to_normal: 0.5,
+            radius: 0.5,
+            fade_start: 20.0,
+            fade_end: 40.0,
+            stiffness: 1.0,
+        };
+        let flat = |p: Vec2| {
+            Some(SurfacePoint {
+                position: Vec3::new(p.x, 0.0, p.y),
+                normal: Vec3::Y,
+            })
+        };
+        let (min, max) = (Vec2::splat(-10.0), Vec2::splat(10.0));
+        let all = scatter(&layer, min, max, &DensityMap::uniform(1.0), 7, flat);
+        assert_eq!(all.len(), 1600);
+        assert!(all.iter().all(|i| (0.8..=1.2).contains(&i.scale)));
+        // The same plants in a chunk of it.
+        let chunk = scatter(&layer, min, Vec2::ZERO, &DensityMap::uniform(1.0), 7, flat);
+        assert!(chunk.len() > 300 && chunk.iter().all(|i| all.contains(i)));
+
+        // Nothing where the map's 0, or on a cliff.
+        let half = DensityMap::new(2, 1, vec![1.0, 0.0]).unwrap();
+        assert_eq!(half.sample(Vec2::new(0.5, 0.5)), 0.5);
+        let halved = scatter(&layer, min, max, &half, 7, flat);
+        assert!(halved.iter().all(|i| i.position.x < 5.0));
+        assert!(halved.len() > 500 && halved.len() < 1000);
+        let cliff = |p: Vec2| {
+            Some(SurfacePoint {
+                position: Vec3::new(p.x, p.x, p.y),
+                normal: Vec3::new(-1.0, 1.0, 0.0).normalize(),
+            })
+        };
+        assert!(scatter(&layer, min, max, &half, 7, cliff).is_empty());
+        assert_eq!(DensityMap::new(2, 2, vec![1.0]), None);
+
+        // Only what's ahead, fading out with distance.
+        let camera =
+            Transform::looking_at(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 2.0, -1.0), Vec3::Y)
+                .to_affine();
+        let frustum =
+            Frustum::from_view_proj(&(perspective(1.2, 1.0, 0.1, 100.0) * view_matrix(&camera)));
+        let row = scatter(
+            &layer,
+            Vec2::new(-0.25, -50.0),
+            Vec2::new(0.25, 50.0),
+            &DensityMap::uniform(1.0),
+            3,
+            flat,
+        );
+        let visible = cull(&row, &frustum, camera.translation.into(), &layer);
+        assert!(!visible.is_empty());
+        for &(i, fade) in &visible {
+            let z = row[i as usize].position.z;
+            assert!(z < 1.0 && z > -41.0);
+            assert!(fade > 0.0 && fade <= 1.0);
+            if z > -19.0 {
+                assert_eq!(fade, 1.0);
+            }
+        }
+
+        assert_eq!(
+            gpu_data(&row).len() as u64,
+            row.len() as u64 * INSTANCE_BYTES
+        );
+        let wind = Wind {
+            direction: Vec2::new(3.0, 4.0),
+            strength: 0.5,
+            gust_frequency: 0.2,
+        };
+        assert!(Vec4::from(wind.gpu_data(1.5)).abs_diff_eq(Vec4::new(0.3, 0.4, 0.2, 1.5), 1e-6));
+    }
+
+    #[test]
+    pub fn lights_and_sways_patches() {
+        let patch = FoliagePatch {
+            layer: FoliageLayer {
+                mesh: MeshId(0),
+                density: 1.0,
+                min_scale: 1.0,
+                max_scale: 1.0,
+                max_slope: 1.0,
+                align_to_normal: 0.0,
+                radius: 0.5,
+                fade_start: 20.0,
+                fade_end: 40.0,
+                stiffness: 1.0,
+            },
+            material: MaterialId(2),
+            instances: Vec::new(),
+        };
+        let wind = Wind::default();
+        let sun = ExtractedLight {
+            entity: Entity::DANGLING,
+            kind: LightKind::Directional,
+            position: Vec3::ZERO,
+            direction: Vec3::NEG_Y,
+            color: LinearColor::rgb(1.0, 0.5, 0.25),
+            intensity: 2.0,
+        };
+        let vec4 = |data: &[u8], i: usize| {
+            let floats: Vec<f32> = data[i * 16..(i + 1) * 16]
+                .chunks(4)
+                .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+                .collect();
+            Vec4::from_slice(&floats)
+        };
+
+        let data = patch_data(&patch, wind.gpu_data(2.0), Some(&sun));
+        assert_eq!(data.len() as u64, BLOCK_BYTES);
+        assert_eq!(vec4(&data, 0), Vec4::from(wind.gpu_data(2.0)));
+        // Towards the sun, and its light.
+        assert_eq!(vec4(&data, 1), Vec4::new(0.0, 1.0, 0.0, 0.0));
+        assert_eq!(vec4(&data, 2), Vec4::new(2.0, 1.0, 0.5, 0.0));
+        assert_eq!(vec4(&data, 3), AMBIENT.to_vec3().extend(0.0));
+        let color = material_color(MaterialId(2)).to_vec3().extend(1.0);
+        assert_eq!(vec4(&data, 4), color);
+        assert!(data[80..].iter().all(|b| *b == 0));
+
+        // Only the ambient without a sun.
+        let dark = patch_data(&patch, wind.gpu_data(2.0), None);
+        assert_eq!(vec4(&dark, 2), Vec4::ZERO);
+        assert_eq!(vec4(&dark, 3), vec4(&data, 3));
+    }
+}
@@ -0,0 +1,23 @@
+#version 450
+// Foliage in its material's colour under the scene's first directional light and a flat ambient, dithered out as it
+// fades. Motion vectors go to a second target, dropped when there isn't one.
+
+#include "../foliage/foliage.glsl"
+#include "../motion_blur/velocity.glsl"
+
+layout(location = 0) in vec3 normal;
+layout(location = 1) flat in float fade;
+layout(location = 2) in vec4 clip;
+layout(location = 3) in vec4 previous_clip;
+
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec2 out_velocity;
+
+void main() {
+    foliage_fade_discard(fade, gl_FragCoord);
+    // Lit through from behind too, like leaves.
+    float lit = abs(dot(normalize(normal), foliage_light_direction.xyz));
+    vec3 light = foliage_ambient.rgb + foliage_light_color.rgb * lit;
+    out_color = vec4(foliage_color.rgb * light, 1.0);
+    out_velocity = velocity_output(clip, previous_clip);
+}
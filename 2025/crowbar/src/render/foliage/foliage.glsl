@@ -0,0 +1,50 @@
+// Drawing culled foliage instances, see foliage.rs. The vertex shader takes each instance's three vec4s as
+// per-instance attributes, in the order the cull pass writes them.
+
+// A patch of it, see foliage::patch_data.
+layout(set = 0, binding = 0) uniform FoliagePatch {
+    // As Wind::gpu_data gives it.
+    vec4 foliage_wind;
+    // Towards the light, and its colour times its intensity.
+    vec4 foliage_light_direction;
+    vec4 foliage_light_color;
+    vec4 foliage_ambient;
+    // The material's.
+    vec4 foliage_color;
+};
+
+// `v` turned by the quaternion `q`.
+vec3 foliage_rotate(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Where the vertex at `local` in the mesh, rooted at the origin and growing up +Y, ends up in the world for the
+// instance, swayed by `wind` as Wind::gpu_data gives it. The sway grows with the square of the height, so the
+// root stays put, and the top dips as it leans so the plant keeps roughly its length.
+vec3 foliage_vertex(vec3 local, vec4 position_scale, vec4 rotation, vec4 params, vec4 wind) {
+    vec3 world = position_scale.xyz + foliage_rotate(rotation, local * position_scale.w);
+    float height = max(local.y, 0.0) * position_scale.w;
+    float strength = length(wind.xy);
+    vec2 along = strength > 0.0 ? wind.xy / strength : vec2(0.0);
+    // Gusts roll across the field with the wind, each plant a little out of step.
+    float gust = sin(wind.w * wind.z * 6.2831853 - dot(position_scale.xz, along) * 0.2 + params.x) * 0.5 + 0.5;
+    float flutter = sin(wind.w * 3.0 + params.x * 2.0) * 0.1;
+    vec2 sway = wind.xy * (0.5 + 0.5 * gust + flutter) * height * height / max(params.y, 1e-3);
+    world.xz += sway;
+    world.y -= dot(sway, sway) * 0.5 / max(height, 1e-3);
+    return world;
+}
+
+// Dithers out a fragment of an instance faded to `fade`, in a 4x4 pattern across the screen.
+void foliage_fade_discard(float fade, vec4 frag_coord) {
+    const float bayer[16] = float[16](
+        0.0, 8.0, 2.0, 10.0,
+        12.0, 4.0, 14.0, 6.0,
+        3.0, 11.0, 1.0, 9.0,
+        15.0, 7.0, 13.0, 5.0
+    );
+    ivec2 cell = ivec2(frag_coord.xy) & 3;
+    if (fade * 16.0 <= bayer[cell.y * 4 + cell.x] + 0.5) {
+        discard;
+    }
+}
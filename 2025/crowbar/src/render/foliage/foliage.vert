@@ -0,0 +1,37 @@
+#version 450
+// Foliage instances the cull pass kept, placed and swayed by the wind, through the last camera. Keep in step with
+// FoliagePass in foliage.rs.
+
+#include "../foliage/foliage.glsl"
+
+layout(push_constant) uniform Push {
+    mat4 view_proj;
+    // As of the last frame, for motion vectors.
+    mat4 previous_view_proj;
+};
+
+// The cube's, as every mesh is until there are mesh assets.
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+// The instance, as the cull pass wrote it.
+layout(location = 4) in vec4 position_scale;
+layout(location = 5) in vec4 rotation;
+layout(location = 6) in vec4 params;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) flat out float out_fade;
+layout(location = 2) out vec4 out_clip;
+layout(location = 3) out vec4 out_previous_clip;
+
+void main() {
+    // Stood on its bottom face, so its root's at the origin.
+    vec3 local = position + vec3(0.0, 0.5, 0.0);
+    vec3 world = foliage_vertex(local, position_scale, rotation, params, foliage_wind);
+    vec4 clip = view_proj * vec4(world, 1.0);
+    out_normal = foliage_rotate(rotation, normal);
+    out_fade = params.w;
+    out_clip = clip;
+    // Only the camera's motion, as the sway's too slight to blur.
+    out_previous_clip = previous_view_proj * vec4(world, 1.0);
+    gl_Position = clip;
+}
@@ -0,0 +1,185 @@
+//! Keeping a few frames in flight: the CPU records the next frame while the GPU is still on the last ones.
+//!
+//! Each frame in flight gets a slot, with a fence to know when the GPU is done with it and a semaphore for the
+//! swapchain image to be ready. Command buffers come from [`super::commands::CommandManager`], with pools per slot
+//! to match. Beginning a frame waits on its slot's fence,
+//! which only blocks if the CPU gets that many frames ahead. Render finished semaphores are per swapchain image
+//! instead, as it's the present that waits on them and an image isn't handed back until that's done.
+//!
+//! Frames are numbered by the caller and go in the slot `frame % frames_in_flight`. Once frame `n` has begun, every
+//! submission up to `n - frames_in_flight` is done, which is what [`super::deletion::DeletionQueue`] relies on.
+
+use ash::{prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// What one frame in flight synchronizes with.
+pub struct FrameSlot {
+    /// Signalled when the GPU is done with the slot's last submission.
+    pub fence: vk::Fence,
+    /// For the swapchain image to be ready to render to.
+    pub image_available: vk::Semaphore,
+}
+
+pub struct FrameSync {
+    device: ash::Device,
+    slots: Vec<FrameSlot>,
+    /// By swapchain image index.
+    render_finished: Vec<vk::Semaphore>,
+    current: usize,
+}
+
+impl FrameSync {
+    /// Slots for `frames_in_flight` frames.
+    pub fn new(device: &ash::Device, frames_in_flight: u32) -> VkResult<FrameSync> {
+        let mut sync = FrameSync {
+            device: device.clone(),
+            slots: Vec::new(),
+            render_finished: Vec::new(),
+            current: 0,
+        };
+        for _ in 0..frames_in_flight.max(1) {
+            // SAFETY: Made from our device, and destroyed with the rest on failure when `sync` drops.
+            unsafe {
+                // Signalled to start with, so the first wait on each doesn't block.
+                let fence = device.create_fence(
+                    &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
+                    allocs(),
+                )?;
+                let image_available =
+                    match device.create_semaphore(&vk::SemaphoreCreateInfo::default(), allocs()) {
+                        Ok(semaphore) => semaphore,
+                        Err(e) => {
+                            device.destroy_fence(fence, allocs());
+                            return Err(e);
+                        }
+                    };
+                sync.slots.push(FrameSlot {
+                    fence,
+                    image_available,
+                });
+            }
+        }
+        return Ok(sync);
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.slots.len() as u32
+    }
+
+    /// Start `frame`: wait for the GPU to be done with its slot. Frames should count up, but can skip, like when
+    /// nothing was drawn.
+    pub fn begin_frame(&mut self, frame: u64) -> VkResult<&FrameSlot> {
+        self.current = (frame % self.slots.len() as u64) as usize;
+        let slot = &self.slots[self.current];
+        // SAFETY: Only a wait.
+        unsafe { self.device.wait_for_fences(&[slot.fence], true, u64::MAX)? };
+        return Ok(slot);
+    }
+
+    /// The slot of the frame last begun.
+    pub fn current(&self) -> &FrameSlot {
+        &self.slots[self.current]
+    }
+
+    /// What to signal when done rendering to swapchain image `image`, and what presenting it waits on.
+    pub fn render_finished(&mut self, image: u32) -> VkResult<vk::Semaphore> {
+        while self.render_finished.len() <= image as usize {
+            // SAFETY: Made from our device, destroyed when dropped.
+            let semaphore = unsafe {
+                self.device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), allocs())?
+            };
+            self.render_finished.push(semaphore);
+        }
+        return Ok(self.render_finished[image as usize]);
+    }
+
+    /// Submit the current frame's `cmds` to `queue`, once `wait` are signalled at their stages. Signals `signal`
+    /// when done, and the slot's fence.
+    ///
+    /// # Safety
+    /// `cmds` must be ended, and not used again until the slot's fence is waited on.
+    pub unsafe fn submit(
+        &mut self,
+        queue: vk::Queue,
+        cmds: &[vk::CommandBuffer],
+        wait: &[(vk::Semaphore, vk::PipelineStageFlags)],
+        signal: &[vk::Semaphore],
+    ) -> VkResult<()> {
+        let slot = &self.slots[self.current];
+        let (semaphores, stages): (Vec<_>, Vec<_>) = wait.iter().copied().unzip();
+        // SAFETY: Passed on to the caller. The fence is only reset once something's about to signal it, so a frame
+        // that's begun but never submitted doesn't leave the next wait hanging.
+        unsafe {
+            self.device.reset_fences(&[slot.fence])?;
+            self.device.queue_submit(
+                queue,
+                &[vk::SubmitInfo::default()
+                    .wait_semaphores(&semaphores)
+                    .wait_dst_stage_mask(&stages)
+                    .command_buffers(cmds)
+                    .signal_semaphores(signal)],
+                slot.fence,
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for FrameSync {
+    fn drop(&mut self) {
+        // SAFETY: Waited on, so nothing's using any of it any more.
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            for slot in self.slots.drain(..) {
+                self.device
+                    .destroy_semaphore(slot.image_available, allocs());
+                self.device.destroy_fence(slot.fence, allocs());
+            }
+            for semaphore in self.render_finished.drain(..) {
+                self.device.destroy_semaphore(semaphore, allocs());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::FrameSync;
+    use crate::test_support::headless;
+
+    #[test]
+    pub fn cycles_slots() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let mut sync = FrameSync::new(device.raw(), 3).unwrap();
+        assert_eq!(sync.frames_in_flight(), 3);
+
+        let mut fences = Vec::new();
+        for frame in 0..7 {
+            let fence = sync.begin_frame(frame).unwrap().fence;
+            fences.push(fence);
+            // Frame 4 is skipped, begun but never submitted, and its slot still comes round again.
+            if frame != 4 {
+                // SAFETY: Nothing to wait on or run.
+                unsafe { sync.submit(device.queue(), &[], &[], &[]).unwrap() };
+            }
+        }
+        assert_eq!(fences[0], fences[3]);
+        assert_eq!(fences[1], fences[4]);
+        assert_ne!(fences[0], fences[1]);
+
+        let a = sync.render_finished(2).unwrap();
+        assert_eq!(sync.render_finished(2).unwrap(), a);
+        assert_ne!(sync.render_finished(0).unwrap(), vk::Semaphore::null());
+    }
+}
@@ -0,0 +1,563 @@
+//! Passes that cover their target with one triangle, a fragment shader working out each pixel from the textures
+//! it's given: upscaling the scene to the window, and the effects done to it on the way.
+//!
+//! They all share [`VERTEX_SHADER`], which places the triangle and the UVs across it from the vertex index alone,
+//! so there are no vertex buffers. A [`FullscreenShader`] says what its fragment shader takes: textures at bindings
+//! 0 up, bound apart from the one linear, clamped sampler after them as naga can't take combined image samplers,
+//! and push constants. Textures are read with `texelFetch` where filtering them isn't wanted, like depth.
+//!
+//! Ship the shaders compiled, as [`VERTEX_ASSET`] and each shader's asset, or they're compiled at startup.
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    depth_of_field,
+    descriptors::{FrameDescriptors, LayoutBinding, LayoutCache, LayoutDesc},
+    hal::vulkan::VulkanDevice,
+    motion_blur, outline,
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, TargetFormats},
+    rendering::PassContext,
+    shader::{
+        compile::{ShaderStage, builtin_shader, builtin_shader_including},
+        reflect::Spirv,
+    },
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// The vertex shader's source, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("fullscreen/fullscreen.vert");
+/// Where the compiled vertex shader goes among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/fullscreen.vert";
+
+/// A fullscreen pass's fragment shader, and what it takes.
+#[derive(Clone, Copy, Debug)]
+pub struct FullscreenShader {
+    /// The file name, for errors.
+    pub path: &'static str,
+    /// Where the compiled shader goes among the assets, without the `.spv`.
+    pub asset: &'static str,
+    /// The source, to compile at runtime.
+    pub text: &'static str,
+    /// What it includes, as `(name, text)` with the name as it's written in the include.
+    pub includes: &'static [(&'static str, &'static str)],
+    /// Textures, at bindings 0 up, with the sampler after.
+    pub images: u32,
+    /// Bytes of fragment push constants.
+    pub push_bytes: u32,
+    /// How what it draws goes over what's in the target.
+    pub blend: BlendMode,
+}
+
+/// One image filtered to the target's size, for upscaling and plain copies.
+pub const COPY: FullscreenShader = FullscreenShader {
+    path: "copy.frag",
+    asset: "shaders/copy.frag",
+    text: include_str!("fullscreen/copy.frag"),
+    includes: &[],
+    images: 1,
+    push_bytes: 0,
+    blend: BlendMode::Opaque,
+};
+
+/// Ambient occlusion from the scene's depth, with the radius, as a fraction of the height, and strength pushed.
+pub const SSAO: FullscreenShader = FullscreenShader {
+    path: "ssao.frag",
+    asset: "shaders/ssao.frag",
+    text: include_str!("fullscreen/ssao.frag"),
+    includes: &[],
+    images: 1,
+    push_bytes: 16,
+    blend: BlendMode::Opaque,
+};
+
+/// The scene's brightest parts blurred to the target's size, with the threshold pushed.
+pub const BLOOM: FullscreenShader = FullscreenShader {
+    path: "bloom.frag",
+    asset: "shaders/bloom.frag",
+    text: include_str!("fullscreen/bloom.frag"),
+    includes: &[],
+    images: 1,
+    push_bytes: 16,
+    blend: BlendMode::Opaque,
+};
+
+/// The scene halved, with its circle of confusion from its depth, for depth of field. The depth of field passes all
+/// have [`depth_of_field::gpu_data`] pushed.
+pub const DOF_SPLIT: FullscreenShader = FullscreenShader {
+    path: "split.frag",
+    asset: "shaders/dof_split.frag",
+    text: depth_of_field::SPLIT_SHADER,
+    includes: &[("dof.glsl", depth_of_field::GLSL)],
+    images: 2,
+    push_bytes: 84,
+    blend: BlendMode::Opaque,
+};
+
+/// The near or far depth of field layer, gathered from [`DOF_SPLIT`]'s.
+pub const DOF_BLUR: FullscreenShader = FullscreenShader {
+    path: "blur.frag",
+    asset: "shaders/dof_blur.frag",
+    text: depth_of_field::BLUR_SHADER,
+    includes: &[("dof.glsl", depth_of_field::GLSL)],
+    images: 1,
+    push_bytes: 84,
+    blend: BlendMode::Opaque,
+};
+
+/// The scene with the depth of field layers put back over it, read with its depth.
+pub const DOF_COMPOSITE: FullscreenShader = FullscreenShader {
+    path: "composite.frag",
+    asset: "shaders/dof_composite.frag",
+    text: depth_of_field::COMPOSITE_SHADER,
+    includes: &[("dof.glsl", depth_of_field::GLSL)],
+    images: 4,
+    push_bytes: 84,
+    blend: BlendMode::Opaque,
+};
+
+/// The scene blurred along its motion vectors, with [`MotionBlurSettings::gpu_data`] pushed.
+///
+/// [`MotionBlurSettings::gpu_data`]: super::motion_blur::MotionBlurSettings::gpu_data
+pub const MOTION_BLUR: FullscreenShader = FullscreenShader {
+    path: "blur.frag",
+    asset: "shaders/motion_blur.frag",
+    text: motion_blur::BLUR_GLSL,
+    includes: &[],
+    images: 2,
+    push_bytes: 12,
+    blend: BlendMode::Opaque,
+};
+
+/// Every pixel the outline mask covers as its own seed, starting the flood.
+pub const OUTLINE_SEED: FullscreenShader = FullscreenShader {
+    path: "seed.frag",
+    asset: "shaders/outline_seed.frag",
+    text: outline::SEED_SHADER,
+    includes: &[("outline.glsl", outline::GLSL)],
+    images: 1,
+    push_bytes: 0,
+    blend: BlendMode::Opaque,
+};
+
+/// A step of the outline flood, with the step size pushed as an `i32`.
+pub const OUTLINE_FLOOD: FullscreenShader = FullscreenShader {
+    path: "flood.frag",
+    asset: "shaders/outline_flood.frag",
+    text: outline::FLOOD_SHADER,
+    includes: &[("outline.glsl", outline::GLSL)],
+    images: 1,
+    push_bytes: 4,
+    blend: BlendMode::Opaque,
+};
+
+/// The outlines from the mask and the flood's offsets, blended over the target.
+pub const OUTLINE_COMPOSITE: FullscreenShader = FullscreenShader {
+    path: "composite.frag",
+    asset: "shaders/outline_composite.frag",
+    text: outline::COMPOSITE_SHADER,
+    includes: &[("outline.glsl", outline::GLSL)],
+    images: 2,
+    push_bytes: 0,
+    blend: BlendMode::Alpha,
+};
+
+/// The scene, ambient occlusion and bloom, tonemapped and graded into the target. The exposure, and which of the
+/// rest are on, are pushed.
+pub const POST: FullscreenShader = FullscreenShader {
+    path: "post.frag",
+    asset: "shaders/post.frag",
+    text: include_str!("fullscreen/post.frag"),
+    includes: &[],
+    images: 3,
+    push_bytes: 16,
+    blend: BlendMode::Opaque,
+};
+
+/// Draws a [`FullscreenShader`] over its target.
+pub struct FullscreenPass {
+    shader: FullscreenShader,
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, kept until the pass goes as there are only ever a few.
+    pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    sampler: vk::Sampler,
+    descriptors: FrameDescriptors,
+    /// The frame the descriptors were last begun for, as a pass can be drawn more than once a frame.
+    begun: Option<u64>,
+}
+
+impl FullscreenPass {
+    /// Set up to draw `shader` for `frames_in_flight` frames, with `vertex` compiled from [`VERTEX_SHADER`] and
+    /// `fragment` from the shader's source.
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s, or null. Both must outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+        shader: FullscreenShader,
+        frames_in_flight: u32,
+    ) -> VkResult<FullscreenPass> {
+        let mut pass = FullscreenPass {
+            shader,
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            sampler: vk::Sampler::null(),
+            descriptors: FrameDescriptors::new(device.raw(), frames_in_flight, &ratios(&shader)),
+            begun: None,
+        };
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, layouts, vertex, fragment) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        return Ok(pass);
+    }
+
+    /// The pass for `shader`, with the shaders shipped compiled or compiled now. `None`, having said why, if they
+    /// couldn't be had or it couldn't be made.
+    ///
+    /// # Safety
+    /// As [`FullscreenPass::new`].
+    pub unsafe fn builtin(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        shader: FullscreenShader,
+        frames_in_flight: u32,
+    ) -> Option<FullscreenPass> {
+        let vertex = builtin_shader(
+            VERTEX_ASSET,
+            "fullscreen.vert",
+            VERTEX_SHADER,
+            ShaderStage::Vertex,
+        );
+        let fragment = builtin_shader_including(
+            shader.asset,
+            shader.path,
+            shader.text,
+            shader.includes,
+            ShaderStage::Fragment,
+        );
+        let (Some(vertex), Some(fragment)) = (vertex, fragment) else {
+            log::warn!("No shaders for {}", shader.path);
+            return None;
+        };
+        // SAFETY: Passed on to the caller.
+        let made = unsafe {
+            FullscreenPass::new(
+                device,
+                layouts,
+                cache,
+                &vertex,
+                &fragment,
+                shader,
+                frames_in_flight,
+            )
+        };
+        return made
+            .inspect_err(|e| log::warn!("Couldn't make the pass for {}: {e}", shader.path))
+            .ok();
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let images = self.shader.images;
+        let binding =
+            |binding, ty| LayoutBinding::new(binding, ty, 1, vk::ShaderStageFlags::FRAGMENT);
+        let bindings = (0..images)
+            .map(|i| binding(i, vk::DescriptorType::SAMPLED_IMAGE))
+            .chain([binding(images, vk::DescriptorType::SAMPLER)]);
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(self.shader.push_bytes);
+        let pushes = match self.shader.push_bytes {
+            0 => &[][..],
+            _ => std::slice::from_ref(&push),
+        };
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = layouts.get(raw, &LayoutDesc::new(bindings))?;
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[self.set_layout])
+                    .push_constant_ranges(pushes),
+                allocs(),
+            )?;
+            self.vertex = vertex.create_module(raw)?;
+            self.fragment = fragment.create_module(raw)?;
+            self.sampler = raw.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                allocs(),
+            )?;
+        }
+        return Ok(());
+    }
+
+    /// Keep descriptors for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        self.descriptors = FrameDescriptors::new(device.raw(), frames, &ratios(&self.shader));
+        self.begun = None;
+    }
+
+    /// The pipeline for drawing to `target`, built the first time it's drawn to.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        let builder = GraphicsPipelineBuilder::new(self.layout)
+            .vertex_fragment(self.vertex, self.fragment)
+            .cull(vk::CullModeFlags::NONE)
+            .samples(vk::SampleCountFlags::from_raw(target.samples))
+            .color(target.color, self.shader.blend)
+            .depth(target.depth, DepthMode::Off)
+            .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// Record drawing over the context's target, reading `images`, in [`TextureState::ShaderRead`], with `push`
+    /// for the push constants.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, and the GPU done with the frame that last used this frame's
+    /// descriptors, like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    ///
+    /// [`TextureState::ShaderRead`]: super::hal::TextureState::ShaderRead
+    pub unsafe fn record(
+        &mut self,
+        ctx: PassContext,
+        images: &[vk::ImageView],
+        push: &[u8],
+    ) -> VkResult<()> {
+        debug_assert_eq!(images.len(), self.shader.images as usize);
+        debug_assert_eq!(push.len(), self.shader.push_bytes as usize);
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            ..
+        } = ctx;
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target)? };
+        if self.begun != Some(frame) {
+            // SAFETY: Passed on to the caller.
+            unsafe { self.descriptors.begin_frame(frame)? };
+            self.begun = Some(frame);
+        }
+        let set = self.descriptors.allocate(self.set_layout)?;
+        let infos: Vec<_> = images
+            .iter()
+            .map(|&view| {
+                [vk::DescriptorImageInfo::default()
+                    .image_view(view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+            })
+            .collect();
+        let sampler = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let writes: Vec<_> = infos
+            .iter()
+            .enumerate()
+            .map(|(i, info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(i as u32)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(info)
+            })
+            .chain([vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(self.shader.images)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler)])
+            .collect();
+
+        // SAFETY: The set was just allocated. Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.update_descriptor_sets(&writes, &[]);
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            if !push.is_empty() {
+                raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::FRAGMENT, 0, push);
+            }
+            raw.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and the set layout goes with its
+        // cache.
+        unsafe {
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            raw.destroy_sampler(self.sampler, allocs());
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.sampler = vk::Sampler::null();
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+/// What a frame's descriptor pools hold, for a set per draw of `shader`.
+fn ratios(shader: &FullscreenShader) -> [(vk::DescriptorType, f32); 2] {
+    [
+        (vk::DescriptorType::SAMPLED_IMAGE, shader.images as f32),
+        (vk::DescriptorType::SAMPLER, 1.0),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{COPY, FullscreenPass};
+    use crate::{
+        capture::{CaptureFormat, CapturedFrame},
+        color::LinearColor,
+        render::{
+            descriptors::LayoutCache,
+            hal::{
+                Attachment, CommandEncoder, Device, LoadOp, TextureDesc, TextureFormat,
+                TextureState, TextureUsage, vulkan::vk_format,
+            },
+            headless::TARGET_FORMAT,
+            pipeline::TargetFormats,
+            rendering::PassContext,
+        },
+        test_support::{assert_frame_hash, frame_hash, headless},
+    };
+
+    #[test]
+    pub fn copies_over_the_target() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let mut layouts = LayoutCache::new();
+        // SAFETY: Everything's this device's, and waited on before it's destroyed.
+        unsafe {
+            let Some(mut pass) =
+                FullscreenPass::builtin(device, &mut layouts, vk::PipelineCache::null(), COPY, 1)
+            else {
+                layouts.destroy(device.raw());
+                return;
+            };
+            // Half the size, so it's upscaled too.
+            let source = device
+                .create_texture(&TextureDesc {
+                    width: 4,
+                    height: 2,
+                    format: TextureFormat::Rgba16Float,
+                    usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                    samples: 1,
+                })
+                .unwrap();
+            let target = TargetFormats {
+                color: vk_format(TARGET_FORMAT),
+                depth: vk::Format::UNDEFINED,
+                velocity: vk::Format::UNDEFINED,
+                samples: 1,
+            };
+            let red = LinearColor::rgb(1.0, 0.0, 0.0);
+            let frame = headless
+                .render(8, 4, |cmds, texture| {
+                    let scene = Attachment {
+                        texture: &source,
+                        before: TextureState::Undefined,
+                        after: TextureState::ShaderRead,
+                        load: LoadOp::Clear(red),
+                        store: true,
+                        resolve: None,
+                    };
+                    cmds.begin_rendering(&[scene], None);
+                    cmds.end_rendering();
+                    let color = Attachment {
+                        texture,
+                        before: TextureState::RenderTarget,
+                        after: TextureState::RenderTarget,
+                        load: LoadOp::DontCare,
+                        store: true,
+                        resolve: None,
+                    };
+                    cmds.begin_rendering(&[color], None);
+                    let (_, cmd) = cmds.raw();
+                    let ctx = PassContext {
+                        device,
+                        cmd,
+                        frame: 0,
+                        target,
+                        extent: texture.extent,
+                    };
+                    pass.record(ctx, &[source.view], &[]).unwrap();
+                    cmds.end_rendering();
+                })
+                .unwrap();
+            pass.destroy(device);
+            device.destroy_texture(source);
+            layouts.destroy(device.raw());
+
+            let expected = CapturedFrame {
+                width: 8,
+                height: 4,
+                format: CaptureFormat::Rgba8Srgb,
+                data: [255, 0, 0, 255].repeat(8 * 4),
+                frame: 0,
+            };
+            assert_frame_hash("copy", &frame, frame_hash(&expected));
+        }
+    }
+}
@@ -0,0 +1,34 @@
+#version 450
+// What's brighter than the threshold, blurred down to the target's size: a tent of nine filtered taps two texels
+// apart, each averaging four texels.
+
+layout(set = 0, binding = 0) uniform texture2D scene;
+layout(set = 0, binding = 1) uniform sampler scene_sampler;
+
+layout(push_constant) uniform Push {
+    // x is the threshold, in linear brightness.
+    vec4 params;
+};
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+// `c` with everything up to the threshold taken off its brightest channel, keeping its hue.
+vec3 bright(vec3 c) {
+    float peak = max(c.r, max(c.g, c.b));
+    return c * (max(peak - params.x, 0.0) / max(peak, 0.0001));
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(scene, scene_sampler), 0));
+    vec3 sum = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float weight = float((2 - abs(x)) * (2 - abs(y)));
+            vec2 at = uv + vec2(float(x), float(y)) * texel * 2.0;
+            sum += bright(texture(sampler2D(scene, scene_sampler), at).rgb) * weight;
+        }
+    }
+    out_color = vec4(sum / 16.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+// The image, filtered to the target's size. The image and sampler are bound apart, as naga needs.
+
+layout(set = 0, binding = 0) uniform texture2D image;
+layout(set = 0, binding = 1) uniform sampler image_sampler;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = texture(sampler2D(image, image_sampler), uv);
+}
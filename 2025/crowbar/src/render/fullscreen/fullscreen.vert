@@ -0,0 +1,12 @@
+#version 450
+// One triangle over the whole target, from the vertex index alone. UVs go from 0 at the top left to 1 at the bottom
+// right of the part on screen. Keep in step with FullscreenPass in fullscreen.rs.
+
+layout(location = 0) out vec2 out_uv;
+
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    // The viewport's flipped to keep clip space Y up, and V counts down.
+    gl_Position = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out_uv = uv;
+}
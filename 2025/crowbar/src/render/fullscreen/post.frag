@@ -0,0 +1,45 @@
+#version 450
+// The scene, upscaled to the target, with ambient occlusion and bloom put in, tonemapped, then graded. Whatever's
+// turned off is bound to the scene in its place and not read.
+
+layout(set = 0, binding = 0) uniform texture2D scene;
+layout(set = 0, binding = 1) uniform texture2D occlusion;
+layout(set = 0, binding = 2) uniform texture2D bloom;
+layout(set = 0, binding = 3) uniform sampler linear_sampler;
+
+layout(push_constant) uniform Push {
+    // x is the exposure, y 1 with ambient occlusion, z how much bloom goes in, w 1 with grading.
+    vec4 params;
+};
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 tonemap(vec3 c) {
+    return clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// A touch more saturation and contrast, around mid grey.
+vec3 grade(vec3 c) {
+    float luma = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    c = mix(vec3(luma), c, 1.1);
+    c = (c - 0.18) * 1.05 + 0.18;
+    return clamp(c, 0.0, 1.0);
+}
+
+void main() {
+    vec3 c = texture(sampler2D(scene, linear_sampler), uv).rgb;
+    if (params.y > 0.0) {
+        c *= texture(sampler2D(occlusion, linear_sampler), uv).r;
+    }
+    if (params.z > 0.0) {
+        c += texture(sampler2D(bloom, linear_sampler), uv).rgb * params.z;
+    }
+    c = tonemap(c * params.x);
+    if (params.w > 0.0) {
+        c = grade(c);
+    }
+    out_color = vec4(c, 1.0);
+}
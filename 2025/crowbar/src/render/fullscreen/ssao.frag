@@ -0,0 +1,45 @@
+#version 450
+// Ambient occlusion from the scene's depth alone: how many points on a spiral around each pixel are nearer than it,
+// by enough to shade it but not so much they're in front of it rather than next to it. 1 is unoccluded.
+
+layout(set = 0, binding = 0) uniform texture2D depth;
+layout(set = 0, binding = 1) uniform sampler depth_sampler;
+
+layout(push_constant) uniform Push {
+    // x is the radius as a fraction of the height, y how dark full occlusion is.
+    vec4 params;
+};
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_occlusion;
+
+const int SAMPLES = 12;
+const float GOLDEN_ANGLE = 2.3999632;
+
+void main() {
+    ivec2 size = textureSize(sampler2D(depth, depth_sampler), 0);
+    ivec2 texel = clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+    float here = texelFetch(sampler2D(depth, depth_sampler), texel, 0).r;
+    // Nothing was drawn here.
+    if (here <= 0.0) {
+        out_occlusion = vec4(1.0);
+        return;
+    }
+    // Turned a different way for each pixel, so the pattern averages out rather than banding.
+    float angle = fract(sin(dot(uv, vec2(12.9898, 78.233))) * 43758.5453) * 6.2831853;
+    vec2 radius = params.x * vec2(float(size.y) / float(size.x), 1.0);
+    float occluded = 0.0;
+    for (int i = 0; i < SAMPLES; i++) {
+        float a = angle + float(i) * GOLDEN_ANGLE;
+        float t = (float(i) + 0.5) / float(SAMPLES);
+        vec2 at = uv + vec2(cos(a), sin(a)) * radius * t;
+        ivec2 p = clamp(ivec2(at * vec2(size)), ivec2(0), size - 1);
+        float there = texelFetch(sampler2D(depth, depth_sampler), p, 0).r;
+        // Reversed-Z depth goes as one over the distance, so this is how much nearer it is than here, relatively.
+        float nearer = there / here - 1.0;
+        occluded += step(0.02, nearer) * (1.0 - smoothstep(0.5, 1.0, nearer));
+    }
+    float occlusion = 1.0 - params.y * occluded / float(SAMPLES);
+    out_occlusion = vec4(vec3(occlusion), 1.0);
+}
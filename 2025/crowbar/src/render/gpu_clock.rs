@@ -0,0 +1,165 @@
+//! Mapping GPU timestamps onto the CPU clock, so GPU work can be drawn on the same timeline as CPU scopes.
+//!
+//! With `VK_EXT_calibrated_timestamps` the device clock is read together with the host clock [`Instant`] runs on,
+//! where the platform has one Vulkan knows (`CLOCK_MONOTONIC` on Linux and Android, the performance counter on
+//! Windows), so the two line up to within the driver's stated deviation. Elsewhere the host side is guessed as
+//! halfway through the call.
+
+use std::time::{Duration, Instant};
+
+use ash::{ext::calibrated_timestamps, vk};
+
+/// The host clock [`Instant`] reads, as Vulkan names it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const HOST_DOMAIN: Option<vk::TimeDomainEXT> = Some(vk::TimeDomainEXT::CLOCK_MONOTONIC);
+#[cfg(target_os = "windows")]
+const HOST_DOMAIN: Option<vk::TimeDomainEXT> = Some(vk::TimeDomainEXT::QUERY_PERFORMANCE_COUNTER);
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+const HOST_DOMAIN: Option<vk::TimeDomainEXT> = None;
+
+/// A reading of [`HOST_DOMAIN`] in nanoseconds, `raw` if given rather than now.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn host_ns(raw: Option<u64>) -> Option<u64> {
+    if let Some(raw) = raw {
+        return Some(raw);
+    }
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: Writes to the timespec it's given, and nothing else.
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    return Some(now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64);
+}
+
+#[cfg(target_os = "windows")]
+fn host_ns(raw: Option<u64>) -> Option<u64> {
+    use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+
+    let (mut count, mut frequency) = (0, 0);
+    // SAFETY: Each writes to the integer it's given, and nothing else.
+    unsafe {
+        QueryPerformanceFrequency(&mut frequency).ok()?;
+        if raw.is_none() {
+            QueryPerformanceCounter(&mut count).ok()?;
+        }
+    }
+    let count = raw.unwrap_or(count as u64);
+    return Some((count as u128 * 1_000_000_000 / frequency.max(1) as u128) as u64);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+fn host_ns(_raw: Option<u64>) -> Option<u64> {
+    None
+}
+
+/// A GPU timestamp tick count paired with the CPU instant it corresponds to.
+#[derive(Clone, Copy, Debug)]
+pub struct GpuClock {
+    /// Nanoseconds per tick, from `VkPhysicalDeviceLimits::timestampPeriod`.
+    pub period_ns: f64,
+    anchor_ticks: u64,
+    anchor: Instant,
+    /// Whether the anchor came from `VK_EXT_calibrated_timestamps` rather than a guess.
+    pub calibrated: bool,
+}
+
+impl GpuClock {
+    /// Calibrate against the device clock with `VK_EXT_calibrated_timestamps`, and the host clock too if
+    /// `domains`, what the device can calibrate, has it. Returns `None` if the call fails, in which case fall back
+    /// to [`GpuClock::from_submit`].
+    pub fn calibrate(
+        loader: &calibrated_timestamps::Device,
+        domains: &[vk::TimeDomainEXT],
+        period_ns: f32,
+    ) -> Option<GpuClock> {
+        let host = HOST_DOMAIN.filter(|d| domains.contains(d));
+        let info: Vec<_> = [Some(vk::TimeDomainEXT::DEVICE), host]
+            .into_iter()
+            .flatten()
+            .map(|domain| vk::CalibratedTimestampInfoEXT::default().time_domain(domain))
+            .collect();
+
+        let before = Instant::now();
+        // SAFETY: The loader is for a live device.
+        let (ticks, _) = unsafe { loader.get_calibrated_timestamps(&info) }.ok()?;
+        let after = Instant::now();
+        let now = host_ns(None);
+
+        // The host reading taken with the device's, moved back from now by however long ago it was.
+        let calibrated = ticks
+            .get(1)
+            .and_then(|&raw| host_ns(Some(raw)))
+            .zip(now)
+            .and_then(|(then, now)| {
+                after.checked_sub(Duration::from_nanos(now.checked_sub(then)?))
+            });
+        // Without one, the host side of the pair is whatever Instant::now() sees either side of the call. Good to
+        // within the call's duration, which is plenty for a profiler.
+        let anchor = calibrated.unwrap_or(before + (after - before) / 2);
+
+        Some(GpuClock {
+            period_ns: period_ns as f64,
+            anchor_ticks: ticks[0],
+            anchor,
+            calibrated: true,
+        })
+    }
+
+    /// Uncalibrated fallback: assume the first timestamp of a submission was taken as it was submitted.
+    /// This hides any queueing delay, so CPU/GPU bubbles will look smaller than they are.
+    pub fn from_submit(period_ns: f32, first_ticks: u64, submitted_at: Instant) -> GpuClock {
+        GpuClock {
+            period_ns: period_ns as f64,
+            anchor_ticks: first_ticks,
+            anchor: submitted_at,
+            calibrated: false,
+        }
+    }
+
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_nanos((ticks as f64 * self.period_ns) as u64)
+    }
+
+    /// The CPU instant a GPU timestamp corresponds to, `None` if that's outside what an [`Instant`] can hold.
+    pub fn to_instant(&self, ticks: u64) -> Option<Instant> {
+        if ticks >= self.anchor_ticks {
+            self.anchor
+                .checked_add(self.ticks_to_duration(ticks - self.anchor_ticks))
+        } else {
+            self.anchor
+                .checked_sub(self.ticks_to_duration(self.anchor_ticks - ticks))
+        }
+    }
+}
+
+/// The time domains the device can calibrate, if they include its own clock. `None` if it can't calibrate at all.
+pub fn calibration_domains(
+    loader: &calibrated_timestamps::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<Vec<vk::TimeDomainEXT>> {
+    // SAFETY: A query, on a device from the loader's instance.
+    unsafe { loader.get_physical_device_calibrateable_time_domains(physical_device) }
+        .ok()
+        .filter(|domains| domains.contains(&vk::TimeDomainEXT::DEVICE))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::GpuClock;
+
+    #[test]
+    pub fn maps_ticks_either_side() {
+        let now = Instant::now();
+        let clock = GpuClock::from_submit(2.0, 1000, now);
+        assert_eq!(clock.to_instant(1500), Some(now + Duration::from_micros(1)));
+        assert_eq!(
+            clock.to_instant(500),
+            now.checked_sub(Duration::from_micros(1))
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! GPU timings per pass, from timestamp queries.
+//!
+//! Each frame in flight has a query pool, reset at the start of its first command buffer. Passes bracket
+//! themselves with [`GpuProfiler::begin_scope`] and [`GpuProfiler::end_scope`], which write a timestamp either
+//! side. Once the frame's slot comes round again its fence has been waited on, so [`GpuProfiler::begin_frame`] can
+//! read the timestamps back without stalling. They're turned into durations with the device's timestamp period,
+//! kept as [`GpuTimings`], and handed to [`crate::profile`] to show on the same timeline as the CPU.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use ash::{ext::calibrated_timestamps, prelude::VkResult, vk};
+
+use super::{alloc::VK_ALLOCATOR_CALLBACKS, gpu_clock::GpuClock};
+use crate::profile::{self, GpuScope};
+
+/// Scopes per frame that can be timed. Any past this go untimed.
+pub const MAX_SCOPES: u32 = 256;
+
+/// How much each frame moves [`GpuPassTiming::average`], out of 1.
+const AVERAGE_WEIGHT: f64 = 0.1;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GpuScopeMarker {
+    scope: usize,
+}
+
+/// A scope begun in a frame, by where its timestamps are in the frame's pool.
+#[derive(Clone, Copy, Debug)]
+struct PendingScope {
+    name: &'static str,
+    depth: u32,
+    begin: u32,
+    /// `None` if it was never ended.
+    end: Option<u32>,
+}
+
+struct FrameQueries {
+    pool: vk::QueryPool,
+    frame: u64,
+    scopes: Vec<PendingScope>,
+    depth: u32,
+    /// When the frame's work was submitted, to line its timestamps up with the CPU's if nothing better.
+    submitted_at: Option<Instant>,
+    /// Reset on the GPU, so it can be written to.
+    reset: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpuPassTiming {
+    pub name: &'static str,
+    /// Nesting depth, 0 for outermost scopes.
+    pub depth: u32,
+    pub duration: Duration,
+    /// Smoothed over the frames it's been timed in.
+    pub average: Duration,
+}
+
+/// The GPU timings of the last frame read back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpuTimings {
+    pub frame: u64,
+    /// In the order the scopes began.
+    pub passes: Vec<GpuPassTiming>,
+}
+
+impl GpuTimings {
+    /// From the first scope beginning to the last one ending.
+    pub fn total(&self, scopes: &[GpuScope], period_ns: f64) -> Duration {
+        let start = scopes.iter().map(|s| s.start_ticks).min();
+        let end = scopes.iter().map(|s| s.end_ticks).max();
+        return match start.zip(end) {
+            Some((start, end)) => ticks_to_duration(end.saturating_sub(start), period_ns),
+            None => Duration::ZERO,
+        };
+    }
+
+    pub fn get(&self, name: &str) -> Option<&GpuPassTiming> {
+        self.passes.iter().find(|p| p.name == name)
+    }
+}
+
+fn ticks_to_duration(ticks: u64, period_ns: f64) -> Duration {
+    Duration::from_nanos((ticks as f64 * period_ns) as u64)
+}
+
+/// The scopes of a frame from the timestamps read back for it, `None` where a timestamp wasn't written. Ticks only
+/// count up to `valid_bits`, so an end that wrapped past the top comes out after its start anyway. Scopes missing a
+/// timestamp are left out.
+fn resolve(scopes: &[PendingScope], results: &[Option<u64>], valid_bits: u32) -> Vec<GpuScope> {
+    let mask = match valid_bits {
+        64.. => u64::MAX,
+        bits => (1 << bits) - 1,
+    };
+    return scopes
+        .iter()
+        .filter_map(|s| {
+            let begin = results.get(s.begin as usize).copied().flatten()? & mask;
+            let end = results.get(s.end? as usize).copied().flatten()? & mask;
+            Some(GpuScope {
+                name: s.name,
+                depth: s.depth,
+                start_ticks: begin,
+                end_ticks: begin + (end.wrapping_sub(begin) & mask),
+            })
+        })
+        .collect();
+}
+
+/// Timestamp queries for every frame in flight.
+pub struct GpuProfiler {
+    device: ash::Device,
+    frames: Vec<FrameQueries>,
+    current: usize,
+    period_ns: f64,
+    valid_bits: u32,
+    timings: GpuTimings,
+    averages: HashMap<&'static str, Duration>,
+    /// `VK_EXT_calibrated_timestamps` and the domains it can calibrate, where the device has it.
+    calibration: Option<(calibrated_timestamps::Device, Vec<vk::TimeDomainEXT>)>,
+}
+
+impl GpuProfiler {
+    /// Query pools for `frames_in_flight` frames, timing work on `queue_family`. `None` if that family can't
+    /// write timestamps. Timings are placed on the CPU timeline with calibrated timestamps if `calibrated` says
+    /// `VK_EXT_calibrated_timestamps` was enabled, as `entry` loads it.
+    ///
+    /// # Safety
+    /// The device must outlive this, and be made from `instance` on `physical_device`.
+    pub unsafe fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        queue_family: u32,
+        frames_in_flight: u32,
+        calibrated: bool,
+    ) -> VkResult<Option<GpuProfiler>> {
+        // SAFETY: Queries, on a device from the instance.
+        let (props, families) = unsafe {
+            (
+                instance.get_physical_device_properties(physical_device),
+                instance.get_physical_device_queue_family_properties(physical_device),
+            )
+        };
+        let valid_bits = families
+            .get(queue_family as usize)
+            .map_or(0, |f| f.timestamp_valid_bits);
+        if valid_bits == 0 {
+            return Ok(None);
+        }
+
+        let calibration = calibrated
+            .then(|| {
+                let loader = calibrated_timestamps::Instance::new(entry, instance);
+                super::gpu_clock::calibration_domains(&loader, physical_device)
+            })
+            .flatten()
+            .map(|domains| {
+                (
+                    calibrated_timestamps::Device::new(instance, device),
+                    domains,
+                )
+            });
+
+        let mut profiler = GpuProfiler {
+            device: device.clone(),
+            frames: Vec::new(),
+            current: 0,
+            period_ns: props.limits.timestamp_period as f64,
+            valid_bits,
+            timings: GpuTimings::default(),
+            averages: HashMap::new(),
+            calibration,
+        };
+        for _ in 0..frames_in_flight.max(1) {
+            let info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(MAX_SCOPES * 2);
+            // SAFETY: Destroyed on drop, and if making the rest fails, by dropping what's made so far.
+            let pool = unsafe { device.create_query_pool(&info, allocs())? };
+            profiler.frames.push(FrameQueries {
+                pool,
+                frame: 0,
+                scopes: Vec::new(),
+                depth: 0,
+                submitted_at: None,
+                reset: false,
+            });
+        }
+        return Ok(Some(profiler));
+    }
+
+    /// Nanoseconds per timestamp tick.
+    pub fn period_ns(&self) -> f64 {
+        self.period_ns
+    }
+
+    /// The last frame read back.
+    pub fn timings(&self) -> &GpuTimings {
+        &self.timings
+    }
+
+    /// Read back the timings of the last frame in `frame`'s slot, then start on `frame`. The slot's fence must
+    /// have been waited on.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.current = frame as usize % self.frames.len();
+        self.read_back();
+        let slot = &mut self.frames[self.current];
+        slot.frame = frame;
+        slot.scopes.clear();
+        slot.depth = 0;
+        slot.submitted_at = None;
+        slot.reset = false;
+    }
+
+    fn read_back(&mut self) {
+        let slot = &self.frames[self.current];
+        let Some(submitted_at) = slot.submitted_at else {
+            return;
+        };
+        if slot.scopes.is_empty() {
+            return;
+        }
+
+        let count = slot.scopes.len() as u32 * 2;
+        let mut results = vec![[0u64; 2]; count as usize];
+        // SAFETY: The slot's fence was waited on, so the GPU's done with the pool.
+        let read = unsafe {
+            self.device.get_query_pool_results(
+                slot.pool,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        if let Err(e) = read
+            && e != vk::Result::NOT_READY
+        {
+            log::warn!("Couldn't read GPU timestamps: {e}");
+            return;
+        }
+        let results: Vec<_> = results
+            .iter()
+            .map(|&[ticks, available]| (available != 0).then_some(ticks))
+            .collect();
+        let scopes = resolve(&slot.scopes, &results, self.valid_bits);
+        let Some(first) = scopes.iter().map(|s| s.start_ticks).min() else {
+            return;
+        };
+
+        // Recalibrated each frame, as the two clocks drift apart.
+        let clock = self
+            .calibration
+            .as_ref()
+            .and_then(|(loader, domains)| {
+                GpuClock::calibrate(loader, domains, self.period_ns as f32)
+            })
+            .unwrap_or_else(|| GpuClock::from_submit(self.period_ns as f32, first, submitted_at));
+        profile::record_gpu_frame(slot.frame, &clock, &scopes);
+        let mut passes = Vec::with_capacity(scopes.len());
+        for scope in &scopes {
+            let duration = ticks_to_duration(scope.end_ticks - scope.start_ticks, self.period_ns);
+            let average = self.averages.entry(scope.name).or_insert(duration);
+            *average = average.mul_f64(1.0 - AVERAGE_WEIGHT) + duration.mul_f64(AVERAGE_WEIGHT);
+            passes.push(GpuPassTiming {
+                name: scope.name,
+                depth: scope.depth,
+                duration,
+                average: *average,
+            });
+        }
+        self.timings = GpuTimings {
+            frame: slot.frame,
+            passes,
+        };
+    }
+
+    /// Reset the frame's queries, at the start of its first command buffer.
+    ///
+    /// # Safety
+    /// `cmd` must be recording, outside a render pass.
+    pub unsafe fn reset(&mut self, cmd: vk::CommandBuffer) {
+        let slot = &mut self.frames[self.current];
+        if slot.reset {
+            return;
+        }
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.device
+                .cmd_reset_query_pool(cmd, slot.pool, 0, MAX_SCOPES * 2)
+        };
+        slot.reset = true;
+    }
+
+    /// Start timing a scope called `name`. `None` past [`MAX_SCOPES`] in a frame, or before [`GpuProfiler::reset`].
+    ///
+    /// # Safety
+    /// `cmd` must be recording, and submitted this frame.
+    pub unsafe fn begin_scope(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        name: &'static str,
+    ) -> Option<GpuScopeMarker> {
+        let slot = &mut self.frames[self.current];
+        if !slot.reset || slot.scopes.len() as u32 == MAX_SCOPES {
+            return None;
+        }
+        let begin = slot.scopes.len() as u32 * 2;
+        slot.scopes.push(PendingScope {
+            name,
+            depth: slot.depth,
+            begin,
+            end: None,
+        });
+        slot.depth += 1;
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                slot.pool,
+                begin,
+            )
+        };
+        return Some(GpuScopeMarker {
+            scope: slot.scopes.len() - 1,
+        });
+    }
+
+    /// # Safety
+    /// As with [`GpuProfiler::begin_scope`].
+    pub unsafe fn end_scope(&mut self, cmd: vk::CommandBuffer, marker: Option<GpuScopeMarker>) {
+        let Some(marker) = marker else {
+            return;
+        };
+        let slot = &mut self.frames[self.current];
+        let scope = &mut slot.scopes[marker.scope];
+        let end = scope.begin + 1;
+        scope.end = Some(end);
+        slot.depth = slot.depth.saturating_sub(1);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                slot.pool,
+                end,
+            )
+        };
+    }
+
+    /// The frame's work was just submitted.
+    pub fn on_submit(&mut self) {
+        let slot = &mut self.frames[self.current];
+        slot.submitted_at.get_or_insert_with(Instant::now);
+    }
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        for slot in &self.frames {
+            // SAFETY: Whoever drops us has made sure the GPU is done with the pools.
+            unsafe { self.device.destroy_query_pool(slot.pool, allocs()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{GpuTimings, PendingScope, resolve, ticks_to_duration};
+
+    #[test]
+    pub fn resolves_scopes() {
+        let scope = |name, depth, begin, end| PendingScope {
+            name,
+            depth,
+            begin,
+            end,
+        };
+        let scopes = [
+            scope("frame", 0, 0, Some(1)),
+            scope("shadows", 1, 2, Some(3)),
+            // Never ended, or its timestamps weren't there.
+            scope("lost", 1, 4, None),
+            scope("missing", 1, 6, Some(7)),
+        ];
+        // 16 bit timestamps, the frame's end wrapping past the top.
+        let results = [
+            Some(0xfff0),
+            Some(0x10010),
+            Some(0xfff8),
+            Some(0xfffc),
+            Some(5),
+            None,
+            Some(6),
+            None,
+        ];
+        let resolved = resolve(&scopes, &results, 16);
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(resolved[0].name, "frame");
+        assert_eq!(resolved[0].end_ticks - resolved[0].start_ticks, 0x20);
+        assert_eq!(resolved[1].end_ticks - resolved[1].start_ticks, 4);
+        assert_eq!(resolved[1].depth, 1);
+
+        // 1.5ns ticks.
+        assert_eq!(ticks_to_duration(0x20, 1.5), Duration::from_nanos(48));
+        assert_eq!(
+            GpuTimings::default().total(&resolved, 1.5),
+            Duration::from_nanos(48)
+        );
+        assert_eq!(GpuTimings::default().total(&[], 1.5), Duration::ZERO);
+    }
+}
@@ -0,0 +1,346 @@
+//! Choosing which GPU to render with, when there's more than one.
+//!
+//! Every device gets looked over and scored: what kind it is first, then how much memory it has and whether it
+//! has queues for async compute and transfers. Devices missing something required don't get a score at all.
+//! Laptops with an integrated and a discrete GPU are the usual reason to care.
+//!
+//! [`GPU_ENV`] or the `r_gpu` cvar force a particular device, by its index in the list or part of its name.
+
+use std::{ffi::CStr, fmt};
+
+use ash::vk;
+
+/// Environment variable that forces a device, over the `r_gpu` cvar.
+pub const GPU_ENV: &str = "CROWBAR_GPU";
+
+/// What a device has to have to be rendered with.
+#[derive(Clone, Debug)]
+pub struct Requirements {
+    pub api_version: u32,
+    pub extensions: Vec<&'static CStr>,
+    /// CPU implementations first, for results that come out the same everywhere.
+    pub prefer_software: bool,
+}
+
+impl Default for Requirements {
+    fn default() -> Requirements {
+        Requirements {
+            api_version: vk::API_VERSION_1_3,
+            extensions: Vec::new(),
+            prefer_software: false,
+        }
+    }
+}
+
+/// A device as found, and what it's missing if it's no good.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    /// Index in enumeration order, which is what [`GpuOverride::Index`] means.
+    pub index: usize,
+    pub physical_device: vk::PhysicalDevice,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    /// Device local memory, in bytes.
+    pub memory: u64,
+    /// A family that does graphics.
+    pub graphics_family: Option<u32>,
+    /// A family that does compute but not graphics, for async compute.
+    pub compute_family: Option<u32>,
+    /// A family that only does transfers, for uploads alongside rendering.
+    pub transfer_family: Option<u32>,
+    /// Why it can't be used, empty if it can.
+    pub missing: Vec<String>,
+}
+
+impl DeviceInfo {
+    /// Look `physical_device` over against `requirements`.
+    pub fn query(
+        instance: &ash::Instance,
+        index: usize,
+        physical_device: vk::PhysicalDevice,
+        requirements: &Requirements,
+    ) -> DeviceInfo {
+        // SAFETY: Only queries, on a device from the instance.
+        let (props, memory_props, families, features13, extensions) = unsafe {
+            let mut features13 = vk::PhysicalDeviceVulkan13Features::default();
+            let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut features13);
+            instance.get_physical_device_features2(physical_device, &mut features);
+            (
+                instance.get_physical_device_properties(physical_device),
+                instance.get_physical_device_memory_properties(physical_device),
+                instance.get_physical_device_queue_family_properties(physical_device),
+                features13,
+                instance
+                    .enumerate_device_extension_properties(physical_device)
+                    .unwrap_or_default(),
+            )
+        };
+
+        let family = |wanted: vk::QueueFlags, unwanted: vk::QueueFlags| {
+            families
+                .iter()
+                .position(|f| f.queue_flags.contains(wanted) && !f.queue_flags.intersects(unwanted))
+                .map(|f| f as u32)
+        };
+        let graphics_family = family(vk::QueueFlags::GRAPHICS, vk::QueueFlags::empty());
+        let compute_family = family(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS);
+        let transfer_family = family(
+            vk::QueueFlags::TRANSFER,
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        );
+
+        let memory = memory_props.memory_heaps[..memory_props.memory_heap_count as usize]
+            .iter()
+            .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|h| h.size)
+            .sum();
+
+        let mut missing = Vec::new();
+        if props.api_version < requirements.api_version {
+            missing.push(format!(
+                "Vulkan {}.{}",
+                vk::api_version_major(requirements.api_version),
+                vk::api_version_minor(requirements.api_version)
+            ));
+        }
+        if features13.dynamic_rendering == vk::FALSE {
+            missing.push("dynamic rendering".to_string());
+        }
+        if graphics_family.is_none() {
+            missing.push("a graphics queue".to_string());
+        }
+        for wanted in &requirements.extensions {
+            if !extensions
+                .iter()
+                .any(|e| e.extension_name_as_c_str() == Ok(*wanted))
+            {
+                missing.push(wanted.to_string_lossy().into_owned());
+            }
+        }
+
+        return DeviceInfo {
+            index,
+            physical_device,
+            name: props
+                .device_name_as_c_str()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            device_type: props.device_type,
+            api_version: props.api_version,
+            memory,
+            graphics_family,
+            compute_family,
+            transfer_family,
+            missing,
+        };
+    }
+
+    pub fn usable(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Higher is better, `None` if it can't be used at all.
+    pub fn score(&self, prefer_software: bool) -> Option<u64> {
+        if !self.usable() {
+            return None;
+        }
+        let kind = match self.device_type {
+            vk::PhysicalDeviceType::CPU if prefer_software => 5,
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+        // The kind outweighs everything else, memory decides between two of a kind.
+        let memory_gib = (self.memory >> 30).min(255);
+        let queues =
+            self.compute_family.is_some() as u64 * 2 + self.transfer_family.is_some() as u64;
+        return Some(kind << 16 | memory_gib << 8 | queues);
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({:?}, {} MiB",
+            self.index,
+            self.name,
+            self.device_type,
+            self.memory >> 20
+        )?;
+        if !self.missing.is_empty() {
+            write!(f, ", missing {}", self.missing.join(", "))?;
+        }
+        return write!(f, ")");
+    }
+}
+
+/// A device asked for by the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuOverride {
+    Index(usize),
+    /// Case insensitive, anywhere in the name.
+    Name(String),
+}
+
+impl GpuOverride {
+    /// A number is an index, anything else part of a name. `None` for an empty string.
+    pub fn parse(s: &str) -> Option<GpuOverride> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        return Some(match s.parse() {
+            Ok(index) => GpuOverride::Index(index),
+            Err(_) => GpuOverride::Name(s.to_lowercase()),
+        });
+    }
+
+    /// From [`GPU_ENV`] if it's set, otherwise from `config`, the `r_gpu` cvar.
+    pub fn from_env_or(config: &str) -> Option<GpuOverride> {
+        match std::env::var(GPU_ENV) {
+            Ok(value) => GpuOverride::parse(&value),
+            Err(_) => GpuOverride::parse(config),
+        }
+    }
+
+    fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            GpuOverride::Index(index) => device.index == *index,
+            GpuOverride::Name(name) => device.name.to_lowercase().contains(name.as_str()),
+        }
+    }
+}
+
+/// Every device on `instance`, in enumeration order.
+pub fn enumerate(instance: &ash::Instance, requirements: &Requirements) -> Vec<DeviceInfo> {
+    // SAFETY: Only a query.
+    let devices = unsafe { instance.enumerate_physical_devices() }.unwrap_or_default();
+    return devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, pd)| DeviceInfo::query(instance, index, pd, requirements))
+        .collect();
+}
+
+/// The device to use out of `devices`. An override wins if it names a usable device, otherwise it's ignored with
+/// a warning and the best scoring one is picked.
+pub fn select<'a>(
+    devices: &'a [DeviceInfo],
+    requirements: &Requirements,
+    gpu_override: Option<&GpuOverride>,
+) -> Option<&'a DeviceInfo> {
+    if let Some(gpu_override) = gpu_override {
+        match devices.iter().find(|d| gpu_override.matches(d)) {
+            Some(device) if device.usable() => return Some(device),
+            Some(device) => log::warn!("Can't use the GPU asked for, {device}"),
+            None => log::warn!("No GPU matches {gpu_override:?}"),
+        }
+    }
+    // Ties go to the first one.
+    let mut best: Option<(&DeviceInfo, u64)> = None;
+    for device in devices {
+        if let Some(score) = device.score(requirements.prefer_software)
+            && best.is_none_or(|(_, s)| score > s)
+        {
+            best = Some((device, score));
+        }
+    }
+    return best.map(|(device, _)| device);
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{DeviceInfo, GpuOverride, Requirements, select};
+
+    fn device(
+        index: usize,
+        name: &str,
+        device_type: vk::PhysicalDeviceType,
+        gib: u64,
+    ) -> DeviceInfo {
+        DeviceInfo {
+            index,
+            physical_device: vk::PhysicalDevice::null(),
+            name: name.to_string(),
+            device_type,
+            api_version: vk::API_VERSION_1_3,
+            memory: gib << 30,
+            graphics_family: Some(0),
+            compute_family: None,
+            transfer_family: None,
+            missing: Vec::new(),
+        }
+    }
+
+    #[test]
+    pub fn scores_and_overrides() {
+        let mut devices = vec![
+            device(
+                0,
+                "Intel UHD 630",
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+                8,
+            ),
+            device(
+                1,
+                "NVIDIA RTX 2060",
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                6,
+            ),
+            device(
+                2,
+                "NVIDIA RTX 4090",
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+                24,
+            ),
+            device(3, "llvmpipe", vk::PhysicalDeviceType::CPU, 32),
+        ];
+        let requirements = Requirements::default();
+        let pick = |devices: &[DeviceInfo], o: Option<GpuOverride>| {
+            select(devices, &requirements, o.as_ref()).map(|d| d.index)
+        };
+
+        // Discrete beats integrated whatever the memory, then the bigger one wins.
+        assert_eq!(pick(&devices, None), Some(2));
+        let software = Requirements {
+            prefer_software: true,
+            ..Requirements::default()
+        };
+        assert_eq!(select(&devices, &software, None).map(|d| d.index), Some(3));
+
+        assert_eq!(pick(&devices, GpuOverride::parse("0")), Some(0));
+        assert_eq!(pick(&devices, GpuOverride::parse("rtx 2060")), Some(1));
+        // Nothing by that name, so back to the best.
+        assert_eq!(pick(&devices, GpuOverride::parse("radeon")), Some(2));
+        assert_eq!(GpuOverride::parse("  "), None);
+
+        // Unusable devices can't be picked, even when asked for.
+        devices[2].missing.push("VK_KHR_swapchain".to_string());
+        assert_eq!(pick(&devices, None), Some(1));
+        assert_eq!(pick(&devices, GpuOverride::parse("4090")), Some(1));
+
+        // A dedicated compute queue settles a tie.
+        let mut integrated = vec![
+            device(
+                0,
+                "Intel UHD 630",
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+                8,
+            ),
+            device(
+                1,
+                "Intel UHD 630",
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+                8,
+            ),
+        ];
+        integrated[1].compute_family = Some(1);
+        assert_eq!(pick(&integrated, None), Some(1));
+    }
+}
@@ -0,0 +1,767 @@
+//! The render graph: passes say what they read and write, and the graph works out the order they run in.
+//!
+//! A resource's readers run after the last pass added before them that writes it, and its writers in the order they
+//! were added, after whatever read it since the writer before. An imported resource's readers added before anything
+//! writes it read what it came in with, so they run before its first writer.
+//! [`CompiledGraph::validate`] catches the usual mistakes, and runs on every compile in debug builds.
+//! [`CompiledGraph::to_dot`] and the overlay's "Render graph" panel show what a frame looks like, and
+//! [`CompiledGraph::barriers`] what it costs in synchronization. [`transients::Transients`] makes the resources it
+//! declares, sharing them between passes where it can, with their memory put down to the passes that use them, and
+//! [`CompiledGraph::record`] runs the passes with their resources moved into the right states for them. The
+//! renderer draws every frame as one.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Write},
+};
+
+use super::hal::{BufferDesc, BufferUsage, TextureDesc, TextureState, TextureUsage};
+
+pub mod barriers;
+pub mod execute;
+pub mod readback;
+pub mod schedule;
+pub mod transients;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PassId(usize);
+
+impl PassId {
+    /// Where the pass is in [`RenderGraph::passes`], for keeping things per pass in a `Vec`.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Queue {
+    Graphics,
+    /// The async compute queue, which runs alongside graphics. See [`CompiledGraph::schedule`].
+    Compute,
+    Transfer,
+}
+
+impl Queue {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Queue::Graphics => "graphics",
+            Queue::Compute => "compute",
+            Queue::Transfer => "transfer",
+        }
+    }
+}
+
+/// How a pass uses a resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Drawn to, as colour or depth depending on the format.
+    RenderTarget,
+    ShaderRead,
+    /// Storage images and buffers.
+    ShaderWrite,
+    CopySrc,
+    CopyDst,
+}
+
+impl Access {
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Access::RenderTarget | Access::ShaderWrite | Access::CopyDst
+        )
+    }
+
+    /// The state a texture has to be in for this.
+    pub fn texture_state(&self) -> Option<TextureState> {
+        match self {
+            Access::RenderTarget => Some(TextureState::RenderTarget),
+            Access::ShaderRead => Some(TextureState::ShaderRead),
+            Access::CopySrc => Some(TextureState::CopySrc),
+            Access::CopyDst => Some(TextureState::CopyDst),
+            // todo: hal has no storage textures yet.
+            Access::ShaderWrite => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Access::RenderTarget => "render target",
+            Access::ShaderRead => "shader read",
+            Access::ShaderWrite => "shader write",
+            Access::CopySrc => "copy src",
+            Access::CopyDst => "copy dst",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ResourceKind {
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+    /// Made outside the graph, and has contents before the first pass gets to it. The swapchain image is one. With
+    /// what it was made as, if it's a texture the graph was told about, see [`RenderGraph::import_texture`].
+    Imported(Option<TextureDesc>),
+}
+
+impl ResourceKind {
+    /// What it was made as, if it's a texture the graph knows that about.
+    pub fn texture_desc(&self) -> Option<TextureDesc> {
+        match self {
+            ResourceKind::Texture(desc) | ResourceKind::Imported(Some(desc)) => Some(*desc),
+            _ => None,
+        }
+    }
+
+    /// Whether the resource was created for `access`.
+    fn allows(&self, access: Access) -> bool {
+        match (self, access) {
+            (ResourceKind::Imported(_), _) => true,
+            (ResourceKind::Texture(desc), access) => match access {
+                Access::RenderTarget => desc.usage.contains(TextureUsage::RENDER_TARGET),
+                Access::ShaderRead => desc.usage.contains(TextureUsage::SAMPLED),
+                // todo: hal has no storage textures yet.
+                Access::ShaderWrite => false,
+                Access::CopySrc => desc.usage.contains(TextureUsage::COPY_SRC),
+                Access::CopyDst => desc.usage.contains(TextureUsage::COPY_DST),
+            },
+            (ResourceKind::Buffer(desc), access) => match access {
+                Access::RenderTarget => false,
+                Access::ShaderRead => [
+                    BufferUsage::VERTEX,
+                    BufferUsage::INDEX,
+                    BufferUsage::UNIFORM,
+                    BufferUsage::STORAGE,
+                ]
+                .iter()
+                .any(|u| desc.usage.contains(*u)),
+                Access::ShaderWrite => desc.usage.contains(BufferUsage::STORAGE),
+                Access::CopySrc => desc.usage.contains(BufferUsage::COPY_SRC),
+                Access::CopyDst => desc.usage.contains(BufferUsage::COPY_DST),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Resource {
+    pub name: String,
+    pub kind: ResourceKind,
+    /// Needed once the frame's done, like the swapchain image or a readback. What doesn't lead to one is wasted.
+    pub output: bool,
+}
+
+/// A pass, and everything it touches.
+#[derive(Clone, Debug)]
+pub struct Pass {
+    name: String,
+    queue: Queue,
+    uses: Vec<(ResourceId, Access)>,
+}
+
+impl Pass {
+    /// On the graphics queue unless told otherwise.
+    pub fn new(name: impl Into<String>) -> Pass {
+        Pass {
+            name: name.into(),
+            queue: Queue::Graphics,
+            uses: Vec::new(),
+        }
+    }
+
+    pub fn with_queue(mut self, queue: Queue) -> Self {
+        self.queue = queue;
+        return self;
+    }
+
+    pub fn with_access(mut self, resource: ResourceId, access: Access) -> Self {
+        self.uses.push((resource, access));
+        return self;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn queue(&self) -> Queue {
+        self.queue
+    }
+
+    pub fn uses(&self) -> &[(ResourceId, Access)] {
+        &self.uses
+    }
+
+    fn writes(&self, resource: ResourceId) -> bool {
+        self.uses.iter().any(|&(r, a)| r == resource && a.writes())
+    }
+
+    fn reads(&self, resource: ResourceId) -> bool {
+        self.uses.iter().any(|&(r, a)| r == resource && !a.writes())
+    }
+}
+
+/// A frame's passes and resources, as declared.
+#[derive(Clone, Debug, Default)]
+pub struct RenderGraph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+    swapchain: Option<ResourceId>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        Self::default()
+    }
+
+    fn add_resource(&mut self, name: impl Into<String>, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.into(),
+            kind,
+            output: false,
+        });
+        return ResourceId(self.resources.len() - 1);
+    }
+
+    pub fn add_texture(&mut self, name: impl Into<String>, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Texture(desc))
+    }
+
+    pub fn add_buffer(&mut self, name: impl Into<String>, desc: BufferDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Buffer(desc))
+    }
+
+    pub fn import(&mut self, name: impl Into<String>) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported(None))
+    }
+
+    /// [`RenderGraph::import`], for a texture made as `desc`, which can then be read back.
+    pub fn import_texture(&mut self, name: impl Into<String>, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported(Some(desc)))
+    }
+
+    /// The image that gets presented this frame.
+    pub fn swapchain(&mut self) -> ResourceId {
+        if let Some(id) = self.swapchain {
+            return id;
+        }
+        let id = self.import("swapchain");
+        self.mark_output(id);
+        self.swapchain = Some(id);
+        return id;
+    }
+
+    /// Keep `resource`, and the passes that make it, after the frame.
+    pub fn mark_output(&mut self, resource: ResourceId) {
+        self.resources[resource.0].output = true;
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) -> PassId {
+        self.passes.push(pass);
+        return PassId(self.passes.len() - 1);
+    }
+
+    pub fn resource(&self, id: ResourceId) -> &Resource {
+        &self.resources[id.0]
+    }
+
+    pub fn pass(&self, id: PassId) -> &Pass {
+        &self.passes[id.0]
+    }
+
+    pub fn resources(&self) -> impl Iterator<Item = ResourceId> {
+        (0..self.resources.len()).map(ResourceId)
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = PassId> {
+        (0..self.passes.len()).map(PassId)
+    }
+
+    /// Every `(before, after)` pair of passes, from what they touch, in the order they were added. A reader runs
+    /// after the last writer added before it, and a writer after the last writer and every reader since, so a
+    /// resource can be written again once what reads it is done. Readers added before anything writes it run after
+    /// the first writer, unless it's imported: then they read what it came in with, and run before.
+    fn dependencies(&self) -> BTreeSet<(PassId, PassId)> {
+        let mut edges = BTreeSet::new();
+        for resource in self.resources() {
+            let imported = matches!(self.resource(resource).kind, ResourceKind::Imported(_));
+            let mut writer: Option<PassId> = None;
+            let mut readers: Vec<PassId> = Vec::new();
+            let mut early: Vec<PassId> = Vec::new();
+            for pass in self.passes() {
+                let (reads, writes) = (
+                    self.pass(pass).reads(resource),
+                    self.pass(pass).writes(resource),
+                );
+                if reads {
+                    match writer {
+                        Some(writer) => {
+                            edges.insert((writer, pass));
+                            readers.push(pass);
+                        }
+                        None if imported => readers.push(pass),
+                        None => early.push(pass),
+                    }
+                }
+                if !writes {
+                    continue;
+                }
+                if writer.is_none() {
+                    edges.extend(early.drain(..).filter(|r| *r != pass).map(|r| (pass, r)));
+                }
+                let before = writer.into_iter().chain(readers.drain(..));
+                edges.extend(before.filter(|b| *b != pass).map(|b| (b, pass)));
+                writer = Some(pass);
+            }
+        }
+        return edges;
+    }
+
+    /// Work out the order. Ties go to whichever pass was added first.
+    /// Passes caught in a cycle can't be ordered and are left out, [`CompiledGraph::validate`] says which.
+    pub fn compile(self) -> CompiledGraph {
+        let edges: Vec<(PassId, PassId)> = self.dependencies().into_iter().collect();
+        let mut counts = vec![0; self.passes.len()];
+        for (_, after) in &edges {
+            counts[after.0] += 1;
+        }
+
+        let mut ready: BTreeSet<usize> = (0..counts.len()).filter(|&i| counts[i] == 0).collect();
+        let mut order = Vec::with_capacity(self.passes.len());
+        while let Some(i) = ready.pop_first() {
+            order.push(PassId(i));
+            for (_, after) in edges.iter().filter(|(before, _)| before.0 == i) {
+                counts[after.0] -= 1;
+                if counts[after.0] == 0 {
+                    ready.insert(after.0);
+                }
+            }
+        }
+
+        let compiled = CompiledGraph {
+            graph: self,
+            order,
+            edges,
+        };
+        if cfg!(debug_assertions) {
+            for issue in compiled.validate() {
+                log::warn!("Render graph: {}", compiled.describe(&issue));
+            }
+        }
+        return compiled;
+    }
+}
+
+/// Something off about a graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphIssue {
+    /// These wait on each other, so none of them run.
+    Cycle(Vec<PassId>),
+    /// Read without anything writing it first, so the pass gets garbage.
+    /// No pass means an output nothing writes.
+    Unbound {
+        pass: Option<PassId>,
+        resource: ResourceId,
+    },
+    /// Used in a way the resource wasn't created for.
+    MissingUsage {
+        pass: PassId,
+        resource: ResourceId,
+        access: Access,
+    },
+    /// Nothing it does reaches the swapchain or another output.
+    Unused(PassId),
+}
+
+impl GraphIssue {
+    /// The passes it's about.
+    pub fn passes(&self) -> Vec<PassId> {
+        match self {
+            GraphIssue::Cycle(passes) => passes.clone(),
+            GraphIssue::Unbound { pass, .. } => pass.iter().copied().collect(),
+            GraphIssue::MissingUsage { pass, .. } | GraphIssue::Unused(pass) => vec![*pass],
+        }
+    }
+}
+
+/// A graph with its passes in order.
+#[derive(Clone, Debug)]
+pub struct CompiledGraph {
+    graph: RenderGraph,
+    order: Vec<PassId>,
+    edges: Vec<(PassId, PassId)>,
+}
+
+impl CompiledGraph {
+    pub fn graph(&self) -> &RenderGraph {
+        &self.graph
+    }
+
+    /// The order passes run in.
+    pub fn order(&self) -> &[PassId] {
+        &self.order
+    }
+
+    /// Every `(before, after)` dependency between passes.
+    pub fn edges(&self) -> &[(PassId, PassId)] {
+        &self.edges
+    }
+
+    /// How many passes deep each pass is, for laying the graph out. Passes in cycles get 0.
+    pub fn depths(&self) -> Vec<usize> {
+        let mut depths = vec![0; self.graph.passes.len()];
+        for &pass in &self.order {
+            let depth = self
+                .edges
+                .iter()
+                .filter(|(_, after)| *after == pass)
+                .map(|(before, _)| depths[before.0] + 1)
+                .max()
+                .unwrap_or(0);
+            depths[pass.0] = depth;
+        }
+        return depths;
+    }
+
+    /// Passes that lead to an output. Everything else could go without changing the frame.
+    fn live(&self) -> BTreeSet<PassId> {
+        let graph = &self.graph;
+        let mut needed: Vec<ResourceId> = graph
+            .resources()
+            .filter(|r| graph.resource(*r).output)
+            .collect();
+        let mut seen: BTreeSet<ResourceId> = needed.iter().copied().collect();
+        let mut live = BTreeSet::new();
+
+        while let Some(resource) = needed.pop() {
+            for pass in graph.passes().filter(|p| graph.pass(*p).writes(resource)) {
+                if !live.insert(pass) {
+                    continue;
+                }
+                for &(used, _) in graph.pass(pass).uses() {
+                    if seen.insert(used) {
+                        needed.push(used);
+                    }
+                }
+            }
+        }
+        return live;
+    }
+
+    pub fn validate(&self) -> Vec<GraphIssue> {
+        let graph = &self.graph;
+        let mut issues = Vec::new();
+
+        let cycle: Vec<PassId> = graph.passes().filter(|p| !self.order.contains(p)).collect();
+        if !cycle.is_empty() {
+            issues.push(GraphIssue::Cycle(cycle));
+        }
+
+        for resource in graph.resources() {
+            let r = graph.resource(resource);
+            let written = graph.passes().any(|p| graph.pass(p).writes(resource));
+            if written || matches!(r.kind, ResourceKind::Imported(_)) {
+                continue;
+            }
+            if r.output {
+                issues.push(GraphIssue::Unbound {
+                    pass: None,
+                    resource,
+                });
+            }
+            for pass in graph.passes().filter(|p| graph.pass(*p).reads(resource)) {
+                issues.push(GraphIssue::Unbound {
+                    pass: Some(pass),
+                    resource,
+                });
+            }
+        }
+
+        for pass in graph.passes() {
+            for &(resource, access) in graph.pass(pass).uses() {
+                if !graph.resource(resource).kind.allows(access) {
+                    issues.push(GraphIssue::MissingUsage {
+                        pass,
+                        resource,
+                        access,
+                    });
+                }
+            }
+        }
+
+        let live = self.live();
+        for pass in graph.passes().filter(|p| !live.contains(p)) {
+            issues.push(GraphIssue::Unused(pass));
+        }
+        return issues;
+    }
+
+    /// An issue in words, with names rather than ids.
+    pub fn describe(&self, issue: &GraphIssue) -> String {
+        let pass = |p: &PassId| self.graph.pass(*p).name();
+        let resource = |r: &ResourceId| &self.graph.resource(*r).name;
+        match issue {
+            GraphIssue::Cycle(passes) => {
+                let names: Vec<&str> = passes.iter().map(pass).collect();
+                format!("{} depend on each other", names.join(", "))
+            }
+            GraphIssue::Unbound {
+                pass: Some(p),
+                resource: r,
+            } => format!("{} reads {}, which nothing writes", pass(p), resource(r)),
+            GraphIssue::Unbound {
+                pass: None,
+                resource: r,
+            } => format!("Nothing writes the output {}", resource(r)),
+            GraphIssue::MissingUsage {
+                pass: p,
+                resource: r,
+                access,
+            } => format!(
+                "{} uses {} as {}, which it wasn't created for",
+                pass(p),
+                resource(r),
+                access.name()
+            ),
+            GraphIssue::Unused(p) => format!("{} doesn't contribute to any output", pass(p)),
+        }
+    }
+
+    /// The graph in Graphviz's format, for `dot -Tsvg`. Passes are boxes, resources ellipses, outputs doubled,
+    /// and passes with issues red or, if they're only unused, grey.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.write_dot(&mut out);
+        return out;
+    }
+
+    fn write_dot(&self, out: &mut String) -> fmt::Result {
+        let graph = &self.graph;
+        let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut broken = BTreeSet::new();
+        let mut unused = BTreeSet::new();
+        for issue in self.validate() {
+            match issue {
+                GraphIssue::Unused(p) => {
+                    unused.insert(p);
+                }
+                issue => broken.extend(issue.passes()),
+            }
+        }
+
+        writeln!(out, "digraph render_graph {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+        for resource in graph.resources() {
+            let r = graph.resource(resource);
+            let peripheries = if r.output { 2 } else { 1 };
+            writeln!(
+                out,
+                "    r{} [label=\"{}\" shape=ellipse peripheries={peripheries}];",
+                resource.0,
+                quote(&r.name)
+            )?;
+        }
+        for pass in graph.passes() {
+            let p = graph.pass(pass);
+            let style = if broken.contains(&pass) {
+                " style=filled fillcolor=\"#f08080\""
+            } else if unused.contains(&pass) {
+                " style=filled fillcolor=\"#c0c0c0\""
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    p{} [label=\"{}\\n{}\" shape=box{style}];",
+                pass.0,
+                quote(p.name()),
+                p.queue().name()
+            )?;
+            for &(resource, access) in p.uses() {
+                let (from, to) = if access.writes() {
+                    (format!("p{}", pass.0), format!("r{}", resource.0))
+                } else {
+                    (format!("r{}", resource.0), format!("p{}", pass.0))
+                };
+                writeln!(out, "    {from} -> {to} [label=\"{}\"];", access.name())?;
+            }
+        }
+        writeln!(out, "}}")?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, GraphIssue, Pass, PassId, Queue, RenderGraph};
+    use crate::render::hal::{TextureDesc, TextureFormat, TextureUsage};
+
+    fn target(format: TextureFormat) -> TextureDesc {
+        TextureDesc {
+            width: 64,
+            height: 64,
+            format,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+            samples: 1,
+        }
+    }
+
+    #[test]
+    pub fn orders_and_validates() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let albedo = graph.add_texture("albedo", target(TextureFormat::Rgba8Srgb));
+        let depth = graph.add_texture("depth", target(TextureFormat::Depth32Float));
+        let hdr = graph.add_texture("hdr", target(TextureFormat::Rgba16Float));
+        let history = graph.add_texture("history", target(TextureFormat::Rgba16Float));
+        let stray = graph.add_texture("stray", target(TextureFormat::Rgba8Unorm));
+
+        // Added out of order on purpose.
+        let tonemap = graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+        let lighting = graph.add_pass(
+            Pass::new("lighting")
+                .with_queue(Queue::Compute)
+                .with_access(albedo, Access::ShaderRead)
+                .with_access(depth, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        let gbuffer = graph.add_pass(
+            Pass::new("gbuffer")
+                .with_access(albedo, Access::RenderTarget)
+                .with_access(depth, Access::RenderTarget),
+        );
+        let debug = graph.add_pass(
+            Pass::new("debug")
+                .with_access(stray, Access::ShaderRead)
+                .with_access(history, Access::CopyDst),
+        );
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[gbuffer, lighting, tonemap, debug]);
+        assert_eq!(compiled.depths()[tonemap.0], 2);
+        assert_eq!(
+            compiled.validate(),
+            vec![
+                GraphIssue::Unbound {
+                    pass: Some(debug),
+                    resource: stray
+                },
+                GraphIssue::MissingUsage {
+                    pass: debug,
+                    resource: history,
+                    access: Access::CopyDst
+                },
+                GraphIssue::Unused(debug),
+            ]
+        );
+
+        let dot = compiled.to_dot();
+        assert!(dot.starts_with("digraph render_graph {"));
+        assert!(dot.contains("p2 -> r2 [label=\"render target\"];"));
+        assert!(dot.contains("r0 [label=\"swapchain\" shape=ellipse peripheries=2];"));
+    }
+
+    #[test]
+    pub fn writes_back_after_reads() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let hdr = graph.add_texture("hdr", target(TextureFormat::Rgba16Float));
+        let scene = graph.add_pass(Pass::new("scene").with_access(hdr, Access::RenderTarget));
+        let bloom = graph.add_pass(Pass::new("bloom").with_access(hdr, Access::ShaderRead));
+        let composite =
+            graph.add_pass(Pass::new("composite").with_access(hdr, Access::RenderTarget));
+        let tonemap = graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[scene, bloom, composite, tonemap]);
+        assert_eq!(
+            compiled.edges(),
+            &[
+                (scene, bloom),
+                (scene, composite),
+                (bloom, composite),
+                (composite, tonemap)
+            ]
+        );
+        assert!(
+            compiled
+                .validate()
+                .iter()
+                .all(|i| !matches!(i, GraphIssue::Cycle(_)))
+        );
+    }
+
+    #[test]
+    pub fn imported_read_before_written() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let history = graph.import_texture("history", target(TextureFormat::Rgba16Float));
+        let hdr = graph.add_texture("hdr", target(TextureFormat::Rgba16Float));
+        // Last frame's history is read, then this frame's written over it.
+        let resolve = graph.add_pass(
+            Pass::new("resolve")
+                .with_access(history, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        let store = graph.add_pass(
+            Pass::new("store")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(history, Access::RenderTarget),
+        );
+        let tonemap = graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(history, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[resolve, store, tonemap]);
+        assert_eq!(compiled.edges(), &[(resolve, store), (store, tonemap)]);
+        assert!(
+            compiled
+                .validate()
+                .iter()
+                .all(|i| !matches!(i, GraphIssue::Cycle(_)))
+        );
+    }
+
+    #[test]
+    pub fn cycles_are_left_out() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let a = graph.add_texture("a", target(TextureFormat::Rgba8Unorm));
+        let b = graph.add_texture("b", target(TextureFormat::Rgba8Unorm));
+        graph.add_pass(
+            Pass::new("ping")
+                .with_access(a, Access::ShaderRead)
+                .with_access(b, Access::RenderTarget),
+        );
+        graph.add_pass(
+            Pass::new("pong")
+                .with_access(b, Access::ShaderRead)
+                .with_access(a, Access::RenderTarget),
+        );
+        let present =
+            graph.add_pass(Pass::new("present").with_access(swapchain, Access::RenderTarget));
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[present]);
+        let issues = compiled.validate();
+        assert_eq!(issues[0], GraphIssue::Cycle(vec![PassId(0), PassId(1)]));
+        assert_eq!(
+            compiled.describe(&issues[0]),
+            "ping, pong depend on each other"
+        );
+    }
+}
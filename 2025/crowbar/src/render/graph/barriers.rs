@@ -0,0 +1,242 @@
+//! The synchronization a compiled graph needs between its passes, and what it adds up to per frame.
+//!
+//! A barrier goes in before a pass for each resource it uses that needs one: the previous use was a write or this
+//! one is, a texture changes layout, or the resource changes queues. Reads after reads on the same queue and in the
+//! same layout are free. Imported resources count as images, since that's what the swapchain is.
+
+use std::ops::Add;
+
+use super::{Access, CompiledGraph, PassId, Queue, ResourceId, ResourceKind};
+
+/// One resource's barrier before a pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Barrier {
+    pub resource: ResourceId,
+    /// The last use, `None` for the first use in the frame.
+    pub from: Option<(Access, Queue)>,
+    pub to: (Access, Queue),
+    pub image: bool,
+    pub layout_transition: bool,
+    pub queue_transfer: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BarrierCounts {
+    pub image_barriers: u32,
+    pub buffer_barriers: u32,
+    pub layout_transitions: u32,
+    /// Queue family ownership transfers, each needing a release on one queue and an acquire on the other.
+    pub queue_transfers: u32,
+}
+
+impl BarrierCounts {
+    pub fn barriers(&self) -> u32 {
+        self.image_barriers + self.buffer_barriers
+    }
+
+    fn count(&mut self, barrier: &Barrier) {
+        if barrier.image {
+            self.image_barriers += 1;
+        } else {
+            self.buffer_barriers += 1;
+        }
+        self.layout_transitions += barrier.layout_transition as u32;
+        self.queue_transfers += barrier.queue_transfer as u32;
+    }
+}
+
+impl Add for BarrierCounts {
+    type Output = BarrierCounts;
+
+    fn add(self, rhs: BarrierCounts) -> BarrierCounts {
+        BarrierCounts {
+            image_barriers: self.image_barriers + rhs.image_barriers,
+            buffer_barriers: self.buffer_barriers + rhs.buffer_barriers,
+            layout_transitions: self.layout_transitions + rhs.layout_transitions,
+            queue_transfers: self.queue_transfers + rhs.queue_transfers,
+        }
+    }
+}
+
+/// The barriers going in before one pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassBarriers {
+    pub pass: PassId,
+    pub counts: BarrierCounts,
+    pub barriers: Vec<Barrier>,
+}
+
+impl PassBarriers {
+    pub(super) fn push(&mut self, barrier: Barrier) {
+        self.counts.count(&barrier);
+        self.barriers.push(barrier);
+    }
+}
+
+/// A frame's worth of barriers, pass by pass in the order they run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BarrierStats {
+    pub passes: Vec<PassBarriers>,
+}
+
+impl BarrierStats {
+    pub fn total(&self) -> BarrierCounts {
+        self.passes
+            .iter()
+            .fold(BarrierCounts::default(), |total, p| total + p.counts)
+    }
+
+    /// The `n` passes with the most barriers, most first. Passes without any are left out.
+    pub fn top_offenders(&self, n: usize) -> Vec<&PassBarriers> {
+        let mut passes: Vec<&PassBarriers> = self
+            .passes
+            .iter()
+            .filter(|p| p.counts.barriers() > 0)
+            .collect();
+        passes.sort_by_key(|p| std::cmp::Reverse(p.counts.barriers()));
+        passes.truncate(n);
+        return passes;
+    }
+}
+
+impl CompiledGraph {
+    /// Walk the passes in order and work out every barrier between them.
+    pub fn barriers(&self) -> BarrierStats {
+        let graph = &self.graph;
+        let mut last: Vec<Option<(Access, Queue)>> = vec![None; graph.resources.len()];
+        let mut stats = BarrierStats::default();
+
+        for &pass in &self.order {
+            let p = graph.pass(pass);
+            let mut barriers: Vec<Barrier> = Vec::new();
+            for &(resource, access) in p.uses() {
+                let image = !matches!(graph.resource(resource).kind, ResourceKind::Buffer(_));
+                let from = last[resource.0];
+                let to = (access, p.queue());
+                last[resource.0] = Some(to);
+
+                // A pass using something more than one way needs the one barrier, to the layout it ends up in.
+                if let Some(earlier) = barriers.iter_mut().find(|b| b.resource == resource) {
+                    if image && earlier.to.0 != access {
+                        earlier.layout_transition = true;
+                    }
+                    earlier.to = to;
+                    continue;
+                }
+
+                let (layout_transition, queue_transfer, hazard) = match from {
+                    // Images start out undefined, buffers need nothing for their first use.
+                    None => (image, false, false),
+                    Some((before, queue)) => (
+                        image && before != access,
+                        queue != p.queue(),
+                        before.writes() || access.writes(),
+                    ),
+                };
+                if layout_transition || queue_transfer || hazard {
+                    barriers.push(Barrier {
+                        resource,
+                        from,
+                        to,
+                        image,
+                        layout_transition,
+                        queue_transfer,
+                    });
+                }
+            }
+
+            let mut counts = BarrierCounts::default();
+            for barrier in &barriers {
+                counts.count(barrier);
+            }
+            stats.passes.push(PassBarriers {
+                pass,
+                counts,
+                barriers,
+            });
+        }
+        return stats;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BarrierCounts;
+    use crate::render::{
+        graph::{Access, Pass, Queue, RenderGraph},
+        hal::{BufferDesc, BufferUsage, MemoryLocation, TextureDesc, TextureFormat, TextureUsage},
+    };
+
+    #[test]
+    pub fn counts_barriers() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let hdr = graph.add_texture(
+            "hdr",
+            TextureDesc {
+                width: 64,
+                height: 64,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                samples: 1,
+            },
+        );
+        let lights = graph.add_buffer(
+            "lights",
+            BufferDesc {
+                size: 1024,
+                usage: BufferUsage::STORAGE,
+                location: MemoryLocation::Device,
+            },
+        );
+
+        let cull = graph.add_pass(
+            Pass::new("cull lights")
+                .with_queue(Queue::Compute)
+                .with_access(lights, Access::ShaderWrite),
+        );
+        let opaque = graph.add_pass(
+            Pass::new("opaque")
+                .with_access(lights, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        // Reading the same buffer again on the same queue costs nothing.
+        let transparent = graph.add_pass(
+            Pass::new("transparent")
+                .with_access(lights, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        let tonemap = graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+
+        let stats = graph.compile().barriers();
+        let passes: Vec<_> = stats.passes.iter().map(|p| p.pass).collect();
+        assert_eq!(passes, vec![cull, opaque, transparent, tonemap]);
+
+        // The light buffer crosses from compute, and hdr comes out of undefined.
+        let opaque = &stats.passes[1];
+        assert_eq!(opaque.counts.queue_transfers, 1);
+        assert_eq!(opaque.counts.layout_transitions, 1);
+        assert_eq!(opaque.counts.barriers(), 2);
+        // hdr is drawn to again, which needs ordering but no transition.
+        assert_eq!(stats.passes[2].counts.image_barriers, 1);
+        assert_eq!(stats.passes[2].counts.layout_transitions, 0);
+
+        assert_eq!(
+            stats.total(),
+            BarrierCounts {
+                image_barriers: 4,
+                buffer_barriers: 1,
+                layout_transitions: 3,
+                queue_transfers: 1,
+            }
+        );
+        let top = stats.top_offenders(2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].counts.barriers(), 2);
+        assert!(stats.top_offenders(10).iter().all(|p| p.pass != passes[0]));
+    }
+}
@@ -0,0 +1,675 @@
+//! Recording a compiled graph: before each pass runs, the graph puts the barriers [`CompiledGraph::barriers`] asks
+//! for in, moving its textures into the states the pass declared, and then the pass records its own commands.
+//!
+//! States are tracked per resource actually made, so a transient sharing a texture with an earlier one moves it
+//! out of whatever that one left it in, and waits on it. What goes in is counted as it's recorded, which can differ
+//! from what [`CompiledGraph::barriers`] works out from the graph alone: imports turn out to be buffers, aliased
+//! transients need moving out of what they were, some barriers don't need recording at all, and queues only change
+//! hands where the device has more than one. Storage textures have no state in hal and are left where they are.
+//!
+//! [`CompiledGraph::record`] puts everything into one encoder, for graphs that are all on the one queue.
+//! [`CompiledGraph::execute`] submits each of [`CompiledGraph::schedule`]'s submissions on its own queue, waiting on
+//! the ones before it with semaphores. A texture going between the graphics and compute queues is released at the
+//! end of the submission that last used it and acquired before the pass that uses it next. Buffers are shared
+//! between the queues, so only need the usual barrier.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{
+    CompiledGraph, PassId, Queue, ResourceId,
+    barriers::{Barrier, BarrierCounts, BarrierStats, PassBarriers},
+    transients::Transients,
+};
+use crate::render::hal::{CommandEncoder, Device, TextureState};
+
+enum Imported<'a, D: Device> {
+    Texture {
+        texture: &'a D::Texture,
+        state: TextureState,
+        end: Option<TextureState>,
+    },
+    Buffer(&'a D::Buffer),
+}
+
+/// What a graph's resources are on a device: its transients, and what stands in for each imported resource.
+pub struct GraphResources<'a, D: Device> {
+    transients: &'a Transients<D>,
+    imported: BTreeMap<ResourceId, Imported<'a, D>>,
+}
+
+/// Whatever a state is tracked for: a made transient, by [`CompiledGraph::aliases`] numbering, or an import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Slot {
+    Made(usize),
+    Imported(ResourceId),
+}
+
+impl<'a, D: Device> GraphResources<'a, D> {
+    pub fn new(transients: &'a Transients<D>) -> GraphResources<'a, D> {
+        GraphResources {
+            transients,
+            imported: BTreeMap::new(),
+        }
+    }
+
+    /// Use `texture`, in `state`, for the imported `resource`. It's left in `end` after the graph, if that's given,
+    /// or whatever its last pass needed otherwise.
+    pub fn import_texture(
+        mut self,
+        resource: ResourceId,
+        texture: &'a D::Texture,
+        state: TextureState,
+        end: Option<TextureState>,
+    ) -> Self {
+        self.imported.insert(
+            resource,
+            Imported::Texture {
+                texture,
+                state,
+                end,
+            },
+        );
+        return self;
+    }
+
+    pub fn import_buffer(mut self, resource: ResourceId, buffer: &'a D::Buffer) -> Self {
+        self.imported.insert(resource, Imported::Buffer(buffer));
+        return self;
+    }
+
+    pub fn texture(&self, resource: ResourceId) -> Option<&'a D::Texture> {
+        match self.imported.get(&resource) {
+            Some(Imported::Texture { texture, .. }) => Some(*texture),
+            Some(Imported::Buffer(_)) => None,
+            None => self.transients.texture(resource),
+        }
+    }
+
+    pub fn buffer(&self, resource: ResourceId) -> Option<&'a D::Buffer> {
+        match self.imported.get(&resource) {
+            Some(Imported::Buffer(buffer)) => Some(*buffer),
+            Some(Imported::Texture { .. }) => None,
+            None => self.transients.buffer(resource),
+        }
+    }
+
+    fn slot(&self, resource: ResourceId) -> Option<Slot> {
+        if self.imported.contains_key(&resource) {
+            return Some(Slot::Imported(resource));
+        }
+        return self.transients.alias(resource).map(Slot::Made);
+    }
+
+    fn initial_state(&self, slot: Slot) -> TextureState {
+        match slot {
+            Slot::Imported(resource) => match self.imported.get(&resource) {
+                Some(Imported::Texture { state, .. }) => *state,
+                _ => TextureState::Undefined,
+            },
+            Slot::Made(_) => TextureState::Undefined,
+        }
+    }
+}
+
+/// Where recording's got to, carried from pass to pass and, for [`CompiledGraph::execute`], from one submission's
+/// encoder to the next.
+struct Recording<'r, 'a, D: Device> {
+    graph: &'r CompiledGraph,
+    resources: &'r GraphResources<'a, D>,
+    states: BTreeMap<Slot, TextureState>,
+    /// Whether each slot was last used on the compute queue.
+    compute: BTreeMap<Slot, bool>,
+    // Resources that have had a pass, and what they're made as, for buffers that share.
+    started: BTreeSet<ResourceId>,
+    touched: BTreeSet<Slot>,
+    /// Textures one queue has given up for the other's next pass to acquire, and the states they went from.
+    released: BTreeMap<ResourceId, TextureState>,
+    stats: BarrierStats,
+}
+
+impl<'r, 'a, D: Device> Recording<'r, 'a, D> {
+    fn new(graph: &'r CompiledGraph, resources: &'r GraphResources<'a, D>) -> Recording<'r, 'a, D> {
+        Recording {
+            graph,
+            resources,
+            states: BTreeMap::new(),
+            compute: BTreeMap::new(),
+            started: BTreeSet::new(),
+            touched: BTreeSet::new(),
+            released: BTreeMap::new(),
+            stats: BarrierStats::default(),
+        }
+    }
+
+    fn state(&mut self, slot: Slot) -> TextureState {
+        let resources = self.resources;
+        return *self
+            .states
+            .entry(slot)
+            .or_insert_with(|| resources.initial_state(slot));
+    }
+
+    /// Put in what `barriers` asks for, then have `record` record the pass.
+    fn pass<'e>(
+        &mut self,
+        encoder: &mut D::Encoder<'e>,
+        barriers: &PassBarriers,
+        record: &mut impl FnMut(PassId, &mut D::Encoder<'e>, &GraphResources<'_, D>),
+    ) {
+        let (graph, resources) = (&self.graph.graph, self.resources);
+        let pass = graph.pass(barriers.pass);
+        let compute = pass.queue() == Queue::Compute;
+        let mut recorded = PassBarriers {
+            pass: barriers.pass,
+            counts: BarrierCounts::default(),
+            barriers: Vec::new(),
+        };
+        for &(resource, access) in pass.uses() {
+            let Some(slot) = resources.slot(resource) else {
+                log::error!(
+                    "Render graph: nothing stands in for {}, used by {}",
+                    graph.resource(resource).name,
+                    pass.name()
+                );
+                continue;
+            };
+            // A shared buffer's first use here still has to wait on whoever had it before.
+            if self.started.insert(resource)
+                && !self.touched.insert(slot)
+                && let Some(buffer) = resources.buffer(resource)
+            {
+                encoder.buffer_barrier(buffer);
+                recorded.push(Barrier {
+                    resource,
+                    from: None,
+                    to: (access, pass.queue()),
+                    image: false,
+                    layout_transition: false,
+                    queue_transfer: false,
+                });
+            }
+        }
+
+        for barrier in &barriers.barriers {
+            let Some(slot) = resources.slot(barrier.resource) else {
+                continue;
+            };
+            if let Some(texture) = resources.texture(barrier.resource) {
+                let Some(to) = barrier.to.0.texture_state() else {
+                    continue;
+                };
+                let (from, queue_transfer) = match self.released.remove(&barrier.resource) {
+                    Some(from) => (from, encoder.acquire_texture(texture, from, to)),
+                    None => {
+                        // A transient the other queue had for something else. Its ownership goes with its
+                        // contents, and the submissions are already in order.
+                        let other = self.compute.get(&slot).is_some_and(|&c| c != compute);
+                        let from = match barrier.from.is_none() && other {
+                            true => TextureState::Undefined,
+                            false => self.state(slot),
+                        };
+                        encoder.transition(texture, from, to);
+                        (from, false)
+                    }
+                };
+                self.states.insert(slot, to);
+                recorded.push(Barrier {
+                    image: true,
+                    layout_transition: from != to,
+                    queue_transfer,
+                    ..*barrier
+                });
+            } else if let Some(buffer) = resources.buffer(barrier.resource)
+                && barrier.from.is_some()
+            {
+                encoder.buffer_barrier(buffer);
+                recorded.push(Barrier {
+                    image: false,
+                    layout_transition: false,
+                    queue_transfer: false,
+                    ..*barrier
+                });
+            }
+        }
+
+        for &(resource, _) in pass.uses() {
+            if let Some(slot) = resources.slot(resource) {
+                self.compute.insert(slot, compute);
+            }
+        }
+        record(barriers.pass, encoder, self.resources);
+        self.stats.passes.push(recorded);
+    }
+
+    /// Give `resource` up to the other queue, moving it into `to` for the pass there that uses it next.
+    fn release(&mut self, encoder: &mut D::Encoder<'_>, resource: ResourceId, to: TextureState) {
+        let (Some(slot), Some(texture)) = (
+            self.resources.slot(resource),
+            self.resources.texture(resource),
+        ) else {
+            return;
+        };
+        let from = self.state(slot);
+        encoder.release_texture(texture, from, to);
+        self.states.insert(slot, to);
+        self.released.insert(resource, from);
+    }
+
+    /// Move the imports `done` with into their end states.
+    fn finish(&mut self, encoder: &mut D::Encoder<'_>, done: impl Fn(ResourceId) -> bool) {
+        for (&resource, imported) in &self.resources.imported {
+            let Imported::Texture {
+                texture,
+                state,
+                end: Some(end),
+            } = imported
+            else {
+                continue;
+            };
+            if !done(resource) {
+                continue;
+            }
+            let from = self
+                .states
+                .get(&Slot::Imported(resource))
+                .copied()
+                .unwrap_or(*state);
+            if from != *end {
+                encoder.transition(texture, from, *end);
+                // No pass's use, so only counted.
+                if let Some(last) = self.stats.passes.last_mut() {
+                    last.counts.image_barriers += 1;
+                    last.counts.layout_transitions += 1;
+                }
+            }
+        }
+    }
+}
+
+/// What [`CompiledGraph::execute`] submitted. It all has to be waited on with [`Executed::wait`] before anything the
+/// graph used is touched again.
+pub struct Executed<D: Device> {
+    fences: Vec<D::Fence>,
+    semaphores: Vec<D::Semaphore>,
+    stats: BarrierStats,
+}
+
+impl<D: Device> Executed<D> {
+    /// Block until every submission is done, and free the semaphores between them. Returns the barriers recorded,
+    /// as [`CompiledGraph::record`] does.
+    pub fn wait(self, device: &D) -> Result<BarrierStats, D::Error> {
+        let Executed {
+            fences,
+            semaphores,
+            stats,
+        } = self;
+        let mut result = Ok(());
+        for fence in fences {
+            result = result.and(device.wait(fence));
+        }
+        if result.is_err() {
+            device.wait_idle();
+        }
+        for semaphore in semaphores {
+            // SAFETY: Everything submitted that used it is done.
+            unsafe { device.destroy_semaphore(semaphore) };
+        }
+        return result.map(|_| stats);
+    }
+
+    /// Wait out what was submitted before `error`, and hand it back.
+    fn abandon(self, device: &D, error: D::Error) -> D::Error {
+        let _ = self.wait(device);
+        return error;
+    }
+}
+
+impl CompiledGraph {
+    /// Record every pass into `encoder` in order, calling `record` for each once its resources are ready for it.
+    /// Resources the graph has no stand in for are skipped, and logged. Returns the barriers recorded, with the
+    /// imports' moves into their end states put down to the last pass.
+    pub fn record<'e, D: Device>(
+        &self,
+        encoder: &mut D::Encoder<'e>,
+        resources: &GraphResources<'_, D>,
+        mut record: impl FnMut(PassId, &mut D::Encoder<'e>, &GraphResources<'_, D>),
+    ) -> BarrierStats {
+        let mut recording = Recording::new(self, resources);
+        for barriers in self.barriers().passes {
+            recording.pass(encoder, &barriers, &mut record);
+        }
+        recording.finish(encoder, |_| true);
+        return recording.stats;
+    }
+
+    /// Record and submit every pass a submission at a time, as [`CompiledGraph::schedule`] splits them, each to its
+    /// own queue: [`Queue::Compute`]'s to [`Device::begin_compute_commands`], the rest to
+    /// [`Device::begin_commands`]. Otherwise like [`CompiledGraph::record`], with the imports' moves into their end
+    /// states going at the end of the submission that last used them.
+    pub fn execute<'d, D: Device>(
+        &self,
+        device: &'d D,
+        resources: &GraphResources<'_, D>,
+        mut record: impl FnMut(PassId, &mut D::Encoder<'d>, &GraphResources<'_, D>),
+    ) -> Result<Executed<D>, D::Error> {
+        let graph = &self.graph;
+        let schedule = self.schedule();
+        let barriers = self.barriers().passes;
+        let handovers = self.handovers();
+        let compute = |pass: PassId| graph.pass(pass).queue() == Queue::Compute;
+        // Which submission each pass went in, and the last to use each resource.
+        let mut placed = vec![None; graph.passes.len()];
+        let mut last = vec![None; graph.resources.len()];
+        for (index, submission) in schedule.iter().enumerate() {
+            for &pass in &submission.passes {
+                placed[pass.0] = Some(index);
+                for &(resource, _) in graph.pass(pass).uses() {
+                    last[resource.0] = Some(index);
+                }
+            }
+        }
+
+        let mut executed = Executed {
+            fences: Vec::new(),
+            semaphores: Vec::new(),
+            stats: BarrierStats::default(),
+        };
+        // Each submission's semaphore in `executed`, if it signals one.
+        let mut signals: Vec<Option<usize>> = Vec::new();
+        let mut recording = Recording::new(self, resources);
+        for (index, submission) in schedule.iter().enumerate() {
+            let encoder = match submission.queue {
+                Queue::Compute => device.begin_compute_commands(),
+                Queue::Graphics | Queue::Transfer => device.begin_commands(),
+            };
+            let mut encoder = match encoder {
+                Ok(encoder) => encoder,
+                Err(e) => return Err(executed.abandon(device, e)),
+            };
+            for &pass in &submission.passes {
+                let Some(barriers) = barriers.iter().find(|b| b.pass == pass) else {
+                    continue;
+                };
+                recording.pass(&mut encoder, barriers, &mut record);
+            }
+
+            // Hand over what the other queue's going to use next. The graph's transfer queue is graphics to hal.
+            for &(before, after, resource) in &handovers {
+                if placed[before.0] != Some(index) || compute(before) == compute(after) {
+                    continue;
+                }
+                let to = barriers
+                    .iter()
+                    .filter(|b| b.pass == after)
+                    .flat_map(|b| &b.barriers)
+                    .find(|b| b.resource == resource)
+                    .and_then(|b| b.to.0.texture_state());
+                if let Some(to) = to {
+                    recording.release(&mut encoder, resource, to);
+                }
+            }
+            recording.finish(&mut encoder, |resource| last[resource.0] == Some(index));
+
+            let signal = match submission.signals {
+                true => match device.create_semaphore() {
+                    Ok(semaphore) => Some(semaphore),
+                    Err(e) => return Err(executed.abandon(device, e)),
+                },
+                false => None,
+            };
+            let waits: Vec<&D::Semaphore> = submission
+                .waits
+                .iter()
+                .filter_map(|&i| signals[i].map(|s| &executed.semaphores[s]))
+                .collect();
+            let fence = device.submit_with(encoder, &waits, signal.as_ref());
+            drop(waits);
+            match fence {
+                Ok(fence) => executed.fences.push(fence),
+                Err(e) => {
+                    if let Some(semaphore) = signal {
+                        // SAFETY: Never submitted.
+                        unsafe { device.destroy_semaphore(semaphore) };
+                    }
+                    return Err(executed.abandon(device, e));
+                }
+            }
+            signals.push(signal.map(|semaphore| {
+                executed.semaphores.push(semaphore);
+                executed.semaphores.len() - 1
+            }));
+        }
+        executed.stats = recording.stats;
+        return Ok(executed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GraphResources;
+    use crate::{
+        render::{
+            graph::{
+                Access, Pass, Queue, RenderGraph, barriers::BarrierCounts, transients::Transients,
+            },
+            hal::{
+                BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+                TextureFormat, TextureUsage,
+            },
+        },
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn records_passes_in_order() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+
+        let mut graph = RenderGraph::new();
+        let upload = graph.import("upload");
+        let between = graph.import("between");
+        let readback = graph.import("readback");
+        graph.mark_output(readback);
+        let desc = TextureDesc {
+            width: 4,
+            height: 4,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+            samples: 1,
+        };
+        let first = graph.add_texture("first", desc);
+        let second = graph.add_texture("second", desc);
+        // Added out of order, the graph sorts it out. Copies through two textures, which can share one.
+        let read = graph.add_pass(
+            Pass::new("read")
+                .with_access(second, Access::CopySrc)
+                .with_access(readback, Access::CopyDst),
+        );
+        let relay = graph.add_pass(
+            Pass::new("relay")
+                .with_access(first, Access::CopySrc)
+                .with_access(between, Access::CopyDst),
+        );
+        let fill = graph.add_pass(
+            Pass::new("fill")
+                .with_access(upload, Access::CopySrc)
+                .with_access(first, Access::CopyDst),
+        );
+        let refill = graph.add_pass(
+            Pass::new("refill")
+                .with_access(between, Access::CopySrc)
+                .with_access(second, Access::CopyDst),
+        );
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[fill, relay, refill, read]);
+
+        let buffer = |usage, location| {
+            device
+                .create_buffer(&BufferDesc {
+                    size: 64,
+                    usage,
+                    location,
+                })
+                .unwrap()
+        };
+        let upload_buffer = buffer(BufferUsage::COPY_SRC, MemoryLocation::Upload);
+        let between_buffer = buffer(
+            BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+            MemoryLocation::Device,
+        );
+        let readback_buffer = buffer(BufferUsage::COPY_DST, MemoryLocation::Readback);
+        let transients = Transients::create(device, &compiled).unwrap();
+        assert_eq!(transients.len(), 1);
+        let pixels: Vec<u8> = (0..64).collect();
+
+        // SAFETY: Everything is waited on before it's read or destroyed.
+        unsafe {
+            device.write_buffer(&upload_buffer, 0, &pixels).unwrap();
+            let resources = GraphResources::new(&transients)
+                .import_buffer(upload, &upload_buffer)
+                .import_buffer(between, &between_buffer)
+                .import_buffer(readback, &readback_buffer);
+            let mut cmds = device.begin_commands().unwrap();
+            let mut ran = Vec::new();
+            let recorded = compiled.record(&mut cmds, &resources, |pass, cmds, resources| {
+                ran.push(pass);
+                let p = compiled.graph().pass(pass);
+                let texture = p.uses().iter().find_map(|(r, _)| resources.texture(*r));
+                let buffer = p.uses().iter().find_map(|(r, _)| resources.buffer(*r));
+                let (texture, buffer) = (texture.unwrap(), buffer.unwrap());
+                if pass == relay || pass == read {
+                    cmds.copy_texture_to_buffer(texture, buffer);
+                } else {
+                    cmds.copy_buffer_to_texture(buffer, texture);
+                }
+            });
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+            assert_eq!(ran, compiled.order());
+            // A move into each pass's texture, and the one wait on the buffer copied through. The imports are
+            // buffers, so there's nothing to move them out of.
+            assert_eq!(
+                recorded.total(),
+                BarrierCounts {
+                    image_barriers: 4,
+                    buffer_barriers: 1,
+                    layout_transitions: 4,
+                    queue_transfers: 0,
+                }
+            );
+
+            let mut out = vec![0; 64];
+            device.read_buffer(&readback_buffer, 0, &mut out).unwrap();
+            assert_eq!(out, pixels);
+
+            transients.destroy(device);
+            device.destroy_buffer(upload_buffer);
+            device.destroy_buffer(between_buffer);
+            device.destroy_buffer(readback_buffer);
+        }
+    }
+
+    #[test]
+    pub fn hands_textures_between_queues() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+
+        let mut graph = RenderGraph::new();
+        let upload = graph.import("upload");
+        let between = graph.import("between");
+        let readback = graph.import("readback");
+        graph.mark_output(readback);
+        let desc = TextureDesc {
+            width: 4,
+            height: 4,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+            samples: 1,
+        };
+        let first = graph.add_texture("first", desc);
+        let second = graph.add_texture("second", desc);
+        // The same copies through two textures, with the first copied out on the compute queue.
+        let fill = graph.add_pass(
+            Pass::new("fill")
+                .with_access(upload, Access::CopySrc)
+                .with_access(first, Access::CopyDst),
+        );
+        let relay = graph.add_pass(
+            Pass::new("relay")
+                .with_queue(Queue::Compute)
+                .with_access(first, Access::CopySrc)
+                .with_access(between, Access::CopyDst),
+        );
+        let refill = graph.add_pass(
+            Pass::new("refill")
+                .with_access(between, Access::CopySrc)
+                .with_access(second, Access::CopyDst),
+        );
+        let read = graph.add_pass(
+            Pass::new("read")
+                .with_access(second, Access::CopySrc)
+                .with_access(readback, Access::CopyDst),
+        );
+        let compiled = graph.compile();
+        assert_eq!(compiled.order(), &[fill, relay, refill, read]);
+        assert_eq!(compiled.schedule().len(), 3);
+
+        let buffer = |usage, location| {
+            device
+                .create_buffer(&BufferDesc {
+                    size: 64,
+                    usage,
+                    location,
+                })
+                .unwrap()
+        };
+        let upload_buffer = buffer(BufferUsage::COPY_SRC, MemoryLocation::Upload);
+        let between_buffer = buffer(
+            BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+            MemoryLocation::Device,
+        );
+        let readback_buffer = buffer(BufferUsage::COPY_DST, MemoryLocation::Readback);
+        let transients = Transients::create(device, &compiled).unwrap();
+        let pixels: Vec<u8> = (0..64).map(|i| 255 - i).collect();
+
+        // SAFETY: Everything is waited on before it's read or destroyed.
+        unsafe {
+            device.write_buffer(&upload_buffer, 0, &pixels).unwrap();
+            let resources = GraphResources::new(&transients)
+                .import_buffer(upload, &upload_buffer)
+                .import_buffer(between, &between_buffer)
+                .import_buffer(readback, &readback_buffer);
+            let mut ran = Vec::new();
+            let executed = compiled.execute(device, &resources, |pass, cmds, resources| {
+                ran.push(pass);
+                let p = compiled.graph().pass(pass);
+                let texture = p.uses().iter().find_map(|(r, _)| resources.texture(*r));
+                let buffer = p.uses().iter().find_map(|(r, _)| resources.buffer(*r));
+                let (texture, buffer) = (texture.unwrap(), buffer.unwrap());
+                if pass == relay || pass == read {
+                    cmds.copy_texture_to_buffer(texture, buffer);
+                } else {
+                    cmds.copy_buffer_to_texture(buffer, texture);
+                }
+            });
+            let recorded = executed.unwrap().wait(device).unwrap();
+            assert_eq!(ran, compiled.order());
+            // The first texture only changes hands if there's a compute queue to hand it to.
+            let transfers = device.compute_queue().is_some() as u32;
+            assert_eq!(recorded.total().queue_transfers, transfers);
+            assert_eq!(recorded.total().layout_transitions, 4);
+
+            let mut out = vec![0; 64];
+            device.read_buffer(&readback_buffer, 0, &mut out).unwrap();
+            assert_eq!(out, pixels);
+
+            transients.destroy(device);
+            device.destroy_buffer(upload_buffer);
+            device.destroy_buffer(between_buffer);
+            device.destroy_buffer(readback_buffer);
+        }
+    }
+}
@@ -0,0 +1,470 @@
+//! Reading any texture in the graph back to the CPU, like the gbuffer, a shadow map or SSAO, to look at without an
+//! external graphics debugger.
+//!
+//! Requests are by name, since that's what the debug UI has and graphs get rebuilt every frame. The copy goes in
+//! right after the last pass that writes the texture, so it sees the finished contents, and the texture goes back to
+//! the state that pass left it in so later passes don't notice. Multisampled colour is resolved into a texture of
+//! its own to copy from, multisampled depth can't be. Formats the CPU can't show directly get converted when the
+//! frame is written out, see [`CapturedFrame::to_srgb8`].
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::{CompiledGraph, PassId, ResourceId};
+use crate::{
+    capture::{CaptureFormat, CapturedFrame},
+    render::hal::{
+        BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+        TextureFormat, TextureState, TextureUsage,
+    },
+};
+
+/// Names of textures to read back, from the debug UI to whoever runs the graph.
+#[derive(Clone, Default)]
+pub struct ReadbackRequests {
+    names: Arc<Mutex<Vec<String>>>,
+}
+
+impl ReadbackRequests {
+    pub fn new() -> ReadbackRequests {
+        ReadbackRequests::default()
+    }
+
+    /// Read `name` back on the next frame.
+    pub fn request(&self, name: &str) {
+        let mut names = self.names.lock().unwrap();
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    /// Everything asked for since the last take.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.names.lock().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.lock().unwrap().is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadbackError {
+    NotFound,
+    /// Buffers, and imported resources the graph wasn't given a texture for, can't be read back this way.
+    NotTexture,
+    /// Made without [`TextureUsage::COPY_SRC`].
+    NotCopyable,
+    /// No pass writes it, so there's nothing to see.
+    NeverWritten,
+    /// Left in a state that can't be copied from and back, like a storage image.
+    Unsupported,
+    /// Block compressed, which captures don't decode.
+    Compressed,
+    /// Multisampled depth, which there's no resolving by copying.
+    MultisampledDepth,
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ReadbackError::NotFound => "no resource by that name",
+            ReadbackError::NotTexture => "not a texture",
+            ReadbackError::NotCopyable => "not created with COPY_SRC usage",
+            ReadbackError::NeverWritten => "no pass writes it",
+            ReadbackError::Unsupported => "its last use can't be copied from",
+            ReadbackError::Compressed => "block compressed",
+            ReadbackError::MultisampledDepth => "multisampled depth",
+        };
+        return write!(f, "{msg}");
+    }
+}
+
+/// Where and how to read a texture back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadbackPoint {
+    pub resource: ResourceId,
+    /// The copy goes in after this pass.
+    pub after: PassId,
+    pub desc: TextureDesc,
+    /// The state the texture is in after `after`, and has to go back to.
+    pub state: TextureState,
+}
+
+/// How a texture format comes back to the CPU. Block compressed formats don't.
+pub fn capture_format(format: TextureFormat) -> Option<CaptureFormat> {
+    let format = match format {
+        TextureFormat::Rgba8Unorm => CaptureFormat::Rgba8Unorm,
+        TextureFormat::Rgba8Srgb => CaptureFormat::Rgba8Srgb,
+        TextureFormat::Bgra8Srgb => CaptureFormat::Bgra8Srgb,
+        TextureFormat::Rgba16Float => CaptureFormat::Rgba16Float,
+        TextureFormat::Rg16Float => CaptureFormat::Rg16Float,
+        TextureFormat::Depth32Float | TextureFormat::Depth32FloatStencil8 => {
+            CaptureFormat::Depth32Float
+        }
+        TextureFormat::Depth24Stencil8 => CaptureFormat::Depth24Unorm,
+        _ => return None,
+    };
+    return Some(format);
+}
+
+impl CompiledGraph {
+    /// Where to read back the texture called `name`.
+    pub fn readback_point(&self, name: &str) -> Result<ReadbackPoint, ReadbackError> {
+        let graph = &self.graph;
+        let resource = graph
+            .resources()
+            .find(|&r| graph.resource(r).name == name)
+            .ok_or(ReadbackError::NotFound)?;
+        let Some(desc) = graph.resource(resource).kind.texture_desc() else {
+            return Err(ReadbackError::NotTexture);
+        };
+        if !desc.usage.contains(TextureUsage::COPY_SRC) {
+            return Err(ReadbackError::NotCopyable);
+        }
+        if capture_format(desc.format).is_none() {
+            return Err(ReadbackError::Compressed);
+        }
+        if desc.samples > 1 && desc.format.is_depth() {
+            return Err(ReadbackError::MultisampledDepth);
+        }
+
+        // The last use of the last pass that writes it, reads after that don't change anything.
+        let mut point = None;
+        for &pass in &self.order {
+            let uses = graph
+                .pass(pass)
+                .uses()
+                .iter()
+                .filter(|(r, _)| *r == resource);
+            let Some(&(_, last)) = uses.clone().next_back() else {
+                continue;
+            };
+            if uses.clone().any(|(_, access)| access.writes()) {
+                point = Some((pass, last));
+            }
+        }
+        let (after, access) = point.ok_or(ReadbackError::NeverWritten)?;
+        let state = access.texture_state().ok_or(ReadbackError::Unsupported)?;
+        return Ok(ReadbackPoint {
+            resource,
+            after,
+            desc,
+            state,
+        });
+    }
+}
+
+/// A copy recorded into a frame, waiting for the GPU to finish it.
+pub struct PendingReadback<D: Device> {
+    pub name: String,
+    buffer: D::Buffer,
+    /// What a multisampled texture was resolved into to copy from.
+    resolved: Option<D::Texture>,
+    desc: TextureDesc,
+    frame: u64,
+}
+
+impl<D: Device> PendingReadback<D> {
+    /// Record a copy of `texture`, at `point`, into `encoder`. `frame` goes on the captured frame.
+    pub fn record(
+        device: &D,
+        encoder: &mut D::Encoder<'_>,
+        name: &str,
+        point: &ReadbackPoint,
+        texture: &D::Texture,
+        frame: u64,
+    ) -> Result<PendingReadback<D>, D::Error> {
+        let desc = point.desc;
+        let buffer = device.create_buffer(&BufferDesc {
+            size: desc.format.size(desc.width, desc.height),
+            usage: BufferUsage::COPY_DST,
+            location: MemoryLocation::Readback,
+        })?;
+        let resolved = match desc.samples > 1 {
+            true => {
+                let resolved = device.create_texture(&TextureDesc {
+                    usage: TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+                    samples: 1,
+                    ..desc
+                });
+                match resolved {
+                    Ok(resolved) => Some(resolved),
+                    Err(e) => {
+                        // SAFETY: Never recorded into anything.
+                        unsafe { device.destroy_buffer(buffer) };
+                        return Err(e);
+                    }
+                }
+            }
+            false => None,
+        };
+
+        if point.state != TextureState::CopySrc {
+            encoder.transition(texture, point.state, TextureState::CopySrc);
+        }
+        match &resolved {
+            Some(resolved) => {
+                encoder.transition(resolved, TextureState::Undefined, TextureState::CopyDst);
+                encoder.resolve_texture(texture, resolved);
+                encoder.transition(resolved, TextureState::CopyDst, TextureState::CopySrc);
+                encoder.copy_texture_to_buffer(resolved, &buffer);
+            }
+            None => encoder.copy_texture_to_buffer(texture, &buffer),
+        }
+        if point.state != TextureState::CopySrc {
+            encoder.transition(texture, TextureState::CopySrc, point.state);
+        }
+        return Ok(PendingReadback {
+            name: name.to_string(),
+            buffer,
+            resolved,
+            desc,
+            frame,
+        });
+    }
+
+    /// Read the copy out, freeing its buffer.
+    ///
+    /// # Safety
+    /// The submission it was recorded into must have finished.
+    pub unsafe fn finish(self, device: &D) -> Result<CapturedFrame, D::Error> {
+        let mut data = vec![0; self.desc.format.size(self.desc.width, self.desc.height) as usize];
+        // SAFETY: The caller vouches the GPU is done with the buffer.
+        let res = unsafe { device.read_buffer(&self.buffer, 0, &mut data) };
+        // SAFETY: As above.
+        unsafe { device.destroy_buffer(self.buffer) };
+        if let Some(resolved) = self.resolved {
+            // SAFETY: As above.
+            unsafe { device.destroy_texture(resolved) };
+        }
+        res?;
+        return Ok(CapturedFrame {
+            width: self.desc.width,
+            height: self.desc.height,
+            format: capture_format(self.desc.format).expect("Readback points are never compressed"),
+            data,
+            frame: self.frame,
+        });
+    }
+
+    /// Throw the copy away without reading it.
+    ///
+    /// # Safety
+    /// The GPU must be done with it, or never have been given it.
+    pub unsafe fn destroy(self, device: &D) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            device.destroy_buffer(self.buffer);
+            if let Some(resolved) = self.resolved {
+                device.destroy_texture(resolved);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PendingReadback, ReadbackError, ReadbackRequests};
+    use crate::{
+        capture::CaptureFormat,
+        color::LinearColor,
+        render::{
+            graph::{Access, Pass, RenderGraph},
+            hal::{
+                BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+                TextureFormat, TextureState, TextureUsage,
+            },
+        },
+        test_support::headless,
+    };
+
+    fn graph() -> RenderGraph {
+        let mut graph = RenderGraph::new();
+        let desc = |format, usage| TextureDesc {
+            width: 4,
+            height: 4,
+            format,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED | usage,
+            samples: 1,
+        };
+        let swapchain = graph.swapchain();
+        let depth = graph.add_texture(
+            "depth",
+            desc(TextureFormat::Depth32Float, TextureUsage::COPY_SRC),
+        );
+        let albedo = graph.add_texture(
+            "albedo",
+            desc(TextureFormat::Rgba8Srgb, TextureUsage::COPY_SRC),
+        );
+        let hdr = graph.add_texture(
+            "hdr",
+            desc(TextureFormat::Rgba16Float, TextureUsage::default()),
+        );
+        graph.add_texture(
+            "unused",
+            desc(TextureFormat::Rgba8Unorm, TextureUsage::COPY_SRC),
+        );
+        let multisampled = |format| TextureDesc {
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::COPY_SRC,
+            samples: 4,
+            ..desc(format, TextureUsage::default())
+        };
+        let msaa_color =
+            graph.import_texture("msaa colour", multisampled(TextureFormat::Rgba16Float));
+        let msaa_depth =
+            graph.import_texture("msaa depth", multisampled(TextureFormat::Depth32Float));
+        let resolved = graph.add_texture(
+            "resolved",
+            desc(TextureFormat::Rgba16Float, TextureUsage::default()),
+        );
+        graph.add_pass(
+            Pass::new("msaa")
+                .with_access(msaa_color, Access::RenderTarget)
+                .with_access(msaa_depth, Access::RenderTarget)
+                .with_access(resolved, Access::RenderTarget),
+        );
+
+        graph.add_pass(
+            Pass::new("gbuffer")
+                .with_access(depth, Access::RenderTarget)
+                .with_access(albedo, Access::RenderTarget),
+        );
+        graph.add_pass(
+            Pass::new("lighting")
+                .with_access(depth, Access::ShaderRead)
+                .with_access(albedo, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+        return graph;
+    }
+
+    #[test]
+    pub fn finds_readback_points() {
+        let compiled = graph().compile();
+        let graph = compiled.graph();
+        let gbuffer = graph.passes().find(|p| graph.pass(*p).name() == "gbuffer");
+
+        // Read right after the gbuffer pass, before lighting samples it.
+        let depth = compiled.readback_point("depth").unwrap();
+        assert_eq!(Some(depth.after), gbuffer);
+        assert_eq!(depth.state, TextureState::RenderTarget);
+
+        assert_eq!(
+            compiled.readback_point("hdr"),
+            Err(ReadbackError::NotCopyable)
+        );
+        assert_eq!(
+            compiled.readback_point("unused"),
+            Err(ReadbackError::NeverWritten)
+        );
+        assert_eq!(
+            compiled.readback_point("swapchain"),
+            Err(ReadbackError::NotTexture)
+        );
+        assert_eq!(
+            compiled.readback_point("nope"),
+            Err(ReadbackError::NotFound)
+        );
+        // Imported with what they were made as, so the colour can be read back once resolved.
+        let msaa = compiled.readback_point("msaa colour").unwrap();
+        assert_eq!(msaa.desc.samples, 4);
+        assert_eq!(
+            compiled.readback_point("msaa depth"),
+            Err(ReadbackError::MultisampledDepth)
+        );
+
+        let requests = ReadbackRequests::new();
+        requests.request("depth");
+        requests.request("depth");
+        assert_eq!(requests.take(), vec!["depth".to_string()]);
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    pub fn reads_back_from_device() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let compiled = graph().compile();
+        let point = compiled.readback_point("albedo").unwrap();
+
+        let texture = device
+            .create_texture(&TextureDesc {
+                usage: point.desc.usage | TextureUsage::COPY_DST,
+                ..point.desc
+            })
+            .unwrap();
+        let upload = device
+            .create_buffer(&BufferDesc {
+                size: 4 * 4 * 4,
+                usage: BufferUsage::COPY_SRC,
+                location: MemoryLocation::Upload,
+            })
+            .unwrap();
+        let pixels: Vec<u8> = (0..64).collect();
+
+        // SAFETY: Everything is waited on before it's read or destroyed.
+        unsafe {
+            device.write_buffer(&upload, 0, &pixels).unwrap();
+            let mut cmds = device.begin_commands().unwrap();
+            // Stand in for the gbuffer pass.
+            cmds.transition(&texture, TextureState::Undefined, TextureState::CopyDst);
+            cmds.copy_buffer_to_texture(&upload, &texture);
+            cmds.transition(&texture, TextureState::CopyDst, point.state);
+            let pending =
+                PendingReadback::record(device, &mut cmds, "albedo", &point, &texture, 7).unwrap();
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+
+            let frame = pending.finish(device).unwrap();
+            assert_eq!(frame.format, CaptureFormat::Rgba8Srgb);
+            assert_eq!(frame.frame, 7);
+            assert_eq!(frame.data, pixels);
+            device.destroy_texture(texture);
+            device.destroy_buffer(upload);
+        }
+    }
+
+    #[test]
+    pub fn resolves_multisampled_before_reading_back() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let compiled = graph().compile();
+        let point = compiled.readback_point("msaa colour").unwrap();
+        let texture = device.create_texture(&point.desc).unwrap();
+
+        // SAFETY: Everything is waited on before it's read or destroyed.
+        unsafe {
+            let mut cmds = device.begin_commands().unwrap();
+            // Stand in for the pass drawing it, cleared to one colour so every sample agrees.
+            cmds.transition(
+                &texture,
+                TextureState::Undefined,
+                TextureState::RenderTarget,
+            );
+            cmds.begin_pass(&texture, Some(LinearColor::RED));
+            cmds.end_pass();
+            let pending =
+                PendingReadback::record(device, &mut cmds, "msaa colour", &point, &texture, 3)
+                    .unwrap();
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+
+            let frame = pending.finish(device).unwrap();
+            assert_eq!(frame.format, CaptureFormat::Rgba16Float);
+            assert_eq!((frame.width, frame.height), (4, 4));
+            let red = frame.linear_rgba()[0];
+            assert_eq!(red, [1.0, 0.0, 0.0, 1.0]);
+            device.destroy_texture(texture);
+        }
+    }
+}
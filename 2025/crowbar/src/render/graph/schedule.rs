@@ -0,0 +1,335 @@
+//! Splitting a compiled graph into queue submissions, so compute passes run on the async compute queue alongside
+//! graphics work rather than in between it.
+//!
+//! Passes go into a submission on their own queue in the order they run. Where a pass depends on a pass from
+//! another queue, that pass's submission gets cut off to signal a semaphore, and the waiting pass starts a new
+//! submission that waits on it. Dependencies on the same queue are left to submission order and barriers. Binary
+//! semaphores can only be waited on once, and a queue that's waited on a submission is already past everything
+//! before it on that queue, so a queue never waits on what it's already waited on. A resource going from one queue
+//! to another counts as a dependency even between reads, since the queue that had it has to give it up first.
+//! [`CompiledGraph::execute`] submits them.
+
+use super::{CompiledGraph, PassId, Queue, ResourceId};
+
+/// A batch of passes for one queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Submission {
+    pub queue: Queue,
+    pub passes: Vec<PassId>,
+    /// Earlier submissions, on other queues, that have to signal before this one starts.
+    pub waits: Vec<usize>,
+    /// Something on another queue waits on this, so it signals a semaphore.
+    pub signals: bool,
+}
+
+impl CompiledGraph {
+    /// Every resource a pass takes over from another queue, as `(before, after, resource)`: the last pass to use it
+    /// before `after`, which is on another queue.
+    pub(super) fn handovers(&self) -> Vec<(PassId, PassId, ResourceId)> {
+        let graph = &self.graph;
+        let mut last: Vec<Option<PassId>> = vec![None; graph.resources.len()];
+        let mut handovers = Vec::new();
+        for &pass in &self.order {
+            for &(resource, _) in graph.pass(pass).uses() {
+                if let Some(before) = last[resource.0]
+                    && before != pass
+                    && graph.pass(before).queue() != graph.pass(pass).queue()
+                    && !handovers.contains(&(before, pass, resource))
+                {
+                    handovers.push((before, pass, resource));
+                }
+                last[resource.0] = Some(pass);
+            }
+        }
+        return handovers;
+    }
+
+    /// Submissions in the order they're made. Each only waits on ones before it, so binary semaphores do.
+    pub fn schedule(&self) -> Vec<Submission> {
+        let graph = &self.graph;
+        let mut submissions: Vec<Submission> = Vec::new();
+        // One being built per queue.
+        let mut open: [Option<Submission>; 3] = [None, None, None];
+        // Where each pass went, once its submission's closed.
+        let mut placed: Vec<Option<usize>> = vec![None; graph.passes.len()];
+        // The submissions each queue has waited on so far.
+        let mut waited: [Vec<usize>; 3] = Default::default();
+        let handovers = self.handovers();
+
+        let close = |submission: Submission,
+                     submissions: &mut Vec<Submission>,
+                     placed: &mut Vec<Option<usize>>| {
+            for pass in &submission.passes {
+                placed[pass.0] = Some(submissions.len());
+            }
+            submissions.push(submission);
+        };
+
+        for &pass in &self.order {
+            let queue = graph.pass(pass).queue();
+            let mut waits = Vec::new();
+            let befores = self
+                .edges
+                .iter()
+                .filter(|(_, after)| *after == pass)
+                .map(|e| e.0);
+            let handed = handovers.iter().filter(|h| h.1 == pass).map(|h| h.0);
+            for before in befores.chain(handed) {
+                let other = graph.pass(before).queue();
+                if other == queue {
+                    continue;
+                }
+                if placed[before.0].is_none()
+                    && let Some(submission) = open[other as usize].take()
+                {
+                    close(submission, &mut submissions, &mut placed);
+                }
+                if let Some(index) = placed[before.0]
+                    && !waits.contains(&index)
+                {
+                    waits.push(index);
+                }
+            }
+            // Anything at or after it on the same queue would do.
+            waits.retain(|&index| {
+                !waited[queue as usize]
+                    .iter()
+                    .any(|&w| w >= index && submissions[w].queue == submissions[index].queue)
+            });
+            waited[queue as usize].extend(&waits);
+
+            waits.sort();
+            for &index in &waits {
+                submissions[index].signals = true;
+            }
+            // Waits happen at the start of a submission, so anything already in this queue's goes ahead without.
+            if !waits.is_empty()
+                && let Some(submission) = open[queue as usize].take()
+            {
+                close(submission, &mut submissions, &mut placed);
+            }
+
+            let submission = open[queue as usize].get_or_insert_with(|| Submission {
+                queue,
+                passes: Vec::new(),
+                waits: Vec::new(),
+                signals: false,
+            });
+            submission.waits.extend(waits);
+            submission.passes.push(pass);
+        }
+
+        for submission in open.into_iter().flatten() {
+            close(submission, &mut submissions, &mut placed);
+        }
+        return submissions;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Submission;
+    use crate::render::{
+        graph::{Access, Pass, Queue, RenderGraph},
+        hal::{TextureDesc, TextureFormat, TextureUsage},
+    };
+
+    #[test]
+    pub fn compute_overlaps_graphics() {
+        let mut graph = RenderGraph::new();
+        let desc = |format| TextureDesc {
+            width: 64,
+            height: 64,
+            format,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+            samples: 1,
+        };
+        let swapchain = graph.swapchain();
+        let depth = graph.add_texture("depth", desc(TextureFormat::Depth32Float));
+        let ao = graph.add_texture("ao", desc(TextureFormat::Rgba8Unorm));
+        let particles = graph.import("particles");
+        let hdr = graph.add_texture("hdr", desc(TextureFormat::Rgba16Float));
+
+        let prepass =
+            graph.add_pass(Pass::new("depth prepass").with_access(depth, Access::RenderTarget));
+        let simulate = graph.add_pass(
+            Pass::new("simulate particles")
+                .with_queue(Queue::Compute)
+                .with_access(particles, Access::ShaderWrite),
+        );
+        let ssao = graph.add_pass(
+            Pass::new("ssao")
+                .with_queue(Queue::Compute)
+                .with_access(depth, Access::ShaderRead)
+                .with_access(ao, Access::ShaderWrite),
+        );
+        let shadows = graph.add_pass(Pass::new("shadows").with_access(hdr, Access::RenderTarget));
+        let lighting = graph.add_pass(
+            Pass::new("lighting")
+                .with_access(ao, Access::ShaderRead)
+                .with_access(particles, Access::ShaderRead)
+                .with_access(hdr, Access::RenderTarget),
+        );
+        let present = graph.add_pass(
+            Pass::new("present")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+
+        let schedule = graph.compile().schedule();
+        assert_eq!(
+            schedule,
+            vec![
+                // Cut short for ssao to wait on.
+                Submission {
+                    queue: Queue::Graphics,
+                    passes: vec![prepass],
+                    waits: vec![],
+                    signals: true,
+                },
+                // Particles don't need to wait, so go ahead of ssao.
+                Submission {
+                    queue: Queue::Compute,
+                    passes: vec![simulate],
+                    waits: vec![],
+                    signals: true,
+                },
+                Submission {
+                    queue: Queue::Compute,
+                    passes: vec![ssao],
+                    waits: vec![0],
+                    signals: true,
+                },
+                // Shadows run while compute works, lighting waits for it.
+                Submission {
+                    queue: Queue::Graphics,
+                    passes: vec![shadows],
+                    waits: vec![],
+                    signals: false,
+                },
+                Submission {
+                    queue: Queue::Graphics,
+                    passes: vec![lighting, present],
+                    waits: vec![1, 2],
+                    signals: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn waits_on_each_semaphore_once() {
+        let mut graph = RenderGraph::new();
+        let desc = TextureDesc {
+            width: 64,
+            height: 64,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+            samples: 1,
+        };
+        let swapchain = graph.swapchain();
+        let [a, b, d] = ["a", "b", "d"].map(|name| graph.add_texture(name, desc));
+        let compute = |name, read, write| {
+            Pass::new(name)
+                .with_queue(Queue::Compute)
+                .with_access(read, Access::ShaderRead)
+                .with_access(write, Access::ShaderWrite)
+        };
+
+        let pass_a = graph.add_pass(Pass::new("a").with_access(a, Access::RenderTarget));
+        let pass_b = graph.add_pass(compute("b", a, b));
+        let pass_c = graph.add_pass(
+            Pass::new("c")
+                .with_access(b, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+        let pass_d = graph.add_pass(compute("d", a, d));
+        graph.mark_output(d);
+
+        let schedule = graph.compile().schedule();
+        assert_eq!(
+            schedule,
+            vec![
+                Submission {
+                    queue: Queue::Graphics,
+                    passes: vec![pass_a],
+                    waits: vec![],
+                    signals: true,
+                },
+                Submission {
+                    queue: Queue::Compute,
+                    passes: vec![pass_b],
+                    waits: vec![0],
+                    signals: true,
+                },
+                Submission {
+                    queue: Queue::Graphics,
+                    passes: vec![pass_c],
+                    waits: vec![1],
+                    signals: false,
+                },
+                // Compute already waited on a's submission for b.
+                Submission {
+                    queue: Queue::Compute,
+                    passes: vec![pass_d],
+                    waits: vec![],
+                    signals: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn hands_over_between_reads() {
+        let mut graph = RenderGraph::new();
+        let desc = TextureDesc {
+            width: 64,
+            height: 64,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+            samples: 1,
+        };
+        let swapchain = graph.swapchain();
+        let [a, x, y] = ["a", "x", "y"].map(|name| graph.add_texture(name, desc));
+        let compute = |name, write| {
+            Pass::new(name)
+                .with_queue(Queue::Compute)
+                .with_access(a, Access::ShaderRead)
+                .with_access(write, Access::ShaderWrite)
+        };
+
+        let write = graph.add_pass(Pass::new("write").with_access(a, Access::RenderTarget));
+        let first = graph.add_pass(compute("first", x));
+        let read = graph.add_pass(
+            Pass::new("read")
+                .with_access(a, Access::ShaderRead)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+        let second = graph.add_pass(compute("second", y));
+        graph.mark_output(x);
+        graph.mark_output(y);
+
+        // Only reads after the write, but each queue has to give a up before the other gets it.
+        let compiled = graph.compile();
+        assert_eq!(
+            compiled.handovers(),
+            vec![(write, first, a), (first, read, a), (read, second, a)]
+        );
+        let submission = |queue, pass, waits: Vec<usize>, signals| Submission {
+            queue,
+            passes: vec![pass],
+            waits,
+            signals,
+        };
+        assert_eq!(
+            compiled.schedule(),
+            vec![
+                submission(Queue::Graphics, write, vec![], true),
+                submission(Queue::Compute, first, vec![0], true),
+                submission(Queue::Graphics, read, vec![1], true),
+                // Past the write already, through first.
+                submission(Queue::Compute, second, vec![2], false),
+            ]
+        );
+    }
+}
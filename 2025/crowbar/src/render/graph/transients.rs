@@ -0,0 +1,432 @@
+//! The textures and buffers a graph makes for itself, each put down to the pass that asked for it so the memory
+//! overlay can say which effect the memory goes to.
+//!
+//! A transient's pass is the first one to use it, which is normally the one that needs it made. Transients whose
+//! lives don't overlap share one resource when they're made the same way, the way a shadow map and a bloom chain
+//! that happen to match would, which is where [`CompiledGraph::aliases`] comes in. Outputs and anything the CPU maps
+//! are never shared, since their contents have to outlive the passes that use them. Shared memory goes down to the
+//! first pass to use it.
+//!
+//! todo: sharing only goes as far as identical descriptions, until hal can place resources in another's memory.
+
+use std::collections::BTreeMap;
+
+use super::{CompiledGraph, PassId, ResourceId, ResourceKind};
+use crate::render::{
+    deletion::{DeletionQueue, Retired},
+    hal::{Device, MemoryLocation},
+};
+
+impl CompiledGraph {
+    /// The pass a transient resource is made for. `None` for imported resources, and ones no pass that runs uses.
+    pub fn requested_by(&self, resource: ResourceId) -> Option<PassId> {
+        if matches!(
+            self.graph.resource(resource).kind,
+            ResourceKind::Imported(_)
+        ) {
+            return None;
+        }
+        return self.order.iter().copied().find(|&pass| {
+            self.graph
+                .pass(pass)
+                .uses()
+                .iter()
+                .any(|(r, _)| *r == resource)
+        });
+    }
+
+    /// Where in [`CompiledGraph::order`] a transient is first and last used, or `None` as for
+    /// [`CompiledGraph::requested_by`]. Outputs last to the end of the frame and past it.
+    pub fn lifetime(&self, resource: ResourceId) -> Option<(usize, usize)> {
+        self.requested_by(resource)?;
+        let mut uses = self.order.iter().enumerate().filter(|(_, pass)| {
+            self.graph
+                .pass(**pass)
+                .uses()
+                .iter()
+                .any(|(r, _)| *r == resource)
+        });
+        let first = uses.clone().next()?.0;
+        let last = match self.graph.resource(resource).output {
+            true => usize::MAX,
+            false => uses.next_back()?.0,
+        };
+        return Some((first, last));
+    }
+
+    /// Which resource each transient is made as, by resource, numbered from 0. Transients that alias share a number.
+    pub fn aliases(&self) -> Vec<Option<usize>> {
+        let graph = &self.graph;
+        let mut by_first: Vec<(ResourceId, (usize, usize))> = graph
+            .resources()
+            .filter_map(|r| Some((r, self.lifetime(r)?)))
+            .collect();
+        by_first.sort_by_key(|(r, (first, _))| (*first, *r));
+
+        let mut aliases = vec![None; graph.resources.len()];
+        // Each made resource's description, when it's free again, and whether it can be shared at all.
+        let mut made: Vec<(ResourceKind, usize, bool)> = Vec::new();
+        for (resource, (first, last)) in by_first {
+            let r = graph.resource(resource);
+            let shareable = !r.output
+                && match r.kind {
+                    ResourceKind::Texture(_) => true,
+                    ResourceKind::Buffer(desc) => desc.location == MemoryLocation::Device,
+                    ResourceKind::Imported(_) => false,
+                };
+            let reuse = made.iter().position(|(kind, free_after, shared)| {
+                *shared && shareable && *free_after < first && same_kind(kind, &r.kind)
+            });
+            let index = match reuse {
+                Some(index) => {
+                    made[index].1 = last;
+                    index
+                }
+                None => {
+                    made.push((r.kind, last, shareable));
+                    made.len() - 1
+                }
+            };
+            aliases[resource.0] = Some(index);
+        }
+        return aliases;
+    }
+
+    /// Roughly how many bytes of transients each pass asks for, by [`PassId::index`], from their sizes alone.
+    /// The allocator's alignment and padding come on top.
+    pub fn transient_bytes(&self) -> Vec<u64> {
+        let mut bytes = vec![0; self.graph.passes.len()];
+        for resource in self.graph.resources() {
+            let Some(pass) = self.requested_by(resource) else {
+                continue;
+            };
+            bytes[pass.0] += size(&self.graph.resource(resource).kind);
+        }
+        return bytes;
+    }
+
+    /// Roughly how many bytes the transients take once aliased, against [`CompiledGraph::transient_bytes`].
+    pub fn aliased_bytes(&self) -> u64 {
+        let mut made = BTreeMap::new();
+        for (resource, alias) in self.aliases().into_iter().enumerate() {
+            if let Some(alias) = alias {
+                made.insert(alias, size(&self.graph.resources[resource].kind));
+            }
+        }
+        return made.values().sum();
+    }
+}
+
+fn same_kind(a: &ResourceKind, b: &ResourceKind) -> bool {
+    match (a, b) {
+        (ResourceKind::Texture(a), ResourceKind::Texture(b)) => a == b,
+        (ResourceKind::Buffer(a), ResourceKind::Buffer(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn size(kind: &ResourceKind) -> u64 {
+    match kind {
+        ResourceKind::Texture(desc) => desc.format.size(desc.width, desc.height),
+        ResourceKind::Buffer(desc) => desc.size,
+        ResourceKind::Imported(_) => 0,
+    }
+}
+
+enum Transient<D: Device> {
+    Texture(D::Texture),
+    Buffer(D::Buffer),
+}
+
+/// The transients of a graph, made on a device.
+pub struct Transients<D: Device> {
+    /// What each resource was made as, by resource. Nothing for imported or unused ones.
+    aliases: Vec<Option<usize>>,
+    made: Vec<Transient<D>>,
+    /// How each of `made` was described.
+    kinds: Vec<ResourceKind>,
+}
+
+impl<D: Device> Transients<D> {
+    /// Make every transient `compiled` uses, sharing them where [`CompiledGraph::aliases`] says, with the device
+    /// putting each one's memory down to its pass.
+    pub fn create(device: &D, compiled: &CompiledGraph) -> Result<Transients<D>, D::Error> {
+        let graph = compiled.graph();
+        let mut transients = Transients {
+            aliases: compiled.aliases(),
+            made: Vec::new(),
+            kinds: Vec::new(),
+        };
+        let count = transients
+            .aliases
+            .iter()
+            .flatten()
+            .max()
+            .map_or(0, |m| m + 1);
+        for index in 0..count {
+            // Made for whichever of the resources sharing it comes first.
+            let resource = graph
+                .resources()
+                .filter(|r| transients.aliases[r.0] == Some(index))
+                .min_by_key(|r| compiled.lifetime(*r))
+                .expect("Every alias has a resource");
+            let pass = compiled.requested_by(resource);
+            device.set_memory_owner(pass.map(|p| graph.pass(p).name()));
+            let made = match graph.resource(resource).kind {
+                ResourceKind::Texture(desc) => device.create_texture(&desc).map(Transient::Texture),
+                ResourceKind::Buffer(desc) => device.create_buffer(&desc).map(Transient::Buffer),
+                ResourceKind::Imported(_) => unreachable!("Imported resources aren't transient"),
+            };
+            match made {
+                Ok(made) => {
+                    transients.made.push(made);
+                    transients.kinds.push(graph.resource(resource).kind);
+                }
+                Err(e) => {
+                    device.set_memory_owner(None);
+                    // SAFETY: Only just made, the GPU hasn't seen them.
+                    unsafe { transients.destroy(device) };
+                    return Err(e);
+                }
+            }
+        }
+        device.set_memory_owner(None);
+        return Ok(transients);
+    }
+
+    /// Whether these would be made the same way for `compiled`, so they can be used for it as they are. Frames are
+    /// graphs built afresh, and mostly the same graph as the frame before.
+    pub fn fits(&self, compiled: &CompiledGraph) -> bool {
+        let graph = compiled.graph();
+        let aliases = compiled.aliases();
+        return aliases == self.aliases
+            && graph.resources().all(|r| match aliases[r.0] {
+                Some(alias) => same_kind(&self.kinds[alias], &graph.resource(r).kind),
+                None => true,
+            });
+    }
+
+    /// What `resource` was made as, by [`CompiledGraph::aliases`] numbering.
+    pub fn alias(&self, resource: ResourceId) -> Option<usize> {
+        self.aliases.get(resource.0).copied().flatten()
+    }
+
+    pub fn texture(&self, resource: ResourceId) -> Option<&D::Texture> {
+        match self.made.get(self.alias(resource)?)? {
+            Transient::Texture(texture) => Some(texture),
+            _ => None,
+        }
+    }
+
+    pub fn buffer(&self, resource: ResourceId) -> Option<&D::Buffer> {
+        match self.made.get(self.alias(resource)?)? {
+            Transient::Buffer(buffer) => Some(buffer),
+            _ => None,
+        }
+    }
+
+    /// How many resources were actually made.
+    pub fn len(&self) -> usize {
+        self.made.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.made.is_empty()
+    }
+
+    /// Hand everything to `deletions`, to be destroyed once the frames in flight as of `frame` are done with it.
+    pub fn retire(self, frame: u64, deletions: &mut DeletionQueue<Retired<D>>) {
+        for transient in self.made {
+            let retired = match transient {
+                Transient::Texture(texture) => Retired::Texture(texture),
+                Transient::Buffer(buffer) => Retired::Buffer(buffer),
+            };
+            deletions.retire(frame, retired);
+        }
+    }
+
+    /// # Safety
+    /// `device` must be the one they were made on, and the GPU done with them.
+    pub unsafe fn destroy(self, device: &D) {
+        for transient in self.made {
+            // SAFETY: Passed on to the caller.
+            unsafe {
+                match transient {
+                    Transient::Texture(texture) => device.destroy_texture(texture),
+                    Transient::Buffer(buffer) => device.destroy_buffer(buffer),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Transients;
+    use crate::{
+        render::{
+            graph::{Access, Pass, RenderGraph},
+            hal::{
+                BufferDesc, BufferUsage, Device, MemoryLocation, TextureDesc, TextureFormat,
+                TextureUsage,
+            },
+        },
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn attributes_transients_to_passes() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let hdr = graph.add_texture(
+            "hdr",
+            TextureDesc {
+                width: 64,
+                height: 32,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                samples: 1,
+            },
+        );
+        let histogram = graph.add_buffer(
+            "histogram",
+            BufferDesc {
+                size: 1024,
+                usage: BufferUsage::STORAGE,
+                location: MemoryLocation::Device,
+            },
+        );
+        let unused = graph.add_buffer(
+            "unused",
+            BufferDesc {
+                size: 4096,
+                usage: BufferUsage::STORAGE,
+                location: MemoryLocation::Device,
+            },
+        );
+        // Added first, but runs second.
+        let tonemap = graph.add_pass(
+            Pass::new("tonemap")
+                .with_access(hdr, Access::ShaderRead)
+                .with_access(histogram, Access::ShaderWrite)
+                .with_access(swapchain, Access::RenderTarget),
+        );
+        let scene = graph.add_pass(Pass::new("scene").with_access(hdr, Access::RenderTarget));
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.requested_by(hdr), Some(scene));
+        assert_eq!(compiled.requested_by(histogram), Some(tonemap));
+        assert_eq!(compiled.requested_by(unused), None);
+        assert_eq!(compiled.requested_by(swapchain), None);
+        assert_eq!(compiled.transient_bytes(), vec![1024, 64 * 32 * 8]);
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let transients = Transients::create(device, &compiled).unwrap();
+        assert!(transients.texture(hdr).is_some());
+        assert!(transients.buffer(unused).is_none());
+        let report = device.memory_report();
+        let owners = report.by_owner();
+        assert!(
+            owners
+                .iter()
+                .any(|&(name, size)| name == "scene" && size >= 64 * 32 * 8)
+        );
+        assert!(
+            owners
+                .iter()
+                .any(|&(name, size)| name == "tonemap" && size >= 1024)
+        );
+        // Good for the same graph next frame, not for another.
+        assert!(transients.fits(&compiled));
+        assert!(!transients.fits(&RenderGraph::new().compile()));
+        // SAFETY: Never used.
+        unsafe { transients.destroy(device) };
+    }
+
+    #[test]
+    pub fn aliases_what_it_can() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.swapchain();
+        let desc = TextureDesc {
+            width: 16,
+            height: 16,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+            samples: 1,
+        };
+        let shadow = graph.add_texture("shadow", desc);
+        let lit = graph.add_texture("lit", desc);
+        let bloom = graph.add_texture("bloom", desc);
+        let half = graph.add_texture(
+            "half",
+            TextureDesc {
+                width: 8,
+                height: 8,
+                ..desc
+            },
+        );
+        let readback = graph.add_buffer(
+            "readback",
+            BufferDesc {
+                size: 64,
+                usage: BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+                location: MemoryLocation::Readback,
+            },
+        );
+        let readback_too = graph.add_buffer(
+            "readback too",
+            BufferDesc {
+                size: 64,
+                usage: BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+                location: MemoryLocation::Readback,
+            },
+        );
+        let pass = |name, uses: &[(_, Access)]| {
+            uses.iter()
+                .fold(Pass::new(name), |p, &(r, a)| p.with_access(r, a))
+        };
+        graph.add_pass(pass("shadows", &[(shadow, Access::RenderTarget)]));
+        graph.add_pass(pass(
+            "light",
+            &[(shadow, Access::ShaderRead), (lit, Access::RenderTarget)],
+        ));
+        // Shadows are done with by now, so bloom can have its texture. Half is a different size.
+        graph.add_pass(pass(
+            "bloom",
+            &[
+                (lit, Access::ShaderRead),
+                (bloom, Access::RenderTarget),
+                (half, Access::RenderTarget),
+                (readback, Access::CopyDst),
+            ],
+        ));
+        graph.add_pass(pass(
+            "present",
+            &[
+                (bloom, Access::ShaderRead),
+                (half, Access::ShaderRead),
+                (readback, Access::CopySrc),
+                (readback_too, Access::CopyDst),
+                (swapchain, Access::RenderTarget),
+            ],
+        ));
+
+        let compiled = graph.compile();
+        assert_eq!(compiled.lifetime(shadow), Some((0, 1)));
+        assert_eq!(compiled.lifetime(swapchain), None);
+        let aliases = compiled.aliases();
+        assert_eq!(aliases[shadow.0], Some(0));
+        assert_eq!(aliases[lit.0], Some(1));
+        assert_eq!(aliases[bloom.0], Some(0));
+        assert_eq!(aliases[half.0], Some(2));
+        // Mapped memory is never shared, even once it's free.
+        assert_ne!(aliases[readback_too.0], aliases[readback.0]);
+        assert_eq!(
+            compiled.aliased_bytes(),
+            compiled.transient_bytes().iter().sum::<u64>() - 16 * 16 * 4
+        );
+    }
+}
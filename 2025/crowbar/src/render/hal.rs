@@ -0,0 +1,529 @@
+//! A thin layer over the graphics API: creating resources, recording commands and submitting them.
+//!
+//! Vulkan through ash is the only backend, in [`vulkan`]. Code written against [`Device`] and
+//! [`CommandEncoder`] rather than ash shouldn't need rewriting when another backend (wgpu, Metal)
+//! turns up. This only covers what the renderer uses so far, things get added as they're needed.
+
+use std::{error::Error, ops::BitOr};
+
+use self::{
+    caps::{CompressionFamily, DeviceCaps},
+    memory::MemoryReport,
+};
+use crate::color::LinearColor;
+
+pub mod caps;
+pub mod memory;
+pub mod vulkan;
+
+/// What a buffer can be used for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferUsage(u32);
+
+impl BufferUsage {
+    pub const COPY_SRC: BufferUsage = BufferUsage(1);
+    pub const COPY_DST: BufferUsage = BufferUsage(2);
+    pub const VERTEX: BufferUsage = BufferUsage(4);
+    pub const INDEX: BufferUsage = BufferUsage(8);
+    pub const UNIFORM: BufferUsage = BufferUsage(16);
+    pub const STORAGE: BufferUsage = BufferUsage(32);
+    /// Draw or dispatch arguments read by the GPU.
+    pub const INDIRECT: BufferUsage = BufferUsage(64);
+
+    pub fn contains(&self, other: BufferUsage) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for BufferUsage {
+    type Output = BufferUsage;
+
+    fn bitor(self, rhs: BufferUsage) -> BufferUsage {
+        BufferUsage(self.0 | rhs.0)
+    }
+}
+
+/// Where a buffer's memory lives, which decides who can get at it quickly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// GPU only.
+    Device,
+    /// Written by the CPU, read by the GPU.
+    Upload,
+    /// Written by the GPU, read back by the CPU.
+    Readback,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferDesc {
+    pub size: u64,
+    pub usage: BufferUsage,
+    pub location: MemoryLocation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8Unorm,
+    Rgba8Srgb,
+    Bgra8Srgb,
+    Rgba16Float,
+    /// Two channels, as motion vectors are.
+    Rg16Float,
+    Depth32Float,
+    /// Where there's no [`TextureFormat::Depth32Float`] to draw to, or stencil is wanted.
+    Depth24Stencil8,
+    /// Float depth with stencil, for the scene, which portals mark their views out in.
+    Depth32FloatStencil8,
+    /// RGB with 1 bit alpha, 8 bytes per 4x4 block.
+    Bc1Unorm,
+    Bc1Srgb,
+    /// RGBA, 16 bytes per block.
+    Bc3Unorm,
+    Bc3Srgb,
+    Bc7Unorm,
+    Bc7Srgb,
+    Etc2Rgb8Unorm,
+    Etc2Rgb8Srgb,
+    Etc2Rgba8Unorm,
+    Etc2Rgba8Srgb,
+    Astc4x4Unorm,
+    Astc4x4Srgb,
+}
+
+impl TextureFormat {
+    pub const ALL: [TextureFormat; 20] = [
+        TextureFormat::Rgba8Unorm,
+        TextureFormat::Rgba8Srgb,
+        TextureFormat::Bgra8Srgb,
+        TextureFormat::Rgba16Float,
+        TextureFormat::Rg16Float,
+        TextureFormat::Depth32Float,
+        TextureFormat::Depth24Stencil8,
+        TextureFormat::Depth32FloatStencil8,
+        TextureFormat::Bc1Unorm,
+        TextureFormat::Bc1Srgb,
+        TextureFormat::Bc3Unorm,
+        TextureFormat::Bc3Srgb,
+        TextureFormat::Bc7Unorm,
+        TextureFormat::Bc7Srgb,
+        TextureFormat::Etc2Rgb8Unorm,
+        TextureFormat::Etc2Rgb8Srgb,
+        TextureFormat::Etc2Rgba8Unorm,
+        TextureFormat::Etc2Rgba8Srgb,
+        TextureFormat::Astc4x4Unorm,
+        TextureFormat::Astc4x4Srgb,
+    ];
+
+    /// Where it is in [`TextureFormat::ALL`].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Bytes per pixel, or per 4x4 block for block compressed formats.
+    fn block_bytes(self) -> u64 {
+        match self {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8Srgb | TextureFormat::Bgra8Srgb => 4,
+            TextureFormat::Rg16Float => 4,
+            // Copies only take the depth, which is 24 bits in 32 for a depth stencil format.
+            TextureFormat::Depth32Float
+            | TextureFormat::Depth24Stencil8
+            | TextureFormat::Depth32FloatStencil8 => 4,
+            TextureFormat::Rgba16Float => 8,
+            TextureFormat::Bc1Unorm
+            | TextureFormat::Bc1Srgb
+            | TextureFormat::Etc2Rgb8Unorm
+            | TextureFormat::Etc2Rgb8Srgb => 8,
+            TextureFormat::Bc3Unorm
+            | TextureFormat::Bc3Srgb
+            | TextureFormat::Bc7Unorm
+            | TextureFormat::Bc7Srgb
+            | TextureFormat::Etc2Rgba8Unorm
+            | TextureFormat::Etc2Rgba8Srgb
+            | TextureFormat::Astc4x4Unorm
+            | TextureFormat::Astc4x4Srgb => 16,
+        }
+    }
+
+    /// Bytes a `width` by `height` image takes, tightly packed.
+    pub fn size(self, width: u32, height: u32) -> u64 {
+        if self.compression().is_some() {
+            return width.div_ceil(4) as u64 * height.div_ceil(4) as u64 * self.block_bytes();
+        }
+        return width as u64 * height as u64 * self.block_bytes();
+    }
+
+    /// Which block compression it is, if it is.
+    pub fn compression(self) -> Option<CompressionFamily> {
+        match self {
+            TextureFormat::Bc1Unorm
+            | TextureFormat::Bc1Srgb
+            | TextureFormat::Bc3Unorm
+            | TextureFormat::Bc3Srgb
+            | TextureFormat::Bc7Unorm
+            | TextureFormat::Bc7Srgb => Some(CompressionFamily::Bc),
+            TextureFormat::Etc2Rgb8Unorm
+            | TextureFormat::Etc2Rgb8Srgb
+            | TextureFormat::Etc2Rgba8Unorm
+            | TextureFormat::Etc2Rgba8Srgb => Some(CompressionFamily::Etc2),
+            TextureFormat::Astc4x4Unorm | TextureFormat::Astc4x4Srgb => {
+                Some(CompressionFamily::Astc)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_srgb(self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Rgba8Srgb
+                | TextureFormat::Bgra8Srgb
+                | TextureFormat::Bc1Srgb
+                | TextureFormat::Bc3Srgb
+                | TextureFormat::Bc7Srgb
+                | TextureFormat::Etc2Rgb8Srgb
+                | TextureFormat::Etc2Rgba8Srgb
+                | TextureFormat::Astc4x4Srgb
+        )
+    }
+
+    pub fn is_depth(self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Depth32Float
+                | TextureFormat::Depth24Stencil8
+                | TextureFormat::Depth32FloatStencil8
+        )
+    }
+
+    pub fn has_stencil(self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Depth24Stencil8 | TextureFormat::Depth32FloatStencil8
+        )
+    }
+}
+
+/// What a texture can be used for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextureUsage(u32);
+
+impl TextureUsage {
+    pub const COPY_SRC: TextureUsage = TextureUsage(1);
+    pub const COPY_DST: TextureUsage = TextureUsage(2);
+    pub const SAMPLED: TextureUsage = TextureUsage(4);
+    /// Drawn to in a pass, as colour or depth depending on the format.
+    pub const RENDER_TARGET: TextureUsage = TextureUsage(8);
+
+    pub fn contains(&self, other: TextureUsage) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for TextureUsage {
+    type Output = TextureUsage;
+
+    fn bitor(self, rhs: TextureUsage) -> TextureUsage {
+        TextureUsage(self.0 | rhs.0)
+    }
+}
+
+/// A 2D texture with one mip and layer. The only kind there's been a use for so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub usage: TextureUsage,
+    /// Samples per texel, a power of two. Anything over 1 is a multisampled render target, which can't be copied to
+    /// or from, only resolved, see [`Attachment::resolve`].
+    pub samples: u32,
+}
+
+/// What a texture is being used for right now. Moving between these is a [`CommandEncoder::transition`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureState {
+    /// Contents are garbage, the state new textures start in.
+    Undefined,
+    RenderTarget,
+    CopySrc,
+    CopyDst,
+    /// Sampled, by fragment or compute shaders.
+    ShaderRead,
+    /// Handed to the presentation engine. Only swapchain images go here.
+    Present,
+}
+
+/// What an attachment starts a pass with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp<T> {
+    Clear(T),
+    /// Whatever was drawn to it before.
+    Load,
+    /// Garbage, for attachments every pixel of is about to be drawn over.
+    DontCare,
+}
+
+/// A texture drawn to in a [`CommandEncoder::begin_rendering`] pass, with the states it's moved in and out of.
+#[derive(Clone, Copy, Debug)]
+pub struct Attachment<'a, T, C> {
+    pub texture: &'a T,
+    /// The state it's in before the pass. From [`TextureState::Undefined`], whatever was there is lost.
+    pub before: TextureState,
+    /// The state it's left in after. [`TextureState::Undefined`] leaves it where it is, for nothing to read.
+    pub after: TextureState,
+    pub load: LoadOp<C>,
+    /// Whether what's drawn is kept. Depth buffers only needed for the pass can skip it.
+    pub store: bool,
+    /// Where a multisampled attachment is resolved to as the pass ends.
+    pub resolve: Option<Resolve<'a, T>>,
+}
+
+/// A single sampled texture an [`Attachment`] is resolved into, the same size and format.
+#[derive(Clone, Copy, Debug)]
+pub struct Resolve<'a, T> {
+    pub texture: &'a T,
+    pub before: TextureState,
+    pub after: TextureState,
+}
+
+/// The device limits the renderer has to work within.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Uniform buffer offsets have to be a multiple of this.
+    pub min_uniform_offset_alignment: u64,
+    /// The most of a uniform buffer one binding can see.
+    pub max_uniform_range: u64,
+}
+
+/// A device resources are made on and commands are submitted to.
+pub trait Device {
+    type Buffer;
+    type Texture;
+    /// Something to wait on for a submission to finish.
+    type Fence;
+    /// Something one submission signals and another waits on, to order them across queues.
+    type Semaphore;
+    type Encoder<'a>: CommandEncoder<Buffer = Self::Buffer, Texture = Self::Texture>
+    where
+        Self: 'a;
+    type Error: Error;
+
+    /// What the device can do, read when it was made.
+    fn caps(&self) -> &DeviceCaps;
+
+    fn limits(&self) -> Limits {
+        self.caps().limits
+    }
+
+    fn create_buffer(&self, desc: &BufferDesc) -> Result<Self::Buffer, Self::Error>;
+
+    /// # Safety
+    /// The GPU must be done with the buffer.
+    unsafe fn destroy_buffer(&self, buffer: Self::Buffer);
+
+    fn create_texture(&self, desc: &TextureDesc) -> Result<Self::Texture, Self::Error>;
+
+    /// # Safety
+    /// The GPU must be done with the texture.
+    unsafe fn destroy_texture(&self, texture: Self::Texture);
+
+    /// Copy into an [`MemoryLocation::Upload`] buffer, starting `offset` bytes in.
+    ///
+    /// # Safety
+    /// The GPU mustn't be using the buffer.
+    unsafe fn write_buffer(
+        &self,
+        buffer: &Self::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Copy out of a [`MemoryLocation::Readback`] buffer, starting `offset` bytes in.
+    ///
+    /// # Safety
+    /// The GPU mustn't be writing to the buffer.
+    unsafe fn read_buffer(
+        &self,
+        buffer: &Self::Buffer,
+        offset: u64,
+        out: &mut [u8],
+    ) -> Result<(), Self::Error>;
+
+    fn begin_commands(&self) -> Result<Self::Encoder<'_>, Self::Error>;
+
+    /// Commands for the device's compute queue, to run alongside graphics work, or the main queue if it hasn't one.
+    /// Only dispatches, copies, compute barriers and buffer work can be recorded in them. They're ordered against
+    /// other submissions with semaphores, see [`Device::submit_with`], and textures have to be handed over with
+    /// [`CommandEncoder::release_texture`].
+    fn begin_compute_commands(&self) -> Result<Self::Encoder<'_>, Self::Error> {
+        self.begin_commands()
+    }
+
+    fn submit(&self, encoder: Self::Encoder<'_>) -> Result<Self::Fence, Self::Error> {
+        self.submit_with(encoder, &[], None)
+    }
+
+    /// [`Device::submit`], not starting until every one of `waits` has been signalled, and signalling `signal` once
+    /// it's done. Each semaphore can only be waited on once per signal.
+    fn submit_with(
+        &self,
+        encoder: Self::Encoder<'_>,
+        waits: &[&Self::Semaphore],
+        signal: Option<&Self::Semaphore>,
+    ) -> Result<Self::Fence, Self::Error>;
+
+    fn create_semaphore(&self) -> Result<Self::Semaphore, Self::Error>;
+
+    /// # Safety
+    /// Nothing submitted can still be signalling or waiting on it.
+    unsafe fn destroy_semaphore(&self, semaphore: Self::Semaphore);
+
+    /// Block until a submission is done. Every fence has to be waited on, or what it holds leaks.
+    fn wait(&self, fence: Self::Fence) -> Result<(), Self::Error>;
+
+    /// Block until the GPU is done with everything.
+    fn wait_idle(&self);
+
+    /// What device memory is allocated, and for what.
+    fn memory_report(&self) -> MemoryReport;
+
+    /// Put the memory for resources created from now on down to `owner` in the [`Device::memory_report`], or to
+    /// nobody. Backends that don't track memory can ignore it.
+    fn set_memory_owner(&self, owner: Option<&str>) {
+        let _ = owner;
+    }
+}
+
+/// Records commands for a [`Device`] to submit.
+pub trait CommandEncoder {
+    type Buffer;
+    type Texture;
+    type ComputePipeline;
+
+    fn transition(&mut self, texture: &Self::Texture, from: TextureState, to: TextureState);
+
+    /// Start drawing to `target`, which must be in [`TextureState::RenderTarget`].
+    /// Clears it to `clear` first, or keeps what's there with `None`.
+    fn begin_pass(&mut self, target: &Self::Texture, clear: Option<LinearColor>);
+
+    fn end_pass(&mut self);
+
+    /// Start drawing to `colors` and `depth`, moving them out of their `before` states first. Viewport and scissor
+    /// cover the first attachment, and the attachments all have to be its size.
+    fn begin_rendering(
+        &mut self,
+        colors: &[Attachment<'_, Self::Texture, LinearColor>],
+        depth: Option<Attachment<'_, Self::Texture, f32>>,
+    );
+
+    /// End a [`CommandEncoder::begin_rendering`] pass, leaving its attachments in their `after` states.
+    fn end_rendering(&mut self);
+
+    /// Copy all of `texture`, in [`TextureState::CopySrc`], into `buffer` as tightly packed rows.
+    fn copy_texture_to_buffer(&mut self, texture: &Self::Texture, buffer: &Self::Buffer);
+
+    /// Fill all of `texture`, in [`TextureState::CopyDst`], from tightly packed rows in `buffer`.
+    fn copy_buffer_to_texture(&mut self, buffer: &Self::Buffer, texture: &Self::Texture);
+
+    /// Average multisampled colour `src`, in [`TextureState::CopySrc`], down into `dst`, in
+    /// [`TextureState::CopyDst`]. They have to be the same size and format.
+    fn resolve_texture(&mut self, src: &Self::Texture, dst: &Self::Texture);
+
+    /// Run `pipeline` over `groups` workgroups, with `push` as its push constants.
+    fn dispatch(&mut self, pipeline: &Self::ComputePipeline, push: &[u8], groups: [u32; 3]);
+
+    /// [`CommandEncoder::dispatch`], with the workgroup counts read from `buffer` at `offset` when it runs.
+    fn dispatch_indirect(
+        &mut self,
+        pipeline: &Self::ComputePipeline,
+        push: &[u8],
+        buffer: &Self::Buffer,
+        offset: u64,
+    );
+
+    /// Make what dispatches so far wrote visible to everything after, including the host.
+    fn compute_barrier(&mut self);
+
+    /// Make everything written to `buffer` so far visible to whatever uses it next, the host included, and wait for
+    /// what's reading it before anything writes it again.
+    fn buffer_barrier(&mut self, buffer: &Self::Buffer);
+
+    /// Give `texture` up to the other queue, [`Device::begin_commands`]' for compute commands and
+    /// [`Device::begin_compute_commands`]' otherwise, moving it from `from` to `to` on the way. The other queue has to
+    /// [`CommandEncoder::acquire_texture`] it with the same states, in a submission that waits on this one. Returns
+    /// whether it changed hands: a device without a queue of its own for compute only has the one, and this is a
+    /// plain transition.
+    fn release_texture(
+        &mut self,
+        texture: &Self::Texture,
+        from: TextureState,
+        to: TextureState,
+    ) -> bool;
+
+    /// Take `texture` over from the other queue, after [`CommandEncoder::release_texture`]. Does nothing, and returns
+    /// false, where there's only the one queue.
+    fn acquire_texture(
+        &mut self,
+        texture: &Self::Texture,
+        from: TextureState,
+        to: TextureState,
+    ) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+        TextureFormat, TextureState, TextureUsage,
+    };
+    use crate::test_support::headless;
+
+    #[test]
+    pub fn texture_round_trip() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+
+        let pixels: Vec<u8> = (0..16 * 16 * 4).map(|i| (i * 7) as u8).collect();
+        let buffer = |usage, location| {
+            let desc = BufferDesc {
+                size: pixels.len() as u64,
+                usage,
+                location,
+            };
+            device.create_buffer(&desc).unwrap()
+        };
+        let upload = buffer(BufferUsage::COPY_SRC, MemoryLocation::Upload);
+        let readback = buffer(BufferUsage::COPY_DST, MemoryLocation::Readback);
+        let texture = device
+            .create_texture(&TextureDesc {
+                width: 16,
+                height: 16,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsage::COPY_SRC | TextureUsage::COPY_DST,
+                samples: 1,
+            })
+            .unwrap();
+
+        // SAFETY: Nothing is touched by the CPU while the GPU has it, and everything is waited on.
+        unsafe {
+            device.write_buffer(&upload, 0, &pixels).unwrap();
+
+            let mut cmds = device.begin_commands().unwrap();
+            cmds.transition(&texture, TextureState::Undefined, TextureState::CopyDst);
+            cmds.copy_buffer_to_texture(&upload, &texture);
+            cmds.transition(&texture, TextureState::CopyDst, TextureState::CopySrc);
+            cmds.copy_texture_to_buffer(&texture, &readback);
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+
+            let mut out = vec![0; pixels.len()];
+            device.read_buffer(&readback, 0, &mut out).unwrap();
+            assert_eq!(out, pixels);
+
+            device.destroy_texture(texture);
+            device.destroy_buffer(upload);
+            device.destroy_buffer(readback);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! What a device can do, read once when it's made, so code can check instead of assuming desktop-class hardware.
+//!
+//! Vulkan only guarantees a lot less than a desktop GPU has: 4096 texel textures, 4x MSAA, and storage on only a
+//! handful of formats. Mobile and software devices sit near those minimums.
+
+use std::{error::Error, fmt, ops::BitOr};
+
+use super::{Limits, TextureDesc, TextureFormat, TextureUsage};
+
+/// What a format can be used for, in textures laid out however suits the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatFeatures(u32);
+
+impl FormatFeatures {
+    pub const SAMPLED: FormatFeatures = FormatFeatures(1);
+    /// Sampled with linear filtering.
+    pub const FILTER: FormatFeatures = FormatFeatures(2);
+    /// Drawn to, as colour or depth depending on the format.
+    pub const RENDER_TARGET: FormatFeatures = FormatFeatures(4);
+    /// Drawn to with blending.
+    pub const BLEND: FormatFeatures = FormatFeatures(8);
+    /// Read and written from shaders as a storage image.
+    pub const STORAGE: FormatFeatures = FormatFeatures(16);
+    pub const COPY_SRC: FormatFeatures = FormatFeatures(32);
+    pub const COPY_DST: FormatFeatures = FormatFeatures(64);
+
+    pub fn contains(&self, other: FormatFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for FormatFeatures {
+    type Output = FormatFeatures;
+
+    fn bitor(self, rhs: FormatFeatures) -> FormatFeatures {
+        FormatFeatures(self.0 | rhs.0)
+    }
+}
+
+/// Supported sample counts, a bit per power of two like Vulkan's `VkSampleCountFlags`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SampleCounts(pub u32);
+
+impl SampleCounts {
+    pub fn supports(&self, samples: u32) -> bool {
+        samples.is_power_of_two() && self.0 & samples != 0
+    }
+
+    /// The most there can be, 1 if nothing else.
+    pub fn max(&self) -> u32 {
+        if self.0 == 0 {
+            return 1;
+        }
+        return 1 << (31 - self.0.leading_zeros());
+    }
+}
+
+/// Families of block compressed formats. Desktop GPUs have BC, mobile ones ETC2 and usually ASTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompressionFamily {
+    Bc,
+    Etc2,
+    /// LDR ASTC, at any block size.
+    Astc,
+}
+
+impl CompressionFamily {
+    pub const ALL: [CompressionFamily; 3] = [
+        CompressionFamily::Bc,
+        CompressionFamily::Etc2,
+        CompressionFamily::Astc,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionFamily::Bc => "BC",
+            CompressionFamily::Etc2 => "ETC2",
+            CompressionFamily::Astc => "ASTC",
+        }
+    }
+}
+
+/// How big a bindless descriptor table can be, on devices with the descriptor indexing features for one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BindlessLimits {
+    /// Sampled images in one set, updated after it's bound.
+    pub max_textures: u32,
+    pub max_storage_buffers: u32,
+}
+
+/// Why a texture can't be made on a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapsError {
+    TooBig {
+        max: u32,
+    },
+    Unsupported {
+        format: TextureFormat,
+        usage: TextureUsage,
+    },
+    /// Only render targets can be multisampled, and only at counts the device has.
+    Samples {
+        samples: u32,
+        max: u32,
+    },
+}
+
+impl fmt::Display for CapsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapsError::TooBig { max } => write!(f, "bigger than the device's {max} texel limit"),
+            CapsError::Unsupported { format, usage } => {
+                write!(f, "{format:?} can't be used as {usage:?} on this device")
+            }
+            CapsError::Samples { samples, max } => {
+                write!(
+                    f,
+                    "can't have {samples} samples, the most it can have is {max}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for CapsError {}
+
+#[derive(Clone, Debug)]
+pub struct DeviceCaps {
+    pub limits: Limits,
+    /// Width and height of 2D textures, in texels.
+    pub max_texture_2d: u32,
+    pub max_texture_3d: u32,
+    pub max_texture_cube: u32,
+    pub max_texture_layers: u32,
+    pub max_color_attachments: u32,
+    /// Sample counts render targets can have.
+    pub color_samples: SampleCounts,
+    pub depth_samples: SampleCounts,
+    /// 1.0 if anisotropic filtering isn't there at all.
+    pub max_anisotropy: f32,
+    pub max_compute_workgroup_size: [u32; 3],
+    pub max_compute_invocations: u32,
+    /// Block compressed families that can be sampled, enabled on the device wherever it has them.
+    pub compression: Vec<CompressionFamily>,
+    /// `None` without the descriptor indexing features bindless needs. Enabled on the device wherever it has them.
+    pub bindless: Option<BindlessLimits>,
+    /// By [`TextureFormat::index`].
+    pub(super) formats: [FormatFeatures; TextureFormat::ALL.len()],
+}
+
+impl DeviceCaps {
+    pub fn format_features(&self, format: TextureFormat) -> FormatFeatures {
+        self.formats[format.index()]
+    }
+
+    pub fn supports_sampling(&self, format: TextureFormat) -> bool {
+        self.format_features(format)
+            .contains(FormatFeatures::SAMPLED)
+    }
+
+    pub fn supports_filtering(&self, format: TextureFormat) -> bool {
+        self.format_features(format)
+            .contains(FormatFeatures::FILTER)
+    }
+
+    pub fn supports_render_target(&self, format: TextureFormat) -> bool {
+        self.format_features(format)
+            .contains(FormatFeatures::RENDER_TARGET)
+    }
+
+    pub fn supports_blending(&self, format: TextureFormat) -> bool {
+        self.format_features(format).contains(FormatFeatures::BLEND)
+    }
+
+    pub fn supports_storage(&self, format: TextureFormat) -> bool {
+        self.format_features(format)
+            .contains(FormatFeatures::STORAGE)
+    }
+
+    pub fn supports_compression(&self, family: CompressionFamily) -> bool {
+        self.compression.contains(&family)
+    }
+
+    /// Whether textures of `format` can be made with all of `usage`.
+    pub fn supports_usage(&self, format: TextureFormat, usage: TextureUsage) -> bool {
+        let mut needed = FormatFeatures::default();
+        for (ours, features) in [
+            (TextureUsage::COPY_SRC, FormatFeatures::COPY_SRC),
+            (TextureUsage::COPY_DST, FormatFeatures::COPY_DST),
+            (TextureUsage::SAMPLED, FormatFeatures::SAMPLED),
+            (TextureUsage::RENDER_TARGET, FormatFeatures::RENDER_TARGET),
+        ] {
+            if usage.contains(ours) {
+                needed = needed | features;
+            }
+        }
+        return self.format_features(format).contains(needed);
+    }
+
+    /// The most samples a render target of `format` can have, 1 if it can't be one.
+    pub fn max_samples(&self, format: TextureFormat) -> u32 {
+        if !self.supports_render_target(format) {
+            return 1;
+        }
+        if format.is_depth() {
+            return self.depth_samples.max();
+        }
+        return self.color_samples.max();
+    }
+
+    /// The most samples, up to `wanted`, that colour and depth targets can both have, for drawing with a depth
+    /// buffer. Always at least 1.
+    pub fn target_samples(&self, wanted: u32) -> u32 {
+        let mut samples = 1 << (31 - wanted.max(1).leading_zeros());
+        while samples > 1
+            && !(self.color_samples.supports(samples) && self.depth_samples.supports(samples))
+        {
+            samples /= 2;
+        }
+        return samples;
+    }
+
+    /// Check a texture can be made before making it.
+    pub fn check_texture(&self, desc: &TextureDesc) -> Result<(), CapsError> {
+        if desc.width > self.max_texture_2d || desc.height > self.max_texture_2d {
+            return Err(CapsError::TooBig {
+                max: self.max_texture_2d,
+            });
+        }
+        if !self.supports_usage(desc.format, desc.usage) {
+            return Err(CapsError::Unsupported {
+                format: desc.format,
+                usage: desc.usage,
+            });
+        }
+        if desc.samples != 1 {
+            let counts = match desc.format.is_depth() {
+                true => self.depth_samples,
+                false => self.color_samples,
+            };
+            let target = desc.usage.contains(TextureUsage::RENDER_TARGET);
+            if !target || !counts.supports(desc.samples) {
+                return Err(CapsError::Samples {
+                    samples: desc.samples,
+                    max: if target {
+                        self.max_samples(desc.format)
+                    } else {
+                        1
+                    },
+                });
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CapsError, CompressionFamily, DeviceCaps, FormatFeatures, SampleCounts};
+    use crate::{
+        render::hal::{Device, Limits, TextureDesc, TextureFormat, TextureUsage},
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn queries() {
+        // About what a low end phone has.
+        let mut caps = DeviceCaps {
+            limits: Limits {
+                min_uniform_offset_alignment: 256,
+                max_uniform_range: 16384,
+            },
+            max_texture_2d: 4096,
+            max_texture_3d: 512,
+            max_texture_cube: 4096,
+            max_texture_layers: 256,
+            max_color_attachments: 4,
+            color_samples: SampleCounts(1 | 4),
+            depth_samples: SampleCounts(1 | 4),
+            max_anisotropy: 1.0,
+            max_compute_workgroup_size: [128, 128, 64],
+            max_compute_invocations: 128,
+            compression: vec![CompressionFamily::Etc2, CompressionFamily::Astc],
+            bindless: None,
+            formats: [FormatFeatures::default(); TextureFormat::ALL.len()],
+        };
+        let copy = FormatFeatures::COPY_SRC | FormatFeatures::COPY_DST;
+        let color =
+            FormatFeatures::SAMPLED | FormatFeatures::FILTER | FormatFeatures::RENDER_TARGET;
+        caps.formats[TextureFormat::Rgba8Unorm.index()] = color | copy | FormatFeatures::STORAGE;
+        caps.formats[TextureFormat::Rgba16Float.index()] = FormatFeatures::SAMPLED | copy;
+
+        assert!(caps.supports_storage(TextureFormat::Rgba8Unorm));
+        assert!(!caps.supports_storage(TextureFormat::Rgba16Float));
+        assert!(!caps.supports_filtering(TextureFormat::Rgba16Float));
+        assert_eq!(caps.max_samples(TextureFormat::Rgba8Unorm), 4);
+        // Can't be drawn to, so can't be multisampled either.
+        assert_eq!(caps.max_samples(TextureFormat::Rgba16Float), 1);
+        assert!(caps.color_samples.supports(4));
+        assert!(!caps.color_samples.supports(2));
+        assert!(!caps.supports_compression(CompressionFamily::Bc));
+
+        let desc = |width, format, usage| TextureDesc {
+            width,
+            height: 16,
+            format,
+            usage,
+            samples: 1,
+        };
+        let target = TextureUsage::RENDER_TARGET | TextureUsage::COPY_SRC;
+        assert_eq!(
+            caps.check_texture(&desc(1024, TextureFormat::Rgba8Unorm, target)),
+            Ok(())
+        );
+        assert_eq!(
+            caps.check_texture(&desc(8192, TextureFormat::Rgba8Unorm, target)),
+            Err(CapsError::TooBig { max: 4096 })
+        );
+        assert!(
+            caps.check_texture(&desc(1024, TextureFormat::Rgba16Float, target))
+                .is_err()
+        );
+        let msaa = |samples, usage| TextureDesc {
+            samples,
+            ..desc(1024, TextureFormat::Rgba8Unorm, usage)
+        };
+        assert_eq!(caps.check_texture(&msaa(4, target)), Ok(()));
+        assert_eq!(
+            caps.check_texture(&msaa(2, target)),
+            Err(CapsError::Samples { samples: 2, max: 4 })
+        );
+        assert_eq!(
+            caps.check_texture(&msaa(4, TextureUsage::SAMPLED)),
+            Err(CapsError::Samples { samples: 4, max: 1 })
+        );
+
+        // 8x isn't there, and neither is 2x to fall back on.
+        assert_eq!(caps.target_samples(8), 4);
+        assert_eq!(caps.target_samples(3), 1);
+        caps.depth_samples = SampleCounts(1);
+        assert_eq!(caps.target_samples(4), 1);
+        assert_eq!(caps.target_samples(0), 1);
+    }
+
+    #[test]
+    pub fn device_meets_minimums() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let caps = headless.device().caps();
+        // What Vulkan 1.3 guarantees.
+        assert!(caps.max_texture_2d >= 4096);
+        assert!(caps.color_samples.supports(4));
+        assert!(caps.supports_usage(
+            TextureFormat::Rgba8Unorm,
+            TextureUsage::SAMPLED | TextureUsage::RENDER_TARGET | TextureUsage::COPY_SRC
+        ));
+        assert!(caps.supports_filtering(TextureFormat::Rgba8Srgb));
+    }
+}
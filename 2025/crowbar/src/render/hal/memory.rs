@@ -0,0 +1,346 @@
+//! What device memory holds, block by block: for the memory overlay, and for anyone chasing fragmentation.
+
+use std::collections::BTreeMap;
+
+/// What an allocation is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    Buffer,
+    Upload,
+    Readback,
+    Texture,
+    RenderTarget,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 5] = [
+        MemoryCategory::Buffer,
+        MemoryCategory::Upload,
+        MemoryCategory::Readback,
+        MemoryCategory::Texture,
+        MemoryCategory::RenderTarget,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryCategory::Buffer => "Buffers",
+            MemoryCategory::Upload => "Upload",
+            MemoryCategory::Readback => "Readback",
+            MemoryCategory::Texture => "Textures",
+            MemoryCategory::RenderTarget => "Render targets",
+        }
+    }
+}
+
+/// Who asked for an allocation, like a render graph pass. An index into [`MemoryReport::owners`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemoryOwner(u32);
+
+/// One resource's piece of a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubAllocation {
+    pub offset: u64,
+    pub size: u64,
+    pub category: MemoryCategory,
+    pub owner: Option<MemoryOwner>,
+}
+
+/// One allocation from the driver, which resources get suballocated out of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockReport {
+    pub memory_type: u32,
+    pub size: u64,
+    /// Made for a single resource, because the driver asked for that or it didn't fit in a shared block.
+    pub dedicated: bool,
+    /// Sorted by offset.
+    pub allocations: Vec<SubAllocation>,
+}
+
+impl BlockReport {
+    pub fn used(&self) -> u64 {
+        self.allocations.iter().map(|a| a.size).sum()
+    }
+
+    /// The gaps between allocations, as (offset, size).
+    pub fn free_ranges(&self) -> Vec<(u64, u64)> {
+        let mut free = Vec::new();
+        let mut end = 0;
+        for a in &self.allocations {
+            if a.offset > end {
+                free.push((end, a.offset - end));
+            }
+            end = end.max(a.offset + a.size);
+        }
+        if self.size > end {
+            free.push((end, self.size - end));
+        }
+        return free;
+    }
+
+    /// 0 when the free space is in one piece, heading for 1 the more it's split up.
+    /// A fragmented block can fail an allocation it has room for in total.
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.free_ranges();
+        let total: u64 = free.iter().map(|(_, size)| size).sum();
+        let largest = free.iter().map(|(_, size)| *size).max().unwrap_or(0);
+        if total == 0 {
+            return 0.0;
+        }
+        return 1.0 - largest as f32 / total as f32;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapReport {
+    pub size: u64,
+    pub device_local: bool,
+    pub blocks: Vec<BlockReport>,
+}
+
+impl HeapReport {
+    /// Taken from the driver, in use or not.
+    pub fn reserved(&self) -> u64 {
+        self.blocks.iter().map(|b| b.size).sum()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.blocks.iter().map(BlockReport::used).sum()
+    }
+}
+
+/// Everything a device has allocated, by heap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapReport>,
+    /// Names for the [`MemoryOwner`]s allocations have.
+    pub owners: Vec<String>,
+}
+
+impl MemoryReport {
+    pub fn reserved(&self) -> u64 {
+        self.heaps.iter().map(HeapReport::reserved).sum()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.heaps.iter().map(HeapReport::used).sum()
+    }
+
+    /// Bytes in use per category.
+    pub fn by_category(&self) -> BTreeMap<&'static str, u64> {
+        let mut sizes = BTreeMap::new();
+        for block in self.heaps.iter().flat_map(|h| &h.blocks) {
+            for a in &block.allocations {
+                *sizes.entry(a.category.name()).or_default() += a.size;
+            }
+        }
+        return sizes;
+    }
+
+    pub fn owner_name(&self, owner: MemoryOwner) -> &str {
+        &self.owners[owner.0 as usize]
+    }
+
+    /// Bytes in use per owner, biggest first. What nobody claimed isn't in it.
+    pub fn by_owner(&self) -> Vec<(&str, u64)> {
+        let mut sizes = vec![0; self.owners.len()];
+        for block in self.heaps.iter().flat_map(|h| &h.blocks) {
+            for a in &block.allocations {
+                if let Some(owner) = a.owner {
+                    sizes[owner.0 as usize] += a.size;
+                }
+            }
+        }
+        let mut owners: Vec<_> = self
+            .owners
+            .iter()
+            .map(String::as_str)
+            .zip(sizes)
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        owners.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        return owners;
+    }
+}
+
+/// Keeps track of a suballocating backend's blocks and what's in them.
+/// Blocks come and go with their allocations, so empty blocks an allocator keeps around for reuse don't show.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    /// By the backend's handle for the block.
+    blocks: BTreeMap<u64, BlockReport>,
+    /// Every owner there's been. Only ever grows, there are only so many passes.
+    owners: Vec<String>,
+    owner: Option<MemoryOwner>,
+}
+
+impl MemoryTracker {
+    /// Put what's allocated from now on down to `owner`, or nobody.
+    pub fn set_owner(&mut self, owner: Option<&str>) {
+        self.owner = owner.map(|name| {
+            let at = match self.owners.iter().position(|o| o == name) {
+                Some(at) => at,
+                None => {
+                    self.owners.push(name.to_string());
+                    self.owners.len() - 1
+                }
+            };
+            MemoryOwner(at as u32)
+        });
+    }
+
+    /// Who allocations are being put down to.
+    pub fn owner(&self) -> Option<MemoryOwner> {
+        self.owner
+    }
+
+    pub fn add(
+        &mut self,
+        block: u64,
+        memory_type: u32,
+        block_size: u64,
+        dedicated: bool,
+        allocation: SubAllocation,
+    ) {
+        let block = self.blocks.entry(block).or_insert_with(|| BlockReport {
+            memory_type,
+            size: block_size,
+            dedicated,
+            allocations: Vec::new(),
+        });
+        let at = block
+            .allocations
+            .partition_point(|a| a.offset < allocation.offset);
+        block.allocations.insert(at, allocation);
+    }
+
+    pub fn remove(&mut self, block: u64, offset: u64) {
+        let Some(report) = self.blocks.get_mut(&block) else {
+            return;
+        };
+        report.allocations.retain(|a| a.offset != offset);
+        if report.allocations.is_empty() {
+            self.blocks.remove(&block);
+        }
+    }
+
+    /// Sort the blocks into heaps. `heaps` has each heap's size and whether it's device local,
+    /// `type_heaps` each memory type's heap.
+    pub fn report(&self, heaps: &[(u64, bool)], type_heaps: &[u32]) -> MemoryReport {
+        let mut report = MemoryReport {
+            heaps: heaps
+                .iter()
+                .map(|&(size, device_local)| HeapReport {
+                    size,
+                    device_local,
+                    blocks: Vec::new(),
+                })
+                .collect(),
+            owners: self.owners.clone(),
+        };
+        for block in self.blocks.values() {
+            let heap = type_heaps
+                .get(block.memory_type as usize)
+                .and_then(|&h| report.heaps.get_mut(h as usize));
+            if let Some(heap) = heap {
+                heap.blocks.push(block.clone());
+            }
+        }
+        return report;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryCategory, MemoryTracker, SubAllocation};
+
+    fn alloc(offset: u64, size: u64) -> SubAllocation {
+        SubAllocation {
+            offset,
+            size,
+            category: MemoryCategory::Texture,
+            owner: None,
+        }
+    }
+
+    #[test]
+    pub fn blocks_by_heap() {
+        let mut tracker = MemoryTracker::default();
+        tracker.add(1, 0, 100, false, alloc(40, 10));
+        tracker.add(1, 0, 100, false, alloc(0, 20));
+        tracker.add(1, 0, 100, false, alloc(70, 10));
+        tracker.add(2, 1, 64, true, alloc(0, 64));
+
+        let report = tracker.report(&[(1000, true), (500, false)], &[0, 1]);
+        assert_eq!(report.reserved(), 164);
+        assert_eq!(report.used(), 104);
+
+        let block = &report.heaps[0].blocks[0];
+        assert_eq!(block.allocations[1].offset, 40);
+        assert_eq!(block.free_ranges(), vec![(20, 20), (50, 20), (80, 20)]);
+        // 60 free, but no more than 20 in one go.
+        assert!((block.fragmentation() - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(report.heaps[1].blocks[0].fragmentation(), 0.0);
+
+        tracker.remove(2, 0);
+        tracker.remove(1, 40);
+        let report = tracker.report(&[(1000, true), (500, false)], &[0, 1]);
+        assert!(report.heaps[1].blocks.is_empty());
+        assert_eq!(report.heaps[0].blocks[0].allocations.len(), 2);
+    }
+
+    #[test]
+    pub fn attributes_to_owners() {
+        let mut tracker = MemoryTracker::default();
+        tracker.set_owner(Some("bloom"));
+        let bloom = tracker.owner();
+        tracker.add(
+            1,
+            0,
+            100,
+            false,
+            SubAllocation {
+                owner: bloom,
+                ..alloc(0, 10)
+            },
+        );
+        tracker.set_owner(Some("ssao"));
+        let ssao = tracker.owner();
+        tracker.add(
+            1,
+            0,
+            100,
+            false,
+            SubAllocation {
+                owner: ssao,
+                ..alloc(10, 30)
+            },
+        );
+        tracker.set_owner(Some("bloom"));
+        assert_eq!(tracker.owner(), bloom);
+        tracker.add(
+            1,
+            0,
+            100,
+            false,
+            SubAllocation {
+                owner: bloom,
+                ..alloc(40, 5)
+            },
+        );
+        tracker.set_owner(None);
+        tracker.add(
+            1,
+            0,
+            100,
+            false,
+            SubAllocation {
+                owner: tracker.owner(),
+                ..alloc(50, 50)
+            },
+        );
+
+        let report = tracker.report(&[(1000, true)], &[0]);
+        assert_eq!(report.by_owner(), vec![("ssao", 30), ("bloom", 15)]);
+        assert_eq!(report.owner_name(bloom.unwrap()), "bloom");
+    }
+}
@@ -0,0 +1,1533 @@
+//! The Vulkan backend, on Vulkan 1.3 with dynamic rendering.
+
+use std::{
+    cell::{Cell, RefCell},
+    ffi::CStr,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+};
+
+use ash::{
+    amd,
+    prelude::VkResult,
+    vk::{self, Handle},
+};
+use gpu_allocator::{
+    AllocationError, AllocationSizes, AllocatorDebugSettings, MemoryLocation as GpuMemoryLocation,
+    vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator, AllocatorCreateDesc},
+};
+
+use super::{
+    Attachment, BufferDesc, BufferUsage, CommandEncoder, Device, Limits, MemoryLocation,
+    TextureDesc, TextureFormat, TextureState, TextureUsage,
+    caps::{BindlessLimits, CompressionFamily, DeviceCaps, FormatFeatures, SampleCounts},
+    memory::{MemoryCategory, MemoryReport, MemoryTracker, SubAllocation},
+};
+use crate::{
+    color::LinearColor,
+    render::{
+        alloc::VK_ALLOCATOR_CALLBACKS,
+        compute::ComputePipeline,
+        rendering::{self, ActiveRendering},
+        validation::DebugMessenger,
+    },
+};
+
+/// Blocks GPU only memory is suballocated from. Anything bigger gets a block to itself.
+const DEVICE_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+/// Blocks for memory the CPU can see, which there's usually less of.
+const HOST_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// # Safety
+/// Nothing else made from `instance` can be left.
+unsafe fn destroy_instance(instance: &ash::Instance, messenger: Option<DebugMessenger>) {
+    // SAFETY: Passed on to the caller. The messenger has to go before the instance it reports on.
+    unsafe {
+        if let Some(messenger) = messenger {
+            messenger.destroy();
+        }
+        instance.destroy_instance(allocs());
+    }
+}
+
+pub fn vk_format(format: TextureFormat) -> vk::Format {
+    match format {
+        TextureFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        TextureFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
+        TextureFormat::Bgra8Srgb => vk::Format::B8G8R8A8_SRGB,
+        TextureFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        TextureFormat::Rg16Float => vk::Format::R16G16_SFLOAT,
+        TextureFormat::Depth32Float => vk::Format::D32_SFLOAT,
+        TextureFormat::Depth24Stencil8 => vk::Format::D24_UNORM_S8_UINT,
+        TextureFormat::Depth32FloatStencil8 => vk::Format::D32_SFLOAT_S8_UINT,
+        TextureFormat::Bc1Unorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        TextureFormat::Bc1Srgb => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        TextureFormat::Bc3Unorm => vk::Format::BC3_UNORM_BLOCK,
+        TextureFormat::Bc3Srgb => vk::Format::BC3_SRGB_BLOCK,
+        TextureFormat::Bc7Unorm => vk::Format::BC7_UNORM_BLOCK,
+        TextureFormat::Bc7Srgb => vk::Format::BC7_SRGB_BLOCK,
+        TextureFormat::Etc2Rgb8Unorm => vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+        TextureFormat::Etc2Rgb8Srgb => vk::Format::ETC2_R8G8B8_SRGB_BLOCK,
+        TextureFormat::Etc2Rgba8Unorm => vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+        TextureFormat::Etc2Rgba8Srgb => vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+        TextureFormat::Astc4x4Unorm => vk::Format::ASTC_4X4_UNORM_BLOCK,
+        TextureFormat::Astc4x4Srgb => vk::Format::ASTC_4X4_SRGB_BLOCK,
+    }
+}
+
+/// Every aspect a texture of `format` has, which is what barriers on it need.
+pub(crate) fn aspect(format: TextureFormat) -> vk::ImageAspectFlags {
+    if format.has_stencil() {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else if format.is_depth() {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+/// # Safety
+/// `physical_device` has to be from `instance`.
+unsafe fn read_caps(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> DeviceCaps {
+    // SAFETY: Only queries, passed on to the caller.
+    let (props, features) = unsafe {
+        (
+            instance.get_physical_device_properties(physical_device),
+            instance.get_physical_device_features(physical_device),
+        )
+    };
+    let limits = &props.limits;
+
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+    // SAFETY: As above, and the instance is 1.3.
+    unsafe {
+        instance.get_physical_device_properties2(
+            physical_device,
+            &mut vk::PhysicalDeviceProperties2::default().push_next(&mut indexing),
+        );
+        instance.get_physical_device_features2(
+            physical_device,
+            &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut features12),
+        );
+    }
+    let bindless = bindless_features(&features12)
+        .iter()
+        .all(|&f| f == vk::TRUE)
+        .then(|| BindlessLimits {
+            max_textures: indexing
+                .max_descriptor_set_update_after_bind_sampled_images
+                .min(indexing.max_per_stage_descriptor_update_after_bind_sampled_images),
+            max_storage_buffers: indexing
+                .max_descriptor_set_update_after_bind_storage_buffers
+                .min(indexing.max_per_stage_descriptor_update_after_bind_storage_buffers),
+        });
+
+    let mut formats = [FormatFeatures::default(); TextureFormat::ALL.len()];
+    for format in TextureFormat::ALL {
+        // SAFETY: As above.
+        let theirs = unsafe {
+            instance.get_physical_device_format_properties(physical_device, vk_format(format))
+        }
+        .optimal_tiling_features;
+        let mut ours = FormatFeatures::default();
+        for (flag, feature) in [
+            (
+                vk::FormatFeatureFlags::SAMPLED_IMAGE,
+                FormatFeatures::SAMPLED,
+            ),
+            (
+                vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+                FormatFeatures::FILTER,
+            ),
+            (
+                vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+                FormatFeatures::RENDER_TARGET,
+            ),
+            (
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+                FormatFeatures::RENDER_TARGET,
+            ),
+            (
+                vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND,
+                FormatFeatures::BLEND,
+            ),
+            (
+                vk::FormatFeatureFlags::STORAGE_IMAGE,
+                FormatFeatures::STORAGE,
+            ),
+            (
+                vk::FormatFeatureFlags::TRANSFER_SRC,
+                FormatFeatures::COPY_SRC,
+            ),
+            (
+                vk::FormatFeatureFlags::TRANSFER_DST,
+                FormatFeatures::COPY_DST,
+            ),
+        ] {
+            if theirs.contains(flag) {
+                ours = ours | feature;
+            }
+        }
+        formats[format.index()] = ours;
+    }
+
+    return DeviceCaps {
+        limits: Limits {
+            min_uniform_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            max_uniform_range: limits.max_uniform_buffer_range as u64,
+        },
+        max_texture_2d: limits.max_image_dimension2_d,
+        max_texture_3d: limits.max_image_dimension3_d,
+        max_texture_cube: limits.max_image_dimension_cube,
+        max_texture_layers: limits.max_image_array_layers,
+        max_color_attachments: limits.max_color_attachments,
+        color_samples: SampleCounts(limits.framebuffer_color_sample_counts.as_raw()),
+        depth_samples: SampleCounts(limits.framebuffer_depth_sample_counts.as_raw()),
+        max_anisotropy: if features.sampler_anisotropy == vk::TRUE {
+            limits.max_sampler_anisotropy
+        } else {
+            1.0
+        },
+        max_compute_workgroup_size: limits.max_compute_work_group_size,
+        max_compute_invocations: limits.max_compute_work_group_invocations,
+        compression: [
+            (features.texture_compression_bc, CompressionFamily::Bc),
+            (features.texture_compression_etc2, CompressionFamily::Etc2),
+            (
+                features.texture_compression_astc_ldr,
+                CompressionFamily::Astc,
+            ),
+        ]
+        .into_iter()
+        .filter(|(supported, _)| *supported == vk::TRUE)
+        .map(|(_, family)| family)
+        .collect(),
+        bindless,
+        formats,
+    };
+}
+
+/// The descriptor indexing features bindless tables need, formerly `VK_EXT_descriptor_indexing`.
+fn bindless_features(features: &vk::PhysicalDeviceVulkan12Features) -> [vk::Bool32; 6] {
+    [
+        features.runtime_descriptor_array,
+        features.descriptor_binding_partially_bound,
+        features.descriptor_binding_sampled_image_update_after_bind,
+        features.descriptor_binding_storage_buffer_update_after_bind,
+        features.shader_sampled_image_array_non_uniform_indexing,
+        features.shader_storage_buffer_array_non_uniform_indexing,
+    ]
+}
+
+/// Layout, and the stages and accesses to synchronize with, for a texture with `aspect` in `state`.
+pub(crate) fn state_info(
+    state: TextureState,
+    aspect: vk::ImageAspectFlags,
+) -> (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags) {
+    match state {
+        TextureState::Undefined => (
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+        TextureState::RenderTarget if aspect.contains(vk::ImageAspectFlags::STENCIL) => (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        TextureState::RenderTarget if aspect.contains(vk::ImageAspectFlags::DEPTH) => (
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        TextureState::RenderTarget => (
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+        TextureState::CopySrc => (
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ,
+        ),
+        TextureState::CopyDst => (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_WRITE,
+        ),
+        TextureState::ShaderRead => (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        ),
+        // Presenting waits on a semaphore, so there's nothing to wait on or for here.
+        TextureState::Present => (
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::empty(),
+        ),
+    }
+}
+
+pub struct VulkanBuffer {
+    pub buffer: vk::Buffer,
+    allocation: Allocation,
+    pub size: u64,
+}
+
+pub struct VulkanTexture {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    allocation: Allocation,
+    pub format: TextureFormat,
+    pub extent: vk::Extent2D,
+}
+
+impl VulkanTexture {
+    /// A texture standing in for `image` and `view`, which belong to something else, like a swapchain, so they can
+    /// go through hal and the render graph. [`Device::destroy_texture`] leaves it alone, its owner destroys it.
+    pub fn borrowed(
+        image: vk::Image,
+        view: vk::ImageView,
+        format: TextureFormat,
+        extent: vk::Extent2D,
+    ) -> VulkanTexture {
+        VulkanTexture {
+            image,
+            view,
+            allocation: Allocation::default(),
+            format,
+            extent,
+        }
+    }
+
+    /// A [`VulkanTexture::borrowed`] stand in for this one, for handing on while it's borrowed elsewhere.
+    pub fn stand_in(&self) -> VulkanTexture {
+        VulkanTexture::borrowed(self.image, self.view, self.format, self.extent)
+    }
+}
+
+pub struct VulkanFence {
+    fence: vk::Fence,
+    cmd: vk::CommandBuffer,
+    /// Whether it was submitted to the compute queue, and the command buffer came from its pool.
+    compute: bool,
+}
+
+/// A queue from a family of its own that only does compute, to run alongside the graphics queue.
+struct ComputeQueue {
+    queue: vk::Queue,
+    family: u32,
+    command_pool: vk::CommandPool,
+}
+
+/// A device, and the instance it came from. Destroys both when dropped.
+pub struct VulkanDevice {
+    instance: ash::Instance,
+    /// Destroyed last, to hear about everything up to the instance going.
+    messenger: Option<DebugMessenger>,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    queue: vk::Queue,
+    queue_family: u32,
+    command_pool: vk::CommandPool,
+    compute: Option<ComputeQueue>,
+    memory_props: vk::PhysicalDeviceMemoryProperties,
+    caps: DeviceCaps,
+    // Dropped by hand, before the device it allocates from.
+    allocator: ManuallyDrop<RefCell<Allocator>>,
+    memory: RefCell<MemoryTracker>,
+    // The command pool isn't synchronized, so neither is the device.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl VulkanDevice {
+    /// Create a device on `physical_device` with one queue from `queue_family`, which must do graphics, another
+    /// from `compute_family` if it's given and different, and `extensions` enabled. Takes ownership of `instance`, and the `messenger` made from it, destroying them if
+    /// this fails.
+    pub fn new(
+        instance: ash::Instance,
+        messenger: Option<DebugMessenger>,
+        physical_device: vk::PhysicalDevice,
+        queue_family: u32,
+        compute_family: Option<u32>,
+        extensions: &[&CStr],
+    ) -> VkResult<VulkanDevice> {
+        let compute_family = compute_family.filter(|&f| f != queue_family);
+        // SAFETY: Everything is created from the instance we were given, and destroyed on failure.
+        unsafe {
+            let caps = read_caps(&instance, physical_device);
+            let mut features13 =
+                vk::PhysicalDeviceVulkan13Features::default().dynamic_rendering(true);
+            let bindless = caps.bindless.is_some();
+            let mut features12 = vk::PhysicalDeviceVulkan12Features::default()
+                .runtime_descriptor_array(bindless)
+                .descriptor_binding_partially_bound(bindless)
+                .descriptor_binding_sampled_image_update_after_bind(bindless)
+                .descriptor_binding_storage_buffer_update_after_bind(bindless)
+                .shader_sampled_image_array_non_uniform_indexing(bindless)
+                .shader_storage_buffer_array_non_uniform_indexing(bindless);
+            // Whatever block compression there is, so loaders never have to transcode what it could sample.
+            let features = vk::PhysicalDeviceFeatures::default()
+                .texture_compression_bc(caps.supports_compression(CompressionFamily::Bc))
+                .texture_compression_etc2(caps.supports_compression(CompressionFamily::Etc2))
+                .texture_compression_astc_ldr(caps.supports_compression(CompressionFamily::Astc));
+            let mut queues = vec![
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(queue_family)
+                    .queue_priorities(&[1.0]),
+            ];
+            if let Some(family) = compute_family {
+                queues.push(
+                    vk::DeviceQueueCreateInfo::default()
+                        .queue_family_index(family)
+                        .queue_priorities(&[1.0]),
+                );
+            }
+            // Only asked for where it's supported, see `breadcrumbs::wanted_extensions`.
+            let mut coherent =
+                vk::PhysicalDeviceCoherentMemoryFeaturesAMD::default().device_coherent_memory(true);
+            let coherent_wanted = extensions.contains(&amd::device_coherent_memory::NAME);
+            let extensions: Vec<_> = extensions.iter().map(|e| e.as_ptr()).collect();
+            let mut info = vk::DeviceCreateInfo::default()
+                .queue_create_infos(&queues)
+                .enabled_extension_names(&extensions)
+                .enabled_features(&features)
+                .push_next(&mut features12)
+                .push_next(&mut features13);
+            if coherent_wanted {
+                info = info.push_next(&mut coherent);
+            }
+            let device = match instance.create_device(physical_device, &info, allocs()) {
+                Ok(device) => device,
+                Err(e) => {
+                    destroy_instance(&instance, messenger);
+                    return Err(e);
+                }
+            };
+
+            let pool_for = |family| {
+                device.create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(family)
+                        .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                    allocs(),
+                )
+            };
+            let command_pool = match pool_for(queue_family) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    device.destroy_device(allocs());
+                    destroy_instance(&instance, messenger);
+                    return Err(e);
+                }
+            };
+            let compute = match compute_family.map(pool_for).transpose() {
+                Ok(pool) => compute_family
+                    .zip(pool)
+                    .map(|(family, command_pool)| ComputeQueue {
+                        queue: device.get_device_queue(family, 0),
+                        family,
+                        command_pool,
+                    }),
+                Err(e) => {
+                    device.destroy_command_pool(command_pool, allocs());
+                    device.destroy_device(allocs());
+                    destroy_instance(&instance, messenger);
+                    return Err(e);
+                }
+            };
+
+            let allocator = match Allocator::new(&AllocatorCreateDesc {
+                instance: instance.clone(),
+                device: device.clone(),
+                physical_device,
+                debug_settings: AllocatorDebugSettings::default(),
+                buffer_device_address: false,
+                allocation_sizes: AllocationSizes::new(DEVICE_BLOCK_SIZE, HOST_BLOCK_SIZE),
+            }) {
+                Ok(allocator) => allocator,
+                Err(e) => {
+                    log::error!("Couldn't create the device memory allocator: {e}");
+                    if let Some(compute) = &compute {
+                        device.destroy_command_pool(compute.command_pool, allocs());
+                    }
+                    device.destroy_command_pool(command_pool, allocs());
+                    device.destroy_device(allocs());
+                    destroy_instance(&instance, messenger);
+                    return Err(vk::Result::ERROR_INITIALIZATION_FAILED);
+                }
+            };
+
+            return Ok(VulkanDevice {
+                caps,
+                allocator: ManuallyDrop::new(RefCell::new(allocator)),
+                memory: RefCell::default(),
+                queue: device.get_device_queue(queue_family, 0),
+                queue_family,
+                memory_props: instance.get_physical_device_memory_properties(physical_device),
+                instance,
+                messenger,
+                physical_device,
+                device,
+                command_pool,
+                compute,
+                _not_sync: PhantomData,
+            });
+        }
+    }
+
+    /// The ash device, for anything the HAL doesn't cover yet.
+    pub fn raw(&self) -> &ash::Device {
+        &self.device
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn queue(&self) -> vk::Queue {
+        self.queue
+    }
+
+    pub fn queue_family(&self) -> u32 {
+        self.queue_family
+    }
+
+    /// The queue [`Device::begin_compute_commands`] submits to, when the device has one apart from
+    /// [`VulkanDevice::queue`].
+    pub fn compute_queue(&self) -> Option<vk::Queue> {
+        self.compute.as_ref().map(|c| c.queue)
+    }
+
+    pub fn compute_queue_family(&self) -> Option<u32> {
+        self.compute.as_ref().map(|c| c.family)
+    }
+
+    fn command_pool(&self, compute: bool) -> vk::CommandPool {
+        match &self.compute {
+            Some(c) if compute => c.command_pool,
+            _ => self.command_pool,
+        }
+    }
+
+    fn submit_queue(&self, compute: bool) -> vk::Queue {
+        match &self.compute {
+            Some(c) if compute => c.queue,
+            _ => self.queue,
+        }
+    }
+
+    /// Record through hal into `cmd`, a primary command buffer for the graphics queue from somewhere else, like the
+    /// renderer's frame. It stays its owner's: the encoder never frees it, and can't go to [`Device::submit`].
+    ///
+    /// # Safety
+    /// `cmd` must be this device's, recording, and outlive the encoder.
+    pub unsafe fn encoder_for(&self, cmd: vk::CommandBuffer) -> VulkanEncoder<'_> {
+        VulkanEncoder {
+            device: self,
+            cmd,
+            compute: false,
+            borrowed: true,
+            rendering: None,
+        }
+    }
+
+    fn begin_commands_on(&self, compute: bool) -> VkResult<VulkanEncoder<'_>> {
+        // SAFETY: The pool is ours, and the device isn't Sync, so nobody else is using it.
+        unsafe {
+            let cmd = self.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool(compute))
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )?[0];
+            // Made first, so it frees the command buffer if beginning fails.
+            let encoder = VulkanEncoder {
+                device: self,
+                cmd,
+                compute,
+                borrowed: false,
+                rendering: None,
+            };
+            self.device.begin_command_buffer(
+                cmd,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+            return Ok(encoder);
+        }
+    }
+
+    /// Suballocate memory for a resource, noting it down for [`Device::memory_report`].
+    fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        location: MemoryLocation,
+        category: MemoryCategory,
+        linear: bool,
+        allocation_scheme: AllocationScheme,
+    ) -> VkResult<Allocation> {
+        let location = match location {
+            MemoryLocation::Device => GpuMemoryLocation::GpuOnly,
+            MemoryLocation::Upload => GpuMemoryLocation::CpuToGpu,
+            MemoryLocation::Readback => GpuMemoryLocation::GpuToCpu,
+        };
+        let allocation = self
+            .allocator
+            .borrow_mut()
+            .allocate(&AllocationCreateDesc {
+                name: category.name(),
+                requirements,
+                location,
+                linear,
+                allocation_scheme,
+            })
+            .map_err(|e| match e {
+                AllocationError::OutOfMemory => vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+                AllocationError::NoCompatibleMemoryTypeFound => {
+                    vk::Result::ERROR_FEATURE_NOT_PRESENT
+                }
+                e => {
+                    log::error!("Device memory allocation failed: {e}");
+                    vk::Result::ERROR_UNKNOWN
+                }
+            })?;
+
+        // gpu-allocator doesn't say which memory type it used, but it takes the first allowed one that has the flags
+        // it wants, so that's also the first allowed one with exactly the flags the allocation ended up with.
+        let flags = allocation.memory_properties();
+        let props = &self.memory_props;
+        let memory_type = (0..props.memory_type_count)
+            .find(|&i| {
+                requirements.memory_type_bits & (1 << i) != 0
+                    && props.memory_types[i as usize].property_flags == flags
+            })
+            .unwrap_or(0);
+        let shared_size = if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            HOST_BLOCK_SIZE
+        } else {
+            DEVICE_BLOCK_SIZE
+        };
+        // Too big to share a block gets one of its own too.
+        let dedicated = allocation.is_dedicated() || allocation.size() > shared_size;
+
+        // SAFETY: The handle is only used as a key.
+        let block = unsafe { allocation.memory() }.as_raw();
+        let mut memory = self.memory.borrow_mut();
+        let owner = memory.owner();
+        memory.add(
+            block,
+            memory_type,
+            if dedicated {
+                allocation.size()
+            } else {
+                shared_size
+            },
+            dedicated,
+            SubAllocation {
+                offset: allocation.offset(),
+                size: allocation.size(),
+                category,
+                owner,
+            },
+        );
+        return Ok(allocation);
+    }
+
+    fn free(&self, allocation: Allocation) {
+        // SAFETY: The handle is only used as a key.
+        let block = unsafe { allocation.memory() }.as_raw();
+        self.memory.borrow_mut().remove(block, allocation.offset());
+        if let Err(e) = self.allocator.borrow_mut().free(allocation) {
+            log::error!("Freeing device memory failed: {e}");
+        }
+    }
+
+    /// Hand `f` the `len` bytes of `buffer` from `offset`. Host visible memory stays mapped, so there's no mapping here.
+    ///
+    /// # Safety
+    /// The buffer must be host visible, and not in use by the GPU.
+    unsafe fn with_mapped(
+        &self,
+        buffer: &VulkanBuffer,
+        offset: u64,
+        len: usize,
+        f: impl FnOnce(*mut u8),
+    ) -> VkResult<()> {
+        assert!(
+            offset + len as u64 <= buffer.size,
+            "Mapping past the end of a buffer"
+        );
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mapped = buffer
+            .allocation
+            .mapped_ptr()
+            .ok_or(vk::Result::ERROR_MEMORY_MAP_FAILED)?;
+        // SAFETY: In bounds, checked above, and the caller vouches for the rest.
+        unsafe {
+            f(mapped.as_ptr().cast::<u8>().add(offset as usize));
+        }
+        return Ok(());
+    }
+}
+
+impl Device for VulkanDevice {
+    type Buffer = VulkanBuffer;
+    type Texture = VulkanTexture;
+    type Fence = VulkanFence;
+    type Semaphore = vk::Semaphore;
+    type Encoder<'a> = VulkanEncoder<'a>;
+    type Error = vk::Result;
+
+    fn caps(&self) -> &DeviceCaps {
+        &self.caps
+    }
+
+    fn create_buffer(&self, desc: &BufferDesc) -> VkResult<VulkanBuffer> {
+        let mut usage = vk::BufferUsageFlags::empty();
+        for (ours, theirs) in [
+            (BufferUsage::COPY_SRC, vk::BufferUsageFlags::TRANSFER_SRC),
+            (BufferUsage::COPY_DST, vk::BufferUsageFlags::TRANSFER_DST),
+            (BufferUsage::VERTEX, vk::BufferUsageFlags::VERTEX_BUFFER),
+            (BufferUsage::INDEX, vk::BufferUsageFlags::INDEX_BUFFER),
+            (BufferUsage::UNIFORM, vk::BufferUsageFlags::UNIFORM_BUFFER),
+            (BufferUsage::STORAGE, vk::BufferUsageFlags::STORAGE_BUFFER),
+            (BufferUsage::INDIRECT, vk::BufferUsageFlags::INDIRECT_BUFFER),
+        ] {
+            if desc.usage.contains(ours) {
+                usage |= theirs;
+            }
+        }
+
+        // Shared between the queues, so compute can work on them without ownership transfers.
+        // Textures are exclusive, and change hands with `CommandEncoder::release_texture`.
+        let families: Vec<_> = match &self.compute {
+            Some(compute) => vec![self.queue_family, compute.family],
+            None => Vec::new(),
+        };
+        let sharing = match families.is_empty() {
+            true => vk::SharingMode::EXCLUSIVE,
+            false => vk::SharingMode::CONCURRENT,
+        };
+
+        // SAFETY: Plain resource creation, undone if it doesn't all work out.
+        unsafe {
+            let buffer = self.device.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(desc.size)
+                    .usage(usage)
+                    .sharing_mode(sharing)
+                    .queue_family_indices(&families),
+                allocs(),
+            )?;
+            let category = match desc.location {
+                MemoryLocation::Device => MemoryCategory::Buffer,
+                MemoryLocation::Upload => MemoryCategory::Upload,
+                MemoryLocation::Readback => MemoryCategory::Readback,
+            };
+            let allocation = match self.allocate(
+                self.device.get_buffer_memory_requirements(buffer),
+                desc.location,
+                category,
+                true,
+                AllocationScheme::GpuAllocatorManaged,
+            ) {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    self.device.destroy_buffer(buffer, allocs());
+                    return Err(e);
+                }
+            };
+            if let Err(e) =
+                self.device
+                    .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+            {
+                self.device.destroy_buffer(buffer, allocs());
+                self.free(allocation);
+                return Err(e);
+            }
+
+            return Ok(VulkanBuffer {
+                buffer,
+                allocation,
+                size: desc.size,
+            });
+        }
+    }
+
+    unsafe fn destroy_buffer(&self, buffer: VulkanBuffer) {
+        // SAFETY: The caller vouches the GPU is done with it.
+        unsafe {
+            self.device.destroy_buffer(buffer.buffer, allocs());
+        }
+        self.free(buffer.allocation);
+    }
+
+    fn create_texture(&self, desc: &TextureDesc) -> VkResult<VulkanTexture> {
+        if let Err(e) = self.caps.check_texture(desc) {
+            log::error!("Can't create a {}x{} texture: {e}", desc.width, desc.height);
+            return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+        }
+        let mut usage = vk::ImageUsageFlags::empty();
+        for (ours, theirs) in [
+            (TextureUsage::COPY_SRC, vk::ImageUsageFlags::TRANSFER_SRC),
+            (TextureUsage::COPY_DST, vk::ImageUsageFlags::TRANSFER_DST),
+            (TextureUsage::SAMPLED, vk::ImageUsageFlags::SAMPLED),
+        ] {
+            if desc.usage.contains(ours) {
+                usage |= theirs;
+            }
+        }
+        if desc.usage.contains(TextureUsage::RENDER_TARGET) {
+            usage |= if desc.format.is_depth() {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            } else {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+            };
+        }
+
+        let format = vk_format(desc.format);
+        let extent = vk::Extent2D {
+            width: desc.width,
+            height: desc.height,
+        };
+
+        // SAFETY: Plain resource creation, undone if it doesn't all work out.
+        unsafe {
+            let image = self.device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(extent.into())
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::from_raw(desc.samples.max(1)))
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                allocs(),
+            )?;
+            // Render targets especially tend to do better with memory of their own, when the driver says so.
+            let mut dedicated = vk::MemoryDedicatedRequirements::default();
+            let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+            self.device.get_image_memory_requirements2(
+                &vk::ImageMemoryRequirementsInfo2::default().image(image),
+                &mut requirements,
+            );
+            let requirements = requirements.memory_requirements;
+            let scheme = if dedicated.prefers_dedicated_allocation == vk::TRUE
+                || dedicated.requires_dedicated_allocation == vk::TRUE
+            {
+                AllocationScheme::DedicatedImage(image)
+            } else {
+                AllocationScheme::GpuAllocatorManaged
+            };
+            let category = if desc.usage.contains(TextureUsage::RENDER_TARGET) {
+                MemoryCategory::RenderTarget
+            } else {
+                MemoryCategory::Texture
+            };
+            let allocation = match self.allocate(
+                requirements,
+                MemoryLocation::Device,
+                category,
+                false,
+                scheme,
+            ) {
+                Ok(allocation) => allocation,
+                Err(e) => {
+                    self.device.destroy_image(image, allocs());
+                    return Err(e);
+                }
+            };
+
+            let view = self
+                .device
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+                .and_then(|_| {
+                    self.device.create_image_view(
+                        &vk::ImageViewCreateInfo::default()
+                            .image(image)
+                            .view_type(vk::ImageViewType::TYPE_2D)
+                            .format(format)
+                            .subresource_range(
+                                // Sampled, a view can only have one aspect, and drawn to its aspects are ignored,
+                                // so depth alone does for a depth stencil texture.
+                                vk::ImageSubresourceRange::default()
+                                    .aspect_mask(
+                                        aspect(desc.format) & !vk::ImageAspectFlags::STENCIL,
+                                    )
+                                    .level_count(1)
+                                    .layer_count(1),
+                            ),
+                        allocs(),
+                    )
+                });
+            let view = match view {
+                Ok(view) => view,
+                Err(e) => {
+                    self.device.destroy_image(image, allocs());
+                    self.free(allocation);
+                    return Err(e);
+                }
+            };
+
+            return Ok(VulkanTexture {
+                image,
+                view,
+                allocation,
+                format: desc.format,
+                extent,
+            });
+        }
+    }
+
+    unsafe fn destroy_texture(&self, texture: VulkanTexture) {
+        // Borrowed, its owner destroys it.
+        if texture.allocation.is_null() {
+            return;
+        }
+        // SAFETY: The caller vouches the GPU is done with it.
+        unsafe {
+            self.device.destroy_image_view(texture.view, allocs());
+            self.device.destroy_image(texture.image, allocs());
+        }
+        self.free(texture.allocation);
+    }
+
+    unsafe fn write_buffer(&self, buffer: &VulkanBuffer, offset: u64, data: &[u8]) -> VkResult<()> {
+        // SAFETY: The mapping is `data.len()` bytes long.
+        unsafe {
+            self.with_mapped(buffer, offset, data.len(), |p| {
+                p.copy_from_nonoverlapping(data.as_ptr(), data.len())
+            })
+        }
+    }
+
+    unsafe fn read_buffer(
+        &self,
+        buffer: &VulkanBuffer,
+        offset: u64,
+        out: &mut [u8],
+    ) -> VkResult<()> {
+        // SAFETY: The mapping is `out.len()` bytes long.
+        unsafe {
+            self.with_mapped(buffer, offset, out.len(), |p| {
+                out.as_mut_ptr().copy_from_nonoverlapping(p, out.len())
+            })
+        }
+    }
+
+    fn begin_commands(&self) -> VkResult<VulkanEncoder<'_>> {
+        self.begin_commands_on(false)
+    }
+
+    fn begin_compute_commands(&self) -> VkResult<VulkanEncoder<'_>> {
+        self.begin_commands_on(true)
+    }
+
+    fn submit_with(
+        &self,
+        mut encoder: VulkanEncoder<'_>,
+        waits: &[&vk::Semaphore],
+        signal: Option<&vk::Semaphore>,
+    ) -> VkResult<VulkanFence> {
+        assert!(
+            !encoder.borrowed,
+            "Borrowed command buffers are submitted by their owner"
+        );
+        // From here on the fence owns the command buffer.
+        let cmd = mem::take(&mut encoder.cmd);
+        let compute = encoder.compute;
+        let pool = self.command_pool(compute);
+
+        // SAFETY: The command buffer is ours and done recording, and gets freed once the fence is waited on.
+        unsafe {
+            let fence = self.device.end_command_buffer(cmd).and_then(|_| {
+                self.device
+                    .create_fence(&vk::FenceCreateInfo::default(), allocs())
+            });
+            let fence = match fence {
+                Ok(fence) => fence,
+                Err(e) => {
+                    self.device.free_command_buffers(pool, &[cmd]);
+                    return Err(e);
+                }
+            };
+
+            let waits: Vec<_> = waits.iter().map(|&&s| s).collect();
+            // Whatever the other queue was up to, nothing here starts until it's done.
+            let stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; waits.len()];
+            let signals: Vec<_> = signal.into_iter().copied().collect();
+            if let Err(e) = self.device.queue_submit(
+                self.submit_queue(compute),
+                &[vk::SubmitInfo::default()
+                    .command_buffers(&[cmd])
+                    .wait_semaphores(&waits)
+                    .wait_dst_stage_mask(&stages)
+                    .signal_semaphores(&signals)],
+                fence,
+            ) {
+                self.device.destroy_fence(fence, allocs());
+                self.device.free_command_buffers(pool, &[cmd]);
+                return Err(e);
+            }
+
+            return Ok(VulkanFence {
+                fence,
+                cmd,
+                compute,
+            });
+        }
+    }
+
+    fn wait(&self, fence: VulkanFence) -> VkResult<()> {
+        // SAFETY: Nothing is freed unless the GPU is done with it.
+        unsafe {
+            let res = self.device.wait_for_fences(&[fence.fence], true, u64::MAX);
+            if res.is_err() {
+                let _ = self.device.device_wait_idle();
+            }
+            self.device.destroy_fence(fence.fence, allocs());
+            self.device
+                .free_command_buffers(self.command_pool(fence.compute), &[fence.cmd]);
+            return res;
+        }
+    }
+
+    fn create_semaphore(&self) -> VkResult<vk::Semaphore> {
+        // SAFETY: Plain object creation.
+        unsafe {
+            self.device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), allocs())
+        }
+    }
+
+    unsafe fn destroy_semaphore(&self, semaphore: vk::Semaphore) {
+        // SAFETY: The caller vouches nothing pending uses it.
+        unsafe { self.device.destroy_semaphore(semaphore, allocs()) };
+    }
+
+    fn wait_idle(&self) {
+        // SAFETY: Always fine.
+        let _ = unsafe { self.device.device_wait_idle() };
+    }
+
+    fn set_memory_owner(&self, owner: Option<&str>) {
+        self.memory.borrow_mut().set_owner(owner);
+    }
+
+    fn memory_report(&self) -> MemoryReport {
+        let props = &self.memory_props;
+        let heaps: Vec<_> = props
+            .memory_heaps_as_slice()
+            .iter()
+            .map(|h| (h.size, h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)))
+            .collect();
+        let type_heaps: Vec<_> = props
+            .memory_types_as_slice()
+            .iter()
+            .map(|t| t.heap_index)
+            .collect();
+        return self.memory.borrow().report(&heaps, &type_heaps);
+    }
+}
+
+impl Drop for VulkanDevice {
+    fn drop(&mut self) {
+        // SAFETY: Everything made from the device was destroyed by whoever made it.
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            if let Some(compute) = &self.compute {
+                self.device
+                    .destroy_command_pool(compute.command_pool, allocs());
+            }
+            self.device
+                .destroy_command_pool(self.command_pool, allocs());
+            // Frees whatever blocks it still has.
+            ManuallyDrop::drop(&mut self.allocator);
+            self.device.destroy_device(allocs());
+            destroy_instance(&self.instance, self.messenger.take());
+        }
+    }
+}
+
+/// A command buffer being recorded. Freed if it's dropped without being submitted.
+pub struct VulkanEncoder<'a> {
+    device: &'a VulkanDevice,
+    cmd: vk::CommandBuffer,
+    /// Recording for the compute queue, if the device has one.
+    compute: bool,
+    /// Recording into someone else's command buffer, see [`VulkanDevice::encoder_for`].
+    borrowed: bool,
+    /// The [`CommandEncoder::begin_rendering`] pass being recorded.
+    rendering: Option<ActiveRendering>,
+}
+
+impl VulkanEncoder<'_> {
+    /// The ash device and command buffer, for anything the HAL doesn't cover yet.
+    pub fn raw(&self) -> (&ash::Device, vk::CommandBuffer) {
+        (&self.device.device, self.cmd)
+    }
+
+    /// Bind descriptor sets for `pipeline`'s next dispatches, starting at set `first`.
+    pub fn bind_compute_sets(
+        &mut self,
+        pipeline: &ComputePipeline,
+        first: u32,
+        sets: &[vk::DescriptorSet],
+    ) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_bind_descriptor_sets(
+                self.cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout(),
+                first,
+                sets,
+                &[],
+            );
+        }
+    }
+
+    /// The queue families a texture goes between, this encoder's first, if the device has two.
+    fn families(&self) -> Option<(u32, u32)> {
+        let compute = self.device.compute.as_ref()?.family;
+        let graphics = self.device.queue_family;
+        return Some(match self.compute {
+            true => (compute, graphics),
+            false => (graphics, compute),
+        });
+    }
+
+    /// Half of a queue ownership transfer of `texture` from `from` to `to`, between `families`: the release if
+    /// `release`, the acquire otherwise. Each half only waits on, or makes visible, what its own queue does.
+    fn ownership_barrier(
+        &mut self,
+        texture: &VulkanTexture,
+        from: TextureState,
+        to: TextureState,
+        families: (u32, u32),
+        release: bool,
+    ) {
+        let aspect = aspect(texture.format);
+        let (old_layout, src_stage, src_access) = state_info(from, aspect);
+        let (new_layout, dst_stage, dst_access) = state_info(to, aspect);
+        // A compute queue can't wait on or for graphics stages, and has never been in them anyway.
+        let stages = |stages: vk::PipelineStageFlags, access, fallback| {
+            let stages = match self.compute {
+                true => {
+                    stages
+                        & (vk::PipelineStageFlags::COMPUTE_SHADER
+                            | vk::PipelineStageFlags::TRANSFER)
+                }
+                false => stages,
+            };
+            match stages.is_empty() {
+                true => (fallback, vk::AccessFlags::empty()),
+                false => (stages, access),
+            }
+        };
+        let none = (
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+        );
+        let ((src_stage, src_access), (dst_stage, dst_access), (src_family, dst_family)) =
+            match release {
+                true => (
+                    stages(src_stage, src_access, vk::PipelineStageFlags::TOP_OF_PIPE),
+                    (
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        vk::AccessFlags::empty(),
+                    ),
+                    families,
+                ),
+                false => (
+                    none,
+                    stages(
+                        dst_stage,
+                        dst_access,
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    ),
+                    (families.1, families.0),
+                ),
+            };
+
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_pipeline_barrier(
+                self.cmd,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier::default()
+                    .image(texture.image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(aspect)
+                            .level_count(1)
+                            .layer_count(1),
+                    )
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)],
+            );
+        }
+    }
+
+    fn bind_compute(&mut self, pipeline: &ComputePipeline, push: &[u8]) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_bind_pipeline(
+                self.cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.raw(),
+            );
+            if !push.is_empty() {
+                self.device.device.cmd_push_constants(
+                    self.cmd,
+                    pipeline.layout(),
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push,
+                );
+            }
+        }
+    }
+
+    fn buffer_image_copy(texture: &VulkanTexture) -> vk::BufferImageCopy {
+        vk::BufferImageCopy::default()
+            .image_subresource(
+                // Copies take one aspect, and the depth is the one wanted from a depth stencil texture.
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(aspect(texture.format) & !vk::ImageAspectFlags::STENCIL)
+                    .layer_count(1),
+            )
+            .image_extent(texture.extent.into())
+    }
+}
+
+impl CommandEncoder for VulkanEncoder<'_> {
+    type Buffer = VulkanBuffer;
+    type Texture = VulkanTexture;
+    type ComputePipeline = ComputePipeline;
+
+    fn transition(&mut self, texture: &VulkanTexture, from: TextureState, to: TextureState) {
+        let aspect = aspect(texture.format);
+        let (old_layout, src_stage, src_access) = state_info(from, aspect);
+        let (new_layout, dst_stage, dst_access) = state_info(to, aspect);
+
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_pipeline_barrier(
+                self.cmd,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier::default()
+                    .image(texture.image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(aspect)
+                            .level_count(1)
+                            .layer_count(1),
+                    )
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)],
+            );
+        }
+    }
+
+    fn begin_pass(&mut self, target: &VulkanTexture, clear: Option<LinearColor>) {
+        let (layout, ..) = state_info(TextureState::RenderTarget, aspect(target.format));
+        let attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(target.view)
+            .image_layout(layout)
+            .load_op(match clear {
+                Some(_) => vk::AttachmentLoadOp::CLEAR,
+                None => vk::AttachmentLoadOp::LOAD,
+            })
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(match clear {
+                Some(_) if target.format.is_depth() => vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 0.0,
+                        stencil: 0,
+                    },
+                },
+                color => vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: color.unwrap_or(LinearColor::BLACK).to_array(),
+                    },
+                },
+            });
+        let attachment = std::slice::from_ref(&attachment);
+
+        let mut info = vk::RenderingInfo::default()
+            .render_area(target.extent.into())
+            .layer_count(1);
+        info = if target.format.is_depth() {
+            info.depth_attachment(&attachment[0])
+        } else {
+            info.color_attachments(attachment)
+        };
+
+        // SAFETY: Recording into our own command buffer, and the target is in the layout the attachment says.
+        unsafe { self.device.device.cmd_begin_rendering(self.cmd, &info) };
+    }
+
+    fn end_pass(&mut self) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe { self.device.device.cmd_end_rendering(self.cmd) };
+    }
+
+    fn begin_rendering(
+        &mut self,
+        colors: &[Attachment<'_, VulkanTexture, LinearColor>],
+        depth: Option<Attachment<'_, VulkanTexture, f32>>,
+    ) {
+        fn raw<C: Copy>(a: &Attachment<'_, VulkanTexture, C>) -> rendering::Attachment<C> {
+            rendering::Attachment {
+                image: a.texture.image,
+                view: a.texture.view,
+                before: a.before,
+                after: a.after,
+                load: a.load,
+                store: a.store,
+                resolve: a.resolve.as_ref().map(|r| rendering::Resolve {
+                    image: r.texture.image,
+                    view: r.texture.view,
+                    before: r.before,
+                    after: r.after,
+                }),
+            }
+        }
+
+        let Some(extent) = colors
+            .first()
+            .map(|a| a.texture.extent)
+            .or(depth.as_ref().map(|a| a.texture.extent))
+        else {
+            log::error!("A pass needs at least one attachment");
+            return;
+        };
+        let colors: Vec<_> = colors.iter().map(raw).collect();
+        let desc = rendering::RenderingDesc {
+            extent,
+            colors: &colors,
+            depth: depth.as_ref().map(raw),
+            stencil: depth
+                .as_ref()
+                .is_some_and(|a| a.texture.format.has_stencil()),
+        };
+        // SAFETY: Recording into our own command buffer, and the attachments are in their `before` states.
+        self.rendering = Some(unsafe { rendering::begin(&self.device.device, self.cmd, &desc) });
+    }
+
+    fn end_rendering(&mut self) {
+        let Some(pass) = self.rendering.take() else {
+            log::error!("Ended a pass that wasn't begun");
+            return;
+        };
+        // SAFETY: Recording into our own command buffer, which the pass was begun in.
+        unsafe { rendering::end(&self.device.device, self.cmd, pass) };
+    }
+
+    fn copy_texture_to_buffer(&mut self, texture: &VulkanTexture, buffer: &VulkanBuffer) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_copy_image_to_buffer(
+                self.cmd,
+                texture.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[Self::buffer_image_copy(texture)],
+            );
+
+            // Make the copy visible to the host, in case it's being read back.
+            self.device.device.cmd_pipeline_barrier(
+                self.cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[vk::BufferMemoryBarrier::default()
+                    .buffer(buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ)],
+                &[],
+            );
+        }
+    }
+
+    fn copy_buffer_to_texture(&mut self, buffer: &VulkanBuffer, texture: &VulkanTexture) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_copy_buffer_to_image(
+                self.cmd,
+                buffer.buffer,
+                texture.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[Self::buffer_image_copy(texture)],
+            );
+        }
+    }
+
+    fn resolve_texture(&mut self, src: &VulkanTexture, dst: &VulkanTexture) {
+        let layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_resolve_image(
+                self.cmd,
+                src.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageResolve::default()
+                    .src_subresource(layers)
+                    .dst_subresource(layers)
+                    .extent(vk::Extent3D {
+                        width: src.extent.width,
+                        height: src.extent.height,
+                        depth: 1,
+                    })],
+            );
+        }
+    }
+
+    fn dispatch(&mut self, pipeline: &ComputePipeline, push: &[u8], groups: [u32; 3]) {
+        self.bind_compute(pipeline, push);
+        let [x, y, z] = groups;
+        // SAFETY: Recording into our own command buffer.
+        unsafe { self.device.device.cmd_dispatch(self.cmd, x, y, z) };
+    }
+
+    fn dispatch_indirect(
+        &mut self,
+        pipeline: &ComputePipeline,
+        push: &[u8],
+        buffer: &VulkanBuffer,
+        offset: u64,
+    ) {
+        self.bind_compute(pipeline, push);
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device
+                .device
+                .cmd_dispatch_indirect(self.cmd, buffer.buffer, offset)
+        };
+    }
+
+    fn buffer_barrier(&mut self, buffer: &VulkanBuffer) {
+        // SAFETY: Recording into our own command buffer.
+        unsafe {
+            self.device.device.cmd_pipeline_barrier(
+                self.cmd,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[vk::BufferMemoryBarrier::default()
+                    .buffer(buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+                    .dst_access_mask(
+                        vk::AccessFlags::MEMORY_READ
+                            | vk::AccessFlags::MEMORY_WRITE
+                            | vk::AccessFlags::HOST_READ,
+                    )],
+                &[],
+            );
+        }
+    }
+
+    fn compute_barrier(&mut self) {
+        // SAFETY: Recording into our own command buffer. Reads in general, so it's valid on either queue.
+        unsafe {
+            self.device.device.cmd_pipeline_barrier(
+                self.cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::default()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(
+                        vk::AccessFlags::MEMORY_READ
+                            | vk::AccessFlags::MEMORY_WRITE
+                            | vk::AccessFlags::HOST_READ,
+                    )],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    fn release_texture(
+        &mut self,
+        texture: &VulkanTexture,
+        from: TextureState,
+        to: TextureState,
+    ) -> bool {
+        let Some(families) = self.families() else {
+            self.transition(texture, from, to);
+            return false;
+        };
+        self.ownership_barrier(texture, from, to, families, true);
+        return true;
+    }
+
+    fn acquire_texture(
+        &mut self,
+        texture: &VulkanTexture,
+        from: TextureState,
+        to: TextureState,
+    ) -> bool {
+        let Some(families) = self.families() else {
+            return false;
+        };
+        self.ownership_barrier(texture, from, to, families, false);
+        return true;
+    }
+}
+
+impl Drop for VulkanEncoder<'_> {
+    fn drop(&mut self) {
+        if !self.borrowed && self.cmd != vk::CommandBuffer::null() {
+            // SAFETY: Never submitted, so the GPU never saw it.
+            unsafe {
+                self.device
+                    .device
+                    .free_command_buffers(self.device.command_pool(self.compute), &[self.cmd])
+            };
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Vulkan with no window, for tests and tools. Renders into an offscreen image and reads it back.
+//!
+//! Any device with Vulkan 1.3 will do, including CPU implementations like lavapipe, which is what
+//! makes this usable on CI machines without a GPU.
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    VK_ENTRY,
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    gpu_select::{self, Requirements},
+    hal::{
+        BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+        TextureFormat, TextureState, TextureUsage,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanEncoder, VulkanTexture},
+    },
+    renderer::create_instance,
+    validation,
+};
+use crate::{
+    app::info::AppInfo,
+    capture::{CaptureFormat, CapturedFrame},
+};
+
+/// Format of the offscreen target. Read back as [`CaptureFormat::Rgba8Srgb`].
+pub const TARGET_FORMAT: TextureFormat = TextureFormat::Rgba8Srgb;
+
+/// The texture being rendered to. It's in [`TextureState::RenderTarget`] while recording, and needs to be left that way.
+pub type Target = VulkanTexture;
+
+pub struct Headless {
+    device: VulkanDevice,
+    pub device_name: String,
+    /// A CPU implementation, which renders the same everywhere.
+    pub software: bool,
+}
+
+impl Headless {
+    /// Set up on the best device there is, or a CPU one if `prefer_software`.
+    /// `None` if there's no Vulkan, or no device that can do 1.3 with dynamic rendering.
+    pub fn new(info: &AppInfo, prefer_software: bool) -> Option<Headless> {
+        let entry = VK_ENTRY.as_ref()?;
+        // Off unless asked for through the environment, as CI machines rarely have the layer.
+        let (instance, messenger) =
+            create_instance(entry, info, &[], validation::requested(false)).ok()?;
+
+        let requirements = Requirements {
+            prefer_software,
+            ..Requirements::default()
+        };
+        let devices = gpu_select::enumerate(&instance, &requirements);
+        let Some(adapter) = gpu_select::select(&devices, &requirements, None) else {
+            log::info!("No Vulkan 1.3 device to render headless with");
+            // SAFETY: Nothing else was made from it.
+            unsafe {
+                if let Some(messenger) = messenger {
+                    messenger.destroy();
+                }
+                instance.destroy_instance(Some(&*VK_ALLOCATOR_CALLBACKS));
+            }
+            return None;
+        };
+
+        let device = match VulkanDevice::new(
+            instance,
+            messenger,
+            adapter.physical_device,
+            adapter
+                .graphics_family
+                .expect("Usable devices have a graphics queue"),
+            adapter.compute_family,
+            &[],
+        ) {
+            Ok(device) => device,
+            Err(e) => {
+                log::warn!("Couldn't create a headless device: {e}");
+                return None;
+            }
+        };
+
+        return Some(Headless {
+            device_name: adapter.name.clone(),
+            software: adapter.device_type == vk::PhysicalDeviceType::CPU,
+            device,
+        });
+    }
+
+    pub fn device(&self) -> &VulkanDevice {
+        &self.device
+    }
+
+    /// Record with `record` into a fresh `width` by `height` target, then wait for it and read it back.
+    pub fn render(
+        &self,
+        width: u32,
+        height: u32,
+        record: impl FnOnce(&mut VulkanEncoder, &Target),
+    ) -> VkResult<CapturedFrame> {
+        let device = &self.device;
+        let target = device.create_texture(&TextureDesc {
+            width,
+            height,
+            format: TARGET_FORMAT,
+            usage: TextureUsage::RENDER_TARGET | TextureUsage::COPY_SRC,
+            samples: 1,
+        })?;
+        let readback = match device.create_buffer(&BufferDesc {
+            size: TARGET_FORMAT.size(width, height),
+            usage: BufferUsage::COPY_DST,
+            location: MemoryLocation::Readback,
+        }) {
+            Ok(readback) => readback,
+            Err(e) => {
+                // SAFETY: Never used.
+                unsafe { device.destroy_texture(target) };
+                return Err(e);
+            }
+        };
+
+        let res = self.render_into(&target, &readback, record);
+        if res.is_err() {
+            device.wait_idle();
+        }
+        // SAFETY: Either the submission was waited on, or the device is idle.
+        unsafe {
+            device.destroy_texture(target);
+            device.destroy_buffer(readback);
+        }
+        return res;
+    }
+
+    fn render_into(
+        &self,
+        target: &Target,
+        readback: &VulkanBuffer,
+        record: impl FnOnce(&mut VulkanEncoder, &Target),
+    ) -> VkResult<CapturedFrame> {
+        let device = &self.device;
+
+        let mut cmds = device.begin_commands()?;
+        cmds.transition(target, TextureState::Undefined, TextureState::RenderTarget);
+        record(&mut cmds, target);
+        cmds.transition(target, TextureState::RenderTarget, TextureState::CopySrc);
+        cmds.copy_texture_to_buffer(target, readback);
+        device.wait(device.submit(cmds)?)?;
+
+        let mut data = vec![0; readback.size as usize];
+        // SAFETY: The GPU is done with it, waited on above.
+        unsafe { device.read_buffer(readback, 0, &mut data)? };
+
+        return Ok(CapturedFrame {
+            width: target.extent.width,
+            height: target.extent.height,
+            format: CaptureFormat::Rgba8Srgb,
+            data,
+            frame: 0,
+        });
+    }
+}
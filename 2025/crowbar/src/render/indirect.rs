@@ -0,0 +1,534 @@
+//! Checking indirect draw arguments before they're drawn, for draws the GPU wrote itself, like from culling.
+//!
+//! A bad index count or instance range in an indirect draw reads past the end of a buffer, which at best draws
+//! garbage and at worst hangs or loses the device, with nothing on the CPU side that ever saw the numbers.
+//! [`IndirectValidator`] runs a compute pass over the arguments just before they're used that clamps each draw to
+//! what its buffers hold and writes down which ones were out of range, read back once the frame's done. It's meant
+//! for debug builds, [`ENABLED`], but nothing stops turning it on in others.
+//!
+//! [`IndirectLimits::check_indexed`] and [`IndirectLimits::check`] do the same to arguments on the CPU.
+//!
+//! The renderer checks the 3D pass's draws with it, see [`MeshPass::prepare`](super::mesh::MeshPass::prepare).
+
+use std::ops::BitOr;
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::{FrameDescriptors, LayoutBinding, LayoutCache, LayoutDesc},
+    hal::{
+        BufferDesc, BufferUsage, Device, MemoryLocation,
+        vulkan::{VulkanBuffer, VulkanDevice},
+    },
+    shader::reflect::Spirv,
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// Whether to validate indirect draws, by default.
+pub const ENABLED: bool = cfg!(debug_assertions);
+/// The shader's source, to compile at runtime.
+pub const SHADER: &str = include_str!("indirect/validate.comp");
+/// Where the compiled shader goes among the assets, without the `.spv`.
+pub const SHADER_ASSET: &str = "shaders/indirect_validate.comp";
+/// Violations written down per batch. Any past this are counted, not described.
+pub const MAX_REPORTED: u32 = 64;
+
+const WORKGROUP: u32 = 64;
+const HEADER_BYTES: u64 = 16;
+const VIOLATION_BYTES: u64 = 24;
+const REPORT_BYTES: u64 = HEADER_BYTES + MAX_REPORTED as u64 * VIOLATION_BYTES;
+
+/// What was wrong with a draw.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViolationKinds(u32);
+
+impl ViolationKinds {
+    /// Indices, or vertices without an index buffer, past the end of the buffer.
+    pub const ELEMENT_RANGE: ViolationKinds = ViolationKinds(1);
+    /// Instances past the end of the instance data.
+    pub const INSTANCE_RANGE: ViolationKinds = ViolationKinds(2);
+
+    pub fn contains(&self, other: ViolationKinds) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for ViolationKinds {
+    type Output = ViolationKinds;
+
+    fn bitor(self, rhs: ViolationKinds) -> ViolationKinds {
+        ViolationKinds(self.0 | rhs.0)
+    }
+}
+
+/// A draw that was out of range, with its arguments from before they were clamped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// Which draw in the batch.
+    pub draw: u32,
+    pub kinds: ViolationKinds,
+    /// Index or vertex count.
+    pub count: u32,
+    /// First index or vertex.
+    pub first: u32,
+    pub instance_count: u32,
+    pub first_instance: u32,
+}
+
+/// How much the buffers a batch of draws reads from hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndirectLimits {
+    /// Indices in the index buffer, or vertices for draws without one.
+    pub elements: u32,
+    pub instances: u32,
+}
+
+impl IndirectLimits {
+    /// What's wrong with a draw, and the counts clamped to fit. Keep in step with the shader.
+    fn clamp(
+        &self,
+        count: u32,
+        first: u32,
+        instance_count: u32,
+        first_instance: u32,
+    ) -> (ViolationKinds, u32, u32) {
+        let mut kinds = ViolationKinds::default();
+        let fit = |first: u32, limit: u32| limit.saturating_sub(first);
+        let (mut count, mut instance_count) = (count, instance_count);
+        if first > self.elements || count > self.elements - first {
+            kinds = kinds | ViolationKinds::ELEMENT_RANGE;
+            count = fit(first, self.elements);
+        }
+        if first_instance > self.instances || instance_count > self.instances - first_instance {
+            kinds = kinds | ViolationKinds::INSTANCE_RANGE;
+            instance_count = fit(first_instance, self.instances);
+        }
+        return (kinds, count, instance_count);
+    }
+
+    /// Clamp `command`, the `draw`th, to fit, saying what was wrong with it if anything was.
+    pub fn check_indexed(
+        &self,
+        draw: u32,
+        command: &mut vk::DrawIndexedIndirectCommand,
+    ) -> Option<Violation> {
+        let original = *command;
+        let (kinds, count, instance_count) = self.clamp(
+            command.index_count,
+            command.first_index,
+            command.instance_count,
+            command.first_instance,
+        );
+        if kinds.is_empty() {
+            return None;
+        }
+        command.index_count = count;
+        command.instance_count = instance_count;
+        return Some(Violation {
+            draw,
+            kinds,
+            count: original.index_count,
+            first: original.first_index,
+            instance_count: original.instance_count,
+            first_instance: original.first_instance,
+        });
+    }
+
+    /// As [`IndirectLimits::check_indexed`], for draws without an index buffer.
+    pub fn check(&self, draw: u32, command: &mut vk::DrawIndirectCommand) -> Option<Violation> {
+        let original = *command;
+        let (kinds, count, instance_count) = self.clamp(
+            command.vertex_count,
+            command.first_vertex,
+            command.instance_count,
+            command.first_instance,
+        );
+        if kinds.is_empty() {
+            return None;
+        }
+        command.vertex_count = count;
+        command.instance_count = instance_count;
+        return Some(Violation {
+            draw,
+            kinds,
+            count: original.vertex_count,
+            first: original.first_vertex,
+            instance_count: original.instance_count,
+            first_instance: original.first_instance,
+        });
+    }
+}
+
+/// A batch of indirect draws in a buffer, to validate.
+#[derive(Clone, Copy)]
+pub struct IndirectDraws<'a> {
+    /// Needs [`BufferUsage::STORAGE`] as well as [`BufferUsage::INDIRECT`].
+    pub buffer: &'a VulkanBuffer,
+    /// Bytes to the first draw, a multiple of 4.
+    pub offset: u64,
+    pub draw_count: u32,
+    /// Bytes from one draw to the next, as passed to the draw.
+    pub stride: u32,
+    /// `VkDrawIndexedIndirectCommand`s rather than `VkDrawIndirectCommand`s.
+    pub indexed: bool,
+    pub limits: IndirectLimits,
+}
+
+/// What a batch's validation found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndirectReport {
+    pub frame: u64,
+    pub label: String,
+    pub draws: u32,
+    /// Every draw that was out of range, including those past [`MAX_REPORTED`].
+    pub total: u32,
+    /// The first [`MAX_REPORTED`], in no particular order.
+    pub violations: Vec<Violation>,
+}
+
+impl IndirectReport {
+    fn decode(bytes: &[u8], frame: u64, label: String, draws: u32) -> IndirectReport {
+        let word = |at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+        let total = word(0);
+        let violations = (0..total.min(MAX_REPORTED) as usize)
+            .map(|i| {
+                let at = (HEADER_BYTES + i as u64 * VIOLATION_BYTES) as usize;
+                Violation {
+                    draw: word(at),
+                    kinds: ViolationKinds(word(at + 4)),
+                    count: word(at + 8),
+                    first: word(at + 12),
+                    instance_count: word(at + 16),
+                    first_instance: word(at + 20),
+                }
+            })
+            .collect();
+        return IndirectReport {
+            frame,
+            label,
+            draws,
+            total,
+            violations,
+        };
+    }
+}
+
+/// A frame in flight's reports.
+#[derive(Default)]
+struct Slot {
+    frame: u64,
+    /// Kept across frames, the first `batches.len()` in use.
+    reports: Vec<VulkanBuffer>,
+    /// Label and draw count of each batch recorded.
+    batches: Vec<(String, u32)>,
+}
+
+/// The validation pass. See the module docs.
+pub struct IndirectValidator {
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptors: FrameDescriptors,
+    slots: Vec<Slot>,
+    current: usize,
+}
+
+impl IndirectValidator {
+    /// Set up for `frames_in_flight` frames, with `spirv` compiled from [`SHADER`].
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s or null.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        spirv: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<IndirectValidator> {
+        let raw = device.raw();
+        let storage = |binding| {
+            LayoutBinding::new(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                1,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+        };
+        let desc = LayoutDesc::new([storage(0), storage(1)]);
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(28);
+        // SAFETY: Passed on to the caller, and whatever's made is destroyed if something after it fails.
+        unsafe {
+            let set_layout = layouts.get(raw, &desc)?;
+            let layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[set_layout])
+                    .push_constant_ranges(&[push]),
+                allocs(),
+            )?;
+            let made = spirv.create_module(raw).and_then(|module| {
+                let stage = vk::PipelineShaderStageCreateInfo::default()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(c"main");
+                let info = vk::ComputePipelineCreateInfo::default()
+                    .stage(stage)
+                    .layout(layout);
+                let made = raw.create_compute_pipelines(cache, &[info], allocs());
+                raw.destroy_shader_module(module, allocs());
+                made.map(|p| p[0]).map_err(|(_, e)| e)
+            });
+            let pipeline = match made {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    raw.destroy_pipeline_layout(layout, allocs());
+                    return Err(e);
+                }
+            };
+            let ratios = [(vk::DescriptorType::STORAGE_BUFFER, 2.0)];
+            return Ok(IndirectValidator {
+                set_layout,
+                layout,
+                pipeline,
+                descriptors: FrameDescriptors::new(raw, frames_in_flight, &ratios),
+                slots: (0..frames_in_flight.max(1))
+                    .map(|_| Slot::default())
+                    .collect(),
+                current: 0,
+            });
+        }
+    }
+
+    /// Start `frame`, handing back what the batches of the frame that last used its slot found, and logging any
+    /// that were out of range.
+    ///
+    /// # Safety
+    /// The GPU must be done with the frame that last used the slot, like
+    /// [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    pub unsafe fn begin_frame(&mut self, device: &VulkanDevice, frame: u64) -> Vec<IndirectReport> {
+        self.current = (frame % self.slots.len() as u64) as usize;
+        // SAFETY: Passed on to the caller.
+        if let Err(e) = unsafe { self.descriptors.begin_frame(frame) } {
+            log::error!("Couldn't reset the indirect validation descriptors: {e}");
+        }
+        let slot = &mut self.slots[self.current];
+        let mut reports = Vec::new();
+        for ((label, draws), buffer) in slot.batches.drain(..).zip(&slot.reports) {
+            let mut bytes = vec![0; REPORT_BYTES as usize];
+            // SAFETY: The caller vouches the GPU's done writing it.
+            if let Err(e) = unsafe { device.read_buffer(buffer, 0, &mut bytes) } {
+                log::error!("Couldn't read back {label}'s indirect validation: {e}");
+                continue;
+            }
+            let report = IndirectReport::decode(&bytes, slot.frame, label, draws);
+            if report.total > 0 {
+                log::error!(
+                    "{} of {} indirect draws in {} were out of range in frame {}, and were clamped: {:?}",
+                    report.total,
+                    report.draws,
+                    report.label,
+                    report.frame,
+                    report.violations.first()
+                );
+            }
+            reports.push(report);
+        }
+        slot.frame = frame;
+        return reports;
+    }
+
+    /// Clamp `draws` in place, recorded into `cmd` before anything draws from them, after whatever wrote them.
+    ///
+    /// # Safety
+    /// `cmd` must be recording for the frame begun with [`IndirectValidator::begin_frame`].
+    pub unsafe fn record(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        label: impl Into<String>,
+        draws: &IndirectDraws,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let slot = &mut self.slots[self.current];
+        let index = slot.batches.len();
+        if index == slot.reports.len() {
+            slot.reports.push(device.create_buffer(&BufferDesc {
+                size: REPORT_BYTES,
+                usage: BufferUsage::STORAGE | BufferUsage::COPY_DST,
+                location: MemoryLocation::Readback,
+            })?);
+        }
+        let report = slot.reports[index].buffer;
+        let set = self.descriptors.allocate(self.set_layout)?;
+        let commands_info = [vk::DescriptorBufferInfo::default()
+            .buffer(draws.buffer.buffer)
+            .range(vk::WHOLE_SIZE)];
+        let report_info = [vk::DescriptorBufferInfo::default()
+            .buffer(report)
+            .range(vk::WHOLE_SIZE)];
+        let write = |binding, info| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(info)
+        };
+        let push: Vec<u8> = [
+            (draws.offset / 4) as u32,
+            draws.stride / 4,
+            draws.draw_count,
+            draws.indexed as u32,
+            draws.limits.elements,
+            draws.limits.instances,
+            MAX_REPORTED,
+        ]
+        .iter()
+        .flat_map(|w| w.to_ne_bytes())
+        .collect();
+        let barrier = |src_stage, src_access, dst_stage, dst_access| {
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access);
+            // SAFETY: Recording into the caller's command buffer.
+            unsafe {
+                raw.cmd_pipeline_barrier(
+                    cmd,
+                    src_stage,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[],
+                    &[],
+                )
+            };
+        };
+
+        // SAFETY: The set was just allocated, and the rest is recording.
+        unsafe {
+            raw.update_descriptor_sets(&[write(0, &commands_info), write(1, &report_info)], &[]);
+            raw.cmd_fill_buffer(cmd, report, 0, HEADER_BYTES, 0);
+            // After the fill, and whatever wrote the draws.
+            barrier(
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            );
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::COMPUTE, 0, &push);
+            raw.cmd_dispatch(cmd, draws.draw_count.div_ceil(WORKGROUP), 1, 1);
+            barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::HOST,
+                vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::HOST_READ,
+            );
+        }
+        slot.batches.push((label.into(), draws.draw_count));
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. The set layout goes with its cache.
+        unsafe {
+            for slot in self.slots.drain(..) {
+                for buffer in slot.reports {
+                    device.destroy_buffer(buffer);
+                }
+            }
+            raw.destroy_pipeline(self.pipeline, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.pipeline = vk::Pipeline::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{
+        HEADER_BYTES, IndirectLimits, IndirectReport, REPORT_BYTES, VIOLATION_BYTES, ViolationKinds,
+    };
+
+    #[test]
+    pub fn clamps_out_of_range_draws() {
+        let limits = IndirectLimits {
+            elements: 300,
+            instances: 10,
+        };
+        let mut fine = vk::DrawIndexedIndirectCommand {
+            index_count: 100,
+            instance_count: 10,
+            first_index: 200,
+            vertex_offset: -5,
+            first_instance: 0,
+        };
+        assert_eq!(limits.check_indexed(0, &mut fine), None);
+
+        // Culling output gone wrong: garbage counts that would wrap if added up carelessly.
+        let mut bad = vk::DrawIndexedIndirectCommand {
+            index_count: u32::MAX,
+            instance_count: 4,
+            first_index: 250,
+            vertex_offset: 0,
+            first_instance: 8,
+        };
+        let wrapped = limits.check_indexed(3, &mut bad).unwrap();
+        assert!(
+            wrapped
+                .kinds
+                .contains(ViolationKinds::ELEMENT_RANGE | ViolationKinds::INSTANCE_RANGE)
+        );
+        assert_eq!((wrapped.draw, wrapped.count), (3, u32::MAX));
+        assert_eq!((bad.index_count, bad.instance_count), (50, 2));
+
+        let mut past_the_end = vk::DrawIndirectCommand {
+            vertex_count: 3,
+            instance_count: 1,
+            first_vertex: 400,
+            first_instance: 0,
+        };
+        let violation = limits.check(0, &mut past_the_end).unwrap();
+        assert_eq!(violation.kinds, ViolationKinds::ELEMENT_RANGE);
+        assert_eq!(past_the_end.vertex_count, 0);
+
+        // As the shader writes it: more found than described.
+        let mut bytes = vec![0u8; REPORT_BYTES as usize];
+        let mut put = |at: u64, word: u32| {
+            bytes[at as usize..at as usize + 4].copy_from_slice(&word.to_ne_bytes())
+        };
+        put(0, 100);
+        for (i, word) in [3, 3, u32::MAX, 250, 4, 8].into_iter().enumerate() {
+            put(HEADER_BYTES + i as u64 * 4, word);
+        }
+        put(HEADER_BYTES + VIOLATION_BYTES, 7);
+        let report = IndirectReport::decode(&bytes, 9, "culled".into(), 1000);
+        assert_eq!(report.total, 100);
+        assert_eq!(report.violations.len(), 64);
+        assert_eq!(report.violations[0], wrapped);
+        assert_eq!(report.violations[1].draw, 7);
+    }
+}
@@ -0,0 +1,439 @@
+//! Lightmaps: diffuse lighting for static geometry, baked offline into a texture laid out by a second UV set.
+//!
+//! Static meshes give every vertex a [`LightmapVertex::lightmap_uv`] alongside the material one, unwrapped so no two
+//! triangles overlap. [`Lightmap::bake`] puts each texel's center back on the geometry through those UVs and path
+//! traces from there across the job system: rays bounce off the meshes, picking up their albedo and emission, until
+//! they escape to the sky. Texels no triangle covers are filled in from their neighbors, so filtering at the edges of
+//! the unwrap doesn't pull in black. [`Lightmap::save`] writes the result out as an EXR.
+//!
+//! At runtime a level's lightmap goes on an entity as [`BakedLightmap`], and meshes with [`Lightmapped`] are lit from
+//! it rather than [`super::probes`], which are for whatever moves through the level. [`super::mesh::MeshPass`]
+//! uploads [`Lightmap::gpu_data`] and its shader samples it with `lightmap_diffuse` from [`GLSL`], through the
+//! mesh's lightmap UVs moved to where the bake put it, and multiplies by albedo the same as with the probes.
+//!
+//! [`BakedLightmap`]: crate::ecs::components::BakedLightmap
+//! [`Lightmapped`]: crate::ecs::components::Lightmapped
+
+use std::{f32::consts::TAU, io, path::Path};
+
+use ash::vk;
+use exr::prelude::f16;
+use glam::{Vec2, Vec3};
+use rayon::prelude::*;
+
+use super::pipeline::GraphicsPipelineBuilder;
+use crate::{
+    jobs::JobSystem,
+    math::{Ray, bounds::Aabb, bvh::Bvh},
+    rng::Rng,
+};
+
+pub const GLSL: &str = include_str!("lightmap/lightmap.glsl");
+
+/// How far rays start off the surface they leave, so they don't hit it again.
+const SURFACE_OFFSET: f32 = 1e-3;
+
+/// How many texels out from the covered ones get filled in.
+const DILATE: u32 = 2;
+
+/// A static mesh's vertex, as the bake takes it and [`LightmapVertex::vertex_input`] lays it out for shading.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LightmapVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    /// The material's texture coordinates.
+    pub uv: [f32; 2],
+    /// Where it is in the lightmap, unique across the whole level.
+    pub lightmap_uv: [f32; 2],
+}
+
+impl LightmapVertex {
+    /// Add the vertex buffer at `binding`, with position, normal, UV and lightmap UV at locations 0 to 3.
+    pub fn vertex_input(builder: GraphicsPipelineBuilder, binding: u32) -> GraphicsPipelineBuilder {
+        let float = |n: u32| 4 * n;
+        builder
+            .vertex_buffer(binding, size_of::<LightmapVertex>() as u32, false)
+            .attribute(0, binding, vk::Format::R32G32B32_SFLOAT, 0)
+            .attribute(1, binding, vk::Format::R32G32B32_SFLOAT, float(3))
+            .attribute(2, binding, vk::Format::R32G32_SFLOAT, float(6))
+            .attribute(3, binding, vk::Format::R32G32_SFLOAT, float(8))
+    }
+}
+
+/// A static mesh to bake, and what its surface does to light.
+#[derive(Clone, Copy, Debug)]
+pub struct BakeMesh<'a> {
+    pub vertices: &'a [LightmapVertex],
+    /// Triangles, three per.
+    pub indices: &'a [u32],
+    /// The fraction of light reflected, in linear RGB.
+    pub albedo: Vec3,
+    /// Light given off, for glowing surfaces and area lights.
+    pub emission: Vec3,
+}
+
+impl BakeMesh<'_> {
+    fn triangle(&self, triangle: usize) -> [LightmapVertex; 3] {
+        std::array::from_fn(|i| self.vertices[self.indices[triangle * 3 + i] as usize])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BakeSettings {
+    /// Paths traced per texel.
+    pub samples: u32,
+    /// How many times a path can bounce off the meshes. Zero only sees emission and the sky.
+    pub bounces: u32,
+    pub seed: u64,
+}
+
+impl Default for BakeSettings {
+    fn default() -> BakeSettings {
+        BakeSettings {
+            samples: 128,
+            bounces: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// Every triangle being baked, in a tree for tracing against.
+struct Scene<'a> {
+    meshes: &'a [BakeMesh<'a>],
+    bvh: Bvh<(u32, u32)>,
+}
+
+struct Hit {
+    mesh: usize,
+    position: Vec3,
+    normal: Vec3,
+}
+
+impl<'a> Scene<'a> {
+    fn new(meshes: &'a [BakeMesh<'a>]) -> Scene<'a> {
+        let mut bvh = Bvh::new(0.0);
+        for (m, mesh) in meshes.iter().enumerate() {
+            for t in 0..mesh.indices.len() / 3 {
+                let points = mesh.triangle(t).map(|v| Vec3::from(v.position));
+                if let Some(aabb) = Aabb::from_points(&points) {
+                    bvh.insert(&aabb, (m as u32, t as u32));
+                }
+            }
+        }
+        return Scene { meshes, bvh };
+    }
+
+    fn positions(&self, (mesh, triangle): (u32, u32)) -> [Vec3; 3] {
+        self.meshes[mesh as usize]
+            .triangle(triangle as usize)
+            .map(|v| Vec3::from(v.position))
+    }
+
+    fn trace(&self, ray: &Ray) -> Option<Hit> {
+        let (item, t) = self.bvh.ray_cast(ray, f32::INFINITY, |item| {
+            ray.triangle(self.positions(item)).map(|(t, _)| t)
+        })?;
+        let [a, b, c] = self.positions(item);
+        // The face's own normal, turned to face where the ray came from.
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        return Some(Hit {
+            mesh: item.0 as usize,
+            position: ray.at(t),
+            normal: match normal.dot(ray.dir) > 0.0 {
+                true => -normal,
+                false => normal,
+            },
+        });
+    }
+
+    /// Light arriving at `position` from the hemisphere around `normal`, cosine weighted, so it's what a white
+    /// Lambertian surface there reflects.
+    fn gather(
+        &self,
+        position: Vec3,
+        normal: Vec3,
+        settings: &BakeSettings,
+        rng: &mut Rng,
+        sky: &impl Fn(Vec3) -> Vec3,
+    ) -> Vec3 {
+        let mut sum = Vec3::ZERO;
+        for _ in 0..settings.samples {
+            let (mut position, mut normal) = (position, normal);
+            let mut throughput = Vec3::ONE;
+            for bounce in 0..=settings.bounces {
+                let dir = cosine_direction(normal, rng);
+                let ray = Ray {
+                    origin: position + normal * SURFACE_OFFSET,
+                    dir,
+                };
+                let Some(hit) = self.trace(&ray) else {
+                    sum += throughput * sky(dir);
+                    break;
+                };
+                let mesh = &self.meshes[hit.mesh];
+                sum += throughput * mesh.emission;
+                if bounce == settings.bounces {
+                    break;
+                }
+                throughput *= mesh.albedo;
+                (position, normal) = (hit.position, hit.normal);
+            }
+        }
+        return sum / settings.samples.max(1) as f32;
+    }
+}
+
+/// A direction about `normal`, more likely the closer it is, by the cosine between them.
+fn cosine_direction(normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let (r, angle) = (rng.f32().sqrt(), rng.f32() * TAU);
+    let (x, y) = normal.any_orthonormal_pair();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    return (x * (r * angle.cos()) + y * (r * angle.sin()) + normal * z).normalize();
+}
+
+/// Diffuse lighting baked into texels, in linear RGB, for a white surface to multiply by its albedo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lightmap {
+    width: u32,
+    height: u32,
+    /// Row by row, from `lightmap_uv` (0, 0).
+    texels: Vec<Vec3>,
+}
+
+impl Lightmap {
+    /// A black lightmap.
+    pub fn new(width: u32, height: u32) -> Lightmap {
+        let (width, height) = (width.max(1), height.max(1));
+        return Lightmap {
+            width,
+            height,
+            texels: vec![Vec3::ZERO; (width * height) as usize],
+        };
+    }
+
+    /// Bake `meshes` into a `width` by `height` lightmap across the job system. `sky(dir)` is the light coming from
+    /// `dir` for rays that hit nothing.
+    pub fn bake(
+        meshes: &[BakeMesh],
+        width: u32,
+        height: u32,
+        settings: &BakeSettings,
+        jobs: &JobSystem,
+        sky: impl Fn(Vec3) -> Vec3 + Sync,
+    ) -> Lightmap {
+        let mut lightmap = Lightmap::new(width, height);
+        let surfaces = lightmap.rasterize(meshes);
+        let scene = Scene::new(meshes);
+        jobs.install(|| {
+            lightmap
+                .texels
+                .par_iter_mut()
+                .zip(&surfaces)
+                .enumerate()
+                .for_each(|(i, (texel, surface))| {
+                    if let Some((position, normal)) = surface {
+                        let mut rng = Rng::new(settings.seed ^ i as u64);
+                        *texel = scene.gather(*position, *normal, settings, &mut rng, &sky);
+                    }
+                });
+        });
+        let mut covered: Vec<bool> = surfaces.iter().map(Option::is_some).collect();
+        for _ in 0..DILATE {
+            lightmap.dilate(&mut covered);
+        }
+        return lightmap;
+    }
+
+    /// Where on the meshes each texel's center is, and which way the surface faces there.
+    fn rasterize(&self, meshes: &[BakeMesh]) -> Vec<Option<(Vec3, Vec3)>> {
+        let mut surfaces = vec![None; self.texels.len()];
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        for mesh in meshes {
+            for t in 0..mesh.indices.len() / 3 {
+                let vertices = mesh.triangle(t);
+                let [a, b, c] = vertices.map(|v| Vec2::from(v.lightmap_uv) * size);
+                let area = (b - a).perp_dot(c - a);
+                if area.abs() < 1e-12 {
+                    continue;
+                }
+                let min = a.min(b).min(c).floor().max(Vec2::ZERO);
+                let max = a.max(b).max(c).ceil().min(size);
+                for y in min.y as u32..max.y as u32 {
+                    for x in min.x as u32..max.x as u32 {
+                        let p = Vec2::new(x as f32, y as f32) + 0.5;
+                        let wb = (p - a).perp_dot(c - a) / area;
+                        let wc = (b - a).perp_dot(p - a) / area;
+                        let wa = 1.0 - wb - wc;
+                        if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                            continue;
+                        }
+                        let blend = |f: fn(&LightmapVertex) -> [f32; 3]| {
+                            Vec3::from(f(&vertices[0])) * wa
+                                + Vec3::from(f(&vertices[1])) * wb
+                                + Vec3::from(f(&vertices[2])) * wc
+                        };
+                        let normal = blend(|v| v.normal).normalize_or_zero();
+                        surfaces[(y * self.width + x) as usize] =
+                            Some((blend(|v| v.position), normal));
+                    }
+                }
+            }
+        }
+        return surfaces;
+    }
+
+    /// Fill in every uncovered texel next to a covered one with the average of its covered neighbors.
+    fn dilate(&mut self, covered: &mut [bool]) {
+        let before = covered.to_vec();
+        let (w, h) = (self.width as i32, self.height as i32);
+        for y in 0..h {
+            for x in 0..w {
+                let i = (y * w + x) as usize;
+                if before[i] {
+                    continue;
+                }
+                let (mut sum, mut n) = (Vec3::ZERO, 0);
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (0..w).contains(&nx)
+                        && (0..h).contains(&ny)
+                        && before[(ny * w + nx) as usize]
+                    {
+                        sum += self.texels[(ny * w + nx) as usize];
+                        n += 1;
+                    }
+                }
+                if n > 0 {
+                    self.texels[i] = sum / n as f32;
+                    covered[i] = true;
+                }
+            }
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn texel(&self, x: u32, y: u32) -> Vec3 {
+        self.texels[(y.min(self.height - 1) * self.width + x.min(self.width - 1)) as usize]
+    }
+
+    /// The lightmap at `uv`, filtered between texels like the GPU does, clamped to its edges.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let p = (uv * size - 0.5).clamp(Vec2::ZERO, size - 1.0);
+        let (base, t) = (p.floor(), p - p.floor());
+        let at = |dx, dy| self.texel(base.x as u32 + dx, base.y as u32 + dy);
+        let top = at(0, 0).lerp(at(1, 0), t.x);
+        let bottom = at(0, 1).lerp(at(1, 1), t.x);
+        return top.lerp(bottom, t.y);
+    }
+
+    /// The texels as [`TextureFormat::Rgba16Float`](super::hal::TextureFormat::Rgba16Float) data, for the texture
+    /// [`GLSL`] samples.
+    pub fn gpu_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.texels.len() * 8);
+        for texel in &self.texels {
+            for f in texel.extend(1.0).to_array() {
+                out.extend(f16::from_f32(f).to_le_bytes());
+            }
+        }
+        return out;
+    }
+
+    /// Write the lightmap out as a half float EXR.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let width = self.width as usize;
+        exr::prelude::write_rgb_file(path, width, self.height as usize, |x, y| {
+            let texel = self.texels[y * width + x];
+            (
+                f16::from_f32(texel.x),
+                f16::from_f32(texel.y),
+                f16::from_f32(texel.z),
+            )
+        })
+        .map_err(io::Error::other)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Lightmap> {
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Lightmap::new(resolution.width() as u32, resolution.height() as u32),
+            |lightmap: &mut Lightmap, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                let i = position.y() * lightmap.width as usize + position.x();
+                lightmap.texels[i] = Vec3::new(r, g, b);
+            },
+        )
+        .map_err(io::Error::other)?;
+        return Ok(image.layer_data.channel_data.pixels);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec2, Vec3};
+
+    use super::{BakeMesh, BakeSettings, Lightmap, LightmapVertex};
+    use crate::jobs::JobSystem;
+
+    /// A square facing up at `height`, from the origin out to `size`, with its lightmap UVs covering `uv`.
+    fn quad(height: f32, size: Vec2, uv: [Vec2; 2]) -> [LightmapVertex; 4] {
+        [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, z)| LightmapVertex {
+            position: [x * size.x, height, z * size.y],
+            normal: [0.0, 1.0, 0.0],
+            uv: [x, z],
+            lightmap_uv: (uv[0] + (uv[1] - uv[0]) * Vec2::new(x, z)).into(),
+        })
+    }
+
+    #[test]
+    pub fn bakes_shadows() {
+        let jobs = JobSystem::new(Some(2));
+        let indices = [0, 1, 2, 2, 3, 0];
+        let floor = quad(0.0, Vec2::splat(10.0), [Vec2::ZERO, Vec2::ONE]);
+        // A black roof over the half of the floor nearest the origin, off the lightmap.
+        let roof = quad(1.0, Vec2::new(5.0, 10.0), [Vec2::ZERO; 2]);
+        let meshes = [
+            BakeMesh {
+                vertices: &floor,
+                indices: &indices,
+                albedo: Vec3::splat(0.5),
+                emission: Vec3::ZERO,
+            },
+            BakeMesh {
+                vertices: &roof,
+                indices: &indices,
+                albedo: Vec3::ZERO,
+                emission: Vec3::ZERO,
+            },
+        ];
+        let settings = BakeSettings {
+            samples: 256,
+            ..BakeSettings::default()
+        };
+        let lightmap = Lightmap::bake(&meshes, 8, 8, &settings, &jobs, |_| Vec3::ONE);
+
+        // Out in the open sees all of the sky, and under the roof hardly any.
+        let open = lightmap.texel(7, 4);
+        let shaded = lightmap.texel(1, 4);
+        assert!(open.x > 0.9, "{open}");
+        assert!(shaded.x < 0.35, "{shaded}");
+        let middle = lightmap.sample(Vec2::new(0.5, 0.5));
+        assert!(shaded.x < middle.x && middle.x < open.x, "{middle}");
+        assert_eq!(lightmap.gpu_data().len(), 8 * 8 * 8);
+
+        let path =
+            std::env::temp_dir().join(format!("crowbar-lightmap-{}.exr", std::process::id()));
+        lightmap.save(&path).unwrap();
+        let loaded = Lightmap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (8, 8));
+        assert!((loaded.texel(7, 4) - open).abs().max_element() < 1e-2);
+    }
+}
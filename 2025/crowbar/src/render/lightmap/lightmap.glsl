@@ -0,0 +1,23 @@
+// Baked lightmap lookups, see lightmap.rs. Define LIGHTMAP_SET, LIGHTMAP_BINDING and LIGHTMAP_SAMPLER_BINDING before
+// including it to put the lightmap and its sampler somewhere other than set 0, bindings 0 and 1. Static meshes pass
+// their lightmap UVs through from vertex input location 3, see LightmapVertex::vertex_input.
+
+#ifndef LIGHTMAP_SET
+#define LIGHTMAP_SET 0
+#endif
+#ifndef LIGHTMAP_BINDING
+#define LIGHTMAP_BINDING 0
+#endif
+#ifndef LIGHTMAP_SAMPLER_BINDING
+#define LIGHTMAP_SAMPLER_BINDING 1
+#endif
+
+// Rgba16Float.
+layout(set = LIGHTMAP_SET, binding = LIGHTMAP_BINDING) uniform texture2D lightmap;
+// Linear filtering, clamped to the edges.
+layout(set = LIGHTMAP_SET, binding = LIGHTMAP_SAMPLER_BINDING) uniform sampler lightmap_sampler;
+
+// Diffuse lighting baked at `lightmap_uv`, to multiply by albedo. Keep in step with Lightmap::sample.
+vec3 lightmap_diffuse(vec2 lightmap_uv) {
+    return texture(sampler2D(lightmap, lightmap_sampler), lightmap_uv).rgb;
+}
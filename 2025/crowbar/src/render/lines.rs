@@ -0,0 +1,362 @@
+//! Thick, anti-aliased lines, built as triangles in viewport pixels instead of leaning on `LINE_LIST`, whose
+//! width and smoothing vary from driver to driver (and whose wide lines most don't do at all).
+//!
+//! A polyline is swept out as strips: a solid core as wide as the line, and a feather a pixel or so wide either
+//! side fading to transparent, which is the anti-aliasing. Corners are mitred, with the mitre clamped so a sharp
+//! turn doesn't spike off, and the ends can be capped. Lines thinner than the feather fade out instead of getting
+//! thinner, so they don't shimmer.
+//!
+//! Vertices are [`SpriteVertex`]es, drawn with [`TextureId::WHITE`] through the same pipeline as sprites. 3D lines
+//! are projected to the viewport first, clipped where they go behind the camera, so they stay the same width at
+//! any distance, like editor grids and debug lines want.
+//!
+//! [`TextureId::WHITE`]: super::sprite::TextureId::WHITE
+
+use std::f32::consts::PI;
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use super::sprite::SpriteVertex;
+use crate::{color::LinearColor, debug_draw::DebugLine, math};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineCap {
+    /// Stop square at the end point.
+    #[default]
+    Butt,
+    /// Stop square, half the width past the end point.
+    Square,
+    /// Round, half the width past the end point.
+    Round,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineStyle {
+    /// In pixels.
+    pub width: f32,
+    pub color: LinearColor,
+    pub cap: LineCap,
+    /// Longest a mitred corner can reach, in half widths.
+    pub miter_limit: f32,
+}
+
+impl LineStyle {
+    pub fn new(width: f32, color: LinearColor) -> LineStyle {
+        LineStyle {
+            width,
+            color,
+            cap: LineCap::Butt,
+            miter_limit: 4.0,
+        }
+    }
+
+    pub fn cap(mut self, cap: LineCap) -> LineStyle {
+        self.cap = cap;
+        self
+    }
+}
+
+/// Points closer than this are merged, there's no direction between them.
+const MIN_SEGMENT: f32 = 1e-4;
+/// How close to the camera plane 3D lines get clipped, in clip space w.
+const CLIP_W: f32 = 1e-5;
+
+/// A frame's lines. Keep it around and [`LineBatch::clear`] it each frame, to reuse its allocations.
+pub struct LineBatch {
+    vertices: Vec<SpriteVertex>,
+    indices: Vec<u32>,
+    /// Width of the anti-aliased edge, in pixels.
+    pub feather: f32,
+}
+
+impl Default for LineBatch {
+    fn default() -> Self {
+        LineBatch {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            feather: 1.0,
+        }
+    }
+}
+
+impl LineBatch {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    pub fn vertices(&self) -> &[SpriteVertex] {
+        &self.vertices
+    }
+
+    /// Triangles, into [`LineBatch::vertices`].
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn line(&mut self, from: Vec2, to: Vec2, style: &LineStyle) {
+        self.polyline(&[from, to], false, style);
+    }
+
+    /// Through `points` in viewport pixels, back to the first if `closed`.
+    pub fn polyline(&mut self, points: &[Vec2], closed: bool, style: &LineStyle) {
+        let mut points: Vec<Vec2> = points.to_vec();
+        points.dedup_by(|b, a| a.distance_squared(*b) < MIN_SEGMENT * MIN_SEGMENT);
+        if closed
+            && points.len() > 2
+            && points[0].distance_squared(points[points.len() - 1]) < MIN_SEGMENT * MIN_SEGMENT
+        {
+            points.pop();
+        }
+        if points.len() < 2 {
+            return;
+        }
+        let closed = closed && points.len() > 2;
+
+        // Thinner than the feather fades rather than narrowing.
+        let feather = self.feather.max(MIN_SEGMENT);
+        let core = (style.width * 0.5 - feather * 0.5).max(0.0);
+        let outer = core + feather;
+        let mut color = style.color;
+        color.a *= (style.width / feather).min(1.0);
+        let (solid, clear) = (color.to_array(), color.with_alpha(0.0).to_array());
+
+        let count = points.len();
+        // Square caps push the ends out along the line.
+        if !closed && style.cap == LineCap::Square {
+            let half = style.width * 0.5;
+            let start = (points[1] - points[0]).normalize();
+            points[0] -= start * half;
+            let end = (points[count - 1] - points[count - 2]).normalize();
+            points[count - 1] += end * half;
+        }
+
+        let segments = if closed { count } else { count - 1 };
+        let normal = |i: usize| {
+            let d = (points[(i + 1) % count] - points[i]).normalize();
+            Vec2::new(-d.y, d.x)
+        };
+
+        let first = self.vertices.len() as u32;
+        for (i, &p) in points.iter().enumerate() {
+            let m = if !closed && i == 0 {
+                normal(0)
+            } else if !closed && i == count - 1 {
+                normal(count - 2)
+            } else {
+                // Averaged, then scaled so it reaches the offset edges of both segments: the mitre.
+                let avg = (normal((i + count - 1) % count) + normal(i)) * 0.5;
+                let len2 = avg.length_squared();
+                let limit = style.miter_limit.max(1.0);
+                if len2 < 1.0 / (limit * limit) {
+                    avg.normalize_or_zero() * limit
+                } else {
+                    avg / len2
+                }
+            };
+            for (offset, color) in [
+                (outer, clear),
+                (core, solid),
+                (-core, solid),
+                (-outer, clear),
+            ] {
+                self.vertices.push(SpriteVertex {
+                    position: (p + m * offset).to_array(),
+                    uv: [0.5, 0.5],
+                    color,
+                });
+            }
+        }
+        for s in 0..segments as u32 {
+            let a = first + s * 4;
+            let b = first + ((s + 1) % count as u32) * 4;
+            for strip in 0..3 {
+                let (a0, a1, b0, b1) = (a + strip, a + strip + 1, b + strip, b + strip + 1);
+                self.indices.extend_from_slice(&[a0, b0, b1, b1, a1, a0]);
+            }
+        }
+
+        if !closed && style.cap == LineCap::Round {
+            let start = -(points[1] - points[0]).normalize();
+            let end = (points[count - 1] - points[count - 2]).normalize();
+            self.round_cap(points[0], start, core, outer, solid, clear);
+            self.round_cap(points[count - 1], end, core, outer, solid, clear);
+        }
+    }
+
+    /// A half disc at `center` bulging towards `dir`, feathered like the line.
+    fn round_cap(
+        &mut self,
+        center: Vec2,
+        dir: Vec2,
+        core: f32,
+        outer: f32,
+        solid: [f32; 4],
+        clear: [f32; 4],
+    ) {
+        // Enough segments that the chords stay within a fraction of a pixel.
+        let steps = ((outer * PI / 3.0).ceil() as u32).clamp(4, 32);
+        let base = Vec2::new(-dir.y, dir.x);
+        let first = self.vertices.len() as u32;
+        self.vertices.push(SpriteVertex {
+            position: center.to_array(),
+            uv: [0.5, 0.5],
+            color: solid,
+        });
+        for step in 0..=steps {
+            let angle = -PI * step as f32 / steps as f32;
+            let r = Vec2::from_angle(angle).rotate(base);
+            for (radius, color) in [(core, solid), (outer, clear)] {
+                self.vertices.push(SpriteVertex {
+                    position: (center + r * radius).to_array(),
+                    uv: [0.5, 0.5],
+                    color,
+                });
+            }
+        }
+        for step in 0..steps {
+            let (c0, o0) = (first + 1 + step * 2, first + 2 + step * 2);
+            let (c1, o1) = (c0 + 2, o0 + 2);
+            self.indices
+                .extend_from_slice(&[first, c0, c1, c0, o0, o1, o1, c1, c0]);
+        }
+    }
+
+    /// Through `points` in world space, projected by `view_proj` onto a `viewport` pixels big. Parts behind the
+    /// camera are cut off, splitting the line if it goes behind and comes back.
+    pub fn polyline_3d(
+        &mut self,
+        points: &[Vec3],
+        closed: bool,
+        view_proj: &Mat4,
+        viewport: Vec2,
+        style: &LineStyle,
+    ) {
+        let clip: Vec<Vec4> = points.iter().map(|p| *view_proj * p.extend(1.0)).collect();
+        let to_viewport = |c: Vec4| math::ndc_to_viewport(c.truncate().truncate() / c.w, viewport);
+        let segments = match closed && clip.len() > 2 {
+            true => clip.len(),
+            false => clip.len().saturating_sub(1),
+        };
+        let mut run: Vec<Vec2> = Vec::new();
+        for s in 0..segments {
+            let (mut a, mut b) = (clip[s], clip[(s + 1) % clip.len()]);
+            if a.w < CLIP_W && b.w < CLIP_W {
+                continue;
+            }
+            let (a_cut, b_cut) = (a.w < CLIP_W, b.w < CLIP_W);
+            if a_cut {
+                a = a.lerp(b, (CLIP_W - a.w) / (b.w - a.w));
+            } else if b_cut {
+                b = b.lerp(a, (CLIP_W - b.w) / (a.w - b.w));
+            }
+            if a_cut || run.is_empty() {
+                self.polyline(&run, false, style);
+                run.clear();
+                run.push(to_viewport(a));
+            }
+            run.push(to_viewport(b));
+            if b_cut {
+                self.polyline(&run, false, style);
+                run.clear();
+            }
+        }
+        // An unbroken closed loop joins up at the start.
+        let whole = closed && run.len() == segments + 1 && segments > 2;
+        if whole {
+            run.pop();
+        }
+        self.polyline(&run, whole, style);
+    }
+
+    /// [`DebugDraw`](crate::debug_draw::DebugDraw)'s lines, `width` pixels wide.
+    pub fn debug_lines(
+        &mut self,
+        lines: &[DebugLine],
+        view_proj: &Mat4,
+        viewport: Vec2,
+        width: f32,
+    ) {
+        for line in lines {
+            let style = LineStyle::new(width, line.color);
+            self.polyline_3d(&[line.from, line.to], false, view_proj, viewport, &style);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Mat4, Vec2, Vec3};
+
+    use super::{LineBatch, LineCap, LineStyle};
+    use crate::{color::LinearColor, math};
+
+    #[test]
+    pub fn sweeps_polylines() {
+        let mut batch = LineBatch::default();
+        let style = LineStyle::new(4.0, LinearColor::WHITE);
+        batch.line(Vec2::new(10.0, 10.0), Vec2::new(20.0, 10.0), &style);
+        assert_eq!(batch.vertices().len(), 8);
+        assert_eq!(batch.indices().len(), 18);
+        // Feathered from 1.5 to 2.5 pixels out, transparent at the edge.
+        let ys: Vec<_> = batch.vertices()[..4]
+            .iter()
+            .map(|v| v.position[1])
+            .collect();
+        assert_eq!(ys, [12.5, 11.5, 8.5, 7.5]);
+        assert_eq!(batch.vertices()[0].color[3], 0.0);
+        assert_eq!(batch.vertices()[1].color[3], 1.0);
+
+        // A right angle mitres its corner out to the offset edges' meeting point.
+        batch.clear();
+        let corner = [Vec2::ZERO, Vec2::new(10.0, 0.0), Vec2::new(10.0, 10.0)];
+        batch.polyline(&corner, false, &style);
+        assert!(Vec2::from(batch.vertices()[5].position).abs_diff_eq(Vec2::new(8.5, 1.5), 1e-4));
+        // Closed, the last point connects back to the first.
+        batch.clear();
+        batch.polyline(&corner, true, &style);
+        assert_eq!(batch.indices().len(), 3 * 18);
+
+        // Thin lines fade, and round caps add their fans.
+        batch.clear();
+        let thin = LineStyle::new(0.5, LinearColor::WHITE).cap(LineCap::Round);
+        batch.line(Vec2::ZERO, Vec2::new(5.0, 0.0), &thin);
+        assert_eq!(batch.vertices()[1].color[3], 0.5);
+        assert!(batch.vertices().len() > 8);
+    }
+
+    #[test]
+    pub fn clips_behind_the_camera() {
+        let viewport = Vec2::new(200.0, 100.0);
+        let view_proj = math::perspective(1.0, 2.0, 0.1, 100.0)
+            * Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let mut batch = LineBatch::default();
+        let style = LineStyle::new(2.0, LinearColor::RED);
+        // From in front of the camera to behind it, then back in front: two separate pieces.
+        let points = [
+            Vec3::new(-1.0, 0.0, -5.0),
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(1.0, 0.0, -5.0),
+        ];
+        batch.polyline_3d(&points, false, &view_proj, viewport, &style);
+        assert_eq!(batch.indices().len(), 2 * 18);
+        for v in batch.vertices() {
+            assert!(v.position.iter().all(|p| p.is_finite()));
+        }
+        // Straight ahead is the middle of the viewport.
+        batch.clear();
+        batch.polyline_3d(
+            &[Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 1.0, -5.0)],
+            false,
+            &view_proj,
+            viewport,
+            &style,
+        );
+        let core = Vec2::from(batch.vertices()[1].position);
+        assert!(core.abs_diff_eq(Vec2::new(100.0, 50.0), 1.0));
+    }
+}
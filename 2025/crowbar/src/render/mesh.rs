@@ -0,0 +1,1734 @@
+//! The 3D pass: every camera's [`DrawList`], drawn in camera order over the frame before the sprites.
+//!
+//! Meshes are all unit cubes until there are mesh assets, see [`MeshRenderer::local_bounds`], and a material is a
+//! colour from [`PALETTE`] by id until there are material assets. They're lit by the scene's first directional
+//! light, and by its irradiance probes, or a flat [`AMBIENT`] if it has none. Meshes with [`Lightmapped`] are lit
+//! from the scene's lightmap instead, through the cube's lightmap UVs, see [`cube`] and [`super::lightmap`]. Every
+//! mesh reflects the scene's reflection probes, if it has any, see [`super::reflections`]. Each batch is one
+//! instanced indirect draw. [`MeshPass::prepare`] copies the transforms, now and as of the last frame, the colours,
+//! where the lightmap UVs go and the draw arguments into buffers per frame in flight before the pass, uploads the
+//! probes, the lightmap and the reflection probes when they change, and has an [`IndirectValidator`] check the
+//! arguments if it's given one.
+//!
+//! Drawn to a target with [`TargetFormats::velocity`], the pass writes motion vectors to it too, see
+//! [`super::motion_blur`].
+//!
+//! Given a shadow map, the light casts shadows: [`MeshPass::record_shadows`] draws every mesh that casts them into
+//! it, looking down the light over the whole scene, in a pass before the cameras'. Without one, nothing's shadowed.
+//!
+//! With anything outlined, [`MeshPass::record_outlines`] draws the outlined meshes again into the outline mask, see
+//! [`super::outline`], through the last camera.
+//!
+//! Given planar reflections, [`MeshPass::record_reflection`] draws each one's view into its target, in a pass of
+//! its own before the cameras', and [`MeshPass::record`] draws the reflectors' surfaces showing them after the last
+//! camera's meshes, see [`super::planar`]. A surface is the cube's +Y face moved down onto the reflector's plane.
+//!
+//! Given views through portals, [`MeshPass::record`] draws them into the stencil-marked regions of the last
+//! camera's view before its own meshes, see [`super::portal`]. A portal's surface is drawn to depth and stencil
+//! alone, as the cube's +Y face like a reflector's. Drawn to a target without a stencil, portals show nothing.
+//!
+//! Ship its shaders compiled, as [`VERTEX_ASSET`], [`FRAGMENT_ASSET`], [`OUTLINE_ASSET`] and [`SURFACE_ASSET`], or
+//! they're compiled from [`VERTEX_SHADER`], [`FRAGMENT_SHADER`], with [`FRAGMENT_INCLUDES`], [`OUTLINE_SHADER`] and
+//! [`SURFACE_SHADER`], with [`SURFACE_INCLUDES`], at startup, see [`MeshShaders::builtin`].
+//!
+//! [`MeshRenderer::local_bounds`]: crate::ecs::components::MeshRenderer::local_bounds
+//! [`Lightmapped`]: crate::ecs::components::Lightmapped
+
+use std::{ops::Range, sync::Arc};
+
+use ash::{prelude::VkResult, vk};
+use glam::{Affine3A, Mat4, Vec2, Vec3};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::{FrameDescriptors, LayoutBinding, LayoutCache, LayoutDesc},
+    draw::DrawList,
+    extract::ExtractedScene,
+    hal::{
+        BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+        TextureFormat, TextureState, TextureUsage,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture, vk_format},
+    },
+    indirect::{IndirectDraws, IndirectLimits, IndirectValidator},
+    lightmap::{self, Lightmap, LightmapVertex},
+    motion_blur,
+    outline::{self, MASK_FORMAT},
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, StencilMode, TargetFormats},
+    planar::{self, PlanarTarget, PlanarView},
+    portal::{self, PortalStep, PortalViews, SubView},
+    probes::{self, ProbeGrid},
+    reflections::{self, ReflectionProbes},
+    rendering::PassContext,
+    shader::{
+        compile::{ShaderStage, builtin_shader, builtin_shader_including},
+        reflect::Spirv,
+    },
+};
+use crate::{
+    color::LinearColor,
+    ecs::components::{LightKind, Lightmapped, MaterialId},
+    math::{self, NDC_FAR, bounds::Aabb},
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// The vertex shader's source, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("mesh/mesh.vert");
+/// The fragment shader's source, to compile at runtime.
+pub const FRAGMENT_SHADER: &str = include_str!("mesh/mesh.frag");
+/// What the fragment shader includes, to compile it at runtime.
+pub const FRAGMENT_INCLUDES: &[(&str, &str)] = &[
+    ("../probes/probes.glsl", probes::GLSL),
+    ("../lightmap/lightmap.glsl", lightmap::GLSL),
+    ("../reflections/reflections.glsl", reflections::GLSL),
+    ("../motion_blur/velocity.glsl", motion_blur::VELOCITY_GLSL),
+];
+/// The outline mask's fragment shader's source, to compile at runtime.
+pub const OUTLINE_SHADER: &str = outline::MASK_SHADER;
+/// Reflectors' surfaces' fragment shader's source, to compile at runtime.
+pub const SURFACE_SHADER: &str = planar::SURFACE_SHADER;
+/// What the surfaces' fragment shader includes, to compile it at runtime.
+pub const SURFACE_INCLUDES: &[(&str, &str)] = &[
+    ("../planar/planar.glsl", planar::GLSL),
+    ("../motion_blur/velocity.glsl", motion_blur::VELOCITY_GLSL),
+];
+/// Where the compiled shaders go among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/mesh.vert";
+pub const FRAGMENT_ASSET: &str = "shaders/mesh.frag";
+pub const OUTLINE_ASSET: &str = "shaders/outline_mask.frag";
+pub const SURFACE_ASSET: &str = "shaders/planar_surface.frag";
+
+/// Material colours, by id, wrapping round.
+pub const PALETTE: [LinearColor; 8] = [
+    LinearColor::rgb(0.8, 0.8, 0.8),
+    LinearColor::rgb(0.8, 0.0, 0.0),
+    LinearColor::rgb(0.2, 0.6, 0.2),
+    LinearColor::rgb(0.15, 0.3, 0.8),
+    LinearColor::rgb(0.9, 0.7, 0.1),
+    LinearColor::rgb(0.6, 0.2, 0.7),
+    LinearColor::rgb(0.1, 0.7, 0.7),
+    LinearColor::rgb(0.3, 0.3, 0.3),
+];
+
+/// Light everything gets, whichever way it faces, in a scene without irradiance probes.
+pub const AMBIENT: LinearColor = LinearColor::rgb(0.1, 0.1, 0.12);
+
+pub(super) const CUBE_VERTICES: u32 = 36;
+/// Where the cube's +Y face is in its vertices, and how many it has, to draw it alone for reflectors' surfaces.
+const SURFACE_VERTICES: (u32, u32) = (12, 6);
+/// A `VkDrawIndirectCommand`.
+const DRAW_BYTES: u64 = 16;
+/// A transform's three rows, the same as of the last frame, the material's colour, and the scale and offset of
+/// the lightmap UVs.
+const INSTANCE_BYTES: u64 = 32 * 4;
+/// A set per frame, with the probes' storage buffer, the light's uniforms, the shadow map and its sampler, the
+/// lightmap and its sampler, and the reflection probes' storage buffer, atlas and sampler. Reflectors' surfaces' sets
+/// have a reflection and its sampler each, which those leave room for.
+const RATIOS: &[(vk::DescriptorType, f32)] = &[
+    (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+    (vk::DescriptorType::UNIFORM_BUFFER, 1.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 3.0),
+    (vk::DescriptorType::SAMPLER, 3.0),
+];
+/// What the per frame buffers start at: room for 256 transforms.
+const MIN_BUFFER_BYTES: u64 = 256 * INSTANCE_BYTES;
+/// The view projection, now and as of the last frame.
+const PUSH_BYTES: u32 = 2 * 16 * 4;
+/// The light's direction and colour, the ambient, the shadow map's view projection and how to sample it.
+const LIGHT_BYTES: u64 = 3 * 4 * 4 + 16 * 4 + 4 * 4;
+/// How far shadow casters are pushed away from the light, constant and by slope, so surfaces don't shadow
+/// themselves.
+const SHADOW_BIAS: (f32, f32) = (-2.0, -2.5);
+/// What the shadow map is drawn as.
+const SHADOW_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+/// A probe grid with no probes, which the fragment shader takes as the flat ambient.
+const NO_PROBES: [u8; 48] = [0; 48];
+/// No reflection probes, which the fragment shader takes as nothing to reflect.
+const NO_REFLECTIONS: [u8; 16] = [0; 16];
+/// What the lightmap is uploaded as, see [`Lightmap::gpu_data`], and the reflection probes' atlas, see
+/// [`ReflectionProbes::atlas`].
+const LIGHTMAP_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// How far into its cell of the cube's lightmap UVs each face stops, so filtering doesn't bleed between faces.
+const CUBE_LIGHTMAP_MARGIN: f32 = 1.0 / 32.0;
+
+pub fn material_color(material: MaterialId) -> LinearColor {
+    return PALETTE[material.0 as usize % PALETTE.len()];
+}
+
+/// A unit cube's triangles, counter-clockwise from outside, as every mesh is drawn and as a lightmap bake takes it.
+/// Each face is a whole UV square, and has a cell of a 3 by 2 grid of lightmap UVs to itself.
+pub fn cube() -> Vec<LightmapVertex> {
+    // Each face's normal, and two axes across it crossing to the normal.
+    let faces = [
+        (Vec3::X, Vec3::Y, Vec3::Z),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::Z, Vec3::X),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::Y, Vec3::X),
+    ];
+    let uvs = [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y];
+    let mut vertices = Vec::with_capacity(36);
+    for (face, (normal, u, v)) in faces.into_iter().enumerate() {
+        let corners = [-u - v, u - v, u + v, -u + v].map(|c| (normal + c) * 0.5);
+        let cell = Vec2::new((face % 3) as f32, (face / 3) as f32);
+        let inset = |uv: Vec2| {
+            let uv = uv * (1.0 - 2.0 * CUBE_LIGHTMAP_MARGIN) + CUBE_LIGHTMAP_MARGIN;
+            return (cell + uv) / Vec2::new(3.0, 2.0);
+        };
+        for i in [0, 1, 2, 2, 3, 0] {
+            vertices.push(LightmapVertex {
+                position: corners[i].to_array(),
+                normal: normal.to_array(),
+                uv: uvs[i].to_array(),
+                lightmap_uv: inset(uvs[i]).to_array(),
+            });
+        }
+    }
+    return vertices;
+}
+
+/// [`cube`] as the vertex buffer every pipeline drawing it takes, see [`LightmapVertex::vertex_input`].
+pub(super) fn cube_bytes() -> Vec<u8> {
+    return cube()
+        .into_iter()
+        .flat_map(|v| {
+            [v.position, v.normal]
+                .concat()
+                .into_iter()
+                .chain(v.uv)
+                .chain(v.lightmap_uv)
+        })
+        .flat_map(f32::to_ne_bytes)
+        .collect();
+}
+
+/// `transform`'s rows, as the vertex shader takes them.
+fn rows(transform: &Affine3A) -> [f32; 12] {
+    let m = transform.matrix3;
+    let t = transform.translation;
+    return [
+        m.x_axis.x, m.y_axis.x, m.z_axis.x, t.x, //
+        m.x_axis.y, m.y_axis.y, m.z_axis.y, t.y, //
+        m.x_axis.z, m.y_axis.z, m.z_axis.z, t.z,
+    ];
+}
+
+/// Where a mesh's lightmap UVs go, as the vertex shader takes it: the scale, then the offset. No scale has it lit by
+/// the probes instead.
+fn lightmap_rect(lightmap: Option<Lightmapped>) -> [f32; 4] {
+    return lightmap.map_or([0.0; 4], |l| [l.scale.x, l.scale.y, l.offset.x, l.offset.y]);
+}
+
+/// `views`' instances, every view's in turn, as the vertex shader takes them: each transform's rows, now and as of
+/// the last frame, then its batch's material colour and where its lightmap UVs go.
+fn instance_data<'a>(views: impl IntoIterator<Item = &'a DrawList>) -> Vec<f32> {
+    let mut data = Vec::new();
+    for view in views {
+        let (instances, previous) = (view.instances(), view.previous_instances());
+        let lightmaps = view.lightmaps();
+        let batches = view
+            .opaque_batches()
+            .iter()
+            .chain(view.transparent_batches());
+        for batch in batches {
+            let color = material_color(batch.key.material).to_array();
+            let first = batch.first_instance as usize;
+            for i in first..first + batch.instance_count as usize {
+                data.extend(rows(&instances[i]));
+                data.extend(rows(&previous[i]));
+                data.extend(color);
+                data.extend(lightmap_rect(lightmaps[i]));
+            }
+        }
+    }
+    return data;
+}
+
+/// An orthographic view down `direction` taking in all of `bounds`, for a directional light's shadow map.
+fn sun_view_proj(direction: Vec3, bounds: &Aabb) -> Mat4 {
+    let direction = direction.normalize_or(math::FORWARD);
+    // Any up will do, as long as it isn't along the light.
+    let up = match direction.dot(math::UP).abs() > 0.99 {
+        true => math::FORWARD,
+        false => math::UP,
+    };
+    let view = Mat4::look_to_rh(bounds.center(), direction, up);
+    let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+    for corner in bounds.corners() {
+        let corner = view.transform_point3(corner);
+        min = min.min(corner);
+        max = max.max(corner);
+    }
+    // Looking down -Z, the nearest corner has the greatest Z. Swapping the planes reverses depth, like
+    // `math::orthographic`.
+    let projection = Mat4::orthographic_rh(min.x, max.x, min.y, max.y, -min.z, -max.z);
+    return projection * view;
+}
+
+/// The cube's vertices and the instances' rows, colours and lightmap UV rects, as every pipeline here takes them.
+fn vertex_input(builder: GraphicsPipelineBuilder<'_>) -> GraphicsPipelineBuilder<'_> {
+    LightmapVertex::vertex_input(builder, 0)
+        .vertex_buffer(1, INSTANCE_BYTES as u32, true)
+        .attribute(4, 1, vk::Format::R32G32B32A32_SFLOAT, 0)
+        .attribute(5, 1, vk::Format::R32G32B32A32_SFLOAT, 16)
+        .attribute(6, 1, vk::Format::R32G32B32A32_SFLOAT, 32)
+        .attribute(7, 1, vk::Format::R32G32B32A32_SFLOAT, 48)
+        .attribute(8, 1, vk::Format::R32G32B32A32_SFLOAT, 64)
+        .attribute(9, 1, vk::Format::R32G32B32A32_SFLOAT, 80)
+        .attribute(10, 1, vk::Format::R32G32B32A32_SFLOAT, 96)
+        .attribute(11, 1, vk::Format::R32G32B32A32_SFLOAT, 112)
+}
+
+/// How many draws `view` has, one per batch.
+fn batches(view: &DrawList) -> usize {
+    view.opaque_batches().len() + view.transparent_batches().len()
+}
+
+/// Drawing with `vertex` and `fragment` through `layout` to `target`, opaque, and depth tested and written if
+/// there's depth.
+fn target_builder<'a>(
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    target: TargetFormats,
+) -> GraphicsPipelineBuilder<'a> {
+    let depth = match target.depth {
+        vk::Format::UNDEFINED => DepthMode::Off,
+        _ => DepthMode::TestWrite,
+    };
+    let mut builder = vertex_input(GraphicsPipelineBuilder::new(layout))
+        .vertex_fragment(vertex, fragment)
+        .samples(vk::SampleCountFlags::from_raw(target.samples))
+        .color(target.color, BlendMode::Opaque)
+        .depth(target.depth, depth);
+    if target.velocity != vk::Format::UNDEFINED {
+        builder = builder.color(target.velocity, BlendMode::Opaque);
+    }
+    return builder;
+}
+
+/// Drawing a portal's surface to `target`'s depth and stencil alone, with `stencil`. Marking tests depth like the
+/// scene, where clearing and unmarking write it over whatever's there, see [`super::portal`].
+fn portal_builder<'a>(
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    target: TargetFormats,
+    stencil: StencilMode,
+) -> GraphicsPipelineBuilder<'a> {
+    let depth = match stencil {
+        StencilMode::Mark => DepthMode::Test,
+        _ => DepthMode::Always,
+    };
+    let mut builder = vertex_input(GraphicsPipelineBuilder::new(layout))
+        .shader(vk::ShaderStageFlags::VERTEX, vertex, c"main")
+        .samples(vk::SampleCountFlags::from_raw(target.samples))
+        .cull(vk::CullModeFlags::NONE)
+        .color(target.color, BlendMode::Masked)
+        .depth(target.depth, depth)
+        .stencil(stencil);
+    if target.velocity != vk::Format::UNDEFINED {
+        builder = builder.color(target.velocity, BlendMode::Masked);
+    }
+    return builder;
+}
+
+/// A draw per batch, every view's in turn, with the views' instances one after another.
+fn draw_commands<'a>(
+    views: impl IntoIterator<Item = &'a DrawList>,
+) -> Vec<vk::DrawIndirectCommand> {
+    let mut draws = Vec::new();
+    let mut base = 0;
+    for view in views {
+        let batches = view
+            .opaque_batches()
+            .iter()
+            .chain(view.transparent_batches());
+        for batch in batches {
+            draws.push(vk::DrawIndirectCommand {
+                vertex_count: CUBE_VERTICES,
+                instance_count: batch.instance_count,
+                first_vertex: 0,
+                first_instance: base + batch.first_instance,
+            });
+        }
+        base += view.instances().len() as u32;
+    }
+    return draws;
+}
+
+#[derive(Default)]
+struct Slot {
+    instances: Option<VulkanBuffer>,
+    /// A draw per batch, every view's in turn.
+    draws: Option<VulkanBuffer>,
+    /// The frame last prepared into it.
+    prepared: Option<u64>,
+    /// The scene's irradiance probes, as [`ProbeGrid::gpu_data`], or [`NO_PROBES`].
+    probes: Option<VulkanBuffer>,
+    /// The light, as the fragment shader's uniforms.
+    light: Option<VulkanBuffer>,
+    /// The light's view projection and which draw has the shadow casters, if they're drawn this frame.
+    shadow: Option<(Mat4, u64)>,
+    /// Which draw has the outlined meshes, if there are any this frame.
+    outline: Option<u64>,
+    /// Each planar reflection's view projection and draws, for the reflections that see anything this frame.
+    mirrored: Vec<Option<(Mat4, Range<u64>)>>,
+    /// Each reflector's surface's set, binding its reflection, and draw, for the ones with a view this frame.
+    surfaces: Vec<(vk::DescriptorSet, u64)>,
+    /// The last camera's view and those through portals from it, see [`PortalViews::views`].
+    portal_views: Vec<SubView>,
+    /// Each view through a portal's draws, and its portal's surface's draw, in `portal_views`' order after the
+    /// camera's.
+    portals: Vec<(Range<u64>, u64)>,
+    /// The order to draw `portal_views` in, see [`super::portal::steps`].
+    steps: Vec<PortalStep>,
+    /// What's in `probes`, to upload again when it changes.
+    grid: Option<Arc<ProbeGrid>>,
+    /// The scene's lightmap, as [`Lightmap::gpu_data`], one per slot like the probes.
+    lightmap: Option<VulkanTexture>,
+    /// What `lightmap` and `atlas` were uploaded from, kept until the GPU's done with the slot.
+    staging: Vec<VulkanBuffer>,
+    /// What's in `lightmap`, to upload again when it changes.
+    baked: Option<Arc<Lightmap>>,
+    /// The scene's reflection probes, as [`ReflectionProbes::gpu_data`], or [`NO_REFLECTIONS`].
+    reflections: Option<VulkanBuffer>,
+    /// Their cubemaps, as [`ReflectionProbes::atlas`], if any are captured.
+    atlas: Option<VulkanTexture>,
+    /// What's in `reflections` and `atlas`, to upload again when it changes.
+    captured: Option<Arc<ReflectionProbes>>,
+    /// Binds `probes`, `light`, the shadow map, `lightmap`, `reflections` and `atlas`, for the frame last prepared.
+    set: vk::DescriptorSet,
+}
+
+pub struct MeshPass {
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, way round triangles face and stencil test, kept until the pass goes as there are
+    /// only ever a few.
+    pipelines: Vec<((TargetFormats, vk::FrontFace, StencilMode), vk::Pipeline)>,
+    /// Draws depth alone into a shadow map.
+    shadow_pipeline: vk::Pipeline,
+    outline_fragment: vk::ShaderModule,
+    /// Draws the outline mask, see [`outline`].
+    outline_pipeline: vk::Pipeline,
+    /// Belongs to the layout cache, like `set_layout`.
+    surface_set_layout: vk::DescriptorSetLayout,
+    surface_layout: vk::PipelineLayout,
+    surface_fragment: vk::ShaderModule,
+    /// Draw reflectors' surfaces, one per target drawn to like `pipelines`.
+    surface_pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    /// Draw portals' surfaces to depth and stencil, one per target and stencil test, see [`portal_builder`].
+    portal_pipelines: Vec<((TargetFormats, StencilMode), vk::Pipeline)>,
+    /// Compares against the shadow map, filtering the results.
+    shadow_sampler: vk::Sampler,
+    /// Bound in the shadow map's place without one, never read.
+    no_shadows: Option<VulkanTexture>,
+    /// Filters the lightmap, clamped to its edges. The reflection probes' atlas is read through it too, a texel at
+    /// a time.
+    lightmap_sampler: vk::Sampler,
+    /// Bound in the lightmap's and the atlas's place without them, never read.
+    no_lightmap: Option<VulkanTexture>,
+    cube: Option<VulkanBuffer>,
+    descriptors: FrameDescriptors,
+    slots: Vec<Slot>,
+}
+
+/// The pass's shaders, see [`MeshPass::new`].
+pub struct MeshShaders {
+    pub vertex: Spirv,
+    pub fragment: Spirv,
+    pub outline: Spirv,
+    pub surface: Spirv,
+}
+
+impl MeshShaders {
+    /// The engine's own, loaded or compiled like [`builtin_shader`]. `None` if any of them can't be had.
+    pub fn builtin() -> Option<MeshShaders> {
+        let vertex = builtin_shader(
+            VERTEX_ASSET,
+            "mesh.vert",
+            VERTEX_SHADER,
+            ShaderStage::Vertex,
+        );
+        let fragment = builtin_shader_including(
+            FRAGMENT_ASSET,
+            "mesh.frag",
+            FRAGMENT_SHADER,
+            FRAGMENT_INCLUDES,
+            ShaderStage::Fragment,
+        );
+        let outline = builtin_shader(
+            OUTLINE_ASSET,
+            "mask.frag",
+            OUTLINE_SHADER,
+            ShaderStage::Fragment,
+        );
+        let surface = builtin_shader_including(
+            SURFACE_ASSET,
+            "surface.frag",
+            SURFACE_SHADER,
+            SURFACE_INCLUDES,
+            ShaderStage::Fragment,
+        );
+        return Some(MeshShaders {
+            vertex: vertex?,
+            fragment: fragment?,
+            outline: outline?,
+            surface: surface?,
+        });
+    }
+}
+
+/// What [`MeshPass::prepare`] copies into a frame's buffers: `scene`, as `views` see it, and what `reflections` and
+/// `portals` see.
+#[derive(Clone, Copy)]
+pub struct MeshPrepare<'a> {
+    pub scene: &'a ExtractedScene,
+    pub views: &'a [DrawList],
+    pub reflections: &'a [PlanarTarget<VulkanDevice>],
+    pub portals: &'a PortalViews,
+    /// Where the shadow casters get drawn, `None` without shadows.
+    pub shadow_map: Option<&'a VulkanTexture>,
+}
+
+impl MeshPass {
+    /// Set up for `frames_in_flight` frames, with `shaders`.
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s, or null. Both must outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        shaders: &MeshShaders,
+        frames_in_flight: u32,
+    ) -> VkResult<MeshPass> {
+        let mut pass = MeshPass {
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            shadow_pipeline: vk::Pipeline::null(),
+            outline_fragment: vk::ShaderModule::null(),
+            outline_pipeline: vk::Pipeline::null(),
+            surface_set_layout: vk::DescriptorSetLayout::null(),
+            surface_layout: vk::PipelineLayout::null(),
+            surface_fragment: vk::ShaderModule::null(),
+            surface_pipelines: Vec::new(),
+            portal_pipelines: Vec::new(),
+            shadow_sampler: vk::Sampler::null(),
+            no_shadows: None,
+            lightmap_sampler: vk::Sampler::null(),
+            no_lightmap: None,
+            cube: None,
+            descriptors: FrameDescriptors::new(device.raw(), frames_in_flight, RATIOS),
+            slots: Vec::new(),
+        };
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, layouts, shaders) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        pass.slots
+            .resize_with(frames_in_flight.max(1) as usize, Slot::default);
+        return Ok(pass);
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        shaders: &MeshShaders,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(PUSH_BYTES);
+        let binding =
+            |binding, ty| LayoutBinding::new(binding, ty, 1, vk::ShaderStageFlags::FRAGMENT);
+        let bindings = [
+            binding(0, vk::DescriptorType::STORAGE_BUFFER),
+            binding(1, vk::DescriptorType::UNIFORM_BUFFER),
+            binding(2, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(3, vk::DescriptorType::SAMPLER),
+            binding(4, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(5, vk::DescriptorType::SAMPLER),
+            binding(6, vk::DescriptorType::STORAGE_BUFFER),
+            binding(7, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(8, vk::DescriptorType::SAMPLER),
+        ];
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = layouts.get(raw, &LayoutDesc::new(bindings))?;
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[self.set_layout])
+                    .push_constant_ranges(std::slice::from_ref(&push)),
+                allocs(),
+            )?;
+            let surface = [
+                binding(0, vk::DescriptorType::SAMPLED_IMAGE),
+                binding(1, vk::DescriptorType::SAMPLER),
+            ];
+            self.surface_set_layout = layouts.get(raw, &LayoutDesc::new(surface))?;
+            self.surface_layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[self.surface_set_layout])
+                    .push_constant_ranges(std::slice::from_ref(&push)),
+                allocs(),
+            )?;
+            self.vertex = shaders.vertex.create_module(raw)?;
+            self.fragment = shaders.fragment.create_module(raw)?;
+            self.surface_fragment = shaders.surface.create_module(raw)?;
+            // Reversed-Z, so what the light reaches is at least as near as what's in the map.
+            self.shadow_sampler = raw.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .compare_enable(true)
+                    .compare_op(vk::CompareOp::GREATER_OR_EQUAL),
+                allocs(),
+            )?;
+            self.lightmap_sampler = raw.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                allocs(),
+            )?;
+            let builder = vertex_input(GraphicsPipelineBuilder::new(self.layout))
+                .shader(vk::ShaderStageFlags::VERTEX, self.vertex, c"main")
+                .depth(vk_format(SHADOW_FORMAT), DepthMode::TestWrite)
+                .depth_bias(SHADOW_BIAS.0, SHADOW_BIAS.1)
+                .cache(self.cache);
+            self.shadow_pipeline = builder.build(raw)?;
+            self.outline_fragment = shaders.outline.create_module(raw)?;
+            // Through everything, so what's outlined shows through what's in front of it.
+            let builder = vertex_input(GraphicsPipelineBuilder::new(self.layout))
+                .vertex_fragment(self.vertex, self.outline_fragment)
+                .color(vk_format(MASK_FORMAT), BlendMode::Opaque)
+                .depth(vk::Format::UNDEFINED, DepthMode::Off)
+                .cache(self.cache);
+            self.outline_pipeline = builder.build(raw)?;
+        }
+        self.no_shadows = Some(device.create_texture(&TextureDesc {
+            width: 1,
+            height: 1,
+            format: SHADOW_FORMAT,
+            usage: TextureUsage::SAMPLED,
+            samples: 1,
+        })?);
+        self.no_lightmap = Some(device.create_texture(&TextureDesc {
+            width: 1,
+            height: 1,
+            format: LIGHTMAP_FORMAT,
+            usage: TextureUsage::SAMPLED,
+            samples: 1,
+        })?);
+        let vertices = cube_bytes();
+        let cube = device.create_buffer(&BufferDesc {
+            size: vertices.len() as u64,
+            usage: BufferUsage::VERTEX,
+            location: MemoryLocation::Upload,
+        })?;
+        // SAFETY: Just made, so the GPU hasn't seen it.
+        let written = unsafe { device.write_buffer(&cube, 0, &vertices) };
+        self.cube = Some(cube);
+        return written;
+    }
+
+    /// Keep instances for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        // SAFETY: Passed on to the caller.
+        unsafe { self.destroy_slots(device) };
+        self.descriptors = FrameDescriptors::new(device.raw(), frames, RATIOS);
+        self.slots
+            .resize_with(frames.max(1) as usize, Slot::default);
+    }
+
+    unsafe fn destroy_slots(&mut self, device: &VulkanDevice) {
+        for slot in self.slots.drain(..) {
+            let buffers = [
+                slot.instances,
+                slot.draws,
+                slot.probes,
+                slot.light,
+                slot.reflections,
+            ];
+            for buffer in buffers.into_iter().flatten().chain(slot.staging) {
+                // SAFETY: Passed on to the caller.
+                unsafe { device.destroy_buffer(buffer) };
+            }
+            for texture in [slot.lightmap, slot.atlas].into_iter().flatten() {
+                // SAFETY: Passed on to the caller.
+                unsafe { device.destroy_texture(texture) };
+            }
+        }
+    }
+
+    /// The pipeline for drawing to `target` with `front_face` facing the camera and `stencil`, built the first time
+    /// it's drawn to that way.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+        front_face: vk::FrontFace,
+        stencil: StencilMode,
+    ) -> VkResult<vk::Pipeline> {
+        let key = (target, front_face, stencil);
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(k, _)| *k == key) {
+            return Ok(pipeline);
+        }
+        let builder = target_builder(self.layout, self.vertex, self.fragment, target)
+            .front_face(front_face)
+            .stencil(stencil)
+            .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((key, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// The pipeline for drawing reflectors' surfaces to `target`, built the first time they're drawn to it.
+    unsafe fn surface_pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.surface_pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        let builder = target_builder(
+            self.surface_layout,
+            self.vertex,
+            self.surface_fragment,
+            target,
+        )
+        .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.surface_pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// The pipeline for drawing portals' surfaces to `target` with `stencil`, built the first time they're drawn
+    /// to it that way.
+    unsafe fn portal_pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+        stencil: StencilMode,
+    ) -> VkResult<vk::Pipeline> {
+        let key = (target, stencil);
+        if let Some(&(_, pipeline)) = self.portal_pipelines.iter().find(|(k, _)| *k == key) {
+            return Ok(pipeline);
+        }
+        let builder = portal_builder(self.layout, self.vertex, target, stencil).cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.portal_pipelines.push((key, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// Copy `input`'s views' transforms and draw arguments into `frame`'s buffers, along with its scene's probes,
+    /// lightmap and reflection probes if they've changed, and record `validator` checking the arguments into `cmd` if
+    /// there is one. With a shadow map, the shadow casters go in too, for [`MeshPass::record_shadows`] to draw into
+    /// it. The outlined meshes go in for [`MeshPass::record_outlines`], and what the reflections see for
+    /// [`MeshPass::record_reflection`], with their surfaces, bar water's, which the water pass shows its own way.
+    /// What the portals see goes in for [`MeshPass::record`], with their surfaces. Goes before the passes
+    /// [`MeshPass::record`] and those draw them in.
+    ///
+    /// # Safety
+    /// `cmd` must be recording outside a pass, and the GPU done with the frame that last used this frame's slot,
+    /// like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for. `validator` has to have
+    /// begun `frame`. The shadow map has to be in [`TextureState::ShaderRead`] by the time the meshes are drawn,
+    /// and the reflections by the time their surfaces are.
+    pub unsafe fn prepare(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        input: MeshPrepare<'_>,
+        validator: Option<&mut IndirectValidator>,
+    ) -> VkResult<()> {
+        let MeshPrepare {
+            scene,
+            views,
+            reflections,
+            portals,
+            shadow_map,
+        } = input;
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+        slot.prepared = None;
+        slot.shadow = None;
+        slot.outline = None;
+        slot.mirrored.clear();
+        slot.surfaces.clear();
+        slot.portals.clear();
+        slot.portal_views.clear();
+        slot.steps.clear();
+        // SAFETY: The caller vouches the GPU's done with this frame's slot.
+        unsafe { self.descriptors.begin_frame(frame)? };
+        let seen: Vec<&PlanarTarget<VulkanDevice>> =
+            reflections.iter().filter(|r| r.view.is_some()).collect();
+        let reflected = seen.iter().map(|r| &r.draws);
+        // Water shows its reflection on its own surface, see super::water.
+        let surfaced: Vec<&PlanarTarget<VulkanDevice>> = seen
+            .iter()
+            .copied()
+            .filter(|r| !scene.waters.iter().any(|w| w.entity == r.entity))
+            .collect();
+        // The camera's own view through the portals has its own draws.
+        let through = 1..portals.views().len();
+        let portaled = through.clone().map(|v| portals.draws(v));
+        let all = || {
+            views
+                .iter()
+                .chain(reflected.clone())
+                .chain(portaled.clone())
+        };
+        let mut count: usize = all().map(|v| v.instances().len()).sum();
+        if count + seen.len() + through.len() == 0 {
+            return Ok(());
+        }
+        let mut instances: Vec<u8> = instance_data(all())
+            .into_iter()
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        let mut draws = draw_commands(all());
+        // The reflections' draws are after the cameras', in the same order.
+        let mut first: u64 = views.iter().map(|v| batches(v) as u64).sum();
+        for reflection in reflections {
+            let mirrored = reflection.view.map(|view| {
+                let range = first..first + batches(&reflection.draws) as u64;
+                first = range.end;
+                (view.view_proj, range)
+            });
+            slot.mirrored.push(mirrored);
+        }
+        // Then the portals' views', in the same order.
+        let mut ranges = Vec::with_capacity(through.len());
+        for draws in portaled.clone() {
+            ranges.push(first..first + batches(draws) as u64);
+            first += batches(draws) as u64;
+        }
+
+        let sun = scene
+            .lights
+            .iter()
+            .find(|l| matches!(l.kind, LightKind::Directional));
+        // Everything that could be shadowed, to fit the shadow map around.
+        let bounds = scene
+            .meshes
+            .iter()
+            .map(|m| m.bounds)
+            .reduce(|a, b| a.union(&b));
+        let casters: Vec<_> = scene.meshes.iter().filter(|m| m.cast_shadows).collect();
+        if let (Some(_), Some(sun), Some(bounds)) = (shadow_map, sun, bounds)
+            && !casters.is_empty()
+        {
+            let view_proj = sun_view_proj(sun.direction, &bounds);
+            slot.shadow = Some((view_proj, draws.len() as u64));
+            draws.push(vk::DrawIndirectCommand {
+                vertex_count: CUBE_VERTICES,
+                instance_count: casters.len() as u32,
+                first_vertex: 0,
+                first_instance: count as u32,
+            });
+            // Only their depth's drawn, so they don't move and have no colour or lightmap.
+            let data = casters.iter().flat_map(|m| {
+                [rows(&m.world), rows(&m.world)]
+                    .concat()
+                    .into_iter()
+                    .chain([0.0; 8])
+            });
+            instances.extend(data.flat_map(f32::to_ne_bytes));
+            count += casters.len();
+        }
+        let outlined: Vec<_> = scene.outlined().collect();
+        if !outlined.is_empty() {
+            slot.outline = Some(draws.len() as u64);
+            draws.push(vk::DrawIndirectCommand {
+                vertex_count: CUBE_VERTICES,
+                instance_count: outlined.len() as u32,
+                first_vertex: 0,
+                first_instance: count as u32,
+            });
+            // The mask has no motion vectors or lighting, and each one's colour is what the mask's covered with.
+            let data = outlined.iter().flat_map(|(m, outline)| {
+                [rows(&m.world), rows(&m.world)]
+                    .concat()
+                    .into_iter()
+                    .chain(outline::mask_value(outline))
+                    .chain([0.0; 4])
+            });
+            instances.extend(data.flat_map(f32::to_ne_bytes));
+            count += outlined.len();
+        }
+        // Each surface is the cube's +Y face moved down onto the reflector's plane, showing the reflection as it is.
+        // Nothing moves them as far as motion vectors go.
+        let mut surfaces = Vec::with_capacity(surfaced.len());
+        for reflection in &surfaced {
+            surfaces.push(draws.len() as u64);
+            draws.push(vk::DrawIndirectCommand {
+                vertex_count: SURFACE_VERTICES.1,
+                instance_count: 1,
+                first_vertex: SURFACE_VERTICES.0,
+                first_instance: count as u32,
+            });
+            let world = reflection.surface * Affine3A::from_translation(Vec3::NEG_Y * 0.5);
+            let data = [rows(&world), rows(&world)]
+                .concat()
+                .into_iter()
+                .chain([1.0; 4])
+                .chain([0.0; 4]);
+            instances.extend(data.flat_map(f32::to_ne_bytes));
+            count += 1;
+        }
+        // Portals' surfaces the same way, only ever drawn to depth and stencil.
+        for (view, range) in through.clone().zip(ranges) {
+            slot.portals.push((range, draws.len() as u64));
+            draws.push(vk::DrawIndirectCommand {
+                vertex_count: SURFACE_VERTICES.1,
+                instance_count: 1,
+                first_vertex: SURFACE_VERTICES.0,
+                first_instance: count as u32,
+            });
+            let world = portals.surface(view) * Affine3A::from_translation(Vec3::NEG_Y * 0.5);
+            let data = [rows(&world), rows(&world)]
+                .concat()
+                .into_iter()
+                .chain([0.0; 8]);
+            instances.extend(data.flat_map(f32::to_ne_bytes));
+            count += 1;
+        }
+        if !through.is_empty() {
+            slot.portal_views.extend_from_slice(portals.views());
+            slot.steps = portal::steps(portals.views());
+        }
+        let draws: Vec<u8> = draws
+            .into_iter()
+            .flat_map(|d| {
+                [
+                    d.vertex_count,
+                    d.instance_count,
+                    d.first_vertex,
+                    d.first_instance,
+                ]
+            })
+            .flat_map(u32::to_ne_bytes)
+            .collect();
+        let (towards, color) = sun.map_or((Vec3::ZERO, [0.0; 4]), |l| {
+            (-l.direction, (l.color.to_array().map(|c| c * l.intensity)))
+        });
+        let (shadow_view_proj, shadow_params) = match (slot.shadow, shadow_map) {
+            (Some((view_proj, _)), Some(map)) => (view_proj, [1.0, 1.0 / map.extent.width as f32]),
+            _ => (Mat4::IDENTITY, [0.0; 2]),
+        };
+        let light: Vec<u8> = towards
+            .extend(0.0)
+            .to_array()
+            .into_iter()
+            .chain(color)
+            .chain(AMBIENT.to_array())
+            .chain(shadow_view_proj.to_cols_array())
+            .chain(shadow_params)
+            .chain([0.0; 2])
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        debug_assert_eq!(light.len() as u64, LIGHT_BYTES);
+
+        // SAFETY: The caller vouches the GPU's done with this slot.
+        unsafe {
+            grow(
+                device,
+                &mut slot.instances,
+                instances.len() as u64,
+                BufferUsage::VERTEX,
+            )?;
+            grow(
+                device,
+                &mut slot.draws,
+                draws.len() as u64,
+                BufferUsage::INDIRECT | BufferUsage::STORAGE,
+            )?;
+            grow(device, &mut slot.light, LIGHT_BYTES, BufferUsage::UNIFORM)?;
+            device.write_buffer(slot.instances.as_ref().unwrap(), 0, &instances)?;
+            device.write_buffer(slot.draws.as_ref().unwrap(), 0, &draws)?;
+            device.write_buffer(slot.light.as_ref().unwrap(), 0, &light)?;
+            if slot.probes.is_none() || !same(&slot.grid, &scene.probes) {
+                let data = scene.probes.as_ref().map(|g| g.gpu_data());
+                let data = data.as_deref().unwrap_or(&NO_PROBES[..]);
+                grow(
+                    device,
+                    &mut slot.probes,
+                    data.len() as u64,
+                    BufferUsage::STORAGE,
+                )?;
+                device.write_buffer(slot.probes.as_ref().unwrap(), 0, data)?;
+                slot.grid = scene.probes.clone();
+            }
+            // The GPU's done with the last uploads.
+            for staging in slot.staging.drain(..) {
+                device.destroy_buffer(staging);
+            }
+            if !same(&slot.baked, &scene.lightmap) {
+                if let Some(texture) = slot.lightmap.take() {
+                    device.destroy_texture(texture);
+                }
+                slot.baked = None;
+                if let Some(baked) = &scene.lightmap {
+                    let size = [baked.width(), baked.height()];
+                    let (texture, staging) =
+                        upload(device, cmd, LIGHTMAP_FORMAT, size, &baked.gpu_data())?;
+                    slot.lightmap = Some(texture);
+                    slot.staging.push(staging);
+                    slot.baked = Some(baked.clone());
+                }
+            }
+            if slot.reflections.is_none() || !same(&slot.captured, &scene.reflections) {
+                if let Some(texture) = slot.atlas.take() {
+                    device.destroy_texture(texture);
+                }
+                slot.captured = None;
+                let data = scene.reflections.as_ref().map(|r| r.gpu_data());
+                let data = data.as_deref().unwrap_or(&NO_REFLECTIONS[..]);
+                grow(
+                    device,
+                    &mut slot.reflections,
+                    data.len() as u64,
+                    BufferUsage::STORAGE,
+                )?;
+                device.write_buffer(slot.reflections.as_ref().unwrap(), 0, data)?;
+                if let Some((size, texels)) = scene.reflections.as_ref().and_then(|r| r.atlas()) {
+                    let (texture, staging) = upload(device, cmd, LIGHTMAP_FORMAT, size, &texels)?;
+                    slot.atlas = Some(texture);
+                    slot.staging.push(staging);
+                }
+                slot.captured = scene.reflections.clone();
+            }
+        }
+        let shadow_map = match shadow_map {
+            Some(map) => map,
+            None => {
+                // Never read, but it has to be in the layout it's bound in. What was there doesn't matter.
+                let blank = self.no_shadows.as_ref().unwrap();
+                // SAFETY: Passed on to the caller.
+                let mut encoder = unsafe { device.encoder_for(cmd) };
+                encoder.transition(blank, TextureState::Undefined, TextureState::ShaderRead);
+                blank
+            }
+        };
+        let blank = self.no_lightmap.as_ref().unwrap();
+        if slot.lightmap.is_none() || slot.atlas.is_none() {
+            // Never read either, like the shadow map's.
+            // SAFETY: Passed on to the caller.
+            let mut encoder = unsafe { device.encoder_for(cmd) };
+            encoder.transition(blank, TextureState::Undefined, TextureState::ShaderRead);
+        }
+        let lightmap = slot.lightmap.as_ref().unwrap_or(blank);
+        let atlas = slot.atlas.as_ref().unwrap_or(blank);
+        slot.set = self.descriptors.allocate(self.set_layout)?;
+        let probes = [vk::DescriptorBufferInfo::default()
+            .buffer(slot.probes.as_ref().unwrap().buffer)
+            .range(vk::WHOLE_SIZE)];
+        let light = [vk::DescriptorBufferInfo::default()
+            .buffer(slot.light.as_ref().unwrap().buffer)
+            .range(LIGHT_BYTES)];
+        let image = [vk::DescriptorImageInfo::default()
+            .image_view(shadow_map.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler = [vk::DescriptorImageInfo::default().sampler(self.shadow_sampler)];
+        let baked = [vk::DescriptorImageInfo::default()
+            .image_view(lightmap.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let filter = [vk::DescriptorImageInfo::default().sampler(self.lightmap_sampler)];
+        let reflections = [vk::DescriptorBufferInfo::default()
+            .buffer(slot.reflections.as_ref().unwrap().buffer)
+            .range(vk::WHOLE_SIZE)];
+        let atlas = [vk::DescriptorImageInfo::default()
+            .image_view(atlas.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = |binding, ty| {
+            vk::WriteDescriptorSet::default()
+                .dst_set(slot.set)
+                .dst_binding(binding)
+                .descriptor_type(ty)
+        };
+        let writes = [
+            write(0, vk::DescriptorType::STORAGE_BUFFER).buffer_info(&probes),
+            write(1, vk::DescriptorType::UNIFORM_BUFFER).buffer_info(&light),
+            write(2, vk::DescriptorType::SAMPLED_IMAGE).image_info(&image),
+            write(3, vk::DescriptorType::SAMPLER).image_info(&sampler),
+            write(4, vk::DescriptorType::SAMPLED_IMAGE).image_info(&baked),
+            write(5, vk::DescriptorType::SAMPLER).image_info(&filter),
+            write(6, vk::DescriptorType::STORAGE_BUFFER).buffer_info(&reflections),
+            write(7, vk::DescriptorType::SAMPLED_IMAGE).image_info(&atlas),
+            write(8, vk::DescriptorType::SAMPLER).image_info(&filter),
+        ];
+        // SAFETY: The set was just allocated.
+        unsafe { device.raw().update_descriptor_sets(&writes, &[]) };
+        for (reflection, draw) in surfaced.iter().zip(surfaces) {
+            let set = self.descriptors.allocate(self.surface_set_layout)?;
+            let image = [vk::DescriptorImageInfo::default()
+                .image_view(reflection.color.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+            let writes = [
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&image),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&filter),
+            ];
+            // SAFETY: The set was just allocated.
+            unsafe { device.raw().update_descriptor_sets(&writes, &[]) };
+            slot.surfaces.push((set, draw));
+        }
+        if let Some(validator) = validator {
+            let batch = IndirectDraws {
+                buffer: slot.draws.as_ref().unwrap(),
+                offset: 0,
+                draw_count: (draws.len() as u64 / DRAW_BYTES) as u32,
+                stride: DRAW_BYTES as u32,
+                indexed: false,
+                limits: IndirectLimits {
+                    elements: CUBE_VERTICES,
+                    instances: count as u32,
+                },
+            };
+            // SAFETY: Passed on to the caller.
+            unsafe { validator.record(device, cmd, "meshes", &batch)? };
+        }
+        slot.prepared = Some(frame);
+        return Ok(());
+    }
+
+    /// Record drawing the shadow casters given to [`MeshPass::prepare`] for the context's frame into its pass, on
+    /// the shadow map alone. Draws nothing if there weren't any, or a light to cast them.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record_shadows(&mut self, ctx: PassContext) -> VkResult<()> {
+        let PassContext {
+            device, cmd, frame, ..
+        } = ctx;
+        let slot = &self.slots[(frame % self.slots.len() as u64) as usize];
+        let (Some(_), Some((view_proj, draw))) =
+            (slot.prepared.filter(|f| *f == frame), slot.shadow)
+        else {
+            return Ok(());
+        };
+        let raw = device.raw();
+        let buffers = [
+            self.cube.as_ref().unwrap().buffer,
+            slot.instances.as_ref().unwrap().buffer,
+        ];
+        let push: Vec<u8> = [view_proj, view_proj]
+            .iter()
+            .flat_map(Mat4::to_cols_array)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.shadow_pipeline);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &buffers, &[0, 0]);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            let draws = slot.draws.as_ref().unwrap().buffer;
+            raw.cmd_draw_indirect(cmd, draws, draw * DRAW_BYTES, 1, DRAW_BYTES as u32);
+        }
+        return Ok(());
+    }
+
+    /// Record drawing the outlined meshes given to [`MeshPass::prepare`] for the context's frame into its pass, on
+    /// the outline mask alone, as `scene`'s last camera sees them. The mask's cleared first, and left clear if
+    /// nothing's outlined.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record_outlines(
+        &mut self,
+        ctx: PassContext,
+        scene: &ExtractedScene,
+    ) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            extent,
+            ..
+        } = ctx;
+        let raw = device.raw();
+        let clear = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0; 4] },
+            },
+        };
+        let area = vk::ClearRect {
+            rect: extent.into(),
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe { raw.cmd_clear_attachments(cmd, &[clear], &[area]) };
+        let slot = &self.slots[(frame % self.slots.len() as u64) as usize];
+        let (Some(_), Some(draw), Some(camera)) = (
+            slot.prepared.filter(|f| *f == frame),
+            slot.outline,
+            scene.cameras.last(),
+        ) else {
+            return Ok(());
+        };
+        let buffers = [
+            self.cube.as_ref().unwrap().buffer,
+            slot.instances.as_ref().unwrap().buffer,
+        ];
+        let view_proj = camera.view_proj(extent.width as f32 / extent.height.max(1) as f32);
+        let push: Vec<u8> = [view_proj, view_proj]
+            .iter()
+            .flat_map(Mat4::to_cols_array)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.outline_pipeline);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &buffers, &[0, 0]);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            let draws = slot.draws.as_ref().unwrap().buffer;
+            raw.cmd_draw_indirect(cmd, draws, draw * DRAW_BYTES, 1, DRAW_BYTES as u32);
+        }
+        return Ok(());
+    }
+
+    /// Record drawing `views`, culled from `scene`'s cameras in the same order and given to
+    /// [`MeshPass::prepare`] for the context's frame, into its pass. Depth is cleared between cameras, so later
+    /// ones draw over earlier ones.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record(
+        &mut self,
+        ctx: PassContext,
+        scene: &ExtractedScene,
+        views: &[DrawList],
+    ) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            extent,
+        } = ctx;
+        let index = (frame % self.slots.len() as u64) as usize;
+        if self.slots[index].prepared != Some(frame) {
+            return Ok(());
+        }
+        let raw = device.raw();
+        let front = vk::FrontFace::COUNTER_CLOCKWISE;
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target, front, StencilMode::Off)? };
+        let surface_pipeline = match self.slots[index].surfaces.is_empty() {
+            true => vk::Pipeline::null(),
+            // SAFETY: Passed on to the caller.
+            false => unsafe { self.surface_pipeline(raw, target)? },
+        };
+        // Drawn through the last camera, the camera's own view last of all.
+        let steps = match target.has_stencil() {
+            true => self.slots[index]
+                .steps
+                .split_last()
+                .map_or(&[][..], |(_, s)| s)
+                .to_vec(),
+            false => Vec::new(),
+        };
+        let mut step_pipelines = Vec::with_capacity(steps.len());
+        for step in &steps {
+            let view = &self.slots[index].portal_views[step.view()];
+            let (stencil, _) = step.stencil(&self.slots[index].portal_views);
+            let front_face = view.front_face();
+            // SAFETY: Passed on to the caller.
+            let pipeline = unsafe {
+                match step {
+                    PortalStep::Draw(_) => self.pipeline(raw, target, front_face, stencil)?,
+                    _ => self.portal_pipeline(raw, target, stencil)?,
+                }
+            };
+            step_pipelines.push(pipeline);
+        }
+        let slot = &self.slots[index];
+        let buffers = [
+            self.cube.as_ref().unwrap().buffer,
+            slot.instances.as_ref().unwrap().buffer,
+        ];
+        let draws = slot.draws.as_ref().unwrap().buffer;
+        let aspect = extent.width as f32 / extent.height.max(1) as f32;
+        let clear = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: NDC_FAR,
+                    stencil: 0,
+                },
+            },
+        };
+        let area = vk::ClearRect {
+            rect: extent.into(),
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        // Flipped like every pass's, see rendering::begin, with the depth range given.
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let viewport = |min_depth: f32, max_depth: f32| vk::Viewport {
+            x: 0.0,
+            y: height,
+            width,
+            height: -height,
+            min_depth,
+            max_depth,
+        };
+        let matrices = |view_proj: Mat4, previous: Mat4| -> Vec<u8> {
+            [view_proj, previous]
+                .iter()
+                .flat_map(Mat4::to_cols_array)
+                .flat_map(f32::to_ne_bytes)
+                .collect()
+        };
+
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[slot.set],
+                &[],
+            );
+            raw.cmd_bind_vertex_buffers(cmd, 0, &buffers, &[0, 0]);
+            let mut draw = 0;
+            let mut push = Vec::new();
+            let cameras = scene.cameras.len().min(views.len());
+            for (i, (camera, view)) in scene.cameras.iter().zip(views).enumerate() {
+                if i > 0 && target.depth != vk::Format::UNDEFINED {
+                    raw.cmd_clear_attachments(cmd, &[clear], &[area]);
+                }
+                if i + 1 == cameras && !steps.is_empty() {
+                    let point = vk::PipelineBindPoint::GRAPHICS;
+                    let stages = vk::ShaderStageFlags::VERTEX;
+                    let faces = vk::StencilFaceFlags::FRONT_AND_BACK;
+                    let views = &slot.portal_views;
+                    for (step, &pipeline) in steps.iter().zip(&step_pipelines) {
+                        let index = step.view();
+                        let (_, reference) = step.stencil(views);
+                        let (range, surface) = slot.portals[index - 1].clone();
+                        raw.cmd_bind_pipeline(cmd, point, pipeline);
+                        raw.cmd_set_stencil_reference(cmd, faces, reference);
+                        if let PortalStep::Draw(_) = step {
+                            // Nothing seen through a portal moves, as far as motion vectors go.
+                            let view_proj = views[index].view_proj;
+                            raw.cmd_push_constants(
+                                cmd,
+                                self.layout,
+                                stages,
+                                0,
+                                &matrices(view_proj, view_proj),
+                            );
+                            for draw in range {
+                                raw.cmd_draw_indirect(
+                                    cmd,
+                                    draws,
+                                    draw * DRAW_BYTES,
+                                    1,
+                                    DRAW_BYTES as u32,
+                                );
+                            }
+                            continue;
+                        }
+                        // The surface is in the view it's seen from.
+                        let parent = views[views[index].parent.unwrap_or(0)].view_proj;
+                        raw.cmd_push_constants(
+                            cmd,
+                            self.layout,
+                            stages,
+                            0,
+                            &matrices(parent, parent),
+                        );
+                        // Cleared by drawing the surface at the far plane.
+                        let clearing = matches!(step, PortalStep::ClearDepth(_));
+                        if clearing {
+                            raw.cmd_set_viewport(cmd, 0, &[viewport(NDC_FAR, NDC_FAR)]);
+                        }
+                        raw.cmd_draw_indirect(
+                            cmd,
+                            draws,
+                            surface * DRAW_BYTES,
+                            1,
+                            DRAW_BYTES as u32,
+                        );
+                        if clearing {
+                            raw.cmd_set_viewport(cmd, 0, &[viewport(0.0, 1.0)]);
+                        }
+                    }
+                    raw.cmd_bind_pipeline(cmd, point, pipeline);
+                }
+                push = matrices(camera.view_proj(aspect), camera.previous_view_proj(aspect));
+                raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+                for _ in 0..batches(view) {
+                    raw.cmd_draw_indirect(cmd, draws, draw * DRAW_BYTES, 1, DRAW_BYTES as u32);
+                    draw += 1;
+                }
+            }
+            // Through the last camera, like the reflections were drawn.
+            if !slot.surfaces.is_empty() {
+                let layout = self.surface_layout;
+                let point = vk::PipelineBindPoint::GRAPHICS;
+                raw.cmd_bind_pipeline(cmd, point, surface_pipeline);
+                raw.cmd_push_constants(cmd, layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+                for &(set, draw) in &slot.surfaces {
+                    raw.cmd_bind_descriptor_sets(cmd, point, layout, 0, &[set], &[]);
+                    raw.cmd_draw_indirect(cmd, draws, draw * DRAW_BYTES, 1, DRAW_BYTES as u32);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Record drawing planar reflection `index`, of those given to [`MeshPass::prepare`] for the context's frame,
+    /// into its pass, lit like the cameras' views. Nothing's drawn if it had no view.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record_reflection(&mut self, ctx: PassContext, index: usize) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            ..
+        } = ctx;
+        let slot = (frame % self.slots.len() as u64) as usize;
+        let Some((view_proj, range)) = self.slots[slot]
+            .mirrored
+            .get(index)
+            .cloned()
+            .flatten()
+            .filter(|_| self.slots[slot].prepared == Some(frame))
+        else {
+            return Ok(());
+        };
+        let raw = device.raw();
+        let front_face = PlanarView::FRONT_FACE;
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target, front_face, StencilMode::Off)? };
+        let slot = &self.slots[slot];
+        let buffers = [
+            self.cube.as_ref().unwrap().buffer,
+            slot.instances.as_ref().unwrap().buffer,
+        ];
+        // Nothing in it moves, as far as it knows.
+        let push: Vec<u8> = [view_proj, view_proj]
+            .iter()
+            .flat_map(Mat4::to_cols_array)
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            let point = vk::PipelineBindPoint::GRAPHICS;
+            raw.cmd_bind_pipeline(cmd, point, pipeline);
+            raw.cmd_bind_descriptor_sets(cmd, point, self.layout, 0, &[slot.set], &[]);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &buffers, &[0, 0]);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            let draws = slot.draws.as_ref().unwrap().buffer;
+            for draw in range {
+                raw.cmd_draw_indirect(cmd, draws, draw * DRAW_BYTES, 1, DRAW_BYTES as u32);
+            }
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and the set layout goes with its
+        // cache.
+        unsafe {
+            self.destroy_slots(device);
+            if let Some(cube) = self.cube.take() {
+                device.destroy_buffer(cube);
+            }
+            for texture in [self.no_shadows.take(), self.no_lightmap.take()]
+                .into_iter()
+                .flatten()
+            {
+                device.destroy_texture(texture);
+            }
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            for (_, pipeline) in self.surface_pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            for (_, pipeline) in self.portal_pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            raw.destroy_shader_module(self.surface_fragment, allocs());
+            raw.destroy_pipeline_layout(self.surface_layout, allocs());
+            raw.destroy_pipeline(self.shadow_pipeline, allocs());
+            raw.destroy_pipeline(self.outline_pipeline, allocs());
+            raw.destroy_shader_module(self.outline_fragment, allocs());
+            raw.destroy_sampler(self.shadow_sampler, allocs());
+            raw.destroy_sampler(self.lightmap_sampler, allocs());
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.shadow_pipeline = vk::Pipeline::null();
+        self.outline_pipeline = vk::Pipeline::null();
+        self.outline_fragment = vk::ShaderModule::null();
+        self.surface_fragment = vk::ShaderModule::null();
+        self.surface_layout = vk::PipelineLayout::null();
+        self.shadow_sampler = vk::Sampler::null();
+        self.lightmap_sampler = vk::Sampler::null();
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+/// Whether `a` and `b` are the same bake, not just equal ones.
+fn same<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    return match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    };
+}
+
+/// A `format` texture `size` texels big holding `data`, and the buffer it's copied from in `cmd`, to keep until the
+/// GPU's done with it.
+///
+/// # Safety
+/// `cmd` must be recording outside a pass.
+pub(super) unsafe fn upload(
+    device: &VulkanDevice,
+    cmd: vk::CommandBuffer,
+    format: TextureFormat,
+    [width, height]: [u32; 2],
+    data: &[u8],
+) -> VkResult<(VulkanTexture, VulkanBuffer)> {
+    let texture = device.create_texture(&TextureDesc {
+        width,
+        height,
+        format,
+        usage: TextureUsage::COPY_DST | TextureUsage::SAMPLED,
+        samples: 1,
+    })?;
+    let staging = device.create_buffer(&BufferDesc {
+        size: data.len() as u64,
+        usage: BufferUsage::COPY_SRC,
+        location: MemoryLocation::Upload,
+    });
+    // SAFETY: The buffer's new, so the GPU hasn't seen it, and the copy goes into the caller's commands.
+    let uploaded = staging.and_then(|staging| unsafe {
+        if let Err(e) = device.write_buffer(&staging, 0, data) {
+            device.destroy_buffer(staging);
+            return Err(e);
+        }
+        let mut encoder = device.encoder_for(cmd);
+        encoder.transition(&texture, TextureState::Undefined, TextureState::CopyDst);
+        encoder.copy_buffer_to_texture(&staging, &texture);
+        encoder.transition(&texture, TextureState::CopyDst, TextureState::ShaderRead);
+        Ok(staging)
+    });
+    return match uploaded {
+        Ok(staging) => Ok((texture, staging)),
+        Err(e) => {
+            // SAFETY: Nothing was recorded with it.
+            unsafe { device.destroy_texture(texture) };
+            Err(e)
+        }
+    };
+}
+
+/// Make sure `buffer` has room for `size` bytes, making a bigger one for `usage` if it hasn't.
+///
+/// # Safety
+/// The GPU must be done with the buffer there.
+pub(super) unsafe fn grow(
+    device: &VulkanDevice,
+    buffer: &mut Option<VulkanBuffer>,
+    size: u64,
+    usage: BufferUsage,
+) -> VkResult<()> {
+    if buffer.as_ref().is_some_and(|b| b.size >= size) {
+        return Ok(());
+    }
+    if let Some(old) = buffer.take() {
+        // SAFETY: Passed on to the caller.
+        unsafe { device.destroy_buffer(old) };
+    }
+    *buffer = Some(device.create_buffer(&BufferDesc {
+        size: size.next_power_of_two().max(MIN_BUFFER_BYTES),
+        usage,
+        location: MemoryLocation::Upload,
+    })?);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Quat, Vec2, Vec3};
+
+    use super::{
+        CUBE_VERTICES, INSTANCE_BYTES, cube, draw_commands, instance_data, material_color, rows,
+        sun_view_proj,
+    };
+    use crate::{
+        ecs::components::{Lightmapped, MaterialId, MeshId},
+        math::bounds::Aabb,
+        render::{
+            draw::{Draw, DrawList, PipelineId, StateKey},
+            indirect::IndirectLimits,
+        },
+    };
+
+    #[test]
+    pub fn cube_faces_outwards() {
+        let vertices = cube();
+        assert_eq!(vertices.len(), 36);
+        for triangle in vertices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(triangle[i].position));
+            let normal = Vec3::from(triangle[0].normal);
+            // Counter-clockwise seen from where the normal points.
+            assert!((b - a).cross(c - a).dot(normal) > 0.0);
+            assert!(a.abs().max_element() == 0.5 && a.dot(normal) == 0.5);
+        }
+        // Each face's lightmap UVs stay inside a cell of their own.
+        for (face, vertices) in vertices.chunks_exact(6).enumerate() {
+            let cell = Vec2::new((face % 3) as f32, (face / 3) as f32);
+            for vertex in vertices {
+                let uv = Vec2::from(vertex.lightmap_uv) * Vec2::new(3.0, 2.0) - cell;
+                assert!(
+                    uv.cmpgt(Vec2::ZERO).all() && uv.cmplt(Vec2::ONE).all(),
+                    "{uv}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn sun_sees_all_of_the_bounds() {
+        let bounds =
+            Aabb::from_center_half_extents(Vec3::new(3.0, 1.0, -2.0), Vec3::new(4.0, 1.0, 2.0));
+        for direction in [Vec3::new(0.3, -1.0, 0.2), Vec3::NEG_Y, Vec3::X, Vec3::ZERO] {
+            let view_proj = sun_view_proj(direction, &bounds);
+            for corner in bounds.corners() {
+                let ndc = view_proj.project_point3(corner);
+                assert!(
+                    ndc.x.abs() <= 1.001 && ndc.y.abs() <= 1.001,
+                    "{direction} {corner} {ndc}"
+                );
+                assert!(
+                    (-0.001..=1.001).contains(&ndc.z),
+                    "{direction} {corner} {ndc}"
+                );
+            }
+            // Reversed, like the camera: towards the light is nearer, so deeper.
+            let direction = direction.try_normalize().unwrap_or(crate::math::FORWARD);
+            let [towards, away] =
+                [-0.5, 0.5].map(|d| view_proj.project_point3(bounds.center() + direction * d));
+            assert!(towards.z > away.z);
+        }
+    }
+
+    #[test]
+    pub fn transforms_go_in_by_row() {
+        let transform = Affine3A::from_rotation_translation(
+            Quat::from_rotation_y(0.5),
+            Vec3::new(1.0, 2.0, 3.0),
+        );
+        let r = rows(&transform);
+        let p = Vec3::new(0.3, -0.2, 0.7);
+        let by_rows = Vec3::new(
+            r[0] * p.x + r[1] * p.y + r[2] * p.z + r[3],
+            r[4] * p.x + r[5] * p.y + r[6] * p.z + r[7],
+            r[8] * p.x + r[9] * p.y + r[10] * p.z + r[11],
+        );
+        assert!(by_rows.abs_diff_eq(transform.transform_point3(p), 1e-6));
+    }
+
+    #[test]
+    pub fn draws_stay_in_range() {
+        let draw = |material, z| Draw {
+            key: StateKey {
+                pipeline: PipelineId(0),
+                material: MaterialId(material),
+                mesh: MeshId(0),
+            },
+            transform: Affine3A::from_translation(Vec3::Z * z),
+            previous_transform: Affine3A::from_translation(Vec3::Z * z),
+            lightmap: (material == 1).then_some(Lightmapped {
+                scale: Vec2::splat(0.5),
+                offset: Vec2::new(0.25, 0.0),
+            }),
+            depth: z,
+        };
+        let mut views = [DrawList::default(), DrawList::default()];
+        for (material, z) in [(0, 1.0), (1, 2.0), (0, 3.0)] {
+            views[0].push(draw(material, z), false);
+        }
+        views[1].push(draw(2, 1.0), true);
+        for view in &mut views {
+            view.build(true);
+        }
+
+        let mut draws = draw_commands(&views);
+        let firsts: Vec<_> = draws
+            .iter()
+            .map(|d| (d.first_instance, d.instance_count))
+            .collect();
+        // The second view's instances come after all the first's.
+        assert_eq!(firsts, vec![(0, 2), (2, 1), (3, 1)]);
+        let limits = IndirectLimits {
+            elements: CUBE_VERTICES,
+            instances: 4,
+        };
+        for (i, draw) in draws.iter_mut().enumerate() {
+            assert_eq!(limits.check(i as u32, draw), None);
+        }
+        // An instance apiece, each with its batch's colour, then where its lightmap UVs go, none for the probes.
+        let data = instance_data(&views);
+        let stride = INSTANCE_BYTES as usize / 4;
+        assert_eq!(data.len(), 4 * stride);
+        let color = material_color(MaterialId(1)).to_array();
+        assert_eq!(data[3 * stride - 8..3 * stride - 4], color);
+        assert_eq!(data[3 * stride - 4..3 * stride], [0.5, 0.5, 0.25, 0.0]);
+        assert_eq!(data[stride - 4..stride], [0.0; 4]);
+    }
+}
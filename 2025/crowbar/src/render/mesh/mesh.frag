@@ -0,0 +1,86 @@
+#version 450
+// The material's colour under one directional light, shadowed by its shadow map, and the irradiance probes or the
+// lightmap, all linear. Without probes, a flat ambient stands in for them. The reflection probes are added on top by
+// how much the surface reflects at that angle. Motion vectors go to a second target, dropped when there isn't one.
+
+#include "../probes/probes.glsl"
+#define LIGHTMAP_BINDING 4
+#define LIGHTMAP_SAMPLER_BINDING 5
+#include "../lightmap/lightmap.glsl"
+#define REFLECTIONS_BINDING 6
+#define REFLECTIONS_ATLAS_BINDING 7
+#define REFLECTIONS_SAMPLER_BINDING 8
+#include "../reflections/reflections.glsl"
+#include "../motion_blur/velocity.glsl"
+
+layout(set = 0, binding = 1) uniform Frame {
+    // Towards the light, and its colour times its intensity.
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient;
+    // World space to the shadow map's clip space.
+    mat4 shadow_view_proj;
+    // x is 1 with a shadow map and 0 without, y a texel of it in UV.
+    vec4 shadow_params;
+};
+layout(set = 0, binding = 2) uniform texture2D shadow_map;
+layout(set = 0, binding = 3) uniform samplerShadow shadow_sampler;
+
+layout(location = 0) in vec3 normal;
+layout(location = 1) in vec3 world;
+layout(location = 2) flat in vec4 color;
+layout(location = 3) in vec4 clip;
+layout(location = 4) in vec4 previous_clip;
+layout(location = 5) in vec2 lightmap_uv;
+layout(location = 6) flat in uint lightmapped;
+layout(location = 7) in vec3 view;
+
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec2 out_velocity;
+
+// How much of the sun gets to `position`, 3x3 filtered comparisons averaged. Outside the map is lit.
+float sunlight(vec3 position) {
+    if (shadow_params.x == 0.0) {
+        return 1.0;
+    }
+    vec4 clip = shadow_view_proj * vec4(position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    // Clip space Y is up, and V counts down.
+    vec2 uv = vec2(ndc.x, -ndc.y) * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec2 offset = vec2(float(x), float(y)) * shadow_params.y;
+            lit += texture(sampler2DShadow(shadow_map, shadow_sampler), vec3(uv + offset, ndc.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+void main() {
+    vec3 n = normalize(normal);
+    float lit = max(dot(n, light_direction.xyz), 0.0);
+    if (lit > 0.0) {
+        lit *= sunlight(world);
+    }
+    vec3 indirect = ambient.rgb;
+    if (lightmapped != 0u) {
+        indirect = lightmap_diffuse(lightmap_uv);
+    } else if (probe_counts.x != 0u) {
+        indirect = probe_diffuse(world, n);
+    }
+    vec3 light = indirect + light_color.rgb * lit;
+    vec3 shaded = color.rgb * light;
+    if (reflection_count != 0u) {
+        vec3 v = normalize(view);
+        // Schlick's, with every material a dielectric until there are material assets.
+        float fresnel = 0.04 + 0.96 * pow(1.0 - clamp(dot(-v, n), 0.0, 1.0), 5.0);
+        vec3 reflected = reflection_sample(world, reflect(v, n), ambient.rgb);
+        shaded = mix(shaded, reflected, fresnel);
+    }
+    out_color = vec4(shaded, color.a);
+    out_velocity = velocity_output(clip, previous_clip);
+}
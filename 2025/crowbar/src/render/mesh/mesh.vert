@@ -0,0 +1,61 @@
+#version 450
+// Instanced meshes through a camera, or through the sun for its shadow map. Keep in step with MeshPass in mesh.rs.
+
+layout(push_constant) uniform Push {
+    mat4 view_proj;
+    // As of the last frame, for motion vectors.
+    mat4 previous_view_proj;
+};
+
+// A LightmapVertex, without the material's UVs, which nothing uses yet.
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 3) in vec2 lightmap_uv;
+// The instance's world transform, a row each.
+layout(location = 4) in vec4 row_x;
+layout(location = 5) in vec4 row_y;
+layout(location = 6) in vec4 row_z;
+// And as of the last frame.
+layout(location = 7) in vec4 previous_row_x;
+layout(location = 8) in vec4 previous_row_y;
+layout(location = 9) in vec4 previous_row_z;
+// The material's colour.
+layout(location = 10) in vec4 color;
+// What the lightmap UVs are scaled by, then moved by. No scale for meshes lit by the probes.
+layout(location = 11) in vec4 lightmap_rect;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec3 out_world;
+layout(location = 2) flat out vec4 out_color;
+// Where the vertex is in clip space now and last frame, for velocity_output.
+layout(location = 3) out vec4 out_clip;
+layout(location = 4) out vec4 out_previous_clip;
+layout(location = 5) out vec2 out_lightmap_uv;
+// 1 to light from the lightmap, 0 for the probes.
+layout(location = 6) flat out uint out_lightmapped;
+// From the camera to the vertex, for reflections.
+layout(location = 7) out vec3 out_view;
+
+void main() {
+    vec4 local = vec4(position, 1.0);
+    vec3 world = vec3(dot(row_x, local), dot(row_y, local), dot(row_z, local));
+    vec3 previous_world = vec3(
+        dot(previous_row_x, local),
+        dot(previous_row_y, local),
+        dot(previous_row_z, local)
+    );
+    gl_Position = view_proj * vec4(world, 1.0);
+    // Skews under non-uniform scale, which will do until there's more than cubes.
+    vec4 n = vec4(normal, 0.0);
+    out_normal = vec3(dot(row_x, n), dot(row_y, n), dot(row_z, n));
+    out_world = world;
+    out_color = color;
+    out_clip = gl_Position;
+    out_previous_clip = previous_view_proj * vec4(previous_world, 1.0);
+    out_lightmap_uv = lightmap_uv * lightmap_rect.xy + lightmap_rect.zw;
+    out_lightmapped = lightmap_rect.xy == vec2(0.0) ? 0u : 1u;
+    // What clip space's z axis comes from, which with reversed depth is the camera, or for an orthographic one the
+    // direction back towards it.
+    vec4 eye = inverse(view_proj) * vec4(0.0, 0.0, 1.0, 0.0);
+    out_view = abs(eye.w) > 1e-6 ? world - eye.xyz / eye.w : -eye.xyz;
+}
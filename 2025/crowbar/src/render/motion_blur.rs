@@ -0,0 +1,204 @@
+//! Motion blur, from motion vectors the scene writes.
+//!
+//! With [`QualitySettings::motion_blur`] on, the mesh pass writes how far each pixel moved on screen since the last
+//! frame alongside the scene's colour, with `velocity_output` from [`VELOCITY_GLSL`]. Its vertex shader works out
+//! where each vertex was from the mesh's and camera's transforms as of the last frame,
+//! [`ExtractedMesh::previous_world`] and [`ExtractedCamera::previous_world`], so a moving object blurs on its own and
+//! a moving camera blurs everything. [`mesh_motion`] is the same on the CPU.
+//!
+//! The blur pass, [`BLUR_GLSL`], then averages the scene's colour along each pixel's vector, centered on it, as
+//! [`blur`] does on the CPU, and everything after the scene reads the blurred colour. How much of the vector it
+//! covers is the shutter: like a film camera's, one at 180° is open for half of each frame, so things smear over
+//! half of how far they moved. Blurs are capped at [`MotionBlurSettings::max_blur`] pixels so fast things don't
+//! smear across the whole screen.
+//!
+//! [`QualitySettings::motion_blur`]: super::quality::QualitySettings::motion_blur
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use super::extract::{ExtractedCamera, ExtractedMesh};
+use crate::cvar::{CVars, EngineCVars};
+
+pub const VELOCITY_GLSL: &str = include_str!("motion_blur/velocity.glsl");
+pub const BLUR_GLSL: &str = include_str!("motion_blur/blur.frag");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotionBlurSettings {
+    /// `r_shutter_angle`, how many degrees of each frame the shutter is open for, up to 360.
+    pub shutter_angle: f32,
+    /// Taps along each pixel's blur.
+    pub samples: u32,
+    /// The longest blur, in pixels.
+    pub max_blur: f32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        MotionBlurSettings {
+            shutter_angle: 180.0,
+            samples: 12,
+            max_blur: 48.0,
+        }
+    }
+}
+
+impl MotionBlurSettings {
+    pub fn from_cvars(cvars: &CVars, engine: EngineCVars) -> MotionBlurSettings {
+        MotionBlurSettings {
+            shutter_angle: cvars.get(engine.r_shutter_angle),
+            ..MotionBlurSettings::default()
+        }
+    }
+
+    /// How much of a frame's motion blurs, 0 to 1.
+    pub fn shutter(&self) -> f32 {
+        (self.shutter_angle / 360.0).clamp(0.0, 1.0)
+    }
+
+    /// The blur across a pixel with motion vector `velocity` in a `size` target, both in UV.
+    pub fn blur_vector(&self, velocity: Vec2, size: [u32; 2]) -> Vec2 {
+        let size = Vec2::from(size.map(|s| s.max(1) as f32));
+        let pixels = velocity * self.shutter() * size;
+        return pixels.clamp_length_max(self.max_blur) / size;
+    }
+
+    /// The push constants [`BLUR_GLSL`] takes: the shutter, the longest blur and the number of taps.
+    pub fn gpu_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12);
+        out.extend(self.shutter().to_ne_bytes());
+        out.extend(self.max_blur.to_ne_bytes());
+        out.extend(self.samples.max(1).to_ne_bytes());
+        return out;
+    }
+}
+
+/// What `velocity_output` in [`VELOCITY_GLSL`] writes for a point at `clip` now and `previous_clip` last frame: how
+/// far it moved on screen, in UV.
+pub fn motion_vector(clip: Vec4, previous_clip: Vec4) -> Vec2 {
+    let ndc = clip.xy() / clip.w;
+    let previous_ndc = previous_clip.xy() / previous_clip.w;
+    // Clip space Y is up, targets' rows go down.
+    return (ndc - previous_ndc) * Vec2::new(0.5, -0.5);
+}
+
+/// The motion vector the scene writes where `local`, in `mesh`'s space, is seen by `camera` with `aspect`.
+pub fn mesh_motion(
+    mesh: &ExtractedMesh,
+    camera: &ExtractedCamera,
+    aspect: f32,
+    local: Vec3,
+) -> Vec2 {
+    let clip = camera.view_proj(aspect) * Mat4::from(mesh.world) * local.extend(1.0);
+    let previous_clip =
+        camera.previous_view_proj(aspect) * Mat4::from(mesh.previous_world) * local.extend(1.0);
+    return motion_vector(clip, previous_clip);
+}
+
+/// What [`BLUR_GLSL`] does to `color` with motion vectors `velocity`, both `size` images with rows top to bottom.
+pub fn blur(
+    color: &[Vec3],
+    velocity: &[Vec2],
+    size: [u32; 2],
+    settings: &MotionBlurSettings,
+) -> Vec<Vec3> {
+    let [width, height] = size.map(|s| s as i32);
+    let extent = Vec2::new(width as f32, height as f32);
+    let samples = settings.samples.max(1);
+    let texel = |uv: Vec2| {
+        let at = (uv * extent).floor().as_ivec2();
+        let (x, y) = (at.x.clamp(0, width - 1), at.y.clamp(0, height - 1));
+        return color[(y * width + x) as usize];
+    };
+
+    let mut out = Vec::with_capacity(color.len());
+    for y in 0..height {
+        for x in 0..width {
+            let uv = (Vec2::new(x as f32, y as f32) + 0.5) / extent;
+            let blur = settings.blur_vector(velocity[(y * width + x) as usize], size);
+            let sum: Vec3 = (0..samples)
+                .map(|i| texel(uv + blur * ((i as f32 + 0.5) / samples as f32 - 0.5)))
+                .sum();
+            out.push(sum / samples as f32);
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec2, Vec3};
+    use hecs::World;
+
+    use super::{MotionBlurSettings, blur, mesh_motion};
+    use crate::{
+        ecs::components::{MaterialId, MeshId, Projection},
+        math::bounds::Aabb,
+        render::extract::{ExtractedCamera, ExtractedMesh},
+    };
+
+    #[test]
+    pub fn blurs_along_motion() {
+        let mut world = World::new();
+        let back = Affine3A::from_translation(Vec3::new(0.0, 0.0, -5.0));
+        let camera = ExtractedCamera {
+            entity: world.spawn(()),
+            world: Affine3A::IDENTITY,
+            previous_world: Affine3A::IDENTITY,
+            projection: Projection::Perspective {
+                fov_y: 1.0,
+                near: 0.1,
+                far: 100.0,
+            },
+            order: 0,
+            lens: None,
+        };
+        let mut mesh = ExtractedMesh {
+            entity: world.spawn(()),
+            world: back,
+            previous_world: back,
+            mesh: MeshId(0),
+            material: MaterialId(0),
+            cast_shadows: false,
+            bounds: Aabb::new(Vec3::splat(-1.0), Vec3::splat(1.0)),
+            outline: None,
+            lightmap: None,
+        };
+        assert_eq!(mesh_motion(&mesh, &camera, 1.0, Vec3::ZERO), Vec2::ZERO);
+
+        // Moving right moves right on screen, and nothing else.
+        mesh.previous_world = Affine3A::from_translation(Vec3::new(-1.0, 0.0, -5.0));
+        let moved = mesh_motion(&mesh, &camera, 1.0, Vec3::ZERO);
+        assert!(moved.x > 0.0 && moved.y.abs() < 1e-6);
+
+        // The camera moving the same way makes it look still, and the rest go the other way.
+        let camera = ExtractedCamera {
+            previous_world: Affine3A::from_translation(Vec3::NEG_X),
+            ..camera
+        };
+        assert!(mesh_motion(&mesh, &camera, 1.0, Vec3::ZERO).abs_diff_eq(Vec2::ZERO, 1e-6));
+        mesh.previous_world = back;
+        assert!(mesh_motion(&mesh, &camera, 1.0, Vec3::ZERO).abs_diff_eq(-moved, 1e-6));
+
+        // Half the motion at 180°, and never past the cap.
+        let settings = MotionBlurSettings {
+            samples: 4,
+            ..MotionBlurSettings::default()
+        };
+        assert_eq!(
+            settings.blur_vector(Vec2::new(0.25, 0.0), [16, 16]),
+            Vec2::new(0.125, 0.0)
+        );
+        let capped = settings.blur_vector(Vec2::new(0.0, 1.0), [100, 100]);
+        assert!(capped.abs_diff_eq(Vec2::new(0.0, 0.48), 1e-6));
+
+        // A bright pixel moving 8 pixels right smears over 4 of them, keeping its light.
+        let mut color = vec![Vec3::ZERO; 8];
+        color[3] = Vec3::ONE;
+        let still = blur(&color, &[Vec2::ZERO; 8], [8, 1], &settings);
+        assert_eq!(still, color);
+        let blurred = blur(&color, &[Vec2::new(1.0, 0.0); 8], [8, 1], &settings);
+        assert_eq!(blurred.iter().filter(|c| c.x > 0.0).count(), 4);
+        assert!((blurred.iter().map(|c| c.x).sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(settings.gpu_data().len(), 12);
+    }
+}
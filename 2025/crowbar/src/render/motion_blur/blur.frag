@@ -0,0 +1,38 @@
+#version 450
+// Blurs the scene along each pixel's motion vector. Keep in step with blur in motion_blur.rs.
+
+layout(set = 0, binding = 0) uniform texture2D scene;
+// Motion vectors in UV, resolved.
+layout(set = 0, binding = 1) uniform texture2D velocity;
+layout(set = 0, binding = 2) uniform sampler linear_sampler;
+
+layout(push_constant) uniform Push {
+    // How much of a frame's motion blurs.
+    float shutter;
+    // The longest blur in pixels.
+    float max_blur;
+    uint samples;
+};
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec2 size = vec2(textureSize(sampler2D(scene, linear_sampler), 0));
+    vec2 pixels = texture(sampler2D(velocity, linear_sampler), uv).xy * shutter * size;
+    float len = length(pixels);
+    if (len > max_blur) {
+        pixels *= max_blur / len;
+    }
+    vec2 blur = pixels / size;
+
+    uint taps = max(samples, 1u);
+    vec3 sum = vec3(0.0);
+    for (uint i = 0u; i < taps; i++) {
+        // Centered on the pixel, so it smears both ways like it would with the shutter open mid frame.
+        float t = (float(i) + 0.5) / float(taps) - 0.5;
+        sum += texture(sampler2D(scene, linear_sampler), uv + blur * t).rgb;
+    }
+    out_color = vec4(sum / float(taps), 1.0);
+}
@@ -0,0 +1,12 @@
+// Motion vectors for the scene to write, see motion_blur.rs. The vertex shader passes on where the vertex is in clip
+// space this frame and last, from the mesh's and camera's transforms now and as of the last frame, both without any
+// jitter. The fragment shader writes velocity_output of them to the velocity target.
+
+// How far the point at `clip_position` now and `previous_clip_position` last frame moved on screen, in UV. Keep in
+// step with motion_vector in motion_blur.rs.
+vec2 velocity_output(vec4 clip_position, vec4 previous_clip_position) {
+    vec2 ndc = clip_position.xy / clip_position.w;
+    vec2 previous_ndc = previous_clip_position.xy / previous_clip_position.w;
+    // Clip space Y is up, the target's rows go down.
+    return (ndc - previous_ndc) * vec2(0.5, -0.5);
+}
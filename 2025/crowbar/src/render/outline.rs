@@ -0,0 +1,313 @@
+//! Outlines around entities with an [`Outline`], which is how the editor shows what's selected and hovered.
+//!
+//! The 3D pass draws outlined meshes again into [`OutlineTargets::mask`] through the last camera, see
+//! [`MeshPass::record_outlines`], without depth testing so they're outlined through whatever's in front, each
+//! instance's colour being its [`mask_value`]. The mask's then spread out with a jump flood. [`SEED_SHADER`] has
+//! each covered pixel be its own seed, then [`FLOOD_SHADER`] runs once per [`flood_steps`], each pixel taking the
+//! nearest seed of those its neighbours that many pixels away know of, back and forth between the two seed targets.
+//! Seeds are kept as offsets from the pixel, which stay small enough for half floats to hold exactly.
+//! [`COMPOSITE_SHADER`] finally blends the outline over the frame, after the scene's tonemapped so outlines come out
+//! the colour they're given, wherever a pixel outside the mask is within its nearest seed's width of it.
+//! [`jump_flood`] and [`composite`] do the same on the CPU.
+//!
+//! [`MeshPass::record_outlines`]: super::mesh::MeshPass::record_outlines
+
+use glam::IVec2;
+
+use super::{
+    deletion::{DeletionQueue, Retired},
+    hal::{Device, TextureDesc, TextureFormat, TextureUsage},
+};
+use crate::{color::LinearColor, ecs::components::Outline};
+
+pub const GLSL: &str = include_str!("outline/outline.glsl");
+pub const MASK_SHADER: &str = include_str!("outline/mask.frag");
+pub const SEED_SHADER: &str = include_str!("outline/seed.frag");
+pub const FLOOD_SHADER: &str = include_str!("outline/flood.frag");
+pub const COMPOSITE_SHADER: &str = include_str!("outline/composite.frag");
+
+pub const MASK_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+pub const SEED_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+/// The widest outline in pixels. Keep in step with `OUTLINE_MAX_WIDTH` in [`GLSL`].
+pub const MAX_WIDTH: f32 = 32.0;
+
+/// What the mask has for a pixel covered by `outline`: its colour, then its width out of [`MAX_WIDTH`].
+pub fn mask_value(outline: &Outline) -> [f32; 4] {
+    let width = (outline.width / MAX_WIDTH).clamp(0.0, 1.0);
+    return outline.color.to_vec3().extend(width).to_array();
+}
+
+/// The step sizes the flood takes for outlines up to `max_width` pixels wide, halving down to 1. None for no
+/// outlines.
+pub fn flood_steps(max_width: f32) -> Vec<u32> {
+    let max_width = max_width.clamp(0.0, MAX_WIDTH).ceil() as u32;
+    if max_width == 0 {
+        return Vec::new();
+    }
+    let first = max_width.next_power_of_two();
+    return std::iter::successors(Some(first), |s| (*s > 1).then_some(s / 2)).collect();
+}
+
+/// What [`SEED_SHADER`] and [`FLOOD_SHADER`] give for a `size` mask with `covered` pixels, rows top to bottom: the
+/// offset from each pixel to the nearest covered one it found, `None` if it's further than the steps reach.
+pub fn jump_flood(covered: &[bool], size: [u32; 2], max_width: f32) -> Vec<Option<IVec2>> {
+    let size = IVec2::new(size[0] as i32, size[1] as i32);
+    let mut offsets: Vec<_> = covered.iter().map(|&c| c.then_some(IVec2::ZERO)).collect();
+    for step in flood_steps(max_width) {
+        let last = offsets.clone();
+        for (i, offset) in offsets.iter_mut().enumerate() {
+            let pixel = IVec2::new(i as i32 % size.x, i as i32 / size.x);
+            let mut best: Option<IVec2> = None;
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let from = pixel + IVec2::new(x, y) * step as i32;
+                    if from.cmplt(IVec2::ZERO).any() || from.cmpge(size).any() {
+                        continue;
+                    }
+                    let Some(seed) = last[(from.y * size.x + from.x) as usize] else {
+                        continue;
+                    };
+                    let to_seed = from - pixel + seed;
+                    if best.is_none_or(|b| to_seed.length_squared() < b.length_squared()) {
+                        best = Some(to_seed);
+                    }
+                }
+            }
+            *offset = best;
+        }
+    }
+    return offsets;
+}
+
+/// What [`COMPOSITE_SHADER`] does to `color`, with `mask` the outline covering each pixel and `offsets` from
+/// [`jump_flood`]. Solid out to each outline's width, fading over the pixel past it.
+pub fn composite(
+    color: &mut [LinearColor],
+    mask: &[Option<Outline>],
+    offsets: &[Option<IVec2>],
+    width: u32,
+) {
+    for (i, pixel) in color.iter_mut().enumerate() {
+        if mask[i].is_some() {
+            continue;
+        }
+        let Some(offset) = offsets[i] else {
+            continue;
+        };
+        let at = IVec2::new(i as i32 % width as i32, i as i32 / width as i32) + offset;
+        let Some(outline) = mask[(at.y * width as i32 + at.x) as usize] else {
+            continue;
+        };
+        let width = outline.width.clamp(0.0, MAX_WIDTH);
+        let coverage = (width + 1.0 - offset.as_vec2().length()).clamp(0.0, 1.0);
+        let blended = pixel.to_vec3().lerp(outline.color.to_vec3(), coverage);
+        *pixel = LinearColor::rgba(blended.x, blended.y, blended.z, pixel.a);
+    }
+}
+
+/// The mask, and two targets for the flood to go back and forth between, made while anything's outlined.
+pub struct OutlineTargets<D: Device> {
+    size: [u32; 2],
+    mask: Option<D::Texture>,
+    seeds: Option<[D::Texture; 2]>,
+}
+
+impl<D: Device> Default for OutlineTargets<D> {
+    fn default() -> Self {
+        OutlineTargets {
+            size: [0, 0],
+            mask: None,
+            seeds: None,
+        }
+    }
+}
+
+fn target(size: [u32; 2], format: TextureFormat) -> TextureDesc {
+    TextureDesc {
+        width: size[0],
+        height: size[1],
+        format,
+        usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+        samples: 1,
+    }
+}
+
+impl<D: Device> OutlineTargets<D> {
+    /// Bring the targets in line with a view `size` big, making them if anything's `outlined` and retiring them as
+    /// of `frame` if not.
+    pub fn update(
+        &mut self,
+        device: &D,
+        outlined: bool,
+        size: [u32; 2],
+        frame: u64,
+        deletions: &mut DeletionQueue<Retired<D>>,
+    ) -> Result<(), D::Error> {
+        let size = size.map(|s| s.max(1));
+        if outlined == self.mask.is_some() && (!outlined || size == self.size) {
+            return Ok(());
+        }
+        self.retire_all(frame, deletions);
+        if !outlined {
+            return Ok(());
+        }
+
+        let mut made = Vec::with_capacity(3);
+        for format in [MASK_FORMAT, SEED_FORMAT, SEED_FORMAT] {
+            match device.create_texture(&target(size, format)) {
+                Ok(texture) => made.push(texture),
+                Err(e) => {
+                    for texture in made {
+                        deletions.retire(frame, Retired::Texture(texture));
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let mut made = made.into_iter();
+        self.mask = made.next();
+        self.seeds = made.next().zip(made.next()).map(|(a, b)| [a, b]);
+        self.size = size;
+        return Ok(());
+    }
+
+    /// What the mask and each seed target are made as, for a render graph to import them with. `None` while
+    /// nothing's outlined.
+    pub fn descs(&self) -> Option<[TextureDesc; 2]> {
+        self.mask.as_ref()?;
+        return Some([MASK_FORMAT, SEED_FORMAT].map(|format| target(self.size, format)));
+    }
+
+    /// The outlined meshes' colours and widths.
+    pub fn mask(&self) -> Option<&D::Texture> {
+        self.mask.as_ref()
+    }
+
+    /// What flood pass `pass` reads and draws to, the seed pass drawing to what pass 0 reads.
+    pub fn flood(&self, pass: usize) -> Option<(&D::Texture, &D::Texture)> {
+        let [a, b] = self.seeds.as_ref()?;
+        return Some(if pass.is_multiple_of(2) {
+            (a, b)
+        } else {
+            (b, a)
+        });
+    }
+
+    /// Where the flood's offsets end up after `passes` passes, for the composite to read.
+    pub fn offsets(&self, passes: usize) -> Option<&D::Texture> {
+        let seeds = self.seeds.as_ref()?;
+        return Some(&seeds[passes % 2]);
+    }
+
+    /// Retire everything, for shutting down.
+    pub fn retire_all(&mut self, frame: u64, deletions: &mut DeletionQueue<Retired<D>>) {
+        let seeds = self.seeds.take().into_iter().flatten();
+        for texture in self.mask.take().into_iter().chain(seeds) {
+            deletions.retire(frame, Retired::Texture(texture));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{IVec2, Vec3};
+
+    use super::{OutlineTargets, composite, flood_steps, jump_flood, mask_value};
+    use crate::{
+        color::LinearColor,
+        ecs::components::Outline,
+        render::deletion::DeletionQueue,
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn floods_and_outlines() {
+        assert_eq!(flood_steps(0.0), Vec::<u32>::new());
+        assert_eq!(flood_steps(3.0), [4, 2, 1]);
+        assert_eq!(flood_steps(100.0), [32, 16, 8, 4, 2, 1]);
+
+        let outline = Outline {
+            color: LinearColor::YELLOW,
+            width: 2.0,
+        };
+        assert_eq!(mask_value(&outline), [1.0, 1.0, 0.0, 1.0 / 16.0]);
+
+        // A 4x4 square and a lone pixel, in a 16x16 view.
+        let size = [16, 16];
+        let covered: Vec<bool> = (0..256)
+            .map(|i| {
+                let (x, y) = (i % 16, i / 16);
+                ((6..10).contains(&x) && (6..10).contains(&y)) || (x, y) == (1, 14)
+            })
+            .collect();
+        let offsets = jump_flood(&covered, size, outline.width);
+
+        // Every pixel the steps reach finds its nearest covered pixel.
+        for (i, offset) in offsets.iter().enumerate() {
+            let pixel = IVec2::new(i as i32 % 16, i as i32 / 16);
+            let nearest = (0..256)
+                .filter(|&j| covered[j])
+                .map(|j| (IVec2::new(j as i32 % 16, j as i32 / 16) - pixel).length_squared())
+                .min()
+                .unwrap();
+            if nearest <= 9 {
+                assert_eq!(offset.unwrap().length_squared(), nearest, "at {pixel}");
+            }
+        }
+        assert_eq!(offsets[6 * 16 + 6], Some(IVec2::ZERO));
+
+        let mask: Vec<_> = covered.iter().map(|&c| c.then_some(outline)).collect();
+        let mut color = vec![LinearColor::BLUE; 256];
+        composite(&mut color, &mask, &offsets, 16);
+        let at = |x: usize, y: usize| color[y * 16 + x].to_vec3();
+        let yellow = Vec3::new(1.0, 1.0, 0.0);
+        // Inside's left alone, the edge is solid out to the width, then fades.
+        assert_eq!(at(7, 7), Vec3::Z);
+        assert_eq!(at(5, 7), yellow);
+        assert_eq!(at(4, 7), yellow);
+        assert_eq!(at(3, 7), Vec3::Z);
+        assert_eq!(at(12, 12), Vec3::Z);
+        // Rounded off round the corners.
+        let corner = 3.0 - 8f32.sqrt();
+        assert!(at(4, 4).abs_diff_eq(Vec3::Z.lerp(yellow, corner), 1e-6));
+        assert_eq!(at(1, 13), yellow);
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let mut deletions = DeletionQueue::new(2);
+        let mut targets = OutlineTargets::default();
+        targets
+            .update(device, true, [64, 32], 0, &mut deletions)
+            .unwrap();
+        assert!(targets.mask().is_some());
+        assert_eq!(targets.descs().unwrap()[1].width, 64);
+        let steps = flood_steps(4.0).len();
+        assert!(std::ptr::eq(
+            targets.flood(steps - 1).unwrap().1,
+            targets.offsets(steps).unwrap()
+        ));
+        assert!(!std::ptr::eq(
+            targets.offsets(steps).unwrap(),
+            targets.offsets(steps + 1).unwrap()
+        ));
+
+        // Nothing changes for the same size, a resize remakes them, and they go once nothing's outlined.
+        targets
+            .update(device, true, [64, 32], 1, &mut deletions)
+            .unwrap();
+        assert_eq!(deletions.len(), 0);
+        targets
+            .update(device, true, [128, 64], 1, &mut deletions)
+            .unwrap();
+        assert_eq!(deletions.len(), 3);
+        targets
+            .update(device, false, [128, 64], 2, &mut deletions)
+            .unwrap();
+        assert!(targets.mask().is_none() && targets.descs().is_none());
+        assert_eq!(deletions.len(), 6);
+        deletions.flush(device);
+    }
+}
@@ -0,0 +1,31 @@
+#version 450
+// Draws the outlines over the frame, blended in with alpha. The frame can be a different size to the mask, so each
+// pixel goes by the mask's pixel under it. Keep in step with composite in outline.rs.
+#include "outline.glsl"
+
+layout(set = 0, binding = 0) uniform texture2D mask;
+// The offsets from the last flood step.
+layout(set = 0, binding = 1) uniform texture2D seeds;
+layout(set = 0, binding = 2) uniform sampler linear_sampler;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 size = textureSize(sampler2D(mask, linear_sampler), 0);
+    ivec2 pixel = clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+    vec2 offset = texelFetch(sampler2D(seeds, linear_sampler), pixel, 0).xy;
+    // Inside the mask, or nowhere near it.
+    if (offset.x >= OUTLINE_NO_SEED || texelFetch(sampler2D(mask, linear_sampler), pixel, 0).a > 0.0) {
+        discard;
+    }
+    vec4 seed = texelFetch(sampler2D(mask, linear_sampler), pixel + ivec2(offset), 0);
+    float width = seed.a * OUTLINE_MAX_WIDTH;
+    // Solid out to the width, fading over the pixel past it.
+    float coverage = clamp(width + 1.0 - length(offset), 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    out_color = vec4(seed.rgb, coverage);
+}
@@ -0,0 +1,42 @@
+#version 450
+// One step of the jump flood: each pixel takes the nearest seed of those it and its neighbours `step` pixels away
+// know of, as an offset from it. Keep in step with jump_flood in outline.rs.
+#include "outline.glsl"
+
+// The offsets from the last step.
+layout(set = 0, binding = 0) uniform texture2D seeds;
+layout(set = 0, binding = 1) uniform sampler linear_sampler;
+
+layout(push_constant) uniform Push {
+    int step;
+} push;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec2 out_offset;
+
+void main() {
+    ivec2 size = textureSize(sampler2D(seeds, linear_sampler), 0);
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec2 best = vec2(OUTLINE_NO_SEED);
+    float best_distance = dot(best, best);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 from = pixel + ivec2(x, y) * push.step;
+            if (any(lessThan(from, ivec2(0))) || any(greaterThanEqual(from, size))) {
+                continue;
+            }
+            vec2 offset = texelFetch(sampler2D(seeds, linear_sampler), from, 0).xy;
+            if (offset.x >= OUTLINE_NO_SEED) {
+                continue;
+            }
+            vec2 to_seed = vec2(from - pixel) + offset;
+            float distance = dot(to_seed, to_seed);
+            if (distance < best_distance) {
+                best = to_seed;
+                best_distance = distance;
+            }
+        }
+    }
+    out_offset = best;
+}
@@ -0,0 +1,11 @@
+#version 450
+// Draws outlined meshes into the mask, with mesh.vert. Each instance's colour is its outline's mask_value from
+// outline.rs.
+
+layout(location = 2) flat in vec4 mask;
+
+layout(location = 0) out vec4 out_mask;
+
+void main() {
+    out_mask = mask;
+}
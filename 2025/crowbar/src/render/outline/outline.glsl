@@ -0,0 +1,6 @@
+// Outlines, see outline.rs.
+
+// The widest outline in pixels. Keep in step with MAX_WIDTH in outline.rs.
+const float OUTLINE_MAX_WIDTH = 32.0;
+// Where a pixel has no seed to offset to, further than any the flood finds. outline.rs has None instead.
+const float OUTLINE_NO_SEED = 16384.0;
@@ -0,0 +1,15 @@
+#version 450
+// Starts the jump flood: every pixel the mask covers is its own seed. Keep in step with jump_flood in outline.rs.
+#include "outline.glsl"
+
+layout(set = 0, binding = 0) uniform texture2D mask;
+layout(set = 0, binding = 1) uniform sampler linear_sampler;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec2 out_offset;
+
+void main() {
+    float width = texelFetch(sampler2D(mask, linear_sampler), ivec2(gl_FragCoord.xy), 0).a;
+    out_offset = width > 0.0 ? vec2(0.0) : vec2(OUTLINE_NO_SEED);
+}
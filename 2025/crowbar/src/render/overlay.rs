@@ -0,0 +1,564 @@
+//! Painting the debug overlay's egui output over the window, see [`crate::overlay`].
+//!
+//! egui hands its textures over as changes to what it sent before, so [`OverlayPass`] keeps a copy of each one's
+//! texels to patch, and uploads it again whole as a new texture, there being no copying into part of one. What it
+//! replaces is retired with the frame, for the frames in flight to finish with. Every texture's sampled linearly and
+//! clamped, whatever its options say, and only egui's own are drawn.
+//!
+//! Ship its shaders compiled, as [`VERTEX_ASSET`] and [`FRAGMENT_ASSET`], or they're compiled from
+//! [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`] at startup.
+
+use std::collections::HashMap;
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    deletion::{DeletionQueue, Retired},
+    descriptors::FrameDescriptors,
+    hal::{
+        BufferDesc, BufferUsage, Device, MemoryLocation,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture},
+    },
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, TargetFormats},
+    rendering::PassContext,
+    shader::reflect::Spirv,
+    sprite::upload_texture,
+};
+use crate::overlay::OverlayOutput;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// The vertex shader's source, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("overlay/overlay.vert");
+/// The fragment shader's source, to compile at runtime.
+pub const FRAGMENT_SHADER: &str = include_str!("overlay/overlay.frag");
+/// Where the compiled shaders go among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/overlay.vert";
+pub const FRAGMENT_ASSET: &str = "shaders/overlay.frag";
+
+/// Bytes per vertex: the position and UV as floats, then the colour as bytes.
+const VERTEX_BYTES: u64 = 20;
+/// The fewest vertices, and indices, a frame's buffers are made for.
+const MIN_VERTICES: u64 = 1024;
+
+/// One of egui's textures, and what it was made from.
+struct OverlayTexture {
+    texture: VulkanTexture,
+    size: [usize; 2],
+    /// sRGB RGBA8, premultiplied, row by row.
+    texels: Vec<u8>,
+}
+
+/// A frame in flight's vertices and indices.
+#[derive(Default)]
+struct Slot {
+    vertices: Option<VulkanBuffer>,
+    indices: Option<VulkanBuffer>,
+}
+
+/// Draws an [`OverlayOutput`], with the textures egui's asked for so far.
+pub struct OverlayPass {
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, kept until the pass goes as there are only ever a few.
+    pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    sampler: vk::Sampler,
+    /// A set per texture drawn with, as they're replaced whenever egui changes them.
+    descriptors: FrameDescriptors,
+    /// The frame the descriptors were last begun for.
+    begun: Option<u64>,
+    textures: HashMap<egui::TextureId, OverlayTexture>,
+    slots: Vec<Slot>,
+}
+
+impl OverlayPass {
+    /// Set up for `frames_in_flight` frames, with shaders compiled from [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`].
+    ///
+    /// # Safety
+    /// `cache` must be `device`'s, or null, and outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        cache: vk::PipelineCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<OverlayPass> {
+        let mut pass = OverlayPass {
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            sampler: vk::Sampler::null(),
+            descriptors: FrameDescriptors::new(device.raw(), frames_in_flight, RATIOS),
+            begun: None,
+            textures: HashMap::new(),
+            slots: Vec::new(),
+        };
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, vertex, fragment) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        pass.slots
+            .resize_with(frames_in_flight.max(1) as usize, Slot::default);
+        return Ok(pass);
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        vertex: &Spirv,
+        fragment: &Spirv,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let binding = |binding, ty| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        };
+        // Apart, as naga can't take combined image samplers.
+        let bindings = [
+            binding(0, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(1, vk::DescriptorType::SAMPLER),
+        ];
+        // The target's size in points.
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(8);
+
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = raw.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                allocs(),
+            )?;
+            let set_layouts = [self.set_layout];
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&set_layouts)
+                    .push_constant_ranges(std::slice::from_ref(&push)),
+                allocs(),
+            )?;
+            self.vertex = vertex.create_module(raw)?;
+            self.fragment = fragment.create_module(raw)?;
+            self.sampler = raw.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                allocs(),
+            )?;
+        }
+        return Ok(());
+    }
+
+    /// Make or change the textures in `set`, as [`egui::TexturesDelta::set`], before drawing. Waits for the uploads,
+    /// and retires the textures replaced as of `frame`.
+    pub fn set_textures(
+        &mut self,
+        device: &VulkanDevice,
+        set: &[(egui::TextureId, egui::epaint::ImageDelta)],
+        frame: u64,
+        deletions: &mut DeletionQueue<Retired<VulkanDevice>>,
+    ) -> VkResult<()> {
+        for (id, delta) in set {
+            let egui::ImageData::Color(image) = &delta.image;
+            let (size, texels) = match (delta.pos, self.textures.get(id)) {
+                (None, _) => (image.size, texels(image)),
+                (Some(pos), Some(old)) => {
+                    let mut texels = old.texels.clone();
+                    patch(&mut texels, old.size, image, pos);
+                    (old.size, texels)
+                }
+                (Some(_), None) => {
+                    log::warn!("egui changed overlay texture {id:?} before making it");
+                    continue;
+                }
+            };
+            let texture = upload_texture(device, size[0] as u32, size[1] as u32, &texels)?;
+            let made = OverlayTexture {
+                texture,
+                size,
+                texels,
+            };
+            if let Some(old) = self.textures.insert(*id, made) {
+                deletions.retire(frame, Retired::Texture(old.texture));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Let go of the textures in `free`, as [`egui::TexturesDelta::free`], after drawing. They're retired as of
+    /// `frame`.
+    pub fn free_textures(
+        &mut self,
+        free: &[egui::TextureId],
+        frame: u64,
+        deletions: &mut DeletionQueue<Retired<VulkanDevice>>,
+    ) {
+        for id in free {
+            if let Some(old) = self.textures.remove(id) {
+                deletions.retire(frame, Retired::Texture(old.texture));
+            }
+        }
+    }
+
+    /// Keep vertices and descriptors for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        // SAFETY: Passed on to the caller.
+        unsafe { self.destroy_slots(device) };
+        self.slots
+            .resize_with(frames.max(1) as usize, Slot::default);
+        self.descriptors = FrameDescriptors::new(device.raw(), frames, RATIOS);
+        self.begun = None;
+    }
+
+    unsafe fn destroy_slots(&mut self, device: &VulkanDevice) {
+        for slot in self.slots.drain(..) {
+            for buffer in [slot.vertices, slot.indices].into_iter().flatten() {
+                // SAFETY: Passed on to the caller.
+                unsafe { device.destroy_buffer(buffer) };
+            }
+        }
+    }
+
+    /// The pipeline for drawing to `target`, built the first time it's drawn to.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        let builder = GraphicsPipelineBuilder::new(self.layout)
+            .vertex_fragment(self.vertex, self.fragment)
+            .vertex_buffer(0, VERTEX_BYTES as u32, false)
+            .attribute(0, 0, vk::Format::R32G32_SFLOAT, 0)
+            .attribute(1, 0, vk::Format::R32G32_SFLOAT, 8)
+            .attribute(2, 0, vk::Format::R8G8B8A8_UNORM, 16)
+            // egui winds its triangles either way.
+            .cull(vk::CullModeFlags::NONE)
+            .samples(vk::SampleCountFlags::from_raw(target.samples))
+            .color(target.color, BlendMode::Premultiplied)
+            .depth(target.depth, DepthMode::Off)
+            .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// A set for drawing with `texture`, from this frame's descriptors.
+    fn texture_set(
+        &mut self,
+        device: &ash::Device,
+        texture: vk::ImageView,
+    ) -> VkResult<vk::DescriptorSet> {
+        let set = self.descriptors.allocate(self.set_layout)?;
+        let image = [vk::DescriptorImageInfo::default()
+            .image_view(texture)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler),
+        ];
+        // SAFETY: The set's new, so nothing's using it.
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+        return Ok(set);
+    }
+
+    /// Record drawing `output`'s meshes into the context's pass, each clipped to its rectangle. Meshes on textures
+    /// the pass hasn't got, and paint callbacks, are skipped.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, and the GPU done with the frame that last used this frame's
+    /// slot, like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    pub unsafe fn record(&mut self, ctx: PassContext, output: &OverlayOutput) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            extent,
+        } = ctx;
+        let meshes: Vec<_> = output
+            .primitives
+            .iter()
+            .filter_map(|p| match &p.primitive {
+                egui::epaint::Primitive::Mesh(mesh) if !mesh.indices.is_empty() => {
+                    Some((p.clip_rect, mesh))
+                }
+                _ => None,
+            })
+            .collect();
+        if meshes.is_empty() {
+            return Ok(());
+        }
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target)? };
+        if self.begun != Some(frame) {
+            // SAFETY: Passed on to the caller.
+            unsafe { self.descriptors.begin_frame(frame)? };
+            self.begun = Some(frame);
+        }
+        let vertices: Vec<u8> = meshes
+            .iter()
+            .flat_map(|(_, mesh)| &mesh.vertices)
+            .flat_map(|v| {
+                let floats = [v.pos.x, v.pos.y, v.uv.x, v.uv.y].map(f32::to_ne_bytes);
+                floats.into_iter().flatten().chain(v.color.to_array())
+            })
+            .collect();
+        let indices: Vec<u8> = meshes
+            .iter()
+            .flat_map(|(_, mesh)| &mesh.indices)
+            .flat_map(|i| i.to_ne_bytes())
+            .collect();
+
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+        // SAFETY: The caller vouches the GPU's done with this slot.
+        unsafe {
+            grow(
+                device,
+                &mut slot.vertices,
+                vertices.len() as u64 / VERTEX_BYTES,
+                VERTEX_BYTES,
+                BufferUsage::VERTEX,
+            )?;
+            grow(
+                device,
+                &mut slot.indices,
+                indices.len() as u64 / 4,
+                4,
+                BufferUsage::INDEX,
+            )?;
+            device.write_buffer(slot.vertices.as_ref().unwrap(), 0, &vertices)?;
+            device.write_buffer(slot.indices.as_ref().unwrap(), 0, &indices)?;
+        }
+        let (vertex_buffer, index_buffer) = (
+            slot.vertices.as_ref().unwrap().buffer,
+            slot.indices.as_ref().unwrap().buffer,
+        );
+        let ppp = output.pixels_per_point;
+        let size = [extent.width as f32 / ppp, extent.height as f32 / ppp];
+        let push = size.map(f32::to_ne_bytes).concat();
+
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer], &[0]);
+            raw.cmd_bind_index_buffer(cmd, index_buffer, 0, vk::IndexType::UINT32);
+        }
+        let mut sets = HashMap::new();
+        let (mut first_index, mut first_vertex) = (0, 0);
+        for (clip, mesh) in meshes {
+            let (count, offset) = (mesh.indices.len() as u32, first_vertex);
+            first_index += count;
+            first_vertex += mesh.vertices.len() as i32;
+            let Some(scissor) = scissor(clip, ppp, extent) else {
+                continue;
+            };
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let set = match sets.get(&mesh.texture_id) {
+                Some(&set) => set,
+                None => {
+                    let set = self.texture_set(raw, texture.texture.view)?;
+                    sets.insert(mesh.texture_id, set);
+                    set
+                }
+            };
+            // SAFETY: As above.
+            unsafe {
+                raw.cmd_set_scissor(cmd, 0, &[scissor]);
+                raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.layout,
+                    0,
+                    &[set],
+                    &[],
+                );
+                raw.cmd_draw_indexed(cmd, count, 1, first_index - count, offset, 0);
+            }
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and sets go with their pools.
+        unsafe {
+            self.destroy_slots(device);
+            for (_, texture) in self.textures.drain() {
+                device.destroy_texture(texture.texture);
+            }
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            raw.destroy_sampler(self.sampler, allocs());
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+            raw.destroy_descriptor_set_layout(self.set_layout, allocs());
+        }
+        self.sampler = vk::Sampler::null();
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+        self.set_layout = vk::DescriptorSetLayout::null();
+    }
+}
+
+/// What a frame's descriptor pools hold, for a set per texture drawn with.
+const RATIOS: &[(vk::DescriptorType, f32)] = &[
+    (vk::DescriptorType::SAMPLED_IMAGE, 1.0),
+    (vk::DescriptorType::SAMPLER, 1.0),
+];
+
+/// `image`'s texels, as bytes.
+fn texels(image: &egui::ColorImage) -> Vec<u8> {
+    image.pixels.iter().flat_map(|c| c.to_array()).collect()
+}
+
+/// Put `image` into `texels`, of an image `size` texels big, with its top left at `pos`. What's off the edge is
+/// dropped.
+fn patch(texels: &mut [u8], size: [usize; 2], image: &egui::ColorImage, pos: [usize; 2]) {
+    let width = image.size[0].min(size[0].saturating_sub(pos[0]));
+    let rows = image.size[1].min(size[1].saturating_sub(pos[1]));
+    for y in 0..rows {
+        let from = &image.pixels[y * image.size[0]..][..width];
+        let at = ((pos[1] + y) * size[0] + pos[0]) * 4;
+        let row = from.iter().flat_map(|c| c.to_array());
+        for (texel, byte) in texels[at..at + width * 4].iter_mut().zip(row) {
+            *texel = byte;
+        }
+    }
+}
+
+/// `clip`, in points, as a scissor in pixels inside `extent`. `None` if nothing's left of it.
+fn scissor(clip: egui::Rect, ppp: f32, extent: vk::Extent2D) -> Option<vk::Rect2D> {
+    let x = |x: f32| (x * ppp).round().clamp(0.0, extent.width as f32) as u32;
+    let y = |y: f32| (y * ppp).round().clamp(0.0, extent.height as f32) as u32;
+    let (min_x, min_y, max_x, max_y) = (x(clip.min.x), y(clip.min.y), x(clip.max.x), y(clip.max.y));
+    if min_x >= max_x || min_y >= max_y {
+        return None;
+    }
+    return Some(vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min_x as i32,
+            y: min_y as i32,
+        },
+        extent: vk::Extent2D {
+            width: max_x - min_x,
+            height: max_y - min_y,
+        },
+    });
+}
+
+/// Make sure `buffer` has room for `count` things of `bytes` each, making a bigger one if it hasn't.
+///
+/// # Safety
+/// The GPU must be done with the buffer there.
+unsafe fn grow(
+    device: &VulkanDevice,
+    buffer: &mut Option<VulkanBuffer>,
+    count: u64,
+    bytes: u64,
+    usage: BufferUsage,
+) -> VkResult<()> {
+    if buffer.as_ref().is_some_and(|b| b.size >= count * bytes) {
+        return Ok(());
+    }
+    if let Some(old) = buffer.take() {
+        // SAFETY: Passed on to the caller.
+        unsafe { device.destroy_buffer(old) };
+    }
+    *buffer = Some(device.create_buffer(&BufferDesc {
+        size: count.next_power_of_two().max(MIN_VERTICES) * bytes,
+        usage,
+        location: MemoryLocation::Upload,
+    })?);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use ash::vk;
+
+    use super::{FRAGMENT_SHADER, VERTEX_SHADER, patch, scissor, texels};
+    use crate::render::shader::compile::{ShaderCompiler, ShaderLanguage, ShaderStage};
+
+    #[test]
+    pub fn patches_and_clips() {
+        let color = |v| egui::Color32::from_gray(v);
+        let whole = egui::ColorImage::new([4, 2], vec![color(0); 8]);
+        let mut pixels = texels(&whole);
+        // Hanging off the right edge, so only its first column lands.
+        let image = egui::ColorImage::new([2, 2], vec![color(9); 4]);
+        patch(&mut pixels, whole.size, &image, [3, 0]);
+        let at = |x: usize, y: usize| pixels[(y * 4 + x) * 4];
+        assert_eq!([at(2, 0), at(3, 0), at(3, 1), at(0, 1)], [0, 9, 9, 0]);
+
+        let extent = vk::Extent2D {
+            width: 100,
+            height: 50,
+        };
+        let clip = egui::Rect::from_min_max(egui::pos2(10.0, -5.0), egui::pos2(80.0, 40.0));
+        let rect = scissor(clip, 2.0, extent).unwrap();
+        assert_eq!((rect.offset.x, rect.offset.y), (20, 0));
+        assert_eq!((rect.extent.width, rect.extent.height), (80, 50));
+        let outside = egui::Rect::from_min_max(egui::pos2(60.0, 0.0), egui::pos2(70.0, 10.0));
+        assert!(scissor(outside, 1.0, extent).is_some());
+        assert!(scissor(outside, 2.0, extent).is_none());
+    }
+
+    #[test]
+    pub fn shaders_compile() {
+        let compiler = ShaderCompiler::new(std::env::temp_dir());
+        if !compiler.available(ShaderLanguage::Glsl) {
+            return;
+        }
+        for (path, text, stage) in [
+            ("overlay.vert", VERTEX_SHADER, ShaderStage::Vertex),
+            ("overlay.frag", FRAGMENT_SHADER, ShaderStage::Fragment),
+        ] {
+            let compiled = compiler.compile_text(Path::new(path), text, stage, "main");
+            assert!(compiled.is_ok(), "{path}: {:?}", compiled.err());
+        }
+    }
+}
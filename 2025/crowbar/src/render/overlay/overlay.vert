@@ -0,0 +1,29 @@
+#version 450
+// egui's meshes in points from the top left. Keep in step with OverlayPass in overlay.rs.
+
+layout(push_constant) uniform Push {
+    // The target's size in points.
+    vec2 screen;
+};
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+// sRGB, premultiplied.
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+vec3 linear(vec3 srgb) {
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(srgb, vec3(0.04045)));
+}
+
+void main() {
+    // Flipped to keep clip space Y up, as points count down.
+    vec2 ndc = position / screen * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    out_uv = uv;
+    out_color = vec4(linear(color.rgb), color.a);
+}
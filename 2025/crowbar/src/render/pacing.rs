@@ -0,0 +1,184 @@
+//! Frame pacing: what the display is actually doing with the frames we hand it.
+//!
+//! Fed from presentation feedback where the platform has it (Wayland's presentation-time protocol),
+//! which says when each frame hit the screen, at what refresh interval, and whether any refreshes went by
+//! without a new frame. Elsewhere there's only the refresh rate the monitor reports, which is still enough to
+//! snap frame deltas to whole refreshes with vsync, so movement doesn't jitter with the OS's timing noise.
+
+use std::time::Duration;
+
+/// How far off a whole number of refreshes a frame's delta can be and still get snapped to it, as a fraction of
+/// the refresh interval.
+pub const SNAP_TOLERANCE: f32 = 0.1;
+
+/// A refresh interval from a rate in millihertz, as winit reports monitors'.
+pub fn refresh_from_millihertz(millihertz: u32) -> Option<Duration> {
+    if millihertz == 0 {
+        return None;
+    }
+    return Some(Duration::from_secs_f64(1000.0 / millihertz as f64));
+}
+
+/// One frame reaching the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentedFrame {
+    /// When it turned to light, on the presentation clock (`CLOCK_MONOTONIC` on Linux).
+    pub time: Duration,
+    /// The display's refresh interval at the time, `None` if it doesn't have a fixed one (VRR).
+    pub refresh: Option<Duration>,
+    /// The display's refresh counter. Gaps mean missed refreshes.
+    pub seq: u64,
+    /// Tied to vblank, rather than whenever it was ready.
+    pub vsync: bool,
+    /// Scanned out of our buffer directly, without the compositor copying it.
+    pub zero_copy: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FramePacer {
+    last: Option<PresentedFrame>,
+    refresh: Option<Duration>,
+    /// What the monitor the window is on says, for when there's no feedback.
+    monitor_refresh: Option<Duration>,
+    presented: u64,
+    /// Refreshes that went by showing an old frame, because the new one wasn't ready.
+    missed: u64,
+    /// Frames that never made it to the screen, replaced before the display got to them.
+    discarded: u64,
+}
+
+impl FramePacer {
+    pub fn on_presented(&mut self, frame: PresentedFrame) {
+        if let Some(last) = self.last {
+            if frame.seq > last.seq {
+                self.missed += frame.seq - last.seq - 1;
+            }
+            // No interval given, so go by how far apart frames were shown, if they were back to back.
+            if frame.refresh.is_none() && frame.seq == last.seq + 1 {
+                self.refresh = frame.time.checked_sub(last.time);
+            }
+        }
+        if frame.refresh.is_some() {
+            self.refresh = frame.refresh;
+        }
+
+        self.last = Some(frame);
+        self.presented += 1;
+    }
+
+    pub fn on_discarded(&mut self) {
+        self.discarded += 1;
+    }
+
+    /// The latest frame that made it to the screen.
+    pub fn last_presented(&self) -> Option<&PresentedFrame> {
+        self.last.as_ref()
+    }
+
+    /// The window moved to a monitor refreshing every `refresh`.
+    pub fn set_monitor_refresh(&mut self, refresh: Option<Duration>) {
+        self.monitor_refresh = refresh;
+    }
+
+    pub fn monitor_refresh(&self) -> Option<Duration> {
+        self.monitor_refresh
+    }
+
+    /// How often the display refreshes, as best we know. Feedback beats what the monitor says, it knows about VRR
+    /// and compositors that run slower than the monitor.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh.or(self.monitor_refresh)
+    }
+
+    /// In Hz.
+    pub fn refresh_rate(&self) -> Option<f32> {
+        self.refresh_interval()
+            .filter(|r| !r.is_zero())
+            .map(|r| 1.0 / r.as_secs_f32())
+    }
+
+    pub fn presented(&self) -> u64 {
+        self.presented
+    }
+
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// `delta`, in seconds, snapped to a whole number of refreshes if it's close to one. With vsync frames can
+    /// only be shown on refreshes, so that's how far apart they really are.
+    pub fn snap_delta(&self, delta: f32) -> f32 {
+        let Some(refresh) = self.refresh_interval().filter(|r| !r.is_zero()) else {
+            return delta;
+        };
+        let refresh = refresh.as_secs_f32();
+        let refreshes = (delta / refresh).round().max(1.0);
+        if (delta - refreshes * refresh).abs() <= refresh * SNAP_TOLERANCE {
+            return refreshes * refresh;
+        }
+        return delta;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{FramePacer, PresentedFrame, refresh_from_millihertz};
+
+    fn frame(seq: u64, refresh: Option<Duration>) -> PresentedFrame {
+        PresentedFrame {
+            time: Duration::from_micros(seq * 16_667),
+            refresh,
+            seq,
+            vsync: true,
+            zero_copy: false,
+        }
+    }
+
+    #[test]
+    pub fn counts_missed_refreshes() {
+        let mut pacer = FramePacer::default();
+        pacer.on_presented(frame(10, None));
+        pacer.on_presented(frame(11, None));
+        assert_eq!(
+            pacer.refresh_interval(),
+            Some(Duration::from_micros(16_667))
+        );
+        assert_eq!(pacer.refresh_rate().unwrap().round(), 60.0);
+
+        // Two refreshes went by on the old frame.
+        pacer.on_presented(frame(14, Some(Duration::from_micros(8_333))));
+        pacer.on_discarded();
+        assert_eq!(pacer.missed(), 2);
+        assert_eq!(pacer.presented(), 3);
+        assert_eq!(pacer.discarded(), 1);
+        // The compositor's word beats our guess.
+        assert_eq!(pacer.refresh_interval(), Some(Duration::from_micros(8_333)));
+    }
+
+    #[test]
+    pub fn snaps_to_monitor_refresh() {
+        let mut pacer = FramePacer::default();
+        assert_eq!(pacer.snap_delta(0.015), 0.015);
+
+        // A 144 Hz monitor, without feedback.
+        pacer.set_monitor_refresh(refresh_from_millihertz(144_000));
+        let refresh = 1.0 / 144.0;
+        let snaps_to = |delta: f32, to: f32| (pacer.snap_delta(delta) - to).abs() < 1e-6;
+        assert!(snaps_to(refresh + 0.0003, refresh));
+        // A missed refresh is two of them.
+        assert!(snaps_to(2.0 * refresh - 0.0004, 2.0 * refresh));
+        // Halfway between is a hitch, not a refresh, leave it alone.
+        assert_eq!(pacer.snap_delta(1.5 * refresh), 1.5 * refresh);
+
+        // Moved to a 60 Hz monitor.
+        pacer.set_monitor_refresh(refresh_from_millihertz(60_000));
+        assert_eq!(pacer.refresh_rate().unwrap().round(), 60.0);
+        assert_eq!(refresh_from_millihertz(0), None);
+    }
+}
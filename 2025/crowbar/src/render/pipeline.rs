@@ -0,0 +1,460 @@
+//! Building graphics pipelines without spelling out all of `VkGraphicsPipelineCreateInfo` each time.
+//!
+//! [`GraphicsPipelineBuilder`] starts from what most draws want: triangle lists, back faces culled, one sample,
+//! no blending, viewport and scissor dynamic so the pipeline doesn't care about window size. Pipelines are for
+//! dynamic rendering, so they take attachment formats rather than a render pass. Depth follows the reversed-Z
+//! conventions in [`crate::math`].
+
+use std::ffi::CStr;
+
+use ash::{prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// # Safety
+/// `spirv` must be valid SPIR-V for the device.
+pub unsafe fn create_shader_module(
+    device: &ash::Device,
+    spirv: &[u32],
+) -> VkResult<vk::ShaderModule> {
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(spirv), allocs())
+    }
+}
+
+/// How a colour attachment combines what's drawn with what's there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite.
+    #[default]
+    Opaque,
+    /// Straight alpha: `src * a + dst * (1 - a)`.
+    Alpha,
+    /// Colour already multiplied by alpha: `src + dst * (1 - a)`.
+    Premultiplied,
+    /// `src * a + dst`, for light and glows.
+    Additive,
+    /// `src * dst`.
+    Multiply,
+    /// Nothing's written, for drawing to depth and stencil alone.
+    Masked,
+}
+
+impl BlendMode {
+    pub fn attachment(self) -> vk::PipelineColorBlendAttachmentState {
+        let state = vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        let (src, dst, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => return state,
+            BlendMode::Masked => return state.color_write_mask(vk::ColorComponentFlags::empty()),
+            BlendMode::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Premultiplied => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE,
+            ),
+            BlendMode::Multiply => (
+                vk::BlendFactor::DST_COLOR,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::DST_ALPHA,
+                vk::BlendFactor::ZERO,
+            ),
+        };
+        return state
+            .blend_enable(true)
+            .src_color_blend_factor(src)
+            .dst_color_blend_factor(dst)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(vk::BlendOp::ADD);
+    }
+}
+
+/// Depth testing and writing, for reversed Z: nearer is greater.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthMode {
+    /// Neither, like for a fullscreen pass.
+    #[default]
+    Off,
+    /// Test without writing, like for transparent things.
+    Test,
+    /// Test and write, for opaque things.
+    TestWrite,
+    /// Pass where the depth is the same, for drawing over a depth prepass.
+    Equal,
+    /// Write without testing, for putting depth back over what was drawn, like a portal's surface over its view.
+    Always,
+}
+
+impl DepthMode {
+    pub fn state(self) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        let state = vk::PipelineDepthStencilStateCreateInfo::default();
+        let (write, compare) = match self {
+            DepthMode::Off => return state,
+            DepthMode::Test => (false, vk::CompareOp::GREATER_OR_EQUAL),
+            DepthMode::TestWrite => (true, vk::CompareOp::GREATER_OR_EQUAL),
+            DepthMode::Equal => (false, vk::CompareOp::EQUAL),
+            DepthMode::Always => (true, vk::CompareOp::ALWAYS),
+        };
+        return state
+            .depth_test_enable(true)
+            .depth_write_enable(write)
+            .depth_compare_op(compare);
+    }
+}
+
+/// Stencil testing against the stencil reference, for drawing into regions marked out in it, like
+/// [`super::portal`]'s. The reference is set while recording, so one pipeline does for every region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StencilMode {
+    #[default]
+    Off,
+    /// Pass where the stencil is the reference, and raise it by 1 there.
+    Mark,
+    /// Pass where the stencil is the reference.
+    Inside,
+    /// Pass where the stencil is the reference, and lower it by 1 there.
+    Unmark,
+}
+
+impl StencilMode {
+    /// `state` with the stencil test added.
+    pub fn apply(
+        self,
+        state: vk::PipelineDepthStencilStateCreateInfo<'static>,
+    ) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        let pass = match self {
+            StencilMode::Off => return state,
+            StencilMode::Mark => vk::StencilOp::INCREMENT_AND_CLAMP,
+            StencilMode::Inside => vk::StencilOp::KEEP,
+            StencilMode::Unmark => vk::StencilOp::DECREMENT_AND_CLAMP,
+        };
+        let face = vk::StencilOpState::default()
+            .fail_op(vk::StencilOp::KEEP)
+            .pass_op(pass)
+            .depth_fail_op(vk::StencilOp::KEEP)
+            .compare_op(vk::CompareOp::EQUAL)
+            .compare_mask(u32::MAX)
+            .write_mask(u32::MAX);
+        return state.stencil_test_enable(true).front(face).back(face);
+    }
+}
+
+fn has_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::S8_UINT
+    )
+}
+
+/// The attachments a pass draws to, which the pipelines it draws with have to be built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TargetFormats {
+    pub color: vk::Format,
+    /// `UNDEFINED` without a depth attachment.
+    pub depth: vk::Format,
+    /// A second colour attachment for motion vectors, `UNDEFINED` without one. Only the scene has one.
+    pub velocity: vk::Format,
+    pub samples: u32,
+}
+
+impl TargetFormats {
+    /// The depth attachment has a stencil to draw with [`StencilMode`]s.
+    pub fn has_stencil(&self) -> bool {
+        has_stencil(self.depth)
+    }
+}
+
+pub struct GraphicsPipelineBuilder<'a> {
+    layout: vk::PipelineLayout,
+    stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule, &'a CStr)>,
+    bindings: Vec<vk::VertexInputBindingDescription>,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    /// Constant and slope factors.
+    depth_bias: Option<(f32, f32)>,
+    samples: vk::SampleCountFlags,
+    colors: Vec<(vk::Format, BlendMode)>,
+    depth_format: vk::Format,
+    depth: DepthMode,
+    stencil: StencilMode,
+    dynamic: Vec<vk::DynamicState>,
+    cache: vk::PipelineCache,
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    /// A pipeline using `layout` for its descriptor sets and push constants.
+    pub fn new(layout: vk::PipelineLayout) -> GraphicsPipelineBuilder<'a> {
+        GraphicsPipelineBuilder {
+            layout,
+            stages: Vec::new(),
+            bindings: Vec::new(),
+            attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            // The flipped viewport keeps counter-clockwise in clip space counter-clockwise on screen.
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            colors: Vec::new(),
+            depth_format: vk::Format::UNDEFINED,
+            depth: DepthMode::Off,
+            stencil: StencilMode::Off,
+            dynamic: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            cache: vk::PipelineCache::null(),
+        }
+    }
+
+    /// Run `module`'s `entry` for `stage`.
+    pub fn shader(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        entry: &'a CStr,
+    ) -> Self {
+        self.stages.push((stage, module, entry));
+        self
+    }
+
+    /// A vertex and a fragment shader, both with `main` as the entry point.
+    pub fn vertex_fragment(self, vertex: vk::ShaderModule, fragment: vk::ShaderModule) -> Self {
+        self.shader(vk::ShaderStageFlags::VERTEX, vertex, c"main")
+            .shader(vk::ShaderStageFlags::FRAGMENT, fragment, c"main")
+    }
+
+    /// A vertex buffer at `binding`, `stride` bytes per vertex, or per instance.
+    pub fn vertex_buffer(mut self, binding: u32, stride: u32, per_instance: bool) -> Self {
+        self.bindings.push(vk::VertexInputBindingDescription {
+            binding,
+            stride,
+            input_rate: if per_instance {
+                vk::VertexInputRate::INSTANCE
+            } else {
+                vk::VertexInputRate::VERTEX
+            },
+        });
+        self
+    }
+
+    /// Vertex shader input `location`, `offset` bytes into `binding`'s vertices.
+    pub fn attribute(
+        mut self,
+        location: u32,
+        binding: u32,
+        format: vk::Format,
+        offset: u32,
+    ) -> Self {
+        self.attributes.push(vk::VertexInputAttributeDescription {
+            location,
+            binding,
+            format,
+            offset,
+        });
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Lines or points instead of filled triangles, for wireframes. Lines need the `fillModeNonSolid` feature.
+    pub fn polygon_mode(mut self, mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = mode;
+        self
+    }
+
+    pub fn cull(mut self, mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = mode;
+        self
+    }
+
+    pub fn front_face(mut self, face: vk::FrontFace) -> Self {
+        self.front_face = face;
+        self
+    }
+
+    /// Push depth towards the camera by `constant` and `slope` factors, for shadow maps and decals. Positive pushes
+    /// nearer, as depth is reversed.
+    pub fn depth_bias(mut self, constant: f32, slope: f32) -> Self {
+        self.depth_bias = Some((constant, slope));
+        self
+    }
+
+    /// Samples per pixel, which has to match the attachments. Pipelines built through
+    /// [`super::renderer::Renderer::add_pipeline`] are handed the count to use.
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Add a colour attachment, after any already added.
+    pub fn color(mut self, format: vk::Format, blend: BlendMode) -> Self {
+        self.colors.push((format, blend));
+        self
+    }
+
+    pub fn depth(mut self, format: vk::Format, mode: DepthMode) -> Self {
+        self.depth_format = format;
+        self.depth = mode;
+        self
+    }
+
+    /// Test the stencil of the depth format, against a reference set while recording.
+    pub fn stencil(mut self, mode: StencilMode) -> Self {
+        self.stencil = mode;
+        if mode != StencilMode::Off {
+            self = self.dynamic(vk::DynamicState::STENCIL_REFERENCE);
+        }
+        self
+    }
+
+    /// State to set while recording, on top of the viewport and scissor.
+    pub fn dynamic(mut self, state: vk::DynamicState) -> Self {
+        if !self.dynamic.contains(&state) {
+            self.dynamic.push(state);
+        }
+        self
+    }
+
+    /// Usually [`super::renderer::Renderer::pipeline_cache`], or the one a hot pipeline's build is handed.
+    pub fn cache(mut self, cache: vk::PipelineCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// # Safety
+    /// The shader modules, layout and cache must be from `device`, and the state has to be something it supports.
+    pub unsafe fn build(&self, device: &ash::Device) -> VkResult<vk::Pipeline> {
+        let stages: Vec<_> = self
+            .stages
+            .iter()
+            .map(|&(stage, module, entry)| {
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage)
+                    .module(module)
+                    .name(entry)
+            })
+            .collect();
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.bindings)
+            .vertex_attribute_descriptions(&self.attributes);
+        let input_assembly =
+            vk::PipelineInputAssemblyStateCreateInfo::default().topology(self.topology);
+        // Counts only, the viewport and scissor are dynamic.
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let mut rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .line_width(1.0);
+        if let Some((constant, slope)) = self.depth_bias {
+            rasterization = rasterization
+                .depth_bias_enable(true)
+                .depth_bias_constant_factor(constant)
+                .depth_bias_slope_factor(slope);
+        }
+        let multisample =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(self.samples);
+        let depth_stencil = self.stencil.apply(self.depth.state());
+        let blends: Vec<_> = self.colors.iter().map(|(_, b)| b.attachment()).collect();
+        let blend = vk::PipelineColorBlendStateCreateInfo::default().attachments(&blends);
+        let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&self.dynamic);
+        let formats: Vec<_> = self.colors.iter().map(|(f, _)| *f).collect();
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&formats)
+            .depth_attachment_format(self.depth_format);
+        if has_stencil(self.depth_format) {
+            rendering = rendering.stencil_attachment_format(self.depth_format);
+        }
+
+        let info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&blend)
+            .dynamic_state(&dynamic)
+            .layout(self.layout)
+            .push_next(&mut rendering);
+        // SAFETY: Passed on to the caller.
+        let pipelines = unsafe { device.create_graphics_pipelines(self.cache, &[info], allocs()) };
+        return pipelines.map(|p| p[0]).map_err(|(_, e)| e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{BlendMode, DepthMode, StencilMode};
+
+    #[test]
+    pub fn standard_states() {
+        assert_eq!(BlendMode::Opaque.attachment().blend_enable, vk::FALSE);
+        let alpha = BlendMode::Alpha.attachment();
+        assert_eq!(alpha.blend_enable, vk::TRUE);
+        assert_eq!(
+            alpha.dst_color_blend_factor,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+        );
+        assert_eq!(
+            BlendMode::Premultiplied.attachment().src_color_blend_factor,
+            vk::BlendFactor::ONE
+        );
+
+        assert_eq!(DepthMode::Off.state().depth_test_enable, vk::FALSE);
+        let opaque = DepthMode::TestWrite.state();
+        assert_eq!(opaque.depth_write_enable, vk::TRUE);
+        // Reversed Z, nearer is greater.
+        assert_eq!(opaque.depth_compare_op, vk::CompareOp::GREATER_OR_EQUAL);
+        assert_eq!(DepthMode::Test.state().depth_write_enable, vk::FALSE);
+        let always = DepthMode::Always.state();
+        assert_eq!(always.depth_compare_op, vk::CompareOp::ALWAYS);
+        assert_eq!(always.depth_write_enable, vk::TRUE);
+        let masked = BlendMode::Masked.attachment();
+        assert!(masked.color_write_mask.is_empty() && masked.blend_enable == vk::FALSE);
+
+        let off = StencilMode::Off.apply(opaque);
+        assert_eq!(off.stencil_test_enable, vk::FALSE);
+        let mark = StencilMode::Mark.apply(opaque);
+        assert_eq!(mark.stencil_test_enable, vk::TRUE);
+        assert_eq!(mark.depth_write_enable, vk::TRUE);
+        assert_eq!(mark.front.compare_op, vk::CompareOp::EQUAL);
+        assert_eq!(mark.back.pass_op, vk::StencilOp::INCREMENT_AND_CLAMP);
+        assert_eq!(
+            StencilMode::Inside.apply(opaque).front.pass_op,
+            vk::StencilOp::KEEP
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! The pipeline cache, kept on disk between runs so pipelines the driver has compiled before come back quickly.
+//!
+//! There's a file per GPU, named after its vendor and device ids. The driver's blob starts with a header saying what
+//! made it, which is checked before it's handed over: a cache from another GPU or driver version is thrown away and
+//! started over rather than trusted to the driver, which some don't check properly themselves. It's written back
+//! by [`PipelineCache::save`] on a clean shutdown, to a temporary file first so a crash halfway through doesn't
+//! leave a broken one behind.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ash::{prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// Bytes in `VkPipelineCacheHeaderVersionOne`.
+const HEADER_SIZE: usize = 32;
+
+/// Why a cache blob can't be used on this device, if it can't.
+fn check_header(
+    data: &[u8],
+    properties: &vk::PhysicalDeviceProperties,
+) -> Result<(), &'static str> {
+    if data.len() < HEADER_SIZE {
+        return Err("too short");
+    }
+    // Always little endian, unlike everything else Vulkan hands over.
+    let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    if (word(0) as usize) < HEADER_SIZE || word(0) as usize > data.len() {
+        return Err("bad header size");
+    }
+    if word(4) != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err("unknown header version");
+    }
+    if word(8) != properties.vendor_id || word(12) != properties.device_id {
+        return Err("made on another GPU");
+    }
+    if data[16..32] != properties.pipeline_cache_uuid {
+        return Err("made by another driver version");
+    }
+    return Ok(());
+}
+
+/// The file for the GPU with `properties`, in `dir`.
+pub fn cache_path(dir: &Path, properties: &vk::PhysicalDeviceProperties) -> PathBuf {
+    dir.join(format!(
+        "pipelines-{:04x}-{:04x}.bin",
+        properties.vendor_id, properties.device_id
+    ))
+}
+
+pub struct PipelineCache {
+    cache: vk::PipelineCache,
+    /// Where it's saved, if anywhere.
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// Load the cache for `physical_device` from `dir`, or start an empty one if there isn't a usable one there.
+    /// Without a `dir` it's never saved.
+    ///
+    /// # Safety
+    /// `device` must have been made from `physical_device`.
+    pub unsafe fn load(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        dir: Option<&Path>,
+    ) -> VkResult<PipelineCache> {
+        // SAFETY: The physical device is the instance's.
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let path = dir.map(|d| cache_path(d, &properties));
+        let data = match path.as_deref().map(fs::read) {
+            Some(Ok(data)) => match check_header(&data, &properties) {
+                Ok(()) => data,
+                Err(why) => {
+                    log::info!("Not using the pipeline cache, it's {why}");
+                    Vec::new()
+                }
+            },
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                log::warn!("Couldn't read the pipeline cache: {e}");
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+
+        let info = vk::PipelineCacheCreateInfo::default().initial_data(&data);
+        // SAFETY: The data's header was checked against the device.
+        let cache = match unsafe { device.create_pipeline_cache(&info, allocs()) } {
+            Ok(cache) => cache,
+            Err(e) if !data.is_empty() => {
+                log::warn!("The driver turned down the pipeline cache ({e}), starting over");
+                let info = vk::PipelineCacheCreateInfo::default();
+                // SAFETY: Empty, nothing to go wrong.
+                unsafe { device.create_pipeline_cache(&info, allocs())? }
+            }
+            Err(e) => return Err(e),
+        };
+        if !data.is_empty() {
+            log::info!("Loaded {} KiB of pipeline cache", data.len() / 1024);
+        }
+        return Ok(PipelineCache { cache, path });
+    }
+
+    /// For pipeline creation to go through.
+    pub fn raw(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Write it to disk, if it has somewhere to go.
+    ///
+    /// # Safety
+    /// `device` must be the one it was made with.
+    pub unsafe fn save(&self, device: &ash::Device) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // SAFETY: Passed on to the caller.
+        let data =
+            unsafe { device.get_pipeline_cache_data(self.cache) }.map_err(io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, &data)?;
+        fs::rename(&temp, path)?;
+        log::info!("Saved {} KiB of pipeline cache", data.len() / 1024);
+        return Ok(());
+    }
+
+    /// # Safety
+    /// `device` must be the one it was made with, and nothing can be creating pipelines with it.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        // SAFETY: Passed on to the caller.
+        unsafe { device.destroy_pipeline_cache(self.cache, allocs()) };
+        self.cache = vk::PipelineCache::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use ash::vk;
+
+    use super::{PipelineCache, cache_path, check_header};
+    use crate::test_support::headless;
+
+    #[test]
+    pub fn checks_headers() {
+        let properties = vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2684,
+            pipeline_cache_uuid: [7; 16],
+            ..Default::default()
+        };
+        let mut blob = Vec::new();
+        for word in [32u32, 1, 0x10de, 0x2684] {
+            blob.extend_from_slice(&word.to_le_bytes());
+        }
+        blob.extend_from_slice(&[7; 16]);
+        blob.extend_from_slice(b"driver data");
+        assert_eq!(check_header(&blob, &properties), Ok(()));
+        assert_eq!(check_header(&blob[..20], &properties), Err("too short"));
+
+        let mut other = blob.clone();
+        other[16] = 8;
+        assert_eq!(
+            check_header(&other, &properties),
+            Err("made by another driver version")
+        );
+        other[12] = 0;
+        assert_eq!(
+            check_header(&other, &properties),
+            Err("made on another GPU")
+        );
+        assert_eq!(
+            cache_path(Path::new("cache"), &properties),
+            Path::new("cache/pipelines-10de-2684.bin")
+        );
+
+        // Round trip through a real driver, where there is one.
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let (raw, instance) = (device.raw(), device.instance());
+        let dir =
+            std::env::temp_dir().join(format!("crowbar-pipeline-cache-{}", std::process::id()));
+        // SAFETY: The device is the physical device's, and idle.
+        unsafe {
+            let mut cache =
+                PipelineCache::load(raw, instance, device.physical_device(), Some(&dir)).unwrap();
+            cache.save(raw).unwrap();
+            cache.destroy(raw);
+            let mut again =
+                PipelineCache::load(raw, instance, device.physical_device(), Some(&dir)).unwrap();
+            assert!(again.path().unwrap().exists());
+            again.destroy(raw);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
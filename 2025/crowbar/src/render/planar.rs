@@ -0,0 +1,302 @@
+//! Planar reflections, for mirrors and water: the scene drawn again from the camera mirrored about the surface,
+//! into a target of the reflector's own, which the surface then reads at wherever it is on screen.
+//!
+//! [`PlanarView`] is the mirrored camera. Its near plane is swapped for the surface's, see
+//! [`math::oblique_near_plane`], so nothing behind the mirror ends up in it, moved
+//! [`ExtractedReflector::clip_offset`] back so what touches the surface doesn't leave a gap. Mirroring turns
+//! triangles round, so the pass draws with [`PlanarView::FRONT_FACE`].
+//!
+//! [`PlanarReflections`] keeps a target for each reflector, sized to the view, and culls the scene from its view
+//! through the last camera, like outlines are drawn through. The renderer gives each a pass in the frame's graph
+//! before the scene's, in which [`super::mesh::MeshPass`] draws it, and then draws the surface in the scene's pass,
+//! its shader calling `planar_reflection` from [`GLSL`].
+
+use ash::vk;
+use glam::{Affine3A, Mat4};
+use hecs::Entity;
+
+use super::{
+    deletion::{DeletionQueue, Retired},
+    draw::{DrawList, PipelineId},
+    extract::{ExtractedReflector, ExtractedScene},
+    hal::{Device, TextureDesc, TextureFormat, TextureUsage},
+};
+use crate::math::{self, Plane, bounds::Frustum};
+
+pub const GLSL: &str = include_str!("planar/planar.glsl");
+/// The surface's fragment shader's source, to compile at runtime.
+pub const SURFACE_SHADER: &str = include_str!("planar/surface.frag");
+
+pub const COLOR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// A camera mirrored about a reflector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlanarView {
+    /// Left handed, being mirrored.
+    pub world: Affine3A,
+    pub view: Mat4,
+    /// The camera's, clipped to the surface.
+    pub projection: Mat4,
+    pub view_proj: Mat4,
+}
+
+impl PlanarView {
+    /// What pipelines drawing the reflection have as their front face, the other way round from usual.
+    pub const FRONT_FACE: vk::FrontFace = vk::FrontFace::CLOCKWISE;
+
+    /// `reflector` as seen by a camera at `camera` with `projection`. `None` from behind the surface, or too close
+    /// to it to clip, where there's nothing to see.
+    pub fn new(
+        camera: &Affine3A,
+        projection: Mat4,
+        reflector: &ExtractedReflector,
+    ) -> Option<PlanarView> {
+        let plane = reflector.plane;
+        if plane.distance(camera.translation.into()) <= reflector.clip_offset.max(0.0) {
+            return None;
+        }
+        let world = plane.reflection() * *camera;
+        let view = math::view_matrix(&world);
+        let clip = Plane {
+            d: plane.d + reflector.clip_offset,
+            ..plane
+        };
+        let projection = math::oblique_near_plane(projection, &clip.transformed(&world.inverse()));
+        return Some(PlanarView {
+            world,
+            view,
+            projection,
+            view_proj: projection * view,
+        });
+    }
+
+    /// For culling what's drawn into the reflection. The near plane is the surface.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&self.view_proj)
+    }
+}
+
+/// A reflector's targets, and what's drawn into them this frame.
+pub struct PlanarTarget<D: Device> {
+    pub entity: Entity,
+    size: [u32; 2],
+    pub color: D::Texture,
+    pub depth: D::Texture,
+    /// Where the surface is: a unit square on this transform's XZ plane, around its origin.
+    pub surface: Affine3A,
+    /// The view as of the last [`PlanarReflections::cull`], `None` with nothing to see.
+    pub view: Option<PlanarView>,
+    /// What's seen from `view`, empty without one.
+    pub draws: DrawList,
+}
+
+/// A colour and depth target for each reflector, remade when the view or the reflector's resolution changes size.
+pub struct PlanarReflections<D: Device> {
+    targets: Vec<PlanarTarget<D>>,
+}
+
+impl<D: Device> Default for PlanarReflections<D> {
+    fn default() -> Self {
+        PlanarReflections {
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl<D: Device> PlanarReflections<D> {
+    /// Bring the targets in line with `reflectors` for a view `size` big. The ones no longer needed are retired as
+    /// of `frame`.
+    pub fn update(
+        &mut self,
+        device: &D,
+        reflectors: &[ExtractedReflector],
+        size: [u32; 2],
+        frame: u64,
+        deletions: &mut DeletionQueue<Retired<D>>,
+    ) -> Result<(), D::Error> {
+        let wanted = |r: &ExtractedReflector| {
+            size.map(|s| ((s as f32 * r.resolution_scale).round() as u32).max(1))
+        };
+        let (keep, retire) = std::mem::take(&mut self.targets)
+            .into_iter()
+            .partition::<Vec<_>, _>(|t| {
+                reflectors
+                    .iter()
+                    .any(|r| r.entity == t.entity && wanted(r) == t.size)
+            });
+        self.targets = keep;
+        for target in retire {
+            deletions.retire(frame, Retired::Texture(target.color));
+            deletions.retire(frame, Retired::Texture(target.depth));
+        }
+
+        for reflector in reflectors {
+            if self.targets.iter().any(|t| t.entity == reflector.entity) {
+                continue;
+            }
+            let size = wanted(reflector);
+            let color = device.create_texture(&target(size, COLOR_FORMAT))?;
+            let depth = match device.create_texture(&target(size, DEPTH_FORMAT)) {
+                Ok(depth) => depth,
+                Err(e) => {
+                    deletions.retire(frame, Retired::Texture(color));
+                    return Err(e);
+                }
+            };
+            self.targets.push(PlanarTarget {
+                entity: reflector.entity,
+                size,
+                color,
+                depth,
+                surface: reflector.world,
+                view: None,
+                draws: DrawList::default(),
+            });
+        }
+        return Ok(());
+    }
+
+    /// Work out each reflector's view through `scene`'s last camera, and cull what it sees into its draws, with
+    /// `pipeline` like [`DrawList::cull`].
+    pub fn cull(&mut self, scene: &ExtractedScene, pipeline: PipelineId) {
+        let camera = scene.cameras.last();
+        for target in &mut self.targets {
+            target.draws.clear();
+            target.view = None;
+            let Some(reflector) = scene.reflectors.iter().find(|r| r.entity == target.entity)
+            else {
+                continue;
+            };
+            target.surface = reflector.world;
+            let Some(camera) = camera else {
+                continue;
+            };
+            let aspect = target.size[0] as f32 / target.size[1] as f32;
+            let projection = camera.projection.matrix(aspect);
+            target.view = PlanarView::new(&camera.world, projection, reflector);
+            if let Some(view) = &target.view {
+                target
+                    .draws
+                    .cull_from(scene, &view.world, &view.view_proj, pipeline);
+            }
+        }
+    }
+
+    /// What each target's colour and depth are made as, in [`PlanarReflections::targets`]' order, for a render
+    /// graph to import them with.
+    pub fn descs(&self) -> Vec<[TextureDesc; 2]> {
+        self.targets
+            .iter()
+            .map(|t| [COLOR_FORMAT, DEPTH_FORMAT].map(|format| target(t.size, format)))
+            .collect()
+    }
+
+    pub fn targets(&self) -> &[PlanarTarget<D>] {
+        &self.targets
+    }
+
+    /// The reflection of `entity`'s reflector for its surface to sample, once its pass has drawn it.
+    pub fn texture(&self, entity: Entity) -> Option<&D::Texture> {
+        let target = self.targets.iter().find(|t| t.entity == entity)?;
+        return Some(&target.color);
+    }
+
+    /// Retire everything, for shutting down.
+    pub fn retire_all(&mut self, frame: u64, deletions: &mut DeletionQueue<Retired<D>>) {
+        for target in self.targets.drain(..) {
+            deletions.retire(frame, Retired::Texture(target.color));
+            deletions.retire(frame, Retired::Texture(target.depth));
+        }
+    }
+}
+
+fn target(size: [u32; 2], format: TextureFormat) -> TextureDesc {
+    // Only the colour's read after.
+    let sampled = match format.is_depth() {
+        true => TextureUsage::default(),
+        false => TextureUsage::SAMPLED,
+    };
+    TextureDesc {
+        width: size[0],
+        height: size[1],
+        format,
+        usage: TextureUsage::RENDER_TARGET | sampled,
+        samples: 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec3};
+    use hecs::World;
+
+    use super::{PlanarReflections, PlanarView};
+    use crate::{
+        math::{NDC_FAR, NDC_NEAR, Plane, Transform, perspective},
+        render::{deletion::DeletionQueue, extract::ExtractedReflector},
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn mirrors_and_clips() {
+        let mut world = World::new();
+        let reflector = ExtractedReflector {
+            entity: world.spawn(()),
+            world: Affine3A::IDENTITY,
+            plane: Plane::from_point_normal(Vec3::ZERO, Vec3::Y),
+            resolution_scale: 0.5,
+            clip_offset: 0.1,
+        };
+        let camera =
+            Transform::looking_at(Vec3::new(0.0, 2.0, 5.0), Vec3::ZERO, Vec3::Y).to_affine();
+        let projection = perspective(1.0, 1.5, 0.1, 100.0);
+        let view = PlanarView::new(&camera, projection, &reflector).unwrap();
+        assert!(Vec3::from(view.world.translation).abs_diff_eq(Vec3::new(0.0, -2.0, 5.0), 1e-5));
+
+        // Above the water shows, below it is cut off, bar the offset.
+        let depth = |p: Vec3| view.view_proj.project_point3(p).z;
+        assert!((NDC_FAR..NDC_NEAR).contains(&depth(Vec3::new(0.0, 1.0, 0.0))));
+        assert!((NDC_FAR..NDC_NEAR).contains(&depth(Vec3::new(0.0, -0.05, 0.0))));
+        assert!(depth(Vec3::new(0.0, -0.5, 0.0)) > NDC_NEAR);
+        assert!(view.frustum().contains_point(Vec3::new(0.0, 1.0, -2.0)));
+        // Something above the water lands where its reflection does on screen.
+        let above = Vec3::new(1.0, 1.0, -1.0);
+        let below = Vec3::new(1.0, -1.0, -1.0);
+        let on_screen = (projection * crate::math::view_matrix(&camera)).project_point3(below);
+        assert!(
+            view.view_proj
+                .project_point3(above)
+                .truncate()
+                .abs_diff_eq(on_screen.truncate(), 1e-4)
+        );
+
+        let under = Affine3A::from_translation(Vec3::new(0.0, -1.0, 5.0));
+        assert_eq!(PlanarView::new(&under, projection, &reflector), None);
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let mut deletions = DeletionQueue::new(2);
+        let mut reflections = PlanarReflections::default();
+        let reflectors = [reflector];
+        reflections
+            .update(device, &reflectors, [64, 32], 0, &mut deletions)
+            .unwrap();
+        assert!(reflections.texture(reflectors[0].entity).is_some());
+        assert_eq!(reflections.descs()[0][0].width, 32);
+
+        // A resize remakes it, and it goes once the reflector does.
+        reflections
+            .update(device, &reflectors, [128, 64], 1, &mut deletions)
+            .unwrap();
+        assert_eq!(deletions.len(), 2);
+        reflections
+            .update(device, &[], [128, 64], 2, &mut deletions)
+            .unwrap();
+        assert!(reflections.texture(reflectors[0].entity).is_none());
+        assert_eq!(deletions.len(), 4);
+        deletions.flush(device);
+    }
+}
@@ -0,0 +1,25 @@
+// Planar reflection lookups, see planar.rs. Define PLANAR_SET, PLANAR_BINDING and PLANAR_SAMPLER_BINDING before
+// including it to put the reflection and its sampler somewhere other than set 0, bindings 0 and 1.
+
+#ifndef PLANAR_SET
+#define PLANAR_SET 0
+#endif
+#ifndef PLANAR_BINDING
+#define PLANAR_BINDING 0
+#endif
+#ifndef PLANAR_SAMPLER_BINDING
+#define PLANAR_SAMPLER_BINDING 1
+#endif
+
+// The reflector's target, read with linear filtering and clamped to the edges.
+layout(set = PLANAR_SET, binding = PLANAR_BINDING) uniform texture2D planar_reflection_map;
+layout(set = PLANAR_SET, binding = PLANAR_SAMPLER_BINDING) uniform sampler planar_reflection_sampler;
+
+// The reflection on the surface at `clip_position`, in the camera's clip space, moved by `distortion` in UV for
+// water's ripples to bend it. The reflection is drawn with the camera's projection, so it lines up on screen.
+vec3 planar_reflection(vec4 clip_position, vec2 distortion) {
+    vec2 ndc = clip_position.xy / clip_position.w;
+    // Clip space Y is up, the target's rows go down.
+    vec2 uv = vec2(ndc.x, -ndc.y) * 0.5 + 0.5;
+    return texture(sampler2D(planar_reflection_map, planar_reflection_sampler), uv + distortion).rgb;
+}
@@ -0,0 +1,18 @@
+#version 450
+// A planar reflector's surface, drawn with mesh.vert over the cube's +Y face, showing the reflection tinted by its
+// colour. Keep in step with MeshPass in mesh.rs.
+
+#include "../planar/planar.glsl"
+#include "../motion_blur/velocity.glsl"
+
+layout(location = 2) flat in vec4 color;
+layout(location = 3) in vec4 clip;
+layout(location = 4) in vec4 previous_clip;
+
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec2 out_velocity;
+
+void main() {
+    out_color = vec4(color.rgb * planar_reflection(clip, vec2(0.0)), color.a);
+    out_velocity = velocity_output(clip, previous_clip);
+}
@@ -0,0 +1,502 @@
+//! Portals, mirrors and X-ray views: regions of the view, marked out in the stencil, showing the scene from another
+//! view of their own.
+//!
+//! Each [`ExtractedPortal`] carries the transform that moves a camera looking into it to where it looks out from,
+//! and the plane that view is clipped to, so nothing between it and the surface gets in the way, see
+//! [`math::oblique_near_plane`]. [`sub_views`] walks out from the camera through every portal it can see, and the
+//! portals those views can see, down to each portal's [`ExtractedPortal::max_depth`] or [`MAX_VIEWS`] in all. A
+//! view's stencil value is how deep it is, so 0 is the camera's own.
+//!
+//! [`steps`] puts the drawing in order, depth first, a view's portals before its own scene. A portal's surface is
+//! drawn with [`StencilMode::Mark`] where its parent's value is, raising it to the view's. Depth is cleared there, the
+//! view drawn and its own portals in turn with [`StencilMode::Inside`], then the surface is drawn again with
+//! [`StencilMode::Unmark`], lowering the stencil back and writing the surface's depth, for the parent's scene to
+//! hide it behind whatever's in front.
+//!
+//! [`PortalViews`] works the views out through the scene's last camera each frame, and culls what each sees, like
+//! [`super::planar`]'s reflections. [`super::mesh::MeshPass`] records the steps in the scene's pass, in place of
+//! the last camera's own draw, as long as the scene's depth has a stencil, see [`TextureFormat::has_stencil`].
+//! Only meshes are drawn through portals.
+//!
+//! [`TextureFormat::has_stencil`]: super::hal::TextureFormat::has_stencil
+
+use std::f32::consts::PI;
+
+use ash::vk;
+use glam::{Affine3A, Mat4, Vec2, Vec3, Vec4Swizzles};
+use hecs::Entity;
+
+use super::{
+    draw::{DrawList, PipelineId},
+    extract::{ExtractedPortal, ExtractedScene},
+    pipeline::StencilMode,
+};
+use crate::{
+    ecs::components::PortalKind,
+    math::{self, Plane, bounds::Frustum},
+};
+
+/// Views through portals there can be in a frame, not counting the camera's.
+pub const MAX_VIEWS: usize = 16;
+
+/// Where a camera looking into a `kind` portal at `world` looks out from, as a transform to put the camera through,
+/// and the world space plane its view is clipped to. `exit` is a window's other end. `None` for a window without
+/// one.
+pub fn through(
+    kind: PortalKind,
+    world: &Affine3A,
+    exit: Option<&Affine3A>,
+) -> Option<(Affine3A, Plane)> {
+    let surface = Plane::from_point_normal(Vec3::ZERO, math::UP);
+    return match kind {
+        PortalKind::Window => {
+            let exit = exit?;
+            // Turned round, so stepping into the front of the surface comes out of the exit's front.
+            let turn = Affine3A::from_rotation_z(PI);
+            Some((*exit * turn * world.inverse(), surface.transformed(exit)))
+        }
+        PortalKind::Mirror => {
+            let plane = surface.transformed(world);
+            Some((plane.reflection(), plane))
+        }
+        PortalKind::XRay => {
+            let behind = Plane::from_point_normal(Vec3::ZERO, -math::UP);
+            Some((Affine3A::IDENTITY, behind.transformed(world)))
+        }
+    };
+}
+
+/// `portal`'s surface, as a transform taking a unit square in XZ onto it.
+pub fn surface(portal: &ExtractedPortal) -> Affine3A {
+    portal.world * Affine3A::from_scale(Vec3::new(portal.size.x, 1.0, portal.size.y))
+}
+
+/// The corners of `portal`'s surface, in world space, going round.
+pub fn corners(portal: &ExtractedPortal) -> [Vec3; 4] {
+    let half = portal.size * 0.5;
+    return [
+        Vec2::new(-half.x, -half.y),
+        Vec2::new(half.x, -half.y),
+        Vec2::new(half.x, half.y),
+        Vec2::new(-half.x, half.y),
+    ]
+    .map(|c| portal.world.transform_point3(Vec3::new(c.x, 0.0, c.y)));
+}
+
+/// The camera's view, or one through a portal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubView {
+    /// What it's seen through, `None` for the camera's own.
+    pub portal: Option<Entity>,
+    /// Where the view it's seen from is in the list, `None` for the camera's own.
+    pub parent: Option<usize>,
+    /// How many portals deep it is, and its value in the stencil.
+    pub depth: u32,
+    pub world: Affine3A,
+    pub view: Mat4,
+    /// The camera's, clipped to the portal's plane.
+    pub projection: Mat4,
+    pub view_proj: Mat4,
+    /// Seen through an odd number of mirrors, which turns triangles round.
+    pub mirrored: bool,
+    /// The corners of the screen it covers at most, in NDC, for scissoring.
+    pub bounds: [Vec2; 2],
+}
+
+impl SubView {
+    fn new(world: Affine3A, projection: Mat4) -> SubView {
+        let view = math::view_matrix(&world);
+        return SubView {
+            portal: None,
+            parent: None,
+            depth: 0,
+            world,
+            view,
+            projection,
+            view_proj: projection * view,
+            mirrored: false,
+            bounds: [Vec2::NEG_ONE, Vec2::ONE],
+        };
+    }
+
+    /// For culling what's drawn into it. For views through portals, the near plane is the portal.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&self.view_proj)
+    }
+
+    /// What pipelines drawing it have as their front face.
+    pub fn front_face(&self) -> vk::FrontFace {
+        match self.mirrored {
+            true => vk::FrontFace::CLOCKWISE,
+            false => vk::FrontFace::COUNTER_CLOCKWISE,
+        }
+    }
+
+    /// Where `portal` covers in this view, in NDC, within its own bounds. `None` if none of it shows.
+    fn region(&self, portal: &ExtractedPortal) -> Option<[Vec2; 2]> {
+        let corners = corners(portal);
+        // Behind the camera, or the portal this view's seen through.
+        let near = self.frustum().planes[4];
+        if corners.iter().all(|&c| near.distance(c) < 0.0) {
+            return None;
+        }
+        let clip = corners.map(|c| self.view_proj * c.extend(1.0));
+        // Part of it's behind the camera, so it could reach anywhere.
+        let [min, max] = if clip.iter().any(|c| c.w <= 1e-6) {
+            [Vec2::NEG_ONE, Vec2::ONE]
+        } else {
+            let ndc = clip.map(|c| c.xy() / c.w);
+            let min = ndc.iter().fold(Vec2::INFINITY, |a, &b| a.min(b));
+            let max = ndc.iter().fold(Vec2::NEG_INFINITY, |a, &b| a.max(b));
+            [min, max]
+        };
+        let min = min.max(self.bounds[0]);
+        let max = max.min(self.bounds[1]);
+        return (min.cmplt(max).all()).then_some([min, max]);
+    }
+}
+
+/// The camera's view at `camera` with `projection`, then every view through `portals` it leads to, depth first.
+pub fn sub_views(camera: &Affine3A, projection: Mat4, portals: &[ExtractedPortal]) -> Vec<SubView> {
+    let mut views = vec![SubView::new(*camera, projection)];
+    walk(&mut views, 0, projection, portals);
+    return views;
+}
+
+fn walk(views: &mut Vec<SubView>, parent: usize, projection: Mat4, portals: &[ExtractedPortal]) {
+    for portal in portals {
+        let from = views[parent];
+        if views.len() > MAX_VIEWS || from.depth >= portal.max_depth.min(u8::MAX as u32) {
+            continue;
+        }
+        // Seen from behind.
+        let surface = Plane::from_point_normal(Vec3::ZERO, math::UP).transformed(&portal.world);
+        if surface.distance(from.world.translation.into()) <= 0.0 {
+            continue;
+        }
+        let Some(bounds) = from.region(portal) else {
+            continue;
+        };
+
+        let world = portal.transform * from.world;
+        let view = math::view_matrix(&world);
+        let clip = portal.clip.transformed(&world.inverse());
+        let clipped = math::oblique_near_plane(projection, &clip);
+        views.push(SubView {
+            portal: Some(portal.entity),
+            parent: Some(parent),
+            depth: from.depth + 1,
+            world,
+            view,
+            projection: clipped,
+            view_proj: clipped * view,
+            mirrored: from.mirrored != portal.mirrors,
+            bounds,
+        });
+        walk(views, views.len() - 1, projection, portals);
+    }
+}
+
+/// One step of drawing the views, see the module docs. Each is about a view in the list [`sub_views`] gives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortalStep {
+    /// Draw the view's portal into its parent, raising the stencil to the view's depth where it shows.
+    Mark(usize),
+    /// Clear depth where the view shows, drawing at the far plane.
+    ClearDepth(usize),
+    /// Draw the view's scene where it shows.
+    Draw(usize),
+    /// Draw the view's portal again, lowering the stencil to its parent's and writing the surface's depth.
+    Unmark(usize),
+}
+
+impl PortalStep {
+    pub fn view(&self) -> usize {
+        match *self {
+            PortalStep::Mark(v)
+            | PortalStep::ClearDepth(v)
+            | PortalStep::Draw(v)
+            | PortalStep::Unmark(v) => v,
+        }
+    }
+
+    /// How the step tests and writes the stencil, and what to compare it to.
+    pub fn stencil(&self, views: &[SubView]) -> (StencilMode, u32) {
+        let view = &views[self.view()];
+        return match self {
+            PortalStep::Mark(_) => (StencilMode::Mark, view.depth - 1),
+            PortalStep::ClearDepth(_) | PortalStep::Draw(_) => (StencilMode::Inside, view.depth),
+            PortalStep::Unmark(_) => (StencilMode::Unmark, view.depth),
+        };
+    }
+}
+
+/// The order to draw `views` in, with the camera's own last.
+pub fn steps(views: &[SubView]) -> Vec<PortalStep> {
+    let mut steps = Vec::with_capacity(views.len() * 4);
+    if !views.is_empty() {
+        push_steps(views, 0, &mut steps);
+    }
+    return steps;
+}
+
+fn push_steps(views: &[SubView], view: usize, steps: &mut Vec<PortalStep>) {
+    let children = (view + 1..views.len()).filter(|&v| views[v].parent == Some(view));
+    for child in children {
+        steps.push(PortalStep::Mark(child));
+        steps.push(PortalStep::ClearDepth(child));
+        push_steps(views, child, steps);
+        steps.push(PortalStep::Unmark(child));
+    }
+    steps.push(PortalStep::Draw(view));
+}
+
+/// The views through the scene's portals from its last camera, and what each of them sees. Keep it around and
+/// [`PortalViews::cull`] each frame, to reuse the draw lists.
+#[derive(Default)]
+pub struct PortalViews {
+    views: Vec<SubView>,
+    /// What each view sees, the camera's own left empty as it has its own.
+    draws: Vec<DrawList>,
+    /// Each view's portal's surface, see [`surface`], the camera's own being the identity.
+    surfaces: Vec<Affine3A>,
+}
+
+impl PortalViews {
+    /// Work out the views through `scene`'s last camera, drawn to a target of `aspect`, and cull what each sees
+    /// into its draws, with `pipeline` like [`DrawList::cull`]. No views at all without portals.
+    pub fn cull(&mut self, scene: &ExtractedScene, aspect: f32, pipeline: PipelineId) {
+        self.views.clear();
+        self.surfaces.clear();
+        let Some(camera) = scene.cameras.last().filter(|_| !scene.portals.is_empty()) else {
+            return;
+        };
+        let projection = camera.projection.matrix(aspect);
+        self.views = sub_views(&camera.world, projection, &scene.portals);
+        self.draws
+            .resize_with(self.views.len().max(self.draws.len()), DrawList::default);
+        for (view, draws) in self.views.iter().zip(&mut self.draws) {
+            let portal = view
+                .portal
+                .and_then(|e| scene.portals.iter().find(|p| p.entity == e));
+            match portal {
+                Some(portal) => {
+                    draws.cull_from(scene, &view.world, &view.view_proj, pipeline);
+                    self.surfaces.push(surface(portal));
+                }
+                None => {
+                    draws.clear();
+                    self.surfaces.push(Affine3A::IDENTITY);
+                }
+            }
+        }
+    }
+
+    /// The camera's own view first, then the ones through portals, or nothing without any portals.
+    pub fn views(&self) -> &[SubView] {
+        &self.views
+    }
+
+    /// What `view` sees, see [`PortalViews::views`].
+    pub fn draws(&self, view: usize) -> &DrawList {
+        &self.draws[view]
+    }
+
+    /// Where `view`'s portal's surface is, see [`surface`].
+    pub fn surface(&self, view: usize) -> Affine3A {
+        self.surfaces[view]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+    use glam::{Affine3A, Vec2, Vec3};
+    use hecs::World;
+
+    use super::{MAX_VIEWS, PortalStep, PortalViews, steps, sub_views, through};
+    use crate::{
+        ecs::components::{
+            Camera, GlobalTransform, MaterialId, MeshId, MeshRenderer, Portal, PortalKind,
+            Projection,
+        },
+        math::{NDC_FAR, NDC_NEAR, Transform, perspective},
+        render::{
+            draw::PipelineId,
+            extract::{ExtractedPortal, ExtractedScene},
+            pipeline::StencilMode,
+        },
+    };
+
+    #[test]
+    pub fn walks_through_portals() {
+        let mut world = World::new();
+        let mut portal = |kind, at: Affine3A, exit: Option<&Affine3A>, max_depth| {
+            let (transform, clip) = through(kind, &at, exit).unwrap();
+            ExtractedPortal {
+                entity: world.spawn(()),
+                world: at,
+                size: Vec2::splat(2.0),
+                max_depth,
+                transform,
+                clip,
+                mirrors: kind == PortalKind::Mirror,
+            }
+        };
+        let facing = |at: Vec3, normal: Vec3| {
+            Transform::looking_at(at, at + normal.any_orthonormal_vector(), normal).to_affine()
+        };
+        let projection = perspective(1.2, 1.0, 0.1, 100.0);
+        let camera = Transform::looking_at(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y).to_affine();
+
+        // Two mirrors facing each other, either side of the camera, reflect back and forth as deep as allowed.
+        let mirrors = [
+            portal(
+                PortalKind::Mirror,
+                facing(Vec3::NEG_Z * 5.0, Vec3::Z),
+                None,
+                3,
+            ),
+            portal(
+                PortalKind::Mirror,
+                facing(Vec3::Z * 5.0, Vec3::NEG_Z),
+                None,
+                3,
+            ),
+        ];
+        let views = sub_views(&camera, projection, &mirrors);
+        let seen: Vec<_> = views.iter().map(|v| (v.portal, v.depth)).collect();
+        let [a, b] = [mirrors[0].entity, mirrors[1].entity];
+        assert_eq!(seen, [(None, 0), (Some(a), 1), (Some(b), 2), (Some(a), 3)]);
+        assert!(!views[0].mirrored && views[1].mirrored && !views[2].mirrored);
+        assert!(Vec3::from(views[1].world.translation).abs_diff_eq(Vec3::NEG_Z * 10.0, 1e-4));
+        // The mirror clips off what's behind it.
+        let depth = |p: Vec3| views[1].view_proj.project_point3(p).z;
+        assert!((NDC_FAR..NDC_NEAR).contains(&depth(Vec3::new(0.0, 0.0, -2.0))));
+        assert!(depth(Vec3::new(0.0, 0.0, -7.0)) > NDC_NEAR);
+
+        // Depth first, each view's portals before its scene, and the stencil going up and back down.
+        let order = steps(&views);
+        use PortalStep::*;
+        assert_eq!(
+            order,
+            [
+                Mark(1),
+                ClearDepth(1),
+                Mark(2),
+                ClearDepth(2),
+                Mark(3),
+                ClearDepth(3),
+                Draw(3),
+                Unmark(3),
+                Draw(2),
+                Unmark(2),
+                Draw(1),
+                Unmark(1),
+                Draw(0)
+            ]
+        );
+        assert_eq!(Mark(2).stencil(&views), (StencilMode::Mark, 1));
+        assert_eq!(Draw(2).stencil(&views), (StencilMode::Inside, 2));
+        assert_eq!(Unmark(2).stencil(&views), (StencilMode::Unmark, 2));
+        assert_eq!(Draw(0).stencil(&views), (StencilMode::Inside, 0));
+        assert_eq!(views[1].front_face(), vk::FrontFace::CLOCKWISE);
+
+        // A window looks out of its exit's front, clipped to it, and too many mirrors stop at the limit.
+        let exit = facing(Vec3::new(20.0, 0.0, 0.0), Vec3::X);
+        let window = portal(PortalKind::Window, mirrors[0].world, Some(&exit), 1);
+        let views = sub_views(&camera, projection, &[window]);
+        assert_eq!(views.len(), 2);
+        let out = Vec3::from(views[1].world.translation);
+        assert!(out.abs_diff_eq(Vec3::new(15.0, 0.0, 0.0), 1e-4));
+        let looking = views[1].world.transform_vector3(Vec3::NEG_Z);
+        assert!(looking.abs_diff_eq(Vec3::X, 1e-4));
+        assert!(!views[1].mirrored);
+        let depth = |p: Vec3| views[1].view_proj.project_point3(p).z;
+        assert!(depth(Vec3::new(19.0, 0.0, 0.0)) > NDC_NEAR);
+        assert!((NDC_FAR..NDC_NEAR).contains(&depth(Vec3::new(25.0, 0.0, 0.0))));
+
+        // An X-ray view sees past the surface, and nothing before it.
+        let wall = portal(PortalKind::XRay, mirrors[0].world, None, 1);
+        let views = sub_views(&camera, projection, &[wall]);
+        assert_eq!(views[1].world, camera);
+        let depth = |p: Vec3| views[1].view_proj.project_point3(p).z;
+        assert!(depth(Vec3::new(0.0, 0.0, -4.0)) > NDC_NEAR);
+        assert!((NDC_FAR..NDC_NEAR).contains(&depth(Vec3::new(0.0, 0.0, -6.0))));
+
+        // Turned away, or off screen, there's nothing to see through.
+        let behind = portal(PortalKind::Mirror, facing(Vec3::Z * 5.0, Vec3::Z), None, 3);
+        let aside = portal(PortalKind::Mirror, facing(Vec3::X * 50.0, Vec3::Z), None, 3);
+        assert_eq!(sub_views(&camera, projection, &[behind, aside]).len(), 1);
+
+        // Close enough together that the reflections don't fall past the far plane first.
+        let deep = portal(
+            PortalKind::Mirror,
+            facing(Vec3::NEG_Z, Vec3::Z),
+            None,
+            u32::MAX,
+        );
+        let back = portal(
+            PortalKind::Mirror,
+            facing(Vec3::Z, Vec3::NEG_Z),
+            None,
+            u32::MAX,
+        );
+        assert_eq!(
+            sub_views(&camera, projection, &[deep, back]).len(),
+            MAX_VIEWS + 1
+        );
+    }
+
+    #[test]
+    pub fn culls_through_the_last_camera() {
+        let mut world = World::new();
+        world.spawn((
+            Camera {
+                projection: Projection::Perspective {
+                    fov_y: 1.0,
+                    near: 0.1,
+                    far: 100.0,
+                },
+                order: 0,
+                active: true,
+                lens: None,
+            },
+            GlobalTransform(Affine3A::IDENTITY),
+        ));
+        let at = Vec3::NEG_Z * 5.0;
+        let facing = Transform::looking_at(at, at + Vec3::X, Vec3::Z).to_affine();
+        world.spawn((Portal::mirror(Vec2::splat(2.0)), GlobalTransform(facing)));
+        // Behind the camera, and behind the mirror.
+        for z in [5.0, -7.0] {
+            let mesh = MeshRenderer {
+                mesh: MeshId(0),
+                material: MaterialId(0),
+                visible: true,
+                cast_shadows: false,
+            };
+            world.spawn((
+                mesh,
+                GlobalTransform(Affine3A::from_translation(Vec3::Z * z)),
+            ));
+        }
+        let mut scene = ExtractedScene::default();
+        scene.extract(&world);
+
+        let mut portals = PortalViews::default();
+        portals.cull(&scene, 1.0, PipelineId(0));
+        assert_eq!(portals.views().len(), 2);
+        // The camera has its own draws, and the mirror only sees what's in front of it.
+        assert_eq!(portals.draws(0).stats().submitted, 0);
+        assert_eq!(portals.draws(1).stats().submitted, 1);
+        assert_eq!(portals.draws(1).instances()[0].translation.z, 5.0);
+        let edge = portals
+            .surface(1)
+            .transform_point3(Vec3::new(0.5, 0.0, 0.5));
+        assert!((edge - at).length() > 1.4);
+
+        scene.portals.clear();
+        portals.cull(&scene, 1.0, PipelineId(0));
+        assert!(portals.views().is_empty());
+    }
+}
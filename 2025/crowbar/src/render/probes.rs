@@ -0,0 +1,347 @@
+//! Irradiance probes: a grid of second order spherical harmonics baked offline, so indirect diffuse lighting varies
+//! across a level rather than being one flat ambient term.
+//!
+//! [`ProbeGrid::bake`] gathers the light arriving at each probe from a radiance function the caller provides, which
+//! is where the scene's ray tracing goes, and [`ProbeGrid::save`] stores the result next to the level. At runtime the
+//! shading pass uploads [`ProbeGrid::gpu_data`] to a storage buffer and calls `probe_diffuse` from [`GLSL`], which
+//! blends the eight probes around a point the same way [`ProbeGrid::diffuse`] does on the CPU. Put the grid on an
+//! entity as [`IrradianceProbes`] for [`MeshPass`] to light the scene with it.
+//!
+//! [`IrradianceProbes`]: crate::ecs::components::IrradianceProbes
+//! [`MeshPass`]: super::mesh::MeshPass
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use glam::{UVec3, Vec3};
+use rayon::prelude::*;
+
+use crate::{jobs::JobSystem, math::bounds::Aabb};
+
+pub const GLSL: &str = include_str!("probes/probes.glsl");
+
+pub const MAGIC: [u8; 4] = *b"CBPR";
+pub const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 4 + 4 + 3 * 4 + 3 * 4 + 3 * 4;
+
+/// Each band's share of the cosine lobe, over pi, which turns radiance into what a white Lambertian surface reflects.
+const BANDS: [f32; 3] = [1.0, 2.0 / 3.0, 0.25];
+
+fn basis(dir: Vec3) -> [f32; 9] {
+    let Vec3 { x, y, z } = dir;
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+/// Light arriving at a point from every direction, as second order spherical harmonics in linear RGB.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShProbe {
+    pub coeffs: [Vec3; 9],
+}
+
+impl ShProbe {
+    /// Add `radiance` arriving from `dir`, which is a unit vector, standing for `weight` steradians of the sphere.
+    pub fn add_sample(&mut self, dir: Vec3, radiance: Vec3, weight: f32) {
+        for (c, b) in self.coeffs.iter_mut().zip(basis(dir)) {
+            *c += radiance * (b * weight);
+        }
+    }
+
+    /// What a white Lambertian surface facing `normal` reflects, to multiply by its albedo.
+    pub fn diffuse(&self, normal: Vec3) -> Vec3 {
+        let sum = self
+            .coeffs
+            .iter()
+            .zip(basis(normal))
+            .enumerate()
+            .map(|(i, (c, b))| *c * (b * BANDS[band(i)]))
+            .sum::<Vec3>();
+        return sum.max(Vec3::ZERO);
+    }
+
+    pub fn lerp(&self, other: &ShProbe, t: f32) -> ShProbe {
+        ShProbe {
+            coeffs: std::array::from_fn(|i| self.coeffs[i].lerp(other.coeffs[i], t)),
+        }
+    }
+}
+
+fn band(coeff: usize) -> usize {
+    match coeff {
+        0 => 0,
+        1..4 => 1,
+        _ => 2,
+    }
+}
+
+/// `n` directions spread evenly over the sphere, on a Fibonacci spiral.
+fn sphere_directions(n: u32) -> impl Iterator<Item = Vec3> {
+    let golden = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..n).map(move |i| {
+        let y = 1.0 - (i as f32 + 0.5) / n as f32 * 2.0;
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let phi = golden * i as f32;
+        Vec3::new(r * phi.cos(), y, r * phi.sin())
+    })
+}
+
+/// Probes evenly spaced through a box, corners included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeGrid {
+    origin: Vec3,
+    spacing: Vec3,
+    counts: UVec3,
+    /// X fastest, then y, then z.
+    probes: Vec<ShProbe>,
+}
+
+impl ProbeGrid {
+    /// A dark grid of `counts` probes over `bounds`. Axes with one probe put it in the middle.
+    pub fn new(bounds: Aabb, counts: UVec3) -> ProbeGrid {
+        let counts = counts.max(UVec3::ONE);
+        let size = bounds.max - bounds.min;
+        let steps = (counts - UVec3::ONE).as_vec3();
+        let spacing = Vec3::select(
+            steps.cmpgt(Vec3::ZERO),
+            size / steps.max(Vec3::ONE),
+            Vec3::ONE,
+        );
+        let origin = Vec3::select(steps.cmpgt(Vec3::ZERO), bounds.min, bounds.center());
+        return ProbeGrid {
+            origin,
+            spacing,
+            counts,
+            probes: vec![ShProbe::default(); counts.element_product() as usize],
+        };
+    }
+
+    /// Bake a grid of `counts` probes over `bounds`, taking `samples` directions per probe across the job system.
+    /// `radiance(origin, dir)` is the light arriving at `origin` from `dir`: the sky, or whatever a ray cast that way
+    /// hits, lit.
+    pub fn bake(
+        bounds: Aabb,
+        counts: UVec3,
+        samples: u32,
+        jobs: &JobSystem,
+        radiance: impl Fn(Vec3, Vec3) -> Vec3 + Sync,
+    ) -> ProbeGrid {
+        let mut grid = ProbeGrid::new(bounds, counts);
+        let weight = 4.0 * std::f32::consts::PI / samples.max(1) as f32;
+        let (origin, spacing, counts) = (grid.origin, grid.spacing, grid.counts);
+        jobs.install(|| {
+            grid.probes
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, probe)| {
+                    let at = origin + spacing * index_position(i as u32, counts).as_vec3();
+                    for dir in sphere_directions(samples.max(1)) {
+                        probe.add_sample(dir, radiance(at, dir), weight);
+                    }
+                });
+        });
+        return grid;
+    }
+
+    pub fn counts(&self) -> UVec3 {
+        self.counts
+    }
+
+    /// Where probe `(x, y, z)` is.
+    pub fn position(&self, probe: UVec3) -> Vec3 {
+        self.origin + self.spacing * probe.as_vec3()
+    }
+
+    pub fn probe(&self, probe: UVec3) -> &ShProbe {
+        &self.probes[self.index(probe)]
+    }
+
+    pub fn probe_mut(&mut self, probe: UVec3) -> &mut ShProbe {
+        let index = self.index(probe);
+        &mut self.probes[index]
+    }
+
+    fn index(&self, probe: UVec3) -> usize {
+        let p = probe.min(self.counts - UVec3::ONE);
+        (p.x + self.counts.x * (p.y + self.counts.y * p.z)) as usize
+    }
+
+    /// The probes around `position` blended together, clamped to the grid's edges.
+    pub fn sample(&self, position: Vec3) -> ShProbe {
+        let last = (self.counts - UVec3::ONE).as_vec3();
+        let cell = ((position - self.origin) / self.spacing).clamp(Vec3::ZERO, last);
+        let base = cell
+            .as_uvec3()
+            .min(self.counts.max(UVec3::splat(2)) - UVec3::splat(2));
+        let t = cell - base.as_vec3();
+
+        let at = |x, y, z| self.probe(base + UVec3::new(x, y, z));
+        let along_x = |y, z| at(0, y, z).lerp(at(1, y, z), t.x);
+        let along_y = |z| along_x(0, z).lerp(&along_x(1, z), t.y);
+        return along_y(0).lerp(&along_y(1), t.z);
+    }
+
+    /// What a white Lambertian surface at `position` facing `normal` reflects. What `probe_diffuse` in [`GLSL`] gives.
+    pub fn diffuse(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        self.sample(position).diffuse(normal)
+    }
+
+    /// The grid as the storage buffer [`GLSL`] reads: origin, reciprocal spacing and counts as 16 byte vectors, then
+    /// nine coefficients per probe, each padded to 16 bytes.
+    pub fn gpu_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(48 + self.probes.len() * 9 * 16);
+        let mut vec4 = |v: [f32; 4]| v.iter().for_each(|f| out.extend(f.to_ne_bytes()));
+        vec4(self.origin.extend(0.0).to_array());
+        vec4(self.spacing.recip().extend(0.0).to_array());
+        for c in self.counts.extend(0).to_array() {
+            out.extend(c.to_ne_bytes());
+        }
+        for probe in &self.probes {
+            for c in probe.coeffs {
+                for f in c.extend(0.0).to_array() {
+                    out.extend(f.to_ne_bytes());
+                }
+            }
+        }
+        return out;
+    }
+
+    /// The baked grid in the file format [`ProbeGrid::from_bytes`] reads, little endian: magic, version, origin,
+    /// spacing, counts, then each probe's coefficients.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + self.probes.len() * 27 * 4);
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        for f in self
+            .origin
+            .to_array()
+            .into_iter()
+            .chain(self.spacing.to_array())
+        {
+            out.extend(f.to_le_bytes());
+        }
+        for c in self.counts.to_array() {
+            out.extend(c.to_le_bytes());
+        }
+        for f in self
+            .probes
+            .iter()
+            .flat_map(|p| p.coeffs)
+            .flat_map(|c| c.to_array())
+        {
+            out.extend(f.to_le_bytes());
+        }
+        return out;
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<ProbeGrid> {
+        let invalid = |msg| io::Error::new(ErrorKind::InvalidData, msg);
+        if data.len() < HEADER_SIZE || data[0..4] != MAGIC {
+            return Err(invalid("not a probe grid"));
+        }
+        let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        if word(4) != VERSION {
+            return Err(invalid("unsupported probe grid version"));
+        }
+        let float = |at: usize| f32::from_bits(word(at));
+        let vec3 = |at: usize| Vec3::new(float(at), float(at + 4), float(at + 8));
+        let counts = UVec3::new(word(32), word(36), word(40));
+        let probes = counts.as_u64vec3().element_product();
+        if counts.min_element() == 0 || (data.len() - HEADER_SIZE) as u64 != probes * 27 * 4 {
+            return Err(invalid("probe grid size doesn't match its counts"));
+        }
+        let probes = data[HEADER_SIZE..]
+            .chunks_exact(27 * 4)
+            .map(|p| ShProbe {
+                coeffs: std::array::from_fn(|i| {
+                    let f = |j: usize| {
+                        f32::from_le_bytes(p[(i * 3 + j) * 4..][..4].try_into().unwrap())
+                    };
+                    Vec3::new(f(0), f(1), f(2))
+                }),
+            })
+            .collect();
+        return Ok(ProbeGrid {
+            origin: vec3(8),
+            spacing: vec3(20),
+            counts,
+            probes,
+        });
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> io::Result<ProbeGrid> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+fn index_position(index: u32, counts: UVec3) -> UVec3 {
+    UVec3::new(
+        index % counts.x,
+        index / counts.x % counts.y,
+        index / (counts.x * counts.y),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{UVec3, Vec3};
+
+    use super::ProbeGrid;
+    use crate::{jobs::JobSystem, math::bounds::Aabb};
+
+    #[test]
+    pub fn bakes_and_interpolates() {
+        let jobs = JobSystem::new(Some(2));
+        let bounds = Aabb::new(Vec3::ZERO, Vec3::new(10.0, 2.0, 2.0));
+        // A white sky above, and a floor that gets brighter along x.
+        let grid = ProbeGrid::bake(bounds, UVec3::new(3, 2, 2), 256, &jobs, |at, dir| {
+            match dir.y > 0.0 {
+                true => Vec3::ONE,
+                false => Vec3::splat(at.x / 10.0),
+            }
+        });
+
+        assert_eq!(grid.position(UVec3::new(2, 1, 1)), bounds.max);
+        // Facing the sky sees mostly sky, and facing down mostly floor.
+        let up = grid.diffuse(Vec3::new(0.0, 1.0, 1.0), Vec3::Y);
+        assert!((up.x - 0.95).abs() < 0.1, "{up}");
+        let down_dark = grid.diffuse(Vec3::new(0.0, 1.0, 1.0), Vec3::NEG_Y);
+        let down_lit = grid.diffuse(Vec3::new(10.0, 1.0, 1.0), Vec3::NEG_Y);
+        assert!(
+            down_dark.x < 0.1 && down_lit.x > 0.85,
+            "{down_dark} {down_lit}"
+        );
+        // Halfway between probes is halfway between what they see.
+        let middle = grid.diffuse(Vec3::new(2.5, 1.0, 1.0), Vec3::NEG_Y);
+        let halves = (grid.diffuse(Vec3::new(0.0, 1.0, 1.0), Vec3::NEG_Y)
+            + grid.diffuse(Vec3::new(5.0, 1.0, 1.0), Vec3::NEG_Y))
+            / 2.0;
+        assert!((middle - halves).abs().max_element() < 1e-4);
+        // Outside the grid clamps to its edge.
+        assert_eq!(
+            grid.diffuse(Vec3::new(-5.0, 1.0, 1.0), Vec3::Y),
+            grid.diffuse(Vec3::new(0.0, 1.0, 1.0), Vec3::Y)
+        );
+
+        let loaded = ProbeGrid::from_bytes(&grid.to_bytes()).unwrap();
+        assert_eq!(loaded, grid);
+        assert!(ProbeGrid::from_bytes(&grid.to_bytes()[..60]).is_err());
+        assert_eq!(grid.gpu_data().len(), 48 + 12 * 9 * 16);
+    }
+}
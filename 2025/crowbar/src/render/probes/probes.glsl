@@ -0,0 +1,64 @@
+// Irradiance probe grid lookups, see probes.rs. Define PROBES_SET and PROBES_BINDING before including it to put the
+// grid somewhere other than set 0, binding 0. Keep in step with ProbeGrid::gpu_data and ProbeGrid::diffuse.
+
+#ifndef PROBES_SET
+#define PROBES_SET 0
+#endif
+#ifndef PROBES_BINDING
+#define PROBES_BINDING 0
+#endif
+
+layout(std430, set = PROBES_SET, binding = PROBES_BINDING) readonly buffer ProbeGrid {
+    vec4 probe_origin;
+    // The reciprocal of the spacing, in xyz.
+    vec4 probe_inv_spacing;
+    uvec4 probe_counts;
+    // Nine RGB coefficients per probe, x fastest, then y, then z.
+    vec4 probe_coeffs[];
+};
+
+// The cosine lobe convolved into each band, with the 1/pi for a Lambertian BRDF folded in.
+const float PROBE_BAND0 = 1.0;
+const float PROBE_BAND1 = 2.0 / 3.0;
+const float PROBE_BAND2 = 0.25;
+
+vec3 probe_coeff(uvec3 probe, uint i) {
+    uint index = probe.x + probe_counts.x * (probe.y + probe_counts.y * probe.z);
+    return probe_coeffs[index * 9 + i].rgb;
+}
+
+// Diffuse lighting at `position` for a surface facing `normal`, to multiply by albedo. Blends the eight probes
+// around it, clamped to the grid's edges.
+vec3 probe_diffuse(vec3 position, vec3 normal) {
+    vec3 cell = clamp((position - probe_origin.xyz) * probe_inv_spacing.xyz, vec3(0.0), vec3(probe_counts.xyz - 1u));
+    // The last cell's far corner is the last probe, so cells stop one short.
+    uvec3 base = min(uvec3(cell), max(probe_counts.xyz, uvec3(2u)) - uvec3(2u));
+    vec3 t = cell - vec3(base);
+
+    float x = normal.x, y = normal.y, z = normal.z;
+    float basis[9] = float[9](
+        0.282095 * PROBE_BAND0,
+        0.488603 * y * PROBE_BAND1,
+        0.488603 * z * PROBE_BAND1,
+        0.488603 * x * PROBE_BAND1,
+        1.092548 * x * y * PROBE_BAND2,
+        1.092548 * y * z * PROBE_BAND2,
+        0.315392 * (3.0 * z * z - 1.0) * PROBE_BAND2,
+        1.092548 * x * z * PROBE_BAND2,
+        0.546274 * (x * x - y * y) * PROBE_BAND2
+    );
+
+    vec3 result = vec3(0.0);
+    for (uint corner = 0u; corner < 8u; corner++) {
+        uvec3 offset = uvec3(corner & 1u, (corner >> 1) & 1u, corner >> 2);
+        uvec3 probe = min(base + offset, probe_counts.xyz - 1u);
+        vec3 w3 = mix(vec3(1.0) - t, t, vec3(offset));
+        float weight = w3.x * w3.y * w3.z;
+        vec3 sum = vec3(0.0);
+        for (uint i = 0u; i < 9u; i++) {
+            sum += probe_coeff(probe, i) * basis[i];
+        }
+        result += sum * weight;
+    }
+    return max(result, vec3(0.0));
+}
@@ -0,0 +1,727 @@
+//! Graphics quality presets, and the render targets that depend on them.
+//!
+//! Each setting is its own cvar, `r_quality` sets them all at once from a preset. Setting one by hand afterwards
+//! makes the preset `custom`. Everything can change while running: [`QualityTargets`] notices what changed and
+//! replaces only those targets, retiring the old ones through the deletion queue so frames still in flight can
+//! finish with them. The renderer draws the sun's shadows into the shadow map, the scene into the scene targets at
+//! `r_render_scale` with `r_msaa` samples, and ambient occlusion and bloom from it at half that, blurs the scene
+//! by depth for a camera with a lens and along the motion vectors drawn with it, then tonemaps the lot up to the
+//! window.
+
+use std::fmt;
+
+use super::{
+    deletion::{DeletionQueue, Retired},
+    hal::{Device, TextureDesc, TextureFormat, TextureUsage, caps::DeviceCaps},
+};
+use crate::cvar::{CVars, EngineCVars};
+
+/// What `r_quality` is when the settings don't match a preset.
+pub const CUSTOM: &str = "custom";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl QualityPreset {
+    pub const ALL: [QualityPreset; 4] = [
+        QualityPreset::Low,
+        QualityPreset::Medium,
+        QualityPreset::High,
+        QualityPreset::Ultra,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QualityPreset::Low => "low",
+            QualityPreset::Medium => "medium",
+            QualityPreset::High => "high",
+            QualityPreset::Ultra => "ultra",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<QualityPreset> {
+        QualityPreset::ALL
+            .into_iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s.trim()))
+    }
+
+    pub fn settings(&self) -> QualitySettings {
+        let (shadow_quality, msaa, render_scale, ssao, post_quality, effects) = match self {
+            QualityPreset::Low => (1, 1, 0.75f32, false, 0, false),
+            QualityPreset::Medium => (2, 2, 1.0f32, false, 1, false),
+            QualityPreset::High => (2, 4, 1.0f32, true, 2, true),
+            QualityPreset::Ultra => (3, 8, 1.0f32, true, 2, true),
+        };
+        return QualitySettings {
+            shadow_quality,
+            msaa,
+            render_scale,
+            ssao,
+            post_quality,
+            depth_of_field: effects,
+            motion_blur: effects,
+        };
+    }
+}
+
+/// Everything a preset sets, each backed by a cvar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualitySettings {
+    /// `r_shadow_quality`, 0 for no shadows. See [`QualitySettings::shadow_resolution`].
+    pub shadow_quality: i64,
+    /// `r_msaa`, samples per pixel, a power of two.
+    pub msaa: u32,
+    /// `r_render_scale`, scene resolution relative to the window.
+    pub render_scale: f32,
+    /// `r_ssao`.
+    pub ssao: bool,
+    /// `r_post_quality`: 0 for tonemapping only, 1 adds colour grading, 2 adds bloom.
+    pub post_quality: i64,
+    /// `r_dof`, see [`super::depth_of_field`].
+    pub depth_of_field: bool,
+    /// `r_motion_blur`, see [`super::motion_blur`].
+    pub motion_blur: bool,
+}
+
+impl QualitySettings {
+    pub fn from_cvars(cvars: &CVars, engine: EngineCVars) -> QualitySettings {
+        let msaa = cvars.get(engine.r_msaa).max(1) as u32;
+        return QualitySettings {
+            shadow_quality: cvars.get(engine.r_shadow_quality),
+            // Rounded down, 3 samples isn't a thing.
+            msaa: 1 << (31 - msaa.leading_zeros()),
+            render_scale: cvars.get(engine.r_render_scale),
+            ssao: cvars.get(engine.r_ssao),
+            post_quality: cvars.get(engine.r_post_quality),
+            depth_of_field: cvars.get(engine.r_dof),
+            motion_blur: cvars.get(engine.r_motion_blur),
+        };
+    }
+
+    /// Set the cvars to these settings.
+    pub fn apply(&self, cvars: &mut CVars, engine: EngineCVars) {
+        cvars.set(engine.r_shadow_quality, self.shadow_quality);
+        cvars.set(engine.r_msaa, self.msaa as i64);
+        cvars.set(engine.r_render_scale, self.render_scale);
+        cvars.set(engine.r_ssao, self.ssao);
+        cvars.set(engine.r_post_quality, self.post_quality);
+        cvars.set(engine.r_dof, self.depth_of_field);
+        cvars.set(engine.r_motion_blur, self.motion_blur);
+    }
+
+    /// Shadow map width and height in texels, 0 without shadows.
+    pub fn shadow_resolution(&self) -> u32 {
+        match self.shadow_quality {
+            ..=0 => 0,
+            1 => 1024,
+            2 => 2048,
+            _ => 4096,
+        }
+    }
+
+    pub fn bloom(&self) -> bool {
+        self.post_quality >= 2
+    }
+
+    /// Whether the scene writes motion vectors, which only motion blur reads for now.
+    pub fn velocity(&self) -> bool {
+        self.motion_blur
+    }
+
+    /// Lowered to what `caps` can do, as presets are made with desktop GPUs in mind.
+    pub fn clamped(mut self, caps: &DeviceCaps) -> QualitySettings {
+        while self.shadow_resolution() > caps.max_texture_2d {
+            self.shadow_quality -= 1;
+        }
+        self.msaa = caps.target_samples(self.msaa);
+        return self;
+    }
+
+    /// The scene's size for a `size` window.
+    pub fn scene_size(&self, size: [u32; 2]) -> [u32; 2] {
+        return size.map(|s| ((s as f32 * self.render_scale).round() as u32).max(1));
+    }
+
+    /// What changed since `old`, so the right things get remade.
+    pub fn changes(&self, old: &QualitySettings) -> QualityChange {
+        QualityChange {
+            shadows: self.shadow_resolution() != old.shadow_resolution(),
+            scene: self.msaa != old.msaa || self.render_scale != old.render_scale,
+            ssao: self.ssao != old.ssao,
+            post: self.post_quality != old.post_quality
+                || self.depth_of_field != old.depth_of_field
+                || self.motion_blur != old.motion_blur,
+        }
+    }
+}
+
+/// Which groups of resources a settings change affects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QualityChange {
+    pub shadows: bool,
+    /// Scene colour and depth, which everything after depends on the size of.
+    pub scene: bool,
+    pub ssao: bool,
+    pub post: bool,
+}
+
+impl QualityChange {
+    pub const ALL: QualityChange = QualityChange {
+        shadows: true,
+        scene: true,
+        ssao: true,
+        post: true,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        *self == QualityChange::default()
+    }
+}
+
+impl fmt::Display for QualityChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (self.shadows, "shadows"),
+            (self.scene, "scene"),
+            (self.ssao, "ssao"),
+            (self.post, "post"),
+        ];
+        let changed: Vec<_> = names.iter().filter(|(c, _)| *c).map(|(_, n)| *n).collect();
+        return write!(f, "{}", changed.join(", "));
+    }
+}
+
+/// Keeps `r_quality` and the cvars it sets in step.
+pub struct QualityTracker {
+    preset: String,
+    settings: QualitySettings,
+}
+
+impl QualityTracker {
+    pub fn new(cvars: &CVars, engine: EngineCVars) -> QualityTracker {
+        QualityTracker {
+            preset: cvars.get(engine.r_quality),
+            settings: QualitySettings::from_cvars(cvars, engine),
+        }
+    }
+
+    /// The settings as of the last poll.
+    pub fn settings(&self) -> QualitySettings {
+        self.settings
+    }
+
+    /// Apply a newly picked preset, and pick up any settings changed by hand since the last poll. What changed, if
+    /// anything did.
+    pub fn poll(&mut self, cvars: &mut CVars, engine: EngineCVars) -> Option<QualityChange> {
+        let preset = cvars.get(engine.r_quality);
+        if preset != self.preset {
+            match QualityPreset::parse(&preset) {
+                Some(p) => p.settings().apply(cvars, engine),
+                None if preset == CUSTOM => {}
+                None => log::warn!(
+                    "No quality preset {preset:?}, there's {}",
+                    QualityPreset::ALL.map(|p| p.name()).join(", ")
+                ),
+            }
+            self.preset = preset;
+        }
+
+        let settings = QualitySettings::from_cvars(cvars, engine);
+        let change = settings.changes(&self.settings);
+        self.settings = settings;
+        if QualityPreset::parse(&self.preset).is_some_and(|p| p.settings() != settings) {
+            self.preset = CUSTOM.to_string();
+            cvars.set(engine.r_quality, self.preset.clone());
+        }
+        if change.is_empty() {
+            return None;
+        }
+        return Some(change);
+    }
+}
+
+/// The render targets quality settings decide the size or existence of.
+///
+/// With `r_msaa` over 1 the scene is drawn multisampled, and resolved into [`QualityTargets::scene_resolve`] and
+/// [`QualityTargets::scene_depth_resolve`] for everything after to read. Motion vectors are drawn and resolved
+/// alongside it when something needs them.
+pub struct QualityTargets<D: Device> {
+    /// What the targets were made with, `None` before they were.
+    made_with: Option<(QualitySettings, [u32; 2])>,
+    shadow_map: Option<D::Texture>,
+    scene_color: Option<D::Texture>,
+    scene_depth: Option<D::Texture>,
+    /// Only there when the scene is multisampled, like `scene_depth_resolve`.
+    scene_resolve: Option<D::Texture>,
+    scene_depth_resolve: Option<D::Texture>,
+    scene_velocity: Option<D::Texture>,
+    /// Only there when the scene is multisampled.
+    velocity_resolve: Option<D::Texture>,
+    /// Half resolution.
+    ssao: Option<D::Texture>,
+    /// Half resolution, the top of the bloom chain.
+    bloom: Option<D::Texture>,
+    /// Half resolution, the scene with its circle of confusion, then blurred apart into near and far layers.
+    dof_split: Option<D::Texture>,
+    dof_near: Option<D::Texture>,
+    dof_far: Option<D::Texture>,
+    /// The layers put back over the scene.
+    depth_of_field: Option<D::Texture>,
+    motion_blur: Option<D::Texture>,
+}
+
+impl<D: Device> Default for QualityTargets<D> {
+    fn default() -> Self {
+        QualityTargets {
+            made_with: None,
+            shadow_map: None,
+            scene_color: None,
+            scene_depth: None,
+            scene_resolve: None,
+            scene_depth_resolve: None,
+            scene_velocity: None,
+            velocity_resolve: None,
+            ssao: None,
+            bloom: None,
+            dof_split: None,
+            dof_near: None,
+            dof_far: None,
+            depth_of_field: None,
+            motion_blur: None,
+        }
+    }
+}
+
+fn target(width: u32, height: u32, format: TextureFormat) -> TextureDesc {
+    TextureDesc {
+        width: width.max(1),
+        height: height.max(1),
+        format,
+        usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED | TextureUsage::COPY_SRC,
+        samples: 1,
+    }
+}
+
+/// A target drawn with `samples` per pixel. Multisampled ones are only ever drawn to and resolved, and copied from
+/// to be resolved for a capture.
+fn scene_target(width: u32, height: u32, format: TextureFormat, samples: u32) -> TextureDesc {
+    if samples <= 1 {
+        return target(width, height, format);
+    }
+    return TextureDesc {
+        usage: TextureUsage::RENDER_TARGET | TextureUsage::COPY_SRC,
+        samples,
+        ..target(width, height, format)
+    };
+}
+
+/// What the targets are made as, for a render graph to import them with. What isn't there is `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SceneDescs {
+    pub shadow_map: Option<TextureDesc>,
+    pub color: TextureDesc,
+    pub depth: TextureDesc,
+    /// Only there when the scene is multisampled, like `depth_resolve`.
+    pub resolve: Option<TextureDesc>,
+    pub depth_resolve: Option<TextureDesc>,
+    /// Motion vectors, when something reads them. Multisampled like `color`, with `velocity_resolve` to resolve to.
+    pub velocity: Option<TextureDesc>,
+    pub velocity_resolve: Option<TextureDesc>,
+    pub ssao: Option<TextureDesc>,
+    pub bloom: Option<TextureDesc>,
+    /// Each of the split and the near and far layers, with `depth_of_field` the layers go back over the scene into.
+    pub dof_layer: Option<TextureDesc>,
+    pub depth_of_field: Option<TextureDesc>,
+    pub motion_blur: Option<TextureDesc>,
+}
+
+impl SceneDescs {
+    pub fn new(settings: &QualitySettings, size: [u32; 2]) -> SceneDescs {
+        let [width, height] = settings.scene_size(size);
+        let (half_width, half_height) = (width / 2, height / 2);
+        let samples = settings.msaa;
+        let res = settings.shadow_resolution();
+        let velocity = settings.velocity();
+        SceneDescs {
+            shadow_map: (res > 0).then(|| target(res, res, TextureFormat::Depth32Float)),
+            color: scene_target(width, height, TextureFormat::Rgba16Float, samples),
+            // With a stencil, which portals mark their views out in.
+            depth: scene_target(width, height, TextureFormat::Depth32FloatStencil8, samples),
+            resolve: (samples > 1).then(|| target(width, height, TextureFormat::Rgba16Float)),
+            depth_resolve: (samples > 1)
+                .then(|| target(width, height, TextureFormat::Depth32FloatStencil8)),
+            velocity: velocity
+                .then(|| scene_target(width, height, TextureFormat::Rg16Float, samples)),
+            velocity_resolve: (velocity && samples > 1)
+                .then(|| target(width, height, TextureFormat::Rg16Float)),
+            ssao: settings
+                .ssao
+                .then(|| target(half_width, half_height, TextureFormat::Rgba8Unorm)),
+            bloom: settings
+                .bloom()
+                .then(|| target(half_width, half_height, TextureFormat::Rgba16Float)),
+            dof_layer: settings
+                .depth_of_field
+                .then(|| target(half_width, half_height, TextureFormat::Rgba16Float)),
+            depth_of_field: settings
+                .depth_of_field
+                .then(|| target(width, height, TextureFormat::Rgba16Float)),
+            motion_blur: settings
+                .motion_blur
+                .then(|| target(width, height, TextureFormat::Rgba16Float)),
+        }
+    }
+}
+
+impl<D: Device> QualityTargets<D> {
+    /// Bring the targets in line with `settings` for a `size` window, remaking whatever changed. The ones replaced
+    /// are retired as of `frame`.
+    pub fn update(
+        &mut self,
+        device: &D,
+        settings: &QualitySettings,
+        size: [u32; 2],
+        frame: u64,
+        deletions: &mut DeletionQueue<Retired<D>>,
+    ) -> Result<QualityChange, D::Error> {
+        let mut change = match &self.made_with {
+            Some((old, _)) => settings.changes(old),
+            None => QualityChange::ALL,
+        };
+        if self.made_with.is_some_and(|(_, old_size)| old_size != size) {
+            // Everything but the shadow map goes by the window's size.
+            change.scene = true;
+            change.ssao = true;
+            change.post = true;
+        }
+        if change.is_empty() {
+            return Ok(change);
+        }
+
+        let mut replace =
+            |slot: &mut Option<D::Texture>, desc: Option<TextureDesc>| -> Result<(), D::Error> {
+                if let Some(old) = slot.take() {
+                    deletions.retire(frame, Retired::Texture(old));
+                }
+                if let Some(desc) = desc {
+                    *slot = Some(device.create_texture(&desc)?);
+                }
+                return Ok(());
+            };
+        let descs = SceneDescs::new(settings, size);
+        if change.shadows {
+            replace(&mut self.shadow_map, descs.shadow_map)?;
+        }
+        if change.scene {
+            replace(&mut self.scene_color, Some(descs.color))?;
+            replace(&mut self.scene_depth, Some(descs.depth))?;
+            replace(&mut self.scene_resolve, descs.resolve)?;
+            replace(&mut self.scene_depth_resolve, descs.depth_resolve)?;
+        }
+        if change.ssao || change.scene {
+            replace(&mut self.ssao, descs.ssao)?;
+        }
+        if change.post || change.scene {
+            replace(&mut self.bloom, descs.bloom)?;
+            replace(&mut self.scene_velocity, descs.velocity)?;
+            replace(&mut self.velocity_resolve, descs.velocity_resolve)?;
+            for slot in [&mut self.dof_split, &mut self.dof_near, &mut self.dof_far] {
+                replace(slot, descs.dof_layer)?;
+            }
+            replace(&mut self.depth_of_field, descs.depth_of_field)?;
+            replace(&mut self.motion_blur, descs.motion_blur)?;
+        }
+        self.made_with = Some((*settings, size));
+        return Ok(change);
+    }
+
+    /// What the targets were made as, `None` before they were.
+    pub fn scene_descs(&self) -> Option<SceneDescs> {
+        let (settings, size) = self.made_with.as_ref()?;
+        return Some(SceneDescs::new(settings, *size));
+    }
+
+    /// The settings the targets were made with, `None` before they were.
+    pub fn settings(&self) -> Option<&QualitySettings> {
+        self.made_with.as_ref().map(|(settings, _)| settings)
+    }
+
+    pub fn shadow_map(&self) -> Option<&D::Texture> {
+        self.shadow_map.as_ref()
+    }
+
+    pub fn scene_color(&self) -> Option<&D::Texture> {
+        self.scene_color.as_ref()
+    }
+
+    pub fn scene_depth(&self) -> Option<&D::Texture> {
+        self.scene_depth.as_ref()
+    }
+
+    /// Where a multisampled scene colour is resolved to, `None` when it isn't multisampled.
+    pub fn scene_resolve(&self) -> Option<&D::Texture> {
+        self.scene_resolve.as_ref()
+    }
+
+    /// Where a multisampled scene depth is resolved to, `None` when it isn't multisampled.
+    pub fn scene_depth_resolve(&self) -> Option<&D::Texture> {
+        self.scene_depth_resolve.as_ref()
+    }
+
+    /// The scene colour for passes after the scene to read: resolved if it's multisampled.
+    pub fn scene_output(&self) -> Option<&D::Texture> {
+        self.scene_resolve.as_ref().or(self.scene_color.as_ref())
+    }
+
+    /// The scene depth for passes after the scene to read: resolved if it's multisampled.
+    pub fn scene_depth_output(&self) -> Option<&D::Texture> {
+        self.scene_depth_resolve
+            .as_ref()
+            .or(self.scene_depth.as_ref())
+    }
+
+    /// Motion vectors as the scene draws them, multisampled like it. `None` when nothing needs them.
+    pub fn scene_velocity(&self) -> Option<&D::Texture> {
+        self.scene_velocity.as_ref()
+    }
+
+    /// Where multisampled motion vectors are resolved to, `None` when they aren't multisampled or there are none.
+    pub fn velocity_resolve(&self) -> Option<&D::Texture> {
+        self.velocity_resolve.as_ref()
+    }
+
+    /// The motion vectors for passes after the scene to read: resolved if they're multisampled.
+    pub fn velocity_output(&self) -> Option<&D::Texture> {
+        self.velocity_resolve
+            .as_ref()
+            .or(self.scene_velocity.as_ref())
+    }
+
+    pub fn ssao(&self) -> Option<&D::Texture> {
+        self.ssao.as_ref()
+    }
+
+    pub fn bloom(&self) -> Option<&D::Texture> {
+        self.bloom.as_ref()
+    }
+
+    /// The depth of field targets: the split, the near and far layers, then what they're put back over the scene
+    /// into. `None` with it off.
+    pub fn depth_of_field(&self) -> Option<[&D::Texture; 4]> {
+        return Some([
+            self.dof_split.as_ref()?,
+            self.dof_near.as_ref()?,
+            self.dof_far.as_ref()?,
+            self.depth_of_field.as_ref()?,
+        ]);
+    }
+
+    /// What the motion blur pass draws to, `None` with it off.
+    pub fn motion_blur(&self) -> Option<&D::Texture> {
+        self.motion_blur.as_ref()
+    }
+
+    /// Retire everything, for shutting down.
+    pub fn retire_all(&mut self, frame: u64, deletions: &mut DeletionQueue<Retired<D>>) {
+        for slot in [
+            &mut self.shadow_map,
+            &mut self.scene_color,
+            &mut self.scene_depth,
+            &mut self.scene_resolve,
+            &mut self.scene_depth_resolve,
+            &mut self.scene_velocity,
+            &mut self.velocity_resolve,
+            &mut self.ssao,
+            &mut self.bloom,
+            &mut self.dof_split,
+            &mut self.dof_near,
+            &mut self.dof_far,
+            &mut self.depth_of_field,
+            &mut self.motion_blur,
+        ] {
+            if let Some(texture) = slot.take() {
+                deletions.retire(frame, Retired::Texture(texture));
+            }
+        }
+        self.made_with = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CUSTOM, QualityChange, QualityPreset, QualitySettings, QualityTargets, QualityTracker,
+    };
+    use crate::{
+        cvar::CVars,
+        render::{deletion::DeletionQueue, hal::Device},
+        test_support::headless,
+    };
+
+    #[test]
+    pub fn presets_through_cvars() {
+        let (mut cvars, engine) = CVars::new();
+        let mut tracker = QualityTracker::new(&cvars, engine);
+        assert_eq!(tracker.poll(&mut cvars, engine), None);
+
+        cvars.set_from_str("r_quality", "low").unwrap();
+        let change = tracker.poll(&mut cvars, engine).unwrap();
+        assert!(change.scene && change.ssao);
+        assert_eq!(tracker.settings(), QualityPreset::Low.settings());
+        assert_eq!(cvars.get(engine.r_render_scale), 0.75);
+
+        // Changing one by hand leaves the preset behind.
+        cvars.set_from_str("r_msaa", "3").unwrap();
+        assert_eq!(
+            tracker.poll(&mut cvars, engine),
+            Some(QualityChange {
+                scene: true,
+                ..QualityChange::default()
+            })
+        );
+        assert_eq!(tracker.settings().msaa, 2);
+        assert_eq!(cvars.get(engine.r_quality), CUSTOM);
+
+        cvars.set_from_str("r_quality", "Ultra").unwrap();
+        assert!(tracker.poll(&mut cvars, engine).unwrap().shadows);
+        assert_eq!(tracker.settings().shadow_resolution(), 4096);
+        assert_eq!(tracker.settings().msaa, 8);
+        cvars.set_from_str("r_quality", "potato").unwrap();
+        assert_eq!(tracker.poll(&mut cvars, engine), None);
+    }
+
+    #[test]
+    pub fn scales_the_scene() {
+        let settings = QualityPreset::Low.settings();
+        assert_eq!(settings.scene_size([1280, 720]), [960, 540]);
+        assert_eq!(settings.scene_size([1, 0]), [1, 1]);
+        assert_eq!(QualityPreset::High.settings().scene_size([7, 5]), [7, 5]);
+    }
+
+    #[test]
+    pub fn recreates_what_changed() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let mut deletions = DeletionQueue::new(2);
+        let mut targets = QualityTargets::default();
+        let settings = QualitySettings {
+            ssao: false,
+            ..QualityPreset::High.settings()
+        }
+        .clamped(device.caps());
+
+        let change = targets
+            .update(device, &settings, [64, 32], 0, &mut deletions)
+            .unwrap();
+        assert_eq!(change, QualityChange::ALL);
+        assert!(targets.shadow_map().is_some() && targets.ssao().is_none());
+        assert!(targets.scene_color().is_some() && targets.scene_depth().is_some());
+        assert!(deletions.is_empty());
+
+        // Nothing changed, nothing's remade.
+        let change = targets
+            .update(device, &settings, [64, 32], 1, &mut deletions)
+            .unwrap();
+        assert!(change.is_empty());
+        assert!(deletions.is_empty());
+
+        // Turning SSAO on makes it without touching anything else.
+        let settings = QualitySettings {
+            ssao: true,
+            ..settings
+        };
+        targets
+            .update(device, &settings, [64, 32], 2, &mut deletions)
+            .unwrap();
+        assert!(targets.ssao().is_some());
+        assert!(deletions.is_empty());
+
+        // A resize replaces everything sized by the window, but not the shadow map.
+        let change = targets
+            .update(device, &settings, [128, 64], 3, &mut deletions)
+            .unwrap();
+        assert!(change.scene && !change.shadows);
+        let resolve = (settings.msaa > 1) as usize;
+        assert_eq!(deletions.len(), 10 + 3 * resolve);
+        assert_eq!(targets.scene_resolve().is_some(), settings.msaa > 1);
+        assert_eq!(targets.velocity_resolve().is_some(), settings.msaa > 1);
+        assert!(targets.velocity_output().is_some() && targets.motion_blur().is_some());
+        assert!(targets.depth_of_field().is_some());
+        assert_eq!(targets.scene_depth_resolve().is_some(), settings.msaa > 1);
+        let descs = targets.scene_descs().unwrap();
+        assert_eq!((descs.color.width, descs.color.height), (128, 64));
+        assert_eq!(descs.ssao.map(|d| (d.width, d.height)), Some((64, 32)));
+
+        // Bloom off drops its target without touching the scene.
+        let settings = QualitySettings {
+            post_quality: 1,
+            ..settings
+        };
+        let change = targets
+            .update(device, &settings, [128, 64], 4, &mut deletions)
+            .unwrap();
+        assert!(change.post && !change.scene);
+        assert!(targets.bloom().is_none() && targets.scene_color().is_some());
+
+        // Motion blur off drops its target and the motion vectors.
+        let settings = QualitySettings {
+            motion_blur: false,
+            ..settings
+        };
+        let change = targets
+            .update(device, &settings, [128, 64], 5, &mut deletions)
+            .unwrap();
+        assert!(change.post && !change.scene);
+        assert!(targets.scene_velocity().is_none() && targets.motion_blur().is_none());
+        assert!(targets.depth_of_field().is_some());
+
+        // As does depth of field.
+        let settings = QualitySettings {
+            depth_of_field: false,
+            ..settings
+        };
+        let change = targets
+            .update(device, &settings, [128, 64], 6, &mut deletions)
+            .unwrap();
+        assert!(change.post && !change.scene);
+        assert!(targets.depth_of_field().is_none());
+
+        // Without MSAA the scene's read straight from its colour and depth.
+        let settings = QualitySettings {
+            msaa: 1,
+            ..settings
+        };
+        targets
+            .update(device, &settings, [128, 64], 7, &mut deletions)
+            .unwrap();
+        assert!(targets.scene_resolve().is_none() && targets.scene_depth_resolve().is_none());
+        assert!(targets.scene_output().is_some() && targets.scene_depth_output().is_some());
+
+        // Shadows off drops the shadow map alone.
+        let settings = QualitySettings {
+            shadow_quality: 0,
+            ..settings
+        };
+        let change = targets
+            .update(device, &settings, [128, 64], 8, &mut deletions)
+            .unwrap();
+        assert_eq!(
+            change,
+            QualityChange {
+                shadows: true,
+                ..QualityChange::default()
+            }
+        );
+        assert!(targets.shadow_map().is_none());
+
+        targets.retire_all(9, &mut deletions);
+        deletions.flush(device);
+    }
+}
@@ -0,0 +1,497 @@
+//! Reflection probes: cubemaps of what a level looks like from a few points, for glossy and mirror reflections
+//! where nothing better is available.
+//!
+//! Each probe captures from its [`ReflectionProbe::position`] and covers a box or sphere around it. Reflections are
+//! looked up with parallax correction: the reflected ray is traced out to the probe's shape, which stands in for the
+//! room it's in, and the cubemap is read in the direction of where it hits from the capture point, so reflections
+//! line up with the walls instead of sliding around as the camera moves. Where probes overlap they blend, smaller
+//! ones first, fading out over each probe's [`ReflectionProbe::blend_distance`] at its edges. Whatever no probe
+//! covers falls back to the sky.
+//!
+//! [`ReflectionProbes::capture_dirty`] captures every probe added or marked since the last capture, so levels capture
+//! theirs once when they load, and anything that changes the scene can ask for a probe again. Capture happens on the
+//! CPU, from a radiance function.
+//!
+//! At runtime a level's probes go on an entity as [`Reflections`], and [`super::mesh::MeshPass`] uploads
+//! [`ReflectionProbes::gpu_data`] and the cubemaps packed into [`ReflectionProbes::atlas`], as hal has no cubemaps.
+//! Its shader calls `reflection_sample` from [`GLSL`], which does what [`ReflectionProbes::sample`] does on the CPU,
+//! and adds what it finds to every surface by how much it reflects at that angle.
+//!
+//! [`Reflections`]: crate::ecs::components::Reflections
+
+use exr::prelude::f16;
+use glam::{Vec2, Vec3};
+use rayon::prelude::*;
+
+use crate::{
+    jobs::JobSystem,
+    math::bounds::{Aabb, Sphere},
+};
+
+pub const GLSL: &str = include_str!("reflections/reflections.glsl");
+
+/// The most probes blended at one point. Keep in step with `REFLECTION_MAX_BLEND` in [`GLSL`].
+pub const MAX_BLEND: usize = 4;
+
+/// Cube faces, in the order Vulkan has a cubemap's layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PosX,
+        CubeFace::NegX,
+        CubeFace::PosY,
+        CubeFace::NegY,
+        CubeFace::PosZ,
+        CubeFace::NegZ,
+    ];
+
+    /// The direction through `uv` on the face, from -1 to 1 across it, with v going down its rows.
+    pub fn direction(self, uv: Vec2) -> Vec3 {
+        let Vec2 { x: u, y: v } = uv;
+        let dir = match self {
+            CubeFace::PosX => Vec3::new(1.0, -v, -u),
+            CubeFace::NegX => Vec3::new(-1.0, -v, u),
+            CubeFace::PosY => Vec3::new(u, 1.0, v),
+            CubeFace::NegY => Vec3::new(u, -1.0, -v),
+            CubeFace::PosZ => Vec3::new(u, -v, 1.0),
+            CubeFace::NegZ => Vec3::new(-u, -v, -1.0),
+        };
+        return dir.normalize();
+    }
+
+    /// The face `dir` goes through, and where on it, undoing [`CubeFace::direction`].
+    pub fn from_direction(dir: Vec3) -> (CubeFace, Vec2) {
+        let a = dir.abs();
+        let (face, u, v, major) = if a.x >= a.y && a.x >= a.z {
+            match dir.x >= 0.0 {
+                true => (CubeFace::PosX, -dir.z, -dir.y, a.x),
+                false => (CubeFace::NegX, dir.z, -dir.y, a.x),
+            }
+        } else if a.y >= a.z {
+            match dir.y >= 0.0 {
+                true => (CubeFace::PosY, dir.x, dir.z, a.y),
+                false => (CubeFace::NegY, dir.x, -dir.z, a.y),
+            }
+        } else {
+            match dir.z >= 0.0 {
+                true => (CubeFace::PosZ, dir.x, -dir.y, a.z),
+                false => (CubeFace::NegZ, -dir.x, -dir.y, a.z),
+            }
+        };
+        return (face, Vec2::new(u, v) / major.max(f32::MIN_POSITIVE));
+    }
+}
+
+/// Six square faces of linear RGB, [`CubeFace::ALL`] order, rows top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cubemap {
+    size: u32,
+    texels: Vec<Vec3>,
+}
+
+impl Cubemap {
+    /// A black cubemap with `size` by `size` faces.
+    pub fn new(size: u32) -> Cubemap {
+        let size = size.max(1);
+        return Cubemap {
+            size,
+            texels: vec![Vec3::ZERO; (6 * size * size) as usize],
+        };
+    }
+
+    /// Fill a `size` cubemap with `radiance(dir)` through each texel's center, across the job system.
+    pub fn capture(size: u32, jobs: &JobSystem, radiance: impl Fn(Vec3) -> Vec3 + Sync) -> Cubemap {
+        let mut cubemap = Cubemap::new(size);
+        let size = cubemap.size;
+        jobs.install(|| {
+            cubemap
+                .texels
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, texel)| {
+                    let i = i as u32;
+                    let face = CubeFace::ALL[(i / (size * size)) as usize];
+                    let (x, y) = (i % size, i / size % size);
+                    let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32 * 2.0 - 1.0;
+                    *texel = radiance(face.direction(uv));
+                });
+        });
+        return cubemap;
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The texel `dir` goes through, unfiltered.
+    pub fn sample(&self, dir: Vec3) -> Vec3 {
+        let (face, uv) = CubeFace::from_direction(dir);
+        let texel = ((uv + 1.0) / 2.0 * self.size as f32)
+            .as_uvec2()
+            .min(glam::UVec2::splat(self.size - 1));
+        return self.texels[((face as u32 * self.size + texel.y) * self.size + texel.x) as usize];
+    }
+}
+
+/// What a probe covers, which also stands in for the shape of the room when correcting for parallax.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProbeShape {
+    Box(Aabb),
+    Sphere(Sphere),
+}
+
+impl ProbeShape {
+    /// How far in from the edge `point` is, negative outside.
+    fn depth(&self, point: Vec3) -> f32 {
+        match self {
+            ProbeShape::Box(aabb) => (point - aabb.min).min(aabb.max - point).min_element(),
+            ProbeShape::Sphere(sphere) => sphere.radius - point.distance(sphere.center),
+        }
+    }
+
+    fn volume(&self) -> f32 {
+        match self {
+            ProbeShape::Box(aabb) => (aabb.max - aabb.min).element_product(),
+            ProbeShape::Sphere(sphere) => 4.0 / 3.0 * std::f32::consts::PI * sphere.radius.powi(3),
+        }
+    }
+
+    /// Where a ray from `origin` inside the shape along `dir` leaves it.
+    fn exit(&self, origin: Vec3, dir: Vec3) -> Option<Vec3> {
+        let t = match self {
+            ProbeShape::Box(aabb) => {
+                let inv = dir.recip();
+                let far = ((aabb.max - origin) * inv).max((aabb.min - origin) * inv);
+                far.min_element()
+            }
+            ProbeShape::Sphere(sphere) => {
+                let oc = origin - sphere.center;
+                let b = oc.dot(dir);
+                let c = oc.length_squared() - sphere.radius * sphere.radius;
+                -b + (b * b - c).max(0.0).sqrt()
+            }
+        };
+        return (t.is_finite() && t >= 0.0).then(|| origin + dir * t);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectionProbe {
+    /// Where the cubemap is captured from, somewhere in [`ReflectionProbe::shape`].
+    pub position: Vec3,
+    pub shape: ProbeShape,
+    /// How far in from the edge of the shape it takes to fade all the way in.
+    pub blend_distance: f32,
+    /// Width and height of each cubemap face.
+    pub resolution: u32,
+    cubemap: Option<Cubemap>,
+}
+
+impl ReflectionProbe {
+    pub fn new(position: Vec3, shape: ProbeShape) -> ReflectionProbe {
+        ReflectionProbe {
+            position,
+            shape,
+            blend_distance: 1.0,
+            resolution: 128,
+            cubemap: None,
+        }
+    }
+
+    pub fn with_blend_distance(mut self, distance: f32) -> Self {
+        self.blend_distance = distance;
+        return self;
+    }
+
+    pub fn with_resolution(mut self, resolution: u32) -> Self {
+        self.resolution = resolution;
+        return self;
+    }
+
+    /// The last capture, if there's been one.
+    pub fn cubemap(&self) -> Option<&Cubemap> {
+        self.cubemap.as_ref()
+    }
+
+    /// How much this probe counts at `point`, from 0 outside to 1 past its blend distance.
+    pub fn weight(&self, point: Vec3) -> f32 {
+        let depth = self.shape.depth(point);
+        if self.blend_distance <= 0.0 {
+            return if depth >= 0.0 { 1.0 } else { 0.0 };
+        }
+        return (depth / self.blend_distance).clamp(0.0, 1.0);
+    }
+
+    /// The direction to look up the cubemap in, for a ray reflected along `dir` at `point`: from the capture point
+    /// to where the ray leaves the shape.
+    pub fn parallax_direction(&self, point: Vec3, dir: Vec3) -> Vec3 {
+        match self.shape.exit(point, dir) {
+            Some(hit) => (hit - self.position).normalize_or(dir),
+            None => dir,
+        }
+    }
+}
+
+/// A level's reflection probes.
+#[derive(Clone, Debug, Default)]
+pub struct ReflectionProbes {
+    /// Smallest first, so they blend in front of the bigger ones around them.
+    probes: Vec<ReflectionProbe>,
+    /// Waiting for a capture, as indices into `probes`.
+    dirty: Vec<usize>,
+}
+
+impl ReflectionProbes {
+    pub fn new() -> ReflectionProbes {
+        ReflectionProbes::default()
+    }
+
+    /// Add a probe, to be captured on the next [`ReflectionProbes::capture_dirty`]. Indices of probes after it in
+    /// blend order shift along one.
+    pub fn add(&mut self, probe: ReflectionProbe) -> usize {
+        let volume = probe.shape.volume();
+        let index = self.probes.partition_point(|p| p.shape.volume() <= volume);
+        for d in &mut self.dirty {
+            *d += (*d >= index) as usize;
+        }
+        self.probes.insert(index, probe);
+        self.dirty.push(index);
+        return index;
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &ReflectionProbe {
+        &self.probes[index]
+    }
+
+    /// Capture `index` again on the next [`ReflectionProbes::capture_dirty`], as what's around it changed.
+    pub fn mark_dirty(&mut self, index: usize) {
+        if !self.dirty.contains(&index) {
+            self.dirty.push(index);
+        }
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = (0..self.probes.len()).collect();
+    }
+
+    /// Capture every probe waiting for one. `radiance(origin, dir)` is the light arriving at `origin` from `dir`.
+    /// How many were captured.
+    pub fn capture_dirty(
+        &mut self,
+        jobs: &JobSystem,
+        radiance: impl Fn(Vec3, Vec3) -> Vec3 + Sync,
+    ) -> usize {
+        let dirty = std::mem::take(&mut self.dirty);
+        for &index in &dirty {
+            let probe = &mut self.probes[index];
+            let position = probe.position;
+            probe.cubemap = Some(Cubemap::capture(probe.resolution, jobs, |dir| {
+                radiance(position, dir)
+            }));
+        }
+        return dirty.len();
+    }
+
+    /// The probes that count at `point` and how much, adding up to at most 1. Smaller probes take their share
+    /// first and bigger ones get what's left, up to [`MAX_BLEND`] of them. Uncaptured probes are skipped.
+    pub fn blend(&self, point: Vec3) -> Vec<(usize, f32)> {
+        let mut out = Vec::new();
+        let mut left = 1.0;
+        for (i, probe) in self.probes.iter().enumerate() {
+            if out.len() == MAX_BLEND || left <= 0.0 {
+                break;
+            }
+            let weight = probe.weight(point);
+            if weight > 0.0 && probe.cubemap.is_some() {
+                out.push((i, weight * left));
+                left -= weight * left;
+            }
+        }
+        return out;
+    }
+
+    /// The reflection at `point` along `dir`, with `sky(dir)` making up whatever the probes there leave.
+    pub fn sample(&self, point: Vec3, dir: Vec3, sky: impl Fn(Vec3) -> Vec3) -> Vec3 {
+        let mut total = 0.0;
+        let mut color = Vec3::ZERO;
+        for (i, weight) in self.blend(point) {
+            let probe = &self.probes[i];
+            let cubemap = probe.cubemap.as_ref().unwrap();
+            color += cubemap.sample(probe.parallax_direction(point, dir)) * weight;
+            total += weight;
+        }
+        return color + sky(dir) * (1.0 - total);
+    }
+
+    /// Where each probe's faces start in [`ReflectionProbes::atlas`], a row of texels each, and their size. `None`
+    /// for the uncaptured ones, which aren't in it.
+    fn atlas_rows(&self) -> Vec<Option<(u32, u32)>> {
+        let mut row = 0;
+        let mut rows = Vec::with_capacity(self.probes.len());
+        for probe in &self.probes {
+            let size = probe.cubemap.as_ref().map(Cubemap::size);
+            rows.push(size.map(|size| (row, size)));
+            row += size.unwrap_or(0);
+        }
+        return rows;
+    }
+
+    /// The captured cubemaps in one 2D texture: each probe's faces side by side in [`CubeFace::ALL`] order, on rows
+    /// of their own, in blend order. Its width and height, and its texels as
+    /// [`TextureFormat::Rgba16Float`](super::hal::TextureFormat::Rgba16Float) data. `None` with nothing captured.
+    pub fn atlas(&self) -> Option<([u32; 2], Vec<u8>)> {
+        let cubemaps: Vec<&Cubemap> = self.probes.iter().filter_map(|p| p.cubemap()).collect();
+        let width = 6 * cubemaps.iter().map(|c| c.size).max()?;
+        let height = cubemaps.iter().map(|c| c.size).sum();
+        let mut texels = vec![Vec3::ZERO; (width * height) as usize];
+        let mut row = 0;
+        for cubemap in cubemaps {
+            let size = cubemap.size;
+            for face in 0..6 {
+                for y in 0..size {
+                    let from = ((face * size + y) * size) as usize;
+                    let to = ((row + y) * width + face * size) as usize;
+                    texels[to..to + size as usize]
+                        .copy_from_slice(&cubemap.texels[from..from + size as usize]);
+                }
+            }
+            row += size;
+        }
+        let mut out = Vec::with_capacity(texels.len() * 8);
+        for texel in texels {
+            for f in texel.extend(1.0).to_array() {
+                out.extend(f16::from_f32(f).to_le_bytes());
+            }
+        }
+        return Some(([width, height], out));
+    }
+
+    /// The probes as the storage buffer [`GLSL`] reads, in blend order: a count padded to 16 bytes, then per probe
+    /// its capture position and blend distance, then its shape, then where it is in [`ReflectionProbes::atlas`].
+    /// Boxes are their min and max with a 0 after the min, spheres their center and radius with a 1 after. The
+    /// atlas has the probe's first row and its faces' size, which is 0 for the uncaptured ones.
+    pub fn gpu_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.probes.len() * 64);
+        out.extend((self.probes.len() as u32).to_ne_bytes());
+        out.extend([0; 12]);
+        let mut vec4 = |v: [f32; 4]| v.iter().for_each(|f| out.extend(f.to_ne_bytes()));
+        for (probe, atlas) in self.probes.iter().zip(self.atlas_rows()) {
+            vec4(probe.position.extend(probe.blend_distance).to_array());
+            match probe.shape {
+                ProbeShape::Box(aabb) => {
+                    vec4(aabb.min.extend(0.0).to_array());
+                    vec4(aabb.max.extend(0.0).to_array());
+                }
+                ProbeShape::Sphere(sphere) => {
+                    vec4(sphere.center.extend(1.0).to_array());
+                    vec4([sphere.radius, 0.0, 0.0, 0.0]);
+                }
+            }
+            let (row, size) = atlas.unwrap_or((0, 0));
+            vec4([row as f32, size as f32, 0.0, 0.0]);
+        }
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use exr::prelude::f16;
+    use glam::{Vec2, Vec3};
+
+    use super::{CubeFace, ProbeShape, ReflectionProbe, ReflectionProbes};
+    use crate::{
+        jobs::JobSystem,
+        math::bounds::{Aabb, Sphere},
+    };
+
+    #[test]
+    pub fn parallax_corrects_and_blends() {
+        for face in CubeFace::ALL {
+            let uv = Vec2::new(0.25, -0.5);
+            let (back, back_uv) = CubeFace::from_direction(face.direction(uv));
+            assert_eq!(back, face);
+            assert!(back_uv.abs_diff_eq(uv, 1e-5), "{face:?} {back_uv}");
+        }
+
+        // A room 10 wide, captured from its middle, with a small probe tucked in one corner.
+        let room = Aabb::new(Vec3::splat(-5.0), Vec3::splat(5.0));
+        let mut probes = ReflectionProbes::new();
+        probes.add(ReflectionProbe::new(Vec3::ZERO, ProbeShape::Box(room)).with_resolution(16));
+        let corner = Vec3::new(4.0, 0.0, 4.0);
+        let small = probes.add(
+            ReflectionProbe::new(corner, ProbeShape::Sphere(Sphere::new(corner, 1.0)))
+                .with_blend_distance(0.5)
+                .with_resolution(4),
+        );
+        assert_eq!(small, 0);
+
+        // Off to the side, looking at the +x wall dead on lands off center in the capture.
+        let room_probe = probes.get(1);
+        let point = Vec3::new(0.0, 0.0, 4.0);
+        let dir = room_probe.parallax_direction(point, Vec3::X);
+        assert!(dir.abs_diff_eq(Vec3::new(5.0, 0.0, 4.0).normalize(), 1e-5));
+        let sphere_dir = probes.get(0).parallax_direction(corner, Vec3::Y);
+        assert!(sphere_dir.abs_diff_eq(Vec3::Y, 1e-5));
+
+        // Nothing's captured yet, so it's all sky.
+        let sky = |_| Vec3::splat(0.25);
+        assert!(probes.blend(point).is_empty());
+        let jobs = JobSystem::new(Some(2));
+        // Walls a color each way along x, so parallax shows.
+        let captured = probes.capture_dirty(&jobs, |_, dir| match dir.x > 0.0 {
+            true => Vec3::X,
+            false => Vec3::Y,
+        });
+        assert_eq!(captured, 2);
+        assert_eq!(probes.capture_dirty(&jobs, |_, _| Vec3::ZERO), 0);
+
+        // In the corner, the small probe takes it all. Halfway into its blend, half.
+        assert_eq!(probes.blend(corner), vec![(0, 1.0)]);
+        let blend = probes.blend(corner + Vec3::new(0.0, 0.75, 0.0));
+        assert_eq!(blend, vec![(0, 0.5), (1, 0.5)]);
+        // Near the room's wall it fades towards the sky.
+        let edge = Vec3::new(-4.5, 0.0, 0.0);
+        assert_eq!(probes.blend(edge), vec![(1, 0.5)]);
+        assert!(
+            probes
+                .sample(edge, Vec3::NEG_X, sky)
+                .abs_diff_eq(Vec3::new(0.125, 0.625, 0.125), 1e-5)
+        );
+        assert_eq!(
+            probes.sample(Vec3::splat(20.0), Vec3::X, sky),
+            Vec3::splat(0.25)
+        );
+
+        // The small probe's faces on the first 4 rows, the room's on the 16 after.
+        assert_eq!(probes.gpu_data().len(), 16 + 2 * 64);
+        let ([width, height], atlas) = probes.atlas().unwrap();
+        assert_eq!([width, height], [6 * 16, 4 + 16]);
+        assert_eq!(atlas.len() as u32, width * height * 8);
+        let texel = |x: u32, y: u32| {
+            let at = ((y * width + x) * 8) as usize;
+            f16::from_le_bytes([atlas[at], atlas[at + 1]]).to_f32()
+        };
+        // The room's +x face is all +x, and its -x face has none.
+        assert_eq!(texel(0, 4), 1.0);
+        assert_eq!(texel(16, 4), 0.0);
+        assert!(ReflectionProbes::new().atlas().is_none());
+    }
+}
@@ -0,0 +1,119 @@
+// Reflection probe lookups, see reflections.rs. Define REFLECTIONS_SET, REFLECTIONS_BINDING,
+// REFLECTIONS_ATLAS_BINDING and REFLECTIONS_SAMPLER_BINDING before including it to put the probes, their atlas and a
+// sampler for it somewhere other than set 0, bindings 0 to 2. Keep in step with ReflectionProbes::gpu_data,
+// ReflectionProbes::atlas and ReflectionProbes::sample.
+
+#ifndef REFLECTIONS_SET
+#define REFLECTIONS_SET 0
+#endif
+#ifndef REFLECTIONS_BINDING
+#define REFLECTIONS_BINDING 0
+#endif
+#ifndef REFLECTIONS_ATLAS_BINDING
+#define REFLECTIONS_ATLAS_BINDING 1
+#endif
+#ifndef REFLECTIONS_SAMPLER_BINDING
+#define REFLECTIONS_SAMPLER_BINDING 2
+#endif
+
+// Keep in step with MAX_BLEND.
+#define REFLECTION_MAX_BLEND 4
+
+layout(std430, set = REFLECTIONS_SET, binding = REFLECTIONS_BINDING) readonly buffer ReflectionProbes {
+    uint reflection_count;
+    uint reflection_pad0;
+    uint reflection_pad1;
+    uint reflection_pad2;
+    // Four per probe, smallest first: position and blend distance, then a box's min and a 0, or a sphere's center
+    // and a 1, then a box's max or a sphere's radius in x, then the first row of its faces in the atlas and their
+    // size, 0 if it's not captured.
+    vec4 reflection_data[];
+};
+
+// Each probe's faces along a row of their own, Rgba16Float. Read texel by texel, so any sampler will do.
+layout(set = REFLECTIONS_SET, binding = REFLECTIONS_ATLAS_BINDING) uniform texture2D reflection_atlas;
+layout(set = REFLECTIONS_SET, binding = REFLECTIONS_SAMPLER_BINDING) uniform sampler reflection_sampler;
+
+// How far in from the edge of probe `i`'s shape `point` is, negative outside.
+float reflection_depth(uint i, vec3 point) {
+    vec4 a = reflection_data[i * 4u + 1u];
+    vec4 b = reflection_data[i * 4u + 2u];
+    if (a.w > 0.5) {
+        return b.x - distance(point, a.xyz);
+    }
+    vec3 d = min(point - a.xyz, b.xyz - point);
+    return min(d.x, min(d.y, d.z));
+}
+
+// The direction to read probe `i`'s cubemap in for a ray from `point` along `dir`: from the capture point to where
+// the ray leaves the probe's shape.
+vec3 reflection_parallax(uint i, vec3 point, vec3 dir) {
+    vec3 position = reflection_data[i * 4u].xyz;
+    vec4 a = reflection_data[i * 4u + 1u];
+    vec4 b = reflection_data[i * 4u + 2u];
+    float t;
+    if (a.w > 0.5) {
+        vec3 oc = point - a.xyz;
+        float h = dot(oc, dir);
+        float c = dot(oc, oc) - b.x * b.x;
+        t = -h + sqrt(max(h * h - c, 0.0));
+    } else {
+        vec3 inv = 1.0 / dir;
+        vec3 far = max((b.xyz - point) * inv, (a.xyz - point) * inv);
+        t = min(far.x, min(far.y, far.z));
+    }
+    if (isinf(t) || isnan(t) || t < 0.0) {
+        return dir;
+    }
+    vec3 to = point + dir * t - position;
+    return dot(to, to) > 0.0 ? normalize(to) : dir;
+}
+
+// The texel of probe `i`'s cubemap `dir` goes through, unfiltered. Keep in step with CubeFace::from_direction and
+// Cubemap::sample.
+vec3 reflection_fetch(uint i, vec3 dir) {
+    vec4 atlas = reflection_data[i * 4u + 3u];
+    vec3 a = abs(dir);
+    uint face;
+    vec2 uv;
+    float major;
+    if (a.x >= a.y && a.x >= a.z) {
+        face = dir.x >= 0.0 ? 0u : 1u;
+        uv = vec2(dir.x >= 0.0 ? -dir.z : dir.z, -dir.y);
+        major = a.x;
+    } else if (a.y >= a.z) {
+        face = dir.y >= 0.0 ? 2u : 3u;
+        uv = vec2(dir.x, dir.y >= 0.0 ? dir.z : -dir.z);
+        major = a.y;
+    } else {
+        face = dir.z >= 0.0 ? 4u : 5u;
+        uv = vec2(dir.z >= 0.0 ? dir.x : -dir.x, -dir.y);
+        major = a.z;
+    }
+    uv /= max(major, 1e-30);
+    int size = int(atlas.y);
+    ivec2 texel = min(ivec2((uv + 1.0) / 2.0 * float(size)), ivec2(size - 1));
+    ivec2 at = ivec2(int(face) * size + texel.x, int(atlas.x) + texel.y);
+    return texelFetch(sampler2D(reflection_atlas, reflection_sampler), at, 0).rgb;
+}
+
+// The reflection at `position` along `dir`, blending the probes there smallest first and filling in the rest with
+// `sky`.
+vec3 reflection_sample(vec3 position, vec3 dir, vec3 sky) {
+    vec3 color = vec3(0.0);
+    float left = 1.0;
+    int blended = 0;
+    for (uint i = 0u; i < reflection_count && blended < REFLECTION_MAX_BLEND && left > 0.0; i++) {
+        float blend_distance = reflection_data[i * 4u].w;
+        float depth = reflection_depth(i, position);
+        float weight = blend_distance > 0.0 ? clamp(depth / blend_distance, 0.0, 1.0) : float(depth >= 0.0);
+        // Uncaptured probes are skipped.
+        if (weight > 0.0 && reflection_data[i * 4u + 3u].y > 0.0) {
+            vec3 lookup = reflection_parallax(i, position, dir);
+            color += reflection_fetch(i, lookup) * weight * left;
+            left -= weight * left;
+            blended++;
+        }
+    }
+    return color + sky * left;
+}
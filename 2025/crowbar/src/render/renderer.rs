@@ -0,0 +1,3875 @@
+//! The renderer: the Vulkan instance and device the app draws with, for as long as it runs.
+//!
+//! Made once, when the app first resumes, since that's the first point a display handle is guaranteed to exist on
+//! every platform. Windows come and go, the renderer stays.
+
+use std::{
+    error::Error,
+    ffi::{CStr, CString},
+    fmt,
+};
+
+use ash::{Entry, ext, prelude::VkResult, vk};
+use glam::{Mat4, Vec3};
+use winit::raw_window_handle::RawDisplayHandle;
+
+use super::{
+    FRAMES_IN_FLIGHT, VK_ENTRY,
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    analysis::{self, AnalysisPass, FrameAnalysis},
+    bindless::BindlessTable,
+    breadcrumbs::{self, Breadcrumbs},
+    commands::CommandManager,
+    deletion::{DeletionQueue, Retired},
+    depth_of_field,
+    descriptors::{DEFAULT_RATIOS, FrameDescriptors, LayoutCache, LayoutDesc},
+    device_lost::DeviceLost,
+    draw::{self, DrawList},
+    extract::ExtractedScene,
+    foliage::{FoliagePass, Wind},
+    frame_sync::FrameSync,
+    fullscreen::{self, FullscreenPass},
+    gpu_profiler::{GpuProfiler, GpuTimings},
+    gpu_select::{self, DeviceInfo, GpuOverride, Requirements},
+    graph::{
+        Access, CompiledGraph, Pass, PassId, RenderGraph, ResourceId,
+        barriers::BarrierStats,
+        execute::GraphResources,
+        readback::{PendingReadback, ReadbackPoint},
+        transients::Transients,
+    },
+    hal::{
+        BufferDesc, BufferUsage, Device, LoadOp, MemoryLocation, TextureDesc, TextureFormat,
+        TextureState, TextureUsage,
+        caps::DeviceCaps,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture, state_info, vk_format},
+    },
+    indirect::{self, IndirectValidator},
+    mesh::{MeshPass, MeshPrepare, MeshShaders},
+    motion_blur::MotionBlurSettings,
+    outline::{self, OutlineTargets},
+    overlay::{self, OverlayPass},
+    pipeline::TargetFormats,
+    pipeline_cache::PipelineCache,
+    planar::{PlanarReflections, PlanarTarget},
+    portal::PortalViews,
+    quality::{QualitySettings, QualityTargets, SceneDescs},
+    rendering::{self, PassContext, RenderingDesc},
+    shader::{
+        ShaderErrors,
+        compile::{ShaderStage, builtin_shader},
+        watch::{BuildPipeline, HotPipelines, PipelineId},
+    },
+    sprite::{self, SpriteBatch, SpritePass, TextureId},
+    surface::{self, PresentStats},
+    swapchain::Swapchain,
+    validation::{self, DebugMessenger},
+    water::WaterPass,
+};
+use crate::{
+    app::info::AppInfo,
+    capture::{CaptureFormat, CapturedFrame},
+    color::LinearColor,
+    consts::ENGINE_VERSION,
+    jobs::JobSystem,
+    math::NDC_FAR,
+    overlay::OverlayOutput,
+    profile_scope,
+};
+
+#[derive(Debug)]
+pub enum RendererError {
+    /// There's no Vulkan loader on this machine.
+    NoLoader,
+    /// Nothing can do Vulkan 1.3 with dynamic rendering, and present if there's a display.
+    NoDevice,
+    Vk(vk::Result),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::NoLoader => write!(f, "no Vulkan loader"),
+            RendererError::NoDevice => write!(f, "no device with Vulkan 1.3 and dynamic rendering"),
+            RendererError::Vk(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for RendererError {}
+
+impl From<vk::Result> for RendererError {
+    fn from(e: vk::Result) -> RendererError {
+        RendererError::Vk(e)
+    }
+}
+
+/// Make an instance for `info` with `extensions` enabled. With `validation`, and the layer installed, it validates
+/// and the messenger routing what it says to the log comes with it.
+pub(crate) fn create_instance(
+    entry: &Entry,
+    info: &AppInfo,
+    extensions: &[&CStr],
+    validation: bool,
+) -> Result<(ash::Instance, Option<DebugMessenger>), vk::Result> {
+    let validation = validation
+        && {
+            let available = validation::layer_available(entry);
+            if !available {
+                log::warn!(
+                    "Validation was asked for, but the layer isn't installed. It comes with the Vulkan SDK."
+                );
+            }
+            available
+        };
+    let app_name = CString::new(info.name()).unwrap_or_default();
+    let app_info = vk::ApplicationInfo::default()
+        .application_name(&app_name)
+        .application_version(info.vk_version())
+        .engine_name(c"Crowbar")
+        .engine_version(ENGINE_VERSION)
+        .api_version(vk::API_VERSION_1_3);
+    let mut extensions: Vec<_> = extensions.iter().map(|e| e.as_ptr()).collect();
+    let mut layers = Vec::new();
+    let mut messenger_info = validation::messenger_info();
+    let mut create_info = vk::InstanceCreateInfo::default().application_info(&app_info);
+    if validation {
+        extensions.push(ext::debug_utils::NAME.as_ptr());
+        layers.push(validation::VALIDATION_LAYER.as_ptr());
+        create_info = create_info.push_next(&mut messenger_info);
+    }
+
+    // SAFETY: The extensions are the caller's to get right, and the create info outlives the call.
+    let instance = unsafe {
+        entry.create_instance(
+            &create_info
+                .enabled_extension_names(&extensions)
+                .enabled_layer_names(&layers),
+            Some(&*VK_ALLOCATOR_CALLBACKS),
+        )?
+    };
+    if !validation {
+        return Ok((instance, None));
+    }
+    // SAFETY: Made with debug utils just above, and handed back alongside the instance.
+    return match unsafe { DebugMessenger::new(entry, &instance) } {
+        Ok(messenger) => {
+            log::info!(
+                "Validating, logging {} and up",
+                validation::min_severity().name()
+            );
+            Ok((instance, Some(messenger)))
+        }
+        Err(e) => {
+            log::warn!("Couldn't make the validation messenger: {e}");
+            Ok((instance, None))
+        }
+    };
+}
+
+/// What a renderer was made with, to make it again the same way.
+#[derive(Clone)]
+struct RendererParams {
+    info: AppInfo,
+    display: Option<RawDisplayHandle>,
+    gpu_override: Option<GpuOverride>,
+    validation: bool,
+    jobs: JobSystem,
+}
+
+/// Breadcrumbs for `frames` in flight on `device`, made with `extensions`. `None` if they couldn't be made, which
+/// only costs saying where the GPU was when it's lost.
+fn make_breadcrumbs(
+    device: &VulkanDevice,
+    extensions: &[&CStr],
+    frames: u32,
+) -> Option<Breadcrumbs> {
+    // SAFETY: Destroyed before the device, see Renderer's drop.
+    let made = unsafe {
+        Breadcrumbs::new(
+            device.instance(),
+            device.physical_device(),
+            device.raw(),
+            extensions,
+            frames as usize,
+        )
+    };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make GPU breadcrumbs: {e}"))
+        .ok();
+}
+
+/// Timestamp queries for `frames` in flight on `device`, made with `extensions`. `None` if they couldn't be made,
+/// which only costs the GPU timings.
+fn make_gpu_profiler(
+    entry: &Entry,
+    device: &VulkanDevice,
+    extensions: &[&CStr],
+    frames: u32,
+) -> Option<GpuProfiler> {
+    // SAFETY: Dropped before the device, see Renderer's drop.
+    let made = unsafe {
+        GpuProfiler::new(
+            entry,
+            device.instance(),
+            device.physical_device(),
+            device.raw(),
+            device.queue_family(),
+            frames,
+            extensions.contains(&ext::calibrated_timestamps::NAME),
+        )
+    };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make GPU timestamp queries: {e}"))
+        .ok()
+        .flatten();
+}
+
+/// Indirect draw validation for `frames` in flight on `device`, where [`indirect::ENABLED`] asks for it. `None`
+/// otherwise, or if its shader couldn't be had or it couldn't be made.
+fn make_indirect_validator(
+    device: &VulkanDevice,
+    layouts: &mut LayoutCache,
+    cache: vk::PipelineCache,
+    frames: u32,
+) -> Option<IndirectValidator> {
+    if !indirect::ENABLED {
+        return None;
+    }
+    let spirv = builtin_shader(
+        indirect::SHADER_ASSET,
+        "indirect_validate.comp",
+        indirect::SHADER,
+        ShaderStage::Compute,
+    )?;
+    // SAFETY: The layouts and cache are the renderer's, and the validator is destroyed before them, see Renderer's
+    // drop.
+    let made = unsafe { IndirectValidator::new(device, layouts, cache, &spirv, frames) };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make indirect draw validation: {e}"))
+        .ok();
+}
+
+/// The 2D pass for `frames` in flight on `device`. `None` if its shaders couldn't be had or it couldn't be made,
+/// which costs every sprite and the UI.
+fn make_sprite_pass(
+    device: &VulkanDevice,
+    cache: vk::PipelineCache,
+    frames: u32,
+) -> Option<SpritePass> {
+    let vertex = builtin_shader(
+        sprite::VERTEX_ASSET,
+        "sprite.vert",
+        sprite::VERTEX_SHADER,
+        ShaderStage::Vertex,
+    );
+    let fragment = builtin_shader(
+        sprite::FRAGMENT_ASSET,
+        "sprite.frag",
+        sprite::FRAGMENT_SHADER,
+        ShaderStage::Fragment,
+    );
+    let (Some(vertex), Some(fragment)) = (vertex, fragment) else {
+        log::warn!("No sprite shaders, 2D drawing is off");
+        return None;
+    };
+    // SAFETY: The cache is the renderer's, and the pass is destroyed before it, see Renderer's drop.
+    let made = unsafe { SpritePass::new(device, cache, &vertex, &fragment, frames) };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make the 2D pass, 2D drawing is off: {e}"))
+        .ok();
+}
+
+/// The debug overlay's pass for `frames` in flight on `device`. `None` if its shaders couldn't be had or it couldn't
+/// be made, which leaves the overlay unpainted.
+fn make_overlay_pass(
+    device: &VulkanDevice,
+    cache: vk::PipelineCache,
+    frames: u32,
+) -> Option<OverlayPass> {
+    let vertex = builtin_shader(
+        overlay::VERTEX_ASSET,
+        "overlay.vert",
+        overlay::VERTEX_SHADER,
+        ShaderStage::Vertex,
+    );
+    let fragment = builtin_shader(
+        overlay::FRAGMENT_ASSET,
+        "overlay.frag",
+        overlay::FRAGMENT_SHADER,
+        ShaderStage::Fragment,
+    );
+    let (Some(vertex), Some(fragment)) = (vertex, fragment) else {
+        log::warn!("No overlay shaders, the debug overlay is off");
+        return None;
+    };
+    // SAFETY: The cache is the renderer's, and the pass is destroyed before it, see Renderer's drop.
+    let made = unsafe { OverlayPass::new(device, cache, &vertex, &fragment, frames) };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make the overlay's pass, it's off: {e}"))
+        .ok();
+}
+
+/// The 3D pass for `frames` in flight on `device`. `None` if its shaders couldn't be had or it couldn't be made,
+/// which leaves the scene undrawn.
+fn make_mesh_pass(
+    device: &VulkanDevice,
+    layouts: &mut LayoutCache,
+    cache: vk::PipelineCache,
+    frames: u32,
+) -> Option<MeshPass> {
+    let Some(shaders) = MeshShaders::builtin() else {
+        log::warn!("No mesh shaders, 3D drawing is off");
+        return None;
+    };
+    // SAFETY: The layouts and cache are the renderer's, and the pass is destroyed before them, see Renderer's drop.
+    let made = unsafe { MeshPass::new(device, layouts, cache, &shaders, frames) };
+    return made
+        .inspect_err(|e| log::warn!("Couldn't make the 3D pass, 3D drawing is off: {e}"))
+        .ok();
+}
+
+/// What [`Renderer::present`] draws over the cleared frame.
+#[derive(Clone, Copy, Default)]
+pub struct FrameContent<'a> {
+    /// Drawn through its cameras, in order, as culled into `views`.
+    pub scene: Option<&'a ExtractedScene>,
+    /// A built list per camera in `scene`.
+    pub views: &'a [DrawList],
+    /// Built, drawn over everything but the overlay.
+    pub sprites: Option<&'a SpriteBatch>,
+    /// The debug overlay, painted over everything else once its textures have been changed as it asks.
+    pub overlay: Option<&'a OverlayOutput>,
+    /// Copy the image out once it's drawn, for [`Renderer::take_captures`] when the GPU's done with it.
+    pub readback: bool,
+    /// Run it through the analysis pass, for [`Renderer::frame_analysis`] when the GPU's done with it.
+    pub analyse: bool,
+    /// Textures in the frame's graph to read back by name, for [`Renderer::take_resource_captures`] when the GPU's
+    /// done with them.
+    pub resources: &'a [String],
+    /// Simulated seconds since the start of the run, for what moves by itself, like water.
+    pub time: f32,
+}
+
+/// A presented image on its way back to the CPU.
+struct SwapchainReadback {
+    buffer: VulkanBuffer,
+    width: u32,
+    height: u32,
+    format: CaptureFormat,
+    frame: u64,
+}
+
+/// Everything Vulkan the app needs to draw, from the instance down to the queue.
+pub struct Renderer {
+    // Owns the instance too, and destroys both when dropped.
+    device: VulkanDevice,
+    entry: &'static Entry,
+    /// The one picked, out of `devices`.
+    adapter: DeviceInfo,
+    devices: Vec<DeviceInfo>,
+    /// Whether windows can be presented to, so surfaces and swapchains can be made.
+    presents: bool,
+    targets: QualityTargets<VulkanDevice>,
+    /// The outline mask and flood targets, at the scene's size while anything's outlined. Retired by hand, like
+    /// `targets`.
+    outlines: OutlineTargets<VulkanDevice>,
+    /// A target for each planar reflector, at its share of the scene's size, while the scene's drawn. Retired by
+    /// hand, like `targets`.
+    planar: PlanarReflections<VulkanDevice>,
+    /// The views through the scene's portals from its last camera, and what they see.
+    portals: PortalViews,
+    /// Samples per pixel, as `r_msaa` asks and the device allows. Pipelines and the scene's targets follow it, and
+    /// swapchains too while the scene's drawn straight to them.
+    samples: u32,
+    deletions: DeletionQueue<Retired<VulkanDevice>>,
+    /// Taken down by hand, before the device, like `commands`.
+    sync: Option<FrameSync>,
+    commands: Option<CommandManager>,
+    /// Sets for the current frame, taken down by hand like `commands`.
+    descriptors: Option<FrameDescriptors>,
+    /// Taken down by hand, after the pipelines.
+    layouts: LayoutCache,
+    /// Made by [`Renderer::enable_bindless`], and taken down by hand like `commands`.
+    bindless: Option<BindlessTable>,
+    /// A frame was begun and not presented yet.
+    begun: bool,
+    /// The last frame begun.
+    frame: u64,
+    /// Taken down by hand, after the pipelines.
+    pipeline_cache: PipelineCache,
+    /// The last frame's graph.
+    graph: Option<CompiledGraph>,
+    /// The barriers recording `graph` put in.
+    barriers: Option<BarrierStats>,
+    /// What the last frame's graph made, kept for the next while it fits. Retired by hand, like `targets`.
+    transients: Option<Transients<VulkanDevice>>,
+    /// Taken down by hand, before the pipeline cache. `None` if it couldn't be made.
+    sprite_pass: Option<SpritePass>,
+    /// Taken down by hand, like `sprite_pass`. Without it, the debug overlay isn't painted.
+    overlay_pass: Option<OverlayPass>,
+    /// Taken down by hand, like `sprite_pass`.
+    mesh_pass: Option<MeshPass>,
+    /// Tonemaps the scene, with its ambient occlusion and bloom, up to the window. Taken down by hand, like
+    /// `sprite_pass`. Without it, the scene's drawn straight to the window.
+    post: Option<FullscreenPass>,
+    /// Works out the scene's ambient occlusion. Taken down by hand, like `post`. Without it, there's none.
+    ssao: Option<FullscreenPass>,
+    /// Blurs the scene's brightest parts for `post` to bloom with. Taken down by hand, like `post`. Without it,
+    /// nothing blooms.
+    bloom: Option<FullscreenPass>,
+    /// Depth of field's passes: halving the scene with its circles of confusion, blurring the near and far layers
+    /// from that, and putting them back over the scene. Taken down by hand, like `post`. Without all three, nothing's
+    /// blurred by depth.
+    dof_split: Option<FullscreenPass>,
+    dof_blur: Option<FullscreenPass>,
+    dof_composite: Option<FullscreenPass>,
+    /// Blurs the scene along its motion vectors, after depth of field and before anything else reads it. Taken down
+    /// by hand, like `post`. Without it, nothing's blurred.
+    motion_blur: Option<FullscreenPass>,
+    /// What `motion_blur` blurs with, as of the last [`Renderer::set_motion_blur`].
+    blur_settings: MotionBlurSettings,
+    /// Outlines' passes: seeding the flood from the mask the 3D pass draws, a step of the flood, and blending the
+    /// outlines over the window after the scene. Taken down by hand, like `post`. Without all three, or the 3D pass,
+    /// nothing's outlined.
+    outline_seed: Option<FullscreenPass>,
+    outline_flood: Option<FullscreenPass>,
+    outline_composite: Option<FullscreenPass>,
+    /// Draws water over the scene, from `water_copy`'s copy of it. Taken down by hand, like `post`. Without both, or
+    /// the 3D pass, there's no water.
+    water: Option<WaterPass>,
+    water_copy: Option<FullscreenPass>,
+    /// Culls foliage and draws it in the 3D pass, through the last camera. Taken down by hand, like `post`. Without
+    /// it, or the 3D pass, there's no foliage.
+    foliage: Option<FoliagePass>,
+    /// What sways the foliage, as of the last [`Renderer::set_wind`].
+    wind: Wind,
+    /// Checks the 3D pass's draws in debug builds. Taken down by hand, before `layouts`.
+    indirect_validator: Option<IndirectValidator>,
+    pipelines: HotPipelines,
+    /// Pipelines replaced or removed, kept like `deletions` until the frames in flight are done with them.
+    retired_pipelines: DeletionQueue<vk::Pipeline>,
+    /// Presented images being copied out, read once their frame is done. Taken down by hand, like `deletions`.
+    readbacks: DeletionQueue<SwapchainReadback>,
+    /// Read back, waiting for [`Renderer::take_captures`].
+    captures: Vec<CapturedFrame>,
+    /// Graph textures being copied out, read once their frame is done. Taken down by hand, like `readbacks`.
+    resource_readbacks: DeletionQueue<PendingReadback<VulkanDevice>>,
+    /// Read back, waiting for [`Renderer::take_resource_captures`].
+    resource_captures: Vec<(String, CapturedFrame)>,
+    /// Made the first time a frame asks for [`FrameContent::analyse`]. Taken down by hand, like `sprite_pass`.
+    analysis: Option<PresentAnalysis>,
+    /// Making `analysis` failed, so it's not tried again until the frames in flight change.
+    analysis_failed: bool,
+    /// The device extensions enabled, required and optional.
+    extensions: Vec<&'static CStr>,
+    /// Taken down by hand, before the device.
+    breadcrumbs: Option<Breadcrumbs>,
+    /// Taken down by hand, like `sync`. `None` if the queue can't time things.
+    gpu_profiler: Option<GpuProfiler>,
+    /// Set once the device is lost, after which nothing's drawn until [`Renderer::recreate`].
+    lost: Option<DeviceLost>,
+    /// Devices lost before this one, this run.
+    losses: u32,
+    params: RendererParams,
+}
+
+impl Renderer {
+    /// Set up on the best device there is, or the one `gpu_override` asks for. With a `display`, the instance can
+    /// make surfaces for its windows and the device can present to them. Frames are recorded on `jobs`' workers.
+    pub fn new(
+        info: &AppInfo,
+        display: Option<RawDisplayHandle>,
+        gpu_override: Option<&GpuOverride>,
+        validation: bool,
+        jobs: &JobSystem,
+    ) -> Result<Renderer, RendererError> {
+        let entry = VK_ENTRY.as_ref().ok_or(RendererError::NoLoader)?;
+
+        let instance_extensions = match display.map(surface::required_extensions) {
+            Some(Some(extensions)) => {
+                let mut extensions = extensions.to_vec();
+                extensions.extend(surface::optional_extensions(entry));
+                Some(extensions)
+            }
+            Some(None) => {
+                log::warn!("Can't present to this kind of display, rendering without windows");
+                None
+            }
+            None => None,
+        };
+        let presents = instance_extensions.is_some();
+        let device_extensions: &[&CStr] = if presents {
+            &[ash::khr::swapchain::NAME]
+        } else {
+            &[]
+        };
+
+        let (instance, messenger) = create_instance(
+            entry,
+            info,
+            instance_extensions.as_ref().map_or(&[], |e| &e[..]),
+            validation,
+        )?;
+        let requirements = Requirements {
+            extensions: device_extensions.to_vec(),
+            ..Requirements::default()
+        };
+        let devices = gpu_select::enumerate(&instance, &requirements);
+        for device in &devices {
+            log::info!("Found GPU {device}");
+        }
+        let Some(adapter) = gpu_select::select(&devices, &requirements, gpu_override).cloned()
+        else {
+            // SAFETY: Nothing else was made from it.
+            unsafe {
+                if let Some(messenger) = messenger {
+                    messenger.destroy();
+                }
+                instance.destroy_instance(Some(&*VK_ALLOCATOR_CALLBACKS));
+            }
+            return Err(RendererError::NoDevice);
+        };
+
+        // SAFETY: A query, on a device from the instance.
+        let available =
+            unsafe { instance.enumerate_device_extension_properties(adapter.physical_device) }
+                .unwrap_or_default();
+        let mut extensions = device_extensions.to_vec();
+        // SAFETY: As above.
+        extensions.extend(unsafe {
+            breadcrumbs::wanted_extensions(&instance, adapter.physical_device, &available)
+        });
+        // For the GPU timeline to line up with the CPU's.
+        if available
+            .iter()
+            .any(|e| e.extension_name_as_c_str() == Ok(ext::calibrated_timestamps::NAME))
+        {
+            extensions.push(ext::calibrated_timestamps::NAME);
+        }
+        let device = VulkanDevice::new(
+            instance,
+            messenger,
+            adapter.physical_device,
+            adapter
+                .graphics_family
+                .expect("Usable devices have a graphics queue"),
+            adapter.compute_family,
+            &extensions,
+        )?;
+        log::info!(
+            "Rendering on {} (Vulkan {}.{}.{})",
+            adapter.name,
+            vk::api_version_major(adapter.api_version),
+            vk::api_version_minor(adapter.api_version),
+            vk::api_version_patch(adapter.api_version),
+        );
+
+        // SAFETY: The device was just made from this physical device.
+        let pipeline_cache = unsafe {
+            PipelineCache::load(
+                device.raw(),
+                device.instance(),
+                adapter.physical_device,
+                Some(&info.cache_dir()),
+            )?
+        };
+        let sync = FrameSync::new(device.raw(), FRAMES_IN_FLIGHT)?;
+        let commands = CommandManager::new(
+            device.raw(),
+            device.queue_family(),
+            FRAMES_IN_FLIGHT,
+            jobs.thread_count(),
+        )?;
+        let descriptors = FrameDescriptors::new(device.raw(), FRAMES_IN_FLIGHT, DEFAULT_RATIOS);
+        let breadcrumbs = make_breadcrumbs(&device, &extensions, FRAMES_IN_FLIGHT);
+        let gpu_profiler = make_gpu_profiler(entry, &device, &extensions, FRAMES_IN_FLIGHT);
+        let sprite_pass = make_sprite_pass(&device, pipeline_cache.raw(), FRAMES_IN_FLIGHT);
+        let overlay_pass = make_overlay_pass(&device, pipeline_cache.raw(), FRAMES_IN_FLIGHT);
+        let mut layouts = LayoutCache::new();
+        let mesh_pass = make_mesh_pass(
+            &device,
+            &mut layouts,
+            pipeline_cache.raw(),
+            FRAMES_IN_FLIGHT,
+        );
+        let indirect_validator = make_indirect_validator(
+            &device,
+            &mut layouts,
+            pipeline_cache.raw(),
+            FRAMES_IN_FLIGHT,
+        );
+        // SAFETY: The layouts and cache are the renderer's, and the passes are destroyed before them, see
+        // Renderer's drop.
+        let [
+            post,
+            ssao,
+            bloom,
+            dof_split,
+            dof_blur,
+            dof_composite,
+            motion_blur,
+            outline_seed,
+            outline_flood,
+            outline_composite,
+            water_copy,
+        ] = [
+            fullscreen::POST,
+            fullscreen::SSAO,
+            fullscreen::BLOOM,
+            fullscreen::DOF_SPLIT,
+            fullscreen::DOF_BLUR,
+            fullscreen::DOF_COMPOSITE,
+            fullscreen::MOTION_BLUR,
+            fullscreen::OUTLINE_SEED,
+            fullscreen::OUTLINE_FLOOD,
+            fullscreen::OUTLINE_COMPOSITE,
+            fullscreen::COPY,
+        ]
+        .map(|shader| unsafe {
+            FullscreenPass::builtin(
+                &device,
+                &mut layouts,
+                pipeline_cache.raw(),
+                shader,
+                FRAMES_IN_FLIGHT,
+            )
+        });
+        // SAFETY: As above.
+        let water = unsafe {
+            WaterPass::builtin(
+                &device,
+                &mut layouts,
+                pipeline_cache.raw(),
+                FRAMES_IN_FLIGHT,
+            )
+        };
+        // SAFETY: As above.
+        let foliage = unsafe {
+            FoliagePass::builtin(
+                &device,
+                &mut layouts,
+                pipeline_cache.raw(),
+                FRAMES_IN_FLIGHT,
+            )
+        };
+        return Ok(Renderer {
+            device,
+            entry,
+            adapter,
+            devices,
+            presents,
+            targets: QualityTargets::default(),
+            outlines: OutlineTargets::default(),
+            planar: PlanarReflections::default(),
+            portals: PortalViews::default(),
+            samples: 1,
+            deletions: DeletionQueue::new(FRAMES_IN_FLIGHT),
+            sync: Some(sync),
+            commands: Some(commands),
+            descriptors: Some(descriptors),
+            layouts,
+            bindless: None,
+            begun: false,
+            frame: 0,
+            pipelines: HotPipelines::new(pipeline_cache.raw()),
+            pipeline_cache,
+            graph: None,
+            barriers: None,
+            transients: None,
+            sprite_pass,
+            overlay_pass,
+            mesh_pass,
+            post,
+            ssao,
+            bloom,
+            dof_split,
+            dof_blur,
+            dof_composite,
+            motion_blur,
+            blur_settings: MotionBlurSettings::default(),
+            outline_seed,
+            outline_flood,
+            outline_composite,
+            water,
+            water_copy,
+            foliage,
+            wind: Wind::default(),
+            indirect_validator,
+            retired_pipelines: DeletionQueue::new(FRAMES_IN_FLIGHT),
+            readbacks: DeletionQueue::new(FRAMES_IN_FLIGHT),
+            resource_readbacks: DeletionQueue::new(FRAMES_IN_FLIGHT),
+            resource_captures: Vec::new(),
+            captures: Vec::new(),
+            analysis: None,
+            analysis_failed: false,
+            extensions,
+            breadcrumbs,
+            gpu_profiler,
+            lost: None,
+            losses: 0,
+            params: RendererParams {
+                info: info.clone(),
+                display,
+                gpu_override: gpu_override.cloned(),
+                validation,
+                jobs: jobs.clone(),
+            },
+        });
+    }
+
+    /// Tear this renderer down after its device was lost, and make it again the way it was made, on whichever
+    /// device that picks now. Pipelines added to it are built again on the new device, and bindless is enabled
+    /// again if it was. Swapchains and sprite textures went with the old device, so windows need new swapchains and
+    /// textures added with [`Renderer::add_sprite_texture`] need adding again.
+    pub fn recreate(mut self) -> Result<Renderer, RendererError> {
+        let params = self.params.clone();
+        let (samples, bindless, losses) = (self.samples, self.bindless.is_some(), self.losses);
+        let mut pipelines = std::mem::take(&mut self.pipelines);
+        let taken = pipelines.take_all();
+        // SAFETY: The device is lost, so the GPU's done with them.
+        unsafe { self.destroy_pipelines(taken) };
+        drop(self);
+
+        let mut renderer = Renderer::new(
+            &params.info,
+            params.display,
+            params.gpu_override.as_ref(),
+            params.validation,
+            &params.jobs,
+        )?;
+        pipelines.move_to(renderer.device.raw(), renderer.pipeline_cache.raw());
+        renderer.pipelines = pipelines;
+        renderer.samples = samples;
+        renderer.losses = losses;
+        if bindless && let Err(e) = renderer.enable_bindless() {
+            log::error!("Couldn't enable bindless again: {e}");
+        }
+        return Ok(renderer);
+    }
+
+    /// What's known about the device being lost, if it has been. Nothing's drawn until [`Renderer::recreate`].
+    pub fn device_lost(&self) -> Option<&DeviceLost> {
+        self.lost.as_ref()
+    }
+
+    /// Write down what there is to know if `e` says the device was lost.
+    fn check_lost(&mut self, e: vk::Result) {
+        if e != vk::Result::ERROR_DEVICE_LOST || self.lost.is_some() {
+            return;
+        }
+        let lost = DeviceLost {
+            device: self.adapter.name.clone(),
+            frame: self.frame,
+            breadcrumbs: self
+                .breadcrumbs
+                .as_ref()
+                .map(|b| b.report(self.device.queue())),
+            previous: self.losses,
+        };
+        log::error!("{lost}");
+        self.lost = Some(lost);
+        self.losses += 1;
+        self.begun = false;
+    }
+
+    pub fn device(&self) -> &VulkanDevice {
+        &self.device
+    }
+
+    /// What the device can do, to check against rather than assume.
+    pub fn caps(&self) -> &DeviceCaps {
+        self.device.caps()
+    }
+
+    pub fn entry(&self) -> &'static Entry {
+        self.entry
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        self.device.instance()
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.device.physical_device()
+    }
+
+    /// The graphics queue, which everything goes through for now.
+    pub fn queue(&self) -> vk::Queue {
+        self.device.queue()
+    }
+
+    pub fn queue_family(&self) -> u32 {
+        self.adapter.graphics_family.unwrap_or_default()
+    }
+
+    /// The device being rendered with.
+    pub fn adapter(&self) -> &DeviceInfo {
+        &self.adapter
+    }
+
+    /// Every device there was to pick from, usable or not.
+    pub fn devices(&self) -> &[DeviceInfo] {
+        &self.devices
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.adapter.name
+    }
+
+    pub fn presents(&self) -> bool {
+        self.presents
+    }
+
+    /// What every pipeline should be created through, so they come back quickly next run.
+    pub fn pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache.raw()
+    }
+
+    /// Write the pipeline cache to disk, for the next run. Done on a clean shutdown, not on drop, so a crash doesn't
+    /// save whatever state the driver was in.
+    pub fn save_pipeline_cache(&self) {
+        // SAFETY: It's this device's.
+        if let Err(e) = unsafe { self.pipeline_cache.save(self.device.raw()) } {
+            log::warn!("Couldn't save the pipeline cache: {e}");
+        }
+    }
+
+    /// The render targets, as of the last [`Renderer::update_targets`].
+    pub fn targets(&self) -> &QualityTargets<VulkanDevice> {
+        &self.targets
+    }
+
+    /// The graph the last frame presented was drawn with.
+    pub fn frame_graph(&self) -> Option<&CompiledGraph> {
+        self.graph.as_ref()
+    }
+
+    /// The barriers recording [`Renderer::frame_graph`] put in, as opposed to what the graph works out it needs.
+    pub fn frame_barriers(&self) -> Option<&BarrierStats> {
+        self.barriers.as_ref()
+    }
+
+    /// Bring the render targets in line with `settings` for a `size` window, at the start of `frame`. Whatever
+    /// gets replaced is destroyed once the frames in flight are done with it.
+    pub fn update_targets(&mut self, settings: &QualitySettings, size: [u32; 2], frame: u64) {
+        if self.lost.is_some() {
+            return;
+        }
+        let settings = settings.clamped(self.device.caps());
+        self.set_samples(settings.msaa);
+        match self
+            .targets
+            .update(&self.device, &settings, size, frame, &mut self.deletions)
+        {
+            Ok(change) if !change.is_empty() => {
+                log::info!(
+                    "Remade render targets for {change} at {}x{}",
+                    size[0],
+                    size[1]
+                )
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Couldn't make render targets: {e}");
+                self.check_lost(e);
+            }
+        }
+    }
+
+    /// Blur the scene with `settings` from the next frame on, while `r_motion_blur` has it blurred.
+    pub fn set_motion_blur(&mut self, settings: MotionBlurSettings) {
+        self.blur_settings = settings;
+    }
+
+    /// Sway the foliage in `wind` from the next frame on.
+    pub fn set_wind(&mut self, wind: Wind) {
+        self.wind = wind;
+    }
+
+    /// Samples per pixel to draw with. Pipelines are handed it when they're built, and rebuilt when it changes.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn set_samples(&mut self, samples: u32) {
+        if samples == self.samples {
+            return;
+        }
+        log::info!("Drawing with {samples} samples per pixel");
+        self.samples = samples;
+        let replaced = self
+            .pipelines
+            .set_samples(self.device.raw(), vk::SampleCountFlags::from_raw(samples));
+        for pipeline in replaced {
+            self.retired_pipelines.retire(self.frame, pipeline);
+        }
+    }
+
+    /// How long each pass took on the GPU, in the last frame read back. `None` if the device can't time passes.
+    pub fn gpu_timings(&self) -> Option<&GpuTimings> {
+        self.gpu_profiler.as_ref().map(|p| p.timings())
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.sync.as_ref().map_or(0, |s| s.frames_in_flight())
+    }
+
+    /// Let the CPU get `frames` ahead of the GPU. Waits for the GPU to catch up if it changes.
+    pub fn set_frames_in_flight(&mut self, frames: u32) {
+        let frames = frames.max(1);
+        if frames == self.frames_in_flight() {
+            return;
+        }
+        self.sync = None;
+        self.commands = None;
+        self.descriptors = None;
+        self.begun = false;
+        self.deletions.flush(&self.device);
+        if let Some(mut breadcrumbs) = self.breadcrumbs.take() {
+            // SAFETY: The flush waited for the GPU to go idle.
+            unsafe { breadcrumbs.destroy(self.device.raw()) };
+            self.breadcrumbs = make_breadcrumbs(&self.device, &self.extensions, frames);
+        }
+        // The flush waited for the GPU to go idle, so it's done with the queries.
+        self.gpu_profiler = None;
+        self.gpu_profiler = make_gpu_profiler(self.entry, &self.device, &self.extensions, frames);
+        self.deletions = DeletionQueue::new(frames);
+        let retired = self.retired_pipelines.drain();
+        // SAFETY: The flush waited for the GPU to go idle.
+        unsafe { self.destroy_pipelines(retired) };
+        self.retired_pipelines = DeletionQueue::new(frames);
+        let readbacks = self.readbacks.drain();
+        // SAFETY: The flush waited for the GPU to go idle.
+        unsafe { self.read_back(readbacks) };
+        self.readbacks = DeletionQueue::new(frames);
+        let readbacks = self.resource_readbacks.drain();
+        // SAFETY: The flush waited for the GPU to go idle.
+        unsafe { self.read_back_resources(readbacks) };
+        self.resource_readbacks = DeletionQueue::new(frames);
+        // Made again for the new count the next time a frame's analysed.
+        if let Some(analysis) = self.analysis.take() {
+            // SAFETY: The flush waited for the GPU to go idle.
+            unsafe { analysis.destroy(&self.device) };
+        }
+        self.analysis_failed = false;
+        if let Some(pass) = &mut self.sprite_pass {
+            // SAFETY: The flush waited for the GPU to go idle.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        if let Some(pass) = &mut self.overlay_pass {
+            // SAFETY: As above.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        if let Some(pass) = &mut self.mesh_pass {
+            // SAFETY: As above.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        let fullscreen = [
+            &mut self.post,
+            &mut self.ssao,
+            &mut self.bloom,
+            &mut self.dof_split,
+            &mut self.dof_blur,
+            &mut self.dof_composite,
+            &mut self.motion_blur,
+            &mut self.outline_seed,
+            &mut self.outline_flood,
+            &mut self.outline_composite,
+            &mut self.water_copy,
+        ];
+        for pass in fullscreen.into_iter().flatten() {
+            // SAFETY: As above.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        if let Some(pass) = &mut self.water {
+            // SAFETY: As above.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        if let Some(pass) = &mut self.foliage {
+            // SAFETY: As above.
+            unsafe { pass.set_frames_in_flight(&self.device, frames) };
+        }
+        if let Some(mut validator) = self.indirect_validator.take() {
+            // SAFETY: As above.
+            unsafe { validator.destroy(&self.device) };
+            self.indirect_validator = make_indirect_validator(
+                &self.device,
+                &mut self.layouts,
+                self.pipeline_cache.raw(),
+                frames,
+            );
+        }
+        let device = self.device.raw();
+        let made = FrameSync::new(device, frames).and_then(|sync| {
+            let commands = CommandManager::new(
+                device,
+                self.device.queue_family(),
+                frames,
+                self.params.jobs.thread_count(),
+            )?;
+            Ok((sync, commands))
+        });
+        match made {
+            Ok((sync, commands)) => {
+                self.sync = Some(sync);
+                self.commands = Some(commands);
+                self.descriptors = Some(FrameDescriptors::new(device, frames, DEFAULT_RATIOS));
+                if let Some(bindless) = &mut self.bindless {
+                    bindless.set_frames_in_flight(frames);
+                }
+            }
+            Err(e) => log::error!("Couldn't set up {frames} frames in flight: {e}"),
+        }
+    }
+
+    /// Start `frame`, waiting for the GPU if it's too far behind, and destroying whatever it's now done with.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.begun = false;
+        if self.lost.is_some() {
+            return;
+        }
+        self.frame = frame;
+        let (Some(sync), Some(commands), Some(descriptors)) =
+            (&mut self.sync, &mut self.commands, &mut self.descriptors)
+        else {
+            return;
+        };
+        // SAFETY: The command and descriptor pools are reset once the fence wait says the GPU is done with them.
+        let begun = sync.begin_frame(frame).and_then(|_| unsafe {
+            commands.begin_frame(frame)?;
+            descriptors.begin_frame(frame)
+        });
+        if let Err(e) = begun {
+            log::error!("Couldn't begin frame {frame}: {e}");
+            self.check_lost(e);
+            return;
+        }
+        self.begun = true;
+        if let Some(breadcrumbs) = &mut self.breadcrumbs {
+            breadcrumbs.begin_frame(frame);
+        }
+        if let Some(profiler) = &mut self.gpu_profiler {
+            profiler.begin_frame(frame);
+        }
+        if let Some(bindless) = &mut self.bindless {
+            bindless.begin_frame(frame);
+        }
+        // SAFETY: Beginning the frame waited for everything up to `frame - frames_in_flight`.
+        unsafe {
+            self.deletions.collect(&self.device, frame);
+            let ready = self.retired_pipelines.ready(frame);
+            self.destroy_pipelines(ready);
+            let ready = self.readbacks.ready(frame);
+            self.read_back(ready);
+            let ready = self.resource_readbacks.ready(frame);
+            self.read_back_resources(ready);
+            if let Some(analysis) = &mut self.analysis {
+                analysis.collect(&self.device, frame);
+            }
+            // Out of range draws are logged as they're read back.
+            if let Some(validator) = &mut self.indirect_validator {
+                validator.begin_frame(&self.device, frame);
+            }
+        }
+    }
+
+    /// Read `readbacks` into [`Renderer::take_captures`], and destroy their buffers.
+    ///
+    /// # Safety
+    /// The GPU must be done with them.
+    unsafe fn read_back(&mut self, readbacks: Vec<SwapchainReadback>) {
+        for readback in readbacks {
+            let size = readback.width as usize
+                * readback.height as usize
+                * readback.format.bytes_per_pixel();
+            let mut data = vec![0; size];
+            // SAFETY: Passed on to the caller.
+            let read = unsafe { self.device.read_buffer(&readback.buffer, 0, &mut data) };
+            match read {
+                Ok(()) => self.captures.push(CapturedFrame {
+                    width: readback.width,
+                    height: readback.height,
+                    format: readback.format,
+                    data,
+                    frame: readback.frame,
+                }),
+                Err(e) => log::error!("Couldn't read back frame {}: {e}", readback.frame),
+            }
+            // SAFETY: Passed on to the caller.
+            unsafe { self.device.destroy_buffer(readback.buffer) };
+        }
+    }
+
+    /// Read graph textures copied out into [`Renderer::take_resource_captures`].
+    ///
+    /// # Safety
+    /// The GPU must be done with them.
+    unsafe fn read_back_resources(&mut self, readbacks: Vec<PendingReadback<VulkanDevice>>) {
+        for readback in readbacks {
+            let name = readback.name.clone();
+            // SAFETY: Passed on to the caller.
+            match unsafe { readback.finish(&self.device) } {
+                Ok(captured) => self.resource_captures.push((name, captured)),
+                Err(e) => log::error!("Couldn't read back {name}: {e}"),
+            }
+        }
+    }
+
+    /// What the analysis pass made of the last frame it's done with, a few frames behind. Asked for with
+    /// [`FrameContent::analyse`].
+    pub fn frame_analysis(&self) -> Option<&FrameAnalysis> {
+        self.analysis.as_ref().and_then(|a| a.latest.as_ref())
+    }
+
+    /// The frames read back since last time, oldest first. Asked for with [`FrameContent::readback`], and ready a
+    /// few frames after.
+    pub fn take_captures(&mut self) -> Vec<CapturedFrame> {
+        std::mem::take(&mut self.captures)
+    }
+
+    /// The graph textures read back since last time, by name, oldest first. Asked for with
+    /// [`FrameContent::resources`], and ready a few frames after.
+    pub fn take_resource_captures(&mut self) -> Vec<(String, CapturedFrame)> {
+        std::mem::take(&mut self.resource_captures)
+    }
+
+    /// # Safety
+    /// The GPU must be done with them.
+    unsafe fn destroy_pipelines(&self, pipelines: Vec<vk::Pipeline>) {
+        for pipeline in pipelines {
+            // SAFETY: Passed on to the caller.
+            unsafe {
+                self.device
+                    .raw()
+                    .destroy_pipeline(pipeline, Some(&*VK_ALLOCATOR_CALLBACKS))
+            };
+        }
+    }
+
+    /// A descriptor set with `layout` for the frame begun, freed once the GPU's done with the frame.
+    pub fn allocate_descriptor_set(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+    ) -> VkResult<vk::DescriptorSet> {
+        let descriptors = self
+            .descriptors
+            .as_mut()
+            .ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+        return descriptors.allocate(layout);
+    }
+
+    /// The descriptor set layout for `desc`, shared with everything else asking for the same one. It lasts as long
+    /// as the renderer.
+    pub fn descriptor_set_layout(
+        &mut self,
+        desc: &LayoutDesc,
+    ) -> VkResult<vk::DescriptorSetLayout> {
+        // SAFETY: Every layout in the cache is this device's.
+        return unsafe { self.layouts.get(self.device.raw(), desc) };
+    }
+
+    /// Switch to bindless textures and buffers, if the device can, making the table. Does nothing if it's already
+    /// on. See [`crate::render::bindless`].
+    pub fn enable_bindless(&mut self) -> VkResult<&mut BindlessTable> {
+        if self.bindless.is_none() {
+            let frames = self.frames_in_flight();
+            // SAFETY: The caps are the device's, and the cache is this device's.
+            let table = unsafe {
+                BindlessTable::new(
+                    self.device.raw(),
+                    self.device.caps(),
+                    &mut self.layouts,
+                    frames,
+                )?
+            };
+            log::info!(
+                "Bindless on, with room for {} textures and {} buffers",
+                table.texture_capacity(),
+                table.buffer_capacity()
+            );
+            self.bindless = Some(table);
+        }
+        return Ok(self.bindless.as_mut().unwrap());
+    }
+
+    /// The bindless table, if [`Renderer::enable_bindless`] has made it.
+    pub fn bindless(&mut self) -> Option<&mut BindlessTable> {
+        self.bindless.as_mut()
+    }
+
+    /// Upload sRGB RGBA8 `texels`, `width` by `height`, for sprites to draw with. Fails without a 2D pass.
+    pub fn add_sprite_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        texels: &[u8],
+    ) -> VkResult<TextureId> {
+        let pass = self
+            .sprite_pass
+            .as_mut()
+            .ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+        return pass.add_texture(&self.device, width, height, texels);
+    }
+
+    /// Build a pipeline with `build`, and rebuild it whenever the files it was built from change, reporting
+    /// failures to `errors`. See [`crate::render::shader::watch`].
+    pub fn add_pipeline(
+        &mut self,
+        name: impl Into<String>,
+        errors: ShaderErrors,
+        build: BuildPipeline,
+    ) -> PipelineId {
+        return self.pipelines.add(self.device.raw(), name, errors, build);
+    }
+
+    /// The last build of `id` that worked, if any has.
+    pub fn pipeline(&self, id: PipelineId) -> Option<vk::Pipeline> {
+        self.pipelines.get(id)
+    }
+
+    /// Stop rebuilding `id`, and destroy its pipeline once the frames in flight are done with it.
+    pub fn remove_pipeline(&mut self, id: PipelineId) {
+        if let Some(pipeline) = self.pipelines.remove(id) {
+            self.retired_pipelines.retire(self.frame, pipeline);
+        }
+    }
+
+    /// Rebuild pipelines whose files have changed, if it's time to check. `all` rebuilds every one, now.
+    pub fn reload_pipelines(&mut self, all: bool) {
+        let replaced = match all {
+            true => self.pipelines.reload_all(self.device.raw()),
+            false => self.pipelines.reload(self.device.raw()),
+        };
+        for pipeline in replaced {
+            self.retired_pipelines.retire(self.frame, pipeline);
+        }
+    }
+
+    /// Draw `content` as the frame begun with [`Renderer::begin_frame`] to `swapchain`, and present it. False if
+    /// there was nothing to present, like while minimized or with the device lost.
+    pub fn present(
+        &mut self,
+        swapchain: &mut Swapchain,
+        stats: &mut PresentStats,
+        content: FrameContent,
+    ) -> Result<bool, RendererError> {
+        let (frame, delta) = (self.frame, content.overlay.map(|o| &o.textures_delta));
+        if let (Some(pass), Some(delta)) = (&mut self.overlay_pass, delta)
+            && let Err(e) = pass.set_textures(&self.device, &delta.set, frame, &mut self.deletions)
+        {
+            log::error!("Couldn't change the overlay's textures: {e}");
+            self.check_lost(e);
+        }
+        let presented = self.draw_and_present(swapchain, stats, content);
+        if let Err(RendererError::Vk(e)) = &presented {
+            self.check_lost(*e);
+        }
+        // Once the frame's recorded, whether or not it drew with them.
+        if let (Some(pass), Some(delta)) = (&mut self.overlay_pass, delta) {
+            pass.free_textures(&delta.free, frame, &mut self.deletions);
+        }
+        return presented;
+    }
+
+    fn draw_and_present(
+        &mut self,
+        swapchain: &mut Swapchain,
+        stats: &mut PresentStats,
+        content: FrameContent,
+    ) -> Result<bool, RendererError> {
+        if content.analyse && self.analysis.is_none() && !self.analysis_failed {
+            let frames = self.frames_in_flight();
+            self.analysis = PresentAnalysis::new(&self.device, self.pipeline_cache.raw(), frames);
+            self.analysis_failed = self.analysis.is_none();
+        }
+        let Some(image_available) = self.sync.as_ref().map(|s| s.current().image_available) else {
+            return Ok(false);
+        };
+        if self.commands.is_none() || !std::mem::take(&mut self.begun) {
+            return Ok(false);
+        }
+        let steps = self.update_frame_targets(swapchain, content);
+        let scene = match (&self.post, self.targets.scene_color()) {
+            (Some(_), Some(_)) => Some(SceneTargets::new(&self.targets)),
+            _ => None,
+        };
+        // The scene has its own targets to multisample, and only the tonemapped scene and the sprites go to the
+        // window.
+        let window_samples = if scene.is_some() { 1 } else { self.samples };
+        swapchain.set_samples(window_samples);
+        let acquired = {
+            profile_scope!("acquire");
+            swapchain.acquire(image_available, stats)?
+        };
+        let Some(index) = acquired else {
+            return Ok(false);
+        };
+        let readback = match content.readback {
+            true => make_readback(&self.device, swapchain, self.frame),
+            false => None,
+        };
+        let analyse = match &mut self.analysis {
+            Some(analysis) if content.analyse => analysis.copy_desc(swapchain),
+            _ => None,
+        };
+        let descs = self.targets.scene_descs();
+        let (outline_descs, planar_descs) = (self.outlines.descs(), self.planar.descs());
+        let post_quality = self.targets.settings().map_or(0, |s| s.post_quality);
+
+        let Renderer {
+            device,
+            frame,
+            samples,
+            mesh_pass,
+            sprite_pass,
+            overlay_pass,
+            foliage,
+            wind,
+            water,
+            water_copy,
+            post,
+            ssao,
+            bloom,
+            dof_split,
+            dof_blur,
+            dof_composite,
+            motion_blur,
+            blur_settings,
+            outline_seed,
+            outline_flood,
+            outline_composite,
+            outlines,
+            planar,
+            portals,
+            analysis,
+            indirect_validator,
+            transients,
+            deletions,
+            commands: Some(commands),
+            sync: Some(sync),
+            breadcrumbs,
+            gpu_profiler,
+            ..
+        } = self
+        else {
+            unreachable!("The frame's sync and commands were checked for above");
+        };
+        let (frame, targets, extracted) = (*frame, scene.as_ref(), content.scene);
+        let mesh = mesh_pass.as_mut();
+        // Each feature's only here with everything it draws with, see FrameRecorder::features.
+        let depth_of_field = targets.zip(extracted).and_then(|(targets, extracted)| {
+            FrameDof::new([dof_split, dof_blur, dof_composite], targets, extracted)
+        });
+        let blurred = targets.and_then(|targets| {
+            let focused = focused(targets, depth_of_field.as_ref());
+            let images = vec![focused.view, targets.velocity_output()?.view];
+            let target = targets.motion_blur.as_ref();
+            FrameEffect::new(motion_blur, target, images, blur_settings.gpu_data())
+        });
+        let ssao = targets.and_then(|targets| {
+            let push = floats([SSAO_RADIUS, SSAO_STRENGTH, 0.0, 0.0]);
+            let images = vec![targets.depth_output().view];
+            FrameEffect::new(ssao, targets.ssao.as_ref(), images, push)
+        });
+        let bloom = targets.and_then(|targets| {
+            let input = post_input(targets, depth_of_field.as_ref(), blurred.as_ref());
+            let push = floats([BLOOM_THRESHOLD, 0.0, 0.0, 0.0]);
+            FrameEffect::new(bloom, targets.bloom.as_ref(), vec![input.view], push)
+        });
+        let water =
+            targets
+                .zip(extracted)
+                .filter(|_| mesh.is_some())
+                .and_then(|(targets, extracted)| {
+                    FrameWater::new(water, water_copy, targets, extracted)
+                });
+        let outline = extracted.filter(|_| mesh.is_some()).and_then(|extracted| {
+            let passes = [outline_seed, outline_flood, outline_composite];
+            FrameOutlines::new(passes, outlines, steps, extracted)
+        });
+        // Culled to and drawn through the last camera, in the scene.
+        let foliage_view = targets
+            .zip(extracted.and_then(|s| s.cameras.last()))
+            .filter(|_| mesh.is_some() && foliage.is_some())
+            .map(|(targets, camera)| {
+                let aspect = aspect_ratio(targets.color.extent);
+                let position = Vec3::from(camera.world.translation);
+                (
+                    camera.view_proj(aspect),
+                    camera.previous_view_proj(aspect),
+                    position,
+                )
+            });
+        let mut recorder = FrameRecorder {
+            content,
+            shadow_map: targets
+                .and_then(|t| t.shadow_map.as_ref())
+                .filter(|_| mesh.is_some()),
+            mesh,
+            foliage: foliage.as_mut(),
+            foliage_view,
+            wind: wind.gpu_data(content.time),
+            validator: indirect_validator.as_mut(),
+            planar: planar.targets(),
+            portals,
+            scene: targets
+                .zip(post.as_mut())
+                .map(|(targets, post)| FrameScene {
+                    targets,
+                    post,
+                    samples: *samples,
+                    post_quality,
+                }),
+            water,
+            ssao,
+            bloom,
+            depth_of_field,
+            motion_blur: blurred,
+            outline,
+            sprites: sprite_pass.as_mut().zip(content.sprites),
+            overlay: overlay_pass
+                .as_mut()
+                .zip(content.overlay.filter(|o| !o.primitives.is_empty())),
+            swapchain,
+            index,
+            analysis: analysis.as_mut().filter(|_| content.analyse),
+            readback: readback.as_ref(),
+            profiler: gpu_profiler.as_mut(),
+            breadcrumbs: breadcrumbs.as_mut(),
+            pending: Vec::new(),
+        };
+        let features = recorder.features(descs, outline_descs, planar_descs, analyse);
+        let (compiled, passes) = frame_graph(features);
+        let points: Vec<(&str, ReadbackPoint)> = content
+            .resources
+            .iter()
+            .filter_map(|name| match compiled.readback_point(name) {
+                Ok(point) => Some((name.as_str(), point)),
+                Err(e) => {
+                    log::warn!("Can't read back {name}: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        let transients = fit_transients(device, transients, &compiled, frame, deletions);
+        let recorded = transients.and_then(|transients| {
+            let cmd = commands.begin()?;
+            profile_scope!("record");
+            let at = FrameCmd { device, cmd, frame };
+            let image = swapchain_texture(swapchain, index);
+            let resources = GraphResources::new(transients).import_texture(
+                passes.swapchain,
+                &image,
+                TextureState::Undefined,
+                Some(TextureState::Present),
+            );
+            let resources = recorder.import(&passes, resources);
+            // SAFETY: The command buffer was just begun, and the image is acquired. Beginning the frame waited for
+            // the last one to use its slot.
+            let barriers = unsafe {
+                recorder.prepare(at)?;
+                let barriers =
+                    recorder.record_graph(at, &compiled, &passes, &points, &resources)?;
+                device.raw().end_command_buffer(cmd)?;
+                barriers
+            };
+            let render_finished = sync.render_finished(index)?;
+            Ok((cmd, render_finished, barriers))
+        });
+        let pending = std::mem::take(&mut recorder.pending);
+        self.graph = Some(compiled);
+        let (cmd, render_finished) = match recorded {
+            Ok((cmd, render_finished, barriers)) => {
+                self.barriers = Some(barriers);
+                (cmd, render_finished)
+            }
+            Err(e) => {
+                self.abandon_frame(swapchain, image_available, readback, pending);
+                return Err(e.into());
+            }
+        };
+        self.submit_frame(cmd, image_available, render_finished, readback, pending)?;
+        profile_scope!("queue_present");
+        swapchain.present(self.device.queue(), index, &[render_finished], stats)?;
+        return Ok(true);
+    }
+
+    /// Remake the outline and planar reflection targets for what `content` shows, and cull what the reflections and
+    /// portals see. The steps the outlines' flood takes, none without outlines.
+    fn update_frame_targets(&mut self, swapchain: &Swapchain, content: FrameContent) -> Vec<u32> {
+        // Outlined through the last camera, as wide as the widest outline needs the flood to reach.
+        let width = content
+            .scene
+            .and_then(|s| s.outlined().map(|(_, o)| o.width).reduce(f32::max))
+            .filter(|w| *w > 0.0);
+        let outline_passes = [
+            &self.outline_seed,
+            &self.outline_flood,
+            &self.outline_composite,
+        ];
+        let outlined = width.is_some()
+            && self.mesh_pass.is_some()
+            && self.post.is_some()
+            && outline_passes.iter().all(|p| p.is_some());
+        let extent = self
+            .targets
+            .scene_color()
+            .map(|t| [t.extent.width, t.extent.height]);
+        let (outlined, size) = (outlined && extent.is_some(), extent.unwrap_or([1, 1]));
+        let made = self.outlines.update(
+            &self.device,
+            outlined,
+            size,
+            self.frame,
+            &mut self.deletions,
+        );
+        if let Err(e) = made {
+            log::error!("Couldn't make the outline targets, nothing's outlined: {e}");
+        }
+        // Reflected through the last camera, into targets of their own, as long as there's a scene to show them in.
+        let reflectors = match content.scene {
+            Some(scene) if self.mesh_pass.is_some() && self.post.is_some() && extent.is_some() => {
+                &scene.reflectors[..]
+            }
+            _ => &[],
+        };
+        let made = self.planar.update(
+            &self.device,
+            reflectors,
+            size,
+            self.frame,
+            &mut self.deletions,
+        );
+        if let Err(e) = made {
+            log::error!("Couldn't make a planar reflection's targets: {e}");
+        }
+        if let Some(scene) = content.scene {
+            self.planar.cull(scene, draw::PipelineId(0));
+            // Drawn into the scene's targets, or the window's without them, like the cameras' own views.
+            let [width, height] = match extent {
+                Some(extent) => extent,
+                None => [swapchain.extent().width, swapchain.extent().height],
+            };
+            let aspect = width as f32 / height.max(1) as f32;
+            self.portals.cull(scene, aspect, draw::PipelineId(0));
+        }
+        return outline::flood_steps(width.unwrap_or(0.0));
+    }
+
+    /// Let go of swapchain image `index`, acquired signalling `image_available`, after recording its frame failed, and
+    /// of what the frame was going to read back into.
+    fn abandon_frame(
+        &mut self,
+        swapchain: &mut Swapchain,
+        image_available: vk::Semaphore,
+        readback: Option<SwapchainReadback>,
+        pending: Vec<PendingReadback<VulkanDevice>>,
+    ) {
+        // The image stays acquired, and the semaphore signalled, until something lets go of them. An empty submit
+        // waiting on the semaphore unsignals it, and the image goes back with the swapchain.
+        let wait = [image_available];
+        let stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let submit = vk::SubmitInfo::default()
+            .wait_semaphores(&wait)
+            .wait_dst_stage_mask(&stages);
+        // SAFETY: The semaphore's signal is pending from the acquire, with nothing else waiting on it.
+        let _ = unsafe {
+            self.device
+                .raw()
+                .queue_submit(self.device.queue(), &[submit], vk::Fence::null())
+        };
+        // SAFETY: Never submitted.
+        unsafe {
+            if let Some(readback) = readback {
+                self.device.destroy_buffer(readback.buffer);
+            }
+            for readback in pending {
+                readback.destroy(&self.device);
+            }
+        }
+        swapchain.invalidate();
+    }
+
+    /// Submit `cmd`, the frame's, waiting on `image_available` and signalling `render_finished`, and keep what it
+    /// reads back into until it's done.
+    fn submit_frame(
+        &mut self,
+        cmd: vk::CommandBuffer,
+        image_available: vk::Semaphore,
+        render_finished: vk::Semaphore,
+        readback: Option<SwapchainReadback>,
+        pending: Vec<PendingReadback<VulkanDevice>>,
+    ) -> VkResult<()> {
+        profile_scope!("submit");
+        let Some(sync) = &mut self.sync else {
+            return Ok(());
+        };
+        // SAFETY: Ended before this, and only handed out again once the frame's slot comes round.
+        let submitted = unsafe {
+            sync.submit(
+                self.device.queue(),
+                &[cmd],
+                &[(
+                    image_available,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                )],
+                &[render_finished],
+            )
+        };
+        match (readback, &submitted) {
+            (Some(readback), Ok(())) => self.readbacks.retire(self.frame, readback),
+            // SAFETY: Never submitted.
+            (Some(readback), Err(_)) => unsafe { self.device.destroy_buffer(readback.buffer) },
+            (None, _) => (),
+        }
+        for readback in pending {
+            match &submitted {
+                Ok(()) => self.resource_readbacks.retire(self.frame, readback),
+                // SAFETY: Never submitted.
+                Err(_) => unsafe { readback.destroy(&self.device) },
+            }
+        }
+        submitted?;
+        if let Some(profiler) = &mut self.gpu_profiler {
+            profiler.on_submit();
+        }
+        return Ok(());
+    }
+}
+
+/// How far round each pixel ambient occlusion looks, as a fraction of the scene's height.
+const SSAO_RADIUS: f32 = 0.04;
+/// How dark full ambient occlusion makes a pixel.
+const SSAO_STRENGTH: f32 = 0.8;
+/// How bright the scene has to be to bloom, in linear brightness.
+const BLOOM_THRESHOLD: f32 = 1.0;
+/// How much of the bloom goes back on the scene.
+const BLOOM_STRENGTH: f32 = 0.5;
+/// What the scene's multiplied by before it's tonemapped.
+const EXPOSURE: f32 = 1.0;
+
+/// What a frame draws, which decides its graph.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct FrameFeatures {
+    /// The scene's drawn into the [`QualityTargets`], made as these, and tonemapped up to the window, rather than
+    /// straight to it. Multisampled, it's resolved as its pass ends. With a shadow map, shadows are drawn into it
+    /// first, and with SSAO, bloom, depth of field or motion blur targets, those are drawn from it after. Depth of
+    /// field goes first, then motion blur, and what's after each reads the scene as it left it.
+    scene: Option<SceneDescs>,
+    /// With the scene, what's outlined is drawn into a mask, flooded out from back and forth between two seed
+    /// targets, and blended over the window after the scene.
+    outline: Option<OutlineFeatures>,
+    /// With the scene, each planar reflection's colour and depth targets, drawn before the scene, which shows them
+    /// on the reflectors' surfaces.
+    planar: Vec<[TextureDesc; 2]>,
+    /// With the scene, the water's drawn over it straight after, with the reflections, from a copy of it made as
+    /// this, and before anything reads it.
+    water: Option<TextureDesc>,
+    /// The debug overlay's painted over the window after everything else, and before it's copied out.
+    overlay: bool,
+    /// What the frame's copied into for analysing, if it is.
+    analyse: Option<TextureDesc>,
+    readback: bool,
+}
+
+/// What the outline targets are made as, and how many steps the flood takes, see [`FrameFeatures::outline`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct OutlineFeatures {
+    mask: TextureDesc,
+    seeds: TextureDesc,
+    floods: usize,
+}
+
+/// The scene's pass, and the targets it draws to.
+#[derive(Clone, Copy, Debug)]
+struct ScenePass {
+    pass: PassId,
+    color: ResourceId,
+    depth: ResourceId,
+    resolve: Option<ResourceId>,
+    depth_resolve: Option<ResourceId>,
+    /// Motion vectors, when something reads them, and where they're resolved to when multisampled.
+    velocity: Option<ResourceId>,
+    velocity_resolve: Option<ResourceId>,
+}
+
+impl ScenePass {
+    /// What passes after the scene read its colour from.
+    fn output(&self) -> ResourceId {
+        self.resolve.unwrap_or(self.color)
+    }
+
+    /// What passes after the scene read its depth from.
+    fn depth_output(&self) -> ResourceId {
+        self.depth_resolve.unwrap_or(self.depth)
+    }
+
+    /// What passes after the scene read its motion vectors from, `None` if it has none.
+    fn velocity_output(&self) -> Option<ResourceId> {
+        self.velocity_resolve.or(self.velocity)
+    }
+}
+
+/// A planar reflection's pass, and the targets it draws to.
+#[derive(Clone, Copy, Debug)]
+struct PlanarPass {
+    pass: PassId,
+    color: ResourceId,
+    depth: ResourceId,
+}
+
+/// Which of a frame's passes one is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FramePass {
+    Shadows,
+    /// The reflection, counting from 0 in [`PlanarReflections::targets`].
+    Planar(usize),
+    Scene,
+    WaterCopy,
+    Water,
+    Ssao,
+    Bloom,
+    DofSplit,
+    DofNear,
+    DofFar,
+    DofComposite,
+    MotionBlur,
+    OutlineMask,
+    OutlineSeed,
+    /// The flood's step, counting from 0.
+    OutlineFlood(usize),
+    Main,
+    Overlay,
+    Analysis,
+    Readback,
+}
+
+/// Depth of field's passes, each with the target it draws to.
+#[derive(Clone, Copy, Debug)]
+struct DofPasses {
+    split: (PassId, ResourceId),
+    near: (PassId, ResourceId),
+    far: (PassId, ResourceId),
+    composite: (PassId, ResourceId),
+}
+
+impl DofPasses {
+    /// In the order they're drawn, like the targets in [`QualityTargets::depth_of_field`].
+    fn all(&self) -> [(PassId, ResourceId); 4] {
+        [self.split, self.near, self.far, self.composite]
+    }
+}
+
+/// Outlines' passes before the window's. The seed pass draws to the first seed target, and each flood pass the other
+/// one from the one before.
+#[derive(Debug)]
+struct OutlinePasses {
+    mask: (PassId, ResourceId),
+    seed: PassId,
+    floods: Vec<PassId>,
+    seeds: [ResourceId; 2],
+}
+
+impl OutlinePasses {
+    /// Which of these `pass` is, `None` if it isn't one.
+    fn kind(&self, pass: PassId) -> Option<FramePass> {
+        if self.mask.0 == pass {
+            return Some(FramePass::OutlineMask);
+        } else if self.seed == pass {
+            return Some(FramePass::OutlineSeed);
+        }
+        let i = self.floods.iter().position(|p| *p == pass)?;
+        return Some(FramePass::OutlineFlood(i));
+    }
+}
+
+/// The passes of a frame [`Renderer::present`] draws, to tell them apart as the graph records them. The ones after
+/// the scene come with the target they draw to, or copy into.
+struct FramePasses {
+    swapchain: ResourceId,
+    shadows: Option<(PassId, ResourceId)>,
+    planar: Vec<PlanarPass>,
+    scene: Option<ScenePass>,
+    /// The water's pass draws to the scene's output, see [`ScenePass::output`].
+    water_copy: Option<(PassId, ResourceId)>,
+    water: Option<(PassId, ResourceId)>,
+    ssao: Option<(PassId, ResourceId)>,
+    bloom: Option<(PassId, ResourceId)>,
+    depth_of_field: Option<DofPasses>,
+    motion_blur: Option<(PassId, ResourceId)>,
+    outline: Option<OutlinePasses>,
+    /// Draws the window: the scene, or the scene tonemapped, then the outlines and sprites over it.
+    main: PassId,
+    /// Paints the debug overlay over the window.
+    overlay: Option<PassId>,
+    /// Copying the image out and analysing it, and the transient it's copied into.
+    analysis: Option<(PassId, ResourceId)>,
+    /// Copying the image out for [`Renderer::take_captures`], and the buffer it goes into.
+    readback: Option<(PassId, ResourceId)>,
+}
+
+impl FramePasses {
+    const SHADOWS: &str = "shadows";
+    const PLANAR: &str = "planar reflection";
+    const SCENE: &str = "scene";
+    const WATER_COPY: &str = "water scene copy";
+    const WATER: &str = "water";
+    const SSAO: &str = "ssao";
+    const BLOOM: &str = "bloom";
+    const DOF_SPLIT: &str = "dof split";
+    const DOF_NEAR: &str = "dof near";
+    const DOF_FAR: &str = "dof far";
+    const DEPTH_OF_FIELD: &str = "depth of field";
+    const MOTION_BLUR: &str = "motion blur";
+    const OUTLINE_MASK: &str = "outline mask";
+    const OUTLINE_SEED: &str = "outline seed";
+    const OUTLINE_FLOOD: &str = "outline flood";
+    const MAIN: &str = "main";
+    const OVERLAY: &str = "overlay";
+    const ANALYSIS: &str = "analysis";
+    const READBACK: &str = "readback";
+
+    /// Which pass `pass` is, `None` if it isn't one of these.
+    fn kind(&self, pass: PassId) -> Option<FramePass> {
+        let is = |p: Option<(PassId, ResourceId)>| p.is_some_and(|(p, _)| p == pass);
+        let kind = if pass == self.main {
+            FramePass::Main
+        } else if self.overlay == Some(pass) {
+            FramePass::Overlay
+        } else if self.scene.is_some_and(|s| s.pass == pass) {
+            FramePass::Scene
+        } else if is(self.shadows) {
+            FramePass::Shadows
+        } else if let Some(i) = self.planar.iter().position(|p| p.pass == pass) {
+            FramePass::Planar(i)
+        } else if is(self.water_copy) {
+            FramePass::WaterCopy
+        } else if is(self.water) {
+            FramePass::Water
+        } else if is(self.ssao) {
+            FramePass::Ssao
+        } else if is(self.bloom) {
+            FramePass::Bloom
+        } else if let Some(i) = self
+            .depth_of_field
+            .and_then(|dof| dof.all().iter().position(|&(p, _)| p == pass))
+        {
+            [
+                FramePass::DofSplit,
+                FramePass::DofNear,
+                FramePass::DofFar,
+                FramePass::DofComposite,
+            ][i]
+        } else if is(self.motion_blur) {
+            FramePass::MotionBlur
+        } else if let Some(kind) = self.outline.as_ref().and_then(|o| o.kind(pass)) {
+            kind
+        } else if is(self.analysis) {
+            FramePass::Analysis
+        } else if is(self.readback) {
+            FramePass::Readback
+        } else {
+            return None;
+        };
+        return Some(kind);
+    }
+
+    /// `pass`'s name, as the GPU profiler and breadcrumbs want it.
+    fn name(&self, pass: PassId) -> &'static str {
+        match self.kind(pass) {
+            Some(FramePass::Shadows) => FramePasses::SHADOWS,
+            Some(FramePass::Planar(_)) => FramePasses::PLANAR,
+            Some(FramePass::Scene) => FramePasses::SCENE,
+            Some(FramePass::WaterCopy) => FramePasses::WATER_COPY,
+            Some(FramePass::Water) => FramePasses::WATER,
+            Some(FramePass::Ssao) => FramePasses::SSAO,
+            Some(FramePass::Bloom) => FramePasses::BLOOM,
+            Some(FramePass::DofSplit) => FramePasses::DOF_SPLIT,
+            Some(FramePass::DofNear) => FramePasses::DOF_NEAR,
+            Some(FramePass::DofFar) => FramePasses::DOF_FAR,
+            Some(FramePass::DofComposite) => FramePasses::DEPTH_OF_FIELD,
+            Some(FramePass::MotionBlur) => FramePasses::MOTION_BLUR,
+            Some(FramePass::OutlineMask) => FramePasses::OUTLINE_MASK,
+            Some(FramePass::OutlineSeed) => FramePasses::OUTLINE_SEED,
+            Some(FramePass::OutlineFlood(_)) => FramePasses::OUTLINE_FLOOD,
+            Some(FramePass::Main) | None => FramePasses::MAIN,
+            Some(FramePass::Overlay) => FramePasses::OVERLAY,
+            Some(FramePass::Analysis) => FramePasses::ANALYSIS,
+            Some(FramePass::Readback) => FramePasses::READBACK,
+        }
+    }
+}
+
+/// The graph of a frame with `features`: the scene and the sprites drawn to the swapchain, the overlay over them,
+/// then copied out.
+fn frame_graph(features: FrameFeatures) -> (CompiledGraph, FramePasses) {
+    let mut graph = RenderGraph::new();
+    let swapchain = graph.swapchain();
+    // Drawing into `target` from `sources`.
+    let effect = |graph: &mut RenderGraph, name, target, sources: &[ResourceId]| {
+        let mut pass = Pass::new(name).with_access(target, Access::RenderTarget);
+        for &source in sources {
+            pass = pass.with_access(source, Access::ShaderRead);
+        }
+        (graph.add_pass(pass), target)
+    };
+    let shadows = features
+        .scene
+        .and_then(|descs| descs.shadow_map)
+        .map(|desc| {
+            let map = graph.import_texture("shadow map", desc);
+            effect(&mut graph, FramePasses::SHADOWS, map, &[])
+        });
+    // Lit like the scene, so shadowed too.
+    let planar: Vec<PlanarPass> = features
+        .planar
+        .iter()
+        .filter(|_| features.scene.is_some())
+        .map(|[color, depth]| {
+            let color = graph.import_texture("planar reflection", *color);
+            let depth = graph.import_texture("planar reflection depth", *depth);
+            let mut pass = Pass::new(FramePasses::PLANAR)
+                .with_access(color, Access::RenderTarget)
+                .with_access(depth, Access::RenderTarget);
+            if let Some((_, map)) = shadows {
+                pass = pass.with_access(map, Access::ShaderRead);
+            }
+            PlanarPass {
+                pass: graph.add_pass(pass),
+                color,
+                depth,
+            }
+        })
+        .collect();
+    let scene = features.scene.map(|descs| {
+        let color = graph.import_texture("scene colour", descs.color);
+        let depth = graph.import_texture("scene depth", descs.depth);
+        let resolve = descs
+            .resolve
+            .map(|desc| graph.import_texture("scene resolve", desc));
+        let depth_resolve = descs
+            .depth_resolve
+            .map(|desc| graph.import_texture("scene depth resolve", desc));
+        let velocity = descs
+            .velocity
+            .map(|desc| graph.import_texture("scene velocity", desc));
+        let velocity_resolve = descs
+            .velocity_resolve
+            .map(|desc| graph.import_texture("scene velocity resolve", desc));
+        let mut pass = Pass::new(FramePasses::SCENE)
+            .with_access(color, Access::RenderTarget)
+            .with_access(depth, Access::RenderTarget);
+        let others = [resolve, depth_resolve, velocity, velocity_resolve];
+        for other in others.into_iter().flatten() {
+            pass = pass.with_access(other, Access::RenderTarget);
+        }
+        if let Some((_, map)) = shadows {
+            pass = pass.with_access(map, Access::ShaderRead);
+        }
+        for reflection in &planar {
+            pass = pass.with_access(reflection.color, Access::ShaderRead);
+        }
+        ScenePass {
+            pass: graph.add_pass(pass),
+            color,
+            depth,
+            resolve,
+            depth_resolve,
+            velocity,
+            velocity_resolve,
+        }
+    });
+    // Over the scene, from a copy of it, before anything reads it.
+    let water_copy = scene.zip(features.water).map(|(scene, desc)| {
+        let copy = graph.add_texture("water scene copy", desc);
+        effect(&mut graph, FramePasses::WATER_COPY, copy, &[scene.output()])
+    });
+    let water = scene.zip(water_copy).map(|(scene, (_, copy))| {
+        let mut reads = vec![copy, scene.depth_output()];
+        reads.extend(planar.iter().map(|reflection| reflection.color));
+        effect(&mut graph, FramePasses::WATER, scene.output(), &reads)
+    });
+    let depth_of_field = scene
+        .zip(
+            features
+                .scene
+                .and_then(|d| d.dof_layer.zip(d.depth_of_field)),
+        )
+        .map(|(scene, (layer, desc))| {
+            let [split, near, far] =
+                ["dof split", "dof near", "dof far"].map(|name| graph.import_texture(name, layer));
+            let target = graph.import_texture("depth of field", desc);
+            let sharp = [scene.output(), scene.depth_output()];
+            let split = effect(&mut graph, FramePasses::DOF_SPLIT, split, &sharp);
+            let near = effect(&mut graph, FramePasses::DOF_NEAR, near, &[split.1]);
+            let far = effect(&mut graph, FramePasses::DOF_FAR, far, &[split.1]);
+            let reads = [sharp[0], sharp[1], near.1, far.1];
+            let composite = effect(&mut graph, FramePasses::DEPTH_OF_FIELD, target, &reads);
+            DofPasses {
+                split,
+                near,
+                far,
+                composite,
+            }
+        });
+    // What's after depth of field reads the scene with it.
+    let focused = |scene: ScenePass| depth_of_field.map_or(scene.output(), |d| d.composite.1);
+    let motion_blur = scene
+        .and_then(|scene| Some((scene, scene.velocity_output()?)))
+        .zip(features.scene.and_then(|d| d.motion_blur))
+        .map(|((scene, velocity), desc)| {
+            let target = graph.import_texture("motion blur", desc);
+            effect(
+                &mut graph,
+                FramePasses::MOTION_BLUR,
+                target,
+                &[focused(scene), velocity],
+            )
+        });
+    // What's after motion blur reads the scene blurred.
+    let blurred = |scene: ScenePass| motion_blur.map_or(focused(scene), |(_, target)| target);
+    let ssao = scene
+        .zip(features.scene.and_then(|d| d.ssao))
+        .map(|(scene, desc)| {
+            let target = graph.import_texture("ssao", desc);
+            effect(
+                &mut graph,
+                FramePasses::SSAO,
+                target,
+                &[scene.depth_output()],
+            )
+        });
+    let bloom = scene
+        .zip(features.scene.and_then(|d| d.bloom))
+        .map(|(scene, desc)| {
+            let target = graph.import_texture("bloom", desc);
+            effect(&mut graph, FramePasses::BLOOM, target, &[blurred(scene)])
+        });
+    let outline = scene.and(features.outline).map(|features| {
+        let mask = graph.import_texture("outline mask", features.mask);
+        let seeds = ["outline seeds", "outline flood"]
+            .map(|name| graph.import_texture(name, features.seeds));
+        let mask = effect(&mut graph, FramePasses::OUTLINE_MASK, mask, &[]);
+        let (seed, _) = effect(&mut graph, FramePasses::OUTLINE_SEED, seeds[0], &[mask.1]);
+        let floods = (0..features.floods)
+            .map(|i| {
+                let (from, to) = (seeds[i % 2], seeds[(i + 1) % 2]);
+                effect(&mut graph, FramePasses::OUTLINE_FLOOD, to, &[from]).0
+            })
+            .collect();
+        OutlinePasses {
+            mask,
+            seed,
+            floods,
+            seeds,
+        }
+    });
+    let mut main = Pass::new(FramePasses::MAIN).with_access(swapchain, Access::RenderTarget);
+    let reads = scene.map(blurred).into_iter();
+    let reads = reads.chain(
+        [ssao, bloom]
+            .into_iter()
+            .flatten()
+            .map(|(_, target)| target),
+    );
+    let outlines = outline
+        .iter()
+        .flat_map(|o| [o.mask.1, o.seeds[o.floods.len() % 2]]);
+    for read in reads.chain(outlines) {
+        main = main.with_access(read, Access::ShaderRead);
+    }
+    let main = graph.add_pass(main);
+    let overlay = features.overlay.then(|| {
+        graph.add_pass(Pass::new(FramePasses::OVERLAY).with_access(swapchain, Access::RenderTarget))
+    });
+    let copy = |graph: &mut RenderGraph, name, resource| {
+        graph.mark_output(resource);
+        let pass = graph.add_pass(
+            Pass::new(name)
+                .with_access(swapchain, Access::CopySrc)
+                .with_access(resource, Access::CopyDst),
+        );
+        (pass, resource)
+    };
+    // The analysis reads the copy in the same pass, which the graph doesn't see, so it's an output of sorts.
+    let analysis = features.analyse.map(|desc| {
+        let resource = graph.add_texture("analysis copy", desc);
+        copy(&mut graph, FramePasses::ANALYSIS, resource)
+    });
+    let readback = features.readback.then(|| {
+        let resource = graph.import("capture");
+        copy(&mut graph, FramePasses::READBACK, resource)
+    });
+    let passes = FramePasses {
+        swapchain,
+        shadows,
+        planar,
+        scene,
+        water_copy,
+        water,
+        ssao,
+        bloom,
+        depth_of_field,
+        motion_blur,
+        outline,
+        main,
+        overlay,
+        analysis,
+        readback,
+    };
+    return (graph.compile(), passes);
+}
+
+/// The transients `compiled` needs, keeping what `transients` has if it fits and retiring it as of `frame` if not.
+fn fit_transients<'a>(
+    device: &VulkanDevice,
+    transients: &'a mut Option<Transients<VulkanDevice>>,
+    compiled: &CompiledGraph,
+    frame: u64,
+    deletions: &mut DeletionQueue<Retired<VulkanDevice>>,
+) -> VkResult<&'a Transients<VulkanDevice>> {
+    if let Some(old) = transients.take_if(|t| !t.fits(compiled)) {
+        old.retire(frame, deletions);
+    }
+    if transients.is_none() {
+        *transients = Some(Transients::create(device, compiled)?);
+    }
+    return Ok(transients.as_ref().unwrap());
+}
+
+/// Swapchain image `index` as a texture, for the frame's graph.
+fn swapchain_texture(swapchain: &Swapchain, index: u32) -> VulkanTexture {
+    let vk_format = swapchain.format().format;
+    // Barriers only look at the aspect, and every swapchain format is colour.
+    let format = swapchain_texture_format(vk_format).unwrap_or(TextureFormat::Rgba8Unorm);
+    return VulkanTexture::borrowed(
+        swapchain.images()[index as usize],
+        swapchain.views()[index as usize],
+        format,
+        swapchain.extent(),
+    );
+}
+
+/// How a swapchain image in `format` comes back to the CPU. HDR10's packed formats don't.
+fn swapchain_capture_format(format: vk::Format) -> Option<CaptureFormat> {
+    let format = match format {
+        vk::Format::B8G8R8A8_SRGB => CaptureFormat::Bgra8Srgb,
+        vk::Format::R8G8B8A8_SRGB => CaptureFormat::Rgba8Srgb,
+        vk::Format::B8G8R8A8_UNORM => CaptureFormat::Bgra8Unorm,
+        vk::Format::R8G8B8A8_UNORM => CaptureFormat::Rgba8Unorm,
+        vk::Format::R16G16B16A16_SFLOAT => CaptureFormat::Rgba16Float,
+        _ => return None,
+    };
+    return Some(format);
+}
+
+/// A buffer to copy `swapchain`'s images into, on `frame`. `None` if they can't be copied out of.
+fn make_readback(
+    device: &VulkanDevice,
+    swapchain: &Swapchain,
+    frame: u64,
+) -> Option<SwapchainReadback> {
+    let vk_format = swapchain.format().format;
+    let format = swapchain_capture_format(vk_format).filter(|_| swapchain.copyable());
+    let Some(format) = format else {
+        log::warn!("Can't capture a swapchain of {vk_format:?}");
+        return None;
+    };
+    let extent = swapchain.extent();
+    let size = extent.width as u64 * extent.height as u64 * format.bytes_per_pixel() as u64;
+    let buffer = device.create_buffer(&BufferDesc {
+        size,
+        usage: BufferUsage::COPY_DST,
+        location: MemoryLocation::Readback,
+    });
+    match buffer {
+        Ok(buffer) => {
+            return Some(SwapchainReadback {
+                buffer,
+                width: extent.width,
+                height: extent.height,
+                format,
+                frame,
+            });
+        }
+        Err(e) => {
+            log::error!("Couldn't make a buffer to capture frame {frame} into: {e}");
+            return None;
+        }
+    }
+}
+
+/// A barrier moving colour `image` from `from` to `to`, waiting on the stages and accesses those say.
+fn color_barrier(
+    image: vk::Image,
+    from: TextureState,
+    to: TextureState,
+) -> vk::ImageMemoryBarrier<'static> {
+    let (old_layout, _, src_access) = state_info(from, vk::ImageAspectFlags::COLOR);
+    let (new_layout, _, dst_access) = state_info(to, vk::ImageAspectFlags::COLOR);
+    return vk::ImageMemoryBarrier::default()
+        .image(image)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1),
+        )
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access);
+}
+
+/// Copy swapchain `image`, in [`TextureState::CopySrc`], into `buffer`, tightly packed, for the CPU to read.
+///
+/// # Safety
+/// `cmd` must be recording, after whatever drew to the image, and `buffer` big enough.
+unsafe fn record_readback(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    buffer: vk::Buffer,
+) {
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        device.cmd_copy_image_to_buffer(
+            cmd,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1),
+                )
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })],
+        );
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[vk::BufferMemoryBarrier::default()
+                .buffer(buffer)
+                .size(vk::WHOLE_SIZE)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)],
+            &[],
+        );
+    }
+}
+
+/// The texture format of swapchain images in `format`, for copies of them. HDR10's packed formats have none.
+fn swapchain_texture_format(format: vk::Format) -> Option<TextureFormat> {
+    let format = match format {
+        vk::Format::B8G8R8A8_SRGB => TextureFormat::Bgra8Srgb,
+        vk::Format::R8G8B8A8_SRGB => TextureFormat::Rgba8Srgb,
+        vk::Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        vk::Format::R16G16B16A16_SFLOAT => TextureFormat::Rgba16Float,
+        _ => return None,
+    };
+    return Some(format);
+}
+
+/// Runs the [`AnalysisPass`] over presented frames, for [`Renderer::frame_analysis`]. Swapchain images can't be
+/// sampled, so each frame is copied into a texture that can be first, one of the frame graph's transients. The depth
+/// buffer can't be sampled either, so there's no depth range.
+struct PresentAnalysis {
+    pass: AnalysisPass,
+    /// The last swapchain format found not to copy, so it's only warned about once.
+    uncopyable: Option<vk::Format>,
+    latest: Option<FrameAnalysis>,
+}
+
+impl PresentAnalysis {
+    /// `None`, with why logged, if there's no shader or pipeline to analyse with.
+    fn new(
+        device: &VulkanDevice,
+        cache: vk::PipelineCache,
+        frames: u32,
+    ) -> Option<PresentAnalysis> {
+        let spirv = builtin_shader(
+            analysis::SHADER_ASSET,
+            "analysis.comp",
+            analysis::SHADER,
+            ShaderStage::Compute,
+        )?;
+        // SAFETY: The cache is the renderer's, and the pass is destroyed before it, see Renderer's drop.
+        let pass = unsafe { AnalysisPass::new(device, cache, &spirv, frames) };
+        let pass = pass
+            .inspect_err(|e| log::warn!("Couldn't make the frame analysis pass: {e}"))
+            .ok()?;
+        return Some(PresentAnalysis {
+            pass,
+            uncopyable: None,
+            latest: None,
+        });
+    }
+
+    /// Pick up what the frame that last used `frame`'s slot found.
+    ///
+    /// # Safety
+    /// As [`AnalysisPass::collect`].
+    unsafe fn collect(&mut self, device: &VulkanDevice, frame: u64) {
+        // SAFETY: Passed on to the caller.
+        if let Some(analysis) = unsafe { self.pass.collect(device, frame) } {
+            self.latest = Some(analysis);
+        }
+    }
+
+    /// The texture frames from `swapchain` are copied into. `None` if it can't be copied into one.
+    fn copy_desc(&mut self, swapchain: &Swapchain) -> Option<TextureDesc> {
+        let (vk_format, extent) = (swapchain.format().format, swapchain.extent());
+        let format = swapchain_texture_format(vk_format).filter(|_| swapchain.copyable());
+        let Some(format) = format else {
+            if self.uncopyable.replace(vk_format) != Some(vk_format) {
+                log::warn!("Can't analyse a swapchain of {vk_format:?}");
+            }
+            return None;
+        };
+        return Some(TextureDesc {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage: TextureUsage::COPY_DST | TextureUsage::SAMPLED,
+            samples: 1,
+        });
+    }
+
+    /// Record copying swapchain image `index` into `copy`, made as [`PresentAnalysis::copy_desc`] says, and
+    /// analysing it, as part of `frame`.
+    ///
+    /// # Safety
+    /// As [`AnalysisPass::record`], with `cmd` recording after whatever drew to the image, which has to be in
+    /// [`TextureState::CopySrc`] and the copy in [`TextureState::CopyDst`].
+    unsafe fn record(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        swapchain: &Swapchain,
+        index: u32,
+        copy: &VulkanTexture,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let image = swapchain.images()[index as usize];
+        let extent = swapchain.extent();
+        let layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            raw.cmd_copy_image(
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                copy.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageCopy::default()
+                    .src_subresource(layers)
+                    .dst_subresource(layers)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })],
+            );
+            raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[color_barrier(
+                    copy.image,
+                    TextureState::CopyDst,
+                    TextureState::ShaderRead,
+                )],
+            );
+            return self.pass.record(device, cmd, frame, copy, None);
+        }
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    unsafe fn destroy(mut self, device: &VulkanDevice) {
+        // SAFETY: Passed on to the caller.
+        unsafe { self.pass.destroy(device) };
+    }
+}
+
+/// Stand ins for the scene's [`QualityTargets`], for the frame's graph to have while the renderer's borrowed.
+struct SceneTargets {
+    shadow_map: Option<VulkanTexture>,
+    color: VulkanTexture,
+    depth: VulkanTexture,
+    resolve: Option<VulkanTexture>,
+    depth_resolve: Option<VulkanTexture>,
+    velocity: Option<VulkanTexture>,
+    velocity_resolve: Option<VulkanTexture>,
+    ssao: Option<VulkanTexture>,
+    bloom: Option<VulkanTexture>,
+    /// Like [`QualityTargets::depth_of_field`].
+    depth_of_field: Option<[VulkanTexture; 4]>,
+    motion_blur: Option<VulkanTexture>,
+}
+
+impl SceneTargets {
+    /// # Panics
+    /// If `targets` haven't been made.
+    fn new(targets: &QualityTargets<VulkanDevice>) -> SceneTargets {
+        SceneTargets {
+            shadow_map: targets.shadow_map().map(|t| t.stand_in()),
+            color: targets.scene_color().unwrap().stand_in(),
+            depth: targets.scene_depth().unwrap().stand_in(),
+            resolve: targets.scene_resolve().map(|t| t.stand_in()),
+            depth_resolve: targets.scene_depth_resolve().map(|t| t.stand_in()),
+            velocity: targets.scene_velocity().map(|t| t.stand_in()),
+            velocity_resolve: targets.velocity_resolve().map(|t| t.stand_in()),
+            ssao: targets.ssao().map(|t| t.stand_in()),
+            bloom: targets.bloom().map(|t| t.stand_in()),
+            depth_of_field: targets.depth_of_field().map(|ts| ts.map(|t| t.stand_in())),
+            motion_blur: targets.motion_blur().map(|t| t.stand_in()),
+        }
+    }
+
+    fn output(&self) -> &VulkanTexture {
+        self.resolve.as_ref().unwrap_or(&self.color)
+    }
+
+    fn depth_output(&self) -> &VulkanTexture {
+        self.depth_resolve.as_ref().unwrap_or(&self.depth)
+    }
+
+    fn velocity_output(&self) -> Option<&VulkanTexture> {
+        self.velocity_resolve.as_ref().or(self.velocity.as_ref())
+    }
+
+    /// What pipelines drawing the scene are built for, with `samples` per pixel.
+    fn formats(&self, samples: u32) -> TargetFormats {
+        TargetFormats {
+            color: vk_format(self.color.format),
+            depth: vk_format(self.depth.format),
+            velocity: self
+                .velocity
+                .as_ref()
+                .map_or(vk::Format::UNDEFINED, |v| vk_format(v.format)),
+            samples,
+        }
+    }
+}
+
+/// What pipelines drawing to `texture` alone are built for.
+fn target_formats(texture: &VulkanTexture) -> TargetFormats {
+    let format = vk_format(texture.format);
+    return match texture.format.is_depth() {
+        true => TargetFormats {
+            color: vk::Format::UNDEFINED,
+            depth: format,
+            velocity: vk::Format::UNDEFINED,
+            samples: 1,
+        },
+        false => TargetFormats {
+            color: format,
+            depth: vk::Format::UNDEFINED,
+            velocity: vk::Format::UNDEFINED,
+            samples: 1,
+        },
+    };
+}
+
+/// Width over height, for a projection onto `extent`.
+fn aspect_ratio(extent: vk::Extent2D) -> f32 {
+    return extent.width as f32 / extent.height.max(1) as f32;
+}
+
+/// Four floats as push constants.
+fn floats(values: [f32; 4]) -> Vec<u8> {
+    return values.into_iter().flat_map(f32::to_ne_bytes).collect();
+}
+
+/// The scene's colour in `targets` as what's after depth of field reads it: with it, if it's drawn this frame.
+fn focused<'a>(targets: &'a SceneTargets, dof: Option<&FrameDof<'a>>) -> &'a VulkanTexture {
+    return dof.map_or(targets.output(), |dof| &dof.targets[3]);
+}
+
+/// The scene's colour in `targets` as what's after motion blur reads it: blurred, if it's drawn this frame.
+fn post_input<'a>(
+    targets: &'a SceneTargets,
+    dof: Option<&FrameDof<'a>>,
+    motion_blur: Option<&FrameEffect<'a>>,
+) -> &'a VulkanTexture {
+    return motion_blur.map_or(focused(targets, dof), |blur| blur.target);
+}
+
+/// Where a frame's passes are recorded.
+#[derive(Clone, Copy)]
+struct FrameCmd<'a> {
+    device: &'a VulkanDevice,
+    cmd: vk::CommandBuffer,
+    frame: u64,
+}
+
+impl<'a> FrameCmd<'a> {
+    /// For pipelines drawing to attachments like `target`, `extent` big.
+    fn context(self, target: TargetFormats, extent: vk::Extent2D) -> PassContext<'a> {
+        return PassContext {
+            device: self.device,
+            cmd: self.cmd,
+            frame: self.frame,
+            target,
+            extent,
+        };
+    }
+
+    /// For pipelines drawing to `texture` alone.
+    fn offscreen(self, texture: &VulkanTexture) -> PassContext<'a> {
+        return self.context(target_formats(texture), texture.extent);
+    }
+
+    /// Record `pass` drawing `target` from `images`, in a pass on it alone.
+    ///
+    /// # Safety
+    /// As [`record_offscreen_pass`] and [`FullscreenPass::record`].
+    unsafe fn effect(
+        self,
+        pass: &mut FullscreenPass,
+        target: &VulkanTexture,
+        images: &[vk::ImageView],
+        push: &[u8],
+    ) -> VkResult<()> {
+        let ctx = self.offscreen(target);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return record_offscreen_pass(self.device.raw(), self.cmd, target, || {
+                pass.record(ctx, images, push)
+            });
+        }
+    }
+}
+
+/// A fullscreen pass drawing a target of its own from `images`.
+struct FrameEffect<'a> {
+    pass: &'a mut FullscreenPass,
+    target: &'a VulkanTexture,
+    images: Vec<vk::ImageView>,
+    push: Vec<u8>,
+}
+
+impl<'a> FrameEffect<'a> {
+    /// `None` without the pass or its target.
+    fn new(
+        pass: &'a mut Option<FullscreenPass>,
+        target: Option<&'a VulkanTexture>,
+        images: Vec<vk::ImageView>,
+        push: Vec<u8>,
+    ) -> Option<FrameEffect<'a>> {
+        return Some(FrameEffect {
+            pass: pass.as_mut()?,
+            target: target?,
+            images,
+            push,
+        });
+    }
+
+    /// # Safety
+    /// As [`FrameCmd::effect`].
+    unsafe fn record(&mut self, at: FrameCmd) -> VkResult<()> {
+        // SAFETY: Passed on to the caller.
+        unsafe { at.effect(self.pass, self.target, &self.images, &self.push) }
+    }
+}
+
+/// The scene drawn into its own targets, and tonemapped up to the window by `post`.
+struct FrameScene<'a> {
+    targets: &'a SceneTargets,
+    post: &'a mut FullscreenPass,
+    /// Per pixel, in the targets.
+    samples: u32,
+    /// As [`QualitySettings::post_quality`].
+    post_quality: i64,
+}
+
+impl SceneTargets {
+    /// `resources` with these imported as `ids`.
+    fn import<'r>(
+        &'r self,
+        ids: ScenePass,
+        resources: GraphResources<'r, VulkanDevice>,
+    ) -> GraphResources<'r, VulkanDevice> {
+        let undefined = TextureState::Undefined;
+        let mut resources = resources
+            .import_texture(ids.color, &self.color, undefined, None)
+            .import_texture(ids.depth, &self.depth, undefined, None);
+        let optional = [
+            (ids.resolve, &self.resolve),
+            (ids.depth_resolve, &self.depth_resolve),
+            (ids.velocity, &self.velocity),
+            (ids.velocity_resolve, &self.velocity_resolve),
+        ];
+        for (id, texture) in optional {
+            if let (Some(id), Some(texture)) = (id, texture) {
+                resources = resources.import_texture(id, texture, undefined, None);
+            }
+        }
+        return resources;
+    }
+}
+
+/// Depth of field's passes, drawing targets like [`QualityTargets::depth_of_field`] from the scene as the last
+/// camera's lens sees it.
+struct FrameDof<'a> {
+    split: &'a mut FullscreenPass,
+    blur: &'a mut FullscreenPass,
+    composite: &'a mut FullscreenPass,
+    targets: &'a [VulkanTexture; 4],
+    /// The scene's colour and depth.
+    scene: [vk::ImageView; 2],
+    /// For the near and far layers.
+    push: [Vec<u8>; 2],
+}
+
+impl<'a> FrameDof<'a> {
+    /// `None` without all three passes, the targets, or a lens to see `extracted` through.
+    fn new(
+        passes: [&'a mut Option<FullscreenPass>; 3],
+        targets: &'a SceneTargets,
+        extracted: &ExtractedScene,
+    ) -> Option<FrameDof<'a>> {
+        let [split, blur, composite] = passes;
+        let extent = targets.color.extent;
+        let (lens, projection) = depth_of_field::scene_lens(extracted, aspect_ratio(extent))?;
+        let size = [extent.width, extent.height];
+        return Some(FrameDof {
+            split: split.as_mut()?,
+            blur: blur.as_mut()?,
+            composite: composite.as_mut()?,
+            targets: targets.depth_of_field.as_ref()?,
+            scene: [targets.output().view, targets.depth_output().view],
+            push: [false, true].map(|far| depth_of_field::gpu_data(&lens, projection, size, far)),
+        });
+    }
+
+    /// Record `kind`, one of its passes.
+    ///
+    /// # Safety
+    /// As [`FrameCmd::effect`].
+    unsafe fn record(&mut self, at: FrameCmd, kind: FramePass) -> VkResult<()> {
+        let [split, near, far, composite] = self.targets;
+        let [near_push, far_push] = &self.push;
+        let [color, depth] = self.scene;
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return match kind {
+                FramePass::DofSplit => at.effect(self.split, split, &self.scene, near_push),
+                FramePass::DofNear => at.effect(self.blur, near, &[split.view], near_push),
+                FramePass::DofFar => at.effect(self.blur, far, &[split.view], far_push),
+                FramePass::DofComposite => {
+                    let images = [color, depth, near.view, far.view];
+                    at.effect(self.composite, composite, &images, near_push)
+                }
+                _ => Ok(()),
+            };
+        }
+    }
+}
+
+/// Water over the scene, seen through the last camera, drawn from `copy`'s copy of it.
+struct FrameWater<'a> {
+    pass: &'a mut WaterPass,
+    copy: &'a mut FullscreenPass,
+    targets: &'a SceneTargets,
+    view_proj: Mat4,
+}
+
+impl<'a> FrameWater<'a> {
+    /// `None` without both passes, or any water or camera in `extracted`.
+    fn new(
+        pass: &'a mut Option<WaterPass>,
+        copy: &'a mut Option<FullscreenPass>,
+        targets: &'a SceneTargets,
+        extracted: &ExtractedScene,
+    ) -> Option<FrameWater<'a>> {
+        let camera = extracted
+            .cameras
+            .last()
+            .filter(|_| !extracted.waters.is_empty())?;
+        return Some(FrameWater {
+            pass: pass.as_mut()?,
+            copy: copy.as_mut()?,
+            targets,
+            view_proj: camera.view_proj(aspect_ratio(targets.color.extent)),
+        });
+    }
+
+    /// Record uploading `extracted`'s water, reflecting what its reflector in `planar` sees, if it sees anything
+    /// this frame.
+    ///
+    /// # Safety
+    /// As [`WaterPass::prepare`].
+    unsafe fn prepare(
+        &mut self,
+        at: FrameCmd,
+        extracted: &ExtractedScene,
+        planar: &[PlanarTarget<VulkanDevice>],
+        time: f32,
+    ) -> VkResult<()> {
+        let reflections: Vec<_> = extracted
+            .waters
+            .iter()
+            .map(|w| {
+                let target = planar.iter().find(|t| t.entity == w.entity);
+                target.filter(|t| t.view.is_some()).map(|t| t.color.view)
+            })
+            .collect();
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return self.pass.prepare(
+                at.device,
+                at.cmd,
+                at.frame,
+                &extracted.waters,
+                &reflections,
+                time,
+            );
+        }
+    }
+
+    /// Record `kind`, copying the scene into `copy` or drawing over it from there.
+    ///
+    /// # Safety
+    /// As [`FrameCmd::effect`] and [`WaterPass::record`].
+    unsafe fn record(
+        &mut self,
+        at: FrameCmd,
+        kind: FramePass,
+        copy: &VulkanTexture,
+    ) -> VkResult<()> {
+        let (target, depth) = (self.targets.output(), self.targets.depth_output().view);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return match kind {
+                FramePass::WaterCopy => at.effect(self.copy, copy, &[target.view], &[]),
+                FramePass::Water => {
+                    let ctx = at.offscreen(target);
+                    record_water_pass(at.device.raw(), at.cmd, target, || {
+                        self.pass.record(ctx, copy.view, depth, self.view_proj)
+                    })
+                }
+                _ => Ok(()),
+            };
+        }
+    }
+}
+
+/// Outlines' passes, and what they draw to: the mask, and the two seed targets the flood goes back and forth
+/// between, like [`OutlineTargets::flood`].
+struct FrameOutlines<'a> {
+    seed: &'a mut FullscreenPass,
+    flood: &'a mut FullscreenPass,
+    composite: &'a mut FullscreenPass,
+    mask: &'a VulkanTexture,
+    seeds: [&'a VulkanTexture; 2],
+    /// As [`outline::flood_steps`].
+    steps: Vec<u32>,
+    /// What's outlined.
+    extracted: &'a ExtractedScene,
+}
+
+impl<'a> FrameOutlines<'a> {
+    /// `None` without all three passes, or the targets.
+    fn new(
+        passes: [&'a mut Option<FullscreenPass>; 3],
+        targets: &'a OutlineTargets<VulkanDevice>,
+        steps: Vec<u32>,
+        extracted: &'a ExtractedScene,
+    ) -> Option<FrameOutlines<'a>> {
+        let [seed, flood, composite] = passes;
+        let (seeds, flooded) = targets.flood(0)?;
+        return Some(FrameOutlines {
+            seed: seed.as_mut()?,
+            flood: flood.as_mut()?,
+            composite: composite.as_mut()?,
+            mask: targets.mask()?,
+            seeds: [seeds, flooded],
+            steps,
+            extracted,
+        });
+    }
+
+    /// `resources` with the targets imported as `ids`.
+    fn import<'r>(
+        &self,
+        ids: &OutlinePasses,
+        resources: GraphResources<'r, VulkanDevice>,
+    ) -> GraphResources<'r, VulkanDevice>
+    where
+        'a: 'r,
+    {
+        let undefined = TextureState::Undefined;
+        let ([seeds, flooded], [a, b]) = (ids.seeds, self.seeds);
+        return resources
+            .import_texture(ids.mask.1, self.mask, undefined, None)
+            .import_texture(seeds, a, undefined, None)
+            .import_texture(flooded, b, undefined, None);
+    }
+
+    /// Record `kind`, one of the passes before the window's, the mask drawn by `mesh`.
+    ///
+    /// # Safety
+    /// As [`FrameCmd::effect`] and [`MeshPass::record_outlines`].
+    unsafe fn record(
+        &mut self,
+        at: FrameCmd,
+        kind: FramePass,
+        mesh: &mut MeshPass,
+    ) -> VkResult<()> {
+        let [a, b] = self.seeds;
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return match kind {
+                FramePass::OutlineMask => {
+                    let ctx = at.offscreen(self.mask);
+                    record_offscreen_pass(at.device.raw(), at.cmd, self.mask, || {
+                        mesh.record_outlines(ctx, self.extracted)
+                    })
+                }
+                FramePass::OutlineSeed => at.effect(self.seed, a, &[self.mask.view], &[]),
+                FramePass::OutlineFlood(i) => {
+                    let (seeds, target) = if i.is_multiple_of(2) { (a, b) } else { (b, a) };
+                    let push = (self.steps[i] as i32).to_ne_bytes();
+                    at.effect(self.flood, target, &[seeds.view], &push)
+                }
+                _ => Ok(()),
+            };
+        }
+    }
+
+    /// Record blending the outlines over what `ctx` draws to, once the flood's done.
+    ///
+    /// # Safety
+    /// As [`FullscreenPass::record`].
+    unsafe fn record_composite(&mut self, ctx: PassContext) -> VkResult<()> {
+        let offsets = self.seeds[self.steps.len() % 2];
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.composite
+                .record(ctx, &[self.mask.view, offsets.view], &[])
+        }
+    }
+}
+
+/// What records each of a frame's passes, borrowed from the renderer for the frame. Each feature's only here with
+/// everything it draws with, and [`FrameRecorder::features`] only asks the graph for the passes of what's here, so
+/// every pass the graph records has what it needs.
+struct FrameRecorder<'a> {
+    content: FrameContent<'a>,
+    /// Draws the scene, its shadows, reflections and outline mask. Without it, none of those are drawn.
+    mesh: Option<&'a mut MeshPass>,
+    /// With the 3D pass and the scene.
+    shadow_map: Option<&'a VulkanTexture>,
+    foliage: Option<&'a mut FoliagePass>,
+    /// The last camera's view and projection, the last frame's, and where it is, for the foliage to be culled to and
+    /// drawn through. `None` without the 3D pass or the scene.
+    foliage_view: Option<(Mat4, Mat4, Vec3)>,
+    /// As [`Wind::gpu_data`].
+    wind: [f32; 4],
+    validator: Option<&'a mut IndirectValidator>,
+    planar: &'a [PlanarTarget<VulkanDevice>],
+    portals: &'a PortalViews,
+    scene: Option<FrameScene<'a>>,
+    water: Option<FrameWater<'a>>,
+    ssao: Option<FrameEffect<'a>>,
+    bloom: Option<FrameEffect<'a>>,
+    depth_of_field: Option<FrameDof<'a>>,
+    motion_blur: Option<FrameEffect<'a>>,
+    outline: Option<FrameOutlines<'a>>,
+    sprites: Option<(&'a mut SpritePass, &'a SpriteBatch)>,
+    overlay: Option<(&'a mut OverlayPass, &'a OverlayOutput)>,
+    /// Drawn to as swapchain image `index`.
+    swapchain: &'a Swapchain,
+    index: u32,
+    analysis: Option<&'a mut PresentAnalysis>,
+    readback: Option<&'a SwapchainReadback>,
+    profiler: Option<&'a mut GpuProfiler>,
+    breadcrumbs: Option<&'a mut Breadcrumbs>,
+    /// Graph textures read back so far, for [`Renderer::take_resource_captures`].
+    pending: Vec<PendingReadback<VulkanDevice>>,
+}
+
+impl<'a> FrameRecorder<'a> {
+    /// What's drawn, for [`frame_graph`], with the scene's targets made as `descs`, the outlines' as `outline`, the
+    /// reflections' as `planar` and what's analysed copied into `analyse`. What there's nothing here to draw with
+    /// isn't drawn.
+    fn features(
+        &self,
+        descs: Option<SceneDescs>,
+        outline: Option<[TextureDesc; 2]>,
+        planar: Vec<[TextureDesc; 2]>,
+        analyse: Option<TextureDesc>,
+    ) -> FrameFeatures {
+        let descs = descs.filter(|_| self.scene.is_some());
+        let dof = self.depth_of_field.is_some();
+        return FrameFeatures {
+            scene: descs.map(|descs| SceneDescs {
+                shadow_map: descs.shadow_map.filter(|_| self.shadow_map.is_some()),
+                ssao: descs.ssao.filter(|_| self.ssao.is_some()),
+                bloom: descs.bloom.filter(|_| self.bloom.is_some()),
+                dof_layer: descs.dof_layer.filter(|_| dof),
+                depth_of_field: descs.depth_of_field.filter(|_| dof),
+                motion_blur: descs.motion_blur.filter(|_| self.motion_blur.is_some()),
+                ..descs
+            }),
+            outline: outline
+                .zip(self.outline.as_ref())
+                .map(|([mask, seeds], outline)| OutlineFeatures {
+                    mask,
+                    seeds,
+                    floods: outline.steps.len(),
+                }),
+            planar,
+            // A copy of what the water's drawn over.
+            water: descs
+                .filter(|_| self.water.is_some())
+                .map(|descs| TextureDesc {
+                    usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                    samples: 1,
+                    ..descs.resolve.unwrap_or(descs.color)
+                }),
+            overlay: self.overlay.is_some(),
+            analyse: analyse.filter(|_| self.analysis.is_some()),
+            readback: self.readback.is_some(),
+        };
+    }
+
+    /// `resources` with what the passes draw to imported as `passes` has them.
+    fn import<'r>(
+        &self,
+        passes: &FramePasses,
+        resources: GraphResources<'r, VulkanDevice>,
+    ) -> GraphResources<'r, VulkanDevice>
+    where
+        'a: 'r,
+    {
+        let mut resources = resources;
+        if let (Some(ids), Some(scene)) = (passes.scene, &self.scene) {
+            resources = scene.targets.import(ids, resources);
+        }
+        let undefined = TextureState::Undefined;
+        let effect = |effect: &Option<FrameEffect<'a>>| effect.as_ref().map(|e| e.target);
+        let targets = [
+            (passes.shadows, self.shadow_map),
+            (passes.ssao, effect(&self.ssao)),
+            (passes.bloom, effect(&self.bloom)),
+            (passes.motion_blur, effect(&self.motion_blur)),
+        ];
+        for (pass, texture) in targets {
+            if let (Some((_, id)), Some(texture)) = (pass, texture) {
+                resources = resources.import_texture(id, texture, undefined, None);
+            }
+        }
+        if let (Some(ids), Some(dof)) = (passes.depth_of_field, &self.depth_of_field) {
+            for ((_, id), texture) in ids.all().into_iter().zip(dof.targets) {
+                resources = resources.import_texture(id, texture, undefined, None);
+            }
+        }
+        for (ids, target) in passes.planar.iter().zip(self.planar) {
+            resources = resources
+                .import_texture(ids.color, &target.color, undefined, None)
+                .import_texture(ids.depth, &target.depth, undefined, None);
+        }
+        if let (Some(ids), Some(outline)) = (&passes.outline, &self.outline) {
+            resources = outline.import(ids, resources);
+        }
+        if let (Some((_, id)), Some(readback)) = (passes.readback, self.readback) {
+            resources = resources.import_buffer(id, &readback.buffer);
+        }
+        return resources;
+    }
+
+    /// Record uploading what the 3D pass, the foliage and the water draw with this frame.
+    ///
+    /// # Safety
+    /// As [`MeshPass::prepare`], [`FoliagePass::prepare`] and [`WaterPass::prepare`], before any of the passes.
+    unsafe fn prepare(&mut self, at: FrameCmd) -> VkResult<()> {
+        let Some(extracted) = self.content.scene else {
+            return Ok(());
+        };
+        let FrameCmd { device, cmd, frame } = at;
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            if let Some(pass) = self.mesh.as_deref_mut() {
+                let input = MeshPrepare {
+                    scene: extracted,
+                    views: self.content.views,
+                    reflections: self.planar,
+                    portals: self.portals,
+                    shadow_map: self.shadow_map,
+                };
+                pass.prepare(device, cmd, frame, input, self.validator.as_deref_mut())?;
+            }
+            if let Some(pass) = self.foliage.as_deref_mut() {
+                let view = self
+                    .foliage_view
+                    .map(|(view_proj, _, position)| (view_proj, position));
+                pass.prepare(device, cmd, frame, extracted, view, self.wind)?;
+            }
+            if let Some(water) = &mut self.water {
+                water.prepare(at, extracted, self.planar, self.content.time)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Record `compiled`, the graph of `passes`, reading back what's at `points` as it goes.
+    ///
+    /// # Safety
+    /// `at.cmd` must be recording after [`FrameRecorder::prepare`], with what's in `resources` imported by
+    /// [`FrameRecorder::import`].
+    unsafe fn record_graph(
+        &mut self,
+        at: FrameCmd,
+        compiled: &CompiledGraph,
+        passes: &FramePasses,
+        points: &[(&str, ReadbackPoint)],
+        resources: &GraphResources<VulkanDevice>,
+    ) -> VkResult<BarrierStats> {
+        let (device, cmd) = (at.device.raw(), at.cmd);
+        let mut drawn = Ok(());
+        // SAFETY: Passed on to the caller.
+        let mut encoder = unsafe {
+            if let Some(profiler) = self.profiler.as_deref_mut() {
+                profiler.reset(cmd);
+            }
+            at.device.encoder_for(cmd)
+        };
+        let barriers = compiled.record(&mut encoder, resources, |pass, encoder, resources| {
+            if drawn.is_err() {
+                return;
+            }
+            let name = passes.name(pass);
+            // SAFETY: Passed on to the caller. The graph put what the pass uses in the states it wants.
+            unsafe {
+                let profiler = self.profiler.as_deref_mut();
+                let scope = profiler.and_then(|p| p.begin_scope(cmd, name));
+                let breadcrumbs = self.breadcrumbs.as_deref_mut();
+                let marker = breadcrumbs.and_then(|b| b.begin_pass(device, cmd, name));
+                if let Some(kind) = passes.kind(pass) {
+                    drawn = self.record(at, passes, kind, resources);
+                }
+                for (name, point) in points.iter().filter(|(_, p)| p.after == pass) {
+                    let Some(texture) = resources.texture(point.resource) else {
+                        continue;
+                    };
+                    match PendingReadback::record(
+                        at.device, encoder, name, point, texture, at.frame,
+                    ) {
+                        Ok(readback) => self.pending.push(readback),
+                        Err(e) => log::error!("Couldn't read back {name}: {e}"),
+                    }
+                }
+                if let Some(breadcrumbs) = self.breadcrumbs.as_deref_mut() {
+                    breadcrumbs.end_pass(device, cmd, marker);
+                }
+                if let Some(profiler) = self.profiler.as_deref_mut() {
+                    profiler.end_scope(cmd, scope);
+                }
+            }
+        });
+        drop(encoder);
+        return drawn.map(|()| barriers);
+    }
+
+    /// Record `kind`, one of `passes`, with `resources` as the graph has them.
+    ///
+    /// # Safety
+    /// `at.cmd` must be recording after [`FrameRecorder::prepare`], with what the pass uses in the states the graph
+    /// put it in.
+    unsafe fn record(
+        &mut self,
+        at: FrameCmd,
+        passes: &FramePasses,
+        kind: FramePass,
+        resources: &GraphResources<VulkanDevice>,
+    ) -> VkResult<()> {
+        let (device, cmd) = (at.device.raw(), at.cmd);
+        let transient = |pass: Option<(PassId, ResourceId)>| resources.texture(pass?.1);
+        // SAFETY: Passed on to the caller. What isn't here isn't in the graph, see FrameRecorder::features.
+        unsafe {
+            return match kind {
+                FramePass::Shadows => match (self.shadow_map, self.mesh.as_deref_mut()) {
+                    (Some(map), Some(mesh)) => {
+                        let ctx = at.offscreen(map);
+                        record_offscreen_pass(device, cmd, map, || mesh.record_shadows(ctx))
+                    }
+                    _ => Ok(()),
+                },
+                FramePass::Planar(i) => match (self.planar.get(i), self.mesh.as_deref_mut()) {
+                    (Some(target), Some(mesh)) => {
+                        let formats = TargetFormats {
+                            color: vk_format(target.color.format),
+                            depth: vk_format(target.depth.format),
+                            velocity: vk::Format::UNDEFINED,
+                            samples: 1,
+                        };
+                        let ctx = at.context(formats, target.color.extent);
+                        record_planar_pass(device, cmd, target, || mesh.record_reflection(ctx, i))
+                    }
+                    _ => Ok(()),
+                },
+                FramePass::Scene => self.record_scene(at),
+                FramePass::WaterCopy | FramePass::Water => {
+                    match (&mut self.water, transient(passes.water_copy)) {
+                        (Some(water), Some(copy)) => water.record(at, kind, copy),
+                        _ => Ok(()),
+                    }
+                }
+                FramePass::Ssao => self.ssao.as_mut().map_or(Ok(()), |e| e.record(at)),
+                FramePass::Bloom => self.bloom.as_mut().map_or(Ok(()), |e| e.record(at)),
+                FramePass::MotionBlur => self.motion_blur.as_mut().map_or(Ok(()), |e| e.record(at)),
+                FramePass::DofSplit
+                | FramePass::DofNear
+                | FramePass::DofFar
+                | FramePass::DofComposite => match &mut self.depth_of_field {
+                    Some(dof) => dof.record(at, kind),
+                    None => Ok(()),
+                },
+                FramePass::OutlineMask | FramePass::OutlineSeed | FramePass::OutlineFlood(_) => {
+                    match (&mut self.outline, self.mesh.as_deref_mut()) {
+                        (Some(outline), Some(mesh)) => outline.record(at, kind, mesh),
+                        _ => Ok(()),
+                    }
+                }
+                FramePass::Main => self.record_main(at),
+                FramePass::Overlay => self.record_overlay(at),
+                FramePass::Analysis => match (&mut self.analysis, transient(passes.analysis)) {
+                    (Some(analysis), Some(copy)) => {
+                        analysis.record(at.device, cmd, at.frame, self.swapchain, self.index, copy)
+                    }
+                    _ => Ok(()),
+                },
+                FramePass::Readback => {
+                    if let Some(readback) = self.readback {
+                        let image = self.swapchain.images()[self.index as usize];
+                        let extent = self.swapchain.extent();
+                        record_readback(device, cmd, image, extent, readback.buffer.buffer);
+                    }
+                    Ok(())
+                }
+            };
+        }
+    }
+
+    /// Record drawing the scene and its foliage with `ctx`, wherever it's drawn.
+    ///
+    /// # Safety
+    /// As [`MeshPass::record`] and [`FoliagePass::record`].
+    unsafe fn draw_scene(&mut self, ctx: PassContext) -> VkResult<()> {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            if let (Some(pass), Some(scene)) = (self.mesh.as_deref_mut(), self.content.scene) {
+                pass.record(ctx, scene, self.content.views)?;
+            }
+            if let (Some(pass), Some((view_proj, previous, _))) =
+                (self.foliage.as_deref_mut(), self.foliage_view)
+            {
+                pass.record(ctx, view_proj, previous)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Record the scene's pass, into its own targets.
+    ///
+    /// # Safety
+    /// As [`FrameRecorder::record`].
+    unsafe fn record_scene(&mut self, at: FrameCmd) -> VkResult<()> {
+        let Some((targets, samples)) = self.scene.as_ref().map(|s| (s.targets, s.samples)) else {
+            return Ok(());
+        };
+        let ctx = at.context(targets.formats(samples), targets.color.extent);
+        // SAFETY: Passed on to the caller.
+        unsafe { record_scene_pass(at.device.raw(), at.cmd, targets, || self.draw_scene(ctx)) }
+    }
+
+    /// Record the window's pass: the scene, or the scene tonemapped, then the outlines and sprites over it.
+    ///
+    /// # Safety
+    /// As [`FrameRecorder::record`].
+    unsafe fn record_main(&mut self, at: FrameCmd) -> VkResult<()> {
+        let (swapchain, index) = (self.swapchain, self.index);
+        let window = TargetFormats {
+            color: swapchain.format().format,
+            depth: swapchain
+                .depth()
+                .map_or(vk::Format::UNDEFINED, |d| vk_format(d.format)),
+            velocity: vk::Format::UNDEFINED,
+            samples: swapchain.samples(),
+        };
+        let ctx = at.context(window, swapchain.extent());
+        let (device, cmd) = (at.device.raw(), at.cmd);
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return record_main_pass(device, cmd, swapchain, index, LinearColor::BLACK, || {
+                match &mut self.scene {
+                    Some(scene) => {
+                        // Whatever's off is stood in for by the scene, and not read.
+                        let input = post_input(
+                            scene.targets,
+                            self.depth_of_field.as_ref(),
+                            self.motion_blur.as_ref(),
+                        );
+                        let color = input.view;
+                        let ssao = self.ssao.as_ref().map(|e| e.target);
+                        let bloom = self.bloom.as_ref().map(|e| e.target);
+                        let push = floats([
+                            EXPOSURE,
+                            ssao.is_some() as u32 as f32,
+                            bloom.map_or(0.0, |_| BLOOM_STRENGTH),
+                            (scene.post_quality >= 1) as u32 as f32,
+                        ]);
+                        let images = [
+                            color,
+                            ssao.map_or(color, |t| t.view),
+                            bloom.map_or(color, |t| t.view),
+                        ];
+                        scene.post.record(ctx, &images, &push)?
+                    }
+                    None => self.draw_scene(ctx)?,
+                }
+                // Over the scene as it's shown, and under the sprites.
+                if let Some(outline) = &mut self.outline {
+                    outline.record_composite(ctx)?;
+                }
+                if let Some((pass, sprites)) = &mut self.sprites {
+                    pass.record(ctx, sprites)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Record painting the debug overlay over the window.
+    ///
+    /// # Safety
+    /// As [`FrameRecorder::record`].
+    unsafe fn record_overlay(&mut self, at: FrameCmd) -> VkResult<()> {
+        let Some((pass, output)) = &mut self.overlay else {
+            return Ok(());
+        };
+        let (swapchain, index) = (self.swapchain, self.index);
+        // Straight onto the presented image, past any multisampling and depth.
+        let window = TargetFormats {
+            color: swapchain.format().format,
+            depth: vk::Format::UNDEFINED,
+            velocity: vk::Format::UNDEFINED,
+            samples: 1,
+        };
+        let ctx = at.context(window, swapchain.extent());
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            return record_overlay_pass(at.device.raw(), at.cmd, swapchain, index, || {
+                pass.record(ctx, output)
+            });
+        }
+    }
+}
+
+/// Clear the `scene` targets, in [`TextureState::RenderTarget`], and record `draw` into a pass on them, resolving
+/// them if they're multisampled. Motion vectors, if there are any, are the second colour attachment, cleared to
+/// nothing moving.
+///
+/// # Safety
+/// `cmd` must be recording.
+unsafe fn record_scene_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    scene: &SceneTargets,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let target = TextureState::RenderTarget;
+    let resolve = |texture: &Option<VulkanTexture>| {
+        texture.as_ref().map(|r| rendering::Resolve {
+            image: r.image,
+            view: r.view,
+            before: target,
+            after: target,
+        })
+    };
+    let mut colors = vec![rendering::Attachment {
+        image: scene.color.image,
+        view: scene.color.view,
+        before: target,
+        after: target,
+        load: LoadOp::Clear(LinearColor::BLACK),
+        // Multisampled, only the resolve is read.
+        store: scene.resolve.is_none(),
+        resolve: resolve(&scene.resolve),
+    }];
+    if let Some(velocity) = &scene.velocity {
+        colors.push(rendering::Attachment {
+            image: velocity.image,
+            view: velocity.view,
+            before: target,
+            after: target,
+            load: LoadOp::Clear(LinearColor::TRANSPARENT),
+            store: scene.velocity_resolve.is_none(),
+            resolve: resolve(&scene.velocity_resolve),
+        });
+    }
+    let desc = RenderingDesc {
+        extent: scene.color.extent,
+        colors: &colors,
+        depth: Some(rendering::Attachment {
+            image: scene.depth.image,
+            view: scene.depth.view,
+            before: target,
+            after: target,
+            load: LoadOp::Clear(NDC_FAR),
+            store: scene.depth_resolve.is_none(),
+            resolve: resolve(&scene.depth_resolve),
+        }),
+        stencil: scene.depth.format.has_stencil(),
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+/// Record `draw` into a pass on a planar reflection's targets, cleared first, in [`TextureState::RenderTarget`].
+///
+/// # Safety
+/// `cmd` must be recording.
+unsafe fn record_planar_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    reflection: &PlanarTarget<VulkanDevice>,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let target = TextureState::RenderTarget;
+    let colors = [rendering::Attachment {
+        image: reflection.color.image,
+        view: reflection.color.view,
+        before: target,
+        after: target,
+        load: LoadOp::Clear(LinearColor::BLACK),
+        store: true,
+        resolve: None,
+    }];
+    let desc = RenderingDesc {
+        extent: reflection.color.extent,
+        colors: &colors,
+        depth: Some(rendering::Attachment {
+            image: reflection.depth.image,
+            view: reflection.depth.view,
+            before: target,
+            after: target,
+            load: LoadOp::Clear(NDC_FAR),
+            // Only the colour is shown.
+            store: false,
+            resolve: None,
+        }),
+        stencil: false,
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+/// Record `draw` into a pass on `texture` alone, in [`TextureState::RenderTarget`]. Depth is cleared first, and
+/// colour left for `draw` to cover.
+///
+/// # Safety
+/// `cmd` must be recording.
+unsafe fn record_offscreen_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    texture: &VulkanTexture,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let target = TextureState::RenderTarget;
+    let colors = [rendering::Attachment {
+        image: texture.image,
+        view: texture.view,
+        before: target,
+        after: target,
+        load: LoadOp::DontCare,
+        store: true,
+        resolve: None,
+    }];
+    let depth = texture.format.is_depth();
+    let desc = RenderingDesc {
+        extent: texture.extent,
+        colors: if depth { &[] } else { &colors },
+        depth: depth.then_some(rendering::Attachment {
+            image: texture.image,
+            view: texture.view,
+            before: target,
+            after: target,
+            load: LoadOp::Clear(NDC_FAR),
+            store: true,
+            resolve: None,
+        }),
+        stencil: false,
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+/// Record `draw` into a pass over what's in `texture`, in [`TextureState::RenderTarget`], without depth.
+///
+/// # Safety
+/// `cmd` must be recording.
+unsafe fn record_water_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    texture: &VulkanTexture,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let target = TextureState::RenderTarget;
+    let colors = [rendering::Attachment {
+        image: texture.image,
+        view: texture.view,
+        before: target,
+        after: target,
+        load: LoadOp::Load,
+        store: true,
+        resolve: None,
+    }];
+    let desc = RenderingDesc {
+        extent: texture.extent,
+        colors: &colors,
+        depth: None,
+        stencil: false,
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+/// Clear swapchain image `index`, through its multisampled colour target if it has one, and its depth buffer if
+/// there's one, and record `draw` into the pass.
+///
+/// # Safety
+/// `cmd` must be recording, and the image acquired with its contents up for grabs, in
+/// [`TextureState::RenderTarget`].
+unsafe fn record_main_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    swapchain: &Swapchain,
+    index: u32,
+    color: LinearColor,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let image = swapchain.images()[index as usize];
+    let view = swapchain.views()[index as usize];
+    let load = LoadOp::Clear(color);
+    let colors = [match swapchain.color() {
+        Some(target) => target.attachment(load, image, view),
+        None => rendering::Attachment {
+            image,
+            view,
+            before: TextureState::RenderTarget,
+            after: TextureState::RenderTarget,
+            load,
+            store: true,
+            resolve: None,
+        },
+    }];
+    let depth = swapchain.depth();
+    let desc = RenderingDesc {
+        extent: swapchain.extent(),
+        colors: &colors,
+        depth: depth.map(|d| d.attachment(LoadOp::Clear(NDC_FAR))),
+        stencil: depth.is_some_and(|d| d.format.has_stencil()),
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+/// Record `draw` into a pass on swapchain image `index` alone, keeping what's been drawn to it.
+///
+/// # Safety
+/// `cmd` must be recording, and the image acquired, in [`TextureState::RenderTarget`].
+unsafe fn record_overlay_pass(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    swapchain: &Swapchain,
+    index: u32,
+    draw: impl FnOnce() -> VkResult<()>,
+) -> VkResult<()> {
+    let target = TextureState::RenderTarget;
+    let colors = [rendering::Attachment {
+        image: swapchain.images()[index as usize],
+        view: swapchain.views()[index as usize],
+        before: target,
+        after: target,
+        load: LoadOp::Load,
+        store: true,
+        resolve: None,
+    }];
+    let desc = RenderingDesc {
+        extent: swapchain.extent(),
+        colors: &colors,
+        depth: None,
+        stencil: false,
+    };
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        let pass = rendering::begin(device, cmd, &desc);
+        let drawn = draw();
+        rendering::end(device, cmd, pass);
+        return drawn;
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        log::info!("Shutting down the renderer on {}", self.adapter.name);
+        self.sync = None;
+        self.commands = None;
+        self.descriptors = None;
+        self.bindless = None;
+        self.targets.retire_all(u64::MAX, &mut self.deletions);
+        self.outlines.retire_all(u64::MAX, &mut self.deletions);
+        self.planar.retire_all(u64::MAX, &mut self.deletions);
+        if let Some(transients) = self.transients.take() {
+            transients.retire(u64::MAX, &mut self.deletions);
+        }
+        self.deletions.flush(&self.device);
+        for readback in self.readbacks.drain() {
+            // SAFETY: The flush waited for the GPU to go idle.
+            unsafe { self.device.destroy_buffer(readback.buffer) };
+        }
+        for readback in self.resource_readbacks.drain() {
+            // SAFETY: As above.
+            unsafe { readback.destroy(&self.device) };
+        }
+        if let Some(analysis) = self.analysis.take() {
+            // SAFETY: As above.
+            unsafe { analysis.destroy(&self.device) };
+        }
+        if let Some(mut pass) = self.sprite_pass.take() {
+            // SAFETY: The flush waited for the GPU to go idle.
+            unsafe { pass.destroy(&self.device) };
+        }
+        if let Some(mut pass) = self.overlay_pass.take() {
+            // SAFETY: As above.
+            unsafe { pass.destroy(&self.device) };
+        }
+        if let Some(mut pass) = self.mesh_pass.take() {
+            // SAFETY: As above.
+            unsafe { pass.destroy(&self.device) };
+        }
+        let fullscreen = [
+            self.post.take(),
+            self.ssao.take(),
+            self.bloom.take(),
+            self.dof_split.take(),
+            self.dof_blur.take(),
+            self.dof_composite.take(),
+            self.motion_blur.take(),
+            self.outline_seed.take(),
+            self.outline_flood.take(),
+            self.outline_composite.take(),
+            self.water_copy.take(),
+        ];
+        for mut pass in fullscreen.into_iter().flatten() {
+            // SAFETY: As above.
+            unsafe { pass.destroy(&self.device) };
+        }
+        if let Some(mut pass) = self.water.take() {
+            // SAFETY: As above.
+            unsafe { pass.destroy(&self.device) };
+        }
+        if let Some(mut pass) = self.foliage.take() {
+            // SAFETY: As above.
+            unsafe { pass.destroy(&self.device) };
+        }
+        if let Some(mut validator) = self.indirect_validator.take() {
+            // SAFETY: As above.
+            unsafe { validator.destroy(&self.device) };
+        }
+        self.gpu_profiler = None;
+        let pipelines: Vec<_> = self
+            .retired_pipelines
+            .drain()
+            .into_iter()
+            .chain(self.pipelines.drain())
+            .collect();
+        // SAFETY: The flush waited for the GPU to go idle, and nothing else makes pipelines.
+        unsafe {
+            if let Some(mut breadcrumbs) = self.breadcrumbs.take() {
+                breadcrumbs.destroy(self.device.raw());
+            }
+            self.destroy_pipelines(pipelines);
+            self.layouts.destroy(self.device.raw());
+            self.pipeline_cache.destroy(self.device.raw());
+        }
+        // The device waits for itself to go idle, then takes the instance down with it.
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        FrameFeatures, FramePass, FramePasses, OutlineFeatures, Renderer, RendererError,
+        frame_graph,
+    };
+    use crate::{
+        app::info::AppInfo,
+        jobs::JobSystem,
+        render::{
+            VK_ENTRY,
+            hal::{TextureDesc, TextureFormat, TextureUsage},
+            outline, planar,
+            quality::{QualitySettings, SceneDescs},
+        },
+    };
+
+    #[test]
+    pub fn lifecycle() {
+        let info = AppInfo::new("renderer test");
+        let jobs = JobSystem::new(Some(2));
+        match Renderer::new(&info, None, None, false, &jobs) {
+            Ok(renderer) => {
+                assert!(!renderer.presents());
+                assert!(!renderer.device_name().is_empty());
+                drop(renderer);
+                // Nothing was leaked, so it can be made again.
+                Renderer::new(&info, None, None, false, &jobs).unwrap();
+            }
+            Err(RendererError::NoLoader) => assert!(VK_ENTRY.is_none()),
+            Err(RendererError::NoDevice) => {}
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    pub fn frame_graph_draws_before_copying_out() {
+        for bits in 0..8192u32 {
+            let [
+                scene,
+                resolve,
+                analyse,
+                readback,
+                shadows,
+                ssao,
+                bloom,
+                blur,
+                dof,
+                outlined,
+                reflecting,
+                water,
+                painted,
+            ] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12].map(|i| bits & (1 << i) != 0);
+            let settings = QualitySettings {
+                shadow_quality: shadows as i64,
+                msaa: if resolve { 4 } else { 1 },
+                render_scale: 0.5,
+                ssao,
+                post_quality: if bloom { 2 } else { 1 },
+                depth_of_field: dof,
+                motion_blur: blur,
+            };
+            let target = |format| TextureDesc {
+                width: 8,
+                height: 8,
+                format,
+                usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                samples: 1,
+            };
+            // Up to 3 pixels wide, so 3 steps of the flood.
+            let floods = outline::flood_steps(3.0).len();
+            let descs = scene.then(|| SceneDescs::new(&settings, [8, 8]));
+            let features = FrameFeatures {
+                scene: descs,
+                outline: outlined.then(|| OutlineFeatures {
+                    mask: target(outline::MASK_FORMAT),
+                    seeds: target(outline::SEED_FORMAT),
+                    floods,
+                }),
+                planar: match reflecting {
+                    true => vec![[planar::COLOR_FORMAT, planar::DEPTH_FORMAT].map(target)],
+                    false => Vec::new(),
+                },
+                water: descs.filter(|_| water).map(|d| TextureDesc {
+                    usage: TextureUsage::RENDER_TARGET | TextureUsage::SAMPLED,
+                    samples: 1,
+                    ..d.resolve.unwrap_or(d.color)
+                }),
+                overlay: painted,
+                analyse: analyse.then_some(TextureDesc {
+                    width: 4,
+                    height: 4,
+                    format: TextureFormat::Bgra8Srgb,
+                    usage: TextureUsage::COPY_DST | TextureUsage::SAMPLED,
+                    samples: 1,
+                }),
+                readback,
+            };
+            let (compiled, passes) = frame_graph(features);
+            assert!(compiled.validate().is_empty());
+            let copied = analyse || scene && water;
+            assert_eq!(compiled.transient_bytes().iter().sum::<u64>() > 0, copied);
+            let order = compiled.order();
+            let at = |pass| order.iter().position(|p| *p == pass).unwrap();
+            let main = at(passes.main);
+            assert_eq!(passes.name(passes.main), FramePasses::MAIN);
+            // The overlay straight after the window, before it's copied out.
+            assert_eq!(passes.overlay.is_some(), painted);
+            if let Some(overlay) = passes.overlay {
+                assert_eq!(at(overlay), main + 1);
+                assert_eq!(passes.name(overlay), FramePasses::OVERLAY);
+            }
+            match passes.scene {
+                Some(scene) => {
+                    assert_eq!(scene.resolve.is_some(), resolve);
+                    assert_eq!(scene.depth_resolve.is_some(), resolve);
+                    assert_eq!(scene.velocity.is_some(), blur);
+                    assert_eq!(scene.velocity_resolve.is_some(), blur && resolve);
+                    // Shadows go before the scene, and what's drawn from it after, all before the window.
+                    let before = passes.shadows.map(|(p, _)| at(p));
+                    assert_eq!(before.is_some(), shadows);
+                    assert!(before.is_none_or(|b| b < at(scene.pass)));
+                    // Reflections too, after the shadows they're lit with.
+                    assert_eq!(passes.planar.len(), reflecting as usize);
+                    for reflection in &passes.planar {
+                        let drawn = at(reflection.pass);
+                        assert!(before.is_none_or(|b| b < drawn) && drawn < at(scene.pass));
+                        assert_eq!(passes.kind(reflection.pass), Some(FramePass::Planar(0)));
+                        assert_eq!(passes.name(reflection.pass), FramePasses::PLANAR);
+                    }
+                    // The water straight after the scene, from a copy of it, with the reflections.
+                    assert_eq!(passes.water.is_some(), water);
+                    let watered =
+                        passes
+                            .water_copy
+                            .zip(passes.water)
+                            .map(|((copy, _), (pass, target))| {
+                                assert!(at(scene.pass) < at(copy) && at(copy) < at(pass));
+                                assert_eq!(target, scene.output());
+                                assert_eq!(passes.kind(copy), Some(FramePass::WaterCopy));
+                                assert_eq!(passes.name(pass), FramePasses::WATER);
+                                at(pass)
+                            });
+                    let afters = [
+                        (passes.ssao, ssao),
+                        (passes.bloom, bloom),
+                        (passes.motion_blur, blur),
+                    ]
+                    .into_iter()
+                    .chain(
+                        passes
+                            .depth_of_field
+                            .map_or([None; 4], |d| d.all().map(Some))
+                            .map(|p| (p, dof)),
+                    );
+                    for after in afters {
+                        assert_eq!(after.0.is_some(), after.1);
+                        let after = after.0.map(|(p, _)| at(p));
+                        let first = watered.unwrap_or(at(scene.pass));
+                        assert!(after.is_none_or(|a| first < a && a < main));
+                    }
+                    // Bloom reads the scene blurred, and motion blur reads it with depth of field.
+                    if let (Some((blur, _)), Some((bloom, _))) = (passes.motion_blur, passes.bloom)
+                    {
+                        assert!(at(blur) < at(bloom));
+                    }
+                    if let Some(dof) = passes.depth_of_field {
+                        let [split, near, far, composite] = dof.all().map(|(p, _)| at(p));
+                        assert!(split < near.min(far) && near.max(far) < composite);
+                        let after = passes.motion_blur.or(passes.bloom).map(|(p, _)| at(p));
+                        assert!(after.is_none_or(|a| composite < a));
+                        assert_eq!(passes.name(dof.composite.0), FramePasses::DEPTH_OF_FIELD);
+                    }
+                    // The mask, the seeds and each step of the flood, one after another, before the window.
+                    assert_eq!(passes.outline.is_some(), outlined);
+                    if let Some(outline) = &passes.outline {
+                        assert_eq!(outline.floods.len(), floods);
+                        let flood = [outline.mask.0, outline.seed]
+                            .into_iter()
+                            .chain(outline.floods.iter().copied())
+                            .map(at);
+                        assert!(flood.clone().is_sorted() && flood.max().unwrap() < main);
+                        let last = *outline.floods.last().unwrap();
+                        assert_eq!(passes.kind(last), Some(FramePass::OutlineFlood(floods - 1)));
+                        assert_eq!(passes.name(outline.seed), FramePasses::OUTLINE_SEED);
+                    }
+                    let effects = ssao as usize + bloom as usize + blur as usize + 4 * dof as usize;
+                    let outlines = outlined as usize * (2 + floods);
+                    assert_eq!(
+                        main,
+                        1 + shadows as usize
+                            + reflecting as usize
+                            + 2 * water as usize
+                            + effects
+                            + outlines
+                    );
+                    // Multisampled or not, the scene's colour can be captured, with the water over it if it's drawn
+                    // there.
+                    let point = compiled.readback_point("scene colour").unwrap();
+                    let last = passes
+                        .water
+                        .filter(|_| !resolve)
+                        .map_or(scene.pass, |(p, _)| p);
+                    assert_eq!(point.after, last);
+                }
+                None => {
+                    assert_eq!(main, 0);
+                    assert!(passes.shadows.is_none() && passes.ssao.is_none());
+                    assert!(passes.outline.is_none() && passes.planar.is_empty());
+                    assert!(passes.water_copy.is_none() && passes.water.is_none());
+                }
+            }
+            let drawn = match scene {
+                true => {
+                    2 + shadows as usize
+                        + reflecting as usize
+                        + 2 * water as usize
+                        + ssao as usize
+                        + bloom as usize
+                        + blur as usize
+                        + 4 * dof as usize
+                        + outlined as usize * (2 + floods)
+                }
+                false => 1,
+            };
+            let copies = analyse as usize + readback as usize;
+            assert_eq!(order.len(), drawn + painted as usize + copies);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! Passes on dynamic rendering: attachments are described where they're drawn to, with the states their images
+//! come in and leave in, and [`begin`] and [`end`] put the layout transitions in around `vkCmdBeginRendering` and
+//! `vkCmdEndRendering`. There are no render passes or framebuffers anywhere.
+//!
+//! [`begin`] also sets the viewport and scissor to the whole area, with the viewport flipped to keep clip space Y
+//! up as [`crate::math`] says, since pipelines leave them dynamic. This works on raw images, so the swapchain's can
+//! go through it. [`CommandEncoder::begin_rendering`](super::hal::CommandEncoder::begin_rendering) is the same for
+//! hal's textures.
+//!
+//! A multisampled attachment can resolve into a single sampled image of the same size and format as the pass ends,
+//! colour averaging its samples and depth taking the first, as the only mode every device has. The multisampled
+//! image usually doesn't need storing after, and never leaving tile memory is most of what makes MSAA cheap on
+//! mobile GPUs.
+
+use ash::vk;
+
+use super::{
+    hal::{
+        LoadOp, TextureState,
+        vulkan::{VulkanDevice, state_info},
+    },
+    pipeline::TargetFormats,
+};
+use crate::color::LinearColor;
+
+/// An image drawn to in a pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Attachment<C> {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    /// The state it's in before the pass. From [`TextureState::Undefined`], whatever was there is lost.
+    pub before: TextureState,
+    /// The state it's left in after. [`TextureState::Undefined`] leaves it where it is, for nothing to read.
+    pub after: TextureState,
+    pub load: LoadOp<C>,
+    /// Whether what's drawn is kept, rather than thrown away at the end of the pass like a depth buffer can be.
+    pub store: bool,
+    /// Where a multisampled attachment is resolved to.
+    pub resolve: Option<Resolve>,
+}
+
+/// A single sampled image an attachment is resolved into, with the states it's moved in and out of.
+#[derive(Clone, Copy, Debug)]
+pub struct Resolve {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub before: TextureState,
+    pub after: TextureState,
+}
+
+/// Where a pass's draws are recorded: a command buffer inside a pass on attachments like `target`, `extent` big,
+/// as part of `frame`.
+#[derive(Clone, Copy)]
+pub struct PassContext<'a> {
+    pub device: &'a VulkanDevice,
+    pub cmd: vk::CommandBuffer,
+    /// Picks which of a pass's per-frame slots are used.
+    pub frame: u64,
+    pub target: TargetFormats,
+    pub extent: vk::Extent2D,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderingDesc<'a> {
+    pub extent: vk::Extent2D,
+    pub colors: &'a [Attachment<LinearColor>],
+    pub depth: Option<Attachment<f32>>,
+    /// The depth attachment has stencil too, which is cleared to 0 along with it and kept if it is.
+    pub stencil: bool,
+}
+
+const WRITES: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+);
+
+/// A pass that's been begun, holding what it takes to end it.
+#[must_use = "A pass has to be ended"]
+pub struct ActiveRendering {
+    barriers: Vec<vk::ImageMemoryBarrier<'static>>,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+}
+
+fn load_op<C>(load: &LoadOp<C>) -> vk::AttachmentLoadOp {
+    match load {
+        LoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+        LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+        LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+    }
+}
+
+fn store_op(store: bool) -> vk::AttachmentStoreOp {
+    match store {
+        true => vk::AttachmentStoreOp::STORE,
+        false => vk::AttachmentStoreOp::DONT_CARE,
+    }
+}
+
+fn depth_aspect(stencil: bool) -> vk::ImageAspectFlags {
+    match stencil {
+        true => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        false => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// An image a pass draws or resolves to, and the states it goes between.
+struct Target {
+    image: vk::Image,
+    aspect: vk::ImageAspectFlags,
+    before: TextureState,
+    after: TextureState,
+}
+
+/// Every image in `desc`, resolve targets included.
+fn targets(desc: &RenderingDesc) -> Vec<Target> {
+    let mut targets = Vec::new();
+    let mut add = |image, aspect, before, after, resolve: Option<Resolve>| {
+        targets.push(Target {
+            image,
+            aspect,
+            before,
+            after,
+        });
+        if let Some(r) = resolve {
+            targets.push(Target {
+                image: r.image,
+                aspect,
+                before: r.before,
+                after: r.after,
+            });
+        }
+    };
+    for a in desc.colors {
+        add(
+            a.image,
+            vk::ImageAspectFlags::COLOR,
+            a.before,
+            a.after,
+            a.resolve,
+        );
+    }
+    if let Some(a) = &desc.depth {
+        let aspect = depth_aspect(desc.stencil);
+        add(a.image, aspect, a.before, a.after, a.resolve);
+    }
+    return targets;
+}
+
+/// Barriers taking each target from `from` to `to`, with the stages they wait on and hold up.
+fn transitions(
+    targets: &[Target],
+    from: impl Fn(&Target) -> TextureState,
+    to: impl Fn(&Target) -> TextureState,
+) -> ActiveRendering {
+    let mut batch = ActiveRendering {
+        barriers: Vec::new(),
+        src_stage: vk::PipelineStageFlags::empty(),
+        dst_stage: vk::PipelineStageFlags::empty(),
+    };
+    for target in targets {
+        // Nothing can be moved into undefined, it just means whatever's there isn't needed any more.
+        if to(target) == TextureState::Undefined {
+            continue;
+        }
+        let aspect = target.aspect;
+        let (old_layout, src_stage, src_access) = state_info(from(target), aspect);
+        let (new_layout, dst_stage, dst_access) = state_info(to(target), aspect);
+        batch.src_stage |= src_stage;
+        batch.dst_stage |= dst_stage;
+        // Swapchain images come in undefined, and their acquire semaphores are waited on at the stage they're drawn
+        // in, so the transition has to wait there too or it can happen before the image is even acquired. Waiting on
+        // the same writes also keeps a depth buffer frames share from being drawn to by two at once.
+        let src_access = match from(target) {
+            TextureState::Undefined => {
+                batch.src_stage |= dst_stage;
+                dst_access & WRITES
+            }
+            _ => src_access,
+        };
+        batch.barriers.push(
+            vk::ImageMemoryBarrier::default()
+                .image(target.image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(aspect)
+                        .level_count(1)
+                        .layer_count(1),
+                )
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access),
+        );
+    }
+    return batch;
+}
+
+/// # Safety
+/// `cmd` must be recording, outside a pass.
+unsafe fn record_barriers(device: &ash::Device, cmd: vk::CommandBuffer, batch: &ActiveRendering) {
+    if batch.barriers.is_empty() {
+        return;
+    }
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        device.cmd_pipeline_barrier(
+            cmd,
+            batch.src_stage,
+            batch.dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &batch.barriers,
+        );
+    }
+}
+
+/// `info` resolving into `resolve` with `mode`, if there's anything to resolve into.
+fn with_resolve<'a>(
+    info: vk::RenderingAttachmentInfo<'a>,
+    resolve: Option<Resolve>,
+    layout: vk::ImageLayout,
+    mode: vk::ResolveModeFlags,
+) -> vk::RenderingAttachmentInfo<'a> {
+    let Some(resolve) = resolve else {
+        return info;
+    };
+    return info
+        .resolve_mode(mode)
+        .resolve_image_view(resolve.view)
+        .resolve_image_layout(layout);
+}
+
+/// Move the attachments into place and start drawing to them. Every attachment transitions, even ones already
+/// drawn to, so passes drawing to the same image one after the other wait on each other.
+///
+/// # Safety
+/// `cmd` must be recording outside a pass, and the attachments' images and resolve targets in their `before` states,
+/// with views the size of `desc.extent`. Resolve targets have to be single sampled and in their attachment's format.
+pub unsafe fn begin(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    desc: &RenderingDesc,
+) -> ActiveRendering {
+    let targets = targets(desc);
+    let before = transitions(&targets, |t| t.before, |_| TextureState::RenderTarget);
+    let colors: Vec<_> = desc
+        .colors
+        .iter()
+        .map(|a| {
+            let clear = match a.load {
+                LoadOp::Clear(color) => color,
+                _ => LinearColor::BLACK,
+            };
+            let layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+            let info = vk::RenderingAttachmentInfo::default()
+                .image_view(a.view)
+                .image_layout(layout)
+                .load_op(load_op(&a.load))
+                .store_op(store_op(a.store))
+                .clear_value(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: clear.to_array(),
+                    },
+                });
+            with_resolve(info, a.resolve, layout, vk::ResolveModeFlags::AVERAGE)
+        })
+        .collect();
+    let (depth_layout, ..) = state_info(TextureState::RenderTarget, depth_aspect(desc.stencil));
+    let depth = desc.depth.map(|a| {
+        let depth = match a.load {
+            LoadOp::Clear(depth) => depth,
+            _ => 0.0,
+        };
+        let info = vk::RenderingAttachmentInfo::default()
+            .image_view(a.view)
+            .image_layout(depth_layout)
+            .load_op(load_op(&a.load))
+            .store_op(store_op(a.store))
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            });
+        // Averaging depth makes no sense, and the first sample is what every device can do.
+        with_resolve(
+            info,
+            a.resolve,
+            depth_layout,
+            vk::ResolveModeFlags::SAMPLE_ZERO,
+        )
+    });
+    let mut info = vk::RenderingInfo::default()
+        .render_area(desc.extent.into())
+        .layer_count(1)
+        .color_attachments(&colors);
+    if let Some(depth) = &depth {
+        info = info.depth_attachment(depth);
+        if desc.stencil {
+            info = info.stencil_attachment(depth);
+        }
+    }
+
+    let (width, height) = (desc.extent.width as f32, desc.extent.height as f32);
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        record_barriers(device, cmd, &before);
+        device.cmd_begin_rendering(cmd, &info);
+        device.cmd_set_viewport(
+            cmd,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: height,
+                width,
+                height: -height,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        device.cmd_set_scissor(cmd, 0, &[desc.extent.into()]);
+    }
+    return transitions(&targets, |_| TextureState::RenderTarget, |t| t.after);
+}
+
+/// Stop drawing, and move the attachments into their `after` states.
+///
+/// # Safety
+/// `cmd` must be the one `pass` was begun in, still recording.
+pub unsafe fn end(device: &ash::Device, cmd: vk::CommandBuffer, pass: ActiveRendering) {
+    // SAFETY: Passed on to the caller.
+    unsafe {
+        device.cmd_end_rendering(cmd);
+        record_barriers(device, cmd, &pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Attachment, RenderingDesc, Resolve, targets, transitions};
+    use crate::{
+        color::LinearColor,
+        render::hal::{
+            Attachment as HalAttachment, BufferDesc, BufferUsage, CommandEncoder, Device, LoadOp,
+            MemoryLocation, TextureDesc, TextureFormat, TextureState, TextureUsage,
+        },
+        test_support::headless,
+    };
+    use ash::vk::{self, Handle};
+
+    #[test]
+    pub fn transitions_around_the_pass() {
+        // Multisampled, resolved into something to present and thrown away.
+        let colors = [Attachment {
+            image: vk::Image::from_raw(1),
+            view: vk::ImageView::null(),
+            before: TextureState::Undefined,
+            after: TextureState::Undefined,
+            load: LoadOp::Clear(LinearColor::BLACK),
+            store: false,
+            resolve: Some(Resolve {
+                image: vk::Image::from_raw(3),
+                view: vk::ImageView::null(),
+                before: TextureState::Undefined,
+                after: TextureState::Present,
+            }),
+        }];
+        let desc = RenderingDesc {
+            extent: vk::Extent2D {
+                width: 8,
+                height: 8,
+            },
+            colors: &colors,
+            stencil: false,
+            depth: Some(Attachment {
+                image: vk::Image::from_raw(2),
+                view: vk::ImageView::null(),
+                before: TextureState::Undefined,
+                after: TextureState::Undefined,
+                load: LoadOp::Clear(0.0),
+                store: false,
+                resolve: None,
+            }),
+        };
+        let targets = targets(&desc);
+        let before = transitions(&targets, |t| t.before, |_| TextureState::RenderTarget);
+        assert_eq!(before.barriers.len(), 3);
+        assert_eq!(
+            before.barriers[2].new_layout,
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+        );
+        assert!(
+            before
+                .dst_stage
+                .contains(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+        );
+        let after = transitions(&targets, |_| TextureState::RenderTarget, |t| t.after);
+        assert_eq!(after.barriers.len(), 1);
+        assert_eq!(after.barriers[0].image, vk::Image::from_raw(3));
+        assert_eq!(
+            after.barriers[0].new_layout,
+            vk::ImageLayout::PRESENT_SRC_KHR
+        );
+
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let target = |format, usage| {
+            device
+                .create_texture(&TextureDesc {
+                    width: 4,
+                    height: 4,
+                    format,
+                    usage: TextureUsage::RENDER_TARGET | usage,
+                    samples: 1,
+                })
+                .unwrap()
+        };
+        let color = target(TextureFormat::Rgba8Unorm, TextureUsage::COPY_SRC);
+        let depth = target(TextureFormat::Depth32Float, TextureUsage::default());
+        let readback = device
+            .create_buffer(&BufferDesc {
+                size: 4 * 4 * 4,
+                usage: BufferUsage::COPY_DST,
+                location: MemoryLocation::Readback,
+            })
+            .unwrap();
+        // SAFETY: Waited on before it's read or destroyed.
+        unsafe {
+            let mut cmds = device.begin_commands().unwrap();
+            let colors = [HalAttachment {
+                texture: &color,
+                before: TextureState::Undefined,
+                after: TextureState::CopySrc,
+                load: LoadOp::Clear(LinearColor::WHITE),
+                store: true,
+                resolve: None,
+            }];
+            cmds.begin_rendering(
+                &colors,
+                Some(HalAttachment {
+                    texture: &depth,
+                    before: TextureState::Undefined,
+                    after: TextureState::RenderTarget,
+                    load: LoadOp::Clear(0.0),
+                    store: false,
+                    resolve: None,
+                }),
+            );
+            cmds.end_rendering();
+            cmds.copy_texture_to_buffer(&color, &readback);
+            device.wait(device.submit(cmds).unwrap()).unwrap();
+
+            let mut out = vec![0; 4 * 4 * 4];
+            device.read_buffer(&readback, 0, &mut out).unwrap();
+            assert!(out.iter().all(|&b| b == 255));
+            device.destroy_texture(color);
+            device.destroy_texture(depth);
+            device.destroy_buffer(readback);
+        }
+    }
+}
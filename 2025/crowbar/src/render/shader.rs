@@ -0,0 +1,454 @@
+//! Shader sources, and what happens when they don't build.
+//!
+//! Sources get stitched together from `#include "file"` lines, remembering which file and line every line of the
+//! result came from, so a compiler complaining about line 340 of the stitched source can be pointed back at the
+//! include the mistake is actually in. A [`PipelineSlot`] keeps the last pipeline that built going while the errors
+//! from a failed rebuild sit in the overlay's "Shader errors" panel until fixed or dismissed.
+//!
+//! Compiling at runtime goes through [`compile::ShaderCompiler`], which also makes the build functions
+//! [`watch::HotPipelines`] calls.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+pub mod compile;
+pub mod reflect;
+pub mod watch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compiler message, placed in the file it's about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    pub file: PathBuf,
+    /// 1 based, 0 when the compiler didn't say.
+    pub line: u32,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        return write!(f, ": {severity}: {}", self.message);
+    }
+}
+
+/// A shader with its includes pasted in.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderSource {
+    text: String,
+    /// Every file that went in, the shader itself first.
+    files: Vec<PathBuf>,
+    /// For each line of `text`, the index into `files` and the line there.
+    lines: Vec<(usize, u32)>,
+}
+
+impl ShaderSource {
+    /// What to hand the compiler.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Everything a change to should trigger a rebuild.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Where a line of [`ShaderSource::text`] came from. Lines are 1 based, as compilers count them.
+    pub fn origin(&self, line: u32) -> Option<(&Path, u32)> {
+        let &(file, line) = self.lines.get((line as usize).checked_sub(1)?)?;
+        return Some((&self.files[file], line));
+    }
+
+    fn push(&mut self, file: usize, line: u32, text: &str) {
+        self.text.push_str(text);
+        self.text.push('\n');
+        self.lines.push((file, line));
+    }
+}
+
+/// Finds and pastes in `#include`s. Includes are looked for next to the including file first, then in the
+/// search directories in the order they were added.
+/// Each file goes in once, later includes of it are skipped, which also takes care of include cycles.
+#[derive(Clone, Debug, Default)]
+pub struct IncludeResolver {
+    dirs: Vec<PathBuf>,
+}
+
+impl IncludeResolver {
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dirs.push(dir.into());
+        return self;
+    }
+
+    /// Load a shader through [`crate::platform::read_asset`].
+    pub fn load(&self, path: &Path) -> Result<ShaderSource, ShaderDiagnostic> {
+        return self.load_with(path, &mut |p| {
+            let data = crate::platform::read_asset(&p.to_string_lossy())?;
+            return String::from_utf8(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        });
+    }
+
+    /// Load a shader, reading files with `read`. A missing include is an error at the line including it.
+    pub fn load_with(
+        &self,
+        path: &Path,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+    ) -> Result<ShaderSource, ShaderDiagnostic> {
+        let text = read(path).map_err(|e| ShaderDiagnostic {
+            file: path.to_path_buf(),
+            line: 0,
+            column: None,
+            severity: Severity::Error,
+            message: format!("Couldn't read it: {e}"),
+        })?;
+
+        let mut source = ShaderSource::default();
+        source.files.push(path.to_path_buf());
+        self.expand(&mut source, 0, &text, read)?;
+        return Ok(source);
+    }
+
+    fn expand(
+        &self,
+        source: &mut ShaderSource,
+        file: usize,
+        text: &str,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+    ) -> Result<(), ShaderDiagnostic> {
+        for (i, line) in text.lines().enumerate() {
+            let number = i as u32 + 1;
+            let Some(name) = include_target(line) else {
+                source.push(file, number, line);
+                continue;
+            };
+
+            let error = |message: String| ShaderDiagnostic {
+                file: source.files[file].clone(),
+                line: number,
+                column: None,
+                severity: Severity::Error,
+                message,
+            };
+            let Some(name) = name else {
+                return Err(error("Expected #include \"file\"".into()));
+            };
+
+            let here = source.files[file].parent().unwrap_or(Path::new(""));
+            let mut found = None;
+            for candidate in
+                std::iter::once(here.join(name)).chain(self.dirs.iter().map(|d| d.join(name)))
+            {
+                if source.files.contains(&candidate) {
+                    found = Some((candidate, None));
+                    break;
+                }
+                if let Ok(text) = read(&candidate) {
+                    found = Some((candidate, Some(text)));
+                    break;
+                }
+            }
+
+            match found {
+                None => return Err(error(format!("Couldn't find include \"{name}\""))),
+                // Already in, keep the line numbering right with a blank line.
+                Some((_, None)) => source.push(file, number, ""),
+                Some((path, Some(text))) => {
+                    source.files.push(path);
+                    let included = source.files.len() - 1;
+                    self.expand(source, included, &text, read)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// `None` if this isn't an include, `Some(None)` if it's a malformed one.
+fn include_target(line: &str) -> Option<Option<&str>> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("include")?.trim();
+    let name = rest
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'))
+        .or_else(|| rest.strip_prefix('<').and_then(|r| r.strip_suffix('>')));
+    return Some(name.filter(|n| !n.is_empty()));
+}
+
+/// Finds `<line>:` or `<line>:<column>:` after the first colon that has them, skipping drive letters and the like.
+fn split_location(s: &str) -> Option<(u32, Option<u32>, &str)> {
+    let number = |s: &str| -> Option<(u32, usize)> {
+        let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 || s.as_bytes().get(digits) != Some(&b':') {
+            return None;
+        }
+        return Some((s[..digits].parse().ok()?, digits + 1));
+    };
+
+    for (at, _) in s.match_indices(':') {
+        let after = &s[at + 1..];
+        let Some((line, len)) = number(after) else {
+            continue;
+        };
+        let after = &after[len..];
+        return Some(match number(after) {
+            Some((column, len)) => (line, Some(column), &after[len..]),
+            None => (line, None, after),
+        });
+    }
+    return None;
+}
+
+/// Turn what a compiler printed about `source` into diagnostics against the files that went into it.
+///
+/// Understands glslang's `ERROR: 0:12: message` and the `name:12:5: error: message` that glslc, dxc and
+/// friends print. If none of the output makes sense, it all comes back as one error against the shader,
+/// so nothing gets lost.
+pub fn map_diagnostics(output: &str, source: &ShaderSource) -> Vec<ShaderDiagnostic> {
+    let top = source.files.first().cloned().unwrap_or_default();
+    let mut diagnostics = Vec::new();
+
+    for line in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (mut severity, rest) = if let Some(rest) = line.strip_prefix("ERROR:") {
+            (Severity::Error, rest)
+        } else if let Some(rest) = line.strip_prefix("WARNING:") {
+            (Severity::Warning, rest)
+        } else {
+            (Severity::Error, line)
+        };
+        let Some((at, column, message)) = split_location(rest) else {
+            continue;
+        };
+
+        let mut message = message.trim();
+        if let Some(m) = message.strip_prefix("error:") {
+            message = m.trim();
+        } else if let Some(m) = message.strip_prefix("warning:") {
+            severity = Severity::Warning;
+            message = m.trim();
+        }
+
+        let (file, line) = match source.origin(at) {
+            Some((file, line)) => (file.to_path_buf(), line),
+            None => (top.clone(), at),
+        };
+        diagnostics.push(ShaderDiagnostic {
+            file,
+            line,
+            column,
+            severity,
+            message: message.to_string(),
+        });
+    }
+
+    if diagnostics.is_empty() && !output.trim().is_empty() {
+        diagnostics.push(ShaderDiagnostic {
+            file: top,
+            line: 0,
+            column: None,
+            severity: Severity::Error,
+            message: output.trim().to_string(),
+        });
+    }
+    return diagnostics;
+}
+
+/// The latest diagnostics from each pipeline that failed to build, for the overlay.
+/// Cloning gives another handle to the same list.
+#[derive(Clone, Default)]
+pub struct ShaderErrors {
+    inner: Arc<Mutex<BTreeMap<String, Vec<ShaderDiagnostic>>>>,
+}
+
+impl ShaderErrors {
+    pub fn new() -> ShaderErrors {
+        Self::default()
+    }
+
+    /// Replaces anything reported for `pipeline` before.
+    pub fn report(&self, pipeline: &str, diagnostics: Vec<ShaderDiagnostic>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(pipeline.to_string(), diagnostics);
+    }
+
+    /// Forget about `pipeline`'s errors, because it built or someone's seen them.
+    pub fn dismiss(&self, pipeline: &str) {
+        self.inner.lock().unwrap().remove(pipeline);
+    }
+
+    pub fn dismiss_all(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    /// By pipeline name.
+    pub fn snapshot(&self) -> Vec<(String, Vec<ShaderDiagnostic>)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, d)| (name.clone(), d.clone()))
+            .collect()
+    }
+}
+
+/// Holds the pipeline a draw uses. A rebuild that works replaces it, one that doesn't leaves it be and reports
+/// why, so a typo in a shader costs an overlay message rather than the frame.
+pub struct PipelineSlot<P> {
+    name: String,
+    current: Option<P>,
+    errors: ShaderErrors,
+}
+
+impl<P> PipelineSlot<P> {
+    pub fn new(name: impl Into<String>, errors: ShaderErrors) -> PipelineSlot<P> {
+        PipelineSlot {
+            name: name.into(),
+            current: None,
+            errors,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The last pipeline that built, `None` if none has yet.
+    pub fn get(&self) -> Option<&P> {
+        self.current.as_ref()
+    }
+
+    /// Let go of the pipeline, for when the device it was made on is going away. The next build that works fills
+    /// the slot again.
+    pub fn take(&mut self) -> Option<P> {
+        self.current.take()
+    }
+
+    /// Take a (re)build's result. Returns the pipeline it replaced, which the caller destroys once the GPU is
+    /// done with it.
+    pub fn update(&mut self, result: Result<P, Vec<ShaderDiagnostic>>) -> Option<P> {
+        match result {
+            Ok(pipeline) => {
+                self.errors.dismiss(&self.name);
+                return self.current.replace(pipeline);
+            }
+            Err(diagnostics) => {
+                for d in &diagnostics {
+                    log::error!("{}: {d}", self.name);
+                }
+                if self.current.is_some() {
+                    log::warn!("{}: keeping the previous pipeline", self.name);
+                }
+                self.errors.report(&self.name, diagnostics);
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        io,
+        path::{Path, PathBuf},
+    };
+
+    use super::{IncludeResolver, PipelineSlot, Severity, ShaderErrors, map_diagnostics};
+
+    fn files() -> HashMap<PathBuf, String> {
+        HashMap::from([
+            (
+                PathBuf::from("shaders/main.frag"),
+                "#version 450\n#include \"common.glsl\"\nvoid main() {\n    oops\n}".to_string(),
+            ),
+            (
+                PathBuf::from("lib/common.glsl"),
+                "#include \"math.glsl\"\nfloat saturate(float x);\n#include \"math.glsl\""
+                    .to_string(),
+            ),
+            (
+                PathBuf::from("lib/math.glsl"),
+                "const float PI = 3.14;\nbroken".to_string(),
+            ),
+        ])
+    }
+
+    #[test]
+    pub fn includes_map_back() {
+        let files = files();
+        let mut read = |p: &Path| files.get(p).cloned().ok_or(io::ErrorKind::NotFound.into());
+        let source = IncludeResolver::default()
+            .with_dir("lib")
+            .load_with(Path::new("shaders/main.frag"), &mut read)
+            .unwrap();
+
+        assert_eq!(source.files().len(), 3);
+        // math.glsl is found next to common.glsl, and only pasted in once.
+        assert_eq!(source.origin(3), Some((Path::new("lib/math.glsl"), 2)));
+        assert_eq!(source.origin(5), Some((Path::new("lib/common.glsl"), 3)));
+        assert_eq!(source.origin(7), Some((Path::new("shaders/main.frag"), 4)));
+        assert_eq!(source.origin(0), None);
+
+        let output = "ERROR: 0:3: 'broken' : syntax error\n\
+                      main.frag:7:5: warning: 'oops' unused\n\
+                      ERROR: 2 compilation errors.  No code generated.";
+        let diagnostics = map_diagnostics(output, &source);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, Path::new("lib/math.glsl"));
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "shaders/main.frag:4:5: warning: 'oops' unused"
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+
+        // Something we can't place still gets through.
+        let diagnostics = map_diagnostics("linker fell over", &source);
+        assert_eq!(diagnostics[0].line, 0);
+        assert_eq!(diagnostics[0].message, "linker fell over");
+
+        let error = IncludeResolver::default()
+            .load_with(Path::new("shaders/main.frag"), &mut read)
+            .unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    pub fn keeps_last_good_pipeline() {
+        let errors = ShaderErrors::new();
+        let mut slot = PipelineSlot::new("sky", errors.clone());
+        assert_eq!(slot.update(Ok(1)), None);
+
+        let diagnostics = map_diagnostics("nope", &Default::default());
+        assert_eq!(slot.update(Err(diagnostics)), None);
+        assert_eq!(slot.get(), Some(&1));
+        assert_eq!(errors.snapshot()[0].0, "sky");
+
+        assert_eq!(slot.update(Ok(2)), Some(1));
+        assert!(errors.is_empty());
+    }
+}
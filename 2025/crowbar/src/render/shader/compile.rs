@@ -0,0 +1,643 @@
+//! Compiling GLSL and HLSL to SPIR-V at runtime, so shaders can be edited without an offline build step.
+//!
+//! The compiling itself is done by running the Vulkan SDK's compilers, glslc for GLSL and dxc for HLSL, found
+//! through `VULKAN_SDK` or `PATH`. Includes are pasted in by an [`IncludeResolver`] beforehand, relative to the
+//! including file and then the shader root, so the compilers' errors map back to the right file and every file
+//! read is known for [`super::watch`] to follow.
+//!
+//! With the `naga` feature GLSL is compiled in process by naga instead, so there's nothing to install. It takes
+//! vertex, fragment and compute shaders only, and has no HLSL front end, so HLSL still goes through dxc.
+//!
+//! Shipped builds should load precompiled `.spv` through [`Spirv::load`] instead: the compilers are a development
+//! dependency, not something to expect on a player's machine.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    IncludeResolver, Severity, ShaderDiagnostic, ShaderSource, map_diagnostics,
+    reflect::Spirv,
+    watch::{BuildPipeline, PipelineBuild},
+};
+use crate::render::alloc::VK_ALLOCATOR_CALLBACKS;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+}
+
+impl ShaderLanguage {
+    /// HLSL for `.hlsl` files, GLSL for anything else.
+    pub fn from_path(path: &Path) -> ShaderLanguage {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("hlsl") => ShaderLanguage::Hlsl,
+            _ => ShaderLanguage::Glsl,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    TessControl,
+    TessEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    /// From glslc's extensions, `sky.frag`, or the one before a `.glsl` or `.hlsl`, `sky.frag.hlsl`.
+    pub fn from_path(path: &Path) -> Option<ShaderStage> {
+        let name = path.file_name()?.to_str()?;
+        let name = name
+            .strip_suffix(".glsl")
+            .or_else(|| name.strip_suffix(".hlsl"))
+            .unwrap_or(name);
+        let stage = match name.rsplit_once('.')?.1 {
+            "vert" => ShaderStage::Vertex,
+            "tesc" => ShaderStage::TessControl,
+            "tese" => ShaderStage::TessEvaluation,
+            "geom" => ShaderStage::Geometry,
+            "frag" => ShaderStage::Fragment,
+            "comp" => ShaderStage::Compute,
+            _ => return None,
+        };
+        return Some(stage);
+    }
+
+    pub fn flags(self) -> vk::ShaderStageFlags {
+        match self {
+            ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+            ShaderStage::TessControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            ShaderStage::TessEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            ShaderStage::Geometry => vk::ShaderStageFlags::GEOMETRY,
+            ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+
+    fn glslc_name(self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vert",
+            ShaderStage::TessControl => "tesc",
+            ShaderStage::TessEvaluation => "tese",
+            ShaderStage::Geometry => "geom",
+            ShaderStage::Fragment => "frag",
+            ShaderStage::Compute => "comp",
+        }
+    }
+
+    fn hlsl_profile(self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vs_6_0",
+            ShaderStage::TessControl => "hs_6_0",
+            ShaderStage::TessEvaluation => "ds_6_0",
+            ShaderStage::Geometry => "gs_6_0",
+            ShaderStage::Fragment => "ps_6_0",
+            ShaderStage::Compute => "cs_6_0",
+        }
+    }
+}
+
+/// A shader compile's result, and every file it read, which is wanted even when it fails.
+pub struct CompiledShader {
+    pub spirv: Result<Spirv, Vec<ShaderDiagnostic>>,
+    pub files: Vec<PathBuf>,
+}
+
+/// `name` in the Vulkan SDK, or else on the `PATH`.
+fn find_tool(name: &str) -> Option<PathBuf> {
+    let file = format!("{name}{}", env::consts::EXE_SUFFIX);
+    let sdk = env::var_os("VULKAN_SDK").map(|sdk| PathBuf::from(sdk).join("bin"));
+    let path = env::var_os("PATH");
+    let dirs = sdk
+        .into_iter()
+        .chain(path.iter().flat_map(env::split_paths));
+    return dirs.map(|d| d.join(&file)).find(|p| p.is_file());
+}
+
+/// One of the engine's own shaders, built into the binary: loaded from the assets as `asset` if it's shipped
+/// compiled, or else compiled from `text` as if it were the file `path`. `None`, with why logged, if neither works.
+pub fn builtin_shader(asset: &str, path: &str, text: &str, stage: ShaderStage) -> Option<Spirv> {
+    return builtin_shader_including(asset, path, text, &[], stage);
+}
+
+/// [`builtin_shader`], for one that includes other files built into the binary, as `(name, text)` with the name
+/// as it's written in the include.
+pub fn builtin_shader_including(
+    asset: &str,
+    path: &str,
+    text: &str,
+    includes: &[(&str, &str)],
+    stage: ShaderStage,
+) -> Option<Spirv> {
+    if let Ok(spirv) = Spirv::load(asset) {
+        return Some(spirv);
+    }
+    let compiler = ShaderCompiler::new(env::temp_dir());
+    let compiled = compiler.compile_text_including(Path::new(path), text, includes, stage, "main");
+    return match compiled {
+        Ok(spirv) => Some(spirv),
+        Err(diagnostics) => {
+            log::error!("{path} isn't shipped compiled, and couldn't be compiled:");
+            for diagnostic in diagnostics {
+                log::error!("{diagnostic}");
+            }
+            None
+        }
+    };
+}
+
+/// Names temporary files apart when several shaders compile at once.
+static NEXT_TEMP: AtomicU32 = AtomicU32::new(0);
+
+/// Compiles shaders from a directory on disk.
+#[derive(Clone, Debug)]
+pub struct ShaderCompiler {
+    root: PathBuf,
+    includes: IncludeResolver,
+    glslc: Option<PathBuf>,
+    dxc: Option<PathBuf>,
+    defines: Vec<(String, String)>,
+    optimize: bool,
+}
+
+impl ShaderCompiler {
+    /// Compiling shaders under `root`, which is also where includes are looked for after the including file's
+    /// directory. Optimizes in release builds, and keeps debug info in debug ones.
+    pub fn new(root: impl Into<PathBuf>) -> ShaderCompiler {
+        let root = root.into();
+        ShaderCompiler {
+            includes: IncludeResolver::default().with_dir(root.clone()),
+            root,
+            glslc: find_tool("glslc"),
+            dxc: find_tool("dxc"),
+            defines: Vec::new(),
+            optimize: !cfg!(debug_assertions),
+        }
+    }
+
+    /// Also look for includes in `dir`, relative to the root.
+    pub fn with_include_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.includes = self.includes.with_dir(self.root.join(dir));
+        return self;
+    }
+
+    /// `#define name value` in every shader.
+    pub fn with_define(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.push((name.into(), value.into()));
+        return self;
+    }
+
+    /// Use this glslc rather than the one found.
+    pub fn with_glslc(mut self, path: impl Into<PathBuf>) -> Self {
+        self.glslc = Some(path.into());
+        return self;
+    }
+
+    /// Use this dxc rather than the one found.
+    pub fn with_dxc(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dxc = Some(path.into());
+        return self;
+    }
+
+    pub fn with_optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        return self;
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether there's a compiler for `language`.
+    pub fn available(&self, language: ShaderLanguage) -> bool {
+        match language {
+            ShaderLanguage::Glsl => cfg!(feature = "naga") || self.glslc.is_some(),
+            ShaderLanguage::Hlsl => self.dxc.is_some(),
+        }
+    }
+
+    /// Compile `path`, relative to the root, as `stage` with `entry` as the entry point. The language comes from
+    /// the extension.
+    pub fn compile(&self, path: &Path, stage: ShaderStage, entry: &str) -> CompiledShader {
+        let path = self.root.join(path);
+        let source = self
+            .includes
+            .load_with(&path, &mut |p| fs::read_to_string(p));
+        return match source {
+            Ok(source) => CompiledShader {
+                spirv: self.compile_source(&source, ShaderLanguage::from_path(&path), stage, entry),
+                files: source.files().to_vec(),
+            },
+            Err(error) => {
+                // Whatever was missing or broken, the file it was in is what'll get fixed.
+                let mut files = vec![path, error.file.clone()];
+                files.dedup();
+                CompiledShader {
+                    spirv: Err(vec![error]),
+                    files,
+                }
+            }
+        };
+    }
+
+    /// Compile `text` as if it were the file `path`, relative to the root, like a shader built into the binary.
+    /// Includes are still read from disk.
+    pub fn compile_text(
+        &self,
+        path: &Path,
+        text: &str,
+        stage: ShaderStage,
+        entry: &str,
+    ) -> Result<Spirv, Vec<ShaderDiagnostic>> {
+        return self.compile_text_including(path, text, &[], stage, entry);
+    }
+
+    /// [`ShaderCompiler::compile_text`], with `includes` as `(name, text)` standing in for the files they name
+    /// next to `path`. Any other includes are read from disk.
+    pub fn compile_text_including(
+        &self,
+        path: &Path,
+        text: &str,
+        includes: &[(&str, &str)],
+        stage: ShaderStage,
+        entry: &str,
+    ) -> Result<Spirv, Vec<ShaderDiagnostic>> {
+        let path = self.root.join(path);
+        let here = path.parent().unwrap_or(&self.root);
+        let builtin = |p: &Path| includes.iter().find(|(name, _)| here.join(name) == p);
+        let source = self.includes.load_with(&path, &mut |p| {
+            if p == path {
+                return Ok(text.to_owned());
+            }
+            return match builtin(p) {
+                Some((_, text)) => Ok((*text).to_owned()),
+                None => fs::read_to_string(p),
+            };
+        });
+        let source = source.map_err(|e| vec![e])?;
+        return self.compile_source(&source, ShaderLanguage::from_path(&path), stage, entry);
+    }
+
+    /// Compile a shader already loaded, with its includes pasted in.
+    pub fn compile_source(
+        &self,
+        source: &ShaderSource,
+        language: ShaderLanguage,
+        stage: ShaderStage,
+        entry: &str,
+    ) -> Result<Spirv, Vec<ShaderDiagnostic>> {
+        let top = source.files().first().cloned().unwrap_or_default();
+        let error = |message: String| {
+            vec![ShaderDiagnostic {
+                file: top.clone(),
+                line: 0,
+                column: None,
+                severity: Severity::Error,
+                message,
+            }]
+        };
+        #[cfg(feature = "naga")]
+        if language == ShaderLanguage::Glsl {
+            return self.compile_naga(source, stage, entry);
+        }
+
+        let (tool, name, extension) = match language {
+            ShaderLanguage::Glsl => (&self.glslc, "glslc", "glsl"),
+            ShaderLanguage::Hlsl => (&self.dxc, "dxc", "hlsl"),
+        };
+        let Some(tool) = tool else {
+            return Err(error(format!(
+                "Couldn't find {name}, install the Vulkan SDK or put it on the PATH"
+            )));
+        };
+
+        let dir = env::temp_dir().join("crowbar-shaders");
+        let stem = format!(
+            "{}-{}",
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        );
+        let input = dir.join(format!("{stem}.{extension}"));
+        let output = dir.join(format!("{stem}.spv"));
+        let written = fs::create_dir_all(&dir).and_then(|_| fs::write(&input, source.text()));
+        if let Err(e) = written {
+            return Err(error(format!("Couldn't write {}: {e}", input.display())));
+        }
+
+        let args = self.args(language, stage, entry, &input, &output);
+        let result = Command::new(tool).args(&args).output();
+        let spirv = fs::read(&output);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => return Err(error(format!("Couldn't run {}: {e}", tool.display()))),
+        };
+
+        let printed = format!(
+            "{}\n{}",
+            String::from_utf8_lossy(&result.stderr),
+            String::from_utf8_lossy(&result.stdout)
+        );
+        let diagnostics = map_diagnostics(&printed, source);
+        if !result.status.success() {
+            return Err(match diagnostics.is_empty() {
+                true => error(format!("{name} failed: {}", result.status)),
+                false => diagnostics,
+            });
+        }
+        for d in &diagnostics {
+            log::warn!("{d}");
+        }
+        return spirv
+            .and_then(|bytes| Spirv::from_bytes(&bytes))
+            .map_err(|e| error(format!("{name} didn't produce SPIR-V: {e}")));
+    }
+
+    /// GLSL through naga, in process.
+    #[cfg(feature = "naga")]
+    fn compile_naga(
+        &self,
+        source: &ShaderSource,
+        stage: ShaderStage,
+        entry: &str,
+    ) -> Result<Spirv, Vec<ShaderDiagnostic>> {
+        let text = source.text();
+        let top = source.files().first().cloned().unwrap_or_default();
+        // Back to the file and line it came from, before the includes were pasted in.
+        let diagnostic = |location: Option<naga::SourceLocation>, message: String| {
+            let (file, line) = location
+                .and_then(|l| source.origin(l.line_number))
+                .map_or((top.clone(), 0), |(file, line)| (file.to_path_buf(), line));
+            ShaderDiagnostic {
+                file,
+                line,
+                column: location.map(|l| l.line_position),
+                severity: Severity::Error,
+                message,
+            }
+        };
+
+        let naga_stage = match stage {
+            ShaderStage::Vertex => naga::ShaderStage::Vertex,
+            ShaderStage::Fragment => naga::ShaderStage::Fragment,
+            ShaderStage::Compute => naga::ShaderStage::Compute,
+            _ => {
+                return Err(vec![diagnostic(
+                    None,
+                    format!("naga can't compile {stage:?} shaders"),
+                )]);
+            }
+        };
+        let options = naga::front::glsl::Options {
+            stage: naga_stage,
+            defines: self.defines.iter().cloned().collect(),
+        };
+        let module = naga::front::glsl::Frontend::default()
+            .parse(&options, text)
+            .map_err(|e| {
+                e.errors
+                    .iter()
+                    .map(|e| diagnostic(e.location(text), e.kind.to_string()))
+                    .collect::<Vec<_>>()
+            })?;
+
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|e| vec![diagnostic(e.location(text), e.as_inner().to_string())])?;
+        let options = naga::back::spv::Options {
+            // What Vulkan 1.3 takes, as glslc is asked for.
+            lang_version: (1, 6),
+            flags: match self.optimize {
+                true => naga::back::spv::WriterFlags::empty(),
+                false => naga::back::spv::WriterFlags::DEBUG,
+            },
+            ..Default::default()
+        };
+        let pipeline = naga::back::spv::PipelineOptions {
+            shader_stage: naga_stage,
+            entry_point: entry.into(),
+        };
+        let words = naga::back::spv::write_vec(&module, &info, &options, Some(&pipeline))
+            .map_err(|e| vec![diagnostic(None, format!("naga couldn't write SPIR-V: {e}"))])?;
+        return Spirv::from_words(words)
+            .map_err(|e| vec![diagnostic(None, format!("naga didn't produce SPIR-V: {e}"))]);
+    }
+
+    fn args(
+        &self,
+        language: ShaderLanguage,
+        stage: ShaderStage,
+        entry: &str,
+        input: &Path,
+        output: &Path,
+    ) -> Vec<String> {
+        let mut args = Vec::new();
+        match language {
+            ShaderLanguage::Glsl => {
+                args.push(format!("-fshader-stage={}", stage.glslc_name()));
+                args.push(format!("-fentry-point={entry}"));
+                args.push("--target-env=vulkan1.3".into());
+                args.push(if self.optimize { "-O" } else { "-g" }.into());
+            }
+            ShaderLanguage::Hlsl => {
+                args.extend(["-spirv".into(), "-T".into(), stage.hlsl_profile().into()]);
+                args.extend(["-E".into(), entry.into()]);
+                args.push("-fspv-target-env=vulkan1.3".into());
+                args.push(if self.optimize { "-O3" } else { "-Zi" }.into());
+            }
+        }
+        for (name, value) in &self.defines {
+            args.push(format!("-D{name}={value}"));
+        }
+        let out = if language == ShaderLanguage::Hlsl {
+            "-Fo"
+        } else {
+            "-o"
+        };
+        args.extend([out.into(), output.to_string_lossy().into_owned()]);
+        args.push(input.to_string_lossy().into_owned());
+        return args;
+    }
+
+    /// A build for [`super::watch::HotPipelines`]: compiles `shaders`, each with `main` as its entry point, and
+    /// hands their modules to `build` with the pipeline cache and sample count. The modules are destroyed again
+    /// after.
+    pub fn pipeline(
+        self: &Arc<Self>,
+        shaders: Vec<(PathBuf, ShaderStage)>,
+        mut build: impl FnMut(
+            &ash::Device,
+            vk::PipelineCache,
+            vk::SampleCountFlags,
+            &[(ShaderStage, vk::ShaderModule)],
+        ) -> VkResult<vk::Pipeline>
+        + 'static,
+    ) -> BuildPipeline {
+        let compiler = self.clone();
+        return Box::new(move |device, cache, samples| {
+            let mut files = Vec::new();
+            let mut diagnostics = Vec::new();
+            let mut compiled = Vec::new();
+            for (path, stage) in &shaders {
+                let shader = compiler.compile(path, *stage, "main");
+                files.extend(shader.files);
+                match shader.spirv {
+                    Ok(spirv) => compiled.push((*stage, spirv)),
+                    Err(d) => diagnostics.extend(d),
+                }
+            }
+            files.dedup();
+            if !diagnostics.is_empty() {
+                return PipelineBuild {
+                    pipeline: Err(diagnostics),
+                    files,
+                };
+            }
+
+            let mut modules = Vec::new();
+            let mut result = Ok(());
+            for (stage, spirv) in &compiled {
+                // SAFETY: The compiler made it for Vulkan 1.3.
+                match unsafe { spirv.create_module(device) } {
+                    Ok(module) => modules.push((*stage, module)),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            let pipeline = result.and_then(|_| build(device, cache, samples, &modules));
+            for (_, module) in modules {
+                // SAFETY: Pipelines don't need their modules once they're made.
+                unsafe { device.destroy_shader_module(module, allocs()) };
+            }
+            let top = shaders.first().map(|s| compiler.root.join(&s.0));
+            return PipelineBuild {
+                pipeline: pipeline.map_err(|e| {
+                    vec![ShaderDiagnostic {
+                        file: top.unwrap_or_default(),
+                        line: 0,
+                        column: None,
+                        severity: Severity::Error,
+                        message: format!("Couldn't create the pipeline: {e}"),
+                    }]
+                }),
+                files,
+            };
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::{ShaderCompiler, ShaderLanguage, ShaderStage};
+
+    #[test]
+    pub fn compiles_with_includes() {
+        assert_eq!(
+            ShaderStage::from_path(Path::new("sky.frag")),
+            Some(ShaderStage::Fragment)
+        );
+        assert_eq!(
+            ShaderStage::from_path(Path::new("post/blur.comp.hlsl")),
+            Some(ShaderStage::Compute)
+        );
+        assert_eq!(ShaderStage::from_path(Path::new("common.glsl")), None);
+        assert_eq!(
+            ShaderLanguage::from_path(Path::new("blur.comp.hlsl")),
+            ShaderLanguage::Hlsl
+        );
+
+        let root =
+            std::env::temp_dir().join(format!("crowbar-shader-compile-{}", std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(
+            root.join("tint.frag"),
+            "#version 450\n#include \"color.glsl\"\nlayout(location = 0) out vec4 color;\n\
+             void main() { color = TINT; }\n",
+        )
+        .unwrap();
+        fs::write(root.join("lib/color.glsl"), "#define TINT vec4(1.0)\n").unwrap();
+
+        // Without a compiler it says so, against the shader, but still knows what to watch. naga is always there.
+        let missing = ShaderCompiler::new(&root)
+            .with_include_dir("lib")
+            .with_glslc(root.join("no-such-glslc"));
+        #[cfg(not(feature = "naga"))]
+        {
+            let shader = missing.compile(Path::new("tint.frag"), ShaderStage::Fragment, "main");
+            assert_eq!(
+                shader.files,
+                [root.join("tint.frag"), root.join("lib/color.glsl")]
+            );
+            let errors = shader.spirv.unwrap_err();
+            assert_eq!(errors[0].file, root.join("tint.frag"));
+            assert!(errors[0].message.starts_with("Couldn't run"));
+        }
+
+        let args = missing.with_define("QUALITY", "2").args(
+            ShaderLanguage::Hlsl,
+            ShaderStage::Vertex,
+            "vs_main",
+            Path::new("in.hlsl"),
+            Path::new("out.spv"),
+        );
+        assert!(args.windows(2).any(|a| a == ["-T", "vs_6_0"]));
+        assert!(args.contains(&"-DQUALITY=2".to_string()));
+        assert_eq!(args[args.len() - 3..], ["-Fo", "out.spv", "in.hlsl"]);
+
+        // With one, errors land in the include they're in.
+        let compiler = ShaderCompiler::new(&root).with_include_dir("lib");
+        if compiler.available(ShaderLanguage::Glsl) {
+            let shader = compiler.compile(Path::new("tint.frag"), ShaderStage::Fragment, "main");
+            let spirv = shader.spirv.unwrap();
+            assert_eq!(
+                spirv.reflect().unwrap().stages(),
+                ShaderStage::Fragment.flags()
+            );
+
+            fs::write(root.join("lib/color.glsl"), "oops\n").unwrap();
+            let shader = compiler.compile(Path::new("tint.frag"), ShaderStage::Fragment, "main");
+            assert_eq!(
+                shader.spirv.unwrap_err()[0].file,
+                root.join("lib/color.glsl")
+            );
+
+            // Built in includes don't need to be on disk.
+            let text = "#version 450\n#include \"../builtin/tint.glsl\"\n\
+                        layout(location = 0) out vec4 color;\nvoid main() { color = TINT; }\n";
+            let includes = [("../builtin/tint.glsl", "#define TINT vec4(0.5)\n")];
+            let stage = ShaderStage::Fragment;
+            let path = Path::new("builtin.frag");
+            assert!(
+                compiler
+                    .compile_text_including(path, text, &includes, stage, "main")
+                    .is_ok()
+            );
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
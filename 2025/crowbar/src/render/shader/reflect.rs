@@ -0,0 +1,886 @@
+//! SPIR-V modules, and what they expect to be bound, read straight out of the binary.
+//!
+//! Reflection walks the module's declarations for the descriptor bindings, push constants and vertex inputs its
+//! entry points use, so pipeline layouts and vertex formats can come from the shaders instead of being kept in
+//! step with them by hand. Only what's needed for that is understood: anything else in the module is skipped.
+//!
+//! This is hand rolled rather than going through rspirv or spirv-reflect: the handful of instructions it needs are
+//! simple to read, and it keeps a large dependency (or a C library) out of a build that only wants bindings.
+//!
+//! Sizes of push constant blocks come from the `Offset`, `ArrayStride` and `MatrixStride` decorations the compiler
+//! puts on them, so they follow whatever layout the shader was compiled with.
+
+use std::{collections::HashMap, io};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    platform,
+    render::{alloc::VK_ALLOCATOR_CALLBACKS, pipeline},
+};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+const MAGIC: u32 = 0x0723_0203;
+
+mod op {
+    pub const NAME: u16 = 5;
+    pub const ENTRY_POINT: u16 = 15;
+    pub const EXECUTION_MODE: u16 = 16;
+    pub const TYPE_BOOL: u16 = 20;
+    pub const TYPE_INT: u16 = 21;
+    pub const TYPE_FLOAT: u16 = 22;
+    pub const TYPE_VECTOR: u16 = 23;
+    pub const TYPE_MATRIX: u16 = 24;
+    pub const TYPE_IMAGE: u16 = 25;
+    pub const TYPE_SAMPLER: u16 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u16 = 27;
+    pub const TYPE_ARRAY: u16 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u16 = 29;
+    pub const TYPE_STRUCT: u16 = 30;
+    pub const TYPE_POINTER: u16 = 32;
+    pub const CONSTANT: u16 = 43;
+    pub const VARIABLE: u16 = 59;
+    pub const DECORATE: u16 = 71;
+    pub const MEMBER_DECORATE: u16 = 72;
+    pub const TYPE_ACCELERATION_STRUCTURE: u16 = 5341;
+}
+
+mod decoration {
+    pub const BUFFER_BLOCK: u32 = 3;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BUILT_IN: u32 = 11;
+    pub const LOCATION: u32 = 30;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+/// The execution mode giving a compute shader's workgroup size as literals.
+const LOCAL_SIZE: u32 = 17;
+
+mod storage {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT: u32 = 1;
+    pub const UNIFORM: u32 = 2;
+    pub const PUSH_CONSTANT: u32 = 9;
+    pub const STORAGE_BUFFER: u32 = 12;
+}
+
+/// A SPIR-V module's words, in native byte order.
+#[derive(Clone, Debug)]
+pub struct Spirv {
+    words: Vec<u32>,
+}
+
+impl Spirv {
+    /// From a `.spv` file's bytes, either endianness, like from `include_bytes!`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Spirv> {
+        if !bytes.len().is_multiple_of(4) || bytes.len() < 20 {
+            return Err(invalid("SPIR-V isn't a whole number of words"));
+        }
+        let mut words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        if words[0] == MAGIC.swap_bytes() {
+            for w in &mut words {
+                *w = w.swap_bytes();
+            }
+        }
+        return Spirv::from_words(words);
+    }
+
+    pub fn from_words(words: Vec<u32>) -> io::Result<Spirv> {
+        if words.len() < 5 || words[0] != MAGIC {
+            return Err(invalid("Not SPIR-V"));
+        }
+        return Ok(Spirv { words });
+    }
+
+    /// Load `name`, a path without the `.spv`, through [`platform::read_asset`].
+    pub fn load(name: &str) -> io::Result<Spirv> {
+        return Spirv::from_bytes(&platform::read_asset(&format!("{name}.spv"))?);
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    /// # Safety
+    /// The module must be valid SPIR-V for the device.
+    pub unsafe fn create_module(&self, device: &ash::Device) -> VkResult<vk::ShaderModule> {
+        // SAFETY: Passed on to the caller.
+        unsafe { pipeline::create_shader_module(device, &self.words) }
+    }
+
+    /// What the module's entry points expect to be bound.
+    pub fn reflect(&self) -> io::Result<Reflection> {
+        return Parsed::parse(&self.words)?.reflect();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: vk::DescriptorType,
+    /// Array length, 1 for a single descriptor, 0 for a runtime sized array.
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    /// The variable's name, if the module kept names.
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+    /// Bytes the format takes up.
+    pub size: u32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// Sorted by set, then binding.
+    pub bindings: Vec<DescriptorBinding>,
+    /// Size of the push constant block in bytes, 0 without one.
+    pub push_constant_size: u32,
+    /// A compute shader's workgroup size. `None` for other stages, or when it's set by specialization constants.
+    pub workgroup_size: Option<[u32; 3]>,
+    /// Vertex shader inputs, without built-ins, sorted by location.
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl Reflection {
+    /// Every stage with an entry point.
+    pub fn stages(&self) -> vk::ShaderStageFlags {
+        self.entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |s, e| s | e.stage)
+    }
+
+    /// Combine with another stage's reflection, for a pipeline made of both. Bindings both use are seen from both
+    /// stages, but have to agree on what they are.
+    pub fn merge(&mut self, other: &Reflection) -> io::Result<()> {
+        self.entry_points.extend(other.entry_points.iter().cloned());
+        for binding in &other.bindings {
+            match self
+                .bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(b) if b.ty != binding.ty || b.count != binding.count => {
+                    return Err(invalid(&format!(
+                        "Stages disagree on set {} binding {}",
+                        b.set, b.binding
+                    )));
+                }
+                Some(b) => b.stages |= binding.stages,
+                None => self.bindings.push(binding.clone()),
+            }
+        }
+        self.bindings.sort_by_key(|b| (b.set, b.binding));
+        self.push_constant_size = self.push_constant_size.max(other.push_constant_size);
+        self.workgroup_size = self.workgroup_size.or(other.workgroup_size);
+        self.vertex_inputs
+            .extend(other.vertex_inputs.iter().cloned());
+        self.vertex_inputs.sort_by_key(|v| v.location);
+        return Ok(());
+    }
+
+    /// How many descriptor sets the layout needs, counting unused ones below the last.
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|b| b.set + 1).max().unwrap_or(0)
+    }
+
+    /// Bindings for set `set`'s layout. Runtime sized arrays get `max_unbounded` descriptors.
+    pub fn set_layout_bindings(
+        &self,
+        set: u32,
+        max_unbounded: u32,
+    ) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(b.binding)
+                    .descriptor_type(b.ty)
+                    .descriptor_count(if b.count == 0 { max_unbounded } else { b.count })
+                    .stage_flags(b.stages)
+            })
+            .collect()
+    }
+
+    /// The push constant range, visible to every stage, if there's a block.
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        (self.push_constant_size > 0).then(|| vk::PushConstantRange {
+            stage_flags: self.stages(),
+            offset: 0,
+            size: self.push_constant_size,
+        })
+    }
+
+    /// Attributes for the vertex inputs packed one after another in one vertex buffer at `binding`, and the stride.
+    pub fn vertex_attributes(
+        &self,
+        binding: u32,
+    ) -> (Vec<vk::VertexInputAttributeDescription>, u32) {
+        let mut offset = 0;
+        let attributes = self
+            .vertex_inputs
+            .iter()
+            .map(|v| {
+                let attribute = vk::VertexInputAttributeDescription {
+                    location: v.location,
+                    binding,
+                    format: v.format,
+                    offset,
+                };
+                offset += v.size;
+                attribute
+            })
+            .collect();
+        return (attributes, offset);
+    }
+
+    /// Make the descriptor set layouts and the pipeline layout. Runtime sized arrays get `max_unbounded`
+    /// descriptors.
+    ///
+    /// # Safety
+    /// The layout must be something `device` supports.
+    pub unsafe fn create_layout(
+        &self,
+        device: &ash::Device,
+        max_unbounded: u32,
+    ) -> VkResult<ReflectedLayout> {
+        let mut layout = ReflectedLayout {
+            sets: Vec::new(),
+            layout: vk::PipelineLayout::null(),
+        };
+        for set in 0..self.set_count() {
+            let bindings = self.set_layout_bindings(set, max_unbounded);
+            // SAFETY: Passed on to the caller. On failure what's been made is destroyed.
+            let result = unsafe {
+                device.create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                    allocs(),
+                )
+            };
+            match result {
+                Ok(set) => layout.sets.push(set),
+                Err(e) => {
+                    // SAFETY: Never used.
+                    unsafe { layout.destroy(device) };
+                    return Err(e);
+                }
+            }
+        }
+        let ranges: Vec<_> = self.push_constant_range().into_iter().collect();
+        // SAFETY: Passed on to the caller.
+        let result = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&layout.sets)
+                    .push_constant_ranges(&ranges),
+                allocs(),
+            )
+        };
+        match result {
+            Ok(l) => layout.layout = l,
+            Err(e) => {
+                // SAFETY: Never used.
+                unsafe { layout.destroy(device) };
+                return Err(e);
+            }
+        }
+        return Ok(layout);
+    }
+}
+
+/// Layouts made from a [`Reflection`].
+#[derive(Debug)]
+pub struct ReflectedLayout {
+    /// By set number.
+    pub sets: Vec<vk::DescriptorSetLayout>,
+    pub layout: vk::PipelineLayout,
+}
+
+impl ReflectedLayout {
+    /// # Safety
+    /// Nothing can still be using the layouts, and they must be from `device`.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        // SAFETY: Passed on to the caller. Destroying null handles does nothing.
+        unsafe {
+            device.destroy_pipeline_layout(self.layout, allocs());
+            for set in self.sets.drain(..) {
+                device.destroy_descriptor_set_layout(set, allocs());
+            }
+        }
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Scalar {
+        float: bool,
+        signed: bool,
+        width: u32,
+    },
+    Vector {
+        component: u32,
+        count: u32,
+    },
+    Matrix {
+        column: u32,
+        count: u32,
+    },
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage,
+    AccelerationStructure,
+    Array {
+        element: u32,
+        length: u32,
+    },
+    RuntimeArray {
+        element: u32,
+    },
+    Struct {
+        members: Vec<u32>,
+    },
+    Pointer {
+        pointee: u32,
+    },
+}
+
+#[derive(Default)]
+struct Parsed {
+    entry_points: Vec<(u32, String, Vec<u32>)>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Id to storage class and pointer type.
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<(u32, u32), u32>,
+    flags: Vec<(u32, u32)>,
+    local_size: Option<[u32; 3]>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+}
+
+/// A nul terminated string packed into words, and how many words it took.
+fn string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (i, w) in words.iter().enumerate() {
+        for b in w.to_le_bytes() {
+            if b == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), i + 1);
+            }
+            bytes.push(b);
+        }
+    }
+    return (String::from_utf8_lossy(&bytes).into_owned(), words.len());
+}
+
+fn stage(model: u32) -> Option<vk::ShaderStageFlags> {
+    Some(match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        5364 => vk::ShaderStageFlags::TASK_EXT,
+        5365 => vk::ShaderStageFlags::MESH_EXT,
+        _ => return None,
+    })
+}
+
+impl Parsed {
+    fn parse(words: &[u32]) -> io::Result<Parsed> {
+        let mut parsed = Parsed::default();
+        let mut at = 5;
+        while at < words.len() {
+            let count = (words[at] >> 16) as usize;
+            let opcode = words[at] as u16;
+            if count == 0 || at + count > words.len() {
+                return Err(invalid("SPIR-V instruction runs past the end"));
+            }
+            let args = &words[at + 1..at + count];
+            let arg = |i: usize| {
+                args.get(i)
+                    .copied()
+                    .ok_or_else(|| invalid("SPIR-V instruction is too short"))
+            };
+            // The operands from `i` on, which there has to be at least an empty run of.
+            let rest = |i: usize| {
+                args.get(i..)
+                    .ok_or_else(|| invalid("SPIR-V instruction is too short"))
+            };
+            match opcode {
+                op::NAME => {
+                    parsed.names.insert(arg(0)?, string(rest(1)?).0);
+                }
+                op::ENTRY_POINT => {
+                    let (name, len) = string(rest(2)?);
+                    let interface = rest(2 + len)?.to_vec();
+                    parsed.entry_points.push((arg(0)?, name, interface));
+                }
+                op::EXECUTION_MODE if arg(1)? == LOCAL_SIZE => {
+                    parsed.local_size = Some([arg(2)?, arg(3)?, arg(4)?]);
+                }
+                op::TYPE_BOOL => {
+                    let ty = Type::Scalar {
+                        float: false,
+                        signed: false,
+                        width: 32,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_INT | op::TYPE_FLOAT => {
+                    let ty = Type::Scalar {
+                        float: opcode == op::TYPE_FLOAT,
+                        signed: opcode == op::TYPE_INT && arg(2)? != 0,
+                        width: arg(1)?,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_VECTOR => {
+                    let ty = Type::Vector {
+                        component: arg(1)?,
+                        count: arg(2)?,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_MATRIX => {
+                    let ty = Type::Matrix {
+                        column: arg(1)?,
+                        count: arg(2)?,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_IMAGE => {
+                    let ty = Type::Image {
+                        dim: arg(2)?,
+                        sampled: arg(6)?,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_SAMPLER => {
+                    parsed.types.insert(arg(0)?, Type::Sampler);
+                }
+                op::TYPE_SAMPLED_IMAGE => {
+                    parsed.types.insert(arg(0)?, Type::SampledImage);
+                }
+                op::TYPE_ACCELERATION_STRUCTURE => {
+                    parsed.types.insert(arg(0)?, Type::AccelerationStructure);
+                }
+                op::TYPE_ARRAY => {
+                    // The length is a constant declared before the array.
+                    let length = *parsed
+                        .constants
+                        .get(&arg(2)?)
+                        .ok_or_else(|| invalid("SPIR-V array length isn't a constant"))?;
+                    let ty = Type::Array {
+                        element: arg(1)?,
+                        length,
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_RUNTIME_ARRAY => {
+                    let ty = Type::RuntimeArray { element: arg(1)? };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_STRUCT => {
+                    let ty = Type::Struct {
+                        members: rest(1)?.to_vec(),
+                    };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::TYPE_POINTER => {
+                    let ty = Type::Pointer { pointee: arg(2)? };
+                    parsed.types.insert(arg(0)?, ty);
+                }
+                op::CONSTANT => {
+                    // Only the low word matters, for array lengths.
+                    parsed.constants.insert(arg(1)?, arg(2)?);
+                }
+                op::VARIABLE => {
+                    parsed.variables.push((arg(1)?, arg(2)?, arg(0)?));
+                }
+                op::DECORATE => match args.get(2) {
+                    Some(&value) => {
+                        parsed.decorations.insert((arg(0)?, arg(1)?), value);
+                    }
+                    None => parsed.flags.push((arg(0)?, arg(1)?)),
+                },
+                op::MEMBER_DECORATE => {
+                    if let Some(&value) = args.get(3) {
+                        parsed
+                            .member_decorations
+                            .insert((arg(0)?, arg(1)?, arg(2)?), value);
+                    }
+                }
+                _ => {}
+            }
+            at += count;
+        }
+        return Ok(parsed);
+    }
+
+    fn ty(&self, id: u32) -> io::Result<&Type> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| invalid("SPIR-V refers to an unknown type"))
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn has_flag(&self, id: u32, decoration: u32) -> bool {
+        self.decorations.contains_key(&(id, decoration)) || self.flags.contains(&(id, decoration))
+    }
+
+    /// Bytes `id` takes up in a block. Matrices are column major, `matrix_stride` apart if decorated.
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> io::Result<u32> {
+        return Ok(match self.ty(id)? {
+            Type::Scalar { width, .. } => width / 8,
+            Type::Vector { component, count } => self.size_of(*component, None)? * count,
+            Type::Matrix { column, count } => {
+                let stride = match matrix_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(*column, None)?,
+                };
+                stride * count
+            }
+            Type::Array { element, length } => {
+                let stride = match self.decoration(id, decoration::ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size_of(*element, matrix_stride)?,
+                };
+                stride * length
+            }
+            Type::RuntimeArray { .. } => 0,
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (i, &member) in members.iter().enumerate() {
+                    let i = i as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(id, i, decoration::OFFSET))
+                        .copied()
+                        .unwrap_or(size);
+                    let stride = self
+                        .member_decorations
+                        .get(&(id, i, decoration::MATRIX_STRIDE))
+                        .copied();
+                    size = size.max(offset + self.size_of(member, stride)?);
+                }
+                size
+            }
+            _ => return Err(invalid("SPIR-V block member has no size")),
+        });
+    }
+
+    /// What kind of descriptor a variable pointing at `id` in `storage` is, and how many.
+    fn descriptor(&self, storage: u32, id: u32) -> io::Result<(vk::DescriptorType, u32)> {
+        let (id, count) = match self.ty(id)? {
+            Type::Array { element, length } => (*element, *length),
+            Type::RuntimeArray { element } => (*element, 0),
+            _ => (id, 1),
+        };
+        let ty = match (storage, self.ty(id)?) {
+            (storage::STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (storage::UNIFORM, _) if self.has_flag(id, decoration::BUFFER_BLOCK) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (storage::UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::AccelerationStructure) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            // Dim 5 is Buffer, 6 SubpassData. Sampled 2 means used without a sampler, for storage.
+            (_, Type::Image { dim: 5, sampled: 2 }) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            (_, Type::Image { dim: 5, .. }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (_, Type::Image { dim: 6, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            (_, Type::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            (_, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            _ => return Err(invalid("SPIR-V resource isn't a known descriptor type")),
+        };
+        return Ok((ty, count));
+    }
+
+    /// The vertex attribute format for a scalar or vector, and its size.
+    fn vertex_format(&self, id: u32) -> io::Result<(vk::Format, u32)> {
+        let (component, count) = match self.ty(id)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (id, 1),
+        };
+        let &Type::Scalar {
+            float,
+            signed,
+            width: 32,
+        } = self.ty(component)?
+        else {
+            return Err(invalid(
+                "Only 32 bit scalar and vector vertex inputs are supported",
+            ));
+        };
+        use vk::Format as F;
+        let formats = match (float, signed) {
+            (true, _) => [
+                F::R32_SFLOAT,
+                F::R32G32_SFLOAT,
+                F::R32G32B32_SFLOAT,
+                F::R32G32B32A32_SFLOAT,
+            ],
+            (false, true) => [
+                F::R32_SINT,
+                F::R32G32_SINT,
+                F::R32G32B32_SINT,
+                F::R32G32B32A32_SINT,
+            ],
+            (false, false) => [
+                F::R32_UINT,
+                F::R32G32_UINT,
+                F::R32G32B32_UINT,
+                F::R32G32B32A32_UINT,
+            ],
+        };
+        let format = formats
+            .get((count as usize).wrapping_sub(1))
+            .ok_or_else(|| invalid("Vertex input vector is too long"))?;
+        return Ok((*format, 4 * count));
+    }
+
+    fn reflect(&self) -> io::Result<Reflection> {
+        let mut reflection = Reflection::default();
+        for (model, name, _) in &self.entry_points {
+            if let Some(stage) = stage(*model) {
+                reflection.entry_points.push(EntryPoint {
+                    name: name.clone(),
+                    stage,
+                });
+            }
+        }
+        let stages = reflection.stages();
+        let vertex_interface: Vec<u32> = self
+            .entry_points
+            .iter()
+            .filter(|(model, _, _)| stage(*model) == Some(vk::ShaderStageFlags::VERTEX))
+            .flat_map(|(_, _, interface)| interface.iter().copied())
+            .collect();
+
+        for &(id, storage, pointer) in &self.variables {
+            let &Type::Pointer { pointee, .. } = self.ty(pointer)? else {
+                return Err(invalid("SPIR-V variable isn't a pointer"));
+            };
+            let name = self.names.get(&id).cloned().unwrap_or_default();
+            match storage {
+                storage::UNIFORM_CONSTANT | storage::UNIFORM | storage::STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (
+                        self.decoration(id, decoration::DESCRIPTOR_SET),
+                        self.decoration(id, decoration::BINDING),
+                    ) else {
+                        continue;
+                    };
+                    let (ty, count) = self.descriptor(storage, pointee)?;
+                    reflection.bindings.push(DescriptorBinding {
+                        set,
+                        binding,
+                        ty,
+                        count,
+                        stages,
+                        name,
+                    });
+                }
+                storage::PUSH_CONSTANT => {
+                    reflection.push_constant_size = reflection
+                        .push_constant_size
+                        .max(self.size_of(pointee, None)?);
+                }
+                storage::INPUT if vertex_interface.contains(&id) => {
+                    if self.has_flag(id, decoration::BUILT_IN) {
+                        continue;
+                    }
+                    let Some(location) = self.decoration(id, decoration::LOCATION) else {
+                        continue;
+                    };
+                    let (format, size) = self.vertex_format(pointee)?;
+                    reflection.vertex_inputs.push(VertexInput {
+                        location,
+                        format,
+                        size,
+                        name,
+                    });
+                }
+                _ => {}
+            }
+        }
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.vertex_inputs.sort_by_key(|v| v.location);
+        reflection.workgroup_size = self.local_size;
+        return Ok(reflection);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{Reflection, Spirv};
+
+    fn op(words: &mut Vec<u32>, opcode: u32, args: &[u32]) {
+        words.push(((args.len() as u32 + 1) << 16) | opcode);
+        words.extend_from_slice(args);
+    }
+
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(s.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    /// A vertex shader with two inputs and a built-in, a uniform block, an array of textures and push constants.
+    fn module() -> Vec<u32> {
+        let mut w = vec![0x0723_0203, 0x0001_0300, 0, 100, 0];
+        let mut entry = vec![0, 1];
+        entry.extend(string("main"));
+        entry.extend([10, 11, 12]);
+        op(&mut w, 15, &entry);
+        op(&mut w, 5, &[[20].as_slice(), &string("globals")].concat());
+        // Inputs at locations 1 and 0, and gl_VertexIndex.
+        op(&mut w, 71, &[10, 30, 1]);
+        op(&mut w, 71, &[11, 30, 0]);
+        op(&mut w, 71, &[12, 11, 42]);
+        // The uniform block at set 0 binding 0, the textures at set 2 binding 3.
+        op(&mut w, 71, &[20, 34, 0]);
+        op(&mut w, 71, &[20, 33, 0]);
+        op(&mut w, 71, &[21, 34, 2]);
+        op(&mut w, 71, &[21, 33, 3]);
+        op(&mut w, 71, &[40, 2]);
+        // Push constants: a mat4 at 0 and a vec4 at 64.
+        op(&mut w, 72, &[50, 0, 35, 0]);
+        op(&mut w, 72, &[50, 0, 7, 16]);
+        op(&mut w, 72, &[50, 1, 35, 64]);
+        op(&mut w, 71, &[50, 2]);
+
+        op(&mut w, 22, &[1, 32]); // float
+        op(&mut w, 21, &[2, 32, 1]); // int
+        op(&mut w, 23, &[3, 1, 2]); // vec2
+        op(&mut w, 23, &[4, 1, 4]); // vec4
+        op(&mut w, 24, &[5, 4, 4]); // mat4
+        op(&mut w, 43, &[2, 6, 4]); // 4
+        op(&mut w, 25, &[7, 1, 1, 0, 0, 0, 1, 0]); // texture2D
+        op(&mut w, 27, &[8, 7]); // sampler2D
+        op(&mut w, 28, &[9, 8, 6]); // sampler2D[4]
+        op(&mut w, 30, &[40, 5]); // uniform block
+        op(&mut w, 30, &[50, 5, 4]); // push constants
+        op(&mut w, 32, &[60, 1, 3]);
+        op(&mut w, 32, &[61, 1, 4]);
+        op(&mut w, 32, &[62, 1, 2]);
+        op(&mut w, 32, &[63, 2, 40]);
+        op(&mut w, 32, &[64, 0, 9]);
+        op(&mut w, 32, &[65, 9, 50]);
+        op(&mut w, 59, &[60, 10, 1]);
+        op(&mut w, 59, &[61, 11, 1]);
+        op(&mut w, 59, &[62, 12, 1]);
+        op(&mut w, 59, &[63, 20, 2]);
+        op(&mut w, 59, &[64, 21, 0]);
+        op(&mut w, 59, &[65, 22, 9]);
+        return w;
+    }
+
+    #[test]
+    pub fn reflects_module() {
+        let bytes: Vec<u8> = module().iter().flat_map(|w| w.to_be_bytes()).collect();
+        // Big endian loads the same.
+        let spirv = Spirv::from_bytes(&bytes).unwrap();
+        assert_eq!(spirv.words(), module());
+        assert!(Spirv::from_bytes(&bytes[..18]).is_err());
+
+        let reflection = spirv.reflect().unwrap();
+        assert_eq!(reflection.entry_points[0].name, "main");
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.push_constant_size, 80);
+        assert_eq!(reflection.workgroup_size, None);
+        assert_eq!(reflection.set_count(), 3);
+        let [globals, textures] = &reflection.bindings[..] else {
+            panic!("{:?}", reflection.bindings);
+        };
+        assert_eq!(
+            (globals.set, globals.binding, globals.ty, globals.count),
+            (0, 0, vk::DescriptorType::UNIFORM_BUFFER, 1)
+        );
+        assert_eq!(globals.name, "globals");
+        assert_eq!(
+            (textures.set, textures.binding, textures.ty, textures.count),
+            (2, 3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4)
+        );
+
+        // The built-in is left out, and the rest go in location order.
+        let (attributes, stride) = reflection.vertex_attributes(0);
+        assert_eq!(stride, 24);
+        assert_eq!(attributes[0].format, vk::Format::R32G32B32A32_SFLOAT);
+        assert_eq!((attributes[1].location, attributes[1].offset), (1, 16));
+
+        let mut fragment = Reflection {
+            bindings: vec![super::DescriptorBinding {
+                stages: vk::ShaderStageFlags::FRAGMENT,
+                ..reflection.bindings[0].clone()
+            }],
+            ..Default::default()
+        };
+        fragment.bindings[0].count = 2;
+        let mut merged = reflection.clone();
+        assert!(merged.merge(&fragment).is_err());
+        fragment.bindings[0].count = 1;
+        let mut merged = reflection.clone();
+        merged.merge(&fragment).unwrap();
+        assert_eq!(
+            merged.bindings[0].stages,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+
+        // A compute shader with `local_size_x = 64`.
+        let mut compute = vec![0x0723_0203, 0x0001_0300, 0, 100, 0];
+        op(&mut compute, 15, &[[5, 1].as_slice(), &string("main")].concat());
+        op(&mut compute, 16, &[1, 17, 64, 1, 1]);
+        let reflection = Spirv::from_words(compute).unwrap().reflect().unwrap();
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(reflection.workgroup_size, Some([64, 1, 1]));
+    }
+
+    #[test]
+    pub fn rejects_short_instructions() {
+        // A name, entry point and struct all missing operands they can't do without.
+        for (opcode, args) in [(5, &[][..]), (15, &[5][..]), (30, &[][..])] {
+            let mut words = vec![0x0723_0203, 0x0001_0300, 0, 100, 0];
+            op(&mut words, opcode, args);
+            assert!(Spirv::from_words(words).unwrap().reflect().is_err());
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Shader hot reloading: pipelines rebuilt when a file they were built from changes on disk.
+//!
+//! Files are polled for their modification times, like the game library in [`crate::hot_reload`], rather than
+//! watched through the OS. Each build hands back every file it read, includes and all, so the watch follows a
+//! shader as its includes change. A rebuild that fails leaves the last pipeline that built in place, through a
+//! [`PipelineSlot`], and the errors in the overlay.
+
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use ash::vk;
+
+use super::{PipelineSlot, ShaderDiagnostic, ShaderErrors};
+
+/// How often files are checked for changes.
+pub const SHADER_POLL: Duration = Duration::from_millis(250);
+/// How long a file has to go untouched before it's rebuilt from, so a half saved file isn't compiled.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watches sets of files, each under a key, for changes.
+pub struct ShaderWatcher<K> {
+    files: HashMap<K, Vec<(PathBuf, Option<SystemTime>)>>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl<K: Clone + Eq + Hash> ShaderWatcher<K> {
+    /// Checking at most every `interval`.
+    pub fn new(interval: Duration) -> ShaderWatcher<K> {
+        ShaderWatcher {
+            files: HashMap::new(),
+            interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Watch `files` for `key`, in place of whatever was watched for it before. They count as unchanged as they
+    /// are now.
+    pub fn watch(&mut self, key: K, files: impl IntoIterator<Item = PathBuf>) {
+        let files = files.into_iter().map(|f| {
+            let modified = modified(&f);
+            (f, modified)
+        });
+        self.files.insert(key, files.collect());
+    }
+
+    pub fn unwatch(&mut self, key: &K) {
+        self.files.remove(key);
+    }
+
+    /// Keys with a file that's changed since it was watched or last reported, if it's time to check.
+    pub fn poll(&mut self) -> Vec<K> {
+        if self.last_poll.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        return self.check();
+    }
+
+    /// [`ShaderWatcher::poll`], now.
+    pub fn check(&mut self) -> Vec<K> {
+        let mut changed = Vec::new();
+        for (key, files) in &mut self.files {
+            let mut any = false;
+            for (path, seen) in files.iter_mut() {
+                let now = modified(path);
+                // A file that's gone is probably being saved by replacing it, so wait for it to come back.
+                let Some(time) = now else {
+                    continue;
+                };
+                let settled = time.elapsed().is_ok_and(|age| age >= SETTLE_TIME);
+                if now != *seen && settled {
+                    *seen = now;
+                    any = true;
+                }
+            }
+            if any {
+                changed.push(key.clone());
+            }
+        }
+        return changed;
+    }
+}
+
+/// A pipeline build's result, and every file it read. The files are wanted even when it fails, so fixing the
+/// mistake triggers a rebuild.
+pub struct PipelineBuild {
+    pub pipeline: Result<vk::Pipeline, Vec<ShaderDiagnostic>>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Builds a pipeline from its shaders on disk, from scratch each time, through the pipeline cache it's given and
+/// with the samples per pixel it's given, so it matches the targets it draws to.
+pub type BuildPipeline =
+    Box<dyn FnMut(&ash::Device, vk::PipelineCache, vk::SampleCountFlags) -> PipelineBuild>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(u32);
+
+struct HotPipeline {
+    slot: PipelineSlot<vk::Pipeline>,
+    build: BuildPipeline,
+}
+
+/// Pipelines rebuilt when their shaders change. Pipelines replaced or removed are handed back, for the caller to
+/// destroy once the frames in flight are done with them.
+pub struct HotPipelines {
+    /// By id. Ids aren't reused, so a stale one finds nothing.
+    pipelines: Vec<Option<HotPipeline>>,
+    watcher: ShaderWatcher<PipelineId>,
+    /// Handed to every build.
+    cache: vk::PipelineCache,
+    samples: vk::SampleCountFlags,
+}
+
+impl Default for HotPipelines {
+    fn default() -> Self {
+        HotPipelines {
+            pipelines: Vec::new(),
+            watcher: ShaderWatcher::new(SHADER_POLL),
+            cache: vk::PipelineCache::null(),
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+impl HotPipelines {
+    /// Builds going through `cache`.
+    pub fn new(cache: vk::PipelineCache) -> HotPipelines {
+        HotPipelines {
+            cache,
+            ..HotPipelines::default()
+        }
+    }
+
+    /// Build a pipeline called `name`, reporting errors to `errors`, and keep it rebuilt. It has no pipeline until
+    /// a build works.
+    pub fn add(
+        &mut self,
+        device: &ash::Device,
+        name: impl Into<String>,
+        errors: ShaderErrors,
+        build: BuildPipeline,
+    ) -> PipelineId {
+        let id = PipelineId(self.pipelines.len() as u32);
+        self.pipelines.push(Some(HotPipeline {
+            slot: PipelineSlot::new(name, errors),
+            build,
+        }));
+        // Nothing to replace the first time.
+        let _ = self.rebuild(device, id);
+        return id;
+    }
+
+    /// The last pipeline that built.
+    pub fn get(&self, id: PipelineId) -> Option<vk::Pipeline> {
+        let hot = self.pipelines.get(id.0 as usize)?.as_ref()?;
+        return hot.slot.get().copied();
+    }
+
+    /// Stop rebuilding it, handing back its pipeline.
+    pub fn remove(&mut self, id: PipelineId) -> Option<vk::Pipeline> {
+        self.watcher.unwatch(&id);
+        let hot = self.pipelines.get_mut(id.0 as usize)?.take()?;
+        return hot.slot.get().copied();
+    }
+
+    /// Rebuild `id`, returning the pipeline it replaced.
+    pub fn rebuild(&mut self, device: &ash::Device, id: PipelineId) -> Option<vk::Pipeline> {
+        let hot = self.pipelines.get_mut(id.0 as usize)?.as_mut()?;
+        let build = (hot.build)(device, self.cache, self.samples);
+        self.watcher.watch(id, build.files);
+        let replaced = hot.slot.update(build.pipeline);
+        if replaced.is_some() {
+            log::info!("Rebuilt pipeline {}", hot.slot.name());
+        }
+        return replaced;
+    }
+
+    /// Rebuild whatever's had its files change, if it's time to check. Returns the pipelines replaced.
+    pub fn reload(&mut self, device: &ash::Device) -> Vec<vk::Pipeline> {
+        let changed = self.watcher.poll();
+        return changed
+            .into_iter()
+            .filter_map(|id| self.rebuild(device, id))
+            .collect();
+    }
+
+    /// Rebuild everything. Returns the pipelines replaced.
+    pub fn reload_all(&mut self, device: &ash::Device) -> Vec<vk::Pipeline> {
+        let ids: Vec<_> = (0..self.pipelines.len() as u32).map(PipelineId).collect();
+        return ids
+            .into_iter()
+            .filter_map(|id| self.rebuild(device, id))
+            .collect();
+    }
+
+    /// Build with `samples` per pixel from now on, rebuilding everything if that's a change. Returns the pipelines
+    /// replaced.
+    pub fn set_samples(
+        &mut self,
+        device: &ash::Device,
+        samples: vk::SampleCountFlags,
+    ) -> Vec<vk::Pipeline> {
+        if samples == self.samples {
+            return Vec::new();
+        }
+        self.samples = samples;
+        return self.reload_all(device);
+    }
+
+    /// Every pipeline, keeping how to build them, for when the device they were made on is going away. Build them
+    /// on the next one with [`HotPipelines::move_to`].
+    pub fn take_all(&mut self) -> Vec<vk::Pipeline> {
+        return self
+            .pipelines
+            .iter_mut()
+            .flatten()
+            .filter_map(|hot| hot.slot.take())
+            .collect();
+    }
+
+    /// Build everything again on `device` through `cache`, which builds go through from now on. After
+    /// [`HotPipelines::take_all`], for a new device.
+    pub fn move_to(&mut self, device: &ash::Device, cache: vk::PipelineCache) {
+        self.cache = cache;
+        let replaced = self.reload_all(device);
+        debug_assert!(
+            replaced.is_empty(),
+            "Pipelines weren't taken off the old device"
+        );
+    }
+
+    /// Every pipeline, for shutting down.
+    pub fn drain(&mut self) -> Vec<vk::Pipeline> {
+        let ids: Vec<_> = (0..self.pipelines.len() as u32).map(PipelineId).collect();
+        return ids.into_iter().filter_map(|id| self.remove(id)).collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use super::ShaderWatcher;
+    use crate::render::shader::{
+        PipelineSlot, ShaderErrors,
+        compile::{ShaderCompiler, ShaderLanguage, ShaderStage},
+    };
+
+    /// Set `path`'s modification time to `ago` seconds ago, or from now for negative ones.
+    fn set_modified(path: &Path, ago: i64) {
+        let now = SystemTime::now();
+        let time = match ago {
+            0.. => now - Duration::from_secs(ago as u64),
+            _ => now + Duration::from_secs(-ago as u64),
+        };
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    pub fn reports_changed_files() {
+        let dir = std::env::temp_dir().join(format!("crowbar-shader-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.glsl"), dir.join("common.glsl"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let set = set_modified;
+        set(&a, 60);
+        set(&b, 60);
+
+        let mut watcher = ShaderWatcher::new(Duration::from_secs(3600));
+        watcher.watch("first", [a.clone(), b.clone()]);
+        watcher.watch("second", [b.clone()]);
+        assert!(watcher.check().is_empty());
+        // Not time to poll yet.
+        set(&a, 30);
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.check(), ["first"]);
+        assert!(watcher.check().is_empty());
+
+        // A shared include changes both, but not until it's settled.
+        set(&b, -60);
+        assert!(watcher.check().is_empty());
+        set(&b, 10);
+        let mut changed = watcher.check();
+        changed.sort();
+        assert_eq!(changed, ["first", "second"]);
+
+        watcher.unwatch(&"second");
+        set(&b, 5);
+        assert_eq!(watcher.check(), ["first"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn failed_rebuild_keeps_the_last_build() {
+        let root =
+            std::env::temp_dir().join(format!("crowbar-shader-rebuild-{}", std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        let (shader, include) = (root.join("tint.frag"), root.join("lib/color.glsl"));
+        fs::write(
+            &shader,
+            "#version 450\n#include \"color.glsl\"\nlayout(location = 0) out vec4 color;\n\
+             void main() { color = TINT; }\n",
+        )
+        .unwrap();
+        fs::write(&include, "#define TINT vec4(1.0)\n").unwrap();
+        set_modified(&include, 60);
+
+        let compiler = ShaderCompiler::new(&root).with_include_dir("lib");
+        if !compiler.available(ShaderLanguage::Glsl) {
+            eprintln!("No GLSL compiler, skipping shader rebuild test.");
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+
+        // What HotPipelines does with a pipeline, with the SPIR-V standing in for it.
+        let errors = ShaderErrors::new();
+        let mut slot = PipelineSlot::new("tint", errors.clone());
+        let mut watcher = ShaderWatcher::new(Duration::ZERO);
+        let mut rebuild = |watcher: &mut ShaderWatcher<&str>| {
+            let built = compiler.compile(Path::new("tint.frag"), ShaderStage::Fragment, "main");
+            watcher.watch("tint", built.files);
+            return slot.update(built.spirv).is_some();
+        };
+        assert!(!rebuild(&mut watcher));
+
+        // Breaking the include fails the rebuild, and the first build stays.
+        fs::write(&include, "oops\n").unwrap();
+        set_modified(&include, 30);
+        assert_eq!(watcher.check(), ["tint"]);
+        assert!(!rebuild(&mut watcher));
+        assert_eq!(errors.snapshot()[0].1[0].file, include);
+
+        // Fixing it replaces the first build, and clears the errors.
+        fs::write(&include, "#define TINT vec4(0.5)\n").unwrap();
+        set_modified(&include, 10);
+        assert_eq!(watcher.check(), ["tint"]);
+        assert!(rebuild(&mut watcher));
+        assert!(errors.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
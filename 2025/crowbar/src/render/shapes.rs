@@ -0,0 +1,427 @@
+//! Vector shapes for UI and plots: rounded rects, circles and arbitrary paths, filled and stroked.
+//!
+//! A [`Path`] is flattened into polygons as it's built, curves cut into segments short enough to stay within
+//! [`TOLERANCE`] of the real thing. Fills are triangulated by ear clipping and get a feathered edge a pixel wide,
+//! the same anti-aliasing as [`super::lines`], which strokes them. The triangles go into a [`SpriteBatch`] on the
+//! white texture with [`ShapeBatch::draw`], so shapes layer and batch with everything else 2D.
+//!
+//! todo: each subpath is filled on its own, so holes don't cut out of the shape around them yet.
+
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec2;
+
+use super::{
+    lines::{LineBatch, LineStyle},
+    sprite::{SpriteBatch, SpriteVertex, TextureId},
+};
+use crate::{color::LinearColor, math::Rect};
+
+/// Furthest a flattened curve strays from the real one, in pixels.
+pub const TOLERANCE: f32 = 0.25;
+/// Points closer than this are merged.
+const MIN_EDGE: f32 = 1e-4;
+/// Most segments a curve is cut into.
+const MAX_SEGMENTS: u32 = 256;
+
+/// Outlines in viewport pixels, made of straight segments.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+    /// Points, and whether it's closed.
+    subpaths: Vec<(Vec<Vec2>, bool)>,
+}
+
+impl Path {
+    pub fn new() -> Path {
+        Path::default()
+    }
+
+    pub fn rect(rect: Rect) -> Path {
+        let Rect { min, max } = rect;
+        return Path::polygon(&[min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)]);
+    }
+
+    /// Corners rounded by `radius`, at most half the shorter side.
+    pub fn rounded_rect(rect: Rect, radius: f32) -> Path {
+        let radius = radius.min(rect.size().min_element() * 0.5).max(0.0);
+        if radius <= 0.0 {
+            return Path::rect(rect);
+        }
+        let Rect { min, max } = rect;
+        let (near, far) = (min + radius, max - radius);
+        let mut path = Path::new();
+        path.arc(Vec2::new(far.x, near.y), radius, -PI * 0.5, 0.0);
+        path.arc(far, radius, 0.0, PI * 0.5);
+        path.arc(Vec2::new(near.x, far.y), radius, PI * 0.5, PI);
+        path.arc(near, radius, PI, PI * 1.5);
+        path.close();
+        return path;
+    }
+
+    pub fn circle(center: Vec2, radius: f32) -> Path {
+        return Path::ellipse(center, Vec2::splat(radius));
+    }
+
+    pub fn ellipse(center: Vec2, radii: Vec2) -> Path {
+        let mut path = Path::new();
+        let steps = arc_segments(radii.max_element(), TAU);
+        for step in 0..steps {
+            let angle = TAU * step as f32 / steps as f32;
+            path.line_to(center + Vec2::from_angle(angle) * radii);
+        }
+        path.close();
+        return path;
+    }
+
+    /// A closed polygon through `points`.
+    pub fn polygon(points: &[Vec2]) -> Path {
+        let mut path = Path::new();
+        for &p in points {
+            path.line_to(p);
+        }
+        path.close();
+        return path;
+    }
+
+    /// Start a new subpath at `to`.
+    pub fn move_to(&mut self, to: Vec2) -> &mut Self {
+        self.subpaths.push((vec![to], false));
+        self
+    }
+
+    /// A line from the last point, or a new subpath's start if there isn't one.
+    pub fn line_to(&mut self, to: Vec2) -> &mut Self {
+        match self.subpaths.last_mut() {
+            Some((points, false)) => {
+                if points
+                    .last()
+                    .is_none_or(|p| p.distance_squared(to) >= MIN_EDGE * MIN_EDGE)
+                {
+                    points.push(to);
+                }
+            }
+            _ => self.subpaths.push((vec![to], false)),
+        }
+        self
+    }
+
+    /// A quadratic Bézier curve from the last point.
+    pub fn quad_to(&mut self, control: Vec2, to: Vec2) -> &mut Self {
+        let from = self.last();
+        // How far the curve bends from its chord, which bounds the error of the segments.
+        let bend = (from - control * 2.0 + to).length();
+        let steps = ((bend / (8.0 * TOLERANCE)).sqrt().ceil() as u32).clamp(1, MAX_SEGMENTS);
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            let p = from.lerp(control, t).lerp(control.lerp(to, t), t);
+            self.line_to(p);
+        }
+        self
+    }
+
+    /// A cubic Bézier curve from the last point.
+    pub fn cubic_to(&mut self, c0: Vec2, c1: Vec2, to: Vec2) -> &mut Self {
+        let from = self.last();
+        let bend = (from - c0 * 2.0 + c1)
+            .length()
+            .max((c0 - c1 * 2.0 + to).length());
+        let steps = ((bend * 3.0 / (4.0 * TOLERANCE)).sqrt().ceil() as u32).clamp(1, MAX_SEGMENTS);
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            let (a, b, c) = (from.lerp(c0, t), c0.lerp(c1, t), c1.lerp(to, t));
+            let p = a.lerp(b, t).lerp(b.lerp(c, t), t);
+            self.line_to(p);
+        }
+        self
+    }
+
+    /// An arc around `center` from angle `start` to `end`, in radians clockwise from +X on screen. It's joined to
+    /// the last point by a line.
+    pub fn arc(&mut self, center: Vec2, radius: f32, start: f32, end: f32) -> &mut Self {
+        let steps = arc_segments(radius, end - start);
+        for step in 0..=steps {
+            let angle = start + (end - start) * step as f32 / steps as f32;
+            self.line_to(center + Vec2::from_angle(angle) * radius);
+        }
+        self
+    }
+
+    /// Join the current subpath back to its start. The next point starts a new one.
+    pub fn close(&mut self) -> &mut Self {
+        if let Some((points, closed)) = self.subpaths.last_mut() {
+            if points.len() > 1
+                && points[0].distance_squared(points[points.len() - 1]) < MIN_EDGE * MIN_EDGE
+            {
+                points.pop();
+            }
+            *closed = true;
+        }
+        self
+    }
+
+    /// Each subpath's points, and whether it's closed.
+    pub fn subpaths(&self) -> impl Iterator<Item = (&[Vec2], bool)> {
+        self.subpaths
+            .iter()
+            .map(|(p, closed)| (p.as_slice(), *closed))
+    }
+
+    fn last(&self) -> Vec2 {
+        self.subpaths
+            .last()
+            .and_then(|(p, closed)| match closed {
+                true => p.first(),
+                false => p.last(),
+            })
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Enough segments for a `radius` arc through `sweep` radians to stay within [`TOLERANCE`].
+fn arc_segments(radius: f32, sweep: f32) -> u32 {
+    if radius <= TOLERANCE {
+        return 4;
+    }
+    let step = 2.0 * (1.0 - TOLERANCE / radius).acos();
+    return ((sweep.abs() / step).ceil() as u32).clamp(4, MAX_SEGMENTS);
+}
+
+/// Twice the polygon's area, positive if it goes clockwise on screen.
+fn signed_area(points: &[Vec2]) -> f32 {
+    let mut area = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        area += a.perp_dot(points[(i + 1) % points.len()]);
+    }
+    return area;
+}
+
+/// Triangles covering a simple polygon going clockwise on screen, by clipping ears. Self intersecting ones come out
+/// covered as best it can.
+fn triangulate(points: &[Vec2], out: &mut Vec<[u32; 3]>) {
+    let mut left: Vec<u32> = (0..points.len() as u32).collect();
+    let at = |i: u32| points[i as usize];
+    let convex = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - b) > 0.0;
+    let inside = |p: Vec2, [a, b, c]: [Vec2; 3]| {
+        (b - a).perp_dot(p - a) >= 0.0
+            && (c - b).perp_dot(p - b) >= 0.0
+            && (a - c).perp_dot(p - c) >= 0.0
+    };
+
+    let mut i = 0;
+    let mut stuck = 0;
+    while left.len() > 3 {
+        let n = left.len();
+        let (prev, cur, next) = (left[(i + n - 1) % n], left[i % n], left[(i + 1) % n]);
+        let corner = [at(prev), at(cur), at(next)];
+        let ear = convex(corner[0], corner[1], corner[2])
+            && !left
+                .iter()
+                .filter(|&&j| j != prev && j != cur && j != next)
+                .any(|&j| at(j) != corner[0] && at(j) != corner[2] && inside(at(j), corner));
+        // Going all the way round without finding one means the polygon isn't simple, so take what's there.
+        if ear || stuck > n {
+            out.push([prev, cur, next]);
+            left.remove(i % n);
+            stuck = 0;
+        } else {
+            i += 1;
+            stuck += 1;
+        }
+        i %= left.len();
+    }
+    out.push([left[0], left[1], left[2]]);
+}
+
+/// A frame's shapes. Keep it around and [`ShapeBatch::clear`] it each frame, to reuse its allocations.
+pub struct ShapeBatch {
+    vertices: Vec<SpriteVertex>,
+    indices: Vec<u32>,
+    /// Width of the anti-aliased edge, in pixels.
+    pub feather: f32,
+    /// Strokes go through it on their way in.
+    lines: LineBatch,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl Default for ShapeBatch {
+    fn default() -> Self {
+        ShapeBatch {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            feather: 1.0,
+            lines: LineBatch::default(),
+            triangles: Vec::new(),
+        }
+    }
+}
+
+impl ShapeBatch {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    pub fn vertices(&self) -> &[SpriteVertex] {
+        &self.vertices
+    }
+
+    /// Triangles, into [`ShapeBatch::vertices`].
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Fill every subpath of `path`, closing the open ones.
+    pub fn fill(&mut self, path: &Path, color: LinearColor) {
+        for (points, _) in path.subpaths() {
+            self.fill_polygon(points, color);
+        }
+    }
+
+    /// Outline `path`.
+    pub fn stroke(&mut self, path: &Path, style: &LineStyle) {
+        self.lines.clear();
+        self.lines.feather = self.feather;
+        for (points, closed) in path.subpaths() {
+            self.lines.polyline(points, closed, style);
+        }
+        let first = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(self.lines.vertices());
+        self.indices
+            .extend(self.lines.indices().iter().map(|i| first + i));
+    }
+
+    /// Fill and outline `path`.
+    pub fn fill_stroke(&mut self, path: &Path, color: LinearColor, style: &LineStyle) {
+        self.fill(path, color);
+        self.stroke(path, style);
+    }
+
+    fn fill_polygon(&mut self, points: &[Vec2], color: LinearColor) {
+        let mut points = points.to_vec();
+        points.dedup_by(|b, a| a.distance_squared(*b) < MIN_EDGE * MIN_EDGE);
+        if points.len() > 1
+            && points[0].distance_squared(points[points.len() - 1]) < MIN_EDGE * MIN_EDGE
+        {
+            points.pop();
+        }
+        let area = signed_area(&points);
+        if points.len() < 3 || area.abs() < MIN_EDGE {
+            return;
+        }
+        if area < 0.0 {
+            points.reverse();
+        }
+
+        // The solid inside is pulled in half the feather, and the edge fades out from it to half the feather out.
+        let half = self.feather * 0.5;
+        let (solid, clear) = (color.to_array(), color.with_alpha(0.0).to_array());
+        let count = points.len();
+        let first = self.vertices.len() as u32;
+        let normal = |i: usize| {
+            let d = (points[(i + 1) % count] - points[i]).normalize();
+            // Outwards, going clockwise on screen.
+            Vec2::new(d.y, -d.x)
+        };
+        for (i, &p) in points.iter().enumerate() {
+            let avg = (normal((i + count - 1) % count) + normal(i)) * 0.5;
+            // Mitred like the lines, clamped so spikes don't spike further.
+            let m = avg / avg.length_squared().max(1.0 / 16.0);
+            for (offset, color) in [(-half, solid), (half, clear)] {
+                self.vertices.push(SpriteVertex {
+                    position: (p + m * offset).to_array(),
+                    uv: [0.5, 0.5],
+                    color,
+                });
+            }
+        }
+
+        self.triangles.clear();
+        triangulate(&points, &mut self.triangles);
+        for t in &self.triangles {
+            self.indices.extend(t.map(|i| first + i * 2));
+        }
+        for i in 0..count as u32 {
+            let j = (i + 1) % count as u32;
+            let (inner_i, outer_i) = (first + i * 2, first + i * 2 + 1);
+            let (inner_j, outer_j) = (first + j * 2, first + j * 2 + 1);
+            self.indices
+                .extend_from_slice(&[inner_i, outer_i, outer_j, outer_j, inner_j, inner_i]);
+        }
+    }
+
+    /// Add everything to `sprites` on `layer`.
+    pub fn draw(&self, sprites: &mut SpriteBatch, layer: i32) {
+        sprites.triangles(&self.vertices, &self.indices, TextureId::WHITE, layer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec2;
+
+    use super::{Path, ShapeBatch, signed_area};
+    use crate::{
+        color::LinearColor,
+        math::Rect,
+        render::{lines::LineStyle, sprite::SpriteBatch},
+    };
+
+    #[test]
+    pub fn fills_and_strokes_paths() {
+        let rect = Rect::new(Vec2::ZERO, Vec2::new(40.0, 20.0));
+        let rounded = Path::rounded_rect(rect, 50.0);
+        let (points, closed) = rounded.subpaths().next().unwrap();
+        assert!(closed);
+        // The radius is cut to fit, so the ends are half circles, and everything stays inside.
+        assert!(points.iter().all(|p| rect.expand(1e-3).contains(*p)));
+        let area = signed_area(points) * 0.5;
+        assert!((area - (20.0 * 20.0 + 100.0 * std::f32::consts::PI)).abs() < 10.0);
+
+        let mut curve = Path::new();
+        curve
+            .move_to(Vec2::ZERO)
+            .quad_to(Vec2::new(50.0, 100.0), Vec2::new(100.0, 0.0));
+        let (points, closed) = curve.subpaths().next().unwrap();
+        assert!(!closed && points.len() > 8);
+        assert_eq!(points[points.len() - 1], Vec2::new(100.0, 0.0));
+
+        // An L, concave, drawn anticlockwise: 6 corners make 4 triangles, plus a fringe quad per edge.
+        let l = Path::polygon(&[
+            Vec2::ZERO,
+            Vec2::new(0.0, 20.0),
+            Vec2::new(20.0, 20.0),
+            Vec2::new(20.0, 10.0),
+            Vec2::new(10.0, 10.0),
+            Vec2::new(10.0, 0.0),
+        ]);
+        let mut shapes = ShapeBatch::default();
+        shapes.fill(&l, LinearColor::BLUE);
+        assert_eq!(shapes.vertices().len(), 12);
+        assert_eq!(shapes.indices().len(), (4 + 6 * 2) * 3);
+        // Every triangle is inside the L, none across the notch.
+        for t in shapes.indices()[..12].chunks(3) {
+            let center = t
+                .iter()
+                .map(|&i| Vec2::from(shapes.vertices()[i as usize].position))
+                .sum::<Vec2>()
+                / 3.0;
+            assert!(!(center.x > 10.0 && center.y < 10.0), "{center}");
+        }
+
+        let fill_indices = shapes.indices().len();
+        shapes.stroke(&l, &LineStyle::new(2.0, LinearColor::WHITE));
+        assert_eq!(shapes.indices().len(), fill_indices + 6 * 18);
+
+        // Into the sprite batch, where fringe quads go in whole.
+        let mut sprites = SpriteBatch::default();
+        shapes.draw(&mut sprites, 3);
+        assert_eq!(sprites.len(), 4 + 6 + 6 * 3);
+        sprites.build();
+        assert_eq!(sprites.runs().len(), 1);
+    }
+}
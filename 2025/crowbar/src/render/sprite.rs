@@ -0,0 +1,1050 @@
+//! 2D drawing: tinted, textured quads in viewport pixels, batched into as few draws as their textures allow.
+//!
+//! Sprites draw in layer order, and in the order they were pushed within a layer, so overlapping sprites in a layer
+//! come out as pushed. That rules out sorting by texture inside a layer, so only neighbours sharing a texture
+//! merge. Put things that interleave on separate layers, or in one atlas, to keep the draw count down.
+//!
+//! A [`SpriteImage`] can also scale by nine-slicing, keeping its border at its own size and stretching or tiling
+//! the middle, or fill its rect by tiling. Both come out as more quads, not a repeating sampler, so they work on
+//! atlas regions too.
+//!
+//! Shapes and other meshes go in as quads with their corners worked out, through [`SpriteBatch::triangles`], so
+//! they layer and batch with the sprites around them.
+//!
+//! [`SpritePass`] draws a built batch over whatever's in the target, and owns the textures sprites draw with,
+//! starting with [`TextureId::WHITE`] and [`TextureId::FONT`]. Ship its shaders compiled, as [`VERTEX_ASSET`] and
+//! [`FRAGMENT_ASSET`], or they're compiled from [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`] at startup.
+
+use ash::{prelude::VkResult, vk};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::DescriptorAllocator,
+    hal::{
+        BufferDesc, BufferUsage, CommandEncoder, Device, MemoryLocation, TextureDesc,
+        TextureFormat, TextureState, TextureUsage,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture},
+    },
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, TargetFormats},
+    rendering::PassContext,
+    shader::reflect::Spirv,
+    text::builtin_atlas,
+};
+use crate::{color::LinearColor, math::Rect};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// The vertex shader's source, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("sprite/sprite.vert");
+/// The fragment shader's source, to compile at runtime.
+pub const FRAGMENT_SHADER: &str = include_str!("sprite/sprite.frag");
+/// Where the compiled shaders go among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/sprite.vert";
+pub const FRAGMENT_ASSET: &str = "shaders/sprite.frag";
+
+/// Index of a texture owned by the renderer, for 2D drawing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureId(pub u32);
+
+impl TextureId {
+    /// Plain white, for flat coloured quads.
+    pub const WHITE: TextureId = TextureId(0);
+    /// The built-in font, see [`super::text::BitmapFont::builtin`].
+    pub const FONT: TextureId = TextureId(1);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprite {
+    /// Where it goes, in viewport pixels.
+    pub rect: Rect,
+    /// What part of the texture, 0..1.
+    pub uv: Rect,
+    /// Multiplies the texture.
+    pub color: LinearColor,
+    pub texture: TextureId,
+    /// Higher layers draw on top.
+    pub layer: i32,
+}
+
+impl Sprite {
+    /// A flat coloured rectangle.
+    pub fn solid(rect: Rect, color: LinearColor, layer: i32) -> Sprite {
+        Sprite {
+            rect,
+            uv: Rect::UNIT,
+            color,
+            texture: TextureId::WHITE,
+            layer,
+        }
+    }
+}
+
+/// Texels at each edge of a nine-slice image that keep their size when it's scaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Border {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Border {
+    pub fn uniform(texels: f32) -> Border {
+        Border {
+            left: texels,
+            top: texels,
+            right: texels,
+            bottom: texels,
+        }
+    }
+
+    /// `left top right bottom`, or one number for all four.
+    pub fn parse(s: &str) -> Option<Border> {
+        let values: Vec<f32> = s
+            .split_whitespace()
+            .map(|v| v.parse().ok())
+            .collect::<Option<_>>()?;
+        match values[..] {
+            [all] => Some(Border::uniform(all)),
+            [left, top, right, bottom] => Some(Border {
+                left,
+                top,
+                right,
+                bottom,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// How an image fills space bigger than itself, or the middle of a nine-slice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SliceFill {
+    #[default]
+    Stretch,
+    /// Repeat at its own size, cutting the last repeat short.
+    Tile,
+}
+
+impl SliceFill {
+    pub fn parse(s: &str) -> Option<SliceFill> {
+        match s {
+            "stretch" => Some(SliceFill::Stretch),
+            "tile" => Some(SliceFill::Tile),
+            _ => None,
+        }
+    }
+}
+
+/// A texture or a region of one, with how it scales. Usually from an atlas or a texture's metadata.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteImage {
+    pub texture: TextureId,
+    pub uv: Rect,
+    /// In texels, what borders and tiles are measured against.
+    pub size: Vec2,
+    /// Nine-slice, if set.
+    pub border: Option<Border>,
+    pub fill: SliceFill,
+}
+
+impl SpriteImage {
+    /// All of a texture `size` texels big, stretched.
+    pub fn whole(texture: TextureId, size: Vec2) -> SpriteImage {
+        SpriteImage {
+            texture,
+            uv: Rect::UNIT,
+            size,
+            border: None,
+            fill: SliceFill::Stretch,
+        }
+    }
+}
+
+/// What the sprite shader takes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+/// Indices of one quad's two triangles, into its four vertices. Clockwise in viewport pixels.
+pub const QUAD_INDICES: [u32; 6] = [0, 1, 2, 2, 3, 0];
+
+/// One draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteRun {
+    pub texture: TextureId,
+    /// Where its quads start, four vertices each.
+    pub first_quad: u32,
+    pub quad_count: u32,
+}
+
+/// Something pushed to a [`SpriteBatch`]: a sprite, or a quad with its vertices already worked out, like a piece
+/// of a shape.
+enum Item {
+    Sprite(Sprite),
+    Quad {
+        vertices: [SpriteVertex; 4],
+        texture: TextureId,
+        layer: i32,
+    },
+}
+
+impl Item {
+    fn layer(&self) -> i32 {
+        match self {
+            Item::Sprite(sprite) => sprite.layer,
+            Item::Quad { layer, .. } => *layer,
+        }
+    }
+}
+
+/// Count the quad just added, ending `vertices` in, into the last run, or start a new one if it's on another
+/// texture.
+fn merge_run(runs: &mut Vec<SpriteRun>, vertices: usize, texture: TextureId) {
+    if let Some(last) = runs.last_mut()
+        && last.texture == texture
+    {
+        last.quad_count += 1;
+        return;
+    }
+    runs.push(SpriteRun {
+        texture,
+        first_quad: vertices as u32 / 4 - 1,
+        quad_count: 1,
+    });
+}
+
+/// A frame's sprites. Keep it around and [`SpriteBatch::clear`] it each frame, to reuse its allocations.
+#[derive(Default)]
+pub struct SpriteBatch {
+    sprites: Vec<Item>,
+    vertices: Vec<SpriteVertex>,
+    runs: Vec<SpriteRun>,
+}
+
+impl SpriteBatch {
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+        self.vertices.clear();
+        self.runs.clear();
+    }
+
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(Item::Sprite(sprite));
+    }
+
+    /// A quad given by its corners, in [`QUAD_INDICES`] order.
+    pub fn quad(&mut self, vertices: [SpriteVertex; 4], texture: TextureId, layer: i32) {
+        self.sprites.push(Item::Quad {
+            vertices,
+            texture,
+            layer,
+        });
+    }
+
+    /// Indexed triangles, like a [`super::shapes::ShapeBatch`]'s. Each triangle goes in as a quad with two corners
+    /// the same, bar pairs that make up a quad between them, which go in as that quad.
+    pub fn triangles(
+        &mut self,
+        vertices: &[SpriteVertex],
+        indices: &[u32],
+        texture: TextureId,
+        layer: i32,
+    ) {
+        let mut triangles = indices.chunks_exact(3).peekable();
+        while let Some(t) = triangles.next() {
+            let corners = match triangles.peek() {
+                // The second half of a quad in QUAD_INDICES order.
+                Some(n) if n[0] == t[2] && n[2] == t[0] => {
+                    let n = triangles.next().unwrap();
+                    [t[0], t[1], t[2], n[1]]
+                }
+                _ => [t[0], t[1], t[2], t[2]],
+            };
+            self.quad(corners.map(|i| vertices[i as usize]), texture, layer);
+        }
+    }
+
+    /// A flat coloured rectangle.
+    pub fn rect(&mut self, rect: Rect, color: LinearColor, layer: i32) {
+        self.push(Sprite::solid(rect, color, layer));
+    }
+
+    /// Draw `image` over `rect`, scaling borders and tiles by `scale` pixels per texel.
+    pub fn image(
+        &mut self,
+        image: &SpriteImage,
+        rect: Rect,
+        scale: f32,
+        color: LinearColor,
+        layer: i32,
+    ) {
+        let fill =
+            |batch: &mut SpriteBatch, dest: Rect, uv: Rect, texels: Vec2, tile: [bool; 2]| {
+                // Tile only along axes being stretched, fixed ones are already the right size.
+                let size = dest.size();
+                let step = Vec2::new(
+                    if tile[0] { texels.x * scale } else { size.x },
+                    if tile[1] { texels.y * scale } else { size.y },
+                );
+                batch.tiles(dest, uv, step, color, image.texture, layer);
+            };
+        let tile = image.fill == SliceFill::Tile;
+        let Some(border) = image.border else {
+            fill(self, rect, image.uv, image.size, [tile; 2]);
+            return;
+        };
+
+        // Borders shrink evenly if the rect is too small to fit them.
+        let size = rect.size();
+        let wanted = Vec2::new(border.left + border.right, border.top + border.bottom) * scale;
+        let shrink = (size / wanted.max(Vec2::splat(f32::EPSILON))).min(Vec2::ONE);
+        let dest_x = [
+            rect.min.x,
+            rect.min.x + border.left * scale * shrink.x,
+            rect.max.x - border.right * scale * shrink.x,
+            rect.max.x,
+        ];
+        let dest_y = [
+            rect.min.y,
+            rect.min.y + border.top * scale * shrink.y,
+            rect.max.y - border.bottom * scale * shrink.y,
+            rect.max.y,
+        ];
+        let texel = image.uv.size() / image.size;
+        let uv_x = [
+            image.uv.min.x,
+            image.uv.min.x + border.left * texel.x,
+            image.uv.max.x - border.right * texel.x,
+            image.uv.max.x,
+        ];
+        let uv_y = [
+            image.uv.min.y,
+            image.uv.min.y + border.top * texel.y,
+            image.uv.max.y - border.bottom * texel.y,
+            image.uv.max.y,
+        ];
+        for row in 0..3 {
+            for column in 0..3 {
+                let dest = Rect::new(
+                    Vec2::new(dest_x[column], dest_y[row]),
+                    Vec2::new(dest_x[column + 1], dest_y[row + 1]),
+                );
+                if dest.size().x <= 0.0 || dest.size().y <= 0.0 {
+                    continue;
+                }
+                let uv = Rect::new(
+                    Vec2::new(uv_x[column], uv_y[row]),
+                    Vec2::new(uv_x[column + 1], uv_y[row + 1]),
+                );
+                let texels = uv.size() / texel;
+                fill(
+                    self,
+                    dest,
+                    uv,
+                    texels,
+                    [tile && column == 1, tile && row == 1],
+                );
+            }
+        }
+    }
+
+    /// Cover `rect` with copies of `uv`, each `step` pixels big, from the top left. The last row and column are cut
+    /// short to fit.
+    pub fn tiles(
+        &mut self,
+        rect: Rect,
+        uv: Rect,
+        step: Vec2,
+        color: LinearColor,
+        texture: TextureId,
+        layer: i32,
+    ) {
+        // Something a few texels wide tiled over a huge rect would be millions of quads, so don't go past this.
+        const MAX_TILES: f32 = 4096.0;
+        let size = rect.size();
+        if size.x <= 0.0 || size.y <= 0.0 {
+            return;
+        }
+        let step = step.max(size / MAX_TILES.sqrt());
+        let mut y = rect.min.y;
+        while y < rect.max.y {
+            let h = step.y.min(rect.max.y - y);
+            let mut x = rect.min.x;
+            while x < rect.max.x {
+                let w = step.x.min(rect.max.x - x);
+                let cut = Vec2::new(w, h) / step;
+                self.push(Sprite {
+                    rect: Rect::from_pos_size(Vec2::new(x, y), Vec2::new(w, h)),
+                    uv: Rect::from_pos_size(uv.min, uv.size() * cut),
+                    color,
+                    texture,
+                    layer,
+                });
+                x += step.x;
+            }
+            y += step.y;
+        }
+    }
+
+    /// Sprites and quads pushed since the last clear.
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    /// Put everything pushed in draw order, and build its vertices and runs.
+    pub fn build(&mut self) {
+        // Stable, so pushes keep their order within a layer.
+        self.sprites.sort_by_key(Item::layer);
+        self.vertices.clear();
+        self.runs.clear();
+        for item in &self.sprites {
+            let sprite = match item {
+                Item::Sprite(sprite) => sprite,
+                Item::Quad {
+                    vertices, texture, ..
+                } => {
+                    self.vertices.extend_from_slice(vertices);
+                    merge_run(&mut self.runs, self.vertices.len(), *texture);
+                    continue;
+                }
+            };
+            let Rect { min, max } = sprite.rect;
+            let Rect {
+                min: uv_min,
+                max: uv_max,
+            } = sprite.uv;
+            let color = sprite.color.to_array();
+            for (position, uv) in [
+                (min, uv_min),
+                (Vec2::new(max.x, min.y), Vec2::new(uv_max.x, uv_min.y)),
+                (max, uv_max),
+                (Vec2::new(min.x, max.y), Vec2::new(uv_min.x, uv_max.y)),
+            ] {
+                self.vertices.push(SpriteVertex {
+                    position: position.to_array(),
+                    uv: uv.to_array(),
+                    color,
+                });
+            }
+            merge_run(&mut self.runs, self.vertices.len(), sprite.texture);
+        }
+    }
+
+    /// As of the last [`SpriteBatch::build`].
+    pub fn vertices(&self) -> &[SpriteVertex] {
+        &self.vertices
+    }
+
+    /// As of the last [`SpriteBatch::build`], in draw order.
+    pub fn runs(&self) -> &[SpriteRun] {
+        &self.runs
+    }
+}
+
+/// Bytes per [`SpriteVertex`].
+const VERTEX_BYTES: u64 = size_of::<SpriteVertex>() as u64;
+/// Bytes of indices per quad.
+const QUAD_INDEX_BYTES: u64 = QUAD_INDICES.len() as u64 * 4;
+/// The fewest quads a frame's buffers are made for.
+const MIN_QUADS: u64 = 256;
+
+/// A frame in flight's vertices, and indices enough for them.
+#[derive(Default)]
+struct Slot {
+    vertices: Option<VulkanBuffer>,
+    indices: Option<VulkanBuffer>,
+}
+
+/// Draws [`SpriteBatch`]es, with the textures it owns.
+pub struct SpritePass {
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, kept until the pass goes as there are only ever a few.
+    pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    sampler: vk::Sampler,
+    /// Never reset, the textures' sets last as long as they do. Taken down by hand, in [`SpritePass::destroy`].
+    descriptors: Option<DescriptorAllocator>,
+    /// By [`TextureId`].
+    textures: Vec<(VulkanTexture, vk::DescriptorSet)>,
+    slots: Vec<Slot>,
+}
+
+impl SpritePass {
+    /// Set up for `frames_in_flight` frames, with shaders compiled from [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`],
+    /// making the built-in textures.
+    ///
+    /// # Safety
+    /// `cache` must be `device`'s, or null, and outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        cache: vk::PipelineCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<SpritePass> {
+        let mut pass = SpritePass {
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            sampler: vk::Sampler::null(),
+            descriptors: None,
+            textures: Vec::new(),
+            slots: Vec::new(),
+        };
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, vertex, fragment) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        pass.slots
+            .resize_with(frames_in_flight.max(1) as usize, Slot::default);
+        return Ok(pass);
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        vertex: &Spirv,
+        fragment: &Spirv,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let binding = |binding, ty| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        };
+        // Apart, as naga can't take combined image samplers.
+        let bindings = [
+            binding(0, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(1, vk::DescriptorType::SAMPLER),
+        ];
+        // The viewport's size.
+        let push = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .size(8);
+
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = raw.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings),
+                allocs(),
+            )?;
+            let set_layouts = [self.set_layout];
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&set_layouts)
+                    .push_constant_ranges(std::slice::from_ref(&push)),
+                allocs(),
+            )?;
+            self.vertex = vertex.create_module(raw)?;
+            self.fragment = fragment.create_module(raw)?;
+            self.sampler = raw.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                allocs(),
+            )?;
+        }
+        self.descriptors = Some(DescriptorAllocator::new(
+            raw,
+            &[
+                (vk::DescriptorType::SAMPLED_IMAGE, 1.0),
+                (vk::DescriptorType::SAMPLER, 1.0),
+            ],
+        ));
+
+        let white = self.add_texture(device, 1, 1, &[255; 4])?;
+        let (width, height, font) = builtin_atlas();
+        let font = self.add_texture(device, width, height, &font)?;
+        debug_assert_eq!((white, font), (TextureId::WHITE, TextureId::FONT));
+        return Ok(());
+    }
+
+    /// Upload sRGB RGBA8 `texels`, `width` by `height`, for sprites to draw with. Waits for the upload.
+    pub fn add_texture(
+        &mut self,
+        device: &VulkanDevice,
+        width: u32,
+        height: u32,
+        texels: &[u8],
+    ) -> VkResult<TextureId> {
+        let descriptors = self
+            .descriptors
+            .as_mut()
+            .ok_or(vk::Result::ERROR_INITIALIZATION_FAILED)?;
+        let set = descriptors.allocate(self.set_layout)?;
+        let texture = upload_texture(device, width, height, texels)?;
+
+        let image = [vk::DescriptorImageInfo::default()
+            .image_view(texture.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let sampler = [vk::DescriptorImageInfo::default().sampler(self.sampler)];
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(&image),
+            vk::WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(&sampler),
+        ];
+        // SAFETY: The set's new, so nothing's using it.
+        unsafe { device.raw().update_descriptor_sets(&writes, &[]) };
+        self.textures.push((texture, set));
+        return Ok(TextureId(self.textures.len() as u32 - 1));
+    }
+
+    /// Keep vertices for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        // SAFETY: Passed on to the caller.
+        unsafe { self.destroy_slots(device) };
+        self.slots
+            .resize_with(frames.max(1) as usize, Slot::default);
+    }
+
+    unsafe fn destroy_slots(&mut self, device: &VulkanDevice) {
+        for slot in self.slots.drain(..) {
+            for buffer in [slot.vertices, slot.indices].into_iter().flatten() {
+                // SAFETY: Passed on to the caller.
+                unsafe { device.destroy_buffer(buffer) };
+            }
+        }
+    }
+
+    /// The pipeline for drawing to `target`, built the first time it's drawn to.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        let builder = GraphicsPipelineBuilder::new(self.layout)
+            .vertex_fragment(self.vertex, self.fragment)
+            .vertex_buffer(0, VERTEX_BYTES as u32, false)
+            .attribute(0, 0, vk::Format::R32G32_SFLOAT, 0)
+            .attribute(1, 0, vk::Format::R32G32_SFLOAT, 8)
+            .attribute(2, 0, vk::Format::R32G32B32A32_SFLOAT, 16)
+            // Shapes and lines come wound either way.
+            .cull(vk::CullModeFlags::NONE)
+            .samples(vk::SampleCountFlags::from_raw(target.samples))
+            .color(target.color, BlendMode::Alpha)
+            // Over everything, whatever the depth buffer says.
+            .depth(target.depth, DepthMode::Off)
+            .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// Record drawing `batch`, built, into the context's pass. Runs on textures the pass hasn't got draw white.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, and the GPU done with the frame that last used this frame's
+    /// slot, like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for.
+    pub unsafe fn record(&mut self, ctx: PassContext, batch: &SpriteBatch) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            extent,
+        } = ctx;
+        if batch.runs().is_empty() {
+            return Ok(());
+        }
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target)? };
+        let quads = batch.vertices().len() as u64 / 4;
+        let vertices: Vec<u8> = batch
+            .vertices()
+            .iter()
+            .flat_map(|v| v.position.into_iter().chain(v.uv).chain(v.color))
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+        // SAFETY: The caller vouches the GPU's done with this slot.
+        unsafe {
+            grow(
+                device,
+                &mut slot.vertices,
+                quads,
+                4 * VERTEX_BYTES,
+                BufferUsage::VERTEX,
+            )?;
+            if grow(
+                device,
+                &mut slot.indices,
+                quads,
+                QUAD_INDEX_BYTES,
+                BufferUsage::INDEX,
+            )? {
+                let buffer = slot.indices.as_ref().unwrap();
+                let indices: Vec<u8> = (0..(buffer.size / QUAD_INDEX_BYTES) as u32)
+                    .flat_map(|quad| QUAD_INDICES.map(|i| quad * 4 + i))
+                    .flat_map(u32::to_ne_bytes)
+                    .collect();
+                device.write_buffer(buffer, 0, &indices)?;
+            }
+            device.write_buffer(slot.vertices.as_ref().unwrap(), 0, &vertices)?;
+        }
+        let (vertex_buffer, index_buffer) = (
+            slot.vertices.as_ref().unwrap().buffer,
+            slot.indices.as_ref().unwrap().buffer,
+        );
+        let push = [extent.width as f32, extent.height as f32]
+            .map(f32::to_ne_bytes)
+            .concat();
+
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            raw.cmd_push_constants(cmd, self.layout, vk::ShaderStageFlags::VERTEX, 0, &push);
+            raw.cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer], &[0]);
+            raw.cmd_bind_index_buffer(cmd, index_buffer, 0, vk::IndexType::UINT32);
+            for run in batch.runs() {
+                let texture = self
+                    .textures
+                    .get(run.texture.0 as usize)
+                    .unwrap_or(&self.textures[TextureId::WHITE.0 as usize]);
+                raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.layout,
+                    0,
+                    &[texture.1],
+                    &[],
+                );
+                let indices = QUAD_INDICES.len() as u32;
+                raw.cmd_draw_indexed(
+                    cmd,
+                    run.quad_count * indices,
+                    1,
+                    run.first_quad * indices,
+                    0,
+                    0,
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and sets go with their pools.
+        unsafe {
+            self.destroy_slots(device);
+            for (texture, _) in self.textures.drain(..) {
+                device.destroy_texture(texture);
+            }
+            self.descriptors = None;
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            raw.destroy_sampler(self.sampler, allocs());
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+            raw.destroy_descriptor_set_layout(self.set_layout, allocs());
+        }
+        self.sampler = vk::Sampler::null();
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+        self.set_layout = vk::DescriptorSetLayout::null();
+    }
+}
+
+/// Upload sRGB RGBA8 `texels`, `width` by `height`, into a new texture to sample, waiting for the upload.
+pub(super) fn upload_texture(
+    device: &VulkanDevice,
+    width: u32,
+    height: u32,
+    texels: &[u8],
+) -> VkResult<VulkanTexture> {
+    let format = TextureFormat::Rgba8Srgb;
+    if texels.len() as u64 != format.size(width, height) {
+        return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+    }
+    let texture = device.create_texture(&TextureDesc {
+        width,
+        height,
+        format,
+        usage: TextureUsage::COPY_DST | TextureUsage::SAMPLED,
+        samples: 1,
+    })?;
+    let staging = device.create_buffer(&BufferDesc {
+        size: texels.len() as u64,
+        usage: BufferUsage::COPY_SRC,
+        location: MemoryLocation::Upload,
+    });
+    let uploaded = staging.and_then(|staging| {
+        // SAFETY: The GPU only has the staging buffer until the wait, and it's destroyed after either way.
+        unsafe {
+            let uploaded = device.write_buffer(&staging, 0, texels).and_then(|_| {
+                let mut cmds = device.begin_commands()?;
+                cmds.transition(&texture, TextureState::Undefined, TextureState::CopyDst);
+                cmds.copy_buffer_to_texture(&staging, &texture);
+                cmds.transition(&texture, TextureState::CopyDst, TextureState::ShaderRead);
+                device.wait(device.submit(cmds)?)
+            });
+            device.destroy_buffer(staging);
+            uploaded
+        }
+    });
+    if let Err(e) = uploaded {
+        device.wait_idle();
+        // SAFETY: The device is idle.
+        unsafe { device.destroy_texture(texture) };
+        return Err(e);
+    }
+    return Ok(texture);
+}
+
+/// Make sure `buffer` has room for `quads` quads of `quad_bytes` each, making a bigger one if it hasn't. True if
+/// it made one.
+///
+/// # Safety
+/// The GPU must be done with the buffer there.
+unsafe fn grow(
+    device: &VulkanDevice,
+    buffer: &mut Option<VulkanBuffer>,
+    quads: u64,
+    quad_bytes: u64,
+    usage: BufferUsage,
+) -> VkResult<bool> {
+    if buffer
+        .as_ref()
+        .is_some_and(|b| b.size >= quads * quad_bytes)
+    {
+        return Ok(false);
+    }
+    if let Some(old) = buffer.take() {
+        // SAFETY: Passed on to the caller.
+        unsafe { device.destroy_buffer(old) };
+    }
+    *buffer = Some(device.create_buffer(&BufferDesc {
+        size: quads.next_power_of_two().max(MIN_QUADS) * quad_bytes,
+        usage,
+        location: MemoryLocation::Upload,
+    })?);
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use ash::vk;
+    use glam::Vec2;
+
+    use super::{
+        Border, FRAGMENT_SHADER, SliceFill, Sprite, SpriteBatch, SpriteImage, SpritePass,
+        TextureId, VERTEX_SHADER,
+    };
+    use crate::{
+        capture::{CaptureFormat, CapturedFrame},
+        color::LinearColor,
+        math::Rect,
+        render::{
+            hal::{Attachment, CommandEncoder, LoadOp, TextureState, vulkan::vk_format},
+            headless::TARGET_FORMAT,
+            pipeline::TargetFormats,
+            rendering::PassContext,
+            shader::compile::{ShaderCompiler, ShaderLanguage, ShaderStage},
+        },
+        test_support::{assert_frame_hash, frame_hash, headless},
+    };
+
+    fn sprite(texture: u32, layer: i32) -> Sprite {
+        Sprite {
+            rect: Rect::from_pos_size(Vec2::splat(layer as f32), Vec2::ONE),
+            uv: Rect::UNIT,
+            color: LinearColor::WHITE,
+            texture: TextureId(texture),
+            layer,
+        }
+    }
+
+    #[test]
+    pub fn batches_in_layer_order() {
+        let mut batch = SpriteBatch::default();
+        for (texture, layer) in [(5, 1), (5, 0), (6, 1), (5, 1), (5, 0)] {
+            batch.push(sprite(texture, layer));
+        }
+        batch.build();
+
+        let runs: Vec<_> = batch
+            .runs()
+            .iter()
+            .map(|r| (r.texture.0, r.first_quad, r.quad_count))
+            .collect();
+        // Layer 0's two merge with layer 1's first, and layer 1's texture 6 keeps its 5s apart.
+        assert_eq!(runs, vec![(5, 0, 3), (6, 3, 1), (5, 4, 1)]);
+        assert_eq!(batch.vertices().len(), 20);
+        assert_eq!(batch.vertices()[2].position, [1.0, 1.0]);
+        assert_eq!(batch.vertices()[3].uv, [0.0, 1.0]);
+    }
+
+    #[test]
+    pub fn slices_and_tiles() {
+        let mut image = SpriteImage {
+            texture: TextureId(2),
+            uv: Rect::new(Vec2::new(0.5, 0.0), Vec2::new(1.0, 0.5)),
+            size: Vec2::splat(32.0),
+            border: Some(Border::uniform(8.0)),
+            fill: SliceFill::Stretch,
+        };
+        let rect = Rect::from_pos_size(Vec2::ZERO, Vec2::new(100.0, 50.0));
+        let mut batch = SpriteBatch::default();
+        batch.image(&image, rect, 2.0, LinearColor::WHITE, 0);
+        batch.build();
+        assert_eq!(batch.len(), 9);
+        // The corners keep their 8 texels, at 2 pixels each, and the middle stretches.
+        let quad = |i: usize| batch.vertices()[i * 4..i * 4 + 4].to_vec();
+        assert_eq!(quad(0)[2].position, [16.0, 16.0]);
+        assert_eq!(quad(0)[2].uv, [0.625, 0.125]);
+        assert_eq!(quad(4)[0].position, [16.0, 16.0]);
+        assert_eq!(quad(4)[2].position, [84.0, 34.0]);
+        assert_eq!(quad(8)[2].uv, [1.0, 0.5]);
+
+        // Too small for the border, which shrinks to fit and leaves the middle out.
+        batch.clear();
+        let small = Rect::from_pos_size(Vec2::ZERO, Vec2::splat(16.0));
+        batch.image(&image, small, 2.0, LinearColor::WHITE, 0);
+        assert_eq!(batch.len(), 4);
+
+        // Tiling the middle: 68 pixels across in 32 pixel tiles is three, the last cut to 4 pixels.
+        batch.clear();
+        image.fill = SliceFill::Tile;
+        batch.image(&image, rect, 2.0, LinearColor::WHITE, 0);
+        // 4 corners, top and bottom 3 each, left and right 1 each, middle 3.
+        assert_eq!(batch.len(), 4 + 6 + 2 + 3);
+
+        batch.clear();
+        batch.tiles(
+            rect,
+            Rect::UNIT,
+            Vec2::splat(40.0),
+            LinearColor::WHITE,
+            TextureId(3),
+            0,
+        );
+        batch.build();
+        assert_eq!(batch.len(), 6);
+        let last = &batch.vertices()[5 * 4..];
+        assert_eq!(last[2].position, [100.0, 50.0]);
+        assert_eq!(last[2].uv, [0.5, 0.25]);
+        assert_eq!(Border::parse("1 2 3 4").unwrap().bottom, 4.0);
+        assert_eq!(Border::parse("1 2"), None);
+    }
+
+    #[test]
+    pub fn draws_over_the_target() {
+        let compiler = ShaderCompiler::new(std::env::temp_dir());
+        if !compiler.available(ShaderLanguage::Glsl) {
+            return;
+        }
+        let compile = |path, text, stage| {
+            compiler
+                .compile_text(Path::new(path), text, stage, "main")
+                .unwrap()
+        };
+        let vertex = compile("sprite.vert", VERTEX_SHADER, ShaderStage::Vertex);
+        let fragment = compile("sprite.frag", FRAGMENT_SHADER, ShaderStage::Fragment);
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+
+        // The right half red, on pixel edges so it's exact anywhere.
+        let mut batch = SpriteBatch::default();
+        let red = LinearColor::rgb(1.0, 0.0, 0.0);
+        batch.rect(Rect::new(Vec2::new(4.0, 0.0), Vec2::new(8.0, 4.0)), red, 0);
+        batch.build();
+        let target = TargetFormats {
+            color: vk_format(TARGET_FORMAT),
+            depth: vk::Format::UNDEFINED,
+            velocity: vk::Format::UNDEFINED,
+            samples: 1,
+        };
+        // SAFETY: Everything's this device's, and waited on before it's destroyed.
+        unsafe {
+            let mut pass =
+                SpritePass::new(device, vk::PipelineCache::null(), &vertex, &fragment, 1).unwrap();
+            let frame = headless
+                .render(8, 4, |cmds, texture| {
+                    let color = Attachment {
+                        texture,
+                        before: TextureState::RenderTarget,
+                        after: TextureState::RenderTarget,
+                        load: LoadOp::Clear(LinearColor::BLACK),
+                        store: true,
+                        resolve: None,
+                    };
+                    cmds.begin_rendering(&[color], None);
+                    let (_, cmd) = cmds.raw();
+                    let ctx = PassContext {
+                        device,
+                        cmd,
+                        frame: 0,
+                        target,
+                        extent: texture.extent,
+                    };
+                    pass.record(ctx, &batch).unwrap();
+                    cmds.end_rendering();
+                })
+                .unwrap();
+            pass.destroy(device);
+
+            let row = [[0, 0, 0, 255]; 4].into_iter().chain([[255, 0, 0, 255]; 4]);
+            let expected = CapturedFrame {
+                width: 8,
+                height: 4,
+                format: CaptureFormat::Rgba8Srgb,
+                data: row.flatten().collect::<Vec<u8>>().repeat(4),
+                frame: 0,
+            };
+            assert_frame_hash("sprites", &frame, frame_hash(&expected));
+        }
+    }
+}
@@ -0,0 +1,21 @@
+#version 450
+// Sprites in viewport pixels from the top left. Keep in step with SpriteVertex and SpritePass in sprite.rs.
+
+layout(push_constant) uniform Push {
+    vec2 viewport;
+};
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+void main() {
+    // The viewport's flipped to keep clip space Y up, and pixels count down.
+    vec2 ndc = position / viewport * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    out_uv = uv;
+    out_color = color;
+}
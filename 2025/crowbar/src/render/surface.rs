@@ -0,0 +1,349 @@
+//! Presenting to windows.
+//! The swapchain made from a surface is in [`super::swapchain`].
+
+use std::{collections::VecDeque, ffi::CStr, ptr, time::Duration};
+
+use ash::{Entry, Instance, khr, prelude::VkResult, vk};
+use winit::{
+    dpi::PhysicalSize,
+    raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle},
+    window::Window,
+};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+/// Instance extensions needed to make surfaces for windows on `display`. `None` if it's a kind we can't present to.
+pub fn required_extensions(display: RawDisplayHandle) -> Option<[&'static CStr; 2]> {
+    let platform = match display {
+        RawDisplayHandle::Wayland(_) => khr::wayland_surface::NAME,
+        RawDisplayHandle::Xlib(_) => khr::xlib_surface::NAME,
+        RawDisplayHandle::Xcb(_) => khr::xcb_surface::NAME,
+        RawDisplayHandle::Windows(_) => khr::win32_surface::NAME,
+        RawDisplayHandle::AppKit(_) => ash::ext::metal_surface::NAME,
+        RawDisplayHandle::Android(_) => khr::android_surface::NAME,
+        _ => return None,
+    };
+    return Some([khr::surface::NAME, platform]);
+}
+
+/// Instance extensions to enable alongside the [`required_extensions`] if the loader has them. The HDR colour spaces
+/// only get listed for surfaces with `VK_EXT_swapchain_colorspace`.
+pub fn optional_extensions(entry: &Entry) -> Vec<&'static CStr> {
+    // SAFETY: Only a query.
+    let available =
+        unsafe { entry.enumerate_instance_extension_properties(None) }.unwrap_or_default();
+    return [ash::ext::swapchain_colorspace::NAME]
+        .into_iter()
+        .filter(|name| {
+            available
+                .iter()
+                .any(|e| e.extension_name_as_c_str() == Ok(*name))
+        })
+        .collect();
+}
+
+/// Make a surface to present to `window` through.
+///
+/// # Safety
+/// `instance` must have the [`required_extensions`] enabled, and the surface must be destroyed before the window.
+pub unsafe fn create_surface(
+    entry: &Entry,
+    instance: &Instance,
+    window: &Window,
+) -> VkResult<vk::SurfaceKHR> {
+    let unsupported = vk::Result::ERROR_EXTENSION_NOT_PRESENT;
+    let display = window.display_handle().map_err(|_| unsupported)?.as_raw();
+    let handle = window.window_handle().map_err(|_| unsupported)?.as_raw();
+    let allocs = Some(&*VK_ALLOCATOR_CALLBACKS);
+
+    // SAFETY: The handles are the window's, which the caller keeps alive longer than the surface.
+    unsafe {
+        return match (display, handle) {
+            (RawDisplayHandle::Wayland(d), RawWindowHandle::Wayland(w)) => {
+                khr::wayland_surface::Instance::new(entry, instance).create_wayland_surface(
+                    &vk::WaylandSurfaceCreateInfoKHR::default()
+                        .display(d.display.as_ptr())
+                        .surface(w.surface.as_ptr()),
+                    allocs,
+                )
+            }
+            (RawDisplayHandle::Xlib(d), RawWindowHandle::Xlib(w)) => {
+                khr::xlib_surface::Instance::new(entry, instance).create_xlib_surface(
+                    &vk::XlibSurfaceCreateInfoKHR::default()
+                        .dpy(d.display.map_or(ptr::null_mut(), |d| d.as_ptr()))
+                        .window(w.window),
+                    allocs,
+                )
+            }
+            (RawDisplayHandle::Xcb(d), RawWindowHandle::Xcb(w)) => {
+                khr::xcb_surface::Instance::new(entry, instance).create_xcb_surface(
+                    &vk::XcbSurfaceCreateInfoKHR::default()
+                        .connection(d.connection.map_or(ptr::null_mut(), |c| c.as_ptr()))
+                        .window(w.window.get()),
+                    allocs,
+                )
+            }
+            (_, RawWindowHandle::Win32(w)) => khr::win32_surface::Instance::new(entry, instance)
+                .create_win32_surface(
+                    &vk::Win32SurfaceCreateInfoKHR::default()
+                        .hinstance(w.hinstance.map_or(0, |h| h.get()))
+                        .hwnd(w.hwnd.get()),
+                    allocs,
+                ),
+            #[cfg(target_os = "macos")]
+            (_, RawWindowHandle::AppKit(_)) => {
+                let layer = crate::platform::macos::metal_layer(window).ok_or(unsupported)?;
+                ash::ext::metal_surface::Instance::new(entry, instance).create_metal_surface(
+                    &vk::MetalSurfaceCreateInfoEXT::default()
+                        .layer(objc2::rc::Retained::as_ptr(&layer).cast()),
+                    allocs,
+                )
+            }
+            (_, RawWindowHandle::AndroidNdk(w)) => {
+                khr::android_surface::Instance::new(entry, instance).create_android_surface(
+                    &vk::AndroidSurfaceCreateInfoKHR::default().window(w.a_native_window.as_ptr()),
+                    allocs,
+                )
+            }
+            _ => Err(unsupported),
+        };
+    }
+}
+
+/// A window's surface, destroyed when dropped.
+pub struct WindowSurface {
+    loader: khr::surface::Instance,
+    surface: vk::SurfaceKHR,
+}
+
+impl WindowSurface {
+    /// See [`create_surface`].
+    ///
+    /// # Safety
+    /// As there, and it has to be dropped before `instance` is destroyed too.
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &Instance,
+        window: &Window,
+    ) -> VkResult<WindowSurface> {
+        // SAFETY: Passed on to the caller.
+        let surface = unsafe { create_surface(entry, instance, window)? };
+        return Ok(WindowSurface {
+            loader: khr::surface::Instance::new(entry, instance),
+            surface,
+        });
+    }
+
+    pub fn raw(&self) -> vk::SurfaceKHR {
+        self.surface
+    }
+}
+
+impl Drop for WindowSurface {
+    fn drop(&mut self) {
+        // SAFETY: The caller of `new` keeps the window and instance around until now, and whatever presented to
+        // the surface went first.
+        unsafe {
+            self.loader
+                .destroy_surface(self.surface, Some(&*VK_ALLOCATOR_CALLBACKS))
+        };
+    }
+}
+
+/// The extent to make a window's swapchain at, from the surface's capabilities and the window's size in physical
+/// pixels. Most surfaces say what they are. On macOS that comes from the CAMetalLayer's drawable size, so it's only
+/// right at Retina scales if the layer is kept in step with the window, see `platform::window_resized`.
+/// Surfaces that leave it to us (Wayland) get the window's size.
+pub fn swapchain_extent(
+    caps: &vk::SurfaceCapabilitiesKHR,
+    window: PhysicalSize<u32>,
+) -> vk::Extent2D {
+    if caps.current_extent.width != u32::MAX {
+        return caps.current_extent;
+    }
+
+    return vk::Extent2D {
+        width: window
+            .width
+            .clamp(caps.min_image_extent.width, caps.max_image_extent.width),
+        height: window
+            .height
+            .clamp(caps.min_image_extent.height, caps.max_image_extent.height),
+    };
+}
+
+/// How many acquire waits [`PresentStats`] keeps, for graphing.
+pub const RECENT_WAITS: usize = 240;
+
+/// How presenting to a window has been going. For telling stutter from presentation apart from slow frames:
+/// long acquire waits mean the swapchain had no image free, and lots of recreation means it keeps going stale.
+#[derive(Clone, Debug, Default)]
+pub struct PresentStats {
+    /// How many times each image was acquired, by index. With FIFO these should be about even.
+    acquired: Vec<u64>,
+    suboptimal: u64,
+    out_of_date: u64,
+    recreated: u64,
+    skipped: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+    recent_waits: VecDeque<Duration>,
+}
+
+impl PresentStats {
+    /// An image was acquired after blocking for `wait`.
+    pub fn on_acquired(&mut self, index: u32, wait: Duration, suboptimal: bool) {
+        let index = index as usize;
+        if self.acquired.len() <= index {
+            self.acquired.resize(index + 1, 0);
+        }
+        self.acquired[index] += 1;
+        self.suboptimal += suboptimal as u64;
+
+        self.total_wait += wait;
+        self.max_wait = self.max_wait.max(wait);
+        if self.recent_waits.len() == RECENT_WAITS {
+            self.recent_waits.pop_front();
+        }
+        self.recent_waits.push_back(wait);
+    }
+
+    /// A present went through, but the swapchain no longer matches the surface exactly.
+    pub fn on_present_suboptimal(&mut self) {
+        self.suboptimal += 1;
+    }
+
+    /// An acquire or present failed because the swapchain was out of date.
+    pub fn on_out_of_date(&mut self) {
+        self.out_of_date += 1;
+    }
+
+    /// A frame had nothing to render to, and was skipped.
+    pub fn on_skipped(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// The swapchain was recreated, with `image_count` images.
+    pub fn on_recreated(&mut self, image_count: u32) {
+        self.recreated += 1;
+        self.acquired.resize(image_count as usize, 0);
+    }
+
+    pub fn acquired(&self) -> &[u64] {
+        &self.acquired
+    }
+
+    pub fn acquires(&self) -> u64 {
+        self.acquired.iter().sum()
+    }
+
+    /// Acquires and presents that came back suboptimal.
+    pub fn suboptimal(&self) -> u64 {
+        self.suboptimal
+    }
+
+    pub fn out_of_date(&self) -> u64 {
+        self.out_of_date
+    }
+
+    pub fn recreated(&self) -> u64 {
+        self.recreated
+    }
+
+    /// Frames skipped for want of an image, like while minimized.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn mean_acquire_wait(&self) -> Duration {
+        match self.acquires() {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.total_wait.as_nanos() / n as u128) as u64),
+        }
+    }
+
+    pub fn max_acquire_wait(&self) -> Duration {
+        self.max_wait
+    }
+
+    /// The last [`RECENT_WAITS`] acquire waits, oldest first.
+    pub fn recent_acquire_waits(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.recent_waits.iter().copied()
+    }
+
+    pub fn reset(&mut self) {
+        let images = self.acquired.len();
+        *self = PresentStats::default();
+        self.acquired.resize(images, 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use ash::vk;
+    use winit::dpi::PhysicalSize;
+
+    use super::{PresentStats, RECENT_WAITS, swapchain_extent};
+
+    #[test]
+    pub fn present_stats() {
+        let mut stats = PresentStats::default();
+        stats.on_recreated(3);
+        for i in 0..300u32 {
+            stats.on_acquired(i % 3, Duration::from_millis(i as u64 % 4), i == 7);
+        }
+        stats.on_present_suboptimal();
+        stats.on_out_of_date();
+        stats.on_skipped();
+
+        assert_eq!(stats.acquired(), &[100, 100, 100]);
+        assert_eq!(stats.suboptimal(), 2);
+        assert_eq!(stats.out_of_date(), 1);
+        assert_eq!(stats.recreated(), 1);
+        assert_eq!(stats.skipped(), 1);
+        assert_eq!(stats.max_acquire_wait(), Duration::from_millis(3));
+        assert_eq!(stats.mean_acquire_wait(), Duration::from_micros(1500));
+        assert_eq!(stats.recent_acquire_waits().count(), RECENT_WAITS);
+
+        stats.reset();
+        assert_eq!(stats.acquired(), &[0, 0, 0]);
+        assert_eq!(stats.mean_acquire_wait(), Duration::ZERO);
+    }
+
+    #[test]
+    pub fn extent_follows_the_surface() {
+        let mut caps = vk::SurfaceCapabilitiesKHR {
+            current_extent: vk::Extent2D {
+                width: 2560,
+                height: 1600,
+            },
+            min_image_extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            max_image_extent: vk::Extent2D {
+                width: 4096,
+                height: 4096,
+            },
+            ..Default::default()
+        };
+        // The window's physical size, a Retina display's 2x of 1280x800 points.
+        let window = PhysicalSize::new(2560, 1600);
+        assert_eq!(swapchain_extent(&caps, window), caps.current_extent);
+
+        // Up to us, within limits.
+        caps.current_extent = vk::Extent2D {
+            width: u32::MAX,
+            height: u32::MAX,
+        };
+        assert_eq!(
+            swapchain_extent(&caps, PhysicalSize::new(8000, 600)),
+            vk::Extent2D {
+                width: 4096,
+                height: 600
+            }
+        );
+    }
+}
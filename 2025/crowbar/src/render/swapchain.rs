@@ -0,0 +1,1014 @@
+//! A window's swapchain, kept matching its surface.
+//!
+//! Anything that makes the swapchain stale, a resize, an out of date or suboptimal acquire or present, or a present
+//! mode change, only marks it. It gets recreated right before the next acquire, so a drag resize sending dozens of
+//! events a frame recreates once. An acquire that comes back out of date recreates and tries once more, so the
+//! frame isn't lost, and one that times out skips the frame. A minimized window has a zero sized surface and no
+//! swapchain can be made for it, so acquires come back empty until it's restored. The surface can also change
+//! again while it's being recreated, which skips the frame and tries again on the next one.
+//!
+//! Each swapchain has a depth buffer the size of its images, remade along with them, so 3D drawn straight to the
+//! window can be depth tested. It's one for all the images, as frames draw to it one after another. With more than
+//! one sample per pixel there's also a multisampled [`ColorTarget`] to draw to instead of the images, resolved into
+//! the one being presented as the frame's pass ends, and the depth buffer has as many samples. Changing the count
+//! marks the swapchain stale like a present mode change does.
+//!
+//! With HDR asked for, and a display that takes it, the images are scRGB or HDR10 instead of sRGB, see
+//! [`OutputColorSpace`]. The surface only lists those with `VK_EXT_swapchain_colorspace` enabled on the instance,
+//! so without it, or on an SDR display, it quietly stays sRGB. Whatever was picked is in [`Swapchain::output`], and
+//! whatever writes the images last has to encode for it.
+//!
+//! todo: the depth buffer and colour target have memory of their own rather than going through hal, so they're not
+//! in the memory report.
+
+use std::time::{Duration, Instant};
+
+use ash::{khr, prelude::VkResult, vk};
+use winit::dpi::PhysicalSize;
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    hal::{
+        Device, LoadOp, TextureFormat, TextureState,
+        vulkan::{aspect, vk_format},
+    },
+    renderer::Renderer,
+    rendering::{Attachment, Resolve},
+    surface::{PresentStats, swapchain_extent},
+};
+use crate::color::LinearColor;
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+/// Longest to wait for an image. Only something gone wrong, like an image acquired and never presented, takes this
+/// long, and it's better to skip frames than hang.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What to do about what an acquire came back with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AcquireAction {
+    /// Render to it, and if it's suboptimal recreate for the next one.
+    Use {
+        index: u32,
+        suboptimal: bool,
+    },
+    /// Recreate and try again. Nothing was signalled, so the semaphore can go again.
+    Recreate,
+    /// Skip the frame. Nothing was signalled here either.
+    Skip,
+    Fail(vk::Result),
+}
+
+impl AcquireAction {
+    fn of(result: VkResult<(u32, bool)>) -> AcquireAction {
+        return match result {
+            Ok((index, suboptimal)) => AcquireAction::Use { index, suboptimal },
+            Err(
+                vk::Result::ERROR_OUT_OF_DATE_KHR
+                | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT,
+            ) => AcquireAction::Recreate,
+            Err(vk::Result::TIMEOUT | vk::Result::NOT_READY) => AcquireAction::Skip,
+            Err(e) => AcquireAction::Fail(e),
+        };
+    }
+}
+
+/// How the presented images are encoded, which decides what gets written to them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    /// sRGB primaries and curve, 0 to 1. With an `_SRGB` format the curve is applied on write.
+    Srgb,
+    /// Linear, with sRGB primaries and 1 as 80 nits, going over 1 for brighter and under 0 for wider colours.
+    ScRgb,
+    /// BT.2020 primaries through the PQ curve, 0 to 1 covering up to 10000 nits.
+    Hdr10,
+}
+
+impl OutputColorSpace {
+    /// What `color_space` is, `None` if it's one we don't present in.
+    pub fn from_vk(color_space: vk::ColorSpaceKHR) -> Option<OutputColorSpace> {
+        return match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR => Some(OutputColorSpace::Srgb),
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Some(OutputColorSpace::ScRgb),
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Some(OutputColorSpace::Hdr10),
+            _ => None,
+        };
+    }
+
+    pub fn is_hdr(self) -> bool {
+        self != OutputColorSpace::Srgb
+    }
+}
+
+/// The format to present in. With `hdr`, scRGB in half floats, then HDR10 in 10 bits or half floats, if the surface
+/// has them. Otherwise 8 bit sRGB, BGRA first as that's what most desktop surfaces list, then whatever sRGB format the
+/// surface likes best, then whatever it likes best at all.
+pub fn choose_format(formats: &[vk::SurfaceFormatKHR], hdr: bool) -> Option<vk::SurfaceFormatKHR> {
+    let find = |format, color_space| {
+        formats
+            .iter()
+            .find(|f| f.format == format && f.color_space == color_space)
+            .copied()
+    };
+    if hdr {
+        let wanted = [
+            (
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            ),
+            (
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+            (
+                vk::Format::A2R10G10B10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+            (
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+        ];
+        if let Some(format) = wanted.into_iter().find_map(|(f, c)| find(f, c)) {
+            return Some(format);
+        }
+    }
+    let srgb = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+    for wanted in [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB] {
+        if let Some(format) = find(wanted, srgb) {
+            return Some(format);
+        }
+    }
+    return formats
+        .iter()
+        .find(|f| f.color_space == srgb)
+        .or(formats.first())
+        .copied();
+}
+
+/// How to present, which can be changed while running. What the surface doesn't have falls back down
+/// [`PresentModePreference::fallbacks`], ending at FIFO, which every surface has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PresentModePreference {
+    /// Wait for vsync, queueing frames up. Never tears.
+    #[default]
+    Fifo,
+    /// Don't wait, replacing the queued frame with each new one. The least latency without tearing.
+    Mailbox,
+    /// Present straight away, tearing. The least latency.
+    Immediate,
+}
+
+impl PresentModePreference {
+    /// FIFO with `vsync`, otherwise mailbox.
+    pub fn from_vsync(vsync: bool) -> PresentModePreference {
+        if vsync {
+            PresentModePreference::Fifo
+        } else {
+            PresentModePreference::Mailbox
+        }
+    }
+
+    /// Whether presenting waits for vsync.
+    pub fn vsync(self) -> bool {
+        self == PresentModePreference::Fifo
+    }
+
+    /// The modes to try, in order. Without vsync both the others come before it, so it only waits if it has to.
+    pub fn fallbacks(self) -> &'static [vk::PresentModeKHR] {
+        use vk::PresentModeKHR as M;
+        return match self {
+            PresentModePreference::Fifo => &[M::FIFO],
+            PresentModePreference::Mailbox => &[M::MAILBOX, M::IMMEDIATE, M::FIFO],
+            PresentModePreference::Immediate => &[M::IMMEDIATE, M::MAILBOX, M::FIFO],
+        };
+    }
+}
+
+/// The first of `preference`'s fallbacks the surface has, or FIFO.
+pub fn choose_present_mode(
+    modes: &[vk::PresentModeKHR],
+    preference: PresentModePreference,
+) -> vk::PresentModeKHR {
+    return preference
+        .fallbacks()
+        .iter()
+        .copied()
+        .find(|m| modes.contains(m))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+}
+
+/// The format for a depth buffer: 32 bit float, which nearly everything can draw to, otherwise 24 bit with stencil.
+/// `None` if `supported` takes neither.
+pub fn choose_depth_format(supported: impl Fn(TextureFormat) -> bool) -> Option<TextureFormat> {
+    [TextureFormat::Depth32Float, TextureFormat::Depth24Stencil8]
+        .into_iter()
+        .find(|&format| supported(format))
+}
+
+/// One more than the minimum, so there's always an image to render to while the others wait to be shown.
+pub fn choose_image_count(caps: &vk::SurfaceCapabilitiesKHR) -> u32 {
+    let count = caps.min_image_count + 1;
+    if caps.max_image_count == 0 {
+        return count;
+    }
+    return count.min(caps.max_image_count);
+}
+
+/// Memory for attachments only drawn to in a frame: lazily allocated if the device has it, where tiled GPUs can
+/// keep them in tile memory and never back them at all, otherwise device local, otherwise anything
+/// `allowed_types` has.
+pub fn choose_memory_type(
+    props: &vk::PhysicalDeviceMemoryProperties,
+    allowed_types: u32,
+) -> Option<u32> {
+    let types = &props.memory_types[..props.memory_type_count as usize];
+    let find = |flags: vk::MemoryPropertyFlags| {
+        (0..types.len() as u32).find(|&i| {
+            allowed_types & (1 << i) != 0 && types[i as usize].property_flags.contains(flags)
+        })
+    };
+    return find(vk::MemoryPropertyFlags::LAZILY_ALLOCATED)
+        .or_else(|| find(vk::MemoryPropertyFlags::DEVICE_LOCAL))
+        .or_else(|| find(vk::MemoryPropertyFlags::empty()));
+}
+
+/// A swapchain's depth buffer.
+pub struct DepthBuffer {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    pub format: TextureFormat,
+    pub samples: u32,
+}
+
+impl DepthBuffer {
+    /// The depth buffer as a pass's depth attachment, starting from `load` and thrown away after, as nothing reads
+    /// it once the frame's drawn.
+    pub fn attachment(&self, load: LoadOp<f32>) -> Attachment<f32> {
+        Attachment {
+            image: self.image,
+            view: self.view,
+            // Whatever the last frame left isn't wanted.
+            before: TextureState::Undefined,
+            after: TextureState::Undefined,
+            load,
+            store: false,
+            resolve: None,
+        }
+    }
+}
+
+/// A swapchain's multisampled colour target, in the images' format, there when it has more than one sample.
+pub struct ColorTarget {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    pub samples: u32,
+}
+
+impl ColorTarget {
+    /// The target as a pass's colour attachment, starting from `load` and resolved into the swapchain image `image`
+    /// with `view`, which the frame's graph has moved to [`TextureState::RenderTarget`] and moves on from there. The
+    /// samples themselves are thrown away.
+    pub fn attachment(
+        &self,
+        load: LoadOp<LinearColor>,
+        image: vk::Image,
+        view: vk::ImageView,
+    ) -> Attachment<LinearColor> {
+        Attachment {
+            image: self.image,
+            view: self.view,
+            before: TextureState::Undefined,
+            after: TextureState::Undefined,
+            load,
+            store: false,
+            resolve: Some(Resolve {
+                image,
+                view,
+                before: TextureState::RenderTarget,
+                after: TextureState::RenderTarget,
+            }),
+        }
+    }
+}
+
+pub struct Swapchain {
+    device: ash::Device,
+    loader: khr::swapchain::Device,
+    surface_loader: khr::surface::Instance,
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    handle: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    format: vk::SurfaceFormatKHR,
+    extent: vk::Extent2D,
+    present_mode: vk::PresentModeKHR,
+    /// `None` if the device has no depth format to draw to.
+    depth_format: Option<TextureFormat>,
+    depth: Option<DepthBuffer>,
+    /// Samples per pixel, with a [`ColorTarget`] when it's over 1.
+    samples: u32,
+    color: Option<ColorTarget>,
+    memory_props: vk::PhysicalDeviceMemoryProperties,
+    present_preference: PresentModePreference,
+    /// Present in HDR if the surface has it.
+    hdr: bool,
+    window_size: PhysicalSize<u32>,
+    /// The images can be copied out of, for screenshots.
+    copyable: bool,
+    /// Gets recreated before the next acquire.
+    stale: bool,
+}
+
+impl Swapchain {
+    /// A swapchain for `surface`, which belongs to a window `window_size` physical pixels big, drawn to with
+    /// [`Renderer::samples`]. The surface has to outlive it, and it has to be dropped before `renderer`.
+    pub fn new(
+        renderer: &Renderer,
+        surface: vk::SurfaceKHR,
+        window_size: PhysicalSize<u32>,
+        present_preference: PresentModePreference,
+        hdr: bool,
+    ) -> VkResult<Swapchain> {
+        let surface_loader = khr::surface::Instance::new(renderer.entry(), renderer.instance());
+        // SAFETY: The surface is from the renderer's instance.
+        let supported = unsafe {
+            surface_loader.get_physical_device_surface_support(
+                renderer.physical_device(),
+                renderer.queue_family(),
+                surface,
+            )?
+        };
+        if !supported {
+            log::error!("{} can't present to this window", renderer.device_name());
+            return Err(vk::Result::ERROR_INCOMPATIBLE_DISPLAY_KHR);
+        }
+
+        let caps = renderer.device().caps();
+        let depth_format = choose_depth_format(|f| caps.supports_render_target(f));
+        if depth_format.is_none() {
+            log::warn!("{} has no depth format to draw to", renderer.device_name());
+        }
+        // SAFETY: Only a query.
+        let memory_props = unsafe {
+            renderer
+                .instance()
+                .get_physical_device_memory_properties(renderer.physical_device())
+        };
+
+        let device = renderer.device().raw().clone();
+        let mut swapchain = Swapchain {
+            loader: khr::swapchain::Device::new(renderer.instance(), &device),
+            device,
+            surface_loader,
+            physical_device: renderer.physical_device(),
+            surface,
+            handle: vk::SwapchainKHR::null(),
+            images: Vec::new(),
+            views: Vec::new(),
+            format: vk::SurfaceFormatKHR::default(),
+            extent: vk::Extent2D::default(),
+            present_mode: vk::PresentModeKHR::FIFO,
+            depth_format,
+            depth: None,
+            samples: renderer.samples(),
+            color: None,
+            memory_props,
+            present_preference,
+            hdr,
+            window_size,
+            copyable: false,
+            stale: true,
+        };
+        swapchain.recreate()?;
+        return Ok(swapchain);
+    }
+
+    /// The window was resized to `size` physical pixels.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size != self.window_size {
+            self.window_size = size;
+            self.stale = true;
+        }
+    }
+
+    /// Present with `preference` from the next frame, or the closest the surface has.
+    pub fn set_present_mode(&mut self, preference: PresentModePreference) {
+        if preference != self.present_preference {
+            self.present_preference = preference;
+            self.stale = true;
+        }
+    }
+
+    /// Ask for HDR output, which the swapchain falls back to sRGB from if the surface doesn't have it.
+    pub fn set_hdr(&mut self, hdr: bool) {
+        if hdr != self.hdr {
+            self.hdr = hdr;
+            self.stale = true;
+        }
+    }
+
+    /// Draw with `samples` per pixel, a count the device has for both colour and depth, see
+    /// [`DeviceCaps::target_samples`](super::hal::caps::DeviceCaps::target_samples).
+    pub fn set_samples(&mut self, samples: u32) {
+        let samples = samples.max(1);
+        if samples != self.samples {
+            self.samples = samples;
+            self.stale = true;
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Make it again to match the surface. False if the surface has no size right now, so nothing could be made.
+    fn recreate(&mut self) -> VkResult<bool> {
+        let (caps, formats, modes) = self.surface_info()?;
+        let extent = swapchain_extent(&caps, self.window_size);
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+        let format =
+            choose_format(&formats, self.hdr).ok_or(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)?;
+        let present_mode = choose_present_mode(&modes, self.present_preference);
+
+        // Screenshots copy out of the images, where the surface lets them.
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (caps.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
+        let composite_alpha = [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|a| caps.supported_composite_alpha.contains(*a))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+
+        // SAFETY: The old swapchain is retired into the new one, and only destroyed once the GPU is done with it.
+        unsafe {
+            let handle = self.loader.create_swapchain(
+                &vk::SwapchainCreateInfoKHR::default()
+                    .surface(self.surface)
+                    .min_image_count(choose_image_count(&caps))
+                    .image_format(format.format)
+                    .image_color_space(format.color_space)
+                    .image_extent(extent)
+                    .image_array_layers(1)
+                    .image_usage(usage)
+                    .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    // Android rotates for us this way, at some cost. todo: prerotate.
+                    .pre_transform(caps.current_transform)
+                    .composite_alpha(composite_alpha)
+                    .present_mode(present_mode)
+                    .clipped(true)
+                    .old_swapchain(self.handle),
+                allocs(),
+            )?;
+            // todo: wait on just the frames using the old images, once there are frames in flight.
+            let _ = self.device.device_wait_idle();
+            self.destroy_images();
+            if self.handle != vk::SwapchainKHR::null() {
+                self.loader.destroy_swapchain(self.handle, allocs());
+            }
+            self.handle = handle;
+
+            self.images = self.loader.get_swapchain_images(handle)?;
+            for &image in &self.images {
+                let view = self.device.create_image_view(
+                    &vk::ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(format.format)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        }),
+                    allocs(),
+                )?;
+                self.views.push(view);
+            }
+            if let Some(format) = self.depth_format {
+                self.depth = Some(self.create_depth(format, extent)?);
+            }
+            if self.samples > 1 {
+                self.color = Some(self.create_color(format.format, extent)?);
+            }
+        }
+
+        if format != self.format || present_mode != self.present_mode {
+            log::info!(
+                "Swapchain is {:?} {:?}, presenting with {present_mode:?}",
+                format.format,
+                format.color_space
+            );
+        }
+        self.format = format;
+        self.extent = extent;
+        self.present_mode = present_mode;
+        self.copyable = usage.contains(vk::ImageUsageFlags::TRANSFER_SRC);
+        self.stale = false;
+        return Ok(true);
+    }
+
+    fn surface_info(
+        &self,
+    ) -> VkResult<(
+        vk::SurfaceCapabilitiesKHR,
+        Vec<vk::SurfaceFormatKHR>,
+        Vec<vk::PresentModeKHR>,
+    )> {
+        let (pd, surface) = (self.physical_device, self.surface);
+        // SAFETY: Queries on our own surface.
+        unsafe {
+            return Ok((
+                self.surface_loader
+                    .get_physical_device_surface_capabilities(pd, surface)?,
+                self.surface_loader
+                    .get_physical_device_surface_formats(pd, surface)?,
+                self.surface_loader
+                    .get_physical_device_surface_present_modes(pd, surface)?,
+            ));
+        }
+    }
+
+    /// # Safety
+    /// The GPU must be done with the images.
+    unsafe fn destroy_images(&mut self) {
+        for view in self.views.drain(..) {
+            // SAFETY: Passed on to the caller.
+            unsafe { self.device.destroy_image_view(view, allocs()) };
+        }
+        self.images.clear();
+        if let Some(depth) = self.depth.take() {
+            // SAFETY: As above.
+            unsafe { self.destroy_target(depth.image, depth.view, depth.memory) };
+        }
+        if let Some(color) = self.color.take() {
+            // SAFETY: As above.
+            unsafe { self.destroy_target(color.image, color.view, color.memory) };
+        }
+    }
+
+    /// A depth buffer `extent` big, with as many samples as the swapchain.
+    ///
+    /// # Safety
+    /// Needs destroying with [`Swapchain::destroy_target`].
+    unsafe fn create_depth(
+        &self,
+        format: TextureFormat,
+        extent: vk::Extent2D,
+    ) -> VkResult<DepthBuffer> {
+        // SAFETY: Passed on to the caller.
+        let (image, view, memory) = unsafe {
+            self.create_target(
+                vk_format(format),
+                aspect(format),
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                extent,
+            )?
+        };
+        return Ok(DepthBuffer {
+            image,
+            view,
+            memory,
+            format,
+            samples: self.samples,
+        });
+    }
+
+    /// A multisampled colour target `extent` big, in `format`.
+    ///
+    /// # Safety
+    /// Needs destroying with [`Swapchain::destroy_target`].
+    unsafe fn create_color(
+        &self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> VkResult<ColorTarget> {
+        // SAFETY: Passed on to the caller.
+        let (image, view, memory) = unsafe {
+            self.create_target(
+                format,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                extent,
+            )?
+        };
+        return Ok(ColorTarget {
+            image,
+            view,
+            memory,
+            samples: self.samples,
+        });
+    }
+
+    /// An image only ever drawn to, `extent` big with the swapchain's samples, in memory of its own.
+    ///
+    /// # Safety
+    /// Needs destroying with [`Swapchain::destroy_target`].
+    unsafe fn create_target(
+        &self,
+        format: vk::Format,
+        aspect: vk::ImageAspectFlags,
+        usage: vk::ImageUsageFlags,
+        extent: vk::Extent2D,
+    ) -> VkResult<(vk::Image, vk::ImageView, vk::DeviceMemory)> {
+        // SAFETY: Plain resource creation, undone if it doesn't all work out.
+        unsafe {
+            let image = self.device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(extent.into())
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::from_raw(self.samples))
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                allocs(),
+            )?;
+            let requirements = self.device.get_image_memory_requirements(image);
+            let Some(memory_type) =
+                choose_memory_type(&self.memory_props, requirements.memory_type_bits)
+            else {
+                self.device.destroy_image(image, allocs());
+                return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+            };
+            let memory = match self.device.allocate_memory(
+                &vk::MemoryAllocateInfo::default()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type)
+                    .push_next(&mut vk::MemoryDedicatedAllocateInfo::default().image(image)),
+                allocs(),
+            ) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    self.device.destroy_image(image, allocs());
+                    return Err(e);
+                }
+            };
+            let view = self
+                .device
+                .bind_image_memory(image, memory, 0)
+                .and_then(|_| {
+                    self.device.create_image_view(
+                        &vk::ImageViewCreateInfo::default()
+                            .image(image)
+                            .view_type(vk::ImageViewType::TYPE_2D)
+                            .format(format)
+                            .subresource_range(
+                                vk::ImageSubresourceRange::default()
+                                    .aspect_mask(aspect)
+                                    .level_count(1)
+                                    .layer_count(1),
+                            ),
+                        allocs(),
+                    )
+                });
+            return match view {
+                Ok(view) => Ok((image, view, memory)),
+                Err(e) => {
+                    self.device.destroy_image(image, allocs());
+                    self.device.free_memory(memory, allocs());
+                    Err(e)
+                }
+            };
+        }
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    unsafe fn destroy_target(
+        &self,
+        image: vk::Image,
+        view: vk::ImageView,
+        memory: vk::DeviceMemory,
+    ) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.device.destroy_image_view(view, allocs());
+            self.device.destroy_image(image, allocs());
+            self.device.free_memory(memory, allocs());
+        }
+    }
+
+    /// The next image to render to, which `semaphore` gets signalled for once it can be. Recreates the swapchain
+    /// first if it's stale. `None` if there's nothing to render to this frame, and the frame should be skipped.
+    pub fn acquire(
+        &mut self,
+        semaphore: vk::Semaphore,
+        stats: &mut PresentStats,
+    ) -> VkResult<Option<u32>> {
+        for _ in 0..2 {
+            if self.stale {
+                match self.recreate() {
+                    Ok(true) => stats.on_recreated(self.images.len() as u32),
+                    Ok(false) => break,
+                    // Changed again while being made, it's still stale.
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        stats.on_out_of_date();
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            let start = Instant::now();
+            // SAFETY: The semaphore is the caller's, and unsignalled as they have to make sure.
+            let result = unsafe {
+                self.loader.acquire_next_image(
+                    self.handle,
+                    ACQUIRE_TIMEOUT.as_nanos() as u64,
+                    semaphore,
+                    vk::Fence::null(),
+                )
+            };
+            match AcquireAction::of(result) {
+                AcquireAction::Use { index, suboptimal } => {
+                    stats.on_acquired(index, start.elapsed(), suboptimal);
+                    // Still usable, so use it, and recreate for the next one.
+                    self.stale |= suboptimal;
+                    return Ok(Some(index));
+                }
+                AcquireAction::Recreate => {
+                    stats.on_out_of_date();
+                    self.stale = true;
+                }
+                AcquireAction::Skip => {
+                    log::warn!("No swapchain image came free in {ACQUIRE_TIMEOUT:?}");
+                    break;
+                }
+                AcquireAction::Fail(e) => return Err(e),
+            }
+        }
+        stats.on_skipped();
+        return Ok(None);
+    }
+
+    /// Present `index` on `queue` once `wait` are signalled.
+    pub fn present(
+        &mut self,
+        queue: vk::Queue,
+        index: u32,
+        wait: &[vk::Semaphore],
+        stats: &mut PresentStats,
+    ) -> VkResult<()> {
+        let swapchains = [self.handle];
+        let indices = [index];
+        // SAFETY: The image was acquired from this swapchain, and the caller's semaphores order the present.
+        let result = unsafe {
+            self.loader.queue_present(
+                queue,
+                &vk::PresentInfoKHR::default()
+                    .wait_semaphores(wait)
+                    .swapchains(&swapchains)
+                    .image_indices(&indices),
+            )
+        };
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                stats.on_present_suboptimal();
+                self.stale = true;
+            }
+            Err(
+                vk::Result::ERROR_OUT_OF_DATE_KHR
+                | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT,
+            ) => {
+                stats.on_out_of_date();
+                self.stale = true;
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    /// Recreate before the next acquire, like after giving up on a frame with an image acquired, which only goes
+    /// back with the swapchain it's from.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn format(&self) -> vk::SurfaceFormatKHR {
+        self.format
+    }
+
+    /// The colour space the images are presented in, sRGB unless HDR was asked for and the surface has it.
+    pub fn output(&self) -> OutputColorSpace {
+        OutputColorSpace::from_vk(self.format.color_space).unwrap_or(OutputColorSpace::Srgb)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// What it presents with, which is [`Swapchain::present_preference`] or what that fell back to.
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    pub fn present_preference(&self) -> PresentModePreference {
+        self.present_preference
+    }
+
+    /// Whether the images can be copied out of, which the surface doesn't have to allow.
+    pub fn copyable(&self) -> bool {
+        self.copyable
+    }
+
+    pub fn images(&self) -> &[vk::Image] {
+        &self.images
+    }
+
+    pub fn views(&self) -> &[vk::ImageView] {
+        &self.views
+    }
+
+    /// The depth buffer to draw with, the same size as the images. `None` if the device has nothing to make one with.
+    pub fn depth(&self) -> Option<&DepthBuffer> {
+        self.depth.as_ref()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// The multisampled colour target to draw to instead of the images, `None` with one sample.
+    pub fn color(&self) -> Option<&ColorTarget> {
+        self.color.as_ref()
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        // SAFETY: Waited on, so nothing's using the images any more.
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.destroy_images();
+            if self.handle != vk::SwapchainKHR::null() {
+                self.loader.destroy_swapchain(self.handle, allocs());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{
+        AcquireAction, OutputColorSpace, PresentModePreference, choose_depth_format, choose_format,
+        choose_image_count, choose_memory_type, choose_present_mode,
+    };
+    use crate::render::hal::TextureFormat;
+
+    #[test]
+    pub fn picks_formats_and_modes() {
+        let format = |format, color_space| vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        };
+        let srgb = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+        let formats = [
+            format(vk::Format::A2B10G10R10_UNORM_PACK32, srgb),
+            format(vk::Format::R8G8B8A8_SRGB, srgb),
+            format(vk::Format::B8G8R8A8_SRGB, srgb),
+        ];
+        assert_eq!(
+            choose_format(&formats, false).unwrap().format,
+            vk::Format::B8G8R8A8_SRGB
+        );
+        // Nothing 8 bit sRGB, so the surface's favourite.
+        assert_eq!(
+            choose_format(&formats[..1], false).unwrap().format,
+            vk::Format::A2B10G10R10_UNORM_PACK32
+        );
+        assert_eq!(choose_format(&[], false), None);
+
+        // HDR prefers scRGB, then HDR10, and falls back to sRGB on an SDR display.
+        let hdr10 = format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let scrgb = format(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        );
+        let hdr = [hdr10, formats[2], scrgb];
+        assert_eq!(choose_format(&hdr, true), Some(scrgb));
+        assert_eq!(choose_format(&hdr[..2], true), Some(hdr10));
+        assert_eq!(choose_format(&hdr, false), Some(formats[2]));
+        assert_eq!(choose_format(&formats, true), Some(formats[2]));
+        // Never HDR without asking, even if it's the surface's favourite.
+        assert_eq!(choose_format(&hdr[..2], false), Some(formats[2]));
+        assert_eq!(choose_format(&[hdr10], false), Some(hdr10));
+        assert_eq!(
+            OutputColorSpace::from_vk(scrgb.color_space),
+            Some(OutputColorSpace::ScRgb)
+        );
+        assert!(!OutputColorSpace::Srgb.is_hdr());
+
+        assert_eq!(
+            choose_depth_format(|_| true),
+            Some(TextureFormat::Depth32Float)
+        );
+        assert_eq!(
+            choose_depth_format(|f| f == TextureFormat::Depth24Stencil8),
+            Some(TextureFormat::Depth24Stencil8)
+        );
+        assert_eq!(choose_depth_format(|f| !f.is_depth()), None);
+
+        let modes = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::IMMEDIATE];
+        let choose = |modes: &[_], preference| choose_present_mode(modes, preference);
+        assert_eq!(
+            choose(&modes, PresentModePreference::Fifo),
+            vk::PresentModeKHR::FIFO
+        );
+        assert_eq!(
+            choose(&modes, PresentModePreference::from_vsync(false)),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+        assert_eq!(
+            choose(&[vk::PresentModeKHR::FIFO], PresentModePreference::Mailbox),
+            vk::PresentModeKHR::FIFO
+        );
+        let all = [
+            vk::PresentModeKHR::FIFO,
+            vk::PresentModeKHR::MAILBOX,
+            vk::PresentModeKHR::IMMEDIATE,
+        ];
+        assert_eq!(
+            choose(&all, PresentModePreference::Mailbox),
+            vk::PresentModeKHR::MAILBOX
+        );
+        assert_eq!(
+            choose(&all, PresentModePreference::Immediate),
+            vk::PresentModeKHR::IMMEDIATE
+        );
+        assert_eq!(
+            choose(&all[..2], PresentModePreference::Immediate),
+            vk::PresentModeKHR::MAILBOX
+        );
+        // Not even FIFO listed, which a surface shouldn't do, still gets FIFO.
+        assert_eq!(
+            choose(&[], PresentModePreference::Immediate),
+            vk::PresentModeKHR::FIFO
+        );
+
+        let mut caps = vk::SurfaceCapabilitiesKHR {
+            min_image_count: 2,
+            max_image_count: 0,
+            ..Default::default()
+        };
+        assert_eq!(choose_image_count(&caps), 3);
+        caps.max_image_count = 2;
+        assert_eq!(choose_image_count(&caps), 2);
+
+        let mut props = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 3,
+            ..Default::default()
+        };
+        props.memory_types[0].property_flags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+        props.memory_types[1].property_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        props.memory_types[2].property_flags =
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED;
+        assert_eq!(choose_memory_type(&props, 0b111), Some(2));
+        assert_eq!(choose_memory_type(&props, 0b011), Some(1));
+        assert_eq!(choose_memory_type(&props, 0b001), Some(0));
+        assert_eq!(choose_memory_type(&props, 0), None);
+    }
+
+    #[test]
+    pub fn recovers_from_acquires() {
+        assert_eq!(
+            AcquireAction::of(Ok((2, true))),
+            AcquireAction::Use {
+                index: 2,
+                suboptimal: true
+            }
+        );
+        assert_eq!(
+            AcquireAction::of(Err(vk::Result::ERROR_OUT_OF_DATE_KHR)),
+            AcquireAction::Recreate
+        );
+        assert_eq!(
+            AcquireAction::of(Err(vk::Result::TIMEOUT)),
+            AcquireAction::Skip
+        );
+        assert_eq!(
+            AcquireAction::of(Err(vk::Result::ERROR_DEVICE_LOST)),
+            AcquireAction::Fail(vk::Result::ERROR_DEVICE_LOST)
+        );
+    }
+}
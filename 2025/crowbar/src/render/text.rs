@@ -0,0 +1,237 @@
+//! Text, drawn through the [`SpriteBatch`] as a quad per glyph, so it batches with the sprites around it.
+//!
+//! Fonts are monospaced bitmap atlases: a grid of equally sized cells, one per character, in character order.
+//! Enough for HUDs and debugging, with no shaping, kerning or fallback fonts.
+//!
+//! The built-in font's atlas is rasterized at startup from Hack, the monospaced font egui already carries, so
+//! there's no font file to ship.
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use glam::Vec2;
+
+use super::sprite::{Sprite, SpriteBatch, TextureId};
+use crate::{color::LinearColor, math::Rect};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitmapFont {
+    pub texture: TextureId,
+    /// Cells across the atlas.
+    pub columns: u32,
+    /// Cells down the atlas.
+    pub rows: u32,
+    /// The character in the top left cell.
+    pub first: char,
+    /// Width over height of a cell.
+    pub aspect: f32,
+    /// Drawn in place of characters the atlas doesn't have.
+    pub fallback: Option<char>,
+}
+
+impl BitmapFont {
+    /// The engine's font: printable ASCII, 16 by 6 cells of 8x16 pixels, in [`TextureId::FONT`].
+    pub fn builtin() -> BitmapFont {
+        BitmapFont {
+            texture: TextureId::FONT,
+            columns: 16,
+            rows: 6,
+            first: ' ',
+            aspect: 0.5,
+            fallback: Some('?'),
+        }
+    }
+
+    /// Where `c` is in the atlas, 0..1.
+    pub fn glyph_uv(&self, c: char) -> Option<Rect> {
+        let index = (c as u32).checked_sub(self.first as u32)?;
+        if index >= self.columns * self.rows {
+            return None;
+        }
+        let cell = Vec2::new(1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let min = Vec2::new((index % self.columns) as f32, (index / self.columns) as f32) * cell;
+        return Some(Rect::new(min, min + cell));
+    }
+
+    /// How far each character moves the pen, with text `height` pixels tall.
+    pub fn advance(&self, height: f32) -> f32 {
+        self.aspect * height
+    }
+
+    /// The size `text` takes up at `height` pixels tall, lines and all.
+    pub fn measure(&self, text: &str, height: f32) -> Vec2 {
+        let (mut longest, mut lines) = (0, 0);
+        for line in text.split('\n') {
+            longest = longest.max(line.chars().count());
+            lines += 1;
+        }
+        return Vec2::new(longest as f32 * self.advance(height), lines as f32 * height);
+    }
+
+    /// Push `text` with its top left at `pos`, `height` pixels tall. Returns the size it took, like
+    /// [`BitmapFont::measure`].
+    pub fn draw(
+        &self,
+        batch: &mut SpriteBatch,
+        text: &str,
+        pos: Vec2,
+        height: f32,
+        color: LinearColor,
+        layer: i32,
+    ) -> Vec2 {
+        let advance = self.advance(height);
+        let mut pen = pos;
+        let mut size = Vec2::new(0.0, height);
+        for c in text.chars() {
+            if c == '\n' {
+                pen = Vec2::new(pos.x, pen.y + height);
+                size.y += height;
+                continue;
+            }
+            let uv = self
+                .glyph_uv(c)
+                .or_else(|| self.fallback.and_then(|f| self.glyph_uv(f)));
+            // Spaces still move the pen, there's just nothing to draw.
+            if let Some(uv) = uv
+                && !c.is_whitespace()
+            {
+                batch.push(Sprite {
+                    rect: Rect::from_pos_size(pen, Vec2::new(advance, height)),
+                    uv,
+                    color,
+                    texture: self.texture,
+                    layer,
+                });
+            }
+            pen.x += advance;
+            size.x = size.x.max(pen.x - pos.x);
+        }
+        return size;
+    }
+}
+
+/// Pixels in a cell of [`BitmapFont::builtin`]'s atlas.
+pub const BUILTIN_CELL: [u32; 2] = [8, 16];
+
+/// [`BitmapFont::builtin`]'s atlas, as its width, height and RGBA8 texels: white, with the glyphs' coverage in alpha.
+pub fn builtin_atlas() -> (u32, u32, Vec<u8>) {
+    let font = BitmapFont::builtin();
+    let [cell_w, cell_h] = BUILTIN_CELL;
+    let (width, height) = (font.columns * cell_w, font.rows * cell_h);
+    let mut texels = [255, 255, 255, 0].repeat((width * height) as usize);
+
+    let fonts = egui::FontDefinitions::default();
+    let Some(hack) = fonts
+        .font_data
+        .get("Hack")
+        .and_then(|data| FontRef::try_from_slice(&data.font).ok())
+    else {
+        log::warn!("egui's fonts have no Hack any more, text will be blank");
+        return (width, height, texels);
+    };
+    // As big as fits the cell both ways. Monospaced, so any glyph's advance does.
+    let unscaled = hack.as_scaled(PxScale::from(cell_h as f32));
+    let fit = (cell_w as f32 / unscaled.h_advance(hack.glyph_id('M'))).min(1.0);
+    let scaled = hack.as_scaled(PxScale::from(cell_h as f32 * fit));
+    let baseline = (cell_h as f32 + scaled.ascent() + scaled.descent()) / 2.0;
+
+    for index in 0..font.columns * font.rows {
+        let Some(c) = char::from_u32(font.first as u32 + index) else {
+            continue;
+        };
+        let (x, y) = (
+            (index % font.columns) * cell_w,
+            (index / font.columns) * cell_h,
+        );
+        let glyph = hack.glyph_id(c).with_scale_and_position(
+            scaled.scale(),
+            point(
+                x as f32 + (cell_w as f32 - scaled.h_advance(hack.glyph_id(c))) / 2.0,
+                y as f32 + baseline,
+            ),
+        );
+        let Some(outline) = hack.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let (px, py) = (
+                bounds.min.x as i64 + gx as i64,
+                bounds.min.y as i64 + gy as i64,
+            );
+            // Clipped to its own cell, so a tall glyph doesn't bleed into the next row.
+            let cell_x = x as i64..(x + cell_w) as i64;
+            let cell_y = y as i64..(y + cell_h) as i64;
+            if cell_x.contains(&px) && cell_y.contains(&py) {
+                let i = (py as usize * width as usize + px as usize) * 4;
+                texels[i + 3] = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        });
+    }
+    return (width, height, texels);
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec2;
+
+    use super::{BUILTIN_CELL, BitmapFont, builtin_atlas};
+    use crate::{color::LinearColor, math::Rect, render::sprite::SpriteBatch};
+
+    #[test]
+    pub fn lays_out_glyphs() {
+        let font = BitmapFont::builtin();
+        assert_eq!(
+            font.glyph_uv('!'),
+            Some(Rect::new(
+                Vec2::new(1.0 / 16.0, 0.0),
+                Vec2::new(2.0 / 16.0, 1.0 / 6.0)
+            ))
+        );
+        assert_eq!(font.glyph_uv('é'), None);
+        assert_eq!(font.measure("ab\nc", 20.0), Vec2::new(20.0, 40.0));
+
+        let mut batch = SpriteBatch::default();
+        let size = font.draw(
+            &mut batch,
+            "a b\né",
+            Vec2::new(100.0, 0.0),
+            20.0,
+            LinearColor::WHITE,
+            0,
+        );
+        assert_eq!(size, font.measure("a b\né", 20.0));
+        // The space isn't drawn, the é falls back to a ?.
+        assert_eq!(batch.len(), 3);
+        batch.build();
+        assert_eq!(batch.vertices()[8].position, [100.0, 20.0]);
+        assert_eq!(
+            batch.vertices()[8].uv,
+            font.glyph_uv('?').unwrap().min.to_array()
+        );
+    }
+
+    #[test]
+    pub fn rasterizes_the_builtin_atlas() {
+        let font = BitmapFont::builtin();
+        let (width, height, texels) = builtin_atlas();
+        assert_eq!([width, height], [16 * BUILTIN_CELL[0], 6 * BUILTIN_CELL[1]]);
+        assert_eq!(texels.len(), (width * height * 4) as usize);
+        // How much of a character's cell is covered.
+        let coverage = |c: char| {
+            let uv = font.glyph_uv(c).unwrap();
+            let (x, y) = (
+                (uv.min.x * width as f32) as u32,
+                (uv.min.y * height as f32) as u32,
+            );
+            let mut sum = 0u32;
+            for cy in y..y + BUILTIN_CELL[1] {
+                for cx in x..x + BUILTIN_CELL[0] {
+                    sum += texels[((cy * width + cx) * 4 + 3) as usize] as u32;
+                }
+            }
+            sum
+        };
+        assert_eq!(coverage(' '), 0);
+        assert!(coverage('.') > 0);
+        assert!(coverage('W') > coverage('.'));
+    }
+}
@@ -0,0 +1,579 @@
+//! Loading textures out of KTX2 files, in whichever block compression the device can sample.
+//!
+//! A content pack can ship a texture in several compressed variants next to each other, named after the family:
+//! `grass.bc.ktx2`, `grass.astc.ktx2`, `grass.etc2.ktx2`, with `grass.ktx2` as the fallback. The loader takes the
+//! first variant the device supports, so the same pack works on desktop and mobile GPUs. If none of them fit, it
+//! transcodes what there is to RGBA8 on the CPU, which costs memory and load time but at least draws.
+//!
+//! 2D textures can say how they scale with [`SLICE_KEY`] in their key/value data: a nine-slice border in texels,
+//! `left top right bottom` or one number for all four, and/or `tile` to tile instead of stretching.
+//!
+//! todo: Only BC1 and BC3 can be transcoded so far. Basis Universal supercompression would make all of this one
+//! file per texture, but needs its transcoder.
+
+use std::{io, ops::Range, sync::Arc};
+
+use ash::vk;
+use glam::Vec2;
+
+use super::{
+    hal::{
+        TextureFormat,
+        caps::{CompressionFamily, DeviceCaps},
+        vulkan::vk_format,
+    },
+    sprite::{Border, SliceFill, SpriteImage, TextureId},
+};
+use crate::{budget::BudgetedCache, platform};
+
+pub const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Magic, then the nine fields up to and including the supercompression scheme, then the index.
+const KTX2_HEADER_SIZE: usize = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+
+/// KTX2 key for how a texture scales, see the module docs.
+pub const SLICE_KEY: &str = "crowbar.slice";
+
+/// A [`SLICE_KEY`] value.
+pub fn parse_slice(value: &str) -> Option<(Option<Border>, SliceFill)> {
+    let (border, fill) = match value.trim().rsplit_once(|c: char| c.is_whitespace()) {
+        Some((border, fill)) if SliceFill::parse(fill).is_some() => (border, fill),
+        _ if SliceFill::parse(value.trim()).is_some() => ("", value.trim()),
+        _ => (value, "stretch"),
+    };
+    let fill = SliceFill::parse(fill)?;
+    if border.trim().is_empty() {
+        return Some((None, fill));
+    }
+    return Some((Some(Border::parse(border)?), fill));
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The format a KTX2 file's `vkFormat` names, if it's one hal has and isn't depth.
+fn format_from_vk(format: vk::Format) -> Option<TextureFormat> {
+    return TextureFormat::ALL
+        .into_iter()
+        .find(|&f| !f.is_depth() && vk_format(f) == format);
+}
+
+/// The parts of a KTX2 file a 2D texture needs.
+#[derive(Clone, Debug)]
+pub struct Ktx2<'a> {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    data: &'a [u8],
+    /// Byte ranges of each mip, biggest first.
+    levels: Vec<Range<usize>>,
+    key_values: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Ktx2<'a> {
+    pub fn parse(data: &'a [u8]) -> io::Result<Ktx2<'a>> {
+        if data.len() < KTX2_HEADER_SIZE || data[..12] != KTX2_MAGIC {
+            return Err(invalid("Not a KTX2 file"));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+        let vk_format = vk::Format::from_raw(u32_at(12) as i32);
+        let format = format_from_vk(vk_format).ok_or_else(|| invalid("Unsupported KTX2 format"))?;
+        let (width, height, depth) = (u32_at(20), u32_at(24), u32_at(28));
+        let (layers, faces, level_count) = (u32_at(32), u32_at(36), u32_at(40));
+        if depth > 1 || layers > 1 || faces != 1 {
+            return Err(invalid("Only 2D KTX2 textures are supported"));
+        }
+        if u32_at(44) != 0 {
+            return Err(invalid("Supercompressed KTX2 files aren't supported"));
+        }
+
+        // Each level halves the size, down to 1x1, so there can't be more than that takes.
+        if level_count > 32 - width.max(height).leading_zeros() {
+            return Err(invalid("KTX2 has more levels than its size allows"));
+        }
+
+        let mut levels = Vec::new();
+        for level in 0..level_count.max(1) as usize {
+            let at = KTX2_HEADER_SIZE + level * 24;
+            if data.len() < at + 24 {
+                return Err(invalid("KTX2 level index is truncated"));
+            }
+            let start = u64_at(at) as usize;
+            let len = u64_at(at + 8) as usize;
+            let expected = format.size((width >> level).max(1), (height >> level).max(1));
+            if len as u64 != expected || start.checked_add(len).is_none_or(|end| end > data.len()) {
+                return Err(invalid("KTX2 level is the wrong size"));
+            }
+            levels.push(start..start + len);
+        }
+
+        // Each entry is its length, the key with a NUL after it, the value, then padding to 4 bytes.
+        let mut key_values = Vec::new();
+        let (kvd_start, kvd_len) = (u32_at(56) as usize, u32_at(60) as usize);
+        let kvd = data
+            .get(kvd_start..kvd_start + kvd_len)
+            .ok_or_else(|| invalid("KTX2 key/value data is truncated"))?;
+        let mut at = 0;
+        while at + 4 <= kvd.len() {
+            let len = u32::from_le_bytes(kvd[at..at + 4].try_into().unwrap()) as usize;
+            let entry = kvd
+                .get(at + 4..at + 4 + len)
+                .ok_or_else(|| invalid("KTX2 key/value is truncated"))?;
+            let nul = entry
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid("KTX2 key isn't terminated"))?;
+            let key =
+                std::str::from_utf8(&entry[..nul]).map_err(|_| invalid("KTX2 key isn't UTF-8"))?;
+            key_values.push((key, &entry[nul + 1..]));
+            at += (4 + len).next_multiple_of(4);
+        }
+
+        return Ok(Ktx2 {
+            format,
+            width,
+            height,
+            data,
+            levels,
+            key_values,
+        });
+    }
+
+    /// The value for `key`, if the file has one.
+    pub fn value(&self, key: &str) -> Option<&'a [u8]> {
+        self.key_values
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+    }
+
+    /// The value for `key` as text, without the NUL it usually ends with.
+    pub fn text_value(&self, key: &str) -> Option<&'a str> {
+        let value = self.value(key)?;
+        std::str::from_utf8(value.strip_suffix(&[0]).unwrap_or(value)).ok()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level(&self, level: usize) -> &'a [u8] {
+        &self.data[self.levels[level].clone()]
+    }
+}
+
+/// Texels ready to upload, with every mip.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub levels: Vec<Vec<u8>>,
+    /// Decompressed on the CPU because the device couldn't sample what was shipped.
+    pub transcoded: bool,
+    /// From [`SLICE_KEY`].
+    pub border: Option<Border>,
+    pub fill: SliceFill,
+}
+
+impl TextureData {
+    /// Bytes held, every mip included.
+    pub fn size(&self) -> u64 {
+        self.levels.iter().map(|l| l.len() as u64).sum()
+    }
+
+    /// All of it, scaling as its metadata says, once uploaded as `texture`.
+    pub fn image(&self, texture: TextureId) -> SpriteImage {
+        SpriteImage {
+            border: self.border,
+            fill: self.fill,
+            ..SpriteImage::whole(texture, Vec2::new(self.width as f32, self.height as f32))
+        }
+    }
+}
+
+/// Loaded textures by name, counted against [`BudgetCategory::Textures`](crate::budget::BudgetCategory).
+pub type TextureCache = BudgetedCache<String, Arc<TextureData>>;
+
+/// Picks texture variants for one device.
+pub struct TextureLoader {
+    /// Families the device supports, most preferred first.
+    families: Vec<CompressionFamily>,
+}
+
+impl TextureLoader {
+    pub fn new(caps: &DeviceCaps) -> TextureLoader {
+        TextureLoader::with_families(&caps.compression)
+    }
+
+    /// For a device that can sample `families`.
+    pub fn with_families(families: &[CompressionFamily]) -> TextureLoader {
+        // ASTC looks better than ETC2 for the size, and BC7 better still where there's BC.
+        let families = [
+            CompressionFamily::Bc,
+            CompressionFamily::Astc,
+            CompressionFamily::Etc2,
+        ]
+        .into_iter()
+        .filter(|f| families.contains(f))
+        .collect();
+        return TextureLoader { families };
+    }
+
+    pub fn can_sample(&self, format: TextureFormat) -> bool {
+        match format.compression() {
+            None => true,
+            Some(family) => self.families.contains(&family),
+        }
+    }
+
+    /// Load `name`, a path without the `.ktx2`, through [`platform::read_asset`].
+    pub fn load(&self, name: &str) -> io::Result<TextureData> {
+        return self.load_with(name, &mut |path| platform::read_asset(path));
+    }
+
+    /// [`TextureLoader::load`], keeping what's loaded in `cache` to hand out again.
+    pub fn load_cached(&self, name: &str, cache: &TextureCache) -> io::Result<Arc<TextureData>> {
+        return cache.get_or_insert_with(&name.to_owned(), || {
+            let data = self.load(name)?;
+            let size = data.size();
+            Ok((Arc::new(data), size))
+        });
+    }
+
+    /// Load `name`, reading files with `read`.
+    pub fn load_with(
+        &self,
+        name: &str,
+        read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>,
+    ) -> io::Result<TextureData> {
+        let mut bytes = None;
+        let variants = self
+            .families
+            .iter()
+            .map(|f| format!("{name}.{}.ktx2", f.name().to_lowercase()))
+            .chain([format!("{name}.ktx2")]);
+        for path in variants {
+            match read(&path) {
+                Ok(read) => {
+                    bytes = Some(read);
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        // Nothing the device can sample, so take any variant and decompress it.
+        if bytes.is_none() {
+            for family in CompressionFamily::ALL {
+                if let Ok(read) = read(&format!("{name}.{}.ktx2", family.name().to_lowercase())) {
+                    bytes = Some(read);
+                    break;
+                }
+            }
+        }
+        let Some(bytes) = bytes else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No variant of texture {name}"),
+            ));
+        };
+        return self.decode(&Ktx2::parse(&bytes)?);
+    }
+
+    /// Copy a parsed file out, transcoding if the device can't sample its format.
+    pub fn decode(&self, ktx: &Ktx2) -> io::Result<TextureData> {
+        let levels = (0..ktx.level_count()).map(|l| ktx.level(l).to_vec());
+        let (border, fill) = match ktx.text_value(SLICE_KEY) {
+            Some(value) => {
+                parse_slice(value).ok_or_else(|| invalid(&format!("Bad {SLICE_KEY} {value:?}")))?
+            }
+            None => (None, SliceFill::Stretch),
+        };
+        if self.can_sample(ktx.format) {
+            return Ok(TextureData {
+                width: ktx.width,
+                height: ktx.height,
+                format: ktx.format,
+                levels: levels.collect(),
+                transcoded: false,
+                border,
+                fill,
+            });
+        }
+
+        let format = ktx.format;
+        let mut out = Vec::new();
+        for (level, data) in levels.enumerate() {
+            let width = (ktx.width >> level).max(1);
+            let height = (ktx.height >> level).max(1);
+            out.push(decode_blocks(format, width, height, &data).ok_or_else(|| {
+                invalid(&format!(
+                    "Can't transcode {format:?}, and the device can't sample it"
+                ))
+            })?);
+        }
+        log::warn!(
+            "Transcoded a {}x{} {format:?} texture to RGBA8, ship a variant this device supports",
+            ktx.width,
+            ktx.height
+        );
+        return Ok(TextureData {
+            width: ktx.width,
+            height: ktx.height,
+            format: if format.is_srgb() {
+                TextureFormat::Rgba8Srgb
+            } else {
+                TextureFormat::Rgba8Unorm
+            },
+            levels: out,
+            transcoded: true,
+            border,
+            fill,
+        });
+    }
+}
+
+fn rgb565(c: u16) -> [u8; 3] {
+    let r = (c >> 11) & 31;
+    let g = (c >> 5) & 63;
+    let b = c & 31;
+    return [
+        (r * 255 / 31) as u8,
+        (g * 255 / 63) as u8,
+        (b * 255 / 31) as u8,
+    ];
+}
+
+/// The 16 colours of a BC1 style colour block, as RGBA8. `punchthrough` is only allowed in BC1 itself.
+fn bc1_block(block: &[u8], punchthrough: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| {
+        let mut out = [0, 0, 0, 255];
+        for i in 0..3 {
+            out[i] = ((a[i] as u32 * wa + b[i] as u32 * wb) / (wa + wb)) as u8;
+        }
+        out
+    };
+    let palette = if c0 > c1 || !punchthrough {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(2, 1),
+            mix(1, 2),
+        ]
+    } else {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(1, 1),
+            [0; 4],
+        ]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    let mut out = [[0; 4]; 16];
+    for (i, texel) in out.iter_mut().enumerate() {
+        *texel = palette[(indices >> (i * 2)) as usize & 3];
+    }
+    return out;
+}
+
+/// The 16 alphas of a BC3 alpha block.
+fn bc3_alpha(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as u32) * a0 + i as u32 * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as u32) * a0 + i as u32 * a1) / 5;
+        }
+        palette[6] = 0;
+    }
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let mut out = [0; 16];
+    for (i, alpha) in out.iter_mut().enumerate() {
+        *alpha = palette[(indices >> (i * 3)) as usize & 7] as u8;
+    }
+    return out;
+}
+
+/// Decompress to tightly packed RGBA8, `None` for formats that can't be yet.
+fn decode_blocks(format: TextureFormat, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let block_bytes = format.size(4, 4) as usize;
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4);
+    let mut out = vec![0; width * height * 4];
+
+    for (index, block) in data.chunks_exact(block_bytes).enumerate() {
+        let texels = match format {
+            TextureFormat::Bc1Unorm | TextureFormat::Bc1Srgb => bc1_block(block, true),
+            TextureFormat::Bc3Unorm | TextureFormat::Bc3Srgb => {
+                let mut texels = bc1_block(&block[8..], false);
+                for (texel, alpha) in texels.iter_mut().zip(bc3_alpha(block)) {
+                    texel[3] = alpha;
+                }
+                texels
+            }
+            _ => return None,
+        };
+
+        let (bx, by) = (index % blocks_wide * 4, index / blocks_wide * 4);
+        for (i, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            // Blocks hang off the edges of images that aren't a multiple of 4.
+            if x < width && y < height {
+                let at = (y * width + x) * 4;
+                out[at..at + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    return Some(out);
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io};
+
+    use ash::vk;
+
+    use super::{KTX2_HEADER_SIZE, KTX2_MAGIC, Ktx2, SLICE_KEY, TextureLoader, parse_slice};
+    use crate::render::{
+        hal::{TextureFormat, caps::CompressionFamily},
+        sprite::{Border, SliceFill},
+    };
+
+    /// A single level 2D KTX2 file, with `key_values`.
+    fn ktx2_with(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        level: &[u8],
+        key_values: &[(&str, &str)],
+    ) -> Vec<u8> {
+        let mut kvd = Vec::new();
+        for (key, value) in key_values {
+            let entry = format!("{key}\0{value}\0");
+            kvd.extend((entry.len() as u32).to_le_bytes());
+            kvd.extend(entry.bytes());
+            kvd.resize(kvd.len().next_multiple_of(4), 0);
+        }
+
+        let mut out = KTX2_MAGIC.to_vec();
+        for field in [format.as_raw() as u32, 1, width, height, 0, 0, 1, 1, 0] {
+            out.extend(field.to_le_bytes());
+        }
+        // No data format descriptor or supercompression data, key/values right after the level index.
+        let kvd_start = KTX2_HEADER_SIZE + 24;
+        for field in [0, 0, kvd_start as u32, kvd.len() as u32] {
+            out.extend(field.to_le_bytes());
+        }
+        out.extend([0; 16]);
+        let start = kvd_start + kvd.len();
+        for field in [start, level.len(), level.len()] {
+            out.extend((field as u64).to_le_bytes());
+        }
+        out.extend(kvd);
+        out.extend(level);
+        return out;
+    }
+
+    fn ktx2(format: vk::Format, width: u32, height: u32, level: &[u8]) -> Vec<u8> {
+        ktx2_with(format, width, height, level, &[])
+    }
+
+    #[test]
+    pub fn picks_or_transcodes() {
+        // Red and blue endpoints, the top row all red and the rest blue.
+        let mut bc1 = vec![0x00, 0xF8, 0x1F, 0x00];
+        bc1.extend(0x5555_5500u32.to_le_bytes());
+        let mut files = HashMap::new();
+        files.insert(
+            "rock.bc.ktx2".to_string(),
+            ktx2(vk::Format::BC1_RGBA_SRGB_BLOCK, 4, 4, &bc1),
+        );
+        files.insert(
+            "rock.astc.ktx2".to_string(),
+            ktx2(vk::Format::ASTC_4X4_SRGB_BLOCK, 4, 4, &[0; 16]),
+        );
+        let mut read = |path: &str| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))
+        };
+
+        let desktop = TextureLoader::with_families(&[CompressionFamily::Bc]);
+        let rock = desktop.load_with("rock", &mut read).unwrap();
+        assert_eq!(rock.format, TextureFormat::Bc1Srgb);
+        assert!(!rock.transcoded);
+
+        let phone =
+            TextureLoader::with_families(&[CompressionFamily::Etc2, CompressionFamily::Astc]);
+        let rock = phone.load_with("rock", &mut read).unwrap();
+        assert_eq!(rock.format, TextureFormat::Astc4x4Srgb);
+
+        // Only BC shipped, on something without it.
+        files.remove("rock.astc.ktx2");
+        let mut read = |path: &str| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))
+        };
+        let rock = phone.load_with("rock", &mut read).unwrap();
+        assert!(rock.transcoded);
+        assert_eq!(rock.format, TextureFormat::Rgba8Srgb);
+        let texels = &rock.levels[0];
+        assert_eq!(&texels[..4], &[255, 0, 0, 255]);
+        assert_eq!(&texels[4 * 4..4 * 4 + 4], &[0, 0, 255, 255]);
+
+        assert!(phone.load_with("grass", &mut read).is_err());
+    }
+
+    #[test]
+    pub fn rejects_too_many_levels() {
+        let mut file = ktx2(vk::Format::R8G8B8A8_SRGB, 1, 1, &[0; 4]);
+        assert!(Ktx2::parse(&file).is_ok());
+        // A 1x1 texture only has the one, and 33 would shift the size right off the end.
+        for levels in [2u32, 33] {
+            file[40..44].copy_from_slice(&levels.to_le_bytes());
+            assert!(Ktx2::parse(&file).is_err());
+        }
+    }
+
+    #[test]
+    pub fn slice_metadata() {
+        let file = ktx2_with(
+            vk::Format::R8G8B8A8_SRGB,
+            1,
+            1,
+            &[0; 4],
+            &[("KTXwriter", "test"), (SLICE_KEY, "4 6 4 6 tile")],
+        );
+        let ktx = Ktx2::parse(&file).unwrap();
+        assert_eq!(ktx.text_value("KTXwriter"), Some("test"));
+        let data = TextureLoader::with_families(&[]).decode(&ktx).unwrap();
+        assert_eq!(data.fill, SliceFill::Tile);
+        assert_eq!(data.border.unwrap().top, 6.0);
+
+        assert_eq!(parse_slice("tile"), Some((None, SliceFill::Tile)));
+        assert_eq!(
+            parse_slice("8"),
+            Some((Some(Border::uniform(8.0)), SliceFill::Stretch))
+        );
+        assert_eq!(parse_slice("8 wiggle"), None);
+    }
+}
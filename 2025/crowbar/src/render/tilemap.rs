@@ -0,0 +1,597 @@
+//! Tilemaps: grids of tiles from tileset atlases, in world space for a [`super::camera2d::Camera2d`].
+//!
+//! Layers are cut into chunks of [`CHUNK_TILES`] square, each with its own mesh built once and rebuilt only when a
+//! tile in it changes, so a big static map costs nothing per frame beyond drawing the chunks in view. A chunk's
+//! generation goes up with every rebuild, for whoever keeps its vertex buffer to know when to upload it again.
+//! Animated tiles stay out of the chunk meshes and are built each frame for what's in view, so a waterfall doesn't
+//! rebuild the chunk it's in every frame.
+//!
+//! Tile ids are Tiled's: 0 is empty, each tileset takes the ids from its `first_gid`, and the top bits flip the tile
+//! (see [`FLIP_H`]). The map's bottom left corner is at the world origin, with row 0 at the top like in Tiled.
+//! Tiles bigger than the grid hang up and to the right of their cell, also like Tiled.
+//!
+//! [`Tilemap::draw`] copies the chunks in view into a [`SpriteBatch`] through a [`Camera2d`], a sprite layer per
+//! map layer, so push maps from [`Plugin::pre_frame`](crate::app::Plugin::pre_frame) like other sprites.
+
+use std::collections::HashMap;
+
+use glam::{UVec2, Vec2};
+
+use super::{
+    camera2d::Camera2d,
+    sprite::{SpriteBatch, SpriteRun, SpriteVertex, TextureId},
+};
+use crate::math::Rect;
+
+pub mod tiled;
+
+/// Tiles along each side of a chunk.
+pub const CHUNK_TILES: u32 = 16;
+
+/// Tile id bit mirroring the tile left to right.
+pub const FLIP_H: u32 = 0x8000_0000;
+/// Tile id bit mirroring the tile top to bottom.
+pub const FLIP_V: u32 = 0x4000_0000;
+/// Tile id bit mirroring the tile across its top left to bottom right diagonal, applied before the other two.
+pub const FLIP_D: u32 = 0x2000_0000;
+/// The bits of a tile id that aren't flags. Tiled's hexagonal rotation bit is ignored.
+pub const GID_MASK: u32 = 0x0fff_ffff;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationFrame {
+    /// Tile index in the same tileset.
+    pub tile: u32,
+    /// In seconds.
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tileset {
+    pub texture: TextureId,
+    /// The tile id of its first tile.
+    pub first_gid: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+    /// In texels, like the rest.
+    pub tile_size: UVec2,
+    pub image_size: UVec2,
+    /// Around the edge of the image.
+    pub margin: u32,
+    /// Between tiles.
+    pub spacing: u32,
+    /// By tile index.
+    pub animations: HashMap<u32, Vec<AnimationFrame>>,
+}
+
+impl Tileset {
+    /// Tileset for a texture cut into a grid of `tile_size` tiles with no gaps.
+    pub fn grid(
+        texture: TextureId,
+        first_gid: u32,
+        image_size: UVec2,
+        tile_size: UVec2,
+    ) -> Tileset {
+        let tiles = image_size / tile_size.max(UVec2::ONE);
+        Tileset {
+            texture,
+            first_gid,
+            tile_count: tiles.x * tiles.y,
+            columns: tiles.x,
+            tile_size,
+            image_size,
+            margin: 0,
+            spacing: 0,
+            animations: HashMap::new(),
+        }
+    }
+
+    /// The tile index of `gid` in this tileset, if it's in it.
+    pub fn index(&self, gid: u32) -> Option<u32> {
+        let index = (gid & GID_MASK).checked_sub(self.first_gid)?;
+        return (index < self.tile_count).then_some(index);
+    }
+
+    /// Where tile `index` is in the texture, 0..1.
+    pub fn uv(&self, index: u32) -> Rect {
+        let columns = self.columns.max(1);
+        let cell = UVec2::new(index % columns, index / columns);
+        let min = UVec2::splat(self.margin) + cell * (self.tile_size + self.spacing);
+        let image = self.image_size.max(UVec2::ONE).as_vec2();
+        return Rect::from_pos_size(min.as_vec2() / image, self.tile_size.as_vec2() / image);
+    }
+
+    /// What tile `index` shows `time` seconds in: itself unless it's animated.
+    pub fn frame_at(&self, index: u32, time: f64) -> u32 {
+        let Some(frames) = self.animations.get(&index) else {
+            return index;
+        };
+        let total: f64 = frames.iter().map(|f| f.duration as f64).sum();
+        if total <= 0.0 {
+            return frames.first().map_or(index, |f| f.tile);
+        }
+        let mut t = time.rem_euclid(total);
+        for frame in frames {
+            if t < frame.duration as f64 {
+                return frame.tile;
+            }
+            t -= frame.duration as f64;
+        }
+        return frames[frames.len() - 1].tile;
+    }
+}
+
+/// Quads in world space ready to upload, sorted by texture so each texture is one draw.
+#[derive(Clone, Debug, Default)]
+pub struct TileMesh {
+    pub vertices: Vec<SpriteVertex>,
+    pub runs: Vec<SpriteRun>,
+}
+
+impl TileMesh {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.runs.clear();
+    }
+
+    fn build(&mut self, quads: &mut [(TextureId, [SpriteVertex; 4])]) {
+        self.clear();
+        quads.sort_by_key(|q| q.0);
+        for (texture, vertices) in quads.iter() {
+            self.vertices.extend_from_slice(vertices);
+            if let Some(last) = self.runs.last_mut()
+                && last.texture == *texture
+            {
+                last.quad_count += 1;
+                continue;
+            }
+            self.runs.push(SpriteRun {
+                texture: *texture,
+                first_quad: self.vertices.len() as u32 / 4 - 1,
+                quad_count: 1,
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TileChunk {
+    /// Its top left tile.
+    pub origin: UVec2,
+    /// World space bounds of its tiles, animated ones too, for culling.
+    pub bounds: Rect,
+    /// Its tiles, bar the animated ones.
+    pub mesh: TileMesh,
+    /// Goes up every time the mesh is rebuilt.
+    pub generation: u64,
+    /// Animated tiles, by position.
+    animated: Vec<(UVec2, u32)>,
+    dirty: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    /// Multiplies the tiles' alpha.
+    pub opacity: f32,
+    /// Tile ids, row by row from the top.
+    tiles: Vec<u32>,
+    chunks: Vec<TileChunk>,
+}
+
+impl TileLayer {
+    pub fn chunks(&self) -> &[TileChunk] {
+        &self.chunks
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tilemap {
+    width: u32,
+    height: u32,
+    /// A cell's size in texels, what tilesets' tile sizes are measured against.
+    grid: UVec2,
+    /// A cell's size in world units.
+    tile_size: Vec2,
+    /// Sorted by first tile id.
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+}
+
+impl Tilemap {
+    /// An empty map `width` by `height` cells of `grid` texels, one world unit per texel.
+    pub fn new(width: u32, height: u32, grid: UVec2) -> Tilemap {
+        Tilemap {
+            width,
+            height,
+            grid: grid.max(UVec2::ONE),
+            tile_size: grid.max(UVec2::ONE).as_vec2(),
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// A cell's size in world units.
+    pub fn tile_size(&self) -> Vec2 {
+        self.tile_size
+    }
+
+    pub fn set_tile_size(&mut self, size: Vec2) {
+        self.tile_size = size;
+        self.mark_all_dirty();
+    }
+
+    /// The whole map, in world space.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(
+            Vec2::ZERO,
+            Vec2::new(self.width as f32, self.height as f32) * self.tile_size,
+        )
+    }
+
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) {
+        let at = self
+            .tilesets
+            .partition_point(|t| t.first_gid <= tileset.first_gid);
+        self.tilesets.insert(at, tileset);
+        self.mark_all_dirty();
+    }
+
+    /// The tileset `gid` is from.
+    pub fn tileset(&self, gid: u32) -> Option<&Tileset> {
+        let gid = gid & GID_MASK;
+        let at = self.tilesets.partition_point(|t| t.first_gid <= gid);
+        let tileset = &self.tilesets[at.checked_sub(1)?];
+        return tileset.index(gid).map(|_| tileset);
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layer_mut(&mut self, layer: usize) -> &mut TileLayer {
+        &mut self.layers[layer]
+    }
+
+    /// The index of the first layer called `name`.
+    pub fn find_layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    /// Add an empty layer on top, returning its index.
+    pub fn add_layer(&mut self, name: impl Into<String>) -> usize {
+        let across = self.width.div_ceil(CHUNK_TILES);
+        let down = self.height.div_ceil(CHUNK_TILES);
+        let chunks = (0..across * down)
+            .map(|i| TileChunk {
+                origin: UVec2::new(i % across, i / across) * CHUNK_TILES,
+                bounds: Rect::default(),
+                mesh: TileMesh::default(),
+                generation: 0,
+                animated: Vec::new(),
+                dirty: true,
+            })
+            .collect();
+        self.layers.push(TileLayer {
+            name: name.into(),
+            visible: true,
+            opacity: 1.0,
+            tiles: vec![0; (self.width * self.height) as usize],
+            chunks,
+        });
+        return self.layers.len() - 1;
+    }
+
+    /// The tile id at `x, y`, flags and all, 0 for empty or outside the map.
+    pub fn tile(&self, layer: usize, x: u32, y: u32) -> u32 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.layers[layer].tiles[(y * self.width + x) as usize]
+    }
+
+    /// Set the tile at `x, y`, ignoring anything outside the map.
+    pub fn set_tile(&mut self, layer: usize, x: u32, y: u32, gid: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let chunk = self.chunk_index(x, y);
+        let layer = &mut self.layers[layer];
+        let tile = &mut layer.tiles[(y * self.width + x) as usize];
+        if *tile != gid {
+            *tile = gid;
+            layer.chunks[chunk].dirty = true;
+        }
+    }
+
+    /// Which cell a world position is in.
+    pub fn cell_at(&self, world: Vec2) -> Option<UVec2> {
+        let cell = (world / self.tile_size).floor();
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return None;
+        }
+        let (x, row) = (cell.x as u32, cell.y as u32);
+        if x >= self.width || row >= self.height {
+            return None;
+        }
+        return Some(UVec2::new(x, self.height - 1 - row));
+    }
+
+    /// World space bounds of cell `x, y`.
+    pub fn cell_rect(&self, x: u32, y: u32) -> Rect {
+        let min = Vec2::new(x as f32, (self.height - 1 - y) as f32) * self.tile_size;
+        return Rect::from_pos_size(min, self.tile_size);
+    }
+
+    fn chunk_index(&self, x: u32, y: u32) -> usize {
+        let across = self.width.div_ceil(CHUNK_TILES);
+        return ((y / CHUNK_TILES) * across + x / CHUNK_TILES) as usize;
+    }
+
+    fn mark_all_dirty(&mut self) {
+        for chunk in self.layers.iter_mut().flat_map(|l| &mut l.chunks) {
+            chunk.dirty = true;
+        }
+    }
+
+    /// The quad for `gid` at `x, y`, if it's from a tileset. `frame` swaps in the tile to show instead.
+    fn quad(
+        &self,
+        x: u32,
+        y: u32,
+        gid: u32,
+        frame: Option<f64>,
+        alpha: f32,
+    ) -> Option<(TextureId, [SpriteVertex; 4])> {
+        let tileset = self.tileset(gid)?;
+        let mut index = tileset.index(gid)?;
+        if let Some(time) = frame {
+            index = tileset.frame_at(index, time);
+        }
+        let uv = tileset.uv(index);
+        let mut uvs = [
+            uv.min,
+            Vec2::new(uv.max.x, uv.min.y),
+            uv.max,
+            Vec2::new(uv.min.x, uv.max.y),
+        ];
+        // Corners go top left, top right, bottom right, bottom left.
+        if gid & FLIP_D != 0 {
+            uvs.swap(1, 3);
+        }
+        if gid & FLIP_H != 0 {
+            uvs.swap(0, 1);
+            uvs.swap(2, 3);
+        }
+        if gid & FLIP_V != 0 {
+            uvs.swap(0, 3);
+            uvs.swap(1, 2);
+        }
+        let cell = self.cell_rect(x, y);
+        let size = tileset.tile_size.as_vec2() / self.grid.as_vec2() * self.tile_size;
+        let (left, bottom) = (cell.min.x, cell.min.y);
+        let (right, top) = (left + size.x, bottom + size.y);
+        let positions = [
+            Vec2::new(left, top),
+            Vec2::new(right, top),
+            Vec2::new(right, bottom),
+            Vec2::new(left, bottom),
+        ];
+        let color = [1.0, 1.0, 1.0, alpha];
+        let vertices = [0, 1, 2, 3].map(|i| SpriteVertex {
+            position: positions[i].to_array(),
+            uv: uvs[i].to_array(),
+            color,
+        });
+        return Some((tileset.texture, vertices));
+    }
+
+    /// Rebuild the meshes of chunks with changed tiles, returning how many.
+    pub fn build_chunks(&mut self) -> usize {
+        let mut quads = Vec::new();
+        let mut rebuilt = 0;
+        for l in 0..self.layers.len() {
+            for c in 0..self.layers[l].chunks.len() {
+                if !self.layers[l].chunks[c].dirty {
+                    continue;
+                }
+                let layer = &self.layers[l];
+                let origin = layer.chunks[c].origin;
+                let end = (origin + CHUNK_TILES).min(UVec2::new(self.width, self.height));
+                let mut animated = Vec::new();
+                let mut bounds: Option<Rect> = None;
+                quads.clear();
+                for y in origin.y..end.y {
+                    for x in origin.x..end.x {
+                        let gid = self.tile(l, x, y);
+                        let Some(quad) = self.quad(x, y, gid, None, layer.opacity) else {
+                            continue;
+                        };
+                        let min = Vec2::from(quad.1[3].position);
+                        let max = Vec2::from(quad.1[1].position);
+                        bounds = Some(match bounds {
+                            Some(b) => Rect::new(b.min.min(min), b.max.max(max)),
+                            None => Rect::new(min, max),
+                        });
+                        let tileset = self.tileset(gid).unwrap();
+                        if tileset
+                            .animations
+                            .contains_key(&tileset.index(gid).unwrap())
+                        {
+                            animated.push((UVec2::new(x, y), gid));
+                        } else {
+                            quads.push(quad);
+                        }
+                    }
+                }
+                let chunk = &mut self.layers[l].chunks[c];
+                chunk.mesh.build(&mut quads);
+                chunk.bounds = bounds.unwrap_or_default();
+                chunk.animated = animated;
+                chunk.generation += 1;
+                chunk.dirty = false;
+                rebuilt += 1;
+            }
+        }
+        return rebuilt;
+    }
+
+    /// Chunks of `layer` with anything in `view`, a world space rect like [`Camera2d::world_bounds`]. Call
+    /// [`Tilemap::build_chunks`] first.
+    ///
+    /// [`Camera2d::world_bounds`]: super::camera2d::Camera2d::world_bounds
+    pub fn visible_chunks(&self, layer: usize, view: Rect) -> impl Iterator<Item = &TileChunk> {
+        let layer = &self.layers[layer];
+        layer.chunks.iter().filter(move |c| {
+            layer.visible && c.bounds.size() != Vec2::ZERO && c.bounds.overlaps(&view)
+        })
+    }
+
+    /// Build the animated tiles of `layer` in `view` as they are `time` seconds in.
+    pub fn build_animated(&self, layer: usize, view: Rect, time: f64, mesh: &mut TileMesh) {
+        let mut quads = Vec::new();
+        let alpha = self.layers[layer].opacity;
+        for chunk in self.visible_chunks(layer, view) {
+            for &(cell, gid) in &chunk.animated {
+                quads.extend(self.quad(cell.x, cell.y, gid, Some(time), alpha));
+            }
+        }
+        mesh.build(&mut quads);
+    }
+
+    /// Draw the layers in view of `camera` into `sprites`, map layer `i` on sprite layer `layer + i`, with animated
+    /// tiles as they are `time` seconds in. Call [`Tilemap::build_chunks`] first.
+    pub fn draw(
+        &self,
+        camera: &Camera2d,
+        viewport: Vec2,
+        time: f64,
+        layer: i32,
+        sprites: &mut SpriteBatch,
+    ) {
+        let view = camera.world_bounds(viewport);
+        let mut animated = TileMesh::default();
+        let mut push = |mesh: &TileMesh, layer: i32| {
+            for run in &mesh.runs {
+                let first = run.first_quad as usize * 4;
+                let end = first + run.quad_count as usize * 4;
+                for quad in mesh.vertices[first..end].chunks_exact(4) {
+                    let vertices = [0, 1, 2, 3].map(|i| SpriteVertex {
+                        position: camera
+                            .world_to_viewport(Vec2::from(quad[i].position), viewport)
+                            .to_array(),
+                        ..quad[i]
+                    });
+                    sprites.quad(vertices, run.texture, layer);
+                }
+            }
+        };
+        for l in 0..self.layers.len() {
+            let sprite_layer = layer + l as i32;
+            for chunk in self.visible_chunks(l, view) {
+                push(&chunk.mesh, sprite_layer);
+            }
+            self.build_animated(l, view, time, &mut animated);
+            push(&animated, sprite_layer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{UVec2, Vec2};
+
+    use super::{AnimationFrame, FLIP_H, Tilemap, Tileset};
+    use crate::{
+        math::Rect,
+        render::{
+            camera2d::Camera2d,
+            sprite::{SpriteBatch, TextureId},
+        },
+    };
+
+    #[test]
+    pub fn builds_chunks() {
+        let mut map = Tilemap::new(40, 20, UVec2::splat(16));
+        // 4 by 4 tiles, the first animated between tiles 0 and 1.
+        let mut tiles = Tileset::grid(TextureId(3), 1, UVec2::splat(64), UVec2::splat(16));
+        tiles.animations.insert(
+            0,
+            vec![
+                AnimationFrame {
+                    tile: 0,
+                    duration: 0.5,
+                },
+                AnimationFrame {
+                    tile: 1,
+                    duration: 0.5,
+                },
+            ],
+        );
+        map.add_tileset(tiles);
+        map.add_tileset(Tileset::grid(
+            TextureId(4),
+            17,
+            UVec2::splat(32),
+            UVec2::splat(32),
+        ));
+        let ground = map.add_layer("ground");
+        assert_eq!(map.layers()[ground].chunks().len(), 3 * 2);
+        assert_eq!(map.build_chunks(), 6);
+        assert_eq!(map.build_chunks(), 0);
+
+        map.set_tile(ground, 0, 19, 2 | FLIP_H);
+        map.set_tile(ground, 1, 19, 17);
+        map.set_tile(ground, 2, 19, 1);
+        map.set_tile(ground, 39, 0, 3);
+        assert_eq!(map.build_chunks(), 2);
+        assert_eq!(map.cell_at(Vec2::new(20.0, 5.0)), Some(UVec2::new(1, 19)));
+
+        // The bottom left chunk has two static tiles, on different textures, and the animated one.
+        let view = Rect::new(Vec2::ZERO, Vec2::new(100.0, 50.0));
+        let chunks: Vec<_> = map.visible_chunks(ground, view).collect();
+        assert_eq!(chunks.len(), 1);
+        let mesh = &chunks[0].mesh;
+        assert_eq!(mesh.runs.len(), 2);
+        assert_eq!(mesh.vertices.len(), 8);
+        // Flipped, the top left corner shows the tile's top right.
+        assert_eq!(mesh.vertices[0].position, [0.0, 16.0]);
+        assert_eq!(mesh.vertices[0].uv, [0.5, 0.0]);
+        // The 32 texel tile hangs over the cell above.
+        assert_eq!(mesh.vertices[4].position, [16.0, 32.0]);
+        assert_eq!(
+            chunks[0].bounds,
+            Rect::new(Vec2::ZERO, Vec2::new(48.0, 32.0))
+        );
+
+        let mut animated = Default::default();
+        map.build_animated(ground, view, 0.75, &mut animated);
+        assert_eq!(animated.vertices.len(), 4);
+        assert_eq!(animated.vertices[0].uv, [0.25, 0.0]);
+        assert_eq!(map.visible_chunks(ground, view.expand(-60.0)).count(), 0);
+
+        // Looking at the bottom left corner from a 100 by 50 viewport, one pixel per unit.
+        let camera = Camera2d {
+            position: Vec2::new(50.0, 25.0),
+            ..Camera2d::default()
+        };
+        let mut sprites = SpriteBatch::default();
+        map.draw(&camera, Vec2::new(100.0, 50.0), 0.75, 10, &mut sprites);
+        sprites.build();
+        assert_eq!(sprites.len(), 3);
+        assert_eq!(sprites.vertices()[0].position, [0.0, 34.0]);
+        assert_eq!(sprites.runs().len(), 3);
+    }
+}
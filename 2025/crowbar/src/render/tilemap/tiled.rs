@@ -0,0 +1,582 @@
+//! Loading maps made in [Tiled](https://www.mapeditor.org), saved as JSON (`.tmj`) or XML (`.tmx`).
+//!
+//! Orthogonal, fixed size maps only. Tile layers are loaded, in group layers too, and everything else (object
+//! layers, image layers, properties) is skipped. Layer data can be plain (JSON arrays, XML CSV) or uncompressed
+//! base64. Tilesets can be embedded or in their own `.tsj`/`.tsx` files, each with a single image.
+//!
+//! Paths in the files are relative to the file they're in, and get resolved before they're read or handed to the
+//! caller to load as textures.
+
+use std::{collections::HashMap, io};
+
+use glam::UVec2;
+use serde::Deserialize;
+
+use super::{AnimationFrame, Tilemap, Tileset};
+use crate::{platform, render::sprite::TextureId};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Deserialize)]
+struct MapFile {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    orientation: Option<String>,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<LayerFile>,
+    #[serde(default)]
+    tilesets: Vec<TilesetFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayerData {
+    Tiles(Vec<u32>),
+    Encoded(String),
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct LayerFile {
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    data: Option<LayerData>,
+    #[serde(default)]
+    encoding: Option<String>,
+    #[serde(default)]
+    compression: Option<String>,
+    #[serde(default = "default_true")]
+    visible: bool,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    /// A group's layers.
+    #[serde(default)]
+    layers: Vec<LayerFile>,
+}
+
+#[derive(Deserialize, Default)]
+struct TilesetFile {
+    #[serde(default)]
+    firstgid: u32,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    tiles: Vec<TileFile>,
+}
+
+#[derive(Deserialize)]
+struct TileFile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<FrameFile>,
+}
+
+#[derive(Deserialize)]
+struct FrameFile {
+    tileid: u32,
+    /// In milliseconds.
+    duration: u32,
+}
+
+/// `path` relative to the directory `file` is in, with any `..` taken out.
+fn relative(file: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = file.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "." | "" => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    return parts.join("/");
+}
+
+/// Load the map at `path`, `.tmj` or `.tmx`, through [`platform::read_asset`]. `texture` loads a tileset image,
+/// given its path.
+pub fn load(
+    path: &str,
+    texture: &mut dyn FnMut(&str) -> io::Result<TextureId>,
+) -> io::Result<Tilemap> {
+    return load_with(path, &mut |p| platform::read_asset(p), texture);
+}
+
+/// Load the map at `path`, reading files with `read`.
+pub fn load_with(
+    path: &str,
+    read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>,
+    texture: &mut dyn FnMut(&str) -> io::Result<TextureId>,
+) -> io::Result<Tilemap> {
+    let bytes = read(path)?;
+    let map = if path.ends_with(".tmx") {
+        xml::map(&xml::parse(&String::from_utf8_lossy(&bytes))?)?
+    } else {
+        serde_json::from_slice(&bytes)?
+    };
+    return build(map, path, read, texture);
+}
+
+fn build(
+    map: MapFile,
+    path: &str,
+    read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>,
+    texture: &mut dyn FnMut(&str) -> io::Result<TextureId>,
+) -> io::Result<Tilemap> {
+    if map
+        .orientation
+        .as_deref()
+        .is_some_and(|o| o != "orthogonal")
+    {
+        return Err(invalid("Only orthogonal Tiled maps are supported"));
+    }
+    if map.infinite {
+        return Err(invalid("Infinite Tiled maps aren't supported"));
+    }
+    let mut tilemap = Tilemap::new(
+        map.width,
+        map.height,
+        UVec2::new(map.tilewidth, map.tileheight),
+    );
+
+    for mut tileset in map.tilesets {
+        // An external tileset brings everything but its first id.
+        let mut file = path.to_owned();
+        if let Some(source) = &tileset.source {
+            file = relative(path, source);
+            let bytes = read(&file)?;
+            let first_gid = tileset.firstgid;
+            tileset = if file.ends_with(".tsx") {
+                xml::tileset(&xml::parse(&String::from_utf8_lossy(&bytes))?)?
+            } else {
+                serde_json::from_slice(&bytes)?
+            };
+            tileset.firstgid = first_gid;
+        }
+        let Some(image) = &tileset.image else {
+            return Err(invalid("Tilesets of separate images aren't supported"));
+        };
+        let mut animations = HashMap::new();
+        for tile in &tileset.tiles {
+            if tile.animation.is_empty() {
+                continue;
+            }
+            let frames = tile
+                .animation
+                .iter()
+                .map(|f| AnimationFrame {
+                    tile: f.tileid,
+                    duration: f.duration as f32 / 1000.0,
+                })
+                .collect();
+            animations.insert(tile.id, frames);
+        }
+        tilemap.add_tileset(Tileset {
+            texture: texture(&relative(&file, image))?,
+            first_gid: tileset.firstgid,
+            tile_count: tileset.tilecount,
+            columns: tileset.columns,
+            tile_size: UVec2::new(tileset.tilewidth, tileset.tileheight),
+            image_size: UVec2::new(tileset.imagewidth, tileset.imageheight),
+            margin: tileset.margin,
+            spacing: tileset.spacing,
+            animations,
+        });
+    }
+
+    let mut layers: Vec<_> = map.layers.into_iter().rev().collect();
+    while let Some(layer) = layers.pop() {
+        match layer.ty.as_str() {
+            "group" => {
+                if layer.visible {
+                    layers.extend(layer.layers.into_iter().rev());
+                }
+                continue;
+            }
+            "tilelayer" => {}
+            _ => continue,
+        }
+        let tiles = match layer.data {
+            Some(LayerData::Tiles(tiles)) => tiles,
+            Some(LayerData::Encoded(text)) => {
+                if layer.encoding.as_deref() != Some("base64")
+                    || layer.compression.as_deref().is_some_and(|c| !c.is_empty())
+                {
+                    return Err(invalid(format!(
+                        "Layer {} isn't plain or uncompressed base64",
+                        layer.name
+                    )));
+                }
+                base64(&text)?
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if tiles.len() != (map.width * map.height) as usize {
+            return Err(invalid(format!("Layer {} is the wrong size", layer.name)));
+        }
+        let index = tilemap.add_layer(layer.name);
+        let l = tilemap.layer_mut(index);
+        l.visible = layer.visible;
+        l.opacity = layer.opacity;
+        l.tiles = tiles;
+    }
+    return Ok(tilemap);
+}
+
+fn base64(text: &str) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return Err(invalid("Bad base64")),
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    return Ok(out);
+}
+
+/// Just enough XML for Tiled's files: elements, attributes and text, without namespaces or DTDs.
+mod xml {
+    use std::io;
+
+    use super::{FrameFile, LayerData, LayerFile, MapFile, TileFile, TilesetFile, invalid};
+
+    #[derive(Debug, Default)]
+    pub struct Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Element>,
+        text: String,
+    }
+
+    impl Element {
+        fn get(&self, name: &str) -> Option<&str> {
+            self.attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        }
+
+        fn number<T: std::str::FromStr + Default>(&self, name: &str) -> io::Result<T> {
+            match self.get(name) {
+                Some(v) => v
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("Attribute {name} isn't a number"))),
+                None => Ok(T::default()),
+            }
+        }
+
+        fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+            self.children.iter().filter(move |c| c.name == name)
+        }
+    }
+
+    fn unescape(s: &str) -> String {
+        s.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    /// The root element.
+    pub fn parse(text: &str) -> io::Result<Element> {
+        let mut stack = vec![Element::default()];
+        let mut rest = text;
+        while let Some(open) = rest.find('<') {
+            stack
+                .last_mut()
+                .unwrap()
+                .text
+                .push_str(&unescape(&rest[..open]));
+            rest = &rest[open..];
+            let end = if rest.starts_with("<!--") {
+                rest.find("-->").map(|e| e + 3)
+            } else {
+                rest.find('>').map(|e| e + 1)
+            }
+            .ok_or_else(|| invalid("Unclosed XML tag"))?;
+            let tag = &rest[1..end - 1];
+            rest = &rest[end..];
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().unwrap();
+                if element.name != name.trim() || stack.is_empty() {
+                    return Err(invalid(format!("Mismatched XML tag {name}")));
+                }
+                stack.last_mut().unwrap().children.push(element);
+                continue;
+            }
+            let closed = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let (name, mut attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let mut element = Element {
+                name: name.to_owned(),
+                ..Default::default()
+            };
+            while let Some(eq) = attrs.find('=') {
+                let key = attrs[..eq].trim();
+                let value = attrs[eq + 1..].trim_start();
+                let quote = value.chars().next().filter(|q| *q == '"' || *q == '\'');
+                let Some(quote) = quote else {
+                    return Err(invalid("Unquoted XML attribute"));
+                };
+                let value = &value[1..];
+                let close = value
+                    .find(quote)
+                    .ok_or_else(|| invalid("Unclosed XML attribute"))?;
+                element
+                    .attributes
+                    .push((key.to_owned(), unescape(&value[..close])));
+                attrs = &value[close + 1..];
+            }
+            if closed {
+                stack.last_mut().unwrap().children.push(element);
+            } else {
+                stack.push(element);
+            }
+        }
+        let mut document = stack.pop().unwrap();
+        if !stack.is_empty() || document.children.len() != 1 {
+            return Err(invalid("XML isn't one element"));
+        }
+        return Ok(document.children.pop().unwrap());
+    }
+
+    pub fn map(root: &Element) -> io::Result<MapFile> {
+        if root.name != "map" {
+            return Err(invalid("Not a Tiled map"));
+        }
+        return Ok(MapFile {
+            width: root.number("width")?,
+            height: root.number("height")?,
+            tilewidth: root.number("tilewidth")?,
+            tileheight: root.number("tileheight")?,
+            orientation: root.get("orientation").map(str::to_owned),
+            infinite: root.get("infinite") == Some("1"),
+            layers: layers(root)?,
+            tilesets: root
+                .children("tileset")
+                .map(tileset)
+                .collect::<io::Result<_>>()?,
+        });
+    }
+
+    fn layers(parent: &Element) -> io::Result<Vec<LayerFile>> {
+        let mut found = Vec::new();
+        for element in &parent.children {
+            let ty = match element.name.as_str() {
+                "layer" => "tilelayer",
+                "group" => "group",
+                _ => continue,
+            };
+            let data = element.children("data").next();
+            let encoding = data.and_then(|d| d.get("encoding"));
+            let tiles = match (data, encoding) {
+                (Some(data), Some("csv")) => {
+                    let tiles = data
+                        .text
+                        .split(',')
+                        .map(|t| t.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid("Bad CSV layer data"))?;
+                    Some(LayerData::Tiles(tiles))
+                }
+                (Some(data), Some(_)) => Some(LayerData::Encoded(data.text.clone())),
+                (Some(data), None) => Some(LayerData::Tiles(
+                    data.children("tile")
+                        .map(|t| t.number("gid"))
+                        .collect::<io::Result<_>>()?,
+                )),
+                (None, _) => None,
+            };
+            found.push(LayerFile {
+                ty: ty.to_owned(),
+                name: element.get("name").unwrap_or_default().to_owned(),
+                data: tiles,
+                encoding: encoding.filter(|e| *e != "csv").map(str::to_owned),
+                compression: data.and_then(|d| d.get("compression")).map(str::to_owned),
+                visible: element.get("visible") != Some("0"),
+                opacity: element
+                    .get("opacity")
+                    .map_or(Ok(1.0), |_| element.number("opacity"))?,
+                layers: layers(element)?,
+            });
+        }
+        return Ok(found);
+    }
+
+    pub fn tileset(element: &Element) -> io::Result<TilesetFile> {
+        let image = element.children("image").next();
+        let mut tiles = Vec::new();
+        for tile in element.children("tile") {
+            let animation = tile
+                .children("animation")
+                .flat_map(|a| a.children("frame"))
+                .map(|f| {
+                    Ok(FrameFile {
+                        tileid: f.number("tileid")?,
+                        duration: f.number("duration")?,
+                    })
+                })
+                .collect::<io::Result<_>>()?;
+            tiles.push(TileFile {
+                id: tile.number("id")?,
+                animation,
+            });
+        }
+        return Ok(TilesetFile {
+            firstgid: element.number("firstgid")?,
+            source: element.get("source").map(str::to_owned),
+            image: image.and_then(|i| i.get("source")).map(str::to_owned),
+            imagewidth: image.map_or(Ok(0), |i| i.number("width"))?,
+            imageheight: image.map_or(Ok(0), |i| i.number("height"))?,
+            tilewidth: element.number("tilewidth")?,
+            tileheight: element.number("tileheight")?,
+            columns: element.number("columns")?,
+            tilecount: element.number("tilecount")?,
+            margin: element.number("margin")?,
+            spacing: element.number("spacing")?,
+            tiles,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io};
+
+    use super::load_with;
+    use crate::render::{sprite::TextureId, tilemap::FLIP_H};
+
+    #[test]
+    pub fn loads_tmj_and_tmx() {
+        let files: HashMap<&str, &str> = HashMap::from([
+            (
+                "maps/level.tmj",
+                r#"{
+                    "width": 3, "height": 2, "tilewidth": 16, "tileheight": 16,
+                    "orientation": "orthogonal", "infinite": false,
+                    "tilesets": [{ "firstgid": 1, "source": "../tiles/terrain.tsx" }],
+                    "layers": [
+                        { "type": "tilelayer", "name": "ground", "data": [1, 2, 0, 0, 2147483649, 3] },
+                        { "type": "objectgroup", "name": "spawns" },
+                        { "type": "group", "name": "top", "layers": [
+                            { "type": "tilelayer", "name": "decor", "opacity": 0.5,
+                              "encoding": "base64", "data": "AAAAAAIAAAAAAAAAAAAAAAAAAAAAAAAA" }
+                        ] }
+                    ]
+                }"#,
+            ),
+            (
+                "maps/level.tmx",
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+                <map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+                 <!-- The same map, minus the decor. -->
+                 <tileset firstgid="1" source="../tiles/terrain.tsx"/>
+                 <layer id="1" name="ground" width="3" height="2">
+                  <data encoding="csv">
+                1,2,0,
+                0,2147483649,3
+                </data>
+                 </layer>
+                </map>"#,
+            ),
+            (
+                "tiles/terrain.tsx",
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+                <tileset name="terrain &amp; water" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+                 <image source="terrain.png" width="32" height="32"/>
+                 <tile id="2">
+                  <animation>
+                   <frame tileid="2" duration="100"/>
+                   <frame tileid="3" duration="300"/>
+                  </animation>
+                 </tile>
+                </tileset>"#,
+            ),
+        ]);
+        let mut read = |path: &str| {
+            files
+                .get(path)
+                .map(|f| f.as_bytes().to_vec())
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))
+        };
+        let mut images = Vec::new();
+        let mut texture = |path: &str| {
+            images.push(path.to_owned());
+            Ok(TextureId(9))
+        };
+
+        let json = load_with("maps/level.tmj", &mut read, &mut texture).unwrap();
+        let xml = load_with("maps/level.tmx", &mut read, &mut texture).unwrap();
+        assert_eq!(images, ["tiles/terrain.png"; 2]);
+        for map in [&json, &xml] {
+            assert_eq!((map.width(), map.height()), (3, 2));
+            let tileset = &map.tilesets()[0];
+            assert_eq!(tileset.tile_count, 4);
+            assert_eq!(tileset.frame_at(2, 0.15), 3);
+            assert_eq!(map.tile(0, 1, 0), 2);
+            assert_eq!(map.tile(0, 1, 1), 1 | FLIP_H);
+        }
+        assert_eq!(json.layers().len(), 2);
+        assert_eq!(json.layers()[1].name, "decor");
+        assert_eq!(json.layers()[1].opacity, 0.5);
+        assert_eq!(json.tile(1, 1, 0), 2);
+        assert_eq!(xml.layers().len(), 1);
+    }
+}
@@ -0,0 +1,203 @@
+//! Per-frame and per-draw constants, written into one big upload buffer that gets bound once per frame with a
+//! dynamic offset per draw, rather than a descriptor update per object.
+//!
+//! The buffer is split into a region per frame in flight, so the CPU writes one frame's constants while the GPU
+//! reads the ones before. Offsets are aligned to the device's `minUniformBufferOffsetAlignment`.
+//!
+//! todo: buffer device addresses would do away with the dynamic offsets, once hal has them.
+
+use super::hal::{BufferDesc, BufferUsage, Device, MemoryLocation};
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+/// The offset bookkeeping, apart from any buffer.
+#[derive(Clone, Debug)]
+pub struct RingAllocator {
+    frame_size: u64,
+    frames: u32,
+    alignment: u64,
+    max_range: u64,
+    frame: u32,
+    /// Bytes used in the current frame's region.
+    head: u64,
+    /// Most bytes any frame has used, for sizing the ring.
+    peak: u64,
+}
+
+impl RingAllocator {
+    /// `frame_size` gets rounded up to the alignment, so every region starts aligned.
+    pub fn new(frame_size: u64, frames: u32, alignment: u64, max_range: u64) -> RingAllocator {
+        let alignment = alignment.max(1);
+        RingAllocator {
+            frame_size: align_up(frame_size, alignment),
+            frames: frames.max(1),
+            alignment,
+            max_range,
+            frame: 0,
+            head: 0,
+            peak: 0,
+        }
+    }
+
+    /// The whole ring, in bytes.
+    pub fn size(&self) -> u64 {
+        self.frame_size * self.frames as u64
+    }
+
+    /// Move on to the next frame's region, dropping everything in it.
+    pub fn begin_frame(&mut self) {
+        self.frame = (self.frame + 1) % self.frames;
+        self.head = 0;
+    }
+
+    /// Where `size` bytes can go, `None` if the frame's region is full or it's more than one binding can see.
+    pub fn allocate(&mut self, size: u64) -> Option<u64> {
+        if size > self.max_range {
+            return None;
+        }
+        let offset = align_up(self.head, self.alignment);
+        if offset + size > self.frame_size {
+            return None;
+        }
+        self.head = offset + size;
+        self.peak = self.peak.max(self.head);
+        return Some(self.frame as u64 * self.frame_size + offset);
+    }
+
+    /// Bytes used so far this frame.
+    pub fn used(&self) -> u64 {
+        self.head
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.peak
+    }
+}
+
+/// A ring of uniform data on a device.
+pub struct UniformRing<D: Device> {
+    buffer: D::Buffer,
+    ring: RingAllocator,
+    /// Pushes that didn't fit since the ring was made.
+    overflows: u64,
+}
+
+impl<D: Device> UniformRing<D> {
+    /// A ring of `frames` regions of `frame_size` bytes each. `frames` should match the frames in flight.
+    pub fn new(device: &D, frame_size: u64, frames: u32) -> Result<UniformRing<D>, D::Error> {
+        let limits = device.limits();
+        let ring = RingAllocator::new(
+            frame_size,
+            frames,
+            limits.min_uniform_offset_alignment,
+            limits.max_uniform_range,
+        );
+        let buffer = device.create_buffer(&BufferDesc {
+            size: ring.size(),
+            usage: BufferUsage::UNIFORM,
+            location: MemoryLocation::Upload,
+        })?;
+        return Ok(UniformRing {
+            buffer,
+            ring,
+            overflows: 0,
+        });
+    }
+
+    /// What to bind, once, as a dynamic uniform buffer.
+    pub fn buffer(&self) -> &D::Buffer {
+        &self.buffer
+    }
+
+    /// Start writing the next frame's constants.
+    pub fn begin_frame(&mut self) {
+        self.ring.begin_frame();
+    }
+
+    /// Copy `data` in, returning the dynamic offset to draw with, or `None` if this frame's region is full.
+    ///
+    /// # Safety
+    /// The GPU must be done with the frame that last used this region, as waiting on that frame's fence before
+    /// [`UniformRing::begin_frame`] makes sure of.
+    pub unsafe fn push(&mut self, device: &D, data: &[u8]) -> Result<Option<u64>, D::Error> {
+        let Some(offset) = self.ring.allocate(data.len() as u64) else {
+            if self.overflows == 0 {
+                log::warn!(
+                    "Uniform ring is full at {} bytes a frame, make it bigger",
+                    self.ring.frame_size
+                );
+            }
+            self.overflows += 1;
+            return Ok(None);
+        };
+        // SAFETY: The offset is inside this frame's region, which the caller vouches the GPU is done with.
+        unsafe {
+            device.write_buffer(&self.buffer, offset, data)?;
+        }
+        return Ok(Some(offset));
+    }
+
+    pub fn allocator(&self) -> &RingAllocator {
+        &self.ring
+    }
+
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    /// # Safety
+    /// The GPU must be done with the whole ring.
+    pub unsafe fn destroy(self, device: &D) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            device.destroy_buffer(self.buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RingAllocator, UniformRing};
+    use crate::{render::hal::Device, test_support::headless};
+
+    #[test]
+    pub fn ring_offsets() {
+        let mut ring = RingAllocator::new(1000, 2, 256, 512);
+        assert_eq!(ring.size(), 2048);
+
+        assert_eq!(ring.allocate(64), Some(0));
+        assert_eq!(ring.allocate(64), Some(256));
+        // More than a binding can see.
+        assert_eq!(ring.allocate(600), None);
+        assert_eq!(ring.allocate(512), Some(512));
+        assert_eq!(ring.allocate(1), None);
+        assert_eq!(ring.peak(), 1024);
+
+        ring.begin_frame();
+        assert_eq!(ring.allocate(16), Some(1024));
+        ring.begin_frame();
+        assert_eq!(ring.allocate(16), Some(0));
+        assert_eq!(ring.used(), 16);
+    }
+
+    #[test]
+    pub fn pushes_to_device() {
+        let Some(headless) = headless() else {
+            return;
+        };
+        let device = headless.device();
+        let alignment = device.limits().min_uniform_offset_alignment;
+
+        let mut ring = UniformRing::new(device, 4096, 2).unwrap();
+        // SAFETY: Nothing's been submitted.
+        unsafe {
+            let first = ring.push(device, &[1; 48]).unwrap().unwrap();
+            let second = ring.push(device, &[2; 48]).unwrap().unwrap();
+            assert_eq!(first, 0);
+            assert_eq!(second % alignment, 0);
+            ring.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Vulkan validation, for developing on the engine: the Khronos validation layer, with everything it says routed
+//! through the log.
+//!
+//! Opt in with the `r_validation` cvar or [`VALIDATION_ENV`], both taking effect on the next start, since the layer
+//! has to be there when the instance is made. It has to be installed too, it comes with the Vulkan SDK. How much
+//! gets logged is `r_validation_severity`, which can change at any time.
+
+use std::{
+    ffi::{CStr, c_void},
+    sync::atomic::{AtomicU8, AtomicU64, Ordering},
+};
+
+use ash::{Entry, Instance, ext, prelude::VkResult, vk};
+
+use super::alloc::VK_ALLOCATOR_CALLBACKS;
+
+pub const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// Environment variable that turns validation on or off, over the `r_validation` cvar.
+pub const VALIDATION_ENV: &str = "CROWBAR_VALIDATION";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Everything the loader and layers do, very noisy.
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 4] = [
+        Severity::Verbose,
+        Severity::Info,
+        Severity::Warning,
+        Severity::Error,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Verbose => "verbose",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Severity> {
+        Severity::ALL
+            .into_iter()
+            .find(|v| v.name().eq_ignore_ascii_case(s.trim()))
+    }
+
+    fn from_vk(flags: vk::DebugUtilsMessageSeverityFlagsEXT) -> Severity {
+        if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            return Severity::Error;
+        }
+        if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            return Severity::Warning;
+        }
+        if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            return Severity::Info;
+        }
+        return Severity::Verbose;
+    }
+
+    fn level(&self) -> log::Level {
+        match self {
+            Severity::Verbose => log::Level::Trace,
+            Severity::Info => log::Level::Debug,
+            Severity::Warning => log::Level::Warn,
+            Severity::Error => log::Level::Error,
+        }
+    }
+}
+
+static MIN_SEVERITY: AtomicU8 = AtomicU8::new(Severity::Warning as u8);
+static COUNTS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// Log messages at `severity` and above, drop the rest.
+pub fn set_min_severity(severity: Severity) {
+    MIN_SEVERITY.store(severity as u8, Ordering::Relaxed);
+}
+
+pub fn min_severity() -> Severity {
+    Severity::ALL[MIN_SEVERITY.load(Ordering::Relaxed) as usize]
+}
+
+/// Messages at `severity` since startup, whether they got logged or not.
+pub fn message_count(severity: Severity) -> u64 {
+    COUNTS[severity as usize].load(Ordering::Relaxed)
+}
+
+/// Whether to validate: [`VALIDATION_ENV`] if it's set, otherwise `config`, the `r_validation` cvar.
+pub fn requested(config: bool) -> bool {
+    match std::env::var(VALIDATION_ENV) {
+        Ok(value) => !matches!(value.trim(), "" | "0" | "false" | "off"),
+        Err(_) => config,
+    }
+}
+
+/// Whether the layer is installed.
+pub fn layer_available(entry: &Entry) -> bool {
+    // SAFETY: Only a query.
+    let layers = unsafe { entry.enumerate_instance_layer_properties() }.unwrap_or_default();
+    return layers
+        .iter()
+        .any(|l| l.layer_name_as_c_str() == Ok(VALIDATION_LAYER));
+}
+
+unsafe extern "system" fn on_message(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let severity = Severity::from_vk(severity);
+    COUNTS[severity as usize].fetch_add(1, Ordering::Relaxed);
+    if severity < min_severity() || data.is_null() {
+        return vk::FALSE;
+    }
+
+    let kind = if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "validation"
+    } else if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    };
+    // SAFETY: The layer hands us valid callback data, alive for the call.
+    let (id, message) = unsafe {
+        let data = &*data;
+        (
+            data.message_id_name_as_c_str()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default(),
+            data.message_as_c_str()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default(),
+        )
+    };
+    log::log!(target: "vulkan", severity.level(), "[{kind}] {id}: {message}");
+    // Never abort the call, that's only meant for testing the layers themselves.
+    return vk::FALSE;
+}
+
+/// What to make the messenger with. Also goes on the instance's create info, to cover making and destroying it.
+pub fn messenger_info() -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+    // Everything, as filtering happens in the callback where it can change at runtime.
+    vk::DebugUtilsMessengerCreateInfoEXT::default()
+        .message_severity(
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        )
+        .message_type(
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(on_message))
+}
+
+/// Routes what the layers say to the log, for as long as it's alive.
+pub struct DebugMessenger {
+    loader: ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    /// # Safety
+    /// `instance` must have been made with [`ext::debug_utils`] enabled, and outlive the messenger.
+    pub unsafe fn new(entry: &Entry, instance: &Instance) -> VkResult<DebugMessenger> {
+        let loader = ext::debug_utils::Instance::new(entry, instance);
+        // SAFETY: Passed on to the caller.
+        let messenger = unsafe {
+            loader
+                .create_debug_utils_messenger(&messenger_info(), Some(&*VK_ALLOCATOR_CALLBACKS))?
+        };
+        return Ok(DebugMessenger { loader, messenger });
+    }
+
+    /// # Safety
+    /// The instance it was made from must still be there.
+    pub unsafe fn destroy(self) {
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            self.loader
+                .destroy_debug_utils_messenger(self.messenger, Some(&*VK_ALLOCATOR_CALLBACKS));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use super::{Severity, min_severity, set_min_severity};
+
+    #[test]
+    pub fn severities() {
+        assert_eq!(Severity::parse(" Warning "), Some(Severity::Warning));
+        assert_eq!(Severity::parse("loud"), None);
+        assert_eq!(
+            Severity::from_vk(
+                vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+            ),
+            Severity::Error
+        );
+        assert!(Severity::Verbose < Severity::Info);
+
+        let before = min_severity();
+        set_min_severity(Severity::Error);
+        assert_eq!(min_severity(), Severity::Error);
+        set_min_severity(before);
+    }
+}
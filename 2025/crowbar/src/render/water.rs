@@ -0,0 +1,668 @@
+//! Water surfaces, see [`Water`].
+//!
+//! The surface is a grid over the unit square on the entity's XZ plane, around its origin, moved by Gerstner waves in
+//! the vertex shader, `water_vertex` in [`GLSL`], which [`surface_point`] matches on the CPU for things floating on
+//! it. `water_shade` scrolls two layers of [`ripple_map`] over the waves' normals, then mixes what's behind the water
+//! with its reflection by Fresnel. What's behind comes from a copy of the scene's colour taken after the 3D pass, and
+//! the scene's depth. It's bent by the ripples, unless that would pull in something in front of the water, and
+//! tinted, going to the deep colour with how much water it's seen through. The surface fades out where it's shallow
+//! enough to meet the shore, and isn't drawn where the scene is in front of it. The reflection is the water's
+//! [`super::planar`] one, bent the same way, if it has one this frame, otherwise the 3D pass's flat [`AMBIENT`].
+//!
+//! [`WaterPass`] draws every surface over the scene in a pass of its own, after the copy and through the last camera
+//! like the reflections. Ship its shaders compiled, as [`VERTEX_ASSET`] and [`FRAGMENT_ASSET`], or they're compiled
+//! from [`VERTEX_SHADER`] and [`FRAGMENT_SHADER`], with [`INCLUDES`], at startup.
+
+use std::f32::consts::TAU;
+
+use ash::{prelude::VkResult, vk};
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles};
+
+use super::{
+    alloc::VK_ALLOCATOR_CALLBACKS,
+    descriptors::{FrameDescriptors, LayoutBinding, LayoutCache, LayoutDesc},
+    extract::ExtractedWater,
+    hal::{
+        BufferUsage, Device, TextureFormat,
+        vulkan::{VulkanBuffer, VulkanDevice, VulkanTexture},
+    },
+    mesh::{self, AMBIENT},
+    pipeline::{BlendMode, DepthMode, GraphicsPipelineBuilder, TargetFormats},
+    planar,
+    rendering::PassContext,
+    shader::{
+        compile::{ShaderStage, builtin_shader_including},
+        reflect::Spirv,
+    },
+};
+use crate::ecs::components::{Water, WaterWave};
+
+fn allocs() -> Option<&'static vk::AllocationCallbacks<'static>> {
+    Some(&*VK_ALLOCATOR_CALLBACKS)
+}
+
+pub const GLSL: &str = include_str!("water/water.glsl");
+/// The shaders' sources, to compile at runtime.
+pub const VERTEX_SHADER: &str = include_str!("water/water.vert");
+pub const FRAGMENT_SHADER: &str = include_str!("water/water.frag");
+/// What the shaders include, to compile them at runtime.
+pub const INCLUDES: &[(&str, &str)] = &[
+    ("../water/water.glsl", GLSL),
+    ("../planar/planar.glsl", planar::GLSL),
+];
+/// Where the compiled shaders go among the assets, without the `.spv`.
+pub const VERTEX_ASSET: &str = "shaders/water.vert";
+pub const FRAGMENT_ASSET: &str = "shaders/water.frag";
+
+/// Cells a side of the grid a surface is drawn as. Keep in step with `WATER_GRID` in [`GLSL`].
+pub const GRID: u32 = 64;
+/// Texels a side of [`ripple_map`].
+pub const RIPPLE_SIZE: u32 = 64;
+const RIPPLE_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+/// Bytes of a surface's [`gpu_data`], and how far apart they are in the buffer, which is as far as a uniform buffer's
+/// offset ever has to be aligned to.
+const BLOCK_BYTES: u64 = 256;
+/// What a frame's descriptor pools hold, for a set per surface.
+const RATIOS: &[(vk::DescriptorType, f32)] = &[
+    (vk::DescriptorType::UNIFORM_BUFFER, 1.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 4.0),
+    (vk::DescriptorType::SAMPLER, 3.0),
+];
+
+/// For how fast waves travel, in m/s².
+const GRAVITY: f32 = 9.81;
+
+/// A wave as the shaders move things with it.
+struct WaveTerms {
+    direction: Vec2,
+    wavenumber: f32,
+    angular_speed: f32,
+    amplitude: f32,
+    /// How far it moves things sideways, which is what makes the crests sharp.
+    horizontal: f32,
+}
+
+impl WaveTerms {
+    /// `None` for a wave without a direction, which does nothing.
+    fn new(wave: &WaterWave) -> Option<WaveTerms> {
+        let direction = wave.direction.try_normalize()?;
+        let wavenumber = TAU / wave.wavelength.max(1e-3);
+        return Some(WaveTerms {
+            direction,
+            wavenumber,
+            angular_speed: (GRAVITY * wavenumber).sqrt(),
+            amplitude: wave.amplitude,
+            horizontal: wave.steepness.clamp(0.0, 1.0) / wavenumber,
+        });
+    }
+}
+
+/// Where the point at `position` in the surface's XZ plane has been moved to by `waves` at `time` seconds, in the
+/// surface's space, with the surface's normal there.
+pub fn surface_point(waves: &[WaterWave], position: Vec2, time: f32) -> (Vec3, Vec3) {
+    let mut point = Vec3::new(position.x, 0.0, position.y);
+    let mut normal = Vec3::Y;
+    for t in waves.iter().filter_map(WaveTerms::new) {
+        let phase = t.wavenumber * t.direction.dot(position) - t.angular_speed * time;
+        let (sin, cos) = phase.sin_cos();
+        point += Vec3::new(
+            t.direction.x * t.horizontal * cos,
+            t.amplitude * sin,
+            t.direction.y * t.horizontal * cos,
+        );
+        normal -= Vec3::new(
+            t.direction.x * t.wavenumber * t.amplitude * cos,
+            t.wavenumber * t.horizontal * sin,
+            t.direction.y * t.wavenumber * t.amplitude * cos,
+        );
+    }
+    return (point, normal.normalize_or(Vec3::Y));
+}
+
+/// The height of the surface over `position` in its XZ plane at `time` seconds, for floating things on it. The
+/// waves move the surface sideways too, so this walks back to the point that ends up over `position`.
+pub fn height_at(waves: &[WaterWave], position: Vec2, time: f32) -> f32 {
+    let mut source = position;
+    for _ in 0..8 {
+        let (moved, _) = surface_point(waves, source, time);
+        source -= moved.xz() - position;
+    }
+    return surface_point(waves, source, time).0.y;
+}
+
+/// `water` as the uniform block [`GLSL`] reads, at `time` seconds: its world transform, then per wave its direction,
+/// wavenumber and angular speed, then its amplitude and horizontal amplitude, all 0 for a wave that does nothing.
+/// Then both ripple scrolls, the shallow colour and ripple scale, the deep colour and depth fade, and the shore fade,
+/// distortion, time and a 1 if it reflects.
+pub fn gpu_data(water: &ExtractedWater, time: f32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BLOCK_BYTES as usize);
+    let mut vec4 = |v: [f32; 4]| v.iter().for_each(|f| out.extend(f.to_ne_bytes()));
+    let world = Mat4::from(water.world).to_cols_array();
+    for column in world.chunks_exact(4) {
+        vec4(column.try_into().unwrap());
+    }
+
+    let Water {
+        waves,
+        ripple_scroll,
+        ripple_scale,
+        shallow_color,
+        deep_color,
+        depth_fade,
+        shore_fade,
+        distortion,
+    } = &water.water;
+    for wave in waves {
+        match WaveTerms::new(wave) {
+            Some(t) => {
+                vec4([t.direction.x, t.direction.y, t.wavenumber, t.angular_speed]);
+                vec4([t.amplitude, t.horizontal, 0.0, 0.0]);
+            }
+            None => {
+                vec4([0.0; 4]);
+                vec4([0.0; 4]);
+            }
+        }
+    }
+    vec4([
+        ripple_scroll[0].x,
+        ripple_scroll[0].y,
+        ripple_scroll[1].x,
+        ripple_scroll[1].y,
+    ]);
+    vec4(shallow_color.to_vec3().extend(*ripple_scale).to_array());
+    vec4(deep_color.to_vec3().extend(depth_fade.max(1e-3)).to_array());
+    vec4([
+        shore_fade.max(1e-3),
+        *distortion,
+        time,
+        if water.reflects { 1.0 } else { 0.0 },
+    ]);
+    return out;
+}
+
+/// The ripples' normal map, [`RIPPLE_SIZE`] texels a side in RGBA8, in tangent space with Z out of the surface. It's
+/// a few waves across it, each a whole number of times each way so it tiles.
+pub fn ripple_map() -> Vec<u8> {
+    // How many times each goes across the map each way, and how high it is against the map's width.
+    const WAVES: [(f32, f32, f32); 5] = [
+        (1.0, 2.0, 0.02),
+        (3.0, -1.0, 0.012),
+        (-2.0, 5.0, 0.006),
+        (7.0, 4.0, 0.002),
+        (-9.0, -6.0, 0.001),
+    ];
+    let size = RIPPLE_SIZE as f32;
+    let mut out = Vec::with_capacity((RIPPLE_SIZE * RIPPLE_SIZE * 4) as usize);
+    for y in 0..RIPPLE_SIZE {
+        for x in 0..RIPPLE_SIZE {
+            let uv = Vec2::new(x as f32, y as f32) / size;
+            let mut slope = Vec2::ZERO;
+            for (i, &(across, down, height)) in WAVES.iter().enumerate() {
+                let k = Vec2::new(across, down) * TAU;
+                // Out of phase, so they don't all peak together.
+                slope += k * height * (k.dot(uv) + i as f32 * 1.7).cos();
+            }
+            let normal = Vec3::new(-slope.x, -slope.y, 1.0).normalize();
+            let texel = (normal * 0.5 + 0.5).extend(1.0).to_array();
+            out.extend(texel.map(|c| (c * 255.0).round() as u8));
+        }
+    }
+    return out;
+}
+
+/// Draws every water surface over the scene, see the module docs.
+pub struct WaterPass {
+    /// Belongs to the layout cache it came from.
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+    cache: vk::PipelineCache,
+    /// One per target drawn to, kept until the pass goes as there are only ever a few.
+    pipelines: Vec<(TargetFormats, vk::Pipeline)>,
+    /// Linear filtering, wrapping for the ripples and clamped to the edges for the scene and the reflections.
+    repeat: vk::Sampler,
+    clamp: vk::Sampler,
+    descriptors: FrameDescriptors,
+    /// Each frame in flight's surfaces, as [`gpu_data`] [`BLOCK_BYTES`] apart.
+    blocks: Vec<Option<VulkanBuffer>>,
+    /// The frame last prepared, with each surface's reflection, if it has one this frame.
+    prepared: Option<(u64, Vec<Option<vk::ImageView>>)>,
+    /// [`ripple_map`], uploaded by the first prepare with any water.
+    ripples: Option<VulkanTexture>,
+    /// What `ripples` was uploaded from, and on which frame, until the GPU's done with it.
+    staging: Option<(u64, VulkanBuffer)>,
+}
+
+impl WaterPass {
+    /// Set up for `frames_in_flight` frames, with `vertex` compiled from [`VERTEX_SHADER`] and `fragment` from
+    /// [`FRAGMENT_SHADER`].
+    ///
+    /// # Safety
+    /// `layouts` must be caching for `device`, and `cache` must be `device`'s, or null. Both must outlive the pass.
+    pub unsafe fn new(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+        frames_in_flight: u32,
+    ) -> VkResult<WaterPass> {
+        let mut pass = WaterPass {
+            set_layout: vk::DescriptorSetLayout::null(),
+            layout: vk::PipelineLayout::null(),
+            vertex: vk::ShaderModule::null(),
+            fragment: vk::ShaderModule::null(),
+            cache,
+            pipelines: Vec::new(),
+            repeat: vk::Sampler::null(),
+            clamp: vk::Sampler::null(),
+            descriptors: FrameDescriptors::new(device.raw(), frames_in_flight, RATIOS),
+            blocks: Vec::new(),
+            prepared: None,
+            ripples: None,
+            staging: None,
+        };
+        pass.blocks
+            .resize_with(frames_in_flight.max(1) as usize, || None);
+        // SAFETY: Passed on to the caller, and whatever was made is destroyed if it goes wrong.
+        unsafe {
+            if let Err(e) = pass.create(device, layouts, vertex, fragment) {
+                pass.destroy(device);
+                return Err(e);
+            }
+        }
+        return Ok(pass);
+    }
+
+    /// The pass with the shaders shipped compiled or compiled now. `None`, having said why, if they couldn't be had
+    /// or it couldn't be made.
+    ///
+    /// # Safety
+    /// As [`WaterPass::new`].
+    pub unsafe fn builtin(
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        cache: vk::PipelineCache,
+        frames_in_flight: u32,
+    ) -> Option<WaterPass> {
+        let vertex = builtin_shader_including(
+            VERTEX_ASSET,
+            "water.vert",
+            VERTEX_SHADER,
+            INCLUDES,
+            ShaderStage::Vertex,
+        );
+        let fragment = builtin_shader_including(
+            FRAGMENT_ASSET,
+            "water.frag",
+            FRAGMENT_SHADER,
+            INCLUDES,
+            ShaderStage::Fragment,
+        );
+        let (Some(vertex), Some(fragment)) = (vertex, fragment) else {
+            log::warn!("No water shaders, water's off");
+            return None;
+        };
+        // SAFETY: Passed on to the caller.
+        let made =
+            unsafe { WaterPass::new(device, layouts, cache, &vertex, &fragment, frames_in_flight) };
+        return made
+            .inspect_err(|e| log::warn!("Couldn't make the water pass, water's off: {e}"))
+            .ok();
+    }
+
+    unsafe fn create(
+        &mut self,
+        device: &VulkanDevice,
+        layouts: &mut LayoutCache,
+        vertex: &Spirv,
+        fragment: &Spirv,
+    ) -> VkResult<()> {
+        let raw = device.raw();
+        let both = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+        let binding =
+            |binding, ty| LayoutBinding::new(binding, ty, 1, vk::ShaderStageFlags::FRAGMENT);
+        // The surface, the ripples, the scene's colour and depth, their samplers, then the reflection and its
+        // sampler, see water.frag.
+        let bindings = [
+            LayoutBinding::new(0, vk::DescriptorType::UNIFORM_BUFFER, 1, both),
+            binding(1, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(2, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(3, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(4, vk::DescriptorType::SAMPLER),
+            binding(5, vk::DescriptorType::SAMPLER),
+            binding(6, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(7, vk::DescriptorType::SAMPLER),
+        ];
+        // The camera's view projection, and the sky.
+        let push = vk::PushConstantRange::default().stage_flags(both).size(80);
+        let sampler = |address| {
+            vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(address)
+                .address_mode_v(address)
+        };
+        // SAFETY: Plain object creation, everything made is kept to destroy.
+        unsafe {
+            self.set_layout = layouts.get(raw, &LayoutDesc::new(bindings))?;
+            self.layout = raw.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[self.set_layout])
+                    .push_constant_ranges(&[push]),
+                allocs(),
+            )?;
+            self.vertex = vertex.create_module(raw)?;
+            self.fragment = fragment.create_module(raw)?;
+            self.repeat = raw.create_sampler(&sampler(vk::SamplerAddressMode::REPEAT), allocs())?;
+            self.clamp =
+                raw.create_sampler(&sampler(vk::SamplerAddressMode::CLAMP_TO_EDGE), allocs())?;
+        }
+        return Ok(());
+    }
+
+    /// Keep buffers and descriptors for `frames` frames in flight from now on.
+    ///
+    /// # Safety
+    /// The GPU must be done with every frame recorded so far.
+    pub unsafe fn set_frames_in_flight(&mut self, device: &VulkanDevice, frames: u32) {
+        for buffer in self.blocks.drain(..).flatten() {
+            // SAFETY: Passed on to the caller.
+            unsafe { device.destroy_buffer(buffer) };
+        }
+        self.blocks.resize_with(frames.max(1) as usize, || None);
+        self.descriptors = FrameDescriptors::new(device.raw(), frames, RATIOS);
+        self.prepared = None;
+    }
+
+    /// The pipeline for drawing to `target`, built the first time it's drawn to.
+    unsafe fn pipeline(
+        &mut self,
+        device: &ash::Device,
+        target: TargetFormats,
+    ) -> VkResult<vk::Pipeline> {
+        if let Some(&(_, pipeline)) = self.pipelines.iter().find(|(t, _)| *t == target) {
+            return Ok(pipeline);
+        }
+        // Seen from either side, and tested against the scene's depth by hand, as that's read too.
+        let builder = GraphicsPipelineBuilder::new(self.layout)
+            .vertex_fragment(self.vertex, self.fragment)
+            .cull(vk::CullModeFlags::NONE)
+            .samples(vk::SampleCountFlags::from_raw(target.samples))
+            .color(target.color, BlendMode::Alpha)
+            .depth(target.depth, DepthMode::Off)
+            .cache(self.cache);
+        // SAFETY: Everything the builder was given is this device's.
+        let pipeline = unsafe { builder.build(device)? };
+        self.pipelines.push((target, pipeline));
+        return Ok(pipeline);
+    }
+
+    /// Copy `waters` as of `time` seconds into `frame`'s buffer, with each one's reflection, if it has one this
+    /// frame, to be drawn with, and upload the ripples the first time there's any water. Goes before the pass that
+    /// draws them.
+    ///
+    /// # Safety
+    /// `cmd` must be recording outside a pass, and the GPU done with the frame that last used this frame's buffer,
+    /// like [`Renderer::begin_frame`](super::renderer::Renderer::begin_frame) waits for. The reflections have to be
+    /// in [`TextureState::ShaderRead`](super::hal::TextureState::ShaderRead) by the time the water's drawn.
+    pub unsafe fn prepare(
+        &mut self,
+        device: &VulkanDevice,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        waters: &[ExtractedWater],
+        reflections: &[Option<vk::ImageView>],
+        time: f32,
+    ) -> VkResult<()> {
+        debug_assert_eq!(waters.len(), reflections.len());
+        self.prepared = None;
+        let frames = self.blocks.len() as u64;
+        if let Some((_, staging)) = self
+            .staging
+            .take_if(|(uploaded, _)| frame >= *uploaded + frames)
+        {
+            // SAFETY: The frame it was uploaded in is done.
+            unsafe { device.destroy_buffer(staging) };
+        }
+        if waters.is_empty() {
+            return Ok(());
+        }
+        if self.ripples.is_none() {
+            let size = [RIPPLE_SIZE; 2];
+            // SAFETY: Passed on to the caller.
+            let (texture, staging) =
+                unsafe { mesh::upload(device, cmd, RIPPLE_FORMAT, size, &ripple_map())? };
+            self.ripples = Some(texture);
+            self.staging = Some((frame, staging));
+        }
+        let mut data = vec![0; waters.len() * BLOCK_BYTES as usize];
+        let blocks = data.chunks_exact_mut(BLOCK_BYTES as usize);
+        for ((block, water), reflection) in blocks.zip(waters).zip(reflections) {
+            // Reflecting only with a reflection to show.
+            let water = ExtractedWater {
+                reflects: water.reflects && reflection.is_some(),
+                ..water.clone()
+            };
+            block.copy_from_slice(&gpu_data(&water, time));
+        }
+        let buffer = &mut self.blocks[(frame % frames) as usize];
+        // SAFETY: Passed on to the caller.
+        unsafe {
+            mesh::grow(device, buffer, data.len() as u64, BufferUsage::UNIFORM)?;
+            device.write_buffer(buffer.as_ref().unwrap(), 0, &data)?;
+        }
+        self.prepared = Some((frame, reflections.to_vec()));
+        return Ok(());
+    }
+
+    /// Record drawing the water prepared for the context's frame over its target, through `view_proj`. `scene` is a
+    /// copy of the target as the 3D pass left it and `depth` the scene's depth, both in
+    /// [`TextureState::ShaderRead`](super::hal::TextureState::ShaderRead). Nothing's drawn if it wasn't prepared.
+    ///
+    /// # Safety
+    /// `ctx.cmd` must be recording inside its pass, after the prepare.
+    pub unsafe fn record(
+        &mut self,
+        ctx: PassContext,
+        scene: vk::ImageView,
+        depth: vk::ImageView,
+        view_proj: Mat4,
+    ) -> VkResult<()> {
+        let PassContext {
+            device,
+            cmd,
+            frame,
+            target,
+            ..
+        } = ctx;
+        let Some((_, reflections)) = self.prepared.take_if(|(prepared, _)| *prepared == frame)
+        else {
+            return Ok(());
+        };
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller.
+        let pipeline = unsafe { self.pipeline(raw, target)? };
+        // SAFETY: The caller vouched for the frame to prepare it.
+        unsafe { self.descriptors.begin_frame(frame)? };
+        let blocks = self.blocks[(frame % self.blocks.len() as u64) as usize]
+            .as_ref()
+            .unwrap()
+            .buffer;
+        let push: Vec<u8> = view_proj
+            .to_cols_array()
+            .into_iter()
+            .chain(AMBIENT.to_array())
+            .flat_map(f32::to_ne_bytes)
+            .collect();
+        let stages = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+        let point = vk::PipelineBindPoint::GRAPHICS;
+        let image = |view| {
+            [vk::DescriptorImageInfo::default()
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        };
+        let ripples = image(self.ripples.as_ref().unwrap().view);
+        let (color, depth) = (image(scene), image(depth));
+        let repeat = [vk::DescriptorImageInfo::default().sampler(self.repeat)];
+        let clamp = [vk::DescriptorImageInfo::default().sampler(self.clamp)];
+
+        // SAFETY: Recording into the caller's command buffer, inside its pass.
+        unsafe {
+            raw.cmd_bind_pipeline(cmd, point, pipeline);
+            raw.cmd_push_constants(cmd, self.layout, stages, 0, &push);
+        }
+        for (i, reflection) in reflections.into_iter().enumerate() {
+            let set = self.descriptors.allocate(self.set_layout)?;
+            let block = [vk::DescriptorBufferInfo::default()
+                .buffer(blocks)
+                .offset(i as u64 * BLOCK_BYTES)
+                .range(BLOCK_BYTES)];
+            // Without a reflection it isn't read, but something has to be bound.
+            let reflected = image(reflection.unwrap_or(scene));
+            let write = |binding, ty| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .descriptor_type(ty)
+            };
+            let writes = [
+                write(0, vk::DescriptorType::UNIFORM_BUFFER).buffer_info(&block),
+                write(1, vk::DescriptorType::SAMPLED_IMAGE).image_info(&ripples),
+                write(2, vk::DescriptorType::SAMPLED_IMAGE).image_info(&color),
+                write(3, vk::DescriptorType::SAMPLED_IMAGE).image_info(&depth),
+                write(4, vk::DescriptorType::SAMPLER).image_info(&repeat),
+                write(5, vk::DescriptorType::SAMPLER).image_info(&clamp),
+                write(6, vk::DescriptorType::SAMPLED_IMAGE).image_info(&reflected),
+                write(7, vk::DescriptorType::SAMPLER).image_info(&clamp),
+            ];
+            // SAFETY: The set was just allocated. Recording into the caller's command buffer, inside its pass.
+            unsafe {
+                raw.update_descriptor_sets(&writes, &[]);
+                raw.cmd_bind_descriptor_sets(cmd, point, self.layout, 0, &[set], &[]);
+                raw.cmd_draw(cmd, GRID * GRID * 6, 1, 0, 0);
+            }
+        }
+        return Ok(());
+    }
+
+    /// # Safety
+    /// The GPU must be done with it.
+    pub unsafe fn destroy(&mut self, device: &VulkanDevice) {
+        let raw = device.raw();
+        // SAFETY: Passed on to the caller. Null handles are skipped by Vulkan, and the set layout goes with its
+        // cache.
+        unsafe {
+            for (_, pipeline) in self.pipelines.drain(..) {
+                raw.destroy_pipeline(pipeline, allocs());
+            }
+            for buffer in self
+                .blocks
+                .drain(..)
+                .flatten()
+                .chain(self.staging.take().map(|s| s.1))
+            {
+                device.destroy_buffer(buffer);
+            }
+            if let Some(texture) = self.ripples.take() {
+                device.destroy_texture(texture);
+            }
+            raw.destroy_sampler(self.repeat, allocs());
+            raw.destroy_sampler(self.clamp, allocs());
+            raw.destroy_shader_module(self.vertex, allocs());
+            raw.destroy_shader_module(self.fragment, allocs());
+            raw.destroy_pipeline_layout(self.layout, allocs());
+        }
+        self.repeat = vk::Sampler::null();
+        self.clamp = vk::Sampler::null();
+        self.vertex = vk::ShaderModule::null();
+        self.fragment = vk::ShaderModule::null();
+        self.layout = vk::PipelineLayout::null();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Affine3A, Vec2, Vec3, Vec3Swizzles};
+    use hecs::World;
+
+    use super::{RIPPLE_SIZE, gpu_data, height_at, ripple_map, surface_point};
+    use crate::{
+        ecs::components::{Water, WaterWave},
+        render::extract::ExtractedWater,
+    };
+
+    #[test]
+    pub fn waves_move_and_float() {
+        let pond = Water::pond();
+        let (point, normal) = surface_point(&pond.waves, Vec2::new(3.0, -2.0), 5.0);
+        assert_eq!(point, Vec3::new(3.0, 0.0, -2.0));
+        assert_eq!(normal, Vec3::Y);
+
+        let waves = [
+            WaterWave {
+                direction: Vec2::new(2.0, 1.0),
+                wavelength: 8.0,
+                amplitude: 0.5,
+                steepness: 0.5,
+            },
+            WaterWave::FLAT,
+        ];
+        // Halfway up the wave, points have slid furthest along it.
+        let (crest, _) = surface_point(&waves, Vec2::ZERO, 0.0);
+        assert!(crest.y.abs() < 1e-6);
+        assert!(crest.x > 0.0 && crest.z > 0.0);
+
+        // The normal is square to the displaced surface.
+        let at = Vec2::new(1.3, 0.4);
+        let (point, normal) = surface_point(&waves, at, 0.7);
+        let step = 1e-3;
+        let along_x = surface_point(&waves, at + Vec2::X * step, 0.7).0 - point;
+        let along_z = surface_point(&waves, at + Vec2::Y * step, 0.7).0 - point;
+        assert!(along_z.cross(along_x).normalize().abs_diff_eq(normal, 1e-3));
+
+        // Floating finds the height of whatever point ends up overhead.
+        assert!((height_at(&waves, point.xz(), 0.7) - point.y).abs() < 1e-3);
+
+        let mut world = World::new();
+        let extracted = ExtractedWater {
+            entity: world.spawn(()),
+            world: Affine3A::from_translation(Vec3::Y),
+            water: Water::ocean(),
+            reflects: true,
+        };
+        let data = gpu_data(&extracted, 2.5);
+        assert_eq!(data.len(), 256);
+        let last: Vec<f32> = data[240..]
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(last, [0.5, 0.02, 2.5, 1.0]);
+    }
+
+    #[test]
+    pub fn ripples_tile_and_face_out() {
+        let map = ripple_map();
+        assert_eq!(map.len(), (RIPPLE_SIZE * RIPPLE_SIZE * 4) as usize);
+        let texel = |x: u32, y: u32| {
+            let i = ((y % RIPPLE_SIZE * RIPPLE_SIZE + x % RIPPLE_SIZE) * 4) as usize;
+            Vec3::from_array([0, 1, 2].map(|c| map[i + c] as f32 / 255.0 * 2.0 - 1.0))
+        };
+        let mut sum = Vec3::ZERO;
+        for y in 0..RIPPLE_SIZE {
+            for x in 0..RIPPLE_SIZE {
+                let normal = texel(x, y);
+                assert!(normal.z > 0.5 && (normal.length() - 1.0).abs() < 0.02);
+                // Across the edge it carries on like anywhere else.
+                let step = |n: Vec3| (n - normal).length();
+                assert!(step(texel(x + 1, y)) < 0.25 && step(texel(x, y + 1)) < 0.25);
+                sum += normal;
+            }
+        }
+        // Level on the whole.
+        let mean = sum / (RIPPLE_SIZE * RIPPLE_SIZE) as f32;
+        assert!(mean.x.abs() < 0.02 && mean.y.abs() < 0.02);
+    }
+}
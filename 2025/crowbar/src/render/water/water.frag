@@ -0,0 +1,33 @@
+#version 450
+// Water surfaces, blended over the scene they show through. Keep in step with WaterPass in water.rs.
+
+#define PLANAR_BINDING 6
+#define PLANAR_SAMPLER_BINDING 7
+#include "../planar/planar.glsl"
+#define WATER_PLANAR
+#include "../water/water.glsl"
+
+layout(push_constant) uniform Push {
+    mat4 view_proj;
+    // What it reflects without a planar reflection.
+    vec4 sky;
+};
+
+layout(location = 0) in vec3 world;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 position;
+layout(location = 3) in vec4 clip;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    mat4 inverse_view_proj = inverse(view_proj);
+    // The camera, or with an orthographic projection the way it looks.
+    vec4 eye = inverse_view_proj * vec4(0.0, 0.0, 1.0, 0.0);
+    vec3 view_dir = normalize(abs(eye.w) > 1e-6 ? world - eye.xyz / eye.w : -eye.xyz);
+    vec4 shaded = water_shade(world, clip, position, normalize(normal), view_dir, sky.rgb, inverse_view_proj);
+    if (shaded.a <= 0.0) {
+        discard;
+    }
+    out_color = shaded;
+}
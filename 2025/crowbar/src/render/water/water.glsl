@@ -0,0 +1,146 @@
+// Water surfaces, see water.rs. Define WATER_SET and WATER_BINDING before including it to put the water somewhere
+// other than set 0, binding 0; the ripple normal map, the scene's colour and depth and their samplers go in the five
+// bindings after. For the planar reflection, include planar.glsl first and define WATER_PLANAR. Keep in step with
+// water::gpu_data and water::surface_point.
+
+#ifndef WATER_SET
+#define WATER_SET 0
+#endif
+#ifndef WATER_BINDING
+#define WATER_BINDING 0
+#endif
+
+// Keep in step with the length of Water::waves.
+#define WATER_WAVES 4
+// Cells a side of the grid the vertex shader moves, keep in step with water::GRID.
+#define WATER_GRID 64
+
+layout(std140, set = WATER_SET, binding = WATER_BINDING) uniform WaterBlock {
+    mat4 water_world;
+    // Two per wave: direction, wavenumber and angular speed, then amplitude and horizontal amplitude.
+    vec4 water_waves[WATER_WAVES * 2];
+    // Both layers' scrolls.
+    vec4 water_ripple_scroll;
+    // Ripple scale in w.
+    vec4 water_shallow;
+    // Depth fade in w.
+    vec4 water_deep;
+    // Shore fade, distortion, time in seconds, and 1 if it reflects.
+    vec4 water_params;
+};
+
+// Tangent space.
+layout(set = WATER_SET, binding = WATER_BINDING + 1) uniform texture2D water_ripples;
+// The scene drawn before the water.
+layout(set = WATER_SET, binding = WATER_BINDING + 2) uniform texture2D water_scene_color;
+layout(set = WATER_SET, binding = WATER_BINDING + 3) uniform texture2D water_scene_depth;
+// Linear filtering, wrapping for the ripples and clamped to the edges for the scene.
+layout(set = WATER_SET, binding = WATER_BINDING + 4) uniform sampler water_repeat;
+layout(set = WATER_SET, binding = WATER_BINDING + 5) uniform sampler water_clamp;
+
+// Where the point at `position` in the surface's XZ plane has been moved to by the waves, in the surface's space,
+// with the surface's normal there.
+vec3 water_displace(vec2 position, out vec3 normal) {
+    float time = water_params.z;
+    vec3 point = vec3(position.x, 0.0, position.y);
+    normal = vec3(0.0, 1.0, 0.0);
+    for (int i = 0; i < WATER_WAVES; i++) {
+        vec4 a = water_waves[i * 2];
+        vec4 b = water_waves[i * 2 + 1];
+        float phase = a.z * dot(a.xy, position) - a.w * time;
+        float s = sin(phase);
+        float c = cos(phase);
+        point += vec3(a.x * b.y * c, b.x * s, a.y * b.y * c);
+        normal -= vec3(a.x * a.z * b.x * c, a.z * b.y * s, a.y * a.z * b.x * c);
+    }
+    normal = normalize(normal);
+    return point;
+}
+
+// For the vertex shader: the world space position and normal of the grid point at `position` in the surface's XZ
+// plane.
+vec3 water_vertex(vec2 position, out vec3 normal) {
+    vec3 local_normal;
+    vec3 point = water_displace(position, local_normal);
+    normal = normalize(transpose(inverse(mat3(water_world))) * local_normal);
+    return (water_world * vec4(point, 1.0)).xyz;
+}
+
+// The waves' `normal`, roughened by both layers of ripples, at `position` in the surface's XZ plane.
+vec3 water_ripple_normal(vec2 position, vec3 normal) {
+    vec2 uv = position / water_shallow.w;
+    float time = water_params.z;
+    vec2 uv_a = uv + water_ripple_scroll.xy * time;
+    vec2 uv_b = uv * 1.37 + water_ripple_scroll.zw * time;
+    vec3 a = texture(sampler2D(water_ripples, water_repeat), uv_a).xyz * 2.0 - 1.0;
+    vec3 b = texture(sampler2D(water_ripples, water_repeat), uv_b).xyz * 2.0 - 1.0;
+    // Whiteout blending, then onto the waves with Y up rather than Z.
+    vec3 ripple = normalize(vec3(a.xy + b.xy, a.z * b.z));
+    vec3 tangent = normalize(cross(normal, vec3(0.0, 0.0, 1.0)));
+    vec3 bitangent = cross(tangent, normal);
+    return normalize(tangent * ripple.x + bitangent * ripple.y + normal * ripple.z);
+}
+
+// The depth the scene drew at `uv` on screen, reversed so 0 is where nothing was drawn, and where that is in the
+// world through `inverse_view_proj`.
+float water_scene_depth_at(vec2 uv, mat4 inverse_view_proj, out vec3 world) {
+    ivec2 size = textureSize(sampler2D(water_scene_depth, water_clamp), 0);
+    ivec2 texel = clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+    float depth = texelFetch(sampler2D(water_scene_depth, water_clamp), texel, 0).r;
+    // Clip space Y is up, the target's rows go down.
+    vec4 point = inverse_view_proj * vec4(vec2(uv.x, 1.0 - uv.y) * 2.0 - 1.0, depth, 1.0);
+    world = point.xyz / max(point.w, 1e-6);
+    return depth;
+}
+
+// The colour and coverage of the water at `world_position`, `clip_position` in the camera's clip space, with the
+// waves' `normal` in world space and `position` in the surface's XZ plane. `view_dir` points from the camera, `sky`
+// is what it reflects without a planar reflection. Nothing where the scene drew something in front of it.
+vec4 water_shade(
+    vec3 world_position,
+    vec4 clip_position,
+    vec2 position,
+    vec3 normal,
+    vec3 view_dir,
+    vec3 sky,
+    mat4 inverse_view_proj
+) {
+    vec2 ndc = clip_position.xy / clip_position.w;
+    vec2 uv = vec2(ndc.x, -ndc.y) * 0.5 + 0.5;
+    float here = clip_position.z / clip_position.w;
+    vec3 behind;
+    float depth = water_scene_depth_at(uv, inverse_view_proj, behind);
+    if (depth > here) {
+        return vec4(0.0);
+    }
+
+    vec3 n = water_ripple_normal(position, normal);
+    vec2 distortion = (n.xz - normal.xz) * water_params.y;
+    // Bent by the ripples, unless that pulls in something in front of the water.
+    vec2 refracted = uv + distortion;
+    vec3 bent;
+    float bent_depth = water_scene_depth_at(refracted, inverse_view_proj, bent);
+    if (bent_depth > here) {
+        refracted = uv;
+    } else {
+        behind = bent;
+        depth = bent_depth;
+    }
+    // As deep as can be where nothing was drawn.
+    float thickness = depth > 0.0 ? distance(behind, world_position) : 1e30;
+    float murk = clamp(thickness / water_deep.w, 0.0, 1.0);
+    vec3 below = texture(sampler2D(water_scene_color, water_clamp), refracted).rgb * water_shallow.rgb;
+    vec3 through = mix(below, water_deep.rgb, murk);
+
+#ifdef WATER_PLANAR
+    vec3 reflected = water_params.w > 0.5 ? planar_reflection(clip_position, distortion) : sky;
+#else
+    vec3 reflected = sky;
+#endif
+    // Schlick, with water's 0.02 head on.
+    float facing = clamp(dot(-view_dir, n), 0.0, 1.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - facing, 5.0);
+
+    float shore = clamp(thickness / water_params.x, 0.0, 1.0);
+    return vec4(mix(through, reflected, fresnel), shore);
+}
@@ -0,0 +1,36 @@
+#version 450
+// Water surfaces: a grid over the unit square on the water's XZ plane, around its origin, moved by its waves. Keep in
+// step with WaterPass in water.rs.
+
+#include "../water/water.glsl"
+
+layout(push_constant) uniform Push {
+    mat4 view_proj;
+    // What it reflects without a planar reflection.
+    vec4 sky;
+};
+
+layout(location = 0) out vec3 out_world;
+layout(location = 1) out vec3 out_normal;
+// Where it is in the surface's XZ plane, before the waves.
+layout(location = 2) out vec2 out_position;
+layout(location = 3) out vec4 out_clip;
+
+void main() {
+    // Two triangles per cell, in rows of WATER_GRID cells.
+    int cell = int(gl_VertexIndex) / 6;
+    int corner = int(gl_VertexIndex) % 6;
+    ivec2 at = ivec2(cell % WATER_GRID, cell / WATER_GRID);
+    at.x += corner == 1 || corner == 4 || corner == 5 ? 1 : 0;
+    at.y += corner == 2 || corner == 3 || corner == 5 ? 1 : 0;
+    vec2 position = vec2(at) / float(WATER_GRID) - 0.5;
+
+    vec3 normal;
+    vec3 world = water_vertex(position, normal);
+    vec4 clip = view_proj * vec4(world, 1.0);
+    out_world = world;
+    out_normal = normal;
+    out_position = position;
+    out_clip = clip;
+    gl_Position = clip;
+}
@@ -0,0 +1,181 @@
+//! Input recording and replay, for reproducible bug reports and soak tests.
+//!
+//! A recording is JSON lines: a header with the seed, then one line per frame holding its delta
+//! and the input events that arrived before it. Lines are flushed as they're written,
+//! so a recording of a run that crashed is still good up to the crash.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::InputEvent;
+
+/// Bumped whenever the format changes, old recordings get rejected rather than misread.
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub delta: f32,
+    pub events: Vec<InputEvent>,
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub struct Recorder {
+    out: Box<dyn Write>,
+    pending: Vec<InputEvent>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, seed: u64) -> io::Result<Recorder> {
+        Recorder::new(BufWriter::new(File::create(path)?), seed)
+    }
+
+    pub fn new(out: impl Write + 'static, seed: u64) -> io::Result<Recorder> {
+        let mut rec = Recorder {
+            out: Box::new(out),
+            pending: Vec::new(),
+        };
+
+        rec.write_line(&ReplayHeader {
+            version: REPLAY_VERSION,
+            seed,
+        })?;
+        return Ok(rec);
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, value).map_err(invalid)?;
+        self.out.write_all(b"\n")?;
+        return self.out.flush();
+    }
+
+    /// Queue an event for the next frame.
+    pub fn record(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    /// Write out a frame with everything recorded since the last one.
+    pub fn frame(&mut self, delta: f32) -> io::Result<()> {
+        let frame = ReplayFrame {
+            delta,
+            events: std::mem::take(&mut self.pending),
+        };
+        return self.write_line(&frame);
+    }
+}
+
+pub struct Replay {
+    pub header: ReplayHeader,
+    frames: VecDeque<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> io::Result<Replay> {
+        Replay::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(input: impl BufRead) -> io::Result<Replay> {
+        let mut lines = input.lines();
+
+        let header: ReplayHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(invalid)?,
+            None => return Err(invalid("Replay is empty")),
+        };
+        if header.version != REPLAY_VERSION {
+            return Err(invalid(format!(
+                "Replay is version {}, we only read {REPLAY_VERSION}",
+                header.version
+            )));
+        }
+
+        let mut frames = VecDeque::new();
+        for line in lines {
+            let line = line?;
+            // A run that crashed mid-write leaves a torn last line, everything before it is still fine.
+            match serde_json::from_str(&line) {
+                Ok(frame) => frames.push_back(frame),
+                Err(_) => break,
+            }
+        }
+
+        return Ok(Replay { header, frames });
+    }
+
+    /// Frames left to play.
+    pub fn remaining(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn next_frame(&mut self) -> Option<ReplayFrame> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use winit::keyboard::KeyCode;
+
+    use super::{Recorder, Replay};
+    use crate::input::InputEvent;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let buf = SharedBuf::default();
+        let mut rec = Recorder::new(buf.clone(), 1234).unwrap();
+
+        let press = InputEvent::Key {
+            key: KeyCode::KeyW,
+            pressed: true,
+        };
+        rec.record(press);
+        rec.record(InputEvent::CursorMoved { x: 10.0, y: 20.5 });
+        rec.frame(1.0 / 60.0).unwrap();
+        rec.frame(1.0 / 30.0).unwrap();
+
+        let mut data = buf.0.lock().unwrap().clone();
+        // Simulate a crash halfway through a line.
+        data.extend_from_slice(b"{\"delta\":0.0");
+
+        let mut replay = Replay::read(&data[..]).unwrap();
+        assert_eq!(replay.header.seed, 1234);
+        assert_eq!(replay.remaining(), 2);
+
+        let first = replay.next_frame().unwrap();
+        assert_eq!(first.delta, 1.0 / 60.0);
+        assert_eq!(first.events[0], press);
+        assert!(replay.next_frame().unwrap().events.is_empty());
+        assert!(replay.next_frame().is_none());
+    }
+}
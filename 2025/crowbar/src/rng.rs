@@ -0,0 +1,175 @@
+//! Deterministic random numbers. Everything random in the simulation should come from here, so a seed
+//! (from `--seed`, a replay or a snapshot) reproduces a run exactly.
+//!
+//! Each system takes its own named stream from [`RngService::stream`]. Streams are derived from the
+//! seed and their name alone, so adding a system or changing how much one draws doesn't shift anyone
+//! else's numbers.
+
+use std::{collections::BTreeMap, f32::consts::TAU};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// Used to expand seeds into full generator state, as the xoshiro authors recommend.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+/// FNV-1a. Stream names need a hash that's the same on every run and every platform, which std's isn't.
+fn hash_name(name: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for b in name.bytes() {
+        hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/// A xoshiro256** generator. Fast, small, and good enough for anything short of cryptography.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut sm = seed;
+        Rng {
+            state: [
+                splitmix64(&mut sm),
+                splitmix64(&mut sm),
+                splitmix64(&mut sm),
+                splitmix64(&mut sm),
+            ],
+        }
+    }
+
+    /// The stream called `name` under `seed`.
+    pub fn stream(seed: u64, name: &str) -> Rng {
+        Rng::new(seed ^ hash_name(name).rotate_left(32))
+    }
+
+    /// A new generator seeded from this one, for handing out to sub-tasks (per particle emitter, per job).
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        return result;
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniform in `0..n`. `n` must not be zero.
+    pub fn below(&mut self, n: u32) -> u32 {
+        // Lemire's multiply and shift. The bias is at most n / 2^32, not worth rejecting for.
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `[min, max)`.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.f32()
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.f32() < p
+    }
+
+    /// Uniform on the unit sphere.
+    pub fn unit_vector(&mut self) -> Vec3 {
+        let z = self.range(-1.0, 1.0);
+        let a = self.range(0.0, TAU);
+        let r = (1.0 - z * z).sqrt();
+        Vec3::new(r * a.cos(), r * a.sin(), z)
+    }
+}
+
+/// The app's random number streams, all derived from the run's seed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RngService {
+    seed: u64,
+    streams: BTreeMap<String, Rng>,
+}
+
+impl RngService {
+    pub fn new(seed: u64) -> RngService {
+        RngService {
+            seed,
+            streams: BTreeMap::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The stream for `name`, picking up where it was last left.
+    pub fn stream(&mut self, name: &str) -> &mut Rng {
+        if !self.streams.contains_key(name) {
+            self.streams
+                .insert(name.to_owned(), Rng::stream(self.seed, name));
+        }
+        return self.streams.get_mut(name).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rng, RngService};
+
+    #[test]
+    pub fn streams_are_independent() {
+        let mut a = RngService::new(42);
+        let first: Vec<u64> = (0..4).map(|_| a.stream("particles").next_u64()).collect();
+
+        // Drawing from another stream in between changes nothing.
+        let mut b = RngService::new(42);
+        let mut second = Vec::new();
+        for _ in 0..4 {
+            b.stream("ai").next_u64();
+            second.push(b.stream("particles").next_u64());
+        }
+        assert_eq!(first, second);
+
+        assert_ne!(Rng::stream(42, "ai"), Rng::stream(42, "particles"));
+        assert_ne!(Rng::new(1), Rng::new(2));
+
+        // And the state survives serialization mid-stream.
+        let mut copy: RngService =
+            serde_json::from_str(&serde_json::to_string(&b).unwrap()).unwrap();
+        assert_eq!(copy.stream("ai").next_u64(), b.stream("ai").next_u64());
+    }
+
+    #[test]
+    pub fn ranges() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let f = rng.f32();
+            assert!((0.0..1.0).contains(&f));
+            assert!(rng.below(10) < 10);
+            assert!((rng.unit_vector().length() - 1.0).abs() < 1e-4);
+        }
+    }
+}
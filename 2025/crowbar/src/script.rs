@@ -0,0 +1,366 @@
+//! Lua scripting, as a plugin.
+//!
+//! Every `.lua` file in the script root gets its own Lua state. Scripts may define global `init()` and
+//! `update(dt)` functions, and get `world`, `input` and `assets` tables to poke the engine with.
+//! Scripts are reloaded (and `init` re-run) when the file changes on disk.
+//!
+//! Scripts only get Lua's base, table, string, math, utf8 and coroutine libraries, less `dofile` and `loadfile`. No
+//! `io`, `os`, `package` or `debug`, so they can't reach the filesystem, run programs or load native code other than
+//! through what the engine hands them.
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use glam::{Quat, Vec3};
+use hecs::{Entity, World};
+use mlua::{Function, Lua, LuaOptions, MultiValue, Scope, StdLib, Table, Variadic};
+use winit::{event::MouseButton, event_loop::ActiveEventLoop, keyboard::KeyCode};
+
+use crate::{app::WinitApp, ecs::components::Transform, input::Input, plugin::Plugin};
+
+/// How often script files are checked for changes.
+const RELOAD_POLL: Duration = Duration::from_millis(250);
+
+/// A Lua state with only the libraries scripts get, see the module docs.
+fn new_lua() -> Lua {
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE;
+    let lua =
+        Lua::new_with(libs, LuaOptions::default()).expect("Only loading unsafe libraries can fail");
+    // The base library comes regardless, with these in it.
+    for name in ["dofile", "loadfile"] {
+        lua.globals()
+            .raw_remove(name)
+            .expect("Removing a global can't fail");
+    }
+    return lua;
+}
+
+struct LoadedScript {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    lua: Lua,
+    /// Set when the script errored, so we don't spam the same error every frame. Cleared on reload.
+    broken: bool,
+}
+
+pub struct ScriptPlugin {
+    root: PathBuf,
+    scripts: Vec<LoadedScript>,
+    last_poll: Instant,
+}
+
+impl ScriptPlugin {
+    pub fn new(root: impl Into<PathBuf>) -> ScriptPlugin {
+        ScriptPlugin {
+            root: root.into(),
+            scripts: Vec::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn load(&self, path: &Path) -> LoadedScript {
+        let lua = new_lua();
+        let mut broken = false;
+
+        let res = fs::read_to_string(path)
+            .map_err(mlua::Error::external)
+            .and_then(|src| lua.load(src).set_name(path.to_string_lossy()).exec());
+
+        if let Err(e) = res {
+            log::error!("Script {} failed to load: {e}", path.display());
+            broken = true;
+        }
+
+        LoadedScript {
+            path: path.to_owned(),
+            modified: Self::modified(path),
+            lua,
+            broken,
+        }
+    }
+
+    fn reload_changed(&mut self) -> Vec<usize> {
+        let mut reloaded = Vec::new();
+
+        for i in 0..self.scripts.len() {
+            let modified = Self::modified(&self.scripts[i].path);
+            if modified != self.scripts[i].modified {
+                log::info!("Reloading script {}", self.scripts[i].path.display());
+                self.scripts[i] = self.load(&self.scripts[i].path.clone());
+                reloaded.push(i);
+            }
+        }
+
+        return reloaded;
+    }
+
+    /// Call a global function in a script with the engine bindings in scope.
+    fn call(&mut self, index: usize, app: &mut WinitApp, func: &str, args: MultiValue) {
+        let script = &mut self.scripts[index];
+        if script.broken {
+            return;
+        }
+
+        let Ok(f) = script.lua.globals().get::<_, Function>(func) else {
+            return;
+        };
+
+        let input = app.input().clone();
+        let world = RefCell::new(app.world_mut());
+
+        let res = script.lua.scope(|scope| {
+            let globals = script.lua.globals();
+            globals.set("world", world_bindings(&script.lua, scope, &world)?)?;
+            globals.set("input", input_bindings(&script.lua, scope, &input)?)?;
+            globals.set("assets", asset_bindings(&script.lua, scope, &self.root)?)?;
+            f.call::<_, ()>(args)
+        });
+
+        if let Err(e) = res {
+            log::error!("Script {} errored in {func}: {e}", script.path.display());
+            script.broken = true;
+        }
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> &'static str {
+        "scripting"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, _event_loop: &ActiveEventLoop) {
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&self.root) {
+            Ok(dir) => dir
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|e| e == "lua"))
+                .collect(),
+            Err(e) => {
+                log::error!("Couldn't read script root {}: {e}", self.root.display());
+                Vec::new()
+            }
+        };
+        paths.sort();
+
+        self.scripts = paths.iter().map(|p| self.load(p)).collect();
+        for i in 0..self.scripts.len() {
+            self.call(i, app, "init", MultiValue::new());
+        }
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        if self.last_poll.elapsed() >= RELOAD_POLL {
+            self.last_poll = Instant::now();
+            for i in self.reload_changed() {
+                self.call(i, app, "init", MultiValue::new());
+            }
+        }
+
+        let dt = app.frame_context().delta;
+        for i in 0..self.scripts.len() {
+            let args = MultiValue::from_vec(vec![mlua::Value::Number(dt as f64)]);
+            self.call(i, app, "update", args);
+        }
+    }
+}
+
+fn entity(id: i64) -> mlua::Result<Entity> {
+    Entity::from_bits(id as u64)
+        .ok_or_else(|| mlua::Error::runtime(format!("Invalid entity id {id}")))
+}
+
+fn world_bindings<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &Scope<'lua, 'scope>,
+    world: &'scope RefCell<&mut World>,
+) -> mlua::Result<Table<'lua>> {
+    let t = lua.create_table()?;
+
+    t.set(
+        "spawn",
+        scope.create_function(|_, ()| {
+            let e = world.borrow_mut().spawn((Transform::IDENTITY,));
+            Ok(e.to_bits().get() as i64)
+        })?,
+    )?;
+    t.set(
+        "despawn",
+        scope.create_function(|_, id: i64| Ok(world.borrow_mut().despawn(entity(id)?).is_ok()))?,
+    )?;
+    t.set(
+        "exists",
+        scope.create_function(|_, id: i64| Ok(world.borrow().contains(entity(id)?)))?,
+    )?;
+
+    // Transform accessors, as loose numbers since there's no vector type on the Lua side.
+    // Getters return nothing for entities without a transform.
+    t.set(
+        "get_translation",
+        scope.create_function(|_, id: i64| {
+            let w = world.borrow();
+            Ok(w.get::<&Transform>(entity(id)?)
+                .ok()
+                .map(|t| Variadic::from_iter(t.translation.to_array()))
+                .unwrap_or_default())
+        })?,
+    )?;
+    t.set(
+        "set_translation",
+        scope.create_function(|_, (id, x, y, z): (i64, f32, f32, f32)| {
+            let w = world.borrow();
+            if let Ok(mut t) = w.get::<&mut Transform>(entity(id)?) {
+                t.translation = Vec3::new(x, y, z);
+            }
+            Ok(())
+        })?,
+    )?;
+    t.set(
+        "get_rotation",
+        scope.create_function(|_, id: i64| {
+            let w = world.borrow();
+            Ok(w.get::<&Transform>(entity(id)?)
+                .ok()
+                .map(|t| Variadic::from_iter(t.rotation.to_array()))
+                .unwrap_or_default())
+        })?,
+    )?;
+    t.set(
+        "set_rotation",
+        scope.create_function(|_, (id, x, y, z, w_): (i64, f32, f32, f32, f32)| {
+            let w = world.borrow();
+            if let Ok(mut t) = w.get::<&mut Transform>(entity(id)?) {
+                t.rotation = Quat::from_xyzw(x, y, z, w_).normalize();
+            }
+            Ok(())
+        })?,
+    )?;
+    t.set(
+        "get_scale",
+        scope.create_function(|_, id: i64| {
+            let w = world.borrow();
+            Ok(w.get::<&Transform>(entity(id)?)
+                .ok()
+                .map(|t| Variadic::from_iter(t.scale.to_array()))
+                .unwrap_or_default())
+        })?,
+    )?;
+    t.set(
+        "set_scale",
+        scope.create_function(|_, (id, x, y, z): (i64, f32, f32, f32)| {
+            let w = world.borrow();
+            if let Ok(mut t) = w.get::<&mut Transform>(entity(id)?) {
+                t.scale = Vec3::new(x, y, z);
+            }
+            Ok(())
+        })?,
+    )?;
+
+    return Ok(t);
+}
+
+fn mouse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
+
+/// Keys are named after winit's KeyCode variants, "KeyW", "Space", "ArrowLeft" and so on.
+fn key_named(name: &str, mut keys: impl Iterator<Item = KeyCode>) -> bool {
+    keys.any(|k| format!("{k:?}") == name)
+}
+
+fn input_bindings<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &Scope<'lua, 'scope>,
+    input: &'scope Input,
+) -> mlua::Result<Table<'lua>> {
+    let t = lua.create_table()?;
+
+    t.set(
+        "key_down",
+        scope.create_function(move |_, name: String| Ok(key_named(&name, input.keys_down())))?,
+    )?;
+    t.set(
+        "key_pressed",
+        scope.create_function(move |_, name: String| Ok(key_named(&name, input.keys_pressed())))?,
+    )?;
+    t.set(
+        "button_down",
+        scope.create_function(|_, name: String| {
+            Ok(mouse_button(&name).is_some_and(|b| input.button_down(b)))
+        })?,
+    )?;
+    t.set(
+        "button_pressed",
+        scope.create_function(|_, name: String| {
+            Ok(mouse_button(&name).is_some_and(|b| input.button_pressed(b)))
+        })?,
+    )?;
+    t.set(
+        "cursor",
+        scope.create_function(|_, ()| {
+            let p = input.cursor_position();
+            Ok((p.x, p.y))
+        })?,
+    )?;
+
+    return Ok(t);
+}
+
+fn asset_bindings<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &Scope<'lua, 'scope>,
+    root: &'scope Path,
+) -> mlua::Result<Table<'lua>> {
+    let t = lua.create_table()?;
+
+    // Scripts only get to read from under the script root.
+    t.set(
+        "read_text",
+        scope.create_function(|_, path: String| {
+            let rel = Path::new(&path);
+            if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+                return Err(mlua::Error::runtime(format!(
+                    "Asset path {path} escapes the script root"
+                )));
+            }
+
+            fs::read_to_string(root.join(rel)).map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    return Ok(t);
+}
+
+#[cfg(test)]
+mod test {
+    use mlua::Value;
+
+    use super::new_lua;
+
+    #[test]
+    pub fn no_unsafe_libraries() {
+        let lua = new_lua();
+        for name in [
+            "io", "os", "package", "debug", "require", "dofile", "loadfile",
+        ] {
+            let value: Value = lua.globals().get(name).unwrap();
+            assert!(value.is_nil(), "{name} is loaded");
+        }
+        let len: i64 = lua
+            .load("return string.len(table.concat({'a', 'b'}))")
+            .eval()
+            .unwrap();
+        assert_eq!(len, 2);
+    }
+}
@@ -0,0 +1,324 @@
+//! Snapshots of the runtime state: the world, the seed, random streams and the clock. For save games, and for saving
+//! state, poking at something, then putting it back.
+//!
+//! Components are saved through a [`SnapshotRegistry`], which knows the engine's own. Games register
+//! theirs, anything unregistered is skipped. [`GlobalTransform`] isn't saved, it's rebuilt next frame.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use hecs::{Component, Entity, World};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    ecs::components::{
+        Camera, Light, MeshRenderer, Name, Parent, PlanarReflector, Portal, Transform, Water,
+    },
+    rng::RngService,
+};
+
+/// Bumped whenever the format changes. Snapshots from before [`OLDEST_SNAPSHOT_VERSION`] get rejected rather than
+/// misread, ones since are brought up to date as they load.
+///
+/// 2: [`Snapshot::rng`].
+/// 3: [`Light::color`] a [`LinearColor`](crate::color::LinearColor) rather than a `Vec3`.
+pub const SNAPSHOT_VERSION: u32 = 3;
+pub const OLDEST_SNAPSHOT_VERSION: u32 = 1;
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    /// [`Entity::to_bits`], so references between entities survive the round trip.
+    pub id: u64,
+    /// Keyed by the name the component was registered under.
+    pub components: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub seed: u64,
+    pub frame: u64,
+    /// Simulated seconds since the start of the run.
+    pub time: f64,
+    /// Filled in by the app, the registry only sees the world. `None` from before version 2, for the app to start
+    /// the streams over from the seed.
+    #[serde(default)]
+    pub rng: Option<RngService>,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut out, self).map_err(io::Error::other)?;
+        return out.flush();
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        let json: Value =
+            serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(invalid)?;
+        return Snapshot::from_json(json);
+    }
+
+    /// Read a snapshot as saved by any version since [`OLDEST_SNAPSHOT_VERSION`].
+    pub fn from_json(json: Value) -> io::Result<Snapshot> {
+        let version = json.get("version").and_then(Value::as_u64).unwrap_or(0);
+        if !(OLDEST_SNAPSHOT_VERSION as u64..=SNAPSHOT_VERSION as u64).contains(&version) {
+            return Err(invalid(format!(
+                "snapshot version {version} isn't supported, expected {OLDEST_SNAPSHOT_VERSION} to {SNAPSHOT_VERSION}"
+            )));
+        }
+        let mut json = json;
+        if version < 3 {
+            upgrade_light_colors(&mut json);
+        }
+        let mut snapshot: Snapshot = serde_json::from_value(json).map_err(invalid)?;
+        snapshot.version = SNAPSHOT_VERSION;
+        return Ok(snapshot);
+    }
+}
+
+/// Lights' `[r, g, b]` colours from before version 3, as the linear colours they were.
+fn upgrade_light_colors(json: &mut Value) {
+    let Some(entities) = json.get_mut("entities").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for entity in entities {
+        let Some(color) = entity.pointer_mut("/components/light/color") else {
+            continue;
+        };
+        if let Some([r, g, b]) = color.as_array().map(Vec::as_slice) {
+            *color = serde_json::json!({ "r": r, "g": g, "b": b, "a": 1.0 });
+        }
+    }
+}
+
+type SaveFn = Box<dyn Fn(&World, Entity) -> Option<serde_json::Result<Value>>>;
+type LoadFn = Box<dyn Fn(&mut World, Entity, Value) -> serde_json::Result<()>>;
+
+struct Registration {
+    save: SaveFn,
+    load: LoadFn,
+}
+
+/// The components that go into snapshots, and how.
+pub struct SnapshotRegistry {
+    components: BTreeMap<&'static str, Registration>,
+}
+
+impl Default for SnapshotRegistry {
+    fn default() -> Self {
+        let mut r = SnapshotRegistry {
+            components: BTreeMap::new(),
+        };
+        r.register::<Name>("name");
+        r.register::<Transform>("transform");
+        r.register::<MeshRenderer>("mesh_renderer");
+        r.register::<Light>("light");
+        r.register::<Camera>("camera");
+        r.register::<PlanarReflector>("planar_reflector");
+        r.register::<Water>("water");
+        r.register_with(
+            "portal",
+            |p: &Portal| (*p, p.exit.map(|e| e.to_bits().get())),
+            |(p, exit): (Portal, Option<u64>)| {
+                let exit = exit.and_then(Entity::from_bits);
+                Some(Portal { exit, ..p })
+            },
+        );
+        r.register_with(
+            "parent",
+            |p: &Parent| p.0.to_bits().get(),
+            |bits: u64| Entity::from_bits(bits).map(Parent),
+        );
+        return r;
+    }
+}
+
+impl SnapshotRegistry {
+    /// Save `T` in snapshots under `name`. Names have to stay stable for old snapshots to load.
+    pub fn register<T: Component + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+    ) {
+        self.register_with(name, T::clone, Some);
+    }
+
+    /// Save `T` in snapshots as an `S`, for components that can't be serialized directly.
+    /// `from` returning `None` drops the component.
+    pub fn register_with<T: Component, S: Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+        to: impl Fn(&T) -> S + 'static,
+        from: impl Fn(S) -> Option<T> + 'static,
+    ) {
+        if self.components.contains_key(name) {
+            panic!("Snapshot component {name} registered twice!");
+        }
+
+        let registration = Registration {
+            save: Box::new(move |world, e| {
+                let c = world.get::<&T>(e).ok()?;
+                Some(serde_json::to_value(to(&c)))
+            }),
+            load: Box::new(move |world, e, value| {
+                if let Some(c) = from(serde_json::from_value(value)?) {
+                    // The entity was spawned just before, it's there.
+                    world.insert_one(e, c).unwrap();
+                }
+                Ok(())
+            }),
+        };
+        self.components.insert(name, registration);
+    }
+
+    pub fn capture(&self, world: &World, seed: u64, frame: u64, time: f64) -> io::Result<Snapshot> {
+        let mut entities = Vec::new();
+
+        for e in world.iter() {
+            let e = e.entity();
+            let mut components = BTreeMap::new();
+            for (name, r) in &self.components {
+                if let Some(value) = (r.save)(world, e) {
+                    components.insert(name.to_string(), value.map_err(io::Error::other)?);
+                }
+            }
+
+            if !components.is_empty() {
+                entities.push(EntitySnapshot {
+                    id: e.to_bits().get(),
+                    components,
+                });
+            }
+        }
+
+        return Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            seed,
+            frame,
+            time,
+            rng: None,
+            entities,
+        });
+    }
+
+    /// Replace everything in `world` with the snapshot's entities, leaving it as it was if the snapshot doesn't load.
+    /// Components the registry doesn't know are skipped with a warning.
+    pub fn restore(&self, snapshot: &Snapshot, world: &mut World) -> io::Result<()> {
+        // Built up on the side, and only swapped in once it's all loaded.
+        let mut restored = World::new();
+
+        // Spawn everything first, so loaders can refer to any entity.
+        let mut spawned = Vec::with_capacity(snapshot.entities.len());
+        for s in &snapshot.entities {
+            let e = Entity::from_bits(s.id)
+                .ok_or_else(|| invalid(format!("{} isn't an entity id", s.id)))?;
+            restored.spawn_at(e, ());
+            spawned.push(e);
+        }
+
+        for (s, e) in snapshot.entities.iter().zip(spawned) {
+            for (name, value) in &s.components {
+                let Some(r) = self.components.get(name.as_str()) else {
+                    log::warn!("Snapshot has unknown component {name}, skipping it.");
+                    continue;
+                };
+                (r.load)(&mut restored, e, value.clone()).map_err(invalid)?;
+            }
+        }
+
+        *world = restored;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+    use hecs::World;
+
+    use super::{SNAPSHOT_VERSION, Snapshot, SnapshotRegistry};
+    use crate::ecs::components::{Name, Parent, Transform};
+    use crate::rng::RngService;
+
+    #[test]
+    pub fn round_trip() {
+        let registry = SnapshotRegistry::default();
+        let mut world = World::new();
+        let parent = world.spawn((Name("parent".into()), Transform::IDENTITY));
+        let child = world.spawn((Transform::from_translation(Vec3::X), Parent(parent)));
+        world.despawn(parent).unwrap();
+        let parent = world.spawn((Name("parent".into()), Transform::IDENTITY));
+        world.insert_one(child, Parent(parent)).unwrap();
+
+        let snapshot = registry.capture(&world, 7, 100, 1.5).unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot = serde_json::from_str(&json).unwrap();
+
+        let mut restored = World::new();
+        restored.spawn(());
+        registry.restore(&snapshot, &mut restored).unwrap();
+
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get::<&Name>(parent).unwrap().0, "parent");
+        assert_eq!(restored.get::<&Parent>(child).unwrap().0, parent);
+        assert_eq!(
+            restored.get::<&Transform>(child).unwrap().translation,
+            Vec3::X
+        );
+    }
+
+    #[test]
+    pub fn failed_restore_keeps_world() {
+        let registry = SnapshotRegistry::default();
+        let mut world = World::new();
+        let kept = world.spawn((Name("kept".into()),));
+
+        // Generation 1, ids 5 and 6.
+        let id = |i: u64| (1 << 32) | i;
+        let bad: Snapshot = serde_json::from_value(serde_json::json!({
+            "version": SNAPSHOT_VERSION,
+            "seed": 0,
+            "frame": 0,
+            "time": 0.0,
+            "entities": [
+                { "id": id(5), "components": { "name": "fine" } },
+                { "id": id(6), "components": { "transform": "not a transform" } },
+            ],
+        }))
+        .unwrap();
+        let e = registry.restore(&bad, &mut world).unwrap_err();
+        assert!(e.to_string().contains("invalid type"), "{e}");
+        assert_eq!(world.len(), 1);
+        assert_eq!(world.get::<&Name>(kept).unwrap().0, "kept");
+    }
+
+    #[test]
+    pub fn loads_old_versions() {
+        let mut rng = RngService::new(3);
+        rng.stream("loot").next_u64();
+        let mut snapshot = SnapshotRegistry::default()
+            .capture(&World::new(), 3, 0, 0.0)
+            .unwrap();
+        snapshot.rng = Some(rng.clone());
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(Snapshot::from_json(json).unwrap().rng, Some(rng));
+
+        let v1 =
+            serde_json::json!({ "version": 1, "seed": 3, "frame": 0, "time": 0.0, "entities": [] });
+        let v1 = Snapshot::from_json(v1).unwrap();
+        assert_eq!((v1.version, v1.rng), (SNAPSHOT_VERSION, None));
+
+        let future = serde_json::json!({ "version": SNAPSHOT_VERSION + 1 });
+        assert!(Snapshot::from_json(future).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+//! A stack of high level app states (loading, menu, game, pause...), with fades between them.
+//!
+//! Only the top state updates. States change the stack by returning a [`Change`] from
+//! [`AppState::update`], and a state that needs time to get ready reports it through
+//! [`AppState::load_progress`], which holds a loading screen up until it's done.
+
+use winit::event_loop::ActiveEventLoop;
+
+use crate::{app::WinitApp, overlay::Curtain, platform::TaskbarProgress, plugin::Plugin};
+
+pub trait AppState: 'static {
+    fn name(&self) -> &'static str;
+
+    /// Called when the state goes on the stack.
+    fn enter(&mut self, _app: &mut WinitApp) {}
+
+    /// Called when the state comes off the stack.
+    fn exit(&mut self, _app: &mut WinitApp) {}
+
+    /// Called when another state is pushed on top of this one.
+    fn pause(&mut self, _app: &mut WinitApp) {}
+
+    /// Called when the state above this one is popped.
+    fn resume(&mut self, _app: &mut WinitApp) {}
+
+    /// Called every frame while this is the top state.
+    fn update(&mut self, _app: &mut WinitApp) -> Option<Change> {
+        None
+    }
+
+    /// How far along getting ready the state is, in `0.0..=1.0`, or `None` if it's ready.
+    /// Polled every frame after `enter` until it's done, with a loading screen up in the meantime. A state reading
+    /// its assets with [`Pack::preload`](crate::pack::Pack::preload) can hand on the preload's progress.
+    fn load_progress(&mut self, _app: &mut WinitApp) -> Option<f32> {
+        None
+    }
+}
+
+pub enum StateCommand {
+    Push(Box<dyn AppState>),
+    Pop,
+    Replace(Box<dyn AppState>),
+    /// Pop everything and exit the app.
+    Quit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transition {
+    /// Switch straight away.
+    Cut,
+    /// Fade to black, switch, fade back in. The duration covers both halves.
+    Fade(f32),
+}
+
+/// A change to the state stack, and how to show it.
+pub struct Change {
+    pub command: StateCommand,
+    pub transition: Transition,
+}
+
+impl Change {
+    pub fn push(state: impl AppState) -> Change {
+        Change::cut(StateCommand::Push(Box::new(state)))
+    }
+
+    pub fn pop() -> Change {
+        Change::cut(StateCommand::Pop)
+    }
+
+    pub fn replace(state: impl AppState) -> Change {
+        Change::cut(StateCommand::Replace(Box::new(state)))
+    }
+
+    pub fn quit() -> Change {
+        Change::cut(StateCommand::Quit)
+    }
+
+    fn cut(command: StateCommand) -> Change {
+        Change {
+            command,
+            transition: Transition::Cut,
+        }
+    }
+
+    /// Fade through black over `seconds`.
+    pub fn fade(mut self, seconds: f32) -> Change {
+        self.transition = Transition::Fade(seconds);
+        return self;
+    }
+}
+
+enum Phase {
+    /// Fading out, with the command still to apply.
+    Out(StateCommand),
+    /// Waiting on the new top state's `load_progress`.
+    Loading,
+    In,
+}
+
+struct Pending {
+    phase: Phase,
+    /// Seconds into the current phase.
+    t: f32,
+    /// Length of each half of the fade.
+    half: f32,
+}
+
+/// Runs the state stack, as a plugin.
+pub struct StatePlugin {
+    stack: Vec<Box<dyn AppState>>,
+    initial: Option<Box<dyn AppState>>,
+    pending: Option<Pending>,
+    /// Whether we put a progress bar on the taskbar, so we know to take it off.
+    showing_progress: bool,
+}
+
+impl StatePlugin {
+    pub fn new(initial: impl AppState) -> StatePlugin {
+        StatePlugin {
+            stack: Vec::new(),
+            initial: Some(Box::new(initial)),
+            pending: None,
+            showing_progress: false,
+        }
+    }
+
+    /// State names, bottom to top.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.stack.iter().map(|s| s.name())
+    }
+
+    fn begin(&mut self, app: &mut WinitApp, change: Change) {
+        match change.transition {
+            Transition::Fade(seconds) if seconds > 0.0 => {
+                self.pending = Some(Pending {
+                    phase: Phase::Out(change.command),
+                    t: 0.0,
+                    half: seconds / 2.0,
+                });
+            }
+            _ => {
+                self.apply(app, change.command);
+                self.pending = Some(Pending {
+                    phase: Phase::Loading,
+                    t: 0.0,
+                    half: 0.0,
+                });
+            }
+        }
+    }
+
+    fn apply(&mut self, app: &mut WinitApp, command: StateCommand) {
+        match command {
+            StateCommand::Push(mut state) => {
+                if let Some(top) = self.stack.last_mut() {
+                    top.pause(app);
+                }
+                state.enter(app);
+                self.stack.push(state);
+            }
+            StateCommand::Pop => {
+                if let Some(mut top) = self.stack.pop() {
+                    top.exit(app);
+                }
+                match self.stack.last_mut() {
+                    Some(top) => top.resume(app),
+                    None => app.request_exit(),
+                }
+            }
+            StateCommand::Replace(mut state) => {
+                if let Some(mut top) = self.stack.pop() {
+                    top.exit(app);
+                }
+                state.enter(app);
+                self.stack.push(state);
+            }
+            StateCommand::Quit => {
+                self.exit_all(app);
+                app.request_exit();
+            }
+        }
+
+        log::info!(
+            "State stack: {}",
+            self.names().collect::<Vec<_>>().join(" > ")
+        );
+    }
+
+    fn exit_all(&mut self, app: &mut WinitApp) {
+        while let Some(mut state) = self.stack.pop() {
+            state.exit(app);
+        }
+    }
+
+    fn set_taskbar_progress(&mut self, app: &mut WinitApp, progress: Option<f32>) {
+        if progress.is_none() && !self.showing_progress {
+            return;
+        }
+        self.showing_progress = progress.is_some();
+
+        let Some(id) = app.main_window() else {
+            return;
+        };
+        if let Some(window) = app.get_window_state_mut(id) {
+            window.set_progress(progress.map_or(TaskbarProgress::None, TaskbarProgress::Normal));
+        }
+    }
+
+    /// Move a transition along. Returns the curtain to show, if any.
+    fn advance(&mut self, app: &mut WinitApp, mut pending: Pending) -> Option<Curtain> {
+        pending.t += app.frame_context().delta;
+
+        match pending.phase {
+            Phase::Out(command) => {
+                if pending.t < pending.half {
+                    let alpha = pending.t / pending.half;
+                    self.pending = Some(Pending {
+                        phase: Phase::Out(command),
+                        ..pending
+                    });
+                    return Some(Curtain::fade(alpha));
+                }
+
+                self.apply(app, command);
+                self.pending = Some(Pending {
+                    phase: Phase::Loading,
+                    t: 0.0,
+                    ..pending
+                });
+                return Some(Curtain::fade(1.0));
+            }
+            Phase::Loading => {
+                let progress = self
+                    .stack
+                    .last_mut()
+                    .and_then(|top| top.load_progress(app))
+                    .filter(|p| *p < 1.0);
+                self.set_taskbar_progress(app, progress);
+
+                if let Some(p) = progress {
+                    self.pending = Some(pending);
+                    return Some(Curtain::loading(p));
+                }
+
+                if pending.half > 0.0 {
+                    self.pending = Some(Pending {
+                        phase: Phase::In,
+                        t: 0.0,
+                        ..pending
+                    });
+                    return Some(Curtain::fade(1.0));
+                }
+                return None;
+            }
+            Phase::In => {
+                if pending.t < pending.half {
+                    let alpha = 1.0 - pending.t / pending.half;
+                    self.pending = Some(pending);
+                    return Some(Curtain::fade(alpha));
+                }
+                return None;
+            }
+        }
+    }
+}
+
+impl Plugin for StatePlugin {
+    fn name(&self) -> &'static str {
+        "states"
+    }
+
+    fn init(&mut self, app: &mut WinitApp, _event_loop: &ActiveEventLoop) {
+        if let Some(initial) = self.initial.take() {
+            self.begin(
+                app,
+                Change {
+                    command: StateCommand::Push(initial),
+                    transition: Transition::Cut,
+                },
+            );
+        }
+    }
+
+    fn pre_frame(&mut self, app: &mut WinitApp) {
+        if let Some(pending) = self.pending.take() {
+            let curtain = self.advance(app, pending);
+            app.overlay_mut().curtain = curtain;
+            return;
+        }
+
+        let change = self.stack.last_mut().and_then(|top| top.update(app));
+        if let Some(change) = change {
+            self.begin(app, change);
+        }
+    }
+
+    fn shutdown(&mut self, app: &mut WinitApp) {
+        self.exit_all(app);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{AppState, Change, StatePlugin};
+    use crate::{app::WinitApp, overlay::Curtain, plugin::Plugin};
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    /// Logs what happens to it, and takes a frame per entry of `progress` to load, last first.
+    struct Logged {
+        name: &'static str,
+        log: Log,
+        progress: Vec<f32>,
+    }
+
+    impl Logged {
+        fn new(name: &'static str, log: &Log) -> Logged {
+            Logged {
+                name,
+                log: log.clone(),
+                progress: Vec::new(),
+            }
+        }
+
+        fn push(&self, event: &str) {
+            self.log.borrow_mut().push(format!("{} {event}", self.name));
+        }
+    }
+
+    impl AppState for Logged {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn enter(&mut self, _app: &mut WinitApp) {
+            self.push("enter");
+        }
+
+        fn exit(&mut self, _app: &mut WinitApp) {
+            self.push("exit");
+        }
+
+        fn pause(&mut self, _app: &mut WinitApp) {
+            self.push("pause");
+        }
+
+        fn resume(&mut self, _app: &mut WinitApp) {
+            self.push("resume");
+        }
+
+        fn load_progress(&mut self, _app: &mut WinitApp) -> Option<f32> {
+            self.progress.pop()
+        }
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        std::mem::take(&mut *log.borrow_mut())
+    }
+
+    #[test]
+    pub fn push_pop_replace() {
+        let log = Log::default();
+        let mut app = WinitApp::for_tests(0.25);
+        let mut states = StatePlugin::new(Logged::new("unused", &log));
+
+        states.begin(&mut app, Change::push(Logged::new("menu", &log)));
+        assert_eq!(take(&log), ["menu enter"]);
+        // Nothing to load, so a cut is done with in one frame.
+        states.pre_frame(&mut app);
+        assert!(states.pending.is_none());
+        assert_eq!(app.overlay_mut().curtain, None);
+
+        states.begin(&mut app, Change::push(Logged::new("game", &log)));
+        assert_eq!(take(&log), ["menu pause", "game enter"]);
+        states.begin(&mut app, Change::replace(Logged::new("options", &log)));
+        assert_eq!(take(&log), ["game exit", "options enter"]);
+        assert_eq!(states.names().collect::<Vec<_>>(), ["menu", "options"]);
+
+        states.begin(&mut app, Change::pop());
+        assert_eq!(take(&log), ["options exit", "menu resume"]);
+        assert!(!app.exit_requested());
+        // Popping the last state exits.
+        states.begin(&mut app, Change::pop());
+        assert_eq!(take(&log), ["menu exit"]);
+        assert!(app.exit_requested());
+
+        let mut app = WinitApp::for_tests(0.25);
+        states.begin(&mut app, Change::push(Logged::new("a", &log)));
+        states.begin(&mut app, Change::push(Logged::new("b", &log)));
+        take(&log);
+        states.begin(&mut app, Change::quit());
+        assert_eq!(take(&log), ["b exit", "a exit"]);
+        assert!(app.exit_requested());
+    }
+
+    #[test]
+    pub fn fades_through_loading() {
+        let log = Log::default();
+        let mut app = WinitApp::for_tests(0.25);
+        let mut states = StatePlugin::new(Logged::new("unused", &log));
+        let level = Logged {
+            progress: vec![0.5, 0.0],
+            ..Logged::new("level", &log)
+        };
+        states.begin(&mut app, Change::push(level).fade(1.0));
+
+        let mut frames = Vec::new();
+        while states.pending.is_some() {
+            states.pre_frame(&mut app);
+            frames.push(app.overlay_mut().curtain);
+        }
+        assert_eq!(
+            frames,
+            [
+                // Out, with the push half way.
+                Some(Curtain::fade(0.5)),
+                Some(Curtain::fade(1.0)),
+                // Held up by loading.
+                Some(Curtain::loading(0.0)),
+                Some(Curtain::loading(0.5)),
+                // And back in.
+                Some(Curtain::fade(1.0)),
+                Some(Curtain::fade(0.5)),
+                None,
+            ]
+        );
+        assert_eq!(take(&log), ["level enter"]);
+    }
+}
@@ -0,0 +1,291 @@
+//! Helpers for tests that render: a headless renderer, reference scenes, and hashing what comes out.
+//!
+//! Hashes only hold for one rasterizer, so tests render on a CPU device where there is one
+//! (lavapipe on CI). Set `CROWBAR_TEST_GPU=1` to use the real GPU instead, and expect hash mismatches.
+//! Without any Vulkan device, rendering tests skip rather than fail.
+
+use ash::vk;
+use hecs::World;
+
+use crate::{
+    app::info::AppInfo,
+    capture::CapturedFrame,
+    color::LinearColor,
+    ecs::propagate_transforms,
+    render::{
+        descriptors::LayoutCache,
+        draw::{DrawList, PipelineId},
+        extract::ExtractedScene,
+        hal::{Attachment, CommandEncoder, LoadOp, TextureState, vulkan::vk_format},
+        headless::{Headless, TARGET_FORMAT},
+        mesh::{MeshPass, MeshPrepare, MeshShaders},
+        pipeline::TargetFormats,
+        portal::PortalViews,
+        rendering::PassContext,
+    },
+};
+
+pub mod golden;
+
+/// A headless renderer for a test, or `None` (with a note on stderr) if the machine can't render.
+pub fn headless() -> Option<Headless> {
+    let prefer_software = std::env::var_os("CROWBAR_TEST_GPU").is_none();
+    let headless = Headless::new(&AppInfo::new("crowbar-tests"), prefer_software);
+
+    match &headless {
+        Some(h) if !h.software && prefer_software => eprintln!(
+            "No CPU Vulkan device, rendering on {}. Frame hashes may not match.",
+            h.device_name
+        ),
+        Some(_) => {}
+        None => eprintln!("No Vulkan device, skipping rendering test."),
+    }
+    return headless;
+}
+
+/// FNV-1a over the size and sRGB pixels, so the same image hashes the same whatever format it was read back in.
+pub fn frame_hash(frame: &CapturedFrame) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let size = [frame.width.to_le_bytes(), frame.height.to_le_bytes()].concat();
+    for b in size.into_iter().chain(frame.to_srgb8()) {
+        hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/// Fail with the hash that came out, so a deliberate change can be accepted by pasting it in.
+#[track_caller]
+pub fn assert_frame_hash(name: &str, frame: &CapturedFrame, expected: u64) {
+    let actual = frame_hash(frame);
+    assert!(
+        actual == expected,
+        "{name} rendered differently: expected {expected:#018x}, got {actual:#018x}"
+    );
+}
+
+/// Record a pass that just clears the target.
+pub fn clear_pass<E: CommandEncoder>(cmds: &mut E, target: &E::Texture, color: LinearColor) {
+    cmds.begin_pass(target, Some(color));
+    cmds.end_pass();
+}
+
+/// Reference scenes, built the same every time.
+pub mod scenes {
+    use hecs::World;
+
+    use crate::{
+        color::LinearColor,
+        ecs::components::{
+            Camera, GlobalTransform, Light, LightKind, MaterialId, MeshId, MeshRenderer,
+            Projection, Transform,
+        },
+        math::Vec3,
+    };
+
+    /// Spawns a scene into an empty world.
+    pub type Build = fn(&mut World);
+
+    /// What each scene hashes to, rendered by [`super::render_world`] at 64 by 64 on a CPU device.
+    pub const HASHES: [(&str, Build, u64); 1] = [("lit_grid", lit_grid, LIT_GRID)];
+
+    /// A checkerboard of white and red squares, 4 pixels across every 8, in the middle of black.
+    pub const LIT_GRID: u64 = 0x76c58932b7cd1605;
+
+    /// A camera looking straight down on a grid of cubes under a sun. Orthographic and lined up with the pixels at
+    /// 64 by 64, with the sun bright enough to take both materials to full, so it renders exactly.
+    pub fn lit_grid(world: &mut World) {
+        world.spawn((
+            Camera {
+                projection: Projection::Orthographic {
+                    height: 16.0,
+                    near: 0.1,
+                    far: 100.0,
+                },
+                order: 0,
+                active: true,
+                lens: None,
+            },
+            Transform::looking_at(Vec3::new(0.0, 10.0, 0.0), Vec3::ZERO, Vec3::NEG_Z),
+            GlobalTransform::default(),
+        ));
+        world.spawn((
+            Light {
+                kind: LightKind::Directional,
+                color: LinearColor::WHITE,
+                intensity: 3.0,
+            },
+            Transform::looking_at(Vec3::ZERO, Vec3::new(-1.0, -2.0, -1.0), Vec3::Y),
+            GlobalTransform::default(),
+        ));
+
+        for x in -2..=2 {
+            for z in -2..=2 {
+                world.spawn((
+                    MeshRenderer {
+                        mesh: MeshId(0),
+                        material: MaterialId(((x + z) & 1) as u32),
+                        visible: true,
+                        cast_shadows: true,
+                    },
+                    Transform::from_translation(Vec3::new(x as f32 * 2.0, 0.0, z as f32 * 2.0)),
+                    GlobalTransform::default(),
+                ));
+            }
+        }
+    }
+}
+
+/// Render `world` as the engine would, into a `width` by `height` frame: propagate its transforms, extract it, cull
+/// each camera's view and draw them through the 3D pass. `None`, with a note on stderr, if there are no shaders to
+/// draw with.
+pub fn render_world(
+    headless: &Headless,
+    world: &mut World,
+    width: u32,
+    height: u32,
+) -> Option<CapturedFrame> {
+    let Some(shaders) = MeshShaders::builtin() else {
+        eprintln!("No mesh shaders, skipping rendering test.");
+        return None;
+    };
+
+    propagate_transforms(world);
+    let mut scene = ExtractedScene::default();
+    scene.extract(world);
+    let aspect = width as f32 / height.max(1) as f32;
+    let views: Vec<DrawList> = scene
+        .cameras
+        .iter()
+        .map(|camera| {
+            let mut view = DrawList::default();
+            view.cull(&scene, camera, aspect, PipelineId(0));
+            view
+        })
+        .collect();
+
+    let device = headless.device();
+    let target = TargetFormats {
+        color: vk_format(TARGET_FORMAT),
+        depth: vk::Format::UNDEFINED,
+        velocity: vk::Format::UNDEFINED,
+        samples: 1,
+    };
+    // SAFETY: Everything's this device's, and the render's waited on before the pass is destroyed.
+    unsafe {
+        let mut layouts = LayoutCache::new();
+        let cache = vk::PipelineCache::null();
+        let mut pass = MeshPass::new(device, &mut layouts, cache, &shaders, 1).unwrap();
+        let frame = headless.render(width, height, |cmds, texture| {
+            let (_, cmd) = cmds.raw();
+            let portals = PortalViews::default();
+            let input = MeshPrepare {
+                scene: &scene,
+                views: &views,
+                reflections: &[],
+                portals: &portals,
+                shadow_map: None,
+            };
+            pass.prepare(device, cmd, 0, input, None).unwrap();
+            let color = Attachment {
+                texture,
+                before: TextureState::RenderTarget,
+                after: TextureState::RenderTarget,
+                load: LoadOp::Clear(LinearColor::BLACK),
+                store: true,
+                resolve: None,
+            };
+            cmds.begin_rendering(&[color], None);
+            let (_, cmd) = cmds.raw();
+            let ctx = PassContext {
+                device,
+                cmd,
+                frame: 0,
+                target,
+                extent: texture.extent,
+            };
+            pass.record(ctx, &scene, &views).unwrap();
+            cmds.end_rendering();
+        });
+        pass.destroy(device);
+        layouts.destroy(device.raw());
+        return Some(frame.unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hecs::World;
+
+    use super::{assert_frame_hash, clear_pass, frame_hash, headless, render_world, scenes};
+    use crate::{
+        capture::{CaptureFormat, CapturedFrame},
+        color::LinearColor,
+    };
+
+    #[test]
+    pub fn clear_reads_back() {
+        let Some(headless) = headless() else {
+            return;
+        };
+
+        let frame = headless
+            .render(8, 4, |cmds, target| {
+                clear_pass(cmds, target, LinearColor::rgb(1.0, 0.0, 1.0))
+            })
+            .unwrap();
+
+        // Clears are exact, so this holds on any device.
+        let expected = CapturedFrame {
+            width: 8,
+            height: 4,
+            format: CaptureFormat::Rgba8Srgb,
+            data: [255, 0, 255, 255].repeat(32),
+            frame: 0,
+        };
+        assert_frame_hash("clear", &frame, frame_hash(&expected));
+    }
+
+    #[test]
+    pub fn lit_grid_hash_is_the_checkerboard() {
+        let mut data = [0, 0, 0, 255].repeat(64 * 64);
+        for z in -2i32..=2 {
+            for x in -2i32..=2 {
+                let color = match (x + z) & 1 {
+                    0 => [255, 255, 255, 255],
+                    _ => [255, 0, 0, 255],
+                };
+                let (left, top) = ((8 * x + 30) as usize, (8 * z + 30) as usize);
+                for row in top..top + 4 {
+                    for column in left..left + 4 {
+                        let at = (row * 64 + column) * 4;
+                        data[at..at + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+        let expected = CapturedFrame {
+            width: 64,
+            height: 64,
+            format: CaptureFormat::Rgba8Srgb,
+            data,
+            frame: 0,
+        };
+        assert_eq!(frame_hash(&expected), scenes::LIT_GRID);
+    }
+
+    #[test]
+    pub fn reference_scenes_render() {
+        let Some(headless) = headless() else {
+            return;
+        };
+
+        for (name, build, hash) in scenes::HASHES {
+            let mut world = World::new();
+            build(&mut world);
+            let Some(frame) = render_world(&headless, &mut world, 64, 64) else {
+                return;
+            };
+            assert_frame_hash(name, &frame, hash);
+        }
+    }
+}
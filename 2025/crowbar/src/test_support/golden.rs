@@ -0,0 +1,295 @@
+//! Comparing rendered frames against stored reference PNGs ("goldens"), within a tolerance.
+//!
+//! Goldens live in `goldens/` at the crate root. Run with `CROWBAR_UPDATE_GOLDENS=1` to write what
+//! rendered as the new golden instead of comparing. On a mismatch the expected, actual and difference
+//! images go to `golden-failures/` in `CARGO_TARGET_DIR` for a look, or in the build's `OUT_DIR` without it.
+
+use std::{
+    fs,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    capture::{CaptureFormat, CapturedFrame, write_png},
+    color::srgb_to_linear,
+};
+
+/// How different a frame can be and still pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// Largest difference in any one 8 bit sRGB channel before a pixel counts as different.
+    pub per_channel: u8,
+    /// Largest perceptual difference (Oklab distance, about 0.02 is just noticeable) before a pixel counts as different.
+    pub perceptual: f32,
+    /// Fraction of pixels allowed to differ, for the odd edge rasterizing differently.
+    pub max_differing: f32,
+}
+
+impl Tolerance {
+    /// Identical, bit for bit.
+    pub const EXACT: Tolerance = Tolerance {
+        per_channel: 0,
+        perceptual: 0.0,
+        max_differing: 0.0,
+    };
+
+    /// Good for most scenes, across GPUs.
+    pub const DEFAULT: Tolerance = Tolerance {
+        per_channel: 8,
+        perceptual: 0.02,
+        max_differing: 0.001,
+    };
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance::DEFAULT
+    }
+}
+
+/// How two frames differ.
+pub struct Comparison {
+    pub max_channel: u8,
+    pub max_perceptual: f32,
+    /// Pixels over either tolerance.
+    pub differing: usize,
+    pub pixels: usize,
+    /// Differing pixels in red, scaled by how different they are, over a faded copy of the expected image.
+    pub diff: CapturedFrame,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.differing as f32 <= tolerance.max_differing * self.pixels as f32
+    }
+}
+
+/// sRGB bytes to Oklab.
+fn oklab(p: &[u8]) -> [f32; 3] {
+    let [r, g, b] = [p[0], p[1], p[2]].map(|c| srgb_to_linear(c as f32 / 255.0));
+
+    let l = (0.4122215 * r + 0.5363325 * g + 0.051446 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.107397 * b).cbrt();
+    let s = (0.0883025 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+
+    return [
+        0.2104543 * l + 0.7936178 * m - 0.004072 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904 * l + 0.7827718 * m - 0.8086758 * s,
+    ];
+}
+
+/// Compare two frames of the same size. `None` if the sizes differ.
+pub fn compare(
+    expected: &CapturedFrame,
+    actual: &CapturedFrame,
+    tolerance: &Tolerance,
+) -> Option<Comparison> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return None;
+    }
+
+    let mut comparison = Comparison {
+        max_channel: 0,
+        max_perceptual: 0.0,
+        differing: 0,
+        pixels: (expected.width * expected.height) as usize,
+        diff: CapturedFrame {
+            width: actual.width,
+            height: actual.height,
+            format: CaptureFormat::Rgba8Srgb,
+            data: Vec::with_capacity(expected.data.len()),
+            frame: 0,
+        },
+    };
+    let (expected, actual) = (expected.to_srgb8(), actual.to_srgb8());
+
+    for (e, a) in expected.chunks_exact(4).zip(actual.chunks_exact(4)) {
+        let channel = e.iter().zip(a).map(|(e, a)| e.abs_diff(*a)).max().unwrap();
+        let [el, ea, eb] = oklab(e);
+        let [al, aa, ab] = oklab(a);
+        let perceptual = ((el - al).powi(2) + (ea - aa).powi(2) + (eb - ab).powi(2)).sqrt();
+
+        comparison.max_channel = comparison.max_channel.max(channel);
+        comparison.max_perceptual = comparison.max_perceptual.max(perceptual);
+
+        let differs = channel > tolerance.per_channel || perceptual > tolerance.perceptual;
+        if differs {
+            comparison.differing += 1;
+            let heat = 128 + (channel / 2);
+            comparison.diff.data.extend_from_slice(&[heat, 0, 0, 255]);
+        } else {
+            let faded = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 12) as u8;
+            comparison
+                .diff
+                .data
+                .extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    return Some(comparison);
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("goldens")
+        .join(format!("{name}.png"))
+}
+
+fn failure_dir() -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from);
+    return target
+        .unwrap_or_else(|| PathBuf::from(env!("OUT_DIR")))
+        .join("golden-failures");
+}
+
+/// Read a golden back. Only handles 8 bit RGBA, which is all [`write_png`] writes.
+pub fn read_png(path: &Path) -> io::Result<CapturedFrame> {
+    let decoder = png::Decoder::new(BufReader::new(fs::File::open(path)?));
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| io::Error::other("Image too big"))?;
+    let mut data = vec![0; size];
+    let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+    if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        return Err(io::Error::other("Goldens need to be 8 bit RGBA"));
+    }
+    data.truncate(info.buffer_size());
+
+    return Ok(CapturedFrame {
+        width: info.width,
+        height: info.height,
+        format: CaptureFormat::Rgba8Srgb,
+        data,
+        frame: 0,
+    });
+}
+
+fn updating() -> bool {
+    std::env::var_os("CROWBAR_UPDATE_GOLDENS").is_some_and(|v| v != "0")
+}
+
+/// Check `frame` against the golden called `name`, or replace the golden in update mode.
+#[track_caller]
+pub fn assert_golden(name: &str, frame: &CapturedFrame, tolerance: &Tolerance) {
+    let path = golden_path(name);
+
+    if updating() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_png(&path, frame).unwrap();
+        eprintln!("Updated golden {}", path.display());
+        return;
+    }
+
+    let expected = match read_png(&path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "Couldn't read golden {}: {e}. Run with CROWBAR_UPDATE_GOLDENS=1 to make it.",
+            path.display()
+        ),
+    };
+
+    let Some(comparison) = compare(&expected, frame, tolerance) else {
+        panic!(
+            "{name} rendered at {}x{}, but the golden is {}x{}",
+            frame.width, frame.height, expected.width, expected.height
+        );
+    };
+    if comparison.passes(tolerance) {
+        return;
+    }
+
+    let dir = failure_dir();
+    let written = fs::create_dir_all(&dir)
+        .and_then(|_| write_png(&dir.join(format!("{name}.expected.png")), &expected))
+        .and_then(|_| write_png(&dir.join(format!("{name}.actual.png")), frame))
+        .and_then(|_| write_png(&dir.join(format!("{name}.diff.png")), &comparison.diff));
+    if let Err(e) = written {
+        eprintln!("Couldn't write the failure images: {e}");
+    }
+
+    panic!(
+        "{name} doesn't match its golden: {} of {} pixels differ (max channel difference {}, max perceptual {:.4}). \
+         Images are in {}",
+        comparison.differing,
+        comparison.pixels,
+        comparison.max_channel,
+        comparison.max_perceptual,
+        dir.display()
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Tolerance, assert_golden, compare, golden_path, read_png};
+    use crate::{
+        capture::{CaptureFormat, CapturedFrame},
+        test_support::{frame_hash, headless, render_world, scenes},
+    };
+
+    fn frame(pixels: &[[u8; 4]]) -> CapturedFrame {
+        CapturedFrame {
+            width: pixels.len() as u32,
+            height: 1,
+            format: CaptureFormat::Rgba8Srgb,
+            data: pixels.concat(),
+            frame: 0,
+        }
+    }
+
+    #[test]
+    pub fn tolerances() {
+        let expected = frame(&[[100, 100, 100, 255]; 1000]);
+        let mut pixels = [[100, 100, 100, 255]; 1000];
+
+        // A little noise everywhere is within the default tolerance, but not exact.
+        for (i, p) in pixels.iter_mut().enumerate() {
+            p[i % 3] += 2;
+        }
+        let noisy = frame(&pixels);
+        let c = compare(&expected, &noisy, &Tolerance::DEFAULT).unwrap();
+        assert!(c.passes(&Tolerance::DEFAULT));
+        assert_eq!(c.max_channel, 2);
+        assert!(
+            !compare(&expected, &noisy, &Tolerance::EXACT)
+                .unwrap()
+                .passes(&Tolerance::EXACT)
+        );
+
+        // One pixel way off is an allowed stray edge, two isn't.
+        pixels[10] = [255, 0, 0, 255];
+        let c = compare(&expected, &frame(&pixels), &Tolerance::DEFAULT).unwrap();
+        assert_eq!(c.differing, 1);
+        assert!(c.passes(&Tolerance::DEFAULT));
+        assert_eq!(c.diff.data[40..44], [128 + 155 / 2, 0, 0, 255]);
+
+        pixels[20] = [0, 0, 255, 255];
+        let c = compare(&expected, &frame(&pixels), &Tolerance::DEFAULT).unwrap();
+        assert!(!c.passes(&Tolerance::DEFAULT));
+
+        assert!(compare(&expected, &frame(&pixels[..10]), &Tolerance::DEFAULT).is_none());
+    }
+
+    #[test]
+    pub fn goldens_read_back() {
+        let golden = read_png(&golden_path("lit_grid")).unwrap();
+        assert_eq!(frame_hash(&golden), scenes::LIT_GRID);
+    }
+
+    #[test]
+    pub fn lit_grid_matches_its_golden() {
+        let Some(headless) = headless() else {
+            return;
+        };
+
+        let mut world = hecs::World::new();
+        scenes::lit_grid(&mut world);
+        let Some(frame) = render_world(&headless, &mut world, 64, 64) else {
+            return;
+        };
+        assert_golden("lit_grid", &frame, &Tolerance::DEFAULT);
+    }
+}
@@ -0,0 +1,669 @@
+//! A small retained UI for in-game HUDs and menus, where the debug overlay's egui look doesn't fit.
+//!
+//! The tree is built once and kept, with widgets holding their own state (slider values, text being typed). Each
+//! frame [`Ui::update`] lays it out, feeds it the frame's input and returns what happened as [`UiEvent`]s, then
+//! [`Ui::draw`] pushes it into a [`SpriteBatch`].
+//!
+//! Layout is anchors and stacks. A node in a free parent sits at one of nine anchor points, nudged by its offset,
+//! or fills the parent. A stack parent puts its children one after another instead, anchors only aligning them
+//! across the stack. Sizes left at zero fit the content.
+
+use glam::Vec2;
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+use crate::{input::Input, math::Rect, render::sprite::SpriteBatch};
+
+pub mod widget;
+
+pub use widget::{UiStyle, Widget};
+
+/// The sprite layer the app draws its UI on, over anything a game is likely to use.
+pub const UI_LAYER: i32 = 1 << 20;
+
+/// A node in a [`Ui`]. Ids of removed nodes get reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+    /// Take up all of the parent, or all of its width or height across a stack.
+    Fill,
+}
+
+impl Anchor {
+    /// Where in the parent, and where in the node, lines up. 0..1 from the top left.
+    fn fraction(self) -> Vec2 {
+        match self {
+            Anchor::TopLeft | Anchor::Fill => Vec2::new(0.0, 0.0),
+            Anchor::Top => Vec2::new(0.5, 0.0),
+            Anchor::TopRight => Vec2::new(1.0, 0.0),
+            Anchor::Left => Vec2::new(0.0, 0.5),
+            Anchor::Center => Vec2::new(0.5, 0.5),
+            Anchor::Right => Vec2::new(1.0, 0.5),
+            Anchor::BottomLeft => Vec2::new(0.0, 1.0),
+            Anchor::Bottom => Vec2::new(0.5, 1.0),
+            Anchor::BottomRight => Vec2::new(1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    fn index(self) -> usize {
+        match self {
+            Axis::Horizontal => 0,
+            Axis::Vertical => 1,
+        }
+    }
+}
+
+/// How a node places its children.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// Each by its own anchor.
+    Free,
+    /// One after another along `axis`, `spacing` logical pixels apart.
+    Stack { axis: Axis, spacing: f32 },
+}
+
+pub struct Node {
+    pub widget: Widget,
+    pub anchor: Anchor,
+    /// Logical pixels from where the anchor puts it.
+    pub offset: Vec2,
+    /// Logical pixels. Zero on an axis fits the content.
+    pub size: Vec2,
+    pub layout: Layout,
+    /// Logical pixels between the node's edge and its children.
+    pub padding: f32,
+    /// Hidden nodes take their children with them, and take no space in a stack.
+    pub visible: bool,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Physical pixels, as of the last layout.
+    rect: Rect,
+}
+
+impl Node {
+    pub fn new(widget: Widget) -> Node {
+        Node {
+            widget,
+            anchor: Anchor::TopLeft,
+            offset: Vec2::ZERO,
+            size: Vec2::ZERO,
+            layout: Layout::Free,
+            padding: 0.0,
+            visible: true,
+            parent: None,
+            children: Vec::new(),
+            rect: Rect::default(),
+        }
+    }
+
+    pub fn anchor(mut self, anchor: Anchor) -> Node {
+        self.anchor = anchor;
+        self
+    }
+
+    pub fn offset(mut self, offset: Vec2) -> Node {
+        self.offset = offset;
+        self
+    }
+
+    pub fn size(mut self, size: Vec2) -> Node {
+        self.size = size;
+        self
+    }
+
+    pub fn stack(mut self, axis: Axis, spacing: f32) -> Node {
+        self.layout = Layout::Stack { axis, spacing };
+        self
+    }
+
+    pub fn padding(mut self, padding: f32) -> Node {
+        self.padding = padding;
+        self
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// Where it ended up, in physical pixels, as of the last layout.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UiEvent {
+    /// A button was pressed and released while hovered.
+    Clicked(NodeId),
+    /// A slider was dragged to a new value.
+    ValueChanged(NodeId, f32),
+    /// A text field's text was typed into.
+    TextChanged(NodeId),
+    /// Enter was pressed in a text field.
+    Submitted(NodeId),
+}
+
+pub struct Ui {
+    nodes: Vec<Option<Node>>,
+    free: Vec<u32>,
+    root: NodeId,
+    style: UiStyle,
+    /// Physical pixels per logical pixel.
+    scale: f32,
+    /// Visible nodes, back to front, as of the last layout.
+    order: Vec<NodeId>,
+    hovered: Option<NodeId>,
+    pressed: Option<NodeId>,
+    focused: Option<NodeId>,
+    pointer_over: bool,
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        Ui::new(UiStyle::default())
+    }
+}
+
+impl Ui {
+    pub fn new(style: UiStyle) -> Ui {
+        Ui {
+            nodes: vec![Some(Node::new(Widget::Container).anchor(Anchor::Fill))],
+            free: Vec::new(),
+            root: NodeId(0),
+            style,
+            scale: 1.0f32,
+            order: Vec::new(),
+            hovered: None,
+            pressed: None,
+            focused: None,
+            pointer_over: false,
+        }
+    }
+
+    /// Covers the whole viewport, everything goes under it.
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn style(&self) -> &UiStyle {
+        &self.style
+    }
+
+    pub fn style_mut(&mut self) -> &mut UiStyle {
+        &mut self.style
+    }
+
+    /// Physical pixels per logical pixel, usually the window's scale factor.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    /// Add `node` as the last child of `parent`, on top of its siblings.
+    pub fn add(&mut self, parent: NodeId, mut node: Node) -> NodeId {
+        node.parent = Some(parent);
+        let id = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index as usize] = Some(node);
+                NodeId(index)
+            }
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() as u32 - 1)
+            }
+        };
+        if let Some(parent) = self.get_mut(parent) {
+            parent.children.push(id);
+        }
+        return id;
+    }
+
+    /// Remove a node and everything under it. The root stays, but loses its children.
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(node) = self.get_mut(id) else {
+            return;
+        };
+        let children = std::mem::take(&mut node.children);
+        let parent = node.parent;
+        for child in children {
+            self.remove(child);
+        }
+        if id == self.root {
+            return;
+        }
+
+        if let Some(parent) = parent.and_then(|p| self.get_mut(p)) {
+            parent.children.retain(|&c| c != id);
+        }
+        self.nodes[id.0 as usize] = None;
+        self.free.push(id.0);
+        for state in [&mut self.hovered, &mut self.pressed, &mut self.focused] {
+            if *state == Some(id) {
+                *state = None;
+            }
+        }
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0 as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.0 as usize)?.as_mut()
+    }
+
+    /// The value of a slider.
+    pub fn value(&self, id: NodeId) -> Option<f32> {
+        match self.get(id)?.widget {
+            Widget::Slider { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The text of a label, button or text field.
+    pub fn text(&self, id: NodeId) -> Option<&str> {
+        match &self.get(id)?.widget {
+            Widget::Label { text } | Widget::TextField { text, .. } => Some(text),
+            Widget::Button { label } => Some(label),
+            _ => None,
+        }
+    }
+
+    pub fn hovered(&self) -> Option<NodeId> {
+        self.hovered
+    }
+
+    /// The text field being typed into.
+    pub fn focused(&self) -> Option<NodeId> {
+        self.focused
+    }
+
+    /// The pointer is over the UI or dragging something in it, so the game shouldn't act on it.
+    pub fn wants_pointer(&self) -> bool {
+        self.pointer_over || self.pressed.is_some()
+    }
+
+    /// A text field has the keyboard.
+    pub fn wants_keyboard(&self) -> bool {
+        self.focused.is_some()
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.get(id).expect("Node ids in the tree should be live.")
+    }
+
+    /// Place everything in a `viewport` physical pixels big.
+    pub fn layout(&mut self, viewport: Vec2) {
+        self.order.clear();
+        self.place(self.root, Rect::new(Vec2::ZERO, viewport));
+    }
+
+    /// The size a node takes, in physical pixels.
+    fn measure(&self, id: NodeId) -> Vec2 {
+        let node = self.node(id);
+        let mut content = node.widget.content_size(&self.style, self.scale);
+        let visible = node.children.iter().filter(|&&c| self.node(c).visible);
+        match node.layout {
+            Layout::Free => {
+                for &child in visible {
+                    content = content.max(self.measure(child));
+                }
+            }
+            Layout::Stack { axis, spacing } => {
+                let (main, cross) = (axis.index(), 1 - axis.index());
+                let mut stacked = Vec2::ZERO;
+                for (i, &child) in visible.enumerate() {
+                    let size = self.measure(child);
+                    stacked[main] += size[main] + if i > 0 { spacing * self.scale } else { 0.0 };
+                    stacked[cross] = stacked[cross].max(size[cross]);
+                }
+                content = content.max(stacked);
+            }
+        }
+        content += node.padding * self.scale * 2.0;
+        let size = node.size * self.scale;
+        return Vec2::new(
+            if size.x > 0.0 { size.x } else { content.x },
+            if size.y > 0.0 { size.y } else { content.y },
+        );
+    }
+
+    fn place(&mut self, id: NodeId, rect: Rect) {
+        self.order.push(id);
+        let scale = self.scale;
+        let node = self.get_mut(id).unwrap();
+        node.rect = rect;
+        let inner = rect.expand(-node.padding * scale);
+        let layout = node.layout;
+        let children: Vec<_> = node.children.clone();
+
+        let mut cursor = inner.min;
+        for child in children {
+            let (visible, anchor, offset) = {
+                let c = self.node(child);
+                (c.visible, c.anchor, c.offset * self.scale)
+            };
+            if !visible {
+                continue;
+            }
+            let mut size = self.measure(child);
+            let fraction = anchor.fraction();
+            let rect = match layout {
+                Layout::Free if anchor == Anchor::Fill => inner,
+                Layout::Free => {
+                    Rect::from_pos_size(inner.min + (inner.size() - size) * fraction + offset, size)
+                }
+                Layout::Stack { axis, spacing } => {
+                    let (main, cross) = (axis.index(), 1 - axis.index());
+                    if anchor == Anchor::Fill {
+                        size[cross] = inner.size()[cross];
+                    }
+                    let mut pos = cursor + offset;
+                    pos[cross] += (inner.size()[cross] - size[cross]) * fraction[cross];
+                    cursor[main] += size[main] + spacing * self.scale;
+                    Rect::from_pos_size(pos, size)
+                }
+            };
+            self.place(child, rect);
+        }
+    }
+
+    /// The topmost node under `point` that keeps the pointer to itself.
+    fn hit(&self, point: Vec2) -> Option<NodeId> {
+        self.order.iter().rev().copied().find(|&id| {
+            let node = self.node(id);
+            node.widget.blocks_pointer() && node.rect.contains(point)
+        })
+    }
+
+    /// Lay out for `viewport` and run the frame's input. Returns what happened, in order.
+    pub fn update(&mut self, input: &Input, viewport: Vec2) -> Vec<UiEvent> {
+        self.layout(viewport);
+        let cursor = input.cursor_position();
+        let hit = self.hit(cursor);
+        self.pointer_over = hit.is_some();
+        self.hovered = hit.filter(|&id| self.node(id).widget.is_interactive());
+
+        let mut events = Vec::new();
+        if input.button_pressed(MouseButton::Left) {
+            self.pressed = self.hovered;
+            // Clicking anywhere else drops the focus.
+            self.focused = self
+                .hovered
+                .filter(|&id| matches!(self.node(id).widget, Widget::TextField { .. }));
+        }
+        if let Some(id) = self.pressed
+            && let Some(node) = self.get_mut(id)
+            && let Widget::Slider { value, min, max } = &mut node.widget
+        {
+            let t = ((cursor.x - node.rect.min.x) / node.rect.size().x).clamp(0.0, 1.0);
+            let new = *min + (*max - *min) * t;
+            if new != *value {
+                *value = new;
+                events.push(UiEvent::ValueChanged(id, new));
+            }
+        }
+        if input.button_released(MouseButton::Left)
+            && let Some(id) = self.pressed.take()
+            && self.hovered == Some(id)
+            && matches!(self.node(id).widget, Widget::Button { .. })
+        {
+            events.push(UiEvent::Clicked(id));
+        }
+
+        if let Some(id) = self.focused
+            && let Some(node) = self.get_mut(id)
+            && let Widget::TextField { text, max_len } = &mut node.widget
+        {
+            let mut changed = false;
+            for c in input.text().chars() {
+                if text.chars().count() < *max_len {
+                    text.push(c);
+                    changed = true;
+                }
+            }
+            // todo: repeat while held, and a movable caret.
+            if input.key_pressed(KeyCode::Backspace) {
+                changed |= text.pop().is_some();
+            }
+            if changed {
+                events.push(UiEvent::TextChanged(id));
+            }
+            if input.key_pressed(KeyCode::Enter) || input.key_pressed(KeyCode::NumpadEnter) {
+                events.push(UiEvent::Submitted(id));
+                self.focused = None;
+            } else if input.key_pressed(KeyCode::Escape) {
+                self.focused = None;
+            }
+        }
+        return events;
+    }
+
+    /// Push everything visible into `batch` on `layer`, as of the last layout.
+    pub fn draw(&self, batch: &mut SpriteBatch, layer: i32) {
+        let style = &self.style;
+        let height = style.text_height * self.scale;
+        let padding = style.padding * self.scale;
+        for &id in &self.order {
+            let node = self.node(id);
+            let rect = node.rect;
+            match &node.widget {
+                Widget::Container => {}
+                Widget::Panel { color } => batch.rect(rect, *color, layer),
+                Widget::Image { image, color } => {
+                    batch.image(image, rect, self.scale, *color, layer)
+                }
+                Widget::Label { text } => {
+                    style
+                        .font
+                        .draw(batch, text, rect.min, height, style.text, layer);
+                }
+                Widget::Button { label } => {
+                    let color = match (self.hovered == Some(id), self.pressed == Some(id)) {
+                        (true, true) => style.pressed,
+                        (true, false) => style.hovered,
+                        (false, _) => style.button,
+                    };
+                    batch.rect(rect, color, layer);
+                    let size = style.font.measure(label, height);
+                    style.font.draw(
+                        batch,
+                        label,
+                        rect.center() - size * 0.5,
+                        height,
+                        style.text,
+                        layer,
+                    );
+                }
+                Widget::Slider { value, min, max } => {
+                    let t = if max > min {
+                        (value - min) / (max - min)
+                    } else {
+                        0.0
+                    };
+                    let track = Rect::new(
+                        Vec2::new(rect.min.x, rect.center().y - padding * 0.5),
+                        Vec2::new(rect.max.x, rect.center().y + padding * 0.5),
+                    );
+                    let x = rect.min.x + rect.size().x * t;
+                    batch.rect(track, style.field, layer);
+                    batch.rect(
+                        Rect::new(track.min, Vec2::new(x, track.max.y)),
+                        style.accent,
+                        layer,
+                    );
+                    let handle = if self.hovered == Some(id) || self.pressed == Some(id) {
+                        style.text
+                    } else {
+                        style.accent
+                    };
+                    batch.rect(
+                        Rect::new(
+                            Vec2::new(x - padding * 0.5, rect.min.y),
+                            Vec2::new(x + padding * 0.5, rect.max.y),
+                        ),
+                        handle,
+                        layer,
+                    );
+                }
+                Widget::TextField { text, .. } => {
+                    let focused = self.focused == Some(id);
+                    if focused {
+                        batch.rect(rect.expand(self.scale), style.accent, layer);
+                    }
+                    batch.rect(rect, style.field, layer);
+                    // todo: clip to the field, and scroll to keep the caret in view.
+                    let pos = Vec2::new(rect.min.x + padding, rect.center().y - height * 0.5);
+                    let size = style.font.draw(batch, text, pos, height, style.text, layer);
+                    if focused {
+                        let caret = Rect::from_pos_size(
+                            Vec2::new(pos.x + size.x, pos.y),
+                            Vec2::new(self.scale.max(1.0), height),
+                        );
+                        batch.rect(caret, style.accent, layer);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec2;
+    use winit::{event::MouseButton, keyboard::KeyCode};
+
+    use super::{Anchor, Axis, Node, Ui, UiEvent, Widget};
+    use crate::{
+        input::{Input, InputEvent},
+        render::sprite::SpriteBatch,
+    };
+
+    fn click(input: &mut Input, x: f32, y: f32, pressed: bool) {
+        input.apply(InputEvent::CursorMoved { x, y });
+        input.apply(InputEvent::Button {
+            button: MouseButton::Left,
+            pressed,
+        });
+    }
+
+    #[test]
+    pub fn lays_out_and_clicks() {
+        let viewport = Vec2::new(800.0, 600.0);
+        let mut ui = Ui::default();
+        ui.set_scale(2.0);
+        let menu = ui.add(
+            ui.root(),
+            Node::new(Widget::Container)
+                .anchor(Anchor::Center)
+                .stack(Axis::Vertical, 5.0),
+        );
+        let play = ui.add(
+            menu,
+            Node::new(Widget::button("Play")).size(Vec2::new(100.0, 20.0)),
+        );
+        let volume = ui.add(
+            menu,
+            Node::new(Widget::slider(0.5, 0.0, 1.0)).anchor(Anchor::Fill),
+        );
+        let name = ui.add(
+            menu,
+            Node::new(Widget::text_field("", 4)).size(Vec2::new(50.0, 20.0)),
+        );
+        let corner = ui.add(
+            ui.root(),
+            Node::new(Widget::label("HUD"))
+                .anchor(Anchor::BottomRight)
+                .offset(Vec2::new(-10.0, -10.0)),
+        );
+        ui.layout(viewport);
+
+        // The slider's default 160 + 2 * 6 logical width is the widest, in a menu centered on the screen.
+        let [menu_rect, play_rect, volume_rect, name_rect, corner_rect] =
+            [menu, play, volume, name, corner].map(|id| ui.get(id).unwrap().rect());
+        assert_eq!(menu_rect.size().x, 344.0);
+        assert_eq!(menu_rect.center(), viewport * 0.5);
+        assert_eq!(play_rect.size(), Vec2::new(200.0, 40.0));
+        assert_eq!(volume_rect.size().x, 344.0);
+        assert_eq!(volume_rect.min.y, play_rect.max.y + 10.0);
+        assert_eq!(corner_rect.max, viewport - 20.0);
+
+        let mut input = Input::default();
+        let play_at = play_rect.center();
+        click(&mut input, play_at.x, play_at.y, true);
+        assert!(ui.update(&input, viewport).is_empty());
+        assert!(ui.wants_pointer());
+        input.end_frame();
+        click(&mut input, play_at.x, play_at.y, false);
+        assert_eq!(ui.update(&input, viewport), vec![UiEvent::Clicked(play)]);
+        input.end_frame();
+
+        // Dragging the slider to its right end.
+        click(&mut input, volume_rect.min.x, volume_rect.center().y, true);
+        assert_eq!(
+            ui.update(&input, viewport),
+            vec![UiEvent::ValueChanged(volume, 0.0)]
+        );
+        input.end_frame();
+        input.apply(InputEvent::CursorMoved {
+            x: volume_rect.max.x + 50.0,
+            y: 0.0,
+        });
+        ui.update(&input, viewport);
+        assert_eq!(ui.value(volume), Some(1.0));
+        click(&mut input, 0.0, 0.0, false);
+        ui.update(&input, viewport);
+        assert!(!ui.wants_pointer());
+        input.end_frame();
+
+        // Typing into the text field, past its limit.
+        let name_at = name_rect.center();
+        click(&mut input, name_at.x, name_at.y, true);
+        for ch in "crowbar".chars() {
+            input.apply(InputEvent::Text { ch });
+        }
+        assert_eq!(
+            ui.update(&input, viewport),
+            vec![UiEvent::TextChanged(name)]
+        );
+        assert_eq!(ui.text(name), Some("crow"));
+        assert!(ui.wants_keyboard());
+        input.end_frame();
+        input.apply(InputEvent::Key {
+            key: KeyCode::Enter,
+            pressed: true,
+        });
+        assert_eq!(ui.update(&input, viewport), vec![UiEvent::Submitted(name)]);
+        assert!(!ui.wants_keyboard());
+
+        let mut batch = SpriteBatch::default();
+        ui.draw(&mut batch, 0);
+        // Button and label, slider track, fill and handle, field and text, and the HUD label.
+        assert_eq!(batch.len(), 1 + 4 + 3 + 1 + 4 + 3);
+
+        ui.remove(menu);
+        assert!(ui.get(play).is_none());
+        assert_eq!(ui.get(ui.root()).unwrap().children(), &[corner]);
+    }
+}
@@ -0,0 +1,131 @@
+//! What a UI node is, and how the built-in widgets look.
+
+use glam::Vec2;
+
+use crate::{
+    color::LinearColor,
+    render::{sprite::SpriteImage, text::BitmapFont},
+};
+
+pub enum Widget {
+    /// Nothing to see, only lays its children out.
+    Container,
+    /// A filled rectangle, usually behind other widgets.
+    Panel {
+        color: LinearColor,
+    },
+    /// An image, nine-sliced or tiled if it says so, tinted by `color`. For panels with a look of their own, and
+    /// repeating backgrounds.
+    Image {
+        image: SpriteImage,
+        color: LinearColor,
+    },
+    Label {
+        text: String,
+    },
+    Button {
+        label: String,
+    },
+    /// Dragged along between `min` and `max`.
+    Slider {
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+    /// Single line, up to `max_len` characters.
+    TextField {
+        text: String,
+        max_len: usize,
+    },
+}
+
+impl Widget {
+    pub fn label(text: impl Into<String>) -> Widget {
+        Widget::Label { text: text.into() }
+    }
+
+    pub fn button(label: impl Into<String>) -> Widget {
+        Widget::Button {
+            label: label.into(),
+        }
+    }
+
+    pub fn slider(value: f32, min: f32, max: f32) -> Widget {
+        Widget::Slider {
+            value: value.clamp(min, max),
+            min,
+            max,
+        }
+    }
+
+    pub fn text_field(text: impl Into<String>, max_len: usize) -> Widget {
+        Widget::TextField {
+            text: text.into(),
+            max_len,
+        }
+    }
+
+    /// Can be hovered, pressed or focused.
+    pub fn is_interactive(&self) -> bool {
+        matches!(
+            self,
+            Widget::Button { .. } | Widget::Slider { .. } | Widget::TextField { .. }
+        )
+    }
+
+    /// Keeps the pointer from whatever's under it. Containers and labels let it through.
+    pub fn blocks_pointer(&self) -> bool {
+        !matches!(self, Widget::Container | Widget::Label { .. })
+    }
+
+    /// The size it wants with nothing else to go by, at `scale`.
+    pub(super) fn content_size(&self, style: &UiStyle, scale: f32) -> Vec2 {
+        let height = style.text_height * scale;
+        let padding = Vec2::splat(style.padding * scale * 2.0);
+        match self {
+            Widget::Container | Widget::Panel { .. } => Vec2::ZERO,
+            Widget::Image { image, .. } => image.size * scale,
+            Widget::Label { text } => style.font.measure(text, height),
+            Widget::Button { label } => style.font.measure(label, height) + padding,
+            Widget::Slider { .. } | Widget::TextField { .. } => {
+                Vec2::new(style.field_width * scale, height) + padding
+            }
+        }
+    }
+}
+
+/// How widgets look. Sizes are in logical pixels, scaled with the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UiStyle {
+    pub font: BitmapFont,
+    pub text_height: f32,
+    /// Between a widget's edge and its content.
+    pub padding: f32,
+    /// Width of sliders and text fields that aren't given one.
+    pub field_width: f32,
+    pub text: LinearColor,
+    pub button: LinearColor,
+    pub hovered: LinearColor,
+    pub pressed: LinearColor,
+    /// Slider tracks and text field backgrounds.
+    pub field: LinearColor,
+    /// Slider fill and handles, and the outline and caret of a focused text field.
+    pub accent: LinearColor,
+}
+
+impl Default for UiStyle {
+    fn default() -> Self {
+        UiStyle {
+            font: BitmapFont::builtin(),
+            text_height: 16.0,
+            padding: 6.0,
+            field_width: 160.0,
+            text: LinearColor::WHITE,
+            button: LinearColor::rgb(0.1, 0.1, 0.12),
+            hovered: LinearColor::rgb(0.18, 0.18, 0.22),
+            pressed: LinearColor::rgb(0.05, 0.05, 0.06),
+            field: LinearColor::rgb(0.03, 0.03, 0.04),
+            accent: LinearColor::rgb(0.9, 0.45, 0.1),
+        }
+    }
+}